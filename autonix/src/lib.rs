@@ -2,4 +2,4 @@ mod mapping;
 mod repo;
 mod project;
 
-pub use repo::Parser;
+pub use repo::{EnvReport, LicenseSummary, OutputTarget, PackageCheck, PackageMap, Parser, Sbom};
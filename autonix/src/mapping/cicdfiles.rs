@@ -74,6 +74,9 @@ pub struct ParsedCiCdFile {
 
     /// Global environment variables
     pub env: HashMap<String, String>,
+
+    /// Secrets the pipeline reads (e.g., `${{ secrets.NPM_TOKEN }}`), sorted
+    pub secrets: Vec<String>,
 }
 
 // GitHub Actions YAML structures (minimal subset)
@@ -150,6 +153,7 @@ fn parse_github_actions(content: &str) -> Result<ParsedCiCdFile, ParseError> {
     let mut result = ParsedCiCdFile {
         jobs: Vec::new(),
         env: extract_env_map(&workflow.env),
+        secrets: extract_secrets(content),
     };
 
     for (job_id, job) in workflow.jobs {
@@ -185,6 +189,24 @@ fn parse_github_actions(content: &str) -> Result<ParsedCiCdFile, ParseError> {
     Ok(result)
 }
 
+/// Names read through `secrets.NAME`, except the token GitHub provides to every run
+fn extract_secrets(content: &str) -> Vec<String> {
+    let mut secrets: Vec<String> = content
+        .match_indices("secrets.")
+        .filter_map(|(at, marker)| {
+            let rest = &content[at + marker.len()..];
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+            (!name.is_empty() && name != "GITHUB_TOKEN").then(|| name.to_string())
+        })
+        .collect();
+    secrets.sort();
+    secrets.dedup();
+    secrets
+}
+
 /// Helper to convert YAML value map to string map
 fn extract_env_map(yaml_map: &HashMap<String, serde_yaml_ng::Value>) -> HashMap<String, String> {
     yaml_map
@@ -381,6 +403,20 @@ mod tests {
         assert!(build_steps.iter().any(|s| s.contains("cargo build --release")));
     }
 
+    #[test]
+    fn test_extract_secrets() {
+        let content = r#"
+env:
+  NPM_TOKEN: ${{ secrets.NPM_TOKEN }}
+jobs:
+  deploy:
+    steps:
+      - run: deploy --key "${{ secrets.DEPLOY_KEY }}" --gh ${{ secrets.GITHUB_TOKEN }}
+      - run: publish ${{secrets.NPM_TOKEN}}
+"#;
+        assert_eq!(extract_secrets(content), vec!["DEPLOY_KEY", "NPM_TOKEN"]);
+    }
+
     #[test]
     fn test_extract_env_map() {
         let mut yaml_map = HashMap::new();
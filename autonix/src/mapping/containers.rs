@@ -4,7 +4,7 @@ use std::path::Path;
 
 use dockerfile_parser::{Dockerfile, Instruction, ShellOrExecExpr};
 
-use crate::mapping::{ParseError, Parseable};
+use crate::mapping::{EnvReference, ParseError, Parseable, envfiles::references};
 
 /// Container and deployment configuration files
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -69,6 +69,10 @@ pub struct ParsedContainerFile {
     /// Example: postgres:15, redis:7, rabbitmq:3
     pub services: Vec<ContainerService>,
 
+    /// Host variables the compose file reads: interpolations and pass-through entries
+    /// Example: `${POSTGRES_PASSWORD}`, `${PORT:-8080}`, `- API_KEY`
+    pub env_references: Vec<EnvReference>,
+
     /// Command to run (CMD/ENTRYPOINT)
     /// Example: ["npm", "start"] or ["cargo", "run", "--release"]
     pub command: Option<Vec<String>>,
@@ -317,7 +321,7 @@ enum DockerComposePort {
 #[derive(Deserialize)]
 #[serde(untagged)]
 enum DockerComposeEnvironment {
-    Map(HashMap<String, String>),
+    Map(HashMap<String, Option<serde_yaml_ng::Value>>),
     List(Vec<String>),
 }
 
@@ -582,21 +586,47 @@ fn parse_docker_compose(path: &Path) -> Result<ParsedContainerFile, ParseError>
     let content = std::fs::read_to_string(path)?;
     let compose: DockerComposeFile = serde_yaml_ng::from_str(&content)?;
 
-    let mut result = ParsedContainerFile::default();
+    let mut result = ParsedContainerFile {
+        env_references: references(&content),
+        ..Default::default()
+    };
 
     for (name, service) in compose.services {
+        // Entries without a value pass the host's variable through
+        let mut pass_through = Vec::new();
         let environment = match service.environment {
-            DockerComposeEnvironment::Map(map) => map,
+            DockerComposeEnvironment::Map(map) => {
+                let mut environment = HashMap::new();
+                for (key, value) in map {
+                    match value.as_ref().and_then(scalar_to_string) {
+                        Some(value) => {
+                            environment.insert(key, value);
+                        }
+                        None => pass_through.push(key),
+                    }
+                }
+                environment
+            }
             DockerComposeEnvironment::List(list) => {
                 let mut map = HashMap::new();
                 for item in list {
                     if let Some((key, value)) = item.split_once('=') {
                         map.insert(key.to_string(), value.to_string());
+                    } else {
+                        pass_through.push(item);
                     }
                 }
                 map
             }
         };
+        for name in pass_through {
+            if !result.env_references.iter().any(|r| r.name == name) {
+                result.env_references.push(EnvReference {
+                    name,
+                    default: None,
+                });
+            }
+        }
 
         let ports: Vec<PortMapping> = service
             .ports
@@ -689,6 +719,15 @@ fn parse_docker_compose(path: &Path) -> Result<ParsedContainerFile, ParseError>
     Ok(result)
 }
 
+fn scalar_to_string(value: &serde_yaml_ng::Value) -> Option<String> {
+    match value {
+        serde_yaml_ng::Value::String(s) => Some(s.clone()),
+        serde_yaml_ng::Value::Number(n) => Some(n.to_string()),
+        serde_yaml_ng::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
 fn parse_port_mapping(port: DockerComposePort) -> Option<PortMapping> {
     match port {
         DockerComposePort::Short(s) => {
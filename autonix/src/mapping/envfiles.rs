@@ -0,0 +1,252 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::mapping::{ParseError, Parseable};
+
+/// Files documenting the environment variables a project expects
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum EnvFile {
+    // .env.example, .env.sample, .env.template, .env.dist
+    DotEnvExample,
+}
+
+/// Parsed example environment file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedEnvFile {
+    /// Variables in file order, with their example value (None when left empty)
+    pub variables: Vec<EnvReference>,
+}
+
+/// A variable a file reads, with the value it falls back to or shows as an example
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvReference {
+    pub name: String,
+    pub default: Option<String>,
+}
+
+/// Whether a variable holds a secret, which must never get a value from the repo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvKind {
+    Secret,
+    Config,
+}
+
+/// Parts of a variable name that mark it as a secret
+const SECRET_MARKERS: [&str; 10] = [
+    "SECRET",
+    "TOKEN",
+    "PASSWORD",
+    "PASSWD",
+    "PRIVATE",
+    "CREDENTIAL",
+    "API_KEY",
+    "APIKEY",
+    "ACCESS_KEY",
+    "SIGNING_KEY",
+];
+
+/// Variables every shell sets, never asked of the user
+const SHELL_VARIABLES: [&str; 7] = ["HOME", "PATH", "USER", "PWD", "SHELL", "HOSTNAME", "TERM"];
+
+/// Example values that only say a value is needed
+const PLACEHOLDER_VALUES: [&str; 6] = ["changeme", "change-me", "change_me", "todo", "xxx", "..."];
+
+impl EnvKind {
+    /// Classify a variable by its name (e.g., `STRIPE_SECRET_KEY`, `DB_PASSWORD`, `JWT_KEY`)
+    pub fn classify(name: &str) -> Self {
+        let upper = name.to_uppercase();
+        if SECRET_MARKERS.iter().any(|marker| upper.contains(marker)) || upper.ends_with("_KEY") {
+            Self::Secret
+        } else {
+            Self::Config
+        }
+    }
+}
+
+impl Parseable for EnvFile {
+    type Output = ParsedEnvFile;
+
+    fn parse(&self, path: &Path) -> Result<Self::Output, ParseError> {
+        let content = std::fs::read_to_string(path)?;
+        match self {
+            Self::DotEnvExample => Ok(parse_dotenv(&content)),
+        }
+    }
+}
+
+/// Parse `NAME=value` lines, ignoring comments, `export ` prefixes and placeholder values
+fn parse_dotenv(content: &str) -> ParsedEnvFile {
+    let variables = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (name, value) = line.split_once('=')?;
+            let name = name.trim();
+            if !is_variable_name(name) {
+                return None;
+            }
+            Some(EnvReference {
+                name: name.to_string(),
+                default: example_value(value),
+            })
+        })
+        .collect();
+    ParsedEnvFile { variables }
+}
+
+/// The value of a dotenv line, unquoted and without trailing comment, if it is not a placeholder
+fn example_value(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or_default(),
+        _ => value.split(" #").next().unwrap_or_default().trim(),
+    };
+    let lower = value.to_lowercase();
+    let placeholder = PLACEHOLDER_VALUES.contains(&lower.as_str())
+        || lower.starts_with("your")
+        || (value.starts_with('<') && value.ends_with('>'));
+    (!value.is_empty() && !placeholder).then(|| value.to_string())
+}
+
+/// Variables a shell-like text reads: `$NAME`, `${NAME}` and `${NAME:-default}` (or `-`, `:?`,
+/// `?` forms); `$$` escapes and GitHub `${{ }}` expressions are skipped
+pub fn references(text: &str) -> Vec<EnvReference> {
+    let mut found: Vec<EnvReference> = Vec::new();
+    let mut rest = text;
+    while let Some(at) = rest.find('$') {
+        rest = &rest[at + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            rest = after;
+            continue;
+        }
+        let reference = if let Some(braced) = rest.strip_prefix('{') {
+            if braced.starts_with('{') {
+                continue;
+            }
+            let Some(end) = braced.find('}') else {
+                continue;
+            };
+            let inner = &braced[..end];
+            rest = &braced[end + 1..];
+            let name_end = inner
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(inner.len());
+            let (name, operator) = inner.split_at(name_end);
+            let default = operator
+                .strip_prefix(":-")
+                .or_else(|| operator.strip_prefix('-'))
+                .filter(|default| !default.is_empty())
+                .map(ToString::to_string);
+            EnvReference {
+                name: name.to_string(),
+                default,
+            }
+        } else {
+            let name_end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let (name, after) = rest.split_at(name_end);
+            rest = after;
+            EnvReference {
+                name: name.to_string(),
+                default: None,
+            }
+        };
+        if is_variable_name(&reference.name)
+            && !SHELL_VARIABLES.contains(&reference.name.as_str())
+            && !found.iter().any(|r| r.name == reference.name)
+        {
+            found.push(reference);
+        }
+    }
+    found
+}
+
+/// Whether `name` is an environment variable name (letters, digits and `_`, not starting with
+/// a digit)
+fn is_variable_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl TryFrom<&str> for EnvFile {
+    type Error = ();
+
+    fn try_from(filename: &str) -> Result<Self, Self::Error> {
+        match filename {
+            ".env.example" | ".env.sample" | ".env.template" | ".env.dist" | "example.env"
+            | "sample.env" => Ok(Self::DotEnvExample),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(name: &str, default: Option<&str>) -> EnvReference {
+        EnvReference {
+            name: name.to_string(),
+            default: default.map(ToString::to_string),
+        }
+    }
+
+    #[test]
+    fn test_parse_dotenv_example() {
+        let parsed = parse_dotenv(
+            r#"
+# Database
+DATABASE_URL=postgres://localhost:5432/app
+export LOG_LEVEL="info" # verbosity
+STRIPE_SECRET_KEY=
+SENTRY_DSN=<your sentry dsn>
+SMTP_PASSWORD=changeme
+PORT=3000 # http
+not a variable
+"#,
+        );
+        assert_eq!(
+            parsed.variables,
+            vec![
+                reference("DATABASE_URL", Some("postgres://localhost:5432/app")),
+                reference("LOG_LEVEL", Some("info")),
+                reference("STRIPE_SECRET_KEY", None),
+                reference("SENTRY_DSN", None),
+                reference("SMTP_PASSWORD", None),
+                reference("PORT", Some("3000")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_references() {
+        assert_eq!(
+            references(
+                "postgres://${DB_USER:-app}:${DB_PASSWORD}@db/$DB_NAME $$HOST ${{ secrets.X }} ${PORT-80} $1 $HOME"
+            ),
+            vec![
+                reference("DB_USER", Some("app")),
+                reference("DB_PASSWORD", None),
+                reference("DB_NAME", None),
+                reference("PORT", Some("80")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(EnvKind::classify("STRIPE_SECRET_KEY"), EnvKind::Secret);
+        assert_eq!(EnvKind::classify("github_token"), EnvKind::Secret);
+        assert_eq!(EnvKind::classify("JWT_KEY"), EnvKind::Secret);
+        assert_eq!(EnvKind::classify("POSTGRES_PASSWORD"), EnvKind::Secret);
+        assert_eq!(EnvKind::classify("DATABASE_URL"), EnvKind::Config);
+        assert_eq!(EnvKind::classify("KEYCLOAK_URL"), EnvKind::Config);
+    }
+}
@@ -0,0 +1,335 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::Deserialize;
+
+use crate::mapping::{ParseError, Parseable};
+
+/// Files configuring the formatters and linters a project gates its commits on
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum FormatterFile {
+    // .pre-commit-config.yaml
+    PreCommit,
+    // treefmt.toml, .treefmt.toml
+    Treefmt,
+}
+
+/// Parsed formatter configuration
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedFormatterFile {
+    /// Formatters and linters that can run as a check, in file order
+    pub formatters: Vec<Formatter>,
+    /// nixpkgs packages for the dev shell, to run them by hand (e.g., `pre-commit`, `treefmt`)
+    pub tools: Vec<String>,
+}
+
+/// A formatter or linter, with the command failing when the sources don't pass it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Formatter {
+    /// Hook id, or `treefmt` for all the formatters of a treefmt.toml
+    pub name: String,
+    /// Command checking the sources without changing them
+    pub command: String,
+    /// nixpkgs packages the command needs
+    pub packages: Vec<String>,
+}
+
+/// nixpkgs package of the executables formatters run, when it is not named after them
+const PACKAGES: [(&str, &str); 7] = [
+    ("clang-format", "clang-tools"),
+    ("flake8", "python3Packages.flake8"),
+    ("gofmt", "go"),
+    ("goimports", "gotools"),
+    ("mdformat", "python3Packages.mdformat"),
+    ("nixfmt", "nixfmt-rfc-style"),
+    ("prettier", "nodePackages.prettier"),
+];
+
+/// Hooks of the usual pre-commit repos, by id: check command and packages
+const HOOKS: [(&str, &str, &[&str]); 24] = [
+    ("black", "black --check .", &["black"]),
+    ("isort", "isort --check-only .", &["isort"]),
+    ("flake8", "flake8 .", &["python3Packages.flake8"]),
+    ("ruff", "ruff check .", &["ruff"]),
+    ("ruff-check", "ruff check .", &["ruff"]),
+    ("ruff-format", "ruff format --check .", &["ruff"]),
+    ("mypy", "mypy .", &["mypy"]),
+    ("prettier", "prettier --check .", &["nodePackages.prettier"]),
+    ("eslint", "eslint .", &["eslint"]),
+    ("fmt", "cargo fmt --all -- --check", &["cargo", "rustfmt"]),
+    (
+        "cargo-fmt",
+        "cargo fmt --all -- --check",
+        &["cargo", "rustfmt"],
+    ),
+    (
+        "clippy",
+        "cargo clippy --all-targets -- -D warnings",
+        &["cargo", "clippy"],
+    ),
+    ("go-fmt", "test -z \"$(gofmt -l .)\"", &["go"]),
+    ("golangci-lint", "golangci-lint run", &["golangci-lint"]),
+    (
+        "shellcheck",
+        "find . -name '*.sh' -exec shellcheck {} +",
+        &["shellcheck"],
+    ),
+    ("shfmt", "shfmt -d .", &["shfmt"]),
+    ("nixpkgs-fmt", "nixpkgs-fmt --check .", &["nixpkgs-fmt"]),
+    ("alejandra", "alejandra --check .", &["alejandra"]),
+    (
+        "nixfmt",
+        "find . -name '*.nix' -exec nixfmt --check {} +",
+        &["nixfmt-rfc-style"],
+    ),
+    ("deadnix", "deadnix --fail .", &["deadnix"]),
+    ("statix", "statix check .", &["statix"]),
+    (
+        "hadolint",
+        "find . -name Dockerfile -exec hadolint {} +",
+        &["hadolint"],
+    ),
+    ("yamllint", "yamllint .", &["yamllint"]),
+    ("markdownlint", "markdownlint .", &["markdownlint-cli"]),
+];
+
+/// Languages of local pre-commit hooks that run their `entry` as is
+const SYSTEM_LANGUAGES: [&str; 3] = ["system", "script", "unsupported"];
+
+#[derive(Debug, Deserialize)]
+struct PreCommitConfig {
+    #[serde(default)]
+    repos: Vec<PreCommitRepo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PreCommitRepo {
+    repo: String,
+    #[serde(default)]
+    hooks: Vec<PreCommitHook>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PreCommitHook {
+    id: String,
+    entry: Option<String>,
+    language: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TreefmtConfig {
+    #[serde(default)]
+    formatter: BTreeMap<String, TreefmtFormatter>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TreefmtFormatter {
+    command: String,
+}
+
+impl Parseable for FormatterFile {
+    type Output = ParsedFormatterFile;
+
+    fn parse(&self, path: &Path) -> Result<Self::Output, ParseError> {
+        let content = std::fs::read_to_string(path)?;
+        match self {
+            Self::PreCommit => Ok(parse_pre_commit(&serde_yaml_ng::from_str(&content)?)),
+            Self::Treefmt => Ok(parse_treefmt(&toml::from_str(&content)?)),
+        }
+    }
+}
+
+/// A check per hook whose tool we know, or local hook running a system command
+///
+/// Other hooks need pre-commit to fetch and install them, which a Nix build can't do
+fn parse_pre_commit(config: &PreCommitConfig) -> ParsedFormatterFile {
+    let mut formatters: Vec<Formatter> = Vec::new();
+    for repo in &config.repos {
+        for hook in &repo.hooks {
+            let formatter = if repo.repo == "local" {
+                local_hook(hook)
+            } else {
+                known_hook(&hook.id)
+            };
+            if let Some(formatter) = formatter
+                && !formatters.iter().any(|f| f.name == formatter.name)
+            {
+                formatters.push(formatter);
+            }
+        }
+    }
+    ParsedFormatterFile {
+        formatters,
+        tools: vec!["pre-commit".to_string()],
+    }
+}
+
+fn known_hook(id: &str) -> Option<Formatter> {
+    HOOKS
+        .iter()
+        .find(|(hook, _, _)| *hook == id)
+        .map(|(name, command, packages)| Formatter {
+            name: (*name).to_string(),
+            command: (*command).to_string(),
+            packages: packages.iter().map(ToString::to_string).collect(),
+        })
+}
+
+fn local_hook(hook: &PreCommitHook) -> Option<Formatter> {
+    if !SYSTEM_LANGUAGES.contains(&hook.language.as_deref()?) {
+        return None;
+    }
+    let entry = hook.entry.as_deref()?.trim();
+    let command = std::iter::once(entry)
+        .chain(hook.args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(Formatter {
+        name: hook.id.clone(),
+        packages: entry
+            .split_whitespace()
+            .next()
+            .and_then(package)
+            .into_iter()
+            .collect(),
+        command,
+    })
+}
+
+/// One check running every formatter through treefmt, failing if any file would change
+fn parse_treefmt(config: &TreefmtConfig) -> ParsedFormatterFile {
+    let mut packages = vec!["treefmt".to_string()];
+    for formatter in config.formatter.values() {
+        if let Some(package) = package(&formatter.command)
+            && !packages.contains(&package)
+        {
+            packages.push(package);
+        }
+    }
+    ParsedFormatterFile {
+        tools: packages.clone(),
+        formatters: vec![Formatter {
+            name: "treefmt".to_string(),
+            command: "treefmt --fail-on-change --no-cache".to_string(),
+            packages,
+        }],
+    }
+}
+
+/// nixpkgs package providing `executable`, None for a path into the repo (e.g., `./fmt.sh`)
+fn package(executable: &str) -> Option<String> {
+    if executable.contains('/') {
+        return None;
+    }
+    let package = PACKAGES
+        .iter()
+        .find(|(name, _)| *name == executable)
+        .map_or(executable, |(_, package)| package);
+    Some(package.to_string())
+}
+
+impl TryFrom<&str> for FormatterFile {
+    type Error = ();
+
+    fn try_from(filename: &str) -> Result<Self, Self::Error> {
+        match filename {
+            ".pre-commit-config.yaml" | ".pre-commit-config.yml" => Ok(Self::PreCommit),
+            "treefmt.toml" | ".treefmt.toml" => Ok(Self::Treefmt),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formatter(name: &str, command: &str, packages: &[&str]) -> Formatter {
+        Formatter {
+            name: name.to_string(),
+            command: command.to_string(),
+            packages: packages.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_pre_commit() {
+        let config = serde_yaml_ng::from_str(
+            r"
+repos:
+  - repo: https://github.com/pre-commit/pre-commit-hooks
+    rev: v4.6.0
+    hooks:
+      - id: trailing-whitespace
+      - id: check-yaml
+  - repo: https://github.com/astral-sh/ruff-pre-commit
+    rev: v0.6.9
+    hooks:
+      - id: ruff
+        args: [--fix]
+      - id: ruff-format
+  - repo: https://github.com/doublify/pre-commit-rust
+    rev: v1.0
+    hooks:
+      - id: fmt
+  - repo: local
+    hooks:
+      - id: sqlfluff
+        name: sqlfluff
+        entry: sqlfluff lint
+        args: [migrations/]
+        language: system
+      - id: typos
+        entry: typos
+        language: python
+",
+        )
+        .unwrap();
+
+        let parsed = parse_pre_commit(&config);
+        assert_eq!(
+            parsed.formatters,
+            vec![
+                formatter("ruff", "ruff check .", &["ruff"]),
+                formatter("ruff-format", "ruff format --check .", &["ruff"]),
+                formatter("fmt", "cargo fmt --all -- --check", &["cargo", "rustfmt"]),
+                formatter("sqlfluff", "sqlfluff lint migrations/", &["sqlfluff"]),
+            ]
+        );
+        assert_eq!(parsed.tools, vec!["pre-commit"]);
+    }
+
+    #[test]
+    fn test_parse_treefmt() {
+        let config = toml::from_str(
+            r#"
+[formatter.nix]
+command = "nixfmt"
+includes = ["*.nix"]
+
+[formatter.rust]
+command = "rustfmt"
+options = ["--edition", "2021"]
+includes = ["*.rs"]
+
+[formatter.custom]
+command = "./scripts/fmt.sh"
+includes = ["*.sql"]
+"#,
+        )
+        .unwrap();
+
+        let parsed = parse_treefmt(&config);
+        let packages = ["treefmt", "nixfmt-rfc-style", "rustfmt"];
+        assert_eq!(
+            parsed.formatters,
+            vec![formatter(
+                "treefmt",
+                "treefmt --fail-on-change --no-cache",
+                &packages
+            )]
+        );
+        assert_eq!(parsed.tools, packages);
+    }
+}
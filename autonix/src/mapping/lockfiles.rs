@@ -1,5 +1,8 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 
+use serde::Deserialize;
+
 use crate::mapping::{ParseError, Parseable};
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
@@ -35,14 +38,340 @@ pub enum LockFile {
     ComposerLock,
 }
 
-pub struct ParsedLockFile;
+/// Exact dependency graph pinned by a lockfile, every package once per
+/// version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedLockFile {
+    pub kind: LockFile,
+    /// Sorted by name then version
+    pub packages: Vec<LockedPackage>,
+}
+
+/// One node of the graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub source: PackageSource,
+    /// Names of the packages it depends on. go.sum has no edges.
+    pub dependencies: Vec<String>,
+    /// License expression, only package-lock.json records it
+    pub license: Option<String>,
+}
+
+/// Where a locked package comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackageSource {
+    /// A package registry: crates.io index, npm registry, `PyPI`, Go module proxy
+    Registry(String),
+    /// A git repository, at a pinned revision when the lockfile has it
+    Git { url: String, rev: Option<String> },
+    /// Part of the repository itself: workspace member, path dependency
+    Local,
+}
 
 impl Parseable for LockFile {
     type Output = ParsedLockFile;
 
-    fn parse(&self, _path: &Path) -> Result<Self::Output, ParseError> {
-        Ok(ParsedLockFile)
+    fn parse(&self, path: &Path) -> Result<Self::Output, ParseError> {
+        let content = std::fs::read_to_string(path)?;
+        self.parse_content(&content)
+    }
+}
+
+impl LockFile {
+    fn parse_content(self, content: &str) -> Result<ParsedLockFile, ParseError> {
+        let mut packages = match self {
+            Self::CargoLock => parse_cargo_lock(content)?,
+            Self::PackageLockJson => parse_package_lock(content)?,
+            Self::PoetryLock => parse_poetry_lock(content)?,
+            Self::GoSum => parse_go_sum(content),
+
+            // Other lockfiles not yet implemented
+            Self::YarnLock
+            | Self::PnpmLockYaml
+            | Self::BunLockb
+            | Self::PipfileLock
+            | Self::PdmLock
+            | Self::UvLock
+            | Self::GradleLockfile
+            | Self::PackagesLockJson
+            | Self::GemfileLock
+            | Self::ComposerLock => Vec::new(),
+        };
+        packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        packages.dedup_by(|a, b| a.name == b.name && a.version == b.version);
+        Ok(ParsedLockFile {
+            kind: self,
+            packages,
+        })
+    }
+}
+
+// ─── Cargo.lock ────────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct CargoLockToml {
+    #[serde(default)]
+    package: Vec<CargoLockPackage>,
+}
+
+#[derive(Deserialize)]
+struct CargoLockPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+fn parse_cargo_lock(content: &str) -> Result<Vec<LockedPackage>, ParseError> {
+    let lock: CargoLockToml = toml::from_str(content)?;
+    Ok(lock
+        .package
+        .into_iter()
+        .map(|package| LockedPackage {
+            source: package
+                .source
+                .as_deref()
+                .map_or(PackageSource::Local, cargo_source),
+            // "name version" or "name version (source)" when ambiguous
+            dependencies: package
+                .dependencies
+                .iter()
+                .filter_map(|dep| dep.split_whitespace().next())
+                .map(str::to_string)
+                .collect(),
+            name: package.name,
+            version: package.version,
+            license: None,
+        })
+        .collect())
+}
+
+/// `registry+<url>`, `sparse+<url>` or `git+<url>?<query>#<rev>`
+fn cargo_source(source: &str) -> PackageSource {
+    match source.split_once('+') {
+        Some(("git", rest)) => {
+            let (url, rev) = match rest.split_once('#') {
+                Some((url, rev)) => (url, Some(rev.to_string())),
+                None => (rest, None),
+            };
+            let url = url.split_once('?').map_or(url, |(url, _)| url);
+            PackageSource::Git {
+                url: url.to_string(),
+                rev,
+            }
+        }
+        Some((_, url)) => PackageSource::Registry(url.to_string()),
+        None => PackageSource::Registry(source.to_string()),
+    }
+}
+
+// ─── package-lock.json ─────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct PackageLockJson {
+    name: Option<String>,
+    /// lockfileVersion 2 and 3
+    #[serde(default)]
+    packages: BTreeMap<String, PackageLockEntry>,
+    /// lockfileVersion 1, nested by install location
+    #[serde(default)]
+    dependencies: BTreeMap<String, PackageLockEntry>,
+}
+
+#[derive(Deserialize)]
+struct PackageLockEntry {
+    name: Option<String>,
+    version: Option<String>,
+    resolved: Option<String>,
+    #[serde(default)]
+    link: bool,
+    /// SPDX expression, or an object in very old packages
+    #[serde(default)]
+    license: serde_json::Value,
+    /// Ranges by name in lockfileVersion 2 and 3, nested installs in 1
+    #[serde(default)]
+    dependencies: serde_json::Value,
+    /// Ranges by name in lockfileVersion 1
+    #[serde(default)]
+    requires: serde_json::Value,
+}
+
+fn parse_package_lock(content: &str) -> Result<Vec<LockedPackage>, ParseError> {
+    let lock: PackageLockJson = serde_json::from_str(content)?;
+    let mut packages = Vec::new();
+    if lock.packages.is_empty() {
+        collect_package_lock_v1(&lock.dependencies, &mut packages);
+        return Ok(packages);
+    }
+    for (location, entry) in &lock.packages {
+        // "" is the project itself, "node_modules/a/node_modules/@s/b" is @s/b
+        let name = match location.rsplit_once("node_modules/") {
+            Some((_, name)) => name.to_string(),
+            None if location.is_empty() => match entry.name.clone().or(lock.name.clone()) {
+                Some(name) => name,
+                None => continue,
+            },
+            // Workspace member, linked from node_modules
+            None => entry
+                .name
+                .clone()
+                .unwrap_or_else(|| location.rsplit('/').next().unwrap_or(location).to_string()),
+        };
+        let source = if entry.link || !location.contains("node_modules/") {
+            PackageSource::Local
+        } else {
+            npm_source(entry.resolved.as_deref())
+        };
+        packages.push(LockedPackage {
+            name,
+            version: entry.version.clone().unwrap_or_default(),
+            source,
+            dependencies: npm_dependency_names(&entry.dependencies),
+            license: entry.license.as_str().map(str::to_string),
+        });
+    }
+    Ok(packages)
+}
+
+fn collect_package_lock_v1(
+    dependencies: &BTreeMap<String, PackageLockEntry>,
+    packages: &mut Vec<LockedPackage>,
+) {
+    for (name, entry) in dependencies {
+        packages.push(LockedPackage {
+            name: name.clone(),
+            version: entry.version.clone().unwrap_or_default(),
+            source: npm_source(entry.resolved.as_deref()),
+            dependencies: npm_dependency_names(&entry.requires),
+            license: None,
+        });
+        // Nested installs, for versions that conflict with the hoisted one
+        if let Ok(nested) =
+            serde_json::from_value::<BTreeMap<String, PackageLockEntry>>(entry.dependencies.clone())
+        {
+            collect_package_lock_v1(&nested, packages);
+        }
+    }
+}
+
+/// Names of `{"name": "range"}`.
+fn npm_dependency_names(dependencies: &serde_json::Value) -> Vec<String> {
+    dependencies
+        .as_object()
+        .map(|deps| deps.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// The registry of a `resolved` tarball URL, or the git URL and commit.
+fn npm_source(resolved: Option<&str>) -> PackageSource {
+    let Some(resolved) = resolved else {
+        return PackageSource::Local;
+    };
+    if let Some(git) = resolved.strip_prefix("git+") {
+        let (url, rev) = match git.split_once('#') {
+            Some((url, rev)) => (url, Some(rev.to_string())),
+            None => (git, None),
+        };
+        return PackageSource::Git {
+            url: url.to_string(),
+            rev,
+        };
     }
+    if resolved.starts_with("file:") {
+        return PackageSource::Local;
+    }
+    // https://registry.npmjs.org/<name>/-/<name>-<version>.tgz
+    let registry = resolved
+        .split_once("://")
+        .and_then(|(scheme, rest)| {
+            let host = rest.split('/').next()?;
+            Some(format!("{scheme}://{host}"))
+        })
+        .unwrap_or_else(|| resolved.to_string());
+    PackageSource::Registry(registry)
+}
+
+// ─── poetry.lock ───────────────────────────────────────────────────────────
+
+const PYPI: &str = "https://pypi.org/simple";
+
+#[derive(Deserialize)]
+struct PoetryLockToml {
+    #[serde(default)]
+    package: Vec<PoetryLockPackage>,
+}
+
+#[derive(Deserialize)]
+struct PoetryLockPackage {
+    name: String,
+    version: String,
+    source: Option<PoetrySource>,
+    #[serde(default)]
+    dependencies: HashMap<String, toml::Value>,
+}
+
+#[derive(Deserialize)]
+struct PoetrySource {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    resolved_reference: Option<String>,
+}
+
+fn parse_poetry_lock(content: &str) -> Result<Vec<LockedPackage>, ParseError> {
+    let lock: PoetryLockToml = toml::from_str(content)?;
+    Ok(lock
+        .package
+        .into_iter()
+        .map(|package| {
+            let source = match package.source {
+                None => PackageSource::Registry(PYPI.to_string()),
+                Some(source) => match source.kind.as_str() {
+                    "git" => PackageSource::Git {
+                        url: source.url,
+                        rev: source.resolved_reference,
+                    },
+                    "directory" | "file" => PackageSource::Local,
+                    _ => PackageSource::Registry(source.url),
+                },
+            };
+            let mut dependencies: Vec<String> = package.dependencies.into_keys().collect();
+            dependencies.sort();
+            LockedPackage {
+                name: package.name,
+                version: package.version,
+                source,
+                dependencies,
+                license: None,
+            }
+        })
+        .collect())
+}
+
+// ─── go.sum ────────────────────────────────────────────────────────────────
+
+const GO_PROXY: &str = "https://proxy.golang.org";
+
+/// `<module> <version>[/go.mod] <hash>` lines. go.sum lists every module
+/// version the build looked at, not only the selected ones.
+fn parse_go_sum(content: &str) -> Vec<LockedPackage> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (module, version) = (fields.next()?, fields.next()?);
+            Some(LockedPackage {
+                name: module.to_string(),
+                version: version.trim_end_matches("/go.mod").to_string(),
+                source: PackageSource::Registry(GO_PROXY.to_string()),
+                dependencies: Vec::new(),
+                license: None,
+            })
+        })
+        .collect()
 }
 
 impl TryFrom<&str> for LockFile {
@@ -84,3 +413,203 @@ impl TryFrom<&str> for LockFile {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(lock: &ParsedLockFile) -> Vec<(&str, &str)> {
+        lock.packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.version.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_cargo_lock_graph() {
+        let content = r#"
+version = 3
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["serde 1.0.200", "tokio", "gitdep"]
+
+[[package]]
+name = "serde"
+version = "1.0.200"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "tokio"
+version = "1.40.0"
+source = "sparse+https://index.crates.io/"
+
+[[package]]
+name = "gitdep"
+version = "0.3.0"
+source = "git+https://github.com/org/gitdep?branch=main#0123abcd"
+"#;
+        let lock = LockFile::CargoLock.parse_content(content).unwrap();
+        assert_eq!(
+            names(&lock),
+            [
+                ("app", "0.1.0"),
+                ("gitdep", "0.3.0"),
+                ("serde", "1.0.200"),
+                ("tokio", "1.40.0")
+            ]
+        );
+        let app = &lock.packages[0];
+        assert_eq!(app.source, PackageSource::Local);
+        assert_eq!(app.dependencies, ["serde", "tokio", "gitdep"]);
+        assert_eq!(
+            lock.packages[1].source,
+            PackageSource::Git {
+                url: "https://github.com/org/gitdep".to_string(),
+                rev: Some("0123abcd".to_string()),
+            }
+        );
+        assert_eq!(
+            lock.packages[2].source,
+            PackageSource::Registry("https://github.com/rust-lang/crates.io-index".to_string())
+        );
+    }
+
+    #[test]
+    fn test_package_lock_graph() {
+        let content = r#"{
+  "name": "web",
+  "lockfileVersion": 3,
+  "packages": {
+    "": { "name": "web", "version": "1.0.0", "dependencies": { "react": "^18.0.0" } },
+    "node_modules/react": {
+      "version": "18.2.0",
+      "resolved": "https://registry.npmjs.org/react/-/react-18.2.0.tgz",
+      "license": "MIT",
+      "dependencies": { "loose-envify": "^1.1.0" }
+    },
+    "node_modules/loose-envify": {
+      "version": "1.4.0",
+      "resolved": "https://registry.npmjs.org/loose-envify/-/loose-envify-1.4.0.tgz"
+    },
+    "node_modules/react/node_modules/@types/prop-types": {
+      "version": "15.7.5",
+      "resolved": "git+ssh://git@github.com/org/prop-types.git#feedbeef"
+    }
+  }
+}"#;
+        let lock = LockFile::PackageLockJson.parse_content(content).unwrap();
+        assert_eq!(
+            names(&lock),
+            [
+                ("@types/prop-types", "15.7.5"),
+                ("loose-envify", "1.4.0"),
+                ("react", "18.2.0"),
+                ("web", "1.0.0")
+            ]
+        );
+        assert_eq!(
+            lock.packages[0].source,
+            PackageSource::Git {
+                url: "ssh://git@github.com/org/prop-types.git".to_string(),
+                rev: Some("feedbeef".to_string()),
+            }
+        );
+        assert_eq!(
+            lock.packages[2].source,
+            PackageSource::Registry("https://registry.npmjs.org".to_string())
+        );
+        assert_eq!(lock.packages[2].dependencies, ["loose-envify"]);
+        assert_eq!(lock.packages[2].license.as_deref(), Some("MIT"));
+        assert_eq!(lock.packages[1].license, None);
+        assert_eq!(lock.packages[3].source, PackageSource::Local);
+    }
+
+    #[test]
+    fn test_package_lock_v1_graph() {
+        let content = r#"{
+  "name": "legacy",
+  "lockfileVersion": 1,
+  "dependencies": {
+    "a": {
+      "version": "1.0.0",
+      "resolved": "https://registry.npmjs.org/a/-/a-1.0.0.tgz",
+      "requires": { "b": "^2.0.0" },
+      "dependencies": {
+        "b": { "version": "2.0.0", "resolved": "https://registry.npmjs.org/b/-/b-2.0.0.tgz" }
+      }
+    },
+    "b": { "version": "1.0.0", "resolved": "https://registry.npmjs.org/b/-/b-1.0.0.tgz" }
+  }
+}"#;
+        let lock = LockFile::PackageLockJson.parse_content(content).unwrap();
+        assert_eq!(
+            names(&lock),
+            [("a", "1.0.0"), ("b", "1.0.0"), ("b", "2.0.0")]
+        );
+        assert_eq!(lock.packages[0].dependencies, ["b"]);
+    }
+
+    #[test]
+    fn test_poetry_lock_graph() {
+        let content = r#"
+[[package]]
+name = "requests"
+version = "2.31.0"
+
+[package.dependencies]
+urllib3 = ">=1.21.1,<3"
+certifi = ">=2017.4.17"
+
+[[package]]
+name = "internal"
+version = "0.4.0"
+
+[package.source]
+type = "git"
+url = "https://github.com/org/internal.git"
+reference = "main"
+resolved_reference = "c0ffee"
+"#;
+        let lock = LockFile::PoetryLock.parse_content(content).unwrap();
+        assert_eq!(
+            names(&lock),
+            [("internal", "0.4.0"), ("requests", "2.31.0")]
+        );
+        assert_eq!(
+            lock.packages[0].source,
+            PackageSource::Git {
+                url: "https://github.com/org/internal.git".to_string(),
+                rev: Some("c0ffee".to_string()),
+            }
+        );
+        assert_eq!(
+            lock.packages[1].source,
+            PackageSource::Registry(PYPI.to_string())
+        );
+        assert_eq!(lock.packages[1].dependencies, ["certifi", "urllib3"]);
+    }
+
+    #[test]
+    fn test_go_sum_graph() {
+        let content = std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/scan/go_standalone/go.sum"),
+        )
+        .unwrap();
+        let lock = LockFile::GoSum.parse_content(&content).unwrap();
+        assert_eq!(names(&lock), [("github.com/stretchr/testify", "v1.8.4")]);
+        assert_eq!(
+            lock.packages[0].source,
+            PackageSource::Registry(GO_PROXY.to_string())
+        );
+    }
+
+    #[test]
+    fn test_unsupported_lockfile_is_empty() {
+        let lock = LockFile::YarnLock
+            .parse_content("# yarn lockfile v1")
+            .unwrap();
+        assert!(lock.packages.is_empty());
+    }
+}
@@ -116,6 +116,9 @@ pub struct ParsedManifest {
     pub entry_points: Vec<String>,
     pub scripts: HashMap<String, String>,
     pub toolchain_version: Option<String>,
+    /// Names of the packages it depends on, sorted (package.json only)
+    /// Used to link the packages of a monorepo
+    pub dependencies: Vec<String>,
 }
 
 #[derive(Debug, Default)]
@@ -166,6 +169,11 @@ struct PackageJson {
     workspaces: Option<PackageJsonWorkspaces>,
     engines: Option<PackageJsonEngines>,
     bin: Option<PackageJsonBin>,
+    dependencies: Option<HashMap<String, serde_json::Value>>,
+    #[serde(rename = "devDependencies")]
+    dev_dependencies: Option<HashMap<String, serde_json::Value>>,
+    #[serde(rename = "peerDependencies")]
+    peer_dependencies: Option<HashMap<String, serde_json::Value>>,
 }
 
 #[derive(Deserialize)]
@@ -249,6 +257,7 @@ impl Parseable for ManifestFile {
                 entry_points: vec![],
                 scripts: HashMap::new(),
                 toolchain_version: Some(content.trim().to_string()),
+                dependencies: vec![],
             }),
 
             // Not yet implemented - return empty manifest
@@ -286,6 +295,7 @@ impl Default for ParsedManifest {
             entry_points: Vec::new(),
             scripts: HashMap::new(),
             toolchain_version: None,
+            dependencies: Vec::new(),
         }
     }
 }
@@ -332,6 +342,14 @@ fn parse_package_json(content: &str, manifest_type: ManifestFile) -> Result<Pars
 
 impl From<PackageJson> for ParsedManifest {
     fn from(pkg: PackageJson) -> Self {
+        let mut dependencies: Vec<String> = [pkg.dependencies, pkg.dev_dependencies, pkg.peer_dependencies]
+            .into_iter()
+            .flatten()
+            .flat_map(HashMap::into_keys)
+            .collect();
+        dependencies.sort();
+        dependencies.dedup();
+
         let mut result = ParsedManifest {
             manifest_type: ManifestFile::PackageJson,
             names: vec![pkg.name],
@@ -345,6 +363,7 @@ impl From<PackageJson> for ParsedManifest {
             entry_points: Vec::new(),
             scripts: pkg.scripts.unwrap_or_default(),
             toolchain_version: pkg.engines.and_then(|e| e.node),
+            dependencies,
         };
 
         if let Some(ws) = pkg.workspaces {
@@ -415,6 +434,7 @@ impl From<PythonProject> for ParsedManifest {
             entry_points,
             scripts: project.scripts.unwrap_or_default(),
             toolchain_version: project.requires_python,
+            dependencies: Vec::new(),
         }
     }
 }
@@ -442,6 +462,7 @@ fn parse_go_mod(content: &str, manifest_type: ManifestFile) -> Result<ParsedMani
         entry_points: Vec::new(),
         scripts: HashMap::new(),
         toolchain_version,
+        dependencies: Vec::new(),
     })
 }
 
@@ -521,6 +542,7 @@ mod tests {
         assert!(result.entry_points.contains(&"test-cli".to_string()));
         assert!(result.entry_points.contains(&"another-tool".to_string()));
 
+        assert_eq!(result.dependencies, vec!["express", "jest"]);
         assert!(result.workspace_members.is_empty());
     }
 
@@ -8,12 +8,16 @@ mod outils;
 mod containers;
 mod version;
 mod cicdfiles;
+mod envfiles;
+mod formatters;
 
 pub use version::{Version, SemVerParser};
+pub use envfiles::{EnvFile, EnvKind, EnvReference, ParsedEnvFile};
+pub use formatters::{FormatterFile, ParsedFormatterFile};
 pub use cicdfiles::{CiCdFile, CiJob, CiService, CiStep, ParsedCiCdFile};
 pub use containers::{ContainerFile, ContainerService, ParsedContainerFile};
 pub use languages::{Language, PackageManager};
-pub use tasks::{TaskFile, BuildSystem, ParsedTaskFile};
-pub use lockfiles::LockFile;
+pub use tasks::{TaskFile, BuildSystem, ParsedTaskFile, PipelineTask, TaskDependency};
+pub use lockfiles::{LockFile, LockedPackage, PackageSource, ParsedLockFile};
 pub use manifests::{ManifestFile, ParsedManifest};
 pub use outils::{ParseError, Parseable};
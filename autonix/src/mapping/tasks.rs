@@ -1,4 +1,6 @@
-use std::path::Path;
+use std::{collections::BTreeMap, path::Path};
+
+use serde::Deserialize;
 
 use crate::mapping::{ParseError, Parseable};
 
@@ -33,6 +35,11 @@ pub enum TaskFile {
 
     // Task (Go task runner)
     Taskfile,
+
+    // JavaScript monorepo orchestrators
+    NxJson,
+    TurboJson,
+    LernaJson,
 }
 
 /// Build system types that can be auto-detected
@@ -47,6 +54,16 @@ pub enum BuildSystem {
     Rake,
     Ant,
     Task,
+    Nx,
+    Turborepo,
+    Lerna,
+}
+
+impl BuildSystem {
+    /// Whether it runs the tasks of every package of a monorepo
+    pub fn is_orchestrator(&self) -> bool {
+        matches!(self, Self::Nx | Self::Turborepo | Self::Lerna)
+    }
 }
 
 /// Parsed build file information
@@ -60,6 +77,124 @@ pub struct ParsedTaskFile {
 
     /// System dependencies mentioned (e.g., "pkg-config", "openssl")
     pub system_deps: Vec<String>,
+
+    /// Package globs of a monorepo orchestrator (e.g., "packages/*")
+    /// Empty when it uses the package manager's workspaces
+    pub workspaces: Vec<String>,
+
+    /// Tasks configured in a monorepo orchestrator and what they run after
+    pub pipeline: Vec<PipelineTask>,
+}
+
+/// A task of a monorepo pipeline (turbo.json `tasks`, nx.json `targetDefaults`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineTask {
+    /// Task name (e.g., "build", "test")
+    pub name: String,
+
+    /// The only project it is configured for (turbo's "web#build")
+    pub project: Option<String>,
+
+    /// Tasks that must be done first
+    pub depends_on: Vec<TaskDependency>,
+
+    /// Whether it never ends, like a dev server (turbo's `persistent`, Nx's `continuous`)
+    pub persistent: bool,
+}
+
+/// A task that runs before another one of a pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskDependency {
+    /// "^build": the task in every workspace package the project depends on
+    Dependencies(String),
+
+    /// "build": a task of the same project
+    SameProject(String),
+
+    /// "api#build": a task of a given project
+    Project { project: String, task: String },
+}
+
+impl From<&str> for TaskDependency {
+    fn from(dependency: &str) -> Self {
+        if let Some(task) = dependency.strip_prefix('^') {
+            Self::Dependencies(task.to_string())
+        } else if let Some((project, task)) = dependency.split_once('#') {
+            Self::Project {
+                project: project.to_string(),
+                task: task.to_string(),
+            }
+        } else {
+            Self::SameProject(dependency.to_string())
+        }
+    }
+}
+
+// turbo.json structures
+#[derive(Deserialize)]
+struct TurboJson {
+    /// Turborepo 2
+    tasks: Option<BTreeMap<String, TurboTask>>,
+    /// Turborepo 1
+    pipeline: Option<BTreeMap<String, TurboTask>>,
+}
+
+#[derive(Deserialize)]
+struct TurboTask {
+    #[serde(default, rename = "dependsOn")]
+    depends_on: Vec<String>,
+    #[serde(default)]
+    persistent: bool,
+}
+
+// nx.json structures
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NxJson {
+    #[serde(default)]
+    target_defaults: BTreeMap<String, NxTarget>,
+    workspace_layout: Option<NxWorkspaceLayout>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NxTarget {
+    #[serde(default)]
+    depends_on: Vec<NxDependency>,
+    #[serde(default)]
+    continuous: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NxDependency {
+    Name(String),
+    Target {
+        target: String,
+        projects: Option<NxProjects>,
+        #[serde(default)]
+        dependencies: bool,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NxProjects {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NxWorkspaceLayout {
+    apps_dir: Option<String>,
+    libs_dir: Option<String>,
+}
+
+// lerna.json structures
+#[derive(Deserialize)]
+struct LernaJson {
+    packages: Option<Vec<String>>,
 }
 
 //TODO: use the extractors from autonix <https://github.com/davidabram/autonix/blob/main/src/detection/task_runner.rs>
@@ -79,12 +214,14 @@ impl Parseable for TaskFile {
             Self::Rakefile => Some(BuildSystem::Rake),
             Self::AntBuildXml => Some(BuildSystem::Ant),
             Self::Taskfile => Some(BuildSystem::Task),
+            Self::NxJson => Some(BuildSystem::Nx),
+            Self::TurboJson => Some(BuildSystem::Turborepo),
+            Self::LernaJson => Some(BuildSystem::Lerna),
         };
 
         let mut result = ParsedTaskFile {
             build_system,
-            targets: Vec::new(),
-            system_deps: Vec::new(),
+            ..Default::default()
         };
 
         // Extract targets and dependencies based on build system
@@ -95,6 +232,15 @@ impl Parseable for TaskFile {
             Self::CMakeLists => {
                 parse_cmake(&content, &mut result);
             }
+            Self::TurboJson => {
+                parse_turbo_json(&content, &mut result)?;
+            }
+            Self::NxJson => {
+                parse_nx_json(&content, &mut result)?;
+            }
+            Self::LernaJson => {
+                parse_lerna_json(&content, &mut result)?;
+            }
             _ => {
                 // For other build systems, just return the type
             }
@@ -157,6 +303,102 @@ fn parse_cmake(content: &str, result: &mut ParsedTaskFile) {
     }
 }
 
+/// Parse turbo.json to extract its task pipeline
+fn parse_turbo_json(content: &str, result: &mut ParsedTaskFile) -> Result<(), ParseError> {
+    let turbo: TurboJson = serde_json::from_str(content)?;
+
+    for (key, task) in turbo.tasks.or(turbo.pipeline).unwrap_or_default() {
+        let (project, name) = match key.split_once('#') {
+            Some((project, name)) => (Some(project.to_string()), name.to_string()),
+            None => (None, key),
+        };
+        let depends_on = task
+            .depends_on
+            .iter()
+            // "$VAR" are Turborepo 1 environment dependencies, not tasks
+            .filter(|dependency| !dependency.starts_with('$'))
+            .map(|dependency| TaskDependency::from(dependency.as_str()))
+            .collect();
+        push_task(result, name, project, depends_on, task.persistent);
+    }
+
+    Ok(())
+}
+
+/// Parse nx.json to extract its target defaults and workspace layout
+fn parse_nx_json(content: &str, result: &mut ParsedTaskFile) -> Result<(), ParseError> {
+    let nx: NxJson = serde_json::from_str(content)?;
+
+    if let Some(layout) = nx.workspace_layout {
+        for dir in [layout.apps_dir, layout.libs_dir].into_iter().flatten() {
+            result.workspaces.push(format!("{}/*", dir.trim_end_matches('/')));
+        }
+    }
+
+    for (name, target) in nx.target_defaults {
+        // Defaults keyed by executor (e.g., "@nx/vite:test") apply to any target using it
+        if name.contains(':') {
+            continue;
+        }
+        let depends_on = target
+            .depends_on
+            .into_iter()
+            .flat_map(|dependency| match dependency {
+                NxDependency::Name(name) => vec![TaskDependency::from(name.as_str())],
+                NxDependency::Target {
+                    target,
+                    projects,
+                    dependencies,
+                } => match projects {
+                    _ if dependencies => vec![TaskDependency::Dependencies(target)],
+                    Some(NxProjects::One(projects))
+                        if projects == "dependencies" || projects == "{dependencies}" =>
+                    {
+                        vec![TaskDependency::Dependencies(target)]
+                    }
+                    None | Some(NxProjects::One(_)) => vec![TaskDependency::SameProject(target)],
+                    Some(NxProjects::Many(projects)) => projects
+                        .into_iter()
+                        .map(|project| TaskDependency::Project {
+                            project,
+                            task: target.clone(),
+                        })
+                        .collect(),
+                },
+            })
+            .collect();
+        push_task(result, name, None, depends_on, target.continuous);
+    }
+
+    Ok(())
+}
+
+/// Parse lerna.json to extract its package globs
+/// Lerna has no pipeline: it runs a script in every package, after the packages they depend on
+fn parse_lerna_json(content: &str, result: &mut ParsedTaskFile) -> Result<(), ParseError> {
+    let lerna: LernaJson = serde_json::from_str(content)?;
+    result.workspaces = lerna.packages.unwrap_or_default();
+    Ok(())
+}
+
+fn push_task(
+    result: &mut ParsedTaskFile,
+    name: String,
+    project: Option<String>,
+    depends_on: Vec<TaskDependency>,
+    persistent: bool,
+) {
+    if !result.targets.contains(&name) {
+        result.targets.push(name.clone());
+    }
+    result.pipeline.push(PipelineTask {
+        name,
+        project,
+        depends_on,
+        persistent,
+    });
+}
+
 impl TryFrom<&str> for TaskFile {
     type Error = ();
 
@@ -173,7 +415,124 @@ impl TryFrom<&str> for TaskFile {
             "build.zig" => Ok(Self::BuildZig),
             "justfile" => Ok(Self::Justfile),
             "Taskfile.yml" | "Taskfile.yaml" => Ok(Self::Taskfile),
+            "nx.json" => Ok(Self::NxJson),
+            "turbo.json" => Ok(Self::TurboJson),
+            "lerna.json" => Ok(Self::LernaJson),
             _ => Err(()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(parse: fn(&str, &mut ParsedTaskFile) -> Result<(), ParseError>, content: &str) -> ParsedTaskFile {
+        let mut result = ParsedTaskFile::default();
+        parse(content, &mut result).expect("Failed to parse task file");
+        result
+    }
+
+    #[test]
+    fn test_turbo_pipeline() {
+        let result = parsed(
+            parse_turbo_json,
+            r#"{
+                "tasks": {
+                    "build": {"dependsOn": ["^build"], "outputs": ["dist/**"]},
+                    "test": {"dependsOn": ["build", "$CI"]},
+                    "dev": {"cache": false, "persistent": true},
+                    "web#deploy": {"dependsOn": ["api#build", "build"]}
+                }
+            }"#,
+        );
+
+        assert_eq!(result.targets, vec!["build", "dev", "test", "deploy"]);
+        assert!(result.pipeline[1].persistent);
+        assert_eq!(
+            result.pipeline,
+            vec![
+                PipelineTask {
+                    name: "build".to_string(),
+                    project: None,
+                    depends_on: vec![TaskDependency::Dependencies("build".to_string())],
+                    persistent: false,
+                },
+                PipelineTask {
+                    name: "dev".to_string(),
+                    project: None,
+                    depends_on: vec![],
+                    persistent: true,
+                },
+                PipelineTask {
+                    name: "test".to_string(),
+                    project: None,
+                    depends_on: vec![TaskDependency::SameProject("build".to_string())],
+                    persistent: false,
+                },
+                PipelineTask {
+                    name: "deploy".to_string(),
+                    project: Some("web".to_string()),
+                    depends_on: vec![
+                        TaskDependency::Project {
+                            project: "api".to_string(),
+                            task: "build".to_string(),
+                        },
+                        TaskDependency::SameProject("build".to_string()),
+                    ],
+                    persistent: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_turbo_v1_pipeline() {
+        let result = parsed(parse_turbo_json, r#"{"pipeline": {"lint": {}}}"#);
+        assert_eq!(result.targets, vec!["lint"]);
+        assert!(result.pipeline[0].depends_on.is_empty());
+    }
+
+    #[test]
+    fn test_nx_target_defaults() {
+        let result = parsed(
+            parse_nx_json,
+            r#"{
+                "workspaceLayout": {"appsDir": "apps", "libsDir": "libs/"},
+                "targetDefaults": {
+                    "build": {"dependsOn": ["^build"], "cache": true},
+                    "e2e": {"dependsOn": [{"target": "serve", "projects": ["api"]}, {"target": "build"}]},
+                    "test": {"dependsOn": [{"target": "build", "projects": "dependencies"}]},
+                    "@nx/vite:test": {"cache": true}
+                }
+            }"#,
+        );
+
+        assert_eq!(result.workspaces, vec!["apps/*", "libs/*"]);
+        assert_eq!(result.targets, vec!["build", "e2e", "test"]);
+        assert_eq!(
+            result.pipeline[1].depends_on,
+            vec![
+                TaskDependency::Project {
+                    project: "api".to_string(),
+                    task: "serve".to_string(),
+                },
+                TaskDependency::SameProject("build".to_string()),
+            ]
+        );
+        assert_eq!(
+            result.pipeline[2].depends_on,
+            vec![TaskDependency::Dependencies("build".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_lerna_packages() {
+        let result = parsed(
+            parse_lerna_json,
+            r#"{"version": "independent", "packages": ["packages/*", "tools/cli"]}"#,
+        );
+        assert_eq!(result.workspaces, vec!["packages/*", "tools/cli"]);
+        assert!(result.pipeline.is_empty());
+    }
+}
@@ -1,14 +1,20 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     path::PathBuf,
 };
 
+use serde::Serialize;
+
 use crate::{
     mapping::{
-        Language, PackageManager, ParsedCiCdFile, ParsedContainerFile, ParsedManifest,
+        BuildSystem, EnvKind, Language, ManifestFile, PackageManager, ParsedCiCdFile,
+        ParsedContainerFile, ParsedEnvFile, ParsedFormatterFile, ParsedLockFile, ParsedManifest,
         ParsedTaskFile, Version,
     },
-    repo::scan::{Repo, ScanIter},
+    repo::{
+        monorepo::{Monorepo, Scope},
+        scan::{Repo, ScanIter},
+    },
 };
 
 /// Analysis result for the entire repository
@@ -46,6 +52,10 @@ pub struct RepoAnalysis {
 
     /// Check operations (tests, lints, formatting, etc.)
     checks: Checks,
+
+    /// Dependency graphs pinned by the lockfiles, one per lockfile
+    /// Used for license scanning and to pick a vendoring strategy
+    lockfiles: Vec<ParsedLockFile>,
 }
 
 impl From<Repo> for RepoAnalysis {
@@ -64,6 +74,7 @@ impl From<Repo> for RepoAnalysis {
         let packages = Packages::from(&ctx);
         let dev_tools = DevTools::from(&ctx);
         let checks = Checks::from(&ctx);
+        let lockfiles = ctx.lockfiles;
 
         RepoAnalysis {
             name,
@@ -71,6 +82,7 @@ impl From<Repo> for RepoAnalysis {
             packages,
             dev_tools,
             checks,
+            lockfiles,
         }
     }
 }
@@ -84,7 +96,8 @@ impl From<&ExtractionContext<'_>> for Packages {
         let dependencies = extract_dependencies(ctx);
 
         // Create one package per manifest
-        for manifest in &ctx.manifests {
+        // A monorepo's root package.json is replaced by its projects, below
+        for manifest in ctx.manifests.iter().filter(|m| !ctx.is_monorepo_root(m)) {
             let language = Language::from(&manifest.manifest_type);
             let package_manager = PackageManager::from(&manifest.manifest_type);
 
@@ -100,18 +113,44 @@ impl From<&ExtractionContext<'_>> for Packages {
                         version: manifest.toolchain_version.as_deref().into(),
                     },
                     dependencies: dependencies.clone(),
-                    metadata: Metadata {
-                        version: manifest.version.as_deref().into(),
-                        description: manifest.metadata.description.clone(),
-                        authors: manifest.metadata.authors.clone(),
-                        license: manifest.metadata.license.clone(),
-                    },
+                    metadata: Metadata::from(manifest),
+                    depends_on: Vec::new(),
                 };
 
                 packages.push(package);
             }
         }
 
+        // One package per monorepo project, built after the projects it depends on
+        if let Some(monorepo) = &ctx.monorepo {
+            let root_version = ctx
+                .manifests
+                .iter()
+                .find(|m| ctx.is_monorepo_root(m))
+                .and_then(|m| m.toolchain_version.as_deref());
+            for project in monorepo.projects() {
+                let Some(manifest) = project.manifest() else {
+                    continue;
+                };
+                packages.push(Package {
+                    name: project.package_name().to_string(),
+                    path: project.path().to_path_buf(),
+                    toolchain: Toolchain {
+                        language: Language::from(&manifest.manifest_type),
+                        package_manager: PackageManager::from(&manifest.manifest_type),
+                        version: manifest.toolchain_version.as_deref().or(root_version).into(),
+                    },
+                    dependencies: dependencies.clone(),
+                    metadata: Metadata::from(manifest),
+                    depends_on: project
+                        .depends_on(monorepo)
+                        .into_iter()
+                        .map(str::to_string)
+                        .collect(),
+                });
+            }
+        }
+
         Self(packages)
     }
 }
@@ -133,6 +172,9 @@ pub struct Package {
 
     /// Package metadata (version, description, license, etc.)
     metadata: Metadata,
+
+    /// Packages of the same monorepo it is built after
+    depends_on: Vec<String>,
 }
 
 /// Toolchain configuration for the repo
@@ -183,6 +225,33 @@ pub struct DevTools {
 
     dependencies: Dependencies,
     services: Services,
+
+    /// Environment variables the repo expects, sorted by name
+    /// Rendered as devShell env stubs, secrets are reported to be provided
+    variables: Vec<EnvVar>,
+}
+
+/// An environment variable the repo reads
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvVar {
+    name: String,
+
+    kind: EnvKind,
+
+    /// Value to fall back to, from an example or a default; never set for secrets
+    default: Option<String>,
+
+    /// Kinds of files reading it, sorted
+    sources: Vec<EnvSource>,
+}
+
+/// Kind of file an environment variable was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnvSource {
+    Ci,
+    Compose,
+    EnvExample,
 }
 
 ///TODO: a lot of those From from context are very similar and we are duplicating too much info
@@ -191,14 +260,15 @@ impl From<&ExtractionContext<'_>> for DevTools {
         let dependencies = extract_dependencies(ctx);
         let services = extract_services(ctx);
         let env = extract_environment(ctx);
+        let variables = extract_variables(ctx);
 
-        // Tools will be empty for now
+        // Only the formatters and linters the repo configures for now
         // In the future, could extract system-level dev tools like:
         // - rust-analyzer, cargo-watch for Rust
         // - nodePackages.typescript-language-server for Node
         // - python3Packages.python-lsp-server for Python
         // These are NOT language package manager dependencies (npm/cargo/pip)
-        let tools = Vec::new();
+        let tools = extract_tools(ctx);
 
         // Shell hook could be extracted from container entrypoint if needed
         let shell_hook = None;
@@ -209,6 +279,7 @@ impl From<&ExtractionContext<'_>> for DevTools {
             shell_hook,
             dependencies,
             services,
+            variables,
         }
     }
 }
@@ -291,7 +362,14 @@ impl From<&ExtractionContext<'_>> for Checks {
         }
 
         // From task file targets (Makefile, etc.)
-        for task_file in &ctx.task_files {
+        // Monorepo orchestrators' targets are run per project, below
+        let make_files = ctx.task_files.iter().filter(|task_file| {
+            !task_file
+                .build_system
+                .as_ref()
+                .is_some_and(BuildSystem::is_orchestrator)
+        });
+        for task_file in make_files {
             for target in &task_file.targets {
                 // Common check targets
                 if matches!(
@@ -309,8 +387,39 @@ impl From<&ExtractionContext<'_>> for Checks {
             }
         }
 
+        // From monorepo projects, one check per project and task
+        if let Some(monorepo) = &ctx.monorepo {
+            for task in monorepo.tasks() {
+                let mut check = create_check(task.name, task.command, ctx, &dependencies, &services);
+                check.depends_on = task.depends_on;
+                check.scope = monorepo.scope(&task.project);
+                checks.push(check);
+            }
+        }
+
+        // From formatter configs (pre-commit hooks, treefmt), with the packages they run
+        for formatter in ctx.formatters.iter().flat_map(|file| &file.formatters) {
+            let mut check = create_check(
+                formatter.name.clone(),
+                formatter.command.clone(),
+                ctx,
+                &dependencies,
+                &services,
+            );
+            for package in &formatter.packages {
+                if !check.dependencies.0.iter().any(|dep| &dep.name == package) {
+                    check.dependencies.0.push(Dependency {
+                        name: package.clone(),
+                        version: Version::default(),
+                    });
+                }
+            }
+            checks.push(check);
+        }
+
         // From manifest scripts
-        for manifest in &ctx.manifests {
+        // A monorepo's root scripts run every project at once, its projects' checks replace them
+        for manifest in ctx.manifests.iter().filter(|m| !ctx.is_monorepo_root(m)) {
             // Collect and sort script names for deterministic order
             let mut script_checks: Vec<(String, String)> = manifest
                 .scripts
@@ -370,6 +479,8 @@ fn create_check(
         toolchain,
         dependencies: dependencies.clone(),
         services: services.clone(),
+        depends_on: Vec::new(),
+        scope: None,
     }
 }
 
@@ -388,6 +499,12 @@ pub struct Check {
 
     dependencies: Dependencies,
     services: Services,
+
+    /// Checks that must pass first (tasks a monorepo pipeline runs before this one)
+    depends_on: Vec<String>,
+
+    /// Monorepo package it checks, None when it checks the whole repo
+    scope: Option<Scope>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -424,6 +541,17 @@ pub struct Metadata {
     license: Option<String>,
 }
 
+impl From<&ParsedManifest> for Metadata {
+    fn from(manifest: &ParsedManifest) -> Self {
+        Self {
+            version: manifest.version.as_deref().into(),
+            description: manifest.metadata.description.clone(),
+            authors: manifest.metadata.authors.clone(),
+            license: manifest.metadata.license.clone(),
+        }
+    }
+}
+
 /// Context for extraction containing all parsed files
 struct ExtractionContext<'a> {
     repo: &'a Repo,
@@ -431,19 +559,31 @@ struct ExtractionContext<'a> {
     task_files: Vec<ParsedTaskFile>,
     cicd: Vec<ParsedCiCdFile>,
     containers: Vec<ParsedContainerFile>,
+    lockfiles: Vec<ParsedLockFile>,
+    env_files: Vec<ParsedEnvFile>,
+    formatters: Vec<ParsedFormatterFile>,
+    /// Projects and pipeline, when a task file is a monorepo orchestrator (Nx, Turborepo, Lerna)
+    monorepo: Option<Monorepo>,
+}
+
+impl ExtractionContext<'_> {
+    /// Whether `manifest` is the root package.json of a monorepo
+    fn is_monorepo_root(&self, manifest: &ParsedManifest) -> bool {
+        self.monorepo.is_some() && manifest.manifest_type == ManifestFile::PackageJson
+    }
 }
 
 // 'ec for extraction context lifetime
 impl<'ec: 'a, 'a> From<&'ec Repo> for ExtractionContext<'a> {
     fn from(repo: &'ec Repo) -> Self {
         // Parse all file types
-        let parsed_manifests = repo
+        let parsed_manifests: Vec<ParsedManifest> = repo
             .manifest_files()
             .iter()
             .filter_map(|file_path| file_path.parse().ok())
             .collect();
 
-        let parsed_task_files = repo
+        let parsed_task_files: Vec<ParsedTaskFile> = repo
             .task_files()
             .iter()
             .filter_map(|f| f.parse().ok())
@@ -461,6 +601,26 @@ impl<'ec: 'a, 'a> From<&'ec Repo> for ExtractionContext<'a> {
             .filter_map(|f| f.parse().ok())
             .collect();
 
+        let parsed_lockfiles = repo
+            .lockfiles()
+            .iter()
+            .filter_map(|f| f.parse().ok())
+            .collect();
+
+        let parsed_env_files = repo
+            .env_files()
+            .iter()
+            .filter_map(|f| f.parse().ok())
+            .collect();
+
+        let parsed_formatters = repo
+            .formatter_files()
+            .iter()
+            .filter_map(|f| f.parse().ok())
+            .collect();
+
+        let monorepo = Monorepo::discover(repo.path(), &parsed_task_files, &parsed_manifests);
+
         // Build extraction context
         Self {
             repo: &repo,
@@ -468,12 +628,36 @@ impl<'ec: 'a, 'a> From<&'ec Repo> for ExtractionContext<'a> {
             task_files: parsed_task_files,
             cicd: parsed_cicd,
             containers: parsed_containers,
+            lockfiles: parsed_lockfiles,
+            env_files: parsed_env_files,
+            formatters: parsed_formatters,
+            monorepo,
         }
     }
 }
 
 /// Helper functions for extraction
 
+/// Dev shell tools to run the configured formatters by hand, sorted and deduplicated
+fn extract_tools(ctx: &ExtractionContext<'_>) -> Vec<Tool> {
+    let names: BTreeSet<&String> = ctx
+        .formatters
+        .iter()
+        .flat_map(|file| {
+            file.tools
+                .iter()
+                .chain(file.formatters.iter().flat_map(|f| &f.packages))
+        })
+        .collect();
+    names
+        .into_iter()
+        .map(|name| Tool {
+            name: name.clone(),
+            version: Version::default(),
+        })
+        .collect()
+}
+
 /// Extract all system dependencies from context (deduplicated)
 fn extract_dependencies(ctx: &ExtractionContext<'_>) -> Dependencies {
     let mut deps = HashSet::new();
@@ -561,8 +745,15 @@ fn extract_environment(ctx: &ExtractionContext<'_>) -> HashMap<String, String> {
     let mut env = HashMap::new();
 
     // From CI/CD global environment
+    // Values set from CI expressions (e.g., `${{ secrets.X }}`) are variables to provide instead
     for cicd_file in &ctx.cicd {
-        env.extend(cicd_file.env.clone());
+        env.extend(
+            cicd_file
+                .env
+                .iter()
+                .filter(|(_, value)| !value.contains("${{"))
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
     }
 
     // From container environment
@@ -573,6 +764,74 @@ fn extract_environment(ctx: &ExtractionContext<'_>) -> HashMap<String, String> {
     env
 }
 
+/// Extract the environment variables the repo expects, from example env files, CI secrets and
+/// compose interpolations (merged by name, a secret anywhere is a secret)
+fn extract_variables(ctx: &ExtractionContext<'_>) -> Vec<EnvVar> {
+    let mut variables: BTreeMap<String, EnvVar> = BTreeMap::new();
+    let mut add = |name: &str, default: Option<&str>, kind: EnvKind, source: EnvSource| {
+        let variable = variables.entry(name.to_string()).or_insert_with(|| EnvVar {
+            name: name.to_string(),
+            kind,
+            default: None,
+            sources: Vec::new(),
+        });
+        variable.kind = variable.kind.min(kind);
+        if variable.default.is_none() {
+            variable.default = default.map(ToString::to_string);
+        }
+        if variable.kind == EnvKind::Secret {
+            variable.default = None;
+        }
+        if !variable.sources.contains(&source) {
+            variable.sources.push(source);
+            variable.sources.sort();
+        }
+    };
+
+    for env_file in &ctx.env_files {
+        for reference in &env_file.variables {
+            let kind = EnvKind::classify(&reference.name);
+            add(&reference.name, reference.default.as_deref(), kind, EnvSource::EnvExample);
+        }
+    }
+
+    for cicd_file in &ctx.cicd {
+        let env: Vec<(&String, &String)> = cicd_file
+            .env
+            .iter()
+            .chain(cicd_file.jobs.iter().flat_map(|job| &job.env))
+            .filter(|(_, value)| value.contains("${{"))
+            .collect();
+        // A variable set from a CI expression has no usable value in a dev shell
+        for (name, value) in &env {
+            let kind = if value.contains("secrets.") {
+                EnvKind::Secret
+            } else {
+                EnvKind::classify(name)
+            };
+            add(name, None, kind, EnvSource::Ci);
+        }
+        // Secrets only passed to commands, not through a variable
+        for secret in &cicd_file.secrets {
+            let bound = env
+                .iter()
+                .any(|(_, value)| value.contains(&format!("secrets.{secret}")));
+            if !bound {
+                add(secret, None, EnvKind::Secret, EnvSource::Ci);
+            }
+        }
+    }
+
+    for container in &ctx.containers {
+        for reference in &container.env_references {
+            let kind = EnvKind::classify(&reference.name);
+            add(&reference.name, reference.default.as_deref(), kind, EnvSource::Compose);
+        }
+    }
+
+    variables.into_values().collect()
+}
+
 // Accessor methods for private fields (used by flake.rs)
 
 impl Analysis {
@@ -597,6 +856,10 @@ impl RepoAnalysis {
     pub fn checks(&self) -> &Checks {
         &self.checks
     }
+
+    pub fn lockfiles(&self) -> &[ParsedLockFile] {
+        &self.lockfiles
+    }
 }
 
 impl Packages {
@@ -625,6 +888,10 @@ impl Package {
     pub fn metadata(&self) -> &Metadata {
         &self.metadata
     }
+
+    pub fn depends_on(&self) -> &[String] {
+        &self.depends_on
+    }
 }
 
 impl Toolchain {
@@ -650,9 +917,41 @@ impl DevTools {
         &self.services
     }
 
+    pub fn tools(&self) -> &[Tool] {
+        &self.tools
+    }
+
     pub fn env(&self) -> &HashMap<String, String> {
         &self.env
     }
+
+    pub fn variables(&self) -> &[EnvVar] {
+        &self.variables
+    }
+}
+
+impl Tool {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl EnvVar {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> EnvKind {
+        self.kind
+    }
+
+    pub fn default(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    pub fn sources(&self) -> &[EnvSource] {
+        &self.sources
+    }
 }
 
 impl Checks {
@@ -681,6 +980,14 @@ impl Check {
     pub fn services(&self) -> &Services {
         &self.services
     }
+
+    pub fn depends_on(&self) -> &[String] {
+        &self.depends_on
+    }
+
+    pub fn scope(&self) -> Option<&Scope> {
+        self.scope.as_ref()
+    }
 }
 
 impl Services {
@@ -732,6 +1039,7 @@ impl Dependency {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mapping::{LockFile, LockedPackage, PackageSource};
     use crate::repo::scan::Scan;
     use std::path::PathBuf;
 
@@ -742,6 +1050,16 @@ mod tests {
             .join("analysis")
     }
 
+    fn local_package(name: &str, version: &str, dependencies: &[&str]) -> LockedPackage {
+        LockedPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            source: PackageSource::Local,
+            dependencies: dependencies.iter().map(ToString::to_string).collect(),
+            license: None,
+        }
+    }
+
     #[test]
     fn test_rust_workspace_analysis() {
         let rust_project = fixtures_path().join("rust");
@@ -763,6 +1081,7 @@ mod tests {
                     shell_hook: None,
                     dependencies: pkg_config_dep.clone(),
                     services: Services(vec![]),
+                    variables: vec![],
                 },
                 checks: Checks(vec![
                     Check {
@@ -775,6 +1094,8 @@ mod tests {
                         },
                         dependencies: pkg_config_dep.clone(),
                         services: Services(vec![]),
+                        depends_on: vec![],
+                        scope: None,
                     },
                     Check {
                         name: "lint".to_string(),
@@ -786,6 +1107,8 @@ mod tests {
                         },
                         dependencies: pkg_config_dep.clone(),
                         services: Services(vec![]),
+                        depends_on: vec![],
+                        scope: None,
                     },
                     Check {
                         name: "format".to_string(),
@@ -797,6 +1120,8 @@ mod tests {
                         },
                         dependencies: pkg_config_dep.clone(),
                         services: Services(vec![]),
+                        depends_on: vec![],
+                        scope: None,
                     },
                     Check {
                         name: "check".to_string(),
@@ -808,8 +1133,18 @@ mod tests {
                         },
                         dependencies: pkg_config_dep.clone(),
                         services: Services(vec![]),
+                        depends_on: vec![],
+                        scope: None,
                     },
                 ]),
+                lockfiles: vec![ParsedLockFile {
+                    kind: LockFile::CargoLock,
+                    packages: vec![
+                        local_package("myapp-api", "0.2.1", &["myapp-core"]),
+                        local_package("myapp-cli", "0.2.1", &["myapp-core"]),
+                        local_package("myapp-core", "0.2.1", &[]),
+                    ],
+                }],
             },
             RepoAnalysis {
                 name: "myapp-api".to_string(),
@@ -821,8 +1156,10 @@ mod tests {
                     shell_hook: None,
                     dependencies: Dependencies(vec![]),
                     services: Services(vec![]),
+                    variables: vec![],
                 },
                 checks: Checks(vec![]),
+                lockfiles: vec![],
             },
             RepoAnalysis {
                 name: "myapp-cli".to_string(),
@@ -834,8 +1171,10 @@ mod tests {
                     shell_hook: None,
                     dependencies: Dependencies(vec![]),
                     services: Services(vec![]),
+                    variables: vec![],
                 },
                 checks: Checks(vec![]),
+                lockfiles: vec![],
             },
             RepoAnalysis {
                 name: "myapp-core".to_string(),
@@ -847,8 +1186,10 @@ mod tests {
                     shell_hook: None,
                     dependencies: Dependencies(vec![]),
                     services: Services(vec![]),
+                    variables: vec![],
                 },
                 checks: Checks(vec![]),
+                lockfiles: vec![],
             },
         ]);
 
@@ -884,6 +1225,7 @@ mod tests {
                         authors: vec!["Development Team <dev@example.com>".to_string()],
                         license: Some("MIT".to_string()),
                     },
+                    depends_on: vec![],
                 },
                 Package {
                     name: "ml-service".to_string(),
@@ -900,6 +1242,7 @@ mod tests {
                         authors: vec!["ML Team".to_string()],
                         license: Some("MIT".to_string()),
                     },
+                    depends_on: vec![],
                 },
             ]),
             dev_tools: DevTools {
@@ -908,6 +1251,7 @@ mod tests {
                 shell_hook: None,
                 dependencies: shared_deps.clone(),
                 services: Services(vec![]),
+                variables: vec![],
             },
             checks: Checks(vec![
                 Check {
@@ -920,6 +1264,8 @@ mod tests {
                     },
                     dependencies: shared_deps.clone(),
                     services: Services(vec![]),
+                    depends_on: vec![],
+                    scope: None,
                 },
                 Check {
                     name: "lint".to_string(),
@@ -931,6 +1277,8 @@ mod tests {
                     },
                     dependencies: shared_deps.clone(),
                     services: Services(vec![]),
+                    depends_on: vec![],
+                    scope: None,
                 },
                 Check {
                     name: "format".to_string(),
@@ -942,6 +1290,8 @@ mod tests {
                     },
                     dependencies: shared_deps.clone(),
                     services: Services(vec![]),
+                    depends_on: vec![],
+                    scope: None,
                 },
                 Check {
                     name: "check".to_string(),
@@ -953,6 +1303,8 @@ mod tests {
                     },
                     dependencies: shared_deps.clone(),
                     services: Services(vec![]),
+                    depends_on: vec![],
+                    scope: None,
                 },
                 Check {
                     name: "format".to_string(),
@@ -964,6 +1316,8 @@ mod tests {
                     },
                     dependencies: shared_deps.clone(),
                     services: Services(vec![]),
+                    depends_on: vec![],
+                    scope: None,
                 },
                 Check {
                     name: "lint".to_string(),
@@ -975,6 +1329,8 @@ mod tests {
                     },
                     dependencies: shared_deps.clone(),
                     services: Services(vec![]),
+                    depends_on: vec![],
+                    scope: None,
                 },
                 Check {
                     name: "test".to_string(),
@@ -986,6 +1342,8 @@ mod tests {
                     },
                     dependencies: shared_deps.clone(),
                     services: Services(vec![]),
+                    depends_on: vec![],
+                    scope: None,
                 },
                 Check {
                     name: "typecheck".to_string(),
@@ -997,10 +1355,108 @@ mod tests {
                     },
                     dependencies: shared_deps.clone(),
                     services: Services(vec![]),
+                    depends_on: vec![],
+                    scope: None,
                 },
             ]),
+            lockfiles: vec![ParsedLockFile {
+                kind: LockFile::PackageLockJson,
+                packages: vec![local_package("fullstack-monorepo", "1.5.0", &[])],
+            }],
         }]);
 
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_turbo_monorepo_analysis() {
+        let turbo_project = fixtures_path().join("turbo_monorepo");
+        let scan = Scan::from(turbo_project.clone());
+        let result = Analysis::from(scan.into_iter());
+        let root = &result.repos()[0];
+        assert_eq!(root.path, turbo_project);
+
+        // The root package.json is replaced by one package per project
+        let packages: Vec<(&str, &[String])> = root
+            .packages()
+            .iter()
+            .map(|p| (p.name(), p.depends_on()))
+            .collect();
+        assert_eq!(
+            packages,
+            vec![("web", ["@acme/ui".to_string()].as_slice()), ("@acme/ui", [].as_slice())]
+        );
+        assert_eq!(root.packages().0[0].path, turbo_project.join("apps/web"));
+
+        // And its scripts by a check per project and task, ordered by the pipeline
+        let checks: Vec<(&str, &[String])> = root
+            .checks()
+            .iter()
+            .map(|c| (c.name(), c.depends_on()))
+            .collect();
+        assert_eq!(
+            checks,
+            vec![
+                ("acme-ui-build", [].as_slice()),
+                ("acme-ui-lint", [].as_slice()),
+                ("web-build", ["acme-ui-build".to_string()].as_slice()),
+                ("web-lint", [].as_slice()),
+                ("web-test", ["web-build".to_string()].as_slice()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_formatter_configs_analysis() {
+        let project = fixtures_path().join("formatters");
+        let result = Analysis::from(Scan::from(project).into_iter());
+        let repo = &result.repos()[0];
+
+        // A check per known or local hook, and one for treefmt, each with what it runs
+        let checks: Vec<(&str, &str, Vec<&str>)> = repo
+            .checks()
+            .iter()
+            .filter(|c| ["fmt", "clippy", "typos", "treefmt"].contains(&c.name()))
+            .map(|c| {
+                let deps = c.dependencies().iter().map(Dependency::name).collect();
+                (c.name(), c.command(), deps)
+            })
+            .collect();
+        assert_eq!(
+            checks,
+            vec![
+                (
+                    "fmt",
+                    "cargo fmt --all -- --check",
+                    vec!["cargo", "rustfmt"]
+                ),
+                (
+                    "clippy",
+                    "cargo clippy --all-targets -- -D warnings",
+                    vec!["cargo", "clippy"]
+                ),
+                ("typos", "typos", vec!["typos"]),
+                (
+                    "treefmt",
+                    "treefmt --fail-on-change --no-cache",
+                    vec!["treefmt", "nixfmt-rfc-style", "rustfmt"]
+                ),
+            ]
+        );
+
+        // Their tools land in the dev shell, to run them by hand
+        let tools: Vec<&str> = repo.dev_tools().tools().iter().map(Tool::name).collect();
+        assert_eq!(
+            tools,
+            vec![
+                "cargo",
+                "clippy",
+                "nixfmt-rfc-style",
+                "pre-commit",
+                "rustfmt",
+                "treefmt",
+                "typos"
+            ]
+        );
+    }
 }
@@ -0,0 +1,125 @@
+// Report of the environment variables a repo expects, for `init` to show which secrets must be
+// provided before the dev shell or the checks can work. Config variables are listed with the
+// default the devShell stubs them with.
+use serde::Serialize;
+
+use crate::{
+    mapping::EnvKind,
+    repo::analysis::{EnvSource, EnvVar, RepoAnalysis},
+};
+
+/// Variables one repo expects, split by kind
+#[derive(Debug, Serialize)]
+pub struct EnvReport {
+    repo: String,
+    secrets: Vec<RequiredVariable>,
+    config: Vec<RequiredVariable>,
+}
+
+/// A variable to provide, and where it was found
+#[derive(Debug, PartialEq, Serialize)]
+struct RequiredVariable {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+    sources: Vec<EnvSource>,
+}
+
+impl EnvReport {
+    #[must_use]
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+
+    /// Names of the secrets to provide
+    pub fn secrets(&self) -> impl Iterator<Item = &str> {
+        self.secrets.iter().map(|variable| variable.name.as_str())
+    }
+}
+
+impl From<&EnvVar> for RequiredVariable {
+    fn from(variable: &EnvVar) -> Self {
+        Self {
+            name: variable.name().to_string(),
+            default: variable.default().map(ToString::to_string),
+            sources: variable.sources().to_vec(),
+        }
+    }
+}
+
+impl From<&RepoAnalysis> for EnvReport {
+    fn from(repo: &RepoAnalysis) -> Self {
+        let (secrets, config): (Vec<&EnvVar>, Vec<&EnvVar>) = repo
+            .dev_tools()
+            .variables()
+            .iter()
+            .partition(|variable| variable.kind() == EnvKind::Secret);
+        Self {
+            repo: repo.name().to_string(),
+            secrets: secrets.into_iter().map(RequiredVariable::from).collect(),
+            config: config.into_iter().map(RequiredVariable::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::repo::{analysis::Analysis, scan::Scan};
+
+    fn fixtures_path() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("env")
+    }
+
+    #[test]
+    fn test_env_report_from_example_and_compose() {
+        let analysis = Analysis::from(Scan::from(fixtures_path().join("web_app")).into_iter());
+        let report = EnvReport::from(&analysis.repos()[0]);
+
+        assert_eq!(report.repo(), "web_app");
+        assert_eq!(
+            report.secrets().collect::<Vec<_>>(),
+            vec!["DB_PASSWORD", "STRIPE_SECRET_KEY"]
+        );
+        assert_eq!(
+            report.secrets,
+            vec![
+                RequiredVariable {
+                    name: "DB_PASSWORD".to_string(),
+                    default: None,
+                    sources: vec![EnvSource::Compose, EnvSource::EnvExample],
+                },
+                RequiredVariable {
+                    name: "STRIPE_SECRET_KEY".to_string(),
+                    default: None,
+                    sources: vec![EnvSource::EnvExample],
+                },
+            ]
+        );
+        assert_eq!(
+            report.config,
+            vec![
+                RequiredVariable {
+                    name: "DATABASE_URL".to_string(),
+                    default: Some("postgres://app@localhost:5432/app".to_string()),
+                    sources: vec![EnvSource::Compose, EnvSource::EnvExample],
+                },
+                RequiredVariable {
+                    name: "DB_USER".to_string(),
+                    default: Some("app".to_string()),
+                    sources: vec![EnvSource::Compose],
+                },
+                RequiredVariable {
+                    name: "LOG_LEVEL".to_string(),
+                    default: Some("info".to_string()),
+                    sources: vec![EnvSource::EnvExample],
+                },
+            ]
+        );
+    }
+}
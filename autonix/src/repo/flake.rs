@@ -1,17 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use askama::Template;
 use serde::Serialize;
 
 use crate::{
-    mapping::{Language, PackageManager, Version},
-    repo::analysis::{Analysis, Metadata, RepoAnalysis, Service, Toolchain},
+    mapping::{EnvKind, Language, PackageManager, Version},
+    repo::{
+        analysis::{Analysis, EnvSource, EnvVar, Metadata, RepoAnalysis, Service, Toolchain},
+        target::OutputTarget,
+    },
 };
 
 /// Nix flake configuration
 /// Maps one-to-one with a real flake.nix structure
 #[derive(Debug, Serialize, Template)]
-#[template(path = "flake.jinja")]
+#[template(path = "flake.jinja", escape = "none")]
 pub struct Configuration<'write> {
     /// Flake description
     description: String,
@@ -71,6 +74,46 @@ impl<'write> Configuration<'write> {
     pub fn to_nix(&self) -> Result<String, askama::Error> {
         self.render()
     }
+
+    /// The files of the target, by file name
+    ///
+    /// # Errors
+    ///
+    /// Fails when one of the target's templates can't be rendered
+    pub fn render_target(
+        &self,
+        target: OutputTarget,
+    ) -> Result<Vec<(&'static str, String)>, askama::Error> {
+        Ok(match target {
+            OutputTarget::Flake => vec![("flake.nix", self.render()?)],
+            OutputTarget::Devenv => vec![("devenv.nix", DevenvNix { config: self }.render()?)],
+            OutputTarget::Legacy => vec![
+                ("shell.nix", ShellNix { config: self }.render()?),
+                ("default.nix", DefaultNix { config: self }.render()?),
+            ],
+        })
+    }
+}
+
+/// devenv module of the dev shell, its services, and the checks as scripts
+#[derive(Template)]
+#[template(path = "devenv.jinja", escape = "none")]
+struct DevenvNix<'config, 'write> {
+    config: &'config Configuration<'write>,
+}
+
+/// Dev shell for `nix-shell`, without flakes
+#[derive(Template)]
+#[template(path = "shell.jinja", escape = "none")]
+struct ShellNix<'config, 'write> {
+    config: &'config Configuration<'write>,
+}
+
+/// Packages for `nix-build`, without flakes
+#[derive(Template)]
+#[template(path = "default.jinja", escape = "none")]
+struct DefaultNix<'config, 'write> {
+    config: &'config Configuration<'write>,
 }
 
 /// Flake inputs
@@ -110,6 +153,8 @@ struct PackageOutput {
     toolchain: ToolchainConfig,
     dependencies: Vec<String>, // nixpkgs package names
     metadata: MetadataConfig,
+    /// Packages of this flake it is built after
+    depends_on: Vec<String>,
 }
 
 /// Toolchain configuration for building packages
@@ -142,6 +187,9 @@ struct DevShellOutput {
     /// Merged environment variables
     env: HashMap<String, String>,
 
+    /// Variables the repos expect, stubbed in the shell hook
+    variables: Vec<VariableOutput>,
+
     /// Shell hook
     shell_hook: Option<String>,
 
@@ -153,8 +201,35 @@ impl DevShellOutput {
     pub fn shell_hook_str(&self) -> &str {
         self.shell_hook.as_deref().unwrap_or("")
     }
+
+    pub fn shell_hook_lines(&self) -> Vec<&str> {
+        self.shell_hook_str().lines().collect()
+    }
+}
+
+/// Environment variable the dev shell expects
+#[derive(Debug, Serialize)]
+struct VariableOutput {
+    name: String,
+    kind: EnvKind,
+    default: Option<String>,
+    sources: Vec<EnvSource>,
 }
 
+impl From<&EnvVar> for VariableOutput {
+    fn from(variable: &EnvVar) -> Self {
+        Self {
+            name: variable.name().to_string(),
+            kind: variable.kind(),
+            default: variable.default().map(ToString::to_string),
+            sources: variable.sources().to_vec(),
+        }
+    }
+}
+
+/// Value exported for a variable nobody set, checked again to warn about missing secrets
+const PLACEHOLDER: &str = "REPLACE_ME";
+
 /// Check output configuration
 #[derive(Debug, Serialize)]
 struct CheckOutput {
@@ -163,6 +238,8 @@ struct CheckOutput {
     toolchain: ToolchainConfig,
     dependencies: Vec<String>,
     services: Vec<ServiceConfig>,
+    /// Checks of this flake that must pass first
+    depends_on: Vec<String>,
 }
 
 /// Service configuration
@@ -174,6 +251,22 @@ pub struct ServiceConfig {
     env: HashMap<String, String>,
 }
 
+impl ServiceConfig {
+    /// Name of the devenv service module, when devenv has one
+    pub fn devenv_name(&self) -> Option<&'static str> {
+        match self.name.to_lowercase().as_str() {
+            "postgres" | "postgresql" => Some("postgres"),
+            "redis" => Some("redis"),
+            "mysql" | "mariadb" => Some("mysql"),
+            "mongodb" | "mongo" => Some("mongodb"),
+            "elasticsearch" => Some("elasticsearch"),
+            "rabbitmq" => Some("rabbitmq"),
+            "memcached" => Some("memcached"),
+            _ => None,
+        }
+    }
+}
+
 impl From<&Service> for ServiceConfig {
     fn from(service: &Service) -> Self {
         Self {
@@ -275,6 +368,7 @@ fn collect_packages(repos: &[&RepoAnalysis]) -> HashMap<String, PackageOutput> {
                 toolchain: ToolchainConfig::from(package.toolchain()),
                 dependencies: extract_dep_names(package.dependencies()),
                 metadata: MetadataConfig::from(package.metadata()),
+                depends_on: package.depends_on().to_vec(),
             };
             packages.insert(package.name().to_string(), pkg_output);
         }
@@ -289,6 +383,7 @@ fn merge_dev_shells(repos: &[&RepoAnalysis]) -> DevShellOutput {
     let mut toolchains_set = HashSet::new();
     let mut dependencies_set = HashSet::new();
     let mut env = HashMap::new();
+    let mut variables: BTreeMap<String, VariableOutput> = BTreeMap::new();
     let mut services_set = HashSet::new();
 
     for repo in repos {
@@ -306,6 +401,11 @@ fn merge_dev_shells(repos: &[&RepoAnalysis]) -> DevShellOutput {
             dependencies_set.insert(dep);
         }
 
+        // Collect dev tools (formatters and linters the repo configures)
+        for tool in repo.dev_tools().tools() {
+            dependencies_set.insert(tool.name().to_string());
+        }
+
         // Merge environment variables (last one wins, with warning comment)
         for (key, value) in repo.dev_tools().env() {
             if let Some(existing) = env.insert(key.clone(), value.clone()) {
@@ -318,11 +418,37 @@ fn merge_dev_shells(repos: &[&RepoAnalysis]) -> DevShellOutput {
             }
         }
 
+        // Merge expected variables (a secret in any repo stays a secret)
+        for variable in repo.dev_tools().variables() {
+            let merged = variables
+                .entry(variable.name().to_string())
+                .or_insert_with(|| VariableOutput::from(variable));
+            merged.kind = merged.kind.min(variable.kind());
+            if merged.default.is_none() {
+                merged.default = variable.default().map(ToString::to_string);
+            }
+            if merged.kind == EnvKind::Secret {
+                merged.default = None;
+            }
+            for source in variable.sources() {
+                if !merged.sources.contains(source) {
+                    merged.sources.push(*source);
+                }
+            }
+            merged.sources.sort();
+        }
+
         // Collect unique services
         for service in repo.dev_tools().services().iter() {
             services_set.insert(service.name().to_string());
         }
     }
+    // Variables with a known value are plain env
+    let variables: Vec<VariableOutput> = variables
+        .into_values()
+        .filter(|variable| !env.contains_key(&variable.name))
+        .collect();
+    let shell_hook = env_hook(&env, &variables);
 
     // Reconstruct toolchains from unique set
     let mut toolchains = Vec::new();
@@ -353,11 +479,58 @@ fn merge_dev_shells(repos: &[&RepoAnalysis]) -> DevShellOutput {
         toolchains,
         dependencies: dependencies_set.into_iter().collect(),
         env,
-        shell_hook: None,
+        variables,
+        shell_hook,
         services,
     }
 }
 
+/// Shell hook exporting env and variable stubs without overriding what the user already set,
+/// then warning about secrets left to the placeholder; escaped for a Nix `''` string
+fn env_hook(env: &HashMap<String, String>, variables: &[VariableOutput]) -> Option<String> {
+    let mut env: Vec<(&String, &String)> = env.iter().collect();
+    env.sort();
+
+    let mut lines: Vec<String> = env
+        .into_iter()
+        .map(|(name, value)| export_line(name, value))
+        .chain(variables.iter().map(|variable| {
+            export_line(&variable.name, variable.default.as_deref().unwrap_or(PLACEHOLDER))
+        }))
+        .collect();
+
+    let secrets: Vec<&str> = variables
+        .iter()
+        .filter(|variable| variable.kind == EnvKind::Secret)
+        .map(|variable| variable.name.as_str())
+        .collect();
+    if !secrets.is_empty() {
+        lines.push(format!(
+            "for var in {}; do if [ \"${{!var}}\" = {PLACEHOLDER} ]; then echo \"autonix: secret $var is not set\" >&2; fi; done",
+            secrets.join(" ")
+        ));
+    }
+
+    (!lines.is_empty()).then(|| nix_escape(&lines.join("\n")))
+}
+
+/// `export NAME="${NAME:-value}"`, with the value escaped for a double-quoted shell word
+fn export_line(name: &str, value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '"' | '$' | '`' | '}') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    format!("export {name}=\"${{{name}:-{escaped}}}\"")
+}
+
+/// Escape text for a Nix indented string (`''` and `${` are special there)
+fn nix_escape(text: &str) -> String {
+    text.replace("''", "'''").replace("${", "''${")
+}
+
 fn collect_checks(repos: &[&RepoAnalysis]) -> HashMap<String, CheckOutput> {
     let mut checks = HashMap::new();
     let mut name_counts: HashMap<String, usize> = HashMap::new();
@@ -387,6 +560,7 @@ fn collect_checks(repos: &[&RepoAnalysis]) -> HashMap<String, CheckOutput> {
                     .iter()
                     .map(ServiceConfig::from)
                     .collect(),
+                depends_on: check.depends_on().to_vec(),
             };
 
             checks.insert(check_name, check_output);
@@ -491,6 +665,8 @@ fn infer_default_port(service_name: &str) -> Option<u16> {
 mod tests {
     use std::path::PathBuf;
 
+    use super::*;
+
     fn fixtures_path() -> PathBuf {
         PathBuf::from(env!("CARGO_MANIFEST_DIR"))
             .join("tests")
@@ -498,4 +674,123 @@ mod tests {
             .join("analysis")
     }
 
+    fn variable(name: &str, kind: EnvKind, default: Option<&str>) -> VariableOutput {
+        VariableOutput {
+            name: name.to_string(),
+            kind,
+            default: default.map(ToString::to_string),
+            sources: vec![EnvSource::EnvExample],
+        }
+    }
+
+    #[test]
+    fn test_env_hook() {
+        let env = HashMap::from([("RUST_LOG".to_string(), "info".to_string())]);
+        let variables = vec![
+            variable("DATABASE_URL", EnvKind::Config, Some("postgres://${USER}@db")),
+            variable("STRIPE_SECRET_KEY", EnvKind::Secret, None),
+        ];
+
+        let hook = env_hook(&env, &variables).expect("hook");
+        assert_eq!(
+            hook.lines().collect::<Vec<_>>(),
+            vec![
+                r#"export RUST_LOG="''${RUST_LOG:-info}""#,
+                r#"export DATABASE_URL="''${DATABASE_URL:-postgres://\''${USER\}@db}""#,
+                r#"export STRIPE_SECRET_KEY="''${STRIPE_SECRET_KEY:-REPLACE_ME}""#,
+                r#"for var in STRIPE_SECRET_KEY; do if [ "''${!var}" = REPLACE_ME ]; then echo "autonix: secret $var is not set" >&2; fi; done"#,
+            ]
+        );
+        assert_eq!(env_hook(&HashMap::new(), &[]), None);
+    }
+
+    fn toolchain() -> ToolchainConfig {
+        ToolchainConfig {
+            language: Language::Rust,
+            package_manager: PackageManager::Cargo,
+            version: Version::Unknown(None),
+        }
+    }
+
+    fn configuration() -> Configuration<'static> {
+        let api = PackageOutput {
+            name: "api".to_string(),
+            toolchain: toolchain(),
+            dependencies: vec!["openssl".to_string()],
+            metadata: MetadataConfig {
+                version: "0.1.0".to_string(),
+                description: None,
+                authors: Vec::new(),
+                license: None,
+            },
+            depends_on: vec!["core".to_string()],
+        };
+        let postgres = ServiceConfig {
+            name: "postgresql".to_string(),
+            version: None,
+            port: Some(5432),
+            env: HashMap::new(),
+        };
+        let test = CheckOutput {
+            name: "test".to_string(),
+            command: "cargo test".to_string(),
+            toolchain: toolchain(),
+            dependencies: Vec::new(),
+            services: Vec::new(),
+            depends_on: Vec::new(),
+        };
+        let env = HashMap::from([("RUST_LOG".to_string(), "info".to_string())]);
+        Configuration {
+            description: "Auto-generated flake for api".to_string(),
+            inputs: Inputs::default(),
+            outputs: Outputs {
+                packages: HashMap::from([("api".to_string(), api)]),
+                dev_shells: DevShellOutput {
+                    toolchains: vec![toolchain()],
+                    dependencies: vec!["openssl".to_string()],
+                    shell_hook: env_hook(&env, &[]),
+                    env,
+                    variables: Vec::new(),
+                    services: vec![postgres],
+                },
+                checks: HashMap::from([("test".to_string(), test)]),
+                procurator: ProcuratorExtensions {
+                    services: Vec::new(),
+                    project: ProjectMetadata {
+                        name: "api".to_string(),
+                        languages: vec![Language::Rust],
+                        package_managers: vec![PackageManager::Cargo],
+                    },
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn test_render_targets() {
+        let config = configuration();
+        let files =
+            |target| -> Vec<(&str, String)> { config.render_target(target).expect("render") };
+
+        let flake = files(OutputTarget::Flake);
+        assert_eq!(flake[0].0, "flake.nix");
+        assert!(flake[0].1.contains("devShells.default = pkgs.mkShell {"));
+        assert!(flake[0].1.contains(r#"export RUST_LOG="''${RUST_LOG:-info}""#));
+
+        let devenv = files(OutputTarget::Devenv);
+        assert_eq!(devenv[0].0, "devenv.nix");
+        let devenv = &devenv[0].1;
+        assert!(devenv.contains("{ pkgs, ... }:"));
+        assert!(devenv.contains("services.postgres.enable = true;"));
+        assert!(devenv.contains("scripts.\"check-test\".exec = ''"));
+        assert!(devenv.contains(r#"export RUST_LOG="''${RUST_LOG:-info}""#));
+
+        let legacy = files(OutputTarget::Legacy);
+        let names: Vec<&str> = legacy.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["shell.nix", "default.nix"]);
+        assert!(legacy[0].1.contains("pkgs.mkShell {"));
+        assert!(legacy[0].1.contains("openssl"));
+        assert!(legacy[1].1.contains("\"api\" = pkgs.stdenv.mkDerivation {"));
+        assert!(legacy[1].1.contains("packages.\"core\""));
+    }
 }
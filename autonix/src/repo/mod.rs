@@ -7,5 +7,14 @@ mod parser;
 mod scan;
 mod analysis;
 mod flake;
+mod sbom;
+mod monorepo;
+mod env;
+mod package_map;
+mod target;
 
+pub use env::EnvReport;
+pub use package_map::{PackageCheck, PackageMap};
 pub use parser::Parser;
+pub use sbom::{LicenseSummary, Sbom};
+pub use target::OutputTarget;
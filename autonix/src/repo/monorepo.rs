@@ -0,0 +1,600 @@
+// Monorepos whose packages are run by a task orchestrator (Nx, Turborepo, Lerna)
+//
+// Instead of one opaque package, such a repo is a graph of projects: the orchestrator's config
+// (or the package manager's workspaces) lists the directories holding them, their package.json
+// (and Nx's project.json) tell their tasks and which other projects they depend on, and the
+// orchestrator's pipeline tells what each task runs after (e.g., "^build": the build of every
+// project it depends on). Each task becomes its own check, run through the orchestrator for that
+// project only, and depends on the checks the pipeline runs before it.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    mapping::{
+        BuildSystem, ManifestFile, Parseable, ParsedManifest, ParsedTaskFile, PipelineTask,
+        TaskDependency,
+    },
+    repo::scan::should_ignore_dir,
+};
+
+/// Tasks that are checks even when the pipeline doesn't configure them
+const CHECK_TASKS: [&str; 6] = ["build", "test", "lint", "format", "check", "typecheck"];
+
+/// Package globs of Nx and Lerna when nothing configures them
+const NX_DEFAULT_WORKSPACES: [&str; 3] = ["apps/*", "libs/*", "packages/*"];
+const LERNA_DEFAULT_WORKSPACES: [&str; 1] = ["packages/*"];
+
+/// A repo whose packages are run by a monorepo orchestrator
+#[derive(Debug)]
+pub struct Monorepo {
+    orchestrator: BuildSystem,
+    projects: Vec<Project>,
+    pipeline: Vec<PipelineTask>,
+}
+
+/// A project (package) of a monorepo
+#[derive(Debug)]
+pub struct Project {
+    /// Project name, as the orchestrator knows it
+    name: String,
+
+    /// Directory of the project
+    path: PathBuf,
+
+    /// Its package.json, if any (Nx projects may only have a project.json)
+    manifest: Option<ParsedManifest>,
+
+    /// Scripts and Nx targets, sorted
+    tasks: Vec<String>,
+
+    /// Projects of the monorepo it depends on, sorted
+    dependencies: Vec<String>,
+}
+
+/// A task of a project, as a check
+#[derive(Debug, PartialEq)]
+pub struct ProjectTask {
+    /// Check name, "<project>-<task>"
+    pub name: String,
+
+    /// Project it runs the task of
+    pub project: String,
+
+    /// Command running the task of this project only
+    pub command: String,
+
+    /// Checks of the tasks that run before it
+    pub depends_on: Vec<String>,
+}
+
+/// The package a check runs the task of, and the directories whose changes affect it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    /// Package name of the project
+    pub package: String,
+
+    /// Directories of the project and of the projects it depends on, sorted
+    pub paths: Vec<PathBuf>,
+}
+
+// project.json structures (Nx)
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NxProjectJson {
+    name: Option<String>,
+    #[serde(default)]
+    targets: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    implicit_dependencies: Vec<String>,
+}
+
+impl Monorepo {
+    /// The monorepo at `root`, if one of its task files is an orchestrator and it has projects
+    pub fn discover(
+        root: &Path,
+        task_files: &[ParsedTaskFile],
+        manifests: &[ParsedManifest],
+    ) -> Option<Self> {
+        let orchestrators: Vec<&ParsedTaskFile> = task_files
+            .iter()
+            .filter(|task_file| {
+                task_file
+                    .build_system
+                    .as_ref()
+                    .is_some_and(BuildSystem::is_orchestrator)
+            })
+            .collect();
+        // Lerna delegates to Nx when both are configured
+        let orchestrator = [BuildSystem::Nx, BuildSystem::Turborepo, BuildSystem::Lerna]
+            .into_iter()
+            .find(|system| {
+                orchestrators
+                    .iter()
+                    .any(|task_file| task_file.build_system.as_ref() == Some(system))
+            })?;
+
+        let mut patterns: Vec<&str> = orchestrators
+            .iter()
+            .flat_map(|task_file| &task_file.workspaces)
+            .chain(
+                manifests
+                    .iter()
+                    .filter(|manifest| manifest.manifest_type == ManifestFile::PackageJson)
+                    .flat_map(|manifest| &manifest.workspace_members),
+            )
+            .map(String::as_str)
+            .collect();
+        if patterns.is_empty() {
+            patterns = match orchestrator {
+                BuildSystem::Nx => NX_DEFAULT_WORKSPACES.to_vec(),
+                BuildSystem::Lerna => LERNA_DEFAULT_WORKSPACES.to_vec(),
+                _ => Vec::new(),
+            };
+        }
+
+        let projects = read_projects(root, &patterns);
+        if projects.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            orchestrator,
+            projects,
+            pipeline: orchestrators
+                .into_iter()
+                .flat_map(|task_file| task_file.pipeline.iter().cloned())
+                .collect(),
+        })
+    }
+
+    pub fn projects(&self) -> &[Project] {
+        &self.projects
+    }
+
+    /// The tasks of every project that are checks, with what they run after
+    pub fn tasks(&self) -> Vec<ProjectTask> {
+        let included: BTreeSet<(&str, &str)> = self
+            .projects
+            .iter()
+            .flat_map(|project| {
+                project
+                    .tasks
+                    .iter()
+                    .filter(|task| self.is_check(project, task))
+                    .map(|task| (project.name.as_str(), task.as_str()))
+            })
+            .collect();
+
+        included
+            .iter()
+            .map(|&(project_name, task)| {
+                let project = self
+                    .project(project_name)
+                    .expect("included from the projects");
+                let mut depends_on: Vec<String> = self
+                    .dependencies(project, task)
+                    .into_iter()
+                    .flat_map(|dependency| match dependency {
+                        TaskDependency::Dependencies(task) => project
+                            .dependencies
+                            .iter()
+                            .map(|dependency| (dependency.clone(), task.clone()))
+                            .collect(),
+                        TaskDependency::SameProject(task) => vec![(project.name.clone(), task)],
+                        TaskDependency::Project { project, task } => vec![(project, task)],
+                    })
+                    .filter(|(project, task)| included.contains(&(project.as_str(), task.as_str())))
+                    .map(|(project, task)| check_name(&project, &task))
+                    .collect();
+                depends_on.sort();
+                depends_on.dedup();
+
+                ProjectTask {
+                    name: check_name(project_name, task),
+                    project: project_name.to_string(),
+                    command: self.command(project, task),
+                    depends_on,
+                }
+            })
+            .collect()
+    }
+
+    /// What the checks of project `name` cover: the project and every project it depends on,
+    /// directly or not
+    pub fn scope(&self, name: &str) -> Option<Scope> {
+        let project = self.project(name)?;
+        let mut seen = BTreeSet::from([name]);
+        let mut pending = vec![project];
+        let mut paths = Vec::new();
+        while let Some(project) = pending.pop() {
+            paths.push(project.path.clone());
+            for dependency in &project.dependencies {
+                if seen.insert(dependency) {
+                    pending.extend(self.project(dependency));
+                }
+            }
+        }
+        paths.sort();
+        Some(Scope {
+            package: project.package_name().to_string(),
+            paths,
+        })
+    }
+
+    fn project(&self, name: &str) -> Option<&Project> {
+        self.projects.iter().find(|project| project.name == name)
+    }
+
+    /// The pipeline's task for `task` of `project`, the project's own first
+    fn rule(&self, project: &Project, task: &str) -> Option<&PipelineTask> {
+        self.pipeline
+            .iter()
+            .filter(|rule| rule.name == task)
+            .find(|rule| rule.project.as_deref() == Some(&project.name))
+            .or_else(|| {
+                self.pipeline
+                    .iter()
+                    .find(|rule| rule.name == task && rule.project.is_none())
+            })
+    }
+
+    fn is_check(&self, project: &Project, task: &str) -> bool {
+        match (self.rule(project, task), &self.orchestrator) {
+            (Some(rule), _) => !rule.persistent,
+            // Turborepo refuses to run tasks its pipeline doesn't configure
+            (None, BuildSystem::Turborepo) => false,
+            (None, _) => CHECK_TASKS.contains(&task),
+        }
+    }
+
+    fn dependencies(&self, project: &Project, task: &str) -> Vec<TaskDependency> {
+        match (self.rule(project, task), &self.orchestrator) {
+            (Some(rule), _) => rule.depends_on.clone(),
+            // Lerna runs a script after it ran in the packages depended on
+            (None, BuildSystem::Lerna) => vec![TaskDependency::Dependencies(task.to_string())],
+            (None, _) => Vec::new(),
+        }
+    }
+
+    fn command(&self, project: &Project, task: &str) -> String {
+        match self.orchestrator {
+            BuildSystem::Nx => {
+                format!(
+                    "npx nx run {}:{task} --excludeTaskDependencies",
+                    project.name
+                )
+            }
+            BuildSystem::Turborepo => {
+                format!(
+                    "npx turbo run {task} --filter={} --only",
+                    project.package_name()
+                )
+            }
+            _ => format!("npx lerna run {task} --scope {}", project.package_name()),
+        }
+    }
+}
+
+impl Project {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn manifest(&self) -> Option<&ParsedManifest> {
+        self.manifest.as_ref()
+    }
+
+    /// Name of its package, or of the project when it has no package.json
+    pub fn package_name(&self) -> &str {
+        self.manifest
+            .as_ref()
+            .and_then(|manifest| manifest.names.first())
+            .unwrap_or(&self.name)
+    }
+
+    /// Package names of the projects it depends on
+    pub fn depends_on<'m>(&self, monorepo: &'m Monorepo) -> Vec<&'m str> {
+        self.dependencies
+            .iter()
+            .filter_map(|name| monorepo.project(name))
+            .map(Project::package_name)
+            .collect()
+    }
+}
+
+/// Name of the check running `task` of `project`, usable as a flake attribute
+fn check_name(project: &str, task: &str) -> String {
+    format!(
+        "{}-{task}",
+        project.trim_start_matches('@').replace('/', "-")
+    )
+}
+
+/// Projects in the directories matching `patterns`, relative to `root`
+/// Patterns starting with '!' exclude the directories they match
+fn read_projects(root: &Path, patterns: &[&str]) -> Vec<Project> {
+    let mut dirs = BTreeSet::new();
+    for pattern in patterns.iter().filter(|pattern| !pattern.starts_with('!')) {
+        dirs.extend(expand(root, pattern));
+    }
+    for pattern in patterns
+        .iter()
+        .filter_map(|pattern| pattern.strip_prefix('!'))
+    {
+        for dir in expand(root, pattern) {
+            dirs.remove(&dir);
+        }
+    }
+
+    let mut projects: Vec<Project> = dirs
+        .into_iter()
+        .filter(|dir| dir != root)
+        .filter_map(read_project)
+        .collect();
+
+    // Projects are depended on by their package name or, in Nx, their project name
+    let names: BTreeMap<String, String> = projects
+        .iter()
+        .flat_map(|project| {
+            [
+                (project.name.clone(), project.name.clone()),
+                (project.package_name().to_string(), project.name.clone()),
+            ]
+        })
+        .collect();
+    for project in &mut projects {
+        let mut dependencies: Vec<String> = project
+            .dependencies
+            .iter()
+            .filter_map(|dependency| names.get(dependency))
+            .filter(|name| **name != project.name)
+            .cloned()
+            .collect();
+        dependencies.sort();
+        dependencies.dedup();
+        project.dependencies = dependencies;
+    }
+
+    projects
+}
+
+/// The project in `dir`, if it has a package.json or a project.json
+/// Its dependencies are every package it depends on, not only the monorepo's
+fn read_project(dir: PathBuf) -> Option<Project> {
+    let manifest = ManifestFile::PackageJson
+        .parse(&dir.join("package.json"))
+        .ok();
+    let nx_project: Option<NxProjectJson> = std::fs::read_to_string(dir.join("project.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+    if manifest.is_none() && nx_project.is_none() {
+        return None;
+    }
+
+    let name = nx_project
+        .as_ref()
+        .and_then(|project| project.name.clone())
+        .or_else(|| manifest.as_ref().and_then(|m| m.names.first().cloned()))
+        .or_else(|| Some(dir.file_name()?.to_str()?.to_string()))?;
+
+    let mut tasks = BTreeSet::new();
+    let mut dependencies = Vec::new();
+    if let Some(manifest) = &manifest {
+        tasks.extend(manifest.scripts.keys().cloned());
+        dependencies.extend(manifest.dependencies.iter().cloned());
+    }
+    if let Some(nx_project) = nx_project {
+        tasks.extend(nx_project.targets.into_keys());
+        dependencies.extend(nx_project.implicit_dependencies);
+    }
+
+    Some(Project {
+        name,
+        path: dir,
+        manifest,
+        tasks: tasks.into_iter().collect(),
+        dependencies,
+    })
+}
+
+/// Directories under `root` matching `pattern`, whose segments may be "**" (any depth) or
+/// hold '*' wildcards (e.g., "packages/*", "apps/**", "services/api-*")
+fn expand(root: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut dirs = vec![root.to_path_buf()];
+    for segment in pattern
+        .split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+    {
+        dirs = dirs
+            .iter()
+            .flat_map(|dir| match segment {
+                "**" => descendants(dir),
+                _ if segment.contains('*') => subdirs(dir)
+                    .into_iter()
+                    .filter(|subdir| {
+                        subdir
+                            .file_name()
+                            .and_then(|name| name.to_str())
+                            .is_some_and(|name| matches_wildcard(segment, name))
+                    })
+                    .collect(),
+                _ => {
+                    let subdir = dir.join(segment);
+                    if subdir.is_dir() {
+                        vec![subdir]
+                    } else {
+                        vec![]
+                    }
+                }
+            })
+            .collect();
+    }
+    dirs
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && !should_ignore_dir(path))
+        .collect()
+}
+
+/// `dir` and every directory under it
+fn descendants(dir: &Path) -> Vec<PathBuf> {
+    let mut found = vec![dir.to_path_buf()];
+    let mut next = 0;
+    while let Some(dir) = found.get(next) {
+        let children = subdirs(dir);
+        found.extend(children);
+        next += 1;
+    }
+    found
+}
+
+fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixtures_path() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("analysis")
+    }
+
+    fn turbo_monorepo() -> Monorepo {
+        let root = fixtures_path().join("turbo_monorepo");
+        let task_files = vec![
+            crate::mapping::TaskFile::TurboJson
+                .parse(&root.join("turbo.json"))
+                .unwrap(),
+        ];
+        let manifests = vec![
+            ManifestFile::PackageJson
+                .parse(&root.join("package.json"))
+                .unwrap(),
+        ];
+        Monorepo::discover(&root, &task_files, &manifests).unwrap()
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches_wildcard("*", "web"));
+        assert!(matches_wildcard("api-*", "api-users"));
+        assert!(matches_wildcard("*-service", "billing-service"));
+        assert!(matches_wildcard("a*b*c", "axxbyyc"));
+        assert!(!matches_wildcard("api-*", "web"));
+        assert!(!matches_wildcard("a*a", "a"));
+    }
+
+    #[test]
+    fn test_turbo_projects_and_their_dependencies() {
+        let monorepo = turbo_monorepo();
+        let names: Vec<(&str, &str)> = monorepo
+            .projects()
+            .iter()
+            .map(|project| (project.name.as_str(), project.package_name()))
+            .collect();
+        assert_eq!(names, vec![("web", "web"), ("@acme/ui", "@acme/ui")]);
+
+        let web = &monorepo.projects()[0];
+        assert_eq!(web.dependencies, vec!["@acme/ui"]);
+        assert_eq!(web.depends_on(&monorepo), vec!["@acme/ui"]);
+
+        let root = fixtures_path().join("turbo_monorepo");
+        assert_eq!(
+            monorepo.scope("web"),
+            Some(Scope {
+                package: "web".to_string(),
+                paths: vec![root.join("apps/web"), root.join("packages/ui")],
+            })
+        );
+        assert_eq!(
+            monorepo.scope("@acme/ui").unwrap().paths,
+            vec![root.join("packages/ui")]
+        );
+    }
+
+    #[test]
+    fn test_turbo_tasks_follow_the_pipeline() {
+        assert_eq!(
+            turbo_monorepo().tasks(),
+            vec![
+                ProjectTask {
+                    name: "acme-ui-build".to_string(),
+                    project: "@acme/ui".to_string(),
+                    command: "npx turbo run build --filter=@acme/ui --only".to_string(),
+                    depends_on: vec![],
+                },
+                ProjectTask {
+                    name: "acme-ui-lint".to_string(),
+                    project: "@acme/ui".to_string(),
+                    command: "npx turbo run lint --filter=@acme/ui --only".to_string(),
+                    depends_on: vec![],
+                },
+                ProjectTask {
+                    name: "web-build".to_string(),
+                    project: "web".to_string(),
+                    command: "npx turbo run build --filter=web --only".to_string(),
+                    depends_on: vec!["acme-ui-build".to_string()],
+                },
+                ProjectTask {
+                    name: "web-lint".to_string(),
+                    project: "web".to_string(),
+                    command: "npx turbo run lint --filter=web --only".to_string(),
+                    depends_on: vec![],
+                },
+                ProjectTask {
+                    name: "web-test".to_string(),
+                    project: "web".to_string(),
+                    command: "npx turbo run test --filter=web --only".to_string(),
+                    depends_on: vec!["web-build".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lerna_runs_scripts_after_the_packages_depended_on() {
+        let root = fixtures_path().join("turbo_monorepo");
+        let lerna = ParsedTaskFile {
+            build_system: Some(BuildSystem::Lerna),
+            workspaces: vec!["apps/*".to_string(), "packages/*".to_string()],
+            ..Default::default()
+        };
+        let monorepo = Monorepo::discover(&root, &[lerna], &[]).unwrap();
+        let web_build = monorepo
+            .tasks()
+            .into_iter()
+            .find(|task| task.name == "web-build")
+            .unwrap();
+        assert_eq!(web_build.command, "npx lerna run build --scope web");
+        assert_eq!(web_build.depends_on, vec!["acme-ui-build"]);
+    }
+}
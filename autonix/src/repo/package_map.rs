@@ -0,0 +1,158 @@
+// Which monorepo package each check belongs to, for CI to only run the checks of the packages a
+// change touches. A check covers its package and the packages it depends on; checks of the whole
+// repo are left out, they always run. A changed file outside every package (a root lockfile, the
+// flake) may affect any of them, so then no check is left out either.
+use std::path::{Path, PathBuf};
+
+use crate::repo::analysis::Analysis;
+
+/// Checks of monorepo packages, with the directories whose changes affect them
+#[derive(Debug, Default, PartialEq)]
+pub struct PackageMap(Vec<PackageCheck>);
+
+/// A check of one package
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageCheck {
+    check: String,
+    package: String,
+    paths: Vec<PathBuf>,
+}
+
+impl PackageCheck {
+    /// Check name, as in `checks.<system>.<name>`
+    #[must_use]
+    pub fn check(&self) -> &str {
+        &self.check
+    }
+
+    #[must_use]
+    pub fn package(&self) -> &str {
+        &self.package
+    }
+
+    /// Directories of the package and of the packages it depends on
+    #[must_use]
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    fn affected_by(&self, path: &Path) -> bool {
+        self.paths.iter().any(|dir| path.starts_with(dir))
+    }
+}
+
+impl From<&Analysis> for PackageMap {
+    fn from(analysis: &Analysis) -> Self {
+        Self(
+            analysis
+                .repos()
+                .iter()
+                .flat_map(|repo| repo.checks().iter())
+                .filter_map(|check| {
+                    let scope = check.scope()?;
+                    Some(PackageCheck {
+                        check: check.name().to_string(),
+                        package: scope.package.clone(),
+                        paths: scope.paths.clone(),
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+impl PackageMap {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PackageCheck> {
+        self.0.iter()
+    }
+
+    /// The checks none of the `changed` files affects. Paths are under the parsed directory, as
+    /// the analysis found the files (e.g., `<dir>/apps/web/src/main.ts`)
+    #[must_use]
+    pub fn unaffected(&self, changed: &[PathBuf]) -> Vec<&PackageCheck> {
+        let outside = changed
+            .iter()
+            .any(|path| !self.0.iter().any(|check| check.affected_by(path)));
+        if outside {
+            return Vec::new();
+        }
+        self.0
+            .iter()
+            .filter(|check| !changed.iter().any(|path| check.affected_by(path)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::scan::Scan;
+
+    fn turbo_monorepo() -> (PathBuf, PackageMap) {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("analysis")
+            .join("turbo_monorepo");
+        let analysis = Analysis::from(Scan::from(root.clone()).into_iter());
+        (root, PackageMap::from(&analysis))
+    }
+
+    fn names<'m>(checks: &[&'m PackageCheck]) -> Vec<&'m str> {
+        checks.iter().map(|check| check.check()).collect()
+    }
+
+    #[test]
+    fn test_package_checks() {
+        let (root, map) = turbo_monorepo();
+        let checks: Vec<(&str, &str)> = map
+            .iter()
+            .map(|check| (check.check(), check.package()))
+            .collect();
+        assert_eq!(
+            checks,
+            vec![
+                ("acme-ui-build", "@acme/ui"),
+                ("acme-ui-lint", "@acme/ui"),
+                ("web-build", "web"),
+                ("web-lint", "web"),
+                ("web-test", "web"),
+            ]
+        );
+        assert_eq!(
+            map.iter().last().unwrap().paths(),
+            [root.join("apps/web"), root.join("packages/ui")]
+        );
+    }
+
+    #[test]
+    fn test_unaffected_checks() {
+        let (root, map) = turbo_monorepo();
+
+        // A change to the app leaves the library it depends on alone
+        assert_eq!(
+            names(&map.unaffected(&[root.join("apps/web/src/index.ts")])),
+            vec!["acme-ui-build", "acme-ui-lint"]
+        );
+        // A change to the library affects the app too
+        assert!(
+            map.unaffected(&[root.join("packages/ui/index.ts")])
+                .is_empty()
+        );
+        // So does a change outside every package
+        assert!(
+            map.unaffected(&[
+                root.join("apps/web/README.md"),
+                root.join("package-lock.json")
+            ])
+            .is_empty()
+        );
+        // Nothing changed, no check is affected
+        assert_eq!(map.unaffected(&[]).len(), 5);
+    }
+}
@@ -1,8 +1,11 @@
 // The parser module looks for the configuration of a repository. Think of it as a mix between
 // railpack and direnv.
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use super::{analysis::Analysis,flake::Configuration, scan::Scan};
+use super::{
+    analysis::Analysis, env::EnvReport, flake::Configuration, package_map::PackageMap, sbom::Sbom,
+    scan::Scan, target::OutputTarget,
+};
 
 #[derive(Debug)]
 pub struct Parser<T = PathBuf>(T);
@@ -33,6 +36,34 @@ impl Parser<Analysis> {
         Parser(Configuration::from(self.0))
     }
 
+    /// One SBOM per repo that has lockfiles or declares a license
+    pub fn sboms(&self) -> Vec<Sbom> {
+        self.0
+            .repos()
+            .iter()
+            .filter(|repo| {
+                !repo.lockfiles().is_empty()
+                    || repo.packages().iter().any(|p| p.metadata().license().is_some())
+            })
+            .map(Sbom::from)
+            .collect()
+    }
+
+    /// One report per repo that expects environment variables
+    pub fn env_reports(&self) -> Vec<EnvReport> {
+        self.0
+            .repos()
+            .iter()
+            .filter(|repo| !repo.dev_tools().variables().is_empty())
+            .map(EnvReport::from)
+            .collect()
+    }
+
+    /// The checks of every monorepo package, to only run those a change affects
+    #[must_use]
+    pub fn package_map(&self) -> PackageMap {
+        PackageMap::from(&self.0)
+    }
 }
 
 impl Parser<Configuration<'_>> {
@@ -41,6 +72,30 @@ impl Parser<Configuration<'_>> {
         std::fs::write(output, flake)
     }
 
+    /// Write the files of the target into `dir`, returning their paths
+    ///
+    /// # Errors
+    ///
+    /// Fails when a template can't be rendered or a file can't be written
+    pub fn generate_target(
+        &self,
+        target: OutputTarget,
+        dir: &Path,
+    ) -> std::io::Result<Vec<PathBuf>> {
+        let files = self
+            .0
+            .render_target(target)
+            .map_err(std::io::Error::other)?;
+        files
+            .into_iter()
+            .map(|(name, content)| {
+                let path = dir.join(name);
+                std::fs::write(&path, content)?;
+                Ok(path)
+            })
+            .collect()
+    }
+
     pub fn as_nix(&self, output: &PathBuf) -> std::io::Result<()> {
         let flake = ser_nix::to_string(&self.0).expect("");
         std::fs::write(output, flake)
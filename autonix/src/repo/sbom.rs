@@ -0,0 +1,467 @@
+// Software bill of materials of a repo, in the CycloneDX JSON format, and a summary of the
+// licenses it pulls in. Components come from the lockfile graphs, licenses from the manifests
+// for the repo's own packages and from the lockfiles that record them for the dependencies.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    mapping::{LockFile, LockedPackage, PackageSource},
+    repo::analysis::RepoAnalysis,
+};
+
+const SPEC_VERSION: &str = "1.5";
+
+/// SBOM and license summary of one repo
+#[derive(Debug, Serialize)]
+pub struct Sbom {
+    repo: String,
+    licenses: LicenseSummary,
+    bom: Document,
+}
+
+/// Licenses found in a repo
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LicenseSummary {
+    /// Licenses the repo's own manifests declare
+    pub declared: Vec<String>,
+    /// Number of dependencies per license expression
+    pub dependencies: BTreeMap<String, usize>,
+    /// Dependencies whose license is not known from the lockfiles
+    pub unknown: usize,
+}
+
+impl From<&RepoAnalysis> for Sbom {
+    fn from(repo: &RepoAnalysis) -> Self {
+        // The repo's own packages, by name, with the license their manifest declares
+        let own: HashMap<&str, Option<&str>> = repo
+            .packages()
+            .iter()
+            .map(|p| (p.name(), p.metadata().license().as_deref()))
+            .collect();
+
+        let mut components: BTreeMap<String, Component> = BTreeMap::new();
+        let mut edges: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut licenses = LicenseSummary {
+            declared: own
+                .values()
+                .flatten()
+                .map(ToString::to_string)
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+            ..LicenseSummary::default()
+        };
+
+        for lockfile in repo.lockfiles() {
+            let refs = bom_refs(lockfile.kind, &lockfile.packages);
+            for package in &lockfile.packages {
+                let bom_ref = purl(lockfile.kind, package);
+                let license = match package.source {
+                    PackageSource::Local => own.get(package.name.as_str()).copied().flatten(),
+                    _ => package.license.as_deref(),
+                };
+
+                if package.source != PackageSource::Local && !components.contains_key(&bom_ref) {
+                    match license {
+                        Some(license) => {
+                            *licenses
+                                .dependencies
+                                .entry(license.to_string())
+                                .or_default() += 1;
+                        }
+                        None => licenses.unknown += 1,
+                    }
+                }
+
+                edges.entry(bom_ref.clone()).or_default().extend(
+                    package
+                        .dependencies
+                        .iter()
+                        .filter_map(|name| refs.get(name.as_str()).cloned().flatten()),
+                );
+                components
+                    .entry(bom_ref.clone())
+                    .or_insert_with(|| Component::locked(bom_ref, package, license));
+            }
+        }
+
+        // The repo depends on its own packages, that depend on the rest
+        let own_refs: BTreeSet<String> = components
+            .values()
+            .filter(|c| c.kind == "application")
+            .map(|c| c.bom_ref.clone())
+            .collect();
+        edges.insert(repo.name().to_string(), own_refs);
+
+        let root = Component {
+            kind: "application",
+            bom_ref: repo.name().to_string(),
+            name: repo.name().to_string(),
+            version: None,
+            purl: None,
+            licenses: licenses
+                .declared
+                .iter()
+                .map(|l| LicenseChoice::from(l.as_str()))
+                .collect(),
+            external_references: Vec::new(),
+        };
+
+        Self {
+            repo: repo.name().to_string(),
+            licenses,
+            bom: Document {
+                bom_format: "CycloneDX",
+                spec_version: SPEC_VERSION,
+                version: 1,
+                metadata: BomMetadata {
+                    tools: Tools {
+                        components: vec![Component {
+                            kind: "application",
+                            bom_ref: "autonix".to_string(),
+                            name: "autonix".to_string(),
+                            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                            purl: None,
+                            licenses: Vec::new(),
+                            external_references: Vec::new(),
+                        }],
+                    },
+                    component: root,
+                },
+                components: components.into_values().collect(),
+                dependencies: edges
+                    .into_iter()
+                    .map(|(reference, depends_on)| DependencyNode {
+                        reference,
+                        depends_on: depends_on.into_iter().collect(),
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl Sbom {
+    #[must_use]
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+
+    #[must_use]
+    pub fn licenses(&self) -> &LicenseSummary {
+        &self.licenses
+    }
+
+    /// The `CycloneDX` document, pretty printed
+    ///
+    /// # Errors
+    ///
+    /// - never in practice, the document only holds strings and lists
+    pub fn to_cyclonedx_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&self.bom)
+    }
+}
+
+/// bom-ref of every name locked at a single version. Edges only carry names, so a name
+/// locked at several versions can't be resolved and its edges are left out.
+fn bom_refs(kind: LockFile, packages: &[LockedPackage]) -> HashMap<&str, Option<String>> {
+    let mut refs = HashMap::new();
+    for package in packages {
+        refs.entry(package.name.as_str())
+            .and_modify(|r| *r = None)
+            .or_insert_with(|| Some(purl(kind, package)));
+    }
+    refs
+}
+
+/// Package URL, also used as bom-ref: `pkg:<type>/<name>@<version>`
+fn purl(kind: LockFile, package: &LockedPackage) -> String {
+    let (kind, name) = match kind {
+        LockFile::CargoLock => ("cargo", package.name.clone()),
+        // The @ of a scope is escaped
+        LockFile::PackageLockJson => ("npm", package.name.replacen('@', "%40", 1)),
+        // PyPI names are case insensitive, `_` and `-` are equivalent
+        LockFile::PoetryLock => ("pypi", package.name.to_lowercase().replace('_', "-")),
+        LockFile::GoSum => ("golang", package.name.clone()),
+        _ => ("generic", package.name.clone()),
+    };
+    format!("pkg:{kind}/{name}@{}", package.version)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: BomMetadata,
+    components: Vec<Component>,
+    dependencies: Vec<DependencyNode>,
+}
+
+#[derive(Debug, Serialize)]
+struct BomMetadata {
+    tools: Tools,
+    /// The repo itself
+    component: Component,
+}
+
+#[derive(Debug, Serialize)]
+struct Tools {
+    components: Vec<Component>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Component {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(rename = "bom-ref")]
+    bom_ref: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    purl: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    licenses: Vec<LicenseChoice>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    external_references: Vec<ExternalReference>,
+}
+
+impl Component {
+    fn locked(bom_ref: String, package: &LockedPackage, license: Option<&str>) -> Self {
+        let external_references = match &package.source {
+            PackageSource::Git { url, rev } => vec![ExternalReference {
+                kind: "vcs",
+                url: match rev {
+                    Some(rev) => format!("{url}#{rev}"),
+                    None => url.clone(),
+                },
+            }],
+            PackageSource::Registry(url) => vec![ExternalReference {
+                kind: "distribution",
+                url: url.clone(),
+            }],
+            PackageSource::Local => Vec::new(),
+        };
+        Self {
+            kind: match package.source {
+                PackageSource::Local => "application",
+                _ => "library",
+            },
+            purl: Some(bom_ref.clone()),
+            bom_ref,
+            name: package.name.clone(),
+            version: Some(package.version.clone()),
+            licenses: license.map(LicenseChoice::from).into_iter().collect(),
+            external_references,
+        }
+    }
+}
+
+/// An SPDX expression, or the name of a license that isn't one
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum LicenseChoice {
+    Expression { expression: String },
+    License { license: LicenseName },
+}
+
+#[derive(Debug, Serialize)]
+struct LicenseName {
+    name: String,
+}
+
+impl From<&str> for LicenseChoice {
+    fn from(license: &str) -> Self {
+        if is_spdx_expression(license) {
+            Self::Expression {
+                expression: license.to_string(),
+            }
+        } else {
+            Self::License {
+                license: LicenseName {
+                    name: license.to_string(),
+                },
+            }
+        }
+    }
+}
+
+/// Identifiers joined by AND, OR and WITH, parentheses aside. Only the shape is checked,
+/// not that the identifiers are on the SPDX list.
+fn is_spdx_expression(license: &str) -> bool {
+    let mut expect_id = true;
+    for token in license
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|token| !token.is_empty())
+    {
+        let operator = matches!(token, "AND" | "OR" | "WITH");
+        let id = token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+' | ':'));
+        let valid = if expect_id { id && !operator } else { operator };
+        if !valid {
+            return false;
+        }
+        expect_id = !expect_id;
+    }
+    // Not empty, not ending on an operator
+    !expect_id
+}
+
+#[derive(Debug, Serialize)]
+struct ExternalReference {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DependencyNode {
+    #[serde(rename = "ref")]
+    reference: String,
+    depends_on: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{analysis::Analysis, scan::Scan};
+
+    fn write(dir: &std::path::Path, name: &str, content: &str) {
+        std::fs::write(dir.join(name), content).unwrap();
+    }
+
+    #[test]
+    fn test_sbom_from_lockfiles() {
+        let dir = std::env::temp_dir().join(format!("autonix-sbom-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write(
+            &dir,
+            "Cargo.toml",
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\nlicense = \"MIT OR Apache-2.0\"\n",
+        );
+        write(
+            &dir,
+            "Cargo.lock",
+            r#"
+version = 3
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["serde", "gitdep"]
+
+[[package]]
+name = "serde"
+version = "1.0.200"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "gitdep"
+version = "0.3.0"
+source = "git+https://github.com/org/gitdep#0123abcd"
+"#,
+        );
+
+        let analysis = Analysis::from(Scan::from(dir.clone()).into_iter());
+        let sbom = Sbom::from(&analysis.repos()[0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            sbom.licenses(),
+            &LicenseSummary {
+                declared: vec!["MIT OR Apache-2.0".to_string()],
+                dependencies: BTreeMap::new(),
+                unknown: 2,
+            }
+        );
+
+        let bom: serde_json::Value =
+            serde_json::from_str(&sbom.to_cyclonedx_json().unwrap()).unwrap();
+        assert_eq!(bom["bomFormat"], "CycloneDX");
+        assert_eq!(bom["specVersion"], SPEC_VERSION);
+        assert_eq!(
+            bom["metadata"]["component"]["licenses"][0]["expression"],
+            "MIT OR Apache-2.0"
+        );
+        let refs: Vec<&str> = bom["components"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["bom-ref"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            refs,
+            [
+                "pkg:cargo/app@0.1.0",
+                "pkg:cargo/gitdep@0.3.0",
+                "pkg:cargo/serde@1.0.200"
+            ]
+        );
+        assert_eq!(bom["components"][0]["type"], "application");
+        assert_eq!(
+            bom["components"][1]["externalReferences"][0]["url"],
+            "https://github.com/org/gitdep#0123abcd"
+        );
+        let app = bom["dependencies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|d| d["ref"] == "pkg:cargo/app@0.1.0")
+            .unwrap();
+        assert_eq!(
+            app["dependsOn"],
+            serde_json::json!(["pkg:cargo/gitdep@0.3.0", "pkg:cargo/serde@1.0.200"])
+        );
+    }
+
+    #[test]
+    fn test_purls_and_ambiguous_names() {
+        let package = |name: &str, version: &str| LockedPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            source: PackageSource::Registry("https://registry.npmjs.org".to_string()),
+            dependencies: Vec::new(),
+            license: None,
+        };
+        let packages = [
+            package("@scope/a", "1.0.0"),
+            package("b", "1.0.0"),
+            package("b", "2.0.0"),
+        ];
+        assert_eq!(
+            purl(LockFile::PackageLockJson, &packages[0]),
+            "pkg:npm/%40scope/a@1.0.0"
+        );
+        let refs = bom_refs(LockFile::PackageLockJson, &packages);
+        assert_eq!(
+            refs["@scope/a"].as_deref(),
+            Some("pkg:npm/%40scope/a@1.0.0")
+        );
+        assert_eq!(refs["b"], None);
+    }
+
+    #[test]
+    fn test_license_choice() {
+        let json = |license| serde_json::to_value(LicenseChoice::from(license)).unwrap();
+        assert_eq!(
+            json("(MIT OR Apache-2.0) AND BSD-3-Clause"),
+            serde_json::json!({"expression": "(MIT OR Apache-2.0) AND BSD-3-Clause"})
+        );
+        assert_eq!(
+            json("Apache-2.0 WITH LLVM-exception"),
+            serde_json::json!({"expression": "Apache-2.0 WITH LLVM-exception"})
+        );
+        assert_eq!(
+            json("SEE LICENSE IN LICENSE.txt"),
+            serde_json::json!({"license": {"name": "SEE LICENSE IN LICENSE.txt"}})
+        );
+        assert!(!is_spdx_expression("MIT OR"));
+        assert!(!is_spdx_expression(""));
+    }
+}
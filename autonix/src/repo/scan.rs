@@ -8,7 +8,8 @@ use std::{
 };
 
 use crate::mapping::{
-    TaskFile, CiCdFile, ContainerFile, Language, LockFile, ManifestFile, ParseError, Parseable
+    TaskFile, CiCdFile, ContainerFile, EnvFile, FormatterFile, Language, LockFile, ManifestFile,
+    ParseError, Parseable
 };
 
 const IGNORED_DIR_BASENAMES: [&str; 32] = [
@@ -51,7 +52,7 @@ const IGNORED_DIR_BASENAMES: [&str; 32] = [
     ".terraform",
 ];
 
-fn should_ignore_dir(path: &Path) -> bool {
+pub(super) fn should_ignore_dir(path: &Path) -> bool {
     path.file_name()
         .and_then(|name| name.to_str())
         .map(|name| IGNORED_DIR_BASENAMES.contains(&name))
@@ -90,6 +91,12 @@ impl Repo {
     pub fn container_files(&self) -> &Vec<FilePath<ContainerFile>> {
         &self.files.container_files
     }
+    pub fn env_files(&self) -> &Vec<FilePath<EnvFile>> {
+        &self.files.env_files
+    }
+    pub fn formatter_files(&self) -> &Vec<FilePath<FormatterFile>> {
+        &self.files.formatter_files
+    }
     pub fn file_per_language(&self) -> &HashMap<Language, u16> {
         &self.files.file_per_language
     }
@@ -231,6 +238,10 @@ pub struct DirectoryScan {
     file_per_language: HashMap<Language, u16>, // NOTE: maybe if we found one file of a lanauge, like python, it's just a script to build or execute some tests, we might want to know that we need the interpreter installed?
     /// Container files are meant to build containers to deploy an application
     container_files: Vec<FilePath<ContainerFile>>,
+    /// Example environment files (.env.example) telling what variables the project expects
+    env_files: Vec<FilePath<EnvFile>>,
+    /// Formatter configurations (.pre-commit-config.yaml, treefmt.toml) gating the sources
+    formatter_files: Vec<FilePath<FormatterFile>>,
     //TODO: maybe scan secrets too?
     //TODO: get or check for infra files
 }
@@ -243,6 +254,8 @@ impl AddAssign<PathBuf> for DirectoryScan {
             FileType::TaskFile(p) => self.task_files.push(p),
             FileType::CicdFile(p) => self.cicd_files.push(p),
             FileType::ContainerFile(p) => self.container_files.push(p),
+            FileType::EnvFile(p) => self.env_files.push(p),
+            FileType::FormatterFile(p) => self.formatter_files.push(p),
             FileType::Regular(lang) => {
                 *self.file_per_language.entry(lang).or_insert(0) += 1;
             }
@@ -260,6 +273,10 @@ impl AddAssign<DirectoryScan> for DirectoryScan {
 
         // Note: We don't merge manifests, lockfiles, task_files, or cicd_files
         // because the directory merged shouldn't have any of them, otherwise it would be "interesting"
+
+        // Env and formatter files don't make a directory interesting, they belong to the repo above
+        self.env_files.extend(other.env_files);
+        self.formatter_files.extend(other.formatter_files);
     }
 }
 
@@ -281,6 +298,8 @@ enum FileType {
     TaskFile(FilePath<TaskFile>),
     CicdFile(FilePath<CiCdFile>),
     ContainerFile(FilePath<ContainerFile>),
+    EnvFile(FilePath<EnvFile>),
+    FormatterFile(FilePath<FormatterFile>),
     Regular(Language),
     Unknown,
 }
@@ -307,6 +326,18 @@ impl From<PathBuf> for FileType {
             return Self::CicdFile(FilePath { kind, path });
         }
 
+        if let Ok(kind) = ContainerFile::try_from(filename) {
+            return Self::ContainerFile(FilePath { kind, path });
+        }
+
+        if let Ok(kind) = EnvFile::try_from(filename) {
+            return Self::EnvFile(FilePath { kind, path });
+        }
+
+        if let Ok(kind) = FormatterFile::try_from(filename) {
+            return Self::FormatterFile(FilePath { kind, path });
+        }
+
         if let Some(language) = path
             .extension()
             .and_then(OsStr::to_str)
@@ -347,6 +378,8 @@ mod tests {
                     task_files: vec![],
                     cicd_files: vec![],
                     container_files: vec![],
+                    env_files: vec![],
+                    formatter_files: vec![],
                     file_per_language: HashMap::from([(Language::Rust, 1)]),
                 },
                 children: vec![],
@@ -377,6 +410,8 @@ mod tests {
                     task_files: vec![],
                     cicd_files: vec![],
                     container_files: vec![],
+                    env_files: vec![],
+                    formatter_files: vec![],
                     file_per_language: HashMap::new(),
                 },
                 children: vec![ScanNode {
@@ -387,6 +422,8 @@ mod tests {
                         task_files: vec![],
                         cicd_files: vec![],
                         container_files: vec![],
+                        env_files: vec![],
+                        formatter_files: vec![],
                         file_per_language: HashMap::new(),
                     },
                     children: vec![
@@ -401,6 +438,8 @@ mod tests {
                                 task_files: vec![],
                                 cicd_files: vec![],
                                 container_files: vec![],
+                                env_files: vec![],
+                                formatter_files: vec![],
                                 file_per_language: HashMap::from([(Language::Rust, 1)]),
                             },
                             children: vec![],
@@ -416,6 +455,8 @@ mod tests {
                                 task_files: vec![],
                                 cicd_files: vec![],
                                 container_files: vec![],
+                                env_files: vec![],
+                                formatter_files: vec![],
                                 file_per_language: HashMap::from([(Language::Rust, 1)]),
                             },
                             children: vec![],
@@ -452,6 +493,8 @@ mod tests {
                     task_files: vec![],
                     cicd_files: vec![],
                     container_files: vec![],
+                    env_files: vec![],
+                    formatter_files: vec![],
                     file_per_language: HashMap::new(),
                 },
                 children: vec![ScanNode {
@@ -462,6 +505,8 @@ mod tests {
                         task_files: vec![],
                         cicd_files: vec![],
                         container_files: vec![],
+                        env_files: vec![],
+                        formatter_files: vec![],
                         file_per_language: HashMap::new(),
                     },
                     children: vec![
@@ -476,6 +521,8 @@ mod tests {
                                 task_files: vec![],
                                 cicd_files: vec![],
                                 container_files: vec![],
+                                env_files: vec![],
+                                formatter_files: vec![],
                                 file_per_language: HashMap::from([(Language::JavaScript, 1)]),
                             },
                             children: vec![],
@@ -491,6 +538,8 @@ mod tests {
                                 task_files: vec![],
                                 cicd_files: vec![],
                                 container_files: vec![],
+                                env_files: vec![],
+                                formatter_files: vec![],
                                 file_per_language: HashMap::from([(Language::JavaScript, 1)]),
                             },
                             children: vec![],
@@ -523,6 +572,8 @@ mod tests {
                     task_files: vec![],
                     cicd_files: vec![],
                     container_files: vec![],
+                    env_files: vec![],
+                    formatter_files: vec![],
                     file_per_language: HashMap::from([(Language::Python, 1)]),
                 },
                 children: vec![],
@@ -552,6 +603,8 @@ mod tests {
                     task_files: vec![],
                     cicd_files: vec![],
                     container_files: vec![],
+                    env_files: vec![],
+                    formatter_files: vec![],
                     file_per_language: HashMap::from([(Language::Go, 1)]),
                 },
                 children: vec![],
@@ -578,6 +631,8 @@ mod tests {
                     task_files: vec![],
                     cicd_files: vec![],
                     container_files: vec![],
+                    env_files: vec![],
+                    formatter_files: vec![],
                     file_per_language: HashMap::new(),
                 },
                 children: vec![
@@ -592,6 +647,8 @@ mod tests {
                             task_files: vec![],
                             cicd_files: vec![],
                             container_files: vec![],
+                            env_files: vec![],
+                            formatter_files: vec![],
                             file_per_language: HashMap::from([(Language::Go, 1)]),
                         },
                         children: vec![],
@@ -607,6 +664,8 @@ mod tests {
                             task_files: vec![],
                             cicd_files: vec![],
                             container_files: vec![],
+                            env_files: vec![],
+                            formatter_files: vec![],
                             file_per_language: HashMap::from([(Language::Go, 1)]),
                         },
                         children: vec![],
@@ -641,6 +700,8 @@ mod tests {
                     task_files: vec![],
                     cicd_files: vec![],
                     container_files: vec![],
+                    env_files: vec![],
+                    formatter_files: vec![],
                     file_per_language: HashMap::from([
                         (Language::JavaScript, 1),
                         (Language::Rust, 1),
@@ -671,6 +732,8 @@ mod tests {
                     task_files: vec![],
                     cicd_files: vec![],
                     container_files: vec![],
+                    env_files: vec![],
+                    formatter_files: vec![],
                     file_per_language: HashMap::new(),
                 },
                 children: vec![
@@ -685,6 +748,8 @@ mod tests {
                             task_files: vec![],
                             cicd_files: vec![],
                             container_files: vec![],
+                            env_files: vec![],
+                            formatter_files: vec![],
                             file_per_language: HashMap::from([(Language::Python, 1)]),
                         },
                         children: vec![],
@@ -700,6 +765,8 @@ mod tests {
                             task_files: vec![],
                             cicd_files: vec![],
                             container_files: vec![],
+                            env_files: vec![],
+                            formatter_files: vec![],
                             file_per_language: HashMap::from([(Language::Rust, 1)]),
                         },
                         children: vec![],
@@ -718,6 +785,8 @@ mod tests {
                             task_files: vec![],
                             cicd_files: vec![],
                             container_files: vec![],
+                            env_files: vec![],
+                            formatter_files: vec![],
                             file_per_language: HashMap::from([(Language::JavaScript, 1)]),
                         },
                         children: vec![],
@@ -750,6 +819,8 @@ mod tests {
                     task_files: vec![],
                     cicd_files: vec![],
                     container_files: vec![],
+                    env_files: vec![],
+                    formatter_files: vec![],
                     file_per_language: HashMap::new(),
                 },
                 children: vec![
@@ -764,6 +835,8 @@ mod tests {
                             task_files: vec![],
                             cicd_files: vec![],
                             container_files: vec![],
+                            env_files: vec![],
+                            formatter_files: vec![],
                             file_per_language: HashMap::from([(Language::Go, 1)]),
                         },
                         children: vec![ScanNode {
@@ -774,6 +847,8 @@ mod tests {
                                 task_files: vec![],
                                 cicd_files: vec![],
                                 container_files: vec![],
+                                env_files: vec![],
+                                formatter_files: vec![],
                                 file_per_language: HashMap::new(),
                             },
                             children: vec![ScanNode {
@@ -787,6 +862,8 @@ mod tests {
                                     task_files: vec![],
                                     cicd_files: vec![],
                                     container_files: vec![],
+                                    env_files: vec![],
+                                    formatter_files: vec![],
                                     file_per_language: HashMap::from([(Language::JavaScript, 1)]),
                                 },
                                 children: vec![],
@@ -804,6 +881,8 @@ mod tests {
                             task_files: vec![],
                             cicd_files: vec![],
                             container_files: vec![],
+                            env_files: vec![],
+                            formatter_files: vec![],
                             file_per_language: HashMap::new(),
                         },
                         children: vec![
@@ -818,6 +897,8 @@ mod tests {
                                     task_files: vec![],
                                     cicd_files: vec![],
                                     container_files: vec![],
+                                    env_files: vec![],
+                                    formatter_files: vec![],
                                     file_per_language: HashMap::from([(Language::Rust, 5)]),
                                 },
                                 children: vec![],
@@ -833,6 +914,8 @@ mod tests {
                                     task_files: vec![],
                                     cicd_files: vec![],
                                     container_files: vec![],
+                                    env_files: vec![],
+                                    formatter_files: vec![],
                                     file_per_language: HashMap::from([(Language::Rust, 1)]),
                                 },
                                 children: vec![],
@@ -868,6 +951,8 @@ mod tests {
                     task_files: vec![],
                     cicd_files: vec![],
                     container_files: vec![],
+                    env_files: vec![],
+                    formatter_files: vec![],
                     file_per_language: HashMap::new(),
                 },
                 children: vec![
@@ -882,6 +967,8 @@ mod tests {
                             task_files: vec![],
                             cicd_files: vec![],
                             container_files: vec![],
+                            env_files: vec![],
+                            formatter_files: vec![],
                             file_per_language: HashMap::from([(Language::Rust, 1)]),
                         },
                         children: vec![],
@@ -897,6 +984,8 @@ mod tests {
                             task_files: vec![],
                             cicd_files: vec![],
                             container_files: vec![],
+                            env_files: vec![],
+                            formatter_files: vec![],
                             file_per_language: HashMap::new(),
                         },
                         children: vec![
@@ -911,6 +1000,8 @@ mod tests {
                                     task_files: vec![],
                                     cicd_files: vec![],
                                     container_files: vec![],
+                                    env_files: vec![],
+                                    formatter_files: vec![],
                                     file_per_language: HashMap::from([(Language::Rust, 5)]),
                                 },
                                 children: vec![],
@@ -926,6 +1017,8 @@ mod tests {
                                     task_files: vec![],
                                     cicd_files: vec![],
                                     container_files: vec![],
+                                    env_files: vec![],
+                                    formatter_files: vec![],
                                     file_per_language: HashMap::from([(Language::Rust, 1)]),
                                 },
                                 children: vec![],
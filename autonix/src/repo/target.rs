@@ -0,0 +1,47 @@
+// The output target is the kind of Nix file a configuration is rendered to. Every target renders
+// the same configuration, merged once from the analysis, only the templates differ.
+use std::{fmt, str::FromStr};
+
+/// Nix files a configuration is rendered to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputTarget {
+    /// `flake.nix` with the packages, dev shell, checks and procurator extensions
+    #[default]
+    Flake,
+    /// `devenv.nix` with the dev shell, its services, and the checks as scripts
+    Devenv,
+    /// `shell.nix` for the dev shell and `default.nix` for the packages, for setups without flakes
+    Legacy,
+}
+
+impl OutputTarget {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OutputTarget::Flake => "flake",
+            OutputTarget::Devenv => "devenv",
+            OutputTarget::Legacy => "legacy",
+        }
+    }
+}
+
+impl fmt::Display for OutputTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OutputTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flake" => Ok(OutputTarget::Flake),
+            "devenv" => Ok(OutputTarget::Devenv),
+            "legacy" => Ok(OutputTarget::Legacy),
+            other => Err(format!(
+                "unknown output target {other:?}, expected flake, devenv or legacy"
+            )),
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! # Build logs
+//!
+//! `GET /log/{name}` serves the build log of a store path, as `nix log`
+//! fetches it from substituters. `name` is the store path's base name,
+//! `<hash>-<name>`, of either a derivation or one of its outputs; anything
+//! else, such as a `..` or a nested path, is refused before the store is
+//! asked.
+//!
+//! The log comes from `nix log`, at most [`DEFAULT_FETCHES`] at once. With
+//! `CACHE_LOG_DIR` set, each log is kept there gzipped and served from disk
+//! afterwards, so CI UIs linking to it do not spawn `nix log` on every
+//! view. A log is indexed by the hash of the path it was asked for and, for
+//! a derivation, by the hash of each of its outputs: once fetched, it is
+//! also served as `/log/<output hash>` alone.
+//!
+//! - `<hash>.log.gz` — a log, gzipped
+//! - `tmp/` — logs being written, cleared on open
+//!
+//! Clients accepting gzip get the log as stored, with
+//! `Content-Encoding: gzip`; others get it decompressed.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+/// `nix log` processes running at once, by default.
+pub const DEFAULT_FETCHES: usize = 4;
+
+/// Length of the hash part of a store path.
+const HASH_LEN: usize = 32;
+/// Longest name after the hash part that Nix accepts.
+const MAX_NAME_LEN: usize = 211;
+/// Alphabet of store path hashes, Nix's base32.
+const HASH_ALPHABET: &str = "0123456789abcdfghijklmnpqrsvwxyz";
+
+/// What a `/log/` request asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRequest<'a> {
+    /// Hash part of the store path
+    pub hash: &'a str,
+    /// The whole base name; `None` for a bare hash, which is only served
+    /// from the log store
+    pub base_name: Option<&'a str>,
+}
+
+impl<'a> LogRequest<'a> {
+    /// Parse `<hash>-<name>` or a bare `<hash>`; `None` for anything that is
+    /// not a store path base name.
+    #[must_use]
+    pub fn parse(raw: &'a str) -> Option<Self> {
+        let hash = raw.get(..HASH_LEN)?;
+        if !hash.chars().all(|c| HASH_ALPHABET.contains(c)) {
+            return None;
+        }
+        let rest = &raw[HASH_LEN..];
+        if rest.is_empty() {
+            return Some(Self {
+                hash,
+                base_name: None,
+            });
+        }
+        let name = rest.strip_prefix('-')?;
+        let valid = !name.is_empty()
+            && name.len() <= MAX_NAME_LEN
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-._?=".contains(c));
+        valid.then_some(Self {
+            hash,
+            base_name: Some(raw),
+        })
+    }
+
+    /// Whether the path is a derivation, whose outputs share its log.
+    #[must_use]
+    pub fn is_derivation(&self) -> bool {
+        self.base_name
+            .is_some_and(|name| Path::new(name).extension().is_some_and(|e| e == "drv"))
+    }
+}
+
+/// Gzipped build logs on disk, see the module docs.
+#[derive(Debug)]
+pub struct LogStore {
+    dir: PathBuf,
+    writes: AtomicU64,
+}
+
+impl LogStore {
+    /// Open (creating it if needed) the log store in `dir`.
+    ///
+    /// # Errors
+    ///
+    /// - if the directories cannot be created or the leftovers removed
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        let tmp = dir.join("tmp");
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp)?;
+        }
+        fs::create_dir_all(&tmp)?;
+        Ok(Self {
+            dir,
+            writes: AtomicU64::new(0),
+        })
+    }
+
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The gzipped log indexed under `hash`, if any.
+    ///
+    /// # Errors
+    ///
+    /// - if the log exists but cannot be read
+    pub fn get(&self, hash: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.log_path(hash)) {
+            Ok(log) => Ok(Some(log)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Keep `gzipped` under each of `hashes`, replacing what was there.
+    ///
+    /// # Errors
+    ///
+    /// - if the log cannot be written
+    pub fn put(&self, hashes: &[&str], gzipped: &[u8]) -> io::Result<()> {
+        let Some((first, aliases)) = hashes.split_first() else {
+            return Ok(());
+        };
+        let n = self.writes.fetch_add(1, Ordering::Relaxed);
+        let tmp = self.dir.join("tmp").join(format!("log-{n}"));
+        let mut file = File::create(&tmp)?;
+        file.write_all(gzipped)?;
+        file.sync_all()?;
+        let path = self.log_path(first);
+        fs::rename(&tmp, &path)?;
+        for alias in aliases {
+            let alias = self.log_path(alias);
+            let _ = fs::remove_file(&alias);
+            fs::hard_link(&path, &alias)?;
+        }
+        Ok(())
+    }
+
+    fn log_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(format!("{hash}.log.gz"))
+    }
+}
+
+/// Gzip `log` for storage and transfer.
+///
+/// # Errors
+///
+/// - never in practice, the output is in memory
+pub fn compress(log: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(log)?;
+    encoder.finish()
+}
+
+/// Undo [`compress`], for clients that do not accept gzip.
+///
+/// # Errors
+///
+/// - if `gzipped` is not a gzip stream
+pub fn decompress(gzipped: &[u8]) -> io::Result<Vec<u8>> {
+    let mut log = Vec::new();
+    GzDecoder::new(gzipped).read_to_end(&mut log)?;
+    Ok(log)
+}
+
+/// Whether an `Accept-Encoding` header value allows gzip.
+#[must_use]
+pub fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let refused = parts.any(|p| {
+            p.strip_prefix("q=")
+                .is_some_and(|q| q.parse::<f32>().is_ok_and(|q| q <= 0.0))
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0mdqa9w1p6cmli6976v4wi0sw9r4p5pr";
+
+    #[test]
+    fn only_store_path_base_names_are_accepted() {
+        let drv = format!("{HASH}-hello-2.12.drv");
+        let request = LogRequest::parse(&drv).unwrap();
+        assert_eq!(request.hash, HASH);
+        assert_eq!(request.base_name, Some(drv.as_str()));
+        assert!(request.is_derivation());
+
+        let bare = LogRequest::parse(HASH).unwrap();
+        assert_eq!(bare.base_name, None);
+        assert!(!bare.is_derivation());
+
+        for bad in [
+            "",
+            "../../etc/passwd",
+            &format!("{HASH}-hello/../../etc"),
+            &format!("{HASH}-.hidden"),
+            &format!("{HASH}-"),
+            &format!("{HASH}hello"),
+            &format!("{HASH}-a b"),
+            // `e`, `o`, `u` and `t` are not in Nix's base32
+            "0mdqa9w1p6cmli6976v4wi0sw9r4p5pe-hello",
+            &format!("{HASH}-{}", "a".repeat(MAX_NAME_LEN + 1)),
+        ] {
+            assert_eq!(LogRequest::parse(bad), None, "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn logs_are_stored_gzipped_under_every_hash() {
+        let dir = std::env::temp_dir().join(format!("cache-build-logs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = LogStore::open(&dir).unwrap();
+        let output = "1b9p07z77phvv2hf6gm9f28syp39f1ag";
+
+        assert_eq!(store.get(HASH).unwrap(), None);
+        let gzipped = compress(b"building...\nok\n").unwrap();
+        store.put(&[HASH, output], &gzipped).unwrap();
+        assert_eq!(store.get(output).unwrap().as_deref(), Some(&gzipped[..]));
+        assert_eq!(
+            decompress(&store.get(HASH).unwrap().unwrap()).unwrap(),
+            b"building...\nok\n"
+        );
+
+        // Reopening keeps the logs
+        drop(store);
+        let store = LogStore::open(&dir).unwrap();
+        assert!(store.get(output).unwrap().is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gzip_is_negotiated() {
+        assert!(accepts_gzip("gzip, deflate, br"));
+        assert!(accepts_gzip("br;q=1.0, GZIP;q=0.5"));
+        assert!(accepts_gzip("*"));
+        assert!(!accepts_gzip("gzip;q=0"));
+        assert!(!accepts_gzip("identity"));
+        assert!(!accepts_gzip(""));
+    }
+}
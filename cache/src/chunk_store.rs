@@ -0,0 +1,608 @@
+//! # Chunk store
+//!
+//! Optional storage for NARs uploaded to the cache (`nix copy --to`), with
+//! content shared between NARs stored once. Successive versions of a
+//! package differ in a few files, so each NAR is cut into content-defined
+//! chunks (`FastCDC`, [`MIN_CHUNK`] to [`MAX_CHUNK`], [`AVG_CHUNK`] on
+//! average) kept under their SHA-256. A stored NAR is only the manifest of
+//! its chunks, and is assembled from them again when it is served.
+//!
+//! Chunks are refcounted by the manifests listing them: replacing or
+//! removing a NAR deletes the chunks no other NAR uses, and deleting an
+//! uploaded narinfo removes the NAR its `URL` names once no other narinfo
+//! names it. The counts are rebuilt from the manifests when the store is
+//! opened, so the files are the only state:
+//!
+//! - `chunks/<first 2 hex>/<sha256 hex>` — chunk content
+//! - `nars/<file>` — manifest of NAR `<file>`, one `<sha256 hex> <length>`
+//!   line per chunk
+//! - `narinfo/<hash>.narinfo` — uploaded narinfos, as sent
+//! - `tmp/` — uploads in progress, cleared on open
+//!
+//! Compressed NARs share next to nothing, so caches using this store are
+//! best filled with `nix copy --to 'http://cache?compression=none'`.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+
+use fastcdc::v2020::StreamCDC;
+use sha2::{Digest, Sha256};
+
+/// Smallest chunk, except for the last one of a NAR.
+pub const MIN_CHUNK: u32 = 16 * 1024;
+/// Chunk size `FastCDC` aims for.
+pub const AVG_CHUNK: u32 = 64 * 1024;
+/// Largest chunk.
+pub const MAX_CHUNK: u32 = 256 * 1024;
+
+/// One chunk of a NAR.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkRef {
+    /// SHA-256 of the content, in hex
+    pub hash: String,
+    pub len: u64,
+}
+
+/// Chunks of a stored NAR, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub chunks: Vec<ChunkRef>,
+}
+
+impl Manifest {
+    /// Size of the assembled NAR.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.chunks.iter().map(|c| c.len).sum()
+    }
+
+    fn parse(text: &str) -> io::Result<Self> {
+        let chunks = text
+            .lines()
+            .map(|line| {
+                line.split_once(' ')
+                    .and_then(|(hash, len)| {
+                        Some(ChunkRef {
+                            hash: hash.to_string(),
+                            len: len.parse().ok()?,
+                        })
+                    })
+                    .filter(|c| is_sha256_hex(&c.hash))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("malformed manifest line {line:?}"),
+                        )
+                    })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self { chunks })
+    }
+
+    fn render(&self) -> String {
+        self.chunks.iter().fold(String::new(), |mut out, c| {
+            let _ = writeln!(out, "{} {}", c.hash, c.len);
+            out
+        })
+    }
+}
+
+/// What storing one NAR took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stored {
+    pub chunks: usize,
+    /// Chunks no stored NAR had yet
+    pub new_chunks: usize,
+    pub bytes: u64,
+    /// Bytes written for the new chunks
+    pub new_bytes: u64,
+}
+
+/// Size of the stored NARs against the space their chunks take.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub nars: usize,
+    pub chunks: usize,
+    /// Sum of the NAR sizes
+    pub logical_bytes: u64,
+    /// Sum of the chunk sizes
+    pub stored_bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    /// Chunk hash → (manifest entries naming it, length)
+    refs: HashMap<String, (u64, u64)>,
+    /// NAR file → size
+    nars: HashMap<String, u64>,
+}
+
+impl Counts {
+    fn add(&mut self, name: &str, manifest: &Manifest) {
+        for chunk in &manifest.chunks {
+            self.refs
+                .entry(chunk.hash.clone())
+                .or_insert((0, chunk.len))
+                .0 += 1;
+        }
+        self.nars.insert(name.to_string(), manifest.size());
+    }
+
+    /// Forget `manifest`, returning the chunks it was the last user of.
+    fn release(&mut self, manifest: &Manifest) -> Vec<String> {
+        let mut unused = Vec::new();
+        for chunk in &manifest.chunks {
+            if let Some((refs, _)) = self.refs.get_mut(&chunk.hash) {
+                *refs -= 1;
+                if *refs == 0 {
+                    self.refs.remove(&chunk.hash);
+                    unused.push(chunk.hash.clone());
+                }
+            }
+        }
+        unused
+    }
+}
+
+/// Deduplicating NAR store under one directory, see the module docs.
+#[derive(Debug)]
+pub struct ChunkStore {
+    dir: PathBuf,
+    counts: Mutex<Counts>,
+    /// Shared while a NAR is stored, exclusive while chunks are deleted, so
+    /// a chunk found on disk is counted before it can be deleted
+    collect: RwLock<()>,
+    /// Names temporary files
+    uploads: AtomicU64,
+}
+
+impl ChunkStore {
+    /// Open the store in `dir`, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// - if the directories cannot be created
+    /// - if a manifest cannot be read or is malformed
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        let tmp = dir.join("tmp");
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp)?;
+        }
+        for sub in ["chunks", "nars", "narinfo", "tmp"] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+
+        let mut counts = Counts::default();
+        for entry in fs::read_dir(dir.join("nars"))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let manifest = Manifest::parse(&fs::read_to_string(entry.path())?)
+                .map_err(|e| io::Error::new(e.kind(), format!("manifest {name}: {e}")))?;
+            counts.add(&name, &manifest);
+        }
+
+        Ok(Self {
+            dir,
+            counts: Mutex::new(counts),
+            collect: RwLock::default(),
+            uploads: AtomicU64::default(),
+        })
+    }
+
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// A fresh path under `tmp/` to receive an upload.
+    #[must_use]
+    pub fn upload_path(&self) -> PathBuf {
+        let n = self.uploads.fetch_add(1, Ordering::Relaxed);
+        self.dir.join("tmp").join(format!("upload-{n}"))
+    }
+
+    /// Store NAR `name` read from `source`, replacing a NAR of that name.
+    ///
+    /// # Errors
+    ///
+    /// - if `name` is not a plain file name
+    /// - if reading `source` or writing the store fails
+    pub fn put(&self, name: &str, source: impl Read) -> io::Result<Stored> {
+        check_name(name)?;
+        let guard = self.collect.read().unwrap_or_else(PoisonError::into_inner);
+
+        let mut stored = Stored::default();
+        let mut manifest = Manifest::default();
+        for chunk in StreamCDC::new(source, MIN_CHUNK, AVG_CHUNK, MAX_CHUNK) {
+            let chunk = chunk?;
+            let hash = sha256_hex(&chunk.data);
+            let len = chunk.data.len() as u64;
+            let path = self.chunk_path(&hash);
+            if !path.exists() {
+                self.write_atomically(&path, &chunk.data)?;
+                stored.new_chunks += 1;
+                stored.new_bytes += len;
+            }
+            stored.chunks += 1;
+            stored.bytes += len;
+            manifest.chunks.push(ChunkRef { hash, len });
+        }
+
+        let manifest_path = self.dir.join("nars").join(name);
+        let previous = read_manifest(&manifest_path)?;
+        self.write_atomically(&manifest_path, manifest.render().as_bytes())?;
+        let unused = {
+            let mut counts = self.lock();
+            counts.add(name, &manifest);
+            previous.map(|p| counts.release(&p)).unwrap_or_default()
+        };
+        drop(guard);
+
+        self.delete_unused(unused)?;
+        Ok(stored)
+    }
+
+    /// Manifest of NAR `name`, `None` when it is not stored.
+    ///
+    /// # Errors
+    ///
+    /// - if the manifest cannot be read or is malformed
+    pub fn manifest(&self, name: &str) -> io::Result<Option<Manifest>> {
+        if check_name(name).is_err() {
+            return Ok(None);
+        }
+        read_manifest(&self.dir.join("nars").join(name))
+    }
+
+    /// Content of `chunk`, checked against its hash.
+    ///
+    /// # Errors
+    ///
+    /// - if the chunk cannot be read
+    /// - if its content does not match its hash
+    pub fn read_chunk(&self, chunk: &ChunkRef) -> io::Result<Vec<u8>> {
+        let data = fs::read(self.chunk_path(&chunk.hash))?;
+        if sha256_hex(&data) != chunk.hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chunk {} is corrupt", chunk.hash),
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Remove NAR `name` and the chunks only it used; `false` when it was
+    /// not stored.
+    ///
+    /// # Errors
+    ///
+    /// - if its manifest or chunks cannot be deleted
+    pub fn remove(&self, name: &str) -> io::Result<bool> {
+        let Some(manifest) = self.manifest(name)? else {
+            return Ok(false);
+        };
+        fs::remove_file(self.dir.join("nars").join(name))?;
+        let unused = {
+            let mut counts = self.lock();
+            counts.nars.remove(name);
+            counts.release(&manifest)
+        };
+        self.delete_unused(unused)?;
+        Ok(true)
+    }
+
+    /// Keep narinfo `text` for `hash_part`.
+    ///
+    /// # Errors
+    ///
+    /// - if `hash_part` is not a plain file name
+    /// - if it cannot be written
+    pub fn put_narinfo(&self, hash_part: &str, text: &str) -> io::Result<()> {
+        check_name(hash_part)?;
+        self.write_atomically(&self.narinfo_path(hash_part), text.as_bytes())
+    }
+
+    /// Uploaded narinfo of `hash_part`, `None` when there is none.
+    ///
+    /// # Errors
+    ///
+    /// - if it exists but cannot be read
+    pub fn narinfo(&self, hash_part: &str) -> io::Result<Option<String>> {
+        if check_name(hash_part).is_err() {
+            return Ok(None);
+        }
+        match fs::read_to_string(self.narinfo_path(hash_part)) {
+            Ok(text) => Ok(Some(text)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Delete the uploaded narinfo of `hash_part`, and the NAR its `URL`
+    /// names unless another narinfo names it too; `false` when there is no
+    /// such narinfo.
+    ///
+    /// # Errors
+    ///
+    /// - if either cannot be deleted, or the other narinfos cannot be read
+    pub fn remove_narinfo(&self, hash_part: &str) -> io::Result<bool> {
+        let Some(narinfo) = self.narinfo(hash_part)? else {
+            return Ok(false);
+        };
+        fs::remove_file(self.narinfo_path(hash_part))?;
+        if let Some(nar) = nar_of(&narinfo)
+            && !self.nar_is_named(nar)?
+        {
+            self.remove(nar)?;
+        }
+        Ok(true)
+    }
+
+    /// Whether an uploaded narinfo has `nar` as its `URL`.
+    fn nar_is_named(&self, nar: &str) -> io::Result<bool> {
+        for entry in fs::read_dir(self.dir.join("narinfo"))? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "narinfo") {
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(text) if nar_of(&text) == Some(nar) => return Ok(true),
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(false)
+    }
+
+    #[must_use]
+    pub fn usage(&self) -> Usage {
+        let counts = self.lock();
+        Usage {
+            nars: counts.nars.len(),
+            chunks: counts.refs.len(),
+            logical_bytes: counts.nars.values().sum(),
+            stored_bytes: counts.refs.values().map(|(_, len)| len).sum(),
+        }
+    }
+
+    /// Delete the chunks in `candidates` that are still unreferenced.
+    fn delete_unused(&self, candidates: Vec<String>) -> io::Result<()> {
+        if candidates.is_empty() {
+            return Ok(());
+        }
+        let _exclusive = self.collect.write().unwrap_or_else(PoisonError::into_inner);
+        let counts = self.lock();
+        for hash in candidates {
+            if counts.refs.contains_key(&hash) {
+                continue;
+            }
+            match fs::remove_file(self.chunk_path(&hash)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `data` to `path` through a temporary file, so readers never
+    /// see it partly written.
+    fn write_atomically(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = self.upload_path();
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("chunks").join(&hash[..2]).join(hash)
+    }
+
+    fn narinfo_path(&self, hash_part: &str) -> PathBuf {
+        self.dir
+            .join("narinfo")
+            .join(format!("{hash_part}.narinfo"))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Counts> {
+        self.counts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn read_manifest(path: &Path) -> io::Result<Option<Manifest>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Manifest::parse(&text).map(Some),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reject names that would leave their directory.
+/// The NAR file name in the `URL: nar/<file>` line of `narinfo`.
+fn nar_of(narinfo: &str) -> Option<&str> {
+    narinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("URL: nar/"))
+        .map(str::trim)
+}
+
+fn check_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid name {name:?}"),
+        ));
+    }
+    Ok(())
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+fn is_sha256_hex(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic bytes that `FastCDC` cuts into several chunks.
+    #[allow(clippy::cast_possible_truncation)]
+    fn content(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn store() -> (ChunkStore, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "chunk-store-{}-{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        (ChunkStore::open(&dir).unwrap(), dir)
+    }
+
+    fn assemble(store: &ChunkStore, name: &str) -> Vec<u8> {
+        let manifest = store.manifest(name).unwrap().unwrap();
+        manifest
+            .chunks
+            .iter()
+            .flat_map(|c| store.read_chunk(c).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn versions_share_their_common_chunks() {
+        let (store, dir) = store();
+        let v1 = content(1, 2 * 1024 * 1024);
+        let mut v2 = v1.clone();
+        v2.splice(1_000_000..1_000_010, content(2, 5_000));
+
+        let first = store.put("v1.nar", v1.as_slice()).unwrap();
+        assert!(first.chunks > 4);
+        assert_eq!(first.new_bytes, v1.len() as u64);
+        let second = store.put("v2.nar", v2.as_slice()).unwrap();
+        assert!(
+            second.new_bytes * 4 < second.bytes,
+            "only the chunks around the edit are new: {second:?}"
+        );
+
+        assert_eq!(assemble(&store, "v1.nar"), v1);
+        assert_eq!(assemble(&store, "v2.nar"), v2);
+        let usage = store.usage();
+        assert_eq!(usage.nars, 2);
+        assert_eq!(usage.logical_bytes, (v1.len() + v2.len()) as u64);
+        assert_eq!(usage.stored_bytes, first.new_bytes + second.new_bytes);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removing_a_nar_deletes_only_its_own_chunks() {
+        let (store, dir) = store();
+        let v1 = content(3, 1024 * 1024);
+        let mut v2 = v1.clone();
+        v2.extend(content(4, 512 * 1024));
+        store.put("v1.nar", v1.as_slice()).unwrap();
+        store.put("v2.nar", v2.as_slice()).unwrap();
+
+        assert!(store.remove("v2.nar").unwrap());
+        assert!(!store.remove("v2.nar").unwrap());
+        assert!(store.manifest("v2.nar").unwrap().is_none());
+        assert_eq!(assemble(&store, "v1.nar"), v1);
+        let chunk_files = fs::read_dir(dir.join("chunks"))
+            .unwrap()
+            .flat_map(|d| fs::read_dir(d.unwrap().path()).unwrap())
+            .count();
+        assert_eq!(chunk_files, store.usage().chunks);
+
+        assert!(store.remove("v1.nar").unwrap());
+        assert_eq!(store.usage(), Usage::default());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn counts_are_rebuilt_on_open() {
+        let (store, dir) = store();
+        store.put("a.nar", content(5, 300_000).as_slice()).unwrap();
+        store
+            .put_narinfo("abc", "StorePath: /nix/store/abc-a\n")
+            .unwrap();
+        let usage = store.usage();
+        drop(store);
+
+        let reopened = ChunkStore::open(&dir).unwrap();
+        assert_eq!(reopened.usage(), usage);
+        assert_eq!(
+            reopened.narinfo("abc").unwrap().as_deref(),
+            Some("StorePath: /nix/store/abc-a\n")
+        );
+        assert!(reopened.narinfo("missing").unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn deleting_a_narinfo_removes_its_nar() {
+        let (store, dir) = store();
+        store
+            .put("abc.nar", content(7, 200_000).as_slice())
+            .unwrap();
+        store
+            .put_narinfo("abc", "StorePath: /nix/store/abc-a\nURL: nar/abc.nar\n")
+            .unwrap();
+
+        // Another narinfo pointing at the same NAR keeps it alive
+        store
+            .put_narinfo("xyz", "StorePath: /nix/store/xyz-b\nURL: nar/abc.nar\n")
+            .unwrap();
+        assert!(store.remove_narinfo("xyz").unwrap());
+        assert!(store.manifest("abc.nar").unwrap().is_some());
+
+        assert!(store.remove_narinfo("abc").unwrap());
+        assert!(!store.remove_narinfo("abc").unwrap());
+        assert!(store.manifest("abc.nar").unwrap().is_none());
+        assert_eq!(store.usage(), Usage::default());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_chunks_and_bad_names_are_refused() {
+        let (store, dir) = store();
+        store.put("a.nar", content(6, 100_000).as_slice()).unwrap();
+        let chunk = store.manifest("a.nar").unwrap().unwrap().chunks[0].clone();
+        fs::write(store.chunk_path(&chunk.hash), b"garbage").unwrap();
+        assert_eq!(
+            store.read_chunk(&chunk).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        assert!(store.put("../escape", &b"x"[..]).is_err());
+        assert!(store.manifest("../a.nar").unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! Lookups across sibling caches
+//!
+//! A path missing from the local store is looked up in every sibling at
+//! once and the first one answering serves it: its narinfo is returned as
+//! is, and the NAR is streamed through from the same sibling, so clients
+//! only ever talk to their local cache. With write-through, a path found in
+//! a sibling is also copied into the local store in the background, so the
+//! next request is answered locally.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tokio::process::Command;
+use tokio::task::JoinSet;
+
+/// How long a sibling gets to answer before it counts as a miss.
+const SIBLING_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct Federation {
+    client: reqwest::Client,
+    /// Base URLs, without trailing slash
+    siblings: Arc<[String]>,
+    write_through: bool,
+    /// Store paths being copied in, so each is copied once
+    copying: Arc<Mutex<HashSet<String>>>,
+}
+
+/// Narinfo found in a sibling
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found<T> {
+    pub sibling: String,
+    pub value: T,
+}
+
+impl Federation {
+    /// Federation over the comma separated `siblings` base URLs.
+    ///
+    /// # Errors
+    ///
+    /// - if the HTTP client cannot be built
+    pub fn new(siblings: &str, write_through: bool) -> Result<Self, reqwest::Error> {
+        let siblings: Vec<String> = siblings
+            .split(',')
+            .map(|s| s.trim().trim_end_matches('/'))
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .connect_timeout(SIBLING_TIMEOUT)
+                .build()?,
+            siblings: siblings.into(),
+            write_through,
+            copying: Arc::default(),
+        })
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.siblings.is_empty()
+    }
+
+    #[must_use]
+    pub fn siblings(&self) -> &[String] {
+        &self.siblings
+    }
+
+    /// Ask every sibling for the narinfo of `hash_part`; the first `200`
+    /// wins and the others are cancelled.
+    pub async fn narinfo(&self, hash_part: &str) -> Option<Found<String>> {
+        let mut lookups = JoinSet::new();
+        for sibling in self.siblings.iter() {
+            let client = self.client.clone();
+            let sibling = sibling.clone();
+            let url = format!("{sibling}/{hash_part}.narinfo");
+            lookups.spawn(async move {
+                let response = client
+                    .get(&url)
+                    .timeout(SIBLING_TIMEOUT)
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status);
+                match response {
+                    Ok(response) => response
+                        .text()
+                        .await
+                        .ok()
+                        .map(|value| Found { sibling, value }),
+                    Err(e) => {
+                        tracing::debug!("Sibling {} has no {}: {}", sibling, url, e);
+                        None
+                    }
+                }
+            });
+        }
+        while let Some(joined) = lookups.join_next().await {
+            if let Ok(Some(found)) = joined {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    /// Open the NAR `nar_file` of `hash_part` on whichever sibling has its
+    /// narinfo.
+    pub async fn nar(&self, hash_part: &str, nar_file: &str) -> Option<Found<reqwest::Response>> {
+        let Found { sibling, .. } = self.narinfo(hash_part).await?;
+        // Streaming a whole NAR can take long, only the connection is timed
+        match self
+            .client
+            .get(format!("{sibling}/nar/{nar_file}"))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+        {
+            Ok(value) => Some(Found { sibling, value }),
+            Err(e) => {
+                tracing::warn!("Sibling {} lost NAR {}: {}", sibling, nar_file, e);
+                None
+            }
+        }
+    }
+
+    /// With write-through, copy the path of `found` into the local store in
+    /// the background. Siblings are configured by the operator and trusted:
+    /// their signatures are not checked again.
+    pub fn write_through(&self, found: &Found<String>) {
+        if !self.write_through {
+            return;
+        }
+        let Some(store_path) = store_path(&found.value) else {
+            tracing::warn!("Narinfo from {} has no StorePath", found.sibling);
+            return;
+        };
+        if !self.lock().insert(store_path.to_string()) {
+            return;
+        }
+
+        let this = self.clone();
+        let sibling = found.sibling.clone();
+        let store_path = store_path.to_string();
+        tokio::spawn(async move {
+            let output = Command::new("nix")
+                .args([
+                    "--extra-experimental-features",
+                    "nix-command",
+                    "copy",
+                    "--no-check-sigs",
+                    "--from",
+                    &sibling,
+                    &store_path,
+                ])
+                .output()
+                .await;
+            match output {
+                Ok(output) if output.status.success() => {
+                    tracing::info!("Copied {} from {}", store_path, sibling);
+                }
+                Ok(output) => tracing::warn!(
+                    "Failed to copy {} from {}: {}",
+                    store_path,
+                    sibling,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Err(e) => tracing::warn!("Failed to run nix copy: {}", e),
+            }
+            this.lock().remove(&store_path);
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<String>> {
+        // Single inserts and removes, a panicking holder cannot leave the set
+        // half-written
+        self.copying.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// `StorePath:` of a narinfo.
+fn store_path(narinfo: &str) -> Option<&str> {
+    narinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("StorePath:"))
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn siblings_are_normalized() {
+        let federation = Federation::new(" http://a:8081/ ,, http://b:8081", false).unwrap();
+        assert_eq!(federation.siblings(), ["http://a:8081", "http://b:8081"]);
+        assert!(Federation::new("", false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn store_path_is_read_from_the_narinfo() {
+        let narinfo = "StorePath: /nix/store/aaaa-hello\nURL: nar/aaaa.nar\n";
+        assert_eq!(store_path(narinfo), Some("/nix/store/aaaa-hello"));
+        assert_eq!(store_path("URL: nar/aaaa.nar\n"), None);
+    }
+}
@@ -2,19 +2,54 @@
 use axum::{
     Router,
     routing::get,
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode, header},
     response::{Response, IntoResponse},
     body::Body,
 };
+use futures::{StreamExt as _, TryStreamExt as _};
+use tokio::io::AsyncWriteExt as _;
 use tokio::process::Command;
 use tokio_util::io::ReaderStream;
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use repo_outils::nix::{self, PathInfo};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use metrics_exporter_prometheus::PrometheusHandle;
+
+use crate::build_logs::{LogRequest, LogStore};
+use crate::chunk_store::{ChunkStore, Manifest};
+use crate::federation::Federation;
+use crate::narinfo_cache::{Lookup, NarinfoCache};
+use crate::priority::ClientClasses;
+use crate::signer::Signer;
+
+mod build_logs;
+mod chunk_store;
+mod federation;
+mod metrics;
+mod narinfo_cache;
+mod priority;
+mod signer;
 
 pub struct NixServeState {
     store_dir: String,
-    secret_key: Option<String>,
+    signer: Option<Signer>,
+    narinfos: NarinfoCache,
+    metrics: Option<PrometheusHandle>,
+    classes: ClientClasses,
+    federation: Federation,
+    /// Uploaded NARs, when `CACHE_CHUNK_DIR` is set
+    chunks: Option<Arc<ChunkStore>>,
+    /// Token uploads and deletes must carry, from `CACHE_UPLOAD_TOKEN_FILE`;
+    /// without it they are only taken from this host
+    upload_token: Option<String>,
+    /// Build logs served so far, when `CACHE_LOG_DIR` is set
+    logs: Option<Arc<LogStore>>,
+    /// Bounds the `nix log` processes running at once
+    log_fetches: tokio::sync::Semaphore,
 }
 
 impl NixServeState {
@@ -24,41 +59,128 @@ impl NixServeState {
 
         tracing::info!("Using store directory: {}", store_dir);
 
-        let secret_key = std::env::var("NIX_SECRET_KEY_FILE")
-            .ok()
-            .and_then(|path| {
-                tracing::info!("Loading secret key from: {}", path);
-                std::fs::read_to_string(path).ok()
-            })
-            .map(|s| s.trim().to_string());
-
-        if secret_key.is_some() {
-            tracing::info!("Secret key loaded successfully");
+        let signer = Signer::from_env()?;
+        if let Some(signer) = &signer {
+            tracing::info!(%signer, "Signing narinfos");
         } else {
             tracing::warn!("No secret key configured - cache will not sign packages");
         }
 
-        Ok(Self { store_dir, secret_key })
+        let capacity = env_or("CACHE_NARINFO_ENTRIES", narinfo_cache::DEFAULT_CAPACITY);
+        let negative_ttl = Duration::from_secs(env_or(
+            "CACHE_NEGATIVE_TTL_SECS",
+            narinfo_cache::DEFAULT_NEGATIVE_TTL.as_secs(),
+        ));
+        tracing::info!(
+            capacity,
+            negative_ttl_secs = negative_ttl.as_secs(),
+            "Caching narinfos in memory"
+        );
+
+        let classes = ClientClasses::parse(
+            env_or("CACHE_PRIORITY", priority::DEFAULT_PRIORITY),
+            &std::env::var("CACHE_CLIENT_PRIORITIES").unwrap_or_default(),
+        )?;
+        tracing::info!(?classes, "Advertising priorities per client class");
+
+        let federation = Federation::new(
+            &std::env::var("CACHE_SIBLINGS").unwrap_or_default(),
+            env_or("CACHE_WRITE_THROUGH", false),
+        )?;
+        if !federation.is_empty() {
+            tracing::info!(siblings = ?federation.siblings(), "Federating lookups");
+        }
+
+        let metrics = crate::metrics::install()
+            .inspect_err(|e| tracing::warn!("Metrics disabled: {}", e))
+            .ok();
+
+        let chunks = match std::env::var("CACHE_CHUNK_DIR") {
+            Ok(dir) if !dir.is_empty() => {
+                let store = ChunkStore::open(dir)?;
+                let usage = store.usage();
+                tracing::info!(
+                    dir = %store.dir().display(),
+                    nars = usage.nars,
+                    logical_bytes = usage.logical_bytes,
+                    stored_bytes = usage.stored_bytes,
+                    "Accepting uploads into the chunk store"
+                );
+                crate::metrics::chunk_store_usage(&usage);
+                Some(Arc::new(store))
+            }
+            _ => None,
+        };
+
+        let upload_token = match std::env::var("CACHE_UPLOAD_TOKEN_FILE") {
+            Ok(file) if !file.is_empty() => {
+                let token = std::fs::read_to_string(&file)?.trim().to_string();
+                if token.is_empty() {
+                    return Err(format!("CACHE_UPLOAD_TOKEN_FILE {file} is empty").into());
+                }
+                Some(token)
+            }
+            _ => None,
+        };
+        if chunks.is_some() && upload_token.is_none() {
+            tracing::warn!("No CACHE_UPLOAD_TOKEN_FILE - only taking uploads from this host");
+        }
+
+        let logs = match std::env::var("CACHE_LOG_DIR") {
+            Ok(dir) if !dir.is_empty() => {
+                let store = LogStore::open(dir)?;
+                tracing::info!(dir = %store.dir().display(), "Keeping build logs");
+                Some(Arc::new(store))
+            }
+            _ => None,
+        };
+        let log_fetches = env_or("CACHE_LOG_FETCHES", build_logs::DEFAULT_FETCHES).max(1);
+
+        Ok(Self {
+            store_dir,
+            signer,
+            narinfos: NarinfoCache::new(capacity, negative_ttl),
+            metrics,
+            classes,
+            federation,
+            chunks,
+            upload_token,
+            logs,
+            log_fetches: tokio::sync::Semaphore::new(log_fetches),
+        })
     }
 }
 
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
 pub fn router() -> Router {
     let state = NixServeState::new().expect("Failed to initialize nix-serve state");
 
     Router::new()
         .route("/nix-cache-info", get(nix_cache_info))
-        .route("/{hash_narinfo}", get(narinfo))
-        .route("/nar/{nar_file}", get(nar_handler))
+        .route(
+            "/{hash_narinfo}",
+            get(narinfo).put(upload_narinfo).delete(delete_narinfo),
+        )
+        .route("/nar/{nar_file}", get(nar_handler).put(upload_nar))
         .route("/log/{*store_path}", get(log))
+        .route("/metrics", get(metrics_handler))
         .with_state(Arc::new(state))
 }
 
 async fn nix_cache_info(
     State(state): State<Arc<NixServeState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
 ) -> impl IntoResponse {
     let response = format!(
-        "StoreDir: {}\nWantMassQuery: 1\nPriority: 30\n",
-        state.store_dir
+        "StoreDir: {}\nWantMassQuery: 1\nPriority: {}\n",
+        state.store_dir,
+        state.classes.priority(peer.ip())
     );
 
     Response::builder()
@@ -84,6 +206,79 @@ async fn narinfo(
         return Err(StatusCode::BAD_REQUEST);
     }
 
+    let now = Instant::now();
+    let result = match state.narinfos.lookup(hash_part, now) {
+        Lookup::Hot(narinfo) => {
+            record_lookup("hot", &state.narinfos);
+            Ok(narinfo)
+        }
+        Lookup::Missing => {
+            record_lookup("negative", &state.narinfos);
+            tracing::debug!("Store path known missing for hash: {}", hash_part);
+            Err(StatusCode::NOT_FOUND)
+        }
+        Lookup::Unknown => {
+            let (result, source) = match query_narinfo(&state, hash_part).await {
+                Ok(narinfo) => (Ok(narinfo), Some("store_hit")),
+                // Only a path missing here is looked up in uploads, then siblings
+                Err(StatusCode::NOT_FOUND) => match uploaded_narinfo(&state, hash_part).await {
+                    Some(narinfo) => (Ok(narinfo), Some("chunk_hit")),
+                    None => match state.federation.narinfo(hash_part).await {
+                        Some(found) => {
+                            tracing::debug!("Found {} in sibling {}", hash_part, found.sibling);
+                            state.federation.write_through(&found);
+                            (Ok(found.value), Some("sibling_hit"))
+                        }
+                        None => (Err(StatusCode::NOT_FOUND), Some("store_miss")),
+                    },
+                },
+                Err(e) => (Err(e), None),
+            };
+            let result = result.map(Arc::<str>::from);
+            match &result {
+                Ok(narinfo) => state.narinfos.insert(hash_part, narinfo.clone()),
+                Err(StatusCode::NOT_FOUND) => state.narinfos.insert_missing(hash_part, now),
+                Err(_) => {}
+            }
+            if let Some(source) = source {
+                record_lookup(source, &state.narinfos);
+            }
+            result
+        }
+    };
+    let response = result?;
+
+    tracing::info!("Serving narinfo for {}", hash_part);
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/x-nix-narinfo")
+        .header("Content-Length", response.len())
+        .body(Body::from(response.to_string()))
+        .unwrap())
+}
+
+/// Narinfo of `hash_part` uploaded into the chunk store, if any.
+async fn uploaded_narinfo(state: &NixServeState, hash_part: &str) -> Option<String> {
+    let store = state.chunks.clone()?;
+    let hash = hash_part.to_string();
+    tokio::task::spawn_blocking(move || store.narinfo(&hash))
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|r| r)
+        .inspect_err(|e| tracing::error!("Failed to read uploaded narinfo {}: {}", hash_part, e))
+        .ok()
+        .flatten()
+}
+
+fn record_lookup(result: &'static str, narinfos: &NarinfoCache) {
+    let (hot, missing) = narinfos.sizes();
+    crate::metrics::narinfo_lookup(result, hot, missing);
+}
+
+/// Build the narinfo of `hash_part` from the store; `NOT_FOUND` when the
+/// store has no such path.
+async fn query_narinfo(state: &NixServeState, hash_part: &str) -> Result<String, StatusCode> {
     // Query store path from hash part
     let output = Command::new("nix-store")
         .args(["--query", "--hash"])
@@ -152,7 +347,7 @@ async fn narinfo(
     }
 
     // Add signature
-    if let Some(ref secret_key) = state.secret_key {
+    if let Some(signer) = &state.signer {
         let fingerprint = fingerprint_path(
             &store_path,
             &path_info.nar_hash,
@@ -160,9 +355,9 @@ async fn narinfo(
             &path_info.references,
         );
         tracing::debug!("Fingerprint to sign: {}", fingerprint);
-        let signature = sign_string(secret_key, &fingerprint)
+        let signature = signer.sign(&fingerprint).await
             .map_err(|e| {
-                tracing::error!("Failed to sign: {}", e);
+                tracing::error!("Failed to sign: {}", repo_outils::report(&e));
                 StatusCode::INTERNAL_SERVER_ERROR
             })?;
         tracing::debug!("Generated signature: {}", signature);
@@ -173,14 +368,15 @@ async fn narinfo(
         }
     }
 
-    tracing::info!("Serving narinfo for {}", hash_part);
+    Ok(response)
+}
 
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "text/x-nix-narinfo")
-        .header("Content-Length", response.len())
-        .body(Body::from(response))
-        .unwrap())
+async fn metrics_handler(State(state): State<Arc<NixServeState>>) -> Result<String, StatusCode> {
+    state
+        .metrics
+        .as_ref()
+        .map(PrometheusHandle::render)
+        .ok_or(StatusCode::NOT_FOUND)
 }
 
 // Combined handler for both new and legacy NAR formats
@@ -190,6 +386,20 @@ async fn nar_handler(
 ) -> Result<Response, StatusCode> {
     tracing::debug!("NAR request for file: {}", nar_file);
 
+    // Uploaded NARs are named by their uploader, e.g. "<filehash>.nar.xz"
+    if let Some(store) = &state.chunks {
+        let lookup = {
+            let store = store.clone();
+            let name = nar_file.clone();
+            tokio::task::spawn_blocking(move || store.manifest(&name)).await
+        };
+        match lookup.map_err(std::io::Error::other).and_then(|r| r) {
+            Ok(Some(manifest)) => return Ok(chunked_nar(store.clone(), &nar_file, manifest)),
+            Ok(None) => {}
+            Err(e) => tracing::error!("Failed to read manifest of {}: {}", nar_file, e),
+        }
+    }
+
     // Parse filename: either "hash_part-nar_hash.nar" or "hash_part.nar" (legacy)
     let filename = nar_file.strip_suffix(".nar")
         .ok_or_else(|| {
@@ -218,11 +428,13 @@ async fn nar_handler(
     let store_path = format!("{}/{}", state.store_dir, hash_part);
 
     // Query path info
-    let path_info = query_path_info(&store_path).await
-        .map_err(|e| {
-            tracing::error!("Failed to query path info for {}: {}", store_path, e);
-            StatusCode::NOT_FOUND
-        })?;
+    let path_info = query_path_info(&store_path)
+        .await
+        .inspect_err(|e| tracing::error!("Failed to query path info for {}: {}", store_path, e))
+        .ok();
+    let Some(path_info) = path_info else {
+        return sibling_nar(&state.federation, &hash_part, &nar_file).await;
+    };
 
     // Verify NAR hash if provided (new format)
     if let Some(expected) = expected_nar_hash {
@@ -265,98 +477,424 @@ async fn nar_handler(
         .unwrap())
 }
 
+/// Assemble NAR `nar_file` from its chunks while streaming it.
+fn chunked_nar(store: Arc<ChunkStore>, nar_file: &str, manifest: Manifest) -> Response {
+    tracing::info!("Streaming NAR {} from {} chunks", nar_file, manifest.chunks.len());
+    let size = manifest.size();
+    let chunks = futures::stream::iter(manifest.chunks).then(move |chunk| {
+        let store = store.clone();
+        async move {
+            tokio::task::spawn_blocking(move || store.read_chunk(&chunk))
+                .await
+                .map_err(std::io::Error::other)
+                .and_then(|r| r)
+                .map(axum::body::Bytes::from)
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/x-nix-archive")
+        .header("Content-Length", size)
+        .body(Body::from_stream(chunks))
+        .unwrap()
+}
+
+/// `PUT /{hash}.narinfo` from `nix copy --to`, kept in the chunk store.
+async fn upload_narinfo(
+    State(state): State<Arc<NixServeState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(hash_narinfo): Path<String>,
+    body: String,
+) -> Result<StatusCode, StatusCode> {
+    let store = state.chunks.clone().ok_or(StatusCode::METHOD_NOT_ALLOWED)?;
+    authorize_write(state.upload_token.as_deref(), peer.ip(), &headers)?;
+    let hash_part = hash_narinfo
+        .strip_suffix(".narinfo")
+        .filter(|h| h.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()))
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+
+    let narinfo = body.clone();
+    let hash = hash_part.clone();
+    tokio::task::spawn_blocking(move || store.put_narinfo(&hash, &narinfo))
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|r| r)
+        .map_err(|e| {
+            tracing::error!("Failed to store narinfo {}: {}", hash_part, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    tracing::info!("Stored uploaded narinfo for {}", hash_part);
+    state.narinfos.insert(&hash_part, Arc::from(body));
+    Ok(StatusCode::OK)
+}
+
+/// `DELETE /{hash}.narinfo`: drop an uploaded path, NAR included.
+async fn delete_narinfo(
+    State(state): State<Arc<NixServeState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(hash_narinfo): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let store = state.chunks.clone().ok_or(StatusCode::METHOD_NOT_ALLOWED)?;
+    authorize_write(state.upload_token.as_deref(), peer.ip(), &headers)?;
+    let hash_part = hash_narinfo
+        .strip_suffix(".narinfo")
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .to_string();
+
+    let removed = {
+        let store = store.clone();
+        let hash = hash_part.clone();
+        tokio::task::spawn_blocking(move || store.remove_narinfo(&hash))
+            .await
+            .map_err(std::io::Error::other)
+            .and_then(|r| r)
+            .map_err(|e| {
+                tracing::error!("Failed to delete {}: {}", hash_part, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+    };
+    state.narinfos.remove(&hash_part);
+    if !removed {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    tracing::info!("Deleted uploaded path {}", hash_part);
+    crate::metrics::chunk_store_usage(&store.usage());
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Uploads and deletes change what every client is served. With a `token`
+/// they need it, as `Authorization: Bearer <token>` or as the password of
+/// basic auth, which is what `nix copy --to` sends from a netrc file.
+/// Without one, only this host may write.
+fn authorize_write(
+    token: Option<&str>,
+    peer: IpAddr,
+    headers: &HeaderMap,
+) -> Result<(), StatusCode> {
+    let Some(token) = token else {
+        return if peer.is_loopback() {
+            Ok(())
+        } else {
+            tracing::warn!(%peer, "Refused a write from another host without an upload token");
+            Err(StatusCode::FORBIDDEN)
+        };
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            if let Some(bearer) = value.strip_prefix("Bearer ") {
+                return Some(bearer.trim().to_string());
+            }
+            let basic = STANDARD.decode(value.strip_prefix("Basic ")?.trim()).ok()?;
+            let basic = String::from_utf8(basic).ok()?;
+            basic
+                .split_once(':')
+                .map(|(_, password)| password.to_string())
+        });
+    match given {
+        Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => Ok(()),
+        _ => {
+            tracing::warn!(%peer, "Refused a write without a valid upload token");
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+/// Compare without returning at the first differing byte, so the time
+/// taken says nothing about how much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// `PUT /nar/{file}` from `nix copy --to`: spooled to disk, then chunked
+/// into the chunk store.
+async fn upload_nar(
+    State(state): State<Arc<NixServeState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(nar_file): Path<String>,
+    body: Body,
+) -> Result<StatusCode, StatusCode> {
+    let store = state.chunks.clone().ok_or(StatusCode::METHOD_NOT_ALLOWED)?;
+    authorize_write(state.upload_token.as_deref(), peer.ip(), &headers)?;
+    let internal = |e: &dyn std::fmt::Display| {
+        tracing::error!("Failed to store NAR {}: {}", nar_file, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    let spool = store.upload_path();
+    let written = async {
+        let mut file = tokio::fs::File::create(&spool).await?;
+        let mut body = body.into_data_stream();
+        while let Some(data) = body.try_next().await.map_err(std::io::Error::other)? {
+            file.write_all(&data).await?;
+        }
+        file.flush().await
+    }
+    .await;
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(&spool).await;
+        return Err(internal(&e));
+    }
+
+    let stored = {
+        let store = store.clone();
+        let name = nar_file.clone();
+        let spool = spool.clone();
+        tokio::task::spawn_blocking(move || store.put(&name, std::fs::File::open(spool)?))
+            .await
+            .map_err(std::io::Error::other)
+            .and_then(|r| r)
+    };
+    let _ = tokio::fs::remove_file(&spool).await;
+    let stored = match stored {
+        Ok(stored) => stored,
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => {
+            tracing::warn!("Refused NAR upload {}: {}", nar_file, e);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Err(e) => return Err(internal(&e)),
+    };
+
+    tracing::info!(
+        chunks = stored.chunks,
+        new_chunks = stored.new_chunks,
+        bytes = stored.bytes,
+        new_bytes = stored.new_bytes,
+        "Stored uploaded NAR {}",
+        nar_file
+    );
+    crate::metrics::chunk_store_usage(&store.usage());
+    Ok(StatusCode::OK)
+}
+
+/// Stream `nar_file` from the sibling that has `hash_part`, `NOT_FOUND` when
+/// none has.
+async fn sibling_nar(
+    federation: &Federation,
+    hash_part: &str,
+    nar_file: &str,
+) -> Result<Response, StatusCode> {
+    let found = federation
+        .nar(hash_part, nar_file)
+        .await
+        .ok_or(StatusCode::NOT_FOUND)?;
+    tracing::info!("Streaming NAR {} from sibling {}", nar_file, found.sibling);
+
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/x-nix-archive");
+    if let Some(length) = found.value.content_length() {
+        response = response.header("Content-Length", length);
+    }
+    Ok(response
+        .body(Body::from_stream(found.value.bytes_stream()))
+        .unwrap())
+}
+
+/// `GET /log/{name}`: the build log of a store path, see [`build_logs`].
 async fn log(
     State(state): State<Arc<NixServeState>>,
-    Path(store_path_suffix): Path<String>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
 ) -> Result<Response, StatusCode> {
-    let store_path = format!("{}/{}", state.store_dir, store_path_suffix);
-    tracing::debug!("Requesting log for: {}", store_path);
+    let request = LogRequest::parse(&name).ok_or_else(|| {
+        tracing::warn!("Invalid log request: {}", name);
+        StatusCode::BAD_REQUEST
+    })?;
 
-    let child = Command::new("nix")
+    let gzipped = if let Some(gzipped) = stored_log(&state, request.hash).await {
+        crate::metrics::build_log("stored");
+        gzipped
+    } else {
+        // A bare hash names no path to ask `nix log` about
+        let Some(base_name) = request.base_name else {
+            crate::metrics::build_log("missing");
+            return Err(StatusCode::NOT_FOUND);
+        };
+        let gzipped = fetch_log(&state, base_name).await.inspect_err(|status| {
+            if *status == StatusCode::NOT_FOUND {
+                crate::metrics::build_log("missing");
+            }
+        })?;
+        crate::metrics::build_log("fetched");
+        keep_log(&state, &request, gzipped.clone()).await;
+        gzipped
+    };
+
+    let gzip = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(build_logs::accepts_gzip);
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(header::VARY, "Accept-Encoding");
+    if gzip {
+        return Ok(response
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(Body::from(gzipped))
+            .unwrap());
+    }
+    let log = tokio::task::spawn_blocking(move || build_logs::decompress(&gzipped))
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|r| r)
+        .map_err(|e| {
+            tracing::error!("Failed to decompress log of {}: {}", name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(response.body(Body::from(log)).unwrap())
+}
+
+/// Gzipped log kept under `hash`, if the log store has one.
+async fn stored_log(state: &NixServeState, hash: &str) -> Option<Vec<u8>> {
+    let store = state.logs.clone()?;
+    let hash = hash.to_string();
+    tokio::task::spawn_blocking(move || store.get(&hash))
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|r| r)
+        .inspect_err(|e| tracing::error!("Failed to read stored log: {}", e))
+        .ok()
+        .flatten()
+}
+
+/// Run `nix log` on `base_name` and gzip what it prints; `NOT_FOUND` when
+/// the store has no log for it.
+async fn fetch_log(state: &NixServeState, base_name: &str) -> Result<Vec<u8>, StatusCode> {
+    let store_path = format!("{}/{}", state.store_dir, base_name);
+    let _permit = state
+        .log_fetches
+        .acquire()
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    tracing::debug!("Fetching log of {}", store_path);
+
+    let output = Command::new("nix")
         .args([
             "--extra-experimental-features", "nix-command",
             "log",
+            "--",
             &store_path,
         ])
-        .stdout(std::process::Stdio::piped())
-        .spawn()
+        .output()
+        .await
         .map_err(|e| {
             tracing::error!("Failed to spawn nix log command: {}", e);
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
+    if !output.status.success() {
+        tracing::debug!("No log for {}", store_path);
+        return Err(StatusCode::NOT_FOUND);
+    }
 
-    let stdout = child.stdout.ok_or_else(|| {
-        tracing::error!("Failed to capture stdout");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    let stream = ReaderStream::new(stdout);
-    let body = Body::from_stream(stream);
-
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "text/plain")
-        .body(body)
-        .unwrap())
+    tokio::task::spawn_blocking(move || build_logs::compress(&output.stdout))
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|r| r)
+        .map_err(|e| {
+            tracing::error!("Failed to compress log of {}: {}", store_path, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
 }
 
-// Helper structs and functions
-
-#[derive(Debug)]
-struct PathInfo {
-    nar_hash: String,
-    nar_size: u64,
-    references: Vec<String>,
-    deriver: Option<String>,
-    signatures: Vec<String>,
+/// Keep a fetched log in the log store, under the hash of the requested
+/// path and, for a derivation, of each of its outputs. Failing only costs
+/// a `nix log` next time, so it is logged and not answered.
+async fn keep_log(state: &NixServeState, request: &LogRequest<'_>, gzipped: Vec<u8>) {
+    let Some(store) = state.logs.clone() else {
+        return;
+    };
+    let mut hashes = vec![request.hash.to_string()];
+    if let Some(base_name) = request.base_name.filter(|_| request.is_derivation()) {
+        hashes.extend(derivation_outputs(&state.store_dir, base_name).await);
+    }
+    let kept = tokio::task::spawn_blocking(move || {
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+        store.put(&hashes, &gzipped)
+    })
+    .await
+    .map_err(std::io::Error::other)
+    .and_then(|r| r);
+    if let Err(e) = kept {
+        tracing::error!("Failed to keep log of {}: {}", request.hash, e);
+    }
 }
 
-async fn query_path_info(store_path: &str) -> Result<PathInfo, Box<dyn std::error::Error>> {
-    tracing::debug!("Querying path info for: {}", store_path);
-
-    // Query using nix-store
+/// Hash parts of the outputs of derivation `base_name`, none when the store
+/// cannot tell.
+async fn derivation_outputs(store_dir: &str, base_name: &str) -> Vec<String> {
     let output = Command::new("nix-store")
-        .args(["--query", "--deriver", "--hash", "--size", "--references", store_path])
+        .args(["--query", "--outputs", "--"])
+        .arg(format!("{store_dir}/{base_name}"))
         .output()
-        .await?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        tracing::error!("nix-store query failed: {}", stderr);
-        return Err("Failed to query path info".into());
+        .await;
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|path| LogRequest::parse(&strip_path(path)).map(|r| r.hash.to_string()))
+            .collect(),
+        Ok(_) | Err(_) => {
+            tracing::debug!("Cannot list the outputs of {}", base_name);
+            Vec::new()
+        }
     }
+}
 
-    let output_str = String::from_utf8(output.stdout)?;
-    let lines: Vec<&str> = output_str.lines().collect();
+// Helper structs and functions
 
-    // Parse output (simplified - real implementation needs proper parsing)
-    let nar_hash = lines.get(0).unwrap_or(&"").to_string();
-    let nar_size: u64 = lines.get(1).unwrap_or(&"0").parse().unwrap_or(0);
-    let references: Vec<String> = lines.iter().skip(2).map(|s| s.to_string()).collect();
+/// Path info of `store_path`, with its NAR hash as `sha256:<nix base32>`,
+/// the form narinfos and their fingerprints use.
+async fn query_path_info(store_path: &str) -> Result<PathInfo, Box<dyn std::error::Error>> {
+    tracing::debug!("Querying path info for: {}", store_path);
 
-    tracing::debug!("Path info: hash={}, size={}, refs={}", nar_hash, nar_size, references.len());
+    let mut path_info = nix::path_info(&[store_path])
+        .await?
+        .pop()
+        .ok_or("Path is not in the store")?;
+    path_info.nar_hash = nar_hash_base32(&path_info.nar_hash)
+        .ok_or_else(|| format!("Unsupported nar hash: {}", path_info.nar_hash))?;
 
-    // Query signatures
-    let sigs_output = Command::new("nix-store")
-        .args(["--query", "--sigs", store_path])
-        .output()
-        .await?;
+    tracing::debug!(
+        "Path info: hash={}, size={}, refs={}",
+        path_info.nar_hash,
+        path_info.nar_size,
+        path_info.references.len()
+    );
+    Ok(path_info)
+}
 
-    let signatures: Vec<String> = if sigs_output.status.success() {
-        String::from_utf8(sigs_output.stdout)?
-            .lines()
-            .map(|s| s.to_string())
-            .collect()
-    } else {
-        Vec::new()
-    };
+/// `sha256:<nix base32>` from either that form or the SRI one
+/// (`sha256-<base64>`) recent nix prints.
+fn nar_hash_base32(nar_hash: &str) -> Option<String> {
+    if let Some(base32) = nar_hash.strip_prefix("sha256:") {
+        return Some(format!("sha256:{base32}"));
+    }
+    let digest = STANDARD.decode(nar_hash.strip_prefix("sha256-")?).ok()?;
+    Some(format!("sha256:{}", nix_base32(&digest)))
+}
 
-    Ok(PathInfo {
-        nar_hash,
-        nar_size,
-        references,
-        deriver: None, // TODO: parse deriver
-        signatures,
-    })
+/// Nix's base32: its own alphabet, and the bytes read from the end.
+fn nix_base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+    let len = (bytes.len() * 8).div_ceil(5);
+    (0..len)
+        .rev()
+        .map(|n| {
+            let (i, j) = (n * 5 / 8, n * 5 % 8);
+            let next = bytes.get(i + 1).copied().unwrap_or(0);
+            let word = u16::from_le_bytes([bytes[i], next]);
+            char::from(ALPHABET[usize::from((word >> j) & 0x1f)])
+        })
+        .collect()
 }
 
 fn strip_path(path: &str) -> String {
@@ -374,31 +912,6 @@ fn fingerprint_path(
     format!("1;{};{};{};{}", store_path, nar_hash, nar_size, refs)
 }
 
-fn sign_string(secret_key: &str, message: &str) -> Result<String, Box<dyn std::error::Error>> {
-    use ed25519_dalek::{Signer, SigningKey};
-    use base64::{Engine as _, engine::general_purpose};
-
-    // Parse secret key (format: "keyname:base64key")
-    let parts: Vec<&str> = secret_key.split(':').collect();
-    if parts.len() != 2 {
-        return Err("Invalid secret key format".into());
-    }
-
-    let key_name = parts[0];
-    let key_bytes = general_purpose::STANDARD.decode(parts[1])?;
-
-    if key_bytes.len() != 32 {
-        return Err("Invalid key length".into());
-    }
-
-    let signing_key = SigningKey::from_bytes(&key_bytes.try_into().unwrap());
-    let signature = signing_key.sign(message.as_bytes());
-
-    let sig_b64 = general_purpose::STANDARD.encode(signature.to_bytes());
-
-    Ok(format!("{}:{}", key_name, sig_b64))
-}
-
 // Main server
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -418,9 +931,66 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!("  GET  /nix-cache-info");
     tracing::info!("  GET  /:hash.narinfo");
     tracing::info!("  GET  /nar/:file.nar");
-    tracing::info!("  GET  /log/*path");
+    tracing::info!("  PUT  /:hash.narinfo, /nar/:file (with CACHE_CHUNK_DIR)");
+    tracing::info!("  DELETE /:hash.narinfo (with CACHE_CHUNK_DIR)");
+    tracing::info!("  GET  /log/:name (with CACHE_LOG_DIR, kept gzipped)");
+    tracing::info!("  GET  /metrics");
 
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nar_hashes_are_converted_to_nix_base32() {
+        // sha256 of the empty string
+        let base32 = "sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73";
+        assert_eq!(
+            nar_hash_base32("sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=").as_deref(),
+            Some(base32)
+        );
+        assert_eq!(nar_hash_base32(base32).as_deref(), Some(base32));
+        assert_eq!(nar_hash_base32("md5-1B2M2Y8AsgTpgAmY7PhCfg=="), None);
+    }
+
+    #[test]
+    fn writes_need_the_upload_token_or_this_host() {
+        let local = IpAddr::from([127, 0, 0, 1]);
+        let remote = IpAddr::from([10, 0, 0, 7]);
+        let with = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+            headers
+        };
+
+        assert_eq!(authorize_write(None, local, &HeaderMap::new()), Ok(()));
+        assert_eq!(
+            authorize_write(None, remote, &with("Bearer anything")),
+            Err(StatusCode::FORBIDDEN)
+        );
+
+        let token = Some("s3cret");
+        assert_eq!(
+            authorize_write(token, remote, &with("Bearer s3cret")),
+            Ok(())
+        );
+        // nix copy --to with `password s3cret` in its netrc
+        let basic = format!("Basic {}", STANDARD.encode("ci:s3cret"));
+        assert_eq!(authorize_write(token, remote, &with(&basic)), Ok(()));
+        for refused in ["Bearer s3cre", "Bearer s3cret2", "Basic !!"] {
+            assert_eq!(
+                authorize_write(token, remote, &with(refused)),
+                Err(StatusCode::UNAUTHORIZED),
+                "{refused}"
+            );
+        }
+        assert_eq!(
+            authorize_write(token, local, &HeaderMap::new()),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+    }
+}
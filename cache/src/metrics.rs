@@ -0,0 +1,94 @@
+//! # Prometheus metrics
+//!
+//! Recorded through the [`metrics`] facade and served in the Prometheus text
+//! format on `GET /metrics` of the cache itself.
+//!
+//! | Metric                                       | Type    | Labels   |
+//! |----------------------------------------------|---------|----------|
+//! | `procurator_cache_narinfo_lookups_total`     | counter | `result` |
+//! | `procurator_cache_narinfo_cached_entries`    | gauge   | `cache`  |
+//! | `procurator_cache_chunk_store_bytes`         | gauge   | `kind`   |
+//! | `procurator_cache_signatures_total`          | counter | `result` |
+//! | `procurator_cache_build_logs_total`          | counter | `result` |
+//!
+//! `result` is `hot` (served from memory), `negative` (known missing),
+//! `store_hit`, `chunk_hit`, `sibling_hit` or `store_miss` (asked
+//! `nix-store`, then the uploads in the chunk store, then the sibling
+//! caches). The hot hit ratio is `hot / sum`, the share of misses
+//! answered without the store is `negative / (negative + store_miss)`.
+//!
+//! `kind` is `logical` (sum of the uploaded NAR sizes) or `stored` (what
+//! their deduplicated chunks take); their ratio is the deduplication ratio.
+//!
+//! A signature's `result` is `cached` (signed before), `signed` (by the
+//! [signer](crate::signer)) or `failed`.
+//!
+//! A build log's `result` is `stored` (served from the
+//! [log store](crate::build_logs)), `fetched` (from `nix log`) or
+//! `missing`.
+
+use metrics::{counter, describe_counter, describe_gauge, gauge};
+use metrics_exporter_prometheus::{BuildError, PrometheusBuilder, PrometheusHandle};
+
+use crate::chunk_store::Usage;
+
+pub const NARINFO_LOOKUPS: &str = "procurator_cache_narinfo_lookups_total";
+pub const NARINFO_CACHED_ENTRIES: &str = "procurator_cache_narinfo_cached_entries";
+pub const CHUNK_STORE_BYTES: &str = "procurator_cache_chunk_store_bytes";
+pub const SIGNATURES: &str = "procurator_cache_signatures_total";
+pub const BUILD_LOGS: &str = "procurator_cache_build_logs_total";
+
+/// Install the Prometheus recorder; the handle renders `GET /metrics`.
+///
+/// # Errors
+///
+/// - if a recorder is already installed
+pub fn install() -> Result<PrometheusHandle, BuildError> {
+    let handle = PrometheusBuilder::new().install_recorder()?;
+    describe_counter!(
+        NARINFO_LOOKUPS,
+        "Narinfo requests by where the answer came from"
+    );
+    describe_gauge!(
+        NARINFO_CACHED_ENTRIES,
+        "Entries of the in-memory hot and negative narinfo caches"
+    );
+    describe_gauge!(
+        CHUNK_STORE_BYTES,
+        "Uploaded NAR bytes, and the bytes their deduplicated chunks take"
+    );
+    describe_counter!(
+        SIGNATURES,
+        "Narinfo signatures by whether the signer was asked"
+    );
+    describe_counter!(
+        BUILD_LOGS,
+        "Build log requests by whether the log store had the log"
+    );
+    Ok(handle)
+}
+
+/// Count a narinfo lookup answered by `result`, with the cache sizes after it.
+#[allow(clippy::cast_precision_loss)]
+pub fn narinfo_lookup(result: &'static str, hot: usize, missing: usize) {
+    counter!(NARINFO_LOOKUPS, "result" => result).increment(1);
+    gauge!(NARINFO_CACHED_ENTRIES, "cache" => "hot").set(hot as f64);
+    gauge!(NARINFO_CACHED_ENTRIES, "cache" => "negative").set(missing as f64);
+}
+
+/// Sizes of the chunk store.
+#[allow(clippy::cast_precision_loss)]
+pub fn chunk_store_usage(usage: &Usage) {
+    gauge!(CHUNK_STORE_BYTES, "kind" => "logical").set(usage.logical_bytes as f64);
+    gauge!(CHUNK_STORE_BYTES, "kind" => "stored").set(usage.stored_bytes as f64);
+}
+
+/// Count a narinfo signature by `result`.
+pub fn signature(result: &'static str) {
+    counter!(SIGNATURES, "result" => result).increment(1);
+}
+
+/// Count a build log request answered by `result`.
+pub fn build_log(result: &'static str) {
+    counter!(BUILD_LOGS, "result" => result).increment(1);
+}
@@ -0,0 +1,203 @@
+//! In-memory narinfo caches
+//!
+//! Every narinfo request used to run `nix-store` twice, even for a hash that
+//! was just served or just found missing. Two caches sit in front of it:
+//!
+//! - the hot cache keeps the last `capacity` narinfo responses served,
+//!   least recently used evicted first. A store path never changes once
+//!   valid, so entries only leave by eviction;
+//! - the negative cache remembers hashes the store does not have for
+//!   `negative_ttl`. Paths appear when someone pushes, so misses expire
+//!   instead of being kept until evicted.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_CAPACITY: usize = 10_000;
+pub const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_mins(1);
+
+/// What the caches know about a hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// Served recently, this is the narinfo
+    Hot(Arc<str>),
+    /// Found missing less than `negative_ttl` ago
+    Missing,
+    /// Not cached, ask the store
+    Unknown,
+}
+
+#[derive(Debug)]
+pub struct NarinfoCache {
+    capacity: usize,
+    negative_ttl: Duration,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// hash part → (narinfo, last use)
+    hot: HashMap<String, (Arc<str>, u64)>,
+    /// last use → hash part, oldest first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    /// hash part → when it was found missing
+    missing: HashMap<String, Instant>,
+}
+
+impl NarinfoCache {
+    /// Caches holding at most `capacity` narinfos and `capacity` missing
+    /// hashes; a `capacity` of `0` disables both.
+    #[must_use]
+    pub fn new(capacity: usize, negative_ttl: Duration) -> Self {
+        Self {
+            capacity,
+            negative_ttl,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn lookup(&self, hash: &str, now: Instant) -> Lookup {
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        inner.tick += 1;
+        let tick = inner.tick;
+        if let Some((narinfo, used)) = inner.hot.get_mut(hash) {
+            let narinfo = narinfo.clone();
+            let previous = std::mem::replace(used, tick);
+            inner.recency.remove(&previous);
+            inner.recency.insert(tick, hash.to_string());
+            return Lookup::Hot(narinfo);
+        }
+        match inner.missing.get(hash) {
+            Some(&at) if now.duration_since(at) < self.negative_ttl => Lookup::Missing,
+            Some(_) => {
+                inner.missing.remove(hash);
+                Lookup::Unknown
+            }
+            None => Lookup::Unknown,
+        }
+    }
+
+    /// Remember `narinfo` as the response for `hash`.
+    pub fn insert(&self, hash: &str, narinfo: Arc<str>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.lock();
+        inner.missing.remove(hash);
+        inner.tick += 1;
+        let tick = inner.tick;
+        if let Some((_, previous)) = inner.hot.insert(hash.to_string(), (narinfo, tick)) {
+            inner.recency.remove(&previous);
+        }
+        inner.recency.insert(tick, hash.to_string());
+        while inner.hot.len() > self.capacity {
+            let Some((_, oldest)) = inner.recency.pop_first() else {
+                break;
+            };
+            inner.hot.remove(&oldest);
+        }
+    }
+
+    /// Remember that the store has no path for `hash`.
+    pub fn insert_missing(&self, hash: &str, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.lock();
+        if inner.missing.len() >= self.capacity {
+            let ttl = self.negative_ttl;
+            inner.missing.retain(|_, at| now.duration_since(*at) < ttl);
+        }
+        if inner.missing.len() >= self.capacity {
+            // All still fresh: make room by forgetting the oldest miss
+            let oldest = inner
+                .missing
+                .iter()
+                .min_by_key(|(_, at)| **at)
+                .map(|(hash, _)| hash.clone());
+            if let Some(oldest) = oldest {
+                inner.missing.remove(&oldest);
+            }
+        }
+        inner.missing.insert(hash.to_string(), now);
+    }
+
+    /// Forget the narinfo of `hash`, e.g. once it was deleted.
+    pub fn remove(&self, hash: &str) {
+        let mut inner = self.lock();
+        if let Some((_, used)) = inner.hot.remove(hash) {
+            inner.recency.remove(&used);
+        }
+    }
+
+    /// Number of (hot, missing) entries.
+    pub fn sizes(&self) -> (usize, usize) {
+        let inner = self.lock();
+        (inner.hot.len(), inner.missing.len())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // Every update leaves the maps consistent before anything can panic
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn narinfo(text: &str) -> Arc<str> {
+        Arc::from(text)
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let cache = NarinfoCache::new(2, DEFAULT_NEGATIVE_TTL);
+        let now = Instant::now();
+        cache.insert("a", narinfo("A"));
+        cache.insert("b", narinfo("B"));
+        assert_eq!(cache.lookup("a", now), Lookup::Hot(narinfo("A")));
+
+        cache.insert("c", narinfo("C"));
+        assert_eq!(cache.lookup("b", now), Lookup::Unknown);
+        assert_eq!(cache.lookup("a", now), Lookup::Hot(narinfo("A")));
+        assert_eq!(cache.lookup("c", now), Lookup::Hot(narinfo("C")));
+        assert_eq!(cache.sizes(), (2, 0));
+    }
+
+    #[test]
+    fn misses_expire_and_are_replaced_by_pushes() {
+        let cache = NarinfoCache::new(10, Duration::from_secs(30));
+        let now = Instant::now();
+        cache.insert_missing("a", now);
+        cache.insert_missing("b", now);
+        assert_eq!(
+            cache.lookup("a", now + Duration::from_secs(29)),
+            Lookup::Missing
+        );
+        assert_eq!(
+            cache.lookup("a", now + Duration::from_secs(30)),
+            Lookup::Unknown
+        );
+
+        cache.insert("b", narinfo("B"));
+        assert_eq!(cache.lookup("b", now), Lookup::Hot(narinfo("B")));
+        cache.remove("b");
+        assert_eq!(cache.lookup("b", now), Lookup::Unknown);
+        assert_eq!(cache.sizes(), (0, 0));
+    }
+
+    #[test]
+    fn misses_are_bounded() {
+        let cache = NarinfoCache::new(2, Duration::from_secs(30));
+        let now = Instant::now();
+        cache.insert_missing("a", now);
+        cache.insert_missing("b", now + Duration::from_secs(1));
+        cache.insert_missing("c", now + Duration::from_secs(2));
+        assert_eq!(cache.sizes(), (0, 2));
+        assert_eq!(cache.lookup("a", now), Lookup::Unknown);
+    }
+}
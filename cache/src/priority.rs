@@ -0,0 +1,126 @@
+//! Priority advertised in `/nix-cache-info`, per client class
+//!
+//! Nix tries substituters in priority order, lower first. A cache shared by
+//! several sites should be the first choice for clients on its own site and
+//! a fallback for the others, so the priority depends on where the client
+//! connects from. Classes are subnets, configured as
+//! `10.1.0.0/16=10,10.2.0.0/16=50`; the longest matching prefix wins and
+//! clients outside every class get the default.
+
+use std::net::IpAddr;
+
+/// Priority of nix-serve and of this cache before classes existed.
+pub const DEFAULT_PRIORITY: u32 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid client class {0:?}, expected <address>/<prefix>=<priority>")]
+pub struct InvalidClass(String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Subnet {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    fn contains(self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as mapped addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        match (self.address, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_eq(
+                u128::from(net.to_bits()) << 96,
+                u128::from(ip.to_bits()) << 96,
+                self.prefix,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(net.to_bits(), ip.to_bits(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_eq(a: u128, b: u128, prefix: u8) -> bool {
+    prefix == 0 || (a ^ b) >> (128 - u32::from(prefix)) == 0
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientClasses {
+    default: u32,
+    classes: Vec<(Subnet, u32)>,
+}
+
+impl ClientClasses {
+    /// Parse `spec`, comma separated `<address>/<prefix>=<priority>`.
+    ///
+    /// # Errors
+    ///
+    /// - on the first entry that is not a subnet and a priority
+    pub fn parse(default: u32, spec: &str) -> Result<Self, InvalidClass> {
+        let mut classes = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let invalid = || InvalidClass(entry.to_string());
+            let (subnet, priority) = entry.split_once('=').ok_or_else(invalid)?;
+            let (address, prefix) = subnet.trim().split_once('/').ok_or_else(invalid)?;
+            let address: IpAddr = address.parse().map_err(|_| invalid())?;
+            let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+            let max = if address.is_ipv4() { 32 } else { 128 };
+            if prefix > max {
+                return Err(invalid());
+            }
+            let priority = priority.trim().parse().map_err(|_| invalid())?;
+            classes.push((Subnet { address, prefix }, priority));
+        }
+        Ok(Self { default, classes })
+    }
+
+    /// Priority to advertise to a client connecting from `ip`.
+    #[must_use]
+    pub fn priority(&self, ip: IpAddr) -> u32 {
+        self.classes
+            .iter()
+            .filter(|(subnet, _)| subnet.contains(ip))
+            .max_by_key(|(subnet, _)| subnet.prefix)
+            .map_or(self.default, |(_, priority)| *priority)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let classes =
+            ClientClasses::parse(40, "10.0.0.0/8=50, 10.1.0.0/16=10, fd00::/8=20").unwrap();
+        assert_eq!(classes.priority(ip("10.1.2.3")), 10);
+        assert_eq!(classes.priority(ip("10.2.0.1")), 50);
+        assert_eq!(classes.priority(ip("::ffff:10.1.0.9")), 10);
+        assert_eq!(classes.priority(ip("fd12::1")), 20);
+        assert_eq!(classes.priority(ip("192.168.1.1")), 40);
+    }
+
+    #[test]
+    fn rejects_malformed_classes() {
+        assert!(ClientClasses::parse(30, "").unwrap().classes.is_empty());
+        for spec in [
+            "10.0.0.0/8",
+            "10.0.0.0=5",
+            "10.0.0.0/33=5",
+            "nope/8=5",
+            "10.0.0.0/8=high",
+        ] {
+            assert_eq!(
+                ClientClasses::parse(30, spec),
+                Err(InvalidClass(spec.to_string()))
+            );
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Narinfo signing, optionally delegated to a hardened component
+//!
+//! The narinfos the cache builds from its store carry a signature of the
+//! path's fingerprint (`1;<store path>;<nar hash>;<nar size>;<references>`).
+//! Who holds the key depends on `CACHE_SIGNER`:
+//!
+//! - `local` (the default): the key in `NIX_SECRET_KEY_FILE`, as written by
+//!   `nix-store --generate-binary-cache-key`, read at startup;
+//! - an `http://` or `https://` URL, or `unix:<socket path>`: a signer
+//!   service. It is sent the fingerprint as the body of a `POST` (to the
+//!   URL, or to `/sign` over the socket) and answers `200` with the
+//!   signature, `<key name>:<base64>`, as its body;
+//! - `pkcs11:<module path>`: an HSM or token, through `pkcs11-tool`. The
+//!   Ed25519 key is `CACHE_PKCS11_KEY_ID` (hex), the PIN is read from
+//!   `CACHE_PKCS11_PIN_FILE` and handed over in the environment, and
+//!   `CACHE_SIGNING_KEY_NAME` names the key in signatures.
+//!
+//! A fingerprint never changes what it describes, so its signature is
+//! cached: the last `CACHE_SIGNATURE_ENTRIES` (default 10000) are kept, the
+//! oldest dropped first, and a path served again after leaving the narinfo
+//! cache does not cost another round trip to the signer.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use ed25519_dalek::{SIGNATURE_LENGTH, Signer as _, SigningKey};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::process::Command;
+
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// How long a signer service or token gets to answer.
+const SIGN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Environment variable `pkcs11-tool` reads the PIN from.
+const PIN_VAR: &str = "CACHE_PKCS11_PIN";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignError {
+    /// The signer failed, or did not answer in time
+    #[error("signer unavailable: {0}")]
+    Unavailable(String),
+    /// The signer service could not be reached or answered an error
+    #[error("signer unavailable")]
+    Http(#[from] reqwest::Error),
+    /// The signer's socket or `pkcs11-tool` could not be used
+    #[error("signer unavailable")]
+    Io(#[from] std::io::Error),
+    /// The signer answered something that is not a signature
+    #[error("invalid signature from signer: {0}")]
+    Invalid(String),
+}
+
+/// Who produces the signatures
+enum Backend {
+    Local {
+        name: String,
+        key: SigningKey,
+    },
+    Http {
+        client: reqwest::Client,
+        url: String,
+    },
+    Unix {
+        socket: PathBuf,
+    },
+    Pkcs11 {
+        module: String,
+        key_id: String,
+        pin: String,
+        name: String,
+    },
+}
+
+impl fmt::Debug for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Local { name, .. } => write!(f, "local key {name}"),
+            Backend::Http { url, .. } => write!(f, "signer at {url}"),
+            Backend::Unix { socket } => write!(f, "signer at unix:{}", socket.display()),
+            Backend::Pkcs11 { module, key_id, .. } => {
+                write!(f, "PKCS#11 key {key_id} of {module}")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Signer {
+    backend: Backend,
+    capacity: usize,
+    signatures: Mutex<Signatures>,
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.backend)
+    }
+}
+
+/// Signatures by fingerprint, and the fingerprints oldest first
+#[derive(Debug, Default)]
+struct Signatures {
+    by_fingerprint: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+impl Signer {
+    /// The signer configured by `CACHE_SIGNER`, `None` when signing is off:
+    /// `local` without `NIX_SECRET_KEY_FILE`.
+    ///
+    /// # Errors
+    ///
+    /// - if `CACHE_SIGNER` is not one of the supported forms
+    /// - if the secret key, or the PKCS#11 settings, cannot be read
+    pub fn from_env() -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let signer = var("CACHE_SIGNER").unwrap_or_else(|| "local".to_string());
+        let backend = if signer == "local" {
+            let Some(path) = var("NIX_SECRET_KEY_FILE") else {
+                return Ok(None);
+            };
+            tracing::info!("Loading secret key from: {}", path);
+            let (name, key) = parse_secret_key(std::fs::read_to_string(&path)?.trim())?;
+            Backend::Local { name, key }
+        } else if signer.starts_with("http://") || signer.starts_with("https://") {
+            Backend::Http {
+                client: reqwest::Client::builder().timeout(SIGN_TIMEOUT).build()?,
+                url: signer,
+            }
+        } else if let Some(socket) = signer.strip_prefix("unix:") {
+            Backend::Unix {
+                socket: PathBuf::from(socket),
+            }
+        } else if let Some(module) = signer.strip_prefix("pkcs11:") {
+            let required =
+                |name: &str| var(name).ok_or(format!("{name} is required with {signer}"));
+            let pin_file = required("CACHE_PKCS11_PIN_FILE")?;
+            Backend::Pkcs11 {
+                module: module.to_string(),
+                key_id: required("CACHE_PKCS11_KEY_ID")?,
+                pin: std::fs::read_to_string(pin_file)?.trim().to_string(),
+                name: required("CACHE_SIGNING_KEY_NAME")?,
+            }
+        } else {
+            return Err(format!(
+                "CACHE_SIGNER must be local, an http(s) URL, unix:<path> or pkcs11:<module>, \
+                 got {signer:?}"
+            )
+            .into());
+        };
+        let capacity = var("CACHE_SIGNATURE_ENTRIES")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_CAPACITY);
+        Ok(Some(Self::new(backend, capacity)))
+    }
+
+    fn new(backend: Backend, capacity: usize) -> Self {
+        Self {
+            backend,
+            capacity,
+            signatures: Mutex::default(),
+        }
+    }
+
+    /// Signature of `fingerprint`, `<key name>:<base64>`.
+    ///
+    /// # Errors
+    ///
+    /// - if the signer fails or answers something that is not a signature
+    pub async fn sign(&self, fingerprint: &str) -> Result<String, SignError> {
+        if let Some(signature) = self.lock().by_fingerprint.get(fingerprint) {
+            crate::metrics::signature("cached");
+            return Ok(signature.clone());
+        }
+        let signature = match &self.backend {
+            Backend::Local { name, key } => Ok(format!(
+                "{name}:{}",
+                STANDARD.encode(key.sign(fingerprint.as_bytes()).to_bytes())
+            )),
+            Backend::Http { client, url } => sign_http(client, url, fingerprint).await,
+            Backend::Unix { socket } => {
+                tokio::time::timeout(SIGN_TIMEOUT, sign_unix(socket, fingerprint))
+                    .await
+                    .unwrap_or_else(|_| Err(SignError::Unavailable("timed out".to_string())))
+            }
+            Backend::Pkcs11 {
+                module,
+                key_id,
+                pin,
+                name,
+            } => sign_pkcs11(module, key_id, pin, fingerprint)
+                .await
+                .map(|raw| format!("{name}:{}", STANDARD.encode(raw))),
+        };
+        let signature = signature
+            .and_then(|signature| check_signature(&signature))
+            .inspect_err(|_| crate::metrics::signature("failed"))?;
+        crate::metrics::signature("signed");
+        self.remember(fingerprint, &signature);
+        Ok(signature)
+    }
+
+    fn remember(&self, fingerprint: &str, signature: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut signatures = self.lock();
+        if signatures
+            .by_fingerprint
+            .insert(fingerprint.to_string(), signature.to_string())
+            .is_none()
+        {
+            signatures.order.push_back(fingerprint.to_string());
+        }
+        while signatures.order.len() > self.capacity {
+            if let Some(oldest) = signatures.order.pop_front() {
+                signatures.by_fingerprint.remove(&oldest);
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Signatures> {
+        self.signatures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Parse a `<key name>:<base64>` secret key; the base64 holds the 32 byte
+/// seed, followed by the public key in keys written by Nix.
+fn parse_secret_key(secret_key: &str) -> Result<(String, SigningKey), Box<dyn std::error::Error>> {
+    let (name, key) = secret_key
+        .split_once(':')
+        .ok_or("Invalid secret key format")?;
+    let bytes = STANDARD.decode(key)?;
+    let seed: [u8; 32] = bytes
+        .get(..32)
+        .and_then(|seed| seed.try_into().ok())
+        .ok_or("Invalid key length")?;
+    Ok((name.to_string(), SigningKey::from_bytes(&seed)))
+}
+
+/// Accept only `<key name>:<base64 of a 64 byte signature>`.
+fn check_signature(signature: &str) -> Result<String, SignError> {
+    let signature = signature.trim().to_string();
+    let valid = signature.split_once(':').is_some_and(|(name, sig)| {
+        !name.is_empty()
+            && STANDARD
+                .decode(sig)
+                .is_ok_and(|bytes| bytes.len() == SIGNATURE_LENGTH)
+    });
+    if valid {
+        Ok(signature)
+    } else {
+        Err(SignError::Invalid(signature))
+    }
+}
+
+async fn sign_http(
+    client: &reqwest::Client,
+    url: &str,
+    fingerprint: &str,
+) -> Result<String, SignError> {
+    Ok(client
+        .post(url)
+        .header("Content-Type", "text/plain")
+        .body(fingerprint.to_string())
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)?
+        .text()
+        .await?)
+}
+
+/// `POST /sign` over the socket, one connection per signature.
+async fn sign_unix(socket: &std::path::Path, fingerprint: &str) -> Result<String, SignError> {
+    let mut stream = tokio::net::UnixStream::connect(socket).await?;
+    let request = format!(
+        "POST /sign HTTP/1.1\r\nHost: signer\r\nContent-Type: text/plain\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{fingerprint}",
+        fingerprint.len()
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    http_body(&String::from_utf8_lossy(&response))
+}
+
+/// Body of a `200` HTTP/1.1 response, read until the connection closed.
+fn http_body(response: &str) -> Result<String, SignError> {
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| SignError::Invalid("truncated HTTP response".to_string()))?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(SignError::Unavailable(status.to_string()));
+    }
+    if head
+        .lines()
+        .any(|line| line.eq_ignore_ascii_case("transfer-encoding: chunked"))
+    {
+        return Err(SignError::Invalid(
+            "chunked responses are not supported".to_string(),
+        ));
+    }
+    Ok(body.to_string())
+}
+
+/// Raw Ed25519 signature of `fingerprint` by key `key_id` of the token.
+async fn sign_pkcs11(
+    module: &str,
+    key_id: &str,
+    pin: &str,
+    fingerprint: &str,
+) -> Result<Vec<u8>, SignError> {
+    let mut child = Command::new("pkcs11-tool")
+        .args(["--module", module, "--login", "--pin"])
+        .arg(format!("env:{PIN_VAR}"))
+        .args(["--sign", "--mechanism", "EDDSA", "--id", key_id])
+        .env(PIN_VAR, pin)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(fingerprint.as_bytes()).await?;
+    }
+    let output = tokio::time::timeout(SIGN_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| SignError::Unavailable("pkcs11-tool timed out".to_string()))??;
+    if !output.status.success() {
+        return Err(SignError::Unavailable(format!(
+            "pkcs11-tool failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET_KEY: &str = "cache.local:hfNlzq2QP6JLd9ghjD/6+IZ3A21etwghcrKP5wvJV2k=";
+
+    fn local(capacity: usize) -> Signer {
+        let (name, key) = parse_secret_key(SECRET_KEY).unwrap();
+        Signer::new(Backend::Local { name, key }, capacity)
+    }
+
+    #[tokio::test]
+    async fn signatures_are_cached_per_fingerprint() {
+        let signer = local(2);
+        let first = signer.sign("1;/nix/store/a;sha256:x;1;").await.unwrap();
+        assert!(first.starts_with("cache.local:"));
+        assert_eq!(check_signature(&first).unwrap(), first);
+        assert_eq!(
+            signer.sign("1;/nix/store/a;sha256:x;1;").await.unwrap(),
+            first
+        );
+
+        signer.sign("1;/nix/store/b;sha256:y;1;").await.unwrap();
+        signer.sign("1;/nix/store/c;sha256:z;1;").await.unwrap();
+        let signatures = signer.lock();
+        assert_eq!(signatures.order.len(), 2);
+        assert!(
+            !signatures
+                .by_fingerprint
+                .contains_key("1;/nix/store/a;sha256:x;1;")
+        );
+    }
+
+    #[test]
+    fn signer_answers_must_be_signatures() {
+        assert!(check_signature("cache.local:c2lnbmF0dXJl").is_err());
+        assert!(check_signature(&format!(":{}", STANDARD.encode([0; 64]))).is_err());
+        assert_eq!(
+            http_body("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nk:sig").unwrap(),
+            "k:sig"
+        );
+        assert!(matches!(
+            http_body("HTTP/1.1 403 Forbidden\r\n\r\n"),
+            Err(SignError::Unavailable(_))
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+//! A master plus N simulated workers, and the scenario-facing API.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use worker::dto::VmSpec;
+
+use crate::fault::{FaultPlan, FaultStats};
+use crate::invariant::{self, Violation, WorkerView};
+use crate::master::{CallError, Master, MasterClient};
+use crate::sim_worker::SimWorker;
+
+/// How often `wait_converged` re-checks the cluster.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    pub workers: usize,
+    pub faults: FaultPlan,
+    /// Time between two assignment polls of one worker.
+    pub poll_interval: Duration,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            workers: 3,
+            faults: FaultPlan::default(),
+            poll_interval: Duration::from_millis(50),
+        }
+    }
+}
+
+pub struct Cluster {
+    // Workers are declared first so they stop before the master does.
+    workers: Vec<SimWorker>,
+    client: MasterClient,
+    master: Master,
+    stats: Arc<FaultStats>,
+    generation: u64,
+    desired: Vec<String>,
+}
+
+impl Cluster {
+    /// Start the master and the workers. Must run inside a `LocalSet`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the master cannot be started or does not accept connections.
+    pub async fn start(config: ClusterConfig) -> Result<Self, CallError> {
+        let master = Master::start()?;
+        let client = connect(&master).await?;
+
+        let stats = Arc::new(FaultStats::default());
+        let workers = (0..config.workers as u64)
+            .map(|index| {
+                SimWorker::start(
+                    index,
+                    master.addr(),
+                    config.faults.clone(),
+                    stats.clone(),
+                    config.poll_interval,
+                )
+            })
+            .collect();
+
+        Ok(Self {
+            workers,
+            client,
+            master,
+            stats,
+            generation: 0,
+            desired: Vec::new(),
+        })
+    }
+
+    /// Publish `specs` as the next generation and return it. The harness's
+    /// own connection to the master is never faulted.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the master rejects the state or cannot be reached.
+    pub async fn publish(&mut self, specs: &[VmSpec]) -> Result<u64, CallError> {
+        let generation = self.generation + 1;
+        self.client.publish_state(generation, specs).await?;
+        self.generation = generation;
+        self.desired = specs.iter().map(|s| s.content_hash().to_string()).collect();
+        Ok(generation)
+    }
+
+    #[must_use]
+    pub fn workers(&self) -> &[SimWorker] {
+        &self.workers
+    }
+
+    /// Crash worker `index`; its VMs are gone.
+    pub fn crash(&mut self, index: usize) {
+        self.workers[index].crash();
+    }
+
+    pub fn restart(&mut self, index: usize) {
+        self.workers[index].restart();
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> &FaultStats {
+        &self.stats
+    }
+
+    pub async fn views(&self) -> Vec<WorkerView> {
+        let mut views = Vec::with_capacity(self.workers.len());
+        for worker in &self.workers {
+            views.push(worker.view().await);
+        }
+        views
+    }
+
+    /// Check the safety invariant once.
+    ///
+    /// # Errors
+    ///
+    /// Every safety violation present right now.
+    pub async fn check_safety(&self) -> Result<(), Vec<Violation>> {
+        let violations = invariant::check_safety(&self.views().await);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Wait until the cluster runs the last published state. Fails as soon
+    /// as safety breaks, or with the remaining violations after `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// The safety violations, or the convergence violations left at the
+    /// deadline.
+    pub async fn wait_converged(&self, timeout: Duration) -> Result<(), Vec<Violation>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let views = self.views().await;
+            let safety = invariant::check_safety(&views);
+            if !safety.is_empty() {
+                return Err(safety);
+            }
+            let violations = invariant::check_converged(self.generation, &self.desired, &views);
+            if violations.is_empty() {
+                return Ok(());
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(violations);
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Stop the workers, then the master.
+    pub fn shutdown(mut self) {
+        self.workers.clear();
+        self.master.stop();
+    }
+}
+
+/// The master thread needs a moment to bind; retry until it accepts.
+async fn connect(master: &Master) -> Result<MasterClient, CallError> {
+    let mut attempts = 50;
+    loop {
+        match MasterClient::connect(master.addr()).await {
+            Ok(client) => return Ok(client),
+            Err(e) if attempts == 0 => return Err(e),
+            Err(_) => {
+                attempts -= 1;
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+    }
+}
+
+/// A VM spec whose toplevel is `/nix/store/<name>`; the mock backend does
+/// not look at the other paths.
+#[must_use]
+pub fn vm(name: &str) -> VmSpec {
+    VmSpec::new(
+        format!("/nix/store/{name}"),
+        format!("/nix/store/{name}/kernel"),
+        format!("/nix/store/{name}/initrd"),
+        format!("/nix/store/{name}/disk.img"),
+        "console=ttyS0".into(),
+        1,
+        256,
+        Vec::new(),
+    )
+}
@@ -0,0 +1,168 @@
+//! Seeded fault injection.
+//!
+//! Every simulated worker draws its faults from its own generator, seeded
+//! from the plan's `seed` and the worker's index, so a failing run replays
+//! the same faults on the same workers.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Faults applied to the traffic between workers and the master.
+///
+/// Crashes are not part of the plan: the scenario decides when a worker goes
+/// down, see [`crate::Cluster::crash`].
+#[derive(Debug, Clone)]
+pub struct FaultPlan {
+    /// Probability, in `0.0..=1.0`, that a worker RPC is dropped before it
+    /// reaches the master.
+    pub rpc_drop_rate: f64,
+    /// Each heartbeat (`pushData`) is held back by up to this long.
+    pub max_heartbeat_delay: Duration,
+    pub seed: u64,
+}
+
+impl Default for FaultPlan {
+    fn default() -> Self {
+        Self {
+            rpc_drop_rate: 0.0,
+            max_heartbeat_delay: Duration::ZERO,
+            seed: 0,
+        }
+    }
+}
+
+/// Faults injected so far, summed over all workers.
+#[derive(Debug, Default)]
+pub struct FaultStats {
+    dropped_rpcs: AtomicU64,
+    delayed_heartbeats: AtomicU64,
+    crashes: AtomicU64,
+}
+
+impl FaultStats {
+    #[must_use]
+    pub fn dropped_rpcs(&self) -> u64 {
+        self.dropped_rpcs.load(Ordering::Relaxed)
+    }
+
+    #[must_use]
+    pub fn delayed_heartbeats(&self) -> u64 {
+        self.delayed_heartbeats.load(Ordering::Relaxed)
+    }
+
+    #[must_use]
+    pub fn crashes(&self) -> u64 {
+        self.crashes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_crash(&self) {
+        self.crashes.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// One worker's fault decisions.
+pub(crate) struct Faults {
+    plan: FaultPlan,
+    state: u64,
+    stats: Arc<FaultStats>,
+}
+
+impl Faults {
+    /// Generator for the worker at `stream`; distinct streams of the same
+    /// plan draw independent sequences.
+    pub(crate) fn new(plan: &FaultPlan, stream: u64, stats: Arc<FaultStats>) -> Self {
+        let mut seed = plan.seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        Self {
+            plan: plan.clone(),
+            state: splitmix64(&mut seed),
+            stats,
+        }
+    }
+
+    /// Whether the next RPC is dropped.
+    pub(crate) fn drop_rpc(&mut self) -> bool {
+        let dropped = self.plan.rpc_drop_rate > 0.0 && self.next_f64() < self.plan.rpc_drop_rate;
+        if dropped {
+            self.stats.dropped_rpcs.fetch_add(1, Ordering::Relaxed);
+        }
+        dropped
+    }
+
+    /// How long to hold back the next heartbeat.
+    pub(crate) fn heartbeat_delay(&mut self) -> Duration {
+        if self.plan.max_heartbeat_delay.is_zero() {
+            return Duration::ZERO;
+        }
+        self.stats
+            .delayed_heartbeats
+            .fetch_add(1, Ordering::Relaxed);
+        self.plan.max_heartbeat_delay.mul_f64(self.next_f64())
+    }
+
+    /// xorshift64*.
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in `0.0..1.0`.
+    #[allow(clippy::cast_precision_loss)]
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Spreads a seed over all 64 bits; xorshift must not start from zero.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)) | 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(rate: f64, seed: u64) -> FaultPlan {
+        FaultPlan {
+            rpc_drop_rate: rate,
+            max_heartbeat_delay: Duration::from_millis(100),
+            seed,
+        }
+    }
+
+    fn drops(faults: &mut Faults, n: usize) -> Vec<bool> {
+        (0..n).map(|_| faults.drop_rpc()).collect()
+    }
+
+    #[test]
+    fn same_seed_and_stream_replay_the_same_faults() {
+        let stats = Arc::new(FaultStats::default());
+        let a = drops(&mut Faults::new(&plan(0.5, 7), 1, stats.clone()), 64);
+        let b = drops(&mut Faults::new(&plan(0.5, 7), 1, stats.clone()), 64);
+        let other = drops(&mut Faults::new(&plan(0.5, 7), 2, stats.clone()), 64);
+        assert_eq!(a, b);
+        assert_ne!(a, other);
+        assert_eq!(
+            stats.dropped_rpcs(),
+            [a, b, other].iter().flatten().filter(|d| **d).count() as u64
+        );
+    }
+
+    #[test]
+    fn drop_rate_bounds_are_exact() {
+        let stats = Arc::new(FaultStats::default());
+        assert!(!drops(&mut Faults::new(&plan(0.0, 3), 0, stats.clone()), 256).contains(&true));
+        assert!(!drops(&mut Faults::new(&plan(1.0, 3), 0, stats.clone()), 256).contains(&false));
+
+        let mut faults = Faults::new(&plan(0.0, 3), 0, stats);
+        for _ in 0..256 {
+            assert!(faults.heartbeat_delay() < Duration::from_millis(100));
+        }
+    }
+}
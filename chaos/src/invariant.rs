@@ -0,0 +1,191 @@
+//! Cluster invariants, checked against what the workers actually run.
+//!
+//! - **Safety** must hold at every instant, faults or not: no VM runs on
+//!   two workers at once.
+//! - **Convergence** must hold eventually once faults stop: every desired VM
+//!   runs exactly once, nothing else runs, and every live worker has caught up
+//!   to the published generation.
+//!
+//! VMs are identified by their spec hash (`commands::hashing`), the
+//! `desiredHash` workers report.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// One worker as seen by the harness.
+#[derive(Debug, Clone)]
+pub struct WorkerView {
+    pub id: String,
+    /// False while the worker is crashed.
+    pub up: bool,
+    /// Last generation the worker applied.
+    pub observed_generation: u64,
+    /// Spec hashes of the VMs it runs.
+    pub running: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The same VM runs on more than one worker (or twice on one).
+    Duplicate { vm: String, workers: Vec<String> },
+    /// A desired VM runs nowhere.
+    Missing { vm: String },
+    /// A VM runs that is not desired.
+    Orphan { vm: String, worker: String },
+    /// A live worker has not applied the published generation.
+    Stale {
+        worker: String,
+        observed: u64,
+        published: u64,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Duplicate { vm, workers } => {
+                write!(f, "{vm} runs on {}", workers.join(", "))
+            }
+            Violation::Missing { vm } => write!(f, "{vm} runs nowhere"),
+            Violation::Orphan { vm, worker } => {
+                write!(f, "{vm} runs on {worker} but is not desired")
+            }
+            Violation::Stale {
+                worker,
+                observed,
+                published,
+            } => write!(
+                f,
+                "{worker} is at generation {observed}, published {published}"
+            ),
+        }
+    }
+}
+
+/// Placement of every running VM, spec hash → workers running it.
+fn placements(workers: &[WorkerView]) -> BTreeMap<&str, Vec<&str>> {
+    let mut placed: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for worker in workers.iter().filter(|w| w.up) {
+        for vm in &worker.running {
+            placed.entry(vm).or_default().push(&worker.id);
+        }
+    }
+    placed
+}
+
+/// Violations of the safety invariant.
+#[must_use]
+pub fn check_safety(workers: &[WorkerView]) -> Vec<Violation> {
+    placements(workers)
+        .into_iter()
+        .filter(|(_, on)| on.len() > 1)
+        .map(|(vm, on)| Violation::Duplicate {
+            vm: vm.to_string(),
+            workers: on.into_iter().map(str::to_string).collect(),
+        })
+        .collect()
+}
+
+/// Violations of convergence to `desired` at generation `published`,
+/// including safety violations.
+#[must_use]
+pub fn check_converged(
+    published: u64,
+    desired: &[String],
+    workers: &[WorkerView],
+) -> Vec<Violation> {
+    let mut violations = check_safety(workers);
+    let placed = placements(workers);
+    let wanted: BTreeSet<&str> = desired.iter().map(String::as_str).collect();
+
+    for vm in &wanted {
+        if !placed.contains_key(vm) {
+            violations.push(Violation::Missing {
+                vm: (*vm).to_string(),
+            });
+        }
+    }
+    for (vm, on) in &placed {
+        if !wanted.contains(vm) {
+            violations.extend(on.iter().map(|worker| Violation::Orphan {
+                vm: (*vm).to_string(),
+                worker: (*worker).to_string(),
+            }));
+        }
+    }
+    for worker in workers.iter().filter(|w| w.up) {
+        if worker.observed_generation != published {
+            violations.push(Violation::Stale {
+                worker: worker.id.clone(),
+                observed: worker.observed_generation,
+                published,
+            });
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(id: &str, up: bool, generation: u64, running: &[&str]) -> WorkerView {
+        WorkerView {
+            id: id.to_string(),
+            up,
+            observed_generation: generation,
+            running: running.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn desired(vms: &[&str]) -> Vec<String> {
+        vms.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn duplicates_break_safety_unless_the_worker_is_down() {
+        let workers = [
+            worker("w0", true, 1, &["a", "b"]),
+            worker("w1", true, 1, &["b"]),
+        ];
+        assert_eq!(
+            check_safety(&workers),
+            vec![Violation::Duplicate {
+                vm: "b".into(),
+                workers: vec!["w0".into(), "w1".into()],
+            }]
+        );
+
+        let workers = [
+            worker("w0", true, 1, &["a", "b"]),
+            worker("w1", false, 0, &["b"]),
+        ];
+        assert!(check_safety(&workers).is_empty());
+    }
+
+    #[test]
+    fn convergence_reports_missing_orphan_and_stale() {
+        let workers = [worker("w0", true, 2, &["a"]), worker("w1", true, 2, &["b"])];
+        assert!(check_converged(2, &desired(&["a", "b"]), &workers).is_empty());
+
+        let workers = [
+            worker("w0", true, 2, &["a", "x"]),
+            worker("w1", true, 1, &[]),
+        ];
+        assert_eq!(
+            check_converged(2, &desired(&["a", "b"]), &workers),
+            vec![
+                Violation::Missing { vm: "b".into() },
+                Violation::Orphan {
+                    vm: "x".into(),
+                    worker: "w0".into()
+                },
+                Violation::Stale {
+                    worker: "w1".into(),
+                    observed: 1,
+                    published: 2
+                },
+            ]
+        );
+    }
+}
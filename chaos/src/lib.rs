@@ -0,0 +1,33 @@
+//! # Chaos harness
+//!
+//! Runs a master and N simulated workers in one process, injects faults
+//! between them and checks cluster invariants.
+//!
+//! ```text
+//!  Cluster ──publishState──► master (own thread, real control_plane::run)
+//!                               ▲
+//!              getAssignment /  │  pushData   (dropped / delayed per FaultPlan)
+//!                               │
+//!  SimWorker × N ──► VmManager<MockBackend>   (crash / restart per scenario)
+//! ```
+//!
+//! - **Workers** run the real `VmManager` over the mock VMM backend, see
+//!   [`SimWorker`].
+//! - **Faults** come from a seeded generator: rerun with the same
+//!   [`FaultPlan::seed`] to replay a failure.
+//! - **Invariants** are checked against what the workers run, not what the
+//!   master believes, see [`invariant`].
+//!
+//! Everything except the master runs on the caller's `LocalSet`, since
+//! capnp-rpc drives its connections with `spawn_local`.
+
+mod cluster;
+mod fault;
+pub mod invariant;
+mod master;
+mod sim_worker;
+
+pub use cluster::{Cluster, ClusterConfig, vm};
+pub use fault::{FaultPlan, FaultStats};
+pub use master::{Assignment, CallError, Master, MasterClient};
+pub use sim_worker::SimWorker;
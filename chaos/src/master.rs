@@ -0,0 +1,316 @@
+//! In-process master and a client for the calls workers make.
+//!
+//! The master is the real `control_plane::run`, on its own thread and
+//! runtime so a blocked or panicking scenario cannot stall it, listening on
+//! a free loopback port with its audit log in a scratch directory.
+
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use capnp_rpc::{RpcSystem, rpc_twoparty_capnp, twoparty};
+use commands::hashing;
+use commands::labels::{read_labels, write_labels};
+use commands::telemetry::TraceHeaders;
+use commands::{common_capnp, master_capnp};
+use futures::AsyncReadExt;
+use tokio_util::sync::CancellationToken;
+use worker::dto::{VmInfo, VmSpec};
+
+/// Time the master gets to drain on stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+/// Upper bound for connecting and for one RPC.
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+// ─── Master ────────────────────────────────────────────────────────────────
+
+pub struct Master {
+    addr: SocketAddr,
+    dir: PathBuf,
+    shutdown: CancellationToken,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Master {
+    /// Start a master on a free loopback port.
+    ///
+    /// # Errors
+    ///
+    /// Fails if no port or scratch directory is available.
+    pub fn start() -> std::io::Result<Self> {
+        let addr = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let dir =
+            std::env::temp_dir().join(format!("pcr-chaos-{}-{}", std::process::id(), addr.port()));
+        std::fs::create_dir_all(&dir)?;
+
+        let shutdown = CancellationToken::new();
+        let audit = control_plane::AuditConfig::new(dir.join("audit.jsonl"));
+        let token = shutdown.clone();
+        let thread = std::thread::Builder::new()
+            .name("chaos-master".into())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("master runtime");
+                let mut config = control_plane::MasterConfig::new("chaos-master", addr, audit);
+                config.shutdown_timeout = SHUTDOWN_TIMEOUT;
+                runtime.block_on(control_plane::run(config, token));
+            })?;
+
+        Ok(Self {
+            addr,
+            dir,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    #[must_use]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop the master and wait for its thread.
+    pub fn stop(&mut self) {
+        self.shutdown.cancel();
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            tracing::error!("Master thread panicked");
+        }
+    }
+}
+
+impl Drop for Master {
+    fn drop(&mut self) {
+        self.stop();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// ─── Client ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, thiserror::Error)]
+pub enum CallError {
+    /// The master could not be started or connected to.
+    #[error("cannot reach the master")]
+    Connect(#[from] std::io::Error),
+    /// Connection or RPC failure; the caller should reconnect.
+    #[error("transport error")]
+    Transport(#[from] capnp::Error),
+    /// The call timed out; the caller should reconnect.
+    #[error("timed out")]
+    Timeout,
+    /// The master answered with an error.
+    #[error("rejected: {0}")]
+    Rejected(String),
+}
+
+/// What the master wants a worker to run.
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub generation: u64,
+    pub desired: Vec<VmSpec>,
+}
+
+/// Master client over one connection. Must run inside a `LocalSet`.
+#[derive(Clone)]
+pub struct MasterClient {
+    client: master_capnp::master::Client,
+}
+
+impl MasterClient {
+    /// # Errors
+    ///
+    /// Fails if the master does not accept the connection in time.
+    pub async fn connect(addr: SocketAddr) -> Result<Self, CallError> {
+        let stream = tokio::time::timeout(CALL_TIMEOUT, tokio::net::TcpStream::connect(addr))
+            .await
+            .map_err(|_| CallError::Timeout)??;
+        stream.set_nodelay(true)?;
+
+        let (reader, writer) = tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
+        let network = Box::new(twoparty::VatNetwork::new(
+            futures::io::BufReader::new(reader),
+            futures::io::BufWriter::new(writer),
+            rpc_twoparty_capnp::Side::Client,
+            capnp::message::ReaderOptions::default(),
+        ));
+        let mut rpc_system = RpcSystem::new(network, None);
+        let client: master_capnp::master::Client =
+            rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+        tokio::task::spawn_local(async move {
+            if let Err(e) = rpc_system.await {
+                tracing::debug!(error = %e, "Chaos RPC system terminated");
+            }
+        });
+        Ok(Self { client })
+    }
+
+    /// Master.publishState — publish `specs` as the desired cluster state,
+    /// built on the previous generation.
+    ///
+    /// # Errors
+    ///
+    /// [`CallError::Rejected`] when the master answers with an error, e.g. a
+    /// conflict with another publisher.
+    pub async fn publish_state(&self, generation: u64, specs: &[VmSpec]) -> Result<(), CallError> {
+        let mut request = self.client.publish_state_request();
+        {
+            let mut params = request.get();
+            params.set_commit("chaos");
+            params.set_publisher("chaos");
+            params.set_generation(generation);
+            params
+                .reborrow()
+                .init_parent()
+                .set_generation(generation.saturating_sub(1));
+            let hashes: Vec<_> = specs.iter().map(VmSpec::content_hash).collect();
+            params.set_intent_hash(hashing::generation_hash(&hashes).as_str());
+            let mut list = params.reborrow().init_vm_specs(len(specs)?);
+            for (i, spec) in (0..).zip(specs) {
+                write_spec(list.reborrow().get(i), spec);
+            }
+            TraceHeaders::current().write(params.init_trace());
+        }
+        let response = call(request.send().promise).await?;
+        match response.get()?.get_result()?.which()? {
+            common_capnp::result::Which::Ok(_) => Ok(()),
+            common_capnp::result::Which::Err(e) => Err(rejected(e)),
+        }
+    }
+
+    /// Master.getAssignment — what `worker_id` should run.
+    ///
+    /// # Errors
+    ///
+    /// [`CallError::Rejected`] when the master answers with an error.
+    pub async fn get_assignment(
+        &self,
+        worker_id: &str,
+        last_seen_generation: u64,
+    ) -> Result<Assignment, CallError> {
+        let mut request = self.client.get_assignment_request();
+        request.get().set_worker_id(worker_id);
+        request.get().set_last_seen_generation(last_seen_generation);
+        TraceHeaders::current().write(request.get().init_trace());
+
+        let response = call(request.send().promise).await?;
+        match response.get()?.get_result()?.which()? {
+            common_capnp::result::Which::Ok(assignment) => {
+                let assignment = assignment?;
+                let desired = assignment
+                    .get_desired_vms()?
+                    .iter()
+                    .map(read_spec)
+                    .collect::<capnp::Result<_>>()?;
+                Ok(Assignment {
+                    generation: assignment.get_generation(),
+                    desired,
+                })
+            }
+            common_capnp::result::Which::Err(e) => Err(rejected(e)),
+        }
+    }
+
+    /// Master.pushData — heartbeat with what `worker_id` runs.
+    ///
+    /// # Errors
+    ///
+    /// [`CallError::Rejected`] when the master answers with an error.
+    pub async fn push_data(
+        &self,
+        worker_id: &str,
+        observed_generation: u64,
+        running: &[VmInfo],
+    ) -> Result<(), CallError> {
+        let mut request = self.client.push_data_request();
+        {
+            let mut params = request.get();
+            params.set_worker_id(worker_id);
+            params.set_observed_generation(observed_generation);
+            let mut list = params.reborrow().init_running_vms(len(running)?);
+            for (i, vm) in (0..).zip(running) {
+                let mut entry = list.reborrow().get(i);
+                entry.set_id(vm.id());
+                entry.set_content_hash(vm.observed_hash());
+                entry.set_desired_hash(vm.desired_hash());
+                entry.set_status(vm.status().as_str());
+                entry.set_state(vm.status().into());
+            }
+            TraceHeaders::current().write(params.init_trace());
+        }
+        let response = call(request.send().promise).await?;
+        match response.get()?.get_result()?.which()? {
+            common_capnp::result::Which::Ok(_) => Ok(()),
+            common_capnp::result::Which::Err(e) => Err(rejected(e)),
+        }
+    }
+}
+
+async fn call<T>(fut: impl Future<Output = capnp::Result<T>>) -> Result<T, CallError> {
+    tokio::time::timeout(CALL_TIMEOUT, fut)
+        .await
+        .map_err(|_| CallError::Timeout)?
+        .map_err(CallError::Transport)
+}
+
+fn rejected(reason: capnp::Result<capnp::text::Reader<'_>>) -> CallError {
+    match reason.and_then(|r| Ok(r.to_str()?.to_string())) {
+        Ok(reason) => CallError::Rejected(reason),
+        Err(e) => CallError::Transport(e),
+    }
+}
+
+fn len<T>(items: &[T]) -> Result<u32, CallError> {
+    u32::try_from(items.len())
+        .map_err(|_| CallError::Transport(capnp::Error::failed("list too long".to_string())))
+}
+
+fn write_spec(mut builder: common_capnp::vm_spec::Builder<'_>, spec: &VmSpec) {
+    builder.set_toplevel(spec.toplevel());
+    builder.set_kernel_path(spec.kernel_path());
+    builder.set_initrd_path(spec.initrd_path());
+    builder.set_disk_image_path(spec.disk_image_path());
+    builder.set_cmdline(spec.cmdline());
+    builder.set_cpu(spec.cpu());
+    builder.set_memory_mb(spec.memory_mb());
+    builder.set_hugepages(spec.hugepages());
+    builder.set_shared_memory(spec.shared_memory());
+    builder.set_net_backend(spec.net_backend().into());
+    write_labels(
+        spec.labels(),
+        builder
+            .reborrow()
+            .init_labels(u32::try_from(spec.labels().len()).unwrap_or(0)),
+    );
+    let domains = spec.network_allowed_domains();
+    let mut list = builder.init_network_allowed_domains(u32::try_from(domains.len()).unwrap_or(0));
+    for (i, domain) in (0..).zip(domains) {
+        list.set(i, domain);
+    }
+}
+
+fn read_spec(reader: common_capnp::vm_spec::Reader<'_>) -> capnp::Result<VmSpec> {
+    let text = |t: capnp::Result<capnp::text::Reader<'_>>| -> capnp::Result<String> {
+        Ok(t?.to_str()?.to_string())
+    };
+    let domains = reader
+        .get_network_allowed_domains()?
+        .iter()
+        .map(text)
+        .collect::<capnp::Result<_>>()?;
+    Ok(VmSpec::new(
+        text(reader.get_toplevel())?,
+        text(reader.get_kernel_path())?,
+        text(reader.get_initrd_path())?,
+        text(reader.get_disk_image_path())?,
+        text(reader.get_cmdline())?,
+        reader.get_cpu(),
+        reader.get_memory_mb(),
+        domains,
+    )
+    .with_labels(read_labels(reader.get_labels()?)?))
+}
@@ -0,0 +1,277 @@
+//! Simulated worker: the real `VmManager` over the mock VMM backend, driven
+//! by a polling agent instead of the worker's RPC server.
+//!
+//! Every `poll_interval` the agent fetches its assignment, creates and
+//! deletes VMs until it runs exactly the assigned specs, then sends a
+//! heartbeat with what it runs. Faults are applied on the way out: a dropped
+//! RPC is simply never sent, a delayed heartbeat is held back.
+//!
+//! A crash aborts the manager and the agent; the mock VMs die with them, as
+//! VMs do when their worker's host goes down. A restart comes back empty at
+//! generation 0.
+
+use std::cell::Cell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use worker::dto::{CommandPayload, CommandResponse, CommandSender, VmInfo, VmSpec};
+use worker::vm_manager::{VmManager, VmManagerConfig};
+use worker::vmm::mock::MockBackend;
+
+use crate::fault::{FaultPlan, FaultStats, Faults};
+use crate::invariant::WorkerView;
+use crate::master::{CallError, MasterClient};
+
+pub struct SimWorker {
+    id: String,
+    index: u64,
+    master_addr: SocketAddr,
+    plan: FaultPlan,
+    stats: Arc<FaultStats>,
+    poll_interval: Duration,
+    /// Incremented on every restart so a restarted worker draws new faults.
+    incarnation: u64,
+    running: Option<Running>,
+}
+
+/// Tasks of a live worker.
+struct Running {
+    commands: CommandSender,
+    observed_generation: Rc<Cell<u64>>,
+    manager: JoinHandle<()>,
+    agent: JoinHandle<()>,
+}
+
+impl SimWorker {
+    /// Start worker number `index`. Must run inside a `LocalSet`.
+    pub(crate) fn start(
+        index: u64,
+        master_addr: SocketAddr,
+        plan: FaultPlan,
+        stats: Arc<FaultStats>,
+        poll_interval: Duration,
+    ) -> Self {
+        let mut worker = Self {
+            id: format!("sim-worker-{index}"),
+            index,
+            master_addr,
+            plan,
+            stats,
+            poll_interval,
+            incarnation: 0,
+            running: None,
+        };
+        worker.boot();
+        worker
+    }
+
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    #[must_use]
+    pub fn is_up(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Kill the worker and every VM on it.
+    pub fn crash(&mut self) {
+        if let Some(running) = self.running.take() {
+            running.agent.abort();
+            running.manager.abort();
+            self.stats.record_crash();
+            debug!(worker_id = %self.id, "Crashed");
+        }
+    }
+
+    /// Bring a crashed worker back with no VMs.
+    pub fn restart(&mut self) {
+        if self.running.is_none() {
+            self.incarnation += 1;
+            self.boot();
+        }
+    }
+
+    /// What the worker runs right now; down workers run nothing.
+    pub async fn view(&self) -> WorkerView {
+        let (up, observed_generation, running) = match &self.running {
+            Some(running) => (
+                true,
+                running.observed_generation.get(),
+                list(&running.commands)
+                    .await
+                    .iter()
+                    .map(|vm| vm.desired_hash().to_string())
+                    .collect(),
+            ),
+            None => (false, 0, Vec::new()),
+        };
+        WorkerView {
+            id: self.id.clone(),
+            up,
+            observed_generation,
+            running,
+        }
+    }
+
+    fn boot(&mut self) {
+        let (backend, _tracker) = MockBackend::new();
+        let config = VmManagerConfig {
+            worker_id: self.id.clone(),
+            max_vms: None,
+            state_dir: None,
+        };
+        let mut manager = VmManager::new(backend, config);
+        let (tx, mut rx) = mpsc::channel(100);
+        let commands = CommandSender::new(tx);
+
+        let manager = tokio::task::spawn_local(async move {
+            while let Some(msg) = rx.recv().await {
+                manager.handle(msg).await;
+                if manager.is_stopped() {
+                    break;
+                }
+            }
+        });
+
+        let stream = self.index | (self.incarnation << 32);
+        let observed_generation = Rc::new(Cell::new(0));
+        let agent = tokio::task::spawn_local(agent(
+            self.id.clone(),
+            self.master_addr,
+            commands.clone(),
+            Faults::new(&self.plan, stream, self.stats.clone()),
+            self.poll_interval,
+            observed_generation.clone(),
+        ));
+
+        self.running = Some(Running {
+            commands,
+            observed_generation,
+            manager,
+            agent,
+        });
+    }
+}
+
+impl Drop for SimWorker {
+    fn drop(&mut self) {
+        if let Some(running) = self.running.take() {
+            running.agent.abort();
+            running.manager.abort();
+        }
+    }
+}
+
+async fn agent(
+    worker_id: String,
+    master_addr: SocketAddr,
+    commands: CommandSender,
+    mut faults: Faults,
+    poll_interval: Duration,
+    observed_generation: Rc<Cell<u64>>,
+) {
+    let mut master: Option<MasterClient> = None;
+    loop {
+        tokio::time::sleep(poll_interval).await;
+
+        let client = match &master {
+            Some(client) => client.clone(),
+            None => match MasterClient::connect(master_addr).await {
+                Ok(client) => master.insert(client).clone(),
+                Err(e) => {
+                    let error = repo_outils::report(&e);
+                    debug!(%worker_id, %error, "Master unreachable");
+                    continue;
+                }
+            },
+        };
+
+        if !faults.drop_rpc() {
+            match client
+                .get_assignment(&worker_id, observed_generation.get())
+                .await
+            {
+                Ok(assignment) => {
+                    if reconcile(&commands, &assignment.desired).await {
+                        observed_generation.set(assignment.generation);
+                    }
+                }
+                Err(CallError::Rejected(reason)) => {
+                    debug!(%worker_id, %reason, "Assignment refused");
+                }
+                Err(e) => {
+                    let error = repo_outils::report(&e);
+                    debug!(%worker_id, %error, "getAssignment failed, reconnecting");
+                    master = None;
+                    continue;
+                }
+            }
+        }
+
+        tokio::time::sleep(faults.heartbeat_delay()).await;
+        if faults.drop_rpc() {
+            continue;
+        }
+        let running = list(&commands).await;
+        match client
+            .push_data(&worker_id, observed_generation.get(), &running)
+            .await
+        {
+            Ok(()) => {}
+            Err(CallError::Rejected(reason)) => {
+                debug!(%worker_id, %reason, "Heartbeat refused");
+            }
+            Err(e) => {
+                let error = repo_outils::report(&e);
+                debug!(%worker_id, %error, "pushData failed, reconnecting");
+                master = None;
+            }
+        }
+    }
+}
+
+/// Create and delete VMs until exactly `desired` runs. Returns whether
+/// every step succeeded.
+async fn reconcile(commands: &CommandSender, desired: &[VmSpec]) -> bool {
+    let running = list(commands).await;
+    let mut ok = true;
+
+    let desired_hashes: Vec<String> = desired
+        .iter()
+        .map(|spec| spec.content_hash().to_string())
+        .collect();
+
+    for vm in &running {
+        if !desired_hashes.iter().any(|hash| hash == vm.desired_hash())
+            && let Err(e) = commands
+                .request(CommandPayload::Delete(vm.id().to_string()))
+                .await
+        {
+            warn!(vm_id = %vm.id(), error = %e, "Delete failed");
+            ok = false;
+        }
+    }
+    for (spec, hash) in desired.iter().zip(&desired_hashes) {
+        if !running.iter().any(|vm| vm.desired_hash() == hash)
+            && let Err(e) = commands.request(CommandPayload::Create(spec.clone())).await
+        {
+            warn!(toplevel = %spec.toplevel(), error = %e, "Create failed");
+            ok = false;
+        }
+    }
+    ok
+}
+
+async fn list(commands: &CommandSender) -> Vec<VmInfo> {
+    match commands.request(CommandPayload::List).await {
+        Ok(CommandResponse::VmList(vms)) => vms,
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,87 @@
+//! Fault scenarios against an in-process cluster.
+
+use std::future::Future;
+use std::time::Duration;
+
+use chaos::{Cluster, ClusterConfig, FaultPlan, vm};
+
+fn run_local<F: Future>(scenario: F) -> F::Output {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    tokio::task::LocalSet::new().block_on(&runtime, scenario)
+}
+
+fn flaky() -> ClusterConfig {
+    ClusterConfig {
+        workers: 3,
+        faults: FaultPlan {
+            rpc_drop_rate: 0.3,
+            max_heartbeat_delay: Duration::from_millis(50),
+            seed: 1446,
+        },
+        poll_interval: Duration::from_millis(20),
+    }
+}
+
+/// Observe the cluster for `duration`, failing on any safety violation.
+async fn hold_safety(cluster: &Cluster, duration: Duration) {
+    let deadline = tokio::time::Instant::now() + duration;
+    while tokio::time::Instant::now() < deadline {
+        if let Err(violations) = cluster.check_safety().await {
+            panic!("safety violated: {violations:?}");
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[test]
+fn safety_holds_through_dropped_rpcs_and_crashes() {
+    run_local(async {
+        let mut cluster = Cluster::start(flaky()).await.unwrap();
+        cluster.publish(&[vm("a"), vm("b")]).await.unwrap();
+        hold_safety(&cluster, Duration::from_millis(500)).await;
+
+        cluster.crash(1);
+        assert!(!cluster.workers()[1].is_up());
+        hold_safety(&cluster, Duration::from_millis(300)).await;
+
+        cluster.restart(1);
+        cluster.publish(&[vm("a"), vm("c")]).await.unwrap();
+        hold_safety(&cluster, Duration::from_millis(500)).await;
+
+        assert!(cluster.stats().dropped_rpcs() > 0);
+        assert!(cluster.stats().delayed_heartbeats() > 0);
+        assert_eq!(cluster.stats().crashes(), 1);
+        cluster.shutdown();
+    });
+}
+
+#[test]
+#[ignore = "Master.getAssignment is not implemented yet, workers never receive VMs"]
+fn converges_after_dropped_rpcs_and_a_crash() {
+    run_local(async {
+        let mut cluster = Cluster::start(flaky()).await.unwrap();
+        let specs = [vm("a"), vm("b"), vm("c"), vm("d")];
+        cluster.publish(&specs).await.unwrap();
+        cluster
+            .wait_converged(Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        cluster.crash(0);
+        cluster
+            .wait_converged(Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        cluster.restart(0);
+        cluster.publish(&specs[..2]).await.unwrap();
+        cluster
+            .wait_converged(Duration::from_secs(10))
+            .await
+            .unwrap();
+        cluster.shutdown();
+    });
+}
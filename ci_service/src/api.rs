@@ -1,31 +1,78 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Form, FromRequestParts, Path, Query, State},
+    http::{header, request::Parts, StatusCode},
+    response::{Html, IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
+use repo_outils::{nix::Diagnostics, report};
 use serde::{Deserialize, Serialize};
 use tracing::info;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
-    builds::{BuildInfo, BuildStatus},
+    auth::{self, Auth, Viewer},
+    builds::{BuildInfo, BuildJob, BuildStatus},
+    database::{BuildFinding, BuildSbom, BuildSummary, DatabaseError},
+    gate::{Gate, GatePolicy},
     job_queue::JobQueue,
+    selection::SkippedCheck,
+    settings::{self, RepoSettings},
+    slo::{self, Objectives, Report},
+    steps::{self, StepStats, StepTiming},
+    vulns::Finding,
 };
 
-#[derive(Debug, Serialize)]
+/// Prefix of the JSON API. Routes under it only change in backward compatible
+/// ways; a breaking change goes under a new version.
+pub const API_V1: &str = "/api/v1";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Procurator CI Service"),
+    servers((url = "/api/v1")),
+    paths(
+        create_build,
+        list_builds,
+        get_build,
+        retry_build,
+        cancel_build,
+        get_build_steps,
+        get_step_stats,
+        get_build_sboms,
+        get_build_sbom,
+        get_build_vulnerabilities,
+        list_vulnerabilities,
+        get_commit_gate,
+        get_stats
+    ),
+    tags(
+        (name = "builds"),
+        (name = "gate"),
+        (name = "steps"),
+        (name = "stats"),
+        (name = "sbom"),
+        (name = "vulnerabilities")
+    )
+)]
+pub struct ApiDoc;
+
+#[derive(Debug, Serialize, ToSchema)]
 pub struct BuildsListResponse {
     builds: Vec<BuildInfo>,
     total: usize,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct BuildRequest {
     repo: String,
     bare_repo_path: String,
     old_rev: Option<String>,
     new_rev: String,
     #[serde(rename = "ref")]
+    #[schema(example = "refs/heads/master")]
     ref_name: String,
     commit_author: Option<String>,
     commit_email: Option<String>,
@@ -35,43 +82,202 @@ pub struct BuildRequest {
     gpg_signer: Option<String>,
     pusher: Option<String>,
     ssh_client_ip: Option<String>,
+    /// Run every check, not only those of the packages the push changed
+    #[serde(default)]
+    all: bool,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct BuildResponse {
     id: i64,
     status: BuildStatus,
 }
 
+/// A push that isn't built
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BuildSkipped {
+    /// Why, e.g. the branch isn't monitored
+    skipped: String,
+}
+
 #[derive(Clone)]
 pub struct AppState {
     queue: JobQueue,
+    gate: GatePolicy,
+    objectives: Objectives,
+    auth: Option<Auth>,
+    settings: Option<RepoSettings>,
 }
 
 impl AppState {
     pub fn new(queue: JobQueue) -> Self {
-        Self { queue }
+        Self {
+            queue,
+            gate: GatePolicy::default(),
+            objectives: Objectives::default(),
+            auth: None,
+            settings: None,
+        }
+    }
+
+    /// Decide deployment gates with `gate` instead of the default policy
+    pub fn with_gate_policy(mut self, gate: GatePolicy) -> Self {
+        self.gate = gate;
+        self
+    }
+
+    /// Check the stats against `objectives` instead of the default ones
+    #[must_use]
+    pub fn with_objectives(mut self, objectives: Objectives) -> Self {
+        self.objectives = objectives;
+        self
+    }
+
+    /// Only show builds to, and let act on them, the users `auth` allows
+    #[must_use]
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Only build the pushes `settings` ask for, and gate repositories that
+    /// require checks on them
+    #[must_use]
+    pub fn with_repo_settings(mut self, settings: RepoSettings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+}
+
+impl FromRequestParts<AppState> for Viewer {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        match &state.auth {
+            Some(auth) => auth.viewer(&parts.headers).await,
+            None => Ok(Viewer::Anyone),
+        }
+    }
+}
+
+/// [`Viewer`] of a request that changes something, refused when it comes
+/// from a browser that didn't send the CSRF token of its cookie.
+struct Actor(Viewer);
+
+impl FromRequestParts<AppState> for Actor {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if state.auth.is_some() {
+            auth::check_csrf(&parts.headers)?;
+        }
+        Viewer::from_request_parts(parts, state).await.map(Actor)
+    }
+}
+
+/// Build `id`, not found when `viewer` may not see it.
+async fn find_build(state: &AppState, viewer: &Viewer, id: i64) -> Result<BuildJob, DatabaseError> {
+    let build = state.queue.get_build(id).await?;
+    if viewer.can_view(build.repo_path()) {
+        Ok(build)
+    } else {
+        Err(DatabaseError::NotFound(format!("Build {id} not found")))
     }
 }
 
+/// Enqueue a build, as sent by the post-receive hook
+#[utoipa::path(
+    post,
+    path = "/builds",
+    tag = "builds",
+    request_body = BuildRequest,
+    responses(
+        (status = ACCEPTED, description = "Build enqueued", body = BuildResponse),
+        (status = OK, description = "Not built, as the repository's CI settings ask", body = BuildSkipped),
+        (status = FORBIDDEN, description = "No write access to the repository, or no CSRF token", body = String),
+        (status = INTERNAL_SERVER_ERROR, description = "Build could not be enqueued", body = String),
+    )
+)]
 async fn create_build(
     State(state): State<AppState>,
+    Actor(viewer): Actor,
     Json(req): Json<BuildRequest>,
-) -> impl axum::response::IntoResponse {
+) -> Result<Response, (StatusCode, String)> {
     info!(?req, "Build request received");
 
+    if !viewer.can_write(&req.bare_repo_path) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("No write access to {}", req.repo),
+        ));
+    }
+
     // Extract branch name
     let branch = req
         .ref_name
         .strip_prefix("refs/heads/")
         .unwrap_or(&req.ref_name);
 
+    if let Some(settings) = &state.settings {
+        match settings.get(&req.bare_repo_path).await {
+            Ok(settings) if !settings.enabled => {
+                info!(repo = req.repo.as_str(), "CI is disabled, not building");
+                let skipped = format!("CI is disabled for {}", req.repo);
+                return Ok(Json(BuildSkipped { skipped }).into_response());
+            }
+            Ok(settings) if !settings.builds(branch) => {
+                info!(
+                    repo = req.repo.as_str(),
+                    branch, "Branch not monitored, not building"
+                );
+                let skipped = format!("branch {branch} of {} is not monitored", req.repo);
+                return Ok(Json(BuildSkipped { skipped }).into_response());
+            }
+            Ok(_) => {}
+            Err(error) => tracing::warn!(
+                repo = req.repo.as_str(),
+                error,
+                "Failed to get CI settings, building"
+            ),
+        }
+    }
+
     //TODO: check if this works as expected. Maybe we want to use username/reponame
 
+    // Checks are only skipped for packages unchanged since a commit whose
+    // build passed: the push's `old_rev` may have failed them. A new branch
+    // has nothing to compare against
+    let base = if req.all {
+        None
+    } else {
+        match state
+            .queue
+            .last_passing_commit(&req.bare_repo_path, branch)
+            .await
+        {
+            Ok(base) => base,
+            Err(e) => {
+                tracing::warn!(
+                    repo = req.repo.as_str(),
+                    branch,
+                    error = report(&e),
+                    "Failed to find the last passing build, running every check"
+                );
+                None
+            }
+        }
+    };
+
     // Enqueue build with bare repo path
     match state
         .queue
-        .enqueue(&req.bare_repo_path, &req.new_rev, branch)
+        .enqueue(&req.bare_repo_path, &req.new_rev, branch, base.as_deref())
         .await
     {
         Ok(id) => {
@@ -87,27 +293,45 @@ async fn create_build(
                     id,
                     status: BuildStatus::Queued,
                 }),
-            ))
+            )
+                .into_response())
         }
         Err(e) => {
+            let error = report(&e);
             tracing::error!(
                 repo = req.repo.as_str(),
                 branch = branch,
-                error = %e,
+                code = e.code(),
+                error,
                 "Failed to enqueue build"
             );
             Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to enqueue build: {}", e),
+                format!("Failed to enqueue build: {}", error),
             ))
         }
     }
 }
 
+/// Every build, latest first
+#[utoipa::path(
+    get,
+    path = "/builds",
+    tag = "builds",
+    responses(
+        (status = OK, body = BuildsListResponse),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
 async fn list_builds(
     State(state): State<AppState>,
+    viewer: Viewer,
 ) -> Result<Json<BuildsListResponse>, (StatusCode, String)> {
-    match state.queue.list_all_builds().await {
+    match state
+        .queue
+        .list_all_builds(viewer.repositories().as_deref())
+        .await
+    {
         Ok(builds) => {
             let total = builds.len();
             let builds_info = builds.into_iter().map(BuildInfo::from).collect();
@@ -117,32 +341,790 @@ async fn list_builds(
             }))
         }
         Err(e) => {
-            tracing::error!("Failed to list builds: {}", e);
+            let error = report(&e);
+            tracing::error!(code = e.code(), error, "Failed to list builds");
             Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to list builds: {}", e),
+                format!("Failed to list builds: {}", error),
             ))
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/builds/{id}",
+    tag = "builds",
+    params(("id" = i64, Path, description = "Build id")),
+    responses(
+        (status = OK, body = BuildInfo),
+        (status = NOT_FOUND, body = String),
+    )
+)]
 async fn get_build(
     State(state): State<AppState>,
+    viewer: Viewer,
     Path(id): Path<i64>,
 ) -> Result<Json<BuildInfo>, (StatusCode, String)> {
-    match state.queue.get_build(id).await {
+    match find_build(&state, &viewer, id).await {
         Ok(build) => Ok(Json(BuildInfo::from(build))),
         Err(e) => {
-            tracing::error!("Failed to get build {}: {}", id, e);
-            Err((StatusCode::NOT_FOUND, format!("Build not found: {}", e)))
+            let error = report(&e);
+            tracing::error!(id, code = e.code(), error, "Failed to get build");
+            Err((StatusCode::NOT_FOUND, format!("Build not found: {}", error)))
+        }
+    }
+}
+
+/// Build `id` if `viewer` may act on it.
+async fn writable_build(
+    state: &AppState,
+    viewer: &Viewer,
+    id: i64,
+) -> Result<BuildJob, (StatusCode, String)> {
+    let build = find_build(state, viewer, id).await.map_err(|e| {
+        let error = report(&e);
+        tracing::error!(id, code = e.code(), error, "Failed to get build");
+        match e {
+            DatabaseError::NotFound(_) => {
+                (StatusCode::NOT_FOUND, format!("Build not found: {error}"))
+            }
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to get build: {error}"),
+            ),
+        }
+    })?;
+    if !viewer.can_write(build.repo_path()) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("No write access to the repository of build {id}"),
+        ));
+    }
+    Ok(build)
+}
+
+/// Build the commit of a finished build again, as a new build
+#[utoipa::path(
+    post,
+    path = "/builds/{id}/retry",
+    tag = "builds",
+    params(("id" = i64, Path, description = "Build id")),
+    responses(
+        (status = ACCEPTED, description = "New build enqueued", body = BuildResponse),
+        (status = FORBIDDEN, description = "No write access to the repository, or no CSRF token", body = String),
+        (status = NOT_FOUND, body = String),
+        (status = CONFLICT, description = "The build hasn't finished", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn retry_build(
+    State(state): State<AppState>,
+    Actor(viewer): Actor,
+    Path(id): Path<i64>,
+) -> Result<(StatusCode, Json<BuildResponse>), (StatusCode, String)> {
+    let build = writable_build(&state, &viewer, id).await?;
+    if !build.finished() {
+        return Err((StatusCode::CONFLICT, format!("Build {id} hasn't finished")));
+    }
+    match state
+        .queue
+        .enqueue(
+            build.repo_path(),
+            build.commit_hash(),
+            build.branch(),
+            build.base_commit(),
+        )
+        .await
+    {
+        Ok(retry) => {
+            info!(build_id = id, retry_id = retry, "Build retried");
+            Ok((
+                StatusCode::ACCEPTED,
+                Json(BuildResponse {
+                    id: retry,
+                    status: BuildStatus::Queued,
+                }),
+            ))
+        }
+        Err(e) => {
+            let error = report(&e);
+            tracing::error!(id, code = e.code(), error, "Failed to retry build");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to retry build: {error}"),
+            ))
+        }
+    }
+}
+
+/// Cancel a build that hasn't started yet
+#[utoipa::path(
+    post,
+    path = "/builds/{id}/cancel",
+    tag = "builds",
+    params(("id" = i64, Path, description = "Build id")),
+    responses(
+        (status = OK, description = "Build canceled", body = BuildResponse),
+        (status = FORBIDDEN, description = "No write access to the repository, or no CSRF token", body = String),
+        (status = NOT_FOUND, body = String),
+        (status = CONFLICT, description = "The build isn't queued anymore", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn cancel_build(
+    State(state): State<AppState>,
+    Actor(viewer): Actor,
+    Path(id): Path<i64>,
+) -> Result<Json<BuildResponse>, (StatusCode, String)> {
+    writable_build(&state, &viewer, id).await?;
+    match state.queue.cancel(id).await {
+        Ok(true) => {
+            info!(build_id = id, "Build canceled");
+            Ok(Json(BuildResponse {
+                id,
+                status: BuildStatus::Canceled,
+            }))
+        }
+        Ok(false) => Err((
+            StatusCode::CONFLICT,
+            format!("Build {id} isn't queued anymore, only queued builds can be canceled"),
+        )),
+        Err(e) => {
+            let error = report(&e);
+            tracing::error!(id, code = e.code(), error, "Failed to cancel build");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to cancel build: {error}"),
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BuildStepsResponse {
+    build_id: i64,
+    /// Depth first, eval stage before build stage
+    steps: Vec<StepTiming>,
+    /// Checks the build left out, as the push didn't affect their package
+    skipped: Vec<SkippedCheck>,
+    /// Kind of error (`evaluation`, `build`, `hash_mismatch`, `other`),
+    /// failing derivation and hint lines, when a stage failed
+    #[schema(value_type = Option<Object>)]
+    diagnostics: Option<Diagnostics>,
+}
+
+/// Step timings and skipped checks recorded for build `id`, from the database.
+async fn load_summary(
+    state: &AppState,
+    viewer: &Viewer,
+    id: i64,
+) -> Result<BuildSummary, (StatusCode, String)> {
+    let summary = match find_build(state, viewer, id).await {
+        Ok(_) => state.queue.get_build_summary(id).await,
+        Err(e) => Err(e),
+    };
+    match summary {
+        Ok(Some(summary)) => Ok(summary),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("No step timings recorded for build {id}"),
+        )),
+        Err(e) => {
+            let error = report(&e);
+            tracing::error!(id, code = e.code(), error, "Failed to get build steps");
+            let status = match e {
+                DatabaseError::NotFound(_) => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err((status, format!("Failed to get build steps: {error}")))
+        }
+    }
+}
+
+/// Step timings of a build
+#[utoipa::path(
+    get,
+    path = "/builds/{id}/steps",
+    tag = "steps",
+    params(("id" = i64, Path, description = "Build id")),
+    responses(
+        (status = OK, body = BuildStepsResponse),
+        (status = NOT_FOUND, description = "No such build, or no step timings recorded", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_build_steps(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i64>,
+) -> Result<Json<BuildStepsResponse>, (StatusCode, String)> {
+    let summary = load_summary(&state, &viewer, id).await?;
+    Ok(Json(BuildStepsResponse {
+        build_id: id,
+        steps: summary.steps,
+        skipped: summary.skipped,
+        diagnostics: summary.diagnostics,
+    }))
+}
+
+async fn build_timeline(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i64>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let summary = load_summary(&state, &viewer, id).await?;
+    Ok(Html(steps::render_timeline(id, &summary.steps)))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct StepStatsQuery {
+    /// Repository path prefix, as for the latest build of a repo
+    repo: String,
+    /// Number of latest successful builds to aggregate
+    #[serde(default = "default_stats_builds")]
+    #[param(default = 50)]
+    builds: i64,
+}
+
+fn default_stats_builds() -> i64 {
+    50
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StepStatsResponse {
+    repo: String,
+    /// Builds that had step timings
+    builds: usize,
+    steps: Vec<StepStats>,
+}
+
+/// p50/p95 duration of every step over the latest successful builds of a
+/// repository, slowest first
+#[utoipa::path(
+    get,
+    path = "/steps/stats",
+    tag = "steps",
+    params(StepStatsQuery),
+    responses(
+        (status = OK, body = StepStatsResponse),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_step_stats(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(query): Query<StepStatsQuery>,
+) -> Result<Json<StepStatsResponse>, (StatusCode, String)> {
+    match state
+        .queue
+        .repo_build_summaries(&query.repo, viewer.repositories().as_deref(), query.builds)
+        .await
+    {
+        Ok(summaries) => Ok(Json(StepStatsResponse {
+            builds: summaries.len(),
+            steps: steps::stats(summaries.iter().map(|s| s.steps.as_slice())),
+            repo: query.repo,
+        })),
+        Err(e) => {
+            let error = report(&e);
+            tracing::error!(
+                repo = query.repo.as_str(),
+                code = e.code(),
+                error,
+                "Failed to aggregate step timings"
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to aggregate step timings: {error}"),
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BuildSbomsResponse {
+    build_id: i64,
+    /// One per repo of the commit, by repo name
+    repos: Vec<BuildSbom>,
+}
+
+/// SBOMs recorded for build `id`, from the database.
+async fn load_sboms(
+    state: &AppState,
+    viewer: &Viewer,
+    id: i64,
+) -> Result<Vec<BuildSbom>, (StatusCode, String)> {
+    let sboms = match find_build(state, viewer, id).await {
+        Ok(_) => state.queue.get_sboms(id).await,
+        Err(e) => Err(e),
+    };
+    match sboms {
+        Ok(sboms) if sboms.is_empty() => Err((
+            StatusCode::NOT_FOUND,
+            format!("No SBOM recorded for build {id}"),
+        )),
+        Ok(sboms) => Ok(sboms),
+        Err(e) => {
+            let error = report(&e);
+            tracing::error!(id, code = e.code(), error, "Failed to get build SBOM");
+            let status = match e {
+                DatabaseError::NotFound(_) => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err((status, format!("Failed to get build SBOM: {error}")))
+        }
+    }
+}
+
+/// License summary and SBOM of every repo of a build's commit
+#[utoipa::path(
+    get,
+    path = "/builds/{id}/sbom",
+    tag = "sbom",
+    params(("id" = i64, Path, description = "Build id")),
+    responses(
+        (status = OK, body = BuildSbomsResponse),
+        (status = NOT_FOUND, description = "No such build, or no SBOM recorded", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_build_sboms(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i64>,
+) -> Result<Json<BuildSbomsResponse>, (StatusCode, String)> {
+    let repos = load_sboms(&state, &viewer, id).await?;
+    Ok(Json(BuildSbomsResponse {
+        build_id: id,
+        repos,
+    }))
+}
+
+/// CycloneDX document of one repo of a build's commit, for SBOM tooling
+#[utoipa::path(
+    get,
+    path = "/builds/{id}/sbom/{repo}",
+    tag = "sbom",
+    params(
+        ("id" = i64, Path, description = "Build id"),
+        ("repo" = String, Path, description = "Repo name, as listed by `/builds/{id}/sbom`"),
+    ),
+    responses(
+        (status = OK, content_type = "application/vnd.cyclonedx+json", body = Object),
+        (status = NOT_FOUND, description = "No such build or repo, or no SBOM recorded", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_build_sbom(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path((id, repo)): Path<(i64, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let sbom = load_sboms(&state, &viewer, id)
+        .await?
+        .into_iter()
+        .find(|sbom| sbom.repo == repo)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("No SBOM recorded for repo {repo} in build {id}"),
+            )
+        })?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/vnd.cyclonedx+json")],
+        Json(sbom.sbom),
+    ))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BuildVulnerabilitiesResponse {
+    build_id: i64,
+    /// Status of the scan, `None` when the build wasn't scanned
+    scan_status: Option<BuildStatus>,
+    /// By repo, package and advisory
+    findings: Vec<Finding>,
+}
+
+/// Findings of the vulnerability scan of a build
+#[utoipa::path(
+    get,
+    path = "/builds/{id}/vulnerabilities",
+    tag = "vulnerabilities",
+    params(("id" = i64, Path, description = "Build id")),
+    responses(
+        (status = OK, body = BuildVulnerabilitiesResponse),
+        (status = NOT_FOUND, body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_build_vulnerabilities(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path(id): Path<i64>,
+) -> Result<Json<BuildVulnerabilitiesResponse>, (StatusCode, String)> {
+    let scanned = match find_build(&state, &viewer, id).await {
+        Ok(build) => state
+            .queue
+            .get_findings(id)
+            .await
+            .map(|findings| (build.scan_status(), findings)),
+        Err(e) => Err(e),
+    };
+    match scanned {
+        Ok((scan_status, findings)) => Ok(Json(BuildVulnerabilitiesResponse {
+            build_id: id,
+            scan_status,
+            findings,
+        })),
+        Err(e) => {
+            let error = report(&e);
+            tracing::error!(id, code = e.code(), error, "Failed to get build findings");
+            let status = match e {
+                DatabaseError::NotFound(_) => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err((status, format!("Failed to get build findings: {error}")))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct VulnerabilitiesQuery {
+    /// Advisory id or alias, e.g. `CVE-2024-1234`
+    advisory: Option<String>,
+    /// Package name, as locked
+    package: Option<String>,
+    /// Repository path prefix, as for the latest build of a repo
+    repo: Option<String>,
+    /// Maximum number of findings returned
+    #[serde(default = "default_vulnerabilities_limit")]
+    #[param(default = 100)]
+    limit: i64,
+}
+
+fn default_vulnerabilities_limit() -> i64 {
+    100
+}
+
+/// Findings across builds, latest builds first, e.g. to see which builds
+/// an advisory affects
+#[utoipa::path(
+    get,
+    path = "/vulnerabilities",
+    tag = "vulnerabilities",
+    params(VulnerabilitiesQuery),
+    responses(
+        (status = OK, body = Vec<BuildFinding>),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn list_vulnerabilities(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(query): Query<VulnerabilitiesQuery>,
+) -> Result<Json<Vec<BuildFinding>>, (StatusCode, String)> {
+    match state
+        .queue
+        .search_findings(
+            query.advisory.as_deref(),
+            query.package.as_deref(),
+            query.repo.as_deref(),
+            viewer.repositories().as_deref(),
+            query.limit,
+        )
+        .await
+    {
+        Ok(findings) => Ok(Json(findings)),
+        Err(e) => {
+            let error = report(&e);
+            tracing::error!(code = e.code(), error, "Failed to search findings");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to search findings: {error}"),
+            ))
         }
     }
 }
 
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct StatsQuery {
+    /// Aggregate the builds enqueued in the last this many hours, at most
+    /// 90 days
+    #[serde(default = "default_stats_hours")]
+    #[param(default = 24)]
+    hours: u32,
+}
+
+fn default_stats_hours() -> u32 {
+    24
+}
+
+/// Query of the trends page, two weeks by default
+#[derive(Debug, Deserialize)]
+pub struct TrendsQuery {
+    #[serde(default = "default_trends_hours")]
+    hours: u32,
+}
+
+fn default_trends_hours() -> u32 {
+    14 * 24
+}
+
+const MAX_STATS_HOURS: u32 = 90 * 24;
+
+/// Stats of the builds `viewer` may see over the last `hours`.
+async fn load_report(
+    state: &AppState,
+    viewer: &Viewer,
+    hours: u32,
+) -> Result<Report, (StatusCode, String)> {
+    if hours == 0 || hours > MAX_STATS_HOURS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("hours must be between 1 and {MAX_STATS_HOURS}"),
+        ));
+    }
+    let now = chrono::Utc::now();
+    let since = now - chrono::Duration::hours(i64::from(hours));
+    match state
+        .queue
+        .build_timings(since, viewer.repositories().as_deref())
+        .await
+    {
+        Ok(timings) => Ok(slo::report(&timings, &state.objectives, hours, now)),
+        Err(e) => {
+            let error = report(&e);
+            tracing::error!(
+                hours,
+                code = e.code(),
+                error,
+                "Failed to aggregate build stats"
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to aggregate build stats: {error}"),
+            ))
+        }
+    }
+}
+
+/// Queue wait, duration percentiles and success rate of the recent builds,
+/// per repository and per day, against the objectives
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "stats",
+    params(StatsQuery),
+    responses(
+        (status = OK, body = Report),
+        (status = BAD_REQUEST, description = "Window out of range", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_stats(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<Report>, (StatusCode, String)> {
+    load_report(&state, &viewer, query.hours).await.map(Json)
+}
+
+/// The stats in the Prometheus text format
+async fn metrics(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(query): Query<StatsQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let report = load_report(&state, &viewer, query.hours).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        slo::render_metrics(&report),
+    )
+        .into_response())
+}
+
+async fn trends(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Query(query): Query<TrendsQuery>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let report = load_report(&state, &viewer, query.hours).await?;
+    Ok(Html(slo::render_trends(&report)))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct GateQuery {
+    /// Only consider builds of this branch, e.g. the one being deployed
+    branch: Option<String>,
+}
+
+/// Whether a commit passed the stages its branch requires, for deploy steps
+/// to wait on
+#[utoipa::path(
+    get,
+    path = "/repos/{repo}/commits/{sha}/gate",
+    tag = "gate",
+    params(
+        ("repo" = String, Path, description = "Bare repository path, or its path under the repos directory without `.git`, URL-encoded"),
+        ("sha" = String, Path, description = "Commit hash, at least 7 characters"),
+        GateQuery,
+    ),
+    responses(
+        (status = OK, description = "The gate, `passed` only when it may be deployed", body = Gate),
+        (status = BAD_REQUEST, description = "Invalid commit hash", body = String),
+        (status = NOT_FOUND, description = "No repository the caller may see", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_commit_gate(
+    State(state): State<AppState>,
+    viewer: Viewer,
+    Path((repo, sha)): Path<(String, String)>,
+    Query(query): Query<GateQuery>,
+) -> Result<Json<Gate>, (StatusCode, String)> {
+    if !viewer.can_view(&repo) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Repository {repo} not found"),
+        ));
+    }
+    if sha.len() < 7 || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid commit hash {sha:?}: expected at least 7 hex digits"),
+        ));
+    }
+    let sha = sha.to_ascii_lowercase();
+    let policy = match &state.settings {
+        Some(settings) => match settings.get(&repo).await {
+            Ok(settings) => settings::gate_policy(&settings),
+            Err(error) => {
+                tracing::warn!(
+                    repo = repo.as_str(),
+                    error,
+                    "Failed to get CI settings, gating with the default policy"
+                );
+                None
+            }
+        },
+        None => None,
+    };
+    let policy = policy.as_ref().unwrap_or(&state.gate);
+    match state
+        .queue
+        .latest_build_of_commit(&repo, &sha, query.branch.as_deref())
+        .await
+    {
+        Ok(build) => Ok(Json(policy.evaluate(&sha, build.as_ref()))),
+        Err(e) => {
+            let error = report(&e);
+            tracing::error!(
+                repo = repo.as_str(),
+                sha = sha.as_str(),
+                code = e.code(),
+                error,
+                "Failed to get commit gate"
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to get commit gate: {error}"),
+            ))
+        }
+    }
+}
+
+/// JSON API, to nest under [`API_V1`]
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/builds", post(create_build).get(list_builds))
         .route("/builds/{id}", get(get_build))
+        .route("/builds/{id}/retry", post(retry_build))
+        .route("/builds/{id}/cancel", post(cancel_build))
+        .route("/builds/{id}/steps", get(get_build_steps))
+        .route("/builds/{id}/sbom", get(get_build_sboms))
+        .route("/builds/{id}/sbom/{repo}", get(get_build_sbom))
+        .route(
+            "/builds/{id}/vulnerabilities",
+            get(get_build_vulnerabilities),
+        )
+        .route("/steps/stats", get(get_step_stats))
+        .route("/vulnerabilities", get(list_vulnerabilities))
+        .route("/repos/{repo}/commits/{sha}/gate", get(get_commit_gate))
+        .route("/stats", get(get_stats))
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginForm {
+    /// Repohub API token
+    token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct LoginResponse {
+    /// To send in the `X-CSRF-Token` header of requests that change something
+    csrf_token: String,
+}
+
+/// Keep a browser's repohub token in its `ci_token` cookie, once repohub
+/// knows it
+async fn login(
+    State(state): State<AppState>,
+    Form(form): Form<LoginForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let Some(auth) = &state.auth else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "No repohub is configured, there is nothing to log in to".to_string(),
+        ));
+    };
+    let token = form.token.trim();
+    if token.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Missing token".to_string()));
+    }
+    auth.access(Some(token)).await?;
+
+    let [token_cookie, csrf_cookie] = auth::session_cookies(token);
+    let body = Json(LoginResponse {
+        csrf_token: auth::csrf_token(token),
+    });
+    Ok((
+        [
+            (header::SET_COOKIE, token_cookie),
+            (header::SET_COOKIE, csrf_cookie),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Forget the token of a browser
+async fn logout() -> Response {
+    let [token_cookie, csrf_cookie] = auth::expired_cookies();
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (header::SET_COOKIE, token_cookie),
+            (header::SET_COOKIE, csrf_cookie),
+        ],
+    )
+        .into_response()
+}
+
+/// HTML pages and Prometheus metrics, served outside of `/api`
+pub fn pages() -> Router<AppState> {
+    Router::new()
+        .route("/login", post(login))
+        .route("/logout", post(logout))
+        .route("/builds/{id}/timeline", get(build_timeline))
+        .route("/stats", get(trends))
+        .route("/metrics", get(metrics))
+}
+
+/// Spec of [`routes`] at `/api/v1/openapi.json`, browsable with
+/// Swagger UI at `/api/docs`
+pub fn docs() -> Router<AppState> {
+    Router::new().merge(SwaggerUi::new("/api/docs").url("/api/v1/openapi.json", ApiDoc::openapi()))
 }
 
 #[cfg(test)]
@@ -165,6 +1147,54 @@ mod tests {
             gpg_signer: None,
             pusher: Some("git".into()),
             ssh_client_ip: Some("192.168.1.15".into()),
+            all: false,
         };
     }
+
+    #[test]
+    fn openapi_documents_every_route() {
+        let doc = ApiDoc::openapi();
+        assert_eq!(doc.servers.unwrap()[0].url, API_V1);
+        let paths: Vec<_> = doc.paths.paths.keys().map(String::as_str).collect();
+        assert_eq!(
+            paths,
+            [
+                "/builds",
+                "/builds/{id}",
+                "/builds/{id}/cancel",
+                "/builds/{id}/retry",
+                "/builds/{id}/sbom",
+                "/builds/{id}/sbom/{repo}",
+                "/builds/{id}/steps",
+                "/builds/{id}/vulnerabilities",
+                "/repos/{repo}/commits/{sha}/gate",
+                "/stats",
+                "/steps/stats",
+                "/vulnerabilities"
+            ]
+        );
+        let builds = &doc.paths.paths["/builds"];
+        assert!(builds.get.is_some() && builds.post.is_some());
+        let schemas = doc.components.unwrap().schemas;
+        for schema in [
+            "BuildInfo",
+            "BuildStatus",
+            "BuildRequest",
+            "BuildSkipped",
+            "StepTiming",
+            "SkippedCheck",
+            "StepStats",
+            "BuildSbom",
+            "Finding",
+            "BuildFinding",
+            "Severity",
+            "Gate",
+            "GateState",
+            "Report",
+            "RepoStats",
+            "Percentiles",
+        ] {
+            assert!(schemas.contains_key(schema), "{schema} missing");
+        }
+    }
 }
@@ -0,0 +1,311 @@
+//! Who may see and act on builds
+//!
+//! Without `Config::repohub_url` the API and pages are open to anyone. With
+//! it, every request is checked against repohub: the caller's API token,
+//! sent as `Authorization: Bearer <token>` or, from a browser, in the
+//! `ci_token` cookie, is resolved with repohub's `GET /api/v1/access` into
+//! the repositories the caller may see and act on. Without a token, that is
+//! every public repository, read-only.
+//!
+//! - Builds of a repository the caller may not see are left out of lists
+//!   and answered 404, so private repositories don't leak. Builds of a
+//!   repository repohub doesn't know are hidden from everyone.
+//! - Enqueuing, retrying and canceling a build needs write access to its
+//!   repository.
+//! - A browser gets its cookie from `POST /login`, `HttpOnly` and
+//!   `SameSite=Strict`. As a cookie is sent whoever made the page, requests
+//!   that change something and carry no bearer token must also echo the
+//!   CSRF token given at login, in [`CSRF_HEADER`].
+//!
+//! Answers are cached per token for [`ACCESS_TTL`], so a new member or a
+//! removed one takes up to that long to be seen.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::http::{header, HeaderMap, StatusCode};
+use repohub_client::{Access, Client, ClientError};
+use sha2::{Digest, Sha256};
+
+/// How long repohub's answer for a token is trusted
+pub const ACCESS_TTL: Duration = Duration::from_mins(1);
+
+/// Cookie holding the token of a browser
+pub const TOKEN_COOKIE: &str = "ci_token";
+
+/// Cookie holding the CSRF token of a browser, readable by its scripts
+pub const CSRF_COOKIE: &str = "ci_csrf";
+
+/// Header echoing the CSRF token on requests that change something
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Longest wait for repohub before refusing a request
+const REPOHUB_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Cached = HashMap<Option<String>, (Instant, Arc<Access>)>;
+
+/// Resolves tokens with repohub.
+#[derive(Clone)]
+pub struct Auth {
+    repohub: Client,
+    cache: Arc<Mutex<Cached>>,
+}
+
+impl Auth {
+    /// Check tokens with the repohub served at `repohub_url`.
+    ///
+    /// # Errors
+    ///
+    /// - if `repohub_url` is not an absolute http(s) URL
+    pub fn new(repohub_url: &str) -> Result<Self, ClientError> {
+        Ok(Self {
+            repohub: Client::new(repohub_url)?,
+            cache: Arc::default(),
+        })
+    }
+
+    /// What the caller of a request with `headers` may see and do.
+    pub(crate) async fn viewer(&self, headers: &HeaderMap) -> Result<Viewer, (StatusCode, String)> {
+        self.access(token(headers)).await.map(Viewer::Restricted)
+    }
+
+    /// What repohub lets the holder of `token` see and do, or an anonymous
+    /// caller without one.
+    pub(crate) async fn access(
+        &self,
+        token: Option<&str>,
+    ) -> Result<Arc<Access>, (StatusCode, String)> {
+        let token = token.map(str::to_string);
+        let now = Instant::now();
+        if let Some((at, access)) = self.cache.lock().unwrap().get(&token) {
+            if now.duration_since(*at) < ACCESS_TTL {
+                return Ok(access.clone());
+            }
+        }
+
+        let access = tokio::time::timeout(REPOHUB_TIMEOUT, self.repohub.access(token.as_deref()))
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Repohub did not answer in time".to_string(),
+                )
+            })?
+            .map_err(|e| match e {
+                ClientError::Status { status: 401, .. } => {
+                    (StatusCode::UNAUTHORIZED, "Invalid token".to_string())
+                }
+                e => {
+                    tracing::error!(error = %e, "Failed to check access with repohub");
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        format!("Failed to check access: {e}"),
+                    )
+                }
+            })?;
+        let access = Arc::new(access);
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (at, _)| now.duration_since(*at) < ACCESS_TTL);
+        cache.insert(token, (now, access.clone()));
+        Ok(access)
+    }
+}
+
+/// Token of a request: its bearer token, or else its `ci_token` cookie.
+fn token(headers: &HeaderMap) -> Option<&str> {
+    bearer(headers).or_else(|| cookie(headers, TOKEN_COOKIE))
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| {
+            cookie
+                .trim()
+                .strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('='))
+        })
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// CSRF token going with the cookie holding `token`.
+///
+/// Derived from the token rather than stored, so only a page that was
+/// given it at login can send it.
+#[must_use]
+pub fn csrf_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("{CSRF_COOKIE}:{token}")))
+}
+
+/// `Set-Cookie` values logging a browser in with `token`.
+#[must_use]
+pub fn session_cookies(token: &str) -> [String; 2] {
+    [
+        format!("{TOKEN_COOKIE}={token}; Path=/; HttpOnly; SameSite=Strict"),
+        format!(
+            "{CSRF_COOKIE}={}; Path=/; SameSite=Strict",
+            csrf_token(token)
+        ),
+    ]
+}
+
+/// `Set-Cookie` values logging a browser out.
+#[must_use]
+pub fn expired_cookies() -> [String; 2] {
+    [TOKEN_COOKIE, CSRF_COOKIE]
+        .map(|name| format!("{name}=; Path=/; Max-Age=0; HttpOnly; SameSite=Strict"))
+}
+
+/// Refuse a request that changes something unless the caller sent it: it
+/// carries a bearer token, which another site's page can't add, or no
+/// `ci_token` cookie at all, or the CSRF token of its cookie in
+/// [`CSRF_HEADER`].
+pub(crate) fn check_csrf(headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    if bearer(headers).is_some() {
+        return Ok(());
+    }
+    let Some(token) = cookie(headers, TOKEN_COOKIE) else {
+        return Ok(());
+    };
+    let sent = headers
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if sent == Some(csrf_token(token).as_str()) {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::FORBIDDEN,
+            format!("Missing or invalid CSRF token, send the one given at login in {CSRF_HEADER}"),
+        ))
+    }
+}
+
+/// The caller of a request, as far as builds go.
+#[derive(Debug, Clone)]
+pub enum Viewer {
+    /// No repohub is configured: anyone may see and do anything
+    Anyone,
+    /// What repohub lets the caller see and do
+    Restricted(Arc<Access>),
+}
+
+impl Viewer {
+    /// Whether builds of `repo_path`, a bare repository's path or its path
+    /// under the repos directory, may be seen.
+    #[must_use]
+    pub fn can_view(&self, repo_path: &str) -> bool {
+        match self {
+            Viewer::Anyone => true,
+            Viewer::Restricted(access) => access.repository(repo_path).is_some(),
+        }
+    }
+
+    /// Whether builds of `repo_path` may be enqueued, retried and canceled.
+    #[must_use]
+    pub fn can_write(&self, repo_path: &str) -> bool {
+        match self {
+            Viewer::Anyone => true,
+            Viewer::Restricted(access) => {
+                access.repository(repo_path).is_some_and(|repo| repo.write)
+            }
+        }
+    }
+
+    /// Repositories whose builds may be seen, `None` for every build.
+    #[must_use]
+    pub fn repositories(&self) -> Option<Vec<&str>> {
+        match self {
+            Viewer::Anyone => None,
+            Viewer::Restricted(access) => Some(
+                access
+                    .repositories
+                    .iter()
+                    .map(|repo| repo.path.as_str())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use repohub_client::RepositoryAccess;
+
+    fn repo(path: &str, write: bool) -> RepositoryAccess {
+        RepositoryAccess {
+            path: path.to_string(),
+            private: true,
+            write,
+        }
+    }
+
+    #[test]
+    fn tokens_come_from_the_bearer_or_the_cookie() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(token(&headers), None);
+        headers.insert(
+            header::COOKIE,
+            "theme=dark; ci_token=rh_cookie".parse().unwrap(),
+        );
+        assert_eq!(token(&headers), Some("rh_cookie"));
+        headers.insert(header::AUTHORIZATION, "Bearer rh_bearer".parse().unwrap());
+        assert_eq!(token(&headers), Some("rh_bearer"));
+    }
+
+    #[test]
+    fn cookie_sessions_need_the_csrf_token_to_change_anything() {
+        let mut headers = HeaderMap::new();
+        assert!(check_csrf(&headers).is_ok());
+
+        headers.insert(header::COOKIE, "ci_token=rh_cookie".parse().unwrap());
+        assert_eq!(check_csrf(&headers).unwrap_err().0, StatusCode::FORBIDDEN);
+        headers.insert(CSRF_HEADER, csrf_token("rh_other").parse().unwrap());
+        assert!(check_csrf(&headers).is_err());
+        headers.insert(CSRF_HEADER, csrf_token("rh_cookie").parse().unwrap());
+        assert!(check_csrf(&headers).is_ok());
+
+        headers.remove(CSRF_HEADER);
+        headers.insert(header::AUTHORIZATION, "Bearer rh_bearer".parse().unwrap());
+        assert!(check_csrf(&headers).is_ok());
+
+        let [token, csrf] = session_cookies("rh_cookie");
+        assert!(token.starts_with("ci_token=rh_cookie;"));
+        assert!(token.contains("HttpOnly") && token.contains("SameSite=Strict"));
+        assert!(!csrf.contains("HttpOnly") && csrf.contains("SameSite=Strict"));
+    }
+
+    #[test]
+    fn restricted_viewers_only_see_and_act_on_their_repositories() {
+        let viewer = Viewer::Restricted(Arc::new(Access {
+            user: None,
+            repositories: vec![repo("lucas/api", true), repo("lucas/docs", false)],
+        }));
+        assert!(viewer.can_view("/var/lib/git-server/lucas/api.git"));
+        assert!(viewer.can_write("/var/lib/git-server/lucas/api.git"));
+        assert!(viewer.can_view("lucas/docs"));
+        assert!(!viewer.can_write("lucas/docs"));
+        assert!(!viewer.can_view("/var/lib/git-server/ana/api.git"));
+        assert_eq!(viewer.repositories(), Some(vec!["lucas/api", "lucas/docs"]));
+
+        assert!(Viewer::Anyone.can_write("/var/lib/git-server/ana/api.git"));
+        assert_eq!(Viewer::Anyone.repositories(), None);
+    }
+}
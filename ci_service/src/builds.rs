@@ -7,6 +7,10 @@ pub struct BuildJob {
     commit_hash: String,
     branch: String,
     status: String,
+    eval_status: String,
+    build_status: String,
+    scan_status: Option<String>,
+    base_commit: Option<String>,
     retry_count: u8,
     max_retries: u8,
     created_at: String,
@@ -19,46 +23,179 @@ impl BuildJob {
         self.id
     }
 
+    /// Path of the bare repository
+    pub fn repo_path(&self) -> &str {
+        &self.repo_path
+    }
+
+    pub fn commit_hash(&self) -> &str {
+        &self.commit_hash
+    }
+
+    pub fn branch(&self) -> &str {
+        &self.branch
+    }
+
     pub fn git_url(&self) -> String {
         format!("{}#{}", self.repo_path, self.commit_hash)
     }
 
+    /// Flake reference of the commit, to pick outputs of
+    pub fn flake_ref(&self) -> String {
+        format!("git+file://{}?rev={}", self.repo_path, self.commit_hash)
+    }
+
+    /// Commit the push started from, `None` to run every check
+    pub fn base_commit(&self) -> Option<&str> {
+        self.base_commit.as_deref()
+    }
+
+    /// Status of the vulnerability scan, `None` when the build wasn't scanned
+    pub fn scan_status(&self) -> Option<BuildStatus> {
+        self.scan_status.as_deref().and_then(|s| s.parse().ok())
+    }
+
+    /// Status of one stage, `None` when it didn't run
+    pub fn stage_status(&self, stage: Stage) -> Option<BuildStatus> {
+        match stage {
+            Stage::Scan => self.scan_status(),
+            Stage::Eval => self.eval_status.parse().ok(),
+            Stage::Build => self.build_status.parse().ok(),
+        }
+    }
+
+    /// Whether the build won't run any further stage, until retried
+    pub fn finished(&self) -> bool {
+        matches!(
+            self.status.parse(),
+            Ok(BuildStatus::Success | BuildStatus::Failed | BuildStatus::Canceled)
+        )
+    }
+
     pub fn can_retry(&self) -> bool {
         self.retry_count < self.max_retries
     }
 }
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
 pub struct BuildInfo {
     id: i64,
     repo_path: String,
     commit_hash: String,
     branch: String,
     status: BuildStatus,
+    eval_status: BuildStatus,
+    build_status: BuildStatus,
+    /// Status of the vulnerability scan, `None` when the build wasn't scanned
+    scan_status: Option<BuildStatus>,
+    /// Every stage in one line, e.g. "eval passed, building"
+    progress: &'static str,
     retry_count: u8,
 }
 
 impl From<BuildJob> for BuildInfo {
     fn from(b: BuildJob) -> Self {
         let status = b.status.parse().unwrap_or(BuildStatus::Queued);
+        let eval_status = b.eval_status.parse().unwrap_or(BuildStatus::Queued);
+        let build_status = b.build_status.parse().unwrap_or(BuildStatus::Queued);
+        let scan_status = b.scan_status();
         Self {
             id: b.id,
             repo_path: b.repo_path,
             commit_hash: b.commit_hash,
             branch: b.branch,
+            progress: progress(scan_status.as_ref(), &eval_status, &build_status),
             status,
+            eval_status,
+            build_status,
+            scan_status,
             retry_count: b.retry_count as u8,
         }
     }
 }
 
+/// The stages a build goes through, each with its own [`BuildStatus`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// Check of the SBOM against the advisory database, run first and only
+    /// when configured
+    Scan,
+    /// `nix flake check --no-build`, reported within seconds
+    Eval,
+    /// The full `nix flake check`, only run once evaluation passed
+    Build,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Scan => "scan",
+            Stage::Eval => "eval",
+            Stage::Build => "build",
+        }
+    }
+
+    /// Column of `builds` holding the status of this stage.
+    pub(crate) fn column(self) -> &'static str {
+        match self {
+            Stage::Scan => "scan_status",
+            Stage::Eval => "eval_status",
+            Stage::Build => "build_status",
+        }
+    }
+}
+
+impl std::str::FromStr for Stage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scan" => Ok(Stage::Scan),
+            "eval" => Ok(Stage::Eval),
+            "build" => Ok(Stage::Build),
+            _ => Err(format!("Invalid stage: {s}")),
+        }
+    }
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+fn progress(scan: Option<&BuildStatus>, eval: &BuildStatus, build: &BuildStatus) -> &'static str {
+    // A failed scan only stops the build when its findings are blocking
+    match (scan, eval) {
+        (Some(BuildStatus::Running), _) => return "scanning",
+        (Some(BuildStatus::Failed), BuildStatus::Queued) => return "scan failed",
+        _ => {}
+    }
+    match (eval, build) {
+        (BuildStatus::Canceled, _) | (_, BuildStatus::Canceled) => "canceled",
+        (BuildStatus::Queued, _) => "queued",
+        (BuildStatus::Running, _) => "evaluating",
+        (BuildStatus::Failed, _) => "eval failed",
+        (BuildStatus::Success, BuildStatus::Queued) => "eval passed",
+        (BuildStatus::Success, BuildStatus::Running) => "eval passed, building",
+        (BuildStatus::Success, BuildStatus::Success) => "eval passed, build passed",
+        (BuildStatus::Success, BuildStatus::Failed) => "eval passed, build failed",
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum BuildStatus {
     Queued,
     Running,
     Success,
     Failed,
+    /// Canceled before it started
+    Canceled,
 }
 
 impl BuildStatus {
@@ -68,6 +205,7 @@ impl BuildStatus {
             BuildStatus::Running => "running",
             BuildStatus::Success => "success",
             BuildStatus::Failed => "failed",
+            BuildStatus::Canceled => "canceled",
         }
     }
 }
@@ -87,6 +225,7 @@ impl std::str::FromStr for BuildStatus {
             "running" => Ok(BuildStatus::Running),
             "success" => Ok(BuildStatus::Success),
             "failed" => Ok(BuildStatus::Failed),
+            "canceled" => Ok(BuildStatus::Canceled),
             _ => Err(format!("Invalid build status: {}", s)),
         }
     }
@@ -99,3 +238,48 @@ impl TryFrom<String> for BuildStatus {
         value.parse()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_reports_the_furthest_stage() {
+        assert_eq!(
+            progress(None, &BuildStatus::Running, &BuildStatus::Queued),
+            "evaluating"
+        );
+        assert_eq!(
+            progress(None, &BuildStatus::Success, &BuildStatus::Running),
+            "eval passed, building"
+        );
+        assert_eq!(
+            progress(None, &BuildStatus::Failed, &BuildStatus::Queued),
+            "eval failed"
+        );
+        assert_eq!(
+            progress(
+                Some(&BuildStatus::Running),
+                &BuildStatus::Queued,
+                &BuildStatus::Queued
+            ),
+            "scanning"
+        );
+        assert_eq!(
+            progress(
+                Some(&BuildStatus::Failed),
+                &BuildStatus::Queued,
+                &BuildStatus::Queued
+            ),
+            "scan failed"
+        );
+        assert_eq!(
+            progress(
+                Some(&BuildStatus::Success),
+                &BuildStatus::Success,
+                &BuildStatus::Running
+            ),
+            "eval passed, building"
+        );
+    }
+}
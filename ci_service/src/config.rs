@@ -1,3 +1,11 @@
+use std::path::PathBuf;
+
+use repo_outils::nix::RetryPolicy;
+
+use crate::gate::GatePolicy;
+use crate::slo::Objectives;
+use crate::vulns::Severity;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub database_url: String,
@@ -6,6 +14,46 @@ pub struct Config {
     pub repos_base_path: String,
     pub max_retries: i64,
     pub worker_poll_interval_ms: u64,
+    /// Builds the worker runs at once; more wait in the queue
+    pub max_concurrent_builds: usize,
+    /// How Nix commands failing transiently, e.g. on a cache that cannot be
+    /// reached, are run again
+    pub nix_retry: RetryPolicy,
+    /// Build each job in its own chroot store instead of the host store
+    pub store_isolation: Option<StoreIsolation>,
+    /// Check every build's SBOM against an advisory database before building
+    pub vuln_scan: Option<VulnScan>,
+    /// Stages each branch requires before its commits may be deployed
+    pub gate: GatePolicy,
+    /// Queue wait, duration and success rate the stats are checked against
+    pub objectives: Objectives,
+    /// Repohub checking API tokens, e.g. `http://localhost:3001`; the API
+    /// and pages are open to anyone when `None`
+    pub repohub_url: Option<String>,
+}
+
+/// Where per-build stores live and where their results go.
+#[derive(Debug, Clone)]
+pub struct StoreIsolation {
+    /// Parent directory of the per-build stores, one `build-<id>` each
+    pub stores_path: PathBuf,
+    /// Store URI results are copied to (e.g. the binary cache); the host
+    /// store when `None`
+    pub copy_to: Option<String>,
+    /// Secret key results are signed with before they are copied, so the
+    /// cache serves them to workers trusting its public key; unsigned
+    /// when `None`
+    pub signing_key_file: Option<PathBuf>,
+}
+
+/// Where the advisories are and what a finding does to the build.
+#[derive(Debug, Clone)]
+pub struct VulnScan {
+    /// Offline mirror of the OSV database, one directory per ecosystem
+    pub advisories_path: PathBuf,
+    /// Fail the build on findings of this severity or above; findings are
+    /// only reported when `None`
+    pub fail_on: Option<Severity>,
 }
 
 impl Default for Config {
@@ -17,6 +65,13 @@ impl Default for Config {
             repos_base_path: "/var/lib/git-server".to_string(),
             max_retries: 3,
             worker_poll_interval_ms: 1000,
+            max_concurrent_builds: 1,
+            nix_retry: RetryPolicy::default(),
+            store_isolation: None,
+            vuln_scan: None,
+            gate: GatePolicy::default(),
+            objectives: Objectives::default(),
+            repohub_url: None,
         }
     }
 }
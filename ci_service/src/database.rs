@@ -7,43 +7,42 @@
 
 use std::{ops::Deref, str::FromStr};
 
+use repo_outils::nix::{Diagnostics, FlakeChecks};
 use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
 use tracing::info;
 
-#[derive(Debug)]
+use crate::selection::SkippedCheck;
+use crate::steps::StepTiming;
+use crate::vulns::Finding;
+
+#[derive(Debug, thiserror::Error)]
 pub enum DatabaseError {
-    Connection(sqlx::Error),
-    Query(sqlx::Error),
-    InvalidData(String),
+    #[error("Database connection error")]
+    Connection(#[source] sqlx::Error),
+    #[error("Database query error")]
+    Query(#[from] sqlx::Error),
+    #[error("Invalid data: {context}")]
+    InvalidData {
+        context: &'static str,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Not found: {0}")]
     NotFound(String),
 }
 
-impl std::fmt::Display for DatabaseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl DatabaseError {
+    /// Stable identifier for this failure, independent of the message.
+    pub fn code(&self) -> &'static str {
         match self {
-            DatabaseError::Connection(err) => write!(f, "Database connection error: {}", err),
-            DatabaseError::Query(err) => write!(f, "Database query error: {}", err),
-            DatabaseError::InvalidData(msg) => write!(f, "Invalid data: {}", msg),
-            DatabaseError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            DatabaseError::Connection(_) => "db.connection",
+            DatabaseError::Query(_) => "db.query",
+            DatabaseError::InvalidData { .. } => "db.invalid_data",
+            DatabaseError::NotFound(_) => "db.not_found",
         }
     }
 }
 
-impl std::error::Error for DatabaseError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            DatabaseError::Connection(err) | DatabaseError::Query(err) => Some(err),
-            _ => None,
-        }
-    }
-}
-
-impl From<sqlx::Error> for DatabaseError {
-    fn from(err: sqlx::Error) -> Self {
-        DatabaseError::Query(err)
-    }
-}
-
 pub type Result<T> = std::result::Result<T, DatabaseError>;
 
 /// DTO for reading builds from simplified tables
@@ -55,6 +54,10 @@ pub struct BuildRow {
     commit_hash: String,
     branch: String,
     status: String,
+    eval_status: String,
+    build_status: String,
+    scan_status: Option<String>,
+    base_commit: Option<String>,
     retry_count: i64,
     max_retries: i64,
     created_at: String,
@@ -63,9 +66,52 @@ pub struct BuildRow {
 }
 
 
-// Structured summary placeholder - stores arbitrary JSON produced by the runner
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct BuildSummary;
+/// Structured summary of a build, stored as JSON in `build_summaries`
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct BuildSummary {
+    /// Every stage's steps, timed from the start of the build
+    pub steps: Vec<StepTiming>,
+    /// Checks left out as the push didn't affect their package
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedCheck>,
+    /// Why the failed stage failed, from nix's errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<Diagnostics>,
+}
+
+/// SBOM of one repo of a build's commit, as stored in `build_sboms`
+#[derive(Debug, Clone, serde::Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct BuildSbom {
+    /// Directory of the repo in the commit, named after the repository at its root
+    pub repo: String,
+    #[cfg_attr(feature = "web", schema(value_type = Object))]
+    pub licenses: autonix::LicenseSummary,
+    /// CycloneDX document
+    #[cfg_attr(feature = "web", schema(value_type = Object))]
+    pub sbom: serde_json::Value,
+}
+
+/// A finding of a build, as listed across builds
+#[derive(Debug, Clone, serde::Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct BuildFinding {
+    pub build_id: i64,
+    /// Path of the bare repository the build is for
+    pub repo_path: String,
+    pub commit_hash: String,
+    #[serde(flatten)]
+    pub finding: Finding,
+}
+
+/// An evaluation found in `eval_cache`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedEval {
+    /// Build whose evaluation passed
+    pub build_id: i64,
+    /// Checks of the flake, `None` until a build listed them
+    pub checks: Option<FlakeChecks>,
+}
 
 #[derive(Clone)]
 pub struct Database {
@@ -106,6 +152,10 @@ impl Database {
                 commit_hash TEXT NOT NULL,
                 branch TEXT NOT NULL,
                 status TEXT NOT NULL DEFAULT 'queued',
+                eval_status TEXT NOT NULL DEFAULT 'queued',
+                build_status TEXT NOT NULL DEFAULT 'queued',
+                scan_status TEXT,
+                base_commit TEXT,
                 retry_count INTEGER NOT NULL DEFAULT 0,
                 max_retries INTEGER NOT NULL DEFAULT 3,
                 created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
@@ -118,6 +168,20 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Databases created before builds had an eval stage lack its columns
+        for column in ["eval_status", "build_status"] {
+            self.add_column_if_missing("builds", column, "TEXT NOT NULL DEFAULT 'queued'")
+                .await?;
+        }
+        self.add_column_if_missing("builds", "scan_status", "TEXT")
+            .await?;
+        self.add_column_if_missing("builds", "base_commit", "TEXT")
+            .await?;
+        // 'hit' or 'miss' once the eval cache was looked up, NULL when the
+        // commit has no flake.lock to key it on
+        self.add_column_if_missing("builds", "eval_cache", "TEXT")
+            .await?;
+
         // Build logs table (referencing builds.build_id)
         sqlx::query(
             r#"
@@ -146,6 +210,65 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // SBOM and license summary of every repo found in a build's commit
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS build_sboms (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                build_id INTEGER NOT NULL,
+                repo TEXT NOT NULL,
+                licenses_json TEXT NOT NULL,
+                sbom_json TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE (build_id, repo)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Advisories matching the packages of a build's SBOMs
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS build_findings (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                build_id INTEGER NOT NULL,
+                repo TEXT NOT NULL,
+                ecosystem TEXT NOT NULL,
+                package TEXT NOT NULL,
+                version TEXT NOT NULL,
+                advisory TEXT NOT NULL,
+                aliases_json TEXT NOT NULL,
+                severity TEXT NOT NULL,
+                summary TEXT NOT NULL,
+                fixed TEXT,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Successful evaluations, per commit and flake.lock, so builds of the
+        // same inputs skip them; `checks_json` is filled once the checks were
+        // listed
+        sqlx::query(
+            r"
+            CREATE TABLE IF NOT EXISTS eval_cache (
+                repo_path TEXT NOT NULL,
+                commit_hash TEXT NOT NULL,
+                lock_hash TEXT NOT NULL,
+                branch TEXT NOT NULL,
+                build_id INTEGER NOT NULL,
+                checks_json TEXT,
+                hits INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (repo_path, commit_hash, lock_hash)
+            )
+            ",
+        )
+        .execute(&self.pool)
+        .await?;
 
         // Create indexes for performance
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_builds_status_created ON builds(status, created_at)")
@@ -166,6 +289,39 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_build_findings_build_id ON build_findings(build_id)",
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_build_findings_advisory ON build_findings(advisory)",
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn add_column_if_missing(
+        &self,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<()> {
+        let columns: Vec<String> =
+            sqlx::query_scalar(&format!("SELECT name FROM pragma_table_info('{table}')"))
+                .fetch_all(&self.pool)
+                .await?;
+        if !columns.iter().any(|c| c == column) {
+            info!(table, column, "Adding missing column");
+            sqlx::query(&format!(
+                "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+            ))
+            .execute(&self.pool)
+            .await?;
+        }
         Ok(())
     }
 }
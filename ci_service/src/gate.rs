@@ -0,0 +1,252 @@
+//! Deployment gate: whether a commit passed the checks its branch requires.
+//!
+//! Like branch protection, a [`GatePolicy`] lists the stages each branch
+//! requires. A commit is gated on its latest build: it passes once every
+//! required stage succeeded, is pending while one may still succeed, and
+//! fails as soon as one failed or the build finished without running it.
+//! Branches no rule matches never pass, so only protected branches deploy.
+
+use serde::Serialize;
+
+use crate::builds::{BuildJob, BuildStatus, Stage};
+
+/// Stages a branch requires before its commits may be deployed.
+#[derive(Debug, Clone)]
+pub struct BranchRule {
+    /// Branch name, or a prefix ending with `*` such as `release/*`
+    pub branch: String,
+    pub required: Vec<Stage>,
+}
+
+impl BranchRule {
+    fn matches(&self, branch: &str) -> bool {
+        match self.branch.strip_suffix('*') {
+            Some(prefix) => branch.starts_with(prefix),
+            None => self.branch == branch,
+        }
+    }
+}
+
+/// Rules of the gate, the first one matching a branch applies.
+#[derive(Debug, Clone)]
+pub struct GatePolicy {
+    pub rules: Vec<BranchRule>,
+}
+
+impl Default for GatePolicy {
+    /// Every branch requires eval and build. The scan stage only runs when
+    /// configured, so it is left out.
+    fn default() -> Self {
+        Self {
+            rules: vec![BranchRule {
+                branch: "*".to_string(),
+                required: vec![Stage::Eval, Stage::Build],
+            }],
+        }
+    }
+}
+
+impl GatePolicy {
+    /// Stages required on `branch`, `None` when it is not protected.
+    pub fn required(&self, branch: &str) -> Option<&[Stage]> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(branch))
+            .map(|rule| rule.required.as_slice())
+    }
+
+    /// Gate of `commit`, decided on its latest build.
+    pub fn evaluate(&self, commit: &str, build: Option<&BuildJob>) -> Gate {
+        let Some(build) = build else {
+            return Gate {
+                commit: commit.to_string(),
+                build_id: None,
+                branch: None,
+                state: GateState::NoBuild,
+                passed: false,
+                checks: Vec::new(),
+                reason: Some(format!("no build of commit {commit}")),
+            };
+        };
+        let required = self.required(build.branch());
+        let checks: Vec<Check> = [Stage::Scan, Stage::Eval, Stage::Build]
+            .into_iter()
+            .map(|stage| Check {
+                name: stage,
+                status: build.stage_status(stage),
+                required: required.is_some_and(|r| r.contains(&stage)),
+            })
+            .collect();
+        let (state, reason) = match required {
+            None => (
+                GateState::Failed,
+                Some(format!("branch {} is not protected", build.branch())),
+            ),
+            Some(_) => decide(&checks, build.finished()),
+        };
+        Gate {
+            commit: build.commit_hash().to_string(),
+            build_id: Some(build.id()),
+            branch: Some(build.branch().to_string()),
+            passed: state == GateState::Passed,
+            state,
+            checks,
+            reason,
+        }
+    }
+}
+
+/// State of the required `checks` of a build, `finished` when it won't run
+/// any further stage.
+fn decide(checks: &[Check], finished: bool) -> (GateState, Option<String>) {
+    let mut required = checks.iter().filter(|check| check.required);
+    if let Some(check) = required
+        .clone()
+        .find(|check| check.status == Some(BuildStatus::Failed))
+    {
+        return (GateState::Failed, Some(format!("{} failed", check.name)));
+    }
+    match required.find(|check| check.status != Some(BuildStatus::Success)) {
+        None => (GateState::Passed, None),
+        Some(check) if finished => (
+            GateState::Failed,
+            Some(format!("{} did not run", check.name)),
+        ),
+        Some(check) => {
+            let status = check
+                .status
+                .as_ref()
+                .map_or("not started", BuildStatus::as_str);
+            (
+                GateState::Pending,
+                Some(format!("{} is {status}", check.name)),
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum GateState {
+    /// Every required stage succeeded
+    Passed,
+    /// A required stage hasn't finished yet
+    Pending,
+    /// A required stage failed or never ran, or the branch is not protected
+    Failed,
+    /// The commit wasn't built
+    NoBuild,
+}
+
+/// One stage of the build the gate was decided on.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct Check {
+    pub name: Stage,
+    /// `None` when the stage didn't run, as the scan when not configured
+    pub status: Option<BuildStatus>,
+    /// Whether the branch requires it
+    pub required: bool,
+}
+
+/// Whether a commit may be deployed.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct Gate {
+    /// Full hash, even when asked with an abbreviated one
+    pub commit: String,
+    /// The latest build of the commit, `None` when it has none
+    pub build_id: Option<i64>,
+    pub branch: Option<String>,
+    pub state: GateState,
+    /// Whether `state` is `passed`
+    pub passed: bool,
+    pub checks: Vec<Check>,
+    /// Why it didn't pass
+    pub reason: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::Database, job_queue::JobQueue};
+
+    #[test]
+    fn the_first_matching_rule_applies() {
+        let policy = GatePolicy {
+            rules: vec![
+                BranchRule {
+                    branch: "release/*".to_string(),
+                    required: vec![Stage::Scan, Stage::Eval, Stage::Build],
+                },
+                BranchRule {
+                    branch: "main".to_string(),
+                    required: vec![Stage::Eval],
+                },
+            ],
+        };
+        assert_eq!(policy.required("release/1.2").unwrap().len(), 3);
+        assert_eq!(policy.required("main"), Some(&[Stage::Eval][..]));
+        assert_eq!(policy.required("feature"), None);
+    }
+
+    #[tokio::test]
+    async fn commits_pass_once_every_required_stage_succeeded() {
+        let path = std::env::temp_dir().join(format!("ci-gate-{}.db", std::process::id()));
+        let queue = JobQueue::new(
+            Database::new(&format!("sqlite://{}", path.display()))
+                .await
+                .unwrap(),
+        );
+        let policy = GatePolicy::default();
+        let commit = "1e43a4529500115f72383235e7112e4e3ba91005";
+
+        let gate = policy.evaluate(commit, None);
+        assert_eq!((gate.state, gate.passed), (GateState::NoBuild, false));
+
+        let id = queue
+            .enqueue("/srv/git/lucas/api.git", commit, "main", None)
+            .await
+            .unwrap();
+        let latest = || queue.latest_build_of_commit("lucas/api", "1e43a45", None);
+        queue
+            .update_stage(id, Stage::Eval, BuildStatus::Success)
+            .await
+            .unwrap();
+        queue
+            .update_stage(id, Stage::Build, BuildStatus::Running)
+            .await
+            .unwrap();
+        let gate = policy.evaluate(commit, latest().await.unwrap().as_ref());
+        assert_eq!(gate.state, GateState::Pending);
+        assert_eq!(gate.reason.as_deref(), Some("build is running"));
+
+        queue
+            .update_stage(id, Stage::Build, BuildStatus::Success)
+            .await
+            .unwrap();
+        queue.update_status(id, BuildStatus::Success).await.unwrap();
+        let gate = policy.evaluate(commit, latest().await.unwrap().as_ref());
+        assert_eq!((gate.state, gate.passed), (GateState::Passed, true));
+        assert_eq!(gate.commit, commit);
+        assert!(queue
+            .latest_build_of_commit("api", commit, Some("release"))
+            .await
+            .unwrap()
+            .is_none());
+
+        // Scan required but never run: the finished build can't pass
+        let strict = GatePolicy {
+            rules: vec![BranchRule {
+                branch: "*".to_string(),
+                required: vec![Stage::Scan, Stage::Build],
+            }],
+        };
+        let gate = strict.evaluate(commit, latest().await.unwrap().as_ref());
+        assert_eq!(gate.state, GateState::Failed);
+        assert_eq!(gate.reason.as_deref(), Some("scan did not run"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -9,10 +9,14 @@
 //! - Retry logic with configurable maximum attempts
 
 use super::{
-    builds::{BuildJob, BuildStatus},
-    database::{BuildSummary, Database, DatabaseError},
+    builds::{BuildJob, BuildStatus, Stage},
+    database::{BuildFinding, BuildSbom, BuildSummary, CachedEval, Database, DatabaseError},
+    slo::BuildTiming,
+    vulns::{Finding, Severity},
 };
 
+use repo_outils::nix::FlakeChecks;
+
 type Result<T> = std::result::Result<T, DatabaseError>;
 
 #[derive(Clone)]
@@ -25,14 +29,26 @@ impl JobQueue {
         Self { db }
     }
 
-    /// Enqueue a new build job
-    pub async fn enqueue(&self, repo_path: &str, commit: &str, branch: &str) -> Result<i64> {
+    /// Enqueue a new build job, only running the checks the changes since
+    /// `base` affect when given
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be updated
+    pub async fn enqueue(
+        &self,
+        repo_path: &str,
+        commit: &str,
+        branch: &str,
+        base: Option<&str>,
+    ) -> Result<i64> {
         let result = sqlx::query(
-            "INSERT INTO builds (repo_path, commit_hash, branch, status) VALUES (?, ?, ?, 'queued')"
+            "INSERT INTO builds (repo_path, commit_hash, branch, base_commit, status) VALUES (?, ?, ?, ?, 'queued')"
         )
         .bind(repo_path)
         .bind(commit)
         .bind(branch)
+        .bind(base)
         .execute(&*self.db)
         .await?;
 
@@ -60,8 +76,8 @@ impl JobQueue {
         }
 
         let job = sqlx::query_as(
-            r#"SELECT id, repo_path, commit_hash, branch, status, retry_count, max_retries, created_at, started_at, finished_at
-               FROM builds WHERE status = 'running' ORDER BY started_at LIMIT 1"#
+            r"SELECT id, repo_path, commit_hash, branch, status, eval_status, build_status, scan_status, base_commit, retry_count, max_retries, created_at, started_at, finished_at
+               FROM builds WHERE status = 'running' ORDER BY started_at LIMIT 1"
         )
         .fetch_one(&*self.db)
         .await?;
@@ -82,7 +98,7 @@ impl JobQueue {
                     .execute(&*self.db)
                     .await?;
             }
-            BuildStatus::Success | BuildStatus::Failed => {
+            BuildStatus::Success | BuildStatus::Failed | BuildStatus::Canceled => {
                 sqlx::query("UPDATE builds SET status = ?, finished_at = ? WHERE id = ?")
                     .bind(status.as_str())
                     .bind(&now)
@@ -102,13 +118,50 @@ impl JobQueue {
         Ok(())
     }
 
-    /// Increment retry count and re-queue a build
+    /// Update the status of one stage of a build
+    pub async fn update_stage(&self, id: i64, stage: Stage, status: BuildStatus) -> Result<()> {
+        sqlx::query(&format!(
+            "UPDATE builds SET {} = ? WHERE id = ?",
+            stage.column()
+        ))
+        .bind(status.as_str())
+        .bind(id)
+        .execute(&*self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Cancel a build that hasn't started, every stage included. `false` when
+    /// it isn't queued anymore.
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be updated
+    pub async fn cancel(&self, id: i64) -> Result<bool> {
+        let now = chrono::Utc::now().to_rfc3339();
+        let canceled = sqlx::query(
+            "UPDATE builds SET status = ?1, eval_status = ?1, build_status = ?1, finished_at = ?2 WHERE id = ?3 AND status = ?4",
+        )
+        .bind(BuildStatus::Canceled.as_str())
+        .bind(&now)
+        .bind(id)
+        .bind(BuildStatus::Queued.as_str())
+        .execute(&*self.db)
+        .await?;
+
+        Ok(canceled.rows_affected() == 1)
+    }
+
+    /// Increment retry count and re-queue a build, every stage starting over
     pub async fn increment_retry(&self, id: i64) -> Result<()> {
-        sqlx::query("UPDATE builds SET retry_count = retry_count + 1, status = ? WHERE id = ?")
-            .bind(BuildStatus::Queued.as_str())
-            .bind(id)
-            .execute(&*self.db)
-            .await?;
+        sqlx::query(
+            "UPDATE builds SET retry_count = retry_count + 1, status = ?1, eval_status = ?1, build_status = ?1, scan_status = NULL WHERE id = ?2",
+        )
+        .bind(BuildStatus::Queued.as_str())
+        .bind(id)
+        .execute(&*self.db)
+        .await?;
 
         Ok(())
     }
@@ -118,8 +171,8 @@ impl JobQueue {
         let job = sqlx::query_as(
             r#"
             SELECT
-                id, repo_path, commit_hash, branch, status,
-                retry_count, max_retries,
+                id, repo_path, commit_hash, branch, status, eval_status, build_status, scan_status,
+                base_commit, retry_count, max_retries,
                 created_at, started_at, finished_at
             FROM builds
             WHERE id = ?
@@ -133,19 +186,26 @@ impl JobQueue {
         Ok(job)
     }
 
-    /// List all builds (for web UI)
-    pub async fn list_all_builds(&self) -> Result<Vec<BuildJob>> {
-        let jobs = sqlx::query_as(
+    /// List the latest builds (for web UI), only of `repos` when set
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be queried
+    pub async fn list_all_builds(&self, repos: Option<&[&str]>) -> Result<Vec<BuildJob>> {
+        let jobs = sqlx::query_as(&format!(
             r#"
             SELECT
-                id, repo_path, commit_hash, branch, status,
-                retry_count, max_retries,
+                id, repo_path, commit_hash, branch, status, eval_status, build_status, scan_status,
+                base_commit, retry_count, max_retries,
                 created_at, started_at, finished_at
-            FROM builds
+            FROM builds b
+            WHERE {}
             ORDER BY created_at DESC
             LIMIT 100
             "#,
-        )
+            in_repos(1)
+        ))
+        .bind(repos_json(repos))
         .fetch_all(&*self.db)
         .await?;
 
@@ -161,8 +221,8 @@ impl JobQueue {
         let job = sqlx::query_as(
             r#"
             SELECT
-                id, repo_path, commit_hash, branch, status,
-                retry_count, max_retries,
+                id, repo_path, commit_hash, branch, status, eval_status, build_status, scan_status,
+                base_commit, retry_count, max_retries,
                 created_at, started_at, finished_at
             FROM builds
             WHERE repo_path LIKE ?
@@ -177,6 +237,66 @@ impl JobQueue {
         Ok(job)
     }
 
+    /// Latest build of a commit of a repository, optionally only those of
+    /// `branch`. `repo` is the bare repository's path, or its path under the
+    /// repos directory without `.git`; `commit` may be abbreviated.
+    pub async fn latest_build_of_commit(
+        &self,
+        repo: &str,
+        commit: &str,
+        branch: Option<&str>,
+    ) -> Result<Option<BuildJob>> {
+        let job = sqlx::query_as(
+            r#"
+            SELECT
+                id, repo_path, commit_hash, branch, status, eval_status, build_status, scan_status,
+                base_commit, retry_count, max_retries,
+                created_at, started_at, finished_at
+            FROM builds
+            WHERE (repo_path = ?1 OR repo_path LIKE '%/' || ?1 || '.git')
+              AND commit_hash LIKE ?2 || '%'
+              AND (?3 IS NULL OR branch = ?3)
+            ORDER BY created_at DESC, id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(repo)
+        .bind(commit)
+        .bind(branch)
+        .fetch_optional(&*self.db)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Commit of the last build of `branch` of `repo_path` that passed, the
+    /// latest to finish first.
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be queried
+    pub async fn last_passing_commit(
+        &self,
+        repo_path: &str,
+        branch: &str,
+    ) -> Result<Option<String>> {
+        let commit = sqlx::query_scalar(
+            r"
+            SELECT commit_hash FROM builds
+            WHERE repo_path = ? AND branch = ? AND status = ?
+            ORDER BY finished_at DESC, id DESC
+            LIMIT 1
+            ",
+        )
+        .bind(repo_path)
+        .bind(branch)
+        .bind(BuildStatus::Success.as_str())
+        .fetch_optional(&*self.db)
+        .await?;
+
+        Ok(commit)
+    }
+
     /// Get build logs for a specific build
     pub async fn get_build_logs(&self, id: i64) -> Result<Option<String>> {
         let log_lines = sqlx::query_scalar::<_, String>(
@@ -226,9 +346,11 @@ impl JobQueue {
 
     /// Store structured build summary
     pub async fn set_build_summary(&self, id: i64, summary: &BuildSummary) -> Result<()> {
-        let summary_json = serde_json::to_string(summary).map_err(|e| {
-            DatabaseError::InvalidData(format!("Failed to serialize build summary: {}", e))
-        })?;
+        let summary_json =
+            serde_json::to_string(summary).map_err(|source| DatabaseError::InvalidData {
+                context: "failed to serialize build summary",
+                source,
+            })?;
 
         sqlx::query(
             "INSERT OR REPLACE INTO build_summaries (build_id, summary_json) VALUES (?, ?)",
@@ -251,12 +373,674 @@ impl JobQueue {
         .await?;
 
         if let Some(json) = summary_json {
-            let summary: BuildSummary = serde_json::from_str(&json).map_err(|e| {
-                DatabaseError::InvalidData(format!("Failed to deserialize build summary: {}", e))
-            })?;
-            Ok(Some(summary))
+            parse_summary(&json)
         } else {
             Ok(None)
         }
     }
+
+    /// Summaries of the latest `limit` successful builds of a repository
+    /// prefix (matches `repo_path`), newest first
+    pub async fn repo_build_summaries(
+        &self,
+        repo_path_prefix: &str,
+        repos: Option<&[&str]>,
+        limit: i64,
+    ) -> Result<Vec<BuildSummary>> {
+        let rows = sqlx::query_scalar::<_, String>(&format!(
+            r#"
+            SELECT s.summary_json
+            FROM build_summaries s JOIN builds b ON b.id = s.build_id
+            WHERE b.repo_path LIKE ?1 AND b.status = 'success' AND {}
+            ORDER BY b.created_at DESC
+            LIMIT ?3
+            "#,
+            in_repos(2)
+        ))
+        .bind(format!("{}%", repo_path_prefix))
+        .bind(repos_json(repos))
+        .bind(limit)
+        .fetch_all(&*self.db)
+        .await?;
+
+        let mut summaries = Vec::with_capacity(rows.len());
+        for json in rows {
+            summaries.extend(parse_summary(&json)?);
+        }
+        Ok(summaries)
+    }
+
+    /// Timestamps of the builds enqueued since `since`, plus those still
+    /// queued or running, of `repos` when set
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be queried
+    pub async fn build_timings(
+        &self,
+        since: chrono::DateTime<chrono::Utc>,
+        repos: Option<&[&str]>,
+    ) -> Result<Vec<BuildTiming>> {
+        let timings = sqlx::query_as(&format!(
+            r"
+            SELECT repo_path, status, created_at, started_at, finished_at, eval_cache
+            FROM builds b
+            WHERE (b.created_at >= ?1 OR b.status IN ('queued', 'running')) AND {}
+            ORDER BY b.created_at
+            ",
+            in_repos(2)
+        ))
+        // The format of CURRENT_TIMESTAMP, so it compares as text
+        .bind(since.format("%Y-%m-%d %H:%M:%S").to_string())
+        .bind(repos_json(repos))
+        .fetch_all(&*self.db)
+        .await?;
+
+        Ok(timings)
+    }
+
+    /// Record whether build `id` found its evaluation in the eval cache
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be updated
+    pub async fn set_eval_cache(&self, id: i64, hit: bool) -> Result<()> {
+        sqlx::query("UPDATE builds SET eval_cache = ? WHERE id = ?")
+            .bind(if hit { "hit" } else { "miss" })
+            .bind(id)
+            .execute(&*self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The evaluation of `commit` with the `flake.lock` hashed `lock_hash`,
+    /// counting a hit when there is one
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be queried or holds invalid checks
+    pub async fn cached_eval(
+        &self,
+        repo_path: &str,
+        commit: &str,
+        lock_hash: &str,
+    ) -> Result<Option<CachedEval>> {
+        let row = sqlx::query_as::<_, (i64, Option<String>)>(
+            r"
+            UPDATE eval_cache SET hits = hits + 1
+            WHERE repo_path = ? AND commit_hash = ? AND lock_hash = ?
+            RETURNING build_id, checks_json
+            ",
+        )
+        .bind(repo_path)
+        .bind(commit)
+        .bind(lock_hash)
+        .fetch_optional(&*self.db)
+        .await?;
+
+        row.map(|(build_id, checks_json)| {
+            let checks = checks_json
+                .map(|json| serde_json::from_str(&json))
+                .transpose()
+                .map_err(|source| DatabaseError::InvalidData {
+                    context: "failed to deserialize cached flake checks",
+                    source,
+                })?;
+            Ok(CachedEval { build_id, checks })
+        })
+        .transpose()
+    }
+
+    /// Cache the passing evaluation of `commit` by build `build_id`. The
+    /// entries of `branch` with another `flake.lock` are dropped: its inputs
+    /// changed, so they won't be built again.
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be updated
+    pub async fn cache_eval(
+        &self,
+        repo_path: &str,
+        branch: &str,
+        commit: &str,
+        lock_hash: &str,
+        build_id: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            r"
+            INSERT INTO eval_cache (repo_path, commit_hash, lock_hash, branch, build_id)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT DO NOTHING
+            ",
+        )
+        .bind(repo_path)
+        .bind(commit)
+        .bind(lock_hash)
+        .bind(branch)
+        .bind(build_id)
+        .execute(&*self.db)
+        .await?;
+
+        sqlx::query("DELETE FROM eval_cache WHERE repo_path = ? AND branch = ? AND lock_hash != ?")
+            .bind(repo_path)
+            .bind(branch)
+            .bind(lock_hash)
+            .execute(&*self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Add the checks listed for `commit` to its cached evaluation
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be updated
+    pub async fn set_eval_checks(
+        &self,
+        repo_path: &str,
+        commit: &str,
+        lock_hash: &str,
+        checks: &FlakeChecks,
+    ) -> Result<()> {
+        let checks_json =
+            serde_json::to_string(checks).map_err(|source| DatabaseError::InvalidData {
+                context: "failed to serialize flake checks",
+                source,
+            })?;
+
+        sqlx::query(
+            "UPDATE eval_cache SET checks_json = ? WHERE repo_path = ? AND commit_hash = ? AND lock_hash = ?",
+        )
+        .bind(&checks_json)
+        .bind(repo_path)
+        .bind(commit)
+        .bind(lock_hash)
+        .execute(&*self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Store the SBOM of every repo of the build's commit, replacing the
+    /// ones of a previous attempt
+    pub async fn set_sboms(&self, id: i64, sboms: &[autonix::Sbom]) -> Result<()> {
+        sqlx::query("DELETE FROM build_sboms WHERE build_id = ?")
+            .bind(id)
+            .execute(&*self.db)
+            .await?;
+
+        for sbom in sboms {
+            let licenses_json = serde_json::to_string(sbom.licenses()).map_err(|source| {
+                DatabaseError::InvalidData {
+                    context: "failed to serialize license summary",
+                    source,
+                }
+            })?;
+            let sbom_json =
+                sbom.to_cyclonedx_json()
+                    .map_err(|source| DatabaseError::InvalidData {
+                        context: "failed to serialize SBOM",
+                        source,
+                    })?;
+            sqlx::query(
+                r#"
+                INSERT INTO build_sboms (build_id, repo, licenses_json, sbom_json)
+                VALUES (?, ?, ?, ?)
+                "#,
+            )
+            .bind(id)
+            .bind(sbom.repo())
+            .bind(&licenses_json)
+            .bind(&sbom_json)
+            .execute(&*self.db)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// SBOMs stored for a build, by repo
+    pub async fn get_sboms(&self, id: i64) -> Result<Vec<BuildSbom>> {
+        let rows = sqlx::query_as::<_, (String, String, String)>(
+            "SELECT repo, licenses_json, sbom_json FROM build_sboms \
+             WHERE build_id = ? ORDER BY repo",
+        )
+        .bind(id)
+        .fetch_all(&*self.db)
+        .await?;
+
+        rows.into_iter()
+            .map(|(repo, licenses, sbom)| {
+                let invalid = |source| DatabaseError::InvalidData {
+                    context: "failed to deserialize SBOM",
+                    source,
+                };
+                Ok(BuildSbom {
+                    repo,
+                    licenses: serde_json::from_str(&licenses).map_err(invalid)?,
+                    sbom: serde_json::from_str(&sbom).map_err(invalid)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Store the findings of the build's vulnerability scan, replacing the
+    /// ones of a previous attempt
+    pub async fn set_findings(&self, id: i64, findings: &[Finding]) -> Result<()> {
+        sqlx::query("DELETE FROM build_findings WHERE build_id = ?")
+            .bind(id)
+            .execute(&*self.db)
+            .await?;
+
+        for finding in findings {
+            let aliases_json = serde_json::to_string(&finding.aliases).map_err(|source| {
+                DatabaseError::InvalidData {
+                    context: "failed to serialize advisory aliases",
+                    source,
+                }
+            })?;
+            sqlx::query(
+                r#"
+                INSERT INTO build_findings
+                    (build_id, repo, ecosystem, package, version, advisory, aliases_json, severity, summary, fixed)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(id)
+            .bind(&finding.repo)
+            .bind(&finding.ecosystem)
+            .bind(&finding.package)
+            .bind(&finding.version)
+            .bind(&finding.advisory)
+            .bind(&aliases_json)
+            .bind(finding.severity.as_str())
+            .bind(&finding.summary)
+            .bind(&finding.fixed)
+            .execute(&*self.db)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Findings of a build's vulnerability scan, by repo and package
+    pub async fn get_findings(&self, id: i64) -> Result<Vec<Finding>> {
+        let rows = sqlx::query_as::<_, FindingRow>(
+            r#"
+            SELECT f.build_id, b.repo_path, b.commit_hash, f.repo, f.ecosystem, f.package,
+                   f.version, f.advisory, f.aliases_json, f.severity, f.summary, f.fixed
+            FROM build_findings f JOIN builds b ON b.id = f.build_id
+            WHERE f.build_id = ?
+            ORDER BY f.repo, f.package, f.version, f.advisory
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&*self.db)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| row.into_build_finding().map(|f| f.finding))
+            .collect()
+    }
+
+    /// Latest findings across builds, optionally of one advisory (by id or
+    /// alias, e.g. a CVE), package or repository path prefix
+    pub async fn search_findings(
+        &self,
+        advisory: Option<&str>,
+        package: Option<&str>,
+        repo_path_prefix: Option<&str>,
+        repos: Option<&[&str]>,
+        limit: i64,
+    ) -> Result<Vec<BuildFinding>> {
+        let rows = sqlx::query_as::<_, FindingRow>(&format!(
+            r#"
+            SELECT f.build_id, b.repo_path, b.commit_hash, f.repo, f.ecosystem, f.package,
+                   f.version, f.advisory, f.aliases_json, f.severity, f.summary, f.fixed
+            FROM build_findings f JOIN builds b ON b.id = f.build_id
+            WHERE (?1 IS NULL OR f.advisory = ?1
+                   OR EXISTS (SELECT 1 FROM json_each(f.aliases_json) WHERE value = ?1))
+              AND (?2 IS NULL OR f.package = ?2)
+              AND (?3 IS NULL OR b.repo_path LIKE ?3)
+              AND {}
+            ORDER BY b.created_at DESC, f.repo, f.package, f.advisory
+            LIMIT ?5
+            "#,
+            in_repos(4)
+        ))
+        .bind(advisory)
+        .bind(package)
+        .bind(repo_path_prefix.map(|prefix| format!("{prefix}%")))
+        .bind(repos_json(repos))
+        .bind(limit)
+        .fetch_all(&*self.db)
+        .await?;
+
+        rows.into_iter()
+            .map(FindingRow::into_build_finding)
+            .collect()
+    }
+}
+
+/// A row of `build_findings`, with the repository and commit of its build
+#[derive(sqlx::FromRow)]
+struct FindingRow {
+    build_id: i64,
+    repo_path: String,
+    commit_hash: String,
+    repo: String,
+    ecosystem: String,
+    package: String,
+    version: String,
+    advisory: String,
+    aliases_json: String,
+    severity: String,
+    summary: String,
+    fixed: Option<String>,
+}
+
+impl FindingRow {
+    fn into_build_finding(self) -> Result<BuildFinding> {
+        let aliases = serde_json::from_str(&self.aliases_json).map_err(|source| {
+            DatabaseError::InvalidData {
+                context: "failed to deserialize advisory aliases",
+                source,
+            }
+        })?;
+        Ok(BuildFinding {
+            build_id: self.build_id,
+            repo_path: self.repo_path,
+            commit_hash: self.commit_hash,
+            finding: Finding {
+                repo: self.repo,
+                ecosystem: self.ecosystem,
+                package: self.package,
+                version: self.version,
+                advisory: self.advisory,
+                aliases,
+                severity: self.severity.parse().unwrap_or(Severity::Unknown),
+                summary: self.summary,
+                fixed: self.fixed,
+            },
+        })
+    }
+}
+
+/// Builds stored before step timings were recorded hold `null`
+fn parse_summary(json: &str) -> Result<Option<BuildSummary>> {
+    serde_json::from_str(json).map_err(|source| DatabaseError::InvalidData {
+        context: "failed to deserialize build summary",
+        source,
+    })
+}
+
+/// Condition on `b.repo_path` keeping the builds of the repositories in
+/// the JSON array bound at `?param`, every build when it is NULL. A
+/// repository is a bare repository's path or its path under the repos
+/// directory without `.git`.
+fn in_repos(param: u8) -> String {
+    format!(
+        "(?{param} IS NULL OR EXISTS (SELECT 1 FROM json_each(?{param}) \
+         WHERE b.repo_path = value OR substr(b.repo_path, -length(value) - 5) = '/' || value || '.git'))"
+    )
+}
+
+fn repos_json(repos: Option<&[&str]>) -> Option<String> {
+    repos.map(|repos| serde_json::Value::from(repos).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn findings_are_searchable_by_alias() {
+        let path = std::env::temp_dir().join(format!("ci-findings-{}.db", std::process::id()));
+        let queue = JobQueue::new(
+            Database::new(&format!("sqlite://{}", path.display()))
+                .await
+                .unwrap(),
+        );
+        let id = queue
+            .enqueue("/srv/git/api.git", "abc123", "main", None)
+            .await
+            .unwrap();
+        let finding = Finding {
+            repo: "api".to_string(),
+            ecosystem: "crates.io".to_string(),
+            package: "parser".to_string(),
+            version: "0.4.1".to_string(),
+            advisory: "RUSTSEC-2024-0001".to_string(),
+            aliases: vec!["CVE-2024-1234".to_string()],
+            severity: Severity::High,
+            summary: "Overflow in parser".to_string(),
+            fixed: Some("0.4.2".to_string()),
+        };
+        queue.set_findings(id, &[finding.clone()]).await.unwrap();
+
+        assert_eq!(queue.get_findings(id).await.unwrap(), [finding.clone()]);
+        let found = queue
+            .search_findings(Some("CVE-2024-1234"), None, Some("/srv/git"), None, 10)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            (found[0].build_id, found[0].commit_hash.as_str()),
+            (id, "abc123")
+        );
+        assert_eq!(found[0].finding, finding);
+        assert!(queue
+            .search_findings(None, Some("serde"), None, None, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn evaluations_are_cached_per_commit_and_lock() {
+        let path = std::env::temp_dir().join(format!("ci-eval-cache-{}.db", std::process::id()));
+        let queue = JobQueue::new(
+            Database::new(&format!("sqlite://{}", path.display()))
+                .await
+                .unwrap(),
+        );
+        let repo = "/srv/git/api.git";
+        let id = queue.enqueue(repo, "abc123", "main", None).await.unwrap();
+        assert_eq!(
+            queue.cached_eval(repo, "abc123", "lock1").await.unwrap(),
+            None
+        );
+
+        queue
+            .cache_eval(repo, "main", "abc123", "lock1", id)
+            .await
+            .unwrap();
+        let checks: FlakeChecks =
+            serde_json::from_str(r#"{"system":"x86_64-linux","names":["api-test"]}"#).unwrap();
+        queue
+            .set_eval_checks(repo, "abc123", "lock1", &checks)
+            .await
+            .unwrap();
+        assert_eq!(
+            queue.cached_eval(repo, "abc123", "lock1").await.unwrap(),
+            Some(CachedEval {
+                build_id: id,
+                checks: Some(checks)
+            })
+        );
+        assert_eq!(
+            queue.cached_eval(repo, "abc123", "lock2").await.unwrap(),
+            None
+        );
+
+        // A new flake.lock on the branch drops the evaluations of the old one
+        queue
+            .cache_eval(repo, "main", "def456", "lock2", id)
+            .await
+            .unwrap();
+        assert_eq!(
+            queue.cached_eval(repo, "abc123", "lock1").await.unwrap(),
+            None
+        );
+        let hits: i64 = sqlx::query_scalar("SELECT hits FROM eval_cache")
+            .fetch_one(&*queue.db)
+            .await
+            .unwrap();
+        assert_eq!(hits, 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn timings_cover_the_window_and_the_queue() {
+        let path = std::env::temp_dir().join(format!("ci-timings-{}.db", std::process::id()));
+        let queue = JobQueue::new(
+            Database::new(&format!("sqlite://{}", path.display()))
+                .await
+                .unwrap(),
+        );
+        let old = queue
+            .enqueue("/srv/git/api.git", "abc123", "main", None)
+            .await
+            .unwrap();
+        let waiting = queue
+            .enqueue("/srv/git/api.git", "def456", "main", None)
+            .await
+            .unwrap();
+        let recent = queue
+            .enqueue("/srv/git/docs.git", "789abc", "main", None)
+            .await
+            .unwrap();
+        for (id, created_at) in [
+            (old, "2020-01-01 00:00:00"),
+            (waiting, "2020-01-01 00:00:00"),
+        ] {
+            sqlx::query("UPDATE builds SET created_at = ? WHERE id = ?")
+                .bind(created_at)
+                .bind(id)
+                .execute(&*queue.db)
+                .await
+                .unwrap();
+        }
+        queue.update_status(old, BuildStatus::Failed).await.unwrap();
+        queue
+            .update_status(recent, BuildStatus::Running)
+            .await
+            .unwrap();
+
+        let since = chrono::Utc::now() - chrono::Duration::hours(1);
+        let timings = queue.build_timings(since, None).await.unwrap();
+        let listed: Vec<(&str, &str)> = timings
+            .iter()
+            .map(|t| (t.repo_path.as_str(), t.status.as_str()))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("/srv/git/api.git", "queued"),
+                ("/srv/git/docs.git", "running")
+            ]
+        );
+        assert!(timings[1].started_at.is_some());
+        assert!(queue
+            .build_timings(since, Some(&["docs"]))
+            .await
+            .unwrap()
+            .iter()
+            .all(|t| t.repo_path == "/srv/git/docs.git"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn builds_are_listed_per_repository_and_canceled_while_queued() {
+        let path = std::env::temp_dir().join(format!("ci-access-{}.db", std::process::id()));
+        let queue = JobQueue::new(
+            Database::new(&format!("sqlite://{}", path.display()))
+                .await
+                .unwrap(),
+        );
+        let api = queue
+            .enqueue("/srv/git/lucas/api.git", "abc123", "main", None)
+            .await
+            .unwrap();
+        let docs = queue
+            .enqueue("/srv/git/lucas/docs.git", "def456", "main", Some("abc123"))
+            .await
+            .unwrap();
+        queue
+            .enqueue("/srv/git/lucas/api-v2.git", "789abc", "main", None)
+            .await
+            .unwrap();
+
+        let listed = |builds: Vec<BuildJob>| builds.iter().map(BuildJob::id).collect::<Vec<_>>();
+        assert_eq!(
+            listed(queue.list_all_builds(Some(&["lucas/api"])).await.unwrap()),
+            [api]
+        );
+        assert_eq!(queue.list_all_builds(None).await.unwrap().len(), 3);
+        assert!(queue.list_all_builds(Some(&[])).await.unwrap().is_empty());
+        assert_eq!(
+            queue.get_build(docs).await.unwrap().base_commit(),
+            Some("abc123")
+        );
+
+        assert!(queue.cancel(api).await.unwrap());
+        let canceled = queue.get_build(api).await.unwrap();
+        assert!(canceled.finished());
+        assert_eq!(
+            canceled.stage_status(Stage::Eval),
+            Some(BuildStatus::Canceled)
+        );
+        queue
+            .update_status(docs, BuildStatus::Running)
+            .await
+            .unwrap();
+        assert!(!queue.cancel(docs).await.unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_passing_builds_are_diffed_against() {
+        let path = std::env::temp_dir().join(format!("ci-passing-{}.db", std::process::id()));
+        let queue = JobQueue::new(
+            Database::new(&format!("sqlite://{}", path.display()))
+                .await
+                .unwrap(),
+        );
+        let repo = "/srv/git/lucas/api.git";
+        assert_eq!(queue.last_passing_commit(repo, "main").await.unwrap(), None);
+
+        let passed = queue.enqueue(repo, "aaa111", "main", None).await.unwrap();
+        queue
+            .update_status(passed, BuildStatus::Success)
+            .await
+            .unwrap();
+        let failed = queue
+            .enqueue(repo, "bbb222", "main", Some("aaa111"))
+            .await
+            .unwrap();
+        queue
+            .update_status(failed, BuildStatus::Failed)
+            .await
+            .unwrap();
+        let other = queue.enqueue(repo, "ccc333", "dev", None).await.unwrap();
+        queue
+            .update_status(other, BuildStatus::Success)
+            .await
+            .unwrap();
+        queue.enqueue(repo, "ddd444", "main", None).await.unwrap();
+
+        let main = queue.last_passing_commit(repo, "main").await.unwrap();
+        assert_eq!(main.as_deref(), Some("aaa111"));
+        let dev = queue.last_passing_commit(repo, "dev").await.unwrap();
+        assert_eq!(dev.as_deref(), Some("ccc333"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
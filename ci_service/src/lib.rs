@@ -1,14 +1,28 @@
 mod api;
+mod auth;
 mod config;
 mod database;
+mod gate;
 mod job_queue;
+mod selection;
+mod settings;
+mod slo;
+mod steps;
 mod worker;
 mod builds;
+mod vulns;
 
-pub use config::Config;
+pub use config::{Config, StoreIsolation, VulnScan};
 pub use database::Database;
+pub use gate::{BranchRule, Gate, GatePolicy, GateState};
 pub use job_queue::JobQueue;
+pub use slo::Objectives;
+pub use vulns::{Finding, Severity};
 pub use worker::Worker;
 
 #[cfg(feature = "web")]
-pub use api::{AppState, routes};
+pub use api::{docs, pages, routes, ApiDoc, AppState, API_V1};
+#[cfg(feature = "web")]
+pub use auth::{Auth, Viewer, ACCESS_TTL, CSRF_COOKIE, CSRF_HEADER, TOKEN_COOKIE};
+#[cfg(feature = "web")]
+pub use settings::RepoSettings;
@@ -1,5 +1,8 @@
 use axum::{routing::get, Router};
-use ci_service::{routes, AppState, Config, Database, JobQueue, Worker};
+use ci_service::{
+    docs, pages, routes, AppState, Auth, Config, Database, JobQueue, RepoSettings, Worker, API_V1,
+};
+use repo_outils::nix::BuildPool;
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
@@ -11,7 +14,16 @@ async fn main() {
         )
         .init();
 
-    let config = Config::default();
+    let config = Config {
+        repohub_url: std::env::var("CI_REPOHUB_URL")
+            .ok()
+            .filter(|url| !url.is_empty()),
+        max_concurrent_builds: std::env::var("CI_MAX_CONCURRENT_BUILDS")
+            .ok()
+            .and_then(|max| max.parse().ok())
+            .unwrap_or(1),
+        ..Config::default()
+    };
 
     info!(
         database = config.database_url.as_str(),
@@ -26,15 +38,34 @@ async fn main() {
     // Initialize job queue and repository store
     let queue = JobQueue::new(database);
 
-    let worker = Worker::new(queue.clone());
-    let state = AppState::new(queue);
+    let mut worker = Worker::new(queue.clone())
+        .with_build_pool(BuildPool::new(config.max_concurrent_builds))
+        .with_retry(config.nix_retry);
+    if let Some(isolation) = config.store_isolation.clone() {
+        worker = worker.with_store_isolation(isolation);
+    }
+    if let Some(vuln_scan) = config.vuln_scan.clone() {
+        worker = worker.with_vuln_scan(vuln_scan);
+    }
+    let mut state = AppState::new(queue)
+        .with_gate_policy(config.gate.clone())
+        .with_objectives(config.objectives.clone());
+    if let Some(repohub_url) = &config.repohub_url {
+        info!(repohub_url, "Checking access and CI settings with repohub");
+        let invalid = "CI_REPOHUB_URL must be an http(s) URL";
+        state = state
+            .with_auth(Auth::new(repohub_url).expect(invalid))
+            .with_repo_settings(RepoSettings::new(repohub_url).expect(invalid));
+    }
 
     tokio::spawn(worker.run());
 
     info!(target: "ciService", "Build worker spawned");
 
     let app = Router::new()
-        .nest("/api", routes())
+        .nest(API_V1, routes())
+        .merge(docs())
+        .merge(pages())
         .route("/health", get(|| async { "OK" }))
         .with_state(state);
 
@@ -0,0 +1,203 @@
+//! Incremental check selection
+//!
+//! A push to a monorepo usually touches a few of its packages, yet
+//! `nix flake check` builds the checks of all of them. When a build knows
+//! the commit the push started from, the worker instead:
+//! - diffs the pushed commit against it, with `git diff --name-only`
+//! - maps the changed paths to packages with autonix's package map, a
+//!   package being affected by changes under its directory or under the
+//!   directory of a package it depends on
+//! - builds the flake's checks of affected packages, and every check that
+//!   doesn't belong to a package
+//!
+//! The skipped checks are stored in the build summary with the reason. A
+//! changed path outside every package (a root lockfile, the flake itself)
+//! may affect any of them, so then every check runs, as it does for new
+//! branches and pushes sent with `all`.
+
+use std::path::{Path, PathBuf};
+
+use repo_outils::nix::FlakeChecks;
+use serde::{Deserialize, Serialize};
+
+/// A flake check the build didn't run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct SkippedCheck {
+    /// Name under `checks.<system>`
+    pub check: String,
+    /// Package the check belongs to
+    pub package: String,
+    /// Why it was skipped, e.g. "no change under apps/web"
+    pub reason: String,
+}
+
+/// A check of a package none of the changed paths affects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unaffected {
+    pub check: String,
+    pub package: String,
+    /// Directories of the package and the packages it depends on, relative
+    /// to the repository
+    pub paths: Vec<String>,
+}
+
+/// The checks of a flake to build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub run: Vec<String>,
+    pub skipped: Vec<SkippedCheck>,
+}
+
+/// Checks that the files changed between `base` and `commit` in the bare
+/// repository don't affect, by exporting `commit` to `export` and mapping
+/// its packages with autonix. Blocking.
+///
+/// # Errors
+///
+/// - if the commits can't be diffed or `commit` exported
+pub fn unaffected(
+    bare_path: &Path,
+    base: &str,
+    commit: &str,
+    export: &Path,
+) -> Result<Vec<Unaffected>, repo_outils::git::RepoError> {
+    let changed = repo_outils::git::changed_paths(bare_path, base, commit)?;
+    repo_outils::git::export_tree(bare_path, commit, export)?;
+
+    let map = autonix::Parser::from(export.to_path_buf())
+        .scan()
+        .analyse()
+        .package_map();
+    let changed: Vec<PathBuf> = changed.iter().map(|path| export.join(path)).collect();
+    Ok(map
+        .unaffected(&changed)
+        .into_iter()
+        .map(|check| Unaffected {
+            check: check.check().to_string(),
+            package: check.package().to_string(),
+            paths: check
+                .paths()
+                .iter()
+                .map(|dir| {
+                    dir.strip_prefix(export)
+                        .unwrap_or(dir)
+                        .display()
+                        .to_string()
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+/// Split the checks the flake defines into those to build and those to skip
+/// as `unaffected`.
+pub fn select(checks: &FlakeChecks, unaffected: &[Unaffected]) -> Selection {
+    let mut selection = Selection {
+        run: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for name in checks.names() {
+        match unaffected.iter().find(|u| &u.check == name) {
+            Some(u) => selection.skipped.push(SkippedCheck {
+                check: name.clone(),
+                package: u.package.clone(),
+                reason: format!("no change under {}", u.paths.join(", ")),
+            }),
+            None => selection.run.push(name.clone()),
+        }
+    }
+    selection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unaffected_check(check: &str, package: &str, paths: &[&str]) -> Unaffected {
+        Unaffected {
+            check: check.to_string(),
+            package: package.to_string(),
+            paths: paths.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn checks_of_unaffected_packages_are_skipped() {
+        let checks: FlakeChecks = serde_json::from_str(
+            r#"{"system":"x86_64-linux","names":["acme-ui-build","format","web-build"]}"#,
+        )
+        .unwrap();
+        let selection = select(
+            &checks,
+            &[
+                unaffected_check("acme-ui-build", "@acme/ui", &["packages/ui"]),
+                // Known to autonix but not defined by the flake
+                unaffected_check("acme-ui-lint", "@acme/ui", &["packages/ui"]),
+            ],
+        );
+        assert_eq!(selection.run, ["format", "web-build"]);
+        assert_eq!(
+            selection.skipped,
+            [SkippedCheck {
+                check: "acme-ui-build".to_string(),
+                package: "@acme/ui".to_string(),
+                reason: "no change under packages/ui".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn changes_are_mapped_to_the_packages_of_the_commit() {
+        let root = std::env::temp_dir().join(format!("ci-selection-{}", std::process::id()));
+        let work = root.join("work");
+        let bare = root.join("repo.git");
+        let write = |path: &str, content: &str| {
+            let path = work.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        write(
+            "package.json",
+            r#"{"name":"shop","private":true,"workspaces":["apps/*","packages/*"]}"#,
+        );
+        write(
+            "turbo.json",
+            r#"{"tasks":{"build":{"dependsOn":["^build"]}}}"#,
+        );
+        write(
+            "apps/web/package.json",
+            r#"{"name":"web","scripts":{"build":"vite build"},"dependencies":{"ui":"*"}}"#,
+        );
+        write(
+            "packages/ui/package.json",
+            r#"{"name":"ui","scripts":{"build":"tsc"}}"#,
+        );
+        write("apps/web/index.ts", "export {};\n");
+
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .arg("-C")
+                .arg(&work)
+                .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{output:?}");
+        };
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&["commit", "-qm", "init"]);
+        write("apps/web/index.ts", "export const a = 1;\n");
+        git(&["commit", "-qam", "web"]);
+        git(&["clone", "-q", "--bare", ".", bare.to_str().unwrap()]);
+
+        let unaffected = unaffected(&bare, "HEAD~1", "HEAD", &root.join("export")).unwrap();
+        assert_eq!(
+            unaffected,
+            [unaffected_check("ui-build", "ui", &["packages/ui"])]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
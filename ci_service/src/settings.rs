@@ -0,0 +1,131 @@
+//! CI settings of repositories, kept in repohub
+//!
+//! With `Config::repohub_url` set, every push is checked against the
+//! settings of its repository (see repohub's `GET /api/v1/repos/{owner}/{repo}/ci`)
+//! before a build is enqueued: a repository with CI disabled, or a branch
+//! that is neither its default branch nor one of its monitored branches, is
+//! not built. When the settings list required checks, they replace
+//! `Config::gate` for the repository's gates, on every built branch.
+//!
+//! Repohub being unreachable doesn't stop the CI: pushes are built, and
+//! gates use `Config::gate`, as without settings.
+
+use std::time::Duration;
+
+use repohub_client::{CiSettings, Client, ClientError};
+
+use crate::{
+    builds::Stage,
+    gate::{BranchRule, GatePolicy},
+};
+
+/// Longest wait for repohub before going on without the settings
+const REPOHUB_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads repository settings from repohub.
+#[derive(Clone)]
+pub struct RepoSettings {
+    repohub: Client,
+}
+
+impl RepoSettings {
+    /// Read settings from the repohub served at `repohub_url`.
+    ///
+    /// # Errors
+    ///
+    /// - if `repohub_url` is not an absolute http(s) URL
+    pub fn new(repohub_url: &str) -> Result<Self, ClientError> {
+        Ok(Self {
+            repohub: Client::new(repohub_url)?,
+        })
+    }
+
+    /// Settings of the repository at `repo_path`, a bare repository's path
+    /// or its path under the repos directory. The defaults when repohub
+    /// doesn't know the repository.
+    pub(crate) async fn get(&self, repo_path: &str) -> Result<CiSettings, String> {
+        let Some((owner, repo)) = owner_and_name(repo_path) else {
+            return Ok(CiSettings::default());
+        };
+        match tokio::time::timeout(REPOHUB_TIMEOUT, self.repohub.ci_settings(owner, repo)).await {
+            Ok(Ok(settings)) => Ok(settings),
+            Ok(Err(e)) if e.is_not_found() => Ok(CiSettings::default()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("repohub did not answer in time".to_string()),
+        }
+    }
+}
+
+/// Owner and name of the repository at `repo_path`, e.g. `lucas` and `api`
+/// for `/var/lib/git-server/lucas/api.git` or `lucas/api`.
+fn owner_and_name(repo_path: &str) -> Option<(&str, &str)> {
+    let path = repo_path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let (parent, name) = path.rsplit_once('/')?;
+    let owner = parent.rsplit('/').next()?;
+    (!owner.is_empty() && !name.is_empty()).then_some((owner, name))
+}
+
+/// Gate policy of a repository whose settings require checks, `None` when
+/// `Config::gate` applies.
+pub(crate) fn gate_policy(settings: &CiSettings) -> Option<GatePolicy> {
+    if settings.required_checks.is_empty() {
+        return None;
+    }
+    let required: Vec<Stage> = settings
+        .required_checks
+        .iter()
+        .filter_map(|check| {
+            check
+                .parse()
+                .inspect_err(|e| {
+                    tracing::warn!(check, error = e, "Ignoring unknown required check");
+                })
+                .ok()
+        })
+        .collect();
+    Some(GatePolicy {
+        rules: settings
+            .monitored()
+            .into_iter()
+            .map(|branch| BranchRule {
+                branch: branch.to_string(),
+                required: required.clone(),
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repositories_are_named_by_owner_and_name() {
+        assert_eq!(
+            owner_and_name("/var/lib/git-server/lucas/api.git"),
+            Some(("lucas", "api"))
+        );
+        assert_eq!(owner_and_name("lucas/api"), Some(("lucas", "api")));
+        assert_eq!(owner_and_name("api.git"), None);
+    }
+
+    #[test]
+    fn required_checks_protect_the_built_branches() {
+        assert!(gate_policy(&CiSettings::default()).is_none());
+
+        let policy = gate_policy(&CiSettings {
+            default_branch: Some("main".to_string()),
+            branches: vec!["release/*".to_string()],
+            required_checks: vec!["scan".to_string(), "build".to_string()],
+            ..CiSettings::default()
+        })
+        .unwrap();
+        assert_eq!(
+            policy.required("main"),
+            Some([Stage::Scan, Stage::Build].as_slice())
+        );
+        assert!(policy.required("release/2").is_some());
+        assert_eq!(policy.required("feature/x"), None);
+    }
+}
@@ -0,0 +1,776 @@
+//! Queue and build SLOs
+//!
+//! How long builds wait in the queue, how long they take and how often they
+//! pass, per repository, over the builds enqueued in a recent window. Every
+//! figure is computed from the `builds` table when asked for, so it covers
+//! restarts and is the same whichever of `/metrics`, the JSON stats API or
+//! the trends page shows it.
+//!
+//! The queue wait of a build runs from its enqueuing to the worker picking
+//! it up, its duration from then until it finished. Canceled builds count
+//! for neither the success rate nor the duration. A [`Report`] also tells
+//! whether each of the [`Objectives`] is met over the window.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+
+use crate::builds::BuildStatus;
+use crate::steps::{escape, percentile};
+
+/// Targets the CI is held to, over the builds of a window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Objectives {
+    /// 95% of builds start within it
+    pub queue_wait_p95: Duration,
+    /// 95% of builds finish within it once started
+    pub duration_p95: Duration,
+    /// Share of the finished builds that pass, between 0 and 1
+    pub success_rate: f64,
+}
+
+impl Default for Objectives {
+    fn default() -> Self {
+        Self {
+            queue_wait_p95: Duration::from_mins(5),
+            duration_p95: Duration::from_mins(30),
+            success_rate: 0.9,
+        }
+    }
+}
+
+/// Timestamps of one build, as stored in `builds`
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct BuildTiming {
+    pub repo_path: String,
+    pub status: String,
+    /// `CURRENT_TIMESTAMP`, in UTC
+    pub created_at: String,
+    /// RFC 3339
+    pub started_at: Option<String>,
+    /// RFC 3339
+    pub finished_at: Option<String>,
+    /// `hit` or `miss` once the eval cache was looked up
+    pub eval_cache: Option<String>,
+}
+
+/// Distribution of a duration, in milliseconds; percentiles are `0` when
+/// there is no sample.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct Percentiles {
+    pub count: usize,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub p99_ms: u64,
+}
+
+impl Percentiles {
+    fn of(mut samples: Vec<u64>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort_unstable();
+        Self {
+            count: samples.len(),
+            p50_ms: percentile(&samples, 50),
+            p95_ms: percentile(&samples, 95),
+            p99_ms: percentile(&samples, 99),
+        }
+    }
+}
+
+/// Builds of one repository, or of all of them, over the window
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct RepoStats {
+    /// Path of the bare repository, empty for every repository
+    pub repo: String,
+    pub builds: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub canceled: usize,
+    /// Share of the succeeded and failed builds that succeeded, `None`
+    /// before any finished
+    pub success_rate: Option<f64>,
+    pub queue_wait: Percentiles,
+    pub duration: Percentiles,
+    /// Builds that skipped evaluating as the eval cache had their commit
+    /// and `flake.lock`
+    pub eval_cache_hits: usize,
+    /// Builds that evaluated, their commit and `flake.lock` not cached
+    pub eval_cache_misses: usize,
+}
+
+/// Builds enqueued on one UTC day
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct DayStats {
+    /// `YYYY-MM-DD`
+    pub day: String,
+    pub builds: usize,
+    pub success_rate: Option<f64>,
+    pub queue_wait_p95_ms: u64,
+    pub duration_p95_ms: u64,
+}
+
+/// One of the [`Objectives`], against what the window shows
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct ObjectiveStatus {
+    /// `queue_wait_p95`, `duration_p95` or `success_rate`
+    pub name: &'static str,
+    /// Milliseconds, or a ratio for `success_rate`
+    pub target: f64,
+    /// `None` without builds to tell
+    pub actual: Option<f64>,
+    /// Objectives without builds to tell count as met
+    pub met: bool,
+}
+
+/// Everything `/metrics`, `GET /api/v1/stats` and `/stats` show
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct Report {
+    pub window_hours: u32,
+    /// Builds waiting for the worker now
+    pub queued: usize,
+    pub running: usize,
+    /// How long the oldest queued build has waited
+    pub oldest_queued_ms: u64,
+    pub overall: RepoStats,
+    /// Repositories with builds in the window, by path
+    pub repos: Vec<RepoStats>,
+    /// Oldest day first
+    pub days: Vec<DayStats>,
+    pub objectives: Vec<ObjectiveStatus>,
+}
+
+/// One build's timing, parsed
+struct Parsed<'a> {
+    repo: &'a str,
+    status: Option<BuildStatus>,
+    created: DateTime<Utc>,
+    wait_ms: Option<u64>,
+    duration_ms: Option<u64>,
+    eval_cache: Option<&'a str>,
+}
+
+impl<'a> Parsed<'a> {
+    fn new(timing: &'a BuildTiming) -> Option<Self> {
+        let created = parse_time(&timing.created_at)?;
+        let started = timing.started_at.as_deref().and_then(parse_time);
+        let finished = timing.finished_at.as_deref().and_then(parse_time);
+        let status = timing.status.parse().ok();
+        let ran = matches!(status, Some(BuildStatus::Success | BuildStatus::Failed));
+        Some(Self {
+            repo: &timing.repo_path,
+            status,
+            created,
+            wait_ms: started.map(|s| millis_between(created, s)),
+            duration_ms: started
+                .zip(finished)
+                .filter(|_| ran)
+                .map(|(s, f)| millis_between(s, f)),
+            eval_cache: timing.eval_cache.as_deref(),
+        })
+    }
+}
+
+/// Aggregate `timings`, the builds enqueued in the last `window_hours` and
+/// those still queued or running, at `now`.
+pub fn report(
+    timings: &[BuildTiming],
+    objectives: &Objectives,
+    window_hours: u32,
+    now: DateTime<Utc>,
+) -> Report {
+    let builds: Vec<Parsed> = timings.iter().filter_map(Parsed::new).collect();
+
+    let queued: Vec<&Parsed> = builds
+        .iter()
+        .filter(|b| b.status == Some(BuildStatus::Queued))
+        .collect();
+    let oldest_queued_ms = queued
+        .iter()
+        .map(|b| millis_between(b.created, now))
+        .max()
+        .unwrap_or_default();
+
+    let mut per_repo: BTreeMap<&str, Vec<&Parsed>> = BTreeMap::new();
+    let mut per_day: BTreeMap<String, Vec<&Parsed>> = BTreeMap::new();
+    for build in &builds {
+        per_repo.entry(build.repo).or_default().push(build);
+        per_day
+            .entry(build.created.format("%Y-%m-%d").to_string())
+            .or_default()
+            .push(build);
+    }
+
+    let overall = stats("", &builds.iter().collect::<Vec<_>>());
+    let objectives = vec![
+        objective(
+            "queue_wait_p95",
+            millis(objectives.queue_wait_p95),
+            (overall.queue_wait.count > 0).then_some(overall.queue_wait.p95_ms),
+        ),
+        objective(
+            "duration_p95",
+            millis(objectives.duration_p95),
+            (overall.duration.count > 0).then_some(overall.duration.p95_ms),
+        ),
+        ObjectiveStatus {
+            name: "success_rate",
+            target: objectives.success_rate,
+            actual: overall.success_rate,
+            met: overall
+                .success_rate
+                .is_none_or(|rate| rate >= objectives.success_rate),
+        },
+    ];
+
+    Report {
+        window_hours,
+        queued: queued.len(),
+        running: builds
+            .iter()
+            .filter(|b| b.status == Some(BuildStatus::Running))
+            .count(),
+        oldest_queued_ms,
+        repos: per_repo
+            .iter()
+            .map(|(repo, builds)| stats(repo, builds))
+            .collect(),
+        days: per_day
+            .into_iter()
+            .map(|(day, builds)| {
+                let stats = stats("", &builds);
+                DayStats {
+                    day,
+                    builds: stats.builds,
+                    success_rate: stats.success_rate,
+                    queue_wait_p95_ms: stats.queue_wait.p95_ms,
+                    duration_p95_ms: stats.duration.p95_ms,
+                }
+            })
+            .collect(),
+        overall,
+        objectives,
+    }
+}
+
+fn stats(repo: &str, builds: &[&Parsed]) -> RepoStats {
+    let count = |status: BuildStatus| {
+        builds
+            .iter()
+            .filter(|b| b.status.as_ref() == Some(&status))
+            .count()
+    };
+    let succeeded = count(BuildStatus::Success);
+    let failed = count(BuildStatus::Failed);
+    #[allow(clippy::cast_precision_loss)]
+    let success_rate =
+        (succeeded + failed > 0).then(|| succeeded as f64 / (succeeded + failed) as f64);
+    RepoStats {
+        repo: repo.to_string(),
+        builds: builds.len(),
+        succeeded,
+        failed,
+        canceled: count(BuildStatus::Canceled),
+        success_rate,
+        queue_wait: Percentiles::of(builds.iter().filter_map(|b| b.wait_ms).collect()),
+        duration: Percentiles::of(builds.iter().filter_map(|b| b.duration_ms).collect()),
+        eval_cache_hits: builds
+            .iter()
+            .filter(|b| b.eval_cache == Some("hit"))
+            .count(),
+        eval_cache_misses: builds
+            .iter()
+            .filter(|b| b.eval_cache == Some("miss"))
+            .count(),
+    }
+}
+
+/// A duration objective, met when `actual` is within `target`.
+#[allow(clippy::cast_precision_loss)]
+fn objective(name: &'static str, target: u64, actual: Option<u64>) -> ObjectiveStatus {
+    ObjectiveStatus {
+        name,
+        target: target as f64,
+        actual: actual.map(|a| a as f64),
+        met: actual.is_none_or(|a| a <= target),
+    }
+}
+
+/// `CURRENT_TIMESTAMP` (`2024-05-01 10:00:00`) or RFC 3339.
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").map(|t| t.and_utc()))
+        .ok()
+}
+
+fn millis_between(from: DateTime<Utc>, to: DateTime<Utc>) -> u64 {
+    u64::try_from((to - from).num_milliseconds()).unwrap_or_default()
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+// ─── Prometheus ────────────────────────────────────────────────────────────
+
+/// `report` in the Prometheus text format. Counts are over the window, so
+/// they are gauges rather than counters.
+pub fn render_metrics(report: &Report) -> String {
+    let mut out = String::new();
+    let window = format!("over the last {} hours", report.window_hours);
+
+    gauge(
+        &mut out,
+        "ci_builds_queued",
+        "Builds waiting for the worker",
+    );
+    let _ = writeln!(out, "ci_builds_queued {}", report.queued);
+    gauge(&mut out, "ci_builds_running", "Builds being built");
+    let _ = writeln!(out, "ci_builds_running {}", report.running);
+    gauge(
+        &mut out,
+        "ci_queue_oldest_wait_seconds",
+        "How long the oldest queued build has waited",
+    );
+    let _ = writeln!(
+        out,
+        "ci_queue_oldest_wait_seconds {}",
+        seconds(report.oldest_queued_ms)
+    );
+
+    gauge(&mut out, "ci_builds", &format!("Builds enqueued {window}"));
+    for repo in &report.repos {
+        for (status, count) in [
+            ("success", repo.succeeded),
+            ("failed", repo.failed),
+            ("canceled", repo.canceled),
+        ] {
+            let _ = writeln!(
+                out,
+                "ci_builds{{repo=\"{}\",status=\"{status}\"}} {count}",
+                label(&repo.repo)
+            );
+        }
+    }
+
+    gauge(
+        &mut out,
+        "ci_build_success_ratio",
+        &format!("Share of the finished builds that passed {window}"),
+    );
+    for repo in &report.repos {
+        if let Some(rate) = repo.success_rate {
+            let _ = writeln!(
+                out,
+                "ci_build_success_ratio{{repo=\"{}\"}} {rate}",
+                label(&repo.repo)
+            );
+        }
+    }
+
+    for (name, help, pick) in [
+        (
+            "ci_queue_wait_seconds",
+            "Time from enqueuing to the worker picking the build up",
+            (|r: &RepoStats| &r.queue_wait) as fn(&RepoStats) -> &Percentiles,
+        ),
+        (
+            "ci_build_duration_seconds",
+            "Time from the start of a build to its end",
+            |r: &RepoStats| &r.duration,
+        ),
+    ] {
+        let _ = writeln!(out, "# HELP {name} {help}, {window}");
+        let _ = writeln!(out, "# TYPE {name} summary");
+        for repo in &report.repos {
+            let p = pick(repo);
+            let repo = label(&repo.repo);
+            for (quantile, ms) in [("0.5", p.p50_ms), ("0.95", p.p95_ms), ("0.99", p.p99_ms)] {
+                let _ = writeln!(
+                    out,
+                    "{name}{{repo=\"{repo}\",quantile=\"{quantile}\"}} {}",
+                    seconds(ms)
+                );
+            }
+            let _ = writeln!(out, "{name}_count{{repo=\"{repo}\"}} {}", p.count);
+        }
+    }
+
+    eval_cache_metrics(&mut out, report, &window);
+
+    gauge(
+        &mut out,
+        "ci_slo_met",
+        &format!("Whether the objective is met {window}"),
+    );
+    for objective in &report.objectives {
+        let _ = writeln!(
+            out,
+            "ci_slo_met{{objective=\"{}\"}} {}",
+            objective.name,
+            u8::from(objective.met)
+        );
+    }
+    out
+}
+
+/// Eval cache hits and misses per repository.
+fn eval_cache_metrics(out: &mut String, report: &Report, window: &str) {
+    gauge(
+        out,
+        "ci_eval_cache_lookups",
+        &format!("Eval cache lookups of the builds enqueued {window}"),
+    );
+    for repo in &report.repos {
+        for (result, count) in [
+            ("hit", repo.eval_cache_hits),
+            ("miss", repo.eval_cache_misses),
+        ] {
+            let _ = writeln!(
+                out,
+                "ci_eval_cache_lookups{{repo=\"{}\",result=\"{result}\"}} {count}",
+                label(&repo.repo)
+            );
+        }
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+}
+
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn seconds(ms: u64) -> f64 {
+    ms as f64 / 1000.0
+}
+
+// ─── Trends page ───────────────────────────────────────────────────────────
+
+/// Standalone HTML page with the objectives, a bar per day and a row per
+/// repository.
+pub fn render_trends(report: &Report) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>CI trends</title>\n\
+         <style>\n\
+         body {{ font-family: sans-serif; margin: 2em; }}\n\
+         table {{ border-collapse: collapse; margin-bottom: 2em; }}\n\
+         td, th {{ padding: 0.2em 0.8em; text-align: right; }}\n\
+         td:first-child, th:first-child {{ text-align: left; }}\n\
+         .bar {{ display: inline-block; height: 0.8em; background: #7cbf7c; }}\n\
+         .missed {{ color: #c0392b; }}\n\
+         </style></head><body>\n\
+         <h1>CI trends</h1>\n\
+         <p>{} builds over the last {} hours, {} queued, {} running</p>\n",
+        report.overall.builds, report.window_hours, report.queued, report.running
+    );
+
+    html.push_str(
+        "<h2>Objectives</h2>\n<table><tr><th>objective</th><th>target</th><th>actual</th></tr>\n",
+    );
+    for objective in &report.objectives {
+        let format = |value: f64| {
+            if objective.name == "success_rate" {
+                format!("{:.1}%", value * 100.0)
+            } else {
+                format!("{:.1} s", value / 1000.0)
+            }
+        };
+        let _ = writeln!(
+            html,
+            "<tr{}><td>{}</td><td>{}</td><td>{}</td></tr>",
+            if objective.met {
+                ""
+            } else {
+                " class=\"missed\""
+            },
+            objective.name,
+            format(objective.target),
+            objective.actual.map_or_else(|| "-".to_string(), format),
+        );
+    }
+    html.push_str("</table>\n");
+
+    let busiest = report
+        .days
+        .iter()
+        .map(|d| d.builds)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    html.push_str(
+        "<h2>Per day</h2>\n<table><tr><th>day</th><th>builds</th><th></th>\
+         <th>success</th><th>wait p95</th><th>duration p95</th></tr>\n",
+    );
+    for day in &report.days {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td style=\"width: 12em; text-align: left\">\
+             <span class=\"bar\" style=\"width: {}%\"></span></td>\
+             <td>{}</td><td>{}</td><td>{}</td></tr>",
+            day.day,
+            day.builds,
+            day.builds * 100 / busiest,
+            rate(day.success_rate),
+            duration(day.queue_wait_p95_ms),
+            duration(day.duration_p95_ms),
+        );
+    }
+    html.push_str("</table>\n");
+
+    html.push_str(
+        "<h2>Per repository</h2>\n<table><tr><th>repository</th><th>builds</th>\
+         <th>success</th><th>wait p50</th><th>wait p95</th><th>duration p50</th>\
+         <th>duration p95</th></tr>\n",
+    );
+    for repo in &report.repos {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&repo.repo),
+            repo.builds,
+            rate(repo.success_rate),
+            duration(repo.queue_wait.p50_ms),
+            duration(repo.queue_wait.p95_ms),
+            duration(repo.duration.p50_ms),
+            duration(repo.duration.p95_ms),
+        );
+    }
+    html.push_str("</table>\n</body></html>\n");
+    html
+}
+
+fn rate(rate: Option<f64>) -> String {
+    rate.map_or_else(|| "-".to_string(), |r| format!("{:.1}%", r * 100.0))
+}
+
+fn duration(ms: u64) -> String {
+    format!("{:.1} s", seconds(ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(
+        repo: &str,
+        status: &str,
+        created: &str,
+        wait_s: Option<i64>,
+        took_s: Option<i64>,
+    ) -> BuildTiming {
+        let created_at = NaiveDateTime::parse_from_str(created, "%Y-%m-%d %H:%M:%S")
+            .unwrap()
+            .and_utc();
+        let started = wait_s.map(|s| created_at + chrono::Duration::seconds(s));
+        BuildTiming {
+            repo_path: repo.to_string(),
+            status: status.to_string(),
+            created_at: created.to_string(),
+            started_at: started.map(|s| s.to_rfc3339()),
+            finished_at: started
+                .zip(took_s)
+                .map(|(s, took)| (s + chrono::Duration::seconds(took)).to_rfc3339()),
+            eval_cache: None,
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-02T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn builds_are_aggregated_per_repository_and_day() {
+        let timings = [
+            timing(
+                "/srv/git/api.git",
+                "success",
+                "2024-05-01 10:00:00",
+                Some(10),
+                Some(100),
+            ),
+            timing(
+                "/srv/git/api.git",
+                "failed",
+                "2024-05-01 11:00:00",
+                Some(30),
+                Some(50),
+            ),
+            timing(
+                "/srv/git/api.git",
+                "canceled",
+                "2024-05-02 09:00:00",
+                None,
+                None,
+            ),
+            timing(
+                "/srv/git/docs.git",
+                "success",
+                "2024-05-02 10:00:00",
+                Some(600),
+                Some(20),
+            ),
+            timing(
+                "/srv/git/docs.git",
+                "queued",
+                "2024-05-02 11:30:00",
+                None,
+                None,
+            ),
+        ];
+        let report = report(&timings, &Objectives::default(), 48, now());
+
+        assert_eq!((report.queued, report.running), (1, 0));
+        assert_eq!(report.oldest_queued_ms, 30 * 60 * 1000);
+
+        let api = &report.repos[0];
+        assert_eq!(api.repo, "/srv/git/api.git");
+        assert_eq!(
+            (api.builds, api.succeeded, api.failed, api.canceled),
+            (3, 1, 1, 1)
+        );
+        assert_eq!(api.success_rate, Some(0.5));
+        assert_eq!(api.queue_wait.count, 2);
+        assert_eq!(
+            (api.queue_wait.p50_ms, api.queue_wait.p95_ms),
+            (10_000, 30_000)
+        );
+        assert_eq!(api.duration.p95_ms, 100_000);
+
+        assert_eq!(report.overall.builds, 5);
+        assert_eq!(
+            report
+                .days
+                .iter()
+                .map(|d| (d.day.as_str(), d.builds))
+                .collect::<Vec<_>>(),
+            [("2024-05-01", 2), ("2024-05-02", 3)]
+        );
+    }
+
+    #[test]
+    fn objectives_are_checked_over_every_repository() {
+        let slow = [
+            timing(
+                "/srv/git/api.git",
+                "success",
+                "2024-05-02 10:00:00",
+                Some(600),
+                Some(60),
+            ),
+            timing(
+                "/srv/git/api.git",
+                "success",
+                "2024-05-02 10:00:00",
+                Some(5),
+                Some(60),
+            ),
+        ];
+        let met = |report: &Report| {
+            report
+                .objectives
+                .iter()
+                .map(|o| (o.name, o.met))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            met(&report(&slow, &Objectives::default(), 24, now())),
+            [
+                ("queue_wait_p95", false),
+                ("duration_p95", true),
+                ("success_rate", true)
+            ]
+        );
+
+        // Nothing to tell yet is not a miss
+        let empty = report(&[], &Objectives::default(), 24, now());
+        assert!(empty.objectives.iter().all(|o| o.met && o.actual.is_none()));
+    }
+
+    #[test]
+    fn metrics_are_in_the_prometheus_format() {
+        let timings = [timing(
+            "/srv/git/\"odd\".git",
+            "success",
+            "2024-05-02 10:00:00",
+            Some(2),
+            Some(90),
+        )];
+        let metrics = render_metrics(&report(&timings, &Objectives::default(), 24, now()));
+
+        assert!(metrics.contains("# TYPE ci_build_duration_seconds summary\n"));
+        assert!(metrics.contains(
+            "ci_build_duration_seconds{repo=\"/srv/git/\\\"odd\\\".git\",quantile=\"0.95\"} 90\n"
+        ));
+        assert!(
+            metrics.contains("ci_queue_wait_seconds_count{repo=\"/srv/git/\\\"odd\\\".git\"} 1\n")
+        );
+        assert!(metrics.contains("ci_build_success_ratio{repo=\"/srv/git/\\\"odd\\\".git\"} 1\n"));
+        assert!(metrics.contains("ci_slo_met{objective=\"success_rate\"} 1\n"));
+        assert!(metrics
+            .lines()
+            .all(|l| l.starts_with('#') || l.split(' ').count() == 2));
+    }
+
+    #[test]
+    fn eval_cache_lookups_are_counted_per_repository() {
+        let timings = ["hit", "hit", "miss"].map(|lookup| BuildTiming {
+            eval_cache: Some(lookup.to_string()),
+            ..timing(
+                "/srv/git/api.git",
+                "success",
+                "2024-05-02 10:00:00",
+                Some(1),
+                Some(1),
+            )
+        });
+        let report = report(&timings, &Objectives::default(), 24, now());
+        assert_eq!(
+            (
+                report.overall.eval_cache_hits,
+                report.overall.eval_cache_misses
+            ),
+            (2, 1)
+        );
+
+        let metrics = render_metrics(&report);
+        assert!(
+            metrics.contains("ci_eval_cache_lookups{repo=\"/srv/git/api.git\",result=\"hit\"} 2\n")
+        );
+        assert!(metrics
+            .contains("ci_eval_cache_lookups{repo=\"/srv/git/api.git\",result=\"miss\"} 1\n"));
+    }
+
+    #[test]
+    fn trends_escape_repository_paths() {
+        let timings = [timing(
+            "/srv/<b>.git",
+            "failed",
+            "2024-05-02 10:00:00",
+            Some(1),
+            Some(1),
+        )];
+        let html = render_trends(&report(&timings, &Objectives::default(), 24, now()));
+        assert!(html.contains("/srv/&lt;b&gt;.git"));
+        assert!(html.contains("class=\"missed\""));
+    }
+}
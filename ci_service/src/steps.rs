@@ -0,0 +1,262 @@
+//! Step timings
+//!
+//! Nix reports each stage of a build as a tree of activities with their
+//! start time and duration (see [`nix::Summary`]). They are flattened here
+//! into [`StepTiming`]s, timed from the start of the build, so they can be
+//! stored with the build, served per build, drawn as a Gantt chart and
+//! aggregated per repository.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime};
+
+use repo_outils::nix::{self, TimelineStep};
+use serde::{Deserialize, Serialize};
+
+use crate::builds::Stage;
+
+/// One nix activity of a build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct StepTiming {
+    /// `eval` or `build`
+    pub stage: String,
+    pub name: String,
+    /// Nesting in the activity tree, `0` for top-level steps
+    pub depth: usize,
+    /// Offset from the start of the build
+    pub start_ms: u64,
+    pub duration_ms: u64,
+}
+
+/// Flatten the timeline of `summary` depth first, each step timed from
+/// `origin`, the start of the build.
+pub fn flatten(stage: Stage, summary: &nix::Summary, origin: SystemTime) -> Vec<StepTiming> {
+    let mut steps = Vec::new();
+    for step in summary.timeline() {
+        push_step(&mut steps, stage, step, 0, origin);
+    }
+    steps
+}
+
+fn push_step(
+    steps: &mut Vec<StepTiming>,
+    stage: Stage,
+    step: &TimelineStep,
+    depth: usize,
+    origin: SystemTime,
+) {
+    steps.push(StepTiming {
+        stage: stage.to_string(),
+        name: step.text().to_string(),
+        depth,
+        start_ms: millis(step.started_at().duration_since(origin).unwrap_or_default()),
+        duration_ms: millis(step.duration()),
+    });
+    for child in step.children() {
+        push_step(steps, stage, child, depth + 1, origin);
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+// ─── Aggregates ────────────────────────────────────────────────────────────
+
+/// Timing of one kind of step across the builds of a repository
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct StepStats {
+    pub stage: String,
+    /// Step name with store hashes replaced, see [`step_key`]
+    pub name: String,
+    /// Builds the step was seen in
+    pub count: usize,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+}
+
+/// Name under which steps are aggregated: the store hash of every path is
+/// replaced by `*`, so `building '/nix/store/<hash>-app.drv'` matches across
+/// commits.
+pub fn step_key(name: &str) -> String {
+    const STORE: &str = "/nix/store/";
+    const HASH_LEN: usize = 32;
+
+    let mut key = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(at) = rest.find(STORE) {
+        let (before, after) = rest.split_at(at + STORE.len());
+        key.push_str(before);
+        let hash = after.get(..HASH_LEN);
+        if hash.is_some_and(|h| h.bytes().all(|b| b.is_ascii_alphanumeric()))
+            && after[HASH_LEN..].starts_with('-')
+        {
+            key.push('*');
+            rest = &after[HASH_LEN..];
+        } else {
+            rest = after;
+        }
+    }
+    key.push_str(rest);
+    key
+}
+
+/// p50 and p95 duration of every step of `builds`, slowest p95 first.
+pub fn stats<'a>(builds: impl IntoIterator<Item = &'a [StepTiming]>) -> Vec<StepStats> {
+    let mut durations: BTreeMap<(String, String), Vec<u64>> = BTreeMap::new();
+    for steps in builds {
+        for step in steps {
+            durations
+                .entry((step.stage.clone(), step_key(&step.name)))
+                .or_default()
+                .push(step.duration_ms);
+        }
+    }
+
+    let mut stats: Vec<StepStats> = durations
+        .into_iter()
+        .map(|((stage, name), mut durations)| {
+            durations.sort_unstable();
+            StepStats {
+                stage,
+                name,
+                count: durations.len(),
+                p50_ms: percentile(&durations, 50),
+                p95_ms: percentile(&durations, 95),
+            }
+        })
+        .collect();
+    stats.sort_by(|a, b| b.p95_ms.cmp(&a.p95_ms).then_with(|| a.name.cmp(&b.name)));
+    stats
+}
+
+/// Nearest-rank percentile of `sorted`, which must not be empty.
+pub(crate) fn percentile(sorted: &[u64], p: usize) -> u64 {
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+// ─── Gantt chart ───────────────────────────────────────────────────────────
+
+/// Standalone HTML page drawing `steps` as a Gantt chart, one row per step.
+pub fn render_timeline(build_id: i64, steps: &[StepTiming]) -> String {
+    let total = steps
+        .iter()
+        .map(|s| s.start_ms + s.duration_ms)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Build {build_id} timeline</title>\n\
+         <style>\n\
+         body {{ font-family: sans-serif; margin: 2em; }}\n\
+         .row {{ display: flex; align-items: center; height: 1.4em; }}\n\
+         .name {{ width: 40%; overflow: hidden; white-space: nowrap; text-overflow: ellipsis; font-size: 0.8em; }}\n\
+         .track {{ width: 60%; position: relative; height: 1em; background: #f3f3f3; }}\n\
+         .bar {{ position: absolute; height: 100%; min-width: 1px; }}\n\
+         .eval {{ background: #6c9bd2; }}\n\
+         .build {{ background: #7cbf7c; }}\n\
+         </style></head><body>\n\
+         <h1>Build {build_id}</h1>\n<p>{} steps over {total} ms</p>\n",
+        steps.len()
+    );
+    for step in steps {
+        // Percentages of the whole build, for the bar offset and width
+        let left = percent(step.start_ms, total);
+        let width = percent(step.duration_ms, total);
+        let _ = writeln!(
+            html,
+            "<div class=\"row\" title=\"{name} ({duration} ms)\">\
+             <div class=\"name\" style=\"padding-left: {indent}em\">{name}</div>\
+             <div class=\"track\"><div class=\"bar {stage}\" style=\"left: {left}%; width: {width}%\"></div></div></div>",
+            name = escape(&step.name),
+            duration = step.duration_ms,
+            indent = step.depth,
+            stage = escape(&step.stage),
+        );
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+/// `part` as a percentage of `total`, with two decimals.
+fn percent(part: u64, total: u64) -> String {
+    let hundredths = part.saturating_mul(10_000) / total;
+    format!("{}.{:02}", hundredths / 100, hundredths % 100)
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(name: &str, duration_ms: u64) -> StepTiming {
+        StepTiming {
+            stage: "build".to_string(),
+            name: name.to_string(),
+            depth: 0,
+            start_ms: 0,
+            duration_ms,
+        }
+    }
+
+    #[test]
+    fn step_key_drops_store_hashes() {
+        assert_eq!(
+            step_key("building '/nix/store/1bxizw9ww8ibvy4y6c2nflzyh15w2h5w-test-app.drv'"),
+            "building '/nix/store/*-test-app.drv'"
+        );
+        assert_eq!(
+            step_key("checking derivation packages.x86_64-linux.default"),
+            "checking derivation packages.x86_64-linux.default"
+        );
+        assert_eq!(
+            step_key("copying /nix/store/short"),
+            "copying /nix/store/short"
+        );
+    }
+
+    #[test]
+    fn stats_are_grouped_across_commits() {
+        let builds: Vec<Vec<StepTiming>> = (1..=20)
+            .map(|i| {
+                vec![
+                    step(&format!("building '/nix/store/{i:0>32}-app.drv'"), i * 100),
+                    step("checking flake output 'packages'", 10),
+                ]
+            })
+            .collect();
+
+        let stats = stats(builds.iter().map(Vec::as_slice));
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "building '/nix/store/*-app.drv'");
+        assert_eq!(stats[0].count, 20);
+        assert_eq!(stats[0].p50_ms, 1000);
+        assert_eq!(stats[0].p95_ms, 1900);
+        assert_eq!(stats[1].p50_ms, 10);
+    }
+
+    #[test]
+    fn timeline_escapes_step_names() {
+        let html = render_timeline(3, &[step("evaluating <script>", 5)]);
+        assert!(html.contains("evaluating &lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+    }
+}
@@ -0,0 +1,522 @@
+//! Vulnerability Scan
+//!
+//! Checks the packages of a build's SBOMs against an offline mirror of the
+//! OSV advisory database (<https://osv.dev>). The mirror holds one directory
+//! per ecosystem (`crates.io`, `npm`, `PyPI`, `Go`) with one OSV JSON file
+//! per advisory, as extracted from the `<ecosystem>/all.zip` dumps of the
+//! OSV bucket.
+//!
+//! Packages come from the purls of the `CycloneDX` components, so anything
+//! producing `CycloneDX` could be scanned the same way. Versions are matched
+//! against the advisory's listed versions and its `SEMVER`/`ECOSYSTEM`
+//! ranges; `GIT` ranges are ignored.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::database::BuildSbom;
+
+/// Severity of an advisory, from its `database_specific.severity` (GHSA
+/// style). Advisories that don't rate themselves are `unknown`, the lowest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Unknown,
+    Low,
+    Moderate,
+    High,
+    Critical,
+}
+
+impl Severity {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Unknown => "unknown",
+            Severity::Low => "low",
+            Severity::Moderate => "moderate",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "unknown" => Ok(Severity::Unknown),
+            "low" => Ok(Severity::Low),
+            "moderate" | "medium" => Ok(Severity::Moderate),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(format!("Invalid severity: {s}")),
+        }
+    }
+}
+
+/// A package of a repo affected by an advisory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "web", derive(utoipa::ToSchema))]
+pub struct Finding {
+    /// Repo of the commit the package is locked in
+    pub repo: String,
+    /// OSV ecosystem, e.g. `crates.io`
+    pub ecosystem: String,
+    pub package: String,
+    pub version: String,
+    /// Advisory id, e.g. `RUSTSEC-2023-0001` or `GHSA-xxxx-xxxx-xxxx`
+    pub advisory: String,
+    /// Other ids of the advisory, e.g. its CVE
+    pub aliases: Vec<String>,
+    pub severity: Severity,
+    pub summary: String,
+    /// First fixed version after the locked one, when there is one
+    pub fixed: Option<String>,
+}
+
+/// `(ecosystem, normalized name)` of the packages of the SBOMs, to the
+/// repos, names and versions they are locked with
+type Locked<'a> = HashMap<(&'static str, String), Vec<(&'a str, String, String)>>;
+
+/// Every package of `sboms` affected by an advisory of the mirror at
+/// `advisories`, by repo, package and advisory.
+///
+/// Only the ecosystems the SBOMs use are read. A missing ecosystem directory
+/// is logged and skipped; unreadable advisories are skipped.
+pub fn scan(advisories: &Path, sboms: &[BuildSbom]) -> std::io::Result<Vec<Finding>> {
+    let mut packages = Locked::new();
+    for sbom in sboms {
+        let components = sbom.sbom["components"].as_array().into_iter().flatten();
+        for purl in components.filter_map(|c| c["purl"].as_str()) {
+            let Some((ecosystem, name, version)) = parse_purl(purl) else {
+                continue;
+            };
+            packages
+                .entry((ecosystem, normalize(ecosystem, &name)))
+                .or_default()
+                .push((&sbom.repo, name, version));
+        }
+    }
+
+    let ecosystems: BTreeSet<&str> = packages.keys().map(|(ecosystem, _)| *ecosystem).collect();
+    let mut findings = Vec::new();
+    let mut seen = HashSet::new();
+    for ecosystem in ecosystems {
+        let dir = advisories.join(ecosystem);
+        if !dir.is_dir() {
+            warn!(ecosystem, dir = %dir.display(), "No advisories for ecosystem");
+            continue;
+        }
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let advisory: Advisory = match std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+            {
+                Ok(advisory) => advisory,
+                Err(error) => {
+                    debug!(path = %path.display(), error, "Skipping unreadable advisory");
+                    continue;
+                }
+            };
+            if advisory.withdrawn.is_some() {
+                continue;
+            }
+
+            for affected in &advisory.affected {
+                if affected.package.ecosystem != ecosystem {
+                    continue;
+                }
+                let key = (ecosystem, normalize(ecosystem, &affected.package.name));
+                for (repo, name, version) in packages.get(&key).into_iter().flatten() {
+                    if !affected.contains(version) {
+                        continue;
+                    }
+                    if !seen.insert((*repo, name.clone(), version.clone(), advisory.id.clone())) {
+                        continue;
+                    }
+                    findings.push(Finding {
+                        repo: repo.to_string(),
+                        ecosystem: ecosystem.to_string(),
+                        package: name.clone(),
+                        version: version.clone(),
+                        advisory: advisory.id.clone(),
+                        aliases: advisory.aliases.clone(),
+                        severity: advisory.severity(),
+                        summary: advisory.summary.clone().unwrap_or_default(),
+                        fixed: affected.fixed_after(version),
+                    });
+                }
+            }
+        }
+    }
+
+    findings.sort_by(|a, b| {
+        (&a.repo, &a.package, &a.version, &a.advisory).cmp(&(
+            &b.repo,
+            &b.package,
+            &b.version,
+            &b.advisory,
+        ))
+    });
+    Ok(findings)
+}
+
+/// `(ecosystem, name, version)` of a `pkg:<type>/<name>@<version>` purl,
+/// for the types autonix emits.
+fn parse_purl(purl: &str) -> Option<(&'static str, String, String)> {
+    let rest = purl.strip_prefix("pkg:")?;
+    let (kind, rest) = rest.split_once('/')?;
+    // Qualifiers and subpath aren't used here
+    let rest = rest.split(['?', '#']).next()?;
+    let (name, version) = rest.rsplit_once('@')?;
+    let ecosystem = match kind {
+        "cargo" => "crates.io",
+        "npm" => "npm",
+        "pypi" => "PyPI",
+        "golang" => "Go",
+        _ => return None,
+    };
+    Some((ecosystem, name.replace("%40", "@"), version.to_string()))
+}
+
+/// Name as compared within `ecosystem`: `PyPI` names are case insensitive and
+/// treat `-`, `_` and `.` alike.
+fn normalize(ecosystem: &str, name: &str) -> String {
+    if ecosystem == "PyPI" {
+        name.to_lowercase().replace(['_', '.'], "-")
+    } else {
+        name.to_string()
+    }
+}
+
+impl std::fmt::Display for Finding {
+    /// One line for the build logs, e.g. `api: parser 0.4.1 is affected by
+    /// RUSTSEC-2024-0001 (high), fixed in 0.4.2: Overflow in parser`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} {} is affected by {} ({})",
+            self.repo, self.package, self.version, self.advisory, self.severity
+        )?;
+        if let Some(fixed) = &self.fixed {
+            write!(f, ", fixed in {fixed}")?;
+        }
+        if !self.summary.is_empty() {
+            write!(f, ": {}", self.summary)?;
+        }
+        Ok(())
+    }
+}
+
+// ─── OSV schema ────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+struct Advisory {
+    id: String,
+    summary: Option<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
+    withdrawn: Option<String>,
+    #[serde(default)]
+    affected: Vec<Affected>,
+    #[serde(default)]
+    database_specific: serde_json::Value,
+}
+
+impl Advisory {
+    fn severity(&self) -> Severity {
+        self.database_specific["severity"]
+            .as_str()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(Severity::Unknown)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Affected {
+    package: OsvPackage,
+    #[serde(default)]
+    ranges: Vec<Range>,
+    #[serde(default)]
+    versions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvPackage {
+    ecosystem: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Range {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Event {
+    Introduced(String),
+    Fixed(String),
+    LastAffected(String),
+    Limit(String),
+}
+
+impl Affected {
+    fn contains(&self, version: &str) -> bool {
+        self.versions.iter().any(|v| compare(v, version).is_eq())
+            || self.version_ranges().any(|range| range.contains(version))
+    }
+
+    /// The lowest fixed version above `version` in any range
+    fn fixed_after(&self, version: &str) -> Option<String> {
+        self.version_ranges()
+            .flat_map(|range| &range.events)
+            .filter_map(|event| match event {
+                Event::Fixed(fixed) if compare(fixed, version).is_gt() => Some(fixed),
+                _ => None,
+            })
+            .min_by(|a, b| compare(a, b))
+            .cloned()
+    }
+
+    fn version_ranges(&self) -> impl Iterator<Item = &Range> {
+        self.ranges
+            .iter()
+            .filter(|range| range.kind == "SEMVER" || range.kind == "ECOSYSTEM")
+    }
+}
+
+impl Range {
+    /// Events in version order decide: `version` is affected once introduced,
+    /// until a fixed version at or below it or a last affected one below it.
+    fn contains(&self, version: &str) -> bool {
+        let mut events: Vec<&Event> = self.events.iter().collect();
+        events.sort_by(|a, b| compare(a.version(), b.version()));
+
+        let mut affected = false;
+        for event in events {
+            match event {
+                Event::Introduced(v) if compare(v, version).is_le() => affected = true,
+                Event::Fixed(v) | Event::Limit(v) if compare(v, version).is_le() => {
+                    affected = false;
+                }
+                Event::LastAffected(v) if compare(v, version).is_lt() => affected = false,
+                _ => {}
+            }
+        }
+        affected
+    }
+}
+
+impl Event {
+    fn version(&self) -> &str {
+        match self {
+            Event::Introduced(v) | Event::Fixed(v) | Event::LastAffected(v) | Event::Limit(v) => v,
+        }
+    }
+}
+
+/// Order of two versions, close enough to semver and PEP 440 for matching
+/// advisories: `v` prefixes dropped, numeric parts compared as numbers, a
+/// pre-release (`-rc.1`, `rc1`) before its release. `0` is the lowest.
+fn compare(a: &str, b: &str) -> Ordering {
+    let split = |v: &str| {
+        let v = v.trim_start_matches('v');
+        let v = v.split('+').next().unwrap_or(v);
+        match v.split_once('-') {
+            Some((release, pre)) => (release.to_string(), Some(pre.to_string())),
+            None => (v.to_string(), None),
+        }
+    };
+    let ((release_a, pre_a), (release_b, pre_b)) = (split(a), split(b));
+
+    let parts_a: Vec<&str> = release_a.split('.').collect();
+    let parts_b: Vec<&str> = release_b.split('.').collect();
+    for i in 0..parts_a.len().max(parts_b.len()) {
+        let ordering = compare_part(
+            parts_a.get(i).copied().unwrap_or("0"),
+            parts_b.get(i).copied().unwrap_or("0"),
+        );
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+
+    match (pre_a, pre_b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => {
+            let parts_a: Vec<&str> = a.split('.').collect();
+            let parts_b: Vec<&str> = b.split('.').collect();
+            parts_a
+                .iter()
+                .zip(&parts_b)
+                .map(|(a, b)| compare_part(a, b))
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| parts_a.len().cmp(&parts_b.len()))
+        }
+    }
+}
+
+/// `12` > `9`; `0` > `0rc1`, a suffix being a pre-release of the number.
+fn compare_part(a: &str, b: &str) -> Ordering {
+    let split = |p: &str| {
+        let digits = p.len() - p.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let (number, suffix) = p.split_at(digits);
+        (number.parse::<u64>().ok(), suffix.to_string())
+    };
+    let ((number_a, suffix_a), (number_b, suffix_b)) = (split(a), split(b));
+    number_a
+        .cmp(&number_b)
+        .then_with(|| match (suffix_a.is_empty(), suffix_b.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => suffix_a.cmp(&suffix_b),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sbom(repo: &str, purls: &[&str]) -> BuildSbom {
+        let components: Vec<_> = purls
+            .iter()
+            .map(|purl| serde_json::json!({ "type": "library", "purl": purl }))
+            .collect();
+        BuildSbom {
+            repo: repo.to_string(),
+            licenses: autonix::LicenseSummary::default(),
+            sbom: serde_json::json!({ "components": components }),
+        }
+    }
+
+    #[test]
+    fn versions_compare_across_schemes() {
+        assert!(compare("1.10.0", "1.9.3").is_gt());
+        assert!(compare("v1.8.4", "1.8.4").is_eq());
+        assert!(compare("1.0.0-rc.1", "1.0.0").is_lt());
+        assert!(compare("1.0.0-rc.2", "1.0.0-rc.10").is_lt());
+        assert!(compare("2.0rc1", "2.0").is_lt());
+        assert!(compare("1.0", "1.0.0").is_eq());
+        assert!(compare("0", "0.0.1").is_lt());
+    }
+
+    #[test]
+    fn ranges_follow_osv_events() {
+        let range = |events: serde_json::Value| -> Range {
+            serde_json::from_value(serde_json::json!({ "type": "SEMVER", "events": events }))
+                .unwrap()
+        };
+        let fixed = range(serde_json::json!([{ "introduced": "0" }, { "fixed": "1.2.0" }]));
+        assert!(fixed.contains("1.1.9"));
+        assert!(!fixed.contains("1.2.0"));
+
+        let reintroduced = range(serde_json::json!([
+            { "introduced": "1.0.0" }, { "fixed": "1.0.5" },
+            { "introduced": "2.0.0" }, { "last_affected": "2.1.0" }
+        ]));
+        assert!(!reintroduced.contains("0.9.0"));
+        assert!(reintroduced.contains("1.0.4"));
+        assert!(!reintroduced.contains("1.5.0"));
+        assert!(reintroduced.contains("2.1.0"));
+        assert!(!reintroduced.contains("2.1.1"));
+    }
+
+    #[test]
+    fn scan_matches_locked_packages() {
+        let dir = std::env::temp_dir().join(format!("ci-vulns-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("crates.io")).unwrap();
+        std::fs::create_dir_all(dir.join("PyPI")).unwrap();
+        std::fs::write(
+            dir.join("crates.io/RUSTSEC-2024-0001.json"),
+            serde_json::json!({
+                "id": "RUSTSEC-2024-0001",
+                "summary": "Overflow in parser",
+                "aliases": ["CVE-2024-1234"],
+                "affected": [{
+                    "package": { "ecosystem": "crates.io", "name": "parser" },
+                    "ranges": [{ "type": "SEMVER", "events": [
+                        { "introduced": "0" }, { "fixed": "0.4.2" }, { "fixed": "0.5.1" }
+                    ]}]
+                }],
+                "database_specific": { "severity": "HIGH" }
+            })
+            .to_string(),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("PyPI/GHSA-aaaa-bbbb-cccc.json"),
+            serde_json::json!({
+                "id": "GHSA-aaaa-bbbb-cccc",
+                "affected": [{
+                    "package": { "ecosystem": "PyPI", "name": "Py_Yaml" },
+                    "versions": ["5.3"]
+                }]
+            })
+            .to_string(),
+        )
+        .unwrap();
+        std::fs::write(dir.join("crates.io/broken.json"), "{").unwrap();
+
+        let findings = scan(
+            &dir,
+            &[
+                sbom("api", &["pkg:cargo/parser@0.4.1", "pkg:cargo/serde@1.0.0"]),
+                sbom("ml", &["pkg:pypi/py-yaml@5.3", "pkg:npm/%40scope/ui@1.0.0"]),
+            ],
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            findings,
+            [
+                Finding {
+                    repo: "api".to_string(),
+                    ecosystem: "crates.io".to_string(),
+                    package: "parser".to_string(),
+                    version: "0.4.1".to_string(),
+                    advisory: "RUSTSEC-2024-0001".to_string(),
+                    aliases: vec!["CVE-2024-1234".to_string()],
+                    severity: Severity::High,
+                    summary: "Overflow in parser".to_string(),
+                    fixed: Some("0.4.2".to_string()),
+                },
+                Finding {
+                    repo: "ml".to_string(),
+                    ecosystem: "PyPI".to_string(),
+                    package: "py-yaml".to_string(),
+                    version: "5.3".to_string(),
+                    advisory: "GHSA-aaaa-bbbb-cccc".to_string(),
+                    aliases: vec![],
+                    severity: Severity::Unknown,
+                    summary: String::new(),
+                    fixed: None,
+                },
+            ]
+        );
+    }
+}
@@ -3,165 +3,224 @@
 //! Polls the build queue for pending builds and executes them using Nix.
 //! This module handles:
 //! - Polling the queue for pending builds
-//! - Running `nix flake check` on the build target
+//! - Running `nix flake check --no-build` as a fast eval stage, then the
+//!   full `nix flake check`, recording the status of each stage
 //! - Capturing build output (stdout/stderr) and storing logs
 //! - Updating build status in the queue (Queued → Running → Success/Failed)
-//! - Implementing retry logic with exponential backoff
+//! - Implementing retry logic with exponential backoff: every Nix command
+//!   runs under the worker's [`RetryPolicy`], which retries transient
+//!   failures only; a build is requeued for any other error but a failed
+//!   evaluation or build, which would fail the same way again
+//! - Optionally isolating each build in its own chroot store, copying its
+//!   results to the host store or cache and removing the store afterwards
+//! - Recording the SBOM and license summary of every repo of the commit
+//! - Optionally scanning the SBOM against an advisory database before
+//!   building, failing the build on findings above the configured severity
+//! - Only building the checks of the packages a push changed, when the build
+//!   knows the commit it started from (see [`crate::selection`])
+//! - Skipping the evaluation, and the listing of the checks, of a commit
+//!   already evaluated with the same `flake.lock` hash, as a retry or a
+//!   rebuild of it does
 //!
 //! The worker runs in a background task and continuously polls the queue
-//! at configurable intervals, processing builds serially.
+//! at configurable intervals. It claims a build only once its [`BuildPool`]
+//! has a free slot and runs each in its own task, so at most the pool's size
+//! build at once and the others wait in the queue. The default pool of one
+//! processes builds serially.
 
-use repo_outils::nix;
+use repo_outils::nix::{self, BuildPool, IsolatedStore, RetryPolicy};
+use std::fmt::Write as _;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tracing::{error, info};
+use std::time::SystemTime;
+use tracing::{error, info, warn};
 
-use crate::builds::{BuildJob, BuildStatus};
+use crate::builds::{BuildJob, BuildStatus, Stage};
+use crate::config::{StoreIsolation, VulnScan};
 use crate::database::BuildSummary as DbBuildSummary;
 use crate::job_queue::JobQueue;
+use crate::selection::{self, Selection};
+use crate::steps::{self, StepTiming};
+use crate::vulns;
 
-use std::fmt;
+use crate::database::DatabaseError;
 
-#[derive(Debug)]
+/// Errors raised while processing a build.
+///
+/// Underlying errors are kept as `source()` instead of being stringified, so
+/// logs can print the full chain with `repo_outils::report`.
+#[derive(Debug, thiserror::Error)]
 #[allow(dead_code)]
 pub enum WorkerError {
-    Database(String),
-    Process(String),
-    Nix(String),
-    Git(String),
-    Io(std::io::Error),
-    Queue(String),
+    #[error("Database error")]
+    Database(#[from] DatabaseError),
+    #[error("Nix build error")]
+    Nix(#[from] nix::Error),
+    #[error("Git error")]
+    Git(#[from] repo_outils::git::RepoError),
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
 }
 
-impl fmt::Display for WorkerError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl WorkerError {
+    /// Stable identifier for this failure; delegates to the wrapped error.
+    pub fn code(&self) -> &'static str {
         match self {
-            WorkerError::Database(msg) => write!(f, "Database error: {}", msg),
-            WorkerError::Process(msg) => write!(f, "Process error: {}", msg),
-            WorkerError::Nix(msg) => write!(f, "Nix build error: {}", msg),
-            WorkerError::Git(msg) => write!(f, "Git error: {}", msg),
-            WorkerError::Io(err) => write!(f, "IO error: {}", err),
-            WorkerError::Queue(msg) => write!(f, "Queue error: {}", msg),
+            WorkerError::Database(e) => e.code(),
+            WorkerError::Nix(e) => e.code(),
+            WorkerError::Git(e) => e.code(),
+            WorkerError::Io(_) => "worker.io",
         }
     }
 }
 
-impl std::error::Error for WorkerError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            WorkerError::Io(err) => Some(err),
-            _ => None,
-        }
-    }
-}
+type Result<T> = std::result::Result<T, WorkerError>;
 
-impl From<std::io::Error> for WorkerError {
-    fn from(err: std::io::Error) -> Self {
-        WorkerError::Io(err)
-    }
+/// What the eval stage left for selecting the checks to build
+struct Evaluation {
+    /// Key of the commit's cached evaluation, `None` when it has none
+    lock_hash: Option<String>,
+    /// Checks its cached evaluation listed
+    checks: Option<nix::FlakeChecks>,
 }
 
-impl From<Box<dyn std::error::Error + Send + Sync>> for WorkerError {
-    fn from(err: Box<dyn std::error::Error + Send + Sync>) -> Self {
-        WorkerError::Database(err.to_string())
-    }
+pub struct Worker {
+    queue: JobQueue,
+    isolation: Option<StoreIsolation>,
+    vuln_scan: Option<VulnScan>,
+    pool: BuildPool,
+    retry: RetryPolicy,
 }
 
-// Update this to use the new nix::Error instead of nix::LogsError
-impl From<nix::Error> for WorkerError {
-    fn from(err: nix::Error) -> Self {
-        match err {
-            nix::Error::Io(io_err) => WorkerError::Io(io_err),
-            nix::Error::ProcessFailed {
-                exit_code: _,
-                stderr,
-            } => WorkerError::Nix(stderr),
-            nix::Error::JsonParse(json_err) => {
-                WorkerError::Process(format!("JSON parse error: {}", json_err))
-            }
-            nix::Error::InvalidFlakePath(path) => {
-                WorkerError::Nix(format!("Invalid flake path: {}", path))
-            }
-            nix::Error::LogParsing(log_err) => {
-                WorkerError::Process(format!("Log parsing error: {}", log_err))
-            }
-            nix::Error::BuildOutputMissing => {
-                WorkerError::Process("Build output missing".to_string())
-            }
+impl Worker {
+    pub fn new(queue: JobQueue) -> Self {
+        Self {
+            queue,
+            isolation: None,
+            vuln_scan: None,
+            pool: BuildPool::new(1),
+            retry: RetryPolicy::none(),
         }
     }
-}
 
-type Result<T> = std::result::Result<T, WorkerError>;
+    /// Run the Nix commands of each build again on transient failures, as
+    /// `policy` says.
+    #[must_use]
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
 
-pub struct Worker {
-    queue: JobQueue,
-}
+    /// Run up to `pool.size()` builds at once instead of one at a time.
+    #[must_use]
+    pub fn with_build_pool(mut self, pool: BuildPool) -> Self {
+        self.pool = pool;
+        self
+    }
 
-impl Worker {
-    pub fn new(queue: JobQueue) -> Self {
-        Self { queue }
+    /// Run every build in its own chroot store under `isolation.stores_path`
+    /// rather than in the host store.
+    #[must_use]
+    pub fn with_store_isolation(mut self, isolation: StoreIsolation) -> Self {
+        self.isolation = Some(isolation);
+        self
+    }
+
+    /// Scan every build's SBOM against the advisories of `vuln_scan` before
+    /// evaluating it.
+    #[must_use]
+    pub fn with_vuln_scan(mut self, vuln_scan: VulnScan) -> Self {
+        self.vuln_scan = Some(vuln_scan);
+        self
     }
 
     pub async fn run(self) {
-        info!(target: "ci_service::worker", "Worker started");
+        info!(
+            target: "ci_service::worker",
+            max_parallel = self.pool.size(),
+            "Worker started"
+        );
+        let worker = Arc::new(self);
 
         loop {
-            match self.queue.get_pending().await {
+            // Claim a build only with a slot to run it, leaving the rest queued
+            let slot = worker.pool.acquire().await;
+            match worker.queue.get_pending().await {
                 Ok(Some(build)) => {
-                    if let Err(err) = self.process_build(&build).await {
-                        error!(
-                            ?build,
-                            %err,
-                            "Build failed"
-                        );
-
-                        // Check if we can retry
-                        if build.can_retry() {
-                            info!(?build, "Scheduling retry for build");
-
-                            if let Err(err) = self
-                                .queue
-                                .increment_retry(build.id())
-                                .await
-                                .map_err(|e| WorkerError::Database(e.to_string()))
-                            {
-                                error!(
-                                    build_id = build.id(),
-                                    %err,
-                                    "Failed to increment retry count"
-                                );
-                            };
-                        } else {
-                            info!(
-                                build_id = build.id(),
-                                "Build exhausted all retries, marking as failed"
-                            );
-                            if let Err(err) = self
-                                .queue
-                                .update_status(build.id(), BuildStatus::Failed)
-                                .await
-                                .map_err(|e| WorkerError::Database(e.to_string()))
-                            {
-                                error!(
-                                    build_id = build.id(),
-                                     %err,
-                                    "Failed to update build status to Failed"
-                                );
-                            };
-                        }
-                    }
+                    let worker = Arc::clone(&worker);
+                    tokio::spawn(async move {
+                        worker.handle(&build).await;
+                        drop(slot);
+                    });
                 }
                 Ok(None) => {
+                    drop(slot);
                     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
                 }
                 Err(err) => {
-                    error!(%err, "Error fetching pending builds");
+                    drop(slot);
+                    error!(
+                        code = err.code(),
+                        error = repo_outils::report(&err),
+                        "Error fetching pending builds"
+                    );
                     tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
                 }
             }
         }
     }
 
+    /// Process `build`, scheduling a retry or marking it failed when it errors.
+    async fn handle(&self, build: &BuildJob) {
+        if let Err(err) = self.process_build(build).await {
+            error!(
+                ?build,
+                code = err.code(),
+                error = repo_outils::report(&err),
+                "Build failed"
+            );
+
+            // Check if we can retry
+            if is_deterministic(&err) {
+                info!(build_id = build.id(), "Build failed in Nix, not retrying");
+            } else if build.can_retry() {
+                info!(?build, "Scheduling retry for build");
+
+                if let Err(err) = self
+                    .queue
+                    .increment_retry(build.id())
+                    .await
+                    .map_err(WorkerError::from)
+                {
+                    error!(
+                        build_id = build.id(),
+                        code = err.code(),
+                        error = repo_outils::report(&err),
+                        "Failed to increment retry count"
+                    );
+                };
+            } else {
+                info!(
+                    build_id = build.id(),
+                    "Build exhausted all retries, marking as failed"
+                );
+                if let Err(err) = self
+                    .queue
+                    .update_status(build.id(), BuildStatus::Failed)
+                    .await
+                    .map_err(WorkerError::from)
+                {
+                    error!(
+                        build_id = build.id(),
+                        code = err.code(),
+                        error = repo_outils::report(&err),
+                        "Failed to update build status to Failed"
+                    );
+                };
+            }
+        }
+    }
+
     //TODO: we need to ensure that the repo has a flake.nix
     async fn process_build(&self, build: &BuildJob) -> Result<()> {
         info!(
@@ -171,72 +230,583 @@ impl Worker {
 
         self.queue
             .update_status(build.id(), BuildStatus::Running)
+            .await?;
+        self.queue.set_logs(build.id(), "").await?;
+
+        // The SBOM is informational: failing to produce it never fails the build
+        if let Err(err) = self.record_sboms(build).await {
+            warn!(
+                build_id = build.id(),
+                code = err.code(),
+                error = repo_outils::report(&err),
+                "Failed to record SBOM"
+            );
+        }
+
+        if let Some(vuln_scan) = &self.vuln_scan {
+            if !self.scan(build, vuln_scan).await? {
+                return Ok(());
+            }
+        }
+
+        let Some(isolation) = &self.isolation else {
+            return self.check(build, None).await;
+        };
+
+        let store =
+            IsolatedStore::create(isolation.stores_path.join(format!("build-{}", build.id())))
+                .await?;
+        let result = self.check(build, Some((&store, isolation))).await;
+
+        let root = store.root().display().to_string();
+        if let Err(err) = store.remove().await {
+            warn!(
+                build_id = build.id(),
+                root,
+                code = err.code(),
+                error = repo_outils::report(&err),
+                "Failed to remove build store"
+            );
+        }
+        result
+    }
+
+    /// Export the build's commit and store the SBOM autonix generates for
+    /// every repo found in it.
+    async fn record_sboms(&self, build: &BuildJob) -> Result<()> {
+        let bare_path = PathBuf::from(build.repo_path());
+        // Named like the repository, autonix names the root repo after its directory
+        let name = bare_path
+            .file_stem()
+            .map_or_else(|| "repo".into(), |s| s.to_string_lossy().into_owned());
+        let export = std::env::temp_dir().join(format!("ci-sbom-{}", build.id()));
+        let commit = build.commit_hash().to_string();
+
+        let dir = export.join(name);
+        let sboms = tokio::task::spawn_blocking(move || {
+            let exported = repo_outils::git::export_tree(&bare_path, &commit, &dir)
+                .map(|()| autonix::Parser::from(dir).scan().analyse().sboms());
+            let _ = std::fs::remove_dir_all(&export);
+            exported
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+
+        info!(build_id = build.id(), repos = sboms.len(), "SBOM generated");
+        self.queue.set_sboms(build.id(), &sboms).await?;
+        Ok(())
+    }
+
+    /// Check the recorded SBOMs of `build` against the advisories, storing
+    /// and logging the findings. Returns whether the build may go on: not
+    /// when a finding reaches `fail_on`, in which case the build is failed
+    /// without a retry since another attempt would find the same.
+    async fn scan(&self, build: &BuildJob, vuln_scan: &VulnScan) -> Result<bool> {
+        self.queue
+            .update_stage(build.id(), Stage::Scan, BuildStatus::Running)
+            .await?;
+        let advisories = vuln_scan.advisories_path.clone();
+        self.queue
+            .append_log(
+                build.id(),
+                &format!("$ scan SBOM against {}\n", advisories.display()),
+            )
+            .await?;
+
+        let sboms = self.queue.get_sboms(build.id()).await?;
+        let scanned = tokio::task::spawn_blocking(move || vulns::scan(&advisories, &sboms))
             .await
-            .map_err(|e| WorkerError::Database(e.to_string()))?;
+            .map_err(std::io::Error::other)
+            .and_then(|scanned| scanned);
+        let findings = match scanned {
+            Ok(findings) => findings,
+            Err(e) => {
+                let e = WorkerError::Io(e);
+                warn!(
+                    build_id = build.id(),
+                    code = e.code(),
+                    error = repo_outils::report(&e),
+                    "Vulnerability scan failed"
+                );
+                let error_log = format!("scan failed: {}\n", repo_outils::report(&e));
+                self.queue.append_log(build.id(), &error_log).await?;
+                self.queue
+                    .update_stage(build.id(), Stage::Scan, BuildStatus::Failed)
+                    .await?;
+                // Without a policy to enforce, not being able to scan doesn't
+                // stop the build
+                return match vuln_scan.fail_on {
+                    Some(_) => {
+                        self.queue
+                            .update_status(build.id(), BuildStatus::Failed)
+                            .await?;
+                        Err(e)
+                    }
+                    None => Ok(true),
+                };
+            }
+        };
 
-        let git_url = build.git_url();
+        self.queue.set_findings(build.id(), &findings).await?;
+        let blocking = findings
+            .iter()
+            .filter(|f| {
+                vuln_scan
+                    .fail_on
+                    .is_some_and(|fail_on| f.severity >= fail_on)
+            })
+            .count();
+        let mut report = String::new();
+        for finding in &findings {
+            let _ = writeln!(report, "{finding}");
+        }
+        let _ = writeln!(
+            report,
+            "scan found {} vulnerabilities, {blocking} at or above the failing severity",
+            findings.len()
+        );
+        self.queue.append_log(build.id(), &report).await?;
 
-        info!(build_id = build.id(), git_url, "Executing nix flake check");
+        info!(
+            build_id = build.id(),
+            findings = findings.len(),
+            blocking,
+            "Vulnerability scan completed"
+        );
+        if blocking > 0 {
+            self.queue
+                .update_stage(build.id(), Stage::Scan, BuildStatus::Failed)
+                .await?;
+            self.queue
+                .update_status(build.id(), BuildStatus::Failed)
+                .await?;
+            return Ok(false);
+        }
+        self.queue
+            .update_stage(build.id(), Stage::Scan, BuildStatus::Success)
+            .await?;
+        Ok(true)
+    }
+
+    /// Evaluate `build` (see [`Worker::evaluate`]), then run the full check
+    /// once that passed, in the isolated store when given. Each stage
+    /// records its own status as it goes; a passing build has its results
+    /// copied out of the isolated store.
+    async fn check(
+        &self,
+        build: &BuildJob,
+        isolated: Option<(&IsolatedStore, &StoreIsolation)>,
+    ) -> Result<()> {
+        let git_url = build.git_url();
 
         // Store the command in logs
         // TODO: this can be a reason why we want a struct NixCommand, to store the actual command used
-        let command_log = format!("$ nix flake check {} --print-build-logs\n", git_url);
+        let store_arg = isolated
+            .map(|(store, _)| format!("--store {} ", store.root().display()))
+            .unwrap_or_default();
+
+        // Step timings are kept from the start of the eval stage; the eval
+        // steps are stored now so a failed build still has them
+        let (origin, eval_steps, evaluation) = self.evaluate(build, isolated, &store_arg).await?;
+        let mut db_summary = DbBuildSummary {
+            steps: eval_steps,
+            skipped: Vec::new(),
+            diagnostics: None,
+        };
+        let selected = match build.base_commit() {
+            Some(base) => {
+                self.select(build, base, isolated.map(|(store, _)| store), evaluation)
+                    .await?
+            }
+            None => None,
+        };
+        if let Some((_, selection)) = &selected {
+            db_summary.skipped.clone_from(&selection.skipped);
+        }
         self.queue
-            .set_logs(build.id(), &command_log)
-            .await
-            .map_err(|e| WorkerError::Database(e.to_string()))?;
+            .set_build_summary(build.id(), &db_summary)
+            .await?;
+        self.queue
+            .update_stage(build.id(), Stage::Eval, BuildStatus::Success)
+            .await?;
+
+        info!(build_id = build.id(), git_url, "Eval passed, building");
+        self.queue
+            .update_stage(build.id(), Stage::Build, BuildStatus::Running)
+            .await?;
+
+        let checked = match &selected {
+            Some((_, selection)) if selection.run.is_empty() => {
+                info!(build_id = build.id(), "No check affected by the push");
+                self.queue
+                    .append_log(
+                        build.id(),
+                        "no check affected by the push, nothing to build\n",
+                    )
+                    .await?;
+                self.queue
+                    .set_build_summary(build.id(), &db_summary)
+                    .await?;
+                self.queue
+                    .update_stage(build.id(), Stage::Build, BuildStatus::Success)
+                    .await?;
+                self.queue
+                    .update_status(build.id(), BuildStatus::Success)
+                    .await?;
+                return Ok(());
+            }
+            Some((checks, selection)) => {
+                let flake_ref = build.flake_ref();
+                let names: Vec<&str> = selection.run.iter().map(String::as_str).collect();
+                let command_log = format!(
+                    "$ nix {store_arg}build --no-link {} --print-build-logs\n",
+                    names
+                        .iter()
+                        .map(|name| format!("{flake_ref}#checks.{}.{name}", checks.system()))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                );
+                self.queue.append_log(build.id(), &command_log).await?;
+                self.retry
+                    .run(|| async {
+                        match isolated {
+                            Some((store, _)) => {
+                                nix::build_checks_in(&flake_ref, checks, &names, store).await
+                            }
+                            None => nix::build_checks(&flake_ref, checks, &names).await,
+                        }
+                    })
+                    .await
+            }
+            None => {
+                let command_log =
+                    format!("$ nix {store_arg}flake check {git_url} --print-build-logs\n");
+                self.queue.append_log(build.id(), &command_log).await?;
+                self.retry
+                    .run(|| async {
+                        match isolated {
+                            Some((store, _)) => nix::flake_check_in(&git_url, store).await,
+                            None => nix::flake_check(&git_url).await,
+                        }
+                    })
+                    .await
+            }
+        };
+        let checked = match (checked, isolated) {
+            (Ok(check_result), Some((store, isolation))) => {
+                let signed = match &isolation.signing_key_file {
+                    Some(key_file) => store.sign(key_file).await,
+                    None => Ok(()),
+                };
+                match signed {
+                    Ok(()) => self
+                        .retry
+                        .run(|| store.copy_to(isolation.copy_to.as_deref()))
+                        .await
+                        .map(|()| check_result),
+                    Err(e) => Err(e),
+                }
+            }
+            (checked, _) => checked,
+        };
 
         // Use the new flake_check function that returns CheckResult
-        match nix::flake_check(&git_url).await {
+        match checked {
             Ok(check_result) => {
-                // Access the summary through the accessor method
-                // let summary = check_result.summary();
-
-                // Convert repo_outils::nix::Summary into your database::BuildSummary
-                // You'll need to implement this conversion based on your DbBuildSummary structure
-                let db_summary = DbBuildSummary;
+                let summary = check_result.summary();
+                db_summary
+                    .steps
+                    .extend(steps::flatten(Stage::Build, summary, origin));
 
                 // Store the structured build summary
                 self.queue
                     .set_build_summary(build.id(), &db_summary)
-                    .await
-                    .map_err(|e| WorkerError::Database(e.to_string()))?;
+                    .await?;
 
+                self.queue
+                    .update_stage(build.id(), Stage::Build, BuildStatus::Success)
+                    .await?;
                 self.queue
                     .update_status(build.id(), BuildStatus::Success)
-                    .await
-                    .map_err(|e| WorkerError::Database(e.to_string()))?;
+                    .await?;
 
-                // info!(
-                //     build_id = build.id(),
-                //     total_steps = summary.total_steps,
-                //     "Build completed successfully"
-                // );
-            }
-            Err(e) => {
-                error!(
+                info!(
                     build_id = build.id(),
-                    git_url,
-                    error = %e,
-                    "Build failed"
+                    total_steps = summary.total_steps(),
+                    duration_ms = summary.duration().as_millis(),
+                    "Build completed successfully"
                 );
+            }
+            Err(e) => return self.fail(build, Stage::Build, e).await,
+        }
 
-                // Store the error information in logs
-                let error_log = format!("Build failed: {}\n", e);
-                self.queue
-                    .append_log(build.id(), &error_log)
-                    .await
-                    .map_err(|e| WorkerError::Database(e.to_string()))?;
+        Ok(())
+    }
 
+    /// Evaluate `build` with `nix flake check --no-build`, unless its commit
+    /// already passed with the same `flake.lock`, caching a passing
+    /// evaluation. Returns when the stage started and its steps, timed from
+    /// then, and what it leaves for selecting the checks.
+    async fn evaluate(
+        &self,
+        build: &BuildJob,
+        isolated: Option<(&IsolatedStore, &StoreIsolation)>,
+        store_arg: &str,
+    ) -> Result<(SystemTime, Vec<StepTiming>, Evaluation)> {
+        let git_url = build.git_url();
+        info!(build_id = build.id(), git_url, "Evaluating flake");
+        self.queue
+            .update_stage(build.id(), Stage::Eval, BuildStatus::Running)
+            .await?;
+
+        let lock_hash = self.lock_hash(build).await;
+        let cached = match &lock_hash {
+            Some(lock_hash) => {
+                let cached = self
+                    .queue
+                    .cached_eval(build.repo_path(), build.commit_hash(), lock_hash)
+                    .await?;
                 self.queue
-                    .update_status(build.id(), BuildStatus::Failed)
-                    .await
-                    .map_err(|e| WorkerError::Database(e.to_string()))?;
+                    .set_eval_cache(build.id(), cached.is_some())
+                    .await?;
+                cached
+            }
+            None => None,
+        };
 
-                return Err(WorkerError::Nix(format!("Build failed: {}", e)));
+        if let Some(cached) = cached {
+            info!(
+                build_id = build.id(),
+                evaluated_by = cached.build_id,
+                "Evaluation cached, skipping it"
+            );
+            let cached_log = format!(
+                "evaluation of {} with this flake.lock passed in build {}, skipping it\n",
+                build.commit_hash(),
+                cached.build_id
+            );
+            self.queue.append_log(build.id(), &cached_log).await?;
+            let evaluation = Evaluation {
+                lock_hash,
+                checks: cached.checks,
+            };
+            return Ok((SystemTime::now(), Vec::new(), evaluation));
+        }
+
+        let command_log = format!("$ nix {store_arg}flake check {git_url} --no-build\n");
+        self.queue.append_log(build.id(), &command_log).await?;
+        let evaluated = self
+            .retry
+            .run(|| async {
+                match isolated {
+                    Some((store, _)) => nix::flake_eval_in(&git_url, store).await,
+                    None => nix::flake_eval(&git_url).await,
+                }
+            })
+            .await;
+        let evaluated = match evaluated {
+            Ok(evaluated) => evaluated,
+            Err(e) => return self.fail(build, Stage::Eval, e).await,
+        };
+        if let Some(lock_hash) = &lock_hash {
+            self.queue
+                .cache_eval(
+                    build.repo_path(),
+                    build.branch(),
+                    build.commit_hash(),
+                    lock_hash,
+                    build.id(),
+                )
+                .await?;
+        }
+
+        let origin = evaluated.summary().started_at();
+        let steps = steps::flatten(Stage::Eval, evaluated.summary(), origin);
+        let evaluation = Evaluation {
+            lock_hash,
+            checks: None,
+        };
+        Ok((origin, steps, evaluation))
+    }
+
+    /// Hash of the `flake.lock` of the build's commit, keying its cached
+    /// evaluation. `None` without one, the inputs then being unlocked, or
+    /// when it can't be read, which only skips the cache.
+    async fn lock_hash(&self, build: &BuildJob) -> Option<String> {
+        let bare_path = PathBuf::from(build.repo_path());
+        let commit = build.commit_hash().to_string();
+        let lock_hash = tokio::task::spawn_blocking(move || {
+            repo_outils::git::file_id(&bare_path, &commit, "flake.lock")
+        })
+        .await
+        .map_err(std::io::Error::other)
+        .map_err(WorkerError::from)
+        .and_then(|lock_hash| lock_hash.map_err(WorkerError::from));
+
+        match lock_hash {
+            Ok(lock_hash) => lock_hash,
+            Err(e) => {
+                warn!(
+                    build_id = build.id(),
+                    code = e.code(),
+                    error = repo_outils::report(&e),
+                    "Failed to hash flake.lock, evaluating without the cache"
+                );
+                None
             }
         }
+    }
 
-        Ok(())
+    /// Pick the checks of the packages the changes since `base` affect,
+    /// logging the skipped ones. `None` when every check should run: no
+    /// check is skipped, or selecting failed, which never fails the build.
+    /// The checks are listed once per cached evaluation.
+    async fn select(
+        &self,
+        build: &BuildJob,
+        base: &str,
+        store: Option<&IsolatedStore>,
+        evaluation: Evaluation,
+    ) -> Result<Option<(nix::FlakeChecks, Selection)>> {
+        let bare_path = PathBuf::from(build.repo_path());
+        let export = std::env::temp_dir().join(format!("ci-select-{}", build.id()));
+        let (base_commit, commit) = (base.to_string(), build.commit_hash().to_string());
+        let unaffected = tokio::task::spawn_blocking(move || {
+            let unaffected = selection::unaffected(&bare_path, &base_commit, &commit, &export);
+            let _ = std::fs::remove_dir_all(&export);
+            unaffected
+        })
+        .await
+        .map_err(std::io::Error::other)
+        .map_err(WorkerError::from)
+        .and_then(|unaffected| unaffected.map_err(WorkerError::from));
+
+        let unaffected = match unaffected {
+            Ok(unaffected) if unaffected.is_empty() => return Ok(None),
+            Ok(unaffected) => unaffected,
+            Err(e) => return self.select_failed(build, &e).await,
+        };
+        let checks = match evaluation.checks {
+            Some(checks) => checks,
+            None => match self
+                .list_checks(build, store, evaluation.lock_hash.as_deref())
+                .await
+            {
+                Ok(checks) => checks,
+                Err(e) => return self.select_failed(build, &e).await,
+            },
+        };
+
+        let selection = selection::select(&checks, &unaffected);
+        if selection.skipped.is_empty() {
+            return Ok(None);
+        }
+        let mut report = String::new();
+        for skipped in &selection.skipped {
+            let _ = writeln!(
+                report,
+                "skipping check {} of {}: {}",
+                skipped.check, skipped.package, skipped.reason
+            );
+        }
+        self.queue.append_log(build.id(), &report).await?;
+        info!(
+            build_id = build.id(),
+            base,
+            run = selection.run.len(),
+            skipped = selection.skipped.len(),
+            "Selected checks affected by the push"
+        );
+        Ok(Some((checks, selection)))
     }
+
+    /// List the checks of the build's flake, adding them to its cached
+    /// evaluation when it has one.
+    async fn list_checks(
+        &self,
+        build: &BuildJob,
+        store: Option<&IsolatedStore>,
+        lock_hash: Option<&str>,
+    ) -> Result<nix::FlakeChecks> {
+        let flake_ref = build.flake_ref();
+        let checks = self
+            .retry
+            .run(|| async {
+                match store {
+                    Some(store) => nix::flake_checks_in(&flake_ref, store).await,
+                    None => nix::flake_checks(&flake_ref).await,
+                }
+            })
+            .await?;
+        if let Some(lock_hash) = lock_hash {
+            self.queue
+                .set_eval_checks(build.repo_path(), build.commit_hash(), lock_hash, &checks)
+                .await?;
+        }
+        Ok(checks)
+    }
+
+    /// Log that checks couldn't be selected, falling back to every check.
+    async fn select_failed(
+        &self,
+        build: &BuildJob,
+        e: &WorkerError,
+    ) -> Result<Option<(nix::FlakeChecks, Selection)>> {
+        warn!(
+            build_id = build.id(),
+            code = e.code(),
+            error = repo_outils::report(e),
+            "Failed to select checks, running every check"
+        );
+        let error_log = format!(
+            "check selection failed, running every check: {}\n",
+            repo_outils::report(e)
+        );
+        self.queue.append_log(build.id(), &error_log).await?;
+        Ok(None)
+    }
+
+    /// Record `stage` and the whole build as failed with `e`.
+    async fn fail<T>(&self, build: &BuildJob, stage: Stage, e: nix::Error) -> Result<T> {
+        error!(
+            build_id = build.id(),
+            git_url = build.git_url(),
+            %stage,
+            code = e.code(),
+            error = repo_outils::report(&e),
+            "Build failed"
+        );
+
+        // Store the error information in logs
+        let error_log = format!("{stage} failed: {}\n", repo_outils::report(&e));
+        self.queue.append_log(build.id(), &error_log).await?;
+
+        // And what went wrong, for the UI to tell an evaluation error from a
+        // failed build or a hash mismatch
+        if let Some(diagnostics) = e.diagnostics() {
+            let mut summary = self
+                .queue
+                .get_build_summary(build.id())
+                .await?
+                .unwrap_or_default();
+            summary.diagnostics = Some(diagnostics.clone());
+            self.queue.set_build_summary(build.id(), &summary).await?;
+        }
+
+        self.queue
+            .update_stage(build.id(), stage, BuildStatus::Failed)
+            .await?;
+        self.queue
+            .update_status(build.id(), BuildStatus::Failed)
+            .await?;
+
+        Err(WorkerError::Nix(e))
+    }
+}
+
+/// Whether `err` is Nix failing in a way another attempt would repeat: the
+/// flake does not evaluate or a check does not build. Transient failures
+/// were already retried by the worker's [`RetryPolicy`].
+fn is_deterministic(err: &WorkerError) -> bool {
+    matches!(err, WorkerError::Nix(e @ nix::Error::ProcessFailed { .. }) if !e.is_transient())
 }
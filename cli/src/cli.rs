@@ -1,14 +1,51 @@
+use std::fmt::Write as _;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::{Args, Parser, Subcommand};
+use commands::hashing;
+use commands::master_capnp::ObjectKind;
+use commands::vm_action::VmAction;
+use repo_outils::nix;
+use tracing::instrument;
 
-#[derive(Debug)]
+use crate::client::{ClientConfig, ClientError, Description, DriftReport, MasterClient};
+use crate::doctor::{self, DoctorConfig, Status};
+use crate::interactive::{self, Session};
+use crate::wait::{self, Target, WaitError, WaitFor};
+
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("file missing")]
     FileMissing,
+    #[error("{0}")]
     RequestFailed(String),
+    /// The master could not be reached or answered with an error
+    #[error("request failed")]
+    Client(#[from] ClientError),
+    #[error("wait failed")]
+    Wait(#[source] WaitError),
+    #[error("cannot run nix")]
+    Nix(#[from] nix::Error),
+    #[error("{0}")]
     InvalidCommand(String),
+    #[error("missing argument: {0}")]
     MissingArgument(String),
-    IoError(std::io::Error),
+    #[error("I/O error")]
+    IoError(#[from] std::io::Error),
+    /// A program run for the user failed; `pcr` exits with its code
+    #[error("exited with code {0}")]
+    Exited(i32),
+}
+
+impl From<WaitError> for Error {
+    fn from(e: WaitError) -> Self {
+        match e {
+            WaitError::Invalid(e) => Error::InvalidCommand(e),
+            e => Error::Wait(e),
+        }
+    }
 }
 
 /// Procurator CLI
@@ -35,13 +72,54 @@ pub struct Cli {
 
 impl Cli {
     pub async fn handle() -> Result<(), Error> {
-        let cli = Self::parse();
+        Self::parse().run().await
+    }
+
+    /// Run one parsed command inside an interactive session, reusing the
+    /// session's master connection for cluster commands.
+    pub(crate) async fn run_in_session(self, session: &mut Session) -> Result<(), Error> {
+        match self.command {
+            Commands::Cluster(cluster) => {
+                let client = session.client().await?;
+                cluster.command.run(client).await
+            }
+            Commands::Describe(describe) => {
+                let client = session.client().await?;
+                describe.command.run(client).await
+            }
+            Commands::Vm(vm) => {
+                let client = session.client().await?;
+                vm.command.run(client).await
+            }
+            Commands::History(history) => {
+                let client = session.client().await?;
+                history.run(client).await
+            }
+            Commands::Wait(args) => args.run(session.config().clone()).await,
+            Commands::Top(args) => {
+                let client = session.client().await?;
+                args.run(client).await
+            }
+            Commands::Interactive(_) => Err(Error::InvalidCommand(
+                "already in interactive mode".to_string(),
+            )),
+            // Boxed: `run` can itself start an interactive session.
+            command => Box::pin(Self { command }.run()).await,
+        }
+    }
 
-        match cli.command {
+    async fn run(self) -> Result<(), Error> {
+        match self.command {
             Commands::Init(args) => {
-                super::init::init(args.path);
+                super::init::init(args.path, args.target);
+            }
+
+            Commands::Sbom(args) => {
+                super::sbom::sbom(args.path, args.output)?;
             }
 
+            Commands::Run(args) => args.run().await?,
+
             Commands::Stack(stack) => match stack.command {
                 StackCommands::Up => println!("Stack up"),
                 StackCommands::Down => println!("Stack down"),
@@ -58,6 +136,51 @@ impl Cli {
                 VcsCommands::Pull => println!("Pull repos"),
             },
 
+            Commands::Cluster(cluster) => {
+                let local = tokio::task::LocalSet::new();
+                local.run_until(cluster.handle()).await?;
+            }
+
+            Commands::Describe(describe) => {
+                let local = tokio::task::LocalSet::new();
+                local.run_until(describe.handle()).await?;
+            }
+
+            Commands::Vm(vm) => {
+                let local = tokio::task::LocalSet::new();
+                local.run_until(vm.handle()).await?;
+            }
+
+            Commands::History(history) => {
+                let local = tokio::task::LocalSet::new();
+                local.run_until(history.handle()).await?;
+            }
+
+            Commands::Wait(args) => {
+                let local = tokio::task::LocalSet::new();
+                local.run_until(args.handle()).await?;
+            }
+
+            Commands::Top(args) => {
+                let local = tokio::task::LocalSet::new();
+                local.run_until(args.handle()).await?;
+            }
+
+            Commands::Doctor(args) => {
+                let local = tokio::task::LocalSet::new();
+                local.run_until(args.run()).await?;
+            }
+
+            Commands::Interactive(args) => {
+                let local = tokio::task::LocalSet::new();
+                local
+                    .run_until(interactive::run(
+                        args.connection.client_config(),
+                        args.history_file,
+                    ))
+                    .await?;
+            }
+
             Commands::Inspect => {
                 println!("Launching inspection TUI...");
                 // TODO: ratatui interface
@@ -80,6 +203,14 @@ enum Commands {
     /// - prepares agent
     Init(InitArgs),
 
+    /// Write a CycloneDX SBOM per repo, from its lockfiles, and summarize
+    /// the licenses of its dependencies
+    Sbom(SbomArgs),
+
+    /// Run an app of the project's flake, or a command in its dev shell,
+    /// with the flake's tools instead of the host's
+    Run(RunArgs),
+
     /// Control local project stack lifecycle. To run the project locally to develop
     /// TODO: maybe we can reduce the commands and keep something more declarative too
     Stack(StackArgs),
@@ -87,6 +218,36 @@ enum Commands {
     /// Version control integrations
     Vcs(VcsArgs),
 
+    /// Inspect the remote cluster through the master
+    Cluster(ClusterArgs),
+
+    /// Show one object in detail, with the events recorded for it
+    Describe(DescribeArgs),
+
+    /// Restart, redeploy, stop or pin one VM without publishing a generation
+    Vm(VmArgs),
+
+    /// List the recent generations, newest first: what is running, who
+    /// published it and which CI build, commit and cache it came from
+    #[command(alias = "generations")]
+    History(HistoryArgs),
+
+    /// Wait until a generation converges or an object meets a condition,
+    /// failing on timeout
+    Wait(WaitArgs),
+
+    /// Show how one VM's or worker's metrics evolved, from the history the
+    /// master keeps
+    Top(TopArgs),
+
+    /// Check nix, flakes and KVM on this machine and that the master, cache
+    /// and CI are reachable and accept the tokens, with how to fix what is
+    /// not
+    Doctor(DoctorArgs),
+
+    /// Start a REPL accepting the same commands, with history and completion
+    Interactive(InteractiveArgs),
+
     /// Start an interative TUI to control and inspect agent sessions, tests, checks vcs things and remote or local clusters
     Inspect,
 }
@@ -95,14 +256,631 @@ enum Commands {
 ///
 #[derive(Debug, Args)]
 struct ClusterArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
     #[command(subcommand)]
     command: ClusterCommands,
 }
 
+impl ClusterArgs {
+    async fn handle(self) -> Result<(), Error> {
+        let client = MasterClient::connect(self.connection.client_config()).await?;
+        self.command.run(&client).await
+    }
+}
+
+/// Arguments for describe
+#[derive(Debug, Args)]
+struct DescribeArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[command(subcommand)]
+    command: DescribeCommands,
+}
+
+impl DescribeArgs {
+    async fn handle(self) -> Result<(), Error> {
+        let client = MasterClient::connect(self.connection.client_config()).await?;
+        self.command.run(&client).await
+    }
+}
+
+/// Arguments for VM actions
+#[derive(Debug, Args)]
+struct VmArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[command(subcommand)]
+    command: VmCommands,
+}
+
+impl VmArgs {
+    async fn handle(self) -> Result<(), Error> {
+        let client = MasterClient::connect(self.connection.client_config()).await?;
+        self.command.run(&client).await
+    }
+}
+
+/// Arguments for history
+#[derive(Debug, Args)]
+struct HistoryArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[command(subcommand)]
+    command: Option<HistoryCommands>,
+}
+
+/// Generations against what the cluster runs
+#[derive(Debug, Subcommand)]
+enum HistoryCommands {
+    /// Report, per VM, the active generation's hash against the one that
+    /// runs, how long it has run as desired and how drift was found
+    Diff {
+        /// What to compare the active generation with
+        #[arg(long, value_parser = ["running"], default_value = "running")]
+        against: String,
+    },
+}
+
+impl HistoryArgs {
+    async fn handle(self) -> Result<(), Error> {
+        let client = MasterClient::connect(self.connection.client_config()).await?;
+        self.run(&client).await
+    }
+
+    /// Root span of the command's distributed trace.
+    #[instrument(name = "pcr.history", skip_all)]
+    async fn run(self, client: &MasterClient) -> Result<(), Error> {
+        if let Some(HistoryCommands::Diff { against }) = self.command {
+            print!("{}", render_drift(&client.drift_report().await?, &against));
+            return Ok(());
+        }
+        for generation in client.generations().await? {
+            let marker = if generation.active { "*" } else { " " };
+            println!(
+                "{marker} {:<6} {:<12} {:<20} published={} vms={}",
+                generation.number,
+                generation.commit,
+                generation.publisher,
+                generation.published,
+                generation.vms
+            );
+            for (name, value) in [
+                ("ci build", &generation.ci_build_id),
+                ("repo", &generation.repo_url),
+                ("commit", &generation.commit_url),
+                ("cache", &generation.cache),
+            ] {
+                if !value.is_empty() {
+                    println!("    {name:<9} {value}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Arguments for wait
+#[derive(Debug, Args)]
+struct WaitArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    /// `vm/<id>`, `worker/<id>` or `generation/<n>` (default: the active
+    /// generation)
+    #[arg(value_parser = Target::parse, conflicts_with = "generation")]
+    target: Option<Target>,
+
+    /// Generation to wait for, instead of a target
+    #[arg(long)]
+    generation: Option<u64>,
+
+    /// `converged`, `ready` or a condition name from `describe` (default:
+    /// converged for generations, ready otherwise)
+    #[arg(long = "for", value_parser = WaitFor::parse)]
+    wait_for: Option<WaitFor>,
+
+    /// How long to wait, e.g. 90s or 10m
+    #[arg(long, default_value = "10m", value_parser = wait::parse_duration)]
+    timeout: Duration,
+
+    /// Delay between checks
+    #[arg(long, default_value = "2s", value_parser = wait::parse_duration)]
+    interval: Duration,
+}
+
+impl WaitArgs {
+    async fn handle(self) -> Result<(), Error> {
+        let config = self.connection.client_config();
+        self.run(config).await
+    }
+
+    /// Root span of the command's distributed trace.
+    ///
+    /// Connects to `config` itself, so a master restart is waited out.
+    #[instrument(name = "pcr.wait", skip_all)]
+    async fn run(self, config: ClientConfig) -> Result<(), Error> {
+        let target = self.target.unwrap_or(Target::Generation(self.generation));
+        wait::wait(config, target, self.wait_for, self.timeout, self.interval).await?;
+        Ok(())
+    }
+}
+
+/// Arguments for top
+#[derive(Debug, Args)]
+struct TopArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    /// `vm/<id>` or `worker/<id>`
+    #[arg(value_parser = Target::parse)]
+    target: Target,
+
+    /// How far back to look, at most a day
+    #[arg(long, default_value = "1h", value_parser = wait::parse_duration)]
+    range: Duration,
+
+    /// Time each row averages, rounded up to whole minutes (default: about
+    /// 60 rows)
+    #[arg(long, value_parser = wait::parse_duration)]
+    step: Option<Duration>,
+}
+
+impl TopArgs {
+    async fn handle(self) -> Result<(), Error> {
+        let client = MasterClient::connect(self.connection.client_config()).await?;
+        self.run(&client).await
+    }
+
+    /// Root span of the command's distributed trace.
+    #[instrument(name = "pcr.top", skip_all)]
+    async fn run(self, client: &MasterClient) -> Result<(), Error> {
+        let (kind, id) = match &self.target {
+            Target::Vm(id) => (ObjectKind::Vm, id),
+            Target::Worker(id) => (ObjectKind::Worker, id),
+            Target::Generation(_) => {
+                return Err(Error::InvalidCommand(
+                    "only VMs and workers have metrics".to_string(),
+                ));
+            }
+        };
+        let secs = |d: Duration| u32::try_from(d.as_secs()).unwrap_or(u32::MAX);
+        let series = client
+            .query_metrics(kind, id, secs(self.range), self.step.map_or(0, secs))
+            .await?;
+
+        println!("{} every {}s", self.target, series.step_secs);
+        let mut header = format!("{:<15}", "timestamp_ms");
+        for name in &series.names {
+            let _ = write!(header, " {name:>22}");
+        }
+        println!("{header}");
+        for (timestamp_ms, values) in &series.points {
+            let mut row = format!("{timestamp_ms:<15}");
+            for value in values {
+                let _ = write!(row, " {value:>22.2}");
+            }
+            println!("{row}");
+        }
+        if series.points.is_empty() {
+            println!("(nothing reported in range)");
+        }
+        Ok(())
+    }
+}
+
+/// Arguments for doctor
+#[derive(Debug, Args)]
+struct DoctorArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    /// Binary cache URL to check, e.g. `http://cache:8081`
+    #[arg(long, env = "PROCURATOR_CACHE")]
+    cache: Option<String>,
+
+    /// CI service URL to check, e.g. `http://ci:3000`
+    #[arg(long, env = "PROCURATOR_CI")]
+    ci: Option<String>,
+
+    /// API token to check against the CI service
+    #[arg(long, env = "PROCURATOR_CI_TOKEN", hide_env_values = true)]
+    ci_token: Option<String>,
+
+    /// This machine runs a worker, so KVM is required
+    #[arg(long)]
+    worker: bool,
+}
+
+impl DoctorArgs {
+    /// Root span of the command's distributed trace.
+    #[instrument(name = "pcr.doctor", skip_all)]
+    async fn run(self) -> Result<(), Error> {
+        let config = DoctorConfig {
+            // One attempt: a master that is down should be reported, not
+            // waited for
+            master: self.connection.client_config().with_connect_retries(0),
+            cache: self.cache,
+            ci: self.ci,
+            ci_token: self.ci_token,
+            worker: self.worker,
+            timeout: Duration::from_secs(self.connection.timeout_secs),
+        };
+        let checks = doctor::doctor(&config).await;
+        print!("{}", doctor::render(&checks));
+        let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+        if failed > 0 {
+            return Err(Error::RequestFailed(format!("{failed} check(s) failed")));
+        }
+        Ok(())
+    }
+}
+
+/// Arguments for the interactive session
+#[derive(Debug, Args)]
+struct InteractiveArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    /// History file (defaults to `$XDG_STATE_HOME/procurator/history`)
+    #[arg(long)]
+    history_file: Option<PathBuf>,
+}
+
+impl ClusterCommands {
+    /// Root span of the command's distributed trace.
+    #[instrument(name = "pcr.cluster", skip(client))]
+    async fn run(self, client: &MasterClient) -> Result<(), Error> {
+        match self {
+            ClusterCommands::Status { namespace } => {
+                let status = client
+                    .cluster_status(namespace.as_deref().unwrap_or_default())
+                    .await?;
+                println!(
+                    "generation {} ({}) — {}% converged",
+                    status.active_generation, status.active_commit, status.convergence_percent
+                );
+                if !status.unschedulable.is_empty() {
+                    println!(
+                        "{} VM(s) cannot be scheduled on any worker:",
+                        status.unschedulable.len()
+                    );
+                    for vm in &status.unschedulable {
+                        println!(
+                            "  ! {:<20} namespace={} {}: {}",
+                            vm.spec_hash, vm.namespace, vm.reason, vm.message
+                        );
+                    }
+                }
+                for worker in &status.workers {
+                    println!(
+                        "worker {:<20} healthy={} generation={} vms={}",
+                        worker.id, worker.healthy, worker.generation, worker.running_vms
+                    );
+                }
+                for vm in &status.vms {
+                    println!(
+                        "vm     {:<20} namespace={} worker={} status={} drifted={}",
+                        vm.id,
+                        none_if_empty(&vm.namespace),
+                        vm.worker_id,
+                        vm.status,
+                        vm.drifted
+                    );
+                }
+            }
+            ClusterCommands::Worker { id } => {
+                let worker = client.worker_status(&id).await?;
+                println!(
+                    "worker {} healthy={} generation={} vms={}",
+                    worker.id, worker.healthy, worker.generation, worker.running_vms
+                );
+            }
+            ClusterCommands::Vm { id } => {
+                let status = client.cluster_status("").await?;
+                let vm = status
+                    .vms
+                    .into_iter()
+                    .find(|vm| vm.id == id)
+                    .ok_or_else(|| Error::RequestFailed(format!("VM {id} not found")))?;
+                // The master only reports a bool; comparing the hashes here
+                // also tells apart unknown and incomparable (different hash
+                // versions) from real drift
+                let drift = hashing::compare(&vm.desired_hash, &vm.observed_hash);
+                println!(
+                    "vm {} worker={} status={} drift={} desired={} observed={}",
+                    vm.id, vm.worker_id, vm.status, drift, vm.desired_hash, vm.observed_hash
+                );
+            }
+            ClusterCommands::Audit { since_ms, limit } => {
+                for entry in client.audit_log(since_ms, limit).await? {
+                    let outcome = if entry.ok {
+                        "ok".to_string()
+                    } else {
+                        format!("error: {}", entry.error)
+                    };
+                    println!(
+                        "{} {:<24} {:<20} {} {}",
+                        entry.timestamp_ms, entry.actor, entry.method, outcome, entry.params
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DescribeCommands {
+    /// Root span of the command's distributed trace.
+    #[instrument(name = "pcr.describe", skip(client))]
+    async fn run(self, client: &MasterClient) -> Result<(), Error> {
+        let (kind, id) = match self {
+            DescribeCommands::Vm { id } => (ObjectKind::Vm, id),
+            DescribeCommands::Worker { id } => (ObjectKind::Worker, id),
+            DescribeCommands::Generation { number } => (ObjectKind::Generation, number.to_string()),
+        };
+        print!("{}", render_description(&client.describe(kind, &id).await?));
+        Ok(())
+    }
+}
+
+impl VmCommands {
+    /// Root span of the command's distributed trace.
+    #[instrument(name = "pcr.vm", skip(client))]
+    async fn run(self, client: &MasterClient) -> Result<(), Error> {
+        let (action, id) = match self {
+            VmCommands::Restart { id } => (VmAction::Restart, id),
+            VmCommands::Redeploy { id } => (VmAction::Redeploy, id),
+            VmCommands::Stop { id } => (VmAction::Stop, id),
+            VmCommands::Pin { id, worker } => {
+                let worker = client.pin_vm(&id, worker.as_deref(), true).await?;
+                println!("vm {id}: pinned to worker {worker}");
+                return Ok(());
+            }
+            VmCommands::Unpin { id } => {
+                client.pin_vm(&id, None, false).await?;
+                println!("vm {id}: unpinned");
+                return Ok(());
+            }
+        };
+        client.vm_action(&id, action).await?;
+        println!("vm {id}: {action} done");
+        Ok(())
+    }
+}
+
+/// `kubectl describe`-like text: one section per part, empty ones shown as
+/// `<none>`.
+fn render_description(description: &Description) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Id:         {}", description.id);
+    if !description.placement.is_empty() {
+        let _ = writeln!(out, "Placement:  {}", description.placement);
+    }
+
+    let _ = writeln!(out, "Fields:");
+    let width = description
+        .fields
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or_default();
+    for (name, desired, observed) in &description.fields {
+        let _ = writeln!(
+            out,
+            "  {name:<width$}  desired={}  observed={}",
+            none_if_empty(desired),
+            none_if_empty(observed)
+        );
+    }
+
+    let _ = writeln!(out, "Conditions:");
+    for c in &description.conditions {
+        let _ = writeln!(
+            out,
+            "  {:<16} {:<5} {:<16} {}",
+            c.name, c.status, c.reason, c.message
+        );
+    }
+
+    let _ = writeln!(out, "Metrics:");
+    if description.metrics.is_empty() {
+        let _ = writeln!(out, "  <none>");
+    }
+    for (name, value) in &description.metrics {
+        let _ = writeln!(out, "  {name}: {value}");
+    }
+
+    let _ = writeln!(out, "Events:");
+    if description.events.is_empty() {
+        let _ = writeln!(out, "  <none>");
+    }
+    for e in &description.events {
+        let _ = writeln!(
+            out,
+            "  {} {:<20} {:<16} {}",
+            e.timestamp_ms, e.object, e.reason, e.message
+        );
+    }
+    out
+}
+
+/// Drift report as a table, drifted and missing VMs counted up front.
+fn render_drift(report: &DriftReport, against: &str) -> String {
+    let drifted = report
+        .vms
+        .iter()
+        .filter(|vm| matches!(vm.drift, "self-reported" | "hash-mismatch"))
+        .count();
+    let missing = report.vms.iter().filter(|vm| vm.drift == "missing").count();
+    let mut out = format!(
+        "generation {} ({}) against {against}: {} VMs, {drifted} drifted, {missing} missing\n",
+        report.generation,
+        none_if_empty(&report.commit),
+        report.vms.len()
+    );
+    let _ = writeln!(
+        out,
+        "{:<20} {:<12} {:<12} {:<14} {:<12} {:<12} converged",
+        "vm", "worker", "namespace", "drift", "desired", "observed"
+    );
+    for vm in &report.vms {
+        let converged = if vm.drift == "in-sync" {
+            format_age(vm.converged_secs)
+        } else {
+            "-".to_string()
+        };
+        let _ = writeln!(
+            out,
+            "{:<20} {:<12} {:<12} {:<14} {:<12} {:<12} {converged}",
+            none_if_empty(&vm.vm_id),
+            none_if_empty(&vm.worker_id),
+            none_if_empty(&vm.namespace),
+            vm.drift,
+            short_hash(&vm.desired_hash),
+            short_hash(&vm.observed_hash),
+        );
+    }
+    out
+}
+
+/// First 12 digits of a content hash, without its version prefix.
+fn short_hash(hash: &str) -> &str {
+    let digits = hash.rsplit_once(':').map_or(hash, |(_, digits)| digits);
+    match digits.get(..12) {
+        Some(short) => short,
+        None if digits.is_empty() => "-",
+        None => digits,
+    }
+}
+
+/// `secs` as e.g. `3d4h`, `2h13m`, `5m` or `40s`.
+fn format_age(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86_400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{days}d{hours}h")
+    } else if hours > 0 {
+        format!("{hours}h{minutes}m")
+    } else if minutes > 0 {
+        format!("{minutes}m")
+    } else {
+        format!("{secs}s")
+    }
+}
+
+fn none_if_empty(value: &str) -> &str {
+    if value.is_empty() { "<none>" } else { value }
+}
+
+/// How to reach the master
+#[derive(Debug, Args)]
+struct ConnectionArgs {
+    /// Master server address
+    #[arg(long, default_value = "127.0.0.1:5000", env = "PROCURATOR_MASTER")]
+    master: SocketAddr,
+
+    /// Timeout in seconds for connecting and for each request
+    #[arg(long, default_value_t = 10)]
+    timeout_secs: u64,
+
+    /// Additional connect attempts before giving up
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// Initial delay between connect attempts, in milliseconds
+    #[arg(long, default_value_t = 200)]
+    retry_backoff_ms: u64,
+
+    /// Token scoping what the master shows, when it requires one
+    #[arg(long, env = "PROCURATOR_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+}
+
+impl ConnectionArgs {
+    fn client_config(&self) -> ClientConfig {
+        let config = ClientConfig::new(self.master)
+            .with_timeout(Duration::from_secs(self.timeout_secs))
+            .with_connect_retries(self.retries)
+            .with_retry_backoff(Duration::from_millis(self.retry_backoff_ms));
+        match &self.token {
+            Some(token) => config.with_token(token),
+            None => config,
+        }
+    }
+}
+
 /// Declarative cluster lifecycle commands
 #[derive(Debug, Subcommand)]
 enum ClusterCommands {
-    Status
+    /// Show generation, convergence, workers and VMs
+    Status {
+        /// Only VMs of this namespace (default: every namespace the token sees)
+        #[arg(long)]
+        namespace: Option<String>,
+    },
+
+    /// Query a single worker through the master
+    Worker { id: String },
+
+    /// Show the desired and observed state of one VM
+    Vm { id: String },
+
+    /// Show the audit trail of mutating calls, oldest first
+    Audit {
+        /// Only entries at or after this Unix time in milliseconds
+        #[arg(long, default_value_t = 0)]
+        since_ms: u64,
+
+        /// Show at most the latest N entries (0 for all)
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+    },
+}
+
+/// Objects that can be described
+#[derive(Debug, Subcommand)]
+enum DescribeCommands {
+    /// One VM, by id or spec hash: spec against observed state, placement,
+    /// metrics, conditions and why it missed its convergence deadline
+    Vm { id: String },
+
+    /// One worker: its generation, metrics, conditions and its VMs' events
+    Worker { id: String },
+
+    /// One of the recent generations: who published it, how far it
+    /// converged and the events of its VMs
+    Generation { number: u64 },
+}
+
+/// Actions on one VM, by id or spec hash, recorded as events of the VM.
+/// Lifecycle actions are carried out by the worker that runs it
+#[derive(Debug, Subcommand)]
+enum VmCommands {
+    /// Reboot the VM, or boot it again when stopped
+    Restart { id: String },
+
+    /// Recreate the VM from its spec with a fresh disk copy, keeping its id
+    Redeploy { id: String },
+
+    /// Shut the VM down; it is kept until deleted
+    Stop { id: String },
+
+    /// Keep the VM on one worker: drain only moves it with `--force` and
+    /// rescheduling leaves it alone
+    Pin {
+        id: String,
+        /// Worker to pin it to, the one running it by default
+        #[arg(long)]
+        worker: Option<String>,
+    },
+
+    /// Let the VM be moved again; pins set by its spec stay
+    Unpin { id: String },
 }
 
 /// Arguments for init command
@@ -111,6 +889,60 @@ struct InitArgs {
     /// Path to repository (defaults to current directory)
     #[arg(short, long)]
     path: Option<PathBuf>,
+
+    /// Nix files to generate: flake (flake.nix), devenv (devenv.nix) or legacy
+    /// (shell.nix and default.nix)
+    #[arg(short, long, default_value = "flake")]
+    target: autonix::OutputTarget,
+}
+
+/// Arguments for sbom command
+#[derive(Debug, Args)]
+struct SbomArgs {
+    /// Path to repository (defaults to current directory)
+    #[arg(short, long)]
+    path: Option<PathBuf>,
+
+    /// Directory to write `<repo>.cdx.json` into (defaults to .procurator/sbom)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// Arguments for run
+#[derive(Debug, Args)]
+struct RunArgs {
+    /// Flake to take the app or dev shell from
+    #[arg(long, default_value = ".")]
+    flake: String,
+
+    /// Run the arguments as a command in the dev shell instead of an app
+    #[arg(long, conflicts_with = "app")]
+    develop: bool,
+
+    /// App to run (default: the flake's default app)
+    app: Option<String>,
+
+    /// Arguments for the app, or the command with `--develop`
+    #[arg(last = true)]
+    args: Vec<String>,
+}
+
+impl RunArgs {
+    async fn run(self) -> Result<(), Error> {
+        let args: Vec<&str> = self.args.iter().map(String::as_str).collect();
+        let output = if self.develop {
+            nix::develop_command(&self.flake, &args).await
+        } else {
+            nix::run(&self.flake, self.app.as_deref().unwrap_or_default(), &args).await
+        }?;
+
+        print!("{}", output.stdout);
+        eprint!("{}", output.stderr);
+        match output.exit_code {
+            Some(0) => Ok(()),
+            code => Err(Error::Exited(code.unwrap_or(1))),
+        }
+    }
 }
 
 /// Arguments for stack namespace
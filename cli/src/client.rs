@@ -0,0 +1,717 @@
+//! Cap'n Proto RPC client for the Master interface.
+//!
+//! A `MasterClient` owns a single RPC system for the lifetime of one CLI
+//! invocation: commands that need several calls (status, then a worker
+//! capability, then a VM listing) share one TCP connection instead of
+//! reconnecting for each request.
+//!
+//! Every call is bounded by `ClientConfig::timeout` so an unresponsive master
+//! surfaces as an error instead of hanging the CLI. Connecting is retried a
+//! limited number of times with exponential backoff; RPC calls are not
+//! retried, since mutating requests are not guaranteed to be idempotent.
+//!
+//! Each call opens a client span and sends its W3C trace context in the
+//! request's `trace` field, so the master and workers continue the trace.
+//!
+//! The RPC system is driven with `spawn_local`, so the client must be used
+//! from inside a `tokio::task::LocalSet`.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use capnp_rpc::{RpcSystem, rpc_twoparty_capnp, twoparty};
+use commands::telemetry::TraceHeaders;
+use commands::vm_action::VmAction;
+use commands::vm_state::{self, VmState};
+use commands::{common_capnp, master_capnp, worker_capnp};
+use futures::AsyncReadExt;
+use tracing::{debug, info, instrument, warn};
+
+// ─── Configuration ─────────────────────────────────────────────────────────
+
+/// Connection settings for a `MasterClient`.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    addr: SocketAddr,
+    timeout: Duration,
+    connect_retries: u32,
+    retry_backoff: Duration,
+    /// Presented to every call, see `Master.getClusterStatus`.
+    token: Option<String>,
+}
+
+impl ClientConfig {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            timeout: Duration::from_secs(10),
+            connect_retries: 3,
+            retry_backoff: Duration::from_millis(200),
+            token: None,
+        }
+    }
+
+    /// Upper bound for connecting and for each individual RPC call.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Number of additional connect attempts after the first one fails.
+    pub fn with_connect_retries(mut self, retries: u32) -> Self {
+        self.connect_retries = retries;
+        self
+    }
+
+    /// Initial delay between connect attempts, doubled after each failure.
+    pub fn with_retry_backoff(mut self, backoff: Duration) -> Self {
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Token the master scopes what this client sees by.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+}
+
+// ─── Errors ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// All connect attempts failed; holds the last I/O error.
+    #[error("failed to connect to {addr} after {attempts} attempt(s)")]
+    Connect {
+        addr: SocketAddr,
+        attempts: u32,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A connect attempt or RPC call exceeded the configured timeout.
+    #[error("request timed out after {0:?}")]
+    Timeout(Duration),
+    /// Transport or protocol failure reported by capnp.
+    #[error("rpc error")]
+    Rpc(#[from] capnp::Error),
+    /// A text field of the response is not UTF-8.
+    #[error("invalid utf-8 in response")]
+    Utf8(#[from] std::str::Utf8Error),
+    /// The master answered with an error.
+    #[error("master: {0}")]
+    Rejected(String),
+}
+
+// ─── Response views ────────────────────────────────────────────────────────
+
+/// Owned copy of `Common.WorkerStatus`, detached from the RPC message.
+#[derive(Debug, Clone)]
+pub struct WorkerSummary {
+    pub id: String,
+    pub healthy: bool,
+    pub generation: u64,
+    pub running_vms: u32,
+}
+
+/// Owned copy of `Common.VmStatus`, detached from the RPC message.
+#[derive(Debug, Clone)]
+pub struct VmSummary {
+    pub id: String,
+    pub worker_id: String,
+    /// Empty when no spec wants the VM
+    pub namespace: String,
+    pub desired_hash: String,
+    pub observed_hash: String,
+    pub status: VmState,
+    pub drifted: bool,
+}
+
+/// Owned copy of `Common.UnschedulableVm`, detached from the RPC message.
+#[derive(Debug, Clone)]
+pub struct UnschedulableVm {
+    pub spec_hash: String,
+    pub namespace: String,
+    pub reason: String,
+    pub message: String,
+}
+
+/// Owned copy of `Master.Event`, detached from the RPC message.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub timestamp_ms: u64,
+    pub object: String,
+    pub reason: String,
+    pub message: String,
+}
+
+/// Owned copy of `Master.Description.Condition`.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub name: String,
+    pub status: bool,
+    pub reason: String,
+    pub message: String,
+}
+
+/// Owned copy of `Master.Description`, detached from the RPC message.
+/// Fields are `(name, desired, observed)`, metrics `(name, value)`.
+#[derive(Debug, Clone)]
+pub struct Description {
+    pub id: String,
+    pub placement: String,
+    pub fields: Vec<(String, String, String)>,
+    pub conditions: Vec<Condition>,
+    pub metrics: Vec<(String, String)>,
+    pub events: Vec<Event>,
+}
+
+/// Owned copy of `Common.ClusterStatus`, detached from the RPC message.
+#[derive(Debug, Clone)]
+pub struct ClusterStatus {
+    pub active_generation: u64,
+    pub active_commit: String,
+    pub convergence_percent: u32,
+    pub workers: Vec<WorkerSummary>,
+    pub vms: Vec<VmSummary>,
+    /// Pending VMs no worker can host, empty from masters that predate it
+    pub unschedulable: Vec<UnschedulableVm>,
+}
+
+/// Owned copy of `Master.AuditEntry`, detached from the RPC message.
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    pub timestamp_ms: u64,
+    pub actor: String,
+    pub method: String,
+    pub params: String,
+    pub ok: bool,
+    pub error: String,
+}
+
+/// Owned copy of `Common.Generation`, detached from the RPC message.
+/// Provenance fields are empty when the publisher did not send them.
+#[derive(Debug, Clone)]
+pub struct GenerationSummary {
+    pub number: u64,
+    pub commit: String,
+    pub publisher: String,
+    /// Unix seconds
+    pub published: u64,
+    pub active: bool,
+    pub vms: u32,
+    pub ci_build_id: String,
+    pub repo_url: String,
+    pub commit_url: String,
+    pub cache: String,
+}
+
+/// Owned copy of `Master.DriftReport`, detached from the RPC message.
+#[derive(Debug, Clone)]
+pub struct DriftReport {
+    pub generation: u64,
+    pub commit: String,
+    pub vms: Vec<DriftEntry>,
+}
+
+/// One VM of a [`DriftReport`]. `drift` is `in-sync`, `self-reported`,
+/// `hash-mismatch` or `missing`.
+#[derive(Debug, Clone)]
+pub struct DriftEntry {
+    /// Empty when no worker reports it
+    pub vm_id: String,
+    pub worker_id: String,
+    pub namespace: String,
+    pub desired_hash: String,
+    pub observed_hash: String,
+    /// Running as desired for this long, 0 unless in sync
+    pub converged_secs: u64,
+    pub drift: &'static str,
+}
+
+/// Owned copy of `Master.MetricSeries`, detached from the RPC message.
+/// Points are `(timestamp_ms, values)`, one value per name.
+#[derive(Debug, Clone)]
+pub struct MetricSeries {
+    pub names: Vec<String>,
+    pub step_secs: u32,
+    pub points: Vec<(u64, Vec<f64>)>,
+}
+
+// ─── Client ────────────────────────────────────────────────────────────────
+
+pub type MasterCapability = master_capnp::master::Client;
+
+/// Master RPC client reusing one connection across calls.
+pub struct MasterClient {
+    client: MasterCapability,
+    config: ClientConfig,
+}
+
+impl MasterClient {
+    /// Connect to the master, retrying up to `connect_retries` times.
+    pub async fn connect(config: ClientConfig) -> Result<Self, ClientError> {
+        let stream = connect_with_retries(&config).await?;
+
+        let (reader, writer) = tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
+        let network = Box::new(twoparty::VatNetwork::new(
+            futures::io::BufReader::new(reader),
+            futures::io::BufWriter::new(writer),
+            rpc_twoparty_capnp::Side::Client,
+            capnp::message::ReaderOptions::default(),
+        ));
+
+        let mut rpc_system = RpcSystem::new(network, None);
+        let client: MasterCapability = rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+
+        tokio::task::spawn_local(async move {
+            if let Err(e) = rpc_system.await {
+                debug!(error = %e, "Master RPC system terminated");
+            }
+        });
+
+        info!(addr = %config.addr, "Connected to master");
+        Ok(Self { client, config })
+    }
+
+    /// Master.getClusterStatus — fetch the aggregated cluster view, of one
+    /// namespace or, when empty, of every namespace the token sees.
+    #[instrument(name = "Master.getClusterStatus", skip(self), fields(otel.kind = "client"))]
+    pub async fn cluster_status(&self, namespace: &str) -> Result<ClusterStatus, ClientError> {
+        let mut request = self.client.get_cluster_status_request();
+        request.get().set_namespace(namespace);
+        request.get().set_token(self.token());
+        TraceHeaders::current().write(request.get().init_trace());
+
+        let response = self.call(request.send().promise).await?;
+        let status = response.get()?.get_status()?;
+
+        let workers = status
+            .get_workers()?
+            .iter()
+            .map(|w| {
+                Ok(WorkerSummary {
+                    id: w.get_id()?.to_str()?.to_string(),
+                    healthy: w.get_healthy(),
+                    generation: w.get_generation(),
+                    running_vms: w.get_running_vms(),
+                })
+            })
+            .collect::<Result<Vec<_>, ClientError>>()?;
+
+        let vms = status
+            .get_vms()?
+            .iter()
+            .map(|vm| {
+                Ok(VmSummary {
+                    id: vm.get_id()?.to_str()?.to_string(),
+                    worker_id: vm.get_worker_id()?.to_str()?.to_string(),
+                    namespace: vm.get_namespace()?.to_str()?.to_string(),
+                    desired_hash: vm.get_desired_hash()?.to_str()?.to_string(),
+                    observed_hash: vm.get_observed_hash()?.to_str()?.to_string(),
+                    status: vm_state::read(vm.get_state(), vm.get_status()?.to_str()?),
+                    drifted: vm.get_drifted(),
+                })
+            })
+            .collect::<Result<Vec<_>, ClientError>>()?;
+
+        let unschedulable = status
+            .get_unschedulable()?
+            .iter()
+            .map(|vm| {
+                Ok(UnschedulableVm {
+                    spec_hash: vm.get_spec_hash()?.to_str()?.to_string(),
+                    namespace: vm.get_namespace()?.to_str()?.to_string(),
+                    reason: vm.get_reason()?.to_str()?.to_string(),
+                    message: vm.get_message()?.to_str()?.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, ClientError>>()?;
+
+        Ok(ClusterStatus {
+            active_generation: status.get_active_generation(),
+            active_commit: status.get_active_commit()?.to_str()?.to_string(),
+            convergence_percent: status.get_convergence_percent(),
+            workers,
+            vms,
+            unschedulable,
+        })
+    }
+
+    /// Master.getWorker + Worker.read — the worker capability is obtained
+    /// and queried over this client's connection, no new TCP session.
+    #[instrument(name = "Master.getWorker", skip(self), fields(otel.kind = "client"))]
+    pub async fn worker_status(&self, worker_id: &str) -> Result<WorkerSummary, ClientError> {
+        let mut request = self.client.get_worker_request();
+        request.get().set_token(self.token());
+        request.get().set_worker_id(worker_id);
+        TraceHeaders::current().write(request.get().init_trace());
+
+        let response = self.call(request.send().promise).await?;
+        let worker: worker_capnp::worker::Client = response.get()?.get_worker()?;
+
+        let mut request = worker.read_request();
+        TraceHeaders::current().write(request.get().init_trace());
+        let response = self.call(request.send().promise).await?;
+        let data = response.get()?.get_data()?;
+
+        Ok(WorkerSummary {
+            id: data.get_id()?.to_str()?.to_string(),
+            healthy: data.get_healthy(),
+            generation: data.get_generation(),
+            running_vms: data.get_running_vms(),
+        })
+    }
+
+    /// Master.getAuditLog — mutating calls since `since_ms`, oldest first.
+    #[instrument(name = "Master.getAuditLog", skip(self), fields(otel.kind = "client"))]
+    pub async fn audit_log(
+        &self,
+        since_ms: u64,
+        limit: u32,
+    ) -> Result<Vec<AuditRecord>, ClientError> {
+        let mut request = self.client.get_audit_log_request();
+        request.get().set_token(self.token());
+        request.get().set_since_ms(since_ms);
+        request.get().set_limit(limit);
+        TraceHeaders::current().write(request.get().init_trace());
+
+        let response = self.call(request.send().promise).await?;
+        response
+            .get()?
+            .get_entries()?
+            .iter()
+            .map(|e| {
+                Ok(AuditRecord {
+                    timestamp_ms: e.get_timestamp_ms(),
+                    actor: e.get_actor()?.to_str()?.to_string(),
+                    method: e.get_method()?.to_str()?.to_string(),
+                    params: e.get_params()?.to_str()?.to_string(),
+                    ok: e.get_ok(),
+                    error: e.get_error()?.to_str()?.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Master.describe — one object in detail, with its recent events.
+    #[instrument(name = "Master.describe", skip(self), fields(otel.kind = "client"))]
+    pub async fn describe(
+        &self,
+        kind: master_capnp::ObjectKind,
+        id: &str,
+    ) -> Result<Description, ClientError> {
+        let mut request = self.client.describe_request();
+        request.get().set_token(self.token());
+        request.get().set_kind(kind);
+        request.get().set_id(id);
+        TraceHeaders::current().write(request.get().init_trace());
+
+        let response = self.call(request.send().promise).await?;
+        let description = match response.get()?.get_result()?.which()? {
+            common_capnp::result::Which::Ok(description) => description?,
+            common_capnp::result::Which::Err(e) => {
+                return Err(ClientError::Rejected(e?.to_str()?.to_string()));
+            }
+        };
+
+        let fields = description
+            .get_fields()?
+            .iter()
+            .map(|f| {
+                Ok((
+                    f.get_name()?.to_str()?.to_string(),
+                    f.get_desired()?.to_str()?.to_string(),
+                    f.get_observed()?.to_str()?.to_string(),
+                ))
+            })
+            .collect::<Result<Vec<_>, ClientError>>()?;
+        let conditions = description
+            .get_conditions()?
+            .iter()
+            .map(|c| {
+                Ok(Condition {
+                    name: c.get_name()?.to_str()?.to_string(),
+                    status: c.get_status(),
+                    reason: c.get_reason()?.to_str()?.to_string(),
+                    message: c.get_message()?.to_str()?.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, ClientError>>()?;
+        let metrics = description
+            .get_metrics()?
+            .iter()
+            .map(|m| {
+                Ok((
+                    m.get_name()?.to_str()?.to_string(),
+                    m.get_value()?.to_str()?.to_string(),
+                ))
+            })
+            .collect::<Result<Vec<_>, ClientError>>()?;
+        let events = description
+            .get_events()?
+            .iter()
+            .map(|e| {
+                Ok(Event {
+                    timestamp_ms: e.get_timestamp_ms(),
+                    object: e.get_object()?.to_str()?.to_string(),
+                    reason: e.get_reason()?.to_str()?.to_string(),
+                    message: e.get_message()?.to_str()?.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, ClientError>>()?;
+
+        Ok(Description {
+            id: description.get_id()?.to_str()?.to_string(),
+            placement: description.get_placement()?.to_str()?.to_string(),
+            fields,
+            conditions,
+            metrics,
+            events,
+        })
+    }
+
+    /// Master.queryMetrics — how one VM's or worker's metrics evolved over
+    /// the last `range_secs`, averaged over `step_secs`. Zero lets the
+    /// master pick.
+    #[instrument(name = "Master.queryMetrics", skip(self), fields(otel.kind = "client"))]
+    pub async fn query_metrics(
+        &self,
+        kind: master_capnp::ObjectKind,
+        id: &str,
+        range_secs: u32,
+        step_secs: u32,
+    ) -> Result<MetricSeries, ClientError> {
+        let mut request = self.client.query_metrics_request();
+        request.get().set_token(self.token());
+        request.get().set_kind(kind);
+        request.get().set_id(id);
+        request.get().set_range_secs(range_secs);
+        request.get().set_step_secs(step_secs);
+        TraceHeaders::current().write(request.get().init_trace());
+
+        let response = self.call(request.send().promise).await?;
+        let series = match response.get()?.get_result()?.which()? {
+            common_capnp::result::Which::Ok(series) => series?,
+            common_capnp::result::Which::Err(e) => {
+                return Err(ClientError::Rejected(e?.to_str()?.to_string()));
+            }
+        };
+
+        let names = series
+            .get_names()?
+            .iter()
+            .map(|n| Ok(n?.to_str()?.to_string()))
+            .collect::<Result<Vec<_>, ClientError>>()?;
+        let points = series
+            .get_points()?
+            .iter()
+            .map(|p| Ok((p.get_timestamp_ms(), p.get_values()?.iter().collect())))
+            .collect::<Result<Vec<_>, ClientError>>()?;
+
+        Ok(MetricSeries {
+            names,
+            step_secs: series.get_step_secs(),
+            points,
+        })
+    }
+
+    /// Master.vmAction — restart, redeploy or stop one VM, by id or spec
+    /// hash. Returns once its worker carried the action out.
+    #[instrument(name = "Master.vmAction", skip(self), fields(otel.kind = "client"))]
+    pub async fn vm_action(&self, vm_id: &str, action: VmAction) -> Result<(), ClientError> {
+        let mut request = self.client.vm_action_request();
+        request.get().set_token(self.token());
+        request.get().set_vm_id(vm_id);
+        request.get().set_action(action.into());
+        TraceHeaders::current().write(request.get().init_trace());
+
+        let response = self.call(request.send().promise).await?;
+        match response.get()?.get_result()?.which()? {
+            common_capnp::result::Which::Ok(_) => Ok(()),
+            common_capnp::result::Which::Err(e) => {
+                Err(ClientError::Rejected(e?.to_str()?.to_string()))
+            }
+        }
+    }
+
+    /// Master.pinVm — pin one VM, by id or spec hash, to `worker_id` (where
+    /// it runs when `None`), or unpin it. Returns the worker it is pinned to,
+    /// empty once unpinned.
+    #[instrument(name = "Master.pinVm", skip(self), fields(otel.kind = "client"))]
+    pub async fn pin_vm(
+        &self,
+        vm_id: &str,
+        worker_id: Option<&str>,
+        pin: bool,
+    ) -> Result<String, ClientError> {
+        let mut request = self.client.pin_vm_request();
+        request.get().set_token(self.token());
+        request.get().set_vm_id(vm_id);
+        request.get().set_worker_id(worker_id.unwrap_or_default());
+        request.get().set_pin(pin);
+        TraceHeaders::current().write(request.get().init_trace());
+
+        let response = self.call(request.send().promise).await?;
+        match response.get()?.get_result()?.which()? {
+            common_capnp::result::Which::Ok(worker_id) => Ok(worker_id?.to_str()?.to_string()),
+            common_capnp::result::Which::Err(e) => {
+                Err(ClientError::Rejected(e?.to_str()?.to_string()))
+            }
+        }
+    }
+
+    /// Master.listGenerations — the last generations made active, newest
+    /// first, with where they came from.
+    #[instrument(name = "Master.listGenerations", skip(self), fields(otel.kind = "client"))]
+    pub async fn generations(&self) -> Result<Vec<GenerationSummary>, ClientError> {
+        let mut request = self.client.list_generations_request();
+        request.get().set_token(self.token());
+        TraceHeaders::current().write(request.get().init_trace());
+
+        let response = self.call(request.send().promise).await?;
+        response
+            .get()?
+            .get_generations()?
+            .iter()
+            .map(|g| {
+                let provenance = g.get_provenance()?;
+                Ok(GenerationSummary {
+                    number: g.get_number(),
+                    commit: g.get_commit()?.to_str()?.to_string(),
+                    publisher: g.get_publisher()?.to_str()?.to_string(),
+                    published: g.get_timestamp(),
+                    active: g.get_is_active(),
+                    vms: g.get_vms(),
+                    ci_build_id: provenance.get_ci_build_id()?.to_str()?.to_string(),
+                    repo_url: provenance.get_repo_url()?.to_str()?.to_string(),
+                    commit_url: provenance.get_commit_url()?.to_str()?.to_string(),
+                    cache: provenance.get_cache()?.to_str()?.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Master.driftReport — every VM's desired and observed hash, against
+    /// the active generation.
+    #[instrument(name = "Master.driftReport", skip(self), fields(otel.kind = "client"))]
+    pub async fn drift_report(&self) -> Result<DriftReport, ClientError> {
+        use master_capnp::drift_report::Drift;
+
+        let mut request = self.client.drift_report_request();
+        request.get().set_token(self.token());
+        TraceHeaders::current().write(request.get().init_trace());
+
+        let response = self.call(request.send().promise).await?;
+        let report = response.get()?.get_report()?;
+        let vms = report
+            .get_vms()?
+            .iter()
+            .map(|vm| {
+                Ok(DriftEntry {
+                    vm_id: vm.get_vm_id()?.to_str()?.to_string(),
+                    worker_id: vm.get_worker_id()?.to_str()?.to_string(),
+                    namespace: vm.get_namespace()?.to_str()?.to_string(),
+                    desired_hash: vm.get_desired_hash()?.to_str()?.to_string(),
+                    observed_hash: vm.get_observed_hash()?.to_str()?.to_string(),
+                    converged_secs: vm.get_converged_secs(),
+                    drift: match vm.get_drift() {
+                        Ok(Drift::InSync) => "in-sync",
+                        Ok(Drift::SelfReported) => "self-reported",
+                        Ok(Drift::HashMismatch) => "hash-mismatch",
+                        Ok(Drift::Missing) => "missing",
+                        Err(_) => "unknown",
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>, ClientError>>()?;
+
+        Ok(DriftReport {
+            generation: report.get_generation(),
+            commit: report.get_commit()?.to_str()?.to_string(),
+            vms,
+        })
+    }
+
+    /// Token presented to the master, empty without one.
+    fn token(&self) -> &str {
+        self.config.token.as_deref().unwrap_or_default()
+    }
+
+    /// Bound a single RPC future by the configured timeout.
+    async fn call<T>(&self, fut: impl Future<Output = capnp::Result<T>>) -> Result<T, ClientError> {
+        tokio::time::timeout(self.config.timeout, fut)
+            .await
+            .map_err(|_| ClientError::Timeout(self.config.timeout))?
+            .map_err(ClientError::Rpc)
+    }
+}
+
+async fn connect_with_retries(config: &ClientConfig) -> Result<tokio::net::TcpStream, ClientError> {
+    let attempts = config.connect_retries.saturating_add(1);
+    let mut backoff = config.retry_backoff;
+    let mut attempt = 1;
+
+    loop {
+        let result =
+            match tokio::time::timeout(config.timeout, tokio::net::TcpStream::connect(config.addr))
+                .await
+            {
+                Ok(result) => result,
+                Err(_) => Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("connect timed out after {:?}", config.timeout),
+                )),
+            };
+
+        match result {
+            Ok(stream) => {
+                stream
+                    .set_nodelay(true)
+                    .map_err(|source| ClientError::Connect {
+                        addr: config.addr,
+                        attempts: attempt,
+                        source,
+                    })?;
+                return Ok(stream);
+            }
+            Err(source) if attempt >= attempts => {
+                return Err(ClientError::Connect {
+                    addr: config.addr,
+                    attempts: attempt,
+                    source,
+                });
+            }
+            Err(e) => {
+                warn!(addr = %config.addr, attempt, error = %e, retry_in = ?backoff, "Connect to master failed, retrying");
+                tokio::time::sleep(backoff).await;
+                backoff = backoff.saturating_mul(2);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn connect_gives_up_after_configured_retries() {
+        // Bind then drop to obtain a local port nothing listens on.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let config = ClientConfig::new(addr)
+            .with_connect_retries(2)
+            .with_retry_backoff(Duration::from_millis(1))
+            .with_timeout(Duration::from_secs(1));
+
+        let err = connect_with_retries(&config).await.unwrap_err();
+        match err {
+            ClientError::Connect { attempts, .. } => assert_eq!(attempts, 3),
+            other => panic!("expected Connect error, got {other:?}"),
+        }
+    }
+}
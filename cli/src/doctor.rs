@@ -0,0 +1,569 @@
+//! `pcr doctor`: check what the other commands rely on and say how to fix
+//! what is missing.
+//!
+//! Local checks cover the nix installation (version, `nix-command` and
+//! `flakes` enabled) and, for worker machines, `/dev/kvm`. Remote checks
+//! connect to the master and make an authenticated call with the token, and
+//! probe the binary cache and the CI service over HTTP when their URLs are
+//! given. HTTPS endpoints are only checked to accept connections, the CLI
+//! does not speak TLS.
+//!
+//! Every failing check prints the step that fixes it; the command fails
+//! when any check does, warnings alone do not.
+
+use std::fmt::{self, Write as _};
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+use crate::client::{ClientConfig, ClientError, MasterClient};
+
+/// Oldest nix with `nix config show`, which the other checks rely on.
+pub const MIN_NIX: (u32, u32) = (2, 20);
+
+const KVM: &str = "/dev/kvm";
+
+/// Features `pcr` and the CI builds need.
+const FEATURES: [&str; 2] = ["nix-command", "flakes"];
+
+/// Outcome of one check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        })
+    }
+}
+
+/// One line of the report, with how to fix it unless it passed.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn failed(
+        name: &'static str,
+        status: Status,
+        detail: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// What to check besides the local nix installation.
+#[derive(Debug, Clone)]
+pub struct DoctorConfig {
+    pub master: ClientConfig,
+    /// Binary cache URL, e.g. `http://cache:8081`
+    pub cache: Option<String>,
+    /// CI service URL, e.g. `http://ci:3000`
+    pub ci: Option<String>,
+    pub ci_token: Option<String>,
+    /// This machine runs a worker: a missing `/dev/kvm` fails instead of
+    /// warning
+    pub worker: bool,
+    pub timeout: Duration,
+}
+
+/// Run every check, in report order.
+///
+/// Talks to the master through `MasterClient`, so it must run inside a
+/// `tokio::task::LocalSet`.
+pub async fn doctor(config: &DoctorConfig) -> Vec<Check> {
+    let mut checks = nix_checks(config.cache.as_deref()).await;
+    checks.push(kvm_check(Path::new(KVM), config.worker));
+    checks.push(master_check(config.master.clone()).await);
+    if let Some(cache) = &config.cache {
+        checks.push(cache_check(cache, config.timeout).await);
+    }
+    if let Some(ci) = &config.ci {
+        checks.push(ci_check(ci, config.ci_token.as_deref(), config.timeout).await);
+    }
+    checks
+}
+
+/// The report printed by `pcr doctor`, one check per line followed by its
+/// fix.
+pub fn render(checks: &[Check]) -> String {
+    let width = checks
+        .iter()
+        .map(|c| c.name.len())
+        .max()
+        .unwrap_or_default();
+    let mut out = String::new();
+    for check in checks {
+        let _ = writeln!(
+            out,
+            "{:<4}  {:<width$}  {}",
+            check.status, check.name, check.detail
+        );
+        if let Some(fix) = &check.fix {
+            let _ = writeln!(out, "      {:<width$}  fix: {fix}", "");
+        }
+    }
+    out
+}
+
+// ─── Local ─────────────────────────────────────────────────────────────────
+
+async fn nix_checks(cache: Option<&str>) -> Vec<Check> {
+    let version = match nix(&["--version"]).await {
+        Ok(output) => output,
+        Err(e) => {
+            return vec![Check::failed(
+                "nix",
+                Status::Fail,
+                format!("cannot run nix: {e}"),
+                "install nix, e.g. `sh <(curl -L https://nixos.org/nix/install) --daemon`, \
+                 and open a new shell",
+            )];
+        }
+    };
+    let version = version.trim().to_string();
+    let mut checks = vec![match parse_nix_version(&version) {
+        Some(v) if v >= MIN_NIX => Check::ok("nix", version),
+        Some(_) => Check::failed(
+            "nix",
+            Status::Fail,
+            format!("{version} is older than {}.{}", MIN_NIX.0, MIN_NIX.1),
+            "upgrade nix, e.g. `nix upgrade-nix` or a newer `nix.package` on NixOS",
+        ),
+        None => Check::failed(
+            "nix",
+            Status::Warn,
+            format!("cannot tell the version from {version:?}"),
+            format!("make sure nix is at least {}.{}", MIN_NIX.0, MIN_NIX.1),
+        ),
+    }];
+
+    let enable = "add `experimental-features = nix-command flakes` to \
+                  ~/.config/nix/nix.conf, or `nix.settings.experimental-features` on NixOS";
+    checks.push(
+        match nix(&["config", "show", "experimental-features"]).await {
+            Ok(enabled) => match missing_features(&enabled).as_slice() {
+                [] => Check::ok("flakes", format!("enabled: {}", enabled.trim())),
+                missing => Check::failed(
+                    "flakes",
+                    Status::Fail,
+                    format!("not enabled: {}", missing.join(", ")),
+                    enable,
+                ),
+            },
+            // Without nix-command, `nix config` itself is refused
+            Err(e) => Check::failed("flakes", Status::Fail, e, enable),
+        },
+    );
+
+    if let Some(cache) = cache {
+        let substituters = nix(&["config", "show", "substituters"]).await;
+        if let Ok(substituters) = substituters {
+            checks.push(substituter_check(cache, &substituters));
+        }
+    }
+    checks
+}
+
+/// Stdout of `nix <args>`, or why it failed.
+async fn nix(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("nix")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().last().unwrap_or_default().trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// `(major, minor)` out of `nix --version`, e.g. `nix (Nix) 2.24.9`.
+fn parse_nix_version(output: &str) -> Option<(u32, u32)> {
+    let version = output.split_whitespace().last()?;
+    let mut parts = version.split(['.', 'p', 'r', '-', '+']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Required features missing from `nix config show experimental-features`.
+fn missing_features(enabled: &str) -> Vec<&'static str> {
+    let enabled: Vec<&str> = enabled.split_whitespace().collect();
+    FEATURES
+        .into_iter()
+        .filter(|feature| !enabled.contains(feature))
+        .collect()
+}
+
+fn substituter_check(cache: &str, substituters: &str) -> Check {
+    let cache = cache.trim_end_matches('/');
+    if substituters
+        .split_whitespace()
+        .any(|s| s.trim_end_matches('/') == cache)
+    {
+        Check::ok("substituter", format!("{cache} is a substituter"))
+    } else {
+        Check::failed(
+            "substituter",
+            Status::Warn,
+            format!("{cache} is not a substituter, builds will not fetch from it"),
+            format!(
+                "add `extra-substituters = {cache}` and the cache's key to \
+                 `extra-trusted-public-keys` in nix.conf"
+            ),
+        )
+    }
+}
+
+/// `/dev/kvm` exists and this user may open it; only workers need it.
+fn kvm_check(path: &Path, worker: bool) -> Check {
+    let status = if worker { Status::Fail } else { Status::Warn };
+    let needed = if worker {
+        ""
+    } else {
+        " (only needed on worker machines)"
+    };
+    if !path.exists() {
+        return Check::failed(
+            "kvm",
+            status,
+            format!("{} does not exist{needed}", path.display()),
+            "enable virtualization (VT-x/AMD-V) in the firmware and load `kvm_intel` or \
+             `kvm_amd`; VMs need nested virtualization enabled",
+        );
+    }
+    match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(_) => Check::ok("kvm", format!("{} is usable", path.display())),
+        Err(e) => Check::failed(
+            "kvm",
+            status,
+            format!("cannot open {}: {e}{needed}", path.display()),
+            "add the user running the worker to the `kvm` group and log in again",
+        ),
+    }
+}
+
+// ─── Remote ────────────────────────────────────────────────────────────────
+
+async fn master_check(config: ClientConfig) -> Check {
+    let result = async {
+        let client = MasterClient::connect(config).await?;
+        client.cluster_status("").await
+    }
+    .await;
+    match result {
+        Ok(status) => Check::ok(
+            "master",
+            format!(
+                "generation {}, {} worker(s), {}% converged",
+                status.active_generation,
+                status.workers.len(),
+                status.convergence_percent
+            ),
+        ),
+        Err(e @ ClientError::Connect { .. }) => Check::failed(
+            "master",
+            Status::Fail,
+            repo_outils::report(&e),
+            "check the master is running and reachable, and point `--master` or \
+             PROCURATOR_MASTER at it",
+        ),
+        Err(e @ ClientError::Rejected(_)) => Check::failed(
+            "master",
+            Status::Fail,
+            repo_outils::report(&e),
+            "set `--token` or PROCURATOR_TOKEN to a token the master accepts",
+        ),
+        Err(e) => Check::failed(
+            "master",
+            Status::Fail,
+            repo_outils::report(&e),
+            "check `--master` is the master's RPC address and not another service",
+        ),
+    }
+}
+
+async fn cache_check(url: &str, timeout: Duration) -> Check {
+    let unreachable = "check the cache is running and the URL, e.g. `--cache http://cache:8081`";
+    match get(url, "/nix-cache-info", None, timeout).await {
+        Ok(Response::Http(200, body)) if body.contains("StoreDir:") => {
+            Check::ok("cache", format!("{url} serves a binary cache"))
+        }
+        Ok(Response::Http(status, _)) => Check::failed(
+            "cache",
+            Status::Fail,
+            format!("{url}/nix-cache-info answered {status}"),
+            "check the URL points at the binary cache and not another service",
+        ),
+        Ok(Response::Reachable) => Check::ok("cache", format!("{url} accepts connections")),
+        Err(e) => Check::failed("cache", Status::Fail, e, unreachable),
+    }
+}
+
+async fn ci_check(url: &str, token: Option<&str>, timeout: Duration) -> Check {
+    match get(url, "/health", None, timeout).await {
+        Ok(Response::Http(200, _)) => {}
+        Ok(Response::Http(status, _)) => {
+            return Check::failed(
+                "ci",
+                Status::Fail,
+                format!("{url}/health answered {status}"),
+                "check the URL points at the CI service",
+            );
+        }
+        Ok(Response::Reachable) => {
+            return Check::ok("ci", format!("{url} accepts connections"));
+        }
+        Err(e) => {
+            return Check::failed(
+                "ci",
+                Status::Fail,
+                e,
+                "check the CI service is running and the URL, e.g. `--ci http://ci:3000`",
+            );
+        }
+    }
+    let Some(token) = token else {
+        return Check::ok("ci", format!("{url} is up, no token to check"));
+    };
+    match get(url, "/api/v1/builds", Some(token), timeout).await {
+        Ok(Response::Http(401 | 403, _)) => Check::failed(
+            "ci",
+            Status::Fail,
+            format!("{url} refused the token"),
+            "set `--ci-token` or PROCURATOR_CI_TOKEN to a repohub API token",
+        ),
+        Ok(Response::Http(status, _)) if status >= 400 => Check::failed(
+            "ci",
+            Status::Warn,
+            format!("{url}/api/v1/builds answered {status}"),
+            "check the CI service logs",
+        ),
+        Ok(_) => Check::ok("ci", format!("{url} is up and accepts the token")),
+        Err(e) => Check::failed("ci", Status::Fail, e, "check the CI service logs"),
+    }
+}
+
+#[derive(Debug)]
+enum Response {
+    /// Status and body
+    Http(u16, String),
+    /// HTTPS endpoint that accepted the connection
+    Reachable,
+}
+
+/// `GET` `path` under `url` over HTTP/1.0, or only connect for `https://`.
+async fn get(
+    url: &str,
+    path: &str,
+    bearer: Option<&str>,
+    timeout: Duration,
+) -> Result<Response, String> {
+    let endpoint = Endpoint::parse(url)?;
+    let connect = TcpStream::connect((endpoint.host.as_str(), endpoint.port));
+    let mut stream = tokio::time::timeout(timeout, connect)
+        .await
+        .map_err(|_| format!("{url} did not answer within {timeout:?}"))?
+        .map_err(|e| format!("cannot connect to {url}: {e}"))?;
+    if endpoint.tls {
+        return Ok(Response::Reachable);
+    }
+
+    let mut request = format!(
+        "GET {}{path} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n",
+        endpoint.base, endpoint.host
+    );
+    if let Some(token) = bearer {
+        let _ = write!(request, "Authorization: Bearer {token}\r\n");
+    }
+    request.push_str("\r\n");
+
+    let exchange = async {
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| format!("{url} did not answer within {timeout:?}"))?
+        .map_err(|e| format!("{url}: {e}"))?;
+    let response = String::from_utf8_lossy(&response);
+    let status = status_code(&response).ok_or_else(|| format!("{url} did not answer HTTP"))?;
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_string())
+        .unwrap_or_default();
+    Ok(Response::Http(status, body))
+}
+
+fn status_code(response: &str) -> Option<u16> {
+    let line = response.lines().next()?;
+    let (version, rest) = line.split_once(' ')?;
+    if !version.starts_with("HTTP/") {
+        return None;
+    }
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+/// Where an `http(s)://host[:port][/base]` URL points.
+#[derive(Debug, PartialEq, Eq)]
+struct Endpoint {
+    tls: bool,
+    host: String,
+    port: u16,
+    /// Path prefix, without the trailing slash
+    base: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self, String> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else {
+            return Err(format!("{url:?} is not an http(s) URL"));
+        };
+        let (authority, base) = rest.split_once('/').unwrap_or((rest, ""));
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("invalid port {port:?} in {url:?}"))?,
+            ),
+            _ => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(format!("{url:?} has no host"));
+        }
+        let base = base.trim_end_matches('/');
+        Ok(Self {
+            tls,
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            base: if base.is_empty() {
+                String::new()
+            } else {
+                format!("/{base}")
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nix_output_is_parsed() {
+        assert_eq!(parse_nix_version("nix (Nix) 2.24.9\n"), Some((2, 24)));
+        assert_eq!(
+            parse_nix_version("nix (Nix) 2.18.1pre20231010"),
+            Some((2, 18))
+        );
+        assert_eq!(
+            parse_nix_version("nix (Lix, like Nix) 2.91.1"),
+            Some((2, 91))
+        );
+        assert_eq!(parse_nix_version("nix"), None);
+
+        assert!(missing_features("ca-derivations flakes nix-command\n").is_empty());
+        assert_eq!(missing_features("nix-command"), vec!["flakes"]);
+        assert_eq!(missing_features(""), vec!["nix-command", "flakes"]);
+    }
+
+    #[test]
+    fn the_cache_should_be_a_substituter() {
+        let substituters = "https://cache.nixos.org/ http://cache:8081/\n";
+        assert_eq!(
+            substituter_check("http://cache:8081", substituters).status,
+            Status::Ok
+        );
+        let check = substituter_check("http://other:8081", substituters);
+        assert_eq!(check.status, Status::Warn);
+        assert!(
+            check
+                .fix
+                .unwrap()
+                .contains("extra-substituters = http://other:8081")
+        );
+    }
+
+    #[test]
+    fn kvm_is_only_required_on_workers() {
+        let missing = Path::new("/nonexistent/kvm");
+        assert_eq!(kvm_check(missing, false).status, Status::Warn);
+        let check = kvm_check(missing, true);
+        assert_eq!(check.status, Status::Fail);
+        assert!(!check.detail.contains("only needed"));
+    }
+
+    #[test]
+    fn endpoints_and_responses_are_parsed() {
+        assert_eq!(
+            Endpoint::parse("http://cache:8081/").unwrap(),
+            Endpoint {
+                tls: false,
+                host: "cache".to_string(),
+                port: 8081,
+                base: String::new(),
+            }
+        );
+        let ci = Endpoint::parse("https://example.com/ci").unwrap();
+        assert_eq!((ci.tls, ci.port, ci.base.as_str()), (true, 443, "/ci"));
+        assert_eq!(Endpoint::parse("http://[::1]:3000").unwrap().host, "::1");
+        assert!(Endpoint::parse("cache:8081").is_err());
+        assert!(Endpoint::parse("http://cache:port").is_err());
+
+        assert_eq!(status_code("HTTP/1.1 401 Unauthorized\r\n\r\n"), Some(401));
+        assert_eq!(status_code("SSH-2.0-OpenSSH_9.6\r\n"), None);
+    }
+
+    #[test]
+    fn failures_print_their_fix() {
+        let report = render(&[
+            Check::ok("nix", "nix (Nix) 2.24.9"),
+            Check::failed("kvm", Status::Warn, "missing", "load kvm"),
+        ]);
+        assert_eq!(
+            report,
+            "ok    nix  nix (Nix) 2.24.9\nwarn  kvm  missing\n           fix: load kvm\n"
+        );
+    }
+}
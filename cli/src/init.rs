@@ -2,9 +2,9 @@
 // TODO: We want to leverage direnv also so we need to check if .envrc exists and if it's configured
 use std::{env, path::PathBuf};
 
-use autonix::Parser;
+use autonix::{OutputTarget, Parser};
 
-pub fn init(path: Option<PathBuf>) {
+pub fn init(path: Option<PathBuf>, target: OutputTarget) {
     tracing::info!("Running autonix");
     let get_current_path = || env::current_dir().expect("Failed to get current directory");
     let path = path
@@ -19,11 +19,31 @@ pub fn init(path: Option<PathBuf>) {
         return;
     };
 
-    let parser = Parser::from(path).scan().analyse().build();
+    let analysis = Parser::from(path).scan().analyse();
+    let env_reports = analysis.env_reports();
+    let parser = analysis.build();
 
     save_and_log(|p| parser.as_json(p), &config_path);
+    save_and_log(
+        |p| std::fs::write(p, serde_json::to_string_pretty(&env_reports).expect("")),
+        &config_path.with_file_name("env.json"),
+    );
+    for report in &env_reports {
+        for secret in report.secrets() {
+            tracing::warn!(
+                repo = report.repo(),
+                "Secret {secret} must be provided, the dev shell only stubs it"
+            );
+        }
+    }
     save_and_log(|p| parser.as_nix(p), &config_path.with_extension("nix"));
-    save_and_log(|p| parser.generate(p), &config_path.with_file_name("flake.nix"));
+    let dir = config_path.parent().expect("this is a batman dir");
+    for path in parser
+        .generate_target(target, dir)
+        .expect("Failed to save the generated Nix files")
+    {
+        tracing::info!(%target, "Configuration saved to {}", path.display());
+    }
 
     // parser
     //     .as_json(&config_path)
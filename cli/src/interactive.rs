@@ -0,0 +1,495 @@
+//! Interactive REPL for `pcr`.
+//!
+//! Every line is parsed with the same clap definition as the non-interactive
+//! CLI, so `cluster status` typed at the prompt behaves exactly like
+//! `pcr cluster status`. Cluster, describe and vm commands share one
+//! `MasterClient` that is connected lazily on first use and kept for the
+//! whole session.
+//!
+//! Lines are read with rustyline: the usual line editing, arrow keys and
+//! Ctrl-R through history, and Tab completion. History is persisted to
+//! `$XDG_STATE_HOME/procurator/history` (falling back to
+//! `~/.local/state/procurator/history`). Lines can also be recalled with
+//! `!N`.
+//!
+//! Tab completes subcommand names from the clap tree, and worker and VM ids
+//! from the cluster status. The ids are fetched on the first Tab that
+//! completes one and cached until `refresh`, so a session that never asks
+//! for them never waits on the master for them.
+
+use std::cell::{Ref, RefCell};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use clap::{CommandFactory, Parser};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{Config, Context, Editor, Helper};
+use tracing::warn;
+
+use crate::cli::{Cli, Error};
+use crate::client::{ClientConfig, ClientError, MasterClient};
+
+type FetchError = Box<dyn std::error::Error + Send + Sync>;
+
+const PROMPT: &str = "pcr> ";
+const MAX_HISTORY: usize = 1000;
+
+// ─── Session ───────────────────────────────────────────────────────────────
+
+/// State shared by all commands of one interactive session.
+pub struct Session {
+    config: ClientConfig,
+    client: Option<MasterClient>,
+}
+
+/// Worker and VM ids last seen in the cluster status.
+#[derive(Debug, Clone, Default)]
+struct KnownIds {
+    workers: BTreeSet<String>,
+    vms: BTreeSet<String>,
+}
+
+impl Session {
+    fn new(config: ClientConfig) -> Self {
+        Self {
+            config,
+            client: None,
+        }
+    }
+
+    /// Master connection, established on first use and reused afterwards.
+    pub async fn client(&mut self) -> Result<&MasterClient, ClientError> {
+        if self.client.is_none() {
+            self.client = Some(MasterClient::connect(self.config.clone()).await?);
+        }
+        Ok(self.client.as_ref().expect("client was just connected"))
+    }
+
+    /// Where the master is, for commands that manage their own connection.
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Forget a connection that failed so the next command reconnects.
+    fn disconnect(&mut self) {
+        self.client = None;
+    }
+}
+
+/// Worker and VM ids in the cluster status.
+///
+/// Completion runs while `readline` blocks the session's runtime, so this
+/// connects from a thread and runtime of its own.
+fn fetch_ids(config: &ClientConfig) -> Result<KnownIds, FetchError> {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                let local = tokio::task::LocalSet::new();
+                local.block_on(&runtime, async {
+                    let client = MasterClient::connect(config.clone()).await?;
+                    let status = client.cluster_status("").await?;
+                    Ok::<_, FetchError>(KnownIds {
+                        workers: status.workers.into_iter().map(|w| w.id).collect(),
+                        vms: status.vms.into_iter().map(|vm| vm.id).collect(),
+                    })
+                })
+            })
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+// ─── History ───────────────────────────────────────────────────────────────
+
+fn default_history_path() -> Option<PathBuf> {
+    let state_dir = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/state")))?;
+    Some(state_dir.join("procurator").join("history"))
+}
+
+/// Resolve `!N` (1-based) to the stored line.
+fn expand<'a>(entries: &[&'a str], line: &'a str) -> Option<&'a str> {
+    match line.strip_prefix('!') {
+        Some(n) => n
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|i| entries.get(i))
+            .copied(),
+        None => Some(line),
+    }
+}
+
+// ─── Completion ────────────────────────────────────────────────────────────
+
+/// What the word under the cursor should complete to.
+#[derive(Debug, PartialEq, Eq)]
+enum Slot {
+    Subcommands(Vec<String>),
+    WorkerId,
+    VmId,
+    Nothing,
+}
+
+/// Walk the clap tree along the already-typed words to find the completion slot.
+fn completion_slot(words: &[&str]) -> Slot {
+    let mut command = Cli::command();
+    let mut path = Vec::new();
+
+    for word in words {
+        match command.find_subcommand(word) {
+            Some(sub) => {
+                path.push(sub.get_name().to_string());
+                command = sub.clone();
+            }
+            None => break,
+        }
+    }
+
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+    match path.as_slice() {
+        ["cluster", "worker"] | ["describe", "worker"] => Slot::WorkerId,
+        ["cluster", "vm"] | ["describe", "vm"] | ["vm", _] => Slot::VmId,
+        _ => {
+            let subcommands: Vec<String> = command
+                .get_subcommands()
+                .map(|s| s.get_name().to_string())
+                .filter(|name| name != "help")
+                .collect();
+            if subcommands.is_empty() {
+                Slot::Nothing
+            } else {
+                Slot::Subcommands(subcommands)
+            }
+        }
+    }
+}
+
+/// Rustyline helper completing the word before the cursor.
+struct PcrHelper {
+    config: ClientConfig,
+    /// Fetched on the first Tab completing an id, until `refresh`
+    ids: RefCell<Option<KnownIds>>,
+}
+
+impl PcrHelper {
+    fn new(config: ClientConfig) -> Self {
+        Self {
+            config,
+            ids: RefCell::new(None),
+        }
+    }
+
+    /// Ids to complete. A master that cannot be asked leaves nothing to
+    /// complete.
+    fn known_ids(&self) -> Ref<'_, KnownIds> {
+        if self.ids.borrow().is_none() {
+            let ids = fetch_ids(&self.config).unwrap_or_else(|e| {
+                let error = repo_outils::report(&*e);
+                warn!(%error, "Cannot fetch ids from the master");
+                KnownIds::default()
+            });
+            *self.ids.borrow_mut() = Some(ids);
+        }
+        Ref::map(self.ids.borrow(), |ids| {
+            ids.as_ref().expect("ids were just fetched")
+        })
+    }
+
+    /// Drop the cached ids so they are fetched again.
+    fn refresh(&mut self) {
+        *self.ids.get_mut() = None;
+    }
+
+    /// Candidates for the last word of `line`, and where that word starts.
+    fn candidates(&self, line: &str) -> (usize, Vec<String>) {
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let (done, prefix) = line.split_at(start);
+        let words: Vec<&str> = done.split_whitespace().collect();
+        let candidates = match completion_slot(&words) {
+            Slot::Subcommands(names) => names,
+            Slot::WorkerId => self.known_ids().workers.iter().cloned().collect(),
+            Slot::VmId => self.known_ids().vms.iter().cloned().collect(),
+            Slot::Nothing => Vec::new(),
+        };
+        let candidates = candidates
+            .into_iter()
+            .filter(|c| c.starts_with(prefix))
+            .collect();
+        (start, candidates)
+    }
+}
+
+impl Completer for PcrHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(&line[..pos]))
+    }
+}
+
+impl Hinter for PcrHelper {
+    type Hint = String;
+}
+
+impl Highlighter for PcrHelper {}
+
+impl Validator for PcrHelper {}
+
+impl Helper for PcrHelper {}
+
+// ─── Line parsing ──────────────────────────────────────────────────────────
+
+/// Split a line into words, honouring single and double quotes.
+fn split_words(line: &str) -> Result<Vec<String>, Error> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err(Error::InvalidCommand("unterminated quote".to_string()));
+    }
+    if in_word {
+        words.push(current);
+    }
+    Ok(words)
+}
+
+// ─── REPL ──────────────────────────────────────────────────────────────────
+
+fn print_help() {
+    println!("Commands are the same as `pcr <command>`, e.g. `cluster status`.");
+    println!("  help          show this message (use `--help` for command help)");
+    println!("  history       list previous lines, recall one with !N");
+    println!("                (`generations` lists the cluster's generations)");
+    println!("  refresh       fetch worker/VM ids for completion again");
+    println!("  exit | quit   leave the session");
+    println!("Tab completes commands and worker/VM ids.");
+}
+
+fn readline_error(e: ReadlineError) -> Error {
+    match e {
+        ReadlineError::Io(e) => Error::IoError(e),
+        e => Error::IoError(std::io::Error::other(e)),
+    }
+}
+
+/// Run the interactive session until EOF or `exit`.
+pub async fn run(config: ClientConfig, history_path: Option<PathBuf>) -> Result<(), Error> {
+    let mut session = Session::new(config);
+    let history_path = history_path.or_else(default_history_path);
+    let editor_config = Config::builder()
+        .max_history_size(MAX_HISTORY)
+        .and_then(|builder| builder.history_ignore_dups(true))
+        .map_err(readline_error)?
+        .auto_add_history(false)
+        .build();
+    let mut editor: Editor<PcrHelper, FileHistory> =
+        Editor::with_config(editor_config).map_err(readline_error)?;
+    editor.set_helper(Some(PcrHelper::new(session.config.clone())));
+    if let Some(path) = history_path.as_ref().filter(|path| path.exists())
+        && let Err(e) = editor.load_history(path)
+    {
+        warn!(error = %e, "Failed to load history");
+    }
+
+    loop {
+        let raw = match editor.readline(PROMPT) {
+            Ok(raw) => raw,
+            // Ctrl-C drops the line, Ctrl-D leaves
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => {
+                println!();
+                return Ok(());
+            }
+            Err(e) => return Err(readline_error(e)),
+        };
+
+        let entries: Vec<&str> = editor.history().iter().map(String::as_str).collect();
+        let Some(line) = expand(&entries, raw.trim()).map(str::to_string) else {
+            eprintln!("no such history entry: {}", raw.trim());
+            continue;
+        };
+        if line.is_empty() {
+            continue;
+        }
+        if line != raw.trim() {
+            println!("{line}");
+        }
+        if let Err(e) = add_history(&mut editor, history_path.as_deref(), &line) {
+            warn!(error = %e, "Failed to persist history");
+        }
+
+        match line.as_str() {
+            "exit" | "quit" => return Ok(()),
+            "help" => {
+                print_help();
+                continue;
+            }
+            "history" => {
+                for (i, entry) in editor.history().iter().enumerate() {
+                    println!("{:>5}  {entry}", i + 1);
+                }
+                continue;
+            }
+            "refresh" => {
+                if let Some(helper) = editor.helper_mut() {
+                    helper.refresh();
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let words = match split_words(&line) {
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("{e}");
+                continue;
+            }
+        };
+
+        let cli = match Cli::try_parse_from(std::iter::once("pcr".to_string()).chain(words)) {
+            Ok(cli) => cli,
+            Err(e) => {
+                // Covers `--help` and `--version` as well as real parse errors.
+                let _ = e.print();
+                continue;
+            }
+        };
+
+        if let Err(e) = cli.run_in_session(&mut session).await {
+            eprintln!("error: {}", repo_outils::report(&e));
+            if matches!(
+                e,
+                Error::RequestFailed(_) | Error::Client(_) | Error::Wait(_)
+            ) {
+                session.disconnect();
+                if let Some(helper) = editor.helper_mut() {
+                    helper.refresh();
+                }
+            }
+        }
+    }
+}
+
+/// Add `line` to the history and append it to the file at `path`.
+fn add_history(
+    editor: &mut Editor<PcrHelper, FileHistory>,
+    path: Option<&Path>,
+    line: &str,
+) -> Result<(), ReadlineError> {
+    editor.add_history_entry(line)?;
+    let Some(path) = path else {
+        return Ok(());
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    editor.append_history(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_words_handles_quotes() {
+        let words = split_words(r#"vcs clone "my repo" 'x y' z"#).unwrap();
+        assert_eq!(words, vec!["vcs", "clone", "my repo", "x y", "z"]);
+    }
+
+    #[test]
+    fn split_words_rejects_unterminated_quote() {
+        assert!(split_words("vcs clone \"oops").is_err());
+    }
+
+    #[test]
+    fn top_level_completion_lists_subcommands() {
+        let Slot::Subcommands(names) = completion_slot(&[]) else {
+            panic!("expected subcommands");
+        };
+        assert!(names.contains(&"cluster".to_string()));
+        assert!(names.contains(&"stack".to_string()));
+        assert!(!names.contains(&"help".to_string()));
+    }
+
+    #[test]
+    fn cluster_worker_completes_worker_ids() {
+        assert_eq!(completion_slot(&["cluster", "worker"]), Slot::WorkerId);
+        assert_eq!(completion_slot(&["describe", "worker"]), Slot::WorkerId);
+    }
+
+    #[test]
+    fn cluster_vm_completes_vm_ids() {
+        assert_eq!(completion_slot(&["cluster", "vm"]), Slot::VmId);
+        assert_eq!(completion_slot(&["describe", "vm"]), Slot::VmId);
+        assert_eq!(completion_slot(&["vm", "restart"]), Slot::VmId);
+    }
+
+    #[test]
+    fn history_expands_bang_references() {
+        let entries = ["cluster status", "cluster worker w1"];
+        assert_eq!(expand(&entries, "!1"), Some("cluster status"));
+        assert_eq!(expand(&entries, "!9"), None);
+        assert_eq!(expand(&entries, "help"), Some("help"));
+    }
+
+    #[test]
+    fn tab_completes_the_word_before_the_cursor() {
+        // Nothing listens there: a fetch would leave no ids
+        let mut helper = PcrHelper::new(ClientConfig::new(([127, 0, 0, 1], 9).into()));
+        let (start, names) = helper.candidates("clu");
+        assert_eq!((start, names), (0, vec!["cluster".to_string()]));
+        assert!(helper.ids.borrow().is_none(), "commands need no ids");
+
+        *helper.ids.get_mut() = Some(KnownIds {
+            workers: BTreeSet::from(["w1".to_string()]),
+            vms: BTreeSet::from(["vm-a".to_string(), "vm-b".to_string(), "db".to_string()]),
+        });
+        assert_eq!(
+            helper.candidates("describe vm vm-"),
+            (12, vec!["vm-a".to_string(), "vm-b".to_string()])
+        );
+        assert_eq!(
+            helper.candidates("cluster worker "),
+            (15, vec!["w1".to_string()])
+        );
+    }
+}
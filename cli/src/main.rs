@@ -1,16 +1,39 @@
 mod cli;
+mod client;
+mod doctor;
 mod init;
+mod interactive;
+mod sbom;
+mod wait;
 
 use cli::Cli;
+use commands::telemetry::{self, TelemetryConfig};
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    tracing_subscriber::fmt()
-    .with_env_filter("info")
-    .init();
-    Cli::handle().await.unwrap_or_else(|err| {
-        tracing::error!(?err, "Error");
+    // Spans are exported when OTEL_EXPORTER_OTLP_ENDPOINT is set.
+    let (otel, otel_guard) = telemetry::otel_layer(&TelemetryConfig::from_env("pcr"))
+        .unwrap_or_else(|e| {
+            eprintln!("invalid OTLP exporter configuration: {e}");
+            std::process::exit(1);
+        });
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::new("info"))
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel)
+        .init();
+
+    let result = Cli::handle().await;
+    otel_guard.shutdown();
+    result.unwrap_or_else(|err| {
+        if let cli::Error::Exited(code) = err {
+            std::process::exit(code);
+        }
+        let error = repo_outils::report(&err);
+        tracing::error!(%error, "Error");
         std::process::exit(1);
     });
 }
@@ -0,0 +1,45 @@
+// Write the CycloneDX SBOM of every repo found under a path and print their license summaries
+use std::{env, path::PathBuf};
+
+use autonix::Parser;
+
+use crate::cli::Error;
+
+pub fn sbom(path: Option<PathBuf>, output: Option<PathBuf>) -> Result<(), Error> {
+    let get_current_path = || env::current_dir().expect("Failed to get current directory");
+    let path = path
+        .unwrap_or_else(get_current_path)
+        .canonicalize()
+        .map_err(Error::IoError)?;
+    let output = output.unwrap_or_else(|| path.join(".procurator").join("sbom"));
+    std::fs::create_dir_all(&output).map_err(Error::IoError)?;
+
+    let sboms = Parser::from(path).scan().analyse().sboms();
+    if sboms.is_empty() {
+        println!("No lockfile or license found");
+        return Ok(());
+    }
+
+    for sbom in &sboms {
+        let file = output.join(format!("{}.cdx.json", sbom.repo()));
+        let document = sbom
+            .to_cyclonedx_json()
+            .map_err(|e| Error::IoError(e.into()))?;
+        std::fs::write(&file, document).map_err(Error::IoError)?;
+
+        let licenses = sbom.licenses();
+        let declared = if licenses.declared.is_empty() {
+            "none declared".to_string()
+        } else {
+            licenses.declared.join(", ")
+        };
+        println!("{} ({declared}) -> {}", sbom.repo(), file.display());
+        for (license, count) in &licenses.dependencies {
+            println!("  {count:>5}  {license}");
+        }
+        if licenses.unknown > 0 {
+            println!("  {:>5}  unknown", licenses.unknown);
+        }
+    }
+    Ok(())
+}
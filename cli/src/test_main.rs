@@ -2,11 +2,13 @@
 //!
 //! Covers all Worker RPC methods defined in worker.capnp:
 //! - read: fetch worker status
-//! - list-vms: list all managed VMs
+//! - list-vms: list managed VMs, filtered by label selector and paginated
 //! - create-vm: create a VM from a spec (JSON file or individual flags)
+//! - update-vm: move a VM to a new spec, resized in place when only cpu and memory change
 //! - delete-vm: destroy a VM by ID
 
 use clap::{Args, Parser, Subcommand};
+use commands::labels::{Labels, Page, Selector, parse_label};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 
@@ -29,16 +31,34 @@ enum Commands {
     /// Fetch worker status (Worker.read)
     Read,
 
-    /// List all VMs (Worker.listVms)
-    ListVms,
+    /// List VMs (Worker.listVms)
+    ListVms(ListVmsArgs),
 
     /// Create a VM from a spec (Worker.createVm)
     CreateVm(CreateVmArgs),
 
+    /// Move a VM to a new spec (Worker.updateVm)
+    UpdateVm(UpdateVmArgs),
+
     /// Delete a VM by ID (Worker.deleteVm)
     DeleteVm(DeleteVmArgs),
 }
 
+#[derive(Debug, Args)]
+struct ListVmsArgs {
+    /// Label selector, e.g. `app=web,tier!=db,canary,!legacy`
+    #[arg(short = 'l', long)]
+    selector: Option<Selector>,
+
+    /// Most VMs returned, 0 for all
+    #[arg(long, default_value = "0")]
+    limit: u32,
+
+    /// Cursor printed with the previous page
+    #[arg(long, default_value = "")]
+    cursor: String,
+}
+
 #[derive(Debug, Args)]
 struct CreateVmArgs {
     /// Path to a VM spec JSON file (output of `nix build .#vmSpecJson`)
@@ -76,6 +96,19 @@ struct CreateVmArgs {
     /// Allowed network domains (can be repeated)
     #[arg(long)]
     allowed_domain: Vec<String>,
+
+    /// Label as key=value (can be repeated, added to the spec file's)
+    #[arg(long, value_parser = parse_label)]
+    label: Vec<(String, String)>,
+}
+
+#[derive(Debug, Args)]
+struct UpdateVmArgs {
+    /// VM ID to update
+    id: String,
+
+    #[command(flatten)]
+    spec: CreateVmArgs,
 }
 
 #[derive(Debug, Args)]
@@ -102,11 +135,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             match cli.command {
                 Commands::Read => worker_client::read(&client).await?,
-                Commands::ListVms => worker_client::list_vms(&client).await?,
+                Commands::ListVms(args) => {
+                    let page = Page {
+                        limit: args.limit,
+                        cursor: args.cursor,
+                    };
+                    worker_client::list_vms(&client, &args.selector.unwrap_or_default(), &page)
+                        .await?;
+                }
                 Commands::CreateVm(args) => {
                     let spec = args.resolve()?;
                     worker_client::create_vm(&client, spec).await?;
                 }
+                Commands::UpdateVm(args) => {
+                    let spec = args.spec.resolve()?;
+                    worker_client::update_vm(&client, &args.id, spec).await?;
+                }
                 Commands::DeleteVm(args) => {
                     worker_client::delete_vm(&client, &args.id).await?;
                 }
@@ -130,6 +174,8 @@ pub struct VmSpecJson {
     pub memory_mb: u32,
     #[serde(default)]
     pub network_allowed_domains: Vec<String>,
+    #[serde(default)]
+    pub labels: Labels,
 }
 
 impl CreateVmArgs {
@@ -137,8 +183,9 @@ impl CreateVmArgs {
         if let Some(path) = self.spec_file {
             let contents = std::fs::read_to_string(&path)
                 .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
-            let spec: VmSpecJson = serde_json::from_str(&contents)
+            let mut spec: VmSpecJson = serde_json::from_str(&contents)
                 .map_err(|e| format!("invalid JSON in {}: {e}", path.display()))?;
+            spec.labels.extend(self.label);
             Ok(spec)
         } else {
             Ok(VmSpecJson {
@@ -150,6 +197,7 @@ impl CreateVmArgs {
                 cpu: self.cpu,
                 memory_mb: self.memory_mb,
                 network_allowed_domains: self.allowed_domain,
+                labels: self.label.into_iter().collect(),
             })
         }
     }
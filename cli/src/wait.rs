@@ -0,0 +1,372 @@
+//! `pcr wait`: block until a generation converges or an object meets a
+//! condition, so deploy scripts can wait on the cluster instead of sleeping.
+//!
+//! The master has no watch RPC, so the object is described every
+//! `--interval` until the condition holds. The command fails once
+//! `--timeout` passes, or right away when the condition can no longer hold,
+//! e.g. the generation was superseded.
+//!
+//! Conditions are those `pcr describe` shows. `converged` is the `Converged`
+//! condition of a generation, `ready` is `Running` and `InSync` for a VM and
+//! `Reporting` for a worker. Objects the master does not know yet are waited
+//! for, so `pcr wait vm/<id>` may start before the VM is reported.
+//!
+//! Losing the master (refused connections, timed out or dropped calls) is
+//! waited out too: it is reconnected on the next check, until `--timeout`.
+
+use std::fmt;
+use std::time::Duration;
+
+use commands::master_capnp::ObjectKind;
+use tokio::time::Instant;
+
+use crate::client::{ClientConfig, ClientError, Description, MasterClient};
+
+/// What to wait on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// The active generation when `None`
+    Generation(Option<u64>),
+    Vm(String),
+    Worker(String),
+}
+
+impl Target {
+    /// Parse `vm/<id>`, `worker/<id>` or `generation/<n>`.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let (kind, id) = raw
+            .split_once('/')
+            .filter(|(_, id)| !id.is_empty())
+            .ok_or_else(|| format!("expected <kind>/<id>, got {raw:?}"))?;
+        match kind {
+            "vm" => Ok(Target::Vm(id.to_string())),
+            "worker" => Ok(Target::Worker(id.to_string())),
+            "generation" => id
+                .parse()
+                .map(|n| Target::Generation(Some(n)))
+                .map_err(|_| format!("invalid generation number {id:?}")),
+            _ => Err(format!(
+                "unknown kind {kind:?}, expected vm, worker or generation"
+            )),
+        }
+    }
+
+    /// Condition waited for when `--for` is not given.
+    fn default_condition(&self) -> WaitFor {
+        match self {
+            Target::Generation(_) => WaitFor::Converged,
+            Target::Vm(_) | Target::Worker(_) => WaitFor::Ready,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Generation(Some(n)) => write!(f, "generation/{n}"),
+            Target::Generation(None) => f.write_str("generation"),
+            Target::Vm(id) => write!(f, "vm/{id}"),
+            Target::Worker(id) => write!(f, "worker/{id}"),
+        }
+    }
+}
+
+/// Condition to wait for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaitFor {
+    Converged,
+    Ready,
+    /// Any condition by name, e.g. `AtGeneration`
+    Condition(String),
+}
+
+impl WaitFor {
+    /// Parse `converged`, `ready` or a condition name, optionally written
+    /// `condition=<name>`.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        match raw {
+            "converged" => Ok(WaitFor::Converged),
+            "ready" => Ok(WaitFor::Ready),
+            name => {
+                let name = name.strip_prefix("condition=").unwrap_or(name);
+                if name.is_empty() {
+                    return Err("expected converged, ready or a condition name".to_string());
+                }
+                Ok(WaitFor::Condition(name.to_string()))
+            }
+        }
+    }
+
+    /// Conditions that must all be true on `target`.
+    fn conditions(&self, target: &Target) -> Result<Vec<&str>, String> {
+        match (self, target) {
+            (WaitFor::Converged, Target::Generation(_)) => Ok(vec!["Converged"]),
+            (WaitFor::Converged, _) => Err("--for=converged only applies to generations".into()),
+            (WaitFor::Ready, Target::Vm(_)) => Ok(vec!["Running", "InSync"]),
+            (WaitFor::Ready, Target::Worker(_)) => Ok(vec!["Reporting"]),
+            (WaitFor::Ready, Target::Generation(_)) => Ok(vec!["Converged"]),
+            (WaitFor::Condition(name), _) => Ok(vec![name.as_str()]),
+        }
+    }
+}
+
+/// Where a described object stands against the awaited conditions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    Met,
+    /// Not yet, with what is still missing
+    Pending(String),
+    /// It will not happen, e.g. the generation was superseded
+    Failed(String),
+}
+
+/// Check `description` against `conditions`.
+pub fn progress(description: &Description, conditions: &[&str]) -> Progress {
+    if let Some(active) = description.conditions.iter().find(|c| c.name == "Active")
+        && !active.status
+        && active.reason == "Superseded"
+    {
+        return Progress::Failed(active.message.clone());
+    }
+    let mut missing = Vec::new();
+    for name in conditions {
+        match description.conditions.iter().find(|c| c.name == *name) {
+            Some(c) if c.status => {}
+            Some(c) if c.message.is_empty() => missing.push(format!("{name}: {}", c.reason)),
+            Some(c) => missing.push(format!("{name}: {} ({})", c.reason, c.message)),
+            None => missing.push(format!("{name}: not reported")),
+        }
+    }
+    if missing.is_empty() {
+        Progress::Met
+    } else {
+        Progress::Pending(missing.join(", "))
+    }
+}
+
+/// Parse `500ms`, `30s`, `10m`, `1h`, or plain seconds.
+pub fn parse_duration(raw: &str) -> Result<Duration, String> {
+    let split = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (value, unit) = raw.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration {raw:?}, expected e.g. 30s or 10m"))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "" | "s" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_secs(value.saturating_mul(60))),
+        "h" => Ok(Duration::from_secs(value.saturating_mul(3600))),
+        _ => Err(format!(
+            "invalid duration unit {unit:?} in {raw:?}, expected ms, s, m or h"
+        )),
+    }
+}
+
+/// Why waiting stopped without the condition being met.
+#[derive(Debug, thiserror::Error)]
+pub enum WaitError {
+    /// The condition does not apply to the target
+    #[error("{0}")]
+    Invalid(String),
+    /// `--timeout` passed; holds what was still missing
+    #[error("timed out waiting for {target}: {missing}")]
+    Timeout { target: String, missing: String },
+    #[error("{target} will not converge: {reason}")]
+    Failed { target: String, reason: String },
+    #[error(transparent)]
+    Client(#[from] ClientError),
+}
+
+/// Poll the master at `config` until `target` meets `wait_for` (its
+/// default condition when `None`), printing what is missing whenever it
+/// changes.
+pub async fn wait(
+    config: ClientConfig,
+    mut target: Target,
+    wait_for: Option<WaitFor>,
+    timeout: Duration,
+    interval: Duration,
+) -> Result<(), WaitError> {
+    let deadline = Instant::now() + timeout;
+    let wait_for = wait_for.unwrap_or_else(|| target.default_condition());
+    let conditions = wait_for.conditions(&target).map_err(WaitError::Invalid)?;
+    // Every check is a retry already
+    let config = config.with_connect_retries(0);
+
+    let mut client = None;
+    let mut last = String::new();
+    loop {
+        let missing = match check(&mut client, &config, &mut target, &conditions).await {
+            Ok(None) => {
+                println!("{target}: {}", conditions.join(", "));
+                return Ok(());
+            }
+            Ok(Some(missing)) => missing,
+            Err(WaitError::Client(e)) if transient(&e) => {
+                client = None;
+                format!("the master ({})", repo_outils::report(&e))
+            }
+            Err(e) => return Err(e),
+        };
+        if missing != last {
+            println!("{target}: waiting for {missing}");
+            last = missing;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(WaitError::Timeout {
+                target: target.to_string(),
+                missing: last,
+            });
+        }
+        // The last check happens at the deadline, not an interval before it
+        tokio::time::sleep_until((now + interval).min(deadline)).await;
+    }
+}
+
+/// Describe `target` once, connecting first when `client` is `None`:
+/// `None` once `conditions` are met, otherwise what is still missing.
+async fn check(
+    client: &mut Option<MasterClient>,
+    config: &ClientConfig,
+    target: &mut Target,
+    conditions: &[&str],
+) -> Result<Option<String>, WaitError> {
+    let master = match client {
+        Some(master) => master,
+        None => client.insert(MasterClient::connect(config.clone()).await?),
+    };
+    if *target == Target::Generation(None) {
+        let active = master.cluster_status("").await?.active_generation;
+        *target = Target::Generation(Some(active));
+    }
+    let (kind, id) = match &*target {
+        Target::Generation(number) => (
+            ObjectKind::Generation,
+            number.unwrap_or_default().to_string(),
+        ),
+        Target::Vm(id) => (ObjectKind::Vm, id.clone()),
+        Target::Worker(id) => (ObjectKind::Worker, id.clone()),
+    };
+    match master.describe(kind, &id).await {
+        Ok(description) => match progress(&description, conditions) {
+            Progress::Met => Ok(None),
+            Progress::Pending(missing) => Ok(Some(missing)),
+            Progress::Failed(reason) => Err(WaitError::Failed {
+                target: target.to_string(),
+                reason,
+            }),
+        },
+        // Not published or reported yet
+        Err(ClientError::Rejected(e)) if e.ends_with("not found") => Ok(Some(e)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether `e` may go away by itself, e.g. while the master restarts.
+fn transient(e: &ClientError) -> bool {
+    match e {
+        ClientError::Connect { .. } | ClientError::Timeout(_) => true,
+        ClientError::Rpc(e) => matches!(
+            e.kind,
+            capnp::ErrorKind::Disconnected | capnp::ErrorKind::Overloaded
+        ),
+        ClientError::Utf8(_) | ClientError::Rejected(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Condition;
+
+    fn described(conditions: &[(&str, bool, &str)]) -> Description {
+        Description {
+            id: "x".to_string(),
+            placement: String::new(),
+            fields: Vec::new(),
+            conditions: conditions
+                .iter()
+                .map(|(name, status, reason)| Condition {
+                    name: (*name).to_string(),
+                    status: *status,
+                    reason: (*reason).to_string(),
+                    message: String::new(),
+                })
+                .collect(),
+            metrics: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn targets_and_durations_parse() {
+        assert_eq!(
+            Target::parse("vm/web-1"),
+            Ok(Target::Vm("web-1".to_string()))
+        );
+        assert_eq!(
+            Target::parse("generation/7"),
+            Ok(Target::Generation(Some(7)))
+        );
+        assert!(Target::parse("vm/").is_err());
+        assert!(Target::parse("pod/x").is_err());
+
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert!(parse_duration("10d").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn conditions_depend_on_the_target() {
+        let vm = Target::Vm("web-1".to_string());
+        assert_eq!(
+            WaitFor::Ready.conditions(&vm).unwrap(),
+            ["Running", "InSync"]
+        );
+        assert!(WaitFor::Converged.conditions(&vm).is_err());
+        assert!(WaitFor::parse("condition=").is_err());
+        assert_eq!(
+            WaitFor::parse("condition=AtGeneration")
+                .unwrap()
+                .conditions(&Target::Worker("w1".to_string()))
+                .unwrap(),
+            ["AtGeneration"]
+        );
+    }
+
+    #[test]
+    fn progress_reports_what_is_missing() {
+        let running = described(&[("Running", true, "running"), ("InSync", false, "Drifted")]);
+        assert_eq!(
+            progress(&running, &["Running", "InSync"]),
+            Progress::Pending("InSync: Drifted".to_string())
+        );
+        let ready = described(&[("Running", true, "running"), ("InSync", true, "InSync")]);
+        assert_eq!(progress(&ready, &["Running", "InSync"]), Progress::Met);
+
+        let superseded = described(&[("Active", false, "Superseded")]);
+        assert!(matches!(
+            progress(&superseded, &["Converged"]),
+            Progress::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn only_losing_the_master_is_waited_out() {
+        let refused = ClientError::Connect {
+            addr: "127.0.0.1:1".parse().unwrap(),
+            attempts: 1,
+            source: std::io::ErrorKind::ConnectionRefused.into(),
+        };
+        assert!(transient(&refused));
+        assert!(transient(&ClientError::Timeout(Duration::from_secs(10))));
+        assert!(transient(
+            &capnp::Error::disconnected("gone".to_string()).into()
+        ));
+        assert!(!transient(&capnp::Error::failed("bad".to_string()).into()));
+        assert!(!transient(&ClientError::Rejected("forbidden".to_string())));
+    }
+}
@@ -1,8 +1,13 @@
 //! Cap'n Proto RPC client for the Worker interface.
 //!
 //! Provides connect + one function per RPC method defined in worker.capnp.
+//! Every request carries the current trace context.
 
 use capnp_rpc::{rpc_twoparty_capnp, twoparty, RpcSystem};
+use commands::labels::{read_labels, write_labels, Page, Selector};
+use commands::common_capnp;
+use commands::telemetry::TraceHeaders;
+use commands::vm_state;
 use commands::worker_capnp;
 use futures::AsyncReadExt;
 use std::net::SocketAddr;
@@ -40,7 +45,9 @@ pub async fn connect(addr: SocketAddr) -> Result<WorkerClient, Box<dyn std::erro
 pub async fn read(client: &WorkerClient) -> Result<(), Box<dyn std::error::Error>> {
     info!("Worker.read()");
 
-    let response = client.read_request().send().promise.await?;
+    let mut request = client.read_request();
+    TraceHeaders::current().write(request.get().init_trace());
+    let response = request.send().promise.await?;
     let data = response.get()?.get_data()?;
 
     let id = data.get_id()?.to_str()?;
@@ -59,12 +66,21 @@ pub async fn read(client: &WorkerClient) -> Result<(), Box<dyn std::error::Error
     Ok(())
 }
 
-/// Worker.listVms — list all managed VMs.
-pub async fn list_vms(client: &WorkerClient) -> Result<(), Box<dyn std::error::Error>> {
-    info!("Worker.listVms()");
+/// Worker.listVms — list the VMs matching `selector`, one page at a time.
+pub async fn list_vms(
+    client: &WorkerClient,
+    selector: &Selector,
+    page: &Page,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!(%selector, limit = page.limit, cursor = %page.cursor, "Worker.listVms()");
 
-    let response = client.list_vms_request().send().promise.await?;
+    let mut request = client.list_vms_request();
+    selector.write(request.get().init_selector());
+    page.write(request.get().init_page());
+    TraceHeaders::current().write(request.get().init_trace());
+    let response = request.send().promise.await?;
     let vms = response.get()?.get_vms()?;
+    let next_cursor = response.get()?.get_next_cursor()?.to_str()?;
 
     if vms.is_empty() {
         info!("✓ No VMs running");
@@ -75,17 +91,29 @@ pub async fn list_vms(client: &WorkerClient) -> Result<(), Box<dyn std::error::E
     for i in 0..vms.len() {
         let vm = vms.get(i);
         let id = vm.get_id()?.to_str()?;
-        let status = vm.get_status()?.to_str()?;
+        let status = vm_state::read(vm.get_state(), vm.get_status()?.to_str()?);
         let drifted = vm.get_drifted();
+        let labels = read_labels(vm.get_labels()?)?;
         let metrics = vm.get_metrics()?;
         info!(
             id = %id,
             status = %status,
             drifted = drifted,
+            labels = ?labels,
             cpu = metrics.get_cpu_usage(),
             memory_bytes = metrics.get_memory_usage(),
             "  VM"
         );
+        let boot_log = vm.get_boot_log()?.to_str()?;
+        if !boot_log.is_empty() {
+            info!(id = %id, "  Serial log of the failed boot:\n{boot_log}");
+        }
+        for transition in vm_state::read_transitions(vm.get_transitions()?)? {
+            info!(id = %id, at_ms = transition.at_ms, "    {transition}");
+        }
+    }
+    if !next_cursor.is_empty() {
+        info!(cursor = %next_cursor, "More VMs: rerun with --cursor");
     }
 
     Ok(())
@@ -105,20 +133,8 @@ pub async fn create_vm(
     );
 
     let mut request = client.create_vm_request();
-    {
-        let mut s = request.get().init_spec();
-        s.set_toplevel(&spec.toplevel);
-        s.set_kernel_path(&spec.kernel_path);
-        s.set_initrd_path(&spec.initrd_path);
-        s.set_disk_image_path(&spec.disk_image_path);
-        s.set_cmdline(&spec.cmdline);
-        s.set_cpu(spec.cpu);
-        s.set_memory_mb(spec.memory_mb);
-        let mut domains = s.init_network_allowed_domains(spec.network_allowed_domains.len() as u32);
-        for (i, d) in spec.network_allowed_domains.iter().enumerate() {
-            domains.set(i as u32, d);
-        }
-    }
+    write_spec(&spec, request.get().init_spec());
+    TraceHeaders::current().write(request.get().init_trace());
 
     let response = request.send().promise.await?;
     let id = response.get()?.get_id()?.to_str()?;
@@ -127,6 +143,30 @@ pub async fn create_vm(
     Ok(())
 }
 
+/// Worker.updateVm — move a VM to a new spec.
+pub async fn update_vm(
+    client: &WorkerClient,
+    id: &str,
+    spec: VmSpecJson,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!(
+        id = %id,
+        cpu = spec.cpu,
+        memory_mb = spec.memory_mb,
+        "Worker.updateVm()"
+    );
+
+    let mut request = client.update_vm_request();
+    request.get().set_id(id);
+    write_spec(&spec, request.get().init_spec());
+    TraceHeaders::current().write(request.get().init_trace());
+
+    request.send().promise.await?;
+
+    info!(id = %id, "✓ VM updated");
+    Ok(())
+}
+
 /// Worker.deleteVm — delete a VM by ID.
 pub async fn delete_vm(
     client: &WorkerClient,
@@ -136,9 +176,28 @@ pub async fn delete_vm(
 
     let mut request = client.delete_vm_request();
     request.get().set_id(id);
+    TraceHeaders::current().write(request.get().init_trace());
 
     request.send().promise.await?;
 
     info!(id = %id, "✓ VM deleted");
     Ok(())
 }
+
+fn write_spec(spec: &VmSpecJson, mut s: common_capnp::vm_spec::Builder<'_>) {
+    s.set_toplevel(&spec.toplevel);
+    s.set_kernel_path(&spec.kernel_path);
+    s.set_initrd_path(&spec.initrd_path);
+    s.set_disk_image_path(&spec.disk_image_path);
+    s.set_cmdline(&spec.cmdline);
+    s.set_cpu(spec.cpu);
+    s.set_memory_mb(spec.memory_mb);
+    write_labels(
+        &spec.labels,
+        s.reborrow().init_labels(spec.labels.len() as u32),
+    );
+    let mut domains = s.init_network_allowed_domains(spec.network_allowed_domains.len() as u32);
+    for (i, d) in spec.network_allowed_domains.iter().enumerate() {
+        domains.set(i as u32, d);
+    }
+}
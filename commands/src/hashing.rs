@@ -0,0 +1,332 @@
+//! Canonical content hashes for VM specs and generations.
+//!
+//! Every hash is a string `v<version>-sha256:<hex>`, e.g.
+//! `v1-sha256:9f86d0…`. The version names the canonical encoding, not just
+//! the digest: two hashes are only comparable when their versions match, so
+//! changing the encoding bumps [`HASH_VERSION`] instead of silently reporting
+//! every VM as drifted.
+//!
+//! - A **spec hash** ([`vm_spec_hash`]) covers every field the worker boots
+//!   a VM from. Allowed domains are a set: their order does not matter.
+//!   Labels are not part of it: relabelling a VM is not drift. Memory
+//!   backing options and a network backend other than `tap` are only
+//!   encoded when set, so specs that leave them off keep the hash they had
+//!   before the options existed.
+//! - A **generation hash** ([`generation_hash`]) covers the spec hashes of
+//!   a published state, in any order. It is the `intentHash` of
+//!   `Master.publishState`; the generation number and commit are not part
+//!   of it, so republishing the same content yields the same hash.
+//!
+//! Strings without a version prefix predate this scheme (e.g. a bare
+//! toplevel path) and parse as [`HashVersion::Legacy`].
+
+use std::fmt::{self, Write as _};
+
+use sha2::{Digest, Sha256};
+
+use crate::net_backend::NetBackend;
+
+/// Version of the canonical encoding produced by this module.
+pub const HASH_VERSION: u32 = 1;
+
+// ─── Hash values ───────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashVersion {
+    /// Unprefixed string from before versioned hashes
+    Legacy,
+    V(u32),
+}
+
+impl fmt::Display for HashVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashVersion::Legacy => f.write_str("legacy"),
+            HashVersion::V(v) => write!(f, "v{v}"),
+        }
+    }
+}
+
+/// A parsed content hash.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContentHash(String);
+
+impl ContentHash {
+    /// Parse any hash string. Never fails: unprefixed strings are legacy
+    /// hashes, compared verbatim.
+    #[must_use]
+    pub fn parse(s: &str) -> Self {
+        Self(s.to_string())
+    }
+
+    #[must_use]
+    pub fn version(&self) -> HashVersion {
+        self.0
+            .strip_prefix('v')
+            .and_then(|rest| rest.split_once("-sha256:"))
+            .and_then(|(version, _)| version.parse().ok())
+            .map_or(HashVersion::Legacy, HashVersion::V)
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn from_digest(digest: &[u8]) -> Self {
+        let mut out = format!("v{HASH_VERSION}-sha256:");
+        for byte in digest {
+            let _ = write!(out, "{byte:02x}");
+        }
+        Self(out)
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// ─── Drift ─────────────────────────────────────────────────────────────────
+
+/// Outcome of comparing a desired hash with an observed one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drift {
+    InSync,
+    Drifted,
+    /// Nothing observed yet (empty hash), e.g. a VM still booting
+    Unknown,
+    /// Hashes from different encodings; they cannot be compared until the
+    /// older side is upgraded
+    Incomparable {
+        desired: HashVersion,
+        observed: HashVersion,
+    },
+}
+
+impl Drift {
+    /// Whether the observed state must be acted upon. Unknown and
+    /// incomparable are not drift: nothing proves the VM differs.
+    #[must_use]
+    pub fn is_drifted(&self) -> bool {
+        matches!(self, Drift::Drifted)
+    }
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::InSync => f.write_str("in-sync"),
+            Drift::Drifted => f.write_str("drifted"),
+            Drift::Unknown => f.write_str("unknown"),
+            Drift::Incomparable { desired, observed } => {
+                write!(f, "incomparable ({desired} vs {observed})")
+            }
+        }
+    }
+}
+
+/// Compare a desired hash with an observed one.
+#[must_use]
+pub fn compare(desired: &str, observed: &str) -> Drift {
+    if observed.is_empty() {
+        return Drift::Unknown;
+    }
+    let (desired, observed) = (ContentHash::parse(desired), ContentHash::parse(observed));
+    if desired.version() != observed.version() {
+        return Drift::Incomparable {
+            desired: desired.version(),
+            observed: observed.version(),
+        };
+    }
+    if desired == observed {
+        Drift::InSync
+    } else {
+        Drift::Drifted
+    }
+}
+
+// ─── Canonical encoding ────────────────────────────────────────────────────
+
+/// Length-prefixed encoder: no two distinct field sequences encode to the
+/// same bytes, whatever the field contents.
+struct Canonical(Sha256);
+
+impl Canonical {
+    fn new(domain: &str) -> Self {
+        let mut canonical = Self(Sha256::new());
+        canonical.str(domain);
+        canonical.u64(HASH_VERSION.into());
+        canonical
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.update(value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u64(value.len() as u64);
+        self.0.update(value.as_bytes());
+    }
+
+    /// Sorted and deduplicated, so order and repeats do not matter.
+    fn set<'a>(&mut self, values: impl IntoIterator<Item = &'a str>) {
+        let mut values: Vec<&str> = values.into_iter().collect();
+        values.sort_unstable();
+        values.dedup();
+        self.u64(values.len() as u64);
+        for value in values {
+            self.str(value);
+        }
+    }
+
+    fn finish(self) -> ContentHash {
+        ContentHash::from_digest(&self.0.finalize())
+    }
+}
+
+/// The fields of a VM spec that go into its hash, borrowed from whichever
+/// representation the caller holds (worker DTO, capnp reader, …).
+#[derive(Debug, Clone, Copy)]
+pub struct VmSpecFields<'a> {
+    pub toplevel: &'a str,
+    pub kernel_path: &'a str,
+    pub initrd_path: &'a str,
+    pub disk_image_path: &'a str,
+    pub cmdline: &'a str,
+    pub cpu: u32,
+    pub memory_mb: u32,
+    pub network_allowed_domains: &'a [&'a str],
+    /// Guest memory backed by hugepages
+    pub hugepages: bool,
+    /// Guest memory mapped shared, for vhost-user devices
+    pub shared_memory: bool,
+    pub net_backend: NetBackend,
+}
+
+/// Canonical hash of one VM spec.
+#[must_use]
+pub fn vm_spec_hash(spec: &VmSpecFields<'_>) -> ContentHash {
+    let mut canonical = Canonical::new("procurator.vm-spec");
+    canonical.str(spec.toplevel);
+    canonical.str(spec.kernel_path);
+    canonical.str(spec.initrd_path);
+    canonical.str(spec.disk_image_path);
+    canonical.str(spec.cmdline);
+    canonical.u64(spec.cpu.into());
+    canonical.u64(spec.memory_mb.into());
+    canonical.set(spec.network_allowed_domains.iter().copied());
+    if spec.hugepages || spec.shared_memory {
+        canonical.str("memory");
+        canonical.u64(spec.hugepages.into());
+        canonical.u64(spec.shared_memory.into());
+    }
+    if spec.net_backend != NetBackend::Tap {
+        canonical.str("net");
+        canonical.str(spec.net_backend.as_str());
+    }
+    canonical.finish()
+}
+
+/// Canonical hash of a generation from the spec hashes of its VMs.
+#[must_use]
+pub fn generation_hash<'a>(specs: impl IntoIterator<Item = &'a ContentHash>) -> ContentHash {
+    let mut canonical = Canonical::new("procurator.generation");
+    // A multiset: two identical VMs are two replicas, keep both
+    let mut specs: Vec<&str> = specs.into_iter().map(ContentHash::as_str).collect();
+    specs.sort_unstable();
+    canonical.u64(specs.len() as u64);
+    for spec in specs {
+        canonical.str(spec);
+    }
+    canonical.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec<'a>(toplevel: &'a str, domains: &'a [&'a str]) -> VmSpecFields<'a> {
+        VmSpecFields {
+            toplevel,
+            kernel_path: "/nix/store/k/bzImage",
+            initrd_path: "/nix/store/i/initrd",
+            disk_image_path: "/nix/store/d/nixos.img",
+            cmdline: "console=ttyS0",
+            cpu: 1,
+            memory_mb: 512,
+            network_allowed_domains: domains,
+            hugepages: false,
+            shared_memory: false,
+            net_backend: NetBackend::Tap,
+        }
+    }
+
+    #[test]
+    fn spec_hash_is_versioned_and_ignores_domain_order() {
+        let a = vm_spec_hash(&spec("/nix/store/a", &["a.com", "b.com"]));
+        let b = vm_spec_hash(&spec("/nix/store/a", &["b.com", "a.com"]));
+        assert_eq!(a, b);
+        assert_eq!(a.version(), HashVersion::V(HASH_VERSION));
+        assert!(a.as_str().starts_with("v1-sha256:"));
+        assert_eq!(a.as_str().len(), "v1-sha256:".len() + 64);
+
+        let c = vm_spec_hash(&spec("/nix/store/a", &["a.com"]));
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn field_boundaries_are_part_of_the_hash() {
+        let mut left = spec("/nix/store/ab", &[]);
+        left.kernel_path = "c";
+        let mut right = spec("/nix/store/a", &[]);
+        right.kernel_path = "bc";
+        assert_ne!(vm_spec_hash(&left), vm_spec_hash(&right));
+    }
+
+    #[test]
+    fn backing_options_are_only_hashed_when_set() {
+        let plain = vm_spec_hash(&spec("/nix/store/a", &[]));
+        // Hash of this spec before memory backing options existed
+        assert_eq!(
+            plain.as_str(),
+            "v1-sha256:33e5f3f810e8420a82e6aff4f9158c061baa5d016365edd6d9807beb2f3d41e9"
+        );
+        let mut huge = spec("/nix/store/a", &[]);
+        huge.hugepages = true;
+        let mut shared = spec("/nix/store/a", &[]);
+        shared.shared_memory = true;
+        assert_ne!(vm_spec_hash(&huge), plain);
+        assert_ne!(vm_spec_hash(&huge), vm_spec_hash(&shared));
+
+        let mut vhost_user = spec("/nix/store/a", &[]);
+        vhost_user.net_backend = NetBackend::VhostUser;
+        assert_ne!(vm_spec_hash(&vhost_user), plain);
+    }
+
+    #[test]
+    fn generation_hash_ignores_order_but_counts_replicas() {
+        let a = vm_spec_hash(&spec("/nix/store/a", &[]));
+        let b = vm_spec_hash(&spec("/nix/store/b", &[]));
+        assert_eq!(generation_hash([&a, &b]), generation_hash([&b, &a]));
+        assert_ne!(generation_hash([&a, &b]), generation_hash([&a, &a, &b]));
+    }
+
+    #[test]
+    fn compare_only_reports_drift_between_same_versions() {
+        let a = vm_spec_hash(&spec("/nix/store/a", &[])).to_string();
+        let b = vm_spec_hash(&spec("/nix/store/b", &[])).to_string();
+        assert_eq!(compare(&a, &a), Drift::InSync);
+        assert!(compare(&a, &b).is_drifted());
+        assert_eq!(compare(&a, ""), Drift::Unknown);
+        assert_eq!(
+            compare(&a, "/nix/store/a"),
+            Drift::Incomparable {
+                desired: HashVersion::V(1),
+                observed: HashVersion::Legacy,
+            }
+        );
+        assert_eq!(compare("/nix/store/a", "/nix/store/b"), Drift::Drifted);
+    }
+}
@@ -0,0 +1,413 @@
+//! Health and readiness probes shared by the master and worker binaries.
+//!
+//! - `serve` answers `GET /healthz` (liveness) and `GET /readyz` (readiness)
+//!   on a minimal HTTP/1.1 listener, for load balancers and orchestrators.
+//!   The status is `200` when every check passes and `503` otherwise; the
+//!   body has one `[+]name ok` or `[-]name failed: reason` line per check.
+//! - `watchdog` pings the systemd watchdog (`WatchdogSec=`) only while the
+//!   liveness checks pass, so a wedged process gets restarted.
+//!
+//! Liveness answers "should this process be restarted", readiness "should it
+//! get traffic": a worker that is running but cannot start VMs is alive but
+//! not ready.
+
+use std::fmt::{self, Write as _};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::lifecycle::{self, NotifyState};
+
+/// Upper bound for one probe; a check that hangs counts as failed.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request head accepted. Probes send a request line and a few
+/// headers, nothing more.
+const MAX_REQUEST_BYTES: usize = 4096;
+
+// ─── Checks ────────────────────────────────────────────────────────────────
+
+/// Outcome of one named check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub result: Result<(), String>,
+}
+
+impl Check {
+    #[must_use]
+    pub fn pass(name: &'static str) -> Self {
+        Self {
+            name,
+            result: Ok(()),
+        }
+    }
+
+    pub fn fail(name: &'static str, reason: impl Into<String>) -> Self {
+        Self {
+            name,
+            result: Err(reason.into()),
+        }
+    }
+
+    /// Pass if `result` is `Ok`, fail with its error otherwise.
+    pub fn from_result<E: fmt::Display>(name: &'static str, result: Result<(), E>) -> Self {
+        Self {
+            name,
+            result: result.map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Pass if `ok`, fail with `reason` otherwise.
+    pub fn expect(name: &'static str, ok: bool, reason: impl Into<String>) -> Self {
+        if ok {
+            Self::pass(name)
+        } else {
+            Self::fail(name, reason)
+        }
+    }
+}
+
+/// Checks run by one probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report(pub Vec<Check>);
+
+impl Report {
+    #[must_use]
+    pub fn healthy(&self) -> bool {
+        self.0.iter().all(|c| c.result.is_ok())
+    }
+
+    /// Failing checks as `name: reason`, comma separated.
+    #[must_use]
+    pub fn failures(&self) -> String {
+        self.0
+            .iter()
+            .filter_map(|c| c.result.as_ref().err().map(|e| format!("{}: {e}", c.name)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn render(&self, probe: &str) -> String {
+        let mut body = String::new();
+        for check in &self.0 {
+            match &check.result {
+                Ok(()) => {
+                    let _ = writeln!(body, "[+]{} ok", check.name);
+                }
+                Err(e) => {
+                    let _ = writeln!(body, "[-]{} failed: {e}", check.name);
+                }
+            }
+        }
+        let verdict = if self.healthy() { "passed" } else { "failed" };
+        let _ = writeln!(body, "{probe} check {verdict}");
+        body
+    }
+}
+
+/// The checks behind `/healthz` and `/readyz`.
+pub trait Probe: Clone + Send + Sync + 'static {
+    /// Whether the process is working at all. Failing restarts it.
+    fn liveness(&self) -> impl Future<Output = Report> + Send;
+
+    /// Whether the process can do useful work right now.
+    fn readiness(&self) -> impl Future<Output = Report> + Send;
+}
+
+/// Raised while a component is up, e.g. while the RPC listener accepts.
+#[derive(Debug, Clone, Default)]
+pub struct Flag(Arc<AtomicBool>);
+
+impl Flag {
+    /// Raise the flag until the returned guard is dropped.
+    #[must_use]
+    pub fn raise(&self) -> FlagGuard {
+        self.0.store(true, Ordering::Release);
+        FlagGuard(self.clone())
+    }
+
+    #[must_use]
+    pub fn is_raised(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Lowers its [`Flag`] when dropped.
+#[derive(Debug)]
+pub struct FlagGuard(Flag);
+
+impl Drop for FlagGuard {
+    fn drop(&mut self) {
+        (self.0).0.store(false, Ordering::Release);
+    }
+}
+
+async fn run_probe(probe: impl Future<Output = Report>) -> Report {
+    match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+        Ok(report) => report,
+        Err(_) => Report(vec![Check::fail(
+            "probe",
+            format!("timed out after {PROBE_TIMEOUT:?}"),
+        )]),
+    }
+}
+
+// ─── HTTP listener ─────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Endpoint {
+    Healthz,
+    Readyz,
+}
+
+/// Map a request line to a probe; `None` means 404.
+fn route(request_line: &str) -> Option<Endpoint> {
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?;
+    let target = parts.next()?;
+    if method != "GET" && method != "HEAD" {
+        return None;
+    }
+    match target.split('?').next()? {
+        "/healthz" | "/livez" => Some(Endpoint::Healthz),
+        "/readyz" => Some(Endpoint::Readyz),
+        _ => None,
+    }
+}
+
+/// Answer `/healthz` and `/readyz` on `addr` until `shutdown` resolves.
+///
+/// # Errors
+///
+/// - if the listener cannot bind `addr`
+pub async fn serve<P: Probe>(
+    addr: SocketAddr,
+    probe: P,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!(%addr, "Serving health probes");
+
+    tokio::pin!(shutdown);
+    loop {
+        let stream = tokio::select! {
+            () = &mut shutdown => return Ok(()),
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!(error = %e, "Health listener accept failed");
+                    continue;
+                }
+            },
+        };
+        let probe = probe.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, probe).await {
+                tracing::debug!(error = %e, "Health probe connection failed");
+            }
+        });
+    }
+}
+
+async fn respond<P: Probe>(mut stream: TcpStream, probe: P) -> io::Result<()> {
+    let head = tokio::time::timeout(PROBE_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request head timed out"))??;
+    let request_line = head.lines().next().unwrap_or_default();
+    let head_only = request_line.starts_with("HEAD ");
+
+    let (status, body) = match route(request_line) {
+        Some(endpoint) => {
+            let (name, report) = match endpoint {
+                Endpoint::Healthz => ("healthz", run_probe(probe.liveness()).await),
+                Endpoint::Readyz => ("readyz", run_probe(probe.readiness()).await),
+            };
+            if report.healthy() {
+                ("200 OK", report.render(name))
+            } else {
+                ("503 Service Unavailable", report.render(name))
+            }
+        }
+        None => ("404 Not Found", "not found\n".to_string()),
+    };
+
+    let mut response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    if !head_only {
+        response.push_str(&body);
+    }
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Read until the blank line ending the request head.
+async fn read_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut buf = Vec::with_capacity(512);
+    let mut chunk = [0u8; 512];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too large",
+            ));
+        }
+    }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+// ─── systemd watchdog ──────────────────────────────────────────────────────
+
+/// Send `WATCHDOG=1` at half the configured interval while liveness passes.
+///
+/// Returns immediately when the unit has no `WatchdogSec=`. Once liveness
+/// fails the pings stop and systemd restarts the service after the
+/// interval; a transient failure that recovers in time is harmless.
+pub async fn watchdog<P: Probe>(probe: P, shutdown: impl Future<Output = ()>) {
+    let Some(interval) = lifecycle::watchdog_interval() else {
+        return;
+    };
+    tracing::info!(?interval, "Pinging systemd watchdog");
+
+    let mut tick = tokio::time::interval(interval / 2);
+    let mut failing = false;
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            () = &mut shutdown => return,
+            _ = tick.tick() => {}
+        }
+
+        let report = run_probe(probe.liveness()).await;
+        if !report.healthy() {
+            if !failing {
+                tracing::error!(
+                    failures = %report.failures(),
+                    "Liveness failing, withholding watchdog ping"
+                );
+            }
+            failing = true;
+            continue;
+        }
+        if failing {
+            tracing::info!("Liveness restored, resuming watchdog pings");
+        }
+        failing = false;
+        if let Err(e) = lifecycle::notify(&[NotifyState::Watchdog]) {
+            tracing::warn!(error = %e, "sd_notify WATCHDOG failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone)]
+    struct Fixed {
+        ready: bool,
+    }
+
+    impl Probe for Fixed {
+        async fn liveness(&self) -> Report {
+            Report(vec![Check::pass("loop")])
+        }
+
+        async fn readiness(&self) -> Report {
+            Report(vec![
+                Check::pass("loop"),
+                Check::expect("backend", self.ready, "not reachable"),
+            ])
+        }
+    }
+
+    #[test]
+    fn routes_probe_paths() {
+        assert_eq!(route("GET /healthz HTTP/1.1"), Some(Endpoint::Healthz));
+        assert_eq!(
+            route("HEAD /readyz?verbose HTTP/1.1"),
+            Some(Endpoint::Readyz)
+        );
+        assert_eq!(route("POST /healthz HTTP/1.1"), None);
+        assert_eq!(route("GET /metrics HTTP/1.1"), None);
+        assert_eq!(route(""), None);
+    }
+
+    #[test]
+    fn report_lists_each_check() {
+        let report = Report(vec![Check::pass("rpc"), Check::fail("vmm", "missing")]);
+        assert!(!report.healthy());
+        assert_eq!(report.failures(), "vmm: missing");
+        assert_eq!(
+            report.render("readyz"),
+            "[+]rpc ok\n[-]vmm failed: missing\nreadyz check failed\n"
+        );
+    }
+
+    #[test]
+    fn flag_is_lowered_when_guard_drops() {
+        let flag = Flag::default();
+        let guard = flag.raise();
+        assert!(flag.is_raised());
+        drop(guard);
+        assert!(!flag.is_raised());
+    }
+
+    /// GET `path`, retrying the connect while the listener starts.
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = None;
+        for _ in 0..50 {
+            if let Ok(s) = TcpStream::connect(addr).await {
+                stream = Some(s);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut stream = stream.expect("health listener did not start");
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\nHost: x\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_liveness_and_readiness() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(addr, Fixed { ready: false }, async {
+            let _ = stop_rx.await;
+        }));
+
+        let healthz = get(addr, "/healthz").await;
+        assert!(healthz.starts_with("HTTP/1.1 200 OK\r\n"), "{healthz}");
+        assert!(healthz.ends_with("[+]loop ok\nhealthz check passed\n"));
+
+        let readyz = get(addr, "/readyz").await;
+        assert!(readyz.starts_with("HTTP/1.1 503 "), "{readyz}");
+        assert!(readyz.contains("[-]backend failed: not reachable\n"));
+
+        assert!(get(addr, "/nope").await.starts_with("HTTP/1.1 404 "));
+
+        stop_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,415 @@
+//! Labels, label selectors and pagination shared by every list RPC.
+//!
+//! - **Labels** are a `key → value` map ([`Labels`]); on the wire they are a
+//!   `List(Label)` with unique keys.
+//! - A **selector** filters by labels, in the syntax of `kubectl -l`:
+//!   comma separated requirements that must all hold.
+//!
+//!   | Requirement | Matches when                          |
+//!   |-------------|---------------------------------------|
+//!   | `key=value` | `key` is set to `value` (also `==`)   |
+//!   | `key!=value`| `key` is unset or set to another value|
+//!   | `key`       | `key` is set                          |
+//!   | `!key`      | `key` is unset                        |
+//!
+//!   The empty selector matches everything.
+//! - A **page** bounds a listing: at most `limit` items (`0` for all) after
+//!   `cursor`, the opaque `nextCursor` of the previous page. Items are
+//!   ordered by a stable key (e.g. the VM id) so pages neither skip nor
+//!   repeat items when others are added in between.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use capnp::struct_list;
+
+use crate::common_capnp::{label, page, selector};
+
+/// Labels of one object, keyed by label key.
+pub type Labels = BTreeMap<String, String>;
+
+// ─── Errors ────────────────────────────────────────────────────────────────
+
+/// A label or selector that does not parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    input: String,
+    reason: &'static str,
+}
+
+impl ParseError {
+    fn new(input: &str, reason: &'static str) -> Self {
+        Self {
+            input: input.to_string(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {:?}: {}", self.input, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Keys are non-empty and made of ASCII alphanumerics, `.`, `_`, `-` and
+/// `/` (for prefixes like `procurator.io/role`).
+fn check_key(key: &str, input: &str) -> Result<(), ParseError> {
+    if key.is_empty() {
+        return Err(ParseError::new(input, "empty key"));
+    }
+    if !key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '/'))
+    {
+        return Err(ParseError::new(
+            input,
+            "keys may only contain letters, digits, '.', '_', '-' and '/'",
+        ));
+    }
+    Ok(())
+}
+
+/// Values may be empty but cannot contain the selector separators.
+fn check_value(value: &str, input: &str) -> Result<(), ParseError> {
+    if value.contains([',', '=', '!']) {
+        return Err(ParseError::new(
+            input,
+            "values cannot contain ',', '=' or '!'",
+        ));
+    }
+    Ok(())
+}
+
+fn text(t: capnp::Result<capnp::text::Reader<'_>>) -> capnp::Result<String> {
+    Ok(t?
+        .to_str()
+        .map_err(|e| capnp::Error::failed(e.to_string()))?
+        .to_string())
+}
+
+// ─── Labels ────────────────────────────────────────────────────────────────
+
+/// Parse one `key=value` label, e.g. from a `--label` flag.
+///
+/// # Errors
+///
+/// Fails without `=` or with a key or value a selector could not match.
+pub fn parse_label(input: &str) -> Result<(String, String), ParseError> {
+    let (key, value) = input
+        .split_once('=')
+        .ok_or_else(|| ParseError::new(input, "expected key=value"))?;
+    let (key, value) = (key.trim(), value.trim());
+    check_key(key, input)?;
+    check_value(value, input)?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Read a `List(Label)` field.
+///
+/// # Errors
+///
+/// Fails on invalid UTF-8 or a key that appears twice.
+pub fn read_labels(list: struct_list::Reader<'_, label::Owned>) -> capnp::Result<Labels> {
+    let mut labels = Labels::new();
+    for label in list {
+        let key = text(label.get_key())?;
+        let value = text(label.get_value())?;
+        if labels.insert(key.clone(), value).is_some() {
+            return Err(capnp::Error::failed(format!("duplicate label {key:?}")));
+        }
+    }
+    Ok(labels)
+}
+
+/// Fill a `List(Label)` field initialized with `labels.len()` elements.
+pub fn write_labels(labels: &Labels, mut list: struct_list::Builder<'_, label::Owned>) {
+    for (i, (key, value)) in (0..list.len()).zip(labels) {
+        let mut label = list.reborrow().get(i);
+        label.set_key(key);
+        label.set_value(value);
+    }
+}
+
+// ─── Selectors ─────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    #[must_use]
+    pub fn matches(&self, labels: &Labels) -> bool {
+        match self {
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let s = input.trim();
+        let requirement = if let Some((key, value)) = s.split_once("!=") {
+            Requirement::NotEquals(key.trim().to_string(), value.trim().to_string())
+        } else if let Some((key, value)) = s.split_once("==").or_else(|| s.split_once('=')) {
+            Requirement::Equals(key.trim().to_string(), value.trim().to_string())
+        } else if let Some(key) = s.strip_prefix('!') {
+            Requirement::NotExists(key.trim().to_string())
+        } else {
+            Requirement::Exists(s.to_string())
+        };
+        match &requirement {
+            Requirement::Equals(key, value) | Requirement::NotEquals(key, value) => {
+                check_key(key, input)?;
+                check_value(value, input)?;
+            }
+            Requirement::Exists(key) | Requirement::NotExists(key) => check_key(key, input)?,
+        }
+        Ok(requirement)
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Requirement::Equals(key, value) => write!(f, "{key}={value}"),
+            Requirement::NotEquals(key, value) => write!(f, "{key}!={value}"),
+            Requirement::Exists(key) => f.write_str(key),
+            Requirement::NotExists(key) => write!(f, "!{key}"),
+        }
+    }
+}
+
+/// Requirements that must all hold. Parses from and displays as the
+/// `key=value,key2!=v` syntax.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector(pub Vec<Requirement>);
+
+impl Selector {
+    #[must_use]
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.0.iter().all(|r| r.matches(labels))
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Read a request's `selector` field. Callers built before the field
+    /// existed send none, which selects everything.
+    ///
+    /// # Errors
+    ///
+    /// Fails on invalid UTF-8, an unknown operator or an invalid key or
+    /// value.
+    pub fn read(reader: capnp::Result<selector::Reader<'_>>) -> capnp::Result<Self> {
+        let mut requirements = Vec::new();
+        for requirement in reader?.get_requirements()? {
+            let key = text(requirement.get_key())?;
+            let value = text(requirement.get_value())?;
+            let requirement = match requirement.get_op()? {
+                selector::Op::Eq => Requirement::Equals(key, value),
+                selector::Op::NotEq => Requirement::NotEquals(key, value),
+                selector::Op::Exists => Requirement::Exists(key),
+                selector::Op::NotExists => Requirement::NotExists(key),
+            };
+            // Same checks as the text form, so both encodings accept the same selectors
+            requirement
+                .to_string()
+                .parse::<Requirement>()
+                .map_err(|e| capnp::Error::failed(e.to_string()))?;
+            requirements.push(requirement);
+        }
+        Ok(Self(requirements))
+    }
+
+    /// Fill a request's `selector` field.
+    pub fn write(&self, builder: selector::Builder<'_>) {
+        let mut list = builder.init_requirements(u32::try_from(self.0.len()).unwrap_or(0));
+        for (i, requirement) in (0..list.len()).zip(&self.0) {
+            let mut out = list.reborrow().get(i);
+            let (op, key, value) = match requirement {
+                Requirement::Equals(key, value) => (selector::Op::Eq, key, value.as_str()),
+                Requirement::NotEquals(key, value) => (selector::Op::NotEq, key, value.as_str()),
+                Requirement::Exists(key) => (selector::Op::Exists, key, ""),
+                Requirement::NotExists(key) => (selector::Op::NotExists, key, ""),
+            };
+            out.set_op(op);
+            out.set_key(key);
+            out.set_value(value);
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        s.split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, requirement) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{requirement}")?;
+        }
+        Ok(())
+    }
+}
+
+// ─── Pagination ────────────────────────────────────────────────────────────
+
+/// One page of a listing. The default is everything, in one page.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Page {
+    /// Most items returned, `0` for no limit
+    pub limit: u32,
+    /// `nextCursor` of the previous page, empty for the first one
+    pub cursor: String,
+}
+
+impl Page {
+    /// Read a request's `page` field. Callers built before the field existed
+    /// send none, which lists everything.
+    ///
+    /// # Errors
+    ///
+    /// Fails on an invalid UTF-8 cursor.
+    pub fn read(reader: capnp::Result<page::Reader<'_>>) -> capnp::Result<Self> {
+        let reader = reader?;
+        Ok(Self {
+            limit: reader.get_limit(),
+            cursor: text(reader.get_cursor())?,
+        })
+    }
+
+    /// Fill a request's `page` field.
+    pub fn write(&self, mut builder: page::Builder<'_>) {
+        builder.set_limit(self.limit);
+        builder.set_cursor(&self.cursor);
+    }
+
+    /// The items of this page, ordered by `key`, and the cursor of the next
+    /// one (empty on the last page). Keys must be unique.
+    pub fn apply<T>(&self, mut items: Vec<T>, key: impl Fn(&T) -> &str) -> (Vec<T>, String) {
+        items.sort_by(|a, b| key(a).cmp(key(b)));
+        if !self.cursor.is_empty() {
+            items.retain(|item| key(item) > self.cursor.as_str());
+        }
+        let limit = usize::try_from(self.limit).unwrap_or(usize::MAX);
+        if self.limit == 0 || items.len() <= limit {
+            return (items, String::new());
+        }
+        items.truncate(limit);
+        let next = items.last().map(|item| key(item).to_string());
+        (items, next.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn selector_parses_every_operator_and_round_trips() {
+        let selector: Selector = " app=web, tier!=db,canary , !legacy,env==prod"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            selector.0,
+            [
+                Requirement::Equals("app".into(), "web".into()),
+                Requirement::NotEquals("tier".into(), "db".into()),
+                Requirement::Exists("canary".into()),
+                Requirement::NotExists("legacy".into()),
+                Requirement::Equals("env".into(), "prod".into()),
+            ]
+        );
+        assert_eq!(
+            selector.to_string(),
+            "app=web,tier!=db,canary,!legacy,env=prod"
+        );
+        assert_eq!(selector.to_string().parse::<Selector>().unwrap(), selector);
+
+        assert!("".parse::<Selector>().unwrap().is_empty());
+        for bad in ["app=web,", "=web", "a b=c", "app=we=b", "!"] {
+            assert!(bad.parse::<Selector>().is_err(), "{bad} should not parse");
+        }
+    }
+
+    #[test]
+    fn selector_requires_every_requirement() {
+        let web = labels(&[("app", "web"), ("canary", "")]);
+        let db = labels(&[("app", "db"), ("tier", "db")]);
+        let selector: Selector = "app=web,tier!=db,canary,!legacy".parse().unwrap();
+        assert!(selector.matches(&web));
+        assert!(!selector.matches(&db));
+        // An unset key is "not equal" to anything
+        assert!("tier!=db".parse::<Selector>().unwrap().matches(&web));
+        assert!(Selector::default().matches(&db));
+    }
+
+    #[test]
+    fn labels_parse_as_key_value() {
+        assert_eq!(
+            parse_label("procurator.io/role=api").unwrap(),
+            ("procurator.io/role".to_string(), "api".to_string())
+        );
+        assert_eq!(parse_label("canary=").unwrap().1, "");
+        assert!(parse_label("app").is_err());
+        assert!(parse_label("app=a,b").is_err());
+    }
+
+    #[test]
+    fn pages_cover_every_item_once() {
+        let items = vec!["d", "a", "c", "b", "e"];
+        let mut page = Page {
+            limit: 2,
+            cursor: String::new(),
+        };
+        let mut seen = Vec::new();
+        loop {
+            let (items, next) = page.apply(items.clone(), |s| *s);
+            seen.extend(items);
+            if next.is_empty() {
+                break;
+            }
+            page.cursor = next;
+        }
+        assert_eq!(seen, ["a", "b", "c", "d", "e"]);
+
+        let (all, next) = Page::default().apply(items, |s| *s);
+        assert_eq!(all.len(), 5);
+        assert!(next.is_empty());
+    }
+}
@@ -1,3 +1,12 @@
+pub mod hashing;
+pub mod health;
+pub mod labels;
+pub mod lifecycle;
+pub mod net_backend;
+pub mod telemetry;
+pub mod vm_action;
+pub mod vm_state;
+
 #[allow(clippy::all, clippy::pedantic, warnings)]
 pub mod common_capnp {
     include!(concat!(env!("OUT_DIR"), "/common_capnp.rs"));
@@ -0,0 +1,160 @@
+//! Process lifecycle helpers shared by the master and worker binaries.
+//!
+//! - `notify` implements the `sd_notify(3)` datagram protocol so both services
+//!   can run as `Type=notify` systemd units. Without `NOTIFY_SOCKET` (not under
+//!   systemd) it is a no-op.
+//! - `watchdog_interval` reads the `WatchdogSec=` the unit was started with.
+//! - `shutdown_signal` resolves on the first SIGTERM or SIGINT.
+
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// States reported to the service manager.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyState {
+    /// Startup finished, the service is accepting requests.
+    Ready,
+    /// Shutdown started.
+    Stopping,
+    /// Free-form status line shown by `systemctl status`.
+    Status(String),
+    /// Keep-alive ping for `WatchdogSec=`.
+    Watchdog,
+}
+
+impl NotifyState {
+    fn as_line(&self) -> String {
+        match self {
+            NotifyState::Ready => "READY=1".to_string(),
+            NotifyState::Stopping => "STOPPING=1".to_string(),
+            NotifyState::Status(status) => format!("STATUS={}", status.replace('\n', " ")),
+            NotifyState::Watchdog => "WATCHDOG=1".to_string(),
+        }
+    }
+}
+
+/// Send `states` to the socket named by `NOTIFY_SOCKET`.
+///
+/// Returns `Ok(false)` when not running under systemd.
+///
+/// # Errors
+///
+/// - if the socket cannot be created or the datagram cannot be sent
+pub fn notify(states: &[NotifyState]) -> io::Result<bool> {
+    notify_socket(std::env::var_os("NOTIFY_SOCKET"), states)
+}
+
+/// [`notify`] with the value of `NOTIFY_SOCKET` passed in.
+fn notify_socket(path: Option<OsString>, states: &[NotifyState]) -> io::Result<bool> {
+    let Some(path) = path else {
+        return Ok(false);
+    };
+
+    let message = states
+        .iter()
+        .map(NotifyState::as_line)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let socket = UnixDatagram::unbound()?;
+    let path = path.to_string_lossy();
+    if let Some(name) = path.strip_prefix('@') {
+        // Abstract namespace socket (systemd's default inside containers).
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+        socket.send_to_addr(message.as_bytes(), &addr)?;
+    } else {
+        socket.send_to(message.as_bytes(), path.as_ref())?;
+    }
+    Ok(true)
+}
+
+/// Watchdog timeout systemd expects pings within, from `WATCHDOG_USEC`.
+///
+/// `None` when the watchdog is disabled or meant for another process
+/// (`WATCHDOG_PID` set to a different pid).
+#[must_use]
+pub fn watchdog_interval() -> Option<Duration> {
+    watchdog_interval_from(|name| std::env::var(name).ok())
+}
+
+/// [`watchdog_interval`] with the environment read through `var`.
+fn watchdog_interval_from(var: impl Fn(&str) -> Option<String>) -> Option<Duration> {
+    if let Some(pid) = var("WATCHDOG_PID")
+        && pid.parse::<u32>().ok() != Some(std::process::id())
+    {
+        return None;
+    }
+    let usec: u64 = var("WATCHDOG_USEC")?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Resolve when the process receives SIGTERM or SIGINT.
+///
+/// # Errors
+///
+/// - if the signal handlers cannot be installed
+pub async fn shutdown_signal() -> io::Result<&'static str> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    tokio::select! {
+        _ = sigterm.recv() => Ok("SIGTERM"),
+        _ = sigint.recv() => Ok("SIGINT"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_lines_are_single_line() {
+        let state = NotifyState::Status("draining\n2 VMs left".to_string());
+        assert_eq!(state.as_line(), "STATUS=draining 2 VMs left");
+    }
+
+    #[test]
+    fn watchdog_interval_honours_pid() {
+        let env = |pid: Option<String>| {
+            move |name: &str| match name {
+                "WATCHDOG_USEC" => Some("20000000".to_string()),
+                "WATCHDOG_PID" => pid.clone(),
+                _ => None,
+            }
+        };
+        let own = Some(std::process::id().to_string());
+        assert_eq!(
+            watchdog_interval_from(env(own)),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(
+            watchdog_interval_from(env(None)),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(watchdog_interval_from(env(Some("1".to_string()))), None);
+        assert_eq!(watchdog_interval_from(|_| None), None);
+    }
+
+    #[test]
+    fn notify_delivers_datagram_to_socket() {
+        let dir = std::env::temp_dir().join(format!("pcr-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        let states = [NotifyState::Ready, NotifyState::Status("up".into())];
+        let sent = notify_socket(Some(path.into()), &states).unwrap();
+
+        let mut buf = [0u8; 64];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert!(sent);
+        assert_eq!(&buf[..n], b"READY=1\nSTATUS=up");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
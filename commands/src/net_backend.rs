@@ -0,0 +1,137 @@
+//! How a VM's network interface is attached on its worker
+//! (`Common.NetBackend`).
+//!
+//! | Backend      | Device                                                   |
+//! |--------------|----------------------------------------------------------|
+//! | `tap`        | virtio-net on a TAP device the worker bridges            |
+//! | `vhost-user` | vhost-user-net on a host switch's socket (OVS-DPDK, passt) |
+//!
+//! Every worker attaches `tap`. A spec asking for another backend only runs
+//! on workers that advertise it in `WorkerStatus.netBackends`, whose host
+//! was prepared for it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::common_capnp;
+
+/// One network backend, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum NetBackend {
+    #[default]
+    Tap,
+    VhostUser,
+}
+
+impl NetBackend {
+    pub const ALL: [NetBackend; 2] = [NetBackend::Tap, NetBackend::VhostUser];
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NetBackend::Tap => "tap",
+            NetBackend::VhostUser => "vhost-user",
+        }
+    }
+}
+
+impl fmt::Display for NetBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// A backend name that is none of [`NetBackend::ALL`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownNetBackend(pub String);
+
+impl fmt::Display for UnknownNetBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown network backend {:?}, expected tap or vhost-user",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownNetBackend {}
+
+impl FromStr for NetBackend {
+    type Err = UnknownNetBackend;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NetBackend::ALL
+            .into_iter()
+            .find(|backend| backend.as_str() == s)
+            .ok_or_else(|| UnknownNetBackend(s.to_string()))
+    }
+}
+
+// ─── Wire ──────────────────────────────────────────────────────────────────
+
+/// The backend a spec asks for. One newer than this build cannot be
+/// attached, so it fails rather than falling back to `tap`.
+///
+/// # Errors
+///
+/// - if the backend is not in this build's schema
+pub fn read(
+    backend: Result<common_capnp::NetBackend, capnp::NotInSchema>,
+) -> Result<NetBackend, capnp::Error> {
+    backend
+        .map(Into::into)
+        .map_err(|capnp::NotInSchema(value)| {
+            capnp::Error::failed(format!("network backend {value} is newer than this build"))
+        })
+}
+
+/// The backends a worker advertises, leaving out those newer than this
+/// build: no spec this build reads can ask for them.
+#[must_use]
+pub fn read_list(list: capnp::enum_list::Reader<'_, common_capnp::NetBackend>) -> Vec<NetBackend> {
+    list.iter().filter_map(Result::ok).map(Into::into).collect()
+}
+
+impl From<common_capnp::NetBackend> for NetBackend {
+    fn from(backend: common_capnp::NetBackend) -> Self {
+        match backend {
+            common_capnp::NetBackend::Tap => NetBackend::Tap,
+            common_capnp::NetBackend::VhostUser => NetBackend::VhostUser,
+        }
+    }
+}
+
+impl From<NetBackend> for common_capnp::NetBackend {
+    fn from(backend: NetBackend) -> Self {
+        match backend {
+            NetBackend::Tap => common_capnp::NetBackend::Tap,
+            NetBackend::VhostUser => common_capnp::NetBackend::VhostUser,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends_parse_back_from_their_names() {
+        for backend in NetBackend::ALL {
+            assert_eq!(backend.as_str().parse::<NetBackend>(), Ok(backend));
+        }
+        assert_eq!(
+            "macvtap".parse::<NetBackend>(),
+            Err(UnknownNetBackend("macvtap".to_string()))
+        );
+    }
+
+    #[test]
+    fn specs_with_a_newer_backend_are_refused() {
+        assert_eq!(
+            read(Ok(common_capnp::NetBackend::VhostUser)).unwrap(),
+            NetBackend::VhostUser
+        );
+        assert!(read(Err(capnp::NotInSchema(7))).is_err());
+    }
+}
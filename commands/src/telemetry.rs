@@ -0,0 +1,276 @@
+//! Distributed tracing shared by the CLI, master and worker.
+//!
+//! Spans are exported over OTLP/gRPC when an endpoint is configured, and the
+//! W3C trace context (`traceparent`/`tracestate`) travels inside every
+//! Cap'n Proto request as a `Common.TraceContext` struct. A `pcr` command
+//! therefore produces one trace spanning the CLI call, the master handler,
+//! the worker RPC and the VM manager work it triggers.
+//!
+//! Without an endpoint nothing is exported, but spans still get OpenTelemetry
+//! ids and the context is still propagated, so `trace_id` can be used to
+//! correlate logs across nodes.
+
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::common_capnp::trace_context;
+
+/// Standard environment variable read when no endpoint is configured.
+pub const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+
+// ─── Export ────────────────────────────────────────────────────────────────
+
+/// Where and how much to export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelemetryConfig {
+    /// `service.name` resource attribute (`procurator-master`, ...).
+    pub service_name: String,
+    /// OTLP/gRPC collector, e.g. `http://localhost:4317`. `None` disables export.
+    pub otlp_endpoint: Option<String>,
+    /// Fraction of new root traces that are sampled; child spans follow
+    /// the caller's decision.
+    pub sample_ratio: f64,
+}
+
+impl TelemetryConfig {
+    /// Export settings taken from `OTEL_EXPORTER_OTLP_ENDPOINT`, sampling everything.
+    #[must_use]
+    pub fn from_env(service_name: impl Into<String>) -> Self {
+        Self {
+            service_name: service_name.into(),
+            otlp_endpoint: std::env::var(OTLP_ENDPOINT_ENV)
+                .ok()
+                .filter(|e| !e.is_empty()),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+/// Keeps the exporter alive; flushes pending spans on `shutdown` or drop.
+pub struct TelemetryGuard {
+    provider: SdkTracerProvider,
+}
+
+impl TelemetryGuard {
+    /// Flush and stop the exporter. Call before the runtime shuts down.
+    pub fn shutdown(self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::warn!(error = %e, "Failed to flush traces");
+        }
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        // Flushing twice is harmless; this covers early returns.
+        let _ = self.provider.force_flush();
+    }
+}
+
+/// `tracing` layer produced by [`otel_layer`].
+pub type OtelLayer<S> =
+    tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>;
+
+/// Build the `tracing` layer that gives spans OpenTelemetry ids and, when an
+/// endpoint is configured, exports them over OTLP/gRPC.
+///
+/// Also installs the W3C propagator globally. Must be called from inside a
+/// Tokio runtime.
+///
+/// # Errors
+///
+/// - if the OTLP exporter cannot be built (e.g. an invalid endpoint)
+pub fn otel_layer<S>(
+    config: &TelemetryConfig,
+) -> Result<(OtelLayer<S>, TelemetryGuard), opentelemetry_otlp::ExporterBuildError>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let mut builder = SdkTracerProvider::builder()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        );
+
+    if let Some(endpoint) = &config.otlp_endpoint {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        builder = builder.with_batch_exporter(exporter);
+    }
+
+    let provider = builder.build();
+    let tracer = provider.tracer(config.service_name.clone());
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer);
+    Ok((layer, TelemetryGuard { provider }))
+}
+
+// ─── Propagation ───────────────────────────────────────────────────────────
+
+/// W3C trace context as carried on the wire.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceHeaders {
+    pub traceparent: String,
+    pub tracestate: String,
+}
+
+impl Injector for TraceHeaders {
+    fn set(&mut self, key: &str, value: String) {
+        match key {
+            TRACEPARENT => self.traceparent = value,
+            TRACESTATE => self.tracestate = value,
+            _ => {}
+        }
+    }
+}
+
+impl Extractor for TraceHeaders {
+    fn get(&self, key: &str) -> Option<&str> {
+        let value = match key {
+            TRACEPARENT => &self.traceparent,
+            TRACESTATE => &self.tracestate,
+            _ => return None,
+        };
+        (!value.is_empty()).then_some(value.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        vec![TRACEPARENT, TRACESTATE]
+    }
+}
+
+impl TraceHeaders {
+    /// Context of `span`, empty if it is not part of a sampled or remote trace.
+    #[must_use]
+    pub fn from_span(span: &tracing::Span) -> Self {
+        let mut headers = Self::default();
+        TraceContextPropagator::new().inject_context(&span.context(), &mut headers);
+        headers
+    }
+
+    /// Context of the span the caller is currently in.
+    #[must_use]
+    pub fn current() -> Self {
+        Self::from_span(&tracing::Span::current())
+    }
+
+    /// Make `span` a child of the remote caller described by these headers.
+    /// Headers that do not parse leave `span` as a new root.
+    pub fn set_parent_of(&self, span: &tracing::Span) {
+        let parent = TraceContextPropagator::new().extract(self);
+        if parent.span().span_context().is_valid() {
+            let _ = span.set_parent(parent);
+        }
+    }
+
+    /// Trace id for log correlation, if the headers carry a valid context.
+    #[must_use]
+    pub fn trace_id(&self) -> Option<String> {
+        let context = TraceContextPropagator::new().extract(self);
+        let span = context.span();
+        let span_context = span.span_context();
+        span_context
+            .is_valid()
+            .then(|| span_context.trace_id().to_string())
+    }
+}
+
+impl TraceHeaders {
+    /// Fill a request's `trace` field.
+    pub fn write(&self, mut builder: trace_context::Builder<'_>) {
+        builder.set_traceparent(&self.traceparent);
+        builder.set_tracestate(&self.tracestate);
+    }
+
+    /// Read a request's `trace` field. Callers built before the field existed
+    /// (or sending invalid UTF-8) yield empty headers, i.e. a new root trace.
+    #[must_use]
+    pub fn read(reader: capnp::Result<trace_context::Reader<'_>>) -> Self {
+        let Ok(reader) = reader else {
+            return Self::default();
+        };
+        let text = |t: capnp::Result<capnp::text::Reader<'_>>| {
+            t.ok()
+                .and_then(|t| t.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        Self {
+            traceparent: text(reader.get_traceparent()),
+            tracestate: text(reader.get_tracestate()),
+        }
+    }
+}
+
+/// Span for an incoming RPC named `method`, parented to the caller's context.
+#[must_use]
+pub fn rpc_span(method: &'static str, headers: &TraceHeaders) -> tracing::Span {
+    let span = tracing::info_span!(
+        "rpc",
+        otel.name = method,
+        otel.kind = "server",
+        rpc.method = method,
+    );
+    headers.set_parent_of(&span);
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn with_otel<R>(f: impl FnOnce() -> R) -> R {
+        let provider = SdkTracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(subscriber, f)
+    }
+
+    #[test]
+    fn child_span_keeps_remote_trace_id() {
+        let incoming = TraceHeaders {
+            traceparent: PARENT.to_string(),
+            tracestate: String::new(),
+        };
+
+        let outgoing = with_otel(|| {
+            let span = tracing::info_span!("handler");
+            incoming.set_parent_of(&span);
+            TraceHeaders::from_span(&span)
+        });
+
+        assert_eq!(
+            outgoing.trace_id().as_deref(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_ne!(outgoing.traceparent, PARENT, "span id must be the child's");
+    }
+
+    #[test]
+    fn garbage_headers_are_ignored() {
+        let headers = TraceHeaders {
+            traceparent: "not-a-traceparent".to_string(),
+            tracestate: String::new(),
+        };
+        assert_eq!(headers.trace_id(), None);
+        assert_eq!(TraceHeaders::default().trace_id(), None);
+    }
+}
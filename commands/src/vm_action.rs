@@ -0,0 +1,126 @@
+//! Lifecycle actions a user asks for on one VM, outside of publishing a
+//! generation (`Master.vmAction`, `Worker.vmAction`, `pcr vm`).
+//!
+//! | Action     | On the worker                                      | While under way |
+//! |------------|----------------------------------------------------|-----------------|
+//! | `restart`  | reboot, or boot again when stopped                 | `restarting`    |
+//! | `redeploy` | delete and create again from its spec, same id     | `redeploying`   |
+//! | `stop`     | shut down; the VM is kept until deleted            | `stopping`      |
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::common_capnp;
+use crate::vm_state::VmState;
+
+/// One lifecycle action, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VmAction {
+    Restart,
+    Redeploy,
+    Stop,
+}
+
+impl VmAction {
+    pub const ALL: [VmAction; 3] = [VmAction::Restart, VmAction::Redeploy, VmAction::Stop];
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VmAction::Restart => "restart",
+            VmAction::Redeploy => "redeploy",
+            VmAction::Stop => "stop",
+        }
+    }
+
+    /// Status of the VM until the worker reports it again.
+    #[must_use]
+    pub fn transient_status(self) -> VmState {
+        match self {
+            VmAction::Restart => VmState::Restarting,
+            VmAction::Redeploy => VmState::Redeploying,
+            VmAction::Stop => VmState::Stopping,
+        }
+    }
+
+    /// Event reason once the worker carried it out.
+    #[must_use]
+    pub fn done_reason(self) -> &'static str {
+        match self {
+            VmAction::Restart => "Restarted",
+            VmAction::Redeploy => "Redeployed",
+            VmAction::Stop => "Stopped",
+        }
+    }
+}
+
+impl fmt::Display for VmAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An action name that is none of [`VmAction::ALL`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownAction(pub String);
+
+impl fmt::Display for UnknownAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown VM action {:?}, expected restart, redeploy or stop",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownAction {}
+
+impl FromStr for VmAction {
+    type Err = UnknownAction;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        VmAction::ALL
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| UnknownAction(s.to_string()))
+    }
+}
+
+// ─── Wire ──────────────────────────────────────────────────────────────────
+
+impl From<common_capnp::VmAction> for VmAction {
+    fn from(action: common_capnp::VmAction) -> Self {
+        match action {
+            common_capnp::VmAction::Restart => VmAction::Restart,
+            common_capnp::VmAction::Redeploy => VmAction::Redeploy,
+            common_capnp::VmAction::Stop => VmAction::Stop,
+        }
+    }
+}
+
+impl From<VmAction> for common_capnp::VmAction {
+    fn from(action: VmAction) -> Self {
+        match action {
+            VmAction::Restart => common_capnp::VmAction::Restart,
+            VmAction::Redeploy => common_capnp::VmAction::Redeploy,
+            VmAction::Stop => common_capnp::VmAction::Stop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_parse_back_from_their_names() {
+        for action in VmAction::ALL {
+            assert_eq!(action.as_str().parse::<VmAction>(), Ok(action));
+        }
+        assert_eq!(
+            "reboot".parse::<VmAction>(),
+            Err(UnknownAction("reboot".to_string()))
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! Lifecycle state of a VM, as workers report it and the master and CLI
+//! show it (`Common.VmState`).
+//!
+//! | State          | Set by  | Meaning                                          |
+//! |----------------|---------|--------------------------------------------------|
+//! | `pending`      | master  | desired, no worker reports it yet                |
+//! | `booting`      | worker  | booted, waiting for the guest to become ready    |
+//! | `running`      | worker  | ready                                            |
+//! | `restarting`   | master  | a [`restart`](crate::vm_action) is under way     |
+//! | `redeploying`  | master  | a `redeploy` is under way                        |
+//! | `stopping`     | master  | a `stop` is under way                            |
+//! | `stopped`      | worker  | shut down by a `stop`, kept until deleted        |
+//! | `boot-failed`  | worker  | not ready within the boot timeout                |
+//! | `draining`     | worker  | deleted, waiting for the guest to power off      |
+//! | `crashed`      | worker  | VMM exited or hung, kept until deleted           |
+//! | `unknown`      | reader  | not set, or a state the reader does not know     |
+//!
+//! Messages carry the state twice: as `state`, and by name as `status` for
+//! peers that predate the enum. [`read`] takes `state` and falls back to
+//! the name when an older peer left it unset; a state added after the
+//! reader was built reads as [`VmState::Unknown`] instead of failing the
+//! whole message.
+//!
+//! Workers only move a VM along the edges [`VmState::can_become`] allows
+//! and keep the latest moves as [`Transition`]s, which they report with the
+//! VM so a stuck one shows how it got there:
+//!
+//! ```text
+//! pending ──► booting ──► running ◄──► stopped
+//!                │   ▲        │
+//!                ▼   │        ▼
+//!        boot-failed ─► restarting / redeploying ──► booting or running
+//! ```
+//!
+//! `restarting` and `redeploying` are also entered from `running` and
+//! `stopped` (redeploy) and from `booting` (both), and `booting` may be
+//! stopped directly. A deleted `booting` or `running` VM may go `draining`
+//! first, and leaves the worker from there. A `running` or `stopped` VM
+//! whose VMM died goes `crashed`, and comes back like a `boot-failed` one.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::common_capnp;
+
+/// One lifecycle state, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum VmState {
+    #[default]
+    Unknown,
+    Pending,
+    Booting,
+    Running,
+    Restarting,
+    Redeploying,
+    Stopping,
+    Stopped,
+    BootFailed,
+    Draining,
+    Crashed,
+}
+
+impl VmState {
+    pub const ALL: [VmState; 11] = [
+        VmState::Unknown,
+        VmState::Pending,
+        VmState::Booting,
+        VmState::Running,
+        VmState::Restarting,
+        VmState::Redeploying,
+        VmState::Stopping,
+        VmState::Stopped,
+        VmState::BootFailed,
+        VmState::Draining,
+        VmState::Crashed,
+    ];
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VmState::Unknown => "unknown",
+            VmState::Pending => "pending",
+            VmState::Booting => "booting",
+            VmState::Running => "running",
+            VmState::Restarting => "restarting",
+            VmState::Redeploying => "redeploying",
+            VmState::Stopping => "stopping",
+            VmState::Stopped => "stopped",
+            VmState::BootFailed => "boot-failed",
+            VmState::Draining => "draining",
+            VmState::Crashed => "crashed",
+        }
+    }
+
+    /// The state named `name`, [`VmState::Unknown`] for any other name.
+    #[must_use]
+    pub fn from_name(name: &str) -> Self {
+        name.parse().unwrap_or_default()
+    }
+
+    /// Whether a worker may move a VM from `self` to `next`, see the module
+    /// docs. Staying in the same state is always allowed.
+    #[must_use]
+    pub fn can_become(self, next: VmState) -> bool {
+        use VmState::{
+            BootFailed, Booting, Crashed, Draining, Pending, Redeploying, Restarting, Running,
+            Stopped,
+        };
+        self == next
+            || matches!(
+                (self, next),
+                (Pending | Restarting | Redeploying, Booting | Running)
+                    | (Booting, Running | BootFailed | Stopped)
+                    | (Running, Stopped)
+                    | (Stopped, Running)
+                    | (Booting | BootFailed | Crashed, Restarting)
+                    | (
+                        Booting | Running | Stopped | BootFailed | Crashed,
+                        Redeploying
+                    )
+                    | (Booting | Running, Draining)
+                    | (Running | Stopped, Crashed)
+            )
+    }
+}
+
+impl fmt::Display for VmState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// A state name that is none of [`VmState::ALL`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownState(pub String);
+
+impl fmt::Display for UnknownState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown VM state {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownState {}
+
+impl FromStr for VmState {
+    type Err = UnknownState;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        VmState::ALL
+            .into_iter()
+            .find(|state| state.as_str() == s)
+            .ok_or_else(|| UnknownState(s.to_string()))
+    }
+}
+
+/// One state change of a VM, as its worker recorded it
+/// (`Common.VmTransition`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub from: VmState,
+    pub to: VmState,
+    /// Unix milliseconds
+    pub at_ms: u64,
+    /// Why, e.g. the boot failure; empty when the states say it all
+    pub reason: String,
+}
+
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.from, self.to)?;
+        if !self.reason.is_empty() {
+            write!(f, ": {}", self.reason)?;
+        }
+        Ok(())
+    }
+}
+
+// ─── Wire ──────────────────────────────────────────────────────────────────
+
+/// The state of a message carrying `state` and, by name, `status`.
+#[must_use]
+pub fn read(state: Result<common_capnp::VmState, capnp::NotInSchema>, status: &str) -> VmState {
+    match state {
+        Ok(common_capnp::VmState::Unknown) => VmState::from_name(status),
+        Ok(state) => state.into(),
+        Err(capnp::NotInSchema(value)) => {
+            tracing::debug!(value, status, "VM state newer than this build");
+            VmState::Unknown
+        }
+    }
+}
+
+/// Fill `list`, as initialised to `transitions.len()`, oldest first.
+pub fn write_transitions(
+    transitions: &[Transition],
+    mut list: capnp::struct_list::Builder<'_, common_capnp::vm_transition::Owned>,
+) {
+    for (i, transition) in (0..list.len()).zip(transitions) {
+        let mut entry = list.reborrow().get(i);
+        entry.set_from(transition.from.into());
+        entry.set_to(transition.to.into());
+        entry.set_at_ms(transition.at_ms);
+        entry.set_reason(&transition.reason);
+    }
+}
+
+/// The transitions of `list`, states this build does not know reading as
+/// [`VmState::Unknown`].
+///
+/// # Errors
+///
+/// - if a reason is not valid text
+pub fn read_transitions(
+    list: capnp::struct_list::Reader<'_, common_capnp::vm_transition::Owned>,
+) -> capnp::Result<Vec<Transition>> {
+    list.iter()
+        .map(|entry| {
+            Ok(Transition {
+                from: read(entry.get_from(), ""),
+                to: read(entry.get_to(), ""),
+                at_ms: entry.get_at_ms(),
+                reason: entry.get_reason()?.to_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+impl From<common_capnp::VmState> for VmState {
+    fn from(state: common_capnp::VmState) -> Self {
+        match state {
+            common_capnp::VmState::Unknown => VmState::Unknown,
+            common_capnp::VmState::Pending => VmState::Pending,
+            common_capnp::VmState::Booting => VmState::Booting,
+            common_capnp::VmState::Running => VmState::Running,
+            common_capnp::VmState::Restarting => VmState::Restarting,
+            common_capnp::VmState::Redeploying => VmState::Redeploying,
+            common_capnp::VmState::Stopping => VmState::Stopping,
+            common_capnp::VmState::Stopped => VmState::Stopped,
+            common_capnp::VmState::BootFailed => VmState::BootFailed,
+            common_capnp::VmState::Draining => VmState::Draining,
+            common_capnp::VmState::Crashed => VmState::Crashed,
+        }
+    }
+}
+
+impl From<VmState> for common_capnp::VmState {
+    fn from(state: VmState) -> Self {
+        match state {
+            VmState::Unknown => common_capnp::VmState::Unknown,
+            VmState::Pending => common_capnp::VmState::Pending,
+            VmState::Booting => common_capnp::VmState::Booting,
+            VmState::Running => common_capnp::VmState::Running,
+            VmState::Restarting => common_capnp::VmState::Restarting,
+            VmState::Redeploying => common_capnp::VmState::Redeploying,
+            VmState::Stopping => common_capnp::VmState::Stopping,
+            VmState::Stopped => common_capnp::VmState::Stopped,
+            VmState::BootFailed => common_capnp::VmState::BootFailed,
+            VmState::Draining => common_capnp::VmState::Draining,
+            VmState::Crashed => common_capnp::VmState::Crashed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn states_parse_back_from_their_names() {
+        for state in VmState::ALL {
+            assert_eq!(state.as_str().parse::<VmState>(), Ok(state));
+        }
+        assert_eq!(
+            "migrating".parse::<VmState>(),
+            Err(UnknownState("migrating".to_string()))
+        );
+        assert_eq!(VmState::from_name("migrating"), VmState::Unknown);
+    }
+
+    #[test]
+    fn workers_follow_the_lifecycle() {
+        use VmState::{
+            BootFailed, Booting, Crashed, Draining, Pending, Redeploying, Restarting, Running,
+            Stopped,
+        };
+        for (from, to) in [
+            (Pending, Booting),
+            (Booting, Running),
+            (Booting, BootFailed),
+            (BootFailed, Restarting),
+            (Restarting, Booting),
+            (Running, Stopped),
+            (Stopped, Running),
+            (Stopped, Redeploying),
+            (Redeploying, Running),
+            (Running, Running),
+            (Running, Draining),
+            (Booting, Draining),
+            (Running, Crashed),
+            (Stopped, Crashed),
+            (Crashed, Restarting),
+            (Crashed, Redeploying),
+        ] {
+            assert!(from.can_become(to), "{from} -> {to}");
+        }
+        for (from, to) in [
+            (Running, Booting),
+            (Running, BootFailed),
+            (Stopped, Booting),
+            (BootFailed, Running),
+            (Running, Pending),
+            (Pending, Stopped),
+            (Stopped, Draining),
+            (Draining, Running),
+            (Draining, Redeploying),
+            (Crashed, Running),
+            (Booting, Crashed),
+            (Draining, Crashed),
+        ] {
+            assert!(!from.can_become(to), "{from} -> {to}");
+        }
+    }
+
+    #[test]
+    fn transitions_survive_the_wire() {
+        let transitions = [
+            Transition {
+                from: VmState::Pending,
+                to: VmState::Booting,
+                at_ms: 1_000,
+                reason: String::new(),
+            },
+            Transition {
+                from: VmState::Booting,
+                to: VmState::BootFailed,
+                at_ms: 61_000,
+                reason: "not ready within the boot timeout".to_string(),
+            },
+        ];
+        let mut message = capnp::message::Builder::new_default();
+        let mut vm = message.init_root::<common_capnp::running_vm::Builder<'_>>();
+        write_transitions(&transitions, vm.reborrow().init_transitions(2));
+        let read_back = read_transitions(vm.into_reader().get_transitions().unwrap()).unwrap();
+        assert_eq!(read_back, transitions);
+        assert_eq!(
+            read_back[1].to_string(),
+            "booting -> boot-failed: not ready within the boot timeout"
+        );
+    }
+
+    #[test]
+    fn reads_fall_back_to_the_name_and_tolerate_newer_states() {
+        assert_eq!(
+            read(Ok(common_capnp::VmState::BootFailed), "boot-failed"),
+            VmState::BootFailed
+        );
+        // An older peer only sets the name
+        assert_eq!(
+            read(Ok(common_capnp::VmState::Unknown), "running"),
+            VmState::Running
+        );
+        // A newer peer sends a state this build does not have
+        assert_eq!(
+            read(Err(capnp::NotInSchema(42)), "migrating"),
+            VmState::Unknown
+        );
+    }
+}
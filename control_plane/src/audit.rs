@@ -0,0 +1,300 @@
+//! Append-only audit trail of mutating master RPCs.
+//!
+//! Every mutating call is recorded as one JSON line: when, who, which method,
+//! a summary of its parameters and the outcome. Once the file grows past
+//! `max_bytes` it is rotated (`audit.jsonl` → `audit.jsonl.1` → … up to
+//! `keep` files); rotated files are never written again.
+//!
+//! RPCs are not authenticated yet, so the actor is the caller's peer
+//! address. Read-only calls and worker telemetry (`pushData`, `pushLogs`)
+//! are not audited.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Where the audit log lives and how much of it is kept.
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    pub path: PathBuf,
+    /// Size after which the current file is rotated.
+    pub max_bytes: u64,
+    /// Number of rotated files kept next to the current one.
+    pub keep: usize,
+}
+
+impl AuditConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_bytes: 64 * 1024 * 1024,
+            keep: 5,
+        }
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+}
+
+/// One audited call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub actor: String,
+    pub method: String,
+    /// Summary of the request, not the full payload.
+    pub params: serde_json::Value,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        actor: impl Into<String>,
+        method: impl Into<String>,
+        params: serde_json::Value,
+        outcome: Result<(), String>,
+    ) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+        Self {
+            timestamp_ms,
+            actor: actor.into(),
+            method: method.into(),
+            params,
+            ok: outcome.is_ok(),
+            error: outcome.err(),
+        }
+    }
+}
+
+/// Shared handle to the audit file; cloned into every connection.
+#[derive(Clone)]
+pub struct AuditLog {
+    inner: Arc<Mutex<Writer>>,
+}
+
+struct Writer {
+    config: AuditConfig,
+    file: File,
+    size: u64,
+}
+
+impl AuditLog {
+    /// Open (or create) the current audit file for appending.
+    ///
+    /// # Errors
+    ///
+    /// - if the directory or file cannot be created
+    pub fn open(config: AuditConfig) -> io::Result<Self> {
+        if let Some(parent) = config.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = open_append(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Writer { config, file, size })),
+        })
+    }
+
+    /// Append `entry`, rotating first if the file is full.
+    ///
+    /// # Errors
+    ///
+    /// - if the entry cannot be written or the file cannot be rotated
+    pub fn record(&self, entry: &AuditEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let mut writer = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if writer.size > 0 && writer.size + line.len() as u64 > writer.config.max_bytes {
+            writer.rotate()?;
+        }
+        writer.file.write_all(&line)?;
+        writer.file.sync_data()?;
+        writer.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Check that entries still land in the file at `path` and that it can
+    /// be appended to, e.g. that it was not removed or remounted read-only.
+    ///
+    /// # Errors
+    ///
+    /// - if the file is missing, was replaced, or cannot be opened for writing
+    pub fn check(&self) -> io::Result<()> {
+        let writer = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let on_disk = std::fs::metadata(&writer.config.path)?;
+        if on_disk.ino() != writer.file.metadata()?.ino() {
+            return Err(io::Error::other("audit file was replaced or removed"));
+        }
+        OpenOptions::new()
+            .append(true)
+            .open(&writer.config.path)
+            .map(drop)
+    }
+
+    /// Entries recorded at or after `since_ms`, oldest first, at most the
+    /// last `limit` of them (`0` for no limit). Rotated files are included.
+    ///
+    /// # Errors
+    ///
+    /// - if an existing audit file cannot be read
+    pub fn query(&self, since_ms: u64, limit: usize) -> io::Result<Vec<AuditEntry>> {
+        let config = {
+            let writer = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+            writer.config.clone()
+        };
+
+        let mut files: Vec<PathBuf> = (1..=config.keep.max(1))
+            .rev()
+            .map(|n| config.rotated(n))
+            .collect();
+        files.push(config.path.clone());
+
+        let mut entries = VecDeque::new();
+        for path in files {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                let entry: AuditEntry = match serde_json::from_str(&line) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        tracing::warn!(path = %path.display(), error = %e, "Skipping malformed audit line");
+                        continue;
+                    }
+                };
+                if entry.timestamp_ms < since_ms {
+                    continue;
+                }
+                entries.push_back(entry);
+                if limit > 0 && entries.len() > limit {
+                    entries.pop_front();
+                }
+            }
+        }
+        Ok(entries.into())
+    }
+}
+
+impl Writer {
+    /// Shift `path.N` to `path.N+1`, dropping the oldest, and start a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        let keep = self.config.keep.max(1);
+        for n in (1..keep).rev() {
+            match std::fs::rename(self.config.rotated(n), self.config.rotated(n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        std::fs::rename(&self.config.path, self.config.rotated(1))?;
+        self.file = open_append(&self.config.path)?;
+        self.size = 0;
+        tracing::info!(path = %self.config.path.display(), "Rotated audit log");
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_config(name: &str) -> AuditConfig {
+        let dir = std::env::temp_dir().join(format!("pcr-audit-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        AuditConfig::new(dir.join("audit.jsonl"))
+    }
+
+    fn entry(method: &str, timestamp_ms: u64) -> AuditEntry {
+        AuditEntry {
+            timestamp_ms,
+            ..AuditEntry::new("peer:127.0.0.1:1", method, json!({}), Ok(()))
+        }
+    }
+
+    #[test]
+    fn query_filters_by_time_and_keeps_the_latest() {
+        let config = temp_config("query");
+        let log = AuditLog::open(config.clone()).unwrap();
+        for ts in [10, 20, 30, 40] {
+            log.record(&entry("Master.publishState", ts)).unwrap();
+        }
+
+        let entries = log.query(20, 2).unwrap();
+        let stamps: Vec<u64> = entries.iter().map(|e| e.timestamp_ms).collect();
+        assert_eq!(stamps, vec![30, 40]);
+        assert_eq!(log.query(0, 0).unwrap().len(), 4);
+
+        let _ = std::fs::remove_dir_all(config.path.parent().unwrap());
+    }
+
+    #[test]
+    fn rotation_keeps_bounded_history() {
+        let mut config = temp_config("rotate");
+        config.max_bytes = 1;
+        config.keep = 2;
+        let log = AuditLog::open(config.clone()).unwrap();
+        for ts in 1..=4 {
+            log.record(&entry("Master.publishState", ts)).unwrap();
+        }
+
+        // One entry per file: current, .1, .2 — entry 1 fell off the end.
+        assert!(config.rotated(2).exists());
+        assert!(!config.rotated(3).exists());
+        let stamps: Vec<u64> = log
+            .query(0, 0)
+            .unwrap()
+            .iter()
+            .map(|e| e.timestamp_ms)
+            .collect();
+        assert_eq!(stamps, vec![2, 3, 4]);
+
+        let _ = std::fs::remove_dir_all(config.path.parent().unwrap());
+    }
+
+    #[test]
+    fn check_fails_once_the_file_is_removed() {
+        let config = temp_config("check");
+        let log = AuditLog::open(config.clone()).unwrap();
+        log.check().unwrap();
+
+        std::fs::remove_file(&config.path).unwrap();
+        assert!(log.check().is_err());
+
+        let _ = std::fs::remove_dir_all(config.path.parent().unwrap());
+    }
+
+    #[test]
+    fn failed_calls_record_the_error() {
+        let entry = AuditEntry::new(
+            "peer:x",
+            "Master.publishState",
+            json!({}),
+            Err("boom".into()),
+        );
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(line.contains(r#""ok":false"#));
+        assert!(line.contains(r#""error":"boom""#));
+    }
+}
@@ -0,0 +1,2708 @@
+//! Convergence deadlines and diagnosis of VMs that do not converge.
+//!
+//! Every published generation gets a deadline: the publisher's
+//! `convergenceDeadlineSecs`, [`DEFAULT_DEADLINE`] otherwise. Until it
+//! passes, VMs are allowed to be pending. After it, every desired VM that no
+//! worker reports running, and every VM a worker runs with a hash the
+//! generation does not want, gets a [`Diagnostic`] naming the likeliest cause:
+//!
+//! - `WorkerError` — a worker reports the VM not running, with its last error.
+//! - `MissingStorePath` — that error is about a store path of the spec the
+//!   worker does not have.
+//! - `Unscheduled` — no worker runs it at all.
+//! - `Drifted` — a worker runs a spec the generation does not want.
+//! - `Misplaced` — it runs, but not on the worker it is pinned to.
+//! - `WorkerUnreachable` — the last worker reporting it has not reported
+//!   for [`STALE_AFTER`], so whatever it said about the VM is stale.
+//!
+//! Only workers reporting within [`STALE_AFTER`] count a VM as running.
+//!
+//! Desired VMs are known by their spec hash until a worker reports them under
+//! a VM id; either can be described. A diagnostic is recorded again only when
+//! the cause changes, and the last [`MAX_EVENTS`] per VM are kept in memory,
+//! like the desired state itself, together with the last [`MAX_GENERATIONS`]
+//! publications and each worker's latest report. [`describe`](crate::describe)
+//! views of VMs, workers and generations are built from them.
+//!
+//! User [actions](commands::vm_action) on a VM are routed to the worker that
+//! last reported it, at the address it reported from. The request and its
+//! outcome are events of the VM, which is shown with the action's transient
+//! status (e.g. `restarting`) until that worker reports again after it.
+//!
+//! A VM is pinned to a worker by its spec's `pinnedWorker`, or once it runs
+//! with [`Convergence::pin`]. It then only counts as running on that worker,
+//! and [`Convergence::movable`] keeps drain and rescheduling from moving it
+//! unless forced. Pins set with `pinVm` last until unpinned.
+//!
+//! A spec asking for a network backend other than `tap` (e.g. `vhostUser`)
+//! only runs on workers advertising it in `pushData`, see
+//! [`scheduler::attaches`]. Pinning it elsewhere is refused, and when no
+//! worker offers the backend, `Unscheduled` says so.
+//!
+//! A [drift report](Convergence::drift_report) compares every reported VM
+//! with the active generation: drift is self-reported when the worker says
+//! the VM runs another spec than it was given, and a hash mismatch when the
+//! master finds its hash is not one the generation wants. A VM in sync
+//! tells how long it has run as desired, since the master first saw it so.
+//!
+//! The [cluster status](Convergence::status) lists every reported VM with
+//! the [namespace](crate::tenancy) of the spec it runs, and the desired VMs
+//! no worker reports as `pending`. Viewed from one namespace, VMs that no
+//! spec wants are left out, and convergence is that of the namespace's VMs.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use commands::hashing::{self, Drift};
+use commands::labels::Labels;
+use commands::net_backend::NetBackend;
+use commands::vm_action::VmAction;
+use commands::vm_state::{Transition, VmState};
+
+use crate::describe::{Condition, Description, Event, Field, Kind, Metric};
+use crate::intake::{Provenance, Publication};
+use crate::scheduler::{self, Resources};
+
+/// Deadline of a generation whose publisher did not set one.
+pub const DEFAULT_DEADLINE: Duration = Duration::from_mins(10);
+
+/// Diagnostics kept per VM, oldest dropped first.
+pub const MAX_EVENTS: usize = 20;
+
+/// Publications kept for `describe generation` and `pcr history`, oldest
+/// dropped first.
+pub const MAX_GENERATIONS: usize = 20;
+
+/// A worker whose last report is older is no longer `Reporting`.
+pub const STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Worker errors that mean a path is absent rather than broken.
+const MISSING_MARKERS: [&str; 3] = ["not found", "does not exist", "No such file"];
+
+/// Milliseconds since the Unix epoch.
+#[must_use]
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// A VM the active generation wants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesiredVm {
+    /// Spec hash, see [`commands::hashing`]
+    pub hash: String,
+    /// Toplevel, kernel, initrd and disk image
+    pub store_paths: Vec<String>,
+    /// Scheduling labels, matched by [maintenance](crate::maintenance) policies
+    pub labels: Labels,
+    /// Worker the spec pins it to
+    pub pinned: Option<String>,
+    /// [Namespace](crate::tenancy) of the spec
+    pub namespace: String,
+    /// How its NIC is attached, which not every worker can
+    pub net_backend: NetBackend,
+    /// CPU and memory the spec asks for
+    pub resources: Resources,
+}
+
+/// What a publication asks to converge to, and by when.
+#[derive(Debug, Clone, Default)]
+pub struct Target {
+    pub vms: Vec<DesiredVm>,
+    /// `None` for [`DEFAULT_DEADLINE`]
+    pub deadline: Option<Duration>,
+    /// Apply even when a [maintenance](crate::maintenance) policy holds it
+    pub emergency: bool,
+}
+
+/// One VM as reported by `pushData`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObservedVm {
+    pub id: String,
+    pub hash: String,
+    /// Hash of the spec the worker was given for it, empty when it does not
+    /// say
+    pub desired_hash: String,
+    pub status: VmState,
+    /// Last failure, empty when healthy
+    pub error: String,
+    pub metrics: VmMetrics,
+    /// Latest state changes the worker recorded, oldest first
+    pub transitions: Vec<Transition>,
+}
+
+/// `Common.VmMetrics` of a reported VM.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VmMetrics {
+    pub cpu_usage: f32,
+    pub memory_bytes: u64,
+    pub network_rx_bytes: u64,
+    pub network_tx_bytes: u64,
+}
+
+/// `Common.WorkerMetrics` of a reporting worker.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkerMetrics {
+    pub available_cpu: f32,
+    pub available_memory: u64,
+    /// Total of the breakdown below
+    pub disk_usage: u64,
+    /// Store closure bytes per image toplevel
+    pub image_disk_usage: Vec<(String, u64)>,
+    /// Volume bytes per VM id
+    pub vm_disk_usage: Vec<(String, u64)>,
+    pub log_disk_usage: u64,
+    pub uptime_secs: u64,
+    pub memory_pressure: MemoryPressure,
+    pub store_janitor: StoreJanitor,
+}
+
+/// `Common.MemoryPressure` of a reporting worker.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryPressure {
+    pub some_avg10: f32,
+    pub full_avg10: f32,
+    pub under_pressure: bool,
+    /// Empty when the worker does not watch memory pressure
+    pub policy: String,
+    pub episodes: u64,
+    pub restarts: u64,
+}
+
+/// `Common.StoreJanitor` of a reporting worker.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StoreJanitor {
+    pub used_percent: f32,
+    /// 0 when the worker does not manage its store
+    pub high_watermark: f32,
+    pub pinned_paths: u32,
+    pub collections: u64,
+    pub freed_bytes: u64,
+}
+
+/// One `pushData` call.
+#[derive(Debug, Clone)]
+pub struct Observation {
+    pub worker_id: String,
+    /// Where the worker serves `Worker`, empty when it did not say
+    pub address: String,
+    pub generation: u64,
+    pub metrics: WorkerMetrics,
+    pub vms: Vec<ObservedVm>,
+    /// Network backends it attaches, none from workers that predate them
+    pub net_backends: Vec<NetBackend>,
+}
+
+/// Likeliest cause of a VM missing its deadline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    WorkerError,
+    MissingStorePath,
+    Unscheduled,
+    Drifted,
+    Misplaced,
+    WorkerUnreachable,
+}
+
+impl Reason {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reason::WorkerError => "WorkerError",
+            Reason::MissingStorePath => "MissingStorePath",
+            Reason::Unscheduled => "Unscheduled",
+            Reason::Drifted => "Drifted",
+            Reason::Misplaced => "Misplaced",
+            Reason::WorkerUnreachable => "WorkerUnreachable",
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Where a VM action goes: the worker that reports the VM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub worker_id: String,
+    pub address: String,
+    pub vm_id: String,
+}
+
+/// Why a VM action cannot be routed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unroutable {
+    /// No worker reports the VM
+    NotReported(String),
+    /// The worker reporting it did not say where it listens
+    NoAddress { worker_id: String, vm_id: String },
+}
+
+impl fmt::Display for Unroutable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unroutable::NotReported(id) => write!(f, "no worker reports VM {id}"),
+            Unroutable::NoAddress { worker_id, vm_id } => write!(
+                f,
+                "worker {worker_id} runs VM {vm_id} but did not report its address"
+            ),
+        }
+    }
+}
+
+/// Why a VM can't be pinned, unpinned or moved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinError {
+    /// No worker reports the VM
+    NotReported(String),
+    /// Its spec pins it elsewhere, or pins it at all when unpinning
+    PinnedBySpec { vm_id: String, worker_id: String },
+    /// It is pinned and the move was not forced
+    Pinned { vm_id: String, worker_id: String },
+    /// The worker cannot attach the NIC its spec asks for
+    Unfit {
+        vm_id: String,
+        worker_id: String,
+        net_backend: NetBackend,
+    },
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinError::NotReported(id) => write!(f, "no worker reports VM {id}"),
+            PinError::PinnedBySpec { vm_id, worker_id } => write!(
+                f,
+                "VM {vm_id} is pinned to worker {worker_id} by its spec; publish a generation to change it"
+            ),
+            PinError::Pinned { vm_id, worker_id } => write!(
+                f,
+                "VM {vm_id} is pinned to worker {worker_id}; force the move or unpin it first"
+            ),
+            PinError::Unfit {
+                vm_id,
+                worker_id,
+                net_backend,
+            } => write!(
+                f,
+                "VM {vm_id} needs {net_backend} networking, which worker {worker_id} does not offer"
+            ),
+        }
+    }
+}
+
+/// A recorded diagnosis.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub timestamp_ms: u64,
+    pub generation: u64,
+    pub reason: Reason,
+    pub message: String,
+}
+
+#[derive(Debug)]
+struct Active {
+    generation: u64,
+    desired: Vec<DesiredVm>,
+    deadline_ms: u64,
+}
+
+/// A publication as remembered for `describe generation`.
+#[derive(Debug)]
+struct GenerationRecord {
+    number: u64,
+    commit: String,
+    publisher: String,
+    intent_hash: String,
+    published_ms: u64,
+    deadline_ms: u64,
+    vms: usize,
+    provenance: Provenance,
+}
+
+/// One of the last generations made active, as listed by `pcr history`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerationInfo {
+    pub number: u64,
+    pub commit: String,
+    pub publisher: String,
+    pub intent_hash: String,
+    pub published_ms: u64,
+    /// Whether it is the active generation
+    pub active: bool,
+    pub vms: usize,
+    pub provenance: Provenance,
+}
+
+/// How a VM in a [drift report](Convergence::drift_report) differs from
+/// the active generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftSource {
+    InSync,
+    /// The worker reports it runs another spec than it was given
+    SelfReported,
+    /// Its hash is not one the generation wants
+    HashMismatch,
+    /// Desired, but no worker reports it or says it was given it
+    Missing,
+}
+
+impl DriftSource {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DriftSource::InSync => "in-sync",
+            DriftSource::SelfReported => "self-reported",
+            DriftSource::HashMismatch => "hash-mismatch",
+            DriftSource::Missing => "missing",
+        }
+    }
+}
+
+impl fmt::Display for DriftSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One VM of a [drift report](Convergence::drift_report).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriftEntry {
+    /// Empty for a missing VM
+    pub vm_id: String,
+    pub worker_id: String,
+    pub namespace: String,
+    /// Empty when no spec of the generation wants it
+    pub desired_hash: String,
+    /// Empty for a missing VM
+    pub observed_hash: String,
+    /// How long it has run as desired, `None` unless in sync
+    pub converged_for: Option<Duration>,
+    pub drift: DriftSource,
+}
+
+/// Desired against observed state of every VM, for the active generation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DriftReport {
+    pub generation: u64,
+    pub commit: String,
+    /// Sorted by namespace, then VM id, missing VMs by spec hash
+    pub vms: Vec<DriftEntry>,
+}
+
+/// One worker in the [cluster status](Convergence::status).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerSummary {
+    pub id: String,
+    /// Reported within [`STALE_AFTER`]
+    pub healthy: bool,
+    pub generation: u64,
+    pub running_vms: usize,
+    pub net_backends: Vec<NetBackend>,
+}
+
+/// One VM in the [cluster status](Convergence::status).
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmSummary {
+    /// Worker VM id, the spec hash while no worker reports it
+    pub id: String,
+    /// Empty while no worker reports it
+    pub worker_id: String,
+    /// Empty when no spec wants it
+    pub namespace: String,
+    /// Empty when no spec wants it
+    pub desired_hash: String,
+    pub observed_hash: String,
+    pub status: VmState,
+    pub drifted: bool,
+    pub labels: Labels,
+    pub metrics: VmMetrics,
+}
+
+/// Why no worker can host a desired VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unschedulable {
+    /// No worker reported recently
+    NoWorkers,
+    /// The worker it is pinned to did not report recently
+    PinnedWorker,
+    /// No worker it may run on offers its network backend
+    NetBackend,
+    /// No worker it may run on has the CPU and memory it asks for free
+    InsufficientResources,
+}
+
+impl Unschedulable {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Unschedulable::NoWorkers => "NoWorkers",
+            Unschedulable::PinnedWorker => "PinnedWorker",
+            Unschedulable::NetBackend => "NetBackend",
+            Unschedulable::InsufficientResources => "InsufficientResources",
+        }
+    }
+}
+
+impl fmt::Display for Unschedulable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A desired VM in the [cluster status](Convergence::status) that no
+/// worker reports and none can host as things stand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnschedulableVm {
+    /// Spec hash
+    pub hash: String,
+    pub namespace: String,
+    pub reason: Unschedulable,
+    /// The reason, with the workers and figures it comes from
+    pub message: String,
+}
+
+/// `Common.ClusterStatus`, for every namespace or one of them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClusterView {
+    pub generation: u64,
+    pub commit: String,
+    /// Desired VMs running, 100 when there are none
+    pub convergence_percent: u32,
+    /// Sorted by id
+    pub workers: Vec<WorkerSummary>,
+    /// Sorted by namespace, then id
+    pub vms: Vec<VmSummary>,
+    /// Pending VMs no worker can host, sorted by namespace, then hash
+    pub unschedulable: Vec<UnschedulableVm>,
+}
+
+/// A user action on a VM whose worker has not reported since.
+#[derive(Debug, Clone, Copy)]
+struct PendingAction {
+    action: VmAction,
+    /// When the worker carried it out, `None` while under way
+    done_ms: Option<u64>,
+}
+
+/// Latest report of a worker.
+#[derive(Debug)]
+struct WorkerView {
+    address: String,
+    generation: u64,
+    seen_ms: u64,
+    metrics: WorkerMetrics,
+    vms: Vec<ObservedVm>,
+    net_backends: Vec<NetBackend>,
+}
+
+impl WorkerView {
+    /// Capacity it last reported left.
+    fn free(&self) -> Resources {
+        Resources {
+            cpu: self.metrics.available_cpu,
+            memory_mb: self.metrics.available_memory / (1024 * 1024),
+        }
+    }
+
+    /// Whether it reported within [`STALE_AFTER`] of `now_ms`.
+    fn reporting(&self, now_ms: u64) -> bool {
+        Duration::from_millis(now_ms.saturating_sub(self.seen_ms)) < STALE_AFTER
+    }
+}
+
+/// Convergence of the active generation, fed by publishes and `pushData`.
+#[derive(Debug, Default)]
+pub struct Convergence {
+    active: Option<Active>,
+    /// Newest last
+    generations: VecDeque<GenerationRecord>,
+    workers: HashMap<String, WorkerView>,
+    /// Keyed by spec hash for desired VMs, by VM id for drifted ones
+    events: HashMap<String, VecDeque<Diagnostic>>,
+    /// Requests and outcomes of user actions, keyed by VM id
+    action_events: HashMap<String, VecDeque<Event>>,
+    /// Keyed by VM id
+    actions: HashMap<String, PendingAction>,
+    /// Worker id of the VMs pinned with `pinVm`, keyed by VM id. Kept while
+    /// the VM is not reported, since that is when a pin matters most.
+    pins: HashMap<String, String>,
+    /// Since when each VM runs a spec the active generation wants, keyed by
+    /// VM id
+    converged: HashMap<String, u64>,
+}
+
+impl Convergence {
+    /// Start tracking a newly published generation.
+    pub fn activate(&mut self, publication: &Publication, target: Target, now_ms: u64) {
+        let deadline = target.deadline.unwrap_or(DEFAULT_DEADLINE);
+        let deadline_ms =
+            now_ms.saturating_add(u64::try_from(deadline.as_millis()).unwrap_or(u64::MAX));
+        if self.generations.len() == MAX_GENERATIONS {
+            self.generations.pop_front();
+        }
+        self.generations.push_back(GenerationRecord {
+            number: publication.generation,
+            commit: publication.commit.clone(),
+            publisher: publication.publisher.clone(),
+            intent_hash: publication.intent_hash.clone(),
+            published_ms: now_ms,
+            deadline_ms,
+            vms: target.vms.len(),
+            provenance: publication.provenance.clone(),
+        });
+        self.active = Some(Active {
+            generation: publication.generation,
+            desired: target.vms,
+            deadline_ms,
+        });
+        self.prune();
+    }
+
+    /// Replace what `observation.worker_id` runs.
+    pub fn observe(&mut self, observation: Observation, now_ms: u64) {
+        for vm in &observation.vms {
+            if self
+                .actions
+                .get(&vm.id)
+                .is_some_and(|pending| pending.done_ms.is_some_and(|done| done <= now_ms))
+            {
+                self.actions.remove(&vm.id);
+            }
+        }
+        let converged: Vec<(String, u64)> = observation
+            .vms
+            .iter()
+            .filter(|vm| vm.status == VmState::Running && self.drift_of(vm) == DriftSource::InSync)
+            .map(|vm| {
+                let since = self.converged.get(&vm.id).copied().unwrap_or(now_ms);
+                (vm.id.clone(), since)
+            })
+            .collect();
+        if let Some(previous) = self.workers.get(&observation.worker_id) {
+            for vm in &previous.vms {
+                self.converged.remove(&vm.id);
+            }
+        }
+        self.converged.extend(converged);
+        self.workers.insert(
+            observation.worker_id,
+            WorkerView {
+                address: observation.address,
+                generation: observation.generation,
+                seen_ms: now_ms,
+                metrics: observation.metrics,
+                vms: observation.vms,
+                net_backends: observation.net_backends,
+            },
+        );
+    }
+
+    /// Route `action` on the VM with worker VM id or spec hash `id`, and
+    /// record that `actor` asked for it.
+    ///
+    /// # Errors
+    ///
+    /// - if no worker reports the VM, or its worker has no address
+    pub fn request_action(
+        &mut self,
+        id: &str,
+        action: VmAction,
+        actor: &str,
+        now_ms: u64,
+    ) -> Result<Route, Unroutable> {
+        let (worker_id, vm) = self
+            .resolve(id)
+            .ok_or_else(|| Unroutable::NotReported(id.to_string()))?;
+        let address = self
+            .workers
+            .get(worker_id)
+            .map(|view| view.address.clone())
+            .unwrap_or_default();
+        if address.is_empty() {
+            return Err(Unroutable::NoAddress {
+                worker_id: worker_id.to_string(),
+                vm_id: vm.id.clone(),
+            });
+        }
+        let route = Route {
+            worker_id: worker_id.to_string(),
+            address,
+            vm_id: vm.id.clone(),
+        };
+
+        self.actions.insert(
+            route.vm_id.clone(),
+            PendingAction {
+                action,
+                done_ms: None,
+            },
+        );
+        self.record_action_event(
+            &route.vm_id,
+            now_ms,
+            "ActionRequested",
+            format!(
+                "{action} requested by {actor}, sent to worker {}",
+                route.worker_id
+            ),
+        );
+        Ok(route)
+    }
+
+    /// Record how the worker of `route` carried out `action`. A failed
+    /// action leaves the VM with its reported status.
+    pub fn finish_action(
+        &mut self,
+        route: &Route,
+        action: VmAction,
+        outcome: Result<(), &str>,
+        now_ms: u64,
+    ) {
+        let (reason, message) = match outcome {
+            Ok(()) => {
+                if let Some(pending) = self.actions.get_mut(&route.vm_id) {
+                    pending.done_ms = Some(now_ms);
+                }
+                (
+                    action.done_reason(),
+                    format!("worker {} carried out {action}", route.worker_id),
+                )
+            }
+            Err(e) => {
+                self.actions.remove(&route.vm_id);
+                (
+                    "ActionFailed",
+                    format!("{action} failed on worker {}: {e}", route.worker_id),
+                )
+            }
+        };
+        self.record_action_event(&route.vm_id, now_ms, reason, message);
+    }
+
+    /// Pin the VM with worker VM id or spec hash `id` to `worker_id`, or to
+    /// the worker running it when `None`, and record that `actor` did.
+    /// Returns the worker it is pinned to.
+    ///
+    /// # Errors
+    ///
+    /// - if no worker reports the VM
+    /// - if its spec pins it to another worker
+    /// - if that worker cannot attach the NIC its spec asks for
+    pub fn pin(
+        &mut self,
+        id: &str,
+        worker_id: Option<&str>,
+        actor: &str,
+        now_ms: u64,
+    ) -> Result<String, PinError> {
+        let (running_on, vm) = self
+            .resolve(id)
+            .ok_or_else(|| PinError::NotReported(id.to_string()))?;
+        let worker_id = worker_id.unwrap_or(running_on).to_string();
+        let vm_id = vm.id.clone();
+        if let Some(spec_pin) = self.spec_pin(&vm.hash)
+            && spec_pin != worker_id
+        {
+            return Err(PinError::PinnedBySpec {
+                vm_id,
+                worker_id: spec_pin.to_string(),
+            });
+        }
+        let net_backend = self.net_backend(&vm.hash);
+        if !self.attaches(&worker_id, net_backend) {
+            return Err(PinError::Unfit {
+                vm_id,
+                worker_id,
+                net_backend,
+            });
+        }
+
+        self.pins.insert(vm_id.clone(), worker_id.clone());
+        self.record_action_event(
+            &vm_id,
+            now_ms,
+            "Pinned",
+            format!("pinned to worker {worker_id} by {actor}"),
+        );
+        Ok(worker_id)
+    }
+
+    /// Remove the pin set with [`Convergence::pin`] on the VM with worker VM
+    /// id or spec hash `id`, and record that `actor` did.
+    ///
+    /// # Errors
+    ///
+    /// - if no worker reports the VM
+    /// - if its spec pins it
+    pub fn unpin(&mut self, id: &str, actor: &str, now_ms: u64) -> Result<(), PinError> {
+        let (_, vm) = self
+            .resolve(id)
+            .ok_or_else(|| PinError::NotReported(id.to_string()))?;
+        let vm_id = vm.id.clone();
+        if let Some(spec_pin) = self.spec_pin(&vm.hash) {
+            return Err(PinError::PinnedBySpec {
+                vm_id,
+                worker_id: spec_pin.to_string(),
+            });
+        }
+
+        if let Some(worker_id) = self.pins.remove(&vm_id) {
+            self.record_action_event(
+                &vm_id,
+                now_ms,
+                "Unpinned",
+                format!("unpinned from worker {worker_id} by {actor}"),
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether VM `vm_id` may be moved off its worker: by drain only when
+    /// `force`d, by rescheduling never, while it is pinned.
+    ///
+    /// # Errors
+    ///
+    /// - if the VM is pinned and `force` is not set
+    #[allow(dead_code)]
+    pub fn movable(&self, vm_id: &str, force: bool) -> Result<(), PinError> {
+        let hash = self
+            .reported()
+            .find(|(_, vm)| vm.id == vm_id)
+            .map(|(_, vm)| vm.hash.as_str())
+            .unwrap_or_default();
+        match self.pinned_to(self.spec_pin(hash), Some(vm_id)) {
+            Some(worker_id) if !force => Err(PinError::Pinned {
+                vm_id: vm_id.to_string(),
+                worker_id: worker_id.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_action_event(&mut self, vm_id: &str, now_ms: u64, reason: &str, message: String) {
+        let events = self.action_events.entry(vm_id.to_string()).or_default();
+        if events.len() == MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(Event {
+            timestamp_ms: now_ms,
+            object: vm_id.to_string(),
+            reason: reason.to_string(),
+            message,
+        });
+    }
+
+    /// Diagnose the VMs that missed the deadline at `now_ms` and return the
+    /// diagnostics that were recorded, keyed like [`Convergence::describe`].
+    pub fn check(&mut self, now_ms: u64) -> Vec<(String, Diagnostic)> {
+        let Some(active) = &self.active else {
+            return Vec::new();
+        };
+        if now_ms < active.deadline_ms {
+            return Vec::new();
+        }
+
+        let mut found: Vec<(String, Reason, String)> = active
+            .desired
+            .iter()
+            .filter_map(|vm| {
+                self.diagnose(vm, now_ms)
+                    .map(|(reason, message)| (vm.hash.clone(), reason, message))
+            })
+            .collect();
+        for (worker_id, vm) in self.reported_at(now_ms) {
+            if vm.status == VmState::Running && !active.desired.iter().any(|d| d.hash == vm.hash) {
+                found.push((
+                    vm.id.clone(),
+                    Reason::Drifted,
+                    format!(
+                        "worker {worker_id} runs {}, which generation {} does not want",
+                        vm.hash, active.generation
+                    ),
+                ));
+            }
+        }
+
+        let generation = active.generation;
+        let mut recorded = Vec::new();
+        for (key, reason, message) in found {
+            let events = self.events.entry(key.clone()).or_default();
+            if events
+                .back()
+                .is_some_and(|last| last.reason == reason && last.message == message)
+            {
+                continue;
+            }
+            let diagnostic = Diagnostic {
+                timestamp_ms: now_ms,
+                generation,
+                reason,
+                message,
+            };
+            if events.len() == MAX_EVENTS {
+                events.pop_front();
+            }
+            events.push_back(diagnostic.clone());
+            recorded.push((key, diagnostic));
+        }
+        recorded
+    }
+
+    /// Describe the VM with worker VM id or spec hash `id`.
+    #[must_use]
+    pub fn describe_vm(&self, id: &str, now_ms: u64) -> Option<Description> {
+        let desired = self.desired().iter().find(|vm| vm.hash == id);
+        let (desired, observed) = if let Some(vm) = desired {
+            (Some(vm), self.best_match(vm))
+        } else {
+            let (worker_id, observed) = self.reported().find(|(_, vm)| vm.id == id)?;
+            let wanted = self.desired().iter().find(|vm| vm.hash == observed.hash);
+            (wanted, Some((worker_id, observed)))
+        };
+        let generation = self.active.as_ref().map(|a| a.generation);
+        let desired_hash = desired.map(|vm| vm.hash.as_str()).unwrap_or_default();
+        let observed_hash = observed.map(|(_, o)| o.hash.as_str()).unwrap_or_default();
+        let status = observed.map_or(VmState::Pending, |(_, o)| {
+            self.actions
+                .get(&o.id)
+                .map_or(o.status, |pending| pending.action.transient_status())
+        });
+        let running = status == VmState::Running;
+
+        let pinned = self.pinned_to(
+            desired.and_then(|vm| vm.pinned.as_deref()),
+            observed.map(|(_, o)| o.id.as_str()),
+        );
+        let placement = match (observed, pinned) {
+            (Some((worker_id, _)), None) => format!("on worker {worker_id}, which reports it"),
+            (Some((worker_id, _)), Some(pinned)) if pinned == worker_id => {
+                format!("on worker {worker_id}, which reports it, pinned there")
+            }
+            (Some((worker_id, _)), Some(pinned)) => {
+                format!("on worker {worker_id}, which reports it, but pinned to worker {pinned}")
+            }
+            (None, None) => format!(
+                "not placed: {}",
+                self.unplaced(desired.map(|vm| vm.net_backend).unwrap_or_default())
+            ),
+            (None, Some(pinned)) => format!(
+                "not placed: {}",
+                self.unplaced_pinned(pinned, desired.map(|vm| vm.net_backend).unwrap_or_default())
+            ),
+        };
+        let mut fields = vec![
+            Field::new("hash", desired_hash, observed_hash),
+            Field::new(
+                "status",
+                if desired.is_some() {
+                    VmState::Running.as_str()
+                } else {
+                    ""
+                },
+                status.as_str(),
+            ),
+            Field::new(
+                "generation",
+                generation.map(|g| g.to_string()).unwrap_or_default(),
+                observed
+                    .and_then(|(worker_id, _)| self.workers.get(worker_id))
+                    .map(|w| w.generation.to_string())
+                    .unwrap_or_default(),
+            ),
+        ];
+        if let Some(vm) = desired {
+            fields.extend(
+                vm.store_paths
+                    .iter()
+                    .map(|p| Field::new("store path", p.as_str(), "")),
+            );
+        }
+
+        let drift = hashing::compare(desired_hash, observed_hash);
+        let mut conditions = vec![
+            Condition::new(
+                "Running",
+                running,
+                status,
+                observed.map(|(_, o)| o.error.as_str()).unwrap_or_default(),
+            ),
+            match desired {
+                Some(_) => Condition::new("InSync", drift == Drift::InSync, drift.to_string(), ""),
+                None => Condition::new(
+                    "InSync",
+                    false,
+                    Reason::Drifted.as_str(),
+                    format!(
+                        "generation {} does not want this spec",
+                        generation.unwrap_or_default()
+                    ),
+                ),
+            },
+        ];
+        if let Some(active) = &self.active
+            && desired.is_some()
+        {
+            conditions.push(deadline_condition(active.deadline_ms, running, now_ms));
+        }
+
+        let metrics = observed
+            .map(|(_, o)| vm_metrics(&o.metrics))
+            .unwrap_or_default();
+        let key = desired.map_or(id, |vm| vm.hash.as_str());
+        let object = observed.map_or(key, |(_, o)| o.id.as_str());
+        let mut events = self.events_of(key, object);
+        events.extend(self.action_events_of(object));
+        if let Some((_, o)) = observed {
+            events.extend(o.transitions.iter().map(|t| Event {
+                timestamp_ms: t.at_ms,
+                object: o.id.clone(),
+                reason: "StateChanged".to_string(),
+                message: t.to_string(),
+            }));
+        }
+        events.sort_by_key(|e| e.timestamp_ms);
+        Some(Description {
+            kind: Kind::Vm,
+            id: object.to_string(),
+            placement,
+            fields,
+            conditions,
+            metrics,
+            events,
+        })
+    }
+
+    /// Describe worker `id` from its latest report.
+    #[must_use]
+    pub fn describe_worker(&self, id: &str, now_ms: u64) -> Option<Description> {
+        let view = self.workers.get(id)?;
+        let active = self.active.as_ref().map(|a| a.generation);
+        let age_secs = now_ms.saturating_sub(view.seen_ms) / 1000;
+        let reporting = Duration::from_secs(age_secs) < STALE_AFTER;
+        let at_generation = active.is_none_or(|g| g == view.generation);
+        let not_running: Vec<&str> = view
+            .vms
+            .iter()
+            .filter(|vm| vm.status != VmState::Running)
+            .map(|vm| vm.id.as_str())
+            .collect();
+
+        let mut events: Vec<Event> = view
+            .vms
+            .iter()
+            .flat_map(|vm| {
+                let mut events = self.events_of(&vm.id, &vm.id);
+                if vm.hash != vm.id {
+                    events.extend(self.events_of(&vm.hash, &vm.id));
+                }
+                events.extend(self.action_events_of(&vm.id));
+                events
+            })
+            .collect();
+        events.sort_by_key(|e| e.timestamp_ms);
+        let skip = events.len().saturating_sub(MAX_EVENTS);
+
+        Some(Description {
+            kind: Kind::Worker,
+            id: id.to_string(),
+            placement: String::new(),
+            fields: vec![
+                Field::new(
+                    "generation",
+                    active.map(|g| g.to_string()).unwrap_or_default(),
+                    view.generation.to_string(),
+                ),
+                Field::new("vms", "", view.vms.len().to_string()),
+                Field::new(
+                    "network backends",
+                    "",
+                    offered(&view.net_backends)
+                        .iter()
+                        .map(NetBackend::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+            ],
+            conditions: vec![
+                Condition::new(
+                    "Reporting",
+                    reporting,
+                    if reporting { "Reporting" } else { "Stale" },
+                    format!("last report {age_secs}s ago"),
+                ),
+                Condition::new(
+                    "AtGeneration",
+                    at_generation,
+                    if at_generation {
+                        "AtGeneration"
+                    } else {
+                        "Behind"
+                    },
+                    format!(
+                        "observed generation {} of {}",
+                        view.generation,
+                        active.unwrap_or_default()
+                    ),
+                ),
+                Condition::new(
+                    "VmsRunning",
+                    not_running.is_empty(),
+                    if not_running.is_empty() {
+                        "AllRunning"
+                    } else {
+                        "NotRunning"
+                    },
+                    not_running.join(", "),
+                ),
+                memory_pressure_condition(&view.metrics.memory_pressure),
+            ],
+            metrics: worker_metrics(&view.metrics),
+            events: events.split_off(skip),
+        })
+    }
+
+    /// Describe generation `number`, one of the last [`MAX_GENERATIONS`].
+    #[must_use]
+    pub fn describe_generation(&self, number: u64, now_ms: u64) -> Option<Description> {
+        let record = self.generations.iter().find(|g| g.number == number)?;
+        let active = self.active.as_ref().filter(|a| a.generation == number);
+        let running = active.map_or(0, |a| {
+            a.desired
+                .iter()
+                .filter(|vm| {
+                    self.best_match(vm)
+                        .is_some_and(|(_, o)| o.status == VmState::Running)
+                })
+                .count()
+        });
+        let draining = self
+            .reported()
+            .filter(|(_, o)| o.status == VmState::Draining)
+            .count();
+        let converged = active.is_some() && running == record.vms && draining == 0;
+        let at_generation = self
+            .workers
+            .values()
+            .filter(|w| w.generation == number)
+            .count();
+
+        let mut conditions = vec![match active {
+            Some(_) => Condition::new("Active", true, "Active", ""),
+            None => Condition::new(
+                "Active",
+                false,
+                "Superseded",
+                format!(
+                    "superseded by generation {}",
+                    self.active
+                        .as_ref()
+                        .map(|a| a.generation)
+                        .unwrap_or_default()
+                ),
+            ),
+        }];
+        if active.is_some() {
+            conditions.push(Condition::new(
+                "Converged",
+                converged,
+                if converged { "Converged" } else { "Converging" },
+                if draining == 0 {
+                    format!("{running} of {} VMs running", record.vms)
+                } else {
+                    format!(
+                        "{running} of {} VMs running, {draining} draining",
+                        record.vms
+                    )
+                },
+            ));
+            conditions.push(deadline_condition(record.deadline_ms, converged, now_ms));
+        }
+
+        let mut events = vec![Event {
+            timestamp_ms: record.published_ms,
+            object: format!("generation {number}"),
+            reason: "Published".to_string(),
+            message: format!(
+                "published by {} from commit {}",
+                record.publisher, record.commit
+            ),
+        }];
+        events.extend(self.events.iter().flat_map(|(key, diagnostics)| {
+            diagnostics
+                .iter()
+                .filter(|d| d.generation == number)
+                .map(|d| event(key, d))
+        }));
+        events.sort_by_key(|e| e.timestamp_ms);
+        let skip = events.len().saturating_sub(MAX_EVENTS);
+
+        let mut fields = vec![
+            Field::new("commit", record.commit.as_str(), ""),
+            Field::new("publisher", record.publisher.as_str(), ""),
+            Field::new("intent hash", record.intent_hash.as_str(), ""),
+        ];
+        fields.extend(record.provenance.fields());
+        fields.extend([
+            Field::new(
+                "vms",
+                record.vms.to_string(),
+                if active.is_some() {
+                    running.to_string()
+                } else {
+                    String::new()
+                },
+            ),
+            Field::new("workers at generation", "", at_generation.to_string()),
+        ]);
+
+        Some(Description {
+            kind: Kind::Generation,
+            id: number.to_string(),
+            placement: String::new(),
+            fields,
+            conditions,
+            metrics: active
+                .map(|_| {
+                    vec![metric(
+                        "convergence percent",
+                        (running * 100)
+                            .checked_div(record.vms)
+                            .unwrap_or(100)
+                            .to_string(),
+                    )]
+                })
+                .unwrap_or_default(),
+            events: events.split_off(skip),
+        })
+    }
+
+    /// The last [`MAX_GENERATIONS`] generations made active, newest first.
+    #[must_use]
+    pub fn history(&self) -> Vec<GenerationInfo> {
+        let active = self.active.as_ref().map(|a| a.generation);
+        self.generations
+            .iter()
+            .rev()
+            .map(|record| GenerationInfo {
+                number: record.number,
+                commit: record.commit.clone(),
+                publisher: record.publisher.clone(),
+                intent_hash: record.intent_hash.clone(),
+                published_ms: record.published_ms,
+                active: active == Some(record.number),
+                vms: record.vms,
+                provenance: record.provenance.clone(),
+            })
+            .collect()
+    }
+
+    /// Workers, VMs and convergence at `now_ms`, of `namespace` only when
+    /// set. Workers are shared, so all of them are listed either way.
+    #[must_use]
+    pub fn status(&self, namespace: Option<&str>, now_ms: u64) -> ClusterView {
+        let generation = self.active.as_ref().map(|a| a.generation);
+        let commit = self
+            .generations
+            .iter()
+            .find(|g| Some(g.number) == generation)
+            .map(|g| g.commit.clone())
+            .unwrap_or_default();
+        let visible = |vm: &&DesiredVm| namespace.is_none_or(|ns| vm.namespace == ns);
+
+        let mut workers: Vec<WorkerSummary> = self
+            .workers
+            .iter()
+            .map(|(id, view)| WorkerSummary {
+                id: id.clone(),
+                healthy: Duration::from_millis(now_ms.saturating_sub(view.seen_ms)) < STALE_AFTER,
+                generation: view.generation,
+                running_vms: view
+                    .vms
+                    .iter()
+                    .filter(|vm| vm.status == VmState::Running)
+                    .count(),
+                net_backends: offered(&view.net_backends),
+            })
+            .collect();
+        workers.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut vms: Vec<VmSummary> = self
+            .reported()
+            .filter_map(|(worker_id, observed)| {
+                let wanted = self.desired().iter().find(|vm| vm.hash == observed.hash);
+                if namespace.is_some() && !wanted.is_some_and(|vm| visible(&vm)) {
+                    return None;
+                }
+                Some(VmSummary {
+                    id: observed.id.clone(),
+                    worker_id: worker_id.to_string(),
+                    namespace: wanted.map(|vm| vm.namespace.clone()).unwrap_or_default(),
+                    desired_hash: wanted.map(|vm| vm.hash.clone()).unwrap_or_default(),
+                    observed_hash: observed.hash.clone(),
+                    status: self
+                        .actions
+                        .get(&observed.id)
+                        .map_or(observed.status, |pending| pending.action.transient_status()),
+                    drifted: wanted.is_none(),
+                    labels: wanted.map(|vm| vm.labels.clone()).unwrap_or_default(),
+                    metrics: observed.metrics,
+                })
+            })
+            .collect();
+        let desired: Vec<&DesiredVm> = self.desired().iter().filter(visible).collect();
+        vms.extend(
+            desired
+                .iter()
+                .filter(|vm| self.best_match(vm).is_none())
+                .map(|vm| VmSummary {
+                    id: vm.hash.clone(),
+                    worker_id: String::new(),
+                    namespace: vm.namespace.clone(),
+                    desired_hash: vm.hash.clone(),
+                    observed_hash: String::new(),
+                    status: VmState::Pending,
+                    drifted: false,
+                    labels: vm.labels.clone(),
+                    metrics: VmMetrics::default(),
+                }),
+        );
+        vms.sort_by(|a, b| (&a.namespace, &a.id).cmp(&(&b.namespace, &b.id)));
+
+        let mut unschedulable: Vec<UnschedulableVm> = desired
+            .iter()
+            .filter(|vm| self.best_match(vm).is_none())
+            .filter_map(|vm| {
+                let (reason, message) = self.unschedulable(vm, now_ms)?;
+                Some(UnschedulableVm {
+                    hash: vm.hash.clone(),
+                    namespace: vm.namespace.clone(),
+                    reason,
+                    message,
+                })
+            })
+            .collect();
+        unschedulable.sort_by(|a, b| (&a.namespace, &a.hash).cmp(&(&b.namespace, &b.hash)));
+
+        let running = desired
+            .iter()
+            .filter(|vm| {
+                self.best_match(vm)
+                    .is_some_and(|(_, o)| o.status == VmState::Running)
+            })
+            .count();
+        ClusterView {
+            generation: generation.unwrap_or_default(),
+            commit,
+            convergence_percent: u32::try_from(
+                (running * 100).checked_div(desired.len()).unwrap_or(100),
+            )
+            .unwrap_or(100),
+            workers,
+            vms,
+            unschedulable,
+        }
+    }
+
+    /// Every reported VM and every desired one no worker reports, against
+    /// the active generation, see the module docs.
+    #[must_use]
+    pub fn drift_report(&self, now_ms: u64) -> DriftReport {
+        let generation = self.active.as_ref().map(|a| a.generation);
+        let commit = self
+            .generations
+            .iter()
+            .find(|g| Some(g.number) == generation)
+            .map(|g| g.commit.clone())
+            .unwrap_or_default();
+        let wanted = |hash: &str| self.desired().iter().find(|vm| vm.hash == hash);
+
+        let mut vms: Vec<DriftEntry> = self
+            .reported()
+            .map(|(worker_id, observed)| {
+                let desired = wanted(&observed.hash).or_else(|| wanted(&observed.desired_hash));
+                let drift = self.drift_of(observed);
+                DriftEntry {
+                    vm_id: observed.id.clone(),
+                    worker_id: worker_id.to_string(),
+                    namespace: desired.map(|vm| vm.namespace.clone()).unwrap_or_default(),
+                    desired_hash: desired.map(|vm| vm.hash.clone()).unwrap_or_default(),
+                    observed_hash: observed.hash.clone(),
+                    converged_for: self
+                        .converged
+                        .get(&observed.id)
+                        .filter(|_| drift == DriftSource::InSync)
+                        .map(|since| Duration::from_millis(now_ms.saturating_sub(*since))),
+                    drift,
+                }
+            })
+            .collect();
+        vms.sort_by(|a, b| (&a.namespace, &a.vm_id).cmp(&(&b.namespace, &b.vm_id)));
+        let mut missing: Vec<DriftEntry> = self
+            .desired()
+            .iter()
+            .filter(|vm| {
+                self.best_match(vm).is_none()
+                    && !self.reported().any(|(_, o)| o.desired_hash == vm.hash)
+            })
+            .map(|vm| DriftEntry {
+                vm_id: String::new(),
+                worker_id: String::new(),
+                namespace: vm.namespace.clone(),
+                desired_hash: vm.hash.clone(),
+                observed_hash: String::new(),
+                converged_for: None,
+                drift: DriftSource::Missing,
+            })
+            .collect();
+        missing
+            .sort_by(|a, b| (&a.namespace, &a.desired_hash).cmp(&(&b.namespace, &b.desired_hash)));
+        vms.extend(missing);
+
+        DriftReport {
+            generation: generation.unwrap_or_default(),
+            commit,
+            vms,
+        }
+    }
+
+    /// How the reported `vm` differs from the active generation; the
+    /// worker's own account wins over the master's.
+    fn drift_of(&self, vm: &ObservedVm) -> DriftSource {
+        if !vm.desired_hash.is_empty() && hashing::compare(&vm.desired_hash, &vm.hash).is_drifted()
+        {
+            DriftSource::SelfReported
+        } else if self.desired().iter().any(|desired| desired.hash == vm.hash) {
+            DriftSource::InSync
+        } else {
+            DriftSource::HashMismatch
+        }
+    }
+
+    /// Diagnostics recorded under `key`, as events about `object`.
+    fn events_of(&self, key: &str, object: &str) -> Vec<Event> {
+        self.events
+            .get(key)
+            .map(|events| events.iter().map(|d| event(object, d)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Requests and outcomes of actions on VM `vm_id`.
+    fn action_events_of(&self, vm_id: &str) -> impl Iterator<Item = Event> + '_ {
+        self.action_events.get(vm_id).into_iter().flatten().cloned()
+    }
+
+    /// VMs of the applied generation.
+    #[must_use]
+    pub fn desired(&self) -> &[DesiredVm] {
+        self.active.as_ref().map_or(&[], |a| a.desired.as_slice())
+    }
+
+    /// Capacity the workers reporting at `now_ms` have left, as they last
+    /// reported it.
+    #[must_use]
+    pub fn free(&self, now_ms: u64) -> Resources {
+        self.workers
+            .values()
+            .filter(|view| Duration::from_millis(now_ms.saturating_sub(view.seen_ms)) < STALE_AFTER)
+            .map(WorkerView::free)
+            .fold(Resources::default(), Resources::plus)
+    }
+
+    /// The reported VM running `vm`'s spec, or any one not running it.
+    /// Draining VMs are leaving, so they never match.
+    fn best_match(&self, vm: &DesiredVm) -> Option<(&str, &ObservedVm)> {
+        let mut matches: Vec<_> = self
+            .reported()
+            .filter(|(_, o)| o.hash == vm.hash && o.status != VmState::Draining)
+            .collect();
+        matches.sort_by_key(|(_, o)| o.status != VmState::Running);
+        matches.first().copied()
+    }
+
+    /// Why worker `pinned` does not run a desired VM pinned to it, whose
+    /// NIC is attached with `net_backend`.
+    fn unplaced_pinned(&self, pinned: &str, net_backend: NetBackend) -> String {
+        match self.workers.get(pinned) {
+            Some(view) if !scheduler::attaches(&view.net_backends, net_backend) => {
+                format!("pinned to worker {pinned}, which does not offer {net_backend} networking")
+            }
+            Some(_) => format!("pinned to worker {pinned}, which does not run it"),
+            None => format!("pinned to worker {pinned}, which has not reported to the master"),
+        }
+    }
+
+    /// How the active generation's spec `hash` attaches its NIC.
+    fn net_backend(&self, hash: &str) -> NetBackend {
+        self.desired()
+            .iter()
+            .find(|vm| vm.hash == hash)
+            .map(|vm| vm.net_backend)
+            .unwrap_or_default()
+    }
+
+    /// Whether `worker_id` attaches NICs with `net_backend`; a worker that
+    /// has not reported only attaches `tap`.
+    fn attaches(&self, worker_id: &str, net_backend: NetBackend) -> bool {
+        let offered = self
+            .workers
+            .get(worker_id)
+            .map_or(&[][..], |view| view.net_backends.as_slice());
+        scheduler::attaches(offered, net_backend)
+    }
+
+    /// Worker a VM pinned by `spec_pin` or, when running, by a `pinVm` pin on
+    /// `vm_id` must run on; the spec wins.
+    fn pinned_to<'a>(&'a self, spec_pin: Option<&'a str>, vm_id: Option<&str>) -> Option<&'a str> {
+        spec_pin.or_else(|| self.pins.get(vm_id?).map(String::as_str))
+    }
+
+    /// Worker the active generation's spec `hash` is pinned to.
+    fn spec_pin(&self, hash: &str) -> Option<&str> {
+        self.desired()
+            .iter()
+            .find(|vm| vm.hash == hash)
+            .and_then(|vm| vm.pinned.as_deref())
+    }
+
+    /// The reported VM with worker VM id or spec hash `id`, with its worker.
+    fn resolve(&self, id: &str) -> Option<(&str, &ObservedVm)> {
+        self.reported().find(|(_, vm)| vm.id == id).or_else(|| {
+            let desired = self.desired().iter().find(|vm| vm.hash == id)?;
+            self.best_match(desired)
+        })
+    }
+
+    /// Why no worker reporting at `now_ms` can host `vm`, `None` when one
+    /// could. Only its pin, network backend and resources are checked
+    /// against what each worker last reported free.
+    fn unschedulable(&self, vm: &DesiredVm, now_ms: u64) -> Option<(Unschedulable, String)> {
+        let reporting: Vec<(&str, &WorkerView)> = self
+            .workers
+            .iter()
+            .filter(|(_, view)| {
+                Duration::from_millis(now_ms.saturating_sub(view.seen_ms)) < STALE_AFTER
+            })
+            .map(|(id, view)| (id.as_str(), view))
+            .collect();
+        if reporting.is_empty() {
+            return Some((
+                Unschedulable::NoWorkers,
+                "no worker has reported to the master recently".to_string(),
+            ));
+        }
+        let wanted = vm.resources;
+
+        if let Some(pinned) = vm.pinned.as_deref() {
+            let Some((_, view)) = reporting.iter().find(|(id, _)| *id == pinned) else {
+                return Some((
+                    Unschedulable::PinnedWorker,
+                    format!("pinned to worker {pinned}, which has not reported recently"),
+                ));
+            };
+            if !scheduler::attaches(&view.net_backends, vm.net_backend) {
+                return Some((
+                    Unschedulable::NetBackend,
+                    format!(
+                        "pinned to worker {pinned}, which does not offer {} networking",
+                        vm.net_backend
+                    ),
+                ));
+            }
+            return (!view.free().covers(wanted)).then(|| {
+                (
+                    Unschedulable::InsufficientResources,
+                    format!(
+                        "needs {wanted}, but worker {pinned} it is pinned to has {} free",
+                        view.free()
+                    ),
+                )
+            });
+        }
+
+        let candidates: Vec<&WorkerView> = reporting
+            .iter()
+            .filter(|(_, view)| scheduler::attaches(&view.net_backends, vm.net_backend))
+            .map(|(_, view)| *view)
+            .collect();
+        if candidates.is_empty() {
+            return Some((
+                Unschedulable::NetBackend,
+                format!(
+                    "needs {} networking, which none of the {} reporting workers offers",
+                    vm.net_backend,
+                    reporting.len()
+                ),
+            ));
+        }
+        (!candidates.iter().any(|view| view.free().covers(wanted))).then(|| {
+            (
+                Unschedulable::InsufficientResources,
+                format!(
+                    "needs {wanted}, more than any of the {} workers it may run on has free",
+                    candidates.len()
+                ),
+            )
+        })
+    }
+
+    /// Why no worker runs a desired VM whose NIC is attached with
+    /// `net_backend`.
+    fn unplaced(&self, net_backend: NetBackend) -> String {
+        if self.workers.is_empty() {
+            "no worker has reported to the master".to_string()
+        } else if !self.workers.keys().any(|id| self.attaches(id, net_backend)) {
+            format!(
+                "needs {net_backend} networking, which none of the {} reporting workers offers",
+                self.workers.len()
+            )
+        } else {
+            format!(
+                "not placed on any of the {} reporting workers",
+                self.workers.len()
+            )
+        }
+    }
+
+    /// Why `vm` is not running at `now_ms`, `None` when it is.
+    fn diagnose(&self, vm: &DesiredVm, now_ms: u64) -> Option<(Reason, String)> {
+        let matches: Vec<_> = self
+            .reported_at(now_ms)
+            .filter(|(_, o)| o.hash == vm.hash)
+            .collect();
+        let pinned = vm.pinned.as_deref().or_else(|| {
+            matches
+                .iter()
+                .find_map(|(_, o)| self.pins.get(&o.id).map(String::as_str))
+        });
+        let running: Vec<_> = matches
+            .iter()
+            .filter(|(_, o)| o.status == VmState::Running)
+            .collect();
+        if running
+            .iter()
+            .any(|(worker_id, _)| pinned.is_none_or(|p| p == *worker_id))
+        {
+            return None;
+        }
+        if let (Some(pinned), Some((worker_id, o))) = (pinned, running.first()) {
+            return Some((
+                Reason::Misplaced,
+                format!(
+                    "pinned to worker {pinned}, but VM {} runs on worker {worker_id}",
+                    o.id
+                ),
+            ));
+        }
+
+        if let Some((worker_id, o)) = matches.iter().find(|(_, o)| !o.error.is_empty()) {
+            let missing = vm.store_paths.iter().find(|path| {
+                o.error.contains(path.as_str())
+                    && MISSING_MARKERS.iter().any(|m| o.error.contains(m))
+            });
+            return Some(match missing {
+                Some(path) => (
+                    Reason::MissingStorePath,
+                    format!("worker {worker_id} does not have {path}: {}", o.error),
+                ),
+                None => (
+                    Reason::WorkerError,
+                    format!(
+                        "worker {worker_id} reports VM {} {}: {}",
+                        o.id, o.status, o.error
+                    ),
+                ),
+            });
+        }
+        let unreachable = self.workers.iter().find_map(|(worker_id, view)| {
+            let o = view.vms.iter().find(|o| o.hash == vm.hash)?;
+            (!view.reporting(now_ms)).then_some((worker_id, view, o))
+        });
+        if let (None, Some((worker_id, view, o))) = (matches.first(), unreachable) {
+            return Some((
+                Reason::WorkerUnreachable,
+                format!(
+                    "worker {worker_id} last reported VM {} {} {}s ago",
+                    o.id,
+                    o.status,
+                    now_ms.saturating_sub(view.seen_ms) / 1000
+                ),
+            ));
+        }
+        if let Some((worker_id, o)) = matches.first() {
+            return Some((
+                Reason::WorkerError,
+                format!(
+                    "worker {worker_id} reports VM {} {} without an error",
+                    o.id, o.status
+                ),
+            ));
+        }
+
+        Some((
+            Reason::Unscheduled,
+            pinned.map_or_else(
+                || self.unplaced(vm.net_backend),
+                |p| self.unplaced_pinned(p, vm.net_backend),
+            ),
+        ))
+    }
+
+    /// Every reported VM with the worker running it.
+    fn reported(&self) -> impl Iterator<Item = (&str, &ObservedVm)> {
+        self.workers
+            .iter()
+            .flat_map(|(worker_id, view)| view.vms.iter().map(move |vm| (worker_id.as_str(), vm)))
+    }
+
+    /// The VMs of [`Convergence::reported`] whose worker still reports at
+    /// `now_ms`.
+    fn reported_at(&self, now_ms: u64) -> impl Iterator<Item = (&str, &ObservedVm)> {
+        self.workers
+            .iter()
+            .filter(move |(_, view)| view.reporting(now_ms))
+            .flat_map(|(worker_id, view)| view.vms.iter().map(move |vm| (worker_id.as_str(), vm)))
+    }
+
+    /// Forget the diagnostics of VMs that are neither desired nor reported.
+    fn prune(&mut self) {
+        let keep: Vec<String> = self
+            .active
+            .iter()
+            .flat_map(|a| a.desired.iter().map(|vm| vm.hash.clone()))
+            .chain(self.reported().map(|(_, vm)| vm.id.clone()))
+            .collect();
+        self.events.retain(|key, _| keep.contains(key));
+        self.action_events.retain(|vm_id, _| keep.contains(vm_id));
+        self.actions.retain(|vm_id, _| keep.contains(vm_id));
+        let in_sync: Vec<String> = self
+            .reported()
+            .filter(|(_, vm)| self.drift_of(vm) == DriftSource::InSync)
+            .map(|(_, vm)| vm.id.clone())
+            .collect();
+        self.converged.retain(|vm_id, _| in_sync.contains(vm_id));
+    }
+}
+
+/// Network backends a worker reporting `advertised` attaches: `tap` alone
+/// when it predates advertising them.
+fn offered(advertised: &[NetBackend]) -> Vec<NetBackend> {
+    if advertised.is_empty() {
+        vec![NetBackend::Tap]
+    } else {
+        advertised.to_vec()
+    }
+}
+
+fn event(object: &str, diagnostic: &Diagnostic) -> Event {
+    Event {
+        timestamp_ms: diagnostic.timestamp_ms,
+        object: object.to_string(),
+        reason: diagnostic.reason.to_string(),
+        message: diagnostic.message.clone(),
+    }
+}
+
+fn metric(name: &'static str, value: impl Into<String>) -> Metric {
+    Metric {
+        name,
+        value: value.into(),
+    }
+}
+
+/// Worker metrics, the disk usage broken down one entry per image and VM.
+fn worker_metrics(metrics: &WorkerMetrics) -> Vec<Metric> {
+    let mut out = vec![
+        metric("available cpu", metrics.available_cpu.to_string()),
+        metric(
+            "available memory bytes",
+            metrics.available_memory.to_string(),
+        ),
+        metric("disk usage bytes", metrics.disk_usage.to_string()),
+    ];
+    out.extend(
+        metrics
+            .image_disk_usage
+            .iter()
+            .map(|(image, bytes)| metric("image disk bytes", format!("{bytes} {image}"))),
+    );
+    out.extend(
+        metrics
+            .vm_disk_usage
+            .iter()
+            .map(|(vm, bytes)| metric("vm disk bytes", format!("{bytes} {vm}"))),
+    );
+    out.push(metric("log disk bytes", metrics.log_disk_usage.to_string()));
+    out.push(metric("uptime secs", metrics.uptime_secs.to_string()));
+    let pressure = &metrics.memory_pressure;
+    if !pressure.policy.is_empty() {
+        out.extend([
+            metric(
+                "memory pressure",
+                format!(
+                    "some {}% full {}%",
+                    pressure.some_avg10, pressure.full_avg10
+                ),
+            ),
+            metric("memory pressure episodes", pressure.episodes.to_string()),
+            metric("memory pressure restarts", pressure.restarts.to_string()),
+        ]);
+    }
+    let janitor = &metrics.store_janitor;
+    if janitor.high_watermark > 0.0 {
+        out.extend([
+            metric(
+                "store usage",
+                format!(
+                    "{}% of {}% watermark",
+                    janitor.used_percent, janitor.high_watermark
+                ),
+            ),
+            metric("store pinned paths", janitor.pinned_paths.to_string()),
+            metric(
+                "store collections",
+                format!(
+                    "{} freed {} bytes",
+                    janitor.collections, janitor.freed_bytes
+                ),
+            ),
+        ]);
+    }
+    out
+}
+
+/// Whether the worker's host has memory to spare, unknown when it does not
+/// watch its memory pressure.
+fn memory_pressure_condition(pressure: &MemoryPressure) -> Condition {
+    if pressure.policy.is_empty() {
+        return Condition::new("MemoryPressure", true, "NotWatched", "");
+    }
+    let message = format!(
+        "some {}% over 10s, policy {}, {} episodes, {} restarts",
+        pressure.some_avg10, pressure.policy, pressure.episodes, pressure.restarts
+    );
+    if pressure.under_pressure {
+        Condition::new("MemoryPressure", false, "UnderPressure", message)
+    } else {
+        Condition::new("MemoryPressure", true, "Normal", message)
+    }
+}
+
+fn vm_metrics(metrics: &VmMetrics) -> Vec<Metric> {
+    vec![
+        metric("cpu usage", metrics.cpu_usage.to_string()),
+        metric("memory bytes", metrics.memory_bytes.to_string()),
+        metric("network rx bytes", metrics.network_rx_bytes.to_string()),
+        metric("network tx bytes", metrics.network_tx_bytes.to_string()),
+    ]
+}
+
+/// `DeadlineExceeded` of something that is `done` or not at `now_ms`.
+fn deadline_condition(deadline_ms: u64, done: bool, now_ms: u64) -> Condition {
+    let exceeded = !done && now_ms >= deadline_ms;
+    Condition::new(
+        "DeadlineExceeded",
+        exceeded,
+        if exceeded {
+            "DeadlineExceeded"
+        } else {
+            "WithinDeadline"
+        },
+        format!("deadline at {deadline_ms}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEADLINE: Duration = Duration::from_mins(1);
+
+    fn desired(hash: &str) -> DesiredVm {
+        DesiredVm {
+            hash: hash.to_string(),
+            store_paths: vec![format!("/nix/store/{hash}-disk/nixos.raw")],
+            labels: Labels::new(),
+            pinned: None,
+            namespace: crate::tenancy::DEFAULT_NAMESPACE.to_string(),
+            net_backend: NetBackend::Tap,
+            resources: Resources::default(),
+        }
+    }
+
+    fn observed(id: &str, hash: &str, status: &str, error: &str) -> ObservedVm {
+        ObservedVm {
+            id: id.to_string(),
+            hash: hash.to_string(),
+            desired_hash: String::new(),
+            status: status.parse().unwrap(),
+            error: error.to_string(),
+            metrics: VmMetrics::default(),
+            transitions: Vec::new(),
+        }
+    }
+
+    fn publication(generation: u64) -> Publication {
+        Publication {
+            publisher: "ci".to_string(),
+            generation,
+            commit: format!("commit-{generation}"),
+            intent_hash: format!("hash-{generation}"),
+            parent: None,
+            provenance: Provenance::default(),
+        }
+    }
+
+    fn tracking(vms: &[&str]) -> Convergence {
+        let mut convergence = Convergence::default();
+        convergence.activate(
+            &publication(3),
+            Target {
+                vms: vms.iter().map(|h| desired(h)).collect(),
+                deadline: Some(DEADLINE),
+                emergency: false,
+            },
+            0,
+        );
+        convergence
+    }
+
+    fn report(convergence: &mut Convergence, worker_id: &str, vms: Vec<ObservedVm>, now_ms: u64) {
+        convergence.observe(
+            Observation {
+                worker_id: worker_id.to_string(),
+                address: "10.0.0.1:6000".to_string(),
+                generation: 3,
+                metrics: WorkerMetrics::default(),
+                vms,
+                net_backends: Vec::new(),
+            },
+            now_ms,
+        );
+    }
+
+    fn condition<'a>(description: &'a Description, name: &str) -> &'a Condition {
+        description
+            .conditions
+            .iter()
+            .find(|c| c.name == name)
+            .unwrap_or_else(|| panic!("no condition {name}"))
+    }
+
+    fn observed_field<'a>(description: &'a Description, name: &str) -> &'a str {
+        &description
+            .fields
+            .iter()
+            .find(|f| f.name == name)
+            .unwrap_or_else(|| panic!("no field {name}"))
+            .observed
+    }
+
+    #[test]
+    fn nothing_is_diagnosed_before_the_deadline() {
+        let mut convergence = tracking(&["aaaa"]);
+        assert!(convergence.check(59_999).is_empty());
+
+        let recorded = convergence.check(60_000);
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].0, "aaaa");
+        assert_eq!(recorded[0].1.reason, Reason::Unscheduled);
+        assert_eq!(recorded[0].1.generation, 3);
+    }
+
+    #[test]
+    fn stuck_vms_are_diagnosed_once_per_cause() {
+        let mut convergence = tracking(&["aaaa", "bbbb", "cccc"]);
+        report(
+            &mut convergence,
+            "w1",
+            vec![
+                observed(
+                    "vm-a",
+                    "aaaa",
+                    "failed",
+                    "Artifact not found: disk image at /nix/store/aaaa-disk/nixos.raw",
+                ),
+                observed(
+                    "vm-b",
+                    "bbbb",
+                    "failed",
+                    "image not trusted: /nix/store/bbbb",
+                ),
+                observed("vm-z", "zzzz", "running", ""),
+            ],
+            50_000,
+        );
+
+        let recorded: HashMap<String, Reason> = convergence
+            .check(60_000)
+            .into_iter()
+            .map(|(key, d)| (key, d.reason))
+            .collect();
+        assert_eq!(recorded["aaaa"], Reason::MissingStorePath);
+        assert_eq!(recorded["bbbb"], Reason::WorkerError);
+        assert_eq!(recorded["cccc"], Reason::Unscheduled);
+        assert_eq!(recorded["vm-z"], Reason::Drifted);
+        assert!(convergence.check(70_000).is_empty(), "same causes again");
+
+        // Described by worker VM id as well as by spec hash
+        let vm = convergence.describe_vm("vm-a", 70_000).unwrap();
+        assert_eq!(vm.placement, "on worker w1, which reports it");
+        assert_eq!(observed_field(&vm, "status"), "failed");
+        assert_eq!(
+            vm.events,
+            convergence.describe_vm("aaaa", 70_000).unwrap().events
+        );
+        assert!(
+            vm.events[0]
+                .message
+                .contains("/nix/store/aaaa-disk/nixos.raw")
+        );
+        assert!(condition(&vm, "DeadlineExceeded").status);
+        let drifted = convergence.describe_vm("vm-z", 70_000).unwrap();
+        assert_eq!(condition(&drifted, "InSync").reason, "Drifted");
+        let pending = convergence.describe_vm("cccc", 70_000).unwrap();
+        assert_eq!(observed_field(&pending, "status"), "pending");
+        assert!(pending.placement.starts_with("not placed: "));
+
+        // A VM that comes up is no longer diagnosed, but keeps its history
+        report(
+            &mut convergence,
+            "w1",
+            vec![observed("vm-a", "aaaa", "running", "")],
+            80_000,
+        );
+        let recorded = convergence.check(80_000);
+        assert!(recorded.iter().all(|(key, _)| key != "aaaa"));
+        let vm = convergence.describe_vm("aaaa", 80_000).unwrap();
+        assert!(condition(&vm, "Running").status);
+        assert!(!condition(&vm, "DeadlineExceeded").status);
+        assert_eq!(vm.events.len(), 1);
+    }
+
+    #[test]
+    fn vms_of_workers_that_stopped_reporting_are_diagnosed() {
+        let mut convergence = tracking(&["aaaa"]);
+        report(
+            &mut convergence,
+            "w1",
+            vec![observed("vm-a", "aaaa", "running", "")],
+            40_000,
+        );
+        assert!(convergence.check(60_000).is_empty());
+
+        // w1 went silent at 40s: by 70s its word on vm-a is stale
+        let recorded = convergence.check(70_000);
+        assert_eq!(recorded.len(), 1, "{recorded:?}");
+        assert_eq!(recorded[0].0, "aaaa");
+        assert_eq!(recorded[0].1.reason, Reason::WorkerUnreachable);
+        assert_eq!(
+            recorded[0].1.message,
+            "worker w1 last reported VM vm-a running 30s ago"
+        );
+
+        // Running on another worker that reports is converged
+        report(
+            &mut convergence,
+            "w2",
+            vec![observed("vm-b", "aaaa", "running", "")],
+            70_000,
+        );
+        assert!(convergence.check(80_000).is_empty());
+    }
+
+    #[test]
+    fn worker_transitions_are_vm_events() {
+        let mut convergence = tracking(&["aaaa"]);
+        let transition = |from, to, at_ms, reason: &str| Transition {
+            from,
+            to,
+            at_ms,
+            reason: reason.to_string(),
+        };
+        report(
+            &mut convergence,
+            "w1",
+            vec![ObservedVm {
+                transitions: vec![
+                    transition(VmState::Pending, VmState::Booting, 1_000, ""),
+                    transition(
+                        VmState::Booting,
+                        VmState::BootFailed,
+                        31_000,
+                        "not ready within the boot timeout",
+                    ),
+                ],
+                ..observed("vm-a", "aaaa", "boot-failed", "")
+            }],
+            40_000,
+        );
+
+        let vm = convergence.describe_vm("vm-a", 40_000).unwrap();
+        let events: Vec<(u64, &str, &str)> = vm
+            .events
+            .iter()
+            .map(|e| (e.timestamp_ms, e.reason.as_str(), e.message.as_str()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (1_000, "StateChanged", "pending -> booting"),
+                (
+                    31_000,
+                    "StateChanged",
+                    "booting -> boot-failed: not ready within the boot timeout"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn workers_and_generations_are_described() {
+        let mut convergence = tracking(&["aaaa", "bbbb"]);
+        report(
+            &mut convergence,
+            "w1",
+            vec![
+                observed("vm-a", "aaaa", "running", ""),
+                observed("vm-b", "bbbb", "failed", "boom"),
+            ],
+            40_000,
+        );
+        convergence.check(60_000);
+
+        let worker = convergence.describe_worker("w1", 80_000).unwrap();
+        assert!(!condition(&worker, "Reporting").status, "stale after 40s");
+        assert!(condition(&worker, "AtGeneration").status);
+        assert_eq!(condition(&worker, "VmsRunning").message, "vm-b");
+        assert_eq!(worker.events.len(), 1);
+        assert_eq!(worker.events[0].object, "vm-b");
+        assert!(convergence.describe_worker("w2", 60_000).is_none());
+
+        let generation = convergence.describe_generation(3, 60_000).unwrap();
+        assert_eq!(observed_field(&generation, "vms"), "1");
+        assert_eq!(
+            condition(&generation, "Converged").message,
+            "1 of 2 VMs running"
+        );
+        assert!(condition(&generation, "DeadlineExceeded").status);
+        assert_eq!(generation.events[0].reason, "Published");
+        assert_eq!(generation.events.len(), 2);
+        assert_eq!(generation.metrics[0].value, "50");
+
+        convergence.activate(&publication(4), Target::default(), 70_000);
+        let superseded = convergence.describe_generation(3, 70_000).unwrap();
+        assert_eq!(condition(&superseded, "Active").reason, "Superseded");
+        assert!(convergence.describe_generation(2, 70_000).is_none());
+    }
+
+    #[test]
+    fn draining_vms_hold_convergence_and_place_nothing() {
+        let mut convergence = tracking(&["aaaa"]);
+        report(
+            &mut convergence,
+            "w1",
+            vec![
+                observed("vm-a", "aaaa", "running", ""),
+                observed("vm-old", "aaaa", "draining", ""),
+            ],
+            0,
+        );
+
+        let generation = convergence.describe_generation(3, 1_000).unwrap();
+        assert!(!condition(&generation, "Converged").status);
+        assert_eq!(
+            condition(&generation, "Converged").message,
+            "1 of 1 VMs running, 1 draining"
+        );
+
+        report(
+            &mut convergence,
+            "w1",
+            vec![observed("vm-old", "aaaa", "draining", "")],
+            2_000,
+        );
+        let generation = convergence.describe_generation(3, 2_000).unwrap();
+        assert_eq!(
+            condition(&generation, "Converged").message,
+            "0 of 1 VMs running, 1 draining"
+        );
+
+        report(
+            &mut convergence,
+            "w1",
+            vec![observed("vm-a", "aaaa", "running", "")],
+            3_000,
+        );
+        let generation = convergence.describe_generation(3, 3_000).unwrap();
+        assert!(condition(&generation, "Converged").status);
+        assert_eq!(
+            condition(&generation, "Converged").message,
+            "1 of 1 VMs running"
+        );
+    }
+
+    #[test]
+    fn history_lists_generations_newest_first_with_their_provenance() {
+        let mut convergence = tracking(&["aaaa"]);
+        let mut built = publication(4);
+        built.provenance = Provenance {
+            ci_build_id: "42".to_string(),
+            commit_url: "https://repohub/infra/commit/commit-4".to_string(),
+            cache: "https://cache.example".to_string(),
+            ..Provenance::default()
+        };
+        convergence.activate(&built, Target::default(), 1_000);
+
+        let history = convergence.history();
+        let numbers: Vec<u64> = history.iter().map(|g| g.number).collect();
+        assert_eq!(numbers, [4, 3]);
+        assert!(history[0].active && !history[1].active);
+        assert_eq!(history[0].provenance, built.provenance);
+        assert_eq!(history[1].vms, 1);
+
+        let described = convergence.describe_generation(4, 1_000).unwrap();
+        let names: Vec<&str> = described.fields.iter().map(|f| f.name).collect();
+        assert!(names.contains(&"ci build") && names.contains(&"cache"));
+        assert!(!names.contains(&"repo"), "unknown fields are left out");
+
+        for generation in 5..30 {
+            convergence.activate(&publication(generation), Target::default(), 2_000);
+        }
+        assert_eq!(convergence.history().len(), MAX_GENERATIONS);
+        assert_eq!(convergence.history()[0].number, 29);
+    }
+
+    #[test]
+    fn actions_are_routed_and_shown_until_the_worker_reports() {
+        let mut convergence = tracking(&["aaaa"]);
+        assert_eq!(
+            convergence.request_action("vm-a", VmAction::Restart, "peer:cli", 1_000),
+            Err(Unroutable::NotReported("vm-a".to_string()))
+        );
+        report(
+            &mut convergence,
+            "w1",
+            vec![observed("vm-a", "aaaa", "running", "")],
+            1_000,
+        );
+
+        let route = convergence
+            .request_action("aaaa", VmAction::Restart, "peer:cli", 2_000)
+            .unwrap();
+        assert_eq!(route.worker_id, "w1");
+        assert_eq!(route.address, "10.0.0.1:6000");
+        assert_eq!(route.vm_id, "vm-a", "spec hashes resolve to the VM id");
+        let vm = convergence.describe_vm("vm-a", 2_000).unwrap();
+        assert_eq!(observed_field(&vm, "status"), "restarting");
+
+        convergence.finish_action(&route, VmAction::Restart, Ok(()), 3_000);
+        report(
+            &mut convergence,
+            "w1",
+            vec![observed("vm-a", "aaaa", "running", "")],
+            4_000,
+        );
+        let vm = convergence.describe_vm("vm-a", 4_000).unwrap();
+        assert_eq!(observed_field(&vm, "status"), "running");
+        let reasons: Vec<&str> = vm.events.iter().map(|e| e.reason.as_str()).collect();
+        assert_eq!(reasons, ["ActionRequested", "Restarted"]);
+
+        let route = convergence
+            .request_action("vm-a", VmAction::Stop, "peer:cli", 5_000)
+            .unwrap();
+        convergence.finish_action(&route, VmAction::Stop, Err("vm.shutdown failed"), 6_000);
+        let vm = convergence.describe_vm("vm-a", 6_000).unwrap();
+        assert_eq!(
+            observed_field(&vm, "status"),
+            "running",
+            "failed actions show no transient status"
+        );
+        assert_eq!(vm.events.last().unwrap().reason, "ActionFailed");
+        let worker = convergence.describe_worker("w1", 6_000).unwrap();
+        assert_eq!(worker.events.len(), 4);
+    }
+
+    #[test]
+    fn pinned_vms_only_run_and_move_where_pinned() {
+        let mut convergence = tracking(&["aaaa", "bbbb"]);
+        convergence.active.as_mut().unwrap().desired[1].pinned = Some("w2".to_string());
+        assert_eq!(
+            convergence.pin("vm-a", None, "peer:cli", 1_000),
+            Err(PinError::NotReported("vm-a".to_string()))
+        );
+        report(
+            &mut convergence,
+            "w1",
+            vec![
+                observed("vm-a", "aaaa", "running", ""),
+                observed("vm-b", "bbbb", "running", ""),
+            ],
+            1_000,
+        );
+
+        assert_eq!(
+            convergence.pin("aaaa", None, "peer:cli", 2_000),
+            Ok("w1".to_string())
+        );
+        assert_eq!(
+            convergence.movable("vm-a", false),
+            Err(PinError::Pinned {
+                vm_id: "vm-a".to_string(),
+                worker_id: "w1".to_string(),
+            })
+        );
+        assert_eq!(convergence.movable("vm-a", true), Ok(()));
+        let vm = convergence.describe_vm("vm-a", 2_000).unwrap();
+        assert_eq!(vm.placement, "on worker w1, which reports it, pinned there");
+        assert_eq!(vm.events.last().unwrap().reason, "Pinned");
+
+        // The spec's pin wins, and only a VM running there counts
+        assert!(matches!(
+            convergence.pin("vm-b", None, "peer:cli", 2_000),
+            Err(PinError::PinnedBySpec { .. })
+        ));
+        assert!(matches!(
+            convergence.unpin("vm-b", "peer:cli", 2_000),
+            Err(PinError::PinnedBySpec { .. })
+        ));
+        report(
+            &mut convergence,
+            "w1",
+            vec![
+                observed("vm-a", "aaaa", "running", ""),
+                observed("vm-b", "bbbb", "running", ""),
+            ],
+            59_000,
+        );
+        let recorded = convergence.check(60_000);
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].0, "bbbb");
+        assert_eq!(recorded[0].1.reason, Reason::Misplaced);
+        let vm = convergence.describe_vm("vm-b", 60_000).unwrap();
+        assert_eq!(
+            vm.placement,
+            "on worker w1, which reports it, but pinned to worker w2"
+        );
+
+        // A pin outlives its worker's reports
+        report(&mut convergence, "w1", vec![], 61_000);
+        assert!(convergence.movable("vm-a", false).is_err());
+        report(
+            &mut convergence,
+            "w1",
+            vec![observed("vm-a", "aaaa", "running", "")],
+            62_000,
+        );
+        convergence.unpin("vm-a", "peer:cli", 62_000).unwrap();
+        assert_eq!(convergence.movable("vm-a", false), Ok(()));
+    }
+
+    #[test]
+    fn vhost_user_vms_only_go_to_workers_offering_it() {
+        let mut convergence = tracking(&["aaaa"]);
+        convergence.active.as_mut().unwrap().desired[0].net_backend = NetBackend::VhostUser;
+        report(&mut convergence, "w1", vec![], 1_000);
+
+        let recorded = convergence.check(60_000);
+        assert_eq!(recorded[0].1.reason, Reason::Unscheduled);
+        assert_eq!(
+            recorded[0].1.message,
+            "needs vhost-user networking, which none of the 1 reporting workers offers"
+        );
+        let worker = convergence.describe_worker("w1", 60_000).unwrap();
+        let backends = worker
+            .fields
+            .iter()
+            .find(|f| f.name == "network backends")
+            .unwrap();
+        assert_eq!(backends.observed, "tap");
+
+        convergence.observe(
+            Observation {
+                worker_id: "w2".to_string(),
+                address: String::new(),
+                generation: 3,
+                metrics: WorkerMetrics::default(),
+                vms: vec![observed("vm-a", "aaaa", "running", "")],
+                net_backends: vec![NetBackend::Tap, NetBackend::VhostUser],
+            },
+            61_000,
+        );
+        assert!(convergence.check(62_000).is_empty());
+        assert_eq!(
+            convergence.pin("vm-a", Some("w1"), "peer:cli", 62_000),
+            Err(PinError::Unfit {
+                vm_id: "vm-a".to_string(),
+                worker_id: "w1".to_string(),
+                net_backend: NetBackend::VhostUser,
+            })
+        );
+        assert_eq!(
+            convergence.pin("vm-a", None, "peer:cli", 62_000),
+            Ok("w2".to_string())
+        );
+    }
+
+    #[test]
+    fn worker_disk_usage_is_broken_down() {
+        let mut convergence = Convergence::default();
+        convergence.observe(
+            Observation {
+                worker_id: "w1".to_string(),
+                address: String::new(),
+                generation: 3,
+                metrics: WorkerMetrics {
+                    disk_usage: 111,
+                    image_disk_usage: vec![("/nix/store/aaaa-nixos-system".to_string(), 100)],
+                    vm_disk_usage: vec![("vm-a".to_string(), 10)],
+                    log_disk_usage: 1,
+                    ..WorkerMetrics::default()
+                },
+                vms: Vec::new(),
+                net_backends: Vec::new(),
+            },
+            0,
+        );
+        let worker = convergence.describe_worker("w1", 0).unwrap();
+        let value = |name: &str| {
+            worker
+                .metrics
+                .iter()
+                .find(|m| m.name == name)
+                .map(|m| m.value.as_str())
+        };
+        assert_eq!(value("disk usage bytes"), Some("111"));
+        assert_eq!(
+            value("image disk bytes"),
+            Some("100 /nix/store/aaaa-nixos-system")
+        );
+        assert_eq!(value("vm disk bytes"), Some("10 vm-a"));
+        assert_eq!(value("log disk bytes"), Some("1"));
+        assert_eq!(value("store usage"), None, "store not managed");
+    }
+
+    #[test]
+    fn worker_store_janitor_is_reported() {
+        let mut convergence = Convergence::default();
+        convergence.observe(
+            Observation {
+                worker_id: "w1".to_string(),
+                address: String::new(),
+                generation: 3,
+                metrics: WorkerMetrics {
+                    store_janitor: StoreJanitor {
+                        used_percent: 72.5,
+                        high_watermark: 85.0,
+                        pinned_paths: 4,
+                        collections: 2,
+                        freed_bytes: 1024,
+                    },
+                    ..WorkerMetrics::default()
+                },
+                vms: Vec::new(),
+                net_backends: Vec::new(),
+            },
+            0,
+        );
+        let worker = convergence.describe_worker("w1", 0).unwrap();
+        let value = |name: &str| {
+            worker
+                .metrics
+                .iter()
+                .find(|m| m.name == name)
+                .map(|m| m.value.as_str())
+        };
+        assert_eq!(value("store usage"), Some("72.5% of 85% watermark"));
+        assert_eq!(value("store pinned paths"), Some("4"));
+        assert_eq!(value("store collections"), Some("2 freed 1024 bytes"));
+    }
+
+    #[test]
+    fn worker_memory_pressure_is_a_condition() {
+        let mut convergence = Convergence::default();
+        let mut observation = Observation {
+            worker_id: "w1".to_string(),
+            address: String::new(),
+            generation: 3,
+            metrics: WorkerMetrics::default(),
+            vms: Vec::new(),
+            net_backends: Vec::new(),
+        };
+        convergence.observe(observation.clone(), 0);
+        let worker = convergence.describe_worker("w1", 0).unwrap();
+        assert_eq!(condition(&worker, "MemoryPressure").reason, "NotWatched");
+        assert!(!worker.metrics.iter().any(|m| m.name == "memory pressure"));
+
+        observation.metrics.memory_pressure = MemoryPressure {
+            some_avg10: 42.5,
+            full_avg10: 3.0,
+            under_pressure: true,
+            policy: "restart".to_string(),
+            episodes: 2,
+            restarts: 1,
+        };
+        convergence.observe(observation, 0);
+        let worker = convergence.describe_worker("w1", 0).unwrap();
+        let pressure = condition(&worker, "MemoryPressure");
+        assert!(!pressure.status);
+        assert_eq!(pressure.reason, "UnderPressure");
+        assert_eq!(
+            pressure.message,
+            "some 42.5% over 10s, policy restart, 2 episodes, 1 restarts"
+        );
+        assert!(
+            worker
+                .metrics
+                .iter()
+                .any(|m| m.name == "memory pressure" && m.value == "some 42.5% full 3%")
+        );
+    }
+
+    #[test]
+    fn status_is_scoped_to_a_namespace() {
+        let mut team = desired("bbbb");
+        team.namespace = "team-a".to_string();
+        let mut convergence = Convergence::default();
+        convergence.activate(
+            &publication(3),
+            Target {
+                vms: vec![desired("aaaa"), team, desired("cccc")],
+                deadline: Some(DEADLINE),
+                emergency: false,
+            },
+            0,
+        );
+        report(
+            &mut convergence,
+            "w1",
+            vec![
+                observed("vm-a", "aaaa", "running", ""),
+                observed("vm-b", "bbbb", "running", ""),
+                observed("vm-z", "zzzz", "running", ""),
+            ],
+            0,
+        );
+
+        let all = convergence.status(None, 1_000);
+        assert_eq!(all.generation, 3);
+        assert_eq!(all.commit, "commit-3");
+        assert_eq!(all.convergence_percent, 66);
+        assert_eq!(all.workers.len(), 1);
+        assert!(all.workers[0].healthy);
+        assert_eq!(all.workers[0].running_vms, 3);
+        let ids: Vec<(&str, &str)> = all
+            .vms
+            .iter()
+            .map(|vm| (vm.namespace.as_str(), vm.id.as_str()))
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                ("", "vm-z"),
+                ("default", "cccc"),
+                ("default", "vm-a"),
+                ("team-a", "vm-b")
+            ]
+        );
+        assert!(all.vms[0].drifted);
+        assert_eq!(all.vms[1].status, VmState::Pending);
+
+        let scoped = convergence.status(Some("team-a"), 1_000);
+        assert_eq!(scoped.convergence_percent, 100);
+        assert_eq!(scoped.workers, all.workers, "workers are shared");
+        let ids: Vec<&str> = scoped.vms.iter().map(|vm| vm.id.as_str()).collect();
+        assert_eq!(ids, ["vm-b"]);
+        assert_eq!(
+            convergence.status(Some("team-b"), 1_000).vms,
+            Vec::new(),
+            "unknown namespaces see nothing"
+        );
+        assert!(!convergence.status(None, 31_000).workers[0].healthy);
+    }
+
+    #[test]
+    fn free_capacity_only_counts_reporting_workers() {
+        let mut convergence = Convergence::default();
+        for (worker_id, seen_ms) in [("w1", 0), ("w2", 40_000)] {
+            convergence.observe(
+                Observation {
+                    worker_id: worker_id.to_string(),
+                    address: String::new(),
+                    generation: 3,
+                    metrics: WorkerMetrics {
+                        available_cpu: 4.0,
+                        available_memory: 8 * 1024 * 1024 * 1024,
+                        ..WorkerMetrics::default()
+                    },
+                    vms: Vec::new(),
+                    net_backends: Vec::new(),
+                },
+                seen_ms,
+            );
+        }
+        let both = Resources {
+            cpu: 8.0,
+            memory_mb: 16_384,
+        };
+        assert_eq!(convergence.free(10_000), both);
+        // w1 went silent
+        assert_eq!(
+            convergence.free(45_000),
+            Resources {
+                cpu: 4.0,
+                memory_mb: 8192
+            }
+        );
+    }
+
+    #[test]
+    fn unschedulable_vms_are_listed_with_why() {
+        let mut convergence = Convergence::default();
+        let vm = |hash: &str, cpu: u32, memory_mb: u32| DesiredVm {
+            resources: Resources::of_spec(cpu, memory_mb),
+            ..desired(hash)
+        };
+        convergence.activate(
+            &publication(3),
+            Target {
+                vms: vec![
+                    vm("small", 1, 512),
+                    vm("big", 16, 512),
+                    DesiredVm {
+                        net_backend: NetBackend::VhostUser,
+                        ..vm("vhost", 1, 512)
+                    },
+                    DesiredVm {
+                        pinned: Some("w9".to_string()),
+                        ..vm("pinned", 1, 512)
+                    },
+                ],
+                deadline: Some(DEADLINE),
+                emergency: false,
+            },
+            0,
+        );
+        let reasons = |view: &ClusterView| -> Vec<(String, Unschedulable)> {
+            view.unschedulable
+                .iter()
+                .map(|vm| (vm.hash.clone(), vm.reason))
+                .collect()
+        };
+        assert!(
+            convergence
+                .status(None, 0)
+                .unschedulable
+                .iter()
+                .all(|vm| vm.reason == Unschedulable::NoWorkers)
+        );
+
+        convergence.observe(
+            Observation {
+                worker_id: "w1".to_string(),
+                address: String::new(),
+                generation: 3,
+                metrics: WorkerMetrics {
+                    available_cpu: 4.0,
+                    available_memory: 8 * 1024 * 1024 * 1024,
+                    ..WorkerMetrics::default()
+                },
+                vms: Vec::new(),
+                net_backends: Vec::new(),
+            },
+            1_000,
+        );
+        let view = convergence.status(None, 1_000);
+        assert_eq!(
+            reasons(&view),
+            [
+                ("big".to_string(), Unschedulable::InsufficientResources),
+                ("pinned".to_string(), Unschedulable::PinnedWorker),
+                ("vhost".to_string(), Unschedulable::NetBackend),
+            ],
+            "small fits on w1"
+        );
+        assert!(view.unschedulable[0].message.contains("16 cpu and 512 MiB"));
+        assert!(view.unschedulable[1].message.contains("w9"));
+        assert_eq!(
+            view.vms.len(),
+            4,
+            "unschedulable VMs are still listed as pending"
+        );
+
+        // Once w1 goes silent, nothing can be placed
+        assert_eq!(convergence.status(None, 40_000).unschedulable.len(), 4);
+    }
+
+    #[test]
+    fn drift_is_reported_by_who_found_it() {
+        let mut convergence = tracking(&["aaaa", "bbbb", "cccc"]);
+        let given = |id: &str, hash: &str, desired_hash: &str| ObservedVm {
+            desired_hash: desired_hash.to_string(),
+            ..observed(id, hash, "running", "")
+        };
+        report(
+            &mut convergence,
+            "w1",
+            vec![
+                given("vm-a", "aaaa", "aaaa"),
+                given("vm-b", "zzzz", "bbbb"),
+                observed("vm-x", "xxxx", "running", ""),
+            ],
+            1_000,
+        );
+        report(
+            &mut convergence,
+            "w1",
+            vec![
+                given("vm-a", "aaaa", "aaaa"),
+                given("vm-b", "zzzz", "bbbb"),
+                observed("vm-x", "xxxx", "running", ""),
+            ],
+            61_000,
+        );
+
+        let drift = convergence.drift_report(121_000);
+        assert_eq!(drift.generation, 3);
+        let summary: Vec<(&str, &str, DriftSource)> = drift
+            .vms
+            .iter()
+            .map(|vm| (vm.vm_id.as_str(), vm.desired_hash.as_str(), vm.drift))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("vm-x", "", DriftSource::HashMismatch),
+                ("vm-a", "aaaa", DriftSource::InSync),
+                ("vm-b", "bbbb", DriftSource::SelfReported),
+                ("", "cccc", DriftSource::Missing),
+            ],
+            "a VM no spec wants has no namespace"
+        );
+        assert_eq!(
+            drift.vms[1].converged_for,
+            Some(Duration::from_secs(120)),
+            "converged since first seen in sync"
+        );
+        assert_eq!(drift.vms[2].converged_for, None);
+
+        // A generation that no longer wants vm-a's spec ends its convergence
+        convergence.activate(
+            &publication(4),
+            Target {
+                vms: vec![desired("dddd")],
+                deadline: Some(DEADLINE),
+                emergency: false,
+            },
+            130_000,
+        );
+        let drift = convergence.drift_report(131_000);
+        assert_eq!(drift.vms[0].drift, DriftSource::HashMismatch);
+        assert_eq!(drift.vms[0].converged_for, None);
+    }
+}
@@ -0,0 +1,110 @@
+//! Detailed view of one object for `Master.describe` (`pcr describe`), in
+//! the spirit of `kubectl describe`: desired against observed fields,
+//! conditions, a metrics snapshot and recent events.
+//!
+//! Views are built by [`Convergence`](crate::convergence::Convergence), which
+//! holds everything the master knows about VMs, workers and generations.
+
+use std::fmt;
+
+/// What can be described.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Vm,
+    Worker,
+    Generation,
+}
+
+impl Kind {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Vm => "vm",
+            Kind::Worker => "worker",
+            Kind::Generation => "generation",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One field as declared and as reported; either side may be empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub desired: String,
+    pub observed: String,
+}
+
+impl Field {
+    pub fn new(
+        name: &'static str,
+        desired: impl Into<String>,
+        observed: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            desired: desired.into(),
+            observed: observed.into(),
+        }
+    }
+}
+
+/// A named yes/no state with why it holds, like `Running` or `Converged`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub name: &'static str,
+    pub status: bool,
+    pub reason: String,
+    pub message: String,
+}
+
+impl Condition {
+    pub fn new(
+        name: &'static str,
+        status: bool,
+        reason: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            reason: reason.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Latest reported value of a metric, formatted for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub value: String,
+}
+
+/// Something that happened to the object or one of its VMs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub timestamp_ms: u64,
+    /// VM id or spec hash the event is about
+    pub object: String,
+    pub reason: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Description {
+    pub kind: Kind,
+    pub id: String,
+    /// Where the object runs and why, empty when that does not apply
+    pub placement: String,
+    pub fields: Vec<Field>,
+    pub conditions: Vec<Condition>,
+    pub metrics: Vec<Metric>,
+    /// Oldest first
+    pub events: Vec<Event>,
+}
@@ -1,13 +1,111 @@
+use commands::vm_action::VmAction;
 use tokio::sync::{
     mpsc::Sender,
     oneshot::{self, Receiver},
 };
 
-pub enum NodeEvent{Apply}
+use crate::convergence::{ClusterView, DriftReport, GenerationInfo, Observation, Route, Target};
+use crate::describe::{Description, Kind};
+use crate::intake::{Conflict, Publication};
+use crate::maintenance::{Policies, Status};
+use crate::metrics_history::{Query, Series};
+use crate::reservations::{self, ReservationRequest};
 
-pub enum NodeError {}
+pub enum NodeEvent {
+    Apply,
+    /// Make a verified publication the active generation
+    Publish(Publication, Target),
+    /// A worker reported what it runs
+    Observe(Observation),
+    /// Describe one object, see [`crate::describe`]
+    Describe(Kind, String),
+    /// The last generations made active
+    History,
+    /// Recent [metrics](crate::metrics_history) of one VM or worker
+    QueryMetrics(Query),
+    /// Workers, VMs and convergence, of one [namespace](crate::tenancy) when
+    /// set
+    ClusterStatus(Option<String>),
+    /// Desired against observed state of every VM
+    Drift,
+    /// Maintenance policies and the pending generation
+    Maintenance,
+    /// Replace the [maintenance](crate::maintenance) policies
+    SetMaintenance(Policies),
+    /// Active [reservations](crate::reservations) and the capacity left
+    Reservations,
+    /// Reserve capacity ahead of a deployment
+    Reserve {
+        request: ReservationRequest,
+        actor: String,
+    },
+    /// End a reservation before it is consumed or expires
+    Release {
+        name: String,
+        actor: String,
+    },
+    /// Route a user's action on a VM, by id or spec hash, and record it
+    VmAction {
+        id: String,
+        action: VmAction,
+        actor: String,
+    },
+    /// The worker of `route` carried out `action`, or failed to
+    VmActionDone {
+        route: Route,
+        action: VmAction,
+        outcome: Result<(), String>,
+    },
+    /// Pin a VM, by id or spec hash, to a worker (where it runs when `None`),
+    /// or unpin it
+    PinVm {
+        id: String,
+        worker_id: Option<String>,
+        pin: bool,
+        actor: String,
+    },
+}
+
+/// What the node answers besides success or failure.
+#[derive(Debug)]
+pub enum NodeReply {
+    Done,
+    Described(Box<Description>),
+    Maintenance(Box<Status>),
+    Reservations(Box<reservations::Status>),
+    /// Where to send a [`NodeEvent::VmAction`]
+    Routed(Route),
+    /// Worker a [`NodeEvent::PinVm`] pinned the VM to, `None` once unpinned
+    Pinned(Option<String>),
+    /// Newest first
+    History(Vec<GenerationInfo>),
+    ClusterStatus(Box<ClusterView>),
+    Drift(Box<DriftReport>),
+    Metrics(Series),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NodeError {
+    #[error(transparent)]
+    Conflict(#[from] Conflict),
+    /// No such object
+    #[error("{0} not found")]
+    NotFound(String),
+    /// A VM action has nowhere to go
+    #[error("{0}")]
+    Unroutable(String),
+    /// The VM is pinned in a way the request can't change
+    #[error("{0}")]
+    Pinned(String),
+    /// Not enough unreserved capacity for a reservation
+    #[error("{0}")]
+    Capacity(String),
+    /// The node loop is gone, e.g. during shutdown
+    #[error("control plane is shutting down")]
+    Stopped,
+}
 
-pub type NodeResult = Result<(), NodeError>;
+pub type NodeResult = Result<NodeReply, NodeError>;
 
 pub struct NodeMessage {
     event: NodeEvent,
@@ -37,6 +135,20 @@ pub struct NodeReceiver(Receiver<NodeResult>);
 #[derive(Clone)]
 pub struct NodeMessenger(Sender<NodeMessage>);
 
+impl NodeMessenger {
+    /// Whether the node loop has stopped receiving.
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+
+    /// Send `event` to the node and wait for its reply.
+    pub async fn request(&self, event: NodeEvent) -> NodeResult {
+        let (NodeReceiver(rx), msg) = NodeMessage::new(event);
+        self.0.send(msg).await.map_err(|_| NodeError::Stopped)?;
+        rx.await.map_err(|_| NodeError::Stopped)?
+    }
+}
+
 impl From<Sender<NodeMessage>> for NodeMessenger {
     fn from(value: Sender<NodeMessage>) -> Self {
         Self(value)
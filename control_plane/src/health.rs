@@ -0,0 +1,52 @@
+//! Master checks behind `/healthz`, `/readyz` and the systemd watchdog.
+//!
+//! - `rpc` (liveness, readiness) — the RPC listener is accepting.
+//! - `node_channel` (liveness, readiness) — the node loop is still receiving.
+//! - `audit_log` (readiness) — the audit file can be appended to; mutating
+//!   calls fail without it.
+
+use commands::health::{Check, Flag, Probe, Report};
+
+use crate::audit::AuditLog;
+use crate::dto::NodeMessenger;
+
+#[derive(Clone)]
+pub struct MasterHealth {
+    node: NodeMessenger,
+    rpc: Flag,
+    audit: AuditLog,
+}
+
+impl MasterHealth {
+    pub fn new(node: impl Into<NodeMessenger>, rpc: Flag, audit: AuditLog) -> Self {
+        Self {
+            node: node.into(),
+            rpc,
+            audit,
+        }
+    }
+
+    fn live(&self) -> Vec<Check> {
+        vec![
+            Check::expect("rpc", self.rpc.is_raised(), "listener not accepting"),
+            Check::expect("node_channel", !self.node.is_closed(), "node loop stopped"),
+        ]
+    }
+}
+
+impl Probe for MasterHealth {
+    async fn liveness(&self) -> Report {
+        Report(self.live())
+    }
+
+    async fn readiness(&self) -> Report {
+        let mut checks = self.live();
+        let audit = self.audit.clone();
+        let writable = tokio::task::spawn_blocking(move || audit.check())
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r.map_err(|e| e.to_string()));
+        checks.push(Check::from_result("audit_log", writable));
+        Report(checks)
+    }
+}
@@ -0,0 +1,1152 @@
+//! HTTP/JSON gateway to the master, for dashboards and scripts that do not
+//! speak Cap'n Proto. Optional: served only when an address is configured.
+//!
+//! | Route                            | Cap'n Proto equivalent                            |
+//! |----------------------------------|---------------------------------------------------|
+//! | `GET /v1/status`                 | readiness checks of the master                    |
+//! | `GET /v1/events`                 | `Master.getAuditLog` (`?since_ms=&limit=`)        |
+//! | `POST /v1/generations`           | `Master.publishState`, plus [templates]           |
+//! | `GET /v1/maintenance`            | none, see [maintenance](crate::maintenance)       |
+//! | `PUT /v1/maintenance`            | none, replaces the windows and freezes            |
+//! | `GET /v1/reservations`           | none, see [reservations](crate::reservations)     |
+//! | `POST /v1/reservations`          | none, reserves capacity ahead of a publish        |
+//! | `DELETE /v1/reservations/{name}` | none, releases a reservation                      |
+//! | `GET /v1/generations`            | `Master.listGenerations`                          |
+//! | `GET /v1/vms`                    | `Master.getClusterStatus` (`?namespace=`)         |
+//! | `GET /v1/drift`                  | `Master.driftReport`                              |
+//! | `GET /v1/metrics/{kind}/{id}`    | `Master.queryMetrics` (`?range_secs=&step_secs=`) |
+//!
+//! Both APIs share the same checks and the [audit log](crate::audit):
+//! a publish is verified, checked for [conflicts](crate::intake) and audited
+//! exactly like its RPC counterpart, with `http:<peer>` as the actor and
+//! default publisher. Errors are `{"error": "..."}` with a matching status.
+//! Setting maintenance policies is audited as `PUT /v1/maintenance`, and
+//! reserving and releasing capacity as `POST` and `DELETE /v1/reservations`.
+//!
+//! With [tokens](crate::tenancy) configured, every route but `/v1/status`
+//! takes one as `Authorization: Bearer <token>`, and its holder's name
+//! prefixes the actor. A token scoped to a namespace may only list that
+//! namespace's VMs; publishing, setting maintenance policies and reserving
+//! capacity need an unscoped token that is not read-only, and metrics and
+//! drift an unscoped one. Refusals are `401` for a missing or
+//! unknown token and `403` otherwise.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{HeaderMap, StatusCode, header::AUTHORIZATION};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use commands::hashing::{self, ContentHash, VmSpecFields};
+use commands::health::{Probe, Report};
+use commands::labels::Labels;
+use commands::net_backend::NetBackend;
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::audit::{AuditEntry, AuditLog};
+use crate::convergence::{
+    ClusterView, DesiredVm, DriftEntry, DriftReport, GenerationInfo, Target, VmSummary,
+};
+use crate::describe::Kind;
+use crate::dto::{NodeError, NodeEvent, NodeMessenger, NodeReply};
+use crate::health::MasterHealth;
+use crate::intake::{Provenance, Publication};
+use crate::maintenance::Policies;
+use crate::metrics_history::{Query as MetricsQuery, Series};
+use crate::reservations::ReservationRequest;
+use crate::scheduler::Resources;
+use crate::server::{deadline_of, publish_summary, verify_intent};
+use crate::tenancy::{self, Denied, Grant, Tokens};
+
+mod templates;
+
+/// Shared state of the gateway handlers.
+#[derive(Clone)]
+pub struct Gateway {
+    messenger: NodeMessenger,
+    audit: AuditLog,
+    health: MasterHealth,
+    tokens: Tokens,
+}
+
+impl Gateway {
+    pub fn new(messenger: impl Into<NodeMessenger>, audit: AuditLog, health: MasterHealth) -> Self {
+        Self {
+            messenger: messenger.into(),
+            audit,
+            health,
+            tokens: Tokens::default(),
+        }
+    }
+
+    /// Require the [tokens](crate::tenancy) to be presented.
+    #[must_use]
+    pub fn with_tokens(mut self, tokens: Tokens) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// The grant of the request's bearer token, if `check` allows it.
+    fn authorize(
+        &self,
+        headers: &HeaderMap,
+        check: impl FnOnce(&Grant) -> Result<(), Denied>,
+    ) -> Result<Grant, Denied> {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        self.tokens
+            .authenticate(token)
+            .and_then(|grant| check(&grant).map(|()| grant))
+            .inspect_err(|denied| warn!(%denied, "HTTP request refused"))
+    }
+
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/v1/status", get(status))
+            .route("/v1/events", get(events))
+            .route("/v1/generations", get(generations).post(publish))
+            .route("/v1/vms", get(vms))
+            .route("/v1/drift", get(drift))
+            .route("/v1/metrics/{kind}/{id}", get(metrics))
+            .route("/v1/maintenance", get(maintenance).put(set_maintenance))
+            .route("/v1/reservations", get(reservations).post(reserve))
+            .route("/v1/reservations/{name}", delete(release))
+            .with_state(self)
+    }
+}
+
+/// Serve the gateway on `addr` until `shutdown` is cancelled.
+///
+/// # Errors
+///
+/// - if `addr` cannot be bound
+pub async fn serve(
+    addr: SocketAddr,
+    gateway: Gateway,
+    shutdown: CancellationToken,
+) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!(%addr, "Serving HTTP gateway");
+    axum::serve(
+        listener,
+        gateway
+            .router()
+            .into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.cancelled_owned())
+    .await
+}
+
+// ─── Errors ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+fn error_response(status: StatusCode, error: impl Into<String>) -> Response {
+    (
+        status,
+        Json(ErrorBody {
+            error: error.into(),
+        }),
+    )
+        .into_response()
+}
+
+fn node_error(e: &NodeError) -> (StatusCode, String) {
+    let status = match e {
+        NodeError::Conflict(_) | NodeError::Pinned(_) | NodeError::Capacity(_) => {
+            StatusCode::CONFLICT
+        }
+        NodeError::NotFound(_) => StatusCode::NOT_FOUND,
+        NodeError::Unroutable(_) => StatusCode::BAD_GATEWAY,
+        NodeError::Stopped => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, e.to_string())
+}
+
+fn denied_response(denied: &Denied) -> Response {
+    let status = match denied {
+        _ if denied.unauthenticated() => StatusCode::UNAUTHORIZED,
+        Denied::Invalid(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::FORBIDDEN,
+    };
+    error_response(status, denied.to_string())
+}
+
+// ─── Status ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    ready: bool,
+    checks: Vec<CheckResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct CheckResult {
+    name: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl From<Report> for StatusResponse {
+    fn from(report: Report) -> Self {
+        Self {
+            ready: report.healthy(),
+            checks: report
+                .0
+                .into_iter()
+                .map(|check| CheckResult {
+                    name: check.name,
+                    ok: check.result.is_ok(),
+                    error: check.result.err(),
+                })
+                .collect(),
+        }
+    }
+}
+
+async fn status(State(gateway): State<Gateway>) -> Json<StatusResponse> {
+    Json(gateway.health.readiness().await.into())
+}
+
+// ─── Events ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    #[serde(default)]
+    since_ms: u64,
+    /// `0` for no limit, as in `Master.getAuditLog`.
+    #[serde(default)]
+    limit: usize,
+}
+
+async fn events(
+    State(gateway): State<Gateway>,
+    headers: HeaderMap,
+    Query(query): Query<EventsQuery>,
+) -> Response {
+    if let Err(denied) = gateway.authorize(&headers, Grant::unscoped) {
+        return denied_response(&denied);
+    }
+    debug!(query.since_ms, query.limit, "Reading audit log over HTTP");
+    let audit = gateway.audit.clone();
+    let entries = tokio::task::spawn_blocking(move || audit.query(query.since_ms, query.limit))
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|r| r);
+    match entries {
+        Ok(entries) => Json(entries).into_response(),
+        Err(e) => {
+            error!(error = %e, "Failed to read audit log");
+            error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("audit log unavailable: {e}"),
+            )
+        }
+    }
+}
+
+// ─── Generations ───────────────────────────────────────────────────────────
+
+/// Body of `POST /v1/generations`, the JSON form of `Master.publishState`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PublishRequest {
+    commit: String,
+    generation: u64,
+    /// Checked against the specs when present, see [`verify_intent`].
+    #[serde(default)]
+    intent_hash: String,
+    #[serde(default)]
+    vm_specs: Vec<VmSpecJson>,
+    /// Specs with variables, see [templates]
+    #[serde(default)]
+    templates: BTreeMap<String, templates::Template>,
+    /// Specs to make from `templates`, added after `vm_specs`
+    #[serde(default)]
+    instances: Vec<templates::Instance>,
+    /// Ignored when tokens are configured, see [`Grant::publisher`]
+    #[serde(default)]
+    publisher: Option<String>,
+    /// Generation the publish was built on; absent publishes unconditionally
+    #[serde(default)]
+    parent_generation: Option<u64>,
+    /// `0` or absent for the master default
+    #[serde(default)]
+    convergence_deadline_secs: u32,
+    /// Apply even outside maintenance windows and during freezes
+    #[serde(default)]
+    emergency: bool,
+    /// Where the generation came from, see [`Provenance`]
+    #[serde(default)]
+    provenance: Provenance,
+}
+
+/// A VM spec in the shape of the Nix `vmSpecJson` output.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VmSpecJson {
+    toplevel: String,
+    /// Kernel, initrd and disk image are empty for a VM booted straight
+    /// from its `toplevel` closure
+    #[serde(default)]
+    kernel_path: String,
+    #[serde(default)]
+    initrd_path: String,
+    #[serde(default)]
+    disk_image_path: String,
+    cmdline: String,
+    cpu: u32,
+    memory_mb: u32,
+    #[serde(default)]
+    network_allowed_domains: Vec<String>,
+    /// Memory backed by hugepages, see `Common.VmSpec.hugepages`
+    #[serde(default)]
+    hugepages: bool,
+    /// Memory mapped `shared=on`, for virtio-fs and vhost-user devices
+    #[serde(default)]
+    shared_memory: bool,
+    /// `tap` or `vhost-user`, see `Common.VmSpec.netBackend`
+    #[serde(default, deserialize_with = "net_backend")]
+    net_backend: NetBackend,
+    #[serde(default)]
+    labels: Labels,
+    /// Worker the VM must run on, see `Common.VmSpec.pinnedWorker`
+    #[serde(default)]
+    pinned_worker: Option<String>,
+    /// [Namespace](crate::tenancy) of the VM, `default` when empty
+    #[serde(default)]
+    namespace: String,
+}
+
+impl VmSpecJson {
+    fn content_hash(&self) -> ContentHash {
+        let domains: Vec<&str> = self
+            .network_allowed_domains
+            .iter()
+            .map(String::as_str)
+            .collect();
+        hashing::vm_spec_hash(&VmSpecFields {
+            toplevel: &self.toplevel,
+            kernel_path: &self.kernel_path,
+            initrd_path: &self.initrd_path,
+            disk_image_path: &self.disk_image_path,
+            cmdline: &self.cmdline,
+            cpu: self.cpu,
+            memory_mb: self.memory_mb,
+            network_allowed_domains: &domains,
+            hugepages: self.hugepages,
+            shared_memory: self.shared_memory,
+            net_backend: self.net_backend,
+        })
+    }
+
+    fn desired(&self) -> Result<DesiredVm, String> {
+        Ok(DesiredVm {
+            hash: self.content_hash().to_string(),
+            store_paths: [
+                &self.toplevel,
+                &self.kernel_path,
+                &self.initrd_path,
+                &self.disk_image_path,
+            ]
+            .into_iter()
+            .filter(|p| !p.is_empty())
+            .cloned()
+            .collect(),
+            labels: self.labels.clone(),
+            pinned: self.pinned_worker.clone().filter(|w| !w.is_empty()),
+            namespace: tenancy::namespace(&self.namespace)?,
+            net_backend: self.net_backend,
+            resources: Resources::of_spec(self.cpu, self.memory_mb),
+        })
+    }
+}
+
+fn net_backend<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NetBackend, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PublishResponse {
+    generation: u64,
+    intent_hash: String,
+}
+
+/// One entry of `GET /v1/generations`, newest first.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerationResponse {
+    generation: u64,
+    commit: String,
+    intent_hash: String,
+    publisher: String,
+    published_ms: u64,
+    active: bool,
+    vms: usize,
+    #[serde(flatten)]
+    provenance: Provenance,
+}
+
+impl From<GenerationInfo> for GenerationResponse {
+    fn from(info: GenerationInfo) -> Self {
+        Self {
+            generation: info.number,
+            commit: info.commit,
+            intent_hash: info.intent_hash,
+            publisher: info.publisher,
+            published_ms: info.published_ms,
+            active: info.active,
+            vms: info.vms,
+            provenance: info.provenance,
+        }
+    }
+}
+
+async fn generations(State(gateway): State<Gateway>, headers: HeaderMap) -> Response {
+    if let Err(denied) = gateway.authorize(&headers, Grant::unscoped) {
+        return denied_response(&denied);
+    }
+    match gateway.messenger.request(NodeEvent::History).await {
+        Ok(NodeReply::History(history)) => Json(
+            history
+                .into_iter()
+                .map(GenerationResponse::from)
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Ok(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unexpected reply from the node",
+        ),
+        Err(e) => {
+            let (status, e) = node_error(&e);
+            error_response(status, e)
+        }
+    }
+}
+
+async fn publish(
+    State(gateway): State<Gateway>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(mut request): Json<PublishRequest>,
+) -> Response {
+    let grant = match gateway.authorize(&headers, Grant::may_write) {
+        Ok(grant) => grant,
+        Err(denied) => return denied_response(&denied),
+    };
+    let actor = grant.actor(&format!("http:{peer}"));
+    let publisher = grant.publisher(request.publisher.as_deref(), &actor);
+    info!(
+        generation = request.generation,
+        commit = %request.commit,
+        intent_hash = %request.intent_hash,
+        %publisher,
+        parent = ?request.parent_generation,
+        instances = request.instances.len(),
+        "Publish request over HTTP"
+    );
+
+    match templates::expand(&request.templates, &request.instances) {
+        Ok(specs) => request.vm_specs.extend(specs),
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    }
+    let hashes: Vec<ContentHash> = request
+        .vm_specs
+        .iter()
+        .map(VmSpecJson::content_hash)
+        .collect();
+    let computed = hashing::generation_hash(&hashes);
+    let verified = verify_intent(&request.intent_hash, &computed);
+
+    let publication = Publication {
+        publisher,
+        generation: request.generation,
+        commit: request.commit,
+        intent_hash: computed.to_string(),
+        parent: request.parent_generation,
+        provenance: request.provenance,
+    };
+    let vms = match request
+        .vm_specs
+        .iter()
+        .map(VmSpecJson::desired)
+        .collect::<Result<_, _>>()
+    {
+        Ok(vms) => vms,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let target = Target {
+        vms,
+        deadline: deadline_of(request.convergence_deadline_secs),
+        emergency: request.emergency,
+    };
+    let summary = publish_summary(&publication, Some(&request.intent_hash), &target);
+
+    let outcome = match verified {
+        Ok(()) => gateway
+            .messenger
+            .request(NodeEvent::Publish(publication, target))
+            .await
+            .map(|_| ())
+            .map_err(|e| node_error(&e)),
+        Err(e) => Err((StatusCode::CONFLICT, e)),
+    };
+
+    let entry = AuditEntry::new(
+        actor,
+        "Master.publishState",
+        summary,
+        outcome.clone().map_err(|(_, e)| e),
+    );
+    if let Err(e) = gateway.audit.record(&entry) {
+        error!(error = %e, "Failed to write audit entry");
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("audit log unavailable: {e}"),
+        );
+    }
+
+    match outcome {
+        Ok(()) => Json(PublishResponse {
+            generation: request.generation,
+            intent_hash: computed.to_string(),
+        })
+        .into_response(),
+        Err((status, e)) => error_response(status, e),
+    }
+}
+
+// ─── VMs ───────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+struct VmsQuery {
+    /// Empty for every namespace the token sees
+    #[serde(default)]
+    namespace: String,
+}
+
+/// Body of `GET /v1/vms`, the JSON form of `Common.ClusterStatus`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VmsResponse {
+    generation: u64,
+    commit: String,
+    convergence_percent: u32,
+    workers: Vec<WorkerResponse>,
+    vms: Vec<VmResponse>,
+    unschedulable: Vec<UnschedulableResponse>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkerResponse {
+    id: String,
+    healthy: bool,
+    generation: u64,
+    running_vms: usize,
+    net_backends: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VmResponse {
+    id: String,
+    worker_id: String,
+    namespace: String,
+    desired_hash: String,
+    observed_hash: String,
+    status: &'static str,
+    drifted: bool,
+    labels: Labels,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UnschedulableResponse {
+    spec_hash: String,
+    namespace: String,
+    reason: &'static str,
+    message: String,
+}
+
+impl From<VmSummary> for VmResponse {
+    fn from(vm: VmSummary) -> Self {
+        Self {
+            id: vm.id,
+            worker_id: vm.worker_id,
+            namespace: vm.namespace,
+            desired_hash: vm.desired_hash,
+            observed_hash: vm.observed_hash,
+            status: vm.status.as_str(),
+            drifted: vm.drifted,
+            labels: vm.labels,
+        }
+    }
+}
+
+impl From<ClusterView> for VmsResponse {
+    fn from(view: ClusterView) -> Self {
+        Self {
+            generation: view.generation,
+            commit: view.commit,
+            convergence_percent: view.convergence_percent,
+            workers: view
+                .workers
+                .into_iter()
+                .map(|w| WorkerResponse {
+                    id: w.id,
+                    healthy: w.healthy,
+                    generation: w.generation,
+                    running_vms: w.running_vms,
+                    net_backends: w.net_backends.into_iter().map(NetBackend::as_str).collect(),
+                })
+                .collect(),
+            vms: view.vms.into_iter().map(VmResponse::from).collect(),
+            unschedulable: view
+                .unschedulable
+                .into_iter()
+                .map(|vm| UnschedulableResponse {
+                    spec_hash: vm.hash,
+                    namespace: vm.namespace,
+                    reason: vm.reason.as_str(),
+                    message: vm.message,
+                })
+                .collect(),
+        }
+    }
+}
+
+async fn vms(
+    State(gateway): State<Gateway>,
+    headers: HeaderMap,
+    Query(query): Query<VmsQuery>,
+) -> Response {
+    let namespace = match gateway
+        .authorize(&headers, |_| Ok(()))
+        .and_then(|grant| grant.view(&query.namespace))
+    {
+        Ok(namespace) => namespace,
+        Err(denied) => return denied_response(&denied),
+    };
+    debug!(?namespace, "Listing VMs over HTTP");
+    match gateway
+        .messenger
+        .request(NodeEvent::ClusterStatus(namespace))
+        .await
+    {
+        Ok(NodeReply::ClusterStatus(view)) => Json(VmsResponse::from(*view)).into_response(),
+        Ok(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unexpected reply from the node",
+        ),
+        Err(e) => {
+            let (status, e) = node_error(&e);
+            error_response(status, e)
+        }
+    }
+}
+
+// ─── Drift ─────────────────────────────────────────────────────────────────
+
+/// Body of `GET /v1/drift`, the JSON form of `Master.DriftReport`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DriftResponse {
+    generation: u64,
+    commit: String,
+    vms: Vec<DriftEntryResponse>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DriftEntryResponse {
+    vm_id: String,
+    worker_id: String,
+    namespace: String,
+    desired_hash: String,
+    observed_hash: String,
+    converged_secs: u64,
+    drift: &'static str,
+}
+
+impl From<DriftEntry> for DriftEntryResponse {
+    fn from(entry: DriftEntry) -> Self {
+        Self {
+            vm_id: entry.vm_id,
+            worker_id: entry.worker_id,
+            namespace: entry.namespace,
+            desired_hash: entry.desired_hash,
+            observed_hash: entry.observed_hash,
+            converged_secs: entry.converged_for.map_or(0, |d| d.as_secs()),
+            drift: entry.drift.as_str(),
+        }
+    }
+}
+
+impl From<DriftReport> for DriftResponse {
+    fn from(report: DriftReport) -> Self {
+        Self {
+            generation: report.generation,
+            commit: report.commit,
+            vms: report
+                .vms
+                .into_iter()
+                .map(DriftEntryResponse::from)
+                .collect(),
+        }
+    }
+}
+
+async fn drift(State(gateway): State<Gateway>, headers: HeaderMap) -> Response {
+    if let Err(denied) = gateway.authorize(&headers, Grant::unscoped) {
+        return denied_response(&denied);
+    }
+    match gateway.messenger.request(NodeEvent::Drift).await {
+        Ok(NodeReply::Drift(report)) => Json(DriftResponse::from(*report)).into_response(),
+        Ok(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unexpected reply from the node",
+        ),
+        Err(e) => {
+            let (status, e) = node_error(&e);
+            error_response(status, e)
+        }
+    }
+}
+
+// ─── Metrics ───────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+struct MetricsParams {
+    /// `0` for all the master keeps
+    #[serde(default)]
+    range_secs: u64,
+    /// `0` for about 60 points
+    #[serde(default)]
+    step_secs: u64,
+}
+
+/// Body of `GET /v1/metrics/{kind}/{id}`, the JSON form of
+/// `Master.MetricSeries`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MetricsResponse {
+    names: &'static [&'static str],
+    step_secs: u64,
+    points: Vec<PointResponse>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PointResponse {
+    timestamp_ms: u64,
+    values: Vec<f64>,
+}
+
+impl From<Series> for MetricsResponse {
+    fn from(series: Series) -> Self {
+        Self {
+            names: series.names,
+            step_secs: series.step.as_secs(),
+            points: series
+                .points
+                .into_iter()
+                .map(|p| PointResponse {
+                    timestamp_ms: p.timestamp_ms,
+                    values: p.values,
+                })
+                .collect(),
+        }
+    }
+}
+
+async fn metrics(
+    State(gateway): State<Gateway>,
+    headers: HeaderMap,
+    Path((kind, id)): Path<(String, String)>,
+    Query(params): Query<MetricsParams>,
+) -> Response {
+    if let Err(denied) = gateway.authorize(&headers, Grant::unscoped) {
+        return denied_response(&denied);
+    }
+    let kind = match kind.as_str() {
+        "vm" => Kind::Vm,
+        "worker" => Kind::Worker,
+        _ => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                format!("unknown kind {kind:?}, expected vm or worker"),
+            );
+        }
+    };
+    debug!(%kind, %id, "Querying metrics over HTTP");
+    let query = MetricsQuery {
+        kind,
+        id,
+        range: Duration::from_secs(params.range_secs),
+        step: Duration::from_secs(params.step_secs),
+    };
+    match gateway
+        .messenger
+        .request(NodeEvent::QueryMetrics(query))
+        .await
+    {
+        Ok(NodeReply::Metrics(series)) => Json(MetricsResponse::from(series)).into_response(),
+        Ok(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unexpected reply from the node",
+        ),
+        Err(e) => {
+            let (status, e) = node_error(&e);
+            error_response(status, e)
+        }
+    }
+}
+
+// ─── Maintenance ───────────────────────────────────────────────────────────
+
+async fn maintenance(State(gateway): State<Gateway>, headers: HeaderMap) -> Response {
+    if let Err(denied) = gateway.authorize(&headers, Grant::unscoped) {
+        return denied_response(&denied);
+    }
+    match gateway.messenger.request(NodeEvent::Maintenance).await {
+        Ok(NodeReply::Maintenance(status)) => Json(*status).into_response(),
+        Ok(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unexpected reply from the node",
+        ),
+        Err(e) => {
+            let (status, e) = node_error(&e);
+            error_response(status, e)
+        }
+    }
+}
+
+async fn set_maintenance(
+    State(gateway): State<Gateway>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(policies): Json<Policies>,
+) -> Response {
+    let grant = match gateway.authorize(&headers, Grant::may_write) {
+        Ok(grant) => grant,
+        Err(denied) => return denied_response(&denied),
+    };
+    let actor = grant.actor(&format!("http:{peer}"));
+    info!(
+        windows = policies.windows.len(),
+        freezes = policies.freezes.len(),
+        "Setting maintenance policies over HTTP"
+    );
+    let summary = serde_json::to_value(&policies).unwrap_or_default();
+
+    let outcome = match policies.validate() {
+        Ok(()) => gateway
+            .messenger
+            .request(NodeEvent::SetMaintenance(policies))
+            .await
+            .map_err(|e| node_error(&e)),
+        Err(e) => Err((StatusCode::BAD_REQUEST, e)),
+    };
+
+    let entry = AuditEntry::new(
+        actor,
+        "PUT /v1/maintenance",
+        summary,
+        outcome.as_ref().map(|_| ()).map_err(|(_, e)| e.clone()),
+    );
+    if let Err(e) = gateway.audit.record(&entry) {
+        error!(error = %e, "Failed to write audit entry");
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("audit log unavailable: {e}"),
+        );
+    }
+
+    match outcome {
+        Ok(NodeReply::Maintenance(status)) => Json(*status).into_response(),
+        Ok(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unexpected reply from the node",
+        ),
+        Err((status, e)) => error_response(status, e),
+    }
+}
+
+// ─── Reservations ──────────────────────────────────────────────────────────
+
+async fn reservations(State(gateway): State<Gateway>, headers: HeaderMap) -> Response {
+    if let Err(denied) = gateway.authorize(&headers, Grant::unscoped) {
+        return denied_response(&denied);
+    }
+    match gateway.messenger.request(NodeEvent::Reservations).await {
+        Ok(NodeReply::Reservations(status)) => Json(*status).into_response(),
+        Ok(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unexpected reply from the node",
+        ),
+        Err(e) => {
+            let (status, e) = node_error(&e);
+            error_response(status, e)
+        }
+    }
+}
+
+async fn reserve(
+    State(gateway): State<Gateway>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<ReservationRequest>,
+) -> Response {
+    let grant = match gateway.authorize(&headers, Grant::may_write) {
+        Ok(grant) => grant,
+        Err(denied) => return denied_response(&denied),
+    };
+    let actor = grant.actor(&format!("http:{peer}"));
+    info!(
+        name = %request.name,
+        resources = %request.resources,
+        ttl_secs = request.ttl_secs,
+        "Reserving capacity over HTTP"
+    );
+    let summary = serde_json::to_value(&request).unwrap_or_default();
+
+    let outcome = match request.validate() {
+        Ok(()) => gateway
+            .messenger
+            .request(NodeEvent::Reserve {
+                request,
+                actor: actor.clone(),
+            })
+            .await
+            .map_err(|e| node_error(&e)),
+        Err(e) => Err((StatusCode::BAD_REQUEST, e)),
+    };
+    audited_reservations(&gateway, actor, "POST /v1/reservations", summary, outcome)
+}
+
+async fn release(
+    State(gateway): State<Gateway>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    let grant = match gateway.authorize(&headers, Grant::may_write) {
+        Ok(grant) => grant,
+        Err(denied) => return denied_response(&denied),
+    };
+    let actor = grant.actor(&format!("http:{peer}"));
+    info!(%name, "Releasing reservation over HTTP");
+    let summary = serde_json::json!({ "name": name });
+
+    let outcome = gateway
+        .messenger
+        .request(NodeEvent::Release {
+            name,
+            actor: actor.clone(),
+        })
+        .await
+        .map_err(|e| node_error(&e));
+    audited_reservations(&gateway, actor, "DELETE /v1/reservations", summary, outcome)
+}
+
+/// Audit a reservation change, answering with the reservations after it.
+fn audited_reservations(
+    gateway: &Gateway,
+    actor: String,
+    method: &str,
+    summary: serde_json::Value,
+    outcome: Result<NodeReply, (StatusCode, String)>,
+) -> Response {
+    let entry = AuditEntry::new(
+        actor,
+        method,
+        summary,
+        outcome.as_ref().map(|_| ()).map_err(|(_, e)| e.clone()),
+    );
+    if let Err(e) = gateway.audit.record(&entry) {
+        error!(error = %e, "Failed to write audit entry");
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("audit log unavailable: {e}"),
+        );
+    }
+
+    match outcome {
+        Ok(NodeReply::Reservations(status)) => Json(*status).into_response(),
+        Ok(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unexpected reply from the node",
+        ),
+        Err((status, e)) => error_response(status, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_hash_matches_the_rpc_and_worker_encoding() {
+        let json = r#"{
+            "commit": "abc123",
+            "generation": 7,
+            "vmSpecs": [{
+                "toplevel": "/nix/store/aaaa-nixos-system",
+                "kernelPath": "/nix/store/bbbb-kernel/bzImage",
+                "initrdPath": "/nix/store/cccc-initrd/initrd",
+                "diskImagePath": "/nix/store/dddd-disk/nixos.raw",
+                "cmdline": "console=ttyS0",
+                "cpu": 2,
+                "memoryMb": 1024,
+                "networkAllowedDomains": ["b.com", "a.com"]
+            }]
+        }"#;
+        let request: PublishRequest = serde_json::from_str(json).unwrap();
+        assert!(request.intent_hash.is_empty());
+
+        let expected = hashing::vm_spec_hash(&VmSpecFields {
+            toplevel: "/nix/store/aaaa-nixos-system",
+            kernel_path: "/nix/store/bbbb-kernel/bzImage",
+            initrd_path: "/nix/store/cccc-initrd/initrd",
+            disk_image_path: "/nix/store/dddd-disk/nixos.raw",
+            cmdline: "console=ttyS0",
+            cpu: 2,
+            memory_mb: 1024,
+            network_allowed_domains: &["a.com", "b.com"],
+            hugepages: false,
+            shared_memory: false,
+            net_backend: NetBackend::Tap,
+        });
+        assert_eq!(request.vm_specs[0].content_hash(), expected);
+    }
+
+    #[test]
+    fn closure_specs_only_name_their_toplevel() {
+        let json = r#"{
+            "toplevel": "/nix/store/aaaa-nixos-system",
+            "cmdline": "",
+            "cpu": 1,
+            "memoryMb": 512
+        }"#;
+        let spec: VmSpecJson = serde_json::from_str(json).unwrap();
+        let desired = spec.desired().unwrap();
+        assert_eq!(desired.store_paths, ["/nix/store/aaaa-nixos-system"]);
+        assert_eq!(desired.namespace, tenancy::DEFAULT_NAMESPACE);
+    }
+
+    #[test]
+    fn generations_flatten_the_known_provenance() {
+        let generation = GenerationResponse::from(GenerationInfo {
+            number: 7,
+            commit: "abc123".to_string(),
+            publisher: "ci".to_string(),
+            intent_hash: "hash".to_string(),
+            published_ms: 1_000,
+            active: true,
+            vms: 2,
+            provenance: Provenance {
+                ci_build_id: "42".to_string(),
+                cache: "https://cache.example".to_string(),
+                ..Provenance::default()
+            },
+        });
+        assert_eq!(
+            serde_json::to_value(generation).unwrap(),
+            serde_json::json!({
+                "generation": 7,
+                "commit": "abc123",
+                "intentHash": "hash",
+                "publisher": "ci",
+                "publishedMs": 1_000,
+                "active": true,
+                "vms": 2,
+                "ciBuildId": "42",
+                "cache": "https://cache.example",
+            })
+        );
+    }
+
+    #[test]
+    fn refusals_tell_unknown_callers_from_forbidden_ones() {
+        let status = |denied: Denied| denied_response(&denied).status();
+        assert_eq!(status(Denied::Missing), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Denied::Unknown), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Denied::ReadOnly), StatusCode::FORBIDDEN);
+        assert_eq!(
+            status(Denied::Scoped("team-a".to_string())),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(Denied::Invalid("bad".to_string())),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn specs_with_invalid_namespaces_are_refused() {
+        let json = r#"{
+            "toplevel": "/nix/store/aaaa-nixos-system",
+            "cmdline": "",
+            "cpu": 1,
+            "memoryMb": 512,
+            "namespace": "Team A"
+        }"#;
+        let spec: VmSpecJson = serde_json::from_str(json).unwrap();
+        assert!(spec.desired().is_err());
+    }
+
+    #[test]
+    fn specs_name_their_network_backend() {
+        let spec = |backend: &str| {
+            serde_json::from_str::<VmSpecJson>(&format!(
+                r#"{{
+                    "toplevel": "/nix/store/aaaa-nixos-system",
+                    "cmdline": "",
+                    "cpu": 1,
+                    "memoryMb": 512,
+                    "netBackend": "{backend}"
+                }}"#
+            ))
+        };
+        assert_eq!(
+            spec("vhost-user").unwrap().desired().unwrap().net_backend,
+            NetBackend::VhostUser
+        );
+        assert!(spec("macvtap").is_err());
+    }
+
+    #[test]
+    fn status_lists_every_check() {
+        let report = Report(vec![
+            commands::health::Check::pass("rpc"),
+            commands::health::Check::fail("audit_log", "read-only"),
+        ]);
+        let status = serde_json::to_value(StatusResponse::from(report)).unwrap();
+        assert_eq!(
+            status,
+            serde_json::json!({
+                "ready": false,
+                "checks": [
+                    {"name": "rpc", "ok": true},
+                    {"name": "audit_log", "ok": false, "error": "read-only"},
+                ],
+            })
+        );
+    }
+}
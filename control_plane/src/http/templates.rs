@@ -0,0 +1,317 @@
+//! Spec templates of `POST /v1/generations`.
+//!
+//! Nearly identical specs, e.g. the same service in staging and production,
+//! are published once as a template and instantiated as often as needed:
+//!
+//! - a **template** is a VM spec whose strings may hold `@name@` variables,
+//!   with the variables it declares and their defaults (`null` when an
+//!   instance must set it);
+//! - an **instance** names a template, sets its variables, and may overlay
+//!   the spec: `cpu`, `memoryMb`, `namespace` and `pinnedWorker` replace the
+//!   template's, `labels` are merged over its labels, and
+//!   `networkAllowedDomains` are added to its domains.
+//!
+//! Each of an instance's `replicas` is one spec, in which `@replica@` is its
+//! index. The overlay is applied before variables are substituted, so it may
+//! use them too. Variables are substituted in every string of the spec:
+//! store paths, `cmdline`, domains, label values, `namespace` and
+//! `pinnedWorker`. `@` followed by anything but a name and `@` is kept as
+//! is, and the `@name@` syntax is that of nixpkgs' `substitute`, so
+//! templates written in Nix need no escaping.
+//!
+//! Templates are expanded on intake, before hashing: the master only keeps
+//! the resulting specs, and the intent hash covers those. Two specs of the
+//! same template that expand to the same spec would be a single VM, so they
+//! are refused.
+
+use std::collections::BTreeMap;
+
+use commands::labels::Labels;
+use serde::Deserialize;
+
+use super::VmSpecJson;
+
+/// Variable holding the index of a replica.
+const REPLICA: &str = "replica";
+
+/// A spec with variables, instantiated by [`Instance`]s.
+#[derive(Debug, Deserialize)]
+pub(super) struct Template {
+    /// Declared variables and their default, `None` when required
+    #[serde(default)]
+    variables: BTreeMap<String, Option<String>>,
+    spec: VmSpecJson,
+}
+
+/// Specs made from a template.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Instance {
+    template: String,
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+    #[serde(default = "one")]
+    replicas: u32,
+    #[serde(default)]
+    overlay: Overlay,
+}
+
+fn one() -> u32 {
+    1
+}
+
+/// What an instance changes in its template's spec.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Overlay {
+    cpu: Option<u32>,
+    memory_mb: Option<u32>,
+    /// Merged over the template's labels
+    #[serde(default)]
+    labels: Labels,
+    /// Added to the template's domains
+    #[serde(default)]
+    network_allowed_domains: Vec<String>,
+    namespace: Option<String>,
+    pinned_worker: Option<String>,
+}
+
+impl Overlay {
+    fn apply(&self, spec: &mut VmSpecJson) {
+        if let Some(cpu) = self.cpu {
+            spec.cpu = cpu;
+        }
+        if let Some(memory_mb) = self.memory_mb {
+            spec.memory_mb = memory_mb;
+        }
+        spec.labels.extend(self.labels.clone());
+        spec.network_allowed_domains
+            .extend(self.network_allowed_domains.iter().cloned());
+        if let Some(namespace) = &self.namespace {
+            spec.namespace.clone_from(namespace);
+        }
+        if let Some(worker) = &self.pinned_worker {
+            spec.pinned_worker = Some(worker.clone());
+        }
+    }
+}
+
+/// The specs of every instance, in order.
+///
+/// # Errors
+///
+/// - if an instance names an unknown template, or sets a variable the
+///   template does not declare or `replica`
+/// - if a spec uses a variable that has no value
+/// - if two replicas of an instance, or two instances of a template, expand
+///   to the same spec
+pub(super) fn expand(
+    templates: &BTreeMap<String, Template>,
+    instances: &[Instance],
+) -> Result<Vec<VmSpecJson>, String> {
+    let mut specs = Vec::new();
+    let mut seen = BTreeMap::new();
+    for (i, instance) in instances.iter().enumerate() {
+        let at = format!("instance {i} of template {:?}", instance.template);
+        let template = templates
+            .get(&instance.template)
+            .ok_or_else(|| format!("{at}: unknown template"))?;
+        if let Some(name) = instance
+            .variables
+            .keys()
+            .find(|name| *name == REPLICA || !template.variables.contains_key(*name))
+        {
+            return Err(format!("{at}: template has no variable {name:?} to set"));
+        }
+        let mut variables: BTreeMap<&str, &str> = template
+            .variables
+            .iter()
+            .filter_map(|(name, default)| Some((name.as_str(), default.as_deref()?)))
+            .collect();
+        variables.extend(
+            instance
+                .variables
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+
+        for replica in 0..instance.replicas {
+            let index = replica.to_string();
+            let mut variables = variables.clone();
+            variables.insert(REPLICA, &index);
+            let mut spec = template.spec.clone();
+            instance.overlay.apply(&mut spec);
+            let spec = substitute_spec(spec, &variables)
+                .map_err(|e| format!("{at}, replica {replica}: {e}"))?;
+            let hash = spec.content_hash().to_string();
+            if let Some(other) = seen.insert(hash, format!("{at}, replica {replica}")) {
+                return Err(format!(
+                    "{at}, replica {replica}: same spec as {other}, use @replica@ or \
+                     variables to tell them apart"
+                ));
+            }
+            specs.push(spec);
+        }
+    }
+    Ok(specs)
+}
+
+fn substitute_spec(
+    mut spec: VmSpecJson,
+    variables: &BTreeMap<&str, &str>,
+) -> Result<VmSpecJson, String> {
+    let fields = [
+        &mut spec.toplevel,
+        &mut spec.kernel_path,
+        &mut spec.initrd_path,
+        &mut spec.disk_image_path,
+        &mut spec.cmdline,
+        &mut spec.namespace,
+    ];
+    for field in fields
+        .into_iter()
+        .chain(&mut spec.network_allowed_domains)
+        .chain(spec.labels.values_mut())
+        .chain(&mut spec.pinned_worker)
+    {
+        *field = substitute(field, variables)?;
+    }
+    Ok(spec)
+}
+
+/// Replace every `@name@` of `input` by the value of variable `name`.
+///
+/// # Errors
+///
+/// - if `input` uses a variable that has no value
+fn substitute(input: &str, variables: &BTreeMap<&str, &str>) -> Result<String, String> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('@') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let name = after.find('@').map(|end| &after[..end]).filter(|name| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        });
+        if let Some(name) = name {
+            let value = variables
+                .get(name)
+                .ok_or_else(|| format!("variable @{name}@ has no value"))?;
+            out.push_str(value);
+            rest = &after[name.len() + 1..];
+        } else {
+            out.push('@');
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates() -> BTreeMap<String, Template> {
+        serde_json::from_str(
+            r#"{
+                "web": {
+                    "variables": {"image": null, "env": "dev"},
+                    "spec": {
+                        "toplevel": "@image@",
+                        "cmdline": "env=@env@ replica=@replica@",
+                        "cpu": 1,
+                        "memoryMb": 512,
+                        "networkAllowedDomains": ["api.@env@.example.com"],
+                        "labels": {"app": "web", "env": "@env@"}
+                    }
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn instances(json: &str) -> Vec<Instance> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn variables_are_substituted_and_kept_otherwise() {
+        let variables = BTreeMap::from([("env", "prod"), ("replica", "0")]);
+        assert_eq!(
+            substitute("user@host env=@env@ @replica@@", &variables).unwrap(),
+            "user@host env=prod 0@"
+        );
+        assert_eq!(substitute("a @b c@", &variables).unwrap(), "a @b c@");
+        assert_eq!(
+            substitute("@image@", &variables).unwrap_err(),
+            "variable @image@ has no value"
+        );
+    }
+
+    #[test]
+    fn instances_expand_per_environment_and_replica() {
+        let specs = expand(
+            &templates(),
+            &instances(
+                r#"[
+                    {"template": "web", "variables": {"image": "/nix/store/a-web"}},
+                    {
+                        "template": "web",
+                        "variables": {"image": "/nix/store/b-web", "env": "prod"},
+                        "replicas": 2,
+                        "overlay": {
+                            "memoryMb": 2048,
+                            "labels": {"tier": "@env@"},
+                            "networkAllowedDomains": ["metrics.example.com"]
+                        }
+                    }
+                ]"#,
+            ),
+        )
+        .unwrap();
+        assert_eq!(specs.len(), 3);
+        assert_eq!(specs[0].cmdline, "env=dev replica=0");
+        assert_eq!(specs[0].memory_mb, 512);
+
+        let prod = &specs[2];
+        assert_eq!(prod.toplevel, "/nix/store/b-web");
+        assert_eq!(prod.cmdline, "env=prod replica=1");
+        assert_eq!(prod.memory_mb, 2048);
+        assert_eq!(
+            prod.network_allowed_domains,
+            ["api.prod.example.com", "metrics.example.com"]
+        );
+        assert_eq!(prod.labels["env"], "prod");
+        assert_eq!(prod.labels["tier"], "prod");
+        assert_ne!(specs[1].content_hash(), prod.content_hash());
+    }
+
+    #[test]
+    fn invalid_instances_are_refused() {
+        let refused = |json: &str| expand(&templates(), &instances(json)).unwrap_err();
+        assert!(refused(r#"[{"template": "db"}]"#).contains("unknown template"));
+        assert!(refused(r#"[{"template": "web"}]"#).contains("@image@ has no value"));
+        assert!(
+            refused(r#"[{"template": "web", "variables": {"image": "x", "imgae": "y"}}]"#)
+                .contains("no variable \"imgae\"")
+        );
+        assert!(
+            refused(r#"[{"template": "web", "variables": {"image": "x", "replica": "1"}}]"#)
+                .contains("no variable \"replica\"")
+        );
+        // Same spec twice: it would be a single VM
+        assert!(
+            refused(
+                r#"[
+                    {"template": "web", "variables": {"image": "x"}},
+                    {"template": "web", "variables": {"image": "x"}}
+                ]"#
+            )
+            .contains("same spec as instance 0")
+        );
+    }
+}
@@ -0,0 +1,296 @@
+//! Desired-state intake: which published generation is active.
+//!
+//! Several publishers (eval servers, CI pipelines) may publish concurrently,
+//! so a publish never silently replaces intent it did not see:
+//!
+//! - Generations only go up. A publish at or below the active generation is
+//!   a conflict, whatever its precondition.
+//! - A publisher may name the generation it built on (`parent`). If another
+//!   generation became active since, the publish is a conflict and the
+//!   publisher has to rebase. Without a parent only the first rule applies.
+//! - Re-sending the active publication (same generation, content and
+//!   publisher) succeeds without change, so a publisher can retry after a
+//!   lost reply.
+//!
+//! Conflicts name the active generation and its publisher.
+//!
+//! The active publication outlives a restart: [`Intake::recover`] takes it
+//! back from the last accepted publish in the [audit log](crate::audit).
+//!
+//! A publication may also say where it came from ([`Provenance`]): the CI
+//! build, the repohub repo and commit, and the cache its images were pushed
+//! to. It is kept with the generation for `pcr history` and `describe`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditEntry;
+use crate::describe::Field;
+
+/// One `publishState` call, after its intent hash was verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Publication {
+    pub publisher: String,
+    pub generation: u64,
+    pub commit: String,
+    /// Hash computed from the published specs
+    pub intent_hash: String,
+    /// Generation the publisher built on, `0` for an empty cluster.
+    /// `None` publishes unconditionally.
+    pub parent: Option<u64>,
+    pub provenance: Provenance,
+}
+
+/// Where a publication came from. Empty fields are unknown.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Provenance {
+    /// CI build that produced the images
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub ci_build_id: String,
+    /// Repohub repo the commit belongs to
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub repo_url: String,
+    /// Repohub page of the published commit
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub commit_url: String,
+    /// Binary cache the images were pushed to
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub cache: String,
+}
+
+impl Provenance {
+    /// The known fields, as shown by `describe generation`.
+    #[must_use]
+    pub fn fields(&self) -> Vec<Field> {
+        [
+            ("ci build", &self.ci_build_id),
+            ("repo", &self.repo_url),
+            ("commit url", &self.commit_url),
+            ("cache", &self.cache),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| Field::new(name, value.as_str(), ""))
+        .collect()
+    }
+}
+
+/// Why a publication was refused.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Conflict {
+    /// `active` was published since the publisher's `parent`
+    #[error(
+        "conflict: built on generation {parent} but generation {active} \
+         (published by {publisher}) is active, rebase and publish again"
+    )]
+    StaleParent {
+        parent: u64,
+        active: u64,
+        publisher: String,
+    },
+    /// `generation` is not above the `active` one
+    #[error(
+        "conflict: generation {generation} is not newer than active generation \
+         {active} (published by {publisher})"
+    )]
+    NotNewer {
+        generation: u64,
+        active: u64,
+        publisher: String,
+    },
+}
+
+/// Result of an accepted publication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accepted {
+    /// The publication is now the active generation
+    Published,
+    /// It already was: a retry
+    Unchanged,
+}
+
+/// Audit summary of a publish, as written by `publish_summary`.
+#[derive(Deserialize)]
+struct Summary {
+    publisher: String,
+    generation: u64,
+    #[serde(default)]
+    commit: String,
+    computed_intent_hash: String,
+    #[serde(default)]
+    parent_generation: Option<u64>,
+    #[serde(default)]
+    provenance: Provenance,
+}
+
+#[derive(Debug, Default)]
+pub struct Intake {
+    active: Option<Publication>,
+}
+
+impl Intake {
+    /// The intake as the last accepted `publishState` in `entries` left it,
+    /// oldest entry first. Empty when none was accepted.
+    #[must_use]
+    pub fn recover(entries: &[AuditEntry]) -> Self {
+        let active = entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.ok && entry.method == "Master.publishState")
+            .find_map(|entry| {
+                serde_json::from_value::<Summary>(entry.params.clone())
+                    .inspect_err(|e| {
+                        tracing::warn!(error = %e, "Skipping unreadable publish in audit log");
+                    })
+                    .ok()
+            })
+            .map(|summary| Publication {
+                publisher: summary.publisher,
+                generation: summary.generation,
+                commit: summary.commit,
+                intent_hash: summary.computed_intent_hash,
+                parent: summary.parent_generation,
+                provenance: summary.provenance,
+            });
+        Self { active }
+    }
+
+    /// The generation currently active, `0` for an empty cluster.
+    #[must_use]
+    pub fn generation(&self) -> u64 {
+        self.active.as_ref().map_or(0, |a| a.generation)
+    }
+
+    /// Accept `publication` as the active generation, see the module docs.
+    ///
+    /// # Errors
+    ///
+    /// [`Conflict`] when it would replace intent the publisher did not see.
+    pub fn publish(&mut self, publication: &Publication) -> Result<Accepted, Conflict> {
+        let (active, by) = self
+            .active
+            .as_ref()
+            .map_or((0, "nobody"), |a| (a.generation, a.publisher.as_str()));
+
+        if let Some(current) = &self.active
+            && current.generation == publication.generation
+            && current.intent_hash == publication.intent_hash
+            && current.publisher == publication.publisher
+        {
+            return Ok(Accepted::Unchanged);
+        }
+        if let Some(parent) = publication.parent
+            && parent != active
+        {
+            return Err(Conflict::StaleParent {
+                parent,
+                active,
+                publisher: by.to_string(),
+            });
+        }
+        if publication.generation <= active {
+            return Err(Conflict::NotNewer {
+                generation: publication.generation,
+                active,
+                publisher: by.to_string(),
+            });
+        }
+
+        self.active = Some(publication.clone());
+        Ok(Accepted::Published)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn publication(publisher: &str, generation: u64, parent: Option<u64>) -> Publication {
+        Publication {
+            publisher: publisher.to_string(),
+            generation,
+            commit: format!("commit-{generation}"),
+            intent_hash: format!("hash-{publisher}-{generation}"),
+            parent,
+            provenance: Provenance::default(),
+        }
+    }
+
+    #[test]
+    fn concurrent_publishers_cannot_overwrite_each_other() {
+        let mut intake = Intake::default();
+        let ci = publication("ci", 1, Some(0));
+        assert_eq!(intake.publish(&ci), Ok(Accepted::Published));
+
+        // Both built on generation 1; the second one has to rebase
+        let eval = publication("eval", 2, Some(1));
+        assert_eq!(intake.publish(&eval), Ok(Accepted::Published));
+        let err = intake.publish(&publication("ci", 2, Some(1))).unwrap_err();
+        assert_eq!(
+            err,
+            Conflict::StaleParent {
+                parent: 1,
+                active: 2,
+                publisher: "eval".to_string(),
+            }
+        );
+        assert!(err.to_string().starts_with("conflict: "));
+
+        // Unconditional publishers still cannot go back or reuse a generation
+        let err = intake.publish(&publication("ci", 2, None)).unwrap_err();
+        assert!(matches!(err, Conflict::NotNewer { active: 2, .. }));
+        assert_eq!(
+            intake.publish(&publication("ci", 3, None)),
+            Ok(Accepted::Published)
+        );
+    }
+
+    #[test]
+    fn a_retried_publication_is_unchanged() {
+        let mut intake = Intake::default();
+        let first = publication("ci", 1, Some(0));
+        assert_eq!(intake.publish(&first), Ok(Accepted::Published));
+        // The parent no longer matches, but this is the active publication
+        assert_eq!(intake.publish(&first), Ok(Accepted::Unchanged));
+
+        let err = intake
+            .publish(&publication("eval", 1, Some(5)))
+            .unwrap_err();
+        assert!(matches!(err, Conflict::StaleParent { parent: 5, .. }));
+    }
+
+    #[test]
+    fn the_active_publication_is_recovered_from_the_audit_log() {
+        let target = crate::convergence::Target {
+            vms: Vec::new(),
+            deadline: None,
+            emergency: false,
+        };
+        let entry = |publication: &Publication, ok: bool| {
+            let summary = crate::server::publish_summary(publication, None, &target);
+            let outcome = if ok {
+                Ok(())
+            } else {
+                Err("conflict".to_string())
+            };
+            AuditEntry::new("ci@peer", "Master.publishState", summary, outcome)
+        };
+        let accepted = publication("eval", 2, Some(1));
+        let entries = [
+            entry(&publication("ci", 1, Some(0)), true),
+            entry(&accepted, true),
+            entry(&publication("ci", 2, Some(1)), false),
+            AuditEntry::new("ops@peer", "Master.pinVm", serde_json::json!({}), Ok(())),
+        ];
+
+        let mut intake = Intake::recover(&entries);
+        assert_eq!(intake.generation(), 2);
+        assert_eq!(intake.active.as_ref(), Some(&accepted));
+        // A publisher that did not see the restart still has to rebase
+        let err = intake.publish(&publication("ci", 2, Some(1))).unwrap_err();
+        assert!(matches!(err, Conflict::StaleParent { active: 2, .. }));
+        assert_eq!(intake.publish(&accepted), Ok(Accepted::Unchanged));
+
+        assert_eq!(Intake::recover(&[]).generation(), 0);
+    }
+}
@@ -1,28 +1,176 @@
 use std::net::SocketAddr;
+use std::time::Duration;
 
+use commands::lifecycle::{self, NotifyState};
 use tokio::{sync::mpsc::channel, task};
+use tokio_util::sync::CancellationToken;
 
-use crate::{node::Node, server::Server};
+use crate::{audit::AuditLog, health::MasterHealth, intake::Intake, node::Node, server::Server};
 
+pub use audit::AuditConfig;
+pub use node::ReconcileConfig;
+pub use tenancy::Tokens;
+pub use webhooks::Webhooks;
+
+mod audit;
+mod convergence;
+mod describe;
 mod dto;
+mod health;
+mod http;
+mod intake;
+mod maintenance;
+mod metrics_history;
 mod node;
+mod reservations;
 mod scheduler;
 mod server;
+mod tenancy;
+mod webhooks;
+
+/// Everything [`run`] needs besides the shutdown token.
+#[derive(Debug, Clone)]
+pub struct MasterConfig {
+    pub hostname: String,
+    /// Cap'n Proto API.
+    pub addr: SocketAddr,
+    pub peers_addr: Vec<SocketAddr>,
+    /// How long the node gets to finish the messages it already received.
+    pub shutdown_timeout: Duration,
+    pub audit: AuditConfig,
+    /// `/healthz` and `/readyz`; not served when `None`.
+    pub health_addr: Option<SocketAddr>,
+    /// HTTP/JSON gateway; not served when `None`.
+    pub http_addr: Option<SocketAddr>,
+    pub tokens: Tokens,
+    pub webhooks: Webhooks,
+    pub reconcile: ReconcileConfig,
+}
+
+impl MasterConfig {
+    /// Only the Cap'n Proto API, no tokens or webhooks, a 30 second
+    /// shutdown timeout and the default reconciliation.
+    pub fn new(hostname: impl Into<String>, addr: SocketAddr, audit: AuditConfig) -> Self {
+        Self {
+            hostname: hostname.into(),
+            addr,
+            peers_addr: Vec::new(),
+            shutdown_timeout: Duration::from_secs(30),
+            audit,
+            health_addr: None,
+            http_addr: None,
+            tokens: Tokens::default(),
+            webhooks: Webhooks::default(),
+            reconcile: ReconcileConfig::default(),
+        }
+    }
+}
+
+/// Run the control plane until SIGTERM/SIGINT. See [`run`].
+pub async fn main(config: MasterConfig) {
+    let shutdown = CancellationToken::new();
+    task::spawn(cancel_on_signal(shutdown.clone()));
+    run(config, shutdown).await;
+}
+
+/// Run the control plane until `shutdown` is cancelled.
+///
+/// On shutdown the server stops accepting RPCs, open connections are dropped
+/// (closing the node channel), and the node gets `shutdown_timeout` to finish
+/// the messages it already received.
+///
+/// Exits early if the audit log cannot be opened: mutating calls are not
+/// served unaudited. The active generation is recovered from it, so it
+/// must be readable too.
+///
+/// With `health_addr` set, `/healthz` and `/readyz` are served there; the
+/// systemd watchdog is pinged whenever the unit sets `WatchdogSec=`.
+///
+/// With `http_addr` set, the HTTP/JSON gateway is served there, next to
+/// the Cap'n Proto API on `addr`.
+///
+/// With `tokens` configured, the cluster status and the gateway are scoped
+/// by the [token](tenancy) each caller presents.
+///
+/// Cluster events are posted to the configured [`webhooks`](webhooks).
+///
+/// Held generations, convergence deadlines and reservations are
+/// reconciled as often as `reconcile` says.
+pub async fn run(config: MasterConfig, shutdown: CancellationToken) {
+    let MasterConfig {
+        hostname: _,
+        addr,
+        peers_addr,
+        shutdown_timeout,
+        audit,
+        health_addr,
+        http_addr,
+        tokens,
+        webhooks,
+        reconcile,
+    } = config;
+    let audit_path = audit.path.clone();
+    let audit = match AuditLog::open(audit) {
+        Ok(audit) => audit,
+        Err(e) => {
+            tracing::error!(path = %audit_path.display(), error = %e, "Cannot open audit log");
+            return;
+        }
+    };
 
-pub async fn main(_hostname: String, addr: SocketAddr, peers_addr: Vec<SocketAddr>) {
     let (tx, rx) = channel(100);
 
-    let node = Node::new(rx, peers_addr);
-    let server = Server::new(tx);
+    // Publishers keep building on the generation active before a restart
+    let intake = match audit.query(0, 0) {
+        Ok(entries) => Intake::recover(&entries),
+        Err(e) => {
+            tracing::error!(path = %audit_path.display(), error = %e, "Cannot read audit log");
+            return;
+        }
+    };
+    tracing::info!(
+        generation = intake.generation(),
+        "Recovered active generation"
+    );
+    let node = Node::new(rx, peers_addr, intake, webhooks, reconcile);
+    let server = Server::new(tx.clone(), audit.clone()).with_tokens(tokens.clone());
+    let probe = MasterHealth::new(tx.clone(), server.listening(), audit.clone());
 
     tracing::info!(?addr, "Starting control plane server",);
 
     let node_task = task::spawn(node.run());
 
-    task::LocalSet::new()
+    if let Some(health_addr) = health_addr {
+        let stop = shutdown.clone().cancelled_owned();
+        let probe = probe.clone();
+        task::spawn(async move {
+            if let Err(e) = commands::health::serve(health_addr, probe, stop).await {
+                tracing::error!(%health_addr, error = %e, "Health listener failed");
+            }
+        });
+    }
+    if let Some(http_addr) = http_addr {
+        let gateway = http::Gateway::new(tx.clone(), audit, probe.clone()).with_tokens(tokens);
+        let stop = shutdown.clone();
+        task::spawn(async move {
+            if let Err(e) = http::serve(http_addr, gateway, stop).await {
+                tracing::error!(%http_addr, error = %e, "HTTP gateway failed");
+            }
+        });
+    }
+    task::spawn(commands::health::watchdog(
+        probe,
+        shutdown.clone().cancelled_owned(),
+    ));
+    // Only the server, gateway and probes hold senders from here on, so
+    // the node stops once they are gone.
+    drop(tx);
+
+    let local_set = task::LocalSet::new();
+    local_set
         .run_until(async move {
             tracing::info!("Internal localset server");
-            let resutl = task::spawn_local(server.serve(addr)).await;
+            let resutl = task::spawn_local(server.serve(addr, shutdown)).await;
             match resutl {
                 Ok(Ok(())) => tracing::info!("Control plane server stopped gracefully"),
                 Ok(Err(err)) => tracing::error!(?err, "Error starting control plane server"),
@@ -30,8 +178,61 @@ pub async fn main(_hostname: String, addr: SocketAddr, peers_addr: Vec<SocketAdd
             }
         })
         .await;
+    // Dropping the LocalSet drops every connection and with it the last
+    // node channel senders, which ends `Node::run`.
+    drop(local_set);
+
+    if let Err(e) = lifecycle::notify(&[NotifyState::Stopping]) {
+        tracing::warn!(error = %e, "sd_notify STOPPING failed");
+    }
+
+    match tokio::time::timeout(shutdown_timeout, node_task).await {
+        Ok(Ok(())) => tracing::info!("Control plane shut down cleanly"),
+        Ok(Err(err)) => tracing::error!(?err, "Node task panicked"),
+        Err(_) => tracing::warn!(timeout = ?shutdown_timeout, "Node did not stop in time"),
+    }
+}
+
+/// Cancel `token` on SIGTERM/SIGINT.
+async fn cancel_on_signal(token: CancellationToken) {
+    match lifecycle::shutdown_signal().await {
+        Ok(signal) => tracing::info!(signal, "Received shutdown signal"),
+        Err(e) => {
+            tracing::error!(error = %e, "Cannot install signal handlers");
+            return;
+        }
+    }
+    token.cancel();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stops_without_waiting_for_the_timeout_when_no_gateway_runs() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("master-stop-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = MasterConfig::new("test", addr, AuditConfig::new(dir.join("audit.jsonl")));
+        config.shutdown_timeout = Duration::from_secs(30);
+        assert!(config.http_addr.is_none());
+
+        let shutdown = CancellationToken::new();
+        let stop = shutdown.clone();
+        task::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            stop.cancel();
+        });
+        tokio::time::timeout(Duration::from_secs(10), run(config, shutdown))
+            .await
+            .expect("the node stops once the server is gone");
 
-    if let Err(err) = node_task.await {
-        tracing::error!(?err, "Node task panicked");
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
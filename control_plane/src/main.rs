@@ -1,7 +1,15 @@
+use commands::telemetry::{self, TelemetryConfig};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() {
+    // Spans are exported when OTEL_EXPORTER_OTLP_ENDPOINT is set.
+    let (otel, otel_guard) = telemetry::otel_layer(&TelemetryConfig::from_env("procurator-master"))
+        .unwrap_or_else(|e| {
+            eprintln!("invalid OTLP exporter configuration: {e}");
+            std::process::exit(1);
+        });
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::fmt::layer()
@@ -12,12 +20,67 @@ async fn main() {
                 .flatten_event(true)
                 .with_span_list(false),
         )
+        .with(otel)
         .init();
 
-    control_plane::main(
-        "hostname".into(),
+    // Without a token file nothing is authenticated.
+    let tokens = match std::env::var_os("PROCURATOR_TOKENS_FILE") {
+        Some(path) => {
+            control_plane::Tokens::load(std::path::Path::new(&path)).unwrap_or_else(|e| {
+                eprintln!("invalid token file: {e}");
+                std::process::exit(1);
+            })
+        }
+        None => control_plane::Tokens::default(),
+    };
+    // Without a webhook file no event is sent.
+    let webhooks = match std::env::var_os("PROCURATOR_WEBHOOKS_FILE") {
+        Some(path) => {
+            control_plane::Webhooks::load(std::path::Path::new(&path)).unwrap_or_else(|e| {
+                eprintln!("invalid webhook file: {e}");
+                std::process::exit(1);
+            })
+        }
+        None => control_plane::Webhooks::default(),
+    };
+
+    // Reconcile every 5 seconds without jitter unless set.
+    let mut reconcile = control_plane::ReconcileConfig::default();
+    if let Some(secs) = secs_from_env("PROCURATOR_RECONCILE_INTERVAL_SECS") {
+        reconcile.interval = secs;
+    }
+    if let Some(secs) = secs_from_env("PROCURATOR_RECONCILE_JITTER_SECS") {
+        reconcile.jitter = secs;
+    }
+    if reconcile.interval.is_zero() {
+        eprintln!("PROCURATOR_RECONCILE_INTERVAL_SECS must be at least 1");
+        std::process::exit(1);
+    }
+
+    let mut config = control_plane::MasterConfig::new(
+        "hostname",
         "127.0.0.1:5000".parse().expect("addr shold be valid"),
-        vec![],
-    )
-    .await;
+        control_plane::AuditConfig::new("audit.jsonl"),
+    );
+    config.health_addr = Some("127.0.0.1:5001".parse().expect("addr shold be valid"));
+    config.http_addr = Some("127.0.0.1:5002".parse().expect("addr shold be valid"));
+    config.tokens = tokens;
+    config.webhooks = webhooks;
+    config.reconcile = reconcile;
+
+    control_plane::main(config).await;
+    otel_guard.shutdown();
+}
+
+/// Seconds in the environment variable `name`, exiting when they are not a
+/// number.
+fn secs_from_env(name: &str) -> Option<std::time::Duration> {
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(secs) => Some(std::time::Duration::from_secs(secs)),
+        Err(e) => {
+            eprintln!("invalid {name} {value:?}: {e}");
+            std::process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,628 @@
+//! Maintenance windows and change freezes.
+//!
+//! A published generation is applied, i.e. becomes the one VMs converge to,
+//! as soon as it is accepted, unless a policy holds it:
+//!
+//! - a **window** is a weekly slot in UTC, e.g. Saturdays from 02:00 for four
+//!   hours. Once VMs are covered by windows, changes to them are applied only
+//!   while one of those windows is open;
+//! - a **freeze** is a one-off period during which changes to the VMs it
+//!   covers are not applied at all.
+//!
+//! Policies cover the VMs matching their label selector, every VM when it is
+//! empty. A generation changes the VMs whose spec hash it adds or drops
+//! compared with the applied generation, and is held while any of them is
+//! frozen or outside its windows.
+//!
+//! A held generation is pending: it is accepted, so the next publish builds
+//! on it, but the applied generation stays. A newer publish replaces it. The
+//! node applies it on the first check where nothing holds it anymore, that is
+//! when the window opens or the freeze ends. Emergency publishes are applied
+//! right away. Policies are set over `PUT /v1/maintenance` and, like the rest
+//! of the master state, kept in memory.
+
+use std::fmt;
+
+use commands::labels::{Labels, ParseError, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::convergence::{DesiredVm, Target};
+use crate::describe::{Condition, Description, Event, Field, Kind};
+use crate::intake::Publication;
+
+const MS_PER_MINUTE: u64 = 60_000;
+const MINUTES_PER_DAY: u64 = 24 * 60;
+const MINUTES_PER_WEEK: u64 = 7 * MINUTES_PER_DAY;
+/// The Unix epoch was a Thursday.
+const EPOCH_WEEKDAY: u64 = Weekday::Thu as u64;
+
+// ─── Policies ──────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+const WEEK: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// Time of day in UTC, `HH:MM` in JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay {
+    minutes: u16,
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let parsed = s
+            .split_once(':')
+            .and_then(|(h, m)| Some((h.parse::<u16>().ok()?, m.parse::<u16>().ok()?)));
+        match parsed {
+            Some((h, m)) if h < 24 && m < 60 => Ok(Self {
+                minutes: h * 60 + m,
+            }),
+            _ => Err(format!("invalid time {s:?}, expected HH:MM")),
+        }
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        time.to_string()
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+/// VMs a policy covers, a label selector in JSON; empty for every VM.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Scope(Selector);
+
+impl Scope {
+    pub fn covers(&self, labels: &Labels) -> bool {
+        self.0.matches(labels)
+    }
+}
+
+impl TryFrom<String> for Scope {
+    type Error = ParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse().map(Self)
+    }
+}
+
+impl From<Scope> for String {
+    fn from(scope: Scope) -> Self {
+        scope.0.to_string()
+    }
+}
+
+/// Weekly period during which changes to the VMs in scope may be applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Window {
+    pub name: String,
+    #[serde(default)]
+    pub selector: Scope,
+    /// Days it opens on, every day when empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub start: TimeOfDay,
+    pub duration_mins: u32,
+}
+
+impl Window {
+    /// Minutes since Monday 00:00 UTC of each opening.
+    fn openings(&self) -> impl Iterator<Item = u64> + '_ {
+        let days = if self.days.is_empty() {
+            &WEEK[..]
+        } else {
+            &self.days[..]
+        };
+        days.iter()
+            .map(|day| *day as u64 * MINUTES_PER_DAY + u64::from(self.start.minutes))
+    }
+
+    fn is_open(&self, now_ms: u64) -> bool {
+        let now = minute_of_week(now_ms);
+        self.openings().any(|opening| {
+            (now + MINUTES_PER_WEEK - opening) % MINUTES_PER_WEEK < u64::from(self.duration_mins)
+        })
+    }
+
+    /// Next opening at or after `now_ms`.
+    fn opens_ms(&self, now_ms: u64) -> u64 {
+        let now = minute_of_week(now_ms);
+        let wait = self
+            .openings()
+            .map(|opening| (opening + MINUTES_PER_WEEK - now) % MINUTES_PER_WEEK)
+            .min()
+            .unwrap_or_default();
+        (now_ms / MS_PER_MINUTE + wait) * MS_PER_MINUTE
+    }
+}
+
+/// One-off period during which changes to the VMs in scope are not applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Freeze {
+    pub name: String,
+    #[serde(default)]
+    pub selector: Scope,
+    pub from_ms: u64,
+    pub until_ms: u64,
+}
+
+impl Freeze {
+    fn is_ongoing(&self, now_ms: u64) -> bool {
+        (self.from_ms..self.until_ms).contains(&now_ms)
+    }
+}
+
+/// Windows and freezes of the cluster, the body of `PUT /v1/maintenance`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Policies {
+    #[serde(default)]
+    pub windows: Vec<Window>,
+    #[serde(default)]
+    pub freezes: Vec<Freeze>,
+}
+
+impl Policies {
+    /// # Errors
+    ///
+    /// - on a window that never opens
+    /// - on a freeze that ends before it starts
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(window) = self.windows.iter().find(|w| w.duration_mins == 0) {
+            return Err(format!("window {:?} has no duration", window.name));
+        }
+        if let Some(freeze) = self.freezes.iter().find(|f| f.until_ms <= f.from_ms) {
+            return Err(format!("freeze {:?} ends before it starts", freeze.name));
+        }
+        Ok(())
+    }
+
+    /// What holds a change to VMs labelled `touched` at `now_ms`, `None`
+    /// when it can be applied. Freezes come first.
+    #[must_use]
+    pub fn hold(&self, touched: &[&Labels], now_ms: u64) -> Option<Hold> {
+        let frozen = touched.iter().find_map(|labels| {
+            self.freezes
+                .iter()
+                .find(|f| f.is_ongoing(now_ms) && f.selector.covers(labels))
+        });
+        if let Some(freeze) = frozen {
+            return Some(Hold {
+                reason: HoldReason::Freeze,
+                policy: freeze.name.clone(),
+                until_ms: freeze.until_ms,
+            });
+        }
+
+        touched.iter().find_map(|labels| {
+            let windows: Vec<&Window> = self
+                .windows
+                .iter()
+                .filter(|w| w.selector.covers(labels))
+                .collect();
+            if windows.iter().any(|w| w.is_open(now_ms)) {
+                return None;
+            }
+            let next = windows.iter().min_by_key(|w| w.opens_ms(now_ms))?;
+            Some(Hold {
+                reason: HoldReason::Window,
+                policy: next.name.clone(),
+                until_ms: next.opens_ms(now_ms),
+            })
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum HoldReason {
+    Freeze,
+    #[serde(rename = "OutsideWindow")]
+    Window,
+}
+
+impl HoldReason {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HoldReason::Freeze => "Freeze",
+            HoldReason::Window => "OutsideWindow",
+        }
+    }
+}
+
+/// Why a generation is not applied yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hold {
+    pub reason: HoldReason,
+    /// Name of the freeze or window
+    pub policy: String,
+    /// When the freeze ends or the window opens
+    pub until_ms: u64,
+}
+
+impl fmt::Display for Hold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reason {
+            HoldReason::Freeze => {
+                write!(f, "freeze {} lasts until {}", self.policy, self.until_ms)
+            }
+            HoldReason::Window => write!(
+                f,
+                "outside maintenance window {}, which opens at {}",
+                self.policy, self.until_ms
+            ),
+        }
+    }
+}
+
+/// Labels of the VMs `next` adds or drops compared with `applied`.
+fn touched<'a>(applied: &'a [DesiredVm], next: &'a [DesiredVm]) -> Vec<&'a Labels> {
+    let added = next
+        .iter()
+        .filter(|vm| !applied.iter().any(|a| a.hash == vm.hash));
+    let dropped = applied
+        .iter()
+        .filter(|vm| !next.iter().any(|n| n.hash == vm.hash));
+    added.chain(dropped).map(|vm| &vm.labels).collect()
+}
+
+fn minute_of_week(now_ms: u64) -> u64 {
+    (now_ms / MS_PER_MINUTE + EPOCH_WEEKDAY * MINUTES_PER_DAY) % MINUTES_PER_WEEK
+}
+
+// ─── Pending generation ────────────────────────────────────────────────────
+
+#[derive(Debug)]
+struct Pending {
+    publication: Publication,
+    target: Target,
+    queued_ms: u64,
+    hold: Hold,
+}
+
+/// Summary of the pending generation in `GET /v1/maintenance`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingStatus {
+    pub generation: u64,
+    pub commit: String,
+    pub publisher: String,
+    pub queued_ms: u64,
+    pub held_by: Hold,
+}
+
+/// Body of `GET /v1/maintenance`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Status {
+    #[serde(flatten)]
+    pub policies: Policies,
+    pub pending: Option<PendingStatus>,
+}
+
+/// Policies and the generation they hold back.
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    policies: Policies,
+    pending: Option<Pending>,
+}
+
+impl Maintenance {
+    pub fn set_policies(&mut self, policies: Policies) {
+        self.policies = policies;
+    }
+
+    /// What holds `target` from replacing `applied` at `now_ms`, emergency
+    /// or not.
+    #[must_use]
+    pub fn hold(&self, applied: &[DesiredVm], target: &Target, now_ms: u64) -> Option<Hold> {
+        self.policies.hold(&touched(applied, &target.vms), now_ms)
+    }
+
+    /// Keep a held publication until nothing holds it. Returns the
+    /// generation it replaces, if one was pending.
+    pub fn queue(
+        &mut self,
+        publication: &Publication,
+        target: Target,
+        hold: Hold,
+        now_ms: u64,
+    ) -> Option<u64> {
+        self.pending
+            .replace(Pending {
+                publication: publication.clone(),
+                target,
+                queued_ms: now_ms,
+                hold,
+            })
+            .map(|p| p.publication.generation)
+    }
+
+    /// Forget the pending generation, superseded by an applied one.
+    pub fn discard(&mut self) -> Option<u64> {
+        self.pending.take().map(|p| p.publication.generation)
+    }
+
+    /// Take the pending generation once nothing holds it over `applied`;
+    /// otherwise refresh what holds it.
+    pub fn release(&mut self, applied: &[DesiredVm], now_ms: u64) -> Option<(Publication, Target)> {
+        let pending = self.pending.as_ref()?;
+        match self.hold(applied, &pending.target, now_ms) {
+            Some(hold) => {
+                if let Some(pending) = &mut self.pending {
+                    pending.hold = hold;
+                }
+                None
+            }
+            None => self.pending.take().map(|p| (p.publication, p.target)),
+        }
+    }
+
+    #[must_use]
+    pub fn status(&self) -> Status {
+        Status {
+            policies: self.policies.clone(),
+            pending: self.pending.as_ref().map(|p| PendingStatus {
+                generation: p.publication.generation,
+                commit: p.publication.commit.clone(),
+                publisher: p.publication.publisher.clone(),
+                queued_ms: p.queued_ms,
+                held_by: p.hold.clone(),
+            }),
+        }
+    }
+
+    /// Describe generation `number` if it is the pending one.
+    #[must_use]
+    pub fn describe_pending(&self, number: u64) -> Option<Description> {
+        let pending = self
+            .pending
+            .as_ref()
+            .filter(|p| p.publication.generation == number)?;
+        let publication = &pending.publication;
+        let mut fields = vec![
+            Field::new("commit", publication.commit.as_str(), ""),
+            Field::new("publisher", publication.publisher.as_str(), ""),
+            Field::new("intent hash", publication.intent_hash.as_str(), ""),
+        ];
+        fields.extend(publication.provenance.fields());
+        fields.push(Field::new("vms", pending.target.vms.len().to_string(), ""));
+        Some(Description {
+            kind: Kind::Generation,
+            id: number.to_string(),
+            placement: String::new(),
+            fields,
+            conditions: vec![Condition::new(
+                "Active",
+                false,
+                "Pending",
+                pending.hold.to_string(),
+            )],
+            metrics: Vec::new(),
+            events: vec![Event {
+                timestamp_ms: pending.queued_ms,
+                object: format!("generation {number}"),
+                reason: pending.hold.reason.as_str().to_string(),
+                message: format!(
+                    "published by {} from commit {}, queued: {}",
+                    publication.publisher, publication.commit, pending.hold
+                ),
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intake::Provenance;
+
+    /// Saturday 2024-01-06 00:00 UTC.
+    const SATURDAY_MS: u64 = 1_704_499_200_000;
+    const HOUR_MS: u64 = 60 * MS_PER_MINUTE;
+
+    fn vm(hash: &str, labels: &[(&str, &str)]) -> DesiredVm {
+        DesiredVm {
+            hash: hash.to_string(),
+            store_paths: Vec::new(),
+            labels: labels
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect(),
+            pinned: None,
+            namespace: crate::tenancy::DEFAULT_NAMESPACE.to_string(),
+            net_backend: commands::net_backend::NetBackend::Tap,
+            resources: crate::scheduler::Resources::default(),
+        }
+    }
+
+    fn target(vms: Vec<DesiredVm>) -> Target {
+        Target {
+            vms,
+            ..Target::default()
+        }
+    }
+
+    fn publication(generation: u64) -> Publication {
+        Publication {
+            publisher: "ci".to_string(),
+            generation,
+            commit: format!("commit-{generation}"),
+            intent_hash: format!("hash-{generation}"),
+            parent: None,
+            provenance: Provenance::default(),
+        }
+    }
+
+    fn policies(json: serde_json::Value) -> Policies {
+        let policies: Policies = serde_json::from_value(json).unwrap();
+        policies.validate().unwrap();
+        policies
+    }
+
+    #[test]
+    fn weekly_windows_open_and_close() {
+        let policies = policies(serde_json::json!({
+            "windows": [{
+                "name": "weekend",
+                "selector": "env=prod",
+                "days": ["sat", "sun"],
+                "start": "22:00",
+                "durationMins": 240,
+            }],
+        }));
+        let prod = [("env", "prod")]
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect();
+        let dev = Labels::new();
+
+        let friday_noon = SATURDAY_MS - 12 * HOUR_MS;
+        let hold = policies.hold(&[&prod], friday_noon).unwrap();
+        assert_eq!(hold.reason, HoldReason::Window);
+        assert_eq!(hold.until_ms, SATURDAY_MS + 22 * HOUR_MS);
+        assert!(policies.hold(&[&dev], friday_noon).is_none());
+
+        // Saturday's window runs past midnight into Sunday
+        assert!(
+            policies
+                .hold(&[&prod], SATURDAY_MS + 23 * HOUR_MS)
+                .is_none()
+        );
+        assert!(
+            policies
+                .hold(&[&prod], SATURDAY_MS + 25 * HOUR_MS)
+                .is_none()
+        );
+        let sunday_noon = SATURDAY_MS + 36 * HOUR_MS;
+        assert_eq!(
+            policies.hold(&[&prod], sunday_noon).unwrap().until_ms,
+            SATURDAY_MS + 46 * HOUR_MS
+        );
+        // Sunday's window runs into Monday, then the next one is a week away
+        let monday_noon = SATURDAY_MS + 60 * HOUR_MS;
+        assert_eq!(
+            policies.hold(&[&prod], monday_noon).unwrap().until_ms,
+            SATURDAY_MS + 7 * 24 * HOUR_MS + 22 * HOUR_MS
+        );
+    }
+
+    #[test]
+    fn freezes_hold_before_windows() {
+        let policies = policies(serde_json::json!({
+            "windows": [{"name": "nightly", "start": "23:00", "durationMins": 120}],
+            "freezes": [{"name": "release", "fromMs": SATURDAY_MS, "untilMs": SATURDAY_MS + HOUR_MS}],
+        }));
+        let labels = Labels::new();
+        let hold = policies.hold(&[&labels], SATURDAY_MS).unwrap();
+        assert_eq!(
+            (hold.reason, hold.until_ms),
+            (HoldReason::Freeze, SATURDAY_MS + HOUR_MS)
+        );
+        assert!(policies.hold(&[&labels], SATURDAY_MS - 1).is_none());
+        assert_eq!(
+            policies
+                .hold(&[&labels], SATURDAY_MS + HOUR_MS)
+                .unwrap()
+                .reason,
+            HoldReason::Window
+        );
+        // Nothing changes, nothing to hold
+        assert!(policies.hold(&[], SATURDAY_MS).is_none());
+
+        for invalid in [
+            serde_json::json!({"windows": [{"name": "w", "start": "24:00", "durationMins": 60}]}),
+            serde_json::json!({"windows": [{"name": "w", "start": "01:00", "durationMins": 60, "selector": "=x"}]}),
+        ] {
+            assert!(serde_json::from_value::<Policies>(invalid).is_err());
+        }
+        let never =
+            serde_json::json!({"windows": [{"name": "w", "start": "01:00", "durationMins": 0}]});
+        assert!(
+            serde_json::from_value::<Policies>(never)
+                .unwrap()
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn pending_generation_waits_for_its_window() {
+        let mut maintenance = Maintenance::default();
+        maintenance.set_policies(policies(serde_json::json!({
+            "windows": [{"name": "db", "selector": "role=db", "start": "02:00", "durationMins": 60}],
+        })));
+        let applied = vec![
+            vm("web-1", &[("role", "web")]),
+            vm("db-1", &[("role", "db")]),
+        ];
+
+        // Only the web VM changes: applied right away
+        let web_only = target(vec![
+            vm("web-2", &[("role", "web")]),
+            vm("db-1", &[("role", "db")]),
+        ]);
+        assert!(maintenance.hold(&applied, &web_only, SATURDAY_MS).is_none());
+
+        let db = target(vec![
+            vm("web-1", &[("role", "web")]),
+            vm("db-2", &[("role", "db")]),
+        ]);
+        let hold = maintenance.hold(&applied, &db, SATURDAY_MS).unwrap();
+        assert_eq!(hold.until_ms, SATURDAY_MS + 2 * HOUR_MS);
+        assert_eq!(
+            maintenance.queue(&publication(4), db.clone(), hold.clone(), SATURDAY_MS),
+            None
+        );
+        assert_eq!(
+            maintenance.queue(&publication(5), db, hold, SATURDAY_MS),
+            Some(4)
+        );
+
+        assert!(maintenance.describe_pending(4).is_none());
+        let described = maintenance.describe_pending(5).unwrap();
+        assert_eq!(described.conditions[0].reason, "Pending");
+        assert_eq!(maintenance.status().pending.unwrap().generation, 5);
+
+        assert!(
+            maintenance
+                .release(&applied, SATURDAY_MS + HOUR_MS)
+                .is_none()
+        );
+        let (publication, _) = maintenance
+            .release(&applied, SATURDAY_MS + 2 * HOUR_MS)
+            .unwrap();
+        assert_eq!(publication.generation, 5);
+        assert!(maintenance.status().pending.is_none());
+        assert_eq!(maintenance.discard(), None);
+    }
+}
@@ -0,0 +1,384 @@
+//! Rolling history of the metrics workers push, for `Master.queryMetrics`
+//! (`pcr top`) and `GET /v1/metrics`, so recent trends can be shown without
+//! an external time-series database.
+//!
+//! Every `pushData` adds a sample for the worker and one for each VM it
+//! reports. Samples are averaged into buckets of [`RESOLUTION`] and kept
+//! for [`RETENTION`]: at most 1440 buckets a series, whatever the push
+//! rate. A series whose object was not reported for the whole retention is
+//! dropped. Queries average the buckets again over steps of their own, a
+//! whole number of buckets, and leave out the steps nothing was reported
+//! in.
+//!
+//! The history lives in memory only and starts empty when the master does.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use crate::convergence::{Observation, VmMetrics, WorkerMetrics};
+use crate::describe::Kind;
+
+/// Width of a bucket, the finest step a query gets.
+pub const RESOLUTION: Duration = Duration::from_secs(60);
+
+/// How far back the history goes.
+pub const RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Points a query without a step gets, about.
+const DEFAULT_POINTS: u64 = 60;
+
+/// What each value of a worker's points is.
+pub const WORKER_SERIES: &[&str] = &[
+    "available cpu",
+    "available memory bytes",
+    "disk usage bytes",
+    "memory pressure",
+    "running vms",
+];
+
+/// What each value of a VM's points is.
+pub const VM_SERIES: &[&str] = &[
+    "cpu usage",
+    "memory bytes",
+    "network rx bytes",
+    "network tx bytes",
+];
+
+/// Averages of one object's metrics over one step.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    /// Start of the step
+    pub timestamp_ms: u64,
+    /// One per name of the series
+    pub values: Vec<f64>,
+}
+
+/// Answer to a query, oldest point first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    pub names: &'static [&'static str],
+    pub step: Duration,
+    pub points: Vec<Point>,
+}
+
+/// Metrics of one VM or worker over the last `range`, averaged over `step`.
+/// A zero range is the whole retention, a zero step gives about
+/// [`DEFAULT_POINTS`] points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub kind: Kind,
+    pub id: String,
+    pub range: Duration,
+    pub step: Duration,
+}
+
+impl Query {
+    /// Range clamped to the retention, at least one bucket.
+    fn range_ms(&self) -> u64 {
+        let range = if self.range.is_zero() {
+            RETENTION
+        } else {
+            self.range.clamp(RESOLUTION, RETENTION)
+        };
+        millis(range)
+    }
+
+    /// Step rounded up to whole buckets, at most the range.
+    fn step_ms(&self) -> u64 {
+        let range_ms = self.range_ms();
+        let step_ms = if self.step.is_zero() {
+            range_ms / DEFAULT_POINTS
+        } else {
+            millis(self.step)
+        };
+        let resolution_ms = millis(RESOLUTION);
+        step_ms
+            .div_ceil(resolution_ms)
+            .max(1)
+            .saturating_mul(resolution_ms)
+            .min(range_ms)
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Samples of one bucket, summed.
+#[derive(Debug, Clone)]
+struct Bucket {
+    start_ms: u64,
+    sums: Vec<f64>,
+    samples: u32,
+}
+
+/// Buckets of one object, oldest first.
+#[derive(Debug, Default)]
+struct Buckets(VecDeque<Bucket>);
+
+impl Buckets {
+    fn record(&mut self, values: &[f64], now_ms: u64) {
+        let start_ms = now_ms - now_ms % millis(RESOLUTION);
+        match self.0.back_mut() {
+            // A clock stepping back lands in the newest bucket too
+            Some(bucket) if bucket.start_ms >= start_ms => {
+                for (sum, value) in bucket.sums.iter_mut().zip(values) {
+                    *sum += value;
+                }
+                bucket.samples += 1;
+            }
+            _ => self.0.push_back(Bucket {
+                start_ms,
+                sums: values.to_vec(),
+                samples: 1,
+            }),
+        }
+    }
+
+    /// Drop the buckets that started before `oldest_ms`.
+    fn expire(&mut self, oldest_ms: u64) {
+        while self.0.front().is_some_and(|b| b.start_ms < oldest_ms) {
+            self.0.pop_front();
+        }
+    }
+
+    fn points(&self, since_ms: u64, step_ms: u64) -> Vec<Point> {
+        let mut points: Vec<(Point, u32)> = Vec::new();
+        for bucket in self.0.iter().filter(|b| b.start_ms >= since_ms) {
+            let timestamp_ms = since_ms + (bucket.start_ms - since_ms) / step_ms * step_ms;
+            match points.last_mut() {
+                Some((point, samples)) if point.timestamp_ms == timestamp_ms => {
+                    for (sum, value) in point.values.iter_mut().zip(&bucket.sums) {
+                        *sum += value;
+                    }
+                    *samples += bucket.samples;
+                }
+                _ => points.push((
+                    Point {
+                        timestamp_ms,
+                        values: bucket.sums.clone(),
+                    },
+                    bucket.samples,
+                )),
+            }
+        }
+        points
+            .into_iter()
+            .map(|(mut point, samples)| {
+                for value in &mut point.values {
+                    *value /= f64::from(samples);
+                }
+                point
+            })
+            .collect()
+    }
+}
+
+/// Recent metrics of every worker and VM, see the module docs.
+#[derive(Debug, Default)]
+pub struct MetricsHistory {
+    workers: HashMap<String, Buckets>,
+    vms: HashMap<String, Buckets>,
+}
+
+impl MetricsHistory {
+    /// Add the samples of one `pushData` call.
+    pub fn record(&mut self, observation: &Observation, now_ms: u64) {
+        self.workers
+            .entry(observation.worker_id.clone())
+            .or_default()
+            .record(&worker_values(observation), now_ms);
+        for vm in &observation.vms {
+            self.vms
+                .entry(vm.id.clone())
+                .or_default()
+                .record(&vm_values(&vm.metrics), now_ms);
+        }
+    }
+
+    /// Drop what is older than the retention, and the series left empty.
+    pub fn expire(&mut self, now_ms: u64) {
+        let oldest_ms = now_ms.saturating_sub(millis(RETENTION));
+        for series in [&mut self.workers, &mut self.vms] {
+            series.retain(|_, buckets| {
+                buckets.expire(oldest_ms);
+                !buckets.0.is_empty()
+            });
+        }
+    }
+
+    /// The points of `query`, `None` for objects without a history, such as
+    /// generations.
+    #[must_use]
+    pub fn query(&self, query: &Query, now_ms: u64) -> Option<Series> {
+        let (buckets, names) = match query.kind {
+            Kind::Worker => (self.workers.get(&query.id)?, WORKER_SERIES),
+            Kind::Vm => (self.vms.get(&query.id)?, VM_SERIES),
+            Kind::Generation => return None,
+        };
+        let step_ms = query.step_ms();
+        // Aligned on steps, so repeated queries keep their timestamps
+        let since_ms = now_ms.saturating_sub(query.range_ms()) / step_ms * step_ms;
+        Some(Series {
+            names,
+            step: Duration::from_millis(step_ms),
+            points: buckets.points(since_ms, step_ms),
+        })
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn worker_values(observation: &Observation) -> Vec<f64> {
+    let metrics: &WorkerMetrics = &observation.metrics;
+    vec![
+        f64::from(metrics.available_cpu),
+        metrics.available_memory as f64,
+        metrics.disk_usage as f64,
+        f64::from(metrics.memory_pressure.some_avg10),
+        observation.vms.len() as f64,
+    ]
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn vm_values(metrics: &VmMetrics) -> Vec<f64> {
+    vec![
+        f64::from(metrics.cpu_usage),
+        metrics.memory_bytes as f64,
+        metrics.network_rx_bytes as f64,
+        metrics.network_tx_bytes as f64,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use commands::vm_state::VmState;
+
+    use super::*;
+    use crate::convergence::ObservedVm;
+
+    const MINUTE_MS: u64 = 60_000;
+
+    fn observation(available_cpu: f32, vm_cpu: f32) -> Observation {
+        Observation {
+            worker_id: "w1".to_string(),
+            address: String::new(),
+            generation: 1,
+            metrics: WorkerMetrics {
+                available_cpu,
+                ..WorkerMetrics::default()
+            },
+            vms: vec![ObservedVm {
+                id: "vm-a".to_string(),
+                hash: "aaaa".to_string(),
+                desired_hash: String::new(),
+                status: VmState::Running,
+                error: String::new(),
+                metrics: VmMetrics {
+                    cpu_usage: vm_cpu,
+                    ..VmMetrics::default()
+                },
+                transitions: Vec::new(),
+            }],
+            net_backends: Vec::new(),
+        }
+    }
+
+    fn query(kind: Kind, id: &str, range_mins: u64, step_mins: u64) -> Query {
+        Query {
+            kind,
+            id: id.to_string(),
+            range: Duration::from_secs(range_mins * 60),
+            step: Duration::from_secs(step_mins * 60),
+        }
+    }
+
+    #[test]
+    fn samples_are_averaged_per_bucket_and_step() {
+        let mut history = MetricsHistory::default();
+        let start = 1000 * MINUTE_MS;
+        // Two pushes in each of the first two minutes, one in the fourth
+        for (offset, cpu) in [
+            (0, 2.0),
+            (30_000, 4.0),
+            (MINUTE_MS, 6.0),
+            (3 * MINUTE_MS, 8.0),
+        ] {
+            history.record(&observation(cpu, cpu / 10.0), start + offset);
+        }
+        let now = start + 3 * MINUTE_MS;
+
+        let series = history
+            .query(&query(Kind::Worker, "w1", 10, 1), now)
+            .unwrap();
+        assert_eq!(series.names, WORKER_SERIES);
+        assert_eq!(series.step, RESOLUTION);
+        let cpu: Vec<(u64, f64)> = series
+            .points
+            .iter()
+            .map(|p| (p.timestamp_ms, p.values[0]))
+            .collect();
+        assert_eq!(
+            cpu,
+            [
+                (start, 3.0),
+                (start + MINUTE_MS, 6.0),
+                (start + 3 * MINUTE_MS, 8.0)
+            ],
+            "the third minute had no report"
+        );
+        assert_eq!(series.points[0].values[4..], [1.0], "one VM running");
+
+        let series = history
+            .query(&query(Kind::Worker, "w1", 10, 2), now)
+            .unwrap();
+        assert_eq!(series.step, Duration::from_secs(120));
+        let cpu: Vec<f64> = series.points.iter().map(|p| p.values[0]).collect();
+        assert_eq!(cpu, [4.0, 8.0], "samples weigh the same in every bucket");
+
+        let vm = history.query(&query(Kind::Vm, "vm-a", 10, 0), now).unwrap();
+        assert_eq!(vm.names, VM_SERIES);
+        assert!((vm.points[0].values[0] - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ranges_and_steps_are_clamped() {
+        let q = query(Kind::Worker, "w1", 0, 0);
+        assert_eq!(q.range_ms(), millis(RETENTION));
+        assert_eq!(q.step_ms(), 24 * MINUTE_MS);
+
+        let q = Query {
+            step: Duration::from_secs(90),
+            ..query(Kind::Worker, "w1", 60, 0)
+        };
+        assert_eq!(q.step_ms(), 2 * MINUTE_MS, "rounded up to whole buckets");
+        assert_eq!(query(Kind::Worker, "w1", 5, 60).step_ms(), 5 * MINUTE_MS);
+        assert_eq!(
+            query(Kind::Worker, "w1", 10_000, 1).range_ms(),
+            millis(RETENTION)
+        );
+    }
+
+    #[test]
+    fn old_samples_and_gone_objects_expire() {
+        let mut history = MetricsHistory::default();
+        let start = 1000 * MINUTE_MS;
+        history.record(&observation(1.0, 0.1), start);
+        let mut later = observation(2.0, 0.2);
+        later.vms.clear();
+        history.record(&later, start + 23 * 60 * MINUTE_MS);
+
+        let now = start + 24 * 60 * MINUTE_MS + MINUTE_MS;
+        history.expire(now);
+        let series = history
+            .query(&query(Kind::Worker, "w1", 0, 1), now)
+            .unwrap();
+        assert_eq!(series.points.len(), 1);
+        assert_eq!(series.points[0].values[..1], [2.0]);
+        assert!(history.query(&query(Kind::Vm, "vm-a", 0, 1), now).is_none());
+        assert!(
+            history
+                .query(&query(Kind::Generation, "1", 0, 1), now)
+                .is_none()
+        );
+    }
+}
@@ -1,8 +1,54 @@
+use std::hash::{BuildHasher, Hasher, RandomState};
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use tokio::sync::mpsc::Receiver;
+use tokio::time::Instant;
 
-use crate::dto::{NodeEvent, NodeMessage};
+use commands::vm_action::VmAction;
+
+use crate::convergence::{self, Convergence, Observation, PinError, Target, Unroutable};
+use crate::describe::Kind;
+use crate::dto::{NodeError, NodeEvent, NodeMessage, NodeReply, NodeResult};
+use crate::intake::{Accepted, Intake, Publication};
+use crate::maintenance::{Maintenance, Policies};
+use crate::metrics_history::{MetricsHistory, Query};
+use crate::reservations::{ReservationRequest, Reservations};
+use crate::webhooks::{ClusterEvent, ClusterWatch, Webhooks};
+
+/// How often the node reconciles: applies a generation maintenance held
+/// back once it may, checks VMs against their convergence deadline,
+/// expires reservations and tells webhooks what changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconcileConfig {
+    pub interval: Duration,
+    /// Up to this much is added to every wait, at random, so masters
+    /// started together do not reconcile in step.
+    pub jitter: Duration,
+}
+
+impl Default for ReconcileConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            jitter: Duration::ZERO,
+        }
+    }
+}
+
+impl ReconcileConfig {
+    /// Wait before the next reconciliation, `interval` plus up to `jitter`
+    /// picked from `random`.
+    fn delay(&self, random: u64) -> Duration {
+        let spread = u64::try_from(self.jitter.as_nanos()).unwrap_or(u64::MAX);
+        self.interval + Duration::from_nanos(random % spread.saturating_add(1))
+    }
+}
+
+/// Random enough for jitter, without a dependency.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
 
 ///! Node that handles communications between the server and the logic handled by the control plane.
 
@@ -10,23 +56,411 @@ pub struct Node {
     /// Channel to receive messages from the server
     node_channel: Receiver<NodeMessage>,
     peers_addr: Vec<SocketAddr>,
+    /// Active desired state
+    intake: Intake,
+    /// How far the cluster is from it
+    convergence: Convergence,
+    /// Metrics workers pushed over the last day
+    metrics: MetricsHistory,
+    /// Windows and freezes, and the generation they hold back
+    maintenance: Maintenance,
+    /// Capacity held for upcoming deployments
+    reservations: Reservations,
+    /// When the next reconciliation runs
+    reconcile: ReconcileConfig,
+    /// Where cluster events are sent
+    webhooks: Webhooks,
+    /// Convergence and worker health already sent to webhooks
+    watch: ClusterWatch,
 }
 
 impl Node {
-    pub fn new(node_channel: Receiver<NodeMessage>, peers_addr: Vec<SocketAddr>) -> Self {
+    /// `intake` holds the generation active before a restart, see
+    /// [`Intake::recover`].
+    pub fn new(
+        node_channel: Receiver<NodeMessage>,
+        peers_addr: Vec<SocketAddr>,
+        intake: Intake,
+        webhooks: Webhooks,
+        reconcile: ReconcileConfig,
+    ) -> Self {
         Node {
             node_channel,
             peers_addr,
+            intake,
+            convergence: Convergence::default(),
+            metrics: MetricsHistory::default(),
+            maintenance: Maintenance::default(),
+            reservations: Reservations::default(),
+            reconcile,
+            webhooks,
+            watch: ClusterWatch::default(),
         }
     }
 
     /// Main loop that processes messages from the server and sends command to the workers and orchestrates tasks
     pub async fn run(mut self) {
-        tracing::info!(peers=?self.peers_addr, "Node started with peers");
-        while let Some(message) = self.node_channel.recv().await {
-            match message.event() {
+        tracing::info!(
+            peers = ?self.peers_addr,
+            reconcile = ?self.reconcile,
+            "Node started with peers"
+        );
+        let checks = tokio::time::sleep(self.reconcile.delay(random()));
+        tokio::pin!(checks);
+        loop {
+            let message = tokio::select! {
+                message = self.node_channel.recv() => match message {
+                    Some(message) => message,
+                    None => break,
+                },
+                () = &mut checks => {
+                    self.expire_reservations();
+                    self.apply_pending();
+                    self.check_convergence();
+                    self.watch_cluster();
+                    self.metrics.expire(convergence::now_ms());
+                    checks
+                        .as_mut()
+                        .reset(Instant::now() + self.reconcile.delay(random()));
+                    continue;
+                }
+            };
+            let result = match message.event() {
                 NodeEvent::Apply => todo!(),
+                NodeEvent::Publish(publication, target) => self.publish(publication, target),
+                NodeEvent::Observe(observation) => {
+                    self.observe(observation);
+                    Ok(NodeReply::Done)
+                }
+                NodeEvent::Describe(kind, id) => self.describe(*kind, id),
+                NodeEvent::History => Ok(NodeReply::History(self.convergence.history())),
+                NodeEvent::QueryMetrics(query) => self.query_metrics(query),
+                NodeEvent::ClusterStatus(namespace) => Ok(NodeReply::ClusterStatus(Box::new(
+                    self.convergence
+                        .status(namespace.as_deref(), convergence::now_ms()),
+                ))),
+                NodeEvent::Drift => Ok(NodeReply::Drift(Box::new(
+                    self.convergence.drift_report(convergence::now_ms()),
+                ))),
+                NodeEvent::Maintenance => {
+                    Ok(NodeReply::Maintenance(Box::new(self.maintenance.status())))
+                }
+                NodeEvent::SetMaintenance(policies) => Ok(self.set_maintenance(policies)),
+                NodeEvent::Reservations => {
+                    self.expire_reservations();
+                    Ok(self.reservations_status())
+                }
+                NodeEvent::Reserve { request, actor } => self.reserve(request, actor),
+                NodeEvent::Release { name, actor } => self.release(name, actor),
+                NodeEvent::VmAction { id, action, actor } => self.vm_action(id, *action, actor),
+                NodeEvent::VmActionDone {
+                    route,
+                    action,
+                    outcome,
+                } => {
+                    self.convergence.finish_action(
+                        route,
+                        *action,
+                        outcome.as_ref().copied().map_err(String::as_str),
+                        convergence::now_ms(),
+                    );
+                    Ok(NodeReply::Done)
+                }
+                NodeEvent::PinVm {
+                    id,
+                    worker_id,
+                    pin,
+                    actor,
+                } => self.pin_vm(id, worker_id.as_deref(), *pin, actor),
+            };
+            message.reply(result);
+        }
+        tracing::info!("Node channel closed, stopping");
+    }
+
+    fn publish(&mut self, publication: &Publication, target: &Target) -> NodeResult {
+        match self.intake.publish(publication) {
+            Ok(Accepted::Published) => {
+                let now_ms = convergence::now_ms();
+                let hold = self
+                    .maintenance
+                    .hold(self.convergence.desired(), target, now_ms);
+                match hold {
+                    Some(hold) if !target.emergency => {
+                        tracing::info!(
+                            generation = publication.generation,
+                            commit = %publication.commit,
+                            publisher = %publication.publisher,
+                            %hold,
+                            "Generation queued"
+                        );
+                        let replaced =
+                            self.maintenance
+                                .queue(publication, target.clone(), hold, now_ms);
+                        if let Some(replaced) = replaced {
+                            tracing::info!(generation = replaced, "Pending generation superseded");
+                        }
+                    }
+                    hold => {
+                        if let Some(hold) = hold {
+                            tracing::warn!(
+                                generation = publication.generation,
+                                publisher = %publication.publisher,
+                                %hold,
+                                "Emergency publish overrides maintenance"
+                            );
+                        }
+                        tracing::info!(
+                            generation = publication.generation,
+                            commit = %publication.commit,
+                            publisher = %publication.publisher,
+                            vms = target.vms.len(),
+                            "Generation published"
+                        );
+                        if let Some(replaced) = self.maintenance.discard() {
+                            tracing::info!(generation = replaced, "Pending generation superseded");
+                        }
+                        self.activate(publication, target.clone(), now_ms);
+                    }
+                }
+                Ok(NodeReply::Done)
+            }
+            Ok(Accepted::Unchanged) => {
+                tracing::debug!(
+                    generation = publication.generation,
+                    publisher = %publication.publisher,
+                    "Generation already active"
+                );
+                Ok(NodeReply::Done)
+            }
+            Err(conflict) => {
+                tracing::warn!(
+                    generation = publication.generation,
+                    publisher = %publication.publisher,
+                    %conflict,
+                    "Publication refused"
+                );
+                Err(NodeError::Conflict(conflict))
             }
         }
     }
+
+    fn observe(&mut self, observation: &Observation) {
+        tracing::debug!(
+            worker_id = %observation.worker_id,
+            generation = observation.generation,
+            vms = observation.vms.len(),
+            "Worker observed"
+        );
+        let now_ms = convergence::now_ms();
+        self.metrics.record(observation, now_ms);
+        self.convergence.observe(observation.clone(), now_ms);
+    }
+
+    fn query_metrics(&self, query: &Query) -> NodeResult {
+        self.metrics
+            .query(query, convergence::now_ms())
+            .map(NodeReply::Metrics)
+            .ok_or_else(|| NodeError::NotFound(format!("metrics of {} {}", query.kind, query.id)))
+    }
+
+    fn describe(&mut self, kind: Kind, id: &str) -> NodeResult {
+        self.check_convergence();
+        let now_ms = convergence::now_ms();
+        let description = match kind {
+            Kind::Vm => self.convergence.describe_vm(id, now_ms),
+            Kind::Worker => self.convergence.describe_worker(id, now_ms),
+            Kind::Generation => id.parse().ok().and_then(|number| {
+                self.convergence
+                    .describe_generation(number, now_ms)
+                    .or_else(|| self.maintenance.describe_pending(number))
+            }),
+        };
+        description
+            .map(|d| NodeReply::Described(Box::new(d)))
+            .ok_or_else(|| NodeError::NotFound(format!("{kind} {id}")))
+    }
+
+    fn set_maintenance(&mut self, policies: &Policies) -> NodeReply {
+        tracing::info!(
+            windows = policies.windows.len(),
+            freezes = policies.freezes.len(),
+            "Maintenance policies set"
+        );
+        self.maintenance.set_policies(policies.clone());
+        self.apply_pending();
+        NodeReply::Maintenance(Box::new(self.maintenance.status()))
+    }
+
+    fn reservations_status(&self) -> NodeReply {
+        let free = self.convergence.free(convergence::now_ms());
+        NodeReply::Reservations(Box::new(self.reservations.status(free)))
+    }
+
+    fn reserve(&mut self, request: &ReservationRequest, actor: &str) -> NodeResult {
+        self.expire_reservations();
+        let now_ms = convergence::now_ms();
+        let free = self.convergence.free(now_ms);
+        match self.reservations.reserve(request, free, actor, now_ms) {
+            Ok(reservation) => {
+                tracing::info!(
+                    name = %reservation.name,
+                    resources = %reservation.resources,
+                    expires_ms = reservation.expires_ms,
+                    actor,
+                    "Capacity reserved"
+                );
+                Ok(self.reservations_status())
+            }
+            Err(e) => Err(NodeError::Capacity(e)),
+        }
+    }
+
+    fn release(&mut self, name: &str, actor: &str) -> NodeResult {
+        match self.reservations.release(name) {
+            Some(reservation) => {
+                tracing::info!(name, resources = %reservation.resources, actor, "Reservation released");
+                Ok(self.reservations_status())
+            }
+            None => Err(NodeError::NotFound(format!("reservation {name}"))),
+        }
+    }
+
+    /// Drop the reservations whose time ran out.
+    fn expire_reservations(&mut self) {
+        for reservation in self.reservations.expire(convergence::now_ms()) {
+            tracing::info!(
+                name = %reservation.name,
+                reserved_by = %reservation.reserved_by,
+                "Reservation expired before its deployment was applied"
+            );
+        }
+    }
+
+    fn vm_action(&mut self, id: &str, action: VmAction, actor: &str) -> NodeResult {
+        match self
+            .convergence
+            .request_action(id, action, actor, convergence::now_ms())
+        {
+            Ok(route) => {
+                tracing::info!(
+                    vm_id = %route.vm_id,
+                    worker_id = %route.worker_id,
+                    %action,
+                    actor,
+                    "VM action requested"
+                );
+                Ok(NodeReply::Routed(route))
+            }
+            Err(Unroutable::NotReported(id)) => Err(NodeError::NotFound(format!("vm {id}"))),
+            Err(unroutable) => Err(NodeError::Unroutable(unroutable.to_string())),
+        }
+    }
+
+    fn pin_vm(&mut self, id: &str, worker_id: Option<&str>, pin: bool, actor: &str) -> NodeResult {
+        let now_ms = convergence::now_ms();
+        let pinned = if pin {
+            self.convergence.pin(id, worker_id, actor, now_ms).map(Some)
+        } else {
+            self.convergence.unpin(id, actor, now_ms).map(|()| None)
+        };
+        match pinned {
+            Ok(pinned) => {
+                tracing::info!(vm = id, pinned = ?pinned, actor, "VM pin changed");
+                Ok(NodeReply::Pinned(pinned))
+            }
+            Err(PinError::NotReported(id)) => Err(NodeError::NotFound(format!("vm {id}"))),
+            Err(e) => Err(NodeError::Pinned(e.to_string())),
+        }
+    }
+
+    /// Apply the pending generation once no window or freeze holds it.
+    fn apply_pending(&mut self) {
+        let now_ms = convergence::now_ms();
+        if let Some((publication, target)) =
+            self.maintenance.release(self.convergence.desired(), now_ms)
+        {
+            tracing::info!(
+                generation = publication.generation,
+                commit = %publication.commit,
+                publisher = %publication.publisher,
+                vms = target.vms.len(),
+                "Pending generation applied"
+            );
+            self.activate(&publication, target, now_ms);
+        }
+    }
+
+    /// Make `publication` the active generation and tell webhooks. The
+    /// reservations for the VMs it adds are theirs now.
+    fn activate(&mut self, publication: &Publication, target: Target, now_ms: u64) {
+        let event = ClusterEvent::applied(publication, target.vms.len());
+        for reservation in self
+            .reservations
+            .consume(self.convergence.desired(), &target.vms)
+        {
+            tracing::info!(
+                name = %reservation.name,
+                generation = publication.generation,
+                "Reservation consumed"
+            );
+        }
+        self.convergence.activate(publication, target, now_ms);
+        self.webhooks.notify(&event);
+    }
+
+    /// Record why VMs missed the active generation's deadline.
+    fn check_convergence(&mut self) {
+        for (vm, diagnostic) in self.convergence.check(convergence::now_ms()) {
+            tracing::warn!(
+                vm,
+                generation = diagnostic.generation,
+                reason = %diagnostic.reason,
+                message = %diagnostic.message,
+                "VM missed its convergence deadline"
+            );
+            self.webhooks
+                .notify(&ClusterEvent::failed(&vm, &diagnostic));
+        }
+    }
+
+    /// Tell webhooks the active generation converged or a worker went
+    /// silent.
+    fn watch_cluster(&mut self) {
+        if !self.webhooks.enabled() {
+            return;
+        }
+        let view = self.convergence.status(None, convergence::now_ms());
+        for event in self.watch.changes(&view) {
+            tracing::info!(event = %event.kind(), "Cluster event");
+            self.webhooks.notify(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconciliations_wait_the_interval_plus_some_jitter() {
+        let steady = ReconcileConfig {
+            interval: Duration::from_secs(5),
+            jitter: Duration::ZERO,
+        };
+        assert_eq!(steady.delay(u64::MAX), Duration::from_secs(5));
+
+        let jittered = ReconcileConfig {
+            interval: Duration::from_secs(5),
+            jitter: Duration::from_secs(2),
+        };
+        assert_eq!(jittered.delay(0), Duration::from_secs(5));
+        for _ in 0..100 {
+            let delay = jittered.delay(random());
+            assert!(
+                (Duration::from_secs(5)..=Duration::from_secs(7)).contains(&delay),
+                "{delay:?}"
+            );
+        }
+    }
 }
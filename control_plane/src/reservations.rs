@@ -0,0 +1,296 @@
+//! Capacity reserved ahead of a deployment.
+//!
+//! A CI deploy step reserves the CPU and memory its next generation needs
+//! before publishing it, so other deployments do not take that capacity in
+//! the meantime. A reservation is for the VMs matching its label selector,
+//! every VM when it is empty, and holds capacity away from everything else,
+//! see [`scheduler::fits`].
+//!
+//! A reservation is only granted if the workers reporting have that much
+//! capacity free, net of the other reservations. It ends, whichever comes
+//! first:
+//!
+//! - when a generation is applied that adds VMs it is for: the capacity is
+//!   theirs now;
+//! - when its `ttlSecs` run out, so a deploy that never publishes does not
+//!   hold capacity forever;
+//! - when it is released with `DELETE /v1/reservations/<name>`.
+//!
+//! Reserving again under the same name replaces the reservation, e.g. to
+//! extend it. Reservations are set over `POST /v1/reservations` and, like
+//! the rest of the master state, kept in memory.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::convergence::DesiredVm;
+use crate::maintenance::Scope;
+use crate::scheduler::{self, Resources};
+
+/// Longest a reservation may last.
+pub const MAX_TTL: Duration = Duration::from_hours(24);
+
+/// Body of `POST /v1/reservations`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReservationRequest {
+    pub name: String,
+    /// VMs the capacity is for
+    #[serde(default)]
+    pub selector: Scope,
+    #[serde(flatten)]
+    pub resources: Resources,
+    pub ttl_secs: u64,
+}
+
+impl ReservationRequest {
+    /// # Errors
+    ///
+    /// - on an empty name
+    /// - when it reserves nothing
+    /// - when it lasts no time or longer than [`MAX_TTL`]
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("reservation has no name".to_string());
+        }
+        if self.resources.cpu <= 0.0 && self.resources.memory_mb == 0 {
+            return Err(format!("reservation {:?} reserves nothing", self.name));
+        }
+        if self.ttl_secs == 0 || self.ttl_secs > MAX_TTL.as_secs() {
+            return Err(format!(
+                "reservation {:?} must last between 1 and {} seconds",
+                self.name,
+                MAX_TTL.as_secs()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A granted reservation.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reservation {
+    pub name: String,
+    pub selector: Scope,
+    #[serde(flatten)]
+    pub resources: Resources,
+    pub reserved_by: String,
+    pub reserved_ms: u64,
+    pub expires_ms: u64,
+}
+
+/// Body of `GET /v1/reservations`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Status {
+    pub reservations: Vec<Reservation>,
+    /// Capacity of the reporting workers
+    pub free: Resources,
+    /// What is left of it for new reservations
+    pub unreserved: Resources,
+}
+
+/// Active reservations, by name.
+#[derive(Debug, Default)]
+pub struct Reservations {
+    active: BTreeMap<String, Reservation>,
+}
+
+impl Reservations {
+    /// Reserve what `request` asks for out of `free`, replacing the
+    /// reservation of the same name.
+    ///
+    /// # Errors
+    ///
+    /// - when `free` does not have it once the other reservations are set
+    ///   aside
+    pub fn reserve(
+        &mut self,
+        request: &ReservationRequest,
+        free: Resources,
+        actor: &str,
+        now_ms: u64,
+    ) -> Result<Reservation, String> {
+        let others = self.reserved_except(Some(&request.name));
+        if !scheduler::fits(free, others, request.resources) {
+            return Err(format!(
+                "cannot reserve {} for {}: {} free, {} of it already reserved",
+                request.resources, request.name, free, others
+            ));
+        }
+        let reservation = Reservation {
+            name: request.name.clone(),
+            selector: request.selector.clone(),
+            resources: request.resources,
+            reserved_by: actor.to_string(),
+            reserved_ms: now_ms,
+            expires_ms: now_ms + request.ttl_secs * 1000,
+        };
+        self.active
+            .insert(reservation.name.clone(), reservation.clone());
+        Ok(reservation)
+    }
+
+    pub fn release(&mut self, name: &str) -> Option<Reservation> {
+        self.active.remove(name)
+    }
+
+    /// Drop the reservations that ran out at `now_ms`.
+    pub fn expire(&mut self, now_ms: u64) -> Vec<Reservation> {
+        self.drain(|r| r.expires_ms <= now_ms)
+    }
+
+    /// Drop the reservations for VMs that `next` adds to `applied`.
+    pub fn consume(&mut self, applied: &[DesiredVm], next: &[DesiredVm]) -> Vec<Reservation> {
+        let added: Vec<&DesiredVm> = next
+            .iter()
+            .filter(|vm| !applied.iter().any(|a| a.hash == vm.hash))
+            .collect();
+        self.drain(|r| added.iter().any(|vm| r.selector.covers(&vm.labels)))
+    }
+
+    #[must_use]
+    pub fn status(&self, free: Resources) -> Status {
+        let reserved = self.reserved_except(None);
+        Status {
+            reservations: self.active.values().cloned().collect(),
+            free,
+            unreserved: free.minus(reserved),
+        }
+    }
+
+    /// Total of the reservations but `name`'s.
+    fn reserved_except(&self, name: Option<&str>) -> Resources {
+        self.active
+            .values()
+            .filter(|r| Some(r.name.as_str()) != name)
+            .map(|r| r.resources)
+            .fold(Resources::default(), Resources::plus)
+    }
+
+    fn drain(&mut self, ended: impl Fn(&Reservation) -> bool) -> Vec<Reservation> {
+        let names: Vec<String> = self
+            .active
+            .values()
+            .filter(|r| ended(r))
+            .map(|r| r.name.clone())
+            .collect();
+        names
+            .iter()
+            .filter_map(|name| self.active.remove(name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commands::net_backend::NetBackend;
+
+    const FREE: Resources = Resources {
+        cpu: 16.0,
+        memory_mb: 32_768,
+    };
+
+    fn request(json: serde_json::Value) -> ReservationRequest {
+        let request: ReservationRequest = serde_json::from_value(json).unwrap();
+        request.validate().unwrap();
+        request
+    }
+
+    fn vm(hash: &str, app: &str) -> DesiredVm {
+        DesiredVm {
+            hash: hash.to_string(),
+            store_paths: Vec::new(),
+            labels: [("app".to_string(), app.to_string())].into_iter().collect(),
+            pinned: None,
+            namespace: crate::tenancy::DEFAULT_NAMESPACE.to_string(),
+            net_backend: NetBackend::Tap,
+            resources: Resources::default(),
+        }
+    }
+
+    #[test]
+    fn requests_are_validated() {
+        let invalid = |json| {
+            serde_json::from_value::<ReservationRequest>(json)
+                .unwrap()
+                .validate()
+                .unwrap_err()
+        };
+        assert!(
+            invalid(serde_json::json!({"name": "web", "cpu": 0, "memoryMb": 0, "ttlSecs": 60}))
+                .contains("nothing")
+        );
+        assert!(
+            invalid(serde_json::json!({"name": "web", "cpu": 1, "memoryMb": 0, "ttlSecs": 0}))
+                .contains("must last")
+        );
+        assert!(
+            invalid(serde_json::json!({"name": " ", "cpu": 1, "memoryMb": 0, "ttlSecs": 60}))
+                .contains("no name")
+        );
+    }
+
+    #[test]
+    fn reservations_cannot_overcommit_the_free_capacity() {
+        let mut reservations = Reservations::default();
+        let web = request(serde_json::json!({
+            "name": "web", "selector": "app=web", "cpu": 12, "memoryMb": 8192, "ttlSecs": 600,
+        }));
+        let granted = reservations.reserve(&web, FREE, "ci", 1_000).unwrap();
+        assert_eq!(granted.expires_ms, 601_000);
+
+        let db = request(serde_json::json!({
+            "name": "db", "cpu": 8, "memoryMb": 8192, "ttlSecs": 600,
+        }));
+        let refused = reservations.reserve(&db, FREE, "ci", 1_000).unwrap_err();
+        assert!(
+            refused.contains("12 cpu and 8192 MiB of it already reserved"),
+            "{refused}"
+        );
+
+        // Reserving again under the same name replaces, so it only counts once
+        let smaller = ReservationRequest {
+            resources: Resources {
+                cpu: 8.0,
+                memory_mb: 8192,
+            },
+            ..web
+        };
+        reservations.reserve(&smaller, FREE, "ci", 2_000).unwrap();
+        reservations.reserve(&db, FREE, "ci", 2_000).unwrap();
+        assert_eq!(
+            reservations.status(FREE).unreserved,
+            Resources {
+                cpu: 0.0,
+                memory_mb: 16_384
+            }
+        );
+    }
+
+    #[test]
+    fn reservations_end_when_applied_or_expired() {
+        let mut reservations = Reservations::default();
+        for (name, selector, ttl_secs) in [("web", "app=web", 600), ("db", "app=db", 60)] {
+            let request = request(serde_json::json!({
+                "name": name, "selector": selector, "cpu": 2, "memoryMb": 1024, "ttlSecs": ttl_secs,
+            }));
+            reservations.reserve(&request, FREE, "ci", 0).unwrap();
+        }
+
+        // Web VMs that were already running do not consume it
+        let applied = [vm("a", "web")];
+        assert!(reservations.consume(&applied, &applied).is_empty());
+        let consumed = reservations.consume(&applied, &[vm("a", "web"), vm("b", "web")]);
+        assert_eq!(consumed.len(), 1);
+        assert_eq!(consumed[0].name, "web");
+
+        assert!(reservations.expire(59_999).is_empty());
+        assert_eq!(reservations.expire(60_000)[0].name, "db");
+        assert!(reservations.status(FREE).reservations.is_empty());
+        assert!(reservations.release("db").is_none());
+    }
+}
@@ -1,3 +1,107 @@
 //! Assigns pods to worker nodes based on resource requirements, constraints, and policies
 
+use std::fmt;
+
+use commands::net_backend::NetBackend;
+use serde::{Deserialize, Serialize};
+
 pub struct Scheduler;
+
+/// Whether a worker advertising `offered` can attach a NIC with `wanted`.
+/// Every worker attaches `tap`, including those that predate advertising
+/// backends and report none.
+#[must_use]
+pub fn attaches(offered: &[NetBackend], wanted: NetBackend) -> bool {
+    wanted == NetBackend::Tap || offered.contains(&wanted)
+}
+
+/// CPU cores and memory, free on workers or asked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resources {
+    pub cpu: f32,
+    pub memory_mb: u64,
+}
+
+impl Resources {
+    /// What a VM spec asks for, its `cpu` and `memoryMb`.
+    #[must_use]
+    pub fn of_spec(cpu: u32, memory_mb: u32) -> Resources {
+        Resources {
+            cpu: f32::from(u16::try_from(cpu).unwrap_or(u16::MAX)),
+            memory_mb: u64::from(memory_mb),
+        }
+    }
+
+    #[must_use]
+    pub fn plus(self, other: Resources) -> Resources {
+        Resources {
+            cpu: self.cpu + other.cpu,
+            memory_mb: self.memory_mb + other.memory_mb,
+        }
+    }
+
+    /// What is left once `other` is taken, none of either when short.
+    #[must_use]
+    pub fn minus(self, other: Resources) -> Resources {
+        Resources {
+            cpu: (self.cpu - other.cpu).max(0.0),
+            memory_mb: self.memory_mb.saturating_sub(other.memory_mb),
+        }
+    }
+
+    #[must_use]
+    pub fn covers(self, wanted: Resources) -> bool {
+        self.cpu >= wanted.cpu && self.memory_mb >= wanted.memory_mb
+    }
+}
+
+impl fmt::Display for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} cpu and {} MiB", self.cpu, self.memory_mb)
+    }
+}
+
+/// Whether `wanted` fits in `free` once capacity `reserved` for other
+/// deployments is set aside. Reserved capacity counts as taken until its
+/// [reservation](crate::reservations) is consumed or expires.
+#[must_use]
+pub fn fits(free: Resources, reserved: Resources, wanted: Resources) -> bool {
+    free.minus(reserved).covers(wanted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_prepared_workers_attach_vhost_user() {
+        assert!(attaches(&[], NetBackend::Tap));
+        assert!(!attaches(&[], NetBackend::VhostUser));
+        assert!(!attaches(&[NetBackend::Tap], NetBackend::VhostUser));
+        assert!(attaches(
+            &[NetBackend::Tap, NetBackend::VhostUser],
+            NetBackend::VhostUser
+        ));
+    }
+
+    #[test]
+    fn reserved_capacity_counts_as_taken() {
+        let free = Resources {
+            cpu: 8.0,
+            memory_mb: 16_384,
+        };
+        let reserved = Resources {
+            cpu: 6.0,
+            memory_mb: 4096,
+        };
+        let wanted = Resources {
+            cpu: 4.0,
+            memory_mb: 4096,
+        };
+        assert!(fits(free, Resources::default(), wanted));
+        assert!(!fits(free, reserved, wanted));
+        assert!(fits(free, reserved, Resources { cpu: 2.0, ..wanted }));
+        assert_eq!(reserved.minus(free), Resources::default());
+    }
+}
@@ -1,33 +1,153 @@
 //! Central point of communication. Talks to workers and receives requests from the cli.
+//!
+//! Every handler runs inside an `rpc` span parented to the caller's `trace`
+//! context, so master-side work joins the trace started by `pcr`.
+//!
+//! Each connection gets its own `Server` carrying the peer address, which is
+//! recorded as the actor of mutating calls in the [audit log](crate::audit).
+//!
+//! `pushData` feeds the [convergence](crate::convergence) tracking that
+//! `describe` reads back. `vmAction` is the one call the master makes to a
+//! worker: it connects to the address the worker reported from and calls
+//! `Worker.vmAction`. `pinVm` only changes what the master records.
+//!
+//! `listGenerations` lists the generations kept for `describe`, with the
+//! [provenance](crate::intake::Provenance) their publisher sent.
+//!
+//! `queryMetrics` reads back the [history](crate::metrics_history) of the
+//! metrics `pushData` carried.
+//!
+//! Calls from the CLI and publishers take a [token](crate::tenancy), checked
+//! as the HTTP gateway checks its bearer token: `getClusterStatus` gives a
+//! token scoped to a namespace only that namespace's VMs, the other reads
+//! need an unscoped token, and calls that change VMs or hand out a worker
+//! an unscoped token that is not read-only. Audited calls record the
+//! token's holder next to the peer. The calls workers make (`pushData`,
+//! `pushLogs`, `getAssignment`) are not authenticated yet.
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use capnp_rpc::{RpcSystem, rpc_twoparty_capnp, twoparty};
+use commands::common_capnp;
+use commands::hashing::{self, ContentHash, Drift, VmSpecFields};
+use commands::health::Flag;
+use commands::labels::{read_labels, write_labels};
+use commands::lifecycle::{self, NotifyState};
+use commands::net_backend;
+use commands::telemetry::{TraceHeaders, rpc_span};
+use commands::vm_action::VmAction;
+use commands::vm_state;
 use futures::AsyncReadExt;
-use tracing::{debug, info, instrument};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, error, info, instrument, warn};
 
-use crate::dto::NodeMessenger;
+use crate::audit::{AuditEntry, AuditLog};
+use crate::convergence::{
+    ClusterView, DesiredVm, DriftReport, DriftSource, MemoryPressure, Observation, ObservedVm,
+    Route, StoreJanitor, Target, VmMetrics, WorkerMetrics,
+};
+use crate::describe::{Description, Kind};
+use crate::dto::{NodeEvent, NodeMessenger, NodeReply};
+use crate::intake::{Provenance, Publication};
+use crate::metrics_history::{Query, Series};
+use crate::scheduler::Resources;
+use crate::tenancy::{self, Denied, Grant, Tokens};
 
 #[derive(Clone)]
 pub struct Server {
     messenger: NodeMessenger,
+    audit: AuditLog,
+    /// Remote end of the connection this instance serves.
+    peer: Option<SocketAddr>,
+    /// Raised while `serve` accepts connections, for the health probes.
+    listening: Flag,
+    tokens: Tokens,
 }
 
 impl Server {
-    pub fn new(messenger: impl Into<NodeMessenger>) -> Self {
+    pub fn new(messenger: impl Into<NodeMessenger>, audit: AuditLog) -> Self {
         Server {
             messenger: messenger.into(),
+            audit,
+            peer: None,
+            listening: Flag::default(),
+            tokens: Tokens::default(),
         }
     }
 
-    #[instrument(skip(self))]
-    pub async fn serve(self, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    /// Check the [tokens](crate::tenancy) callers present.
+    #[must_use]
+    pub fn with_tokens(mut self, tokens: Tokens) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    pub fn listening(&self) -> Flag {
+        self.listening.clone()
+    }
+
+    fn actor(&self) -> String {
+        self.peer
+            .map_or_else(|| "unknown".to_string(), |peer| format!("peer:{peer}"))
+    }
+
+    /// The grant of the caller's `token`, if `check` allows it.
+    fn authorize(
+        &self,
+        method: &str,
+        token: capnp::Result<capnp::text::Reader<'_>>,
+        check: impl FnOnce(&Grant) -> Result<(), Denied>,
+    ) -> Result<Grant, capnp::Error> {
+        let token = token?.to_str()?;
+        self.tokens
+            .authenticate(Some(token))
+            .and_then(|grant| check(&grant).map(|()| grant))
+            .map_err(|denied| {
+                warn!(method, actor = %self.actor(), %denied, "RPC refused");
+                capnp::Error::failed(denied.to_string())
+            })
+    }
+
+    /// Record a mutating call by `actor`. A call that cannot be audited is
+    /// reported as failed to the caller.
+    fn record_audit(
+        &self,
+        actor: &str,
+        method: &str,
+        params: serde_json::Value,
+        outcome: Result<(), String>,
+    ) -> Result<(), capnp::Error> {
+        let entry = AuditEntry::new(actor, method, params, outcome);
+        self.audit.record(&entry).map_err(|e| {
+            error!(method, error = %e, "Failed to write audit entry");
+            capnp::Error::failed(format!("audit log unavailable: {e}"))
+        })
+    }
+
+    /// Accept connections until `shutdown` is cancelled, then return `Ok`.
+    /// Reports READY to systemd once the listener is bound.
+    #[instrument(skip(self, shutdown))]
+    pub async fn serve(
+        self,
+        addr: SocketAddr,
+        shutdown: CancellationToken,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         info!(addr = %addr, "Starting server");
         let listener = tokio::net::TcpListener::bind(&addr).await?;
 
-        let client: commands::master_capnp::master::Client = capnp_rpc::new_client(self);
+        if let Err(e) = lifecycle::notify(&[NotifyState::Ready]) {
+            warn!(error = %e, "sd_notify READY failed");
+        }
+        let _listening = self.listening.raise();
 
         loop {
-            let (stream, peer_addr) = listener.accept().await?;
+            let (stream, peer_addr) = tokio::select! {
+                () = shutdown.cancelled() => {
+                    info!("Shutdown requested, no longer accepting connections");
+                    return Ok(());
+                }
+                accepted = listener.accept() => accepted?,
+            };
             debug!(peer_addr = %peer_addr, "New connection");
             stream.set_nodelay(true)?;
             let (reader, writer) =
@@ -41,13 +161,384 @@ impl Server {
 
             // TODO: Determine which client to provide based on connection context
             // For now, defaulting to master_control for CLI connections
-            let rpc_system = RpcSystem::new(Box::new(network), Some(client.clone().client));
+            let client: commands::master_capnp::master::Client = capnp_rpc::new_client(Server {
+                peer: Some(peer_addr),
+                ..self.clone()
+            });
+            let rpc_system = RpcSystem::new(Box::new(network), Some(client.client));
 
             tokio::task::spawn_local(rpc_system);
         }
     }
 }
 
+/// Generation hash of published specs, see [`commands::hashing`], and the
+/// VMs they describe.
+fn read_specs(
+    specs: capnp::struct_list::Reader<'_, common_capnp::vm_spec::Owned>,
+) -> capnp::Result<(ContentHash, Vec<DesiredVm>)> {
+    let mut hashes = Vec::with_capacity(specs.len() as usize);
+    let mut vms = Vec::with_capacity(specs.len() as usize);
+    for spec in specs {
+        let domains = spec
+            .get_network_allowed_domains()?
+            .iter()
+            .map(|d| Ok(d?.to_str()?))
+            .collect::<capnp::Result<Vec<&str>>>()?;
+        let store_paths = [
+            spec.get_toplevel()?.to_str()?,
+            spec.get_kernel_path()?.to_str()?,
+            spec.get_initrd_path()?.to_str()?,
+            spec.get_disk_image_path()?.to_str()?,
+        ];
+        let [toplevel, kernel_path, initrd_path, disk_image_path] = store_paths;
+        let net_backend = net_backend::read(spec.get_net_backend())?;
+        let hash = hashing::vm_spec_hash(&VmSpecFields {
+            toplevel,
+            kernel_path,
+            initrd_path,
+            disk_image_path,
+            cmdline: spec.get_cmdline()?.to_str()?,
+            cpu: spec.get_cpu(),
+            memory_mb: spec.get_memory_mb(),
+            network_allowed_domains: &domains,
+            hugepages: spec.get_hugepages(),
+            shared_memory: spec.get_shared_memory(),
+            net_backend,
+        });
+        vms.push(DesiredVm {
+            hash: hash.to_string(),
+            store_paths: store_paths
+                .into_iter()
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+                .collect(),
+            labels: read_labels(spec.get_labels()?)?,
+            pinned: Some(spec.get_pinned_worker()?.to_str()?)
+                .filter(|w| !w.is_empty())
+                .map(str::to_string),
+            namespace: tenancy::namespace(spec.get_namespace()?.to_str()?)
+                .map_err(capnp::Error::failed)?,
+            net_backend,
+            resources: Resources::of_spec(spec.get_cpu(), spec.get_memory_mb()),
+        });
+        hashes.push(hash);
+    }
+    Ok((hashing::generation_hash(&hashes), vms))
+}
+
+/// What a worker reports running in `pushData`.
+fn read_observation(
+    params: &commands::master_capnp::master::push_data_params::Reader<'_>,
+) -> capnp::Result<Observation> {
+    let vms = params
+        .get_running_vms()?
+        .iter()
+        .map(|vm| {
+            let metrics = vm.get_metrics()?;
+            Ok(ObservedVm {
+                id: vm.get_id()?.to_str()?.to_string(),
+                hash: vm.get_content_hash()?.to_str()?.to_string(),
+                desired_hash: vm.get_desired_hash()?.to_str()?.to_string(),
+                status: vm_state::read(vm.get_state(), vm.get_status()?.to_str()?),
+                error: vm.get_error()?.to_str()?.to_string(),
+                metrics: VmMetrics {
+                    cpu_usage: metrics.get_cpu_usage(),
+                    memory_bytes: metrics.get_memory_usage(),
+                    network_rx_bytes: metrics.get_network_rx_bytes(),
+                    network_tx_bytes: metrics.get_network_tx_bytes(),
+                },
+                transitions: vm_state::read_transitions(vm.get_transitions()?)?,
+            })
+        })
+        .collect::<capnp::Result<_>>()?;
+    let metrics = params.get_metrics()?;
+    let pressure = metrics.get_memory_pressure()?;
+    let janitor = metrics.get_store_janitor()?;
+    Ok(Observation {
+        worker_id: params.get_worker_id()?.to_str()?.to_string(),
+        address: params.get_address()?.to_str()?.to_string(),
+        generation: params.get_observed_generation(),
+        metrics: WorkerMetrics {
+            available_cpu: metrics.get_available_cpu(),
+            available_memory: metrics.get_available_memory(),
+            disk_usage: metrics.get_disk_usage(),
+            image_disk_usage: read_disk_usage(metrics.get_image_disk_usage()?)?,
+            vm_disk_usage: read_disk_usage(metrics.get_vm_disk_usage()?)?,
+            log_disk_usage: metrics.get_log_disk_usage(),
+            uptime_secs: metrics.get_uptime(),
+            memory_pressure: MemoryPressure {
+                some_avg10: pressure.get_some_avg10(),
+                full_avg10: pressure.get_full_avg10(),
+                under_pressure: pressure.get_under_pressure(),
+                policy: pressure.get_policy()?.to_str()?.to_string(),
+                episodes: pressure.get_episodes(),
+                restarts: pressure.get_restarts(),
+            },
+            store_janitor: StoreJanitor {
+                used_percent: janitor.get_used_percent(),
+                high_watermark: janitor.get_high_watermark(),
+                pinned_paths: janitor.get_pinned_paths(),
+                collections: janitor.get_collections(),
+                freed_bytes: janitor.get_freed_bytes(),
+            },
+        },
+        net_backends: net_backend::read_list(params.get_net_backends()?),
+        vms,
+    })
+}
+
+/// `(name, bytes)` pairs of a `Common.DiskUsage` list.
+fn read_disk_usage(
+    entries: capnp::struct_list::Reader<'_, common_capnp::disk_usage::Owned>,
+) -> capnp::Result<Vec<(String, u64)>> {
+    entries
+        .iter()
+        .map(|entry| Ok((entry.get_name()?.to_str()?.to_string(), entry.get_bytes())))
+        .collect()
+}
+
+fn kind_of(kind: commands::master_capnp::ObjectKind) -> Kind {
+    match kind {
+        commands::master_capnp::ObjectKind::Vm => Kind::Vm,
+        commands::master_capnp::ObjectKind::Worker => Kind::Worker,
+        commands::master_capnp::ObjectKind::Generation => Kind::Generation,
+    }
+}
+
+fn write_description(
+    mut builder: commands::master_capnp::description::Builder<'_>,
+    description: &Description,
+) {
+    builder.set_kind(match description.kind {
+        Kind::Vm => commands::master_capnp::ObjectKind::Vm,
+        Kind::Worker => commands::master_capnp::ObjectKind::Worker,
+        Kind::Generation => commands::master_capnp::ObjectKind::Generation,
+    });
+    builder.set_id(&description.id);
+    builder.set_placement(&description.placement);
+
+    let mut fields = builder
+        .reborrow()
+        .init_fields(description.fields.len() as u32);
+    for (i, field) in description.fields.iter().enumerate() {
+        let mut entry = fields.reborrow().get(i as u32);
+        entry.set_name(field.name);
+        entry.set_desired(&field.desired);
+        entry.set_observed(&field.observed);
+    }
+    let mut conditions = builder
+        .reborrow()
+        .init_conditions(description.conditions.len() as u32);
+    for (i, condition) in description.conditions.iter().enumerate() {
+        let mut entry = conditions.reborrow().get(i as u32);
+        entry.set_name(condition.name);
+        entry.set_status(condition.status);
+        entry.set_reason(&condition.reason);
+        entry.set_message(&condition.message);
+    }
+    let mut metrics = builder
+        .reborrow()
+        .init_metrics(description.metrics.len() as u32);
+    for (i, metric) in description.metrics.iter().enumerate() {
+        let mut entry = metrics.reborrow().get(i as u32);
+        entry.set_name(metric.name);
+        entry.set_value(&metric.value);
+    }
+    let mut events = builder.init_events(description.events.len() as u32);
+    for (i, event) in description.events.iter().enumerate() {
+        let mut entry = events.reborrow().get(i as u32);
+        entry.set_timestamp_ms(event.timestamp_ms);
+        entry.set_object(&event.object);
+        entry.set_reason(&event.reason);
+        entry.set_message(&event.message);
+    }
+}
+
+fn write_drift_report(
+    mut builder: commands::master_capnp::drift_report::Builder<'_>,
+    report: &DriftReport,
+) {
+    use commands::master_capnp::drift_report::Drift;
+
+    builder.set_generation(report.generation);
+    builder.set_commit(&report.commit);
+    let mut vms = builder.init_vms(report.vms.len() as u32);
+    for (i, vm) in report.vms.iter().enumerate() {
+        let mut entry = vms.reborrow().get(i as u32);
+        entry.set_vm_id(&vm.vm_id);
+        entry.set_worker_id(&vm.worker_id);
+        entry.set_namespace(&vm.namespace);
+        entry.set_desired_hash(&vm.desired_hash);
+        entry.set_observed_hash(&vm.observed_hash);
+        entry.set_converged_secs(vm.converged_for.map_or(0, |d| d.as_secs()));
+        entry.set_drift(match vm.drift {
+            DriftSource::InSync => Drift::InSync,
+            DriftSource::SelfReported => Drift::SelfReported,
+            DriftSource::HashMismatch => Drift::HashMismatch,
+            DriftSource::Missing => Drift::Missing,
+        });
+    }
+}
+
+fn write_metric_series(
+    mut builder: commands::master_capnp::metric_series::Builder<'_>,
+    series: &Series,
+) {
+    let mut names = builder.reborrow().init_names(series.names.len() as u32);
+    for (i, name) in series.names.iter().enumerate() {
+        names.set(i as u32, *name);
+    }
+    builder.set_step_secs(u32::try_from(series.step.as_secs()).unwrap_or(u32::MAX));
+    let mut points = builder.init_points(series.points.len() as u32);
+    for (i, point) in series.points.iter().enumerate() {
+        let mut entry = points.reborrow().get(i as u32);
+        entry.set_timestamp_ms(point.timestamp_ms);
+        let mut values = entry.init_values(point.values.len() as u32);
+        for (j, value) in point.values.iter().enumerate() {
+            values.set(j as u32, *value);
+        }
+    }
+}
+
+fn write_cluster_status(
+    mut builder: common_capnp::cluster_status::Builder<'_>,
+    view: &ClusterView,
+) {
+    builder.set_active_generation(view.generation);
+    builder.set_active_commit(&view.commit);
+    builder.set_convergence_percent(view.convergence_percent);
+
+    let mut workers = builder.reborrow().init_workers(view.workers.len() as u32);
+    for (i, worker) in view.workers.iter().enumerate() {
+        let mut entry = workers.reborrow().get(i as u32);
+        entry.set_id(&worker.id);
+        entry.set_healthy(worker.healthy);
+        entry.set_generation(worker.generation);
+        entry.set_running_vms(u32::try_from(worker.running_vms).unwrap_or(u32::MAX));
+        let mut backends = entry.init_net_backends(worker.net_backends.len() as u32);
+        for (j, backend) in worker.net_backends.iter().enumerate() {
+            backends.set(j as u32, (*backend).into());
+        }
+    }
+    let mut vms = builder.reborrow().init_vms(view.vms.len() as u32);
+    for (i, vm) in view.vms.iter().enumerate() {
+        let mut entry = vms.reborrow().get(i as u32);
+        entry.set_id(&vm.id);
+        entry.set_worker_id(&vm.worker_id);
+        entry.set_namespace(&vm.namespace);
+        entry.set_desired_hash(&vm.desired_hash);
+        entry.set_observed_hash(&vm.observed_hash);
+        entry.set_status(vm.status.as_str());
+        entry.set_state(vm.status.into());
+        entry.set_drifted(vm.drifted);
+        let mut metrics = entry.reborrow().init_metrics();
+        metrics.set_cpu_usage(vm.metrics.cpu_usage);
+        metrics.set_memory_usage(vm.metrics.memory_bytes);
+        metrics.set_network_rx_bytes(vm.metrics.network_rx_bytes);
+        metrics.set_network_tx_bytes(vm.metrics.network_tx_bytes);
+        write_labels(&vm.labels, entry.init_labels(vm.labels.len() as u32));
+    }
+    let mut unschedulable = builder.init_unschedulable(view.unschedulable.len() as u32);
+    for (i, vm) in view.unschedulable.iter().enumerate() {
+        let mut entry = unschedulable.reborrow().get(i as u32);
+        entry.set_spec_hash(&vm.hash);
+        entry.set_namespace(&vm.namespace);
+        entry.set_reason(vm.reason.as_str());
+        entry.set_message(&vm.message);
+    }
+}
+
+/// Carry out `action` on the worker of `route`.
+async fn send_vm_action(route: &Route, action: VmAction) -> Result<(), String> {
+    let failed = |e: &dyn std::fmt::Display| format!("worker {}: {e}", route.worker_id);
+    let addr: SocketAddr = route.address.parse().map_err(|e| failed(&e))?;
+    let stream = tokio::net::TcpStream::connect(addr)
+        .await
+        .map_err(|e| failed(&e))?;
+    stream.set_nodelay(true).map_err(|e| failed(&e))?;
+    let (reader, writer) = tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
+    let network = twoparty::VatNetwork::new(
+        futures::io::BufReader::new(reader),
+        futures::io::BufWriter::new(writer),
+        rpc_twoparty_capnp::Side::Client,
+        Default::default(),
+    );
+    let mut rpc_system = RpcSystem::new(Box::new(network), None);
+    let worker: commands::worker_capnp::worker::Client =
+        rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+    tokio::task::spawn_local(rpc_system);
+
+    let mut request = worker.vm_action_request();
+    request.get().set_id(&route.vm_id);
+    request.get().set_action(action.into());
+    TraceHeaders::current().write(request.get().init_trace());
+    request
+        .send()
+        .promise
+        .await
+        .map(|_| ())
+        .map_err(|e| failed(&e))
+}
+
+/// Deadline of a publish, `None` for the master default.
+pub(crate) fn deadline_of(secs: u32) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(u64::from(secs)))
+}
+
+/// Reject a publish whose `intentHash` disagrees with its specs. Publishers
+/// that send no hash or a pre-versioning one are let through with a warning.
+pub(crate) fn verify_intent(claimed: &str, computed: &ContentHash) -> Result<(), String> {
+    match hashing::compare(computed.as_str(), claimed) {
+        Drift::InSync => Ok(()),
+        Drift::Drifted => Err(format!(
+            "intentHash {claimed} does not match the published specs ({computed})"
+        )),
+        drift @ (Drift::Unknown | Drift::Incomparable { .. }) => {
+            warn!(%claimed, %computed, %drift, "intentHash not verified");
+            Ok(())
+        }
+    }
+}
+
+/// Provenance of a publish; publishers that predate it send none.
+fn read_provenance(provenance: capnp::Result<common_capnp::provenance::Reader<'_>>) -> Provenance {
+    let text = |t: capnp::Result<capnp::text::Reader<'_>>| {
+        t.ok()
+            .and_then(|t| t.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    provenance.map_or_else(
+        |_| Provenance::default(),
+        |p| Provenance {
+            ci_build_id: text(p.get_ci_build_id()),
+            repo_url: text(p.get_repo_url()),
+            commit_url: text(p.get_commit_url()),
+            cache: text(p.get_cache()),
+        },
+    )
+}
+
+/// Audit summary of a publish, shared by the RPC and HTTP APIs.
+pub(crate) fn publish_summary(
+    publication: &Publication,
+    claimed: Option<&str>,
+    target: &Target,
+) -> serde_json::Value {
+    serde_json::json!({
+        "publisher": publication.publisher,
+        "commit": publication.commit,
+        "generation": publication.generation,
+        "parent_generation": publication.parent,
+        "intent_hash": claimed,
+        "computed_intent_hash": publication.intent_hash,
+        "vm_specs": target.vms.len(),
+        "emergency": target.emergency,
+        "provenance": publication.provenance,
+    })
+}
+
 impl commands::master_capnp::master::Server for Server {
     fn publish_state(
         &mut self,
@@ -56,19 +547,87 @@ impl commands::master_capnp::master::Server for Server {
     ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
         match params.get() {
             Ok(p) => {
+                let span = rpc_span("Master.publishState", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
+                let grant =
+                    match self.authorize("Master.publishState", p.get_token(), Grant::may_write) {
+                        Ok(grant) => grant,
+                        Err(e) => return ::capnp::capability::Promise::err(e),
+                    };
+                let actor = grant.actor(&self.actor());
                 let commit = p.get_commit();
                 let generation = p.get_generation();
                 let intent_hash = p.get_intent_hash();
-                let _vm_specs = p.get_vm_specs();
+                let vm_specs = p.get_vm_specs();
+                let named = p.get_publisher().ok().and_then(|t| t.to_str().ok());
+                let publisher = grant.publisher(named, &actor);
+                let parent = match p.get_parent().and_then(|parent| Ok(parent.which()?)) {
+                    Ok(commands::master_capnp::parent_generation::Which::Unchecked(())) => None,
+                    Ok(commands::master_capnp::parent_generation::Which::Generation(g)) => Some(g),
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
 
-                info!(generation, ?commit, ?intent_hash, "Publish request");
+                let emergency = p.get_emergency();
+                info!(generation, ?commit, ?intent_hash, %publisher, ?parent, emergency, "Publish request");
 
-                // TODO: Implement actual publishing logic
-                if let Ok(result_builder) = results.get().get_result() {
-                    let _ = result_builder.init_ok();
-                }
+                let (computed, vms) = match vm_specs.and_then(read_specs) {
+                    Ok(read) => read,
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
+                let target = Target {
+                    vms,
+                    deadline: deadline_of(p.get_convergence_deadline_secs()),
+                    emergency,
+                };
+                let claimed = intent_hash.ok().and_then(|h| h.to_str().ok());
+                let verified = verify_intent(claimed.unwrap_or_default(), &computed);
 
-                ::capnp::capability::Promise::ok(())
+                let publication = Publication {
+                    publisher,
+                    generation,
+                    commit: commit
+                        .ok()
+                        .and_then(|c| c.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string(),
+                    intent_hash: computed.to_string(),
+                    parent,
+                    provenance: read_provenance(p.get_provenance()),
+                };
+                let summary = publish_summary(&publication, claimed, &target);
+
+                let server = self.clone();
+                ::capnp::capability::Promise::from_future(
+                    async move {
+                        let outcome = match verified {
+                            Ok(()) => server
+                                .messenger
+                                .request(NodeEvent::Publish(publication, target))
+                                .await
+                                .map(|_| ())
+                                .map_err(|e| e.to_string()),
+                            Err(e) => Err(e),
+                        };
+                        server.record_audit(
+                            &actor,
+                            "Master.publishState",
+                            summary,
+                            outcome.clone(),
+                        )?;
+
+                        let mut result_builder = results.get().get_result()?;
+                        match outcome {
+                            Ok(()) => {
+                                let _ = result_builder.init_ok();
+                            }
+                            Err(e) => {
+                                let _ = result_builder.set_err(e.as_str());
+                            }
+                        }
+                        Ok(())
+                    }
+                    .instrument(span.clone()),
+                )
             }
             Err(e) => ::capnp::capability::Promise::err(e),
         }
@@ -81,6 +640,8 @@ impl commands::master_capnp::master::Server for Server {
     ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
         match params.get() {
             Ok(p) => {
+                let span = rpc_span("Master.getAssignment", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
                 let worker_id = p.get_worker_id();
                 let last_seen_generation = p.get_last_seen_generation();
 
@@ -104,14 +665,81 @@ impl commands::master_capnp::master::Server for Server {
     ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
         match params.get() {
             Ok(p) => {
+                let span = rpc_span("Master.pushData", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
                 let worker_id = p.get_worker_id();
                 let observed_generation = p.get_observed_generation();
-                let _running_vms = p.get_running_vms();
-                let _metrics = p.get_metrics();
 
                 debug!(?worker_id, observed_generation, "Worker pushing data");
 
-                // TODO: Implement state observation logic
+                let observation = match read_observation(&p) {
+                    Ok(observation) => observation,
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
+                let messenger = self.messenger.clone();
+                ::capnp::capability::Promise::from_future(
+                    async move {
+                        let outcome = messenger.request(NodeEvent::Observe(observation)).await;
+                        let mut result_builder = results.get().get_result()?;
+                        match outcome {
+                            Ok(_) => {
+                                let _ = result_builder.init_ok();
+                            }
+                            Err(e) => {
+                                let _ = result_builder.set_err(e.to_string().as_str());
+                            }
+                        }
+                        Ok(())
+                    }
+                    .instrument(span.clone()),
+                )
+            }
+            Err(e) => ::capnp::capability::Promise::err(e),
+        }
+    }
+
+    fn push_logs(
+        &mut self,
+        params: commands::master_capnp::master::PushLogsParams,
+        mut results: commands::master_capnp::master::PushLogsResults,
+    ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+        match params.get() {
+            Ok(p) => {
+                let span = rpc_span("Master.pushLogs", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
+                let worker_id = p
+                    .get_worker_id()
+                    .ok()
+                    .and_then(|w| w.to_str().ok())
+                    .unwrap_or_default();
+                let entries = match p.get_entries() {
+                    Ok(entries) => entries,
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
+                debug!(worker_id, entries = entries.len(), "Worker pushing logs");
+
+                // Re-emitted under their own target so the master's log
+                // pipeline (journald, OTLP) can route them separately.
+                for entry in entries.iter() {
+                    let source = entry
+                        .get_source()
+                        .ok()
+                        .and_then(|s| s.to_str().ok())
+                        .unwrap_or_default();
+                    let line = entry
+                        .get_line()
+                        .ok()
+                        .and_then(|l| l.to_str().ok())
+                        .unwrap_or_default();
+                    info!(
+                        target: "procurator::forwarded",
+                        worker_id,
+                        source,
+                        timestamp_ms = entry.get_timestamp_ms(),
+                        "{line}"
+                    );
+                }
+
                 if let Ok(result_builder) = results.get().get_result() {
                     let _ = result_builder.init_ok();
                 }
@@ -124,12 +752,59 @@ impl commands::master_capnp::master::Server for Server {
 
     fn get_cluster_status(
         &mut self,
-        _params: commands::master_capnp::master::GetClusterStatusParams,
-        _results: commands::master_capnp::master::GetClusterStatusResults,
+        params: commands::master_capnp::master::GetClusterStatusParams,
+        mut results: commands::master_capnp::master::GetClusterStatusResults,
     ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
-        debug!("Getting cluster status");
-        // TODO: Implement cluster status retrieval
-        ::capnp::capability::Promise::ok(())
+        match params.get() {
+            Ok(p) => {
+                let span = rpc_span(
+                    "Master.getClusterStatus",
+                    &TraceHeaders::read(p.get_trace()),
+                );
+                let _entered = span.enter();
+                let request = p
+                    .get_namespace()
+                    .and_then(|ns| Ok((ns.to_str()?, p.get_token()?.to_str()?)));
+                let (requested, token) = match request {
+                    Ok(request) => request,
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
+                let namespace = match self
+                    .tokens
+                    .authenticate(Some(token))
+                    .and_then(|grant| grant.view(requested))
+                {
+                    Ok(namespace) => namespace,
+                    Err(denied) => {
+                        warn!(actor = %self.actor(), %denied, "Cluster status refused");
+                        return ::capnp::capability::Promise::err(capnp::Error::failed(
+                            denied.to_string(),
+                        ));
+                    }
+                };
+                debug!(?namespace, "Getting cluster status");
+
+                let messenger = self.messenger.clone();
+                ::capnp::capability::Promise::from_future(
+                    async move {
+                        let view =
+                            match messenger.request(NodeEvent::ClusterStatus(namespace)).await {
+                                Ok(NodeReply::ClusterStatus(view)) => view,
+                                Ok(_) => {
+                                    return Err(capnp::Error::failed(
+                                        "unexpected reply from the node".to_string(),
+                                    ));
+                                }
+                                Err(e) => return Err(capnp::Error::failed(e.to_string())),
+                            };
+                        write_cluster_status(results.get().init_status(), &view);
+                        Ok(())
+                    }
+                    .instrument(span.clone()),
+                )
+            }
+            Err(e) => ::capnp::capability::Promise::err(e),
+        }
     }
 
     fn get_worker(
@@ -139,6 +814,12 @@ impl commands::master_capnp::master::Server for Server {
     ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
         match params.get() {
             Ok(p) => {
+                let span = rpc_span("Master.getWorker", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
+                if let Err(e) = self.authorize("Master.getWorker", p.get_token(), Grant::may_write)
+                {
+                    return ::capnp::capability::Promise::err(e);
+                }
                 let worker_id = p.get_worker_id();
                 debug!(?worker_id, "Getting worker capability");
 
@@ -150,4 +831,455 @@ impl commands::master_capnp::master::Server for Server {
             Err(e) => ::capnp::capability::Promise::err(e),
         }
     }
+
+    fn get_audit_log(
+        &mut self,
+        params: commands::master_capnp::master::GetAuditLogParams,
+        mut results: commands::master_capnp::master::GetAuditLogResults,
+    ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+        match params.get() {
+            Ok(p) => {
+                let span = rpc_span("Master.getAuditLog", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
+                if let Err(e) = self.authorize("Master.getAuditLog", p.get_token(), Grant::unscoped)
+                {
+                    return ::capnp::capability::Promise::err(e);
+                }
+                let since_ms = p.get_since_ms();
+                let limit = p.get_limit();
+                debug!(since_ms, limit, "Reading audit log");
+
+                let entries = match self.audit.query(since_ms, limit as usize) {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        error!(error = %e, "Failed to read audit log");
+                        return ::capnp::capability::Promise::err(capnp::Error::failed(format!(
+                            "audit log unavailable: {e}"
+                        )));
+                    }
+                };
+
+                let mut list = results.get().init_entries(entries.len() as u32);
+                for (i, entry) in entries.iter().enumerate() {
+                    let mut builder = list.reborrow().get(i as u32);
+                    builder.set_timestamp_ms(entry.timestamp_ms);
+                    builder.set_actor(&entry.actor);
+                    builder.set_method(&entry.method);
+                    builder.set_params(entry.params.to_string().as_str());
+                    builder.set_ok(entry.ok);
+                    builder.set_error(entry.error.as_deref().unwrap_or_default());
+                }
+
+                ::capnp::capability::Promise::ok(())
+            }
+            Err(e) => ::capnp::capability::Promise::err(e),
+        }
+    }
+
+    fn describe(
+        &mut self,
+        params: commands::master_capnp::master::DescribeParams,
+        mut results: commands::master_capnp::master::DescribeResults,
+    ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+        match params.get() {
+            Ok(p) => {
+                let span = rpc_span("Master.describe", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
+                if let Err(e) = self.authorize("Master.describe", p.get_token(), Grant::unscoped) {
+                    return ::capnp::capability::Promise::err(e);
+                }
+                let request = p
+                    .get_kind()
+                    .map_err(capnp::Error::from)
+                    .and_then(|kind| Ok((kind, p.get_id()?.to_str()?.to_string())));
+                let (kind, id) = match request {
+                    Ok((kind, id)) => (kind_of(kind), id),
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
+                debug!(%kind, %id, "Describing");
+
+                let messenger = self.messenger.clone();
+                ::capnp::capability::Promise::from_future(
+                    async move {
+                        let outcome = messenger.request(NodeEvent::Describe(kind, id)).await;
+                        let mut result_builder = results.get().get_result()?;
+                        match outcome {
+                            Ok(NodeReply::Described(description)) => {
+                                write_description(result_builder.init_ok(), &description);
+                            }
+                            Ok(_) => {
+                                let _ = result_builder.set_err("unexpected reply from the node");
+                            }
+                            Err(e) => {
+                                let _ = result_builder.set_err(e.to_string().as_str());
+                            }
+                        }
+                        Ok(())
+                    }
+                    .instrument(span.clone()),
+                )
+            }
+            Err(e) => ::capnp::capability::Promise::err(e),
+        }
+    }
+
+    fn vm_action(
+        &mut self,
+        params: commands::master_capnp::master::VmActionParams,
+        mut results: commands::master_capnp::master::VmActionResults,
+    ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+        match params.get() {
+            Ok(p) => {
+                let span = rpc_span("Master.vmAction", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
+                let actor = match self.authorize("Master.vmAction", p.get_token(), Grant::may_write)
+                {
+                    Ok(grant) => grant.actor(&self.actor()),
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
+                let request = p
+                    .get_action()
+                    .map_err(capnp::Error::from)
+                    .and_then(|action| Ok((VmAction::from(action), p.get_vm_id()?.to_str()?)));
+                let (action, vm_id) = match request {
+                    Ok((action, vm_id)) => (action, vm_id.to_string()),
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
+                info!(%vm_id, %action, %actor, "VM action request");
+                let summary = serde_json::json!({
+                    "vm_id": vm_id,
+                    "action": action.as_str(),
+                });
+
+                let server = self.clone();
+                ::capnp::capability::Promise::from_future(
+                    async move {
+                        let event = NodeEvent::VmAction {
+                            id: vm_id,
+                            action,
+                            actor: actor.clone(),
+                        };
+                        let outcome = match server.messenger.request(event).await {
+                            Ok(NodeReply::Routed(route)) => {
+                                let sent = send_vm_action(&route, action).await;
+                                if let Err(e) = &sent {
+                                    warn!(vm_id = %route.vm_id, %action, error = %e, "VM action failed");
+                                }
+                                let done = NodeEvent::VmActionDone {
+                                    route,
+                                    action,
+                                    outcome: sent.clone(),
+                                };
+                                if let Err(e) = server.messenger.request(done).await {
+                                    warn!(error = %e, "Could not record VM action outcome");
+                                }
+                                sent
+                            }
+                            Ok(_) => Err("unexpected reply from the node".to_string()),
+                            Err(e) => Err(e.to_string()),
+                        };
+                        server.record_audit(&actor, "Master.vmAction", summary, outcome.clone())?;
+
+                        let mut result_builder = results.get().get_result()?;
+                        match outcome {
+                            Ok(()) => {
+                                let _ = result_builder.init_ok();
+                            }
+                            Err(e) => {
+                                let _ = result_builder.set_err(e.as_str());
+                            }
+                        }
+                        Ok(())
+                    }
+                    .instrument(span.clone()),
+                )
+            }
+            Err(e) => ::capnp::capability::Promise::err(e),
+        }
+    }
+
+    fn pin_vm(
+        &mut self,
+        params: commands::master_capnp::master::PinVmParams,
+        mut results: commands::master_capnp::master::PinVmResults,
+    ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+        match params.get() {
+            Ok(p) => {
+                let span = rpc_span("Master.pinVm", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
+                let actor = match self.authorize("Master.pinVm", p.get_token(), Grant::may_write) {
+                    Ok(grant) => grant.actor(&self.actor()),
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
+                let request = p
+                    .get_vm_id()
+                    .and_then(|id| Ok((id.to_str()?, p.get_worker_id()?.to_str()?)));
+                let (vm_id, worker_id) = match request {
+                    Ok((vm_id, worker_id)) => (
+                        vm_id.to_string(),
+                        Some(worker_id)
+                            .filter(|w| !w.is_empty())
+                            .map(str::to_string),
+                    ),
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
+                let pin = p.get_pin();
+                info!(%vm_id, ?worker_id, pin, %actor, "VM pin request");
+                let summary = serde_json::json!({
+                    "vm_id": vm_id,
+                    "worker_id": worker_id,
+                    "pin": pin,
+                });
+
+                let server = self.clone();
+                ::capnp::capability::Promise::from_future(
+                    async move {
+                        let event = NodeEvent::PinVm {
+                            id: vm_id,
+                            worker_id,
+                            pin,
+                            actor: actor.clone(),
+                        };
+                        let outcome = match server.messenger.request(event).await {
+                            Ok(NodeReply::Pinned(pinned)) => Ok(pinned.unwrap_or_default()),
+                            Ok(_) => Err("unexpected reply from the node".to_string()),
+                            Err(e) => Err(e.to_string()),
+                        };
+                        server.record_audit(
+                            &actor,
+                            "Master.pinVm",
+                            summary,
+                            outcome.as_ref().map(|_| ()).map_err(Clone::clone),
+                        )?;
+
+                        let mut result_builder = results.get().get_result()?;
+                        match outcome {
+                            Ok(worker_id) => {
+                                result_builder.set_ok(worker_id.as_str())?;
+                            }
+                            Err(e) => {
+                                let _ = result_builder.set_err(e.as_str());
+                            }
+                        }
+                        Ok(())
+                    }
+                    .instrument(span.clone()),
+                )
+            }
+            Err(e) => ::capnp::capability::Promise::err(e),
+        }
+    }
+
+    fn list_generations(
+        &mut self,
+        params: commands::master_capnp::master::ListGenerationsParams,
+        mut results: commands::master_capnp::master::ListGenerationsResults,
+    ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+        match params.get() {
+            Ok(p) => {
+                let span = rpc_span("Master.listGenerations", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
+                if let Err(e) =
+                    self.authorize("Master.listGenerations", p.get_token(), Grant::unscoped)
+                {
+                    return ::capnp::capability::Promise::err(e);
+                }
+                debug!("Listing generations");
+
+                let messenger = self.messenger.clone();
+                ::capnp::capability::Promise::from_future(
+                    async move {
+                        let history = match messenger.request(NodeEvent::History).await {
+                            Ok(NodeReply::History(history)) => history,
+                            Ok(_) => {
+                                return Err(capnp::Error::failed(
+                                    "unexpected reply from the node".to_string(),
+                                ));
+                            }
+                            Err(e) => return Err(capnp::Error::failed(e.to_string())),
+                        };
+
+                        let mut list = results.get().init_generations(history.len() as u32);
+                        for (i, generation) in history.iter().enumerate() {
+                            let mut builder = list.reborrow().get(i as u32);
+                            builder.set_number(generation.number);
+                            builder.set_commit(&generation.commit);
+                            builder.set_intent_hash(&generation.intent_hash);
+                            builder.set_timestamp(generation.published_ms / 1000);
+                            builder.set_is_active(generation.active);
+                            builder.set_publisher(&generation.publisher);
+                            builder.set_vms(u32::try_from(generation.vms).unwrap_or(u32::MAX));
+                            let source = &generation.provenance;
+                            let mut provenance = builder.init_provenance();
+                            provenance.set_ci_build_id(&source.ci_build_id);
+                            provenance.set_repo_url(&source.repo_url);
+                            provenance.set_commit_url(&source.commit_url);
+                            provenance.set_cache(&source.cache);
+                        }
+                        Ok(())
+                    }
+                    .instrument(span.clone()),
+                )
+            }
+            Err(e) => ::capnp::capability::Promise::err(e),
+        }
+    }
+
+    fn query_metrics(
+        &mut self,
+        params: commands::master_capnp::master::QueryMetricsParams,
+        mut results: commands::master_capnp::master::QueryMetricsResults,
+    ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+        match params.get() {
+            Ok(p) => {
+                let span = rpc_span("Master.queryMetrics", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
+                if let Err(e) =
+                    self.authorize("Master.queryMetrics", p.get_token(), Grant::unscoped)
+                {
+                    return ::capnp::capability::Promise::err(e);
+                }
+                let request = p
+                    .get_kind()
+                    .map_err(capnp::Error::from)
+                    .and_then(|kind| Ok((kind, p.get_id()?.to_str()?.to_string())));
+                let query = match request {
+                    Ok((kind, id)) => Query {
+                        kind: kind_of(kind),
+                        id,
+                        range: Duration::from_secs(p.get_range_secs().into()),
+                        step: Duration::from_secs(p.get_step_secs().into()),
+                    },
+                    Err(e) => return ::capnp::capability::Promise::err(e),
+                };
+                debug!(
+                    kind = %query.kind,
+                    id = %query.id,
+                    range = ?query.range,
+                    step = ?query.step,
+                    "Querying metrics"
+                );
+
+                let messenger = self.messenger.clone();
+                ::capnp::capability::Promise::from_future(
+                    async move {
+                        let mut result_builder = results.get().get_result()?;
+                        if query.kind == Kind::Generation {
+                            let _ = result_builder.set_err("only VMs and workers have metrics");
+                            return Ok(());
+                        }
+                        match messenger.request(NodeEvent::QueryMetrics(query)).await {
+                            Ok(NodeReply::Metrics(series)) => {
+                                write_metric_series(result_builder.init_ok(), &series);
+                            }
+                            Ok(_) => {
+                                let _ = result_builder.set_err("unexpected reply from the node");
+                            }
+                            Err(e) => {
+                                let _ = result_builder.set_err(e.to_string().as_str());
+                            }
+                        }
+                        Ok(())
+                    }
+                    .instrument(span.clone()),
+                )
+            }
+            Err(e) => ::capnp::capability::Promise::err(e),
+        }
+    }
+
+    fn drift_report(
+        &mut self,
+        params: commands::master_capnp::master::DriftReportParams,
+        mut results: commands::master_capnp::master::DriftReportResults,
+    ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+        match params.get() {
+            Ok(p) => {
+                let span = rpc_span("Master.driftReport", &TraceHeaders::read(p.get_trace()));
+                let _entered = span.enter();
+                if let Err(e) = self.authorize("Master.driftReport", p.get_token(), Grant::unscoped)
+                {
+                    return ::capnp::capability::Promise::err(e);
+                }
+                debug!("Reporting drift");
+
+                let messenger = self.messenger.clone();
+                ::capnp::capability::Promise::from_future(
+                    async move {
+                        let report = match messenger.request(NodeEvent::Drift).await {
+                            Ok(NodeReply::Drift(report)) => report,
+                            Ok(_) => {
+                                return Err(capnp::Error::failed(
+                                    "unexpected reply from the node".to_string(),
+                                ));
+                            }
+                            Err(e) => return Err(capnp::Error::failed(e.to_string())),
+                        };
+                        write_drift_report(results.get().init_report(), &report);
+                        Ok(())
+                    }
+                    .instrument(span.clone()),
+                )
+            }
+            Err(e) => ::capnp::capability::Promise::err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditConfig;
+
+    /// A server accepting an unscoped `ops` token, a read-only `auditor`
+    /// and a `team-a` token scoped to its namespace.
+    fn server_with_tokens(name: &str) -> Server {
+        let dir = std::env::temp_dir().join(format!("server-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tokens.json");
+        std::fs::write(
+            &path,
+            r#"[
+                {"name": "ops", "token": "ops-secret"},
+                {"name": "auditor", "token": "audit-secret", "readOnly": true},
+                {"name": "team-a", "token": "a-secret", "namespace": "team-a"}
+            ]"#,
+        )
+        .unwrap();
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let audit = AuditLog::open(AuditConfig::new(dir.join("audit.jsonl"))).unwrap();
+        Server::new(tx, audit).with_tokens(Tokens::load(&path).unwrap())
+    }
+
+    #[test]
+    fn rpcs_check_the_token_as_the_gateway_does() {
+        let server = server_with_tokens("authorize");
+        let check = |token: &str, check: fn(&Grant) -> Result<(), Denied>| {
+            server.authorize("Master.test", Ok(token.into()), check)
+        };
+
+        assert!(check("", Grant::unscoped).is_err(), "a token is required");
+        assert!(check("nope", Grant::unscoped).is_err());
+        assert!(
+            check("a-secret", Grant::unscoped).is_err(),
+            "scoped tokens only see their VMs"
+        );
+        assert!(check("a-secret", Grant::may_write).is_err());
+        assert!(check("audit-secret", Grant::unscoped).is_ok());
+        assert!(check("audit-secret", Grant::may_write).is_err());
+
+        let grant = check("ops-secret", Grant::may_write).unwrap();
+        assert_eq!(grant.actor(&server.actor()), "ops@unknown");
+    }
+
+    #[test]
+    fn publish_is_rejected_only_on_a_mismatching_versioned_hash() {
+        let computed = hashing::generation_hash([]);
+        let other = hashing::generation_hash([&computed]);
+
+        assert!(verify_intent(computed.as_str(), &computed).is_ok());
+        assert!(verify_intent(other.as_str(), &computed).is_err());
+        assert!(verify_intent("", &computed).is_ok());
+        assert!(verify_intent("ci-1234", &computed).is_ok());
+    }
 }
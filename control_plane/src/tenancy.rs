@@ -0,0 +1,377 @@
+//! Namespaces of VM specs and the tokens that scope what callers see.
+//!
+//! Every VM spec belongs to a namespace, [`DEFAULT_NAMESPACE`] when its
+//! publisher leaves it empty. Like labels, the namespace is not part of the
+//! spec hash. Workers are shared infrastructure and run VMs of every
+//! namespace side by side.
+//!
+//! Tokens are read from a JSON file kept outside the Nix store:
+//!
+//! ```json
+//! [
+//!   {"name": "ops", "token": "…"},
+//!   {"name": "team-a", "token": "…", "namespace": "team-a", "readOnly": true}
+//! ]
+//! ```
+//!
+//! A token with a `namespace` only sees the VMs of that namespace in the
+//! cluster status. Over HTTP and Cap'n Proto alike, only unscoped tokens
+//! that are not `readOnly` may publish, act on VMs or change maintenance
+//! policies, since those apply to the whole cluster; the calls workers
+//! make take no token yet. Without a token file nothing is authenticated,
+//! as before namespaces existed.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// Namespace of specs published without one.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Longest namespace, as for a DNS label.
+const MAX_NAMESPACE_LEN: usize = 63;
+
+/// The namespace `raw` names, [`DEFAULT_NAMESPACE`] when empty.
+///
+/// # Errors
+///
+/// - if it is not a DNS label: lowercase letters, digits and `-`, starting
+///   and ending with a letter or digit, at most 63 characters
+pub fn namespace(raw: &str) -> Result<String, String> {
+    if raw.is_empty() {
+        return Ok(DEFAULT_NAMESPACE.to_string());
+    }
+    let valid = raw.len() <= MAX_NAMESPACE_LEN
+        && raw
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !raw.starts_with('-')
+        && !raw.ends_with('-');
+    if valid {
+        Ok(raw.to_string())
+    } else {
+        Err(format!(
+            "invalid namespace {raw:?}: expected lowercase letters, digits and '-', at most {MAX_NAMESPACE_LEN} characters"
+        ))
+    }
+}
+
+/// One entry of the token file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct TokenEntry {
+    /// Who holds it, recorded as the actor of audited calls
+    name: String,
+    token: String,
+    /// Only this namespace is visible, all of them when absent
+    #[serde(default)]
+    namespace: Option<String>,
+    #[serde(default)]
+    read_only: bool,
+}
+
+/// Accepted tokens. Empty when no token file is configured, which lets
+/// every caller in unscoped.
+#[derive(Debug, Clone, Default)]
+pub struct Tokens {
+    entries: Vec<TokenEntry>,
+}
+
+impl Tokens {
+    /// Read the token file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// - if it cannot be read or is not a list of token entries
+    /// - if a token is empty or listed twice, or a namespace is invalid
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        };
+        let raw = std::fs::read(path)?;
+        let mut entries: Vec<TokenEntry> =
+            serde_json::from_slice(&raw).map_err(|e| invalid(e.to_string()))?;
+        let mut seen = HashSet::new();
+        for entry in &mut entries {
+            if entry.token.is_empty() {
+                return Err(invalid(format!("token {} is empty", entry.name)));
+            }
+            if !seen.insert(entry.token.clone()) {
+                return Err(invalid(format!("token {} is listed twice", entry.name)));
+            }
+            if let Some(ns) = &entry.namespace {
+                entry.namespace = Some(namespace(ns).map_err(invalid)?);
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    /// Whether any token is configured.
+    #[must_use]
+    pub fn enabled(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
+    /// What the caller presenting `token` may do.
+    ///
+    /// # Errors
+    ///
+    /// - if tokens are configured and `token` is absent or not one of them
+    pub fn authenticate(&self, token: Option<&str>) -> Result<Grant, Denied> {
+        if !self.enabled() {
+            return Ok(Grant::open());
+        }
+        let token = token.filter(|t| !t.is_empty()).ok_or(Denied::Missing)?;
+        self.entries
+            .iter()
+            .find(|entry| constant_time_eq(entry.token.as_bytes(), token.as_bytes()))
+            .map(|entry| Grant {
+                name: Some(entry.name.clone()),
+                namespace: entry.namespace.clone(),
+                read_only: entry.read_only,
+            })
+            .ok_or(Denied::Unknown)
+    }
+}
+
+/// Compare without returning early on the first differing byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// What an authenticated caller may see and do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+    /// Holder of the token, `None` when nothing is authenticated
+    pub name: Option<String>,
+    /// The only namespace it sees, every one when `None`
+    pub namespace: Option<String>,
+    pub read_only: bool,
+}
+
+impl Grant {
+    /// The grant of every caller while no token is configured.
+    #[must_use]
+    pub fn open() -> Self {
+        Self {
+            name: None,
+            namespace: None,
+            read_only: false,
+        }
+    }
+
+    /// The namespace to show when the caller asks for `requested`, empty
+    /// for all of them: `None` for every namespace, VMs no spec wants
+    /// included.
+    ///
+    /// # Errors
+    ///
+    /// - if `requested` is invalid or outside the token's namespace
+    pub fn view(&self, requested: &str) -> Result<Option<String>, Denied> {
+        let requested = if requested.is_empty() {
+            None
+        } else {
+            Some(namespace(requested).map_err(Denied::Invalid)?)
+        };
+        match (&self.namespace, requested) {
+            (None, requested) => Ok(requested),
+            (Some(own), None) => Ok(Some(own.clone())),
+            (Some(own), Some(requested)) if *own == requested => Ok(Some(requested)),
+            (Some(own), Some(requested)) => Err(Denied::Namespace {
+                own: own.clone(),
+                requested,
+            }),
+        }
+    }
+
+    /// Whether it may read cluster-wide state, such as the audit log.
+    ///
+    /// # Errors
+    ///
+    /// - if the token is scoped to a namespace
+    pub fn unscoped(&self) -> Result<(), Denied> {
+        match &self.namespace {
+            Some(own) => Err(Denied::Scoped(own.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether it may change cluster-wide state.
+    ///
+    /// # Errors
+    ///
+    /// - if the token is read-only or scoped to a namespace
+    pub fn may_write(&self) -> Result<(), Denied> {
+        self.unscoped()?;
+        if self.read_only {
+            return Err(Denied::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Publisher of a generation `peer` publishes with this grant: the
+    /// token holder, or while no token is configured the name the caller
+    /// `claimed`, falling back to `peer`.
+    #[must_use]
+    pub fn publisher(&self, claimed: Option<&str>, peer: &str) -> String {
+        match (&self.name, claimed) {
+            (Some(name), _) => name.clone(),
+            (None, Some(claimed)) if !claimed.is_empty() => claimed.to_string(),
+            (None, _) => peer.to_string(),
+        }
+    }
+
+    /// Actor of audited calls made by `peer` with this grant.
+    #[must_use]
+    pub fn actor(&self, peer: &str) -> String {
+        match &self.name {
+            Some(name) => format!("{name}@{peer}"),
+            None => peer.to_string(),
+        }
+    }
+}
+
+/// Why a caller is refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denied {
+    /// Tokens are configured and none was presented
+    Missing,
+    /// The token is not one of them
+    Unknown,
+    ReadOnly,
+    /// The token only sees this namespace
+    Scoped(String),
+    /// Another namespace than the token's was asked for
+    Namespace {
+        own: String,
+        requested: String,
+    },
+    /// The requested namespace is invalid
+    Invalid(String),
+}
+
+impl Denied {
+    /// Whether the caller is unknown, as opposed to known and not allowed.
+    #[must_use]
+    pub fn unauthenticated(&self) -> bool {
+        matches!(self, Denied::Missing | Denied::Unknown)
+    }
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Denied::Missing => f.write_str("unauthenticated: a token is required"),
+            Denied::Unknown => f.write_str("unauthenticated: unknown token"),
+            Denied::ReadOnly => f.write_str("forbidden: the token is read-only"),
+            Denied::Scoped(own) => write!(
+                f,
+                "forbidden: the token is scoped to namespace {own}, not the whole cluster"
+            ),
+            Denied::Namespace { own, requested } => write!(
+                f,
+                "forbidden: the token is scoped to namespace {own}, not {requested}"
+            ),
+            Denied::Invalid(e) => f.write_str(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens() -> Tokens {
+        let raw = r#"[
+            {"name": "ops", "token": "ops-secret"},
+            {"name": "auditor", "token": "audit-secret", "readOnly": true},
+            {"name": "team-a", "token": "a-secret", "namespace": "team-a", "readOnly": true}
+        ]"#;
+        Tokens {
+            entries: serde_json::from_str(raw).unwrap(),
+        }
+    }
+
+    #[test]
+    fn namespaces_default_and_must_be_dns_labels() {
+        assert_eq!(namespace("").unwrap(), DEFAULT_NAMESPACE);
+        assert_eq!(namespace("team-a").unwrap(), "team-a");
+        assert!(namespace("Team-A").is_err());
+        assert!(namespace("-team").is_err());
+        assert!(namespace("team.a").is_err());
+        assert!(namespace(&"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn without_tokens_everyone_sees_and_changes_everything() {
+        let grant = Tokens::default().authenticate(None).unwrap();
+        assert_eq!(grant, Grant::open());
+        assert_eq!(grant.view("").unwrap(), None);
+        assert_eq!(grant.view("team-b").unwrap(), Some("team-b".to_string()));
+        assert!(grant.may_write().is_ok());
+        assert_eq!(grant.publisher(Some("ci"), "http:peer"), "ci");
+        assert_eq!(grant.publisher(Some(""), "http:peer"), "http:peer");
+    }
+
+    #[test]
+    fn scoped_tokens_only_see_their_namespace() {
+        let tokens = tokens();
+        assert_eq!(tokens.authenticate(None), Err(Denied::Missing));
+        assert_eq!(tokens.authenticate(Some("nope")), Err(Denied::Unknown));
+
+        let team = tokens.authenticate(Some("a-secret")).unwrap();
+        assert_eq!(team.view("").unwrap(), Some("team-a".to_string()));
+        assert_eq!(team.view("team-a").unwrap(), Some("team-a".to_string()));
+        assert!(matches!(team.view("team-b"), Err(Denied::Namespace { .. })));
+        assert_eq!(team.unscoped(), Err(Denied::Scoped("team-a".to_string())));
+        assert_eq!(team.may_write(), Err(Denied::Scoped("team-a".to_string())));
+        assert_eq!(
+            team.actor("http:10.0.0.1:4000"),
+            "team-a@http:10.0.0.1:4000"
+        );
+
+        let auditor = tokens.authenticate(Some("audit-secret")).unwrap();
+        assert!(auditor.unscoped().is_ok());
+        assert_eq!(auditor.may_write(), Err(Denied::ReadOnly));
+
+        let ops = tokens.authenticate(Some("ops-secret")).unwrap();
+        assert_eq!(ops.view("").unwrap(), None);
+        assert!(ops.may_write().is_ok());
+        // Token holders cannot publish under another name
+        assert_eq!(ops.publisher(Some("ci"), "http:peer"), "ops");
+    }
+
+    #[test]
+    fn token_files_are_validated() {
+        let dir = std::env::temp_dir().join(format!("tenancy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tokens.json");
+
+        std::fs::write(
+            &path,
+            r#"[{"name": "a", "token": "x", "namespace": "Team"}]"#,
+        )
+        .unwrap();
+        assert!(Tokens::load(&path).is_err());
+        std::fs::write(
+            &path,
+            r#"[{"name": "a", "token": "x"}, {"name": "b", "token": "x"}]"#,
+        )
+        .unwrap();
+        assert!(Tokens::load(&path).is_err());
+        std::fs::write(
+            &path,
+            r#"[{"name": "a", "token": "x", "namespace": "team"}]"#,
+        )
+        .unwrap();
+        assert!(Tokens::load(&path).unwrap().enabled());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,569 @@
+//! Webhooks notifying external systems of cluster events, so alerting and
+//! chatops can react without polling the API.
+//!
+//! Webhooks are read from a JSON file kept outside the Nix store, since it
+//! holds their secrets:
+//!
+//! ```json
+//! [
+//!   {"name": "alerts", "url": "http://127.0.0.1:9093/procurator", "secret": "…",
+//!    "events": ["vm.failed", "worker.unhealthy"]},
+//!   {"name": "chatops", "url": "http://chatops.internal/hooks/pcr", "secret": "…"}
+//! ]
+//! ```
+//!
+//! A webhook without `events` receives all of them:
+//!
+//! - `generation.applied` — a generation was made active, on publish or
+//!   when a maintenance window released it.
+//! - `convergence.reached` — every VM of the active generation runs; sent
+//!   once per generation.
+//! - `vm.failed` — a VM missed the convergence deadline, with the
+//!   [diagnosis](crate::convergence) of why. Sent again only when the cause
+//!   changes.
+//! - `worker.unhealthy` — a worker stopped reporting for
+//!   [`STALE_AFTER`](crate::convergence::STALE_AFTER). Sent again only once
+//!   it reported in between.
+//!
+//! Each event is a `POST` of `{"id", "timestampMs", "event", "data"}` with
+//! an `X-Procurator-Event` header naming it, an `X-Procurator-Delivery`
+//! header repeating the id, and an `X-Procurator-Signature` header,
+//! `sha256=<hex>`, the HMAC-SHA256 of the body under the webhook's secret.
+//! Receivers should check the signature and drop ids they already handled:
+//! a delivery that failed with a connection error, a timeout, a `408`, a
+//! `429` or a `5xx` is retried up to [`MAX_ATTEMPTS`] times with the same
+//! id, backing off exponentially. Other statuses are not retried.
+//!
+//! Deliveries run in the background, so a slow receiver never holds the
+//! master up, and events may arrive out of order: order them by
+//! `timestampMs`. URLs are plain `http://`; reach a TLS endpoint through a
+//! local proxy.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::{debug, warn};
+
+use crate::convergence::{ClusterView, Diagnostic};
+use crate::intake::Publication;
+
+/// Attempts per delivery, the first included.
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry, doubled before each next one.
+const RETRY_BASE: Duration = Duration::from_secs(1);
+
+/// Longest a receiver may take to answer one attempt.
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Kinds of events a webhook subscribes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EventKind {
+    #[serde(rename = "generation.applied")]
+    GenerationApplied,
+    #[serde(rename = "convergence.reached")]
+    ConvergenceReached,
+    #[serde(rename = "vm.failed")]
+    VmFailed,
+    #[serde(rename = "worker.unhealthy")]
+    WorkerUnhealthy,
+}
+
+impl EventKind {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::GenerationApplied => "generation.applied",
+            EventKind::ConvergenceReached => "convergence.reached",
+            EventKind::VmFailed => "vm.failed",
+            EventKind::WorkerUnhealthy => "worker.unhealthy",
+        }
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Something that happened in the cluster, as sent to webhooks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", content = "data")]
+pub enum ClusterEvent {
+    #[serde(rename = "generation.applied", rename_all = "camelCase")]
+    GenerationApplied {
+        generation: u64,
+        commit: String,
+        publisher: String,
+        vms: usize,
+    },
+    #[serde(rename = "convergence.reached", rename_all = "camelCase")]
+    ConvergenceReached {
+        generation: u64,
+        commit: String,
+        vms: usize,
+    },
+    #[serde(rename = "vm.failed", rename_all = "camelCase")]
+    VmFailed {
+        /// Worker VM id, or spec hash while no worker reports it
+        vm: String,
+        generation: u64,
+        reason: String,
+        message: String,
+    },
+    #[serde(rename = "worker.unhealthy", rename_all = "camelCase")]
+    WorkerUnhealthy {
+        worker_id: String,
+        /// Generation of its last report
+        generation: u64,
+        running_vms: usize,
+    },
+}
+
+impl ClusterEvent {
+    /// `generation.applied` for `publication`, wanting `vms` VMs.
+    #[must_use]
+    pub fn applied(publication: &Publication, vms: usize) -> Self {
+        ClusterEvent::GenerationApplied {
+            generation: publication.generation,
+            commit: publication.commit.clone(),
+            publisher: publication.publisher.clone(),
+            vms,
+        }
+    }
+
+    /// `vm.failed` for a VM diagnosed under `vm`.
+    #[must_use]
+    pub fn failed(vm: &str, diagnostic: &Diagnostic) -> Self {
+        ClusterEvent::VmFailed {
+            vm: vm.to_string(),
+            generation: diagnostic.generation,
+            reason: diagnostic.reason.to_string(),
+            message: diagnostic.message.clone(),
+        }
+    }
+
+    #[must_use]
+    pub fn kind(&self) -> EventKind {
+        match self {
+            ClusterEvent::GenerationApplied { .. } => EventKind::GenerationApplied,
+            ClusterEvent::ConvergenceReached { .. } => EventKind::ConvergenceReached,
+            ClusterEvent::VmFailed { .. } => EventKind::VmFailed,
+            ClusterEvent::WorkerUnhealthy { .. } => EventKind::WorkerUnhealthy,
+        }
+    }
+}
+
+/// Body of a delivery, the same for every attempt.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Payload<'a> {
+    id: String,
+    timestamp_ms: u64,
+    #[serde(flatten)]
+    event: &'a ClusterEvent,
+}
+
+/// One entry of the webhook file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct HookEntry {
+    /// Shown in logs, never sent
+    name: String,
+    url: String,
+    secret: String,
+    /// Every kind when empty
+    #[serde(default)]
+    events: Vec<EventKind>,
+}
+
+#[derive(Debug)]
+struct Hook {
+    name: String,
+    url: Url,
+    secret: String,
+    events: Vec<EventKind>,
+}
+
+impl Hook {
+    fn wants(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+/// Configured webhooks. Empty when no webhook file is configured.
+#[derive(Debug, Clone, Default)]
+pub struct Webhooks {
+    hooks: Arc<[Hook]>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    /// Read the webhook file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// - if it cannot be read or is not a list of webhook entries
+    /// - if a name is listed twice, a secret is empty or a URL is not
+    ///   `http://`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        };
+        let raw = std::fs::read(path)?;
+        let entries: Vec<HookEntry> =
+            serde_json::from_slice(&raw).map_err(|e| invalid(e.to_string()))?;
+        let mut seen = HashSet::new();
+        let mut hooks = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if !seen.insert(entry.name.clone()) {
+                return Err(invalid(format!("webhook {} is listed twice", entry.name)));
+            }
+            if entry.secret.is_empty() {
+                return Err(invalid(format!(
+                    "webhook {} has an empty secret",
+                    entry.name
+                )));
+            }
+            let url = Url::parse(&entry.url)
+                .map_err(|e| invalid(format!("webhook {}: {e}", entry.name)))?;
+            if url.scheme() != "http" {
+                return Err(invalid(format!(
+                    "webhook {}: only http:// URLs are supported, reach {} through a local proxy",
+                    entry.name, entry.url
+                )));
+            }
+            hooks.push(Hook {
+                name: entry.name,
+                url,
+                secret: entry.secret,
+                events: entry.events,
+            });
+        }
+        let client = reqwest::Client::builder()
+            .timeout(ATTEMPT_TIMEOUT)
+            .build()
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(Self {
+            hooks: hooks.into(),
+            client,
+        })
+    }
+
+    /// Whether any webhook is configured.
+    #[must_use]
+    pub fn enabled(&self) -> bool {
+        !self.hooks.is_empty()
+    }
+
+    /// Deliver `event` in the background to every webhook subscribed to it.
+    pub fn notify(&self, event: &ClusterEvent) {
+        let kind = event.kind();
+        let mut hooks = self.hooks.iter().enumerate().filter(|(_, h)| h.wants(kind));
+        let Some(first) = hooks.next() else {
+            return;
+        };
+        let payload = Payload {
+            id: uuid::Uuid::now_v7().to_string(),
+            timestamp_ms: crate::convergence::now_ms(),
+            event,
+        };
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => Arc::<[u8]>::from(body),
+            Err(e) => {
+                warn!(event = %kind, error = %e, "Cannot encode webhook payload");
+                return;
+            }
+        };
+        for (index, _) in std::iter::once(first).chain(hooks) {
+            tokio::spawn(deliver(
+                self.clone(),
+                index,
+                payload.id.clone(),
+                kind,
+                Arc::clone(&body),
+            ));
+        }
+    }
+}
+
+/// `sha256=<hex>` HMAC-SHA256 of `body` under `secret`.
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    use std::fmt::Write;
+
+    // HMAC takes keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(body);
+    let mut out = String::from("sha256=");
+    for byte in mac.finalize().into_bytes() {
+        let _ = write!(out, "{byte:02x}");
+    }
+    out
+}
+
+/// Whether an attempt answered with `status` is worth repeating.
+fn retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Post `body` to the `index`th webhook until it is accepted, refused or
+/// [`MAX_ATTEMPTS`] are used up.
+async fn deliver(webhooks: Webhooks, index: usize, id: String, kind: EventKind, body: Arc<[u8]>) {
+    let hook = &webhooks.hooks[index];
+    let signature = sign(hook.secret.as_bytes(), &body);
+    let mut wait = RETRY_BASE;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let sent = webhooks
+            .client
+            .post(hook.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Procurator-Event", kind.as_str())
+            .header("X-Procurator-Delivery", &id)
+            .header("X-Procurator-Signature", &signature)
+            .body(body.to_vec())
+            .send()
+            .await;
+        let error = match sent {
+            Ok(response) if response.status().is_success() => {
+                debug!(webhook = %hook.name, event = %kind, delivery = %id, attempt, "Webhook delivered");
+                return;
+            }
+            Ok(response) if !retryable(response.status()) => {
+                warn!(
+                    webhook = %hook.name,
+                    event = %kind,
+                    delivery = %id,
+                    status = %response.status(),
+                    "Webhook refused the event"
+                );
+                return;
+            }
+            Ok(response) => response.status().to_string(),
+            Err(e) => e.to_string(),
+        };
+        if attempt == MAX_ATTEMPTS {
+            warn!(webhook = %hook.name, event = %kind, delivery = %id, error, "Webhook delivery abandoned");
+            return;
+        }
+        debug!(webhook = %hook.name, event = %kind, delivery = %id, attempt, error, ?wait, "Webhook delivery failed, retrying");
+        tokio::time::sleep(wait).await;
+        wait *= 2;
+    }
+}
+
+/// Turns successive [cluster views](ClusterView) into the events that are
+/// not tied to a single call: convergence reached and workers gone silent.
+#[derive(Debug, Default)]
+pub struct ClusterWatch {
+    /// Generation whose convergence was already sent
+    converged: Option<u64>,
+    /// Workers already sent as unhealthy, until they report again
+    unhealthy: HashSet<String>,
+}
+
+impl ClusterWatch {
+    /// Events since the previous view.
+    pub fn changes(&mut self, view: &ClusterView) -> Vec<ClusterEvent> {
+        let mut events = Vec::new();
+        if view.generation != 0
+            && view.convergence_percent == 100
+            && self.converged != Some(view.generation)
+        {
+            self.converged = Some(view.generation);
+            events.push(ClusterEvent::ConvergenceReached {
+                generation: view.generation,
+                commit: view.commit.clone(),
+                vms: view
+                    .vms
+                    .iter()
+                    .filter(|vm| !vm.desired_hash.is_empty())
+                    .count(),
+            });
+        }
+        for worker in &view.workers {
+            if worker.healthy {
+                self.unhealthy.remove(&worker.id);
+            } else if self.unhealthy.insert(worker.id.clone()) {
+                events.push(ClusterEvent::WorkerUnhealthy {
+                    worker_id: worker.id.clone(),
+                    generation: worker.generation,
+                    running_vms: worker.running_vms,
+                });
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convergence::{VmMetrics, VmSummary, WorkerSummary};
+    use commands::labels::Labels;
+    use commands::vm_state::VmState;
+
+    fn load(raw: &str) -> io::Result<Webhooks> {
+        let path = std::env::temp_dir().join(format!(
+            "pcr-webhooks-{}-{}.json",
+            std::process::id(),
+            raw.len()
+        ));
+        std::fs::write(&path, raw).unwrap();
+        let loaded = Webhooks::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    fn worker(id: &str, healthy: bool) -> WorkerSummary {
+        WorkerSummary {
+            id: id.to_string(),
+            healthy,
+            generation: 3,
+            running_vms: 1,
+            net_backends: Vec::new(),
+        }
+    }
+
+    fn vm(id: &str, status: VmState) -> VmSummary {
+        VmSummary {
+            id: id.to_string(),
+            worker_id: "w1".to_string(),
+            namespace: "default".to_string(),
+            desired_hash: format!("hash-{id}"),
+            observed_hash: format!("hash-{id}"),
+            status,
+            drifted: false,
+            labels: Labels::default(),
+            metrics: VmMetrics::default(),
+        }
+    }
+
+    #[test]
+    fn webhook_files_are_validated() {
+        let hooks = load(
+            r#"[
+                {"name": "alerts", "url": "http://127.0.0.1:9093/pcr", "secret": "s", "events": ["vm.failed"]},
+                {"name": "chatops", "url": "http://chatops.internal/hooks", "secret": "t"}
+            ]"#,
+        )
+        .unwrap();
+        assert!(hooks.enabled());
+        assert!(!hooks.hooks[0].wants(EventKind::GenerationApplied));
+        assert!(hooks.hooks[0].wants(EventKind::VmFailed));
+        assert!(hooks.hooks[1].wants(EventKind::WorkerUnhealthy));
+        assert!(!Webhooks::default().enabled());
+
+        for invalid in [
+            r#"[{"name": "a", "url": "https://example.com", "secret": "s"}]"#,
+            r#"[{"name": "a", "url": "http://example.com", "secret": ""}]"#,
+            r#"[{"name": "a", "url": "not a url", "secret": "s"}]"#,
+            r#"[{"name": "a", "url": "http://example.com", "secret": "s", "events": ["vm.exploded"]}]"#,
+            r#"[{"name": "a", "url": "http://a", "secret": "s"}, {"name": "a", "url": "http://b", "secret": "t"}]"#,
+        ] {
+            assert!(load(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn signatures_are_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn payloads_name_the_event_and_nest_its_data() {
+        let event = ClusterEvent::WorkerUnhealthy {
+            worker_id: "w1".to_string(),
+            generation: 3,
+            running_vms: 2,
+        };
+        let payload = Payload {
+            id: "d1".to_string(),
+            timestamp_ms: 42,
+            event: &event,
+        };
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            serde_json::json!({
+                "id": "d1",
+                "timestampMs": 42,
+                "event": "worker.unhealthy",
+                "data": {"workerId": "w1", "generation": 3, "runningVms": 2},
+            })
+        );
+    }
+
+    #[test]
+    fn only_server_errors_and_throttling_are_retried() {
+        assert!(retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(retryable(StatusCode::REQUEST_TIMEOUT));
+        assert!(!retryable(StatusCode::UNAUTHORIZED));
+        assert!(!retryable(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn convergence_and_unhealthy_workers_are_sent_once() {
+        let mut watch = ClusterWatch::default();
+        let mut view = ClusterView {
+            generation: 3,
+            commit: "abc".to_string(),
+            convergence_percent: 50,
+            workers: vec![worker("w1", true)],
+            vms: vec![vm("a", VmState::Running), vm("b", VmState::Pending)],
+            unschedulable: Vec::new(),
+        };
+        assert!(watch.changes(&view).is_empty());
+
+        view.convergence_percent = 100;
+        view.vms[1].status = VmState::Running;
+        assert_eq!(
+            watch.changes(&view),
+            vec![ClusterEvent::ConvergenceReached {
+                generation: 3,
+                commit: "abc".to_string(),
+                vms: 2,
+            }]
+        );
+        assert!(watch.changes(&view).is_empty());
+
+        view.workers[0].healthy = false;
+        assert_eq!(
+            watch.changes(&view),
+            vec![ClusterEvent::WorkerUnhealthy {
+                worker_id: "w1".to_string(),
+                generation: 3,
+                running_vms: 1,
+            }]
+        );
+        assert!(watch.changes(&view).is_empty(), "still unhealthy");
+        view.workers[0].healthy = true;
+        assert!(watch.changes(&view).is_empty());
+        view.workers[0].healthy = false;
+        assert_eq!(watch.changes(&view).len(), 1, "unhealthy again");
+
+        view.generation = 4;
+        view.workers[0].healthy = true;
+        assert_eq!(
+            watch.changes(&view)[0].kind(),
+            EventKind::ConvergenceReached
+        );
+    }
+}
@@ -0,0 +1,72 @@
+# withVmBoot — Override how a vmSpec boots, without rebuilding its image.
+#
+# Takes a vmSpec (from mkVmImage, mkClosureVmSpec or mkSandbox) and
+# returns it with another kernel, initramfs or kernel command line. The
+# image, the toplevel and everything else are kept, so a VM can boot a
+# debug kernel or with `loglevel=7` while every other VM of the image
+# boots as built.
+#
+# Usage:
+#   withVmBoot = import ./boot {};
+#   spec = withVmBoot vm.vmSpec {
+#     extraCmdline = [ "loglevel=7" "systemd.log_level=debug" ];
+#   };
+#
+# Fields left null keep the image's value:
+#   kernel       — kernel to boot (path or derivation) → kernelPath
+#   initramfs    — initramfs to boot (path or derivation) → initrdPath
+#   cmdline      — replaces the whole kernel command line
+#   extraCmdline — parameters appended to the command line (e.g. console=,
+#                  loglevel=); the kernel takes the last of duplicates
+
+{}: # No dependencies — this is pure data
+
+# withVmBoot function
+vmSpec:
+{
+  kernel ? null,
+  initramfs ? null,
+  cmdline ? null,
+  extraCmdline ? [],
+}:
+
+let
+  # ── Validation ─────────────────────────────────────────────────────
+  assertNullOrPath = name: val:
+    if val != null && !(builtins.isString val || builtins.isPath val || val ? outPath) then
+      builtins.throw "withVmBoot: ${name} must be a path, a derivation or null, got ${builtins.typeOf val}"
+    else
+      true;
+
+  assertNullOrString = name: val:
+    if val != null && !(builtins.isString val) then
+      builtins.throw "withVmBoot: ${name} must be a string or null, got ${builtins.typeOf val}"
+    else
+      true;
+
+  assertStrings = name: val:
+    if !(builtins.isList val && builtins.all builtins.isString val) then
+      builtins.throw "withVmBoot: ${name} must be a list of strings"
+    else
+      true;
+
+  validated =
+    assert (vmSpec ? cmdline)
+      || builtins.throw "withVmBoot: first argument must be a vmSpec";
+    assert assertNullOrPath "kernel" kernel;
+    assert assertNullOrPath "initramfs" initramfs;
+    assert assertNullOrString "cmdline" cmdline;
+    assert assertStrings "extraCmdline" extraCmdline;
+    true;
+
+  baseCmdline = if cmdline != null then cmdline else vmSpec.cmdline;
+
+in
+  assert validated;
+  vmSpec
+  // (if kernel != null then { kernelPath = toString kernel; } else {})
+  // (if initramfs != null then { initrdPath = toString initramfs; } else {})
+  // {
+    cmdline = builtins.concatStringsSep " "
+      (builtins.filter (p: p != "") ([ baseCmdline ] ++ extraCmdline));
+  }
@@ -62,6 +62,8 @@ let
       };
       role = vm.role or "worker";
       labels = vm.labels or [];
+      pinnedWorker = vm.pinnedWorker or null;
+      namespace = vm.namespace or null;
       replicas = vm.replicas or 1;
 
       # Derived from profile — for scheduling decisions
@@ -4,6 +4,9 @@
 #   mkVmProfile  — Pure validation + normalization of VM guest config
 #   mkVmImage    — Build NixOS disk image + vmSpec from a profile
 #   mkVmSpecJson — Convenience: build image and return just the JSON spec
+#   mkClosureVmSpec    — vmSpec booting a NixOS system straight from its closure
+#   closureGuestModule — NixOS module for guests booted that way
+#   withVmBoot   — Override a vmSpec's kernel, initramfs or cmdline
 #   evalCluster  — Validate cluster topology with profile references
 #   mkSandbox    — Dockerfile-like API for building CH sandbox VMs
 #
@@ -19,11 +22,43 @@ let
   mkVmImage = import ./image { inherit pkgs nixpkgs system; };
   mkSandbox = import ./sandbox { inherit pkgs nixpkgs system; };
   evalCluster = import ./cluster {};
+  withVmBoot = import ./boot {};
   # export diskVm as a function that merges caller args with the library defaults
   diskVm = args: import ./diskVm.nix ( { inherit pkgs nixpkgs system; } // args );
 in {
-  inherit mkVmProfile mkVmImage mkSandbox evalCluster diskVm;
+  inherit mkVmProfile mkVmImage mkSandbox evalCluster withVmBoot diskVm;
 
   # Convenience: build a VM image and return just the JSON spec derivation.
   mkVmSpecJson = args: (mkVmImage args).vmSpecJson;
+
+  closureGuestModule = ./image/closure-module.nix;
+
+  # vmSpec for a nixosConfigurations entry (built with closureGuestModule),
+  # booted from its toplevel without a disk image. The worker takes the
+  # kernel, initrd and kernel-params from the closure itself, unless
+  # kernel or initramfs name others to boot.
+  # hugepages backs the guest memory with the worker's hugepages, which
+  # must have memoryMb free; sharedMemory maps it shared for vhost-user
+  # devices (closures always share it for the store's virtio-fs).
+  # netBackend "vhost-user" attaches the NIC to the worker's host switch
+  # instead of a TAP, and only runs on workers set up for it.
+  mkClosureVmSpec = {
+    nixos,
+    cpu ? 1,
+    memoryMb ? 512,
+    allowedDomains ? [],
+    kernel ? null,
+    initramfs ? null,
+    cmdline ? "",
+    hugepages ? false,
+    sharedMemory ? false,
+    netBackend ? "tap",
+  }: {
+    toplevel = toString nixos.config.system.build.toplevel;
+    kernelPath = if kernel != null then toString kernel else "";
+    initrdPath = if initramfs != null then toString initramfs else "";
+    diskImagePath = "";
+    inherit cmdline cpu memoryMb hugepages sharedMemory netBackend;
+    networkAllowedDomains = allowedDomains;
+  };
 }
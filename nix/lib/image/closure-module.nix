@@ -0,0 +1,32 @@
+# closure-module.nix — NixOS guest module for VMs booted from their closure.
+#
+# The worker boots the system's own kernel and initrd (init=<toplevel>/init)
+# and shares the host /nix/store read-only over virtio-fs under the tag
+# "nix-store". There is no disk: the root is a tmpfs and the store comes
+# from the host, so nothing the guest writes survives a restart.
+#
+# Usage (in a nixosConfigurations entry):
+#   modules = [ procurator.lib.${system}.closureGuestModule ./configuration.nix ];
+{lib, ...}: {
+  boot = {
+    initrd.availableKernelModules = ["virtio_pci" "virtiofs"];
+    kernelParams = ["console=ttyS0"];
+    loader.grub.enable = false;
+  };
+
+  fileSystems."/" = lib.mkForce {
+    device = "none";
+    fsType = "tmpfs";
+    options = ["mode=0755" "size=50%"];
+  };
+
+  fileSystems."/nix/store" = {
+    device = "nix-store";
+    fsType = "virtiofs";
+    options = ["ro"];
+    neededForBoot = true;
+  };
+
+  # The shared store has no database in the guest
+  nix.enable = lib.mkDefault false;
+}
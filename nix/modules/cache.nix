@@ -35,6 +35,122 @@ in {
       description = "Maximum size for the cache storage (e.g., 50G, 100G).";
     };
 
+    narinfoCacheEntries = mkOption {
+      type = types.ints.unsigned;
+      default = 10000;
+      description = "Narinfo responses and missing hashes kept in memory; 0 disables both caches.";
+    };
+
+    negativeTtl = mkOption {
+      type = types.ints.unsigned;
+      default = 60;
+      description = "Seconds a hash missing from the store is answered 404 without asking the store again.";
+    };
+
+    priority = mkOption {
+      type = types.ints.unsigned;
+      default = 30;
+      description = "Priority advertised in /nix-cache-info to clients outside every clientPriorities subnet; lower is tried first.";
+    };
+
+    clientPriorities = mkOption {
+      type = types.attrsOf types.ints.unsigned;
+      default = {};
+      example = { "10.1.0.0/16" = 10; "10.2.0.0/16" = 50; };
+      description = "Priority advertised per client subnet; the longest matching prefix wins.";
+    };
+
+    siblings = mkOption {
+      type = types.listOf types.str;
+      default = [];
+      example = [ "http://cache.site-b:8081" ];
+      description = "Sibling caches asked, in parallel, for paths missing from the local store.";
+    };
+
+    writeThrough = mkOption {
+      type = types.bool;
+      default = false;
+      description = "Copy paths found in a sibling into the local store.";
+    };
+
+    chunkStore = mkOption {
+      type = types.bool;
+      default = false;
+      description = ''
+        Accept uploads (nix copy --to http://...) into a deduplicating chunk
+        store under storageDir/chunks. Without uploadTokenFile, only
+        clients on this host may upload or delete.
+      '';
+    };
+
+    uploadTokenFile = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      description = ''
+        File holding the token uploads and deletes must carry, as a bearer
+        token or the basic auth password (netrc); kept out of the Nix store.
+      '';
+    };
+
+    buildLogs = mkOption {
+      type = types.bool;
+      default = true;
+      description = ''
+        Keep the build logs served on /log/ gzipped under storageDir/logs,
+        so each is fetched with nix log once.
+      '';
+    };
+
+    logFetches = mkOption {
+      type = types.ints.positive;
+      default = 4;
+      description = "nix log processes run at once for /log/ requests.";
+    };
+
+    secretKeyFile = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/run/keys/cache-private.pem";
+      description = "Signing key (nix-store --generate-binary-cache-key) of the local signer; kept out of the Nix store.";
+    };
+
+    signer = mkOption {
+      type = types.str;
+      default = "local";
+      example = "unix:/run/procurator-signer/signer.sock";
+      description = ''
+        Who signs narinfos: local (secretKeyFile), an http(s) URL or
+        unix:<socket> of a signer service, or pkcs11:<module path> for a key
+        on an HSM or token, used through pkcs11-tool.
+      '';
+    };
+
+    pkcs11 = {
+      keyId = mkOption {
+        type = types.str;
+        default = "";
+        example = "01";
+        description = "Hex id of the Ed25519 key on the token.";
+      };
+      keyName = mkOption {
+        type = types.str;
+        default = "";
+        example = "cache.example.com-1";
+        description = "Key name written in signatures, as clients list it in trusted-public-keys.";
+      };
+      pinFile = mkOption {
+        type = types.nullOr types.str;
+        default = null;
+        description = "File holding the token's user PIN; kept out of the Nix store.";
+      };
+    };
+
+    signatureCacheEntries = mkOption {
+      type = types.ints.unsigned;
+      default = 10000;
+      description = "Signatures kept in memory per fingerprint, so the signer is asked once per path.";
+    };
+
     user = mkOption {
       type = types.str;
       default = "procurator-cache";
@@ -78,8 +194,29 @@ in {
         CACHE_STORAGE_DIR = cfg.storageDir;
         CACHE_MAX_SIZE = cfg.maxSize;
         CACHE_ADDR = cfg.addr;
+        CACHE_NARINFO_ENTRIES = toString cfg.narinfoCacheEntries;
+        CACHE_NEGATIVE_TTL_SECS = toString cfg.negativeTtl;
+        CACHE_PRIORITY = toString cfg.priority;
+        CACHE_CLIENT_PRIORITIES = concatStringsSep "," (mapAttrsToList (subnet: p: "${subnet}=${toString p}") cfg.clientPriorities);
+        CACHE_SIBLINGS = concatStringsSep "," cfg.siblings;
+        CACHE_WRITE_THROUGH = boolToString cfg.writeThrough;
+        CACHE_CHUNK_DIR = optionalString cfg.chunkStore "${cfg.storageDir}/chunks";
+        CACHE_LOG_DIR = optionalString cfg.buildLogs "${cfg.storageDir}/logs";
+        CACHE_LOG_FETCHES = toString cfg.logFetches;
+        CACHE_SIGNER = cfg.signer;
+        CACHE_SIGNATURE_ENTRIES = toString cfg.signatureCacheEntries;
+        CACHE_PKCS11_KEY_ID = cfg.pkcs11.keyId;
+        CACHE_SIGNING_KEY_NAME = cfg.pkcs11.keyName;
+      } // optionalAttrs (cfg.secretKeyFile != null) {
+        NIX_SECRET_KEY_FILE = cfg.secretKeyFile;
+      } // optionalAttrs (cfg.pkcs11.pinFile != null) {
+        CACHE_PKCS11_PIN_FILE = cfg.pkcs11.pinFile;
+      } // optionalAttrs (cfg.uploadTokenFile != null) {
+        CACHE_UPLOAD_TOKEN_FILE = cfg.uploadTokenFile;
       };
 
+      path = optional (hasPrefix "pkcs11:" cfg.signer) pkgs.opensc;
+
       serviceConfig = {
         Type = "simple";
         User = cfg.user;
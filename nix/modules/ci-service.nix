@@ -42,12 +42,54 @@ in {
       description = "URL of the binary cache service. If null, cache integration is disabled.";
     };
 
+    repohubUrl = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "http://localhost:3001";
+      description = ''
+        Repohub checking API tokens and holding the repositories' CI
+        settings: builds are only shown to users who may see their
+        repository, enqueuing, retrying and canceling builds needs write
+        access, and only the branches the settings monitor are built. If
+        null, the API and pages are open to anyone and every push is built.
+      '';
+    };
+
     maxConcurrentBuilds = mkOption {
       type = types.int;
       default = 4;
       description = "Maximum number of concurrent build jobs.";
     };
 
+    vulnScan = {
+      enable = mkEnableOption "scanning every build's SBOM against the OSV advisory database";
+
+      advisoriesDir = mkOption {
+        type = types.path;
+        default = "/var/lib/procurator-ci/advisories";
+        description = "Offline mirror of the OSV database, one directory per ecosystem.";
+      };
+
+      ecosystems = mkOption {
+        type = types.listOf types.str;
+        default = [ "crates.io" "npm" "PyPI" "Go" ];
+        description = "OSV ecosystems mirrored into advisoriesDir.";
+      };
+
+      failOn = mkOption {
+        type = types.nullOr (types.enum [ "unknown" "low" "moderate" "high" "critical" ]);
+        default = null;
+        example = "high";
+        description = "Fail builds with findings of this severity or above. If null, findings are only reported.";
+      };
+
+      syncInterval = mkOption {
+        type = types.str;
+        default = "daily";
+        description = "How often the advisory mirror is refreshed, as a systemd calendar expression.";
+      };
+    };
+
     user = mkOption {
       type = types.str;
       default = "procurator-ci";
@@ -80,12 +122,15 @@ in {
     systemd.tmpfiles.rules = [
       "d /var/lib/procurator-ci 0750 ${cfg.user} ${cfg.group} -"
       "d ${cfg.workDir} 0750 ${cfg.user} ${cfg.group} -"
-    ];
+    ] ++ optional cfg.vulnScan.enable
+      "d ${cfg.vulnScan.advisoriesDir} 0750 ${cfg.user} ${cfg.group} -";
 
     systemd.services.procurator-ci = {
       description = "Procurator CI/CD Service";
       wantedBy = [ "multi-user.target" ];
       after = [ "network.target" ];
+      # git and tar export each build's commit for its SBOM
+      path = [ pkgs.git pkgs.gnutar ];
 
       environment = {
         CI_ADDR = cfg.addr;
@@ -94,6 +139,12 @@ in {
         CI_MAX_CONCURRENT_BUILDS = toString cfg.maxConcurrentBuilds;
       } // optionalAttrs (cfg.cacheUrl != null) {
         CI_CACHE_URL = cfg.cacheUrl;
+      } // optionalAttrs (cfg.repohubUrl != null) {
+        CI_REPOHUB_URL = cfg.repohubUrl;
+      } // optionalAttrs cfg.vulnScan.enable {
+        CI_ADVISORIES_DIR = cfg.vulnScan.advisoriesDir;
+      } // optionalAttrs (cfg.vulnScan.enable && cfg.vulnScan.failOn != null) {
+        CI_VULN_FAIL_ON = cfg.vulnScan.failOn;
       };
 
       serviceConfig = {
@@ -113,5 +164,47 @@ in {
         StateDirectory = "procurator-ci";
       };
     };
+
+    # Refresh the advisory mirror from the OSV bucket's per-ecosystem dumps,
+    # swapping each ecosystem in whole so a scan never sees half of it
+    systemd.services.procurator-ci-osv-sync = mkIf cfg.vulnScan.enable {
+      description = "Procurator CI OSV advisory mirror sync";
+      after = [ "network-online.target" ];
+      wants = [ "network-online.target" ];
+      path = [ pkgs.curl pkgs.unzip ];
+      script = ''
+        set -eu
+        cd ${cfg.vulnScan.advisoriesDir}
+        for ecosystem in ${escapeShellArgs cfg.vulnScan.ecosystems}; do
+          rm -rf "$ecosystem.new" "$ecosystem.zip"
+          curl -sSfL -o "$ecosystem.zip" \
+            "https://osv-vulnerabilities.storage.googleapis.com/$ecosystem/all.zip"
+          unzip -q -d "$ecosystem.new" "$ecosystem.zip"
+          rm -rf "$ecosystem.old"
+          if [ -d "$ecosystem" ]; then mv "$ecosystem" "$ecosystem.old"; fi
+          mv "$ecosystem.new" "$ecosystem"
+          rm -rf "$ecosystem.old" "$ecosystem.zip"
+        done
+      '';
+      serviceConfig = {
+        Type = "oneshot";
+        User = cfg.user;
+        Group = cfg.group;
+        NoNewPrivileges = true;
+        PrivateTmp = true;
+        ProtectSystem = "strict";
+        ProtectHome = true;
+        ReadWritePaths = [ cfg.vulnScan.advisoriesDir ];
+      };
+    };
+
+    systemd.timers.procurator-ci-osv-sync = mkIf cfg.vulnScan.enable {
+      wantedBy = [ "timers.target" ];
+      timerConfig = {
+        OnCalendar = cfg.vulnScan.syncInterval;
+        OnActiveSec = "1min";
+        Persistent = true;
+      };
+    };
   };
 }
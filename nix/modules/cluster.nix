@@ -106,6 +106,19 @@ in {
             description = "Labels for scheduling and filtering";
           };
 
+          pinnedWorker = mkOption {
+            type = types.nullOr types.str;
+            default = null;
+            description = "Worker this VM must run on and is never moved from, for stateful VMs";
+          };
+
+          namespace = mkOption {
+            type = types.nullOr types.str;
+            default = null;
+            example = "team-a";
+            description = "Tenant namespace of this VM; null for \"default\". Tokens scoped to it only see its VMs";
+          };
+
           replicas = mkOption {
             type = types.int;
             default = 1;
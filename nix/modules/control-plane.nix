@@ -20,6 +20,21 @@ let
         peers_addr = derivedPeers;
       };
     };
+    shutdown = {
+      timeout_secs = cfg.shutdownTimeoutSeconds;
+    };
+    reconcile = {
+      interval_secs = cfg.reconcileIntervalSeconds;
+      jitter_secs = cfg.reconcileJitterSeconds;
+    };
+  } // optionalAttrs (cfg.healthAddr != null) {
+    health_addr = cfg.healthAddr;
+  } // optionalAttrs (cfg.httpAddr != null) {
+    http_addr = cfg.httpAddr;
+  } // optionalAttrs (cfg.tokensFile != null) {
+    tokens_file = cfg.tokensFile;
+  } // optionalAttrs (cfg.webhooksFile != null) {
+    webhooks_file = cfg.webhooksFile;
   });
 in {
   options.services.procurator.control-plane = {
@@ -77,6 +92,73 @@ in {
       default = "procurator-control-plane";
       description = "Group under which the control plane runs.";
     };
+
+    shutdownTimeoutSeconds = mkOption {
+      type = types.ints.positive;
+      default = 30;
+      description = "Max seconds the control plane waits for in-flight work on SIGTERM.";
+    };
+
+    reconcileIntervalSeconds = mkOption {
+      type = types.ints.positive;
+      default = 5;
+      description = ''
+        Seconds between reconciliations: applying a generation held back
+        by maintenance, checking convergence deadlines, expiring
+        reservations and notifying webhooks.
+      '';
+    };
+
+    reconcileJitterSeconds = mkOption {
+      type = types.ints.unsigned;
+      default = 0;
+      description = "Up to this many seconds added at random to every wait between reconciliations.";
+    };
+
+    healthAddr = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "0.0.0.0:8081";
+      description = "Address serving /healthz and /readyz. Null disables the probe listener.";
+    };
+
+    httpAddr = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "127.0.0.1:8082";
+      description = ''
+        Address serving the HTTP/JSON API (/v1/status, /v1/events,
+        /v1/generations, /v1/vms) for dashboards and scripts. Requests are
+        only authenticated with tokensFile set: keep it on a trusted
+        interface otherwise. Null disables it.
+      '';
+    };
+
+    tokensFile = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/run/secrets/procurator-tokens.json";
+      description = ''
+        JSON list of API tokens, kept out of the Nix store:
+        `[{"name": "team-a", "token": "...", "namespace": "team-a", "readOnly": true}]`.
+        A token with a namespace only sees that namespace's VMs in the
+        cluster status; only unscoped tokens that are not read-only may
+        publish or change maintenance policies. Null authenticates nothing.
+      '';
+    };
+
+    webhooksFile = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/run/secrets/procurator-webhooks.json";
+      description = ''
+        JSON list of webhooks notified of cluster events, kept out of the
+        Nix store since it holds their secrets:
+        `[{"name": "alerts", "url": "http://127.0.0.1:9093/pcr", "secret": "...", "events": ["vm.failed"]}]`.
+        Events are generation.applied, convergence.reached, vm.failed and
+        worker.unhealthy, all of them when events is absent. Null sends none.
+      '';
+    };
   };
 
   config = mkIf cfg.enable {
@@ -94,12 +176,17 @@ in {
       after = [ "network.target" ];
 
       serviceConfig = {
+        # `procurator` does not start a role yet, so nothing sends
+        # READY=1 or watchdog pings.
         Type = "simple";
         User = cfg.user;
         Group = cfg.group;
         ExecStart = "${cfg.package}/bin/procurator ${configFile}";
+        # SIGHUP re-reads the config and applies reloadable settings.
+        ExecReload = "${pkgs.coreutils}/bin/kill -HUP $MAINPID";
         Restart = "on-failure";
         RestartSec = "10s";
+        TimeoutStopSec = cfg.shutdownTimeoutSeconds + 5;
 
         # Security hardening
         NoNewPrivileges = true;
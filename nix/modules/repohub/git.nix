@@ -1,14 +1,32 @@
-{pkgs, ...}: let
+{
+  config,
+  lib,
+  pkgs,
+  ...
+}: let
+  cfg = config.services.procurator.repohub;
   gitServerPath = "/var/lib/git-server";
   # TO make it executable
   postReceiveHook = pkgs.writeScript "post-receive" (builtins.readFile ./post-receive);
+  # Defaults for every repository, read by repohub-pre-receive. A repository
+  # overrides them with `git config procurator.<key>`.
+  policyConfig =
+    lib.optionalString (cfg.policy.maxFileSize != null) "    maxFileSize = ${cfg.policy.maxFileSize}\n"
+    + lib.concatMapStrings (pattern: "    forbiddenPath = \"${pattern}\"\n") cfg.policy.forbiddenPaths
+    + lib.optionalString cfg.policy.requireSignedCommits "    requireSignedCommits = true\n"
+    + lib.optionalString (cfg.policy.allowedSigners != null) "    allowedSigners = ${cfg.policy.allowedSigners}\n"
+    + lib.concatMapStrings (branch: "    protectedBranch = \"${branch}\"\n") cfg.policy.protectedBranches
+    + lib.optionalString (cfg.policy.ownerSigners != null) "    ownerSigners = ${cfg.policy.ownerSigners}\n";
   # Create git config file the same way as the hook
-  gitConfig = pkgs.writeText "gitconfig" ''
-    [core]
-        hooksPath = ${gitServerPath}/hooks
-    [safe]
-        directory = *
-  '';
+  gitConfig = pkgs.writeText "gitconfig" (''
+      [core]
+          hooksPath = ${gitServerPath}/hooks
+      [safe]
+          directory = *
+      [receive]
+          advertisePushOptions = true
+    ''
+    + lib.optionalString (policyConfig != "") "[procurator]\n${policyConfig}");
 in {
   users.groups.git = {};
 
@@ -48,6 +66,9 @@ in {
     # Create symlink from hooks directory to the actual post-receive script
     "L+ ${gitServerPath}/hooks/post-receive - - - - /etc/git-server/post-receive"
 
+    # Enforce the push policies before any ref is updated
+    "L+ ${gitServerPath}/hooks/pre-receive - - - - ${cfg.package}/bin/repohub-pre-receive"
+
     # Create symlink to git config file for the git user
     "L+ ${gitServerPath}/.gitconfig - - - - /etc/git-server/gitconfig"
 
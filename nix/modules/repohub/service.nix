@@ -44,6 +44,49 @@ in {
       description = "URL to send webhook notifications (e.g., to CI service). If null, webhooks are disabled.";
     };
 
+    policy = {
+      maxFileSize = mkOption {
+        type = types.nullOr types.str;
+        default = null;
+        example = "50m";
+        description = "Reject pushes bringing in a file larger than this (bytes, or with a k, m or g suffix).";
+      };
+
+      forbiddenPaths = mkOption {
+        type = types.listOf types.str;
+        default = [];
+        example = ["*.pem" ".env" "secrets/*"];
+        description = "Reject pushes bringing in a file matching one of these globs. A pattern without / matches file names anywhere.";
+      };
+
+      requireSignedCommits = mkOption {
+        type = types.bool;
+        default = false;
+        description = "Reject pushes bringing in a commit without a good signature, from a key in allowedSigners or trusted in the git user's GPG keyring.";
+      };
+
+      allowedSigners = mkOption {
+        type = types.nullOr types.path;
+        default = null;
+        example = "/etc/repohub/allowed-signers";
+        description = "allowed_signers file (see ssh-keygen) with the SSH keys commits may be signed with. A valid signature from any other key is refused.";
+      };
+
+      protectedBranches = mkOption {
+        type = types.listOf types.str;
+        default = [];
+        example = ["main" "release/*"];
+        description = "Reject pushes to a branch matching one of these globs that change paths of its CODEOWNERS file, unless the new tip is signed by one of their owners with a key in ownerSigners.";
+      };
+
+      ownerSigners = mkOption {
+        type = types.nullOr types.path;
+        default = null;
+        example = "/etc/repohub/owner-signers";
+        description = "allowed_signers file (see ssh-keygen) with the SSH keys of the code owners, their @username or e-mail address as principal. Only signatures from these keys approve changes to protected branches.";
+      };
+    };
+
     user = mkOption {
       type = types.str;
       default = "procurator-repohub";
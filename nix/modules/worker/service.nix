@@ -22,6 +22,50 @@ with lib; let
       socket_dir = cfg.vmRuntimeDir;
       socket_timeout_secs = cfg.cloudHypervisorSocketTimeoutSeconds;
       bridge_name = cfg.bridgeName;
+      trusted_public_keys = cfg.trustedPublicKeys;
+    } // optionalAttrs (cfg.virtiofsdBinaryPath != null) {
+      virtiofsd_binary = cfg.virtiofsdBinaryPath;
+    } // optionalAttrs (cfg.vmImageDir != null) {
+      image_dir = cfg.vmImageDir;
+    } // optionalAttrs (cfg.vmLogDir != null) {
+      log_dir = cfg.vmLogDir;
+    } // optionalAttrs (cfg.vmStagingDir != null) {
+      staging_dir = cfg.vmStagingDir;
+    } // optionalAttrs (cfg.vhostUserSocketDir != null) {
+      vhost_user_net = {
+        socket_dir = cfg.vhostUserSocketDir;
+        mode = cfg.vhostUserMode;
+      };
+    } // optionalAttrs (cfg.hotplugMaxVcpus != null) {
+      hotplug = {
+        max_vcpus = cfg.hotplugMaxVcpus;
+        max_memory_mb = cfg.hotplugMaxMemoryMb;
+      };
+    };
+    vms = {
+      worker_id = cfg.workerId;
+      state_dir = "/var/lib/procurator-worker/vms";
+    } // optionalAttrs (cfg.maxVms != null) {
+      max_vms = cfg.maxVms;
+    };
+    shutdown = {
+      timeout_secs = cfg.shutdownTimeoutSeconds;
+      stop_vms = cfg.stopVmsOnShutdown;
+    };
+  } // optionalAttrs (cfg.metricsListenAddr != null) {
+    metrics.listen_addr = cfg.metricsListenAddr;
+  } // optionalAttrs (cfg.healthListenAddr != null) {
+    health.listen_addr = cfg.healthListenAddr;
+  } // optionalAttrs (cfg.logForwarding != null) {
+    log_forwarding = cfg.logForwarding;
+  } // optionalAttrs (cfg.bootWatchdog != null) {
+    boot_watchdog = cfg.bootWatchdog;
+  } // optionalAttrs (cfg.memoryPressure != null) {
+    memory_pressure = cfg.memoryPressure;
+  } // optionalAttrs (cfg.dnsProxy != null) {
+    dns_proxy = {
+      listen_addr = cfg.dnsProxy.listenAddr;
+      upstreams = cfg.dnsProxy.upstreams;
     };
   });
 in {
@@ -81,6 +125,46 @@ in {
       description = "Directory for per-VM runtime artifacts (sockets, writable disks, logs).";
     };
 
+    vmImageDir = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/var/lib/procurator-worker/images";
+      description = "Directory for the writable per-VM disk copies. Null keeps them in vmRuntimeDir.";
+    };
+
+    vmLogDir = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/var/log/procurator-worker/vms";
+      description = "Directory for per-VM serial and cloud-hypervisor logs. Null keeps them in vmRuntimeDir.";
+    };
+
+    vmStagingDir = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/mnt/nvme/procurator-worker";
+      description = ''
+        Directory on fast local storage (e.g. NVMe) where store images are staged once
+        before boot. Per-VM disks are reflinked from them on btrfs or XFS and copied
+        otherwise. Also holds the disk copies unless vmImageDir is set. Null copies disks
+        straight from the store.
+      '';
+    };
+
+    workerId = mkOption {
+      type = types.str;
+      default = config.networking.hostName;
+      defaultText = literalExpression "config.networking.hostName";
+      description = "Name of this worker in VM statuses, identities and forwarded logs.";
+    };
+
+    maxVms = mkOption {
+      type = types.nullOr types.ints.positive;
+      default = null;
+      example = 16;
+      description = "Most VMs the worker holds at once; further creates are refused. Null for no limit.";
+    };
+
     cloudHypervisorBinaryPath = mkOption {
       type = types.str;
       default = "${pkgs.cloud-hypervisor}/bin/cloud-hypervisor";
@@ -88,6 +172,13 @@ in {
       description = "Absolute path to the cloud-hypervisor binary used by the worker.";
     };
 
+    virtiofsdBinaryPath = mkOption {
+      type = types.nullOr types.str;
+      default = "${pkgs.virtiofsd}/bin/virtiofsd";
+      defaultText = literalExpression "\"${pkgs.virtiofsd}/bin/virtiofsd\"";
+      description = "virtiofsd binary sharing the Nix store with VMs booted from a closure instead of a disk image. Null refuses such VMs.";
+    };
+
     cloudHypervisorSocketTimeoutSeconds = mkOption {
       type = types.ints.positive;
       default = 10;
@@ -101,6 +192,180 @@ in {
       example = "br0";
       description = "Bridge name used for VM TAP attachment. Set to null to disable VM networking.";
     };
+
+    vhostUserSocketDir = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "/run/openvswitch/vhost";
+      description = ''
+        Directory of the per-VM vhost-user sockets (`<vm-id>.sock`) shared
+        with a host switch such as OVS-DPDK or passt. When set, the worker
+        advertises vhost-user networking and runs VMs whose spec asks for
+        `netBackend = "vhost-user"`. Null refuses such VMs.
+      '';
+    };
+
+    vhostUserMode = mkOption {
+      type = types.enum [ "server" "client" ];
+      default = "server";
+      description = ''
+        `server`: cloud-hypervisor creates each socket and the switch
+        connects (OVS-DPDK `dpdkvhostuserclient` ports). `client`: the
+        switch creates it before the VM starts.
+      '';
+    };
+
+    hotplugMaxVcpus = mkOption {
+      type = types.nullOr (types.ints.between 1 255);
+      default = null;
+      example = 8;
+      description = ''
+        Most vCPUs a VM can be resized to without a redeploy. When set,
+        VMs boot with room to grow up to it and `hotplugMaxMemoryMb`, and
+        a spec change touching only `cpu` or `memoryMb` is hot-plugged.
+        Null redeploys VMs whose resources change.
+      '';
+    };
+
+    hotplugMaxMemoryMb = mkOption {
+      type = types.ints.positive;
+      default = 8192;
+      description = ''
+        Most memory, in MiB, a VM can be resized to without a redeploy,
+        when `hotplugMaxVcpus` is set. It is plugged in 128 MiB blocks on
+        top of what the VM booted with.
+      '';
+    };
+
+    trustedPublicKeys = mkOption {
+      type = types.listOf types.str;
+      default = [];
+      example = [ "cache.example.com-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=" ];
+      description = ''
+        Keys the closure of every VM image must be signed with (binary
+        cache, CI), checked with `nix store verify` before boot. Images
+        without a trusted signature are refused. Empty disables the check.
+      '';
+    };
+
+    shutdownTimeoutSeconds = mkOption {
+      type = types.ints.positive;
+      default = 30;
+      description = "Max seconds the worker spends draining commands and stopping VMs on SIGTERM.";
+    };
+
+    metricsListenAddr = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "0.0.0.0:9101";
+      description = "Address serving Prometheus metrics on /metrics. Null disables the exporter.";
+    };
+
+    healthListenAddr = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "0.0.0.0:9102";
+      description = "Address serving /healthz and /readyz. Null disables the probe listener.";
+    };
+
+    logForwarding = mkOption {
+      type = types.nullOr types.attrs;
+      default = null;
+      example = literalExpression ''
+        {
+          sink = { type = "loki"; url = "http://loki:3100"; };
+          spool_dir = "/var/lib/procurator-worker/log-spool";
+        }
+      '';
+      description = ''
+        Forward worker and VM console logs, passed through as the
+        `log_forwarding` config section. `sink.type` is `master`, `loki` or
+        `syslog`; keep `spool_dir` under /var/lib/procurator-worker so unsent
+        logs survive restarts. Null disables forwarding.
+      '';
+    };
+
+    bootWatchdog = mkOption {
+      type = types.nullOr types.attrs;
+      default = null;
+      example = literalExpression ''
+        {
+          timeout_secs = 180;
+          restart = "on-failure";
+          max_restarts = 3;
+        }
+      '';
+      description = ''
+        Fail VMs whose serial console does not show `ready_pattern`
+        (default "Multi-User System") within `timeout_secs`, keeping the
+        log tail, and restart them per `restart` (`never` or `on-failure`).
+        Passed through as the `boot_watchdog` config section. Null trusts
+        every boot.
+      '';
+    };
+
+    memoryPressure = mkOption {
+      type = types.nullOr types.attrs;
+      default = null;
+      example = literalExpression ''
+        {
+          threshold_percent = 25;
+          policy = "throttle";
+        }
+      '';
+      description = ''
+        Watch the host's memory pressure stall information and, once
+        `some avg10` stays at or above `threshold_percent` (default 20) for
+        `sustained_checks` checks, log and report it and apply `policy`:
+        `report`, `throttle` (refuse new VMs) or `restart` (redeploy the
+        VM with the most memory). Passed through as the `memory_pressure`
+        config section. Null leaves overcommitted hosts to the OOM killer.
+      '';
+    };
+
+    zramSwap = mkOption {
+      type = types.nullOr (types.ints.between 1 100);
+      default = null;
+      example = 50;
+      description = ''
+        Percentage of RAM given to a compressed zram swap device, so guest
+        memory the host runs short of is swapped rather than a VM being
+        OOM-killed. Hugepage-backed guests are never swapped. Null leaves
+        swap to the rest of the host configuration.
+      '';
+    };
+
+    dnsProxy = mkOption {
+      type = types.nullOr (types.submodule {
+        options = {
+          listenAddr = mkOption {
+            type = types.str;
+            example = "192.168.100.1:53";
+            description = "Address VMs resolve through, the bridge address on port 53.";
+          };
+          upstreams = mkOption {
+            type = types.listOf types.str;
+            default = [ "1.1.1.1:53" "8.8.8.8:53" ];
+            description = "Resolvers allowed queries are forwarded to, tried in order.";
+          };
+        };
+      });
+      default = null;
+      description = ''
+        Resolve only each VM's allowed domains and log the queries denied.
+        Pair with services.procurator.vmm.dnsProxy so VMs are pointed at
+        it. Null leaves DNS to dnsmasq, unfiltered.
+      '';
+    };
+
+    stopVmsOnShutdown = mkOption {
+      type = types.bool;
+      default = false;
+      description = ''
+        Stop every VM when the worker stops. When false, cloud-hypervisor
+        processes are left running and survive a worker restart.
+      '';
+    };
   };
 
   config = mkIf cfg.enable {
@@ -133,12 +398,27 @@ in {
 
     users.groups.${cfg.group} = {};
 
+    # Overcommitted guest memory is swapped to compressed RAM first.
+    zramSwap = mkIf (cfg.zramSwap != null) {
+      enable = true;
+      memoryPercent = cfg.zramSwap;
+    };
+
+    # ReadWritePaths must exist before the service starts.
+    systemd.tmpfiles.rules =
+      map (dir: "d ${dir} 0750 ${cfg.user} ${cfg.group} -")
+      (filter (dir: dir != null) [ cfg.vmImageDir cfg.vmLogDir cfg.vmStagingDir ]);
+
     systemd.services.procurator-worker = {
       description = "Procurator Worker Node";
       wantedBy = ["multi-user.target"];
       after = ["network.target"];
+      # `nix store verify` for image signature checks
+      path = [ config.nix.package ];
 
       serviceConfig = {
+        # `procurator` does not start a role yet, so nothing sends
+        # READY=1 or watchdog pings.
         Type = "simple";
         User = cfg.user;
         Group = cfg.group;
@@ -149,20 +429,38 @@ in {
         SupplementaryGroups = [ "kvm" "netdev" ];
 
         ExecStart = "${cfg.package}/bin/procurator ${configFile}";
+        # SIGHUP re-reads the config and applies reloadable settings.
+        ExecReload = "${pkgs.coreutils}/bin/kill -HUP $MAINPID";
         Restart = "on-failure";
         RestartSec = "10s";
 
+        # ── Shutdown ──────────────────────────────────────────────────
+        # The worker drains and optionally stops VMs itself within
+        # shutdownTimeoutSeconds; systemd only kills what is left after.
+        # With KillMode=process the cloud-hypervisor children are not
+        # part of the stop, so VMs outlive the worker.
+        TimeoutStopSec = cfg.shutdownTimeoutSeconds + 5;
+        KillMode =
+          if cfg.stopVmsOnShutdown
+          then "mixed"
+          else "process";
+
         # ── Capabilities ──────────────────────────────────────────────
         # CAP_NET_ADMIN — create/delete TAP devices, attach to bridges,
         #                 set link up/down via netlink.
         # CAP_NET_RAW   — needed by CH for raw packet I/O on virtio-net.
+        # CAP_NET_BIND_SERVICE — only with dnsProxy, to listen on port 53.
         #
         # Ambient caps are inherited by child processes (cloud-hypervisor)
         # even with NoNewPrivileges=true. This is the correct mechanism:
         # ambient caps survive fork+exec without requiring setuid or
         # file capabilities.
-        AmbientCapabilities = [ "CAP_NET_ADMIN" "CAP_NET_RAW" ];
-        CapabilityBoundingSet = [ "CAP_NET_ADMIN" "CAP_NET_RAW" ];
+        AmbientCapabilities =
+          [ "CAP_NET_ADMIN" "CAP_NET_RAW" ]
+          ++ optional (cfg.dnsProxy != null) "CAP_NET_BIND_SERVICE";
+        CapabilityBoundingSet =
+          [ "CAP_NET_ADMIN" "CAP_NET_RAW" ]
+          ++ optional (cfg.dnsProxy != null) "CAP_NET_BIND_SERVICE";
 
         # ── Device access ─────────────────────────────────────────────
         # Explicit allowlist prevents future hardening (PrivateDevices)
@@ -189,9 +487,19 @@ in {
         # /tmp/procurator/vms — per-VM dirs: writable disk copies, serial
         #                       logs, API sockets, CH log files.
         # /run/procurator-worker — RuntimeDirectory for ephemeral state.
-        ReadWritePaths = [ cfg.vmRuntimeDir ];
+        ReadWritePaths =
+          [ cfg.vmRuntimeDir ]
+          ++ optional (cfg.vmImageDir != null) cfg.vmImageDir
+          ++ optional (cfg.vmLogDir != null) cfg.vmLogDir
+          ++ optional (cfg.vmStagingDir != null) cfg.vmStagingDir;
         StateDirectory = "procurator-worker";
         RuntimeDirectory = "procurator-worker";
+        # VMs left running keep their API sockets here; the next worker
+        # adopts them from the records in StateDirectory.
+        RuntimeDirectoryPreserve =
+          if cfg.stopVmsOnShutdown
+          then "no"
+          else "yes";
       };
     };
   };
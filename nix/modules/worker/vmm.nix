@@ -39,6 +39,17 @@ in {
       default = ["1.1.1.1" "8.8.8.8"];
       description = "Upstream DNS servers for VMs.";
     };
+
+    dnsProxy = mkOption {
+      type = types.bool;
+      default = false;
+      description = ''
+        Hand out the bridge address as DNS server and leave answering to
+        the worker's DNS proxy (services.procurator.worker.dnsProxy), which
+        only resolves each VM's allowed domains. dnsmasq then serves DHCP
+        only, and dnsServers is unused.
+      '';
+    };
   };
 
   config = mkIf cfg.enable {
@@ -84,22 +95,35 @@ in {
     # Kernel forwarding required for NAT.
     boot.kernel.sysctl."net.ipv4.ip_forward" = 1;
 
-    # dnsmasq for DHCP and DNS forwarding on the bridge. No domain filtering here.
+    # dnsmasq for DHCP and DNS forwarding on the bridge. No domain filtering
+    # here; with dnsProxy the worker answers DNS instead and filters.
     services.dnsmasq = {
       enable = true;
-      settings = {
-        interface = "br0";
-        # bind-dynamic: attaches when br0 is ready, avoids silent bind failures
-        # that occur with bind-interfaces if br0 gets its IP after dnsmasq starts.
-        bind-dynamic = true;
-        dhcp-range = cfg.dhcpRange;
-        # Without this the lease has no gateway → guest ip route is empty.
-        dhcp-option = "option:router,${cfg.bridgeAddress}";
-        server = cfg.dnsServers;
-        # Don't read host resolv.conf — only forward to servers listed above.
-        no-resolv = true;
-        log-dhcp = true; # helps debugging; can remove once working
-      };
+      settings =
+        {
+          interface = "br0";
+          # bind-dynamic: attaches when br0 is ready, avoids silent bind failures
+          # that occur with bind-interfaces if br0 gets its IP after dnsmasq starts.
+          bind-dynamic = true;
+          dhcp-range = cfg.dhcpRange;
+          # Without this the lease has no gateway → guest ip route is empty.
+          dhcp-option =
+            ["option:router,${cfg.bridgeAddress}"]
+            ++ optional cfg.dnsProxy "option:dns-server,${cfg.bridgeAddress}";
+          log-dhcp = true; # helps debugging; can remove once working
+        }
+        // (
+          if cfg.dnsProxy
+          then {
+            # Port 53 belongs to the worker's proxy
+            port = 0;
+          }
+          else {
+            server = cfg.dnsServers;
+            # Don't read host resolv.conf — only forward to servers listed above.
+            no-resolv = true;
+          }
+        );
     };
   };
 }
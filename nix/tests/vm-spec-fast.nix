@@ -7,6 +7,7 @@
 #   - JSON round-trip preserves values
 #   - Default values match VmmBackend expectations
 #   - Nix store paths have /nix/store/ prefix
+#   - withVmBoot overrides kernel, initramfs and cmdline, keeping the rest
 #
 # Run:
 #   nix eval --json -f ./nix/tests/vm-spec-fast.nix
@@ -14,6 +15,8 @@
 # Returns true on success, throws with descriptive message on failure.
 
 let
+  withVmBoot = import ../lib/boot {};
+
   # ── Assertion helpers ──────────────────────────────────────────────
   assert' = msg: cond:
     if cond then true
@@ -149,6 +152,30 @@ let
     && assert' "diskImagePath starts with /nix/store/"
       (hasPrefix "/nix/store/" defaultSpec.diskImagePath);
 
+  # ── 10. Boot overrides ─────────────────────────────────────────────
+  debugSpec = withVmBoot defaultSpec {
+    kernel = "/nix/store/kkkk-linux-debug/bzImage";
+    extraCmdline = [ "loglevel=7" "console=hvc0" ];
+  };
+  replacedSpec = withVmBoot customSpec {
+    initramfs = "/nix/store/iiii-initrd/initrd";
+    cmdline = "console=ttyS0 root=/dev/vda ro init=/sbin/init";
+  };
+  bootOverrides =
+    assertEq "override kernelPath" debugSpec.kernelPath "/nix/store/kkkk-linux-debug/bzImage"
+    && assertEq "kept initrdPath" debugSpec.initrdPath defaultSpec.initrdPath
+    && assertEq "appended cmdline" debugSpec.cmdline
+      "console=ttyS0 root=/dev/vda rw init=/sbin/init loglevel=7 console=hvc0"
+    && assertEq "override keeps fields" (builtins.attrNames debugSpec) requiredFields
+    && assertEq "kept diskImagePath" debugSpec.diskImagePath defaultSpec.diskImagePath
+    && assertEq "override initrdPath" replacedSpec.initrdPath "/nix/store/iiii-initrd/initrd"
+    && assertEq "kept kernelPath" replacedSpec.kernelPath customSpec.kernelPath
+    && assertEq "replaced cmdline" replacedSpec.cmdline
+      "console=ttyS0 root=/dev/vda ro init=/sbin/init"
+    && assertEq "no override is identity" (withVmBoot customSpec {}) customSpec
+    && assert' "extraCmdline must be strings"
+      (!(builtins.tryEval (withVmBoot defaultSpec { extraCmdline = [ 7 ]; }).cmdline).success);
+
 in
   allFieldsPresent
   && noExtraFields
@@ -159,3 +186,4 @@ in
   && customFieldNames
   && jsonRoundTrip
   && nixPathCheck
+  && bootOverrides
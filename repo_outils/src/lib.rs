@@ -1,2 +1,16 @@
 pub mod nix;
 pub mod git;
+
+/// Render an error and its whole `source()` chain on one line,
+/// e.g. `Log parsing error: IO error: No such file or directory`.
+#[must_use]
+pub fn report(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut out = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        out.push_str(": ");
+        out.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    out
+}
@@ -0,0 +1,400 @@
+//! Concurrent cluster image builds
+//!
+//! Every VM of a [`ClusterMetadata`] is built from its `drvPath`, with at
+//! most [`BuildPool::size`] `nix build` processes at a time. All builds
+//! report into one [`BuildProgress`], so a caller can poll a single
+//! [`ProgressReport`] for the whole cluster while they run. A failed image
+//! is recorded in its [`ImageBuild`] and does not stop the others.
+//!
+//! The pool is not tied to images: any build can take one of its slots with
+//! [`BuildPool::acquire`] or [`BuildPool::run`], waiting in line while all
+//! are taken, so one pool can bound every `nix build` of a host.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+use tokio::process::Command;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{info, warn};
+
+use super::cluster::ClusterMetadata;
+use super::commands::{Error, run_command_with};
+use super::logs::{MsgEntry, Parser, ResultEntry, StartEntry, State, StopEntry, Summary};
+
+/// Bound on the number of `nix build` processes running at once.
+#[derive(Debug, Clone)]
+pub struct BuildPool {
+    permits: Arc<Semaphore>,
+    size: usize,
+}
+
+impl BuildPool {
+    /// Pool running at most `max_parallel` builds, and at least one.
+    #[must_use]
+    pub fn new(max_parallel: usize) -> Self {
+        let size = max_parallel.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(size)),
+            size,
+        }
+    }
+
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Slots free right now; builds asking for one while it is zero queue.
+    #[must_use]
+    pub fn available(&self) -> usize {
+        self.permits.available_permits()
+    }
+
+    /// Wait for a free slot, in the order slots were asked for. The slot is
+    /// taken until the returned [`BuildSlot`] is dropped, so it can be moved
+    /// into a spawned task.
+    pub async fn acquire(&self) -> BuildSlot {
+        // The semaphore is never closed
+        BuildSlot {
+            _permit: self.permits.clone().acquire_owned().await.ok(),
+        }
+    }
+
+    /// Run `build` once a slot is free, holding the slot until it finishes.
+    pub async fn run<F: Future>(&self, build: F) -> F::Output {
+        let _slot = self.acquire().await;
+        build.await
+    }
+}
+
+/// A slot of a [`BuildPool`], given back when dropped.
+#[derive(Debug)]
+pub struct BuildSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Default for BuildPool {
+    /// One build per available CPU.
+    fn default() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, NonZeroUsize::get))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageStatus {
+    /// Waiting for a slot in the pool
+    Queued,
+    Building,
+    Built,
+    Failed,
+}
+
+/// Progress of one image, from its nix internal-json log
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageProgress {
+    pub status: ImageStatus,
+    /// Nix activities (builds, downloads, copies) started so far
+    pub activities_started: usize,
+    pub activities_done: usize,
+    /// Text of the latest activity started, e.g. `building '/nix/store/…'`
+    pub current: Option<String>,
+}
+
+impl ImageProgress {
+    fn queued() -> Self {
+        Self {
+            status: ImageStatus::Queued,
+            activities_started: 0,
+            activities_done: 0,
+            current: None,
+        }
+    }
+}
+
+/// Snapshot of a whole cluster build
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressReport {
+    pub queued: usize,
+    pub building: usize,
+    pub built: usize,
+    pub failed: usize,
+    pub activities_started: usize,
+    pub activities_done: usize,
+    pub images: BTreeMap<String, ImageProgress>,
+}
+
+impl ProgressReport {
+    /// Every image either built or failed.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.queued == 0 && self.building == 0
+    }
+}
+
+/// Progress shared by the builds of one cluster. Cheap to clone; clones
+/// see the same state.
+#[derive(Debug, Clone, Default)]
+pub struct BuildProgress {
+    images: Arc<Mutex<BTreeMap<String, ImageProgress>>>,
+}
+
+impl BuildProgress {
+    #[must_use]
+    pub fn report(&self) -> ProgressReport {
+        let images = self.lock().clone();
+        let count = |status| images.values().filter(|p| p.status == status).count();
+        ProgressReport {
+            queued: count(ImageStatus::Queued),
+            building: count(ImageStatus::Building),
+            built: count(ImageStatus::Built),
+            failed: count(ImageStatus::Failed),
+            activities_started: images.values().map(|p| p.activities_started).sum(),
+            activities_done: images.values().map(|p| p.activities_done).sum(),
+            images,
+        }
+    }
+
+    fn update(&self, image: &str, f: impl FnOnce(&mut ImageProgress)) {
+        f(self
+            .lock()
+            .entry(image.to_string())
+            .or_insert_with(ImageProgress::queued));
+    }
+
+    fn set_status(&self, image: &str, status: ImageStatus) {
+        self.update(image, |p| p.status = status);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, ImageProgress>> {
+        // Updates are single assignments, a panicking holder cannot leave
+        // the map half-written
+        self.images.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Per-image log handler: keeps the usual [`Summary`] and mirrors activity
+/// into the shared progress.
+struct ImageLog {
+    image: String,
+    progress: BuildProgress,
+    state: State,
+}
+
+impl Parser for ImageLog {
+    type Output = Summary;
+
+    fn handle_start(&mut self, start: StartEntry, timestamp: SystemTime) {
+        self.progress.update(&self.image, |p| {
+            p.activities_started += 1;
+            if !start.text().is_empty() {
+                p.current = Some(start.text().to_string());
+            }
+        });
+        self.state.handle_start(start, timestamp);
+    }
+
+    fn handle_stop(&mut self, stop: StopEntry, timestamp: SystemTime) {
+        self.progress
+            .update(&self.image, |p| p.activities_done += 1);
+        self.state.handle_stop(stop, timestamp);
+    }
+
+    fn handle_msg(&mut self, msg: MsgEntry, timestamp: SystemTime) {
+        self.state.handle_msg(msg, timestamp);
+    }
+
+    fn handle_result(&mut self, result: ResultEntry, timestamp: SystemTime) {
+        self.state.handle_result(result, timestamp);
+    }
+
+    fn into_output(self, started_at: SystemTime, completed_at: SystemTime) -> Self::Output {
+        self.state.into_output(started_at, completed_at)
+    }
+}
+
+/// Outcome of building one VM image
+#[derive(Debug)]
+pub struct ImageBuild {
+    pub name: String,
+    pub drv_path: String,
+    pub result: Result<BuiltImage, Error>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BuiltImage {
+    pub out_paths: Vec<String>,
+    pub summary: Summary,
+}
+
+/// Build the image of every VM in `metadata` (no link), `pool` bounding how
+/// many run at once. Returns one [`ImageBuild`] per VM, in name order,
+/// whether it succeeded or not.
+pub async fn build_cluster_images(
+    metadata: &ClusterMetadata,
+    pool: &BuildPool,
+    progress: &BuildProgress,
+) -> Vec<ImageBuild> {
+    for name in metadata.vms.keys() {
+        progress.set_status(name, ImageStatus::Queued);
+    }
+
+    let handles: Vec<_> = metadata
+        .vms
+        .iter()
+        .map(|(name, vm)| {
+            let name = name.clone();
+            let drv_path = vm.drv_path.clone();
+            let pool = pool.clone();
+            let progress = progress.clone();
+            tokio::spawn(async move {
+                let result = build_image(&name, &drv_path, &pool, &progress).await;
+                ImageBuild {
+                    name,
+                    drv_path,
+                    result,
+                }
+            })
+        })
+        .collect();
+
+    let mut builds = Vec::with_capacity(handles.len());
+    for handle in handles {
+        match handle.await {
+            Ok(build) => builds.push(build),
+            // Nothing aborts these tasks, a join error is a panic
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+    builds
+}
+
+async fn build_image(
+    name: &str,
+    drv_path: &str,
+    pool: &BuildPool,
+    progress: &BuildProgress,
+) -> Result<BuiltImage, Error> {
+    let _slot = pool.acquire().await;
+    progress.set_status(name, ImageStatus::Building);
+    info!(image = %name, %drv_path, "Building image");
+
+    let mut command = Command::new("nix");
+    command
+        .arg("build")
+        .arg("--no-link")
+        .arg("--print-out-paths")
+        .arg("--log-format")
+        .arg("internal-json")
+        .arg(format!("{drv_path}^*"));
+
+    let handler = ImageLog {
+        image: name.to_string(),
+        progress: progress.clone(),
+        state: State::default(),
+    };
+    let result = run_command_with(command, handler)
+        .await
+        .and_then(|(summary, stdout)| {
+            let out_paths = stdout
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>();
+            if out_paths.is_empty() {
+                return Err(Error::BuildOutputMissing);
+            }
+            Ok(BuiltImage { out_paths, summary })
+        });
+
+    match &result {
+        Ok(_) => {
+            progress.set_status(name, ImageStatus::Built);
+            info!(image = %name, "Image built");
+        }
+        Err(e) => {
+            progress.set_status(name, ImageStatus::Failed);
+            warn!(image = %name, error = %crate::report(e), code = e.code(), "Image build failed");
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use tokio::io::BufReader;
+
+    use super::*;
+
+    async fn feed(image: &str, progress: &BuildProgress, log: &str) {
+        let mut handler = ImageLog {
+            image: image.to_string(),
+            progress: progress.clone(),
+            state: State::default(),
+        };
+        handler
+            .parse_lines(BufReader::new(Cursor::new(log.to_string())))
+            .await;
+    }
+
+    #[tokio::test]
+    async fn progress_aggregates_every_image() {
+        let progress = BuildProgress::default();
+        progress.set_status("db", ImageStatus::Queued);
+        progress.set_status("web", ImageStatus::Building);
+
+        feed(
+            "web",
+            &progress,
+            r#"@nix {"action":"start","id":1,"level":3,"parent":0,"text":"building '/nix/store/aaa-web.drv'","type":105}
+@nix {"action":"start","id":2,"level":4,"parent":1,"text":"","type":0}
+@nix {"action":"stop","id":2}"#,
+        )
+        .await;
+
+        let report = progress.report();
+        assert_eq!((report.queued, report.building), (1, 1));
+        assert_eq!((report.activities_started, report.activities_done), (2, 1));
+        assert_eq!(
+            report.images["web"].current.as_deref(),
+            Some("building '/nix/store/aaa-web.drv'")
+        );
+        assert!(!report.is_finished());
+
+        progress.set_status("web", ImageStatus::Built);
+        progress.set_status("db", ImageStatus::Failed);
+        let report = progress.report();
+        assert_eq!((report.built, report.failed), (1, 1));
+        assert!(report.is_finished());
+    }
+
+    #[test]
+    fn pool_runs_at_least_one_build() {
+        assert_eq!(BuildPool::new(0).size(), 1);
+        assert_eq!(BuildPool::new(4).size(), 4);
+    }
+
+    #[tokio::test]
+    async fn pool_queues_builds_beyond_its_size() {
+        let pool = BuildPool::new(2);
+        let first = pool.acquire().await;
+        let _second = pool.acquire().await;
+        assert_eq!(pool.available(), 0);
+
+        let queued = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(async { "built" }).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!queued.is_finished());
+
+        drop(first);
+        assert_eq!(queued.await.unwrap(), "built");
+        assert_eq!(pool.available(), 1);
+    }
+}
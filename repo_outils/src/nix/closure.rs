@@ -0,0 +1,274 @@
+//! Closure diff between two generations
+//!
+//! The equivalent of `nix store diff-closures`: store paths of both
+//! closures are grouped by package name (the store path name without its
+//! version), and every package whose paths differ is reported with its
+//! versions before and after and its size delta.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::commands::Error;
+use super::path_info::{PathInfo, closure_info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// Change of one package between the two closures
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageDiff {
+    pub name: String,
+    pub kind: ChangeKind,
+    /// Versions in the old closure, empty for unversioned paths
+    pub old_versions: Vec<String>,
+    pub new_versions: Vec<String>,
+    pub removed_paths: Vec<String>,
+    pub added_paths: Vec<String>,
+    /// NAR size delta in bytes
+    pub size_delta: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ClosureDiff {
+    /// Changed packages, by name
+    pub packages: Vec<PackageDiff>,
+    /// Total NAR size delta of the closure in bytes
+    pub size_delta: i64,
+}
+
+impl ClosureDiff {
+    /// Compare two closures.
+    #[must_use]
+    pub fn between(old: &[PathInfo], new: &[PathInfo]) -> Self {
+        let old_packages = group(old);
+        let new_packages = group(new);
+        let names: BTreeSet<&str> = old_packages
+            .keys()
+            .chain(new_packages.keys())
+            .copied()
+            .collect();
+
+        let empty = Package::default();
+        let packages = names
+            .into_iter()
+            .filter_map(|name| {
+                let before = old_packages.get(name).unwrap_or(&empty);
+                let after = new_packages.get(name).unwrap_or(&empty);
+                if before.paths == after.paths {
+                    return None;
+                }
+                let kind = if before.paths.is_empty() {
+                    ChangeKind::Added
+                } else if after.paths.is_empty() {
+                    ChangeKind::Removed
+                } else {
+                    ChangeKind::Changed
+                };
+                Some(PackageDiff {
+                    name: name.to_string(),
+                    kind,
+                    old_versions: before.versions.iter().cloned().collect(),
+                    new_versions: after.versions.iter().cloned().collect(),
+                    removed_paths: before.paths.difference(&after.paths).cloned().collect(),
+                    added_paths: after.paths.difference(&before.paths).cloned().collect(),
+                    size_delta: delta(before.size, after.size),
+                })
+            })
+            .collect();
+
+        Self {
+            packages,
+            size_delta: delta(total(old), total(new)),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    pub fn of_kind(&self, kind: ChangeKind) -> impl Iterator<Item = &PackageDiff> {
+        self.packages.iter().filter(move |p| p.kind == kind)
+    }
+}
+
+/// One line per package, like `nix store diff-closures`:
+/// `openssl: 3.0.12 → 3.0.13, +1.2 KiB`
+impl fmt::Display for ClosureDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for package in &self.packages {
+            let versions = |v: &[String]| match v {
+                [] => "∅".to_string(),
+                v => v.join(", "),
+            };
+            write!(
+                f,
+                "{}: {} → {}",
+                package.name,
+                versions(&package.old_versions),
+                versions(&package.new_versions)
+            )?;
+            if package.size_delta != 0 {
+                write!(f, ", {}", Size(package.size_delta))?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "total: {}", Size(self.size_delta))
+    }
+}
+
+/// Signed byte count in binary units, e.g. `-3.5 MiB`
+struct Size(i64);
+
+impl fmt::Display for Size {
+    #[allow(clippy::cast_precision_loss)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        let sign = if self.0 < 0 { '-' } else { '+' };
+        let bytes = self.0.unsigned_abs();
+        if bytes < 1024 {
+            return write!(f, "{sign}{bytes} B");
+        }
+        let mut value = bytes as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{sign}{value:.1} {}", UNITS[unit])
+    }
+}
+
+#[derive(Debug, Default)]
+struct Package {
+    versions: BTreeSet<String>,
+    paths: BTreeSet<String>,
+    size: u64,
+}
+
+fn group(closure: &[PathInfo]) -> BTreeMap<&str, Package> {
+    let mut packages: BTreeMap<&str, Package> = BTreeMap::new();
+    for info in closure {
+        let (name, version) = split_name(&info.path);
+        let package = packages.entry(name).or_default();
+        if !version.is_empty() {
+            package.versions.insert(version.to_string());
+        }
+        package.paths.insert(info.path.clone());
+        package.size += info.nar_size;
+    }
+    packages
+}
+
+fn total(closure: &[PathInfo]) -> u64 {
+    closure.iter().map(|info| info.nar_size).sum()
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn delta(before: u64, after: u64) -> i64 {
+    after as i64 - before as i64
+}
+
+/// Package name and version of a store path, split the way nix does: the
+/// version starts at the first `-` not followed by a letter.
+/// `/nix/store/<hash>-openssl-3.0.13-dev` → (`openssl`, `3.0.13-dev`)
+fn split_name(path: &str) -> (&str, &str) {
+    let base = path.rsplit('/').next().unwrap_or(path);
+    let name = base.split_once('-').map_or(base, |(_, name)| name);
+    let version_start = name
+        .char_indices()
+        .find(|&(i, c)| {
+            c == '-'
+                && name[i + 1..]
+                    .chars()
+                    .next()
+                    .is_some_and(|next| !next.is_ascii_alphabetic())
+        })
+        .map(|(i, _)| i);
+    match version_start {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    }
+}
+
+/// Diff the closures of two generations' store paths
+///
+/// # Errors
+///
+/// Either closure failing to load, see [`closure_info`].
+pub async fn diff_closures(old: &str, new: &str) -> Result<ClosureDiff, Error> {
+    let (old, new) = tokio::try_join!(closure_info(old), closure_info(new))?;
+    Ok(ClosureDiff::between(&old, &new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(path: &str, nar_size: u64) -> PathInfo {
+        PathInfo {
+            path: format!("/nix/store/{path}"),
+            nar_size,
+            ..PathInfo::default()
+        }
+    }
+
+    #[test]
+    fn splits_names_like_nix() {
+        assert_eq!(
+            split_name("/nix/store/abc-openssl-3.0.13-dev"),
+            ("openssl", "3.0.13-dev")
+        );
+        assert_eq!(
+            split_name("/nix/store/abc-nixos-system-web-24.05"),
+            ("nixos-system-web", "24.05")
+        );
+        assert_eq!(split_name("/nix/store/abc-etc"), ("etc", ""));
+    }
+
+    #[test]
+    fn reports_added_removed_and_changed_packages() {
+        let old = [
+            info("aaa-openssl-3.0.12", 5000),
+            info("bbb-curl-8.5.0", 1000),
+            info("ccc-etc", 100),
+        ];
+        let new = [
+            info("ddd-openssl-3.0.13", 6024),
+            info("ccc-etc", 100),
+            info("eee-jq-1.7", 300),
+        ];
+
+        let diff = ClosureDiff::between(&old, &new);
+
+        assert_eq!(diff.size_delta, 324);
+        let kinds: Vec<_> = diff
+            .packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("curl", ChangeKind::Removed),
+                ("jq", ChangeKind::Added),
+                ("openssl", ChangeKind::Changed),
+            ]
+        );
+        let openssl = diff.of_kind(ChangeKind::Changed).next().unwrap();
+        assert_eq!(openssl.old_versions, ["3.0.12"]);
+        assert_eq!(openssl.new_versions, ["3.0.13"]);
+        assert_eq!(openssl.removed_paths, ["/nix/store/aaa-openssl-3.0.12"]);
+        assert_eq!(openssl.size_delta, 1024);
+        assert_eq!(
+            diff.to_string(),
+            "curl: 8.5.0 → ∅, -1000 B\njq: ∅ → 1.7, +300 B\nopenssl: 3.0.12 → 3.0.13, +1.0 KiB\ntotal: +324 B"
+        );
+    }
+}
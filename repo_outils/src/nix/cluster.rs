@@ -0,0 +1,403 @@
+//! Cluster metadata schema
+//!
+//! Typed form of the attribute evaluated by [`eval_cluster_metadata`]
+//! (`nix eval --json <flake>#<attr>`). Version 1:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "vms": {
+//!     "web": {
+//!       "drvPath": "/nix/store/…-web.drv",
+//!       "outPath": "/nix/store/…-web",
+//!       "contentHash": "sha256-…",
+//!       "resources": { "cpu": 2, "memoryMb": 1024 },
+//!       "replicas": 2,
+//!       "labels": ["frontend"],
+//!       "networks": ["public"],
+//!       "volumes": [{ "volume": "assets", "mountPoint": "/srv", "readOnly": true }]
+//!     }
+//!   },
+//!   "networks": { "public": { "allowedDomains": ["api.example.com"] } },
+//!   "volumes": { "assets": { "sizeMb": 512 } }
+//! }
+//! ```
+//!
+//! Parsing stops at the first field that does not match the schema;
+//! validation then reports every remaining problem at once. Both point at the
+//! flake attribute to fix, e.g. `clusterMetadata.vms.web.resources.memoryMb`.
+//!
+//! [`eval_cluster_metadata`]: super::eval_cluster_metadata
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// Schema version this crate reads and writes.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Longest VM name; names double as hostnames.
+const MAX_NAME_LEN: usize = 63;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ClusterMetadata {
+    pub version: u64,
+    pub vms: BTreeMap<String, VmMetadata>,
+    #[serde(default)]
+    pub networks: BTreeMap<String, Network>,
+    #[serde(default)]
+    pub volumes: BTreeMap<String, Volume>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct VmMetadata {
+    pub drv_path: String,
+    pub out_path: String,
+    pub content_hash: String,
+    pub resources: Resources,
+    /// Zero keeps the VM declared but stopped.
+    #[serde(default = "default_replicas")]
+    pub replicas: u32,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Names of entries in [`ClusterMetadata::networks`].
+    #[serde(default)]
+    pub networks: Vec<String>,
+    #[serde(default)]
+    pub volumes: Vec<VolumeMount>,
+}
+
+fn default_replicas() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Resources {
+    pub cpu: u32,
+    pub memory_mb: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Network {
+    /// Domains VMs on this network can reach; empty means isolated.
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Volume {
+    pub size_mb: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct VolumeMount {
+    /// Name of an entry in [`ClusterMetadata::volumes`].
+    pub volume: String,
+    pub mount_point: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+// ============================================================================
+// Errors
+// ============================================================================
+
+/// One validation failure, at the flake attribute path it concerns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invalid {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MetadataError {
+    #[error("{path}: unsupported schema version {found}, expected {SCHEMA_VERSION}")]
+    UnsupportedVersion { path: String, found: u64 },
+    #[error("{path}: does not match the cluster metadata schema")]
+    Parse {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("{}", join(.0))]
+    Invalid(Vec<Invalid>),
+}
+
+fn join(problems: &[Invalid]) -> String {
+    problems
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// ============================================================================
+// Parsing and validation
+// ============================================================================
+
+impl ClusterMetadata {
+    /// Parse and validate the JSON value of flake attribute `attr`.
+    ///
+    /// # Errors
+    ///
+    /// Unsupported `version`, a field not matching the schema, or the
+    /// problems found by [`ClusterMetadata::validate`].
+    pub fn from_json(attr: &str, json: &[u8]) -> Result<Self, MetadataError> {
+        // Read the version alone first, so a newer document fails on the
+        // version rather than on the first field this crate does not know.
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: Option<u64>,
+        }
+        if let Ok(Versioned {
+            version: Some(found),
+        }) = serde_json::from_slice(json)
+            && found != SCHEMA_VERSION
+        {
+            return Err(MetadataError::UnsupportedVersion {
+                path: format!("{attr}.version"),
+                found,
+            });
+        }
+
+        let deserializer = &mut serde_json::Deserializer::from_slice(json);
+        let metadata: Self = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            let path = match e.path().to_string().as_str() {
+                "." => attr.to_string(),
+                inner => format!("{attr}.{inner}"),
+            };
+            MetadataError::Parse {
+                path,
+                source: e.into_inner(),
+            }
+        })?;
+
+        metadata.validate(attr)?;
+        Ok(metadata)
+    }
+
+    /// Check bounds and cross-references that the types cannot express.
+    ///
+    /// # Errors
+    ///
+    /// Every problem found, with paths under `attr`.
+    pub fn validate(&self, attr: &str) -> Result<(), MetadataError> {
+        let mut problems = Vec::new();
+        let mut invalid = |path: String, message: String| problems.push(Invalid { path, message });
+
+        for (name, vm) in &self.vms {
+            let at = format!("{attr}.vms.{name}");
+            if let Err(reason) = check_name(name) {
+                invalid(at.clone(), reason);
+            }
+            if !(vm.drv_path.starts_with("/nix/store/")
+                && std::path::Path::new(&vm.drv_path)
+                    .extension()
+                    .is_some_and(|ext| ext == "drv"))
+            {
+                invalid(
+                    format!("{at}.drvPath"),
+                    format!("{:?} is not a store derivation", vm.drv_path),
+                );
+            }
+            if !vm.out_path.starts_with("/nix/store/") {
+                invalid(
+                    format!("{at}.outPath"),
+                    format!("{:?} is not a store path", vm.out_path),
+                );
+            }
+            if vm.content_hash.is_empty() {
+                invalid(format!("{at}.contentHash"), "must not be empty".into());
+            }
+            if vm.resources.cpu == 0 {
+                invalid(format!("{at}.resources.cpu"), "must be at least 1".into());
+            }
+            if vm.resources.memory_mb == 0 {
+                invalid(
+                    format!("{at}.resources.memoryMb"),
+                    "must be at least 1".into(),
+                );
+            }
+
+            for (i, network) in vm.networks.iter().enumerate() {
+                if !self.networks.contains_key(network) {
+                    invalid(
+                        format!("{at}.networks.{i}"),
+                        format!("unknown network {network:?}"),
+                    );
+                }
+            }
+
+            let mut mount_points = HashSet::new();
+            for (i, mount) in vm.volumes.iter().enumerate() {
+                let at = format!("{at}.volumes.{i}");
+                if !self.volumes.contains_key(&mount.volume) {
+                    invalid(
+                        format!("{at}.volume"),
+                        format!("unknown volume {:?}", mount.volume),
+                    );
+                }
+                if !mount.mount_point.starts_with('/') {
+                    invalid(
+                        format!("{at}.mountPoint"),
+                        format!("{:?} is not absolute", mount.mount_point),
+                    );
+                } else if !mount_points.insert(mount.mount_point.as_str()) {
+                    invalid(
+                        format!("{at}.mountPoint"),
+                        format!("{:?} is mounted twice", mount.mount_point),
+                    );
+                }
+                if !mount.read_only && vm.replicas > 1 {
+                    invalid(
+                        format!("{at}.readOnly"),
+                        format!(
+                            "volume {:?} would be written by {} replicas",
+                            mount.volume, vm.replicas
+                        ),
+                    );
+                }
+            }
+        }
+
+        for (name, network) in &self.networks {
+            for (i, domain) in network.allowed_domains.iter().enumerate() {
+                if domain.is_empty() || domain.contains(char::is_whitespace) {
+                    invalid(
+                        format!("{attr}.networks.{name}.allowedDomains.{i}"),
+                        format!("{domain:?} is not a domain"),
+                    );
+                }
+            }
+        }
+
+        for (name, volume) in &self.volumes {
+            if volume.size_mb == 0 {
+                invalid(
+                    format!("{attr}.volumes.{name}.sizeMb"),
+                    "must be at least 1".into(),
+                );
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(MetadataError::Invalid(problems))
+        }
+    }
+}
+
+/// VM names become hostnames: lowercase DNS labels.
+pub(super) fn check_name(name: &str) -> Result<(), String> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || !valid_chars
+        || name.starts_with('-')
+        || name.ends_with('-')
+    {
+        return Err(format!(
+            "{name:?} is not a valid VM name (lowercase letters, digits and '-', at most {MAX_NAME_LEN})"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ATTR: &str = "clusterMetadata";
+
+    fn document() -> serde_json::Value {
+        serde_json::json!({
+            "version": 1,
+            "vms": {
+                "web": {
+                    "drvPath": "/nix/store/aaaa-web.drv",
+                    "outPath": "/nix/store/aaaa-web",
+                    "contentHash": "sha256-abc",
+                    "resources": { "cpu": 2, "memoryMb": 1024 },
+                    "replicas": 2,
+                    "networks": ["public"],
+                    "volumes": [{ "volume": "assets", "mountPoint": "/srv", "readOnly": true }]
+                }
+            },
+            "networks": { "public": { "allowedDomains": ["api.example.com"] } },
+            "volumes": { "assets": { "sizeMb": 512 } }
+        })
+    }
+
+    fn parse(value: &serde_json::Value) -> Result<ClusterMetadata, MetadataError> {
+        ClusterMetadata::from_json(ATTR, &serde_json::to_vec(value).unwrap())
+    }
+
+    #[test]
+    fn parses_a_valid_document() {
+        let metadata = parse(&document()).unwrap();
+        let web = &metadata.vms["web"];
+        assert_eq!(
+            web.resources,
+            Resources {
+                cpu: 2,
+                memory_mb: 1024
+            }
+        );
+        assert_eq!(web.replicas, 2);
+        assert!(web.labels.is_empty());
+        assert_eq!(metadata.volumes["assets"].size_mb, 512);
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_flake_attribute() {
+        let mut doc = document();
+        doc["vms"]["web"]["resources"]["memoryMb"] = serde_json::json!("1G");
+        let err = parse(&doc).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "clusterMetadata.vms.web.resources.memoryMb: does not match the cluster metadata schema"
+        );
+
+        doc["version"] = serde_json::json!(2);
+        assert_eq!(
+            parse(&doc).unwrap_err().to_string(),
+            "clusterMetadata.version: unsupported schema version 2, expected 1"
+        );
+    }
+
+    #[test]
+    fn validation_reports_every_problem() {
+        let mut doc = document();
+        doc["vms"]["web"]["drvPath"] = serde_json::json!("/tmp/web");
+        doc["vms"]["web"]["networks"] = serde_json::json!(["public", "private"]);
+        doc["vms"]["web"]["volumes"][0]["readOnly"] = serde_json::json!(false);
+
+        let MetadataError::Invalid(problems) = parse(&doc).unwrap_err() else {
+            panic!("expected validation errors");
+        };
+        let paths: Vec<_> = problems.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "clusterMetadata.vms.web.drvPath",
+                "clusterMetadata.vms.web.networks.1",
+                "clusterMetadata.vms.web.volumes.0.readOnly",
+            ]
+        );
+    }
+}
@@ -1,95 +1,163 @@
 use serde::{Deserialize, Serialize};
 use std::{ops::Not, path::Path, time::SystemTime};
-use tokio::{io::BufReader, process::Command};
-
-use super::logs::{Error as LogError, Parser, State, Summary};
+use tokio::{
+    io::{AsyncReadExt, BufReader},
+    process::Command,
+};
+
+use super::cluster::{ClusterMetadata, MetadataError};
+use super::diagnostics::Diagnostics;
+use super::logs::{
+    Error as LogError, MsgEntry, Parser, ResultEntry, StartEntry, State, StopEntry, Summary,
+};
+use super::store::IsolatedStore;
 
 /// Errors specific to each command type
-#[derive(Debug)]
+///
+/// Causes are kept as `source()` rather than folded into the message, so
+/// callers can walk the chain (see [`crate::report`]) or match on [`Error::code`].
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
-    Io(std::io::Error),
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Process failed with exit code {exit_code:?}: {stderr}")]
     ProcessFailed {
         exit_code: Option<i32>,
         stderr: String,
+        /// What `stderr` says went wrong
+        diagnostics: Diagnostics,
     },
-    JsonParse(serde_json::Error),
+    #[error("Failed to parse JSON output")]
+    JsonParse(#[from] serde_json::Error),
+    #[error("Invalid flake path: {0}")]
     InvalidFlakePath(String),
-    LogParsing(LogError),
+    #[error("Log parsing error")]
+    LogParsing(#[from] LogError),
+    #[error("Build output missing")]
     BuildOutputMissing,
+    #[error("Invalid cluster metadata")]
+    InvalidMetadata(#[from] MetadataError),
+    #[error("Not a store path: {0}")]
+    InvalidStorePath(String),
 }
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Error {
+    /// Stable identifier for this failure, independent of the message.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
         match self {
-            Error::Io(err) => write!(f, "IO error: {}", err),
-            Error::ProcessFailed { exit_code, stderr } => {
-                write!(
-                    f,
-                    "Process failed with exit code {:?}: {}",
-                    exit_code, stderr
-                )
-            }
-            Error::JsonParse(err) => write!(f, "Failed to parse JSON output: {}", err),
-            Error::InvalidFlakePath(path) => write!(f, "Invalid flake path: {}", path),
-            Error::LogParsing(err) => write!(f, "Log parsing error: {}", err),
-            Error::BuildOutputMissing => write!(f, "Build output missing"),
+            Error::Io(_) => "nix.io",
+            Error::ProcessFailed { .. } => "nix.process_failed",
+            Error::JsonParse(_) => "nix.json",
+            Error::InvalidFlakePath(_) => "nix.invalid_flake_path",
+            Error::LogParsing(_) => "nix.log_parsing",
+            Error::BuildOutputMissing => "nix.build_output_missing",
+            Error::InvalidMetadata(_) => "nix.invalid_metadata",
+            Error::InvalidStorePath(_) => "nix.invalid_store_path",
         }
     }
-}
 
-impl std::error::Error for Error {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            Error::Io(err) => Some(err),
-            Error::JsonParse(err) => Some(err),
-            Error::LogParsing(err) => Some(err),
-            _ => None,
+    /// Nix exiting with an error, diagnosed from its `stderr`.
+    pub(super) fn process_failed(exit_code: Option<i32>, stderr: String) -> Self {
+        Error::ProcessFailed {
+            exit_code,
+            diagnostics: Diagnostics::parse(&stderr),
+            stderr,
         }
     }
-}
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error::Io(err)
+    /// Why Nix failed, when it ran and exited with an error.
+    #[must_use]
+    pub fn diagnostics(&self) -> Option<&Diagnostics> {
+        match self {
+            Error::ProcessFailed { diagnostics, .. } => Some(diagnostics),
+            _ => None,
+        }
     }
 }
 
-impl From<serde_json::Error> for Error {
-    fn from(err: serde_json::Error) -> Self {
-        Error::JsonParse(err)
-    }
-}
+type Result<T> = std::result::Result<T, Error>;
 
-impl From<LogError> for Error {
-    fn from(err: LogError) -> Self {
-        Error::LogParsing(err)
-    }
+async fn run_command<H: Parser + Default>(command: Command) -> Result<H::Output> {
+    let (output, _stdout) = run_command_with(command, H::default()).await?;
+    Ok(output)
 }
 
-type Result<T> = std::result::Result<T, Error>;
-
-async fn run_command<H: Parser>(mut command: Command) -> Result<H::Output> {
+/// Run `command` feeding its stderr to `handler`; returns the handler output
+/// and stdout.
+pub(super) async fn run_command_with<H: Parser>(
+    mut command: Command,
+    mut handler: H,
+) -> Result<(H::Output, String)> {
     let started_at = SystemTime::now();
-    let mut handler = H::default();
 
-    let mut child = command.stderr(std::process::Stdio::piped()).spawn()?;
+    let mut handler = ErrorMessages {
+        handler,
+        errors: Vec::new(),
+    };
+    let mut child = command
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
 
+    let mut stdout = child.stdout.take().unwrap();
     let stderr = child.stderr.take().unwrap();
     let reader = BufReader::new(stderr);
 
-    handler.parse_lines(reader).await;
+    // Drain both pipes together so neither can fill up and block nix
+    let mut out = String::new();
+    let (read, ()) = tokio::join!(stdout.read_to_string(&mut out), handler.parse_lines(reader));
+    read?;
 
     // Wait for the process to finish
     let status = child.wait().await?;
 
     if status.success().not() {
-        return Err(Error::ProcessFailed {
-            exit_code: status.code(),
-            stderr: "Build failed".to_string(),
-        });
+        let stderr = if handler.errors.is_empty() {
+            "Build failed".to_string()
+        } else {
+            handler.errors.join("\n")
+        };
+        return Err(Error::process_failed(status.code(), stderr));
+    }
+
+    Ok((
+        handler.handler.into_output(started_at, SystemTime::now()),
+        out,
+    ))
+}
+
+/// Keeps the text of the error messages of a log besides feeding it to `handler`
+struct ErrorMessages<H> {
+    handler: H,
+    errors: Vec<String>,
+}
+
+impl<H: Parser> Parser for ErrorMessages<H> {
+    type Output = H::Output;
+
+    fn handle_start(&mut self, start: StartEntry, timestamp: SystemTime) {
+        self.handler.handle_start(start, timestamp);
+    }
+
+    fn handle_stop(&mut self, stop: StopEntry, timestamp: SystemTime) {
+        self.handler.handle_stop(stop, timestamp);
+    }
+
+    fn handle_msg(&mut self, msg: MsgEntry, timestamp: SystemTime) {
+        if msg.level() == 0 {
+            self.errors.push(msg.text().to_string());
+        }
+        self.handler.handle_msg(msg, timestamp);
+    }
+
+    fn handle_result(&mut self, result: ResultEntry, timestamp: SystemTime) {
+        self.handler.handle_result(result, timestamp);
     }
 
-    Ok(handler.into_output(started_at, SystemTime::now()))
+    fn into_output(self, started_at: SystemTime, completed_at: SystemTime) -> Self::Output {
+        self.handler.into_output(started_at, completed_at)
+    }
 }
 
 /// Result from `nix flake check`
@@ -98,24 +166,23 @@ pub struct CheckResult {
     summary: Summary,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct VmMetadata {
-    pub name: String,
-    pub drv_path: String,
-    pub out_path: String,
-    pub content_hash: String,
-    pub cpu: f64,
-    pub memory_bytes: u64,
-    pub labels: Vec<String>,
-    pub replicas: u64,
+impl CheckResult {
+    #[must_use]
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
 }
 
-/// Evaluate cluster metadata from flake output (JSON)
+/// Evaluate and validate cluster metadata from flake output (JSON)
+///
+/// # Errors
+///
+/// `nix eval` failing, or [`Error::InvalidMetadata`] pointing at the
+/// offending attribute under `attr`.
 pub async fn eval_cluster_metadata(
     flake_path: impl AsRef<Path>,
     attr: &str,
-) -> Result<std::collections::HashMap<String, VmMetadata>> {
+) -> Result<ClusterMetadata> {
     let path = flake_path.as_ref();
     validate_path(path)?;
 
@@ -127,63 +194,186 @@ pub async fn eval_cluster_metadata(
 
     let output = command.output().await?;
     if output.status.success().not() {
-        return Err(Error::ProcessFailed {
-            exit_code: output.status.code(),
-            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-        });
+        return Err(Error::process_failed(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
     }
 
-    let parsed: std::collections::HashMap<String, VmMetadata> =
-        serde_json::from_slice(&output.stdout)?;
-    Ok(parsed)
+    Ok(ClusterMetadata::from_json(attr, &output.stdout)?)
+}
+
+/// Run `nix flake check` - returns detailed summary and success status
+pub async fn flake_check(flake_path: impl AsRef<Path>) -> Result<CheckResult> {
+    run_flake_check(flake_path.as_ref(), None, true).await
 }
 
-/// Build cluster images (no link) and return output paths
-pub async fn build_cluster_images(
+/// [`flake_check`] against `store` instead of the host store; what it builds
+/// stays there until copied out with [`IsolatedStore::copy_to`].
+///
+/// # Errors
+///
+/// - as [`flake_check`]
+pub async fn flake_check_in(
     flake_path: impl AsRef<Path>,
-    attr: &str,
-) -> Result<Vec<String>> {
-    let path = flake_path.as_ref();
+    store: &IsolatedStore,
+) -> Result<CheckResult> {
+    run_flake_check(flake_path.as_ref(), Some(store.root()), true).await
+}
+
+/// Run `nix flake check --no-build`: evaluates every output and checks its
+/// shape without building anything, so it fails or passes within seconds.
+///
+/// # Errors
+///
+/// - as [`flake_check`]
+pub async fn flake_eval(flake_path: impl AsRef<Path>) -> Result<CheckResult> {
+    run_flake_check(flake_path.as_ref(), None, false).await
+}
+
+/// [`flake_eval`] against `store`, see [`flake_check_in`].
+///
+/// # Errors
+///
+/// - as [`flake_check`]
+pub async fn flake_eval_in(
+    flake_path: impl AsRef<Path>,
+    store: &IsolatedStore,
+) -> Result<CheckResult> {
+    run_flake_check(flake_path.as_ref(), Some(store.root()), false).await
+}
+
+async fn run_flake_check(path: &Path, store: Option<&Path>, build: bool) -> Result<CheckResult> {
     validate_path(path)?;
 
     let mut command = Command::new("nix");
+    if let Some(store) = store {
+        command.arg("--store").arg(store);
+    }
+    command.arg("flake").arg("check").arg(path);
+    if build.not() {
+        command.arg("--no-build");
+    }
     command
-        .arg("build")
-        .arg("--no-link")
-        .arg("--print-out-paths")
-        .arg(format!("{}#{}", path.display(), attr));
+        .arg("--print-build-logs")
+        .arg("--log-format")
+        .arg("internal-json");
 
-    let output = command.output().await?;
-    if output.status.success().not() {
-        return Err(Error::ProcessFailed {
-            exit_code: output.status.code(),
-            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-        });
-    }
+    let summary = run_command::<State>(command).await?;
+
+    Ok(CheckResult { summary })
+}
+
+/// Expression listing the checks of the host's system, applied to a flake's
+/// `checks` output
+const CHECK_NAMES: &str = "checks: let system = builtins.currentSystem; \
+    in { inherit system; names = builtins.attrNames (checks.${system} or { }); }";
 
-    let paths = String::from_utf8_lossy(&output.stdout)
-        .lines()
-        .map(|line| line.trim().to_string())
-        .filter(|line| !line.is_empty())
-        .collect::<Vec<_>>();
+/// Checks a flake defines for the host's system, from [`flake_checks`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlakeChecks {
+    system: String,
+    names: Vec<String>,
+}
 
-    if paths.is_empty() {
-        return Err(Error::BuildOutputMissing);
+impl FlakeChecks {
+    #[must_use]
+    pub fn system(&self) -> &str {
+        &self.system
     }
 
-    Ok(paths)
+    /// Names under `checks.<system>`, sorted
+    #[must_use]
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
 }
 
-/// Run `nix flake check` - returns detailed summary and success status
-pub async fn flake_check(flake_path: impl AsRef<Path>) -> Result<CheckResult> {
-    let path = flake_path.as_ref();
-    validate_path(path)?;
+/// List the checks `flake_ref` (e.g. `git+file:///srv/git/app.git?rev=<sha>`)
+/// defines for the host's system, without building them.
+///
+/// # Errors
+///
+/// - if `nix eval` fails, e.g. when the flake has no `checks` output
+pub async fn flake_checks(flake_ref: &str) -> Result<FlakeChecks> {
+    run_flake_checks(flake_ref, None).await
+}
+
+/// [`flake_checks`] against `store`, see [`flake_check_in`].
+///
+/// # Errors
+///
+/// - as [`flake_checks`]
+pub async fn flake_checks_in(flake_ref: &str, store: &IsolatedStore) -> Result<FlakeChecks> {
+    run_flake_checks(flake_ref, Some(store.root())).await
+}
+
+/// Build only the checks `names` of `flake_ref`, out of the `checks`
+/// listed by [`flake_checks`], instead of every check as [`flake_check`]
+/// does.
+///
+/// # Errors
+///
+/// - as [`flake_check`]
+pub async fn build_checks(
+    flake_ref: &str,
+    checks: &FlakeChecks,
+    names: &[&str],
+) -> Result<CheckResult> {
+    run_build_checks(flake_ref, checks, names, None).await
+}
+
+/// [`build_checks`] against `store`, see [`flake_check_in`].
+///
+/// # Errors
+///
+/// - as [`flake_check`]
+pub async fn build_checks_in(
+    flake_ref: &str,
+    checks: &FlakeChecks,
+    names: &[&str],
+    store: &IsolatedStore,
+) -> Result<CheckResult> {
+    run_build_checks(flake_ref, checks, names, Some(store.root())).await
+}
+
+async fn run_flake_checks(flake_ref: &str, store: Option<&Path>) -> Result<FlakeChecks> {
+    validate_path(Path::new(flake_ref))?;
 
     let mut command = Command::new("nix");
+    if let Some(store) = store {
+        command.arg("--store").arg(store);
+    }
+    let output = command
+        .args(["eval", "--json", "--impure"])
+        .arg(format!("{flake_ref}#checks"))
+        .args(["--apply", CHECK_NAMES])
+        .output()
+        .await?;
+    if output.status.success().not() {
+        return Err(Error::process_failed(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+async fn run_build_checks(
+    flake_ref: &str,
+    checks: &FlakeChecks,
+    names: &[&str],
+    store: Option<&Path>,
+) -> Result<CheckResult> {
+    validate_path(Path::new(flake_ref))?;
+
+    let mut command = Command::new("nix");
+    if let Some(store) = store {
+        command.arg("--store").arg(store);
+    }
     command
-        .arg("flake")
-        .arg("check")
-        .arg(path)
+        .args(["build", "--no-link"])
+        .args(check_installables(flake_ref, checks, names))
         .arg("--print-build-logs")
         .arg("--log-format")
         .arg("internal-json");
@@ -193,6 +383,14 @@ pub async fn flake_check(flake_path: impl AsRef<Path>) -> Result<CheckResult> {
     Ok(CheckResult { summary })
 }
 
+/// Installables of the checks `names`, quoted as they may hold dots.
+fn check_installables(flake_ref: &str, checks: &FlakeChecks, names: &[&str]) -> Vec<String> {
+    names
+        .iter()
+        .map(|name| format!("{flake_ref}#checks.{}.\"{name}\"", checks.system))
+        .collect()
+}
+
 /// Validate that a path is reasonable for a flake
 fn validate_path(path: &Path) -> Result<()> {
     let path_str = path
@@ -214,7 +412,29 @@ mod tests {
     use tokio::fs::File;
     use tokio::io::AsyncWriteExt;
 
-    use crate::nix::commands::flake_check;
+    use crate::nix::commands::{Error, FlakeChecks, LogError, check_installables, flake_check};
+    use crate::report;
+
+    #[test]
+    fn check_installables_select_the_checks_of_the_system() {
+        let checks: FlakeChecks =
+            serde_json::from_str(r#"{"system":"x86_64-linux","names":["web-build","web.lint"]}"#)
+                .unwrap();
+        assert_eq!(checks.names(), ["web-build", "web.lint"]);
+        assert_eq!(
+            check_installables("git+file:///srv/app.git?rev=abc", &checks, &["web.lint"]),
+            [r#"git+file:///srv/app.git?rev=abc#checks.x86_64-linux."web.lint""#]
+        );
+    }
+
+    #[test]
+    fn report_includes_source_chain() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "nix not found");
+        let err = Error::from(LogError::from(io));
+
+        assert_eq!(err.code(), "nix.log_parsing");
+        assert_eq!(report(&err), "Log parsing error: IO error: nix not found");
+    }
 
     #[tokio::test]
     async fn test_run_checks_detailed() {
@@ -0,0 +1,330 @@
+//! Copying store paths between stores
+//!
+//! [`copy`] runs `nix copy` on the closure of some store paths: a worker
+//! pushes what it built to the binary cache, or pulls paths from a peer
+//! worker's store instead of building or substituting them again. Stores
+//! are Nix store URIs (`http://cache:5000`, `ssh-ng://worker-2`, a local
+//! root); left out, the host store is used on that side.
+//!
+//! Nix only copies the paths the destination lacks. The closure is listed
+//! in the source store first, so the [`CopyResult`] tells the paths it
+//! copied from those that were already present.
+
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::ops::Not;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use super::commands::Error;
+use super::logs::LogEntry;
+use super::retry::RetryPolicy;
+
+type Result<T> = std::result::Result<T, Error>;
+
+const STORE_DIR: &str = "/nix/store/";
+
+/// Activity type of `copying path ...` in Nix's internal JSON log
+const ACT_COPY_PATH: u64 = 100;
+
+/// Verbosity of error messages in Nix's internal JSON log
+const LVL_ERROR: u8 = 0;
+
+/// What to copy and where, for [`copy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyArgs {
+    paths: Vec<String>,
+    from: Option<String>,
+    to: Option<String>,
+    substitute_on_destination: bool,
+    check_sigs: bool,
+    retry: RetryPolicy,
+}
+
+impl CopyArgs {
+    /// Copy the closures of `paths` from the host store to the host store;
+    /// set at least one side with [`CopyArgs::from`] or [`CopyArgs::to`].
+    #[must_use]
+    pub fn new<I, S>(paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            paths: paths.into_iter().map(Into::into).collect(),
+            from: None,
+            to: None,
+            substitute_on_destination: false,
+            check_sigs: true,
+            retry: RetryPolicy::none(),
+        }
+    }
+
+    /// Read from the store at `store` (`--from`).
+    #[must_use]
+    pub fn from(mut self, store: impl Into<String>) -> Self {
+        self.from = Some(store.into());
+        self
+    }
+
+    /// Write to the store at `store` (`--to`).
+    #[must_use]
+    pub fn to(mut self, store: impl Into<String>) -> Self {
+        self.to = Some(store.into());
+        self
+    }
+
+    /// Let the destination fetch paths from its own substituters rather
+    /// than receive them from the source (`--substitute-on-destination`),
+    /// when it is closer to a cache than to the source.
+    #[must_use]
+    pub fn substitute_on_destination(mut self, enabled: bool) -> Self {
+        self.substitute_on_destination = enabled;
+        self
+    }
+
+    /// Accept paths without a trusted signature (`--no-check-sigs`), for
+    /// local builds that are not signed.
+    #[must_use]
+    pub fn no_check_sigs(mut self) -> Self {
+        self.check_sigs = false;
+        self
+    }
+
+    /// Run `nix path-info` and `nix copy` again on a transient failure,
+    /// e.g. a cache that can't be reached; once by default.
+    #[must_use]
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    #[must_use]
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Arguments of `nix copy`, without the paths.
+    fn copy_args(&self) -> Vec<&str> {
+        let mut args = vec!["copy"];
+        if let Some(from) = &self.from {
+            args.extend(["--from", from]);
+        }
+        if let Some(to) = &self.to {
+            args.extend(["--to", to]);
+        }
+        if self.substitute_on_destination {
+            args.push("--substitute-on-destination");
+        }
+        if self.check_sigs.not() {
+            args.push("--no-check-sigs");
+        }
+        args.extend(["--log-format", "internal-json"]);
+        args
+    }
+}
+
+/// Outcome of [`copy`], over the whole closure of the requested paths
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CopyResult {
+    copied: Vec<String>,
+    present: Vec<String>,
+}
+
+impl CopyResult {
+    /// Paths the destination lacked, sorted
+    #[must_use]
+    pub fn copied(&self) -> &[String] {
+        &self.copied
+    }
+
+    /// Paths the destination already had, sorted
+    #[must_use]
+    pub fn present(&self) -> &[String] {
+        &self.present
+    }
+}
+
+/// Copy the closures of the paths of `args` between stores.
+///
+/// # Errors
+///
+/// - [`Error::InvalidStorePath`] if a path is not a store path
+/// - if `nix path-info` or `nix copy` cannot be run or fails, e.g. when a
+///   path is missing from the source or lacks a trusted signature
+pub async fn copy(args: &CopyArgs) -> Result<CopyResult> {
+    if let Some(path) = args.paths.iter().find(|p| p.starts_with(STORE_DIR).not()) {
+        return Err(Error::InvalidStorePath(path.clone()));
+    }
+    if args.paths.is_empty() {
+        return Ok(CopyResult::default());
+    }
+
+    let closure = args.retry.run(|| closure_of(args)).await?;
+    let copied = args.retry.run(|| copy_paths(args)).await?;
+
+    let result = split(closure, &copied);
+    info!(
+        from = args.from.as_deref().unwrap_or("host store"),
+        to = args.to.as_deref().unwrap_or("host store"),
+        copied = result.copied.len(),
+        present = result.present.len(),
+        "Copied store paths"
+    );
+    Ok(result)
+}
+
+/// Run `nix copy`, returning the paths it copied.
+async fn copy_paths(args: &CopyArgs) -> Result<BTreeSet<String>> {
+    let output = Command::new("nix")
+        .args(args.copy_args())
+        .args(&args.paths)
+        .output()
+        .await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.success().not() {
+        return Err(Error::process_failed(
+            output.status.code(),
+            errors(&stderr).unwrap_or_else(|| stderr.to_string()),
+        ));
+    }
+    Ok(copied_paths(&stderr))
+}
+
+/// Every path in the closures of `args.paths`, as the source store has them.
+async fn closure_of(args: &CopyArgs) -> Result<BTreeSet<String>> {
+    let mut command = Command::new("nix");
+    command.args(["path-info", "--recursive"]);
+    if let Some(from) = &args.from {
+        command.args(["--store", from]);
+    }
+    let output = command.args(&args.paths).output().await?;
+    if output.status.success().not() {
+        return Err(Error::process_failed(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    let closure: BTreeSet<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.is_empty().not())
+        .map(ToString::to_string)
+        .collect();
+    debug!(
+        paths = args.paths.len(),
+        closure = closure.len(),
+        "Listed closure to copy"
+    );
+    Ok(closure)
+}
+
+/// Parsed internal-JSON log lines of `stderr`.
+fn log_entries(stderr: &str) -> impl Iterator<Item = LogEntry> + '_ {
+    stderr
+        .lines()
+        .filter_map(|line| line.strip_prefix("@nix "))
+        .filter_map(|json| serde_json::from_str(json).ok())
+}
+
+/// Store paths `nix copy` reported copying.
+fn copied_paths(stderr: &str) -> BTreeSet<String> {
+    log_entries(stderr)
+        .filter_map(|entry| match entry {
+            LogEntry::Start(start) if start.log_type() == ACT_COPY_PATH => {
+                start.field(0).map(ToString::to_string)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Error messages of a failed run, `None` when it logged none.
+fn errors(stderr: &str) -> Option<String> {
+    let messages: Vec<String> = log_entries(stderr)
+        .filter_map(|entry| match entry {
+            LogEntry::Msg(msg) if msg.level() == LVL_ERROR => Some(msg.text().to_string()),
+            _ => None,
+        })
+        .collect();
+    messages.is_empty().not().then(|| messages.join("\n"))
+}
+
+/// `closure` split by whether `nix copy` copied each path.
+fn split(closure: BTreeSet<String>, copied: &BTreeSet<String>) -> CopyResult {
+    let (copied, present) = closure.into_iter().partition(|path| copied.contains(path));
+    CopyResult { copied, present }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_copy_arguments() {
+        let args = CopyArgs::new(["/nix/store/aaa-hello"])
+            .from("ssh-ng://worker-2")
+            .to("http://cache:5000")
+            .substitute_on_destination(true)
+            .no_check_sigs();
+        assert_eq!(
+            args.copy_args(),
+            [
+                "copy",
+                "--from",
+                "ssh-ng://worker-2",
+                "--to",
+                "http://cache:5000",
+                "--substitute-on-destination",
+                "--no-check-sigs",
+                "--log-format",
+                "internal-json",
+            ]
+        );
+        assert_eq!(
+            CopyArgs::new(["/nix/store/aaa-hello"]).copy_args(),
+            ["copy", "--log-format", "internal-json"]
+        );
+    }
+
+    #[test]
+    fn splits_the_closure_into_copied_and_present_paths() {
+        let stderr = r#"@nix {"action":"start","id":1,"level":3,"parent":0,"text":"copying 2 paths","type":103}
+@nix {"action":"start","id":2,"level":3,"parent":1,"text":"copying path '/nix/store/bbb-hello' to 'http://cache:5000'","type":100,"fields":["/nix/store/bbb-hello","local","http://cache:5000"]}
+@nix {"action":"stop","id":2}
+@nix {"action":"start","id":3,"level":3,"parent":1,"text":"copying path '/nix/store/ccc-glibc' to 'http://cache:5000'","type":100,"fields":["/nix/store/ccc-glibc","local","http://cache:5000"]}
+@nix {"action":"stop","id":3}
+@nix {"action":"stop","id":1}"#;
+        let closure: BTreeSet<String> = [
+            "/nix/store/aaa-libidn",
+            "/nix/store/bbb-hello",
+            "/nix/store/ccc-glibc",
+        ]
+        .into_iter()
+        .map(ToString::to_string)
+        .collect();
+        let result = split(closure, &copied_paths(stderr));
+        assert_eq!(
+            result.copied(),
+            ["/nix/store/bbb-hello", "/nix/store/ccc-glibc"]
+        );
+        assert_eq!(result.present(), ["/nix/store/aaa-libidn"]);
+    }
+
+    #[test]
+    fn failures_report_the_logged_errors() {
+        let stderr = r#"@nix {"action":"msg","level":0,"msg":"error: path '/nix/store/aaa-hello' is not valid"}
+@nix {"action":"msg","level":3,"msg":"retrying"}"#;
+        assert_eq!(
+            errors(stderr).as_deref(),
+            Some("error: path '/nix/store/aaa-hello' is not valid")
+        );
+        assert_eq!(errors("plain text"), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_paths_outside_the_store() {
+        let result = copy(&CopyArgs::new(["/tmp/hello"]).to("http://cache:5000")).await;
+        assert!(matches!(result, Err(Error::InvalidStorePath(path)) if path == "/tmp/hello"));
+    }
+}
@@ -0,0 +1,344 @@
+//! Derivations and their build-time dependency graph
+//!
+//! [`derivation_show`] parses `nix derivation show`: what a derivation
+//! builds, with which builder, and the derivations and sources it takes as
+//! inputs. [`dependency_graph`] does the same for every derivation in the
+//! build-time closure (`--recursive`) and links them into a
+//! [`DependencyGraph`], so a scheduler or prefetcher knows everything a
+//! build needs, in an order it can be built in, before running anything.
+//!
+//! Nix 2.19 turned each entry of `inputDrvs` from a list of outputs into
+//! an object with `outputs` and `dynamicOutputs`; both parse the same.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Not;
+use tokio::process::Command;
+
+use super::commands::Error;
+
+/// One derivation, as `nix derivation show` reports it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Derivation {
+    /// Store path of the `.drv`
+    pub path: String,
+    pub name: String,
+    pub system: String,
+    pub builder: String,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    /// Output name → store path; `None` for content-addressed outputs,
+    /// whose path is only known once built
+    pub outputs: BTreeMap<String, Option<String>>,
+    /// Derivation inputs → the outputs of each this one uses
+    pub input_drvs: BTreeMap<String, Vec<String>>,
+    /// Sources, already in the store, this one uses
+    pub input_srcs: Vec<String>,
+}
+
+/// The derivations `installable` evaluates to, e.g. `.#hello` or a
+/// `.drv` store path
+///
+/// # Errors
+///
+/// `nix derivation show` failing (e.g. the flake does not evaluate) or its
+/// output not parsing.
+pub async fn derivation_show(installable: &str) -> Result<Vec<Derivation>, Error> {
+    show(installable, false).await
+}
+
+/// Every derivation needed to build `installable`, itself included, linked
+/// by their inputs
+///
+/// # Errors
+///
+/// Same as [`derivation_show`].
+pub async fn dependency_graph(installable: &str) -> Result<DependencyGraph, Error> {
+    Ok(DependencyGraph::new(show(installable, true).await?))
+}
+
+async fn show(installable: &str, recursive: bool) -> Result<Vec<Derivation>, Error> {
+    let mut command = Command::new("nix");
+    command.args(["derivation", "show"]);
+    if recursive {
+        command.arg("--recursive");
+    }
+    let output = command.arg(installable).output().await?;
+    if output.status.success().not() {
+        return Err(Error::process_failed(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    parse(&output.stdout)
+}
+
+fn parse(json: &[u8]) -> Result<Vec<Derivation>, Error> {
+    let entries: BTreeMap<String, Entry> = serde_json::from_slice(json)?;
+    Ok(entries
+        .into_iter()
+        .map(|(path, entry)| entry.into_derivation(path))
+        .collect())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    system: String,
+    #[serde(default)]
+    builder: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    outputs: BTreeMap<String, Output>,
+    #[serde(default)]
+    input_drvs: BTreeMap<String, InputOutputs>,
+    #[serde(default)]
+    input_srcs: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Output {
+    #[serde(default)]
+    path: Option<String>,
+}
+
+/// Outputs of an input derivation, before and since nix 2.19
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InputOutputs {
+    List(Vec<String>),
+    Object { outputs: Vec<String> },
+}
+
+impl Entry {
+    fn into_derivation(self, path: String) -> Derivation {
+        Derivation {
+            path,
+            name: self.name,
+            system: self.system,
+            builder: self.builder,
+            args: self.args,
+            env: self.env,
+            outputs: self
+                .outputs
+                .into_iter()
+                .map(|(name, output)| (name, output.path))
+                .collect(),
+            input_drvs: self
+                .input_drvs
+                .into_iter()
+                .map(|(drv, outputs)| match outputs {
+                    InputOutputs::List(outputs) | InputOutputs::Object { outputs } => {
+                        (drv, outputs)
+                    }
+                })
+                .collect(),
+            input_srcs: self.input_srcs,
+        }
+    }
+}
+
+/// Derivations of a build-time closure, linked by their inputs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DependencyGraph {
+    derivations: BTreeMap<String, Derivation>,
+}
+
+impl DependencyGraph {
+    /// Graph of `derivations`; inputs that are not among them are left
+    /// out of [`DependencyGraph::dependencies`].
+    #[must_use]
+    pub fn new(derivations: Vec<Derivation>) -> Self {
+        Self {
+            derivations: derivations
+                .into_iter()
+                .map(|drv| (drv.path.clone(), drv))
+                .collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.derivations.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.derivations.is_empty()
+    }
+
+    #[must_use]
+    pub fn get(&self, path: &str) -> Option<&Derivation> {
+        self.derivations.get(path)
+    }
+
+    /// Every derivation, by path
+    pub fn derivations(&self) -> impl Iterator<Item = &Derivation> {
+        self.derivations.values()
+    }
+
+    /// Derivations `path` takes as inputs directly
+    pub fn dependencies<'a>(&'a self, path: &str) -> impl Iterator<Item = &'a Derivation> {
+        self.derivations
+            .get(path)
+            .into_iter()
+            .flat_map(|drv| drv.input_drvs.keys())
+            .filter_map(|input| self.derivations.get(input))
+    }
+
+    /// Derivations no other one depends on, what was asked for
+    #[must_use]
+    pub fn roots(&self) -> Vec<&Derivation> {
+        let inputs: BTreeSet<&str> = self
+            .derivations
+            .values()
+            .flat_map(|drv| drv.input_drvs.keys().map(String::as_str))
+            .collect();
+        self.derivations
+            .values()
+            .filter(|drv| inputs.contains(drv.path.as_str()).not())
+            .collect()
+    }
+
+    /// Sources in the store the whole closure uses, sorted
+    #[must_use]
+    pub fn sources(&self) -> BTreeSet<&str> {
+        self.derivations
+            .values()
+            .flat_map(|drv| drv.input_srcs.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Every derivation after all of its dependencies, the order they can
+    /// be built in. Derivation graphs have no cycles; if one did, its
+    /// derivations would be left out.
+    #[must_use]
+    pub fn build_order(&self) -> Vec<&Derivation> {
+        let mut waiting: BTreeMap<&str, usize> = self
+            .derivations
+            .values()
+            .map(|drv| (drv.path.as_str(), self.dependencies(&drv.path).count()))
+            .collect();
+        let mut dependents: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for drv in self.derivations.values() {
+            for input in self.dependencies(&drv.path) {
+                dependents
+                    .entry(input.path.as_str())
+                    .or_default()
+                    .push(drv.path.as_str());
+            }
+        }
+
+        let mut ready: Vec<&str> = waiting
+            .iter()
+            .filter(|&(_, &count)| count == 0)
+            .map(|(&path, _)| path)
+            .collect();
+        let mut order = Vec::with_capacity(self.derivations.len());
+        while let Some(path) = ready.pop() {
+            order.push(&self.derivations[path]);
+            for &dependent in dependents.get(path).into_iter().flatten() {
+                if let Some(count) = waiting.get_mut(dependent) {
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.push(dependent);
+                    }
+                }
+            }
+        }
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drv(name: &str, inputs: &[&str]) -> Derivation {
+        Derivation {
+            path: format!("/nix/store/{name}.drv"),
+            name: name.to_string(),
+            input_drvs: inputs
+                .iter()
+                .map(|input| (format!("/nix/store/{input}.drv"), vec!["out".to_string()]))
+                .collect(),
+            ..Derivation::default()
+        }
+    }
+
+    #[test]
+    fn parses_both_input_formats() {
+        let json = r#"{
+          "/nix/store/aaa-hello-2.12.drv": {
+            "name": "hello-2.12",
+            "system": "x86_64-linux",
+            "builder": "/nix/store/bbb-bash/bin/bash",
+            "args": ["-e", "/nix/store/ccc-builder.sh"],
+            "env": {"out": "/nix/store/ddd-hello-2.12"},
+            "outputs": {"out": {"path": "/nix/store/ddd-hello-2.12"}, "ca": {"hashAlgo": "sha256", "method": "nar"}},
+            "inputDrvs": {"/nix/store/eee-gcc.drv": ["out", "lib"]},
+            "inputSrcs": ["/nix/store/ccc-builder.sh"]
+          },
+          "/nix/store/fff-app.drv": {
+            "name": "app",
+            "inputDrvs": {"/nix/store/aaa-hello-2.12.drv": {"outputs": ["out"], "dynamicOutputs": {}}}
+          }
+        }"#;
+        let derivations = parse(json.as_bytes()).unwrap();
+        assert_eq!(derivations.len(), 2);
+
+        let hello = &derivations[0];
+        assert_eq!(hello.path, "/nix/store/aaa-hello-2.12.drv");
+        assert_eq!(hello.system, "x86_64-linux");
+        assert_eq!(hello.args.len(), 2);
+        assert_eq!(
+            hello.outputs["out"].as_deref(),
+            Some("/nix/store/ddd-hello-2.12")
+        );
+        assert_eq!(hello.outputs["ca"], None);
+        assert_eq!(hello.input_drvs["/nix/store/eee-gcc.drv"], ["out", "lib"]);
+        assert_eq!(hello.input_srcs, ["/nix/store/ccc-builder.sh"]);
+
+        assert_eq!(
+            derivations[1].input_drvs["/nix/store/aaa-hello-2.12.drv"],
+            ["out"]
+        );
+    }
+
+    #[test]
+    fn graph_orders_dependencies_first() {
+        let graph = DependencyGraph::new(vec![
+            drv("app", &["lib", "tool"]),
+            drv("lib", &["libc"]),
+            drv("tool", &["libc", "bootstrap"]),
+            drv("libc", &[]),
+        ]);
+        assert_eq!(graph.len(), 4);
+
+        let roots: Vec<&str> = graph.roots().iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(roots, ["app"]);
+        let deps: Vec<&str> = graph
+            .dependencies("/nix/store/tool.drv")
+            .map(|d| d.name.as_str())
+            .collect();
+        assert_eq!(deps, ["libc"], "inputs outside the graph are left out");
+
+        let order: Vec<&str> = graph
+            .build_order()
+            .iter()
+            .map(|d| d.name.as_str())
+            .collect();
+        assert_eq!(order.len(), 4);
+        let position = |name: &str| order.iter().position(|n| *n == name).unwrap();
+        assert!(position("libc") < position("lib"));
+        assert!(position("libc") < position("tool"));
+        assert!(position("lib") < position("app"));
+        assert!(position("tool") < position("app"));
+    }
+}
@@ -0,0 +1,278 @@
+//! Structured Nix error diagnostics
+//!
+//! A failed Nix command leaves its error on stderr, or in the `msg` entries
+//! of its `--log-format internal-json` log. [`Diagnostics`] sorts that text
+//! into an evaluation error, a failed build or a hash mismatch, with the
+//! derivation at fault and the lines worth showing next to the message, so
+//! a UI can tell them apart instead of printing the raw stderr.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// What went wrong, most specific first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// A fixed-output derivation fetched something other than its hash
+    HashMismatch,
+    /// A derivation's builder failed
+    Build,
+    /// The Nix expressions didn't evaluate
+    Evaluation,
+    /// Anything else, e.g. a cache that can't be reached
+    #[default]
+    Other,
+}
+
+/// Messages of Nix evaluation errors, without the position or trace lines
+/// that usually come with them
+const EVALUATION: [&str; 14] = [
+    "undefined variable",
+    "syntax error",
+    "infinite recursion",
+    "assertion",
+    "evaluation aborted",
+    "called with unexpected argument",
+    "called without required argument",
+    "cannot coerce",
+    "value is a",
+    "does not provide attribute",
+    "attribute '",
+    "getting status of",
+    "cannot find flake",
+    "while evaluating",
+];
+
+/// Error of a failed Nix command, parsed from its stderr
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostics {
+    kind: DiagnosticKind,
+    /// Innermost `error:` line, without the prefix
+    message: Option<String>,
+    /// Store path of the derivation that failed to build or fetch
+    derivation: Option<String>,
+    /// Lines around the message: positions, the builder's last log lines,
+    /// the specified and actual hashes
+    hints: Vec<String>,
+}
+
+impl Diagnostics {
+    /// Parse the stderr of a Nix command, or the text of the error messages
+    /// of its internal-json log. Colors are ignored.
+    #[must_use]
+    pub fn parse(stderr: &str) -> Self {
+        let stderr = strip_ansi(stderr);
+        let lines: Vec<&str> = stderr.lines().map(str::trim).collect();
+
+        let message = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("error:"))
+            .map(str::trim)
+            .rfind(|message| !message.is_empty())
+            .map(ToString::to_string);
+
+        let hash_mismatch = lines
+            .iter()
+            .find(|line| line.contains("hash mismatch in fixed-output derivation"));
+        let build = lines.iter().find(|line| {
+            line.contains("builder for '") || line.starts_with("error: Cannot build '")
+        });
+        let (kind, derivation) = match (hash_mismatch, build) {
+            (Some(line), _) => (DiagnosticKind::HashMismatch, derivation(line)),
+            (None, Some(line)) => (DiagnosticKind::Build, derivation(line)),
+            (None, None) if lines.iter().any(|line| is_evaluation(line)) => {
+                (DiagnosticKind::Evaluation, None)
+            }
+            (None, None) => (DiagnosticKind::Other, None),
+        };
+
+        Self {
+            kind,
+            hints: hints(&lines, message.as_deref()),
+            message,
+            derivation,
+        }
+    }
+
+    #[must_use]
+    pub fn kind(&self) -> DiagnosticKind {
+        self.kind
+    }
+
+    #[must_use]
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    #[must_use]
+    pub fn derivation(&self) -> Option<&str> {
+        self.derivation.as_deref()
+    }
+
+    #[must_use]
+    pub fn hints(&self) -> &[String] {
+        &self.hints
+    }
+}
+
+fn is_evaluation(line: &str) -> bool {
+    EVALUATION.iter().any(|pattern| line.contains(pattern)) || is_position(line)
+}
+
+/// `at /nix/store/…-source/flake.nix:7:17:`
+fn is_position(line: &str) -> bool {
+    line.strip_prefix("at ")
+        .and_then(|position| position.strip_suffix(':'))
+        .and_then(|position| position.rsplit(':').nth(1))
+        .is_some_and(|number| number.parse::<u32>().is_ok())
+}
+
+/// First `.drv` store path quoted on `line`
+fn derivation(line: &str) -> Option<String> {
+    line.split('\'')
+        .find(|quoted| {
+            quoted.starts_with("/nix/store/")
+                && Path::new(quoted).extension() == Some("drv".as_ref())
+        })
+        .map(ToString::to_string)
+}
+
+/// Lines of the error, leaving out the message itself, the "while
+/// evaluating" trace frames and the source snippets under positions
+fn hints(lines: &[&str], message: Option<&str>) -> Vec<String> {
+    let mut hints = Vec::new();
+    let mut in_trace = false;
+    for line in lines.iter().skip_while(|line| !line.starts_with("error:")) {
+        if line.is_empty() {
+            in_trace = false;
+            continue;
+        }
+        if line.starts_with('…') {
+            in_trace = true;
+        }
+        let snippet = line
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .starts_with('|');
+        let is_message = line
+            .strip_prefix("error:")
+            .is_some_and(|text| text.trim().is_empty() || Some(text.trim()) == message);
+        if in_trace || snippet || is_message || line.ends_with("log lines:") {
+            continue;
+        }
+        hints.push((*line).to_string());
+    }
+    hints
+}
+
+/// `text` without its terminal color escapes
+fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // CSI sequences end with a letter, e.g. `\x1b[31;1m`
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluation_errors_keep_their_position() {
+        let stderr = "\u{1b}[31;1merror:\u{1b}[0m
+       … while calling the 'derivationStrict' builtin
+         at /builtin/derivation.nix:9:12: (source not available)
+
+       … while evaluating derivation 'hello'
+         whose name attribute is located at /nix/store/aaa-source/flake.nix:5:9
+
+       error: undefined variable 'pkgz'
+       at /nix/store/aaa-source/flake.nix:7:17:
+            6|         name = \"hello\";
+            7|         buildInputs = [ pkgz.hello ];
+             |                 ^
+";
+        let diagnostics = Diagnostics::parse(stderr);
+        assert_eq!(diagnostics.kind(), DiagnosticKind::Evaluation);
+        assert_eq!(diagnostics.message(), Some("undefined variable 'pkgz'"));
+        assert_eq!(diagnostics.derivation(), None);
+        assert_eq!(
+            diagnostics.hints(),
+            ["at /nix/store/aaa-source/flake.nix:7:17:"]
+        );
+    }
+
+    #[test]
+    fn build_failures_name_their_derivation() {
+        let stderr = "error: builder for '/nix/store/abc-hello.drv' failed with exit code 2;
+       last 2 log lines:
+       > make: *** No rule to make target 'install'.  Stop.
+       > error: install phase failed
+       For full logs, run 'nix log /nix/store/abc-hello.drv'.
+error: 1 dependencies of derivation '/nix/store/def-checks.drv' failed to build
+";
+        let diagnostics = Diagnostics::parse(stderr);
+        assert_eq!(diagnostics.kind(), DiagnosticKind::Build);
+        assert_eq!(diagnostics.derivation(), Some("/nix/store/abc-hello.drv"));
+        assert_eq!(
+            diagnostics.message(),
+            Some("1 dependencies of derivation '/nix/store/def-checks.drv' failed to build")
+        );
+        assert_eq!(
+            diagnostics.hints(),
+            [
+                "error: builder for '/nix/store/abc-hello.drv' failed with exit code 2;",
+                "> make: *** No rule to make target 'install'.  Stop.",
+                "> error: install phase failed",
+                "For full logs, run 'nix log /nix/store/abc-hello.drv'.",
+            ]
+        );
+    }
+
+    #[test]
+    fn hash_mismatches_show_both_hashes() {
+        let stderr = "error: hash mismatch in fixed-output derivation '/nix/store/xyz-source.drv':
+         specified: sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=
+            got:    sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB=
+error: 1 dependencies of derivation '/nix/store/app.drv' failed to build
+";
+        let diagnostics = Diagnostics::parse(stderr);
+        assert_eq!(diagnostics.kind(), DiagnosticKind::HashMismatch);
+        assert_eq!(diagnostics.derivation(), Some("/nix/store/xyz-source.drv"));
+        assert_eq!(
+            diagnostics.hints(),
+            [
+                "error: hash mismatch in fixed-output derivation '/nix/store/xyz-source.drv':",
+                "specified: sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+                "got:    sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB=",
+            ]
+        );
+    }
+
+    #[test]
+    fn anything_else_is_other() {
+        let diagnostics = Diagnostics::parse(
+            "error: unable to download 'https://cache.nixos.org/x.narinfo': HTTP error 503",
+        );
+        assert_eq!(diagnostics.kind(), DiagnosticKind::Other);
+        assert!(
+            diagnostics
+                .message()
+                .unwrap()
+                .starts_with("unable to download")
+        );
+        assert_eq!(Diagnostics::parse(""), Diagnostics::default());
+    }
+}
@@ -217,39 +217,44 @@ pub struct FlakeMetadata {
     pub nixos_modules: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum NixParserError {
+    #[error("Command failed to start")]
+    Spawn(#[from] std::io::Error),
+    #[error("Command failed: {0}")]
     CommandFailed(String),
-    ParseError(String),
+    #[error("Parse error")]
+    ParseError(#[from] serde_json::Error),
+    #[error("Not a Nix flake")]
     NotAFlake,
 }
 
-impl std::fmt::Display for NixParserError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl NixParserError {
+    /// Stable identifier for this failure, independent of the message.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
         match self {
-            NixParserError::CommandFailed(msg) => write!(f, "Command failed: {}", msg),
-            NixParserError::ParseError(msg) => write!(f, "Parse error: {}", msg),
-            NixParserError::NotAFlake => write!(f, "Not a Nix flake"),
+            NixParserError::Spawn(_) => "flake.spawn",
+            NixParserError::CommandFailed(_) => "flake.command_failed",
+            NixParserError::ParseError(_) => "flake.parse",
+            NixParserError::NotAFlake => "flake.not_a_flake",
         }
     }
 }
 
-impl std::error::Error for NixParserError {}
-
 type Result<T> = std::result::Result<T, NixParserError>;
 
 /// Run a nix command and return the parsed JSON output
 fn run_nix_command<T: serde::de::DeserializeOwned>(args: &[&str]) -> Result<T> {
     let output = Command::new("nix")
         .args(args)
-        .output()
-        .map_err(|e| NixParserError::CommandFailed(e.to_string()))?;
+        .output()?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(NixParserError::CommandFailed(stderr.to_string()));
     }
-    serde_json::from_slice(&output.stdout).map_err(|e| NixParserError::ParseError(e.to_string()))
+    Ok(serde_json::from_slice(&output.stdout)?)
 }
 
 /// Get the HEAD commit hash from a bare git repository
@@ -257,8 +262,7 @@ fn get_head_rev(bare_repo_path: &std::path::Path) -> Result<String> {
     let output = Command::new("git")
         .args(["--git-dir", &bare_repo_path.to_string_lossy()])
         .args(["rev-parse", "HEAD"])
-        .output()
-        .map_err(|e| NixParserError::CommandFailed(e.to_string()))?;
+        .output()?;
 
     if !output.status.success() {
         return Err(NixParserError::CommandFailed(
@@ -0,0 +1,395 @@
+//! Bootable disk images from system closures
+//!
+//! [`ImageBuilder::build`] turns the toplevel of a NixOS system into an
+//! unpartitioned ext4 image labelled `nixos`, the layout `mkVmImage`
+//! produces: the closure under `/nix/store`, `/sbin/init` pointing at the
+//! toplevel, and a `/nix-path-registration` the guest loads on first boot.
+//! The filesystem is filled with `mkfs.ext4 -d`, so no VM runs at build
+//! time. A qcow2 image is converted from it with `qemu-img`.
+//!
+//! The image is added to the Nix store and, when configured, copied to a
+//! binary cache. Builds are cached by closure: a toplevel store path fixes
+//! its whole closure, so a second build of the same toplevel and format
+//! returns the recorded image as long as it is still in the store.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Not;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use super::commands::Error;
+use super::path_info::closure_info;
+use super::retry::RetryPolicy;
+use super::store::remove_tree;
+
+type Result<T> = std::result::Result<T, Error>;
+
+const STORE_DIR: &str = "/nix/store/";
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Raw,
+    Qcow2,
+}
+
+impl ImageFormat {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ImageFormat::Raw => "raw",
+            ImageFormat::Qcow2 => "qcow2",
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(ImageFormat::Raw),
+            "qcow2" => Ok(ImageFormat::Qcow2),
+            other => Err(format!(
+                "unknown image format {other:?}, expected raw or qcow2"
+            )),
+        }
+    }
+}
+
+/// A built image, as recorded in the builder's cache directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskImage {
+    pub toplevel: String,
+    pub format: ImageFormat,
+    /// Store path of the image file
+    pub path: String,
+    pub size_bytes: u64,
+    /// Returned from the cache rather than built
+    #[serde(skip)]
+    pub cached: bool,
+}
+
+/// Builds disk images, keeping an index of them under `cache_dir`
+#[derive(Debug, Clone)]
+pub struct ImageBuilder {
+    cache_dir: PathBuf,
+    upload_to: Option<String>,
+    additional_space: u64,
+    retry: RetryPolicy,
+}
+
+impl ImageBuilder {
+    /// Builder recording its images in `cache_dir`, also used for staging.
+    #[must_use]
+    pub fn new(cache_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            cache_dir: cache_dir.into(),
+            upload_to: None,
+            additional_space: 256 * MIB,
+            retry: RetryPolicy::none(),
+        }
+    }
+
+    /// Copy every built image to `store`, a store URI such as the binary cache.
+    #[must_use]
+    pub fn with_upload(mut self, store: impl Into<String>) -> Self {
+        self.upload_to = Some(store.into());
+        self
+    }
+
+    /// Free space left in the filesystem on top of the closure, 256 MiB
+    /// by default.
+    #[must_use]
+    pub fn with_additional_space(mut self, bytes: u64) -> Self {
+        self.additional_space = bytes;
+        self
+    }
+
+    /// Build and upload again on a transient failure, e.g. the store
+    /// locked by another Nix process or the cache unreachable; once by
+    /// default.
+    #[must_use]
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// The image of `toplevel` in `format`, built unless the cache has it.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::InvalidStorePath`] if `toplevel` is not a store path
+    /// - if the closure cannot be queried, or `mkfs.ext4`, `qemu-img`,
+    ///   `nix store add-file` or the upload fails
+    pub async fn build(&self, toplevel: &str, format: ImageFormat) -> Result<DiskImage> {
+        let key = image_key(toplevel, format)?;
+        if let Some(image) = self.cached(&key).await? {
+            debug!(toplevel, %format, path = image.path, "Image cached");
+            return Ok(image);
+        }
+
+        info!(toplevel, %format, "Building disk image");
+        let image = self
+            .retry
+            .run(|| self.build_staged(&key, toplevel, format))
+            .await?;
+
+        if let Some(store) = &self.upload_to {
+            self.retry
+                .run(|| async {
+                    run(Command::new("nix").args(["copy", "--to", store, &image.path])).await
+                })
+                .await?;
+            info!(path = image.path, store, "Uploaded disk image");
+        }
+        tokio::fs::write(self.index_path(&key), serde_json::to_vec_pretty(&image)?).await?;
+        Ok(image)
+    }
+
+    /// The recorded image for `key`, if its store path still exists.
+    async fn cached(&self, key: &str) -> Result<Option<DiskImage>> {
+        let index = match tokio::fs::read(self.index_path(key)).await {
+            Ok(index) => index,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let image: DiskImage = serde_json::from_slice(&index)?;
+        if tokio::fs::try_exists(&image.path).await? {
+            return Ok(Some(DiskImage {
+                cached: true,
+                ..image
+            }));
+        }
+        Ok(None)
+    }
+
+    fn index_path(&self, key: &str) -> PathBuf {
+        self.cache_dir.join(format!("{key}.json"))
+    }
+
+    /// Build in a fresh staging directory, removed afterwards.
+    async fn build_staged(
+        &self,
+        key: &str,
+        toplevel: &str,
+        format: ImageFormat,
+    ) -> Result<DiskImage> {
+        let staging = self.cache_dir.join(format!("tmp-{key}"));
+        if tokio::fs::try_exists(&staging).await? {
+            remove_tree(staging.clone()).await?;
+        }
+        let built = self.build_in(&staging, toplevel, format).await;
+        remove_tree(staging).await?;
+        built
+    }
+
+    async fn build_in(
+        &self,
+        staging: &Path,
+        toplevel: &str,
+        format: ImageFormat,
+    ) -> Result<DiskImage> {
+        let closure = closure_info(toplevel).await?;
+        let root = staging.join("root");
+        populate(
+            &root,
+            toplevel,
+            &closure.iter().map(|p| p.path.as_str()).collect::<Vec<_>>(),
+        )
+        .await?;
+
+        let nar_size = closure.iter().map(|p| p.nar_size).sum();
+        let raw = staging.join("nixos.raw");
+        tokio::fs::File::create(&raw)
+            .await?
+            .set_len(image_size(nar_size, self.additional_space))
+            .await?;
+        run(Command::new("mkfs.ext4")
+            .args(["-q", "-F", "-L", "nixos", "-d"])
+            .arg(&root)
+            .arg(&raw))
+        .await?;
+
+        let file = match format {
+            ImageFormat::Raw => raw,
+            ImageFormat::Qcow2 => {
+                let qcow2 = staging.join("nixos.qcow2");
+                run(Command::new("qemu-img")
+                    .args(["convert", "-f", "raw", "-O", "qcow2"])
+                    .arg(&raw)
+                    .arg(&qcow2))
+                .await?;
+                qcow2
+            }
+        };
+
+        let size_bytes = tokio::fs::metadata(&file).await?.len();
+        let added = run(Command::new("nix")
+            .args(["store", "add-file", "--name", &format!("nixos.{format}")])
+            .arg(&file))
+        .await?;
+        let path = added.trim().to_string();
+        if path.is_empty() {
+            return Err(Error::BuildOutputMissing);
+        }
+        info!(toplevel, %format, path, size_bytes, "Disk image built");
+        Ok(DiskImage {
+            toplevel: toplevel.to_string(),
+            format,
+            path,
+            size_bytes,
+            cached: false,
+        })
+    }
+}
+
+/// Cache key of an image: the hash part of the toplevel, which fixes the
+/// whole closure, and the format, e.g. `<hash>-qcow2`.
+fn image_key(toplevel: &str, format: ImageFormat) -> Result<String> {
+    let hash = toplevel
+        .strip_prefix(STORE_DIR)
+        .and_then(|name| name.split_once('-'))
+        .map(|(hash, _)| hash)
+        .filter(|hash| hash.len() == 32 && hash.bytes().all(|b| b.is_ascii_alphanumeric()))
+        .ok_or_else(|| Error::InvalidStorePath(toplevel.to_string()))?;
+    Ok(format!("{hash}-{format}"))
+}
+
+/// Filesystem size for a closure of `nar_size` bytes: a fifth more for
+/// filesystem overhead, plus `additional_space`, rounded up to a MiB.
+fn image_size(nar_size: u64, additional_space: u64) -> u64 {
+    (nar_size + nar_size / 5 + additional_space).div_ceil(MIB) * MIB
+}
+
+/// Lay out the image root: the closure, `/sbin/init`, the system profile
+/// and the registration of the store paths.
+async fn populate(root: &Path, toplevel: &str, paths: &[&str]) -> Result<()> {
+    const DIRS: [&str; 14] = [
+        "nix/store",
+        "nix/var/nix/profiles",
+        "nix/var/nix/db",
+        "nix/var/nix/gcroots",
+        "sbin",
+        "etc",
+        "proc",
+        "sys",
+        "dev",
+        "tmp",
+        "run",
+        "root",
+        "var/log",
+        "var/lib",
+    ];
+    for dir in DIRS {
+        tokio::fs::create_dir_all(root.join(dir)).await?;
+    }
+
+    run(Command::new("cp")
+        .args(["-a", "--reflink=auto"])
+        .args(paths)
+        .arg(root.join("nix/store")))
+    .await?;
+    tokio::fs::symlink(format!("{toplevel}/init"), root.join("sbin/init")).await?;
+    tokio::fs::symlink(toplevel, root.join("nix/var/nix/profiles/system")).await?;
+    tokio::fs::symlink(
+        format!("{toplevel}/etc/os-release"),
+        root.join("etc/os-release"),
+    )
+    .await?;
+    tokio::fs::write(root.join("etc/NIXOS"), "").await?;
+
+    let registration = run(Command::new("nix-store").arg("--dump-db").args(paths)).await?;
+    tokio::fs::write(root.join("nix-path-registration"), registration).await?;
+    Ok(())
+}
+
+/// Run `command` to completion, returning its stdout.
+async fn run(command: &mut Command) -> Result<String> {
+    let output = command.output().await?;
+    if output.status.success().not() {
+        return Err(Error::process_failed(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOPLEVEL: &str = "/nix/store/0123456789abcdfghijklmnpqrsvwxyz-nixos-system-web";
+
+    #[test]
+    fn images_are_keyed_by_toplevel_hash_and_format() {
+        assert_eq!(
+            image_key(TOPLEVEL, ImageFormat::Qcow2).unwrap(),
+            "0123456789abcdfghijklmnpqrsvwxyz-qcow2"
+        );
+        for path in [
+            "/tmp/nixos-system",
+            "/nix/store/short-nixos",
+            "/nix/store/../x",
+        ] {
+            assert!(matches!(
+                image_key(path, ImageFormat::Raw),
+                Err(Error::InvalidStorePath(_))
+            ));
+        }
+        assert_eq!("qcow2".parse(), Ok(ImageFormat::Qcow2));
+        assert!("vmdk".parse::<ImageFormat>().is_err());
+    }
+
+    #[test]
+    fn image_size_leaves_room_for_the_filesystem() {
+        assert_eq!(image_size(0, 256 * MIB), 256 * MIB);
+        assert_eq!(image_size(100 * MIB, 0), 120 * MIB);
+        assert_eq!(image_size(1, 0), MIB);
+    }
+
+    #[tokio::test]
+    async fn cached_images_are_returned_while_in_the_store() {
+        let dir = std::env::temp_dir().join(format!("image-cache-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let builder = ImageBuilder::new(&dir);
+        let key = image_key(TOPLEVEL, ImageFormat::Raw).unwrap();
+        assert_eq!(builder.cached(&key).await.unwrap(), None);
+
+        // A file standing in for the image's store path
+        let file = dir.join("nixos.raw");
+        tokio::fs::write(&file, "image").await.unwrap();
+        let image = DiskImage {
+            toplevel: TOPLEVEL.to_string(),
+            format: ImageFormat::Raw,
+            path: file.display().to_string(),
+            size_bytes: 5,
+            cached: false,
+        };
+        tokio::fs::write(
+            builder.index_path(&key),
+            serde_json::to_vec(&image).unwrap(),
+        )
+        .await
+        .unwrap();
+        let cached = builder.cached(&key).await.unwrap().unwrap();
+        assert!(cached.cached);
+        assert_eq!(cached.path, image.path);
+
+        // Garbage collected: built again
+        tokio::fs::remove_file(&file).await.unwrap();
+        assert_eq!(builder.cached(&key).await.unwrap(), None);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
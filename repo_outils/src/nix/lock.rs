@@ -0,0 +1,472 @@
+//! Locked flake inputs from `nix flake metadata --json`
+//!
+//! [`flake_metadata`] tells which revision of a flake was evaluated and
+//! [`flake_lock`] the exact inputs it was locked to, so a generation can
+//! record what it was built from. Inputs are named by their path from the
+//! root flake, `crane/nixpkgs` for the `nixpkgs` input of `crane`; inputs
+//! that `follows` another are left out, the input they follow is listed.
+//!
+//! [`flake_update`] and [`flake_lock_update`] rewrite the `flake.lock` of a
+//! checkout, e.g. for CI to refresh some inputs on a schedule and build the
+//! result, and return what changed in it as [`LockChange`]s.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Not;
+use std::path::Path;
+use tokio::process::Command;
+
+use super::commands::Error;
+
+/// A flake as `nix flake metadata` resolved and locked it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedFlake {
+    /// Locked URL of the flake itself, e.g. `git+file:///src?rev=…`
+    pub url: String,
+    /// URL as given, e.g. `github:owner/repo`
+    pub original_url: String,
+    /// Commit, unless the tree is dirty
+    pub revision: Option<String>,
+    /// Commit the dirty tree is based on, suffixed with `-dirty`
+    pub dirty_revision: Option<String>,
+    /// Unix seconds
+    pub last_modified: Option<u64>,
+    pub nar_hash: Option<String>,
+    /// Store path of the flake's source
+    pub path: Option<String>,
+    pub description: Option<String>,
+    pub inputs: Vec<LockedInput>,
+}
+
+/// One input of `flake.lock`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedInput {
+    /// Path from the root flake, `/`-separated
+    pub name: String,
+    /// Locked URL, e.g. `github:NixOS/nixpkgs/<rev>`
+    pub url: String,
+    pub rev: Option<String>,
+    pub nar_hash: Option<String>,
+    /// Unix seconds
+    pub last_modified: Option<u64>,
+}
+
+/// Metadata and locked inputs of `flake_ref`
+///
+/// # Errors
+///
+/// `nix flake metadata` failing (e.g. the flake does not exist or cannot
+/// be locked) or its output not parsing.
+pub async fn flake_metadata(flake_ref: &str) -> Result<LockedFlake, Error> {
+    let output = Command::new("nix")
+        .arg("flake")
+        .arg("metadata")
+        .arg("--json")
+        .arg("--no-write-lock-file")
+        .arg(flake_ref)
+        .output()
+        .await?;
+    if output.status.success().not() {
+        return Err(Error::process_failed(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+
+    let json: MetadataJson = serde_json::from_slice(&output.stdout)?;
+    Ok(json.into())
+}
+
+/// Locked inputs of `flake_ref`, direct ones first
+///
+/// # Errors
+///
+/// Same as [`flake_metadata`].
+pub async fn flake_lock(flake_ref: &str) -> Result<Vec<LockedInput>, Error> {
+    Ok(flake_metadata(flake_ref).await?.inputs)
+}
+
+/// An input the lock file gained, lost or moved to another revision
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockChange {
+    pub name: String,
+    /// `None` for an input added to the lock
+    pub before: Option<LockedInput>,
+    /// `None` for an input removed from it
+    pub after: Option<LockedInput>,
+}
+
+/// Update `inputs` of the flake in `flake_dir` to their latest revision,
+/// every input when empty, and write its `flake.lock`
+///
+/// # Errors
+///
+/// Same as [`flake_metadata`], or `nix flake update` failing, e.g. on an
+/// input the flake does not have.
+pub async fn flake_update(flake_dir: &Path, inputs: &[&str]) -> Result<Vec<LockChange>, Error> {
+    relock(
+        flake_dir,
+        Command::new("nix").arg("flake").arg("update").args(inputs),
+    )
+    .await
+}
+
+/// Lock the inputs of the flake in `flake_dir` that its `flake.lock` lacks
+/// or that no longer match `flake.nix`, leaving the others as they are
+///
+/// # Errors
+///
+/// Same as [`flake_metadata`], or `nix flake lock` failing.
+pub async fn flake_lock_update(flake_dir: &Path) -> Result<Vec<LockChange>, Error> {
+    relock(flake_dir, Command::new("nix").arg("flake").arg("lock")).await
+}
+
+/// Run `command` on the flake in `flake_dir` and diff its lock around it
+async fn relock(flake_dir: &Path, command: &mut Command) -> Result<Vec<LockChange>, Error> {
+    let flake_ref = flake_dir
+        .to_str()
+        .ok_or_else(|| Error::InvalidFlakePath(flake_dir.display().to_string()))?;
+    // A flake without a lock yet has no inputs to compare with
+    let before = flake_lock(flake_ref).await.unwrap_or_default();
+
+    let output = command.arg("--flake").arg(flake_dir).output().await?;
+    if output.status.success().not() {
+        return Err(Error::process_failed(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+
+    let after = flake_lock(flake_ref).await?;
+    Ok(diff_locks(&before, &after))
+}
+
+/// Inputs added, removed or moved to another revision between two locks,
+/// by name
+#[must_use]
+pub fn diff_locks(before: &[LockedInput], after: &[LockedInput]) -> Vec<LockChange> {
+    let mut inputs: BTreeMap<&str, (Option<&LockedInput>, Option<&LockedInput>)> = BTreeMap::new();
+    for input in before {
+        inputs.entry(&input.name).or_default().0 = Some(input);
+    }
+    for input in after {
+        inputs.entry(&input.name).or_default().1 = Some(input);
+    }
+    inputs
+        .into_iter()
+        .filter(|(_, (before, after))| match (before, after) {
+            (Some(b), Some(a)) => b.rev != a.rev || b.nar_hash != a.nar_hash || b.url != a.url,
+            _ => true,
+        })
+        .map(|(name, (before, after))| LockChange {
+            name: name.to_string(),
+            before: before.cloned(),
+            after: after.cloned(),
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MetadataJson {
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    original_url: String,
+    revision: Option<String>,
+    dirty_revision: Option<String>,
+    last_modified: Option<u64>,
+    path: Option<String>,
+    description: Option<String>,
+    locked: Option<Locked>,
+    locks: Option<Lock>,
+}
+
+/// `flake.lock`
+#[derive(Deserialize)]
+struct Lock {
+    root: String,
+    nodes: BTreeMap<String, Node>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    /// Node keys, or for inputs that `follows`, the list of the input path
+    /// they follow
+    #[serde(default)]
+    inputs: BTreeMap<String, serde_json::Value>,
+    locked: Option<Locked>,
+}
+
+/// Attributes of a locked reference; which ones are set depends on `type`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Locked {
+    #[serde(rename = "type")]
+    kind: String,
+    owner: Option<String>,
+    repo: Option<String>,
+    host: Option<String>,
+    url: Option<String>,
+    path: Option<String>,
+    #[serde(rename = "ref")]
+    reference: Option<String>,
+    rev: Option<String>,
+    nar_hash: Option<String>,
+    last_modified: Option<u64>,
+}
+
+impl Locked {
+    /// Flake URL of the reference, pinned to its revision when it has one
+    fn url(&self) -> String {
+        let rev = self.rev.as_deref();
+        match (self.kind.as_str(), &self.owner, &self.repo) {
+            (kind @ ("github" | "gitlab" | "sourcehut"), Some(owner), Some(repo)) => {
+                let mut url = format!("{kind}:{owner}/{repo}");
+                if let Some(rev) = rev.or(self.reference.as_deref()) {
+                    url = format!("{url}/{rev}");
+                }
+                if let Some(host) = &self.host {
+                    url = format!("{url}?host={host}");
+                }
+                url
+            }
+            ("path", ..) => format!("path:{}", self.path.as_deref().unwrap_or_default()),
+            (kind @ ("git" | "hg"), ..) => {
+                let base = self.url.as_deref().unwrap_or_default();
+                match rev {
+                    Some(rev) => format!("{kind}+{base}?rev={rev}"),
+                    None => format!("{kind}+{base}"),
+                }
+            }
+            (kind, ..) => match &self.url {
+                Some(url) if kind == "tarball" || kind == "file" => url.clone(),
+                Some(url) => format!("{kind}+{url}"),
+                None => format!("{kind}:"),
+            },
+        }
+    }
+}
+
+impl Lock {
+    /// Inputs reachable from the root, breadth first, skipping `follows`
+    fn inputs(&self) -> Vec<LockedInput> {
+        let mut inputs = Vec::new();
+        let mut queue = VecDeque::from([(String::new(), self.root.as_str())]);
+        while let Some((prefix, key)) = queue.pop_front() {
+            let Some(node) = self.nodes.get(key) else {
+                continue;
+            };
+            for (name, input) in &node.inputs {
+                let Some(child) = input.as_str() else {
+                    continue;
+                };
+                let name = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{prefix}/{name}")
+                };
+                if let Some(locked) = self.nodes.get(child).and_then(|n| n.locked.as_ref()) {
+                    inputs.push(LockedInput {
+                        name: name.clone(),
+                        url: locked.url(),
+                        rev: locked.rev.clone(),
+                        nar_hash: locked.nar_hash.clone(),
+                        last_modified: locked.last_modified,
+                    });
+                }
+                queue.push_back((name, child));
+            }
+        }
+        inputs
+    }
+}
+
+impl From<MetadataJson> for LockedFlake {
+    fn from(json: MetadataJson) -> Self {
+        LockedFlake {
+            url: json.url,
+            original_url: json.original_url,
+            revision: json.revision,
+            dirty_revision: json.dirty_revision,
+            last_modified: json.last_modified,
+            nar_hash: json.locked.and_then(|l| l.nar_hash),
+            path: json.path,
+            description: json.description,
+            inputs: json.locks.map(|l| l.inputs()).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &str = r#"{
+        "description": "procurator",
+        "lastModified": 1718000000,
+        "locked": {
+            "lastModified": 1718000000,
+            "narHash": "sha256-root=",
+            "rev": "0123456789abcdef0123456789abcdef01234567",
+            "type": "git",
+            "url": "file:///src/procurator"
+        },
+        "locks": {
+            "nodes": {
+                "crane": {
+                    "inputs": {"nixpkgs": ["nixpkgs"]},
+                    "locked": {
+                        "lastModified": 1717000000,
+                        "narHash": "sha256-crane=",
+                        "owner": "ipetkov",
+                        "repo": "crane",
+                        "rev": "aaaa",
+                        "type": "github"
+                    },
+                    "original": {"owner": "ipetkov", "repo": "crane", "type": "github"}
+                },
+                "nixpkgs": {
+                    "locked": {
+                        "lastModified": 1716000000,
+                        "narHash": "sha256-nixpkgs=",
+                        "owner": "NixOS",
+                        "repo": "nixpkgs",
+                        "rev": "bbbb",
+                        "type": "github"
+                    },
+                    "original": {"owner": "NixOS", "ref": "nixos-unstable", "repo": "nixpkgs", "type": "github"}
+                },
+                "flake-utils": {
+                    "inputs": {"systems": "systems"},
+                    "locked": {
+                        "lastModified": 1715000000,
+                        "narHash": "sha256-utils=",
+                        "rev": "cccc",
+                        "type": "git",
+                        "url": "https://github.com/numtide/flake-utils"
+                    }
+                },
+                "systems": {
+                    "locked": {
+                        "narHash": "sha256-systems=",
+                        "type": "tarball",
+                        "url": "https://example.com/systems.tar.gz"
+                    }
+                },
+                "root": {
+                    "inputs": {"crane": "crane", "flake-utils": "flake-utils", "nixpkgs": "nixpkgs"}
+                }
+            },
+            "root": "root",
+            "version": 7
+        },
+        "originalUrl": "git+file:///src/procurator",
+        "path": "/nix/store/aaa-source",
+        "revision": "0123456789abcdef0123456789abcdef01234567",
+        "url": "git+file:///src/procurator?rev=0123456789abcdef0123456789abcdef01234567"
+    }"#;
+
+    fn input(name: &str, rev: &str) -> LockedInput {
+        LockedInput {
+            name: name.to_string(),
+            url: format!("github:owner/{name}/{rev}"),
+            rev: Some(rev.to_string()),
+            nar_hash: Some(format!("sha256-{rev}=")),
+            last_modified: None,
+        }
+    }
+
+    fn parse(json: &str) -> LockedFlake {
+        serde_json::from_str::<MetadataJson>(json).unwrap().into()
+    }
+
+    #[test]
+    fn parses_the_flake_itself() {
+        let flake = parse(METADATA);
+        assert_eq!(
+            flake.revision.as_deref(),
+            Some("0123456789abcdef0123456789abcdef01234567")
+        );
+        assert_eq!(flake.dirty_revision, None);
+        assert_eq!(flake.last_modified, Some(1_718_000_000));
+        assert_eq!(flake.nar_hash.as_deref(), Some("sha256-root="));
+        assert_eq!(flake.path.as_deref(), Some("/nix/store/aaa-source"));
+        assert_eq!(flake.original_url, "git+file:///src/procurator");
+    }
+
+    #[test]
+    fn lists_inputs_by_path_without_follows() {
+        let inputs = parse(METADATA).inputs;
+        let names: Vec<&str> = inputs.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["crane", "flake-utils", "nixpkgs", "flake-utils/systems"]
+        );
+
+        assert_eq!(
+            inputs[2],
+            LockedInput {
+                name: "nixpkgs".to_string(),
+                url: "github:NixOS/nixpkgs/bbbb".to_string(),
+                rev: Some("bbbb".to_string()),
+                nar_hash: Some("sha256-nixpkgs=".to_string()),
+                last_modified: Some(1_716_000_000),
+            }
+        );
+        assert_eq!(
+            inputs[1].url,
+            "git+https://github.com/numtide/flake-utils?rev=cccc"
+        );
+        assert_eq!(inputs[3].url, "https://example.com/systems.tar.gz");
+        assert_eq!(inputs[3].rev, None);
+    }
+
+    #[test]
+    fn dirty_trees_and_flakes_without_inputs() {
+        let flake = parse(
+            r#"{
+                "dirtyRevision": "0123-dirty",
+                "locks": {"nodes": {"root": {}}, "root": "root", "version": 7},
+                "originalUrl": "path:/src",
+                "url": "path:/src"
+            }"#,
+        );
+        assert_eq!(flake.revision, None);
+        assert_eq!(flake.dirty_revision.as_deref(), Some("0123-dirty"));
+        assert!(flake.inputs.is_empty());
+    }
+
+    #[test]
+    fn diffs_the_inputs_that_moved() {
+        let before = [
+            input("crane", "aaaa"),
+            input("nixpkgs", "bbbb"),
+            input("old", "cccc"),
+        ];
+        let after = [
+            input("crane", "aaaa"),
+            input("nixpkgs", "dddd"),
+            input("new", "eeee"),
+        ];
+
+        let changes = diff_locks(&before, &after);
+        let names: Vec<&str> = changes.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["new", "nixpkgs", "old"]);
+        assert_eq!(changes[0].before, None);
+        assert_eq!(
+            changes[1].before.as_ref().unwrap().rev.as_deref(),
+            Some("bbbb")
+        );
+        assert_eq!(
+            changes[1].after.as_ref().unwrap().rev.as_deref(),
+            Some("dddd")
+        );
+        assert_eq!(changes[2].after, None);
+
+        assert!(diff_locks(&after, &after).is_empty());
+    }
+}
@@ -1,6 +1,5 @@
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
-use std::fmt;
 use std::num::NonZeroU64;
 use std::ops::Not;
 use std::time::{Duration, SystemTime};
@@ -8,57 +7,37 @@ use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
 use tracing::{info, warn};
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
-    IoError(std::io::Error),
+    #[error("IO error")]
+    IoError(#[from] std::io::Error),
+    #[error("Nix process failed with exit code {exit_code:?}: {stderr}")]
     ProcessFailed {
         exit_code: Option<i32>,
         stderr: String,
     },
-    JsonParseError(serde_json::Error),
+    #[error("JSON parse error")]
+    JsonParseError(#[from] serde_json::Error),
+    #[error("Invalid flake path: {0}")]
     InvalidFlakePath(String),
+    #[error("Process timed out after {0:?}")]
     Timeout(Duration),
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Error {
+    /// Stable identifier for this failure, independent of the message.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
         match self {
-            Error::IoError(err) => write!(f, "IO error: {}", err),
-            Error::ProcessFailed { exit_code, stderr } => {
-                write!(
-                    f,
-                    "Nix process failed with exit code {:?}: {}",
-                    exit_code, stderr
-                )
-            }
-            Error::JsonParseError(err) => write!(f, "JSON parse error: {}", err),
-            Error::InvalidFlakePath(path) => write!(f, "Invalid flake path: {}", path),
-            Error::Timeout(duration) => write!(f, "Process timed out after {:?}", duration),
+            Error::IoError(_) => "nix.logs.io",
+            Error::ProcessFailed { .. } => "nix.logs.process_failed",
+            Error::JsonParseError(_) => "nix.logs.json",
+            Error::InvalidFlakePath(_) => "nix.logs.invalid_flake_path",
+            Error::Timeout(_) => "nix.logs.timeout",
         }
     }
 }
 
-impl std::error::Error for Error {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            Error::IoError(err) => Some(err),
-            Error::JsonParseError(err) => Some(err),
-            _ => None,
-        }
-    }
-}
-
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error::IoError(err)
-    }
-}
-
-impl From<serde_json::Error> for Error {
-    fn from(err: serde_json::Error) -> Self {
-        Error::JsonParseError(err)
-    }
-}
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Hash, Copy, Clone, PartialOrd, Ord)]
 struct EntryId(NonZeroU64);
 
@@ -76,6 +55,26 @@ pub struct StartEntry {
     text: String,
     #[serde(rename = "type")]
     log_type: u64,
+    /// Activity details, e.g. the path, source and destination of a copy
+    #[serde(default)]
+    fields: Vec<serde_json::Value>,
+}
+
+impl StartEntry {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    #[must_use]
+    pub fn log_type(&self) -> u64 {
+        self.log_type
+    }
+
+    /// The `index`th field when it is a string.
+    #[must_use]
+    pub fn field(&self, index: usize) -> Option<&str> {
+        self.fields.get(index).and_then(serde_json::Value::as_str)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -93,6 +92,18 @@ pub struct MsgEntry {
     line: Option<u32>,
 }
 
+impl MsgEntry {
+    #[must_use]
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    #[must_use]
+    pub fn text(&self) -> &str {
+        &self.msg
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResultEntry {
     id: u64,
@@ -111,7 +122,7 @@ pub enum LogEntry {
 }
 
 /// Trait for handling Nix log parsing with different strategies
-pub trait Parser: Default {
+pub trait Parser {
     /// The output type produced by this handler
     type Output: Serialize;
 
@@ -229,9 +240,13 @@ impl Summary {
         self.total_steps
     }
 
-    pub fn duration(&self) -> Duration {
+    pub fn started_at(&self) -> SystemTime {
         self.started_at
-            .duration_since(self.completed_at)
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.completed_at
+            .duration_since(self.started_at)
             .unwrap_or_default()
     }
     pub fn timeline(&self) -> &[TimelineStep] {
@@ -243,6 +258,7 @@ impl Summary {
 #[derive(Serialize, Debug, Clone)]
 pub struct TimelineStep {
     text: String,
+    started_at: SystemTime,
     duration: Duration,
     children: Vec<TimelineStep>,
 }
@@ -252,6 +268,10 @@ impl TimelineStep {
         &self.text
     }
 
+    pub fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+
     pub fn duration(&self) -> Duration {
         self.duration
     }
@@ -305,6 +325,7 @@ fn build_step_tree(
 
     TimelineStep {
         text: step.text.clone(),
+        started_at: step.started_at,
         duration,
         children,
     }
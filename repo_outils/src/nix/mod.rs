@@ -1,8 +1,49 @@
+mod build;
+mod closure;
+mod cluster;
+mod copy;
+mod derivation;
+mod diagnostics;
 mod flake;
+mod image;
+mod lock;
 mod logs;
+mod path_info;
+mod retry;
+mod run;
 mod commands;
+mod scaffold;
+mod sign;
+mod store;
 
-pub use flake::{FlakeMetadata, Infrastructure};
+pub use build::{
+	build_cluster_images, BuildPool, BuildProgress, BuildSlot, BuiltImage, ImageBuild,
+	ImageProgress, ImageStatus, ProgressReport,
+};
+pub use closure::{diff_closures, ChangeKind, ClosureDiff, PackageDiff};
+pub use cluster::{
+	ClusterMetadata, Invalid, MetadataError, Network, Resources, SCHEMA_VERSION, VmMetadata,
+	Volume, VolumeMount,
+};
+pub use copy::{copy, CopyArgs, CopyResult};
+pub use derivation::{dependency_graph, derivation_show, DependencyGraph, Derivation};
+pub use diagnostics::{DiagnosticKind, Diagnostics};
+pub use scaffold::{
+	scaffold_infrastructure, ScaffoldError, ScaffoldOptions, ScaffoldVm, CLUSTER_METADATA_ATTR,
+};
+pub use sign::{store_sign, store_verify, Verification};
+pub use flake::{FlakeMetadata, Infrastructure, NixParserError};
+pub use image::{DiskImage, ImageBuilder, ImageFormat};
+pub use lock::{
+	diff_locks, flake_lock, flake_lock_update, flake_metadata, flake_update, LockChange,
+	LockedFlake, LockedInput,
+};
+pub use logs::{Summary, TimelineStep};
+pub use path_info::{closure_info, path_info, PathInfo};
+pub use retry::RetryPolicy;
+pub use run::{develop_command, run, RunOutput};
 pub use commands::{
-	flake_check, build_cluster_images, eval_cluster_metadata, Error, VmMetadata,
+	build_checks, build_checks_in, flake_check, flake_check_in, flake_checks, flake_checks_in,
+	flake_eval, flake_eval_in, eval_cluster_metadata, Error, FlakeChecks,
 };
+pub use store::IsolatedStore;
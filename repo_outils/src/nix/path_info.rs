@@ -0,0 +1,225 @@
+//! Store path metadata from `nix path-info --json`
+//!
+//! The JSON changed shape across nix releases: a list of objects carrying
+//! their `path` before 2.19, an object keyed by path since. Both parse into
+//! the same [`PathInfo`].
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ops::Not;
+use tokio::process::Command;
+
+use super::commands::Error;
+
+/// One store path, as reported by `nix path-info`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathInfo {
+    pub path: String,
+    /// Hash of the path's NAR, e.g. `sha256:1b8m…` or `sha256-qvT…` in
+    /// recent nix
+    pub nar_hash: String,
+    pub nar_size: u64,
+    /// Store paths this one refers to, itself included when it does
+    pub references: Vec<String>,
+    /// Derivation that built the path, if known
+    pub deriver: Option<String>,
+    pub signatures: Vec<String>,
+}
+
+/// Metadata of `paths`, in the order nix reports them
+///
+/// # Errors
+///
+/// `nix path-info` failing (e.g. a path is not in the store) or its output
+/// not parsing.
+pub async fn path_info(paths: &[&str]) -> Result<Vec<PathInfo>, Error> {
+    query(
+        Command::new("nix")
+            .arg("path-info")
+            .arg("--json")
+            .args(paths),
+    )
+    .await
+}
+
+/// Closure of `store_path`, one entry per path
+///
+/// # Errors
+///
+/// Same as [`path_info`].
+pub async fn closure_info(store_path: &str) -> Result<Vec<PathInfo>, Error> {
+    query(
+        Command::new("nix")
+            .arg("path-info")
+            .arg("--recursive")
+            .arg("--json")
+            .arg(store_path),
+    )
+    .await
+}
+
+async fn query(command: &mut Command) -> Result<Vec<PathInfo>, Error> {
+    let output = command.output().await?;
+    if output.status.success().not() {
+        return Err(Error::process_failed(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+
+    let json: PathInfoJson = serde_json::from_slice(&output.stdout)?;
+    Ok(json.into())
+}
+
+/// `nix path-info --json` output. Paths missing from the store come as
+/// `{"path": …, "valid": false}` in the list and as `null` in the map.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PathInfoJson {
+    List(Vec<ListEntry>),
+    Map(BTreeMap<String, Option<Entry>>),
+}
+
+#[derive(Deserialize)]
+struct ListEntry {
+    path: String,
+    #[serde(default = "valid")]
+    valid: bool,
+    #[serde(flatten)]
+    entry: Option<Entry>,
+}
+
+fn valid() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    #[serde(default)]
+    nar_hash: String,
+    nar_size: u64,
+    #[serde(default)]
+    references: Vec<String>,
+    #[serde(default)]
+    deriver: Option<String>,
+    #[serde(default)]
+    signatures: Vec<String>,
+}
+
+impl Entry {
+    fn into_info(self, path: String) -> PathInfo {
+        PathInfo {
+            path,
+            nar_hash: self.nar_hash,
+            nar_size: self.nar_size,
+            references: self.references,
+            deriver: self.deriver,
+            signatures: self.signatures,
+        }
+    }
+}
+
+impl From<PathInfoJson> for Vec<PathInfo> {
+    fn from(json: PathInfoJson) -> Self {
+        match json {
+            PathInfoJson::List(entries) => entries
+                .into_iter()
+                .filter(|e| e.valid)
+                .filter_map(|e| Some(e.entry?.into_info(e.path)))
+                .collect(),
+            PathInfoJson::Map(entries) => entries
+                .into_iter()
+                .filter_map(|(path, e)| Some(e?.into_info(path)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Vec<PathInfo> {
+        serde_json::from_str::<PathInfoJson>(json).unwrap().into()
+    }
+
+    #[test]
+    fn parses_every_field() {
+        let expected = PathInfo {
+            path: "/nix/store/aaa-hello-2.12".to_string(),
+            nar_hash: "sha256-qvT2r8hJd0UGE4W2QYg4b4n3mBZ3aA7fhVwPjC8Gd3E=".to_string(),
+            nar_size: 226_560,
+            references: vec![
+                "/nix/store/aaa-hello-2.12".to_string(),
+                "/nix/store/bbb-glibc-2.39".to_string(),
+            ],
+            deriver: Some("/nix/store/ccc-hello-2.12.drv".to_string()),
+            signatures: vec!["cache.nixos.org-1:abc==".to_string()],
+        };
+
+        let list = parse(
+            r#"[{
+                "path": "/nix/store/aaa-hello-2.12",
+                "narHash": "sha256-qvT2r8hJd0UGE4W2QYg4b4n3mBZ3aA7fhVwPjC8Gd3E=",
+                "narSize": 226560,
+                "references": ["/nix/store/aaa-hello-2.12", "/nix/store/bbb-glibc-2.39"],
+                "deriver": "/nix/store/ccc-hello-2.12.drv",
+                "signatures": ["cache.nixos.org-1:abc=="],
+                "valid": true
+            }]"#,
+        );
+        let map = parse(
+            r#"{"/nix/store/aaa-hello-2.12": {
+                "narHash": "sha256-qvT2r8hJd0UGE4W2QYg4b4n3mBZ3aA7fhVwPjC8Gd3E=",
+                "narSize": 226560,
+                "references": ["/nix/store/aaa-hello-2.12", "/nix/store/bbb-glibc-2.39"],
+                "deriver": "/nix/store/ccc-hello-2.12.drv",
+                "signatures": ["cache.nixos.org-1:abc=="],
+                "ultimate": false
+            }}"#,
+        );
+
+        assert_eq!(list, std::slice::from_ref(&expected));
+        assert_eq!(map, [expected]);
+    }
+
+    #[test]
+    fn parses_both_path_info_formats() {
+        let list = r#"[{"path":"/nix/store/aaa-jq-1.7","narSize":300}]"#;
+        let map = r#"{"/nix/store/aaa-jq-1.7":{"narSize":300,"references":[]}}"#;
+        let expected = PathInfo {
+            path: "/nix/store/aaa-jq-1.7".to_string(),
+            nar_size: 300,
+            ..PathInfo::default()
+        };
+        for json in [list, map] {
+            assert_eq!(parse(json), std::slice::from_ref(&expected));
+        }
+    }
+
+    #[test]
+    fn skips_invalid_paths_and_missing_fields() {
+        let list = parse(
+            r#"[
+                {"path": "/nix/store/aaa-gone", "valid": false},
+                {"path": "/nix/store/bbb-etc", "narHash": "sha256:0abc", "narSize": 100}
+            ]"#,
+        );
+        let map = parse(
+            r#"{
+                "/nix/store/aaa-gone": null,
+                "/nix/store/bbb-etc": {"narHash": "sha256:0abc", "narSize": 100, "deriver": null}
+            }"#,
+        );
+
+        let expected = PathInfo {
+            path: "/nix/store/bbb-etc".to_string(),
+            nar_hash: "sha256:0abc".to_string(),
+            nar_size: 100,
+            ..PathInfo::default()
+        };
+        assert_eq!(list, std::slice::from_ref(&expected));
+        assert_eq!(map, [expected]);
+    }
+}
@@ -0,0 +1,260 @@
+//! Retrying transient Nix failures
+//!
+//! Some failures of a Nix command say nothing about what it was asked to
+//! do: a substituter or cache that can't be reached or answers 5xx, or the
+//! store database locked by another Nix process. A [`RetryPolicy`] runs the
+//! command again after an exponential backoff with jitter, and gives up
+//! right away on anything else, a failed build or evaluation being the same
+//! on every attempt.
+
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tracing::warn;
+
+use super::commands::Error;
+
+/// Lines of Nix's stderr that only mean a build of a derivation failed,
+/// whatever else went wrong around it
+const DETERMINISTIC: [&str; 3] = ["builder for '", "build of '", "error: evaluation aborted"];
+
+/// Lines of Nix's stderr for a failure that may not happen again
+const TRANSIENT: [&str; 12] = [
+    "unable to download",
+    "Couldn't resolve host",
+    "Could not resolve host",
+    "Connection refused",
+    "Connection reset",
+    "Connection timed out",
+    "Timeout was reached",
+    "HTTP error 502",
+    "HTTP error 503",
+    "HTTP error 504",
+    "database is locked",
+    "' is busy",
+];
+
+impl Error {
+    /// Whether running the command again may succeed: a network error
+    /// talking to a substituter or cache, or store lock contention. A failed
+    /// build or evaluation never is.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::ProcessFailed { stderr, .. } => {
+                DETERMINISTIC.iter().all(|line| !stderr.contains(line))
+                    && TRANSIENT.iter().any(|line| stderr.contains(line))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// How many times, and how far apart, to run a Nix command whose failures
+/// are [transient](Error::is_transient)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    /// 4 attempts, 1s then 2s then 4s apart, with jitter.
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run commands once, never retrying.
+    #[must_use]
+    pub fn none() -> Self {
+        Self {
+            attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Run a command at most `attempts` times, at least once.
+    #[must_use]
+    pub fn with_attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Wait `delay` before the first retry, doubling it before each next one.
+    #[must_use]
+    pub fn with_base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// Never wait longer than `delay` between attempts.
+    #[must_use]
+    pub fn with_max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Wait exactly the backoff instead of a random time between half of it
+    /// and all of it; retries of many workers then hit a cache together.
+    #[must_use]
+    pub fn without_jitter(mut self) -> Self {
+        self.jitter = false;
+        self
+    }
+
+    #[must_use]
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Time to wait after the failed attempt number `attempt`, from 1.
+    #[must_use]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        if !self.jitter {
+            return backoff;
+        }
+        let half = backoff / 2;
+        let spread = u64::try_from(backoff.saturating_sub(half).as_nanos()).unwrap_or(u64::MAX);
+        half + Duration::from_nanos(random() % spread.saturating_add(1))
+    }
+
+    /// Run `command` until it succeeds, fails with an error that is not
+    /// transient, or runs out of attempts.
+    ///
+    /// # Errors
+    ///
+    /// - the error of the last attempt
+    pub async fn run<T, F, Fut>(&self, mut command: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match command().await {
+                Err(e) if e.is_transient() && attempt < self.attempts => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        attempt,
+                        attempts = self.attempts,
+                        ?delay,
+                        error = crate::report(&e),
+                        "Transient Nix failure, retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Random enough for jitter, without a dependency.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(stderr: &str) -> Error {
+        Error::process_failed(Some(1), stderr.to_string())
+    }
+
+    #[test]
+    fn only_network_and_lock_failures_are_transient() {
+        assert!(
+            failed("error: unable to download 'https://cache.nixos.org/x.narinfo': HTTP error 503")
+                .is_transient()
+        );
+        assert!(
+            failed("error: SQLite database '/nix/var/nix/db/db.sqlite' is busy").is_transient()
+        );
+        assert!(
+            !failed("error: builder for '/nix/store/x.drv' failed with exit code 1").is_transient()
+        );
+        assert!(
+            !failed("warning: unable to download 'https://cache/x': HTTP error 503\nerror: build of '/nix/store/x.drv' failed")
+                .is_transient()
+        );
+        assert!(!failed("error: undefined variable 'pkgs'").is_transient());
+        assert!(!Error::BuildOutputMissing.is_transient());
+    }
+
+    #[test]
+    fn delays_double_up_to_the_max() {
+        let policy = RetryPolicy::default()
+            .with_max_delay(Duration::from_secs(5))
+            .without_jitter();
+        let delays: Vec<_> = (1..=5)
+            .map(|attempt| policy.delay(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 5, 5]);
+
+        let jittered = RetryPolicy::default();
+        for _ in 0..100 {
+            let delay = jittered.delay(3);
+            assert!(
+                delay >= Duration::from_secs(2) && delay <= Duration::from_secs(4),
+                "{delay:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures_only() {
+        let policy = RetryPolicy::default()
+            .with_attempts(3)
+            .with_base_delay(Duration::ZERO);
+
+        let mut calls = 0;
+        let result = policy
+            .run(|| {
+                calls += 1;
+                let result = if calls < 3 {
+                    Err(failed("error: database is locked"))
+                } else {
+                    Ok(calls)
+                };
+                async move { result }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: Result<(), _> = policy
+            .run(|| {
+                calls += 1;
+                async { Err(failed("error: builder for '/nix/store/x.drv' failed")) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let result: Result<(), _> = policy
+            .run(|| {
+                calls += 1;
+                async { Err(failed("error: Connection reset by peer")) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+}
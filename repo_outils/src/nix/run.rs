@@ -0,0 +1,165 @@
+//! Running programs a flake defines
+//!
+//! [`run`] starts one of a flake's apps (`nix run`) and [`develop_command`]
+//! runs a command inside its dev shell (`nix develop --command`), so check
+//! scripts and tools come from the repository's flake rather than from the
+//! host. Both wait for the program and capture what it printed and how it
+//! exited: a program failing is a [`RunOutput`] like any other, and only
+//! Nix not starting at all is an error. When Nix itself fails, e.g. to
+//! evaluate the flake, [`RunOutput::diagnostics`] tells why.
+
+use serde::Serialize;
+use std::ffi::OsString;
+use tokio::process::Command;
+use tracing::debug;
+
+use super::commands::Error;
+use super::diagnostics::Diagnostics;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// What a program run by [`run`] or [`develop_command`] printed, and its
+/// exit code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RunOutput {
+    /// `None` when the program was killed by a signal
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl RunOutput {
+    #[must_use]
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    /// Why the run failed, read from `stderr`; `None` when it succeeded.
+    /// Only Nix's own errors are recognised, not the program's.
+    #[must_use]
+    pub fn diagnostics(&self) -> Option<Diagnostics> {
+        (!self.success()).then(|| Diagnostics::parse(&self.stderr))
+    }
+}
+
+/// Run app `app` of `flake_ref` with `args`, or its default app when `app`
+/// is empty: `nix run <flake_ref>#<app> -- <args>`.
+///
+/// # Errors
+///
+/// - if `flake_ref` is empty or `nix` cannot be started
+pub async fn run(flake_ref: &str, app: &str, args: &[&str]) -> Result<RunOutput> {
+    run_nix(run_args(flake_ref, app, args)?).await
+}
+
+/// Run `cmd`, a program and its arguments, in the dev shell of
+/// `flake_ref`: `nix develop <flake_ref> --command <cmd>`.
+///
+/// # Errors
+///
+/// - if `flake_ref` or `cmd` is empty, or `nix` cannot be started
+pub async fn develop_command(flake_ref: &str, cmd: &[&str]) -> Result<RunOutput> {
+    run_nix(develop_args(flake_ref, cmd)?).await
+}
+
+fn run_args(flake_ref: &str, app: &str, args: &[&str]) -> Result<Vec<OsString>> {
+    check_flake_ref(flake_ref)?;
+    let installable = if app.is_empty() {
+        flake_ref.to_string()
+    } else {
+        format!("{flake_ref}#{app}")
+    };
+    let mut nix_args: Vec<OsString> = vec!["run".into(), installable.into(), "--".into()];
+    nix_args.extend(args.iter().map(OsString::from));
+    Ok(nix_args)
+}
+
+fn develop_args(flake_ref: &str, cmd: &[&str]) -> Result<Vec<OsString>> {
+    check_flake_ref(flake_ref)?;
+    if cmd.is_empty() {
+        return Err(Error::InvalidFlakePath(format!(
+            "no command to run in the dev shell of {flake_ref}"
+        )));
+    }
+    let mut nix_args: Vec<OsString> = vec!["develop".into(), flake_ref.into(), "--command".into()];
+    nix_args.extend(cmd.iter().map(OsString::from));
+    Ok(nix_args)
+}
+
+fn check_flake_ref(flake_ref: &str) -> Result<()> {
+    if flake_ref.is_empty() {
+        return Err(Error::InvalidFlakePath(
+            "Flake reference cannot be empty".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+async fn run_nix(nix_args: Vec<OsString>) -> Result<RunOutput> {
+    debug!(?nix_args, "Running nix");
+    let output = Command::new("nix")
+        .args(nix_args)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await?;
+    Ok(RunOutput {
+        exit_code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(nix_args: Vec<OsString>) -> Vec<String> {
+        nix_args
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn apps_and_dev_shell_commands_are_passed_through() {
+        assert_eq!(
+            strings(run_args(".", "lint", &["--fix", "src"]).unwrap()),
+            ["run", ".#lint", "--", "--fix", "src"]
+        );
+        assert_eq!(
+            strings(run_args("github:org/app", "", &[]).unwrap()),
+            ["run", "github:org/app", "--"]
+        );
+        assert_eq!(
+            strings(develop_args(".", &["cargo", "test", "--workspace"]).unwrap()),
+            ["develop", ".", "--command", "cargo", "test", "--workspace"]
+        );
+
+        assert!(matches!(
+            run_args("", "lint", &[]),
+            Err(Error::InvalidFlakePath(_))
+        ));
+        assert!(matches!(
+            develop_args(".", &[]),
+            Err(Error::InvalidFlakePath(_))
+        ));
+    }
+
+    #[test]
+    fn only_failed_runs_have_diagnostics() {
+        let mut output = RunOutput {
+            exit_code: Some(0),
+            stdout: "ok\n".to_string(),
+            stderr: String::new(),
+        };
+        assert!(output.success());
+        assert!(output.diagnostics().is_none());
+
+        output.exit_code = None;
+        output.stderr =
+            "error: flake 'path:/src' does not provide attribute 'apps.x86_64-linux.lint'\n"
+                .to_string();
+        assert!(!output.success());
+        assert!(output.diagnostics().is_some());
+    }
+}
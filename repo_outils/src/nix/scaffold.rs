@@ -0,0 +1,285 @@
+//! Starter `flake.nix` for cluster-enabled repositories
+//!
+//! [`scaffold_infrastructure`] writes a flake that builds one image per VM
+//! with the procurator Nix library (`mkVmProfile` + `mkVmImage`) and exposes
+//! them as the `clusterMetadata` attribute in the shape
+//! [`eval_cluster_metadata`] reads (see [`super::cluster`]). Each image is
+//! also a package, so `nix build .#<vm>` works.
+//!
+//! [`eval_cluster_metadata`]: super::eval_cluster_metadata
+
+use std::fmt::Write as _;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::cluster::{SCHEMA_VERSION, check_name};
+
+/// Attribute the scaffolded flake exposes the cluster metadata under
+pub const CLUSTER_METADATA_ATTR: &str = "clusterMetadata";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScaffoldError {
+    #[error("{0} already exists")]
+    AlreadyExists(PathBuf),
+    #[error("Invalid VM {name:?}: {reason}")]
+    InvalidVm { name: String, reason: String },
+    #[error("IO error")]
+    Io(#[from] std::io::Error),
+}
+
+impl ScaffoldError {
+    /// Stable identifier for this failure, independent of the message.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            ScaffoldError::AlreadyExists(_) => "nix.scaffold.already_exists",
+            ScaffoldError::InvalidVm { .. } => "nix.scaffold.invalid_vm",
+            ScaffoldError::Io(_) => "nix.scaffold.io",
+        }
+    }
+}
+
+/// A VM of the starter cluster
+#[derive(Debug, Clone)]
+pub struct ScaffoldVm {
+    pub name: String,
+    pub cpu: u32,
+    pub memory_mb: u32,
+    pub replicas: u32,
+}
+
+impl ScaffoldVm {
+    /// One replica with 1 CPU and 512 MiB, the `mkVmProfile` defaults.
+    #[must_use]
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            cpu: 1,
+            memory_mb: 512,
+            replicas: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScaffoldOptions {
+    pub description: String,
+    pub system: String,
+    pub nixpkgs_url: String,
+    pub procurator_url: String,
+    pub vms: Vec<ScaffoldVm>,
+    /// Replace an existing `flake.nix` instead of failing
+    pub overwrite: bool,
+}
+
+impl Default for ScaffoldOptions {
+    fn default() -> Self {
+        Self {
+            description: "Cluster managed by procurator".to_string(),
+            system: "x86_64-linux".to_string(),
+            nixpkgs_url: "github:NixOS/nixpkgs/nixos-25.11".to_string(),
+            procurator_url: "github:lucas-montes/procurator?dir=nix".to_string(),
+            vms: vec![ScaffoldVm::new("app")],
+            overwrite: false,
+        }
+    }
+}
+
+/// Write a starter `flake.nix` into the directory `path`, creating it if
+/// needed. Returns the path of the written file.
+///
+/// # Errors
+///
+/// [`ScaffoldError::AlreadyExists`] if there is a `flake.nix` and
+/// `options.overwrite` is off, [`ScaffoldError::InvalidVm`] for a VM the
+/// metadata schema would reject, or the write failing.
+pub fn scaffold_infrastructure(
+    path: impl AsRef<Path>,
+    options: &ScaffoldOptions,
+) -> Result<PathBuf, ScaffoldError> {
+    for vm in &options.vms {
+        let invalid = |reason: String| ScaffoldError::InvalidVm {
+            name: vm.name.clone(),
+            reason,
+        };
+        check_name(&vm.name).map_err(invalid)?;
+        if vm.cpu == 0 || vm.memory_mb == 0 || vm.replicas == 0 {
+            return Err(invalid(
+                "cpu, memoryMb and replicas must be at least 1".to_string(),
+            ));
+        }
+    }
+
+    let dir = path.as_ref();
+    std::fs::create_dir_all(dir)?;
+    let flake = dir.join("flake.nix");
+
+    let contents = render(options);
+    if options.overwrite {
+        std::fs::write(&flake, contents)?;
+    } else {
+        // create_new so a flake appearing between check and write is not clobbered
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&flake)
+            .map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => ScaffoldError::AlreadyExists(flake.clone()),
+                _ => ScaffoldError::Io(e),
+            })?;
+        std::io::Write::write_all(&mut file, contents.as_bytes())?;
+    }
+    Ok(flake)
+}
+
+const TEMPLATE: &str = r"{
+  description = @description@;
+
+  inputs = {
+    nixpkgs.url = @nixpkgs@;
+    procurator.url = @procurator@;
+  };
+
+  outputs = { self, nixpkgs, procurator, ... }:
+    let
+      system = @system@;
+      pLib = procurator.libs.${system};
+
+      # One image per VM; edit the profiles to add packages, files,
+      # entrypoints or allowed domains (see mkVmProfile).
+      mkVm = name: { cpu, memoryMb, replicas ? 1 }:
+        let
+          vm = pLib.mkVmImage {
+            profile = pLib.mkVmProfile {
+              hostname = name;
+              inherit cpu memoryMb;
+            };
+          };
+        in {
+          inherit (vm) image;
+          metadata = {
+            drvPath = vm.image.drvPath;
+            outPath = vm.image.outPath;
+            # Store path hash: changes whenever the image does
+            contentHash = builtins.substring 11 32 vm.image.outPath;
+            resources = { inherit cpu memoryMb; };
+            inherit replicas;
+          };
+        };
+
+      vms = builtins.mapAttrs mkVm {
+@vms@      };
+    in {
+      packages.${system} = builtins.mapAttrs (_: vm: vm.image) vms;
+
+      # Read by procurator (eval_cluster_metadata), schema version @version@
+      @attr@ = {
+        version = @version@;
+        vms = builtins.mapAttrs (_: vm: vm.metadata) vms;
+        networks = { };
+        volumes = { };
+      };
+    };
+}
+";
+
+fn render(options: &ScaffoldOptions) -> String {
+    let mut vms = String::new();
+    for vm in &options.vms {
+        let _ = writeln!(
+            vms,
+            "        {} = {{ cpu = {}; memoryMb = {}; replicas = {}; }};",
+            vm.name, vm.cpu, vm.memory_mb, vm.replicas
+        );
+    }
+
+    TEMPLATE
+        .replace("@description@", &nix_string(&options.description))
+        .replace("@nixpkgs@", &nix_string(&options.nixpkgs_url))
+        .replace("@procurator@", &nix_string(&options.procurator_url))
+        .replace("@system@", &nix_string(&options.system))
+        .replace("@attr@", CLUSTER_METADATA_ATTR)
+        .replace("@version@", &SCHEMA_VERSION.to_string())
+        .replace("@vms@", &vms)
+}
+
+/// Quote `s` as a Nix string literal.
+fn nix_string(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${");
+    format!("\"{escaped}\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "repo-outils-scaffold-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn writes_every_vm_and_refuses_to_overwrite() {
+        let dir = scratch("write");
+        let options = ScaffoldOptions {
+            description: "Shop \"prod\" ${cluster}".to_string(),
+            vms: vec![
+                ScaffoldVm {
+                    replicas: 2,
+                    ..ScaffoldVm::new("web")
+                },
+                ScaffoldVm {
+                    cpu: 2,
+                    memory_mb: 2048,
+                    ..ScaffoldVm::new("db")
+                },
+            ],
+            ..ScaffoldOptions::default()
+        };
+
+        let flake = scaffold_infrastructure(&dir, &options).unwrap();
+        let contents = std::fs::read_to_string(&flake).unwrap();
+        assert!(contents.contains(r#"description = "Shop \"prod\" \${cluster}";"#));
+        assert!(contents.contains("web = { cpu = 1; memoryMb = 512; replicas = 2; };"));
+        assert!(contents.contains("db = { cpu = 2; memoryMb = 2048; replicas = 1; };"));
+        assert!(contents.contains("clusterMetadata = {\n        version = 1;"));
+        assert!(!contents.contains('@'));
+
+        let err = scaffold_infrastructure(&dir, &options).unwrap_err();
+        assert_eq!(err.code(), "nix.scaffold.already_exists");
+        let overwrite = ScaffoldOptions {
+            overwrite: true,
+            ..options
+        };
+        assert!(scaffold_infrastructure(&dir, &overwrite).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_vms_the_schema_would_reject() {
+        let dir = scratch("invalid");
+        for vm in [
+            ScaffoldVm::new("Web_1"),
+            ScaffoldVm {
+                memory_mb: 0,
+                ..ScaffoldVm::new("web")
+            },
+        ] {
+            let options = ScaffoldOptions {
+                vms: vec![vm],
+                ..ScaffoldOptions::default()
+            };
+            let err = scaffold_infrastructure(&dir, &options).unwrap_err();
+            assert_eq!(err.code(), "nix.scaffold.invalid_vm");
+        }
+        assert!(!dir.exists());
+    }
+}
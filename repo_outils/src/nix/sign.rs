@@ -0,0 +1,219 @@
+//! Signing store paths and verifying their signatures
+//!
+//! [`store_sign`] runs `nix store sign` so the CI worker can sign what it
+//! built before pushing it to the binary cache, and [`store_verify`] runs
+//! `nix store verify` so a worker only boots images signed by a key it
+//! trusts. Both act on whole closures: signing a package without its
+//! dependencies leaves the cache serving unsigned paths.
+//!
+//! Verification skips the contents (`--no-contents`) and needs one trusted
+//! signature on every path, including paths built on the host, which Nix
+//! would otherwise trust implicitly.
+
+use std::ffi::OsStr;
+use std::ops::Not;
+use std::path::Path;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+use super::commands::Error;
+
+type Result<T> = std::result::Result<T, Error>;
+
+const STORE_DIR: &str = "/nix/store/";
+
+/// Outcome of [`store_verify`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Verification {
+    untrusted: Vec<String>,
+}
+
+impl Verification {
+    /// Whether every path carries a signature from a trusted key
+    #[must_use]
+    pub fn is_trusted(&self) -> bool {
+        self.untrusted.is_empty()
+    }
+
+    /// Paths unsigned or signed by no trusted key, as Nix reported them
+    #[must_use]
+    pub fn untrusted(&self) -> &[String] {
+        &self.untrusted
+    }
+}
+
+/// Sign the closures of `paths` in the host store with the secret key in
+/// `key_file`, as made by `nix key generate-secret`.
+///
+/// # Errors
+///
+/// - [`Error::InvalidStorePath`] if a path is not a store path
+/// - if `nix store sign` cannot be run or fails, e.g. when a path is not
+///   valid or the key cannot be read
+pub async fn store_sign(paths: &[String], key_file: &Path) -> Result<()> {
+    check_store_paths(paths)?;
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let output = Command::new("nix")
+        .args(sign_args(key_file))
+        .args(paths)
+        .output()
+        .await?;
+    if output.status.success().not() {
+        return Err(Error::process_failed(
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    info!(
+        paths = paths.len(),
+        key_file = %key_file.display(),
+        "Signed store paths"
+    );
+    Ok(())
+}
+
+/// Check that every path in the closures of `paths` carries a signature
+/// from one of `trusted_keys` (`name:base64` entries, as in `nix.conf`).
+/// Untrusted paths are not an error: the [`Verification`] lists them.
+///
+/// # Errors
+///
+/// - [`Error::InvalidStorePath`] if a path is not a store path
+/// - if `nix store verify` cannot be run or fails for another reason than
+///   untrusted paths, e.g. when a path is not valid
+pub async fn store_verify(paths: &[String], trusted_keys: &[String]) -> Result<Verification> {
+    check_store_paths(paths)?;
+    if paths.is_empty() {
+        return Ok(Verification::default());
+    }
+
+    debug!(paths = paths.len(), "Verifying store path signatures");
+    let output = Command::new("nix")
+        .args(verify_args(trusted_keys))
+        .args(paths)
+        .output()
+        .await?;
+    if output.status.success() {
+        return Ok(Verification::default());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let untrusted = untrusted_paths(&stderr);
+    if untrusted.is_empty() {
+        return Err(Error::process_failed(
+            output.status.code(),
+            stderr.to_string(),
+        ));
+    }
+    warn!(
+        count = untrusted.len(),
+        first = untrusted[0].as_str(),
+        "Store paths lack a trusted signature"
+    );
+    Ok(Verification { untrusted })
+}
+
+fn check_store_paths(paths: &[String]) -> Result<()> {
+    match paths.iter().find(|p| p.starts_with(STORE_DIR).not()) {
+        Some(path) => Err(Error::InvalidStorePath(path.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Arguments of `nix store sign`, without the paths.
+fn sign_args(key_file: &Path) -> [&OsStr; 5] {
+    [
+        OsStr::new("store"),
+        OsStr::new("sign"),
+        OsStr::new("--recursive"),
+        OsStr::new("--key-file"),
+        key_file.as_os_str(),
+    ]
+}
+
+/// Arguments of `nix store verify`, without the paths.
+fn verify_args(trusted_keys: &[String]) -> Vec<String> {
+    [
+        "store",
+        "verify",
+        "--recursive",
+        "--no-contents",
+        "--sigs-needed",
+        "1",
+        "--option",
+        "trusted-public-keys",
+    ]
+    .into_iter()
+    .map(ToString::to_string)
+    .chain([trusted_keys.join(" ")])
+    .collect()
+}
+
+/// Paths of the `path '<p>' is untrusted` lines of `stderr`.
+fn untrusted_paths(stderr: &str) -> Vec<String> {
+    stderr
+        .lines()
+        .filter_map(|line| {
+            let rest = &line[line.find("path '")? + "path '".len()..];
+            rest.strip_suffix("' is untrusted").map(ToString::to_string)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_sign_and_verify_arguments() {
+        assert_eq!(
+            sign_args(Path::new("/etc/nix/ci.sec")),
+            [
+                "store",
+                "sign",
+                "--recursive",
+                "--key-file",
+                "/etc/nix/ci.sec"
+            ]
+        );
+        let keys = ["cache:AAAA".to_string(), "ci:BBBB".to_string()];
+        assert_eq!(
+            verify_args(&keys),
+            [
+                "store",
+                "verify",
+                "--recursive",
+                "--no-contents",
+                "--sigs-needed",
+                "1",
+                "--option",
+                "trusted-public-keys",
+                "cache:AAAA ci:BBBB",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_the_untrusted_paths() {
+        let stderr = "path '/nix/store/aaa-hello' is untrusted\n\
+                      path '/nix/store/bbb-glibc' is untrusted\n\
+                      2 paths are untrusted";
+        assert_eq!(
+            untrusted_paths(stderr),
+            ["/nix/store/aaa-hello", "/nix/store/bbb-glibc"]
+        );
+        assert!(untrusted_paths("error: path '/nix/store/aaa-hello' is not valid").is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_paths_outside_the_store() {
+        let paths = ["/tmp/hello".to_string()];
+        let signed = store_sign(&paths, Path::new("/etc/nix/ci.sec")).await;
+        assert!(matches!(signed, Err(Error::InvalidStorePath(path)) if path == "/tmp/hello"));
+        let verified = store_verify(&paths, &[]).await;
+        assert!(matches!(verified, Err(Error::InvalidStorePath(path)) if path == "/tmp/hello"));
+    }
+}
@@ -0,0 +1,174 @@
+//! Per-build chroot stores
+//!
+//! A build run with `--store <root>` gets its own store under `<root>/nix/store`
+//! and its own database, so nothing it creates can be garbage collected by, or
+//! leak into, the host store while it runs. As non-root, Nix builds into a
+//! chroot store from a private user namespace. Results are signed with
+//! [`IsolatedStore::sign`] when their destination checks signatures, copied
+//! to the host store (or a cache) with [`IsolatedStore::copy_to`] and the
+//! whole root is dropped with [`IsolatedStore::remove`].
+
+use std::ops::Not;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use super::commands::Error;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A chroot store owned by one build
+#[derive(Debug)]
+pub struct IsolatedStore {
+    root: PathBuf,
+}
+
+impl IsolatedStore {
+    /// Chroot store rooted at `root`, starting empty: whatever an earlier
+    /// attempt left there is removed first.
+    ///
+    /// # Errors
+    ///
+    /// - if the leftovers cannot be removed or the root cannot be created
+    pub async fn create(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        if tokio::fs::try_exists(&root).await? {
+            debug!(root = %root.display(), "Removing leftover build store");
+            remove_tree(root.clone()).await?;
+        }
+        tokio::fs::create_dir_all(&root).await?;
+        Ok(Self { root })
+    }
+
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Copy every path of this store to `destination`, a store URI such as
+    /// a binary cache, or the host store when `None`. Builds are local and
+    /// unsigned, so signatures are not checked.
+    ///
+    /// # Errors
+    ///
+    /// - if `nix copy` cannot be run or fails
+    pub async fn copy_to(&self, destination: Option<&str>) -> Result<()> {
+        let mut command = Command::new("nix");
+        command
+            .arg("copy")
+            .arg("--all")
+            .arg("--no-check-sigs")
+            .arg("--from")
+            .arg(&self.root);
+        if let Some(destination) = destination {
+            command.arg("--to").arg(destination);
+        }
+
+        let output = command.output().await?;
+        if output.status.success().not() {
+            return Err(Error::process_failed(
+                output.status.code(),
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+        info!(
+            root = %self.root.display(),
+            destination = destination.unwrap_or("host store"),
+            "Copied build store"
+        );
+        Ok(())
+    }
+
+    /// Sign every path of this store with the secret key in `key_file`, so
+    /// that stores trusting its public key accept them once copied.
+    ///
+    /// # Errors
+    ///
+    /// - if `nix store sign` cannot be run or fails
+    pub async fn sign(&self, key_file: &Path) -> Result<()> {
+        let output = Command::new("nix")
+            .args(["store", "sign", "--all", "--store"])
+            .arg(&self.root)
+            .arg("--key-file")
+            .arg(key_file)
+            .output()
+            .await?;
+        if output.status.success().not() {
+            return Err(Error::process_failed(
+                output.status.code(),
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+        info!(root = %self.root.display(), "Signed build store");
+        Ok(())
+    }
+
+    /// Delete the store and everything in it.
+    ///
+    /// # Errors
+    ///
+    /// - if the tree cannot be removed
+    pub async fn remove(self) -> Result<()> {
+        remove_tree(self.root).await
+    }
+}
+
+/// `remove_dir_all` that first makes the tree writable: store paths are
+/// read-only, so their entries cannot be unlinked as they are.
+pub(super) async fn remove_tree(root: PathBuf) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        make_writable(&root)?;
+        std::fs::remove_dir_all(&root)
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+    Ok(())
+}
+
+fn make_writable(path: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.is_symlink() {
+        return Ok(());
+    }
+    let mut permissions = metadata.permissions();
+    if permissions.readonly() {
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        std::fs::set_permissions(path, permissions)?;
+    }
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            make_writable(&entry?.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn remove_deletes_read_only_trees() {
+        let root = std::env::temp_dir().join(format!("isolated-store-{}", std::process::id()));
+        let store = IsolatedStore::create(&root).await.unwrap();
+
+        let path = root.join("nix/store/aaaa-hello");
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("bin"), "hello").unwrap();
+        for p in [path.join("bin"), path.clone()] {
+            let mut permissions = std::fs::metadata(&p).unwrap().permissions();
+            permissions.set_readonly(true);
+            std::fs::set_permissions(&p, permissions).unwrap();
+        }
+
+        // A retry starts from an empty store
+        drop(store);
+        let store = IsolatedStore::create(&root).await.unwrap();
+        assert!(!path.exists());
+        assert!(root.exists());
+
+        store.remove().await.unwrap();
+        assert!(!root.exists());
+    }
+}
@@ -0,0 +1,765 @@
+//! JSON API, served under [`API_V1`]
+//!
+//! Routes there only change in backward compatible ways; a breaking change
+//! goes under a new version. Requests and responses are the wire types of
+//! `repohub-client`, and the spec generated from the handlers is served at
+//! `/api/v1/openapi.json`, with Swagger UI at `/api/docs`.
+//!
+//! The create handlers are also routed at their historical paths (`/users`,
+//! `/{username}/projects`, ...) for the pages.
+//!
+//! The CI settings of a project apply to its repositories, unless a
+//! repository has its own. The CI reads them at `/repos/{owner}/{repo}/ci`,
+//! by the repository's path on the git server, on every push.
+//!
+//! An import clones a repository hosted elsewhere with its branches and
+//! tags. It either takes over from its source, or mirrors it: the source
+//! stays the truth and `POST /repos/{owner}/{repo}/mirror` fetches it again.
+//! The credentials of a private source are only used for the clone or fetch
+//! they come with, never stored.
+//!
+//! `GET /access` tells other services, like the CI, which repositories the
+//! bearer of an API token may see and act on; see [`crate::tokens`].
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use repo_outils::git::GitCredentials;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{
+    database::{DatabaseError, ProjectRow, UserRow},
+    models::{
+        Access, CiSettings, CreateProjectRequest, CreateRepositoryRequest, CreateTokenRequest,
+        CreateUserRequest, Created, CreatedToken, ImportRepositoryRequest, ImportedRepository,
+        Project, Repository, RepositoryAccess, SourceCredentials, SyncMirrorRequest, User,
+    },
+    services::repository_service::RepositoryError,
+    tokens,
+    web::AppState,
+};
+
+/// Prefix of the JSON API
+pub const API_V1: &str = "/api/v1";
+
+type ApiResult<T> = Result<T, (StatusCode, String)>;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Repohub"),
+    servers((url = "/api/v1")),
+    paths(
+        list_users,
+        create_user,
+        list_projects,
+        create_project,
+        list_repositories,
+        create_repository,
+        import_repository,
+        get_repository,
+        sync_mirror,
+        get_project_ci_settings,
+        set_project_ci_settings,
+        get_repository_ci_settings,
+        set_repository_ci_settings,
+        reset_repository_ci_settings,
+        create_token,
+        get_access
+    ),
+    tags(
+        (name = "users"),
+        (name = "projects"),
+        (name = "repositories"),
+        (name = "ci"),
+        (name = "tokens")
+    )
+)]
+pub struct ApiDoc;
+
+/// `NOT_FOUND` for a missing row, `INTERNAL_SERVER_ERROR` otherwise.
+fn db_error(e: &DatabaseError, context: &str) -> (StatusCode, String) {
+    let error = repo_outils::report(e);
+    if let DatabaseError::NotFound(_) = e {
+        return (StatusCode::NOT_FOUND, format!("{context}: {error}"));
+    }
+    tracing::error!(code = e.code(), %error, "{context}");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("{context}: {error}"),
+    )
+}
+
+async fn find_user(state: &AppState, username: &str) -> ApiResult<UserRow> {
+    state
+        .db
+        .get_user_by_username(username)
+        .await
+        .map_err(|e| db_error(&e, "User not found"))
+}
+
+async fn find_project(state: &AppState, username: &str, project: &str) -> ApiResult<ProjectRow> {
+    let user = find_user(state, username).await?;
+    state
+        .db
+        .get_project(user.id, project)
+        .await
+        .map_err(|e| db_error(&e, "Project not found"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "users",
+    responses(
+        (status = OK, body = Vec<User>),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn list_users(State(state): State<AppState>) -> ApiResult<Json<Vec<User>>> {
+    let users = state
+        .db
+        .list_users()
+        .await
+        .map_err(|e| db_error(&e, "Failed to list users"))?;
+    Ok(Json(users.into_iter().map(User::from).collect()))
+}
+
+/// Create a new user
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "users",
+    request_body = CreateUserRequest,
+    responses(
+        (status = CREATED, body = Created),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+pub(crate) async fn create_user(
+    State(state): State<AppState>,
+    Json(req): Json<CreateUserRequest>,
+) -> ApiResult<(StatusCode, Json<Created>)> {
+    let id = state
+        .db
+        .create_user(&req.username, req.email.as_deref())
+        .await
+        .map_err(|e| db_error(&e, "Failed to create user"))?;
+    tracing::info!(user_id = id, username = req.username, "User created");
+    Ok((StatusCode::CREATED, Json(Created { id })))
+}
+
+/// Projects owned by a user
+#[utoipa::path(
+    get,
+    path = "/users/{username}/projects",
+    tag = "projects",
+    params(("username" = String, Path)),
+    responses(
+        (status = OK, body = Vec<Project>),
+        (status = NOT_FOUND, description = "No such user", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn list_projects(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> ApiResult<Json<Vec<Project>>> {
+    let user = find_user(&state, &username).await?;
+    let projects = state
+        .db
+        .list_projects_by_owner(user.id)
+        .await
+        .map_err(|e| db_error(&e, "Failed to list projects"))?;
+    Ok(Json(projects.into_iter().map(Project::from).collect()))
+}
+
+/// Create a new project for a user
+#[utoipa::path(
+    post,
+    path = "/users/{username}/projects",
+    tag = "projects",
+    params(("username" = String, Path)),
+    request_body = CreateProjectRequest,
+    responses(
+        (status = CREATED, body = Created),
+        (status = NOT_FOUND, description = "No such user", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+pub(crate) async fn create_project(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Json(req): Json<CreateProjectRequest>,
+) -> ApiResult<(StatusCode, Json<Created>)> {
+    let user = find_user(&state, &username).await?;
+    let id = state
+        .db
+        .create_project(&req.name, user.id, req.description.as_deref())
+        .await
+        .map_err(|e| db_error(&e, "Failed to create project"))?;
+    tracing::info!(
+        project_id = id,
+        owner = username,
+        project_name = req.name,
+        "Project created"
+    );
+    Ok((StatusCode::CREATED, Json(Created { id })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{username}/projects/{project}/repositories",
+    tag = "repositories",
+    params(("username" = String, Path), ("project" = String, Path)),
+    responses(
+        (status = OK, body = Vec<Repository>),
+        (status = NOT_FOUND, description = "No such user or project", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn list_repositories(
+    State(state): State<AppState>,
+    Path((username, project)): Path<(String, String)>,
+) -> ApiResult<Json<Vec<Repository>>> {
+    let project = find_project(&state, &username, &project).await?;
+    let repos = state
+        .db
+        .list_repositories_by_project(project.id)
+        .await
+        .map_err(|e| db_error(&e, "Failed to list repositories"))?;
+    Ok(Json(repos.into_iter().map(Repository::from).collect()))
+}
+
+/// Create a new repository in a project, empty or cloned from `git_url`
+#[utoipa::path(
+    post,
+    path = "/users/{username}/projects/{project}/repositories",
+    tag = "repositories",
+    params(("username" = String, Path), ("project" = String, Path)),
+    request_body = CreateRepositoryRequest,
+    responses(
+        (status = CREATED, body = Created),
+        (status = NOT_FOUND, description = "No such user or project", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+pub(crate) async fn create_repository(
+    State(state): State<AppState>,
+    Path((username, project_name)): Path<(String, String)>,
+    Json(req): Json<CreateRepositoryRequest>,
+) -> ApiResult<(StatusCode, Json<Created>)> {
+    let project = find_project(&state, &username, &project_name).await?;
+
+    // Use the repository service to create or clone the repository
+    let git_url = state
+        .repo_service
+        .create_or_clone_repository(&username, &req.name, req.git_url.as_deref())
+        .map_err(|e| {
+            let error = repo_outils::report(&e);
+            tracing::error!(%error, "Failed to create/clone repository");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to create repository: {error}"),
+            )
+        })?;
+
+    // Persist repository in DB
+    let id = state
+        .db
+        .create_repository(project.id, &req.name, &git_url, req.private)
+        .await
+        .map_err(|e| db_error(&e, "Failed to create repository"))?;
+    tracing::info!(
+        repo_id = id,
+        project = project_name,
+        repo_name = req.name,
+        "Repository created"
+    );
+    Ok((StatusCode::CREATED, Json(Created { id })))
+}
+
+/// `BAD_REQUEST` for a source that may not be imported, `CONFLICT` when
+/// the repository exists, or isn't a mirror, `BAD_GATEWAY` when git fails
+/// against the source, e.g. unreachable or refusing the credentials,
+/// `INTERNAL_SERVER_ERROR` otherwise.
+fn import_error(e: &RepositoryError, context: &str) -> (StatusCode, String) {
+    use repo_outils::git::RepoError;
+
+    let error = repo_outils::report(e);
+    tracing::error!(%error, "{context}");
+    let status = match e {
+        RepositoryError::CloneFailed(RepoError::InvalidUrl(_)) => StatusCode::BAD_REQUEST,
+        RepositoryError::CloneFailed(RepoError::AlreadyExists(_)) => StatusCode::CONFLICT,
+        RepositoryError::CloneFailed(RepoError::GitError(_))
+        | RepositoryError::MirrorFetchFailed(RepoError::GitError(_)) => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, format!("{context}: {error}"))
+}
+
+fn git_credentials(credentials: Option<SourceCredentials>) -> Option<GitCredentials> {
+    credentials.map(|credentials| GitCredentials {
+        username: credentials.username,
+        password: credentials.token,
+    })
+}
+
+/// Import a repository hosted elsewhere, e.g. on GitHub or GitLab, with its
+/// branches and tags, optionally mirroring it, and set its CI settings
+#[utoipa::path(
+    post,
+    path = "/users/{username}/projects/{project}/imports",
+    tag = "repositories",
+    params(("username" = String, Path), ("project" = String, Path)),
+    request_body = ImportRepositoryRequest,
+    responses(
+        (status = CREATED, body = ImportedRepository),
+        (status = BAD_REQUEST, description = "Unknown required check, or a source that is not https or ssh", body = String),
+        (status = NOT_FOUND, description = "No such user or project", body = String),
+        (status = CONFLICT, description = "The repository exists", body = String),
+        (status = BAD_GATEWAY, description = "The source can't be cloned", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn import_repository(
+    State(state): State<AppState>,
+    Path((username, project_name)): Path<(String, String)>,
+    Json(req): Json<ImportRepositoryRequest>,
+) -> ApiResult<(StatusCode, Json<ImportedRepository>)> {
+    let project = find_project(&state, &username, &project_name).await?;
+    // Rejected before the clone, which can take a while
+    if let Some(settings) = &req.ci {
+        store_settings(settings)?;
+    }
+
+    let service = state.repo_service.clone();
+    let (owner, name, source) = (username.clone(), req.name.clone(), req.source_url.clone());
+    let credentials = git_credentials(req.credentials);
+    let mirror = req.mirror;
+    let (git_url, default_branch) = tokio::task::spawn_blocking(move || {
+        service.import_repository(&owner, &name, &source, credentials.as_ref(), mirror)
+    })
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to import repository: {}", repo_outils::report(&e)),
+        )
+    })?
+    .map_err(|e| import_error(&e, "Failed to import repository"))?;
+
+    let id = state
+        .db
+        .create_repository(project.id, &req.name, &git_url, req.private)
+        .await
+        .map_err(|e| db_error(&e, "Failed to create repository"))?;
+    let mirror_url = req
+        .mirror
+        .then(|| repo_outils::git::strip_credentials(&req.source_url));
+    if let Some(mirror_url) = &mirror_url {
+        state
+            .db
+            .set_repository_mirror(id, mirror_url)
+            .await
+            .map_err(|e| db_error(&e, "Failed to record the mirror"))?;
+    }
+    if let Some(mut settings) = req.ci {
+        if settings.default_branch.is_none() {
+            settings.default_branch.clone_from(&default_branch);
+        }
+        state
+            .db
+            .set_repository_ci_settings(id, Some(&store_settings(&settings)?))
+            .await
+            .map_err(|e| db_error(&e, "Failed to set CI settings"))?;
+    }
+    tracing::info!(
+        repo_id = id,
+        project = project_name,
+        repo_name = req.name,
+        ?mirror_url,
+        "Repository imported"
+    );
+    Ok((
+        StatusCode::CREATED,
+        Json(ImportedRepository {
+            id,
+            git_url,
+            default_branch,
+            mirror_url,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{username}/projects/{project}/repositories/{repo}",
+    tag = "repositories",
+    params(
+        ("username" = String, Path),
+        ("project" = String, Path),
+        ("repo" = String, Path),
+    ),
+    responses(
+        (status = OK, body = Repository),
+        (status = NOT_FOUND, description = "No such user, project or repository", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_repository(
+    State(state): State<AppState>,
+    Path((username, project, repo)): Path<(String, String, String)>,
+) -> ApiResult<Json<Repository>> {
+    let project = find_project(&state, &username, &project).await?;
+    let repo = state
+        .db
+        .get_repository(project.id, &repo)
+        .await
+        .map_err(|e| db_error(&e, "Repository not found"))?;
+    Ok(Json(Repository::from(repo)))
+}
+
+/// Stored CI settings, the defaults when unset.
+fn parse_settings(json: Option<&str>) -> CiSettings {
+    json.and_then(|json| {
+        serde_json::from_str(json)
+            .inspect_err(|e| tracing::error!("Invalid stored CI settings: {e}"))
+            .ok()
+    })
+    .unwrap_or_default()
+}
+
+/// `settings` as stored, `BAD_REQUEST` for a check the CI doesn't know.
+fn store_settings(settings: &CiSettings) -> ApiResult<String> {
+    if let Some(check) = settings
+        .required_checks
+        .iter()
+        .find(|check| !CiSettings::CHECKS.contains(&check.as_str()))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Unknown check {check:?}, expected one of {}",
+                CiSettings::CHECKS.join(", ")
+            ),
+        ));
+    }
+    serde_json::to_string(settings).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to store CI settings: {e}"),
+        )
+    })
+}
+
+/// CI settings of a project, inherited by its repositories
+#[utoipa::path(
+    get,
+    path = "/users/{username}/projects/{project}/ci",
+    tag = "ci",
+    params(("username" = String, Path), ("project" = String, Path)),
+    responses(
+        (status = OK, body = CiSettings),
+        (status = NOT_FOUND, description = "No such user or project", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_project_ci_settings(
+    State(state): State<AppState>,
+    Path((username, project)): Path<(String, String)>,
+) -> ApiResult<Json<CiSettings>> {
+    let project = find_project(&state, &username, &project).await?;
+    let settings = state
+        .db
+        .get_project_ci_settings(project.id)
+        .await
+        .map_err(|e| db_error(&e, "Failed to get CI settings"))?;
+    Ok(Json(parse_settings(settings.as_deref())))
+}
+
+#[utoipa::path(
+    put,
+    path = "/users/{username}/projects/{project}/ci",
+    tag = "ci",
+    params(("username" = String, Path), ("project" = String, Path)),
+    request_body = CiSettings,
+    responses(
+        (status = OK, body = CiSettings),
+        (status = BAD_REQUEST, description = "Unknown required check", body = String),
+        (status = NOT_FOUND, description = "No such user or project", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn set_project_ci_settings(
+    State(state): State<AppState>,
+    Path((username, project_name)): Path<(String, String)>,
+    Json(settings): Json<CiSettings>,
+) -> ApiResult<Json<CiSettings>> {
+    let project = find_project(&state, &username, &project_name).await?;
+    state
+        .db
+        .set_project_ci_settings(project.id, &store_settings(&settings)?)
+        .await
+        .map_err(|e| db_error(&e, "Failed to set CI settings"))?;
+    tracing::info!(project = project_name, ?settings, "Project CI settings set");
+    Ok(Json(settings))
+}
+
+/// CI settings that apply to a repository: its own, or else its project's
+#[utoipa::path(
+    get,
+    path = "/repos/{owner}/{repo}/ci",
+    tag = "ci",
+    params(
+        ("owner" = String, Path, description = "Owner of the project, the repository's directory on the git server"),
+        ("repo" = String, Path),
+    ),
+    responses(
+        (status = OK, body = CiSettings),
+        (status = NOT_FOUND, description = "No such repository", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_repository_ci_settings(
+    State(state): State<AppState>,
+    Path((owner, repo)): Path<(String, String)>,
+) -> ApiResult<Json<CiSettings>> {
+    let (_, settings, inherited) = state
+        .db
+        .get_repository_ci_settings(&owner, &repo)
+        .await
+        .map_err(|e| db_error(&e, "Repository not found"))?;
+    Ok(Json(parse_settings(settings.or(inherited).as_deref())))
+}
+
+/// Override the CI settings of a repository
+#[utoipa::path(
+    put,
+    path = "/repos/{owner}/{repo}/ci",
+    tag = "ci",
+    params(("owner" = String, Path), ("repo" = String, Path)),
+    request_body = CiSettings,
+    responses(
+        (status = OK, body = CiSettings),
+        (status = BAD_REQUEST, description = "Unknown required check", body = String),
+        (status = NOT_FOUND, description = "No such repository", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn set_repository_ci_settings(
+    State(state): State<AppState>,
+    Path((owner, repo)): Path<(String, String)>,
+    Json(settings): Json<CiSettings>,
+) -> ApiResult<Json<CiSettings>> {
+    let stored = store_settings(&settings)?;
+    let (id, _, _) = state
+        .db
+        .get_repository_ci_settings(&owner, &repo)
+        .await
+        .map_err(|e| db_error(&e, "Repository not found"))?;
+    state
+        .db
+        .set_repository_ci_settings(id, Some(&stored))
+        .await
+        .map_err(|e| db_error(&e, "Failed to set CI settings"))?;
+    tracing::info!(owner, repo, ?settings, "Repository CI settings set");
+    Ok(Json(settings))
+}
+
+/// Go back to the project's CI settings, returned
+#[utoipa::path(
+    delete,
+    path = "/repos/{owner}/{repo}/ci",
+    tag = "ci",
+    params(("owner" = String, Path), ("repo" = String, Path)),
+    responses(
+        (status = OK, body = CiSettings),
+        (status = NOT_FOUND, description = "No such repository", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn reset_repository_ci_settings(
+    State(state): State<AppState>,
+    Path((owner, repo)): Path<(String, String)>,
+) -> ApiResult<Json<CiSettings>> {
+    let (id, _, inherited) = state
+        .db
+        .get_repository_ci_settings(&owner, &repo)
+        .await
+        .map_err(|e| db_error(&e, "Repository not found"))?;
+    state
+        .db
+        .set_repository_ci_settings(id, None)
+        .await
+        .map_err(|e| db_error(&e, "Failed to reset CI settings"))?;
+    tracing::info!(owner, repo, "Repository CI settings reset");
+    Ok(Json(parse_settings(inherited.as_deref())))
+}
+
+/// Create an API token for a user; the token is only returned here
+#[utoipa::path(
+    post,
+    path = "/users/{username}/tokens",
+    tag = "tokens",
+    params(("username" = String, Path)),
+    request_body = CreateTokenRequest,
+    responses(
+        (status = CREATED, body = CreatedToken),
+        (status = NOT_FOUND, description = "No such user", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn create_token(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Json(req): Json<CreateTokenRequest>,
+) -> ApiResult<(StatusCode, Json<CreatedToken>)> {
+    let user = find_user(&state, &username).await?;
+    let token = tokens::generate().map_err(|e| {
+        tracing::error!("Failed to generate a token: {e}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to generate a token: {e}"),
+        )
+    })?;
+    let id = state
+        .db
+        .create_token(user.id, &req.name, &tokens::hash(&token))
+        .await
+        .map_err(|e| db_error(&e, "Failed to create token"))?;
+    tracing::info!(
+        token_id = id,
+        username,
+        token_name = req.name,
+        "Token created"
+    );
+    Ok((StatusCode::CREATED, Json(CreatedToken { id, token })))
+}
+
+/// Repositories the bearer of the token may see, and whether they may act
+/// on them; public repositories only, read-only, without a token
+#[utoipa::path(
+    get,
+    path = "/access",
+    tag = "tokens",
+    responses(
+        (status = OK, body = Access),
+        (status = UNAUTHORIZED, description = "Unknown token", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn get_access(State(state): State<AppState>, headers: HeaderMap) -> ApiResult<Json<Access>> {
+    let user = match tokens::bearer(&headers) {
+        Some(token) => match state.db.get_user_by_token_hash(&tokens::hash(token)).await {
+            Ok(user) => Some(user),
+            Err(DatabaseError::NotFound(_)) => {
+                return Err((StatusCode::UNAUTHORIZED, "Unknown token".to_string()));
+            }
+            Err(e) => return Err(db_error(&e, "Failed to check token")),
+        },
+        None => None,
+    };
+    let repositories = state
+        .db
+        .list_repository_access(user.as_ref().map(|user| user.id))
+        .await
+        .map_err(|e| db_error(&e, "Failed to list repositories"))?;
+    Ok(Json(Access {
+        user: user.map(User::from),
+        repositories: repositories
+            .into_iter()
+            .map(RepositoryAccess::from)
+            .collect(),
+    }))
+}
+
+/// JSON API, to nest under [`API_V1`]
+/// Fetch a mirrored import from its source
+#[utoipa::path(
+    post,
+    path = "/repos/{owner}/{repo}/mirror",
+    tag = "repositories",
+    params(("owner" = String, Path), ("repo" = String, Path)),
+    request_body = SyncMirrorRequest,
+    responses(
+        (status = OK, body = Repository),
+        (status = NOT_FOUND, description = "No such repository", body = String),
+        (status = CONFLICT, description = "The repository is not a mirror", body = String),
+        (status = BAD_GATEWAY, description = "The source can't be fetched", body = String),
+        (status = INTERNAL_SERVER_ERROR, body = String),
+    )
+)]
+async fn sync_mirror(
+    State(state): State<AppState>,
+    Path((owner, repo)): Path<(String, String)>,
+    Json(req): Json<SyncMirrorRequest>,
+) -> ApiResult<Json<Repository>> {
+    let row = state
+        .db
+        .get_repository_by_owner(&owner, &repo)
+        .await
+        .map_err(|e| db_error(&e, "Repository not found"))?;
+    if row.mirror_url.is_none() {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("Repository {owner}/{repo} is not a mirror"),
+        ));
+    }
+
+    let service = state.repo_service.clone();
+    let credentials = git_credentials(req.credentials);
+    tokio::task::spawn_blocking(move || service.sync_mirror(&owner, &repo, credentials.as_ref()))
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to fetch mirror: {}", repo_outils::report(&e)),
+            )
+        })?
+        .map_err(|e| import_error(&e, "Failed to fetch mirror"))?;
+    Ok(Json(Repository::from(row)))
+}
+
+pub fn api_routes() -> Router<AppState> {
+    Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route(
+            "/users/{username}/projects",
+            get(list_projects).post(create_project),
+        )
+        .route(
+            "/users/{username}/projects/{project}/repositories",
+            get(list_repositories).post(create_repository),
+        )
+        .route(
+            "/users/{username}/projects/{project}/imports",
+            post(import_repository),
+        )
+        .route(
+            "/users/{username}/projects/{project}/repositories/{repo}",
+            get(get_repository),
+        )
+        .route(
+            "/users/{username}/projects/{project}/ci",
+            get(get_project_ci_settings).put(set_project_ci_settings),
+        )
+        .route(
+            "/repos/{owner}/{repo}/ci",
+            get(get_repository_ci_settings)
+                .put(set_repository_ci_settings)
+                .delete(reset_repository_ci_settings),
+        )
+        .route("/repos/{owner}/{repo}/mirror", post(sync_mirror))
+        .route("/users/{username}/tokens", post(create_token))
+        .route("/access", get(get_access))
+}
+
+/// Spec of [`api_routes`] at `/api/v1/openapi.json`, browsable with
+/// Swagger UI at `/api/docs`
+pub fn docs() -> Router<AppState> {
+    Router::new().merge(SwaggerUi::new("/api/docs").url("/api/v1/openapi.json", ApiDoc::openapi()))
+}
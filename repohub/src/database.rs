@@ -8,33 +8,30 @@ use std::{ops::Deref, str::FromStr};
 use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
 use tracing::info;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum DatabaseError {
-    Connection(sqlx::Error),
-    Query(sqlx::Error),
+    #[error("Database connection error")]
+    Connection(#[source] sqlx::Error),
+    #[error("Database query error")]
+    Query(#[from] sqlx::Error),
+    #[error("Invalid data: {0}")]
     InvalidData(String),
+    #[error("Not found: {0}")]
     NotFound(String),
 }
 
-impl std::fmt::Display for DatabaseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl DatabaseError {
+    /// Stable identifier for this failure, independent of the message.
+    pub fn code(&self) -> &'static str {
         match self {
-            DatabaseError::Connection(err) => write!(f, "Database connection error: {}", err),
-            DatabaseError::Query(err) => write!(f, "Database query error: {}", err),
-            DatabaseError::InvalidData(msg) => write!(f, "Invalid data: {}", msg),
-            DatabaseError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            DatabaseError::Connection(_) => "db.connection",
+            DatabaseError::Query(_) => "db.query",
+            DatabaseError::InvalidData(_) => "db.invalid_data",
+            DatabaseError::NotFound(_) => "db.not_found",
         }
     }
 }
 
-impl std::error::Error for DatabaseError {}
-
-impl From<sqlx::Error> for DatabaseError {
-    fn from(err: sqlx::Error) -> Self {
-        DatabaseError::Query(err)
-    }
-}
-
 pub type Result<T> = std::result::Result<T, DatabaseError>;
 
 /// Database row for users table
@@ -63,9 +60,24 @@ pub struct RepositoryRow {
     pub project_id: i64,
     pub name: String,
     pub git_url: String,
+    pub private: bool,
+    pub mirror_url: Option<String>,
     pub created_at: String,
 }
 
+/// A repository a user, or anyone, may see, with the user's role in its
+/// project
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct RepositoryAccessRow {
+    /// Username of the project owner, the repository's directory
+    pub owner: String,
+    pub name: String,
+    pub private: bool,
+    /// `owner`, the member's role, or `None` for a public repository of
+    /// someone else's project
+    pub role: Option<String>,
+}
+
 /// Database row for project members table
 #[derive(Debug, Clone, sqlx::FromRow)]
 pub struct ProjectMemberRow {
@@ -125,6 +137,7 @@ impl Database {
                 name TEXT NOT NULL,
                 owner_id INTEGER NOT NULL,
                 description TEXT,
+                ci_settings TEXT,
                 created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
                 FOREIGN KEY (owner_id) REFERENCES users(id),
                 UNIQUE(owner_id, name)
@@ -142,6 +155,9 @@ impl Database {
                 project_id INTEGER NOT NULL,
                 name TEXT NOT NULL,
                 git_url TEXT NOT NULL,
+                private INTEGER NOT NULL DEFAULT 0,
+                ci_settings TEXT,
+                mirror_url TEXT,
                 created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
                 FOREIGN KEY (project_id) REFERENCES projects(id),
                 UNIQUE(project_id, name)
@@ -151,6 +167,19 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // Databases created before repositories could be private
+        self.add_column_if_missing("repositories", "private", "INTEGER NOT NULL DEFAULT 0")
+            .await?;
+        // CI settings as JSON, NULL for the defaults or, on a repository, its
+        // project's
+        for table in ["projects", "repositories"] {
+            self.add_column_if_missing(table, "ci_settings", "TEXT")
+                .await?;
+        }
+        // Source of a mirrored import, NULL for a repository hosted here
+        self.add_column_if_missing("repositories", "mirror_url", "TEXT")
+            .await?;
+
         // Project members table (for collaboration)
         sqlx::query(
             r#"
@@ -168,6 +197,22 @@ impl Database {
         .execute(&self.pool)
         .await?;
 
+        // API tokens, only their SHA-256 is stored
+        sqlx::query(
+            r"
+            CREATE TABLE IF NOT EXISTS api_tokens (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                token_hash TEXT NOT NULL UNIQUE,
+                created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (user_id) REFERENCES users(id)
+            )
+            ",
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Create indexes for performance
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_projects_owner_id ON projects(owner_id)")
             .execute(&self.pool)
@@ -188,6 +233,27 @@ impl Database {
         Ok(())
     }
 
+    async fn add_column_if_missing(
+        &self,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<()> {
+        let columns: Vec<String> =
+            sqlx::query_scalar(&format!("SELECT name FROM pragma_table_info('{table}')"))
+                .fetch_all(&self.pool)
+                .await?;
+        if !columns.iter().any(|c| c == column) {
+            info!(table, column, "Adding missing column");
+            sqlx::query(&format!(
+                "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+            ))
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
     // ========== User Operations ==========
 
     pub async fn create_user(&self, username: &str, email: Option<&str>) -> Result<i64> {
@@ -322,16 +388,18 @@ impl Database {
         project_id: i64,
         name: &str,
         git_url: &str,
+        private: bool,
     ) -> Result<i64> {
         let result = sqlx::query(
             r#"
-            INSERT INTO repositories (project_id, name, git_url)
-            VALUES (?, ?, ?)
+            INSERT INTO repositories (project_id, name, git_url, private)
+            VALUES (?, ?, ?, ?)
             "#,
         )
         .bind(project_id)
         .bind(name)
         .bind(git_url)
+        .bind(private)
         .execute(&self.pool)
         .await?;
 
@@ -345,7 +413,7 @@ impl Database {
     ) -> Result<RepositoryRow> {
         sqlx::query_as::<_, RepositoryRow>(
             r#"
-            SELECT id, project_id, name, git_url, created_at
+            SELECT id, project_id, name, git_url, private, mirror_url, created_at
             FROM repositories
             WHERE project_id = ? AND name = ?
             "#,
@@ -369,7 +437,7 @@ impl Database {
     ) -> Result<Vec<RepositoryRow>> {
         sqlx::query_as::<_, RepositoryRow>(
             r#"
-            SELECT id, project_id, name, git_url, created_at
+            SELECT id, project_id, name, git_url, private, mirror_url, created_at
             FROM repositories
             WHERE project_id = ?
             ORDER BY name
@@ -381,6 +449,47 @@ impl Database {
         .map_err(DatabaseError::Query)
     }
 
+    /// Record that a repository mirrors `mirror_url`
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be updated
+    pub async fn set_repository_mirror(&self, repository_id: i64, mirror_url: &str) -> Result<()> {
+        sqlx::query("UPDATE repositories SET mirror_url = ? WHERE id = ?")
+            .bind(mirror_url)
+            .bind(repository_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The repository `repo` of `owner`'s projects
+    ///
+    /// # Errors
+    ///
+    /// - `NotFound` if `owner` has no such repository
+    pub async fn get_repository_by_owner(&self, owner: &str, repo: &str) -> Result<RepositoryRow> {
+        sqlx::query_as::<_, RepositoryRow>(
+            r"
+            SELECT r.id, r.project_id, r.name, r.git_url, r.private, r.mirror_url, r.created_at
+            FROM repositories r
+            JOIN projects p ON p.id = r.project_id
+            JOIN users u ON u.id = p.owner_id
+            WHERE u.username = ? AND r.name = ?
+            ",
+        )
+        .bind(owner)
+        .bind(repo)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                DatabaseError::NotFound(format!("Repository '{owner}/{repo}' not found"))
+            }
+            e => DatabaseError::Query(e),
+        })
+    }
+
     // ========== Project Members Operations ==========
 
     pub async fn add_project_member(
@@ -417,4 +526,271 @@ impl Database {
         .await
         .map_err(DatabaseError::Query)
     }
+
+    // ========== CI Settings Operations ==========
+
+    /// # Errors
+    ///
+    /// - if the database can't be queried
+    pub async fn get_project_ci_settings(&self, project_id: i64) -> Result<Option<String>> {
+        sqlx::query_scalar("SELECT ci_settings FROM projects WHERE id = ?")
+            .bind(project_id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(DatabaseError::Query)
+    }
+
+    /// # Errors
+    ///
+    /// - if the database can't be updated
+    pub async fn set_project_ci_settings(&self, project_id: i64, settings: &str) -> Result<()> {
+        sqlx::query("UPDATE projects SET ci_settings = ? WHERE id = ?")
+            .bind(settings)
+            .bind(project_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Id, CI settings and project's CI settings of the repository
+    /// `owner/repo` on the git server
+    ///
+    /// # Errors
+    ///
+    /// - `NotFound` if `owner` has no such repository
+    pub async fn get_repository_ci_settings(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> Result<(i64, Option<String>, Option<String>)> {
+        sqlx::query_as(
+            r"
+            SELECT r.id, r.ci_settings, p.ci_settings
+            FROM repositories r
+            JOIN projects p ON p.id = r.project_id
+            JOIN users u ON u.id = p.owner_id
+            WHERE u.username = ? AND r.name = ?
+            ",
+        )
+        .bind(owner)
+        .bind(repo)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                DatabaseError::NotFound(format!("Repository '{owner}/{repo}' not found"))
+            }
+            e => DatabaseError::Query(e),
+        })
+    }
+
+    /// Set the CI settings of a repository, `None` to inherit its project's
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be updated
+    pub async fn set_repository_ci_settings(
+        &self,
+        repository_id: i64,
+        settings: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query("UPDATE repositories SET ci_settings = ? WHERE id = ?")
+            .bind(settings)
+            .bind(repository_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // ========== API Token Operations ==========
+
+    /// # Errors
+    ///
+    /// - if the database can't be updated
+    pub async fn create_token(&self, user_id: i64, name: &str, token_hash: &str) -> Result<i64> {
+        let result = sqlx::query(
+            r"
+            INSERT INTO api_tokens (user_id, name, token_hash)
+            VALUES (?, ?, ?)
+            ",
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(token_hash)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Owner of the token whose SHA-256 is `token_hash`
+    ///
+    /// # Errors
+    ///
+    /// - `NotFound` if no token has this hash
+    pub async fn get_user_by_token_hash(&self, token_hash: &str) -> Result<UserRow> {
+        sqlx::query_as::<_, UserRow>(
+            r"
+            SELECT u.id, u.username, u.email, u.created_at
+            FROM api_tokens t JOIN users u ON u.id = t.user_id
+            WHERE t.token_hash = ?
+            ",
+        )
+        .bind(token_hash)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => DatabaseError::NotFound("Unknown token".to_string()),
+            e => DatabaseError::Query(e),
+        })
+    }
+
+    /// Every public repository, and the private ones of the projects
+    /// `user_id` owns or is a member of
+    ///
+    /// # Errors
+    ///
+    /// - if the database can't be queried
+    pub async fn list_repository_access(
+        &self,
+        user_id: Option<i64>,
+    ) -> Result<Vec<RepositoryAccessRow>> {
+        sqlx::query_as::<_, RepositoryAccessRow>(
+            r"
+            SELECT u.username AS owner, r.name, r.private,
+                   CASE WHEN p.owner_id = ?1 THEN 'owner' ELSE m.role END AS role
+            FROM repositories r
+            JOIN projects p ON p.id = r.project_id
+            JOIN users u ON u.id = p.owner_id
+            LEFT JOIN project_members m ON m.project_id = p.id AND m.user_id = ?1
+            WHERE r.private = 0 OR p.owner_id = ?1 OR m.user_id IS NOT NULL
+            ORDER BY u.username, r.name
+            ",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(DatabaseError::Query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn private_repositories_are_only_listed_for_their_project() {
+        let path = std::env::temp_dir().join(format!("repohub-access-{}.db", std::process::id()));
+        let db = Database::new(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        let lucas = db.create_user("lucas", None).await.unwrap();
+        let ana = db.create_user("ana", None).await.unwrap();
+        let bob = db.create_user("bob", None).await.unwrap();
+        let project = db.create_project("homelab", lucas, None).await.unwrap();
+        db.create_repository(project, "api", "git@homelab:lucas/api.git", true)
+            .await
+            .unwrap();
+        db.create_repository(project, "docs", "git@homelab:lucas/docs.git", false)
+            .await
+            .unwrap();
+        db.add_project_member(project, ana, "reader").await.unwrap();
+
+        let listed = |rows: Vec<RepositoryAccessRow>| {
+            rows.into_iter()
+                .map(|row| (row.name, row.role))
+                .collect::<Vec<_>>()
+        };
+        let owner = Some("owner".to_string());
+        assert_eq!(
+            listed(db.list_repository_access(Some(lucas)).await.unwrap()),
+            [
+                ("api".to_string(), owner.clone()),
+                ("docs".to_string(), owner)
+            ]
+        );
+        let reader = Some("reader".to_string());
+        assert_eq!(
+            listed(db.list_repository_access(Some(ana)).await.unwrap()),
+            [
+                ("api".to_string(), reader.clone()),
+                ("docs".to_string(), reader)
+            ]
+        );
+        for user in [Some(bob), None] {
+            assert_eq!(
+                listed(db.list_repository_access(user).await.unwrap()),
+                [("docs".to_string(), None)]
+            );
+        }
+
+        db.create_token(bob, "ci", "hash").await.unwrap();
+        assert_eq!(db.get_user_by_token_hash("hash").await.unwrap().id, bob);
+        assert!(matches!(
+            db.get_user_by_token_hash("other").await,
+            Err(DatabaseError::NotFound(_))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn repository_ci_settings_fall_back_to_the_project() {
+        let path = std::env::temp_dir().join(format!("repohub-ci-{}.db", std::process::id()));
+        let db = Database::new(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        let lucas = db.create_user("lucas", None).await.unwrap();
+        let project = db.create_project("homelab", lucas, None).await.unwrap();
+        db.create_repository(project, "api", "git@homelab:lucas/api.git", false)
+            .await
+            .unwrap();
+
+        let (api, repository, inherited) =
+            db.get_repository_ci_settings("lucas", "api").await.unwrap();
+        assert_eq!((repository, inherited), (None, None));
+        db.set_project_ci_settings(project, "{}").await.unwrap();
+        db.set_repository_ci_settings(api, Some(r#"{"enabled":false}"#))
+            .await
+            .unwrap();
+        let (_, repository, inherited) =
+            db.get_repository_ci_settings("lucas", "api").await.unwrap();
+        assert_eq!(repository.as_deref(), Some(r#"{"enabled":false}"#));
+        assert_eq!(inherited.as_deref(), Some("{}"));
+        assert!(matches!(
+            db.get_repository_ci_settings("ana", "api").await,
+            Err(DatabaseError::NotFound(_))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn mirrors_remember_their_source() {
+        let path = std::env::temp_dir().join(format!("repohub-mirror-{}.db", std::process::id()));
+        let db = Database::new(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        let lucas = db.create_user("lucas", None).await.unwrap();
+        let project = db.create_project("homelab", lucas, None).await.unwrap();
+        let api = db
+            .create_repository(project, "api", "git@homelab:lucas/api.git", false)
+            .await
+            .unwrap();
+
+        assert_eq!(db.get_repository_by_owner("lucas", "api").await.unwrap().mirror_url, None);
+        db.set_repository_mirror(api, "https://github.com/lucas/api.git")
+            .await
+            .unwrap();
+        assert_eq!(
+            db.get_repository(project, "api").await.unwrap().mirror_url.as_deref(),
+            Some("https://github.com/lucas/api.git")
+        );
+        assert!(matches!(
+            db.get_repository_by_owner("ana", "api").await,
+            Err(DatabaseError::NotFound(_))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -1,9 +1,13 @@
+mod api;
 mod config;
 mod database;
 mod models;
+pub mod policy;
 mod services;
+mod tokens;
 mod web;
 
+pub use api::{API_V1, ApiDoc};
 pub use config::Config;
 pub use database::Database;
 pub use services::RepositoryService;
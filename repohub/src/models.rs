@@ -5,19 +5,16 @@
 
 pub mod configuration;
 
-use serde::{Deserialize, Serialize};
-
-use crate::database::{ProjectRow, RepositoryRow, UserRow};
+use crate::database::{ProjectRow, RepositoryAccessRow, RepositoryRow, UserRow};
 
 pub use configuration::*;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct User {
-    pub id: i64,
-    pub username: String,
-    pub email: Option<String>,
-    pub created_at: String,
-}
+// Entities and requests are the wire types of the JSON API, shared with the
+// client so both ends agree on them
+pub use repohub_client::types::{
+    Access, CiSettings, CreateProjectRequest, CreateRepositoryRequest, CreateTokenRequest,
+    CreateUserRequest, Created, CreatedToken, ImportRepositoryRequest, ImportedRepository,
+    Project, Repository, RepositoryAccess, SourceCredentials, SyncMirrorRequest, User,
+};
 
 impl From<UserRow> for User {
     fn from(row: UserRow) -> Self {
@@ -30,15 +27,6 @@ impl From<UserRow> for User {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Project {
-    pub id: i64,
-    pub name: String,
-    pub owner_id: i64,
-    pub description: Option<String>,
-    pub created_at: String,
-}
-
 impl From<ProjectRow> for Project {
     fn from(row: ProjectRow) -> Self {
         Self {
@@ -51,15 +39,6 @@ impl From<ProjectRow> for Project {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Repository {
-    pub id: i64,
-    pub project_id: i64,
-    pub name: String,
-    pub git_url: String,
-    pub created_at: String,
-}
-
 impl From<RepositoryRow> for Repository {
     fn from(row: RepositoryRow) -> Self {
         Self {
@@ -67,26 +46,20 @@ impl From<RepositoryRow> for Repository {
             project_id: row.project_id,
             name: row.name,
             git_url: row.git_url,
+            private: row.private,
+            mirror_url: row.mirror_url,
             created_at: row.created_at,
         }
     }
 }
 
-// DTOs for creating entities
-#[derive(Debug, Deserialize)]
-pub struct CreateUserRequest {
-    pub username: String,
-    pub email: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct CreateProjectRequest {
-    pub name: String,
-    pub description: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct CreateRepositoryRequest {
-    pub name: String,
-    pub git_url: Option<String>,
+impl From<RepositoryAccessRow> for RepositoryAccess {
+    fn from(row: RepositoryAccessRow) -> Self {
+        Self {
+            path: format!("{}/{}", row.owner, row.name),
+            private: row.private,
+            // Readers may look, everyone else in the project may act
+            write: row.role.is_some_and(|role| role != "reader"),
+        }
+    }
 }
@@ -0,0 +1,937 @@
+//! Pre-receive policies
+//!
+//! Checks run by the `pre-receive` hook (`repohub-pre-receive`) on what a
+//! push brings in, before any ref is updated. A push breaking one of them is
+//! rejected as a whole, and the pusher gets one line per violation.
+//!
+//! Policies are git config, so they can be set for every repository in the
+//! server's gitconfig and overridden per repository with `git config`:
+//!
+//! | Key                              | Value                                  |
+//! |----------------------------------|----------------------------------------|
+//! | `procurator.maxFileSize`         | largest blob, e.g. `50m` (k, m, g)     |
+//! | `procurator.forbiddenPath`       | glob, once per pattern, e.g. `*.pem`   |
+//! | `procurator.requireSignedCommits`| every new commit has a good signature  |
+//! | `procurator.allowedSigners`      | SSH keys commits may be signed with    |
+//! | `procurator.protectedBranch`     | glob, once per pattern, e.g. `main`    |
+//! | `procurator.ownerSigners`        | SSH keys of the code owners, see below |
+//!
+//! A good signature is one from a key the server trusts: an SSH key in the
+//! `allowedSigners` file, in the `allowed_signers` format of `ssh-keygen`,
+//! or a GPG key trusted in the keyring of the user running the hook. A
+//! valid signature from any other key, one the pusher made up, is refused.
+//!
+//! A pattern without `/` matches file names anywhere in the tree, one with
+//! `/` matches the whole path. `*` matches within one path segment and `?`
+//! matches one character.
+//!
+//! ## Code owners
+//!
+//! A push moving a protected branch must be approved by the owners of the
+//! paths it changes. Owners come from the `CODEOWNERS` file of the branch
+//! as it was before the push (`CODEOWNERS`, `.github/CODEOWNERS` or
+//! `docs/CODEOWNERS`, the first found), so a push cannot drop its own
+//! owners. A new protected branch uses the file of the default branch
+//! (`HEAD`), and changes what it brings in since it forked from it; only
+//! the first push to a repository, or a branch sharing no history with the
+//! default one, uses the file it brings in and has every path checked.
+//! Each line is a pattern followed by owners, `@username` or e-mail
+//! addresses:
+//!
+//! ```text
+//! *                @lead
+//! /nix/            @ops ops@example.com
+//! docs/
+//! ```
+//!
+//! The last matching line wins, and a line without owners leaves its paths
+//! unowned. Patterns match like the ones above, and also match every path
+//! below a directory of that name; a trailing `/` only matches
+//! directories.
+//!
+//! An owner approves by signing the commit the branch moves to with their
+//! SSH key. The keys are the `ownerSigners` file on the server, in the
+//! `allowed_signers` format of `ssh-keygen`, with the owner as principal:
+//!
+//! ```text
+//! @ops  ssh-ed25519 AAAAC3Nza...
+//! ```
+//!
+//! The pusher controls the commits but not this file, so the principal of
+//! a good signature is who approved. Every changed path with owners needs
+//! the signer among them, and without `ownerSigners` no push changing owned
+//! paths is accepted.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use repo_outils::git::RepoError;
+
+/// Git writes this as the old or new value of a created or deleted ref.
+const ZERO_REV: &str = "0000000000000000000000000000000000000000";
+
+/// Policies of one repository, see the module docs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub max_file_size: Option<u64>,
+    pub forbidden_paths: Vec<String>,
+    pub require_signed_commits: bool,
+    pub allowed_signers: Option<PathBuf>,
+    pub protected_branches: Vec<String>,
+    pub owner_signers: Option<PathBuf>,
+}
+
+/// One line of the hook's input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefUpdate {
+    pub old: String,
+    pub new: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    MaxFileSize,
+    ForbiddenPath,
+    SignedCommits,
+    CodeOwners,
+}
+
+impl Rule {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rule::MaxFileSize => "max-file-size",
+            Rule::ForbiddenPath => "forbidden-path",
+            Rule::SignedCommits => "signed-commits",
+            Rule::CodeOwners => "code-owners",
+        }
+    }
+}
+
+/// Why a push is rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub ref_name: String,
+    pub rule: Rule,
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}",
+            self.ref_name,
+            self.rule.as_str(),
+            self.detail
+        )
+    }
+}
+
+impl RefUpdate {
+    /// Parse `<old> <new> <ref>`, as git feeds the hook on stdin.
+    #[must_use]
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let update = Self {
+            old: words.next()?.to_string(),
+            new: words.next()?.to_string(),
+            name: words.next()?.to_string(),
+        };
+        words.next().is_none().then_some(update)
+    }
+
+    #[must_use]
+    pub fn is_delete(&self) -> bool {
+        self.new == ZERO_REV
+    }
+
+    #[must_use]
+    pub fn is_create(&self) -> bool {
+        self.old == ZERO_REV
+    }
+}
+
+impl Policy {
+    /// Policies of the repository at `git_dir`, merged with the global and
+    /// system gitconfig.
+    ///
+    /// # Errors
+    ///
+    /// - if git cannot be run, or a value cannot be parsed
+    pub fn load(git_dir: &Path) -> Result<Self, RepoError> {
+        // Exits with 1 when no key matches
+        let output = git_command(git_dir)
+            .args(["config", "--null", "--get-regexp", r"^procurator\."])
+            .output()?;
+        match output.status.code() {
+            Some(0) => Self::parse(&String::from_utf8_lossy(&output.stdout)),
+            Some(1) => Ok(Self::default()),
+            _ => Err(RepoError::GitError(format!(
+                "git config failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    }
+
+    /// Parse the output of `git config --null --get-regexp`, keys in lower
+    /// case. The last `maxFileSize`, `requireSignedCommits`,
+    /// `allowedSigners` and `ownerSigners` win, like for any git setting.
+    fn parse(entries: &str) -> Result<Self, RepoError> {
+        let mut policy = Self::default();
+        for entry in entries.split('\0').filter(|e| !e.is_empty()) {
+            let (key, value) = entry.split_once('\n').unwrap_or((entry, "true"));
+            let invalid = || RepoError::GitError(format!("invalid {key} {value:?}"));
+            match key {
+                "procurator.maxfilesize" => {
+                    policy.max_file_size = Some(parse_size(value).ok_or_else(invalid)?);
+                }
+                "procurator.forbiddenpath" => policy.forbidden_paths.push(value.to_string()),
+                "procurator.requiresignedcommits" => {
+                    policy.require_signed_commits = parse_bool(value).ok_or_else(invalid)?;
+                }
+                "procurator.allowedsigners" => {
+                    policy.allowed_signers = Some(PathBuf::from(value));
+                }
+                "procurator.protectedbranch" => policy.protected_branches.push(value.to_string()),
+                "procurator.ownersigners" => policy.owner_signers = Some(PathBuf::from(value)),
+                _ => {}
+            }
+        }
+        Ok(policy)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Everything `updates` break, in the repository at `git_dir`. Only what
+    /// the push brings in is checked: commits and blobs already reachable
+    /// from a ref were accepted before.
+    ///
+    /// # Errors
+    ///
+    /// - if git cannot be run on the repository
+    pub fn check(
+        &self,
+        git_dir: &Path,
+        updates: &[RefUpdate],
+    ) -> Result<Vec<Violation>, RepoError> {
+        let mut violations = Vec::new();
+        for update in updates.iter().filter(|u| !u.is_delete()) {
+            if self.max_file_size.is_some() || !self.forbidden_paths.is_empty() {
+                let blobs = parse_blobs(&git_output(
+                    git_dir,
+                    &["rev-list", "--objects", &update.new, "--not", "--all"],
+                    None,
+                )?);
+                let blobs = with_sizes(git_dir, blobs)?;
+                violations.extend(self.check_blobs(&update.name, &blobs));
+            }
+            if self.require_signed_commits {
+                let allowed = signers_config(self.allowed_signers.as_deref());
+                let commits = git_output(
+                    git_dir,
+                    &[
+                        "-c",
+                        &allowed,
+                        "log",
+                        "--format=%H%x00%G?%x00%s",
+                        &update.new,
+                        "--not",
+                        "--all",
+                    ],
+                    None,
+                )?;
+                violations.extend(check_signatures(&update.name, &commits));
+            }
+            if self.protects(&update.name) {
+                violations.extend(check_owners(
+                    git_dir,
+                    update,
+                    self.owner_signers.as_deref(),
+                )?);
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Whether `ref_name` is a branch matching a `protectedBranch` glob.
+    fn protects(&self, ref_name: &str) -> bool {
+        ref_name.strip_prefix("refs/heads/").is_some_and(|branch| {
+            self.protected_branches
+                .iter()
+                .any(|pattern| glob(pattern.as_bytes(), branch.as_bytes()))
+        })
+    }
+
+    /// Size and path checks on the `(path, size)` of new blobs.
+    fn check_blobs(&self, ref_name: &str, blobs: &[(String, u64)]) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for (path, size) in blobs {
+            if let Some(pattern) = self.forbidden_paths.iter().find(|p| path_matches(p, path)) {
+                violations.push(Violation {
+                    ref_name: ref_name.to_string(),
+                    rule: Rule::ForbiddenPath,
+                    detail: format!("{path} matches {pattern:?}"),
+                });
+            }
+            if let Some(max) = self.max_file_size.filter(|max| size > max) {
+                violations.push(Violation {
+                    ref_name: ref_name.to_string(),
+                    rule: Rule::MaxFileSize,
+                    detail: format!("{path} is {}, limit {}", human_size(*size), human_size(max)),
+                });
+            }
+        }
+        violations
+    }
+}
+
+/// Commits of `git log --format=%H%x00%G?%x00%s` without a good signature.
+/// Only `G` is: `U` is a valid signature from a key nobody vouched for,
+/// which anyone can make.
+fn check_signatures(ref_name: &str, log: &str) -> Vec<Violation> {
+    log.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\0');
+            let (hash, status, subject) = (fields.next()?, fields.next()?, fields.next()?);
+            let why = match status {
+                "G" => return None,
+                "N" => "is not signed",
+                "U" => "is signed by an unknown key",
+                "B" => "has a bad signature",
+                "E" => "has a signature that cannot be checked",
+                "X" | "Y" => "has an expired signature or key",
+                "R" => "is signed by a revoked key",
+                _ => "has no valid signature",
+            };
+            Some(Violation {
+                ref_name: ref_name.to_string(),
+                rule: Rule::SignedCommits,
+                detail: format!("{} {subject:?} {why}", &hash[..hash.len().min(8)]),
+            })
+        })
+        .collect()
+}
+
+/// Where a `CODEOWNERS` file is looked for, in order.
+const CODEOWNERS_PATHS: [&str; 3] = ["CODEOWNERS", ".github/CODEOWNERS", "docs/CODEOWNERS"];
+
+/// Owners of the paths of a repository, from its `CODEOWNERS` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeOwners {
+    /// `(pattern, owners)`, in file order
+    rules: Vec<(String, Vec<String>)>,
+}
+
+impl CodeOwners {
+    /// Parse a `CODEOWNERS` file, skipping blank lines and `#` comments.
+    #[must_use]
+    pub fn parse(text: &str) -> Self {
+        let rules = text
+            .lines()
+            .filter_map(|line| {
+                let line = line.split_once('#').map_or(line, |(rule, _)| rule);
+                let mut words = line.split_whitespace();
+                let pattern = words.next()?.to_string();
+                Some((pattern, words.map(str::to_string).collect()))
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Owners of `path`: the ones of the last matching line, none when no
+    /// line matches.
+    #[must_use]
+    pub fn owners_of(&self, path: &str) -> &[String] {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| owner_pattern_matches(pattern, path))
+            .map_or(&[], |(_, owners)| owners)
+    }
+
+    /// The changed `paths` none of whose owners is among `approvals`,
+    /// grouped by their owners.
+    #[must_use]
+    pub fn unapproved<'a>(
+        &'a self,
+        paths: &'a [String],
+        approvals: &[String],
+    ) -> Vec<(&'a [String], Vec<&'a str>)> {
+        let mut groups: Vec<(&[String], Vec<&str>)> = Vec::new();
+        for path in paths {
+            let owners = self.owners_of(path);
+            let approved = owners
+                .iter()
+                .any(|owner| approvals.iter().any(|approval| approves(approval, owner)));
+            if owners.is_empty() || approved {
+                continue;
+            }
+            match groups.iter_mut().find(|(o, _)| *o == owners) {
+                Some((_, group)) => group.push(path),
+                None => groups.push((owners, vec![path])),
+            }
+        }
+        groups
+    }
+}
+
+/// Paths `update` changes that lack the approval of their owners, with
+/// their keys in the `owner_signers` file, see the module docs.
+fn check_owners(
+    git_dir: &Path,
+    update: &RefUpdate,
+    owner_signers: Option<&Path>,
+) -> Result<Vec<Violation>, RepoError> {
+    // Owners come from `owners_rev`, changes are counted since `since`
+    let (owners_rev, since) = if update.is_create() {
+        match resolve(git_dir, "HEAD")? {
+            Some(default) => {
+                let fork = git_command(git_dir)
+                    .args(["merge-base", &default, &update.new])
+                    .output()?;
+                let fork = String::from_utf8_lossy(&fork.stdout).trim().to_string();
+                (default, (!fork.is_empty()).then_some(fork))
+            }
+            None => (update.new.clone(), None),
+        }
+    } else {
+        (update.old.clone(), Some(update.old.clone()))
+    };
+    let Some(codeowners) = read_codeowners(git_dir, &owners_rev)? else {
+        return Ok(Vec::new());
+    };
+    let changed = match &since {
+        Some(since) => git_output(
+            git_dir,
+            &["diff", "--name-only", "-z", since, &update.new],
+            None,
+        )?,
+        None => git_output(
+            git_dir,
+            &["ls-tree", "-r", "-z", "--name-only", &update.new],
+            None,
+        )?,
+    };
+    let changed: Vec<String> = changed
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect();
+    let approvals = match owner_signers {
+        Some(signers) => approved_by(git_dir, &update.new, signers)?,
+        None => Vec::new(),
+    };
+
+    Ok(codeowners
+        .unapproved(&changed, &approvals)
+        .into_iter()
+        .map(|(owners, paths)| Violation {
+            ref_name: update.name.clone(),
+            rule: Rule::CodeOwners,
+            detail: format!(
+                "{} must approve {} by signing the commit",
+                owners.join(" or "),
+                paths.join(", ")
+            ),
+        })
+        .collect())
+}
+
+/// The commit `rev` names, `None` when it names none, e.g. `HEAD` of a
+/// repository nothing was pushed to yet.
+fn resolve(git_dir: &Path, rev: &str) -> Result<Option<String>, RepoError> {
+    let output = git_command(git_dir)
+        .args([
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{rev}^{{commit}}"),
+        ])
+        .output()?;
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((output.status.success() && !commit.is_empty()).then_some(commit))
+}
+
+/// The `CODEOWNERS` file of the tree of `rev`, if it has one.
+fn read_codeowners(git_dir: &Path, rev: &str) -> Result<Option<CodeOwners>, RepoError> {
+    for path in CODEOWNERS_PATHS {
+        // Fails when the file does not exist in that tree
+        let output = git_command(git_dir)
+            .args(["cat-file", "blob", &format!("{rev}:{path}")])
+            .output()?;
+        if output.status.success() {
+            return Ok(Some(CodeOwners::parse(&String::from_utf8_lossy(
+                &output.stdout,
+            ))));
+        }
+    }
+    Ok(None)
+}
+
+/// The owner whose key in `signers` made a good signature of `rev`, if any.
+fn approved_by(git_dir: &Path, rev: &str, signers: &Path) -> Result<Vec<String>, RepoError> {
+    let allowed = signers_config(Some(signers));
+    let log = git_output(
+        git_dir,
+        &["-c", &allowed, "log", "-1", "--format=%G?%x00%GS", rev],
+        None,
+    )?;
+    Ok(match log.trim_end().split_once('\0') {
+        Some(("G", signer)) if !signer.is_empty() => vec![signer.to_string()],
+        _ => Vec::new(),
+    })
+}
+
+/// `-c` value making git trust the SSH keys of `signers` and no other. It
+/// is passed on the command line, so the repository's config cannot point
+/// git at keys of the pusher's.
+fn signers_config(signers: Option<&Path>) -> String {
+    let signers = signers.unwrap_or(Path::new("/dev/null"));
+    format!("gpg.ssh.allowedSignersFile={}", signers.display())
+}
+
+/// Whether the approval `approval` names `owner`: the whole value, or the
+/// address between `<>`, case-insensitively.
+fn approves(approval: &str, owner: &str) -> bool {
+    let email = approval
+        .split_once('<')
+        .and_then(|(_, rest)| rest.split_once('>'))
+        .map(|(email, _)| email);
+    approval.trim().eq_ignore_ascii_case(owner)
+        || email.is_some_and(|email| email.trim().eq_ignore_ascii_case(owner))
+}
+
+/// Whether the `CODEOWNERS` `pattern` matches `path`, or a directory above
+/// it, see the module docs.
+fn owner_pattern_matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.strip_suffix("/**").unwrap_or(pattern);
+    let (pattern, only_dirs) = match pattern.strip_suffix('/') {
+        Some(dir) => (dir, true),
+        None => (pattern, false),
+    };
+    // `/` alone owns everything
+    if pattern.trim_start_matches('/').is_empty() {
+        return true;
+    }
+    let mut dirs = path.match_indices('/').map(|(end, _)| &path[..end]);
+    (!only_dirs && path_matches(pattern, path)) || dirs.any(|dir| path_matches(pattern, dir))
+}
+
+/// `(object id, path)` of the objects listed by `rev-list --objects` that
+/// have a path, i.e. blobs and trees; commits come without one.
+fn parse_blobs(rev_list: &str) -> Vec<(String, String)> {
+    rev_list
+        .lines()
+        .filter_map(|line| {
+            let (id, path) = line.split_once(' ')?;
+            (!path.is_empty()).then(|| (id.to_string(), path.to_string()))
+        })
+        .collect()
+}
+
+/// `(path, size)` of the blobs among `objects`, through one `cat-file`.
+fn with_sizes(
+    git_dir: &Path,
+    objects: Vec<(String, String)>,
+) -> Result<Vec<(String, u64)>, RepoError> {
+    if objects.is_empty() {
+        return Ok(Vec::new());
+    }
+    let input = objects.iter().fold(String::new(), |mut input, (id, _)| {
+        input.push_str(id);
+        input.push('\n');
+        input
+    });
+    let output = git_output(
+        git_dir,
+        &["cat-file", "--batch-check=%(objecttype) %(objectsize)"],
+        Some(&input),
+    )?;
+    Ok(objects
+        .into_iter()
+        .zip(output.lines())
+        .filter_map(|((_, path), line)| {
+            let size = line.strip_prefix("blob ")?.parse().ok()?;
+            Some((path, size))
+        })
+        .collect())
+}
+
+fn git_command(git_dir: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.arg("--git-dir").arg(git_dir);
+    command
+}
+
+fn git_output(git_dir: &Path, args: &[&str], stdin: Option<&str>) -> Result<String, RepoError> {
+    use std::io::Write as _;
+    use std::process::Stdio;
+
+    let mut child = git_command(git_dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut pipe = child.stdin.take().expect("stdin is piped");
+    if let Some(input) = stdin {
+        pipe.write_all(input.as_bytes())?;
+    }
+    drop(pipe);
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(RepoError::GitError(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Sizes as git config takes them: bytes, or with a `k`, `m` or `g` suffix.
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim().to_ascii_lowercase();
+    let (digits, unit) = match value.chars().last()? {
+        'k' => (&value[..value.len() - 1], 1 << 10),
+        'm' => (&value[..value.len() - 1], 1 << 20),
+        'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value.as_str(), 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn human_size(bytes: u64) -> String {
+    const UNITS: [(&str, u64); 3] = [("GiB", 1 << 30), ("MiB", 1 << 20), ("KiB", 1 << 10)];
+    UNITS.iter().find(|(_, unit)| bytes >= *unit).map_or_else(
+        || format!("{bytes} B"),
+        |(name, unit)| format!("{:.1} {name}", bytes as f64 / *unit as f64),
+    )
+}
+
+/// Whether `path` matches `pattern`, see the module docs.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_start_matches('/');
+    if pattern.contains('/') {
+        glob(pattern.as_bytes(), path.as_bytes())
+    } else {
+        path.rsplit('/')
+            .next()
+            .is_some_and(|name| glob(pattern.as_bytes(), name.as_bytes()))
+    }
+}
+
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob(&pattern[1..], text)
+                || (text.first().is_some_and(|c| *c != b'/') && glob(pattern, &text[1..]))
+        }
+        (Some(b'?'), Some(c)) if *c != b'/' => glob(&pattern[1..], &text[1..]),
+        (Some(p), Some(c)) if p == c => glob(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_are_read_from_git_config() {
+        let entries = "procurator.maxfilesize\n50m\0\
+                       procurator.forbiddenpath\n*.pem\0\
+                       procurator.forbiddenpath\nsecrets/*\0\
+                       procurator.requiresignedcommits\0\
+                       procurator.allowedsigners\n/etc/repohub/signers\0\
+                       procurator.protectedbranch\nrelease/*\0\
+                       procurator.ownersigners\n/etc/repohub/owners\0";
+        let policy = Policy::parse(entries).unwrap();
+        assert_eq!(policy.max_file_size, Some(50 << 20));
+        assert_eq!(policy.forbidden_paths, ["*.pem", "secrets/*"]);
+        assert!(policy.require_signed_commits);
+        assert_eq!(
+            policy.allowed_signers.as_deref(),
+            Some(Path::new("/etc/repohub/signers"))
+        );
+        assert!(policy.protects("refs/heads/release/1.0"));
+        assert!(!policy.protects("refs/heads/main"));
+        assert!(!policy.protects("refs/tags/release/1.0"));
+        assert_eq!(
+            policy.owner_signers.as_deref(),
+            Some(Path::new("/etc/repohub/owners"))
+        );
+        assert!(Policy::parse("procurator.maxfilesize\nhuge\0").is_err());
+        assert!(Policy::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn patterns_match_names_or_whole_paths() {
+        assert!(path_matches("*.pem", "deploy/tls/server.pem"));
+        assert!(path_matches(".env", ".env"));
+        assert!(path_matches(".env", "app/.env"));
+        assert!(!path_matches(".env", "app/.envrc"));
+        assert!(path_matches("secrets/*", "secrets/db"));
+        assert!(!path_matches("secrets/*", "secrets/db/password"));
+        assert!(!path_matches("secrets/*", "app/secrets/db"));
+        assert!(path_matches("id_?sa", "home/id_rsa"));
+    }
+
+    #[test]
+    fn unsigned_commits_are_reported() {
+        let log = "aaaaaaaaaaaa\0G\0signed\n\
+                   bbbbbbbbbbbb\0N\0wip\n\
+                   cccccccccccc\0B\0tampered\n\
+                   dddddddddddd\0U\0self-signed\n";
+        let violations = check_signatures("refs/heads/main", log);
+        assert_eq!(violations.len(), 3);
+        assert_eq!(
+            violations[0].to_string(),
+            r#"refs/heads/main [signed-commits] bbbbbbbb "wip" is not signed"#
+        );
+        assert_eq!(
+            violations[2].to_string(),
+            r#"refs/heads/main [signed-commits] dddddddd "self-signed" is signed by an unknown key"#
+        );
+    }
+
+    #[test]
+    fn last_matching_codeowners_line_wins() {
+        let owners = CodeOwners::parse(
+            "# everything\n\
+             *          @lead\n\
+             /nix/      @ops ops@example.com\n\
+             *.md       @docs  # prose\n\
+             vendor/\n",
+        );
+        assert_eq!(owners.owners_of("src/main.rs"), ["@lead"]);
+        assert_eq!(
+            owners.owners_of("nix/modules/worker.nix"),
+            ["@ops", "ops@example.com"]
+        );
+        assert_eq!(owners.owners_of("nix/README.md"), ["@docs"]);
+        assert!(owners.owners_of("lib/vendor/x/y.c").is_empty());
+        assert!(CodeOwners::parse("nix/ @ops").owners_of("nix").is_empty());
+        assert!(CodeOwners::parse("").owners_of("anything").is_empty());
+    }
+
+    #[test]
+    fn owned_paths_need_an_owner_approval() {
+        let owners = CodeOwners::parse("/nix/ @ops ops@example.com\nsecrets/ @sec\n");
+        let paths = [
+            "nix/a.nix".to_string(),
+            "nix/b.nix".to_string(),
+            "deploy/secrets/db".to_string(),
+            "README.md".to_string(),
+        ];
+        assert_eq!(
+            owners.unapproved(&paths, &[]),
+            [
+                (
+                    &["@ops".to_string(), "ops@example.com".to_string()][..],
+                    vec!["nix/a.nix", "nix/b.nix"]
+                ),
+                (&["@sec".to_string()][..], vec!["deploy/secrets/db"]),
+            ]
+        );
+        let approvals = ["Ops Team <OPS@example.com>".to_string(), "@sec".to_string()];
+        assert!(owners.unapproved(&paths, &approvals).is_empty());
+        assert!(!approves("@security", "@sec"));
+    }
+
+    /// Objects of a commit that no ref points to, as the ones of a push
+    /// waiting in the quarantine.
+    #[test]
+    fn new_blobs_are_checked_against_the_policy() {
+        let dir = std::env::temp_dir().join(format!("pcr-policy-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("tls")).unwrap();
+        let git = |args: &[&str]| git_output(&dir.join(".git"), args, None).unwrap();
+        let status = Command::new("git")
+            .args(["init", "--quiet"])
+            .arg(&dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::write(dir.join("README.md"), "hello").unwrap();
+        std::fs::write(dir.join("tls/server.pem"), "key").unwrap();
+        std::fs::write(dir.join("disk.img"), vec![0u8; 4096]).unwrap();
+        let work = format!("--work-tree={}", dir.display());
+        git(&[&work, "add", "."]);
+        git(&[
+            "-c",
+            "user.name=Test",
+            "-c",
+            "user.email=test@example.com",
+            &work,
+            "commit",
+            "--quiet",
+            "--no-gpg-sign",
+            "-m",
+            "init",
+        ]);
+        let head = git(&["rev-parse", "HEAD"]).trim().to_string();
+        let branch = git(&["symbolic-ref", "HEAD"]).trim().to_string();
+        git(&["update-ref", "-d", &branch]);
+
+        let policy = Policy {
+            max_file_size: Some(1024),
+            forbidden_paths: vec!["*.pem".to_string()],
+            require_signed_commits: true,
+            ..Policy::default()
+        };
+        let update = RefUpdate::parse(&format!("{ZERO_REV} {head} refs/heads/main")).unwrap();
+        let violations = policy.check(&dir.join(".git"), &[update]).unwrap();
+        let rules: Vec<_> = violations
+            .iter()
+            .map(|v| (v.rule, v.detail.as_str()))
+            .collect();
+        assert_eq!(rules.len(), 3, "{violations:?}");
+        assert!(rules.contains(&(Rule::MaxFileSize, "disk.img is 4.0 KiB, limit 1.0 KiB")));
+        assert!(rules.contains(&(Rule::ForbiddenPath, r#"tls/server.pem matches "*.pem""#)));
+        assert!(rules.iter().any(|(rule, _)| *rule == Rule::SignedCommits));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn protected_branches_check_the_owners_of_changed_paths() {
+        let dir = std::env::temp_dir().join(format!("pcr-owners-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("nix")).unwrap();
+        let git = |args: &[&str]| git_output(&dir.join(".git"), args, None).unwrap();
+        let status = Command::new("git")
+            .args(["init", "--quiet"])
+            .arg(&dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let work = format!("--work-tree={}", dir.display());
+        // Keys of the owner and of someone else, only the owner's trusted
+        let keys = std::env::temp_dir().join(format!("pcr-owner-keys-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&keys);
+        std::fs::create_dir_all(&keys).unwrap();
+        for name in ["ops", "intruder"] {
+            let status = Command::new("ssh-keygen")
+                .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+                .arg(keys.join(name))
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        let ops_key = std::fs::read_to_string(keys.join("ops.pub")).unwrap();
+        std::fs::write(keys.join("allowed_signers"), format!("@ops {ops_key}")).unwrap();
+        let commit = |message: &str, key: Option<&str>| {
+            git(&[&work, "add", "."]);
+            let signing = match key {
+                Some(key) => format!("-S{}", keys.join(key).display()),
+                None => "--no-gpg-sign".to_string(),
+            };
+            git(&[
+                "-c",
+                "user.name=Test",
+                "-c",
+                "user.email=test@example.com",
+                "-c",
+                "gpg.format=ssh",
+                &work,
+                "commit",
+                "--quiet",
+                &signing,
+                "-m",
+                message,
+            ]);
+            git(&["rev-parse", "HEAD"]).trim().to_string()
+        };
+        std::fs::write(dir.join("CODEOWNERS"), "/nix/ @ops\n").unwrap();
+        std::fs::write(dir.join("nix/a.nix"), "{}").unwrap();
+        let base = commit("init", None);
+        std::fs::write(dir.join("nix/a.nix"), "{ a = 1; }").unwrap();
+        let unapproved = commit("change a", None);
+        std::fs::write(dir.join("nix/a.nix"), "{ a = 2; }").unwrap();
+        let forged = commit("change a again\n\nApproved-by: @ops", Some("intruder"));
+        std::fs::write(dir.join("nix/a.nix"), "{ a = 3; }").unwrap();
+        let approved = commit("change a once more", Some("ops"));
+
+        let policy = Policy {
+            protected_branches: vec!["main".to_string(), "release/*".to_string()],
+            owner_signers: Some(keys.join("allowed_signers")),
+            ..Policy::default()
+        };
+        let check = |new: &str| {
+            let update = RefUpdate::parse(&format!("{base} {new} refs/heads/main")).unwrap();
+            policy.check(&dir.join(".git"), &[update]).unwrap()
+        };
+        let violations = check(&unapproved);
+        assert_eq!(violations.len(), 1, "{violations:?}");
+        assert_eq!(
+            violations[0].to_string(),
+            "refs/heads/main [code-owners] @ops must approve nix/a.nix by signing the commit"
+        );
+        assert_eq!(check(&forged).len(), 1);
+        assert!(check(&approved).is_empty());
+        let unset = Policy {
+            owner_signers: None,
+            ..policy.clone()
+        };
+        let update = RefUpdate::parse(&format!("{base} {approved} refs/heads/main")).unwrap();
+        assert_eq!(unset.check(&dir.join(".git"), &[update]).unwrap().len(), 1);
+
+        // A new protected branch answers to the default branch's owners,
+        // even when it drops the CODEOWNERS file
+        std::fs::remove_file(dir.join("CODEOWNERS")).unwrap();
+        std::fs::write(dir.join("nix/a.nix"), "{ a = 4; }").unwrap();
+        git(&[&work, "add", "-A"]);
+        let tree = git(&["write-tree"]).trim().to_string();
+        let dropped = git(&[
+            "-c",
+            "user.name=Test",
+            "-c",
+            "user.email=test@example.com",
+            "commit-tree",
+            &tree,
+            "-p",
+            &base,
+            "-m",
+            "drop the owners",
+        ])
+        .trim()
+        .to_string();
+        let create = |new: &str| {
+            let update =
+                RefUpdate::parse(&format!("{ZERO_REV} {new} refs/heads/release/x")).unwrap();
+            policy.check(&dir.join(".git"), &[update]).unwrap()
+        };
+        let violations = create(&dropped);
+        assert_eq!(violations.len(), 1, "{violations:?}");
+        assert_eq!(
+            violations[0].to_string(),
+            "refs/heads/release/x [code-owners] @ops must approve nix/a.nix by signing the commit"
+        );
+        assert!(create(&approved).is_empty());
+        let other = RefUpdate::parse(&format!("{base} {unapproved} refs/heads/dev")).unwrap();
+        assert!(
+            policy
+                .check(&dir.join(".git"), &[other])
+                .unwrap()
+                .is_empty()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&keys).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+//! `pre-receive` hook enforcing the repository's policies
+//!
+//! Git runs it in the bare repository with the ref updates of the push on
+//! stdin, before updating any of them. Everything it writes to stderr is
+//! shown to the pusher; exiting non-zero rejects the whole push. See
+//! `repohub::policy` for the policies and how to configure them.
+
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use repohub::policy::{Policy, RefUpdate};
+
+fn main() -> ExitCode {
+    let git_dir = PathBuf::from(std::env::var_os("GIT_DIR").unwrap_or_else(|| ".".into()));
+
+    // Fail closed: a push is only accepted once its policies were checked
+    let policy = match Policy::load(&git_dir) {
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("✗ push rejected: cannot read the repository policies: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if policy.is_empty() {
+        return ExitCode::SUCCESS;
+    }
+
+    let updates: Vec<RefUpdate> = std::io::stdin()
+        .lock()
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| RefUpdate::parse(&line))
+        .collect();
+
+    match policy.check(&git_dir, &updates) {
+        Ok(violations) if violations.is_empty() => ExitCode::SUCCESS,
+        Ok(violations) => {
+            eprintln!("✗ push rejected by repository policy:");
+            for violation in &violations {
+                eprintln!("  {violation}");
+            }
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("✗ push rejected: policy check failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
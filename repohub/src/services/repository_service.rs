@@ -1,36 +1,25 @@
 use std::path::PathBuf;
-use std::fmt;
-use repo_outils::git::{RepoPath, create_bare_repo, clone_into_bare};
+use repo_outils::git::{
+    GitCredentials, RepoError, RepoPath, clone_into_bare, create_bare_repo, default_branch,
+    fetch_mirror, import_into_bare,
+};
 use repo_outils::nix::{FlakeMetadata, Infrastructure};
 use crate::config::Config;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum RepositoryError {
-    DirectoryCreation(std::io::Error),
-    BareRepoCreation(String),
-    CloneFailed(String),
+    #[error("Failed to create directory")]
+    DirectoryCreation(#[from] std::io::Error),
+    #[error("Failed to create bare repository")]
+    BareRepoCreation(#[source] RepoError),
+    #[error("Failed to clone repository")]
+    CloneFailed(#[source] RepoError),
+    #[error("Failed to fetch the mirror's source")]
+    MirrorFetchFailed(#[source] RepoError),
+    #[error("Invalid repository path")]
     InvalidPath,
 }
 
-impl fmt::Display for RepositoryError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            RepositoryError::DirectoryCreation(e) => write!(f, "Failed to create directory: {}", e),
-            RepositoryError::BareRepoCreation(msg) => write!(f, "Failed to create bare repository: {}", msg),
-            RepositoryError::CloneFailed(msg) => write!(f, "Failed to clone repository: {}", msg),
-            RepositoryError::InvalidPath => write!(f, "Invalid repository path"),
-        }
-    }
-}
-
-impl std::error::Error for RepositoryError {}
-
-impl From<std::io::Error> for RepositoryError {
-    fn from(err: std::io::Error) -> Self {
-        RepositoryError::DirectoryCreation(err)
-    }
-}
-
 #[derive(Clone)]
 pub struct RepositoryService {
     repos_base_path: PathBuf,
@@ -58,7 +47,7 @@ impl RepositoryService {
         let git_url_to_store = if let Some(remote_url) = git_url {
             // Clone from remote
             clone_into_bare(&bare_path, remote_url)
-                .map_err(|e| RepositoryError::CloneFailed(e.to_string()))?;
+                .map_err(RepositoryError::CloneFailed)?;
 
             tracing::info!(
                 remote = remote_url,
@@ -73,7 +62,7 @@ impl RepositoryService {
                 .map_err(RepositoryError::DirectoryCreation)?;
 
             create_bare_repo(&bare_path)
-                .map_err(|e| RepositoryError::BareRepoCreation(e.to_string()))?;
+                .map_err(RepositoryError::BareRepoCreation)?;
 
             tracing::info!(path = %bare_path.display(), "Created bare repository");
 
@@ -83,6 +72,59 @@ impl RepositoryService {
         Ok(git_url_to_store)
     }
 
+    /// Import `source_url` with its branches and tags, as a mirror of it or
+    /// standing on its own. Returns the repository's URL here and the
+    /// source's default branch.
+    ///
+    /// # Errors
+    ///
+    /// - `CloneFailed` if the repository exists or the source can't be
+    ///   cloned
+    pub fn import_repository(
+        &self,
+        username: &str,
+        repo_name: &str,
+        source_url: &str,
+        credentials: Option<&GitCredentials>,
+        mirror: bool,
+    ) -> Result<(String, Option<String>), RepositoryError> {
+        let repo_path = RepoPath::new(&self.repos_base_path, username, repo_name);
+        let bare_path = repo_path.bare_repo_path();
+
+        import_into_bare(&bare_path, source_url, credentials, mirror)
+            .map_err(RepositoryError::CloneFailed)?;
+        let branch = default_branch(&bare_path).map_err(RepositoryError::CloneFailed)?;
+
+        tracing::info!(
+            source = repo_outils::git::strip_credentials(source_url),
+            path = %bare_path.display(),
+            mirror,
+            ?branch,
+            "Imported repository"
+        );
+
+        Ok((repo_path.to_ssh_url(&self.domain), branch))
+    }
+
+    /// Fetch a mirrored import from its source
+    ///
+    /// # Errors
+    ///
+    /// - `MirrorFetchFailed` if the repository is not a mirror or the source
+    ///   can't be fetched
+    pub fn sync_mirror(
+        &self,
+        username: &str,
+        repo_name: &str,
+        credentials: Option<&GitCredentials>,
+    ) -> Result<(), RepositoryError> {
+        let repo_path = RepoPath::new(&self.repos_base_path, username, repo_name);
+        fetch_mirror(&repo_path.bare_repo_path(), credentials)
+            .map_err(RepositoryError::MirrorFetchFailed)?;
+        tracing::info!(repo = %repo_path, "Fetched mirror");
+        Ok(())
+    }
+
     /// Parse flake metadata for a repository (best-effort)
     pub fn parse_flake_metadata(
         &self,
@@ -0,0 +1,75 @@
+//! API tokens
+//!
+//! A token is 32 random bytes, hex encoded after an `rh_` prefix so it is
+//! recognizable in configs and logs. It is shown once, when created: only
+//! its SHA-256 is stored. Other services, like the CI, send the token of
+//! their user to `GET /api/v1/access` to learn what that user may see.
+
+use std::{fmt::Write, io::Read};
+
+use axum::http::{HeaderMap, header};
+use sha2::{Digest, Sha256};
+
+const PREFIX: &str = "rh_";
+
+/// A new random token.
+///
+/// # Errors
+///
+/// - if `/dev/urandom` cannot be read
+pub fn generate() -> std::io::Result<String> {
+    let mut bytes = [0u8; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(format!("{PREFIX}{}", hex(&bytes)))
+}
+
+/// What is stored of `token`.
+#[must_use]
+pub fn hash(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))
+}
+
+/// Token of an `Authorization: Bearer` header.
+#[must_use]
+pub fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, b| {
+        let _ = write!(out, "{b:02x}");
+        out
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_random_and_stored_hashed() {
+        let token = generate().unwrap();
+        assert!(token.starts_with("rh_"));
+        assert_eq!(token.len(), 3 + 64);
+        assert_ne!(token, generate().unwrap());
+        assert_eq!(
+            hash("rh_abc"),
+            "0255473dabb6ed31be738f4488295eb94acaf8e04772fd6fe268642db25b1160"
+        );
+    }
+
+    #[test]
+    fn bearer_tokens_are_read_from_the_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer(&headers), None);
+        headers.insert(header::AUTHORIZATION, "Bearer rh_abc".parse().unwrap());
+        assert_eq!(bearer(&headers), Some("rh_abc"));
+        headers.insert(header::AUTHORIZATION, "Basic dXNlcg==".parse().unwrap());
+        assert_eq!(bearer(&headers), None);
+    }
+}
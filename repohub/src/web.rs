@@ -9,11 +9,9 @@ use axum::{
 };
 
 use crate::{
+    api::{self, API_V1},
     database::Database,
-    models::{
-        CreateProjectRequest, CreateRepositoryRequest, CreateUserRequest,
-        Project, Repository, User, SaveConfigurationRequest
-    },
+    models::{Project, Repository, User, SaveConfigurationRequest},
     config::Config,
     services::RepositoryService,
 };
@@ -117,10 +115,11 @@ async fn index(State(state): State<AppState>) -> impl IntoResponse {
             HtmlTemplate(IndexTemplate { users }).into_response()
         }
         Err(e) => {
-            tracing::error!("Failed to list users: {}", e);
+            let error = repo_outils::report(&e);
+            tracing::error!("Failed to list users: {}", error);
             HtmlTemplate(NotImplementedTemplate {
                 feature: "Error".to_string(),
-                description: format!("Failed to list users: {}", e),
+                description: format!("Failed to list users: {}", error),
                 back_url: "/".to_string(),
             })
             .into_response()
@@ -128,38 +127,17 @@ async fn index(State(state): State<AppState>) -> impl IntoResponse {
     }
 }
 
-/// Create a new user
-async fn create_user(
-    State(state): State<AppState>,
-    Json(req): Json<CreateUserRequest>,
-) -> impl IntoResponse {
-    match state
-        .db
-        .create_user(&req.username, req.email.as_deref())
-        .await
-    {
-        Ok(id) => {
-            tracing::info!(user_id = id, username = req.username, "User created");
-            (StatusCode::CREATED, Json(serde_json::json!({ "id": id }))).into_response()
-        }
-        Err(e) => {
-            tracing::error!("Failed to create user: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to create user: {}", e),
-            )
-                .into_response()
-        }
-    }
-}
-
 /// The main page of a given user. We'll fetch users by username and show projects related to that user
 async fn user(State(state): State<AppState>, Path(username): Path<String>) -> impl IntoResponse {
     // Get user
     let user = match state.db.get_user_by_username(&username).await {
         Ok(user) => User::from(user),
         Err(e) => {
-            tracing::error!("Failed to get user '{}': {}", username, e);
+            tracing::error!(
+                "Failed to get user '{}': {}",
+                username,
+                repo_outils::report(&e)
+            );
             return HtmlTemplate(NotImplementedTemplate {
                 feature: "User Not Found".to_string(),
                 description: format!("User '{}' not found", username),
@@ -176,10 +154,11 @@ async fn user(State(state): State<AppState>, Path(username): Path<String>) -> im
             HtmlTemplate(UserTemplate { user, projects }).into_response()
         }
         Err(e) => {
-            tracing::error!("Failed to list projects for user '{}': {}", username, e);
+            let error = repo_outils::report(&e);
+            tracing::error!("Failed to list projects for user '{}': {}", username, error);
             HtmlTemplate(NotImplementedTemplate {
                 feature: "Error".to_string(),
-                description: format!("Failed to list projects: {}", e),
+                description: format!("Failed to list projects: {}", error),
                 back_url: "/".to_string(),
             })
             .into_response()
@@ -187,45 +166,6 @@ async fn user(State(state): State<AppState>, Path(username): Path<String>) -> im
     }
 }
 
-/// Create a new project for a user
-async fn create_project(
-    State(state): State<AppState>,
-    Path(username): Path<String>,
-    Json(req): Json<CreateProjectRequest>,
-) -> impl IntoResponse {
-    // Get user first
-    let user = match state.db.get_user_by_username(&username).await {
-        Ok(user) => user,
-        Err(e) => {
-            return (StatusCode::NOT_FOUND, format!("User not found: {}", e)).into_response();
-        }
-    };
-
-    match state
-        .db
-        .create_project(&req.name, user.id, req.description.as_deref())
-        .await
-    {
-        Ok(id) => {
-            tracing::info!(
-                project_id = id,
-                owner = username,
-                project_name = req.name,
-                "Project created"
-            );
-            (StatusCode::CREATED, Json(serde_json::json!({ "id": id }))).into_response()
-        }
-        Err(e) => {
-            tracing::error!("Failed to create project: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to create project: {}", e),
-            )
-                .into_response()
-        }
-    }
-}
-
 /// The main page of a given project. We'll drift from traditional repositories hub. Instead of having a per repo page, we are going to have a project.
 /// A project can be one or many repositories, think of it like all the repos an org might have.
 /// We should also find a config section in this project that would allow us to link all the repos together, add external dependencies (such as databases, proxies, etc...)
@@ -273,10 +213,11 @@ async fn project(
             .into_response()
         }
         Err(e) => {
-            tracing::error!("Failed to list repositories: {}", e);
+            let error = repo_outils::report(&e);
+            tracing::error!("Failed to list repositories: {}", error);
             HtmlTemplate(NotImplementedTemplate {
                 feature: "Error".to_string(),
-                description: format!("Failed to list repositories: {}", e),
+                description: format!("Failed to list repositories: {}", error),
                 back_url: format!("/{}", username),
             })
             .into_response()
@@ -284,71 +225,6 @@ async fn project(
     }
 }
 
-/// Create a new repository in a project
-async fn create_repository(
-    State(state): State<AppState>,
-    Path((username, project_name)): Path<(String, String)>,
-    Json(req): Json<CreateRepositoryRequest>,
-) -> impl IntoResponse {
-    // Get user
-    let user = match state.db.get_user_by_username(&username).await {
-        Ok(user) => user,
-        Err(e) => {
-            return (StatusCode::NOT_FOUND, format!("User not found: {}", e)).into_response();
-        }
-    };
-
-    // Get project
-    let project = match state.db.get_project(user.id, &project_name).await {
-        Ok(project) => project,
-        Err(e) => {
-            return (StatusCode::NOT_FOUND, format!("Project not found: {}", e)).into_response();
-        }
-    };
-
-    // Use the repository service to create or clone the repository
-    let git_url = match state.repo_service.create_or_clone_repository(
-        &username,
-        &req.name,
-        req.git_url.as_deref(),
-    ) {
-        Ok(url) => url,
-        Err(e) => {
-            tracing::error!(error = ?e, "Failed to create/clone repository");
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to create repository: {}", e),
-            )
-            .into_response();
-        }
-    };
-
-    // Persist repository in DB
-    match state
-        .db
-        .create_repository(project.id, &req.name, &git_url)
-        .await
-    {
-        Ok(id) => {
-            tracing::info!(
-                repo_id = id,
-                project = project_name,
-                repo_name = req.name,
-                "Repository created"
-            );
-            (StatusCode::CREATED, Json(serde_json::json!({ "id": id }))).into_response()
-        }
-        Err(e) => {
-            tracing::error!("Failed to create repository: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to create repository: {}", e),
-            )
-                .into_response()
-        }
-    }
-}
-
 /// TODO: find a better name
 /// The idea for this view is to have a way to manage the configuration, infrastructure and everything needed to run all the services.
 /// I would like to have a separate repo, which the user doesn't need to create, that will have all the settings together, maybe even the docs.
@@ -519,12 +395,14 @@ async fn repo(
 
 pub fn routes() -> Router<AppState> {
     Router::new()
+        .nest(API_V1, api::api_routes())
+        .merge(api::docs())
         .route("/", get(index))
-        .route("/users", post(create_user))
+        .route("/users", post(api::create_user))
         .route("/{username}", get(user))
-        .route("/{username}/projects", post(create_project))
+        .route("/{username}/projects", post(api::create_project))
         .route("/{username}/{project}", get(project))
-        .route("/{username}/{project}/repositories", post(create_repository))
+        .route("/{username}/{project}/repositories", post(api::create_repository))
         .route("/{username}/{project}/testing", get(testing))
         .route("/{username}/{project}/configuration", get(configuration).post(save_configuration))
         .route("/{username}/{project}/{repo}", get(repo))
@@ -0,0 +1,468 @@
+//! Typed client for the repohub JSON API (`/api/v1`)
+//!
+//! Used by everything that talks to repohub over HTTP instead of
+//! hand-rolling requests. The wire types live in [`types`] and are the ones
+//! repohub serves, see its spec at `/api/v1/openapi.json`.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), repohub_client::ClientError> {
+//! let repohub = repohub_client::Client::new("http://localhost:3001")?;
+//! for repo in repohub.list_repositories("lucas", "homelab").await? {
+//!     println!("{} {}", repo.name, repo.git_url);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod types;
+
+use reqwest::{Method, Url};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+pub use types::{
+    Access, CiSettings, CreateProjectRequest, CreateRepositoryRequest, CreateTokenRequest,
+    CreateUserRequest, Created, CreatedToken, ImportRepositoryRequest, ImportedRepository, Project,
+    Repository, RepositoryAccess, SourceCredentials, SyncMirrorRequest, User,
+};
+
+/// Prefix of the API, relative to the base URL of repohub
+pub const API_PREFIX: [&str; 2] = ["api", "v1"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("invalid repohub URL {0:?}")]
+    InvalidUrl(String),
+    #[error("request to repohub failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// Repohub answered with an error status, `body` is its message
+    #[error("repohub answered {status}: {body}")]
+    Status { status: u16, body: String },
+}
+
+impl ClientError {
+    /// The user, project or repository does not exist.
+    #[must_use]
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ClientError::Status { status: 404, .. })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base: Url,
+}
+
+impl Client {
+    /// Client of the repohub served at `base_url`, e.g. `http://localhost:3001`.
+    ///
+    /// # Errors
+    ///
+    /// - if `base_url` is not an absolute http(s) URL
+    pub fn new(base_url: &str) -> Result<Self, ClientError> {
+        Self::with_http(reqwest::Client::new(), base_url)
+    }
+
+    /// Same as [`Client::new`], sending requests with `http`, e.g. one with
+    /// timeouts set.
+    ///
+    /// # Errors
+    ///
+    /// - if `base_url` is not an absolute http(s) URL
+    pub fn with_http(http: reqwest::Client, base_url: &str) -> Result<Self, ClientError> {
+        let base = Url::parse(base_url)
+            .ok()
+            .filter(|url| !url.cannot_be_a_base() && url.scheme().starts_with("http"))
+            .ok_or_else(|| ClientError::InvalidUrl(base_url.to_string()))?;
+        Ok(Self { http, base })
+    }
+
+    /// # Errors
+    ///
+    /// - see [`ClientError`]
+    pub async fn list_users(&self) -> Result<Vec<User>, ClientError> {
+        self.send(Method::GET, &["users"], None::<&()>).await
+    }
+
+    /// # Errors
+    ///
+    /// - see [`ClientError`]
+    pub async fn create_user(&self, req: &CreateUserRequest) -> Result<Created, ClientError> {
+        self.send(Method::POST, &["users"], Some(req)).await
+    }
+
+    /// Projects owned by `username`.
+    ///
+    /// # Errors
+    ///
+    /// - see [`ClientError`]
+    pub async fn list_projects(&self, username: &str) -> Result<Vec<Project>, ClientError> {
+        self.send(Method::GET, &["users", username, "projects"], None::<&()>)
+            .await
+    }
+
+    /// # Errors
+    ///
+    /// - see [`ClientError`]
+    pub async fn create_project(
+        &self,
+        username: &str,
+        req: &CreateProjectRequest,
+    ) -> Result<Created, ClientError> {
+        self.send(Method::POST, &["users", username, "projects"], Some(req))
+            .await
+    }
+
+    /// # Errors
+    ///
+    /// - see [`ClientError`]
+    pub async fn list_repositories(
+        &self,
+        username: &str,
+        project: &str,
+    ) -> Result<Vec<Repository>, ClientError> {
+        let path = ["users", username, "projects", project, "repositories"];
+        self.send(Method::GET, &path, None::<&()>).await
+    }
+
+    /// # Errors
+    ///
+    /// - see [`ClientError`]
+    pub async fn get_repository(
+        &self,
+        username: &str,
+        project: &str,
+        repo: &str,
+    ) -> Result<Repository, ClientError> {
+        let path = ["users", username, "projects", project, "repositories", repo];
+        self.send(Method::GET, &path, None::<&()>).await
+    }
+
+    /// Create an empty repository, or clone `req.git_url`.
+    ///
+    /// # Errors
+    ///
+    /// - see [`ClientError`]
+    pub async fn create_repository(
+        &self,
+        username: &str,
+        project: &str,
+        req: &CreateRepositoryRequest,
+    ) -> Result<Created, ClientError> {
+        let path = ["users", username, "projects", project, "repositories"];
+        self.send(Method::POST, &path, Some(req)).await
+    }
+
+    /// Import `req.source_url`, with its branches and tags, as a repository
+    /// of the project.
+    ///
+    /// # Errors
+    ///
+    /// - see [`ClientError`], with status 409 when the repository exists
+    ///   and 502 when the source can't be cloned
+    pub async fn import_repository(
+        &self,
+        username: &str,
+        project: &str,
+        req: &ImportRepositoryRequest,
+    ) -> Result<ImportedRepository, ClientError> {
+        let path = ["users", username, "projects", project, "imports"];
+        self.send(Method::POST, &path, Some(req)).await
+    }
+
+    /// Fetch the mirror at `owner/repo` from its source.
+    ///
+    /// # Errors
+    ///
+    /// - see [`ClientError`], with status 409 when the repository is not a
+    ///   mirror and 502 when the source can't be fetched
+    pub async fn sync_mirror(
+        &self,
+        owner: &str,
+        repo: &str,
+        req: &SyncMirrorRequest,
+    ) -> Result<Repository, ClientError> {
+        self.send(Method::POST, &["repos", owner, repo, "mirror"], Some(req))
+            .await
+    }
+
+    /// CI settings of a project, the defaults when it has none.
+    ///
+    /// # Errors
+    ///
+    /// - see [`ClientError`]
+    pub async fn project_ci_settings(
+        &self,
+        username: &str,
+        project: &str,
+    ) -> Result<CiSettings, ClientError> {
+        let path = ["users", username, "projects", project, "ci"];
+        self.send(Method::GET, &path, None::<&()>).await
+    }
+
+    /// Set the CI settings of a project, inherited by its repositories.
+    ///
+    /// # Errors
+    ///
+    /// - see [`ClientError`], with status 400 for an unknown check
+    pub async fn set_project_ci_settings(
+        &self,
+        username: &str,
+        project: &str,
+        settings: &CiSettings,
+    ) -> Result<CiSettings, ClientError> {
+        let path = ["users", username, "projects", project, "ci"];
+        self.send(Method::PUT, &path, Some(settings)).await
+    }
+
+    /// CI settings that apply to the repository at `owner/repo` on the git
+    /// server: its own, or else its project's.
+    ///
+    /// # Errors
+    ///
+    /// - see [`ClientError`]
+    pub async fn ci_settings(&self, owner: &str, repo: &str) -> Result<CiSettings, ClientError> {
+        self.send(Method::GET, &["repos", owner, repo, "ci"], None::<&()>)
+            .await
+    }
+
+    /// Override the CI settings of the repository at `owner/repo`, or go
+    /// back to its project's with `None`. Returns the settings that apply.
+    ///
+    /// # Errors
+    ///
+    /// - see [`ClientError`], with status 400 for an unknown check
+    pub async fn set_ci_settings(
+        &self,
+        owner: &str,
+        repo: &str,
+        settings: Option<&CiSettings>,
+    ) -> Result<CiSettings, ClientError> {
+        let path = ["repos", owner, repo, "ci"];
+        match settings {
+            Some(settings) => self.send(Method::PUT, &path, Some(settings)).await,
+            None => self.send(Method::DELETE, &path, None::<&()>).await,
+        }
+    }
+
+    /// Create an API token for `username`.
+    ///
+    /// # Errors
+    ///
+    /// - see [`ClientError`]
+    pub async fn create_token(
+        &self,
+        username: &str,
+        req: &CreateTokenRequest,
+    ) -> Result<CreatedToken, ClientError> {
+        self.send(Method::POST, &["users", username, "tokens"], Some(req))
+            .await
+    }
+
+    /// What the bearer of `token` may see and do, or anyone when `None`.
+    ///
+    /// # Errors
+    ///
+    /// - see [`ClientError`], with status 401 when `token` is not valid
+    pub async fn access(&self, token: Option<&str>) -> Result<Access, ClientError> {
+        self.send_as(Method::GET, &["access"], None::<&()>, token)
+            .await
+    }
+
+    /// URL of `segments` under the API prefix, each segment escaped.
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("checked in Client::new")
+            .pop_if_empty()
+            .extend(API_PREFIX)
+            .extend(segments);
+        url
+    }
+
+    async fn send<B: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        segments: &[&str],
+        body: Option<&B>,
+    ) -> Result<T, ClientError> {
+        self.send_as(method, segments, body, None).await
+    }
+
+    /// Same as `send`, authenticated with `token` when set.
+    async fn send_as<B: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        segments: &[&str],
+        body: Option<&B>,
+        token: Option<&str>,
+    ) -> Result<T, ClientError> {
+        let mut request = self.http.request(method, self.url(segments));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ClientError::Status {
+                status: status.as_u16(),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+        Ok(response.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::extract::Path;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{Json, Router};
+
+    fn repository(name: &str) -> Repository {
+        Repository {
+            id: 1,
+            project_id: 2,
+            name: name.to_string(),
+            git_url: format!("git@homelab:lucas/{name}.git"),
+            private: false,
+            mirror_url: None,
+            created_at: "2026-01-01 00:00:00".to_string(),
+        }
+    }
+
+    async fn serve(app: Router) -> Client {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Client::new(&format!("http://{address}/")).unwrap()
+    }
+
+    #[test]
+    fn segments_are_escaped_under_the_prefix() {
+        let client = Client::new("http://repohub:3001/hub").unwrap();
+        assert_eq!(
+            client.url(&["users", "a b/c", "projects"]).as_str(),
+            "http://repohub:3001/hub/api/v1/users/a%20b%2Fc/projects"
+        );
+        assert!(matches!(
+            Client::new("repohub:3001"),
+            Err(ClientError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn access_matches_bare_paths_and_repo_paths() {
+        let access = Access {
+            user: None,
+            repositories: vec![RepositoryAccess {
+                path: "lucas/api".to_string(),
+                private: true,
+                write: false,
+            }],
+        };
+        assert!(access.repository("lucas/api").is_some());
+        assert!(access.repository("/srv/git/lucas/api.git").is_some());
+        assert!(access.repository("/srv/git/xlucas/api.git").is_none());
+        assert!(access.repository("/srv/git/lucas/api-v2.git").is_none());
+        assert!(access.repository("lucas/api.git").is_none());
+    }
+
+    #[test]
+    fn ci_settings_pick_the_branches_to_build() {
+        let every = CiSettings::default();
+        assert!(every.builds("feature/x"));
+
+        let settings: CiSettings =
+            serde_json::from_str(r#"{"default_branch": "main", "branches": ["release/*"]}"#)
+                .unwrap();
+        assert!(settings.enabled);
+        assert!(settings.builds("main"));
+        assert!(settings.builds("release/1.2"));
+        assert!(!settings.builds("feature/x"));
+
+        let disabled = CiSettings {
+            enabled: false,
+            ..CiSettings::default()
+        };
+        assert!(!disabled.builds("main"));
+    }
+
+    #[tokio::test]
+    async fn tokens_are_sent_as_bearer() {
+        let app = Router::new().route(
+            "/api/v1/access",
+            get(|headers: axum::http::HeaderMap| async move {
+                match headers.get("authorization").map(|v| v.to_str().unwrap()) {
+                    Some("Bearer rh_good") | None => Ok(Json(Access {
+                        user: None,
+                        repositories: Vec::new(),
+                    })),
+                    Some(_) => Err((StatusCode::UNAUTHORIZED, "Invalid token")),
+                }
+            }),
+        );
+        let client = serve(app).await;
+        assert!(client.access(None).await.is_ok());
+        assert!(client.access(Some("rh_good")).await.is_ok());
+        let err = client.access(Some("rh_bad")).await.unwrap_err();
+        assert!(matches!(err, ClientError::Status { status: 401, .. }));
+    }
+
+    #[tokio::test]
+    async fn requests_round_trip_through_the_wire_types() {
+        let app = Router::new()
+            .route(
+                "/api/v1/users/{username}/projects/{project}/repositories",
+                get(|| async { Json(vec![repository("app")]) }).post(
+                    |Json(req): Json<CreateRepositoryRequest>| async move {
+                        assert_eq!(req.name, "infra");
+                        (StatusCode::CREATED, Json(Created { id: 7 }))
+                    },
+                ),
+            )
+            .route(
+                "/api/v1/users/{username}/projects/{project}/repositories/{repo}",
+                get(
+                    |Path((_, _, repo)): Path<(String, String, String)>| async move {
+                        (
+                            StatusCode::NOT_FOUND,
+                            format!("Repository not found: {repo}"),
+                        )
+                    },
+                ),
+            );
+        let client = serve(app).await;
+
+        let repos = client.list_repositories("lucas", "homelab").await.unwrap();
+        assert_eq!(repos, [repository("app")]);
+
+        let req = CreateRepositoryRequest {
+            name: "infra".to_string(),
+            git_url: None,
+            private: false,
+        };
+        let created = client
+            .create_repository("lucas", "homelab", &req)
+            .await
+            .unwrap();
+        assert_eq!(created, Created { id: 7 });
+
+        let err = client
+            .get_repository("lucas", "homelab", "gone")
+            .await
+            .unwrap_err();
+        assert!(err.is_not_found());
+        assert_eq!(
+            err.to_string(),
+            "repohub answered 404: Repository not found: gone"
+        );
+    }
+}
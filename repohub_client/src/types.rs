@@ -0,0 +1,267 @@
+//! Wire types of the repohub JSON API
+//!
+//! Repohub serves these types as they are, so the client and the server
+//! cannot drift apart. With the `schema` feature they also describe
+//! themselves in repohub's API spec.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub email: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct Project {
+    pub id: i64,
+    pub name: String,
+    pub owner_id: i64,
+    pub description: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct Repository {
+    pub id: i64,
+    pub project_id: i64,
+    pub name: String,
+    /// Where to clone it from
+    pub git_url: String,
+    /// Only visible to the project's owner and members
+    #[serde(default)]
+    pub private: bool,
+    /// Source the repository mirrors, see [`ImportRepositoryRequest::mirror`]
+    #[serde(default)]
+    pub mirror_url: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct CreateUserRequest {
+    pub username: String,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct CreateProjectRequest {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct CreateRepositoryRequest {
+    pub name: String,
+    /// Clone this repository instead of creating an empty one
+    pub git_url: Option<String>,
+    /// Only visible to the project's owner and members
+    #[serde(default)]
+    pub private: bool,
+}
+
+/// Move a repository hosted elsewhere, e.g. on GitHub or GitLab, onto
+/// repohub with its branches and tags
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct ImportRepositoryRequest {
+    pub name: String,
+    /// Where to clone it from, without credentials
+    pub source_url: String,
+    /// For a private source over https
+    #[serde(default)]
+    pub credentials: Option<SourceCredentials>,
+    /// Keep fetching from the source instead of taking over from it
+    #[serde(default)]
+    pub mirror: bool,
+    /// Only visible to the project's owner and members
+    #[serde(default)]
+    pub private: bool,
+    /// CI settings of the repository, its project's when unset; the
+    /// default branch is the source's unless set
+    #[serde(default)]
+    pub ci: Option<CiSettings>,
+}
+
+/// Credentials of a source over https, never stored by repohub
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct SourceCredentials {
+    /// Any non-empty name for a GitHub token, `oauth2` for a GitLab one
+    pub username: String,
+    /// Access token, or password
+    pub token: String,
+}
+
+impl std::fmt::Debug for SourceCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SourceCredentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A repository that was just imported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct ImportedRepository {
+    pub id: i64,
+    /// Where to clone it from now
+    pub git_url: String,
+    /// The source's default branch, `None` for an empty source
+    pub default_branch: Option<String>,
+    /// Source it keeps fetching from, when mirrored
+    pub mirror_url: Option<String>,
+}
+
+/// Fetch a mirror from its source
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct SyncMirrorRequest {
+    /// For a private source over https
+    #[serde(default)]
+    pub credentials: Option<SourceCredentials>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct CreateTokenRequest {
+    /// What the token is for, e.g. `ci`
+    pub name: String,
+}
+
+/// An API token, only ever returned when it is created
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct CreatedToken {
+    pub id: i64,
+    /// Sent as `Authorization: Bearer <token>`
+    pub token: String,
+}
+
+/// What the bearer of a token, or anyone without one, may see and do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct Access {
+    /// Owner of the token, `None` without a token
+    pub user: Option<User>,
+    /// Every public repository, and the private ones of the user's projects
+    pub repositories: Vec<RepositoryAccess>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct RepositoryAccess {
+    /// Path of the bare repository under the repos directory, without
+    /// `.git`, e.g. `lucas/api`
+    pub path: String,
+    pub private: bool,
+    /// Whether the user may act on it, e.g. retry its builds: they own its
+    /// project or are a member other than a `reader`
+    pub write: bool,
+}
+
+impl Access {
+    /// Access to the repository at `repo_path`, either a bare repository's
+    /// path (`/srv/git/lucas/api.git`) or its path under the repos directory
+    /// (`lucas/api`).
+    #[must_use]
+    pub fn repository(&self, repo_path: &str) -> Option<&RepositoryAccess> {
+        self.repositories
+            .iter()
+            .find(|repo| repo.matches(repo_path))
+    }
+}
+
+impl RepositoryAccess {
+    /// Whether `repo_path` is this repository, see [`Access::repository`].
+    #[must_use]
+    pub fn matches(&self, repo_path: &str) -> bool {
+        repo_path == self.path
+            || repo_path
+                .strip_suffix(".git")
+                .and_then(|bare| bare.strip_suffix(self.path.as_str()))
+                .is_some_and(|parent| parent.ends_with('/'))
+    }
+}
+
+/// Id of a user, project or repository that was just created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct Created {
+    pub id: i64,
+}
+
+/// How the CI builds a repository's pushes, set on its project and
+/// overridden per repository
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct CiSettings {
+    /// Build pushes at all
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    /// Main branch, always built
+    #[serde(default)]
+    pub default_branch: Option<String>,
+    /// Branches built besides the default one, names or prefixes ending
+    /// with `*`; every branch when both are unset
+    #[serde(default)]
+    pub branches: Vec<String>,
+    /// CI stages (`scan`, `eval`, `build`) a commit of a built branch needs
+    /// before it may be deployed; the CI's own policy when empty
+    #[serde(default)]
+    pub required_checks: Vec<String>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for CiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            default_branch: None,
+            branches: Vec::new(),
+            required_checks: Vec::new(),
+        }
+    }
+}
+
+impl CiSettings {
+    /// Checks the CI knows about
+    pub const CHECKS: [&str; 3] = ["scan", "eval", "build"];
+
+    /// Branch patterns whose pushes are built, `*` for every branch.
+    #[must_use]
+    pub fn monitored(&self) -> Vec<&str> {
+        if self.default_branch.is_none() && self.branches.is_empty() {
+            return vec!["*"];
+        }
+        self.default_branch
+            .iter()
+            .chain(&self.branches)
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Whether pushes to `branch` are built.
+    #[must_use]
+    pub fn builds(&self, branch: &str) -> bool {
+        self.enabled
+            && self
+                .monitored()
+                .into_iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => branch.starts_with(prefix),
+                    None => pattern == branch,
+                })
+    }
+}
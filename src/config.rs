@@ -0,0 +1,777 @@
+//! Node configuration loading.
+//!
+//! The config is assembled in three layers, later layers winning:
+//!
+//! 1. the config file — `.toml`, `.yaml`/`.yml`, anything else is read as JSON
+//! 2. `PROCURATOR_*` environment variables
+//! 3. command line flags
+//!
+//! All layers are merged into one `serde_json::Value` tree before being turned
+//! into a typed `Config`, so every error can name the exact key path that
+//! caused it (e.g. `role.peers_addr[1]`), regardless of which layer set it.
+//!
+//! Environment variables map to key paths by stripping the prefix, lowercasing
+//! and splitting on `__`: `PROCURATOR_ROLE__MASTER_ADDR` sets `role.master_addr`.
+//! Values are parsed as JSON when possible (`'["10.0.0.1:5000"]'`, `42`) and
+//! taken as plain strings otherwise. Variables whose first segment is not a
+//! config key are ignored, so unrelated `PROCURATOR_*` settings don't clash.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+pub const ENV_PREFIX: &str = "PROCURATOR_";
+
+/// Top-level keys accepted in the config, used to reject typos early.
+const TOP_LEVEL_KEYS: &[&str] = &[
+    "hostname",
+    "addr",
+    "role",
+    "shutdown",
+    "log_level",
+    "cache_urls",
+    "telemetry",
+    "health_addr",
+];
+
+/// Log filter used when `log_level` is not set.
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Role {
+    Master { peers_addr: Vec<SocketAddr> },
+    Worker { master_addr: SocketAddr },
+}
+
+/// Graceful shutdown settings applied on SIGTERM/SIGINT.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShutdownConfig {
+    pub timeout: Duration,
+    /// Workers stop their VMs instead of leaving them running.
+    pub stop_vms: bool,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            stop_vms: false,
+        }
+    }
+}
+
+/// Distributed tracing export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelemetryConfig {
+    /// OTLP/gRPC collector; falls back to `OTEL_EXPORTER_OTLP_ENDPOINT`.
+    pub otlp_endpoint: Option<String>,
+    /// Fraction of new traces that are sampled, between 0 and 1.
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub hostname: String,
+    pub addr: SocketAddr,
+    pub role: Role,
+    pub shutdown: ShutdownConfig,
+    /// `EnvFilter` directives, e.g. `info,worker=debug`. Reloadable.
+    pub log_level: String,
+    /// Binary caches nodes substitute from, in priority order.
+    pub cache_urls: Vec<String>,
+    pub telemetry: TelemetryConfig,
+    /// Where `/healthz` and `/readyz` are served, if anywhere.
+    pub health_addr: Option<SocketAddr>,
+}
+
+// ─── Errors ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("cannot read {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The file is not valid for its format; the source carries line info.
+    #[error("invalid {format} in {}", path.display())]
+    Syntax {
+        path: PathBuf,
+        format: Format,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// A key is missing or holds a value of the wrong shape.
+    #[error("`{key}`: {message}")]
+    Invalid { key: String, message: String },
+    /// A `--set` flag that is not of the form `key.path=value`.
+    #[error("invalid override `{0}`, expected key.path=value")]
+    Override(String),
+}
+
+fn invalid(key: &str, message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.to_string(),
+        message: message.into(),
+    }
+}
+
+// ─── File formats ──────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("yaml" | "yml") => Format::Yaml,
+            _ => Format::Json,
+        }
+    }
+
+    fn parse(self, contents: &str) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            Format::Json => Ok(serde_json::from_str(contents)?),
+            Format::Toml => Ok(toml::from_str(contents)?),
+            Format::Yaml => Ok(serde_yaml_ng::from_str(contents)?),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Json => write!(f, "JSON"),
+            Format::Toml => write!(f, "TOML"),
+            Format::Yaml => write!(f, "YAML"),
+        }
+    }
+}
+
+// ─── Layering ──────────────────────────────────────────────────────────────
+
+/// Read and parse the config file into an untyped tree.
+pub fn read_file(path: &Path) -> Result<Value, ConfigError> {
+    let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let format = Format::from_path(path);
+    format
+        .parse(&contents)
+        .map_err(|source| ConfigError::Syntax {
+            path: path.to_path_buf(),
+            format,
+            source,
+        })
+}
+
+/// Turn `PROCURATOR_*` variables into `(key.path, value)` overrides.
+pub fn env_overrides(vars: impl IntoIterator<Item = (String, String)>) -> Vec<(String, Value)> {
+    vars.into_iter()
+        .filter_map(|(name, raw)| {
+            let rest = name.strip_prefix(ENV_PREFIX)?;
+            let key = rest.to_lowercase().replace("__", ".");
+            let top = key.split('.').next()?;
+            if !TOP_LEVEL_KEYS.contains(&top) {
+                return None;
+            }
+            Some((key, parse_override_value(&raw)))
+        })
+        .collect()
+}
+
+/// Parse a `--set key.path=value` flag.
+pub fn parse_set_flag(arg: &str) -> Result<(String, Value), ConfigError> {
+    match arg.split_once('=') {
+        Some((key, raw)) if !key.is_empty() => Ok((key.to_string(), parse_override_value(raw))),
+        _ => Err(ConfigError::Override(arg.to_string())),
+    }
+}
+
+fn parse_override_value(raw: &str) -> Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+/// Set `value` at the dotted `key` path, creating intermediate tables.
+pub fn apply_override(root: &mut Value, key: &str, value: Value) -> Result<(), ConfigError> {
+    let mut node = root;
+    let mut walked = String::new();
+    let segments: Vec<&str> = key.split('.').collect();
+
+    for (i, segment) in segments.iter().enumerate() {
+        if !walked.is_empty() {
+            walked.push('.');
+        }
+        walked.push_str(segment);
+
+        if !node.is_object() {
+            return Err(invalid(
+                &walked,
+                "cannot override a field of a non-table value",
+            ));
+        }
+        let table = node.as_object_mut().expect("checked above");
+
+        if i + 1 == segments.len() {
+            table.insert((*segment).to_string(), value);
+            return Ok(());
+        }
+        node = table
+            .entry((*segment).to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    Ok(())
+}
+
+// ─── Typed extraction ──────────────────────────────────────────────────────
+
+fn required<'a>(
+    table: &'a Map<String, Value>,
+    parent: &str,
+    name: &str,
+) -> Result<&'a Value, ConfigError> {
+    table
+        .get(name)
+        .ok_or_else(|| invalid(&join(parent, name), "missing required key"))
+}
+
+fn join(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{parent}.{name}")
+    }
+}
+
+fn typed<T: DeserializeOwned>(value: &Value, key: &str, expected: &str) -> Result<T, ConfigError> {
+    serde_json::from_value(value.clone()).map_err(|_| {
+        invalid(
+            key,
+            format!("expected {expected}, found {}", describe(value)),
+        )
+    })
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => format!("boolean {b}"),
+        Value::Number(n) => format!("number {n}"),
+        Value::String(s) => format!("string \"{s}\""),
+        Value::Array(_) => "a list".to_string(),
+        Value::Object(_) => "a table".to_string(),
+    }
+}
+
+fn socket_addr(value: &Value, key: &str) -> Result<SocketAddr, ConfigError> {
+    let raw: String = typed(value, key, "a socket address string")?;
+    raw.parse()
+        .map_err(|e| invalid(key, format!("invalid socket address \"{raw}\": {e}")))
+}
+
+fn table<'a>(value: &'a Value, key: &str) -> Result<&'a Map<String, Value>, ConfigError> {
+    value
+        .as_object()
+        .ok_or_else(|| invalid(key, format!("expected a table, found {}", describe(value))))
+}
+
+fn reject_unknown(
+    table: &Map<String, Value>,
+    parent: &str,
+    known: &[&str],
+) -> Result<(), ConfigError> {
+    match table.keys().find(|k| !known.contains(&k.as_str())) {
+        Some(unknown) => Err(invalid(
+            &join(parent, unknown),
+            format!("unknown key, expected one of: {}", known.join(", ")),
+        )),
+        None => Ok(()),
+    }
+}
+
+impl Role {
+    fn from_value(value: &Value) -> Result<Self, ConfigError> {
+        let role = table(value, "role")?;
+        match (role.get("peers_addr"), role.get("master_addr")) {
+            (Some(peers), None) => {
+                let list: &Vec<Value> = peers.as_array().ok_or_else(|| {
+                    invalid(
+                        "role.peers_addr",
+                        format!("expected a list, found {}", describe(peers)),
+                    )
+                })?;
+                let peers_addr = list
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| socket_addr(v, &format!("role.peers_addr[{i}]")))
+                    .collect::<Result<_, _>>()?;
+                reject_unknown(role, "role", &["peers_addr"])?;
+                Ok(Role::Master { peers_addr })
+            }
+            (None, Some(master)) => {
+                let master_addr = socket_addr(master, "role.master_addr")?;
+                reject_unknown(role, "role", &["master_addr"])?;
+                Ok(Role::Worker { master_addr })
+            }
+            (Some(_), Some(_)) => Err(invalid(
+                "role",
+                "set either `peers_addr` (master) or `master_addr` (worker), not both",
+            )),
+            (None, None) => Err(invalid(
+                "role",
+                "expected `peers_addr` (master) or `master_addr` (worker)",
+            )),
+        }
+    }
+}
+
+impl ShutdownConfig {
+    fn from_value(value: &Value) -> Result<Self, ConfigError> {
+        let shutdown = table(value, "shutdown")?;
+        reject_unknown(shutdown, "shutdown", &["timeout_secs", "stop_vms"])?;
+        let mut config = Self::default();
+        if let Some(secs) = shutdown.get("timeout_secs") {
+            let secs: u64 = typed(secs, "shutdown.timeout_secs", "a number of seconds")?;
+            config.timeout = Duration::from_secs(secs);
+        }
+        if let Some(stop_vms) = shutdown.get("stop_vms") {
+            config.stop_vms = typed(stop_vms, "shutdown.stop_vms", "a boolean")?;
+        }
+        Ok(config)
+    }
+}
+
+impl TelemetryConfig {
+    fn from_value(value: &Value) -> Result<Self, ConfigError> {
+        let telemetry = table(value, "telemetry")?;
+        reject_unknown(telemetry, "telemetry", &["otlp_endpoint", "sample_ratio"])?;
+        let mut config = Self::default();
+        if let Some(endpoint) = telemetry.get("otlp_endpoint") {
+            config.otlp_endpoint =
+                Some(typed(endpoint, "telemetry.otlp_endpoint", "a URL string")?);
+        }
+        if let Some(ratio) = telemetry.get("sample_ratio") {
+            config.sample_ratio =
+                typed(ratio, "telemetry.sample_ratio", "a number between 0 and 1")?;
+        }
+        Ok(config)
+    }
+
+    /// Exporter settings for this node, `OTEL_EXPORTER_OTLP_ENDPOINT` filling
+    /// in for a missing endpoint.
+    pub fn exporter(&self, service_name: &str) -> commands::telemetry::TelemetryConfig {
+        let from_env = commands::telemetry::TelemetryConfig::from_env(service_name);
+        commands::telemetry::TelemetryConfig {
+            otlp_endpoint: self.otlp_endpoint.clone().or(from_env.otlp_endpoint),
+            sample_ratio: self.sample_ratio,
+            ..from_env
+        }
+    }
+}
+
+impl Config {
+    /// Build a typed config from the merged tree.
+    pub fn from_value(value: &Value) -> Result<Self, ConfigError> {
+        let root = table(value, "<root>")?;
+        reject_unknown(root, "", TOP_LEVEL_KEYS)?;
+
+        Ok(Self {
+            hostname: typed(required(root, "", "hostname")?, "hostname", "a string")?,
+            addr: socket_addr(required(root, "", "addr")?, "addr")?,
+            role: Role::from_value(required(root, "", "role")?)?,
+            shutdown: root
+                .get("shutdown")
+                .map(ShutdownConfig::from_value)
+                .transpose()?
+                .unwrap_or_default(),
+            log_level: root
+                .get("log_level")
+                .map(|v| typed(v, "log_level", "a filter string such as \"info\""))
+                .transpose()?
+                .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string()),
+            cache_urls: root
+                .get("cache_urls")
+                .map(|v| typed(v, "cache_urls", "a list of URL strings"))
+                .transpose()?
+                .unwrap_or_default(),
+            telemetry: root
+                .get("telemetry")
+                .map(TelemetryConfig::from_value)
+                .transpose()?
+                .unwrap_or_default(),
+            health_addr: root
+                .get("health_addr")
+                .map(|v| socket_addr(v, "health_addr"))
+                .transpose()?,
+        })
+    }
+
+    /// Semantic checks beyond parsing, reporting every problem found.
+    ///
+    /// Binds `addr` briefly to prove it is available, so this must run
+    /// before the node starts listening.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut issues = Vec::new();
+
+        if self.hostname.trim().is_empty() {
+            issues.push(invalid("hostname", "must not be empty"));
+        }
+
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log_level) {
+            issues.push(invalid("log_level", format!("invalid filter: {e}")));
+        }
+
+        for (i, url) in self.cache_urls.iter().enumerate() {
+            if !(url.starts_with("http://")
+                || url.starts_with("https://")
+                || url.starts_with("file://"))
+            {
+                issues.push(invalid(
+                    &format!("cache_urls[{i}]"),
+                    format!("unsupported cache URL \"{url}\""),
+                ));
+            }
+        }
+
+        if !(0.0..=1.0).contains(&self.telemetry.sample_ratio) {
+            issues.push(invalid(
+                "telemetry.sample_ratio",
+                format!("{} is not between 0 and 1", self.telemetry.sample_ratio),
+            ));
+        }
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint
+            && !(endpoint.starts_with("http://") || endpoint.starts_with("https://"))
+        {
+            issues.push(invalid(
+                "telemetry.otlp_endpoint",
+                format!("expected an http(s) URL, got \"{endpoint}\""),
+            ));
+        }
+
+        if let Err(e) = std::net::TcpListener::bind(self.addr) {
+            issues.push(invalid("addr", format!("cannot bind {}: {e}", self.addr)));
+        }
+        if self.health_addr == Some(self.addr) {
+            issues.push(invalid(
+                "health_addr",
+                "must differ from `addr`, probes are served on their own listener",
+            ));
+        }
+
+        match &self.role {
+            Role::Master { peers_addr } => {
+                if peers_addr.is_empty() {
+                    issues.push(invalid(
+                        "role.peers_addr",
+                        "a master needs at least one peer",
+                    ));
+                }
+                for (i, peer) in peers_addr.iter().enumerate() {
+                    if *peer == self.addr {
+                        issues.push(invalid(
+                            &format!("role.peers_addr[{i}]"),
+                            "a master must not list itself as a peer",
+                        ));
+                    }
+                    if peers_addr[..i].contains(peer) {
+                        issues.push(invalid(
+                            &format!("role.peers_addr[{i}]"),
+                            format!("duplicate peer {peer}"),
+                        ));
+                    }
+                }
+            }
+            Role::Worker { master_addr } => {
+                if *master_addr == self.addr {
+                    issues.push(invalid(
+                        "role.master_addr",
+                        "must differ from the worker's own `addr`",
+                    ));
+                }
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+
+    /// Load the file, then layer environment variables and flag overrides on top.
+    pub fn load(
+        path: &Path,
+        env: impl IntoIterator<Item = (String, String)>,
+        flags: Vec<(String, Value)>,
+    ) -> Result<Self, ConfigError> {
+        let mut value = read_file(path)?;
+        for (key, v) in env_overrides(env).into_iter().chain(flags) {
+            apply_override(&mut value, &key, v)?;
+        }
+        Self::from_value(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn write_tmp(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("procurator-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn err_key(result: Result<Config, ConfigError>) -> String {
+        match result.unwrap_err() {
+            ConfigError::Invalid { key, .. } => key,
+            other => panic!("expected Invalid, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn loads_toml_worker() {
+        let path = write_tmp(
+            "worker.toml",
+            "hostname = \"w1\"\naddr = \"127.0.0.1:6000\"\n[role]\nmaster_addr = \"127.0.0.1:5000\"\n",
+        );
+        let cfg = Config::load(&path, [], vec![]).unwrap();
+        assert_eq!(cfg.hostname, "w1");
+        assert!(matches!(cfg.role, Role::Worker { .. }));
+    }
+
+    #[test]
+    fn loads_yaml_master() {
+        let path = write_tmp(
+            "master.yaml",
+            "hostname: m1\naddr: 127.0.0.1:5000\nrole:\n  peers_addr:\n    - 127.0.0.1:5001\n",
+        );
+        let cfg = Config::load(&path, [], vec![]).unwrap();
+        let Role::Master { peers_addr } = cfg.role else {
+            panic!("expected master role");
+        };
+        assert_eq!(peers_addr.len(), 1);
+    }
+
+    #[test]
+    fn syntax_errors_name_the_format() {
+        let path = write_tmp("broken.toml", "hostname = ");
+        let err = Config::load(&path, [], vec![]).unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::Syntax {
+                format: Format::Toml,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn env_overrides_file_and_flags_override_env() {
+        let path = write_tmp(
+            "layered.json",
+            r#"{"hostname":"file","addr":"127.0.0.1:1","role":{"master_addr":"127.0.0.1:2"}}"#,
+        );
+        let env = [
+            ("PROCURATOR_HOSTNAME".to_string(), "env".to_string()),
+            ("PROCURATOR_ADDR".to_string(), "127.0.0.1:3".to_string()),
+            ("PROCURATOR_MASTER".to_string(), "ignored".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        let flags = vec![parse_set_flag("hostname=flag").unwrap()];
+
+        let cfg = Config::load(&path, env, flags).unwrap();
+        assert_eq!(cfg.hostname, "flag");
+        assert_eq!(cfg.addr, "127.0.0.1:3".parse().unwrap());
+    }
+
+    #[test]
+    fn nested_env_keys_use_double_underscore() {
+        let overrides = env_overrides([(
+            "PROCURATOR_ROLE__PEERS_ADDR".to_string(),
+            r#"["127.0.0.1:5001"]"#.to_string(),
+        )]);
+        assert_eq!(
+            overrides,
+            vec![("role.peers_addr".to_string(), json!(["127.0.0.1:5001"]))]
+        );
+    }
+
+    #[test]
+    fn errors_report_key_path() {
+        let value = json!({
+            "hostname": "m1",
+            "addr": "127.0.0.1:5000",
+            "role": { "peers_addr": ["127.0.0.1:5001", "not-an-addr"] }
+        });
+        assert_eq!(err_key(Config::from_value(&value)), "role.peers_addr[1]");
+
+        let value = json!({ "hostname": 3, "addr": "127.0.0.1:5000", "role": {} });
+        assert_eq!(err_key(Config::from_value(&value)), "hostname");
+
+        let value = json!({ "hostname": "h", "addr": "127.0.0.1:5000" });
+        assert_eq!(err_key(Config::from_value(&value)), "role");
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let value = json!({
+            "hostname": "h",
+            "addr": "127.0.0.1:5000",
+            "adrr": "typo",
+            "role": { "master_addr": "127.0.0.1:5001" }
+        });
+        assert_eq!(err_key(Config::from_value(&value)), "adrr");
+    }
+
+    #[test]
+    fn override_into_scalar_is_rejected() {
+        let mut value = json!({ "hostname": "h" });
+        let err = apply_override(&mut value, "hostname.inner", json!(1)).unwrap_err();
+        assert!(matches!(err, ConfigError::Invalid { key, .. } if key == "hostname.inner"));
+    }
+
+    fn free_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn issue_keys(cfg: &Config) -> Vec<String> {
+        cfg.validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|e| match e {
+                ConfigError::Invalid { key, .. } => key,
+                other => panic!("expected Invalid, got {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_worker_config_passes() {
+        let value = json!({
+            "hostname": "w1",
+            "addr": free_addr(),
+            "role": { "master_addr": "127.0.0.1:5000" }
+        });
+        Config::from_value(&value).unwrap().validate().unwrap();
+    }
+
+    #[test]
+    fn master_without_peers_is_invalid() {
+        let value = json!({ "hostname": "m1", "addr": free_addr(), "role": { "peers_addr": [] } });
+        let cfg = Config::from_value(&value).unwrap();
+        assert_eq!(issue_keys(&cfg), vec!["role.peers_addr"]);
+    }
+
+    #[test]
+    fn address_in_use_is_reported() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken = listener.local_addr().unwrap().to_string();
+        let value = json!({
+            "hostname": "w1",
+            "addr": taken,
+            "role": { "master_addr": "127.0.0.1:5000" }
+        });
+        let cfg = Config::from_value(&value).unwrap();
+        assert_eq!(issue_keys(&cfg), vec!["addr"]);
+    }
+
+    #[test]
+    fn self_and_duplicate_peers_are_all_reported() {
+        let addr = free_addr();
+        let value = json!({
+            "hostname": "m1",
+            "addr": addr,
+            "role": { "peers_addr": [addr, "10.0.0.1:5000", "10.0.0.1:5000"] }
+        });
+        let cfg = Config::from_value(&value).unwrap();
+        assert_eq!(
+            issue_keys(&cfg),
+            vec!["role.peers_addr[0]", "role.peers_addr[2]"]
+        );
+    }
+
+    #[test]
+    fn shutdown_timeout_defaults_and_overrides() {
+        let value = json!({ "hostname": "h", "addr": "127.0.0.1:1", "role": { "master_addr": "127.0.0.1:2" } });
+        let cfg = Config::from_value(&value).unwrap();
+        assert_eq!(cfg.shutdown.timeout, Duration::from_secs(30));
+        assert!(!cfg.shutdown.stop_vms);
+
+        let mut value = value;
+        apply_override(&mut value, "shutdown.timeout_secs", json!(5)).unwrap();
+        apply_override(&mut value, "shutdown.stop_vms", json!(true)).unwrap();
+        let cfg = Config::from_value(&value).unwrap();
+        assert_eq!(cfg.shutdown.timeout, Duration::from_secs(5));
+        assert!(cfg.shutdown.stop_vms);
+    }
+
+    #[test]
+    fn telemetry_section_is_parsed_and_validated() {
+        let value = json!({
+            "hostname": "h",
+            "addr": free_addr(),
+            "role": { "master_addr": "127.0.0.1:2" },
+            "telemetry": { "otlp_endpoint": "collector:4317", "sample_ratio": 1.5 }
+        });
+        let cfg = Config::from_value(&value).unwrap();
+        assert_eq!(
+            cfg.telemetry.otlp_endpoint.as_deref(),
+            Some("collector:4317")
+        );
+        assert_eq!(
+            issue_keys(&cfg),
+            vec!["telemetry.sample_ratio", "telemetry.otlp_endpoint"]
+        );
+    }
+
+    #[test]
+    fn health_addr_must_not_reuse_the_rpc_addr() {
+        let addr = free_addr();
+        let value = json!({
+            "hostname": "h",
+            "addr": addr,
+            "role": { "master_addr": "127.0.0.1:2" },
+            "health_addr": addr
+        });
+        let cfg = Config::from_value(&value).unwrap();
+        assert_eq!(cfg.health_addr, Some(addr.parse().unwrap()));
+        assert_eq!(issue_keys(&cfg), vec!["health_addr"]);
+    }
+
+    #[test]
+    fn malformed_set_flag_is_rejected() {
+        assert!(matches!(
+            parse_set_flag("novalue"),
+            Err(ConfigError::Override(_))
+        ));
+        assert!(matches!(
+            parse_set_flag("=x"),
+            Err(ConfigError::Override(_))
+        ));
+    }
+}
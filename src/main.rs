@@ -1,25 +1,117 @@
+mod config;
+mod reload;
+
 use std::{net::SocketAddr, path::PathBuf};
 
-use serde::Deserialize;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use clap::Parser;
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::config::{Config, Role};
+use crate::reload::ConfigSource;
+
+/// Run a procurator node (master or worker) from a config file.
+#[derive(Debug, Parser)]
+#[command(name = "procurator", version)]
+struct Args {
+    /// Config file (.json, .toml, .yaml/.yml)
+    config: PathBuf,
+
+    /// Override `hostname`
+    #[arg(long)]
+    hostname: Option<String>,
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum Role {
-    Master { peers_addr: Vec<SocketAddr> },
-    Worker { master_addr: SocketAddr },
+    /// Override `addr`
+    #[arg(long)]
+    addr: Option<SocketAddr>,
+
+    /// Override any key, e.g. `--set role.master_addr=10.0.0.1:5000`
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+
+    /// Parse and validate the config, report problems and exit without starting
+    #[arg(long, visible_alias = "check-config")]
+    validate: bool,
 }
 
-#[derive(Debug, Deserialize)]
-struct Config {
-    hostname: String,
-    addr: SocketAddr,
-    role: Role,
+impl Args {
+    /// Flags as `(key.path, value)` overrides, applied after the environment.
+    fn overrides(&self) -> Result<Vec<(String, serde_json::Value)>, config::ConfigError> {
+        let mut overrides = self
+            .overrides
+            .iter()
+            .map(|arg| config::parse_set_flag(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(hostname) = &self.hostname {
+            overrides.push(("hostname".to_string(), hostname.clone().into()));
+        }
+        if let Some(addr) = self.addr {
+            overrides.push(("addr".to_string(), addr.to_string().into()));
+        }
+        Ok(overrides)
+    }
+}
+
+/// Report validation results in plain text for deployment pipelines.
+fn check_config(path: &std::path::Path, cfg: &Config) -> i32 {
+    match cfg.validate() {
+        Ok(()) => {
+            println!("{}: OK", path.display());
+            0
+        }
+        Err(issues) => {
+            for issue in &issues {
+                eprintln!("{}: {issue}", path.display());
+            }
+            eprintln!("{}: {} problem(s) found", path.display(), issues.len());
+            1
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
+    let args = Args::parse();
+    let config_path = &args.config;
+
+    // Logging is configured from the file, so failures before that go to stderr.
+    let flags = args.overrides().unwrap_or_else(|e| {
+        eprintln!("invalid command line override: {}", repo_outils::report(&e));
+        std::process::exit(1);
+    });
+
+    let cfg = Config::load(config_path, std::env::vars(), flags.clone()).unwrap_or_else(|e| {
+        eprintln!(
+            "{}: failed to load config: {}",
+            config_path.display(),
+            repo_outils::report(&e)
+        );
+        std::process::exit(1);
+    });
+
+    if args.validate {
+        std::process::exit(check_config(config_path, &cfg));
+    }
+
+    let service_name = match cfg.role {
+        Role::Master { .. } => "procurator-master",
+        Role::Worker { .. } => "procurator-worker",
+    };
+    let (otel, otel_guard) = commands::telemetry::otel_layer(&cfg.telemetry.exporter(service_name))
+        .unwrap_or_else(|e| {
+            eprintln!("invalid OTLP exporter configuration: {e}");
+            std::process::exit(1);
+        });
+
+    // An invalid filter is reported once the subscriber is up. The filter
+    // sits behind a reload layer so SIGHUP can change it.
+    let (log_filter, invalid_log_level) = match EnvFilter::try_new(&cfg.log_level) {
+        Ok(filter) => (filter, None),
+        Err(e) => (EnvFilter::new(config::DEFAULT_LOG_LEVEL), Some(e)),
+    };
+    let (log_filter, log_filter_handle) = tracing_subscriber::reload::Layer::new(log_filter);
+
     tracing_subscriber::registry()
+        .with(log_filter)
         .with(
             tracing_subscriber::fmt::layer()
                 .json()
@@ -29,33 +121,34 @@ async fn main() {
                 .flatten_event(true)
                 .with_span_list(false),
         )
+        .with(otel)
         .init();
 
-    let config_path = std::env::args()
-        .nth(1)
-        .map(PathBuf::from)
-        .expect("Config path must be provided as the first argument");
-
-    let contents = tokio::fs::read(&config_path).await.unwrap_or_else(|e| {
-        tracing::error!(path = ?config_path, error = %e, "Could not read config");
-        std::process::exit(1);
-    });
+    tracing::info!(path = ?config_path, ?cfg, "Loaded configuration");
 
-    let cfg: Config = serde_json::from_slice(&contents).unwrap_or_else(|e| {
-        tracing::error!(path = ?config_path, error = %e, "Failed to parse config");
-        std::process::exit(1);
-    });
+    if let Some(e) = invalid_log_level {
+        tracing::warn!(log_level = %cfg.log_level, error = %e, "Invalid log_level, using default");
+    }
 
-    tracing::info!(path = ?config_path, ?cfg, "Loaded configuration");
+    // Re-reads the config for as long as the node runs.
+    tokio::spawn(reload::watch_sighup(
+        ConfigSource {
+            path: config_path.clone(),
+            flags,
+        },
+        cfg.clone(),
+        log_filter_handle,
+    ));
 
     // match cfg.role {
     //     Role::Master { peers_addr } => {
     //         tracing::info!(?peers_addr, "Starting in Master mode");
-    //         control_plane::main(cfg.hostname, cfg.addr, peers_addr).await;
+    //         control_plane::main(cfg.hostname, cfg.addr, peers_addr, cfg.shutdown.timeout).await;
     //     }
     //     Role::Worker { master_addr } => {
     //         tracing::info!(?master_addr, "Starting in Worker mode");
     //         worker::main(cfg.hostname, cfg.addr, master_addr).await;
     //     }
     // }
+    otel_guard.shutdown();
 }
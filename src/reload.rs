@@ -0,0 +1,202 @@
+//! Configuration hot reload on SIGHUP.
+//!
+//! The config is re-read with the same layering as at startup (file, then
+//! `PROCURATOR_*` env, then the original command line overrides). Only
+//! `log_level` is reloadable: it is swapped into the live `EnvFilter`.
+//!
+//! Changes to anything that is bound at startup (`hostname`, `addr`, `role`,
+//! `shutdown`, `cache_urls`, `telemetry`, `health_addr`) are ignored with a
+//! warning and need a restart. A config that fails to load leaves the running
+//! settings untouched.
+
+use std::path::PathBuf;
+
+use serde_json::Value;
+use tokio::signal::unix::{SignalKind, signal};
+use tracing_subscriber::{EnvFilter, Registry, reload};
+
+use crate::config::Config;
+
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Where the config came from, so it can be rebuilt identically.
+pub struct ConfigSource {
+    pub path: PathBuf,
+    pub flags: Vec<(String, Value)>,
+}
+
+impl ConfigSource {
+    fn load(&self) -> Result<Config, crate::config::ConfigError> {
+        Config::load(&self.path, std::env::vars(), self.flags.clone())
+    }
+}
+
+/// Keys that differ between two configs, split by whether they can be
+/// applied without a restart.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub reloadable: Vec<&'static str>,
+    pub immutable: Vec<&'static str>,
+}
+
+impl Changes {
+    pub fn between(old: &Config, new: &Config) -> Self {
+        let mut changes = Self::default();
+
+        let immutable = [
+            ("hostname", old.hostname != new.hostname),
+            ("addr", old.addr != new.addr),
+            ("role", old.role != new.role),
+            ("shutdown", old.shutdown != new.shutdown),
+            ("cache_urls", old.cache_urls != new.cache_urls),
+            ("telemetry", old.telemetry != new.telemetry),
+            ("health_addr", old.health_addr != new.health_addr),
+        ];
+        let reloadable = [("log_level", old.log_level != new.log_level)];
+
+        changes.immutable = immutable
+            .into_iter()
+            .filter(|(_, c)| *c)
+            .map(|(k, _)| k)
+            .collect();
+        changes.reloadable = reloadable
+            .into_iter()
+            .filter(|(_, c)| *c)
+            .map(|(k, _)| k)
+            .collect();
+        changes
+    }
+}
+
+/// Apply the reloadable part of `new`, returning the config now in effect.
+///
+/// Immutable keys keep their running values so later diffs keep warning
+/// until the process is restarted.
+fn apply(current: &Config, new: Config, log_filter: &LogFilterHandle) -> Config {
+    let changes = Changes::between(current, &new);
+    let mut effective = current.clone();
+
+    for key in &changes.immutable {
+        tracing::warn!(
+            key,
+            "Setting changed on disk but requires a restart, ignoring"
+        );
+    }
+
+    if changes.reloadable.contains(&"log_level") {
+        match EnvFilter::try_new(&new.log_level) {
+            Ok(filter) => match log_filter.reload(filter) {
+                Ok(()) => {
+                    tracing::info!(log_level = %new.log_level, "Log level reloaded");
+                    effective.log_level = new.log_level;
+                }
+                Err(e) => tracing::error!(error = %e, "Failed to swap log filter"),
+            },
+            Err(e) => {
+                tracing::warn!(log_level = %new.log_level, error = %e, "Invalid log_level, keeping previous");
+            }
+        }
+    }
+
+    if changes.reloadable.is_empty() && changes.immutable.is_empty() {
+        tracing::info!("Config unchanged");
+    }
+
+    effective
+}
+
+/// Re-read the config on every SIGHUP until the process exits.
+pub async fn watch_sighup(source: ConfigSource, mut current: Config, log_filter: LogFilterHandle) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!(error = %e, "Cannot install SIGHUP handler, hot reload disabled");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        tracing::info!(path = ?source.path, "SIGHUP received, reloading config");
+        match source.load() {
+            Ok(new) => current = apply(&current, new, &log_filter),
+            Err(e) => {
+                let error = repo_outils::report(&e);
+                tracing::error!(%error, "Reload failed, keeping current config");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn config(overrides: &[(&str, Value)]) -> Config {
+        let mut value = json!({
+            "hostname": "w1",
+            "addr": "127.0.0.1:6000",
+            "role": { "master_addr": "127.0.0.1:5000" }
+        });
+        for (key, v) in overrides {
+            crate::config::apply_override(&mut value, key, v.clone()).unwrap();
+        }
+        Config::from_value(&value).unwrap()
+    }
+
+    /// The returned subscriber must outlive the handle; it is never installed.
+    fn log_filter() -> (LogFilterHandle, impl tracing::Subscriber) {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+        (handle, Registry::default().with(layer))
+    }
+
+    #[test]
+    fn changes_are_classified() {
+        let old = config(&[]);
+        let new = config(&[
+            ("addr", json!("127.0.0.1:7000")),
+            ("log_level", json!("debug")),
+        ]);
+        let changes = Changes::between(&old, &new);
+        assert_eq!(changes.immutable, vec!["addr"]);
+        assert_eq!(changes.reloadable, vec!["log_level"]);
+    }
+
+    #[test]
+    fn immutable_changes_are_not_applied() {
+        let (log_filter, _subscriber) = log_filter();
+        let old = config(&[]);
+        let new = config(&[
+            ("role", json!({ "master_addr": "127.0.0.1:5999" })),
+            ("cache_urls", json!(["https://cache.example.org"])),
+        ]);
+
+        let effective = apply(&old, new, &log_filter);
+        assert_eq!(effective, old);
+    }
+
+    #[test]
+    fn reloadable_changes_are_applied() {
+        let (log_filter, _subscriber) = log_filter();
+        let old = config(&[]);
+        let new = config(&[
+            ("log_level", json!("debug")),
+            ("hostname", json!("renamed")),
+        ]);
+
+        let effective = apply(&old, new, &log_filter);
+        assert_eq!(effective.log_level, "debug");
+        assert_eq!(effective.hostname, "w1");
+    }
+
+    #[test]
+    fn invalid_log_level_keeps_previous() {
+        let (log_filter, _subscriber) = log_filter();
+        let old = config(&[]);
+        let new = config(&[("log_level", json!("worker=notalevel"))]);
+
+        let effective = apply(&old, new, &log_filter);
+        assert_eq!(effective.log_level, "info");
+    }
+}
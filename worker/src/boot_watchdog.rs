@@ -0,0 +1,202 @@
+//! # Boot watchdog
+//!
+//! `vm.boot` only starts the guest, so a VM stuck in its initrd or in a
+//! failing unit would otherwise count as running forever. With a
+//! `boot_watchdog` section, a freshly deployed VM is `booting` until
+//! `ready_pattern` shows up in its serial console log, and `running` after.
+//!
+//! A VM that is not ready within `timeout_secs`, or whose VMM exits first,
+//! has its attempt killed and is marked `boot-failed` with the last
+//! `log_tail_lines` lines of its serial log attached. `restart` then decides
+//! whether it is deployed again under the same id, at most `max_restarts`
+//! times in a row; otherwise it stays `boot-failed` until deleted or
+//! restarted by hand.
+//!
+//! The default pattern matches systemd reaching `multi-user.target`, which
+//! the guest reports on its serial console. Backends without a serial log,
+//! such as the mock backend of `--simulate`, are not watched: their VMs are
+//! running as soon as they booted.
+
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Enables the boot watchdog; disabled when the section is absent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BootWatchdogSection {
+    /// How long a VM may take from boot until it is ready.
+    pub timeout_secs: u64,
+    /// Serial log text that marks the VM ready.
+    pub ready_pattern: String,
+    /// Lines of serial log kept with a failed boot.
+    pub log_tail_lines: usize,
+    /// What happens to a VM whose boot failed.
+    pub restart: RestartPolicy,
+    /// Most restarts in a row before the VM is left `boot-failed`.
+    pub max_restarts: u32,
+}
+
+impl Default for BootWatchdogSection {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 300,
+            ready_pattern: "Multi-User System".to_string(),
+            log_tail_lines: 40,
+            restart: RestartPolicy::OnFailure,
+            max_restarts: 3,
+        }
+    }
+}
+
+/// Whether a VM whose boot failed is deployed again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Leave it `boot-failed`
+    Never,
+    /// Deploy it again, up to `max_restarts` times in a row
+    OnFailure,
+}
+
+/// Decides when a booting VM is ready and when it has failed, see the
+/// module docs.
+#[derive(Debug, Clone)]
+pub struct BootWatchdog {
+    timeout: Duration,
+    ready_pattern: String,
+    log_tail_lines: usize,
+    restart: RestartPolicy,
+    max_restarts: u32,
+}
+
+impl BootWatchdog {
+    /// A watchdog with the default tail length and restart policy.
+    pub fn new(timeout: Duration, ready_pattern: impl Into<String>) -> Self {
+        let defaults = BootWatchdogSection::default();
+        Self {
+            timeout,
+            ready_pattern: ready_pattern.into(),
+            log_tail_lines: defaults.log_tail_lines,
+            restart: defaults.restart,
+            max_restarts: defaults.max_restarts,
+        }
+    }
+
+    /// Restart failed boots according to `restart`, at most `max_restarts`
+    /// times in a row.
+    #[must_use]
+    pub fn with_restart(mut self, restart: RestartPolicy, max_restarts: u32) -> Self {
+        self.restart = restart;
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    #[must_use]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// How often booting VMs are checked: a tenth of the timeout, between
+    /// half a second and five seconds.
+    #[must_use]
+    pub fn check_interval(&self) -> Duration {
+        (self.timeout / 10).clamp(Duration::from_millis(500), Duration::from_secs(5))
+    }
+
+    /// Whether serial log `log` shows the VM ready.
+    #[must_use]
+    pub fn is_ready(&self, log: &str) -> bool {
+        log.contains(&self.ready_pattern)
+    }
+
+    /// Whether a VM whose boot failed after `restarts` restarts in a row
+    /// is deployed again.
+    #[must_use]
+    pub fn should_restart(&self, restarts: u32) -> bool {
+        self.restart == RestartPolicy::OnFailure && restarts < self.max_restarts
+    }
+
+    /// The last `log_tail_lines` lines of serial log `log`.
+    #[must_use]
+    pub fn excerpt(&self, log: &str) -> String {
+        let lines: Vec<&str> = log.lines().collect();
+        let start = lines.len().saturating_sub(self.log_tail_lines);
+        lines[start..].join("\n")
+    }
+}
+
+impl From<BootWatchdogSection> for BootWatchdog {
+    fn from(section: BootWatchdogSection) -> Self {
+        Self {
+            timeout: Duration::from_secs(section.timeout_secs),
+            ready_pattern: section.ready_pattern,
+            log_tail_lines: section.log_tail_lines,
+            restart: section.restart,
+            max_restarts: section.max_restarts,
+        }
+    }
+}
+
+/// Serial log at `path`, empty while the VMM has not written it yet.
+/// Bytes that are not UTF-8, such as a garbled console, are replaced.
+pub fn read_log(path: &Path) -> String {
+    match std::fs::read(path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!(path = %path.display(), error = %e, "Cannot read serial log");
+            }
+            String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_once_the_pattern_is_logged() {
+        let watchdog = BootWatchdog::new(Duration::from_secs(60), "Multi-User System");
+        assert!(!watchdog.is_ready("[  OK  ] Reached target Basic System.\n"));
+        assert!(watchdog.is_ready(
+            "[  OK  ] Reached target Basic System.\n[  OK  ] Reached target Multi-User System.\n"
+        ));
+    }
+
+    #[test]
+    fn excerpt_keeps_the_last_lines() {
+        let mut watchdog = BootWatchdog::new(Duration::from_secs(60), "ready");
+        watchdog.log_tail_lines = 2;
+        assert_eq!(watchdog.excerpt("a\nb\nc\n"), "b\nc");
+        assert_eq!(watchdog.excerpt("only"), "only");
+        assert_eq!(watchdog.excerpt(""), "");
+    }
+
+    #[test]
+    fn restarts_follow_the_policy() {
+        let watchdog = BootWatchdog::new(Duration::from_secs(60), "ready")
+            .with_restart(RestartPolicy::OnFailure, 2);
+        assert!(watchdog.should_restart(0));
+        assert!(watchdog.should_restart(1));
+        assert!(!watchdog.should_restart(2));
+
+        let watchdog = watchdog.with_restart(RestartPolicy::Never, 2);
+        assert!(!watchdog.should_restart(0));
+    }
+
+    #[test]
+    fn section_parses_kebab_case_policies() {
+        let section: BootWatchdogSection =
+            serde_json::from_str(r#"{"timeout_secs": 120, "restart": "never"}"#).unwrap();
+        assert_eq!(section.timeout_secs, 120);
+        assert_eq!(section.restart, RestartPolicy::Never);
+        assert_eq!(section.ready_pattern, "Multi-User System");
+        assert_eq!(
+            BootWatchdog::from(section).check_interval(),
+            Duration::from_secs(5)
+        );
+    }
+}
@@ -0,0 +1,564 @@
+//! Loading and validating the worker config file.
+//!
+//! The file is JSON. `PROCURATOR_WORKER_*` environment variables override
+//! single fields, mapped to key paths the same way as the node config:
+//! strip the prefix, lowercase and split on `__`, so
+//! `PROCURATOR_WORKER_VMS__MAX_VMS=8` sets `vms.max_vms` and
+//! `PROCURATOR_WORKER_CLOUD_HYPERVISOR__BRIDGE_NAME=br1` sets
+//! `cloud_hypervisor.bridge_name`. Values are parsed as JSON when possible
+//! and taken as plain strings otherwise. Variables whose first segment is
+//! not a config section are ignored.
+
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::{CloudHypervisorSection, Config, store_janitor};
+
+pub const ENV_PREFIX: &str = "PROCURATOR_WORKER_";
+
+/// Top-level keys of [`Config`], the only ones environment variables set.
+const TOP_LEVEL_KEYS: &[&str] = &[
+    "listen_addr",
+    "master_addr",
+    "cloud_hypervisor",
+    "vms",
+    "shutdown",
+    "metrics",
+    "log_forwarding",
+    "health",
+    "simulate",
+    "identity",
+    "dns_proxy",
+    "boot_watchdog",
+    "memory_pressure",
+    "store_janitor",
+    "drain",
+    "vmm_monitor",
+];
+
+/// Linux interface names are at most `IFNAMSIZ - 1` bytes.
+const MAX_IFNAME_LEN: usize = 15;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("cannot read {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// Not valid JSON, or not the shape of [`Config`].
+    #[error("invalid config {}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// A field holds a value the worker cannot run with.
+    #[error("`{key}`: {message}")]
+    Invalid { key: String, message: String },
+}
+
+fn invalid(key: &str, message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.to_string(),
+        message: message.into(),
+    }
+}
+
+/// Read the config file and apply `PROCURATOR_WORKER_*` overrides from `env`.
+///
+/// # Errors
+///
+/// - if the file cannot be read
+/// - if the file, with overrides applied, is not a valid [`Config`]
+/// - if an override sets a field below a non-table value
+pub fn load(
+    path: &Path,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> Result<Config, ConfigError> {
+    let contents = std::fs::read(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let parse = |source| ConfigError::Parse {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut value: Value = serde_json::from_slice(&contents).map_err(parse)?;
+    for (key, v) in env_overrides(env) {
+        apply_override(&mut value, &key, v)?;
+    }
+    serde_json::from_value(value).map_err(parse)
+}
+
+/// Turn `PROCURATOR_WORKER_*` variables into `(key.path, value)` overrides.
+fn env_overrides(vars: impl IntoIterator<Item = (String, String)>) -> Vec<(String, Value)> {
+    vars.into_iter()
+        .filter_map(|(name, raw)| {
+            let rest = name.strip_prefix(ENV_PREFIX)?;
+            let key = rest.to_lowercase().replace("__", ".");
+            let top = key.split('.').next()?;
+            if !TOP_LEVEL_KEYS.contains(&top) {
+                return None;
+            }
+            let value = serde_json::from_str(&raw).unwrap_or(Value::String(raw));
+            Some((key, value))
+        })
+        .collect()
+}
+
+/// Set `value` at the dotted `key` path, creating intermediate tables.
+fn apply_override(root: &mut Value, key: &str, value: Value) -> Result<(), ConfigError> {
+    let (parents, last) = key
+        .rsplit_once('.')
+        .map_or((None, key), |(p, l)| (Some(p), l));
+    let mut node = root;
+    for segment in parents.into_iter().flat_map(|p| p.split('.')) {
+        if node.is_null() {
+            *node = Value::Object(Map::new());
+        }
+        node = node
+            .as_object_mut()
+            .ok_or_else(|| invalid(key, "cannot override a field of a non-table value"))?
+            .entry(segment)
+            .or_insert(Value::Null);
+    }
+    if node.is_null() {
+        *node = Value::Object(Map::new());
+    }
+    node.as_object_mut()
+        .ok_or_else(|| invalid(key, "cannot override a field of a non-table value"))?
+        .insert(last.to_string(), value);
+    Ok(())
+}
+
+impl Config {
+    /// Semantic checks beyond parsing, reporting every problem found.
+    ///
+    /// Run after [`Config::simulate`], since simulating makes the
+    /// `cloud_hypervisor` section optional.
+    ///
+    /// # Errors
+    ///
+    /// Every invalid field, as [`ConfigError::Invalid`].
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut issues = Vec::new();
+
+        if self.listen_addr == self.master_addr {
+            issues.push(invalid(
+                "master_addr",
+                "must differ from the worker's own `listen_addr`",
+            ));
+        }
+
+        if self.vms.worker_id.trim().is_empty() {
+            issues.push(invalid("vms.worker_id", "must not be empty"));
+        }
+        if self.vms.max_vms == Some(0) {
+            issues.push(invalid(
+                "vms.max_vms",
+                "must be at least 1, leave it out for no limit",
+            ));
+        }
+        check_absolute(
+            &[("vms.state_dir", self.vms.state_dir.as_ref())],
+            &mut issues,
+        );
+
+        match (&self.cloud_hypervisor, &self.simulate) {
+            (Some(section), _) => section.validate(&mut issues),
+            (None, None) => issues.push(invalid(
+                "cloud_hypervisor",
+                "missing, required unless simulating",
+            )),
+            (None, Some(_)) => {}
+        }
+
+        if let Some(section) = &self.dns_proxy {
+            if section.upstreams.is_empty() {
+                issues.push(invalid(
+                    "dns_proxy.upstreams",
+                    "must name at least one resolver",
+                ));
+            }
+            if section.upstreams.contains(&section.listen_addr) {
+                issues.push(invalid(
+                    "dns_proxy.upstreams",
+                    "must not contain the proxy's own `listen_addr`",
+                ));
+            }
+            if section.upstream_timeout_ms == 0 {
+                issues.push(invalid(
+                    "dns_proxy.upstream_timeout_ms",
+                    "must be at least 1",
+                ));
+            }
+            if section.max_in_flight == 0 {
+                issues.push(invalid("dns_proxy.max_in_flight", "must be at least 1"));
+            }
+        }
+
+        if let Some(section) = &self.boot_watchdog {
+            if section.timeout_secs == 0 {
+                issues.push(invalid("boot_watchdog.timeout_secs", "must be at least 1"));
+            }
+            if section.ready_pattern.is_empty() {
+                issues.push(invalid("boot_watchdog.ready_pattern", "must not be empty"));
+            }
+        }
+
+        if let Some(section) = &self.memory_pressure {
+            if section.check_interval_secs == 0 {
+                issues.push(invalid(
+                    "memory_pressure.check_interval_secs",
+                    "must be at least 1",
+                ));
+            }
+            if !(section.threshold_percent > 0.0 && section.threshold_percent <= 100.0) {
+                issues.push(invalid(
+                    "memory_pressure.threshold_percent",
+                    "must be above 0 and at most 100",
+                ));
+            }
+            if section.sustained_checks == 0 {
+                issues.push(invalid(
+                    "memory_pressure.sustained_checks",
+                    "must be at least 1",
+                ));
+            }
+        }
+
+        if let Some(section) = &self.store_janitor {
+            check_absolute(
+                &[
+                    ("store_janitor.store_dir", Some(&section.store_dir)),
+                    ("store_janitor.gc_roots_dir", Some(&section.gc_roots_dir)),
+                ],
+                &mut issues,
+            );
+            if section.gc_roots_dir.is_absolute()
+                && !store_janitor::is_roots_subdir(&section.gc_roots_dir)
+            {
+                issues.push(invalid(
+                    "store_janitor.gc_roots_dir",
+                    "must be a directory below /nix/var/nix/gcroots",
+                ));
+            }
+            if section.check_interval_secs == 0 {
+                issues.push(invalid(
+                    "store_janitor.check_interval_secs",
+                    "must be at least 1",
+                ));
+            }
+            if !(section.high_watermark_percent > 0.0 && section.high_watermark_percent <= 100.0) {
+                issues.push(invalid(
+                    "store_janitor.high_watermark_percent",
+                    "must be above 0 and at most 100",
+                ));
+            }
+            if !(section.low_watermark_percent >= 0.0
+                && section.low_watermark_percent < section.high_watermark_percent)
+            {
+                issues.push(invalid(
+                    "store_janitor.low_watermark_percent",
+                    "must be at least 0 and below high_watermark_percent",
+                ));
+            }
+        }
+
+        if let Some(section) = &self.drain
+            && section.timeout_secs == 0
+        {
+            issues.push(invalid(
+                "drain.timeout_secs",
+                "must be at least 1, leave the section out to stop VMs at once",
+            ));
+        }
+
+        if let Some(section) = &self.vmm_monitor {
+            for (key, value) in [
+                (
+                    "vmm_monitor.check_interval_secs",
+                    section.check_interval_secs,
+                ),
+                ("vmm_monitor.api_timeout_ms", section.api_timeout_ms),
+                (
+                    "vmm_monitor.unresponsive_checks",
+                    u64::from(section.unresponsive_checks),
+                ),
+            ] {
+                if value == 0 {
+                    issues.push(invalid(key, "must be at least 1"));
+                }
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+}
+
+impl CloudHypervisorSection {
+    fn validate(&self, issues: &mut Vec<ConfigError>) {
+        // A bare name is looked up in PATH when spawning
+        if self.binary_path.components().count() > 1 && !self.binary_path.is_file() {
+            issues.push(invalid(
+                "cloud_hypervisor.binary_path",
+                format!("{} is not a file", self.binary_path.display()),
+            ));
+        }
+        if let Some(virtiofsd) = &self.virtiofsd_binary
+            && virtiofsd.components().count() > 1
+            && !virtiofsd.is_file()
+        {
+            issues.push(invalid(
+                "cloud_hypervisor.virtiofsd_binary",
+                format!("{} is not a file", virtiofsd.display()),
+            ));
+        }
+
+        check_absolute(
+            &[
+                ("cloud_hypervisor.socket_dir", Some(&self.socket_dir)),
+                ("cloud_hypervisor.image_dir", self.image_dir.as_ref()),
+                ("cloud_hypervisor.staging_dir", self.staging_dir.as_ref()),
+                ("cloud_hypervisor.log_dir", self.log_dir.as_ref()),
+                (
+                    "cloud_hypervisor.vhost_user_net.socket_dir",
+                    self.vhost_user_net.as_ref().map(|v| &v.socket_dir),
+                ),
+            ],
+            issues,
+        );
+
+        if self.socket_timeout_secs == 0 {
+            issues.push(invalid(
+                "cloud_hypervisor.socket_timeout_secs",
+                "must be at least 1",
+            ));
+        }
+
+        if let Some(hotplug) = &self.hotplug {
+            if hotplug.max_vcpus == 0 {
+                issues.push(invalid(
+                    "cloud_hypervisor.hotplug.max_vcpus",
+                    "must be at least 1",
+                ));
+            }
+            if hotplug.max_memory_mb == 0 {
+                issues.push(invalid(
+                    "cloud_hypervisor.hotplug.max_memory_mb",
+                    "must be at least 1",
+                ));
+            }
+        }
+
+        if let Some(bridge) = &self.bridge_name
+            && let Err(reason) = check_ifname(bridge)
+        {
+            issues.push(invalid(
+                "cloud_hypervisor.bridge_name",
+                format!("\"{bridge}\" is not an interface name: {reason}"),
+            ));
+        }
+
+        for (i, key) in self.trusted_public_keys.iter().enumerate() {
+            if !matches!(key.split_once(':'), Some((name, b64)) if !name.is_empty() && !b64.is_empty())
+            {
+                issues.push(invalid(
+                    &format!("cloud_hypervisor.trusted_public_keys[{i}]"),
+                    format!("expected name:base64, got \"{key}\""),
+                ));
+            }
+        }
+    }
+}
+
+fn check_absolute(dirs: &[(&str, Option<&PathBuf>)], issues: &mut Vec<ConfigError>) {
+    for (key, dir) in dirs {
+        if let Some(dir) = dir.filter(|d| !d.is_absolute()) {
+            issues.push(invalid(
+                key,
+                format!("expected an absolute path, got {}", dir.display()),
+            ));
+        }
+    }
+}
+
+/// The rules the kernel applies to interface names.
+fn check_ifname(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err("reserved or empty");
+    }
+    if name.len() > MAX_IFNAME_LEN {
+        return Err("longer than 15 bytes");
+    }
+    if name.contains(['/', ':']) || name.contains(char::is_whitespace) {
+        return Err("contains '/', ':' or whitespace");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn base() -> Value {
+        json!({
+            "listen_addr": "127.0.0.1:6000",
+            "master_addr": "127.0.0.1:5000",
+            "cloud_hypervisor": {
+                "binary_path": "cloud-hypervisor",
+                "socket_dir": "/run/procurator-worker/vms",
+                "socket_timeout_secs": 5,
+                "bridge_name": "br0"
+            }
+        })
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect()
+    }
+
+    fn with_env(pairs: &[(&str, &str)]) -> Config {
+        let mut value = base();
+        for (key, v) in env_overrides(vars(pairs)) {
+            apply_override(&mut value, &key, v).unwrap();
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn issue_keys(config: &Config) -> Vec<String> {
+        config
+            .validate()
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|e| match e {
+                ConfigError::Invalid { key, .. } => key,
+                other => panic!("unexpected {other}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn env_overrides_set_single_fields() {
+        let config = with_env(&[
+            ("PROCURATOR_WORKER_VMS__WORKER_ID", "worker-7"),
+            ("PROCURATOR_WORKER_VMS__MAX_VMS", "8"),
+            ("PROCURATOR_WORKER_CLOUD_HYPERVISOR__BRIDGE_NAME", "br1"),
+            (
+                "PROCURATOR_WORKER_CLOUD_HYPERVISOR__LOG_DIR",
+                "/var/log/vms",
+            ),
+            ("PROCURATOR_WORKER_UNRELATED", "ignored"),
+            ("PROCURATOR_ROLE__MASTER_ADDR", "ignored"),
+        ]);
+        assert_eq!(config.vms.worker_id, "worker-7");
+        assert_eq!(config.vms.max_vms, Some(8));
+        let ch = config.cloud_hypervisor.as_ref().unwrap();
+        assert_eq!(ch.bridge_name.as_deref(), Some("br1"));
+        assert_eq!(ch.log_dir, Some(PathBuf::from("/var/log/vms")));
+        assert_eq!(ch.image_dir, None);
+        assert!(config.validate().is_ok());
+
+        let mut value = base();
+        let err = apply_override(&mut value, "listen_addr.port", json!(1)).unwrap_err();
+        assert!(err.to_string().contains("non-table"));
+    }
+
+    #[test]
+    fn validation_reports_every_invalid_field() {
+        let config = with_env(&[
+            ("PROCURATOR_WORKER_MASTER_ADDR", "127.0.0.1:6000"),
+            ("PROCURATOR_WORKER_VMS__WORKER_ID", " "),
+            ("PROCURATOR_WORKER_VMS__MAX_VMS", "0"),
+            (
+                "PROCURATOR_WORKER_CLOUD_HYPERVISOR__BINARY_PATH",
+                "/nonexistent/ch",
+            ),
+            ("PROCURATOR_WORKER_CLOUD_HYPERVISOR__IMAGE_DIR", "images"),
+            ("PROCURATOR_WORKER_CLOUD_HYPERVISOR__STAGING_DIR", "nvme"),
+            (
+                "PROCURATOR_WORKER_CLOUD_HYPERVISOR__VHOST_USER_NET__SOCKET_DIR",
+                "vhost",
+            ),
+            (
+                "PROCURATOR_WORKER_CLOUD_HYPERVISOR__SOCKET_TIMEOUT_SECS",
+                "0",
+            ),
+            (
+                "PROCURATOR_WORKER_CLOUD_HYPERVISOR__HOTPLUG",
+                r#"{"max_vcpus": 0, "max_memory_mb": 4096}"#,
+            ),
+            (
+                "PROCURATOR_WORKER_CLOUD_HYPERVISOR__BRIDGE_NAME",
+                "bridge-name-too-long",
+            ),
+            (
+                "PROCURATOR_WORKER_CLOUD_HYPERVISOR__TRUSTED_PUBLIC_KEYS",
+                r#"["nokey"]"#,
+            ),
+            (
+                "PROCURATOR_WORKER_DNS_PROXY__LISTEN_ADDR",
+                "192.168.100.1:53",
+            ),
+            ("PROCURATOR_WORKER_DNS_PROXY__UPSTREAMS", "[]"),
+            ("PROCURATOR_WORKER_DNS_PROXY__UPSTREAM_TIMEOUT_MS", "0"),
+            ("PROCURATOR_WORKER_DNS_PROXY__MAX_IN_FLIGHT", "0"),
+            ("PROCURATOR_WORKER_BOOT_WATCHDOG__TIMEOUT_SECS", "0"),
+            (
+                "PROCURATOR_WORKER_MEMORY_PRESSURE__THRESHOLD_PERCENT",
+                "120",
+            ),
+            ("PROCURATOR_WORKER_STORE_JANITOR__GC_ROOTS_DIR", "gcroots"),
+            (
+                "PROCURATOR_WORKER_STORE_JANITOR__LOW_WATERMARK_PERCENT",
+                "90",
+            ),
+            ("PROCURATOR_WORKER_DRAIN__TIMEOUT_SECS", "0"),
+            ("PROCURATOR_WORKER_VMM_MONITOR__API_TIMEOUT_MS", "0"),
+        ]);
+        assert_eq!(
+            issue_keys(&config),
+            [
+                "master_addr",
+                "vms.worker_id",
+                "vms.max_vms",
+                "cloud_hypervisor.binary_path",
+                "cloud_hypervisor.image_dir",
+                "cloud_hypervisor.staging_dir",
+                "cloud_hypervisor.vhost_user_net.socket_dir",
+                "cloud_hypervisor.socket_timeout_secs",
+                "cloud_hypervisor.hotplug.max_vcpus",
+                "cloud_hypervisor.bridge_name",
+                "cloud_hypervisor.trusted_public_keys[0]",
+                "dns_proxy.upstreams",
+                "dns_proxy.upstream_timeout_ms",
+                "dns_proxy.max_in_flight",
+                "boot_watchdog.timeout_secs",
+                "memory_pressure.threshold_percent",
+                "store_janitor.gc_roots_dir",
+                "store_janitor.low_watermark_percent",
+                "drain.timeout_secs",
+                "vmm_monitor.api_timeout_ms",
+            ]
+        );
+
+        let mut value = base();
+        value.as_object_mut().unwrap().remove("cloud_hypervisor");
+        let mut config: Config = serde_json::from_value(value).unwrap();
+        assert_eq!(issue_keys(&config), ["cloud_hypervisor"]);
+        config.simulate();
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,155 @@
+//! Disk usage accounting, per image and per VM
+//!
+//! A worker's disk holds three kinds of VM data:
+//!
+//! - **images**: the store closure a VM boots from (toplevel, kernel,
+//!   initrd, disk image and everything they reference). VMs booting the same
+//!   toplevel share it, so it is counted once per image;
+//! - **volumes**: the writable disk copy of each VM;
+//! - **logs**: the serial console and hypervisor logs of each VM.
+//!
+//! The backend measures each VM (see
+//! [`VmmBackend::disk_usage`](crate::vmm::VmmBackend::disk_usage)) and
+//! [`DiskUsage`] adds them up for `Worker.read`. Sizes are allocated bytes,
+//! so a sparse disk copy counts for what it really takes.
+
+use std::collections::BTreeMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use tokio::process::Command;
+use tracing::debug;
+
+use crate::dto::VmError;
+
+/// What one VM takes on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VmDiskUsage {
+    /// Store closure of the image it boots
+    pub closure_bytes: u64,
+    /// Writable disk copy
+    pub volume_bytes: u64,
+    /// Serial console and hypervisor logs
+    pub log_bytes: u64,
+}
+
+/// What the VMs of a worker take on disk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// Closure size per image toplevel
+    pub images: BTreeMap<String, u64>,
+    /// Volume size per VM id
+    pub vms: BTreeMap<String, u64>,
+    pub log_bytes: u64,
+}
+
+impl DiskUsage {
+    /// Account for VM `vm_id` booting `toplevel`.
+    pub fn add(&mut self, vm_id: &str, toplevel: &str, usage: VmDiskUsage) {
+        self.images
+            .insert(toplevel.to_string(), usage.closure_bytes);
+        self.vms.insert(vm_id.to_string(), usage.volume_bytes);
+        self.log_bytes += usage.log_bytes;
+    }
+
+    /// Images, volumes and logs together.
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.images.values().sum::<u64>() + self.vms.values().sum::<u64>() + self.log_bytes
+    }
+}
+
+/// Bytes allocated to `path` and, for a directory, everything below it.
+/// Symlinks are not followed; missing or unreadable entries count as zero.
+#[must_use]
+pub fn allocated_bytes(path: &Path) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+    // st_blocks is in 512-byte units whatever the filesystem block size
+    let own = metadata.blocks() * 512;
+    if !metadata.is_dir() {
+        return own;
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return own;
+    };
+    own + entries
+        .filter_map(Result::ok)
+        .map(|entry| allocated_bytes(&entry.path()))
+        .sum::<u64>()
+}
+
+/// Size of the closure of `store_paths` together, each path counted once.
+///
+/// # Errors
+///
+/// - if `nix path-info` cannot be run or fails, e.g. on a path that is not
+///   in the store
+pub async fn closure_bytes(store_paths: &[String]) -> Result<u64, VmError> {
+    let output = Command::new("nix")
+        .args(["--extra-experimental-features", "nix-command"])
+        .args(["path-info", "--recursive", "--size"])
+        .args(store_paths)
+        .output()
+        .await
+        .map_err(|e| VmError::Internal(format!("failed to run nix path-info: {e}")))?;
+    if !output.status.success() {
+        return Err(VmError::Internal(format!(
+            "nix path-info failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let total = sum_sizes(&String::from_utf8_lossy(&output.stdout));
+    debug!(?store_paths, total, "Measured image closure");
+    Ok(total)
+}
+
+/// Sum of the `<path> <size>` lines of `nix path-info --size`.
+fn sum_sizes(stdout: &str) -> u64 {
+    stdout
+        .lines()
+        .filter_map(|line| line.split_whitespace().last()?.parse::<u64>().ok())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_counted_once() {
+        let usage = VmDiskUsage {
+            closure_bytes: 100,
+            volume_bytes: 10,
+            log_bytes: 1,
+        };
+        let mut disk = DiskUsage::default();
+        disk.add("vm-a", "/nix/store/aaaa-nixos-system", usage);
+        disk.add("vm-b", "/nix/store/aaaa-nixos-system", usage);
+        assert_eq!(disk.images.len(), 1);
+        assert_eq!(disk.vms.len(), 2);
+        assert_eq!(disk.total(), 100 + 2 * 10 + 2);
+    }
+
+    #[test]
+    fn directories_add_up_their_files() {
+        let dir = std::env::temp_dir().join(format!("pcr-disk-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("vm")).unwrap();
+        std::fs::write(dir.join("vm/serial.log"), vec![b'x'; 64 * 1024]).unwrap();
+        let file = allocated_bytes(&dir.join("vm/serial.log"));
+        assert!(file >= 64 * 1024);
+        assert!(allocated_bytes(&dir) >= file);
+        assert_eq!(allocated_bytes(&dir.join("missing")), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn path_info_sizes_are_summed() {
+        let stdout = "/nix/store/aaaa-nixos-system\t  1024\n\
+                      /nix/store/bbbb-kernel\t2048\n\
+                      warning: something\n";
+        assert_eq!(sum_sizes(stdout), 3072);
+    }
+}
@@ -0,0 +1,547 @@
+//! # Allowed-domain DNS proxy
+//!
+//! VMs resolve names through the worker instead of an open resolver on the
+//! bridge. The proxy answers only for the `network_allowed_domains` of the
+//! VM asking, forwards those queries upstream, and logs every other one, so
+//! there is both enforcement and an audit trail of what VMs tried to reach:
+//!
+//! - **Who is asking** — every VM has its own TAP device on the bridge,
+//!   named after its id ([`tap_name`]). A guest picks its own MAC and
+//!   address, so neither says which VM it is: the source address of a
+//!   query leads to a MAC through the kernel neighbour table, and the
+//!   bridge's forwarding table tells which port that MAC was last seen on.
+//!   That port, the TAP the answer is sent through, is the VM asking. A
+//!   guest claiming another VM's MAC is still seen on its own TAP.
+//! - **Policy** — a domain allows itself and its subdomains: `github.com`
+//!   lets `api.github.com` resolve. A VM without allowed domains is not
+//!   restricted, as in its guest-side firewall. Queries from addresses that
+//!   are no known VM are refused.
+//! - **Answers** — denied names get `NXDOMAIN`, so clients fail at once
+//!   instead of retrying; allowed ones are forwarded to the upstreams in
+//!   order, `SERVFAIL` when none answers.
+//! - **Audit** — denied and refused queries are logged at `warn` with the
+//!   VM id, the name and the record type, under the `dns_audit` target, and
+//!   counted in `procurator_worker_dns_queries_total`.
+//!
+//! At most `max_in_flight` queries are answered at once; while they are, the
+//! next ones wait in the socket's receive buffer and are dropped by the
+//! kernel when it is full, so a VM flooding the proxy cannot exhaust the
+//! worker. Only UDP is served. Guests that retry over TCP after a truncated
+//! answer get no response for it.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::metrics;
+pub use crate::vmm::cloud_hypervisor::tap_name;
+
+/// Kernel neighbour table, IPv4 entries.
+const NEIGHBOURS: &str = "/proc/net/arp";
+
+/// Bridges live under it, each with its forwarding table in `brforward`
+/// and its ports in `brif`.
+const SYS_NET: &str = "/sys/class/net";
+
+/// Size of a `struct __fdb_entry` in `brforward`.
+const FDB_ENTRY_LEN: usize = 16;
+
+/// Larger than any query a guest sends, EDNS included.
+const MAX_QUERY: usize = 4096;
+const MAX_RESPONSE: usize = 65_535;
+
+const HEADER_LEN: usize = 12;
+const RCODE_SERVFAIL: u8 = 2;
+const RCODE_NXDOMAIN: u8 = 3;
+const RCODE_REFUSED: u8 = 5;
+
+// ─── Configuration ─────────────────────────────────────────────────────────
+
+/// DNS proxy for VMs; disabled when the section is absent.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DnsProxySection {
+    /// Address VMs are told to use as resolver, usually the bridge address
+    /// on port 53.
+    pub listen_addr: SocketAddr,
+    /// Resolvers allowed queries are forwarded to, tried in order.
+    pub upstreams: Vec<SocketAddr>,
+    /// How long each upstream gets to answer.
+    #[serde(default = "default_upstream_timeout_ms")]
+    pub upstream_timeout_ms: u64,
+    /// Queries answered at once; the next ones wait to be received.
+    #[serde(default = "default_max_in_flight")]
+    pub max_in_flight: usize,
+}
+
+fn default_upstream_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_max_in_flight() -> usize {
+    256
+}
+
+// ─── Policies ──────────────────────────────────────────────────────────────
+
+/// Whether a VM may resolve a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug)]
+struct VmPolicy {
+    vm_id: String,
+    /// Lowercase, without leading `*.` or trailing dot; empty allows all
+    domains: Vec<String>,
+}
+
+/// Allowed domains of every VM, by TAP device. Shared between the VM
+/// manager, which registers VMs, and the proxy, which reads them.
+#[derive(Debug, Clone, Default)]
+pub struct DnsPolicies {
+    by_tap: Arc<RwLock<HashMap<String, VmPolicy>>>,
+}
+
+impl DnsPolicies {
+    /// Let `vm_id` resolve `domains` and their subdomains, replacing what it
+    /// was allowed before.
+    pub fn allow(&self, vm_id: &str, domains: &[String]) {
+        let domains = domains
+            .iter()
+            .map(|d| normalize(d.trim_start_matches("*.")))
+            .filter(|d| !d.is_empty())
+            .collect();
+        let policy = VmPolicy {
+            vm_id: vm_id.to_string(),
+            domains,
+        };
+        self.write().insert(tap_name(vm_id), policy);
+    }
+
+    pub fn remove(&self, vm_id: &str) {
+        self.write().remove(&tap_name(vm_id));
+    }
+
+    /// The VM behind `tap` and whether it may resolve `name`; `None` when
+    /// `tap` is no known VM's.
+    #[must_use]
+    pub fn check(&self, tap: &str, name: &str) -> Option<(String, Verdict)> {
+        let by_tap = self.by_tap.read().unwrap_or_else(PoisonError::into_inner);
+        let policy = by_tap.get(tap)?;
+        let verdict = if policy.domains.is_empty() || domain_allowed(&policy.domains, name) {
+            Verdict::Allow
+        } else {
+            Verdict::Deny
+        };
+        Some((policy.vm_id.clone(), verdict))
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, VmPolicy>> {
+        // Single inserts and removes, a panicking holder cannot leave the
+        // map half-written
+        self.by_tap.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// MAC the VM's network interface is created with: locally administered,
+/// derived from the VM id with FNV-1a so it is the same after a worker
+/// restart. The guest can change it, so it does not identify the VM.
+#[must_use]
+pub fn guest_mac(vm_id: &str) -> String {
+    let hash = vm_id.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    let b = hash.to_be_bytes();
+    format!(
+        "02:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        b[3], b[4], b[5], b[6], b[7]
+    )
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// True if `name` is one of `domains` or a subdomain of one.
+fn domain_allowed(domains: &[String], name: &str) -> bool {
+    let name = normalize(name);
+    domains.iter().any(|domain| {
+        name == *domain
+            || name
+                .strip_suffix(domain.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+// ─── Wire format ───────────────────────────────────────────────────────────
+
+/// The single question of a standard query
+#[derive(Debug, PartialEq, Eq)]
+struct Question {
+    name: String,
+    qtype: u16,
+    /// Length of the header and question, echoed in replies
+    len: usize,
+}
+
+/// The question of `packet` if it is a standard query with exactly one;
+/// anything else is dropped.
+fn parse_query(packet: &[u8]) -> Option<Question> {
+    let header = packet.get(..HEADER_LEN)?;
+    let is_query = header[2] & 0x80 == 0;
+    let opcode = (header[2] >> 3) & 0x0f;
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    if !is_query || opcode != 0 || questions != 1 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut at = HEADER_LEN;
+    loop {
+        let len = usize::from(*packet.get(at)?);
+        at += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers have no place in a question
+        if len > 63 {
+            return None;
+        }
+        let label = packet.get(at..at + len)?;
+        labels.push(std::str::from_utf8(label).ok()?.to_ascii_lowercase());
+        at += len;
+    }
+    let name = labels.join(".");
+    if name.len() > 253 {
+        return None;
+    }
+    let qtype = packet.get(at..at + 2)?;
+    let qtype = u16::from_be_bytes([qtype[0], qtype[1]]);
+    // QTYPE and QCLASS
+    let len = at + 4;
+    if packet.len() < len {
+        return None;
+    }
+    Some(Question { name, qtype, len })
+}
+
+/// Answer to `query` carrying only `rcode` and the question.
+fn reply(query: &[u8], question: &Question, rcode: u8) -> Vec<u8> {
+    let mut response = query[..question.len].to_vec();
+    // QR, the query's opcode and RD, then RA and the code
+    response[2] = 0x80 | (query[2] & 0x79);
+    response[3] = 0x80 | rcode;
+    // One question, no answer, authority or additional records
+    response[6..HEADER_LEN].fill(0);
+    response
+}
+
+// ─── Neighbour and forwarding tables ───────────────────────────────────────
+
+/// MAC the kernel has for `ip` and the interface it is reached on, from the
+/// text of `/proc/net/arp`.
+fn neighbour(table: &str, ip: Ipv4Addr) -> Option<([u8; 6], String)> {
+    let ip = ip.to_string();
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [address, _, _, mac, _, device] = fields[..] else {
+            return None;
+        };
+        let mac = parse_mac(mac).filter(|mac| *mac != [0; 6])?;
+        (address == ip).then(|| (mac, device.to_string()))
+    })
+}
+
+fn parse_mac(text: &str) -> Option<[u8; 6]> {
+    let mut mac = [0; 6];
+    let mut octets = text.split(':');
+    for byte in &mut mac {
+        *byte = u8::from_str_radix(octets.next()?, 16).ok()?;
+    }
+    octets.next().is_none().then_some(mac)
+}
+
+/// Port `mac` was learned on, from the binary `brforward` of a bridge.
+/// The bridge's own addresses are on no port.
+fn fdb_port(table: &[u8], mac: [u8; 6]) -> Option<u16> {
+    table.chunks_exact(FDB_ENTRY_LEN).find_map(|entry| {
+        let is_local = entry[7] != 0;
+        (entry[..6] == mac && !is_local).then_some(u16::from_be_bytes([entry[12], entry[6]]))
+    })
+}
+
+/// Port number in a `brif/<port>/port_no` file, written as `0x1`.
+fn parse_port_no(text: &str) -> Option<u16> {
+    u16::from_str_radix(text.trim().strip_prefix("0x")?, 16).ok()
+}
+
+/// TAP device a query from `ip` came in through.
+async fn tap_of(ip: IpAddr) -> Option<String> {
+    let ip = match ip {
+        IpAddr::V4(v4) => v4,
+        IpAddr::V6(v6) => v6.to_ipv4_mapped()?,
+    };
+    let table = tokio::fs::read_to_string(NEIGHBOURS)
+        .await
+        .inspect_err(|e| warn!(error = %e, "Cannot read the neighbour table"))
+        .ok()?;
+    let (mac, bridge) = neighbour(&table, ip)?;
+
+    let bridge = std::path::Path::new(SYS_NET).join(bridge);
+    // Not a bridge: the client is not behind a TAP
+    let fdb = tokio::fs::read(bridge.join("brforward")).await.ok()?;
+    let port = fdb_port(&fdb, mac)?;
+    let mut ports = tokio::fs::read_dir(bridge.join("brif")).await.ok()?;
+    while let Ok(Some(entry)) = ports.next_entry().await {
+        let port_no = tokio::fs::read_to_string(entry.path().join("port_no")).await;
+        if port_no.ok().as_deref().and_then(parse_port_no) == Some(port) {
+            return entry.file_name().into_string().ok();
+        }
+    }
+    None
+}
+
+// ─── Serving ───────────────────────────────────────────────────────────────
+
+/// Answer VM queries on `section.listen_addr` until `stop` is cancelled.
+///
+/// # Errors
+///
+/// - if the listener cannot be bound or stops receiving
+pub async fn serve(
+    section: DnsProxySection,
+    policies: DnsPolicies,
+    stop: CancellationToken,
+) -> io::Result<()> {
+    let socket = Arc::new(UdpSocket::bind(section.listen_addr).await?);
+    let upstreams: Arc<[SocketAddr]> = section.upstreams.into();
+    let timeout = Duration::from_millis(section.upstream_timeout_ms);
+    let in_flight = Arc::new(Semaphore::new(section.max_in_flight));
+    tracing::info!(
+        listen_addr = %section.listen_addr,
+        upstreams = ?upstreams,
+        max_in_flight = section.max_in_flight,
+        "Serving DNS for VMs"
+    );
+
+    let mut buf = vec![0; MAX_QUERY];
+    loop {
+        // Wait for a slot before receiving, so the backlog stays in the
+        // socket buffer rather than in tasks
+        let permit = tokio::select! {
+            () = stop.cancelled() => return Ok(()),
+            permit = Arc::clone(&in_flight).acquire_owned() => {
+                permit.expect("the semaphore is never closed")
+            }
+        };
+        let (len, client) = tokio::select! {
+            () = stop.cancelled() => return Ok(()),
+            received = socket.recv_from(&mut buf) => received?,
+        };
+        let query = buf[..len].to_vec();
+        let socket = Arc::clone(&socket);
+        let policies = policies.clone();
+        let upstreams = Arc::clone(&upstreams);
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Some(response) = answer(&query, client, &policies, &upstreams, timeout).await
+                && let Err(e) = socket.send_to(&response, client).await
+            {
+                debug!(%client, error = %e, "Cannot send DNS response");
+            }
+        });
+    }
+}
+
+async fn answer(
+    query: &[u8],
+    client: SocketAddr,
+    policies: &DnsPolicies,
+    upstreams: &[SocketAddr],
+    timeout: Duration,
+) -> Option<Vec<u8>> {
+    let question = parse_query(query)?;
+    let checked = match tap_of(client.ip()).await {
+        Some(tap) => policies.check(&tap, &question.name),
+        None => None,
+    };
+    let Some((vm_id, verdict)) = checked else {
+        metrics::dns_query("unknown_client");
+        warn!(
+            target: "dns_audit",
+            %client,
+            name = %question.name,
+            qtype = question.qtype,
+            "DNS query refused, not from a known VM"
+        );
+        return Some(reply(query, &question, RCODE_REFUSED));
+    };
+    if verdict == Verdict::Deny {
+        metrics::dns_query("denied");
+        warn!(
+            target: "dns_audit",
+            vm_id = %vm_id,
+            %client,
+            name = %question.name,
+            qtype = question.qtype,
+            "DNS query denied"
+        );
+        return Some(reply(query, &question, RCODE_NXDOMAIN));
+    }
+
+    debug!(vm_id = %vm_id, name = %question.name, qtype = question.qtype, "DNS query allowed");
+    for upstream in upstreams {
+        match forward(query, *upstream, timeout).await {
+            Ok(response) => {
+                metrics::dns_query("allowed");
+                return Some(response);
+            }
+            Err(e) => debug!(%upstream, error = %e, "Upstream resolver failed"),
+        }
+    }
+    metrics::dns_query("upstream_error");
+    warn!(vm_id = %vm_id, name = %question.name, "No upstream resolver answered");
+    Some(reply(query, &question, RCODE_SERVFAIL))
+}
+
+/// Send `query` to `upstream` and wait for the response with the same id.
+async fn forward(query: &[u8], upstream: SocketAddr, timeout: Duration) -> io::Result<Vec<u8>> {
+    let local: SocketAddr = if upstream.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(upstream).await?;
+    socket.send(query).await?;
+
+    let mut buf = vec![0; MAX_RESPONSE];
+    let len = tokio::time::timeout(timeout, socket.recv(&mut buf))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    if len < HEADER_LEN || buf[..2] != query[..2] {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "response does not match the query",
+        ));
+    }
+    buf.truncate(len);
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Query for `name` with id 0x1234, RD set.
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            packet.push(u8::try_from(label.len()).unwrap());
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn queries_are_parsed_and_answered() {
+        let packet = query("API.GitHub.com", 28);
+        let question = parse_query(&packet).unwrap();
+        assert_eq!(question.name, "api.github.com");
+        assert_eq!(question.qtype, 28);
+        assert_eq!(question.len, packet.len());
+
+        let response = reply(&packet, &question, RCODE_NXDOMAIN);
+        assert_eq!(response[..2], [0x12, 0x34]);
+        assert_eq!(response[2], 0x81, "QR and RD");
+        assert_eq!(response[3], 0x83, "RA and NXDOMAIN");
+        assert_eq!(response[4..HEADER_LEN], [0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(response[HEADER_LEN..], packet[HEADER_LEN..]);
+
+        // Responses, truncated packets and compressed names are not queries
+        let mut response = packet.clone();
+        response[2] |= 0x80;
+        assert_eq!(parse_query(&response), None);
+        assert_eq!(parse_query(&packet[..packet.len() - 1]), None);
+        let mut compressed = packet[..HEADER_LEN].to_vec();
+        compressed.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1]);
+        assert_eq!(parse_query(&compressed), None);
+    }
+
+    #[test]
+    fn domains_allow_their_subdomains() {
+        let policies = DnsPolicies::default();
+        policies.allow(
+            "vm-1",
+            &["github.com".to_string(), "*.pypi.org.".to_string()],
+        );
+        policies.allow("vm-2", &[]);
+        let tap = tap_name("vm-1");
+
+        for (name, verdict) in [
+            ("github.com", Verdict::Allow),
+            ("api.github.com.", Verdict::Allow),
+            ("files.PYPI.org", Verdict::Allow),
+            ("notgithub.com", Verdict::Deny),
+            ("github.com.evil.net", Verdict::Deny),
+        ] {
+            assert_eq!(
+                policies.check(&tap, name),
+                Some(("vm-1".to_string(), verdict)),
+                "{name}"
+            );
+        }
+        assert_eq!(
+            policies.check(&tap_name("vm-2"), "anything.example"),
+            Some(("vm-2".to_string(), Verdict::Allow))
+        );
+        assert_eq!(policies.check(&guest_mac("vm-1"), "github.com"), None);
+
+        policies.remove("vm-1");
+        assert_eq!(policies.check(&tap, "github.com"), None);
+    }
+
+    #[test]
+    fn clients_are_found_on_their_bridge_port() {
+        let table = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.100.12   0x1         0x2         02:AA:bb:cc:dd:ee     *        br0
+192.168.100.13   0x1         0x0         00:00:00:00:00:00     *        br0
+";
+        let mac = [0x02, 0xaa, 0xbb, 0xcc, 0xdd, 0xee];
+        assert_eq!(
+            neighbour(table, Ipv4Addr::new(192, 168, 100, 12)),
+            Some((mac, "br0".to_string()))
+        );
+        assert_eq!(neighbour(table, Ipv4Addr::new(192, 168, 100, 13)), None);
+        assert_eq!(neighbour(table, Ipv4Addr::new(192, 168, 100, 14)), None);
+
+        // The bridge's own address, then the client's, learned on port 0x103
+        let mut fdb = vec![
+            0x02, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        fdb.extend_from_slice(&[
+            0x02, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 3, 0, 0, 0, 0, 0, 1, 0, 0, 0,
+        ]);
+        assert_eq!(fdb_port(&fdb, mac), Some(0x103));
+        assert_eq!(fdb_port(&fdb[..FDB_ENTRY_LEN], mac), None);
+        assert_eq!(parse_port_no("0x103\n"), Some(0x103));
+
+        let mac = guest_mac("0190a4b2-7c3e-7def-8abc-0123456789ab");
+        assert_eq!(mac, guest_mac("0190a4b2-7c3e-7def-8abc-0123456789ab"));
+        assert!(mac.starts_with("02:") && mac.len() == 17);
+    }
+}
@@ -0,0 +1,180 @@
+//! # Drain
+//!
+//! Without a `drain` section, deleting a VM shuts it down through the VMM
+//! and kills the process at once, cutting off whatever the guest was doing.
+//! With one, `Delete` first asks the guest to power off and reports the VM
+//! `draining`; it is removed once its VMM exited, and force-stopped like
+//! before if that takes longer than `timeout_secs`.
+//!
+//! `signal` decides how the guest is asked: `power-button` presses the ACPI
+//! power button, so the guest runs its own shutdown and the VMM exits after
+//! it; `shutdown` has the VMM stop the guest, which it does before replying,
+//! so the VM is removed at the next check.
+//!
+//! Only `booting` and `running` VMs drain. Stopped and `boot-failed` ones
+//! have no guest to wait for, and VMs whose signal fails are force-stopped
+//! right away. Redeploys, restarts after failed boots and the worker's own
+//! shutdown do not drain either: they replace or stop the VM on purpose.
+
+use std::fmt;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Enables draining; VMs are killed at once when the section is absent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DrainSection {
+    /// How long a VM may take to power off before it is force-stopped.
+    pub timeout_secs: u64,
+    /// How the guest is asked to power off.
+    pub signal: DrainSignal,
+}
+
+impl Default for DrainSection {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 30,
+            signal: DrainSignal::PowerButton,
+        }
+    }
+}
+
+/// How a draining VM's guest is asked to power off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DrainSignal {
+    /// Press the ACPI power button and wait for the VMM to exit
+    PowerButton,
+    /// Have the VMM shut the guest down
+    Shutdown,
+}
+
+impl DrainSignal {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DrainSignal::PowerButton => "power-button",
+            DrainSignal::Shutdown => "shutdown",
+        }
+    }
+}
+
+impl fmt::Display for DrainSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How a drain ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrainOutcome {
+    /// The guest powered off
+    Clean,
+    /// The timeout passed first
+    Forced,
+}
+
+impl DrainOutcome {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DrainOutcome::Clean => "clean",
+            DrainOutcome::Forced => "forced",
+        }
+    }
+}
+
+/// Decides when a draining VM is done, see the module docs.
+#[derive(Debug, Clone)]
+pub struct Drain {
+    timeout: Duration,
+    signal: DrainSignal,
+}
+
+impl Drain {
+    pub fn new(timeout: Duration, signal: DrainSignal) -> Self {
+        Self { timeout, signal }
+    }
+
+    #[must_use]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    #[must_use]
+    pub fn signal(&self) -> DrainSignal {
+        self.signal
+    }
+
+    /// How often draining VMs are checked: a tenth of the timeout, between
+    /// half a second and five seconds.
+    #[must_use]
+    pub fn check_interval(&self) -> Duration {
+        (self.timeout / 10).clamp(Duration::from_millis(500), Duration::from_secs(5))
+    }
+
+    /// How the drain of a VM signalled `elapsed` ago ended, given whether
+    /// its VMM `exited`; `None` while it goes on.
+    #[must_use]
+    pub fn outcome(&self, exited: bool, elapsed: Duration) -> Option<DrainOutcome> {
+        if exited || self.signal == DrainSignal::Shutdown {
+            Some(DrainOutcome::Clean)
+        } else if elapsed >= self.timeout {
+            Some(DrainOutcome::Forced)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<DrainSection> for Drain {
+    fn from(section: DrainSection) -> Self {
+        Self {
+            timeout: Duration::from_secs(section.timeout_secs),
+            signal: section.signal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_button_drains_until_the_vmm_exits_or_the_timeout() {
+        let drain = Drain::new(Duration::from_secs(30), DrainSignal::PowerButton);
+        assert_eq!(drain.outcome(false, Duration::from_secs(1)), None);
+        assert_eq!(
+            drain.outcome(true, Duration::from_secs(1)),
+            Some(DrainOutcome::Clean)
+        );
+        assert_eq!(
+            drain.outcome(false, Duration::from_secs(30)),
+            Some(DrainOutcome::Forced)
+        );
+    }
+
+    #[test]
+    fn shutdown_is_done_once_signalled() {
+        let drain = Drain::new(Duration::from_secs(30), DrainSignal::Shutdown);
+        assert_eq!(
+            drain.outcome(false, Duration::ZERO),
+            Some(DrainOutcome::Clean)
+        );
+    }
+
+    #[test]
+    fn section_defaults_to_the_power_button() {
+        let section: DrainSection = serde_json::from_str("{}").unwrap();
+        assert_eq!(section.timeout_secs, 30);
+        assert_eq!(section.signal, DrainSignal::PowerButton);
+
+        let section: DrainSection = serde_json::from_str(r#"{"signal": "shutdown"}"#).unwrap();
+        assert_eq!(section.signal, DrainSignal::Shutdown);
+        assert_eq!(
+            Drain::from(section).check_interval(),
+            Duration::from_secs(3)
+        );
+    }
+}
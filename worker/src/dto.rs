@@ -4,46 +4,57 @@
 //! and replies via the embedded oneshot sender. No capnp types cross the channel —
 //! only plain Rust structs.
 
-use std::fmt;
-
-use serde::Deserialize;
+use commands::hashing::{self, ContentHash, VmSpecFields};
+use commands::labels::Labels;
+use commands::net_backend::NetBackend;
+use commands::vm_action::VmAction;
+use commands::vm_state::{Transition, VmState};
+use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, oneshot};
 
+use crate::disk_usage::DiskUsage;
+use crate::hugepages::HugePages;
+use crate::memory_pressure::PressureReport;
+use crate::store_janitor::JanitorReport;
+
 // ─── Error type that crosses the channel ───────────────────────────────────
 
 /// Errors returned by Node/VmManager through the oneshot reply.
 /// Converted to `capnp::Error` at the RPC boundary in Server.
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum VmError {
     /// The requested VM does not exist in the manager's table
+    #[error("VM not found: {0}")]
     NotFound(String),
     /// The CloudHypervisor REST call failed
+    #[error("cloud-hypervisor error: {0}")]
     Hypervisor(String),
     /// The CH process failed to spawn or died unexpectedly
+    #[error("process error: {0}")]
     ProcessFailed(String),
+    /// The image is not signed by a trusted key; the VM was not started
+    #[error("image not trusted: {0}")]
+    Untrusted(String),
+    /// The worker already runs its configured maximum of VMs
+    #[error("worker is at capacity ({0} VMs)")]
+    AtCapacity(usize),
+    /// The host cannot provide what the spec asks for, e.g. hugepages
+    #[error("cannot run on this worker: {0}")]
+    Unschedulable(String),
     /// The command channel is closed (Node is down)
+    #[error("VM manager is down")]
     ManagerDown,
+    /// Issuing or installing the VM's identity failed
+    #[error("cannot issue VM identity")]
+    Identity(#[from] crate::identity::IdentityError),
     /// Catch-all for unexpected failures
+    #[error("internal error: {0}")]
     Internal(String),
 }
 
-impl fmt::Display for VmError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            VmError::NotFound(id) => write!(f, "VM not found: {id}"),
-            VmError::Hypervisor(msg) => write!(f, "cloud-hypervisor error: {msg}"),
-            VmError::ProcessFailed(msg) => write!(f, "process error: {msg}"),
-            VmError::ManagerDown => write!(f, "VM manager is down"),
-            VmError::Internal(msg) => write!(f, "internal error: {msg}"),
-        }
-    }
-}
-
-impl std::error::Error for VmError {}
-
 impl From<VmError> for capnp::Error {
     fn from(e: VmError) -> Self {
-        capnp::Error::failed(e.to_string())
+        capnp::Error::failed(repo_outils::report(&e))
     }
 }
 
@@ -51,8 +62,9 @@ impl From<VmError> for capnp::Error {
 
 /// Internal representation of a VM's desired configuration.
 /// Built from capnp VmSpec in the Server, consumed by Node/VmManager.
-/// Also deserializable from the JSON produced by the Nix `vmSpecJson` output.
-#[derive(Debug, Clone, Deserialize)]
+/// Also deserializable from the JSON produced by the Nix `vmSpecJson` output,
+/// and serialized in the same shape into [`records`](crate::records).
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct VmSpec {
     toplevel: String,
@@ -63,6 +75,14 @@ pub struct VmSpec {
     cpu: u32,
     memory_mb: u32,
     network_allowed_domains: Vec<String>,
+    #[serde(default)]
+    labels: Labels,
+    #[serde(default)]
+    hugepages: bool,
+    #[serde(default)]
+    shared_memory: bool,
+    #[serde(default, with = "net_backend_name")]
+    net_backend: NetBackend,
 }
 
 impl VmSpec {
@@ -85,9 +105,47 @@ impl VmSpec {
             cpu,
             memory_mb,
             network_allowed_domains,
+            labels: Labels::new(),
+            hugepages: false,
+            shared_memory: false,
+            net_backend: NetBackend::Tap,
         }
     }
 
+    /// Back the guest memory with the host's hugepages, see
+    /// [`hugepages`](crate::hugepages).
+    #[must_use]
+    pub fn with_hugepages(mut self, hugepages: bool) -> Self {
+        self.hugepages = hugepages;
+        self
+    }
+
+    /// Map the guest memory shared, as virtio-fs and vhost-user devices
+    /// need. Closure boots always do, for the shared store.
+    #[must_use]
+    pub fn with_shared_memory(mut self, shared_memory: bool) -> Self {
+        self.shared_memory = shared_memory;
+        self
+    }
+
+    /// Attach the NIC through `net_backend` rather than a TAP device. Only
+    /// workers whose backend offers it run the VM.
+    #[must_use]
+    pub fn with_net_backend(mut self, net_backend: NetBackend) -> Self {
+        self.net_backend = net_backend;
+        self
+    }
+
+    /// Labels for selectors. They are not part of the [`content_hash`]:
+    /// relabelling a VM does not make it drift.
+    ///
+    /// [`content_hash`]: VmSpec::content_hash
+    #[must_use]
+    pub fn with_labels(mut self, labels: Labels) -> Self {
+        self.labels = labels;
+        self
+    }
+
     pub fn toplevel(&self) -> &str {
         &self.toplevel
     }
@@ -119,6 +177,122 @@ impl VmSpec {
     pub fn network_allowed_domains(&self) -> &[String] {
         &self.network_allowed_domains
     }
+
+    #[must_use]
+    pub fn hugepages(&self) -> bool {
+        self.hugepages
+    }
+
+    #[must_use]
+    pub fn shared_memory(&self) -> bool {
+        self.shared_memory
+    }
+
+    #[must_use]
+    pub fn net_backend(&self) -> NetBackend {
+        self.net_backend
+    }
+
+    /// How the VM boots: from its disk image, or straight from the
+    /// toplevel when the spec names no disk image.
+    #[must_use]
+    pub fn boot_mode(&self) -> BootMode {
+        if self.disk_image_path.is_empty() && !self.toplevel.is_empty() {
+            BootMode::Closure
+        } else {
+            BootMode::DiskImage
+        }
+    }
+
+    /// Kernel to boot, the closure's own `kernel` when the spec leaves it
+    /// empty in closure mode.
+    #[must_use]
+    pub fn boot_kernel(&self) -> String {
+        self.closure_default(&self.kernel_path, "kernel")
+    }
+
+    /// Initrd to boot, the closure's own `initrd` when the spec leaves it
+    /// empty in closure mode.
+    #[must_use]
+    pub fn boot_initrd(&self) -> String {
+        self.closure_default(&self.initrd_path, "initrd")
+    }
+
+    fn closure_default(&self, path: &str, file: &str) -> String {
+        if path.is_empty() && self.boot_mode() == BootMode::Closure {
+            format!("{}/{file}", self.toplevel.trim_end_matches('/'))
+        } else {
+            path.to_string()
+        }
+    }
+
+    #[must_use]
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
+    /// Whether `other` runs the same closure as this spec and only asks
+    /// for other vCPUs or memory, so a booted VM can be resized to it.
+    #[must_use]
+    pub fn only_resources_differ(&self, other: &VmSpec) -> bool {
+        let resized = VmSpec {
+            cpu: other.cpu,
+            memory_mb: other.memory_mb,
+            ..self.clone()
+        };
+        resized.content_hash() == other.content_hash()
+    }
+
+    /// Canonical hash of the spec, see [`commands::hashing`].
+    pub fn content_hash(&self) -> ContentHash {
+        let domains: Vec<&str> = self
+            .network_allowed_domains
+            .iter()
+            .map(String::as_str)
+            .collect();
+        hashing::vm_spec_hash(&VmSpecFields {
+            toplevel: &self.toplevel,
+            kernel_path: &self.kernel_path,
+            initrd_path: &self.initrd_path,
+            disk_image_path: &self.disk_image_path,
+            cmdline: &self.cmdline,
+            cpu: self.cpu,
+            memory_mb: self.memory_mb,
+            network_allowed_domains: &domains,
+            hugepages: self.hugepages,
+            shared_memory: self.shared_memory,
+            net_backend: self.net_backend,
+        })
+    }
+}
+
+/// [`NetBackend`] stored by its name, as in the Nix specs.
+mod net_backend_name {
+    use commands::net_backend::NetBackend;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        backend: &NetBackend,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(backend.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NetBackend, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Where a VM's root filesystem comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootMode {
+    /// A writable copy of `disk_image_path`, the `mkVmImage` output
+    DiskImage,
+    /// The NixOS system closure at `toplevel`: its kernel, initrd and
+    /// `init`, with the host store shared into the guest
+    Closure,
 }
 
 /// Internal representation of a VM's observed status.
@@ -127,20 +301,24 @@ impl VmSpec {
 pub struct VmInfo {
     id: String,
     worker_id: String,
-    status: VmStatus,
+    status: VmState,
     desired_hash: String,
     observed_hash: String,
     metrics: VmMetrics,
+    labels: Labels,
+    boot_log: Option<String>,
+    transitions: Vec<Transition>,
 }
 
 impl VmInfo {
     pub fn new(
         id: String,
         worker_id: String,
-        status: VmStatus,
+        status: VmState,
         desired_hash: String,
         observed_hash: String,
         metrics: VmMetrics,
+        labels: Labels,
     ) -> Self {
         Self {
             id,
@@ -149,9 +327,26 @@ impl VmInfo {
             desired_hash,
             observed_hash,
             metrics,
+            labels,
+            boot_log: None,
+            transitions: Vec::new(),
         }
     }
 
+    /// Serial log tail of the boot that left the VM `boot-failed`.
+    #[must_use]
+    pub fn with_boot_log(mut self, boot_log: Option<String>) -> Self {
+        self.boot_log = boot_log;
+        self
+    }
+
+    /// Latest state changes of the VM, oldest first.
+    #[must_use]
+    pub fn with_transitions(mut self, transitions: Vec<Transition>) -> Self {
+        self.transitions = transitions;
+        self
+    }
+
     pub fn id(&self) -> &str {
         &self.id
     }
@@ -160,8 +355,9 @@ impl VmInfo {
         &self.worker_id
     }
 
-    pub fn status(&self) -> &VmStatus {
-        &self.status
+    #[must_use]
+    pub fn status(&self) -> VmState {
+        self.status
     }
 
     pub fn desired_hash(&self) -> &str {
@@ -175,22 +371,20 @@ impl VmInfo {
     pub fn metrics(&self) -> &VmMetrics {
         &self.metrics
     }
-}
 
-#[derive(Debug, Clone)]
-pub enum VmStatus {
-    Running,
-}
+    #[must_use]
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
 
-impl VmStatus {
-    pub fn as_str(&self) -> &str {
-        match self {
-            VmStatus::Running => "running",
-        }
+    #[must_use]
+    pub fn boot_log(&self) -> Option<&str> {
+        self.boot_log.as_deref()
     }
 
-    pub fn is_drifted(&self, desired: &str, observed: &str) -> bool {
-        desired != observed
+    #[must_use]
+    pub fn transitions(&self) -> &[Transition] {
+        &self.transitions
     }
 }
 
@@ -209,18 +403,62 @@ pub struct WorkerInfo {
     healthy: bool,
     generation: u64,
     running_vms: u32,
+    disk_usage: DiskUsage,
+    hugepages: HugePages,
+    net_backends: Vec<NetBackend>,
+    memory_pressure: PressureReport,
+    store_janitor: JanitorReport,
 }
 
 impl WorkerInfo {
-    pub fn new(id: String, healthy: bool, generation: u64, running_vms: u32) -> Self {
+    pub fn new(
+        id: String,
+        healthy: bool,
+        generation: u64,
+        running_vms: u32,
+        disk_usage: DiskUsage,
+    ) -> Self {
         Self {
             id,
             healthy,
             generation,
             running_vms,
+            disk_usage,
+            hugepages: HugePages::default(),
+            net_backends: vec![NetBackend::Tap],
+            memory_pressure: PressureReport::default(),
+            store_janitor: JanitorReport::default(),
         }
     }
 
+    /// Hugepage pool of the host.
+    #[must_use]
+    pub fn with_hugepages(mut self, hugepages: HugePages) -> Self {
+        self.hugepages = hugepages;
+        self
+    }
+
+    /// Network backends the host can attach VMs to.
+    #[must_use]
+    pub fn with_net_backends(mut self, net_backends: Vec<NetBackend>) -> Self {
+        self.net_backends = net_backends;
+        self
+    }
+
+    /// Memory pressure of the host, as last checked.
+    #[must_use]
+    pub fn with_memory_pressure(mut self, memory_pressure: PressureReport) -> Self {
+        self.memory_pressure = memory_pressure;
+        self
+    }
+
+    /// Store garbage collection, as last checked.
+    #[must_use]
+    pub fn with_store_janitor(mut self, store_janitor: JanitorReport) -> Self {
+        self.store_janitor = store_janitor;
+        self
+    }
+
     pub fn id(&self) -> &str {
         &self.id
     }
@@ -236,6 +474,31 @@ impl WorkerInfo {
     pub fn running_vms(&self) -> u32 {
         self.running_vms
     }
+
+    #[must_use]
+    pub fn disk_usage(&self) -> &DiskUsage {
+        &self.disk_usage
+    }
+
+    #[must_use]
+    pub fn hugepages(&self) -> HugePages {
+        self.hugepages
+    }
+
+    #[must_use]
+    pub fn memory_pressure(&self) -> PressureReport {
+        self.memory_pressure
+    }
+
+    #[must_use]
+    pub fn store_janitor(&self) -> JanitorReport {
+        self.store_janitor
+    }
+
+    #[must_use]
+    pub fn net_backends(&self) -> &[NetBackend] {
+        &self.net_backends
+    }
 }
 
 
@@ -248,8 +511,32 @@ impl WorkerInfo {
 pub enum CommandPayload {
     Create(VmSpec),
     Delete(String),
+    /// Restart, redeploy or stop one VM, see [`VmAction`]
+    Action { vm_id: String, action: VmAction },
+    /// Move one VM to a new spec, resizing it in place when only its
+    /// vCPUs or memory change
+    Update { vm_id: String, spec: VmSpec },
     List,
     GetWorkerStatus,
+    /// Re-issue the identity certificates due for renewal
+    RenewIdentities,
+    /// Mark booting VMs that became ready as running and fail the ones
+    /// past the boot timeout
+    CheckBoots,
+    /// Read the host's memory pressure and apply the policy
+    CheckMemoryPressure,
+    /// Pin the store paths of assigned VMs and collect garbage above the
+    /// high watermark
+    CheckStore,
+    /// Remove draining VMs that powered off and force-stop the ones past
+    /// the drain timeout
+    CheckDrains,
+    /// Mark VMs whose VMM exited or stopped answering as crashed and
+    /// follow guests that shut down or came back by themselves
+    CheckVmms,
+    /// Last command before exit: stop every VM, or leave them running
+    /// so they survive a worker restart.
+    Shutdown { stop_vms: bool },
 }
 
 /// Unified response envelope for commands. The Node replies with this
@@ -262,11 +549,13 @@ pub enum CommandResponse {
     WorkerInfo(WorkerInfo),
 }
 
-/// Message sent over the mpsc channel. Contains the command payload
-/// and a oneshot `reply` sender used by the Node to respond.
+/// Message sent over the mpsc channel. Contains the command payload,
+/// a oneshot `reply` sender used by the Node to respond, and the sender's
+/// span so the Node's work shows up in the caller's trace.
 pub struct Message {
     data: CommandPayload,
     reply: oneshot::Sender<Result<CommandResponse, VmError>>,
+    span: tracing::Span,
 }
 
 impl Message {
@@ -275,12 +564,21 @@ impl Message {
         data: CommandPayload,
         reply: oneshot::Sender<Result<CommandResponse, VmError>>,
     ) -> Self {
-        Self { data, reply }
+        Self {
+            data,
+            reply,
+            span: tracing::Span::none(),
+        }
     }
 
     pub fn into_parts(self) -> (CommandPayload, oneshot::Sender<Result<CommandResponse, VmError>>) {
         (self.data, self.reply)
     }
+
+    /// Span that was current when the command was sent.
+    pub fn span(&self) -> &tracing::Span {
+        &self.span
+    }
 }
 
 // ─── Channel wrapper (cloneable handle for Server) ─────────────────────────
@@ -300,13 +598,31 @@ impl CommandSender {
     /// Send a command to the Node and await the response.
     ///
     /// Creates the oneshot channel, wraps the payload in a `Message`,
-    /// sends it, and awaits the reply — all in one call.
+    /// sends it, and awaits the reply — all in one call. The current span
+    /// travels with the message.
     pub async fn request(&self, data: CommandPayload) -> Result<CommandResponse, VmError> {
         let (reply_tx, reply_rx) = oneshot::channel();
-        let msg = Message { data, reply: reply_tx };
+        let msg = Message {
+            data,
+            reply: reply_tx,
+            span: tracing::Span::current(),
+        };
         self.0.send(msg).await.map_err(|_| VmError::ManagerDown)?;
+        crate::metrics::command_queue_depth(self.0.max_capacity() - self.0.capacity());
         reply_rx.await.map_err(|_| VmError::ManagerDown)?
     }
+
+    /// Whether the manager loop has stopped receiving.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+
+    /// Free slots in the command queue.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
 }
 
 impl From<mpsc::Sender<Message>> for CommandSender {
@@ -0,0 +1,145 @@
+//! Worker checks behind `/healthz`, `/readyz` and the systemd watchdog.
+//!
+//! - `rpc` (liveness, readiness) — the RPC listener is accepting.
+//! - `command_channel` (liveness, readiness) — the VM manager loop is still
+//!   receiving; for readiness its queue must also have room.
+//! - `vmm` (readiness) — the cloud-hypervisor binary is executable and
+//!   `/dev/kvm` can be opened. Skipped when simulating.
+//! - `vm_dir` (readiness) — the per-VM working directory accepts new files.
+//!
+//! Liveness does not send the manager a command: it handles commands one at
+//! a time, and a long image copy must not get the worker restarted.
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use commands::health::{Check, Flag, Probe, Report};
+
+use crate::dto::CommandSender;
+
+const KVM_DEVICE: &str = "/dev/kvm";
+
+#[derive(Clone)]
+pub struct WorkerHealth {
+    commands: CommandSender,
+    rpc: Flag,
+    /// `None` when VMs are simulated.
+    ch_binary: Option<PathBuf>,
+    vm_dir: PathBuf,
+}
+
+impl WorkerHealth {
+    #[must_use]
+    pub fn new(
+        commands: CommandSender,
+        rpc: Flag,
+        ch_binary: Option<PathBuf>,
+        vm_dir: PathBuf,
+    ) -> Self {
+        Self {
+            commands,
+            rpc,
+            ch_binary,
+            vm_dir,
+        }
+    }
+
+    fn rpc(&self) -> Check {
+        Check::expect("rpc", self.rpc.is_raised(), "listener not accepting")
+    }
+
+    fn manager_running(&self) -> Check {
+        Check::expect(
+            "command_channel",
+            !self.commands.is_closed(),
+            "VM manager stopped",
+        )
+    }
+}
+
+impl Probe for WorkerHealth {
+    async fn liveness(&self) -> Report {
+        Report(vec![self.rpc(), self.manager_running()])
+    }
+
+    async fn readiness(&self) -> Report {
+        let channel = if self.commands.is_closed() {
+            self.manager_running()
+        } else {
+            Check::expect(
+                "command_channel",
+                self.commands.capacity() > 0,
+                "command queue full",
+            )
+        };
+        let (ch_binary, vm_dir) = (self.ch_binary.clone(), self.vm_dir.clone());
+        let (vmm, vm_dir) = tokio::task::spawn_blocking(move || {
+            (
+                ch_binary.map(|binary| {
+                    Check::from_result("vmm", vmm_available(&binary, Path::new(KVM_DEVICE)))
+                }),
+                Check::from_result("vm_dir", dir_writable(&vm_dir)),
+            )
+        })
+        .await
+        .unwrap_or_else(|e| {
+            (
+                Some(Check::fail("vmm", format!("check panicked: {e}"))),
+                Check::fail("vm_dir", format!("check panicked: {e}")),
+            )
+        });
+        let mut checks = vec![self.rpc(), channel];
+        checks.extend(vmm);
+        checks.push(vm_dir);
+        Report(checks)
+    }
+}
+
+/// The VMM binary is executable and the KVM device can be opened.
+fn vmm_available(ch_binary: &Path, kvm: &Path) -> Result<(), String> {
+    let meta = std::fs::metadata(ch_binary).map_err(|e| format!("{}: {e}", ch_binary.display()))?;
+    if !meta.is_file() || meta.permissions().mode() & 0o111 == 0 {
+        return Err(format!("{} is not executable", ch_binary.display()));
+    }
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(kvm)
+        .map_err(|e| format!("{}: {e}", kvm.display()))?;
+    Ok(())
+}
+
+/// `dir` exists (or can be created) and accepts new files.
+fn dir_writable(dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    let probe = dir.join(format!(".health-{}", std::process::id()));
+    std::fs::write(&probe, b"").map_err(|e| format!("{}: {e}", dir.display()))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vmm_check_requires_executable_binary() {
+        let dir = std::env::temp_dir().join(format!("pcr-health-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("cloud-hypervisor");
+        let kvm = dir.join("kvm");
+        std::fs::write(&binary, b"").unwrap();
+        std::fs::write(&kvm, b"").unwrap();
+
+        let err = vmm_available(&binary, &kvm).unwrap_err();
+        assert!(err.ends_with("is not executable"), "{err}");
+
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(vmm_available(&binary, &kvm), Ok(()));
+        assert!(vmm_available(&binary, &dir.join("missing")).is_err());
+
+        assert_eq!(dir_writable(&dir.join("vms")), Ok(()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
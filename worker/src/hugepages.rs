@@ -0,0 +1,125 @@
+//! Hugepage pool of the host, for VMs whose memory is backed by hugepages.
+//!
+//! The pool is read from `/proc/meminfo`: the default hugepage size, how
+//! many pages the host reserved, and how many are free and not yet promised
+//! to a mapping. A hugepage-backed VM is only started when its whole memory
+//! fits in those pages, so a host without enough of them refuses it up
+//! front instead of the hypervisor failing halfway through the boot.
+//! `Worker.read` reports the pool next to the worker's status.
+
+use std::path::Path;
+
+/// Where the kernel reports the pool.
+pub const MEMINFO: &str = "/proc/meminfo";
+
+/// Hugepages of the default size, all zeroes when the host has none.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HugePages {
+    pub page_size_kb: u64,
+    /// Pages reserved on the host
+    pub total: u64,
+    /// Pages neither used nor reserved by a mapping
+    pub free: u64,
+}
+
+impl HugePages {
+    /// Parse the `HugePages_*` and `Hugepagesize` lines of `/proc/meminfo`.
+    #[must_use]
+    pub fn parse(meminfo: &str) -> Self {
+        let mut pool = Self::default();
+        let (mut free, mut reserved) = (0, 0);
+        for line in meminfo.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value
+                .split_whitespace()
+                .next()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+            match key {
+                "HugePages_Total" => pool.total = value,
+                "HugePages_Free" => free = value,
+                "HugePages_Rsvd" => reserved = value,
+                "Hugepagesize" => pool.page_size_kb = value,
+                _ => {}
+            }
+        }
+        pool.free = free.saturating_sub(reserved);
+        pool
+    }
+
+    /// The pool reported at `path`, empty when it cannot be read.
+    #[must_use]
+    pub fn read(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .map(|meminfo| Self::parse(&meminfo))
+            .unwrap_or_default()
+    }
+
+    /// Whether `memory_mb` of guest memory can be backed by the free pages.
+    ///
+    /// # Errors
+    ///
+    /// - if the host reserved no hugepages
+    /// - if `memory_mb` is not a whole number of pages
+    /// - if fewer pages than that are free
+    pub fn fits(&self, memory_mb: u32) -> Result<(), String> {
+        if self.total == 0 || self.page_size_kb == 0 {
+            return Err("no hugepages are reserved on this host".to_string());
+        }
+        let kib = u64::from(memory_mb) * 1024;
+        if !kib.is_multiple_of(self.page_size_kb) {
+            return Err(format!(
+                "{memory_mb} MiB is not a multiple of the {} KiB hugepage size",
+                self.page_size_kb
+            ));
+        }
+        let needed = kib / self.page_size_kb;
+        if needed > self.free {
+            return Err(format!(
+                "{needed} hugepages of {} KiB needed, {} free",
+                self.page_size_kb, self.free
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEMINFO_2M: &str = "MemTotal:       32768000 kB\n\
+        HugePages_Total:     512\n\
+        HugePages_Free:      300\n\
+        HugePages_Rsvd:       44\n\
+        HugePages_Surp:        0\n\
+        Hugepagesize:       2048 kB\n";
+
+    #[test]
+    fn reserved_pages_are_not_free() {
+        let pool = HugePages::parse(MEMINFO_2M);
+        assert_eq!(
+            pool,
+            HugePages {
+                page_size_kb: 2048,
+                total: 512,
+                free: 256,
+            }
+        );
+        assert_eq!(
+            HugePages::parse("MemTotal: 1024 kB\n"),
+            HugePages::default()
+        );
+    }
+
+    #[test]
+    fn vms_must_fit_in_whole_free_pages() {
+        let pool = HugePages::parse(MEMINFO_2M);
+        assert_eq!(pool.fits(512), Ok(()));
+        assert!(pool.fits(513).unwrap_err().contains("not a multiple"));
+        assert!(pool.fits(1024).unwrap_err().contains("512 hugepages"));
+        assert!(HugePages::default().fits(512).is_err());
+    }
+}
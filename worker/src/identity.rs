@@ -0,0 +1,706 @@
+//! # Per-VM workload identity
+//!
+//! Every VM gets a SPIFFE-style identity from a cluster CA, so workloads can
+//! do mTLS between VMs without managing keys themselves:
+//!
+//! - **Identity** — `spiffe://<trust_domain>/worker/<worker_id>/vm/<vm_id>`,
+//!   carried as the only URI SAN of a short-lived leaf certificate
+//!   (`serverAuth` + `clientAuth`). The VM keeps it for its whole life;
+//!   only the certificate and key rotate.
+//! - **Delivery** — issued before the VMM is spawned and written to
+//!   `<dir>/<vm_id>/`, the directory served to the guest by its metadata
+//!   channel:
+//!
+//!   ```text
+//!   identity.json   identity document (SPIFFE ID, validity)
+//!   svid.pem        leaf certificate
+//!   svid.key        private key, mode 0600
+//!   bundle.pem      CA certificate, the trust bundle
+//!   ```
+//!
+//! - **Renewal** — a certificate is due once half its lifetime has passed.
+//!   The worker periodically sends `CommandPayload::RenewIdentities`; the
+//!   manager re-issues due certificates in place. Every file is replaced
+//!   atomically, so a reader never sees a certificate without its key.
+//! - **Worker CA** — the cluster root key never reaches a worker. Each one
+//!   signs with an intermediate of its own ([`issue_worker_ca`], run where
+//!   the root key is kept), which may sign leaves only (path length 0),
+//!   and only under the subject `O=<trust_domain>, OU=worker/<worker_id>`.
+//!   A worker refuses to start with a CA lacking that constraint. X.509
+//!   name constraints cannot restrict the path of a URI, so the SPIFFE ID
+//!   of a leaf is bound to its worker by verifiers checking it is under
+//!   the URI SAN of the intermediate that signed it,
+//!   `spiffe://<trust_domain>/worker/<worker_id>`. `svid.pem` holds the
+//!   leaf followed by the intermediate, `bundle.pem` the root.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write as _};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CustomExtension, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, GeneralSubtree, Ia5String, IsCa, KeyPair, KeyUsagePurpose,
+    NameConstraints, SanType,
+};
+use serde::{Deserialize, Serialize};
+
+/// Leaf certificates start this far in the past, for clock skew between
+/// the worker and the guests verifying them.
+const BACKDATE: Duration = Duration::from_mins(1);
+
+// ─── Configuration ─────────────────────────────────────────────────────────
+
+/// Worker CA issuing VM identities; disabled when the section is absent.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdentitySection {
+    /// SPIFFE trust domain, e.g. `prod.example.com`.
+    pub trust_domain: String,
+    /// PEM cluster root CA certificate, handed to VMs as their trust bundle.
+    pub bundle: PathBuf,
+    /// PEM intermediate CA certificate of this worker, see
+    /// [`issue_worker_ca`].
+    pub ca_cert: PathBuf,
+    /// PEM private key of the worker's intermediate CA.
+    pub ca_key: PathBuf,
+    /// Lifetime of an issued certificate.
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Per-VM identity directories are created here.
+    pub dir: PathBuf,
+}
+
+fn default_ttl_secs() -> u64 {
+    3600
+}
+
+// ─── Errors ────────────────────────────────────────────────────────────────
+
+#[derive(Debug, thiserror::Error)]
+pub enum IdentityError {
+    /// The trust domain is not a valid SPIFFE trust domain
+    #[error("invalid trust domain {0:?}")]
+    TrustDomain(String),
+    /// The CA certificate or key could not be read
+    #[error("cannot read CA file {}", path.display())]
+    CaFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// The CA certificate or key does not parse
+    #[error("cannot load CA {what}")]
+    Ca {
+        what: &'static str,
+        #[source]
+        source: rcgen::Error,
+    },
+    /// The CA may sign for more than this worker
+    #[error("CA certificate is not constrained to worker {worker_id}, issue one with `issue-ca`")]
+    Unconstrained { worker_id: String },
+    /// Building or signing a certificate failed
+    #[error("cannot issue certificate")]
+    Certificate(#[from] rcgen::Error),
+    #[error("cannot write identity")]
+    Io(#[from] io::Error),
+}
+
+// ─── Issued identity ───────────────────────────────────────────────────────
+
+/// Certificate and key of one VM, as written to its identity directory.
+#[derive(Debug, Clone)]
+pub struct VmIdentity {
+    spiffe_id: String,
+    cert_pem: String,
+    key_pem: String,
+    not_before: SystemTime,
+    not_after: SystemTime,
+}
+
+impl VmIdentity {
+    #[must_use]
+    pub fn spiffe_id(&self) -> &str {
+        &self.spiffe_id
+    }
+
+    #[must_use]
+    pub fn cert_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    #[must_use]
+    pub fn key_pem(&self) -> &str {
+        &self.key_pem
+    }
+
+    #[must_use]
+    pub fn not_after(&self) -> SystemTime {
+        self.not_after
+    }
+
+    /// Due for renewal once half the lifetime has passed, so a failed
+    /// renewal leaves the other half to retry.
+    #[must_use]
+    pub fn needs_renewal(&self, now: SystemTime) -> bool {
+        let lifetime = self
+            .not_after
+            .duration_since(self.not_before)
+            .unwrap_or_default();
+        now >= self.not_before + lifetime / 2
+    }
+}
+
+/// `identity.json`, the document the guest reads to learn who it is.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityDocument {
+    pub spiffe_id: String,
+    pub trust_domain: String,
+    pub worker_id: String,
+    pub vm_id: String,
+    /// Seconds since the Unix epoch.
+    pub not_before: u64,
+    pub not_after: u64,
+}
+
+// ─── Issuer ────────────────────────────────────────────────────────────────
+
+/// The worker's intermediate CA, issuing and installing identities for the
+/// VMs of that worker.
+pub struct IdentityIssuer {
+    trust_domain: String,
+    worker_id: String,
+    ca_cert: Certificate,
+    ca_key: KeyPair,
+    /// The intermediate certificate as configured, sent after each leaf.
+    ca_cert_pem: String,
+    /// The root certificate as configured, handed out unchanged.
+    bundle_pem: String,
+    ttl: Duration,
+    dir: PathBuf,
+}
+
+impl IdentityIssuer {
+    /// Load the CA named by the config section, for worker `worker_id`.
+    ///
+    /// # Errors
+    ///
+    /// Unreadable files, or any error of [`IdentityIssuer::new`].
+    pub fn load(section: &IdentitySection, worker_id: &str) -> Result<Self, IdentityError> {
+        let read = |path: &Path| {
+            fs::read_to_string(path).map_err(|source| IdentityError::CaFile {
+                path: path.to_path_buf(),
+                source,
+            })
+        };
+        Self::new(
+            &section.trust_domain,
+            worker_id,
+            &read(&section.bundle)?,
+            &read(&section.ca_cert)?,
+            &read(&section.ca_key)?,
+            Duration::from_secs(section.ttl_secs),
+            section.dir.clone(),
+        )
+    }
+
+    /// Issuer for `worker_id` in `trust_domain`, from the PEM root
+    /// certificate and the worker's intermediate certificate and key.
+    ///
+    /// # Errors
+    ///
+    /// An invalid trust domain, a CA certificate or key that does not
+    /// parse, or a CA not constrained to `worker_id`.
+    pub fn new(
+        trust_domain: &str,
+        worker_id: &str,
+        bundle_pem: &str,
+        ca_cert_pem: &str,
+        ca_key_pem: &str,
+        ttl: Duration,
+        dir: PathBuf,
+    ) -> Result<Self, IdentityError> {
+        check_trust_domain(trust_domain)?;
+        let ca_key = KeyPair::from_pem(ca_key_pem).map_err(|source| IdentityError::Ca {
+            what: "key",
+            source,
+        })?;
+        let params = CertificateParams::from_ca_cert_pem(ca_cert_pem).map_err(|source| {
+            IdentityError::Ca {
+                what: "certificate",
+                source,
+            }
+        })?;
+        if params.name_constraints != Some(worker_constraints(trust_domain, worker_id)) {
+            return Err(IdentityError::Unconstrained {
+                worker_id: worker_id.to_string(),
+            });
+        }
+        // rcgen signs with a `Certificate`; re-signing the parsed CA params
+        // with the same key yields the same subject and key identifier.
+        let ca_cert = params
+            .self_signed(&ca_key)
+            .map_err(|source| IdentityError::Ca {
+                what: "certificate",
+                source,
+            })?;
+        Ok(Self {
+            trust_domain: trust_domain.to_string(),
+            worker_id: worker_id.to_string(),
+            ca_cert,
+            ca_key,
+            ca_cert_pem: ca_cert_pem.to_string(),
+            bundle_pem: bundle_pem.to_string(),
+            ttl,
+            dir,
+        })
+    }
+
+    #[must_use]
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    #[must_use]
+    pub fn spiffe_id(&self, vm_id: &str) -> String {
+        format!(
+            "{}/vm/{vm_id}",
+            worker_spiffe_id(&self.trust_domain, &self.worker_id)
+        )
+    }
+
+    /// Identity directory of a VM.
+    #[must_use]
+    pub fn vm_dir(&self, vm_id: &str) -> PathBuf {
+        self.dir.join(vm_id)
+    }
+
+    /// Issue a fresh key and certificate for a VM, valid from `now`.
+    ///
+    /// # Errors
+    ///
+    /// Key generation or signing failing.
+    pub fn issue(&self, vm_id: &str, now: SystemTime) -> Result<VmIdentity, IdentityError> {
+        let spiffe_id = self.spiffe_id(vm_id);
+        let not_before = now - BACKDATE;
+        let not_after = now + self.ttl;
+
+        let mut params = CertificateParams::default();
+        params.distinguished_name = worker_subject(&self.trust_domain, &self.worker_id);
+        params.distinguished_name.push(DnType::CommonName, vm_id);
+        params.subject_alt_names = vec![SanType::URI(Ia5String::try_from(spiffe_id.as_str())?)];
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+        params.use_authority_key_identifier_extension = true;
+        params.not_before = not_before.into();
+        params.not_after = not_after.into();
+
+        let key = KeyPair::generate()?;
+        let cert = params.signed_by(&key, &self.ca_cert, &self.ca_key)?;
+        Ok(VmIdentity {
+            spiffe_id,
+            cert_pem: cert.pem() + &self.ca_cert_pem,
+            key_pem: key.serialize_pem(),
+            not_before,
+            not_after,
+        })
+    }
+
+    /// Write `identity` into the VM's directory, replacing any previous one.
+    ///
+    /// # Errors
+    ///
+    /// Creating the directory or writing a file failing.
+    pub fn install(&self, vm_id: &str, identity: &VmIdentity) -> Result<(), IdentityError> {
+        let dir = self.vm_dir(vm_id);
+        fs::create_dir_all(&dir)?;
+
+        let document = IdentityDocument {
+            spiffe_id: identity.spiffe_id.clone(),
+            trust_domain: self.trust_domain.clone(),
+            worker_id: self.worker_id.clone(),
+            vm_id: vm_id.to_string(),
+            not_before: unix_secs(identity.not_before),
+            not_after: unix_secs(identity.not_after),
+        };
+        let document = serde_json::to_vec_pretty(&document).map_err(io::Error::from)?;
+
+        // Key before certificate: a guest reloading on a new svid.pem
+        // finds the matching key already in place.
+        write_atomic(&dir.join("bundle.pem"), self.bundle_pem.as_bytes(), 0o644)?;
+        write_atomic(&dir.join("svid.key"), identity.key_pem.as_bytes(), 0o600)?;
+        write_atomic(&dir.join("svid.pem"), identity.cert_pem.as_bytes(), 0o644)?;
+        write_atomic(&dir.join("identity.json"), &document, 0o644)?;
+        Ok(())
+    }
+
+    /// Delete a VM's identity directory. Missing is fine.
+    ///
+    /// # Errors
+    ///
+    /// The directory existing but not being removable.
+    pub fn remove(&self, vm_id: &str) -> io::Result<()> {
+        match fs::remove_dir_all(self.vm_dir(vm_id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Intermediate CA of one worker, as PEM certificate and key.
+pub struct WorkerCa {
+    pub cert_pem: String,
+    pub key_pem: String,
+}
+
+impl WorkerCa {
+    /// Write `ca.pem` and `ca.key` into `dir`, the key readable by its
+    /// owner only.
+    ///
+    /// # Errors
+    ///
+    /// Creating the directory or writing a file failing.
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        write_atomic(&dir.join("ca.key"), self.key_pem.as_bytes(), 0o600)?;
+        write_atomic(&dir.join("ca.pem"), self.cert_pem.as_bytes(), 0o644)
+    }
+}
+
+/// Sign an intermediate CA for `worker_id` with the cluster root, valid
+/// for `ttl` from `now`. Run where the root key is kept, and hand only the
+/// result to the worker.
+///
+/// # Errors
+///
+/// An invalid trust domain, a root certificate or key that does not
+/// parse, or signing failing.
+pub fn issue_worker_ca(
+    trust_domain: &str,
+    worker_id: &str,
+    root_cert_pem: &str,
+    root_key_pem: &str,
+    ttl: Duration,
+    now: SystemTime,
+) -> Result<WorkerCa, IdentityError> {
+    check_trust_domain(trust_domain)?;
+    let root_key = KeyPair::from_pem(root_key_pem).map_err(|source| IdentityError::Ca {
+        what: "key",
+        source,
+    })?;
+    let root_cert = CertificateParams::from_ca_cert_pem(root_cert_pem)
+        .and_then(|params| params.self_signed(&root_key))
+        .map_err(|source| IdentityError::Ca {
+            what: "certificate",
+            source,
+        })?;
+
+    let spiffe_id = worker_spiffe_id(trust_domain, worker_id);
+    let mut params = CertificateParams::default();
+    params.distinguished_name = worker_subject(trust_domain, worker_id);
+    params.subject_alt_names = vec![SanType::URI(Ia5String::try_from(spiffe_id.as_str())?)];
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.custom_extensions = vec![name_constraints_extension(trust_domain, worker_id)];
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    params.use_authority_key_identifier_extension = true;
+    params.not_before = (now - BACKDATE).into();
+    params.not_after = (now + ttl).into();
+
+    let key = KeyPair::generate()?;
+    let cert = params.signed_by(&key, &root_cert, &root_key)?;
+    Ok(WorkerCa {
+        cert_pem: cert.pem(),
+        key_pem: key.serialize_pem(),
+    })
+}
+
+fn worker_spiffe_id(trust_domain: &str, worker_id: &str) -> String {
+    format!("spiffe://{trust_domain}/worker/{worker_id}")
+}
+
+/// `O=<trust_domain>, OU=worker/<worker_id>`, the subject of the worker's
+/// CA and the prefix of its leaves'.
+fn worker_subject(trust_domain: &str, worker_id: &str) -> DistinguishedName {
+    let mut name = DistinguishedName::new();
+    name.push(DnType::OrganizationName, trust_domain);
+    name.push(
+        DnType::OrganizationalUnitName,
+        format!("worker/{worker_id}"),
+    );
+    name
+}
+
+/// Name constraints of a worker's CA, as read back from its certificate.
+fn worker_constraints(trust_domain: &str, worker_id: &str) -> NameConstraints {
+    NameConstraints {
+        permitted_subtrees: vec![GeneralSubtree::DirectoryName(worker_subject(
+            trust_domain,
+            worker_id,
+        ))],
+        excluded_subtrees: Vec::new(),
+    }
+}
+
+/// Name constraints extension (RFC 5280, 4.2.1.10) permitting only
+/// subjects under [`worker_subject`]. rcgen writes a directory name
+/// without the explicit tag its `GeneralName` choice needs, which
+/// verifiers reject, so the extension is encoded here.
+fn name_constraints_extension(trust_domain: &str, worker_id: &str) -> CustomExtension {
+    const OID_NAME_CONSTRAINTS: &[u64] = &[2, 5, 29, 30];
+    // id-at-organizationName and id-at-organizationalUnitName, as UTF8String
+    let attribute = |oid: u8, value: &str| {
+        let pair = [der(0x06, &[0x55, 0x04, oid]), der(0x0c, value.as_bytes())].concat();
+        der(0x31, &der(0x30, &pair))
+    };
+    let name = [
+        attribute(10, trust_domain),
+        attribute(11, &format!("worker/{worker_id}")),
+    ]
+    .concat();
+    // NameConstraints { permittedSubtrees [0] { GeneralSubtree { [4] Name } } }
+    let subtree = der(0x30, &der(0xa4, &der(0x30, &name)));
+    let content = der(0x30, &der(0xa0, &subtree));
+    let mut extension = CustomExtension::from_oid_content(OID_NAME_CONSTRAINTS, content);
+    extension.set_criticality(true);
+    extension
+}
+
+/// DER encoding of `content` under `tag`.
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match u8::try_from(content.len()) {
+        Ok(len) if len < 0x80 => out.push(len),
+        _ => {
+            let len = content.len().to_be_bytes();
+            let len = &len[len.iter().take_while(|b| **b == 0).count()..];
+            out.push(0x80 | u8::try_from(len.len()).expect("a usize has at most 8 bytes"));
+            out.extend_from_slice(len);
+        }
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Lowercase letters, digits, `.`, `-` and `_`, per the SPIFFE ID spec.
+fn check_trust_domain(trust_domain: &str) -> Result<(), IdentityError> {
+    let valid = !trust_domain.is_empty()
+        && trust_domain
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '-' | '_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(IdentityError::TrustDomain(trust_domain.to_string()))
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Write through a temporary file and rename, so readers see either the
+/// old or the new contents.
+///
+/// Each target gets its own temporary file, created anew with `mode`, so
+/// a leftover from an earlier run never lends the key laxer permissions.
+fn write_atomic(path: &Path, contents: &[u8], mode: u32) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    if let Err(e) = fs::remove_file(&tmp)
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        return Err(e);
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn ca() -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "procurator test CA");
+        params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign];
+        let cert = params.self_signed(&key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn issuer(name: &str) -> IdentityIssuer {
+        let dir =
+            std::env::temp_dir().join(format!("worker-identity-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (root, root_key) = ca();
+        let hour = Duration::from_hours(1);
+        let ca = issue_worker_ca(
+            "cluster.test",
+            "w1",
+            &root,
+            &root_key,
+            hour,
+            SystemTime::now(),
+        )
+        .unwrap();
+        IdentityIssuer::new(
+            "cluster.test",
+            "w1",
+            &root,
+            &ca.cert_pem,
+            &ca.key_pem,
+            hour,
+            dir,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn issues_a_spiffe_certificate_due_at_half_life() {
+        let issuer = issuer("issue");
+        let now = SystemTime::now();
+        let identity = issuer.issue("vm-1", now).unwrap();
+
+        assert_eq!(
+            identity.spiffe_id(),
+            "spiffe://cluster.test/worker/w1/vm/vm-1"
+        );
+        let params = CertificateParams::from_ca_cert_pem(identity.cert_pem()).unwrap();
+        assert_eq!(
+            params.subject_alt_names,
+            [SanType::URI(
+                Ia5String::try_from("spiffe://cluster.test/worker/w1/vm/vm-1").unwrap()
+            )]
+        );
+        assert_eq!(params.is_ca, IsCa::ExplicitNoCa);
+        assert!(identity.cert_pem().ends_with(&issuer.ca_cert_pem));
+
+        assert!(!identity.needs_renewal(now));
+        assert!(!identity.needs_renewal(now + Duration::from_secs(1700)));
+        assert!(identity.needs_renewal(now + Duration::from_secs(1800)));
+    }
+
+    #[test]
+    fn installs_and_removes_identity_files() {
+        let issuer = issuer("install");
+        let identity = issuer.issue("vm-1", SystemTime::now()).unwrap();
+        issuer.install("vm-1", &identity).unwrap();
+
+        let dir = issuer.vm_dir("vm-1");
+        let key = fs::metadata(dir.join("svid.key")).unwrap();
+        assert_eq!(key.permissions().mode() & 0o777, 0o600);
+        assert_eq!(
+            fs::read_to_string(dir.join("svid.pem")).unwrap(),
+            identity.cert_pem()
+        );
+        let document: IdentityDocument =
+            serde_json::from_slice(&fs::read(dir.join("identity.json")).unwrap()).unwrap();
+        assert_eq!(document.spiffe_id, identity.spiffe_id());
+        assert_eq!(document.vm_id, "vm-1");
+
+        issuer.remove("vm-1").unwrap();
+        assert!(!dir.exists());
+        issuer.remove("vm-1").unwrap();
+        let _ = fs::remove_dir_all(&issuer.dir);
+    }
+
+    #[test]
+    fn a_leftover_temporary_file_does_not_widen_the_key_mode() {
+        let issuer = issuer("leftover");
+        let dir = issuer.vm_dir("vm-1");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("svid.key.tmp"), "stale").unwrap();
+        fs::set_permissions(dir.join("svid.key.tmp"), fs::Permissions::from_mode(0o644)).unwrap();
+
+        let identity = issuer.issue("vm-1", SystemTime::now()).unwrap();
+        issuer.install("vm-1", &identity).unwrap();
+
+        let key = fs::metadata(dir.join("svid.key")).unwrap();
+        assert_eq!(key.permissions().mode() & 0o777, 0o600);
+        assert_eq!(
+            fs::read_to_string(dir.join("svid.key")).unwrap(),
+            identity.key_pem()
+        );
+        let _ = fs::remove_dir_all(&issuer.dir);
+    }
+
+    #[test]
+    fn rejects_invalid_trust_domains() {
+        let (cert, key) = ca();
+        for td in ["", "Cluster.Test", "spiffe://x", "a/b"] {
+            let err = IdentityIssuer::new(
+                td,
+                "w1",
+                &cert,
+                &cert,
+                &key,
+                Duration::from_secs(1),
+                PathBuf::new(),
+            )
+            .err()
+            .unwrap();
+            assert!(matches!(err, IdentityError::TrustDomain(_)), "{td}");
+        }
+    }
+
+    #[test]
+    fn only_a_ca_constrained_to_the_worker_is_used() {
+        let (root, root_key) = ca();
+        let hour = Duration::from_hours(1);
+        let load = |worker_id: &str, cert: &str, key: &str| {
+            IdentityIssuer::new(
+                "cluster.test",
+                worker_id,
+                &root,
+                cert,
+                key,
+                hour,
+                PathBuf::new(),
+            )
+        };
+        let err = load("w1", &root, &root_key).err().unwrap();
+        assert!(matches!(err, IdentityError::Unconstrained { .. }));
+
+        let ca = issue_worker_ca(
+            "cluster.test",
+            "w2",
+            &root,
+            &root_key,
+            hour,
+            SystemTime::now(),
+        )
+        .unwrap();
+        assert!(load("w2", &ca.cert_pem, &ca.key_pem).is_ok());
+        let err = load("w1", &ca.cert_pem, &ca.key_pem).err().unwrap();
+        assert!(matches!(err, IdentityError::Unconstrained { .. }));
+
+        let params = CertificateParams::from_ca_cert_pem(&ca.cert_pem).unwrap();
+        assert_eq!(params.is_ca, IsCa::Ca(BasicConstraints::Constrained(0)));
+        assert_eq!(
+            params.subject_alt_names,
+            [SanType::URI(
+                Ia5String::try_from("spiffe://cluster.test/worker/w2").unwrap()
+            )]
+        );
+    }
+}
@@ -1,7 +1,21 @@
+pub mod boot_watchdog;
+pub mod config;
+pub mod disk_usage;
+pub mod dns_proxy;
+pub mod drain;
 pub mod dto;
+pub mod health;
+pub mod hugepages;
+pub mod identity;
+pub mod log_forward;
+pub mod memory_pressure;
+pub mod metrics;
+pub mod records;
 pub mod server;
+pub mod store_janitor;
 pub mod vm_manager;
 pub mod vmm;
+pub mod vmm_monitor;
 
 #[cfg(test)]
 mod vm_manager_tests;
@@ -10,82 +24,605 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
 
+use commands::lifecycle::{self, NotifyState};
 use serde::Deserialize;
 use server::Server;
+use tokio::sync::mpsc;
 use tokio::task;
-use tokio::{join, sync::mpsc};
+use tokio_util::sync::CancellationToken;
 use vm_manager::{VmManager, VmManagerConfig};
-use vmm::cloud_hypervisor::{CloudHypervisorBackend, CloudHypervisorConfig};
+use vmm::VmmBackend;
+use vmm::cloud_hypervisor::{CloudHypervisorBackend, CloudHypervisorConfig, Hotplug, VhostUserNet};
+use vmm::mock::{MockBackend, MockBackendConfig};
 
-use crate::dto::CommandSender;
+use crate::boot_watchdog::{BootWatchdog, BootWatchdogSection};
+use crate::dns_proxy::{DnsPolicies, DnsProxySection};
+use crate::drain::{Drain, DrainSection};
+use crate::dto::{CommandPayload, CommandSender, Message};
+use crate::health::WorkerHealth;
+use crate::identity::{IdentityIssuer, IdentitySection};
+use crate::memory_pressure::{MemoryPressure, MemoryPressureSection};
+use crate::store_janitor::{StoreJanitor, StoreJanitorSection};
+use crate::vmm_monitor::{VmmMonitor, VmmMonitorSection};
 
 #[derive(Debug, Deserialize)]
 pub struct CloudHypervisorSection {
     binary_path: PathBuf,
     socket_dir: PathBuf,
+    /// Writable disk copies; defaults to `staging_dir`, then `socket_dir`.
+    #[serde(default)]
+    image_dir: Option<PathBuf>,
+    /// Fast local disk (e.g. an `NVMe` drive) store images are staged
+    /// on before boot, VM disks being reflinked from them. Absent copies
+    /// disks straight from the store.
+    #[serde(default)]
+    staging_dir: Option<PathBuf>,
+    /// Serial console and cloud-hypervisor logs, tailed by log
+    /// forwarding; defaults to `socket_dir`.
+    #[serde(default)]
+    log_dir: Option<PathBuf>,
     socket_timeout_secs: u64,
     bridge_name: Option<String>,
+    /// Keys (`name:base64`) VM images must be signed with, e.g. the
+    /// binary cache and CI keys. Empty boots unverified images.
+    #[serde(default)]
+    trusted_public_keys: Vec<String>,
+    /// `virtiofsd` sharing the store with VMs that boot from a NixOS
+    /// closure instead of a disk image. Absent refuses such VMs.
+    #[serde(default)]
+    virtiofsd_binary: Option<PathBuf>,
+    /// Sockets of a host switch (OVS-DPDK, passt) for VMs asking for
+    /// vhost-user networking. Absent refuses such VMs, and the worker
+    /// does not advertise the backend.
+    #[serde(default)]
+    vhost_user_net: Option<VhostUserNet>,
+    /// Most vCPUs and memory VMs can be resized to without a redeploy.
+    /// Absent redeploys VMs whose resources change.
+    #[serde(default)]
+    hotplug: Option<Hotplug>,
+}
+
+/// Settings of the VM manager, independent of the backend.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct VmsSection {
+    /// Name of this worker in VM statuses, identities and forwarded logs.
+    worker_id: String,
+    /// Most VMs held at once; creates beyond it are refused. Unlimited
+    /// when absent.
+    max_vms: Option<usize>,
+    /// Records of running VMs, adopted again after a restart. Without it
+    /// a restarted worker forgets the VMs it left running.
+    state_dir: Option<PathBuf>,
+}
+
+impl Default for VmsSection {
+    fn default() -> Self {
+        let defaults = VmManagerConfig::default();
+        Self {
+            worker_id: defaults.worker_id,
+            max_vms: defaults.max_vms,
+            state_dir: defaults.state_dir,
+        }
+    }
+}
+
+impl From<VmsSection> for VmManagerConfig {
+    fn from(section: VmsSection) -> Self {
+        Self {
+            worker_id: section.worker_id,
+            max_vms: section.max_vms,
+            state_dir: section.state_dir,
+        }
+    }
+}
+
+/// What happens to running VMs when the worker receives SIGTERM/SIGINT.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ShutdownSection {
+    /// Upper bound for draining commands and stopping VMs.
+    timeout_secs: u64,
+    /// Stop every VM before exiting. When false, VMs keep running and
+    /// outlive the worker process.
+    stop_vms: bool,
+}
+
+impl Default for ShutdownSection {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 30,
+            stop_vms: false,
+        }
+    }
+}
+
+/// Prometheus exporter; disabled when the section is absent.
+#[derive(Debug, Deserialize)]
+pub struct MetricsSection {
+    /// Address serving `GET /metrics`.
+    listen_addr: SocketAddr,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HealthSection {
+    /// Address serving `GET /healthz` and `GET /readyz`.
+    listen_addr: SocketAddr,
+}
+
+/// Run VMs on the mock backend, for development on machines without
+/// virtualization. Enabled by this section or by `--simulate`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SimulateSection {
+    /// Time a simulated VM takes to boot.
+    boot_delay_ms: u64,
+    /// Working directory for health checks and log forwarding.
+    vm_dir: PathBuf,
+}
+
+impl Default for SimulateSection {
+    fn default() -> Self {
+        Self {
+            boot_delay_ms: 1500,
+            vm_dir: std::env::temp_dir().join("procurator-sim"),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     listen_addr: SocketAddr,
     master_addr: SocketAddr,
-    cloud_hypervisor: CloudHypervisorSection,
+    /// Required unless simulating.
+    #[serde(default)]
+    cloud_hypervisor: Option<CloudHypervisorSection>,
+    #[serde(default)]
+    vms: VmsSection,
+    #[serde(default)]
+    shutdown: ShutdownSection,
+    #[serde(default)]
+    metrics: Option<MetricsSection>,
+    #[serde(default)]
+    log_forwarding: Option<log_forward::LogForwardConfig>,
+    #[serde(default)]
+    health: Option<HealthSection>,
+    #[serde(default)]
+    simulate: Option<SimulateSection>,
+    /// Issue VM identities from a cluster CA; disabled when absent.
+    #[serde(default)]
+    identity: Option<IdentitySection>,
+    /// Resolve only each VM's allowed domains; disabled when absent.
+    #[serde(default)]
+    dns_proxy: Option<DnsProxySection>,
+    /// Fail and restart VMs that never become ready; disabled when absent.
+    #[serde(default)]
+    boot_watchdog: Option<BootWatchdogSection>,
+    /// Act on host memory pressure before the OOM killer does; disabled
+    /// when absent.
+    #[serde(default)]
+    memory_pressure: Option<MemoryPressureSection>,
+    /// Pin the store paths of assigned VMs and collect the rest when the
+    /// store fills up; disabled when absent.
+    #[serde(default)]
+    store_janitor: Option<StoreJanitorSection>,
+    /// Let deleted VMs power off before they are stopped; killed at once
+    /// when absent.
+    #[serde(default)]
+    drain: Option<DrainSection>,
+    /// Mark VMs whose VMM exited or hung as crashed; disabled when
+    /// absent.
+    #[serde(default)]
+    vmm_monitor: Option<VmmMonitorSection>,
+}
+
+impl Config {
+    /// Simulate VMs, keeping the `simulate` section if the file has one.
+    pub fn simulate(&mut self) {
+        self.simulate.get_or_insert_with(SimulateSection::default);
+    }
 }
 
 pub async fn main(config: Config) {
+    if let Some(section) = &config.metrics {
+        match metrics::install(section.listen_addr) {
+            Ok(()) => tracing::info!(listen_addr = %section.listen_addr, "Serving metrics"),
+            Err(e) => tracing::error!(error = %e, "Metrics exporter failed, continuing without"),
+        }
+    }
+
     let (cmd_tx, cmd_rx) = mpsc::channel(100);
 
     // Server only holds the sending end — no VMM, no state
-    let server = Server::new(CommandSender::new(cmd_tx));
+    let commands_tx = CommandSender::new(cmd_tx);
+    let server = Server::new(commands_tx.clone());
 
     // Backend handles process spawning, socket management, config building.
     // All runtime settings come from the parsed config file.
-    let ch_config = CloudHypervisorConfig {
-        socket_dir: config.cloud_hypervisor.socket_dir,
-        ch_binary: config.cloud_hypervisor.binary_path,
-        socket_timeout: Duration::from_secs(config.cloud_hypervisor.socket_timeout_secs),
-        bridge_name: config.cloud_hypervisor.bridge_name,
+    let Some((backend, ch_binary, vm_dir, log_dir)) =
+        Backend::from_config(config.simulate, config.cloud_hypervisor)
+    else {
+        tracing::error!("Config needs a `cloud_hypervisor` section unless simulating");
+        return;
     };
 
-    tracing::info!(
-        ch_binary = %ch_config.ch_binary.display(),
-        socket_dir = %ch_config.socket_dir.display(),
-        socket_timeout_secs = ch_config.socket_timeout.as_secs(),
-        bridge_name = ?ch_config.bridge_name,
-        "Using cloud-hypervisor binary"
+    let probe = WorkerHealth::new(
+        commands_tx.clone(),
+        server.listening(),
+        ch_binary,
+        vm_dir,
     );
 
-    let backend = CloudHypervisorBackend::new(ch_config);
-
     // VmManager owns all VM state and handles commands sequentially.
-    let manager_config = VmManagerConfig::default();
-    let mut manager = VmManager::new(backend, manager_config);
+    let manager_config = VmManagerConfig::from(config.vms);
+    let forwarding = config.log_forwarding.and_then(|section| {
+        let worker_id = manager_config.worker_id.clone();
+        log_forward::start(section, worker_id, config.master_addr, log_dir)
+            .inspect_err(|e| tracing::error!(error = %e, "Log forwarding failed, continuing"))
+            .ok()
+    });
+    let issuer = config
+        .identity
+        .as_ref()
+        .map(|section| IdentityIssuer::load(section, &manager_config.worker_id))
+        .transpose();
+    let issuer = match issuer {
+        Ok(issuer) => issuer,
+        Err(e) => {
+            let error = repo_outils::report(&e);
+            tracing::error!(%error, "Cannot load the identity CA");
+            return;
+        }
+    };
+    let renew_every = issuer.as_ref().map(|issuer| renew_interval(issuer.ttl()));
+    let dns = config.dns_proxy.as_ref().map(|_| DnsPolicies::default());
+    let watchdog = config.boot_watchdog.map(BootWatchdog::from);
+    let check_boots_every = watchdog.as_ref().map(BootWatchdog::check_interval);
+    let pressure = config.memory_pressure.map(MemoryPressure::from);
+    let check_pressure_every = pressure.as_ref().map(MemoryPressure::check_interval);
+    let janitor = config.store_janitor.map(StoreJanitor::from);
+    let check_store_every = janitor.as_ref().map(StoreJanitor::check_interval);
+    let drain = config.drain.map(Drain::from);
+    let check_drains_every = drain.as_ref().map(Drain::check_interval);
+    let monitor = config.vmm_monitor.map(VmmMonitor::from);
+    let check_vmms_every = monitor.as_ref().map(VmmMonitor::check_interval);
+    let manager_task = match backend {
+        Backend::CloudHypervisor(backend) => spawn_manager(
+            backend,
+            manager_config,
+            issuer,
+            dns.clone(),
+            watchdog,
+            pressure,
+            janitor,
+            drain,
+            monitor,
+            cmd_rx,
+        ),
+        Backend::Simulated(backend) => spawn_manager(
+            backend,
+            manager_config,
+            issuer,
+            dns.clone(),
+            watchdog,
+            pressure,
+            janitor,
+            drain,
+            monitor,
+            cmd_rx,
+        ),
+    };
     tracing::info!(master_addr = %config.master_addr, "Worker manager started");
 
-    let manager_task = task::spawn(async move {
-        let mut cmd_rx = cmd_rx;
-        while let Some(msg) = cmd_rx.recv().await {
-            manager.handle(msg).await;
-        }
-        tracing::info!("Worker manager command channel closed, shutting down");
-    });
+    let shutdown = CancellationToken::new();
+    task::spawn(cancel_on_signal(shutdown.clone()));
+
+    if let Some(every) = renew_every {
+        tracing::info!(?every, "Renewing VM identities");
+        task::spawn(repeat(
+            commands_tx.clone(),
+            || CommandPayload::RenewIdentities,
+            every,
+            shutdown.clone(),
+        ));
+    }
+
+    if let Some(every) = check_boots_every {
+        tracing::info!(?every, "Watching VM boots");
+        task::spawn(repeat(
+            commands_tx.clone(),
+            || CommandPayload::CheckBoots,
+            every,
+            shutdown.clone(),
+        ));
+    }
+
+    if let Some(every) = check_pressure_every {
+        tracing::info!(?every, "Watching memory pressure");
+        task::spawn(repeat(
+            commands_tx.clone(),
+            || CommandPayload::CheckMemoryPressure,
+            every,
+            shutdown.clone(),
+        ));
+    }
+
+    if let Some(every) = check_drains_every {
+        tracing::info!(?every, "Draining deleted VMs");
+        task::spawn(repeat(
+            commands_tx.clone(),
+            || CommandPayload::CheckDrains,
+            every,
+            shutdown.clone(),
+        ));
+    }
+
+    if let Some(every) = check_vmms_every {
+        tracing::info!(?every, "Monitoring VMMs");
+        task::spawn(repeat(
+            commands_tx.clone(),
+            || CommandPayload::CheckVmms,
+            every,
+            shutdown.clone(),
+        ));
+    }
+
+    if let Some(every) = check_store_every {
+        tracing::info!(?every, "Managing the Nix store");
+        task::spawn(repeat(
+            commands_tx.clone(),
+            || CommandPayload::CheckStore,
+            every,
+            shutdown.clone(),
+        ));
+    }
+
+    if let (Some(section), Some(policies)) = (config.dns_proxy, dns) {
+        let stop = shutdown.clone();
+        task::spawn(async move {
+            let listen_addr = section.listen_addr;
+            if let Err(e) = dns_proxy::serve(section, policies, stop).await {
+                tracing::error!(%listen_addr, error = %e, "DNS proxy failed");
+            }
+        });
+    }
+
+    if let Some(section) = &config.health {
+        let stop = shutdown.clone().cancelled_owned();
+        let listen_addr = section.listen_addr;
+        let probe = probe.clone();
+        task::spawn(async move {
+            if let Err(e) = commands::health::serve(listen_addr, probe, stop).await {
+                tracing::error!(%listen_addr, error = %e, "Health listener failed");
+            }
+        });
+    }
+    task::spawn(commands::health::watchdog(
+        probe,
+        shutdown.clone().cancelled_owned(),
+    ));
 
     // capnp-rpc requires spawn_local, which needs a LocalSet context
     let local_set = task::LocalSet::new();
-    let server_task = local_set.run_until(task::spawn_local(server.serve(config.listen_addr)));
+    let server_result = local_set
+        .run_until(task::spawn_local(server.serve(config.listen_addr, shutdown.clone())))
+        .await;
+    // Drops the per-connection RPC systems along with any in-flight calls.
+    drop(local_set);
+
+    match server_result {
+        Ok(Ok(())) => tracing::info!("Worker server stopped accepting connections"),
+        Ok(Err(err)) => tracing::error!(?err, "Worker server failed"),
+        Err(err) => tracing::error!(?err, "Worker server task panicked"),
+    }
+
+    if let Err(e) = lifecycle::notify(&[NotifyState::Stopping]) {
+        tracing::warn!(error = %e, "sd_notify STOPPING failed");
+    }
+
+    // Commands already queued are handled first, Shutdown is the last one.
+    let timeout = Duration::from_secs(config.shutdown.timeout_secs);
+    let stop_vms = config.shutdown.stop_vms;
+    tracing::info!(?timeout, stop_vms, "Shutting down worker");
+    let drain = async move {
+        if let Err(e) = commands_tx.request(CommandPayload::Shutdown { stop_vms }).await {
+            tracing::warn!(error = %e, "Manager did not acknowledge shutdown");
+        }
+        drop(commands_tx);
+        manager_task.await
+    };
+
+    match tokio::time::timeout(timeout, drain).await {
+        Ok(Ok(())) => tracing::info!("Worker shut down cleanly"),
+        Ok(Err(err)) => tracing::error!(?err, "Worker manager task panicked"),
+        Err(_) => tracing::warn!(?timeout, "Shutdown timed out, exiting with work pending"),
+    }
+
+    if let Some(forwarding) = forwarding {
+        forwarding.shutdown(timeout).await;
+    }
+}
+
+/// Backend chosen by the config; `VmManager` is generic, so the two are
+/// spawned separately.
+enum Backend {
+    CloudHypervisor(CloudHypervisorBackend),
+    Simulated(MockBackend),
+}
+
+impl Backend {
+    /// The backend, the VMM binary the health probe checks (none when
+    /// simulating), the VM working directory and the directory holding
+    /// per-VM logs. `None` without either section.
+    fn from_config(
+        simulate: Option<SimulateSection>,
+        cloud_hypervisor: Option<CloudHypervisorSection>,
+    ) -> Option<(Self, Option<PathBuf>, PathBuf, PathBuf)> {
+        match (simulate, cloud_hypervisor) {
+            (Some(section), _) => {
+                tracing::warn!(
+                    boot_delay_ms = section.boot_delay_ms,
+                    vm_dir = %section.vm_dir.display(),
+                    "Simulating VMs with the mock backend, nothing is virtualized"
+                );
+                let (backend, _tracker) = MockBackend::with_config(MockBackendConfig {
+                    boot_delay: Duration::from_millis(section.boot_delay_ms),
+                    synthetic_metrics: true,
+                    ..MockBackendConfig::default()
+                });
+                let log_dir = section.vm_dir.clone();
+                Some((Backend::Simulated(backend), None, section.vm_dir, log_dir))
+            }
+            (None, Some(section)) => {
+                let ch_config = CloudHypervisorConfig {
+                    // Next to the staged images, so disks can be reflinked from them
+                    image_dir: section
+                        .image_dir
+                        .or_else(|| section.staging_dir.clone())
+                        .unwrap_or_else(|| section.socket_dir.clone()),
+                    log_dir: section.log_dir.unwrap_or_else(|| section.socket_dir.clone()),
+                    socket_dir: section.socket_dir,
+                    ch_binary: section.binary_path,
+                    socket_timeout: Duration::from_secs(section.socket_timeout_secs),
+                    bridge_name: section.bridge_name,
+                    trusted_public_keys: section.trusted_public_keys,
+                    virtiofsd_binary: section.virtiofsd_binary,
+                    staging_dir: section.staging_dir,
+                    vhost_user_net: section.vhost_user_net,
+                    hotplug: section.hotplug,
+                };
+
+                tracing::info!(
+                    ch_binary = %ch_config.ch_binary.display(),
+                    socket_dir = %ch_config.socket_dir.display(),
+                    image_dir = %ch_config.image_dir.display(),
+                    staging_dir = ?ch_config.staging_dir,
+                    log_dir = %ch_config.log_dir.display(),
+                    socket_timeout_secs = ch_config.socket_timeout.as_secs(),
+                    bridge_name = ?ch_config.bridge_name,
+                    trusted_keys = ch_config.trusted_public_keys.len(),
+                    virtiofsd = ?ch_config.virtiofsd_binary,
+                    vhost_user_net = ?ch_config.vhost_user_net,
+                    hotplug = ?ch_config.hotplug,
+                    "Using cloud-hypervisor binary"
+                );
+                if ch_config.trusted_public_keys.is_empty() {
+                    tracing::warn!(
+                        "No trusted_public_keys configured, VM images boot without signature verification"
+                    );
+                }
 
-    match join!(manager_task, server_task) {
-        (manager_result, server_result) => {
-            if let Err(err) = manager_result {
-                tracing::error!(?err, "Worker manager task panicked");
+                let ch_binary = ch_config.ch_binary.clone();
+                let vm_dir = ch_config.socket_dir.clone();
+                let log_dir = ch_config.log_dir.clone();
+                let backend = CloudHypervisorBackend::new(ch_config);
+                Some((
+                    Backend::CloudHypervisor(backend),
+                    Some(ch_binary),
+                    vm_dir,
+                    log_dir,
+                ))
             }
-            match server_result {
-                Ok(Ok(())) => tracing::info!("Worker server stopped gracefully"),
-                Ok(Err(err)) => tracing::error!(?err, "Worker server failed"),
-                Err(err) => tracing::error!(?err, "Worker server task panicked"),
+            (None, None) => None,
+        }
+    }
+}
+
+fn spawn_manager<B>(
+    backend: B,
+    config: VmManagerConfig,
+    issuer: Option<IdentityIssuer>,
+    dns: Option<DnsPolicies>,
+    watchdog: Option<BootWatchdog>,
+    pressure: Option<MemoryPressure>,
+    janitor: Option<StoreJanitor>,
+    drain: Option<Drain>,
+    monitor: Option<VmmMonitor>,
+    mut cmd_rx: mpsc::Receiver<Message>,
+) -> task::JoinHandle<()>
+where
+    B: VmmBackend + Sync,
+    B::Client: Sync,
+    B::Process: Sync,
+{
+    let mut manager = VmManager::new(backend, config);
+    if let Some(issuer) = issuer {
+        manager = manager.with_identity(issuer);
+    }
+    if let Some(dns) = dns {
+        manager = manager.with_dns_policies(dns);
+    }
+    if let Some(watchdog) = watchdog {
+        manager = manager.with_boot_watchdog(watchdog);
+    }
+    if let Some(pressure) = pressure {
+        manager = manager.with_memory_pressure(pressure);
+    }
+    if let Some(janitor) = janitor {
+        manager = manager.with_store_janitor(janitor);
+    }
+    if let Some(drain) = drain {
+        manager = manager.with_drain(drain);
+    }
+    if let Some(monitor) = monitor {
+        manager = manager.with_vmm_monitor(monitor);
+    }
+    task::spawn(async move {
+        let adopted = manager.adopt_running().await;
+        if adopted > 0 {
+            tracing::info!(adopted, "Adopted VMs left running by the previous worker");
+        }
+        while let Some(msg) = cmd_rx.recv().await {
+            metrics::command_queue_depth(cmd_rx.len());
+            manager.handle(msg).await;
+            if manager.is_stopped() {
+                break;
             }
         }
+        tracing::info!("Worker manager stopped");
+    })
+}
+
+/// Often enough that a certificate is renewed well before it expires,
+/// since it becomes due at half its lifetime.
+fn renew_interval(ttl: Duration) -> Duration {
+    (ttl / 4).clamp(Duration::from_secs(1), Duration::from_mins(5))
+}
+
+/// Send the manager `command()` every `every` until shutdown, e.g. to
+/// renew due identities, check booting VMs or memory pressure.
+async fn repeat(
+    commands: CommandSender,
+    command: fn() -> CommandPayload,
+    every: Duration,
+    stop: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(every);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            () = stop.cancelled() => return,
+            _ = ticker.tick() => {}
+        }
+        let command = command();
+        let name = format!("{command:?}");
+        if let Err(e) = commands.request(command).await {
+            tracing::warn!(command = %name, error = %e, "Periodic command failed");
+            if commands.is_closed() {
+                return;
+            }
+        }
+    }
+}
+
+/// Cancel `token` on SIGTERM/SIGINT.
+async fn cancel_on_signal(token: CancellationToken) {
+    match lifecycle::shutdown_signal().await {
+        Ok(signal) => tracing::info!(signal, "Received shutdown signal"),
+        Err(e) => {
+            tracing::error!(error = %e, "Cannot install signal handlers");
+            return;
+        }
     }
+    token.cancel();
 }
@@ -0,0 +1,113 @@
+//! Sink shipping batches to the master over `Master.pushLogs`.
+//!
+//! The connection is opened on first use and dropped after any failure, so
+//! the next batch reconnects. Must run inside a `LocalSet` (capnp-rpc uses
+//! `spawn_local`).
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use capnp_rpc::{RpcSystem, rpc_twoparty_capnp, twoparty};
+use commands::{common_capnp, master_capnp};
+use futures::AsyncReadExt;
+
+use super::LogRecord;
+use super::sink::{LogSink, SinkError};
+
+/// Upper bound for connecting and for one `pushLogs` call.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct MasterSink {
+    addr: SocketAddr,
+    worker_id: String,
+    client: Option<master_capnp::master::Client>,
+}
+
+impl MasterSink {
+    #[must_use]
+    pub fn new(addr: SocketAddr, worker_id: String) -> Self {
+        Self {
+            addr,
+            worker_id,
+            client: None,
+        }
+    }
+
+    async fn client(&mut self) -> Result<master_capnp::master::Client, SinkError> {
+        if let Some(client) = &self.client {
+            return Ok(client.clone());
+        }
+
+        let stream = tokio::time::timeout(TIMEOUT, tokio::net::TcpStream::connect(self.addr))
+            .await
+            .map_err(|_| SinkError::new(format!("connect to master {} timed out", self.addr)))?
+            .map_err(|e| SinkError::caused_by(format!("connect to master {}", self.addr), e))?;
+        stream
+            .set_nodelay(true)
+            .map_err(|e| SinkError::caused_by("set_nodelay", e))?;
+
+        let (reader, writer) = tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
+        let network = Box::new(twoparty::VatNetwork::new(
+            futures::io::BufReader::new(reader),
+            futures::io::BufWriter::new(writer),
+            rpc_twoparty_capnp::Side::Client,
+            capnp::message::ReaderOptions::default(),
+        ));
+        let mut rpc_system = RpcSystem::new(network, None);
+        let client: master_capnp::master::Client =
+            rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+        tokio::task::spawn_local(async move {
+            if let Err(e) = rpc_system.await {
+                tracing::debug!(error = %e, "Log forwarding RPC system terminated");
+            }
+        });
+
+        self.client = Some(client.clone());
+        Ok(client)
+    }
+
+    async fn push(
+        &self,
+        client: &master_capnp::master::Client,
+        batch: &[LogRecord],
+    ) -> capnp::Result<()> {
+        let mut request = client.push_logs_request();
+        {
+            let mut params = request.get();
+            params.set_worker_id(&self.worker_id);
+            let len = u32::try_from(batch.len())
+                .map_err(|_| capnp::Error::failed("log batch too large".to_string()))?;
+            let mut entries = params.init_entries(len);
+            for (i, record) in (0..len).zip(batch) {
+                let mut entry = entries.reborrow().get(i);
+                entry.set_timestamp_ms(record.timestamp_ms);
+                entry.set_source(&record.source);
+                entry.set_line(&record.line);
+            }
+        }
+
+        let response = request.send().promise.await?;
+        match response.get()?.get_result()?.which()? {
+            common_capnp::result::Which::Ok(_) => Ok(()),
+            common_capnp::result::Which::Err(e) => Err(capnp::Error::failed(
+                e?.to_str()
+                    .unwrap_or("master rejected logs (invalid utf-8 reason)")
+                    .to_string(),
+            )),
+        }
+    }
+}
+
+impl LogSink for MasterSink {
+    async fn send(&mut self, batch: &[LogRecord]) -> Result<(), SinkError> {
+        let client = self.client().await?;
+        let result = tokio::time::timeout(TIMEOUT, self.push(&client, batch)).await;
+        let error = match result {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(e)) => SinkError::caused_by(format!("pushLogs to master {}", self.addr), e),
+            Err(_) => SinkError::new(format!("pushLogs to master {} timed out", self.addr)),
+        };
+        self.client = None;
+        Err(error)
+    }
+}
@@ -0,0 +1,481 @@
+//! # Log forwarding
+//!
+//! Optional pipeline shipping the worker's own tracing output and the
+//! console logs of its VMs off the host, so they survive losing the worker.
+//!
+//! ```text
+//! tracing events ──┐
+//!                  ├─► bounded channel ─► Forwarder ─► LogSink (master | Loki | syslog)
+//! VM log tailer ───┘                        │   ▲
+//!                                           ▼   │ replay, oldest first
+//!                                          Spool (disk)
+//! ```
+//!
+//! - **Backpressure** — the tracing layer never blocks: when the channel is
+//!   full the record is dropped and counted. The tailer waits for room
+//!   instead; unread lines simply stay in the VM's log file.
+//! - **Disk buffering** — a batch the sink rejects is appended to the spool
+//!   and replayed before any newer batch, so ordering is kept across sink
+//!   outages and worker restarts. The spool is capped at `max_spool_bytes`.
+//! - The forwarder runs on its own thread with a current-thread runtime, so
+//!   the capnp master sink (not `Send`) works and spool I/O never stalls the
+//!   RPC server.
+//! - Events emitted by this module are not captured, so a failing sink
+//!   cannot feed its own errors back into the pipeline.
+
+mod master_sink;
+mod sink;
+mod spool;
+mod tail;
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber, debug, info, warn};
+use tracing_subscriber::layer::{Context, Layer};
+
+pub use master_sink::MasterSink;
+pub use sink::{LogSink, LokiSink, SinkError, SyslogSink};
+pub use spool::Spool;
+pub use tail::Tailer;
+
+use crate::metrics;
+
+/// Records buffered between producers and the forwarder.
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// How often VM log files are checked for new lines.
+const TAIL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Set once forwarding is started; the tracing layer is inert until then.
+static SENDER: OnceLock<mpsc::Sender<LogRecord>> = OnceLock::new();
+
+// ─── Configuration ─────────────────────────────────────────────────────────
+
+/// Where forwarded logs go.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// `Master.pushLogs` on the configured master.
+    Master,
+    /// Loki push API at `url` (e.g. `http://loki:3100`).
+    Loki {
+        url: String,
+        /// Extra static stream labels.
+        #[serde(default)]
+        labels: BTreeMap<String, String>,
+    },
+    /// RFC 5424 syslog over UDP.
+    Syslog { addr: SocketAddr },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogForwardConfig {
+    sink: SinkConfig,
+    /// Directory holding the spool of unsent batches.
+    spool_dir: PathBuf,
+    #[serde(default = "default_batch_size")]
+    batch_size: usize,
+    #[serde(default = "default_flush_interval_ms")]
+    flush_interval_ms: u64,
+    #[serde(default = "default_max_spool_bytes")]
+    max_spool_bytes: u64,
+    /// Also forward every VM's serial console and cloud-hypervisor log.
+    #[serde(default = "default_vm_logs")]
+    vm_logs: bool,
+}
+
+fn default_batch_size() -> usize {
+    500
+}
+
+fn default_flush_interval_ms() -> u64 {
+    2000
+}
+
+fn default_max_spool_bytes() -> u64 {
+    256 * 1024 * 1024
+}
+
+fn default_vm_logs() -> bool {
+    true
+}
+
+// ─── Records ───────────────────────────────────────────────────────────────
+
+/// One forwarded log line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// `worker`, or `vm:<id>/<file>` for VM logs.
+    pub source: String,
+    pub line: String,
+}
+
+impl LogRecord {
+    pub fn now(source: impl Into<String>, line: impl Into<String>) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+        Self {
+            timestamp_ms,
+            source: source.into(),
+            line: line.into(),
+        }
+    }
+}
+
+// ─── Tracing layer ─────────────────────────────────────────────────────────
+
+/// `tracing` layer copying the worker's events into the forwarder.
+///
+/// Can be installed unconditionally: it does nothing until [`start`] runs.
+pub struct ForwardLayer;
+
+impl<S: Subscriber> Layer<S> for ForwardLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let Some(tx) = SENDER.get() else {
+            return;
+        };
+        let meta = event.metadata();
+        if meta.target().starts_with(module_path!()) {
+            return;
+        }
+
+        let mut line = format!("{} {}:", meta.level(), meta.target());
+        event.record(&mut LineVisitor(&mut line));
+        if tx.try_send(LogRecord::now("worker", line)).is_err() {
+            metrics::log_records_dropped("channel_full", 1);
+        }
+    }
+}
+
+/// Renders an event as `message key=value ...`.
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {value}");
+        } else {
+            let _ = write!(self.0, " {}={value}", field.name());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, " {value:?}");
+        } else {
+            let _ = write!(self.0, " {}={value:?}", field.name());
+        }
+    }
+}
+
+// ─── Forwarder ─────────────────────────────────────────────────────────────
+
+/// Batches records and hands them to a sink, spooling what cannot be sent.
+pub struct Forwarder<S: LogSink> {
+    rx: mpsc::Receiver<LogRecord>,
+    sink: S,
+    spool: Spool,
+    batch_size: usize,
+    flush_interval: Duration,
+    /// Whether the last send failed, to log outages once rather than per flush.
+    sink_down: bool,
+}
+
+impl<S: LogSink> Forwarder<S> {
+    pub fn new(
+        rx: mpsc::Receiver<LogRecord>,
+        sink: S,
+        spool: Spool,
+        batch_size: usize,
+        flush_interval: Duration,
+    ) -> Self {
+        Self {
+            rx,
+            sink,
+            spool,
+            batch_size: batch_size.max(1),
+            flush_interval,
+            sink_down: false,
+        }
+    }
+
+    /// Forward until `shutdown` is cancelled or every sender is gone, then
+    /// flush what is queued (spooling it if the sink is unreachable).
+    pub async fn run(mut self, shutdown: CancellationToken) {
+        let mut batch = Vec::with_capacity(self.batch_size);
+        let mut tick = tokio::time::interval(self.flush_interval);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                () = shutdown.cancelled() => break,
+                record = self.rx.recv() => match record {
+                    Some(record) => {
+                        batch.push(record);
+                        if batch.len() >= self.batch_size {
+                            self.flush(&mut batch).await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = tick.tick() => self.flush(&mut batch).await,
+            }
+        }
+
+        while let Ok(record) = self.rx.try_recv() {
+            batch.push(record);
+        }
+        self.flush(&mut batch).await;
+        debug!(spooled_bytes = self.spool.size(), "Log forwarder stopped");
+    }
+
+    /// Replay the spool, then send `batch`. Whatever cannot be sent is spooled.
+    async fn flush(&mut self, batch: &mut Vec<LogRecord>) {
+        if self.replay().await && !batch.is_empty() {
+            match self.sink.send(batch).await {
+                Ok(()) => {
+                    self.sink_up();
+                    batch.clear();
+                    return;
+                }
+                Err(e) => self.sink_failed(&e),
+            }
+        }
+        if batch.is_empty() {
+            return;
+        }
+
+        match self.spool.push(batch) {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!(records = batch.len(), "Log spool full, dropping batch");
+                metrics::log_records_dropped("spool_full", batch.len() as u64);
+            }
+            Err(e) => {
+                warn!(error = %e, records = batch.len(), "Failed to spool logs, dropping batch");
+            }
+        }
+        metrics::log_spool_bytes(self.spool.size());
+        batch.clear();
+    }
+
+    /// Send spooled records oldest first. True once the spool is empty.
+    async fn replay(&mut self) -> bool {
+        while !self.spool.is_empty() {
+            let records = match self.spool.peek(self.batch_size) {
+                Ok(records) => records,
+                Err(e) => {
+                    warn!(error = %e, "Failed to read log spool");
+                    return false;
+                }
+            };
+            if let Err(e) = self.sink.send(&records).await {
+                self.sink_failed(&e);
+                return false;
+            }
+            self.sink_up();
+            if let Err(e) = self.spool.consume(records.len()) {
+                warn!(error = %e, "Failed to trim log spool");
+                return false;
+            }
+            metrics::log_spool_bytes(self.spool.size());
+        }
+        true
+    }
+
+    fn sink_failed(&mut self, error: &SinkError) {
+        if !self.sink_down {
+            let error = repo_outils::report(error);
+            warn!(%error, "Log sink unavailable, spooling to disk");
+        }
+        self.sink_down = true;
+    }
+
+    fn sink_up(&mut self) {
+        if self.sink_down {
+            info!("Log sink reachable again, replaying spool");
+        }
+        self.sink_down = false;
+    }
+}
+
+// ─── Startup ───────────────────────────────────────────────────────────────
+
+/// Running pipeline; stop it with [`LogForwarding::shutdown`].
+pub struct LogForwarding {
+    token: CancellationToken,
+    thread: std::thread::JoinHandle<()>,
+}
+
+/// Start forwarding: opens the spool, routes [`ForwardLayer`] into the
+/// pipeline, tails the VM logs under `vm_dir` and runs the forwarder thread.
+///
+/// # Errors
+///
+/// - if the spool cannot be opened or the forwarder thread cannot start
+pub fn start(
+    config: LogForwardConfig,
+    worker_id: String,
+    master_addr: SocketAddr,
+    vm_dir: PathBuf,
+) -> std::io::Result<LogForwarding> {
+    let spool = Spool::open(&config.spool_dir, config.max_spool_bytes)?;
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let token = CancellationToken::new();
+
+    if config.vm_logs {
+        let tailer = Tailer::new(vm_dir);
+        tokio::spawn(tailer.run(tx.clone(), TAIL_INTERVAL, token.clone()));
+    }
+    if SENDER.set(tx).is_err() {
+        warn!("Log forwarding already started, worker events go to the first pipeline");
+    }
+
+    let batch_size = config.batch_size;
+    let flush_interval = Duration::from_millis(config.flush_interval_ms);
+    let sink_config = config.sink;
+    let run_token = token.clone();
+    let thread = std::thread::Builder::new()
+        .name("log-forwarder".to_string())
+        .spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    warn!(error = %e, "Cannot start log forwarder runtime");
+                    return;
+                }
+            };
+            let local = tokio::task::LocalSet::new();
+            local.block_on(&runtime, async move {
+                match sink_config {
+                    SinkConfig::Master => {
+                        let sink = MasterSink::new(master_addr, worker_id);
+                        Forwarder::new(rx, sink, spool, batch_size, flush_interval)
+                            .run(run_token)
+                            .await;
+                    }
+                    SinkConfig::Loki { url, labels } => {
+                        let sink = LokiSink::new(&url, labels, worker_id);
+                        Forwarder::new(rx, sink, spool, batch_size, flush_interval)
+                            .run(run_token)
+                            .await;
+                    }
+                    SinkConfig::Syslog { addr } => {
+                        let sink = SyslogSink::new(addr, worker_id);
+                        Forwarder::new(rx, sink, spool, batch_size, flush_interval)
+                            .run(run_token)
+                            .await;
+                    }
+                }
+            });
+        })?;
+
+    Ok(LogForwarding { token, thread })
+}
+
+impl LogForwarding {
+    /// Stop tailing, flush or spool what is queued, and wait up to `timeout`.
+    pub async fn shutdown(self, timeout: Duration) {
+        self.token.cancel();
+        let thread = self.thread;
+        let joined = tokio::task::spawn_blocking(move || thread.join());
+        match tokio::time::timeout(timeout, joined).await {
+            Ok(Ok(Ok(()))) => {}
+            Ok(_) => warn!("Log forwarder thread panicked"),
+            Err(_) => warn!(?timeout, "Log forwarder did not stop in time"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Sink failing its first `failures` sends and recording the rest.
+    #[derive(Clone, Default)]
+    struct FlakySink {
+        failures: Arc<Mutex<usize>>,
+        received: Arc<Mutex<Vec<LogRecord>>>,
+    }
+
+    impl LogSink for FlakySink {
+        async fn send(&mut self, batch: &[LogRecord]) -> Result<(), SinkError> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(SinkError::new("unreachable"));
+            }
+            self.received.lock().unwrap().extend_from_slice(batch);
+            Ok(())
+        }
+    }
+
+    fn record(line: &str) -> LogRecord {
+        LogRecord {
+            timestamp_ms: 1,
+            source: "worker".to_string(),
+            line: line.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_batches_are_spooled_and_replayed_in_order() {
+        let dir = std::env::temp_dir().join(format!("pcr-forward-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let spool = Spool::open(&dir, 1024 * 1024).unwrap();
+        let sink = FlakySink::default();
+        *sink.failures.lock().unwrap() = 1;
+
+        let (_tx, rx) = mpsc::channel(8);
+        let mut forwarder = Forwarder::new(rx, sink.clone(), spool, 10, Duration::from_secs(60));
+
+        // Sink down: the batch lands in the spool.
+        let mut batch = vec![record("a"), record("b")];
+        forwarder.flush(&mut batch).await;
+        assert!(sink.received.lock().unwrap().is_empty());
+        assert!(!forwarder.spool.is_empty());
+
+        // Sink back: the spool is replayed before the new batch.
+        let mut batch = vec![record("c")];
+        forwarder.flush(&mut batch).await;
+        let lines: Vec<String> = sink
+            .received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| r.line.clone())
+            .collect();
+        assert_eq!(lines, vec!["a", "b", "c"]);
+        assert!(forwarder.spool.is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sink_config_is_tagged_by_type() {
+        let config: LogForwardConfig = serde_json::from_str(
+            r#"{"sink": {"type": "loki", "url": "http://loki:3100"}, "spool_dir": "/tmp/spool"}"#,
+        )
+        .unwrap();
+        assert!(matches!(config.sink, SinkConfig::Loki { .. }));
+        assert_eq!(config.batch_size, 500);
+        assert!(config.vm_logs);
+    }
+}
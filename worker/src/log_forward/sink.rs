@@ -0,0 +1,275 @@
+//! Destinations for forwarded logs.
+//!
+//! A sink either accepts a whole batch or fails; the forwarder spools
+//! failed batches and retries them later, so sinks do not retry themselves.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::SocketAddr;
+
+use hyper::client::HttpConnector;
+use tokio::net::UdpSocket;
+
+use super::LogRecord;
+
+/// A batch could not be delivered.
+#[derive(Debug, thiserror::Error)]
+#[error("{context}")]
+pub struct SinkError {
+    context: String,
+    #[source]
+    source: Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+impl SinkError {
+    pub fn new(context: impl Into<String>) -> Self {
+        Self {
+            context: context.into(),
+            source: None,
+        }
+    }
+
+    /// `context` failed because of `source`.
+    pub fn caused_by(
+        context: impl Into<String>,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        Self {
+            context: context.into(),
+            source: Some(source.into()),
+        }
+    }
+}
+
+/// Delivers batches of records somewhere off the host.
+///
+/// The futures need not be `Send`: the forwarder runs them on its own
+/// single-threaded runtime.
+pub trait LogSink {
+    fn send(&mut self, batch: &[LogRecord]) -> impl Future<Output = Result<(), SinkError>>;
+}
+
+// ─── Loki ──────────────────────────────────────────────────────────────────
+
+/// Grafana Loki push API (`POST /loki/api/v1/push`, JSON encoding).
+///
+/// Each record source becomes one stream labelled `job`, `worker` and
+/// `source`, plus any configured static labels.
+pub struct LokiSink {
+    push_url: String,
+    labels: BTreeMap<String, String>,
+    client: hyper::Client<HttpConnector>,
+}
+
+impl LokiSink {
+    #[must_use]
+    pub fn new(url: &str, mut labels: BTreeMap<String, String>, worker_id: String) -> Self {
+        labels.insert("job".to_string(), "procurator-worker".to_string());
+        labels.insert("worker".to_string(), worker_id);
+        Self {
+            push_url: format!("{}/loki/api/v1/push", url.trim_end_matches('/')),
+            labels,
+            client: hyper::Client::new(),
+        }
+    }
+
+    fn body(&self, batch: &[LogRecord]) -> serde_json::Value {
+        let mut streams: BTreeMap<&str, Vec<[String; 2]>> = BTreeMap::new();
+        for record in batch {
+            let nanos = u128::from(record.timestamp_ms) * 1_000_000;
+            streams
+                .entry(&record.source)
+                .or_default()
+                .push([nanos.to_string(), record.line.clone()]);
+        }
+
+        let streams: Vec<serde_json::Value> = streams
+            .into_iter()
+            .map(|(source, values)| {
+                let mut labels = self.labels.clone();
+                labels.insert("source".to_string(), source.to_string());
+                serde_json::json!({ "stream": labels, "values": values })
+            })
+            .collect();
+        serde_json::json!({ "streams": streams })
+    }
+}
+
+impl LogSink for LokiSink {
+    async fn send(&mut self, batch: &[LogRecord]) -> Result<(), SinkError> {
+        let body = serde_json::to_vec(&self.body(batch))
+            .map_err(|e| SinkError::caused_by("encode loki batch", e))?;
+        let request = hyper::Request::post(&self.push_url)
+            .header("content-type", "application/json")
+            .body(hyper::Body::from(body))
+            .map_err(|e| SinkError::caused_by("build loki request", e))?;
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| SinkError::caused_by(format!("loki push to {}", self.push_url), e))?;
+        if !response.status().is_success() {
+            return Err(SinkError::new(format!(
+                "loki push to {} returned {}",
+                self.push_url,
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+// ─── Syslog ────────────────────────────────────────────────────────────────
+
+/// RFC 5424 messages over UDP, facility `user`, severity `info`, one
+/// datagram per record.
+pub struct SyslogSink {
+    addr: SocketAddr,
+    hostname: String,
+    socket: Option<UdpSocket>,
+}
+
+/// `<PRI>` for facility user (1) and severity informational (6).
+const SYSLOG_PRI: u8 = 14;
+
+impl SyslogSink {
+    #[must_use]
+    pub fn new(addr: SocketAddr, hostname: String) -> Self {
+        Self {
+            addr,
+            hostname,
+            socket: None,
+        }
+    }
+
+    async fn socket(&mut self) -> std::io::Result<&UdpSocket> {
+        if self.socket.is_none() {
+            let bind: SocketAddr = if self.addr.is_ipv4() {
+                ([0, 0, 0, 0], 0).into()
+            } else {
+                ([0u16; 8], 0).into()
+            };
+            let socket = UdpSocket::bind(bind).await?;
+            socket.connect(self.addr).await?;
+            self.socket = Some(socket);
+        }
+        Ok(self.socket.as_ref().expect("socket was just bound"))
+    }
+
+    fn format(&self, record: &LogRecord) -> String {
+        format!(
+            "<{SYSLOG_PRI}>1 {} {} procurator-worker - - - {}: {}",
+            rfc3339(record.timestamp_ms),
+            self.hostname,
+            record.source,
+            record.line
+        )
+    }
+}
+
+impl LogSink for SyslogSink {
+    async fn send(&mut self, batch: &[LogRecord]) -> Result<(), SinkError> {
+        let messages: Vec<String> = batch.iter().map(|r| self.format(r)).collect();
+        let addr = self.addr;
+        let socket = self
+            .socket()
+            .await
+            .map_err(|e| SinkError::caused_by(format!("syslog socket for {addr}"), e))?;
+
+        let mut failed = None;
+        for message in &messages {
+            if let Err(e) = socket.send(message.as_bytes()).await {
+                failed = Some(e);
+                break;
+            }
+        }
+        if let Some(e) = failed {
+            // Rebind on the next attempt, e.g. after ICMP port unreachable.
+            self.socket = None;
+            return Err(SinkError::caused_by(format!("syslog send to {addr}"), e));
+        }
+        Ok(())
+    }
+}
+
+/// `YYYY-MM-DDTHH:MM:SS.mmmZ` for a Unix timestamp in milliseconds.
+fn rfc3339(timestamp_ms: u64) -> String {
+    let secs = timestamp_ms / 1000;
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Days since the epoch to a civil date (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        timestamp_ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(source: &str, line: &str) -> LogRecord {
+        LogRecord {
+            timestamp_ms: 1_700_000_000_123,
+            source: source.to_string(),
+            line: line.to_string(),
+        }
+    }
+
+    #[test]
+    fn rfc3339_formats_utc_timestamps() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339(1_700_000_000_123), "2023-11-14T22:13:20.123Z");
+        assert_eq!(rfc3339(951_782_400_000), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn loki_body_groups_records_by_source() {
+        let sink = LokiSink::new("http://loki:3100/", BTreeMap::new(), "w1".to_string());
+        assert_eq!(sink.push_url, "http://loki:3100/loki/api/v1/push");
+
+        let body = sink.body(&[
+            record("worker", "a"),
+            record("vm:x/serial", "b"),
+            record("worker", "c"),
+        ]);
+        let streams = body["streams"].as_array().unwrap();
+        assert_eq!(streams.len(), 2);
+        let worker = streams
+            .iter()
+            .find(|s| s["stream"]["source"] == "worker")
+            .unwrap();
+        assert_eq!(worker["stream"]["worker"], "w1");
+        assert_eq!(worker["values"][0][0], "1700000000123000000");
+        assert_eq!(worker["values"][1][1], "c");
+    }
+
+    #[tokio::test]
+    async fn syslog_sends_one_datagram_per_record() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut sink = SyslogSink::new(receiver.local_addr().unwrap(), "w1".to_string());
+
+        sink.send(&[record("worker", "hello")]).await.unwrap();
+
+        let mut buf = [0u8; 256];
+        let n = receiver.recv(&mut buf).await.unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            "<14>1 2023-11-14T22:13:20.123Z w1 procurator-worker - - - worker: hello"
+        );
+    }
+}
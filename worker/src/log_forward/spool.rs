@@ -0,0 +1,185 @@
+//! On-disk buffer of batches the sink did not accept.
+//!
+//! A single JSON-lines file, appended to at the end and trimmed from the
+//! front once records are delivered. Trimming rewrites the remainder through
+//! a temporary file and a rename, so a crash never loses the spool.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::LogRecord;
+
+const SPOOL_FILE: &str = "spool.jsonl";
+
+pub struct Spool {
+    path: PathBuf,
+    max_bytes: u64,
+    size: u64,
+}
+
+impl Spool {
+    /// Open the spool in `dir`, keeping records left by a previous run.
+    ///
+    /// # Errors
+    ///
+    /// - if `dir` cannot be created or the spool file cannot be inspected
+    pub fn open(dir: &Path, max_bytes: u64) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(SPOOL_FILE);
+        let size = match std::fs::metadata(&path) {
+            Ok(meta) => meta.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        if size > 0 {
+            tracing::info!(path = %path.display(), bytes = size, "Found unsent logs in spool");
+        }
+        Ok(Self {
+            path,
+            max_bytes,
+            size,
+        })
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Current spool size in bytes.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Append `batch`. Returns `false` without writing if it would exceed
+    /// the size cap.
+    ///
+    /// # Errors
+    ///
+    /// - if the spool file cannot be written
+    pub fn push(&mut self, batch: &[LogRecord]) -> io::Result<bool> {
+        let mut buf = Vec::new();
+        for record in batch {
+            serde_json::to_writer(&mut buf, record)?;
+            buf.push(b'\n');
+        }
+        if self.size + buf.len() as u64 > self.max_bytes {
+            return Ok(false);
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&buf)?;
+        file.sync_data()?;
+        self.size += buf.len() as u64;
+        Ok(true)
+    }
+
+    /// Up to `max` oldest records. Unparseable lines are skipped.
+    ///
+    /// # Errors
+    ///
+    /// - if the spool file cannot be read
+    pub fn peek(&self, max: usize) -> io::Result<Vec<LogRecord>> {
+        let Some(file) = self.open_existing()? else {
+            return Ok(Vec::new());
+        };
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines().take(max) {
+            match serde_json::from_str(&line?) {
+                Ok(record) => records.push(record),
+                Err(e) => tracing::warn!(error = %e, "Skipping corrupt spool line"),
+            }
+        }
+        Ok(records)
+    }
+
+    /// Drop the `count` oldest lines.
+    ///
+    /// # Errors
+    ///
+    /// - if the remainder cannot be written back
+    pub fn consume(&mut self, count: usize) -> io::Result<()> {
+        let Some(file) = self.open_existing()? else {
+            self.size = 0;
+            return Ok(());
+        };
+
+        let tmp = self.path.with_extension("jsonl.tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        let mut size = 0;
+        for line in BufReader::new(file).lines().skip(count) {
+            let line = line?;
+            out.write_all(line.as_bytes())?;
+            out.write_all(b"\n")?;
+            size += line.len() as u64 + 1;
+        }
+        out.into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()?;
+        std::fs::rename(&tmp, &self.path)?;
+        self.size = size;
+        Ok(())
+    }
+
+    fn open_existing(&self) -> io::Result<Option<File>> {
+        match File::open(&self.path) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(line: &str) -> LogRecord {
+        LogRecord {
+            timestamp_ms: 1,
+            source: "vm:x/serial".to_string(),
+            line: line.to_string(),
+        }
+    }
+
+    #[test]
+    fn spool_survives_reopen_and_trims_from_the_front() {
+        let dir = std::env::temp_dir().join(format!("pcr-spool-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut spool = Spool::open(&dir, 1024 * 1024).unwrap();
+        assert!(
+            spool
+                .push(&[record("a"), record("b"), record("c")])
+                .unwrap()
+        );
+
+        let mut spool = Spool::open(&dir, 1024 * 1024).unwrap();
+        assert!(!spool.is_empty());
+        assert_eq!(spool.peek(2).unwrap(), vec![record("a"), record("b")]);
+
+        spool.consume(2).unwrap();
+        assert_eq!(spool.peek(10).unwrap(), vec![record("c")]);
+        spool.consume(1).unwrap();
+        assert!(spool.is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn push_refuses_to_exceed_the_cap() {
+        let dir = std::env::temp_dir().join(format!("pcr-spool-cap-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut spool = Spool::open(&dir, 10).unwrap();
+        assert!(!spool.push(&[record("too long for ten bytes")]).unwrap());
+        assert!(spool.is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,214 @@
+//! Follows the log files the cloud-hypervisor backend writes per VM
+//! (`<vm_dir>/<vm_id>/serial.log` and `cloud-hypervisor.log`).
+//!
+//! Files that already exist when the tailer starts are followed from their
+//! current end, so a worker restart does not resend old output; files that
+//! appear later are read from the beginning. A file that shrinks is assumed
+//! truncated and re-read from the start.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::LogRecord;
+
+/// Per-VM files that are forwarded, with the source suffix used for them.
+const LOG_FILES: &[(&str, &str)] = &[("serial.log", "serial"), ("cloud-hypervisor.log", "vmm")];
+
+/// Upper bound read from one file per poll, so one chatty VM cannot starve
+/// the others.
+const MAX_READ_PER_POLL: u64 = 1024 * 1024;
+
+struct Followed {
+    source: String,
+    offset: u64,
+    /// Bytes after the last newline, held until the line is complete.
+    partial: Vec<u8>,
+}
+
+pub struct Tailer {
+    vm_dir: PathBuf,
+    files: HashMap<PathBuf, Followed>,
+}
+
+impl Tailer {
+    /// Start following `vm_dir`, skipping what its log files already contain.
+    #[must_use]
+    pub fn new(vm_dir: PathBuf) -> Self {
+        let mut tailer = Self {
+            vm_dir,
+            files: HashMap::new(),
+        };
+        for (path, source) in tailer.discover() {
+            let offset = std::fs::metadata(&path).map_or(0, |m| m.len());
+            tailer.files.insert(
+                path,
+                Followed {
+                    source,
+                    offset,
+                    partial: Vec::new(),
+                },
+            );
+        }
+        tailer
+    }
+
+    /// Poll every `interval` and send new lines until `shutdown` is cancelled.
+    ///
+    /// Waits for room in the channel rather than dropping lines: unread
+    /// output stays in the files until the forwarder catches up.
+    pub async fn run(
+        mut self,
+        tx: mpsc::Sender<LogRecord>,
+        interval: Duration,
+        shutdown: CancellationToken,
+    ) {
+        loop {
+            for record in self.poll() {
+                tokio::select! {
+                    () = shutdown.cancelled() => return,
+                    sent = tx.send(record) => {
+                        if sent.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            tokio::select! {
+                () = shutdown.cancelled() => return,
+                () = tokio::time::sleep(interval) => {}
+            }
+        }
+    }
+
+    /// Read complete new lines from every followed file.
+    pub fn poll(&mut self) -> Vec<LogRecord> {
+        let present = self.discover();
+        self.files
+            .retain(|path, _| present.iter().any(|(p, _)| p == path));
+        for (path, source) in present {
+            self.files.entry(path).or_insert(Followed {
+                source,
+                offset: 0,
+                partial: Vec::new(),
+            });
+        }
+
+        let mut records = Vec::new();
+        for (path, followed) in &mut self.files {
+            if let Err(e) = read_new_lines(path, followed, &mut records) {
+                tracing::debug!(path = %path.display(), error = %e, "Cannot read VM log");
+            }
+        }
+        records
+    }
+
+    /// `(path, source)` of every per-VM log file currently on disk.
+    fn discover(&self) -> Vec<(PathBuf, String)> {
+        let Ok(entries) = std::fs::read_dir(&self.vm_dir) else {
+            return Vec::new();
+        };
+        let mut found = Vec::new();
+        for entry in entries.flatten() {
+            let dir = entry.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            let vm_id = entry.file_name().to_string_lossy().to_string();
+            for (file, suffix) in LOG_FILES {
+                let path = dir.join(file);
+                if path.is_file() {
+                    found.push((path, format!("vm:{vm_id}/{suffix}")));
+                }
+            }
+        }
+        found
+    }
+}
+
+fn read_new_lines(
+    path: &Path,
+    followed: &mut Followed,
+    out: &mut Vec<LogRecord>,
+) -> io::Result<()> {
+    let len = std::fs::metadata(path)?.len();
+    if len < followed.offset {
+        followed.offset = 0;
+        followed.partial.clear();
+    }
+    if len == followed.offset {
+        return Ok(());
+    }
+
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(followed.offset))?;
+    let mut buf = Vec::new();
+    let read = file.take(MAX_READ_PER_POLL).read_to_end(&mut buf)?;
+    followed.offset += read as u64;
+
+    followed.partial.extend_from_slice(&buf);
+    let Some(last_newline) = followed.partial.iter().rposition(|b| *b == b'\n') else {
+        return Ok(());
+    };
+    let rest = followed.partial.split_off(last_newline + 1);
+    let complete = std::mem::replace(&mut followed.partial, rest);
+
+    for line in String::from_utf8_lossy(&complete).lines() {
+        let line = line.trim_end_matches('\r');
+        if !line.is_empty() {
+            out.push(LogRecord::now(followed.source.clone(), line));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn append(path: &Path, text: &str) {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn follows_new_output_and_holds_partial_lines() {
+        let dir = std::env::temp_dir().join(format!("pcr-tail-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("vm1")).unwrap();
+        let serial = dir.join("vm1").join("serial.log");
+        append(&serial, "booted before the worker\n");
+
+        let mut tailer = Tailer::new(dir.clone());
+        assert!(tailer.poll().is_empty(), "existing output is not resent");
+
+        append(&serial, "login: \nwelcome\npartial");
+        let lines: Vec<String> = tailer.poll().into_iter().map(|r| r.line).collect();
+        assert_eq!(lines, vec!["login: ", "welcome"]);
+
+        append(&serial, " line\n");
+        let records = tailer.poll();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].line, "partial line");
+        assert_eq!(records[0].source, "vm:vm1/serial");
+
+        // A VM created after startup is read from the beginning.
+        std::fs::create_dir_all(dir.join("vm2")).unwrap();
+        append(&dir.join("vm2").join("cloud-hypervisor.log"), "started\n");
+        let records = tailer.poll();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].source, "vm:vm2/vmm");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,10 +1,23 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
+use commands::telemetry::{self, TelemetryConfig};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use worker::Config;
+use worker::identity;
 
 #[tokio::main]
 async fn main() {
+    // Usage: worker issue-ca <trust_domain> <worker_id> <root.pem> <root.key> <out_dir> [ttl_days]
+    if std::env::args().nth(1).as_deref() == Some("issue-ca") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        if let Err(e) = issue_ca(&args) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let filter = tracing_subscriber::EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| {
             tracing_subscriber::EnvFilter::new(
@@ -12,6 +25,13 @@ async fn main() {
             )
         });
 
+    // Spans are exported when OTEL_EXPORTER_OTLP_ENDPOINT is set.
+    let (otel, otel_guard) = telemetry::otel_layer(&TelemetryConfig::from_env("procurator-worker"))
+        .unwrap_or_else(|e| {
+            eprintln!("invalid OTLP exporter configuration: {e}");
+            std::process::exit(1);
+        });
+
     tracing_subscriber::registry()
         .with(filter)
         .with(
@@ -19,23 +39,68 @@ async fn main() {
                 .log_internal_errors(true)
                 .with_target(false),
         )
+        .with(otel)
+        .with(worker::log_forward::ForwardLayer)
         .init();
 
-    let config_path = std::env::args()
-        .nth(1)
-        .map(PathBuf::from)
-        .expect("Config path must be provided as the first argument");
+    // Usage: worker [--simulate] <config.json>
+    let mut simulate = false;
+    let mut config_path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--simulate" => simulate = true,
+            _ => config_path = Some(PathBuf::from(arg)),
+        }
+    }
+    let config_path = config_path.expect("Config path must be provided as an argument");
 
-    let contents = tokio::fs::read(&config_path).await.unwrap_or_else(|e| {
-        tracing::error!(path = ?config_path, error = %e, "Could not read config");
+    let mut cfg: Config = worker::config::load(&config_path, std::env::vars()).unwrap_or_else(|e| {
+        let error = repo_outils::report(&e);
+        tracing::error!(path = ?config_path, %error, "Failed to load config");
         std::process::exit(1);
     });
-
-    let cfg: Config = serde_json::from_slice(&contents).unwrap_or_else(|e| {
-        tracing::error!(path = ?config_path, error = %e, "Failed to parse config");
+    if simulate {
+        cfg.simulate();
+    }
+    if let Err(issues) = cfg.validate() {
+        for issue in &issues {
+            tracing::error!(path = ?config_path, "Invalid config: {issue}");
+        }
         std::process::exit(1);
-    });
+    }
 
     worker::main(cfg)
     .await;
+    otel_guard.shutdown();
+}
+
+/// Sign the intermediate CA of one worker with the cluster root, see
+/// [`identity::issue_worker_ca`].
+fn issue_ca(args: &[String]) -> Result<(), String> {
+    const USAGE: &str = "usage: procurator-worker issue-ca <trust_domain> <worker_id> \
+                         <root.pem> <root.key> <out_dir> [ttl_days]";
+    let (trust_domain, worker_id, root_cert, root_key, out_dir, ttl_days) = match args {
+        [td, worker, cert, key, out] => (td, worker, cert, key, out, 365),
+        [td, worker, cert, key, out, days] => {
+            let days = days.parse::<u64>().ok().filter(|days| *days > 0);
+            (td, worker, cert, key, out, days.ok_or(USAGE)?)
+        }
+        _ => return Err(USAGE.to_string()),
+    };
+    let read = |path: &String| {
+        std::fs::read_to_string(path).map_err(|e| format!("cannot read {path}: {e}"))
+    };
+    let ca = identity::issue_worker_ca(
+        trust_domain,
+        worker_id,
+        &read(root_cert)?,
+        &read(root_key)?,
+        Duration::from_hours(ttl_days * 24),
+        SystemTime::now(),
+    )
+    .map_err(|e| repo_outils::report(&e))?;
+    ca.write(Path::new(out_dir))
+        .map_err(|e| format!("cannot write {out_dir}: {e}"))?;
+    println!("Wrote {out_dir}/ca.pem and {out_dir}/ca.key for worker {worker_id}");
+    Ok(())
 }
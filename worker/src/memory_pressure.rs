@@ -0,0 +1,333 @@
+//! # Memory pressure
+//!
+//! Guest memory is overcommitted: a VM's `memory_mb` is only what it may
+//! use, and the host backs it lazily, or with zram swap when the host has
+//! it set up. When the guests grow into more than the host has, the kernel
+//! OOM killer picks a cloud-hypervisor process and the VM is simply gone.
+//!
+//! With a `memory_pressure` section the worker reads the host's pressure
+//! stall information (`/proc/pressure/memory`) every `check_interval_secs`.
+//! Once the share of time some task stalled on memory (`some avg10`) stays
+//! at or above `threshold_percent` for `sustained_checks` checks in a row,
+//! the host is under pressure until a check falls below it again. Each
+//! episode is logged, counted in the metrics and reported in
+//! `Worker.read`, and `policy` decides what else happens:
+//!
+//! - `report`: nothing, the master decides
+//! - `throttle`: new VMs are refused as unschedulable while under pressure
+//! - `restart`: the running VM with the most memory is redeployed, freeing
+//!   what its guest took, on entering pressure and again after every
+//!   `sustained_checks` checks it lasts
+//!
+//! VMs backed by hugepages are never restarted: their memory is reserved
+//! up front and freeing it does not relieve the pressure.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Where the kernel reports memory pressure.
+pub const PSI_MEMORY: &str = "/proc/pressure/memory";
+
+/// Enables the memory-pressure monitor; disabled when the section is absent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MemoryPressureSection {
+    /// PSI file to read, for hosts that expose it elsewhere.
+    pub psi_path: PathBuf,
+    /// How often pressure is read.
+    pub check_interval_secs: u64,
+    /// `some avg10` percentage at which the host counts as under pressure.
+    pub threshold_percent: f32,
+    /// Checks in a row at or above the threshold before acting.
+    pub sustained_checks: u32,
+    /// What happens while the host is under pressure.
+    pub policy: PressurePolicy,
+}
+
+impl Default for MemoryPressureSection {
+    fn default() -> Self {
+        Self {
+            psi_path: PathBuf::from(PSI_MEMORY),
+            check_interval_secs: 5,
+            threshold_percent: 20.0,
+            sustained_checks: 3,
+            policy: PressurePolicy::Report,
+        }
+    }
+}
+
+/// What the worker does about memory pressure, see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PressurePolicy {
+    /// Only log and report it
+    Report,
+    /// Refuse new VMs until it is relieved
+    Throttle,
+    /// Redeploy the running VM with the most memory
+    Restart,
+}
+
+impl PressurePolicy {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Report => "report",
+            Self::Throttle => "throttle",
+            Self::Restart => "restart",
+        }
+    }
+}
+
+impl fmt::Display for PressurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Ten-second averages of the PSI file, all zeroes when it has none.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Psi {
+    /// Percentage of time at least one task stalled on memory
+    pub some_avg10: f32,
+    /// Percentage of time every non-idle task stalled on memory
+    pub full_avg10: f32,
+}
+
+impl Psi {
+    /// Parse the `some` and `full` lines of a PSI file.
+    #[must_use]
+    pub fn parse(psi: &str) -> Self {
+        let mut pressure = Self::default();
+        for line in psi.lines() {
+            let mut fields = line.split_whitespace();
+            let kind = fields.next();
+            let avg10 = fields
+                .find_map(|field| field.strip_prefix("avg10="))
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0.0);
+            match kind {
+                Some("some") => pressure.some_avg10 = avg10,
+                Some("full") => pressure.full_avg10 = avg10,
+                _ => {}
+            }
+        }
+        pressure
+    }
+
+    /// The pressure reported at `path`, none when it cannot be read, e.g.
+    /// on a kernel built without PSI.
+    #[must_use]
+    pub fn read(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(psi) => Self::parse(&psi),
+            Err(e) => {
+                tracing::warn!(path = %path.display(), error = %e, "Cannot read memory pressure");
+                Self::default()
+            }
+        }
+    }
+}
+
+/// What changed with a check, see [`MemoryPressure::observe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    /// The threshold was exceeded for `sustained_checks` checks
+    Entered,
+    /// Still under pressure `sustained_checks` checks later
+    Persisting,
+    /// Below the threshold again
+    Relieved,
+}
+
+/// Memory pressure as `Worker.read` reports it, all zeroes when the
+/// monitor is disabled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PressureReport {
+    pub psi: Psi,
+    pub under_pressure: bool,
+    /// `None` when the monitor is disabled
+    pub policy: Option<PressurePolicy>,
+    /// Times the host came under pressure since the worker started
+    pub episodes: u64,
+    /// VMs the `restart` policy redeployed
+    pub restarts: u64,
+}
+
+/// Tracks the host's memory pressure across checks, see the module docs.
+#[derive(Debug, Clone)]
+pub struct MemoryPressure {
+    psi_path: PathBuf,
+    check_interval: Duration,
+    threshold: f32,
+    sustained_checks: u32,
+    policy: PressurePolicy,
+    /// Checks in a row at or above the threshold since the last transition
+    above: u32,
+    report: PressureReport,
+}
+
+impl MemoryPressure {
+    /// A monitor of `/proc/pressure/memory` with the default interval and
+    /// number of checks.
+    #[must_use]
+    pub fn new(threshold_percent: f32, policy: PressurePolicy) -> Self {
+        Self::from(MemoryPressureSection {
+            threshold_percent,
+            policy,
+            ..MemoryPressureSection::default()
+        })
+    }
+
+    #[must_use]
+    pub fn policy(&self) -> PressurePolicy {
+        self.policy
+    }
+
+    #[must_use]
+    pub fn check_interval(&self) -> Duration {
+        self.check_interval
+    }
+
+    #[must_use]
+    pub fn psi_path(&self) -> &Path {
+        &self.psi_path
+    }
+
+    #[must_use]
+    pub fn is_under_pressure(&self) -> bool {
+        self.report.under_pressure
+    }
+
+    /// Whether new VMs are refused right now.
+    #[must_use]
+    pub fn throttles(&self) -> bool {
+        self.policy == PressurePolicy::Throttle && self.report.under_pressure
+    }
+
+    #[must_use]
+    pub fn report(&self) -> PressureReport {
+        self.report
+    }
+
+    /// Count a VM redeployed to relieve the pressure.
+    pub fn restarted(&mut self) {
+        self.report.restarts += 1;
+    }
+
+    /// Record one check of `psi`.
+    pub fn observe(&mut self, psi: Psi) -> Option<Transition> {
+        self.report.psi = psi;
+        if psi.some_avg10 < self.threshold {
+            self.above = 0;
+            let relieved = self.report.under_pressure;
+            self.report.under_pressure = false;
+            return relieved.then_some(Transition::Relieved);
+        }
+        self.above += 1;
+        if self.above < self.sustained_checks {
+            return None;
+        }
+        self.above = 0;
+        if self.report.under_pressure {
+            return Some(Transition::Persisting);
+        }
+        self.report.under_pressure = true;
+        self.report.episodes += 1;
+        Some(Transition::Entered)
+    }
+}
+
+impl From<MemoryPressureSection> for MemoryPressure {
+    fn from(section: MemoryPressureSection) -> Self {
+        Self {
+            psi_path: section.psi_path,
+            check_interval: Duration::from_secs(section.check_interval_secs),
+            threshold: section.threshold_percent,
+            sustained_checks: section.sustained_checks.max(1),
+            policy: section.policy,
+            above: 0,
+            report: PressureReport {
+                policy: Some(section.policy),
+                ..PressureReport::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn psi(some_avg10: f32) -> Psi {
+        Psi {
+            some_avg10,
+            full_avg10: 0.0,
+        }
+    }
+
+    #[test]
+    fn parses_the_ten_second_averages() {
+        let pressure = Psi::parse(
+            "some avg10=31.52 avg60=12.01 avg300=3.40 total=123456\n\
+             full avg10=8.25 avg60=2.00 avg300=0.50 total=2345\n",
+        );
+        assert_eq!(
+            pressure,
+            Psi {
+                some_avg10: 31.52,
+                full_avg10: 8.25
+            }
+        );
+        assert_eq!(Psi::parse(""), Psi::default());
+    }
+
+    #[test]
+    fn pressure_must_be_sustained() {
+        let mut monitor = MemoryPressure::new(20.0, PressurePolicy::Report);
+        assert_eq!(monitor.observe(psi(50.0)), None);
+        assert_eq!(monitor.observe(psi(50.0)), None);
+        assert_eq!(monitor.observe(psi(5.0)), None);
+        assert!(!monitor.is_under_pressure());
+
+        for _ in 0..2 {
+            assert_eq!(monitor.observe(psi(20.0)), None);
+        }
+        assert_eq!(monitor.observe(psi(20.0)), Some(Transition::Entered));
+        assert!(monitor.is_under_pressure());
+        for _ in 0..2 {
+            assert_eq!(monitor.observe(psi(40.0)), None);
+        }
+        assert_eq!(monitor.observe(psi(40.0)), Some(Transition::Persisting));
+        assert_eq!(monitor.observe(psi(1.0)), Some(Transition::Relieved));
+        assert_eq!(monitor.observe(psi(1.0)), None);
+
+        let report = monitor.report();
+        assert!(!report.under_pressure);
+        assert_eq!(report.episodes, 1);
+        assert_eq!(report.policy, Some(PressurePolicy::Report));
+    }
+
+    #[test]
+    fn only_throttle_refuses_vms() {
+        let section: MemoryPressureSection = serde_json::from_str(
+            r#"{"threshold_percent": 10, "sustained_checks": 1, "policy": "throttle"}"#,
+        )
+        .unwrap();
+        assert_eq!(section.psi_path, Path::new(PSI_MEMORY));
+        let mut monitor = MemoryPressure::from(section);
+        assert!(!monitor.throttles());
+        assert_eq!(monitor.observe(psi(10.0)), Some(Transition::Entered));
+        assert!(monitor.throttles());
+
+        let mut monitor = MemoryPressure::new(10.0, PressurePolicy::Restart);
+        for _ in 0..3 {
+            monitor.observe(psi(10.0));
+        }
+        assert!(monitor.is_under_pressure());
+        assert!(!monitor.throttles());
+    }
+}
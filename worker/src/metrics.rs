@@ -0,0 +1,271 @@
+//! # Prometheus metrics
+//!
+//! Recorded through the [`metrics`] facade from wherever the event happens,
+//! so recording is free until [`install`] registers the Prometheus exporter.
+//! The exporter then serves the text format on `GET /metrics` at the
+//! configured `metrics.listen_addr`.
+//!
+//! | Metric                                             | Type      | Labels           |
+//! |----------------------------------------------------|-----------|------------------|
+//! | `procurator_worker_vm_boot_duration_seconds`       | histogram |                  |
+//! | `procurator_worker_image_prepare_duration_seconds` | histogram | `result`         |
+//! | `procurator_worker_vmm_operations_total`           | counter   | `op`, `result`   |
+//! | `procurator_worker_vmm_unexpected_exits_total`     | counter   |                  |
+//! | `procurator_worker_vmm_unresponsive_total`         | counter   |                  |
+//! | `procurator_worker_vm_boot_failures_total`         | counter   |                  |
+//! | `procurator_worker_vm_drains_total`                | counter   | `result`         |
+//! | `procurator_worker_vm_updates_total`               | counter   | `applied`        |
+//! | `procurator_worker_vms_running`                    | gauge     |                  |
+//! | `procurator_worker_command_queue_depth`            | gauge     |                  |
+//! | `procurator_worker_log_records_dropped_total`      | counter   | `reason`         |
+//! | `procurator_worker_log_spool_bytes`                | gauge     |                  |
+//! | `procurator_worker_dns_queries_total`              | counter   | `result`         |
+//! | `procurator_worker_staged_disks_total`             | counter   | `image`, `clone` |
+//! | `procurator_worker_memory_pressure_percent`        | gauge     | `kind`           |
+//! | `procurator_worker_memory_pressure_events_total`   | counter   | `event`          |
+//! | `procurator_worker_store_used_percent`             | gauge     |                  |
+//! | `procurator_worker_store_collections_total`        | counter   | `result`         |
+//! | `procurator_worker_store_freed_bytes_total`        | counter   |                  |
+//!
+//! Boot duration covers spawn → create → boot → network attach; the time
+//! spent fetching artifacts from the cache is the separate prepare
+//! histogram. Unexpected exits are VMM processes that exited on their own,
+//! right after boot or as seen by the [VMM monitor](crate::vmm_monitor),
+//! and unresponsive VMMs the ones the monitor killed for not answering.
+//! Boot failures are the VMs the [boot watchdog](crate::boot_watchdog)
+//! gave up waiting for.
+//! Drains are the deleted VMs that were given time to [power
+//! off](crate::drain), by whether they did (`clean`) or were stopped at the
+//! timeout (`forced`).
+//! Updates are the VMs moved to a new spec, by whether they were resized
+//! in place (`resized`) or deployed again (`redeployed`).
+//! Staged disks count the VM disks made from [staged](crate::vmm::staging)
+//! images, by whether the image was staged already (`hit`, `miss`) and how
+//! the disk was made (`reflink`, `copy`).
+//! Memory pressure is the host's `some` and `full` ten-second average,
+//! as last read by the [monitor](crate::memory_pressure), and its events
+//! are the episodes it saw (`entered`, `relieved`) and the VMs its policy
+//! restarted (`restart`).
+//! Store usage is the share of the store's filesystem in use at the last
+//! check of the [janitor](crate::store_janitor), and collections are the
+//! garbage collections it ran above the high watermark.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use metrics::{
+    Unit, counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram,
+};
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder};
+
+pub const VM_BOOT_DURATION: &str = "procurator_worker_vm_boot_duration_seconds";
+pub const IMAGE_PREPARE_DURATION: &str = "procurator_worker_image_prepare_duration_seconds";
+pub const VMM_OPERATIONS: &str = "procurator_worker_vmm_operations_total";
+pub const VMM_UNEXPECTED_EXITS: &str = "procurator_worker_vmm_unexpected_exits_total";
+pub const VMM_UNRESPONSIVE: &str = "procurator_worker_vmm_unresponsive_total";
+pub const VM_BOOT_FAILURES: &str = "procurator_worker_vm_boot_failures_total";
+pub const VM_DRAINS: &str = "procurator_worker_vm_drains_total";
+pub const VM_UPDATES: &str = "procurator_worker_vm_updates_total";
+pub const VMS_RUNNING: &str = "procurator_worker_vms_running";
+pub const COMMAND_QUEUE_DEPTH: &str = "procurator_worker_command_queue_depth";
+pub const LOG_RECORDS_DROPPED: &str = "procurator_worker_log_records_dropped_total";
+pub const LOG_SPOOL_BYTES: &str = "procurator_worker_log_spool_bytes";
+pub const DNS_QUERIES: &str = "procurator_worker_dns_queries_total";
+pub const STAGED_DISKS: &str = "procurator_worker_staged_disks_total";
+pub const MEMORY_PRESSURE: &str = "procurator_worker_memory_pressure_percent";
+pub const MEMORY_PRESSURE_EVENTS: &str = "procurator_worker_memory_pressure_events_total";
+pub const STORE_USED: &str = "procurator_worker_store_used_percent";
+pub const STORE_COLLECTIONS: &str = "procurator_worker_store_collections_total";
+pub const STORE_FREED: &str = "procurator_worker_store_freed_bytes_total";
+
+/// Boots take seconds, artifact copies can take minutes.
+const DURATION_BUCKETS: &[f64] = &[
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+];
+
+/// Start the `/metrics` listener and install it as the global recorder.
+///
+/// Must be called from inside a Tokio runtime.
+///
+/// # Errors
+///
+/// - if the listener cannot be set up or a recorder is already installed
+pub fn install(listen_addr: SocketAddr) -> Result<(), BuildError> {
+    PrometheusBuilder::new()
+        .with_http_listener(listen_addr)
+        .set_buckets_for_metric(
+            Matcher::Suffix("_duration_seconds".to_string()),
+            DURATION_BUCKETS,
+        )?
+        .install()?;
+    describe();
+    Ok(())
+}
+
+/// Register help texts with the current recorder.
+pub fn describe() {
+    describe_histogram!(
+        VM_BOOT_DURATION,
+        Unit::Seconds,
+        "Time from VMM spawn until the VM is booted and networked"
+    );
+    describe_histogram!(
+        IMAGE_PREPARE_DURATION,
+        Unit::Seconds,
+        "Time spent making VM artifacts available locally"
+    );
+    describe_counter!(VMM_OPERATIONS, "VMM API and process operations by outcome");
+    describe_counter!(
+        VMM_UNEXPECTED_EXITS,
+        "VMM processes that exited without being deleted"
+    );
+    describe_counter!(
+        VMM_UNRESPONSIVE,
+        "VMMs killed after their API stopped answering"
+    );
+    describe_counter!(
+        VM_BOOT_FAILURES,
+        "VMs not ready within the boot timeout, or whose VMM exited while booting"
+    );
+    describe_counter!(
+        VM_DRAINS,
+        "Deleted VMs given time to power off, by outcome (clean, forced)"
+    );
+    describe_counter!(
+        VM_UPDATES,
+        "VMs moved to a new spec, by how (resized, redeployed)"
+    );
+    describe_gauge!(VMS_RUNNING, "VMs currently owned by the manager");
+    describe_gauge!(COMMAND_QUEUE_DEPTH, "Commands waiting for the VM manager");
+    describe_counter!(
+        LOG_RECORDS_DROPPED,
+        "Log records lost by the forwarder (channel_full, spool_full)"
+    );
+    describe_gauge!(
+        LOG_SPOOL_BYTES,
+        Unit::Bytes,
+        "Unsent log records buffered on disk"
+    );
+    describe_counter!(
+        DNS_QUERIES,
+        "VM DNS queries by outcome (allowed, denied, unknown_client, upstream_error)"
+    );
+    describe_counter!(
+        STAGED_DISKS,
+        "VM disks made from images staged on fast storage, by staging hit and clone method"
+    );
+    describe_gauge!(
+        MEMORY_PRESSURE,
+        Unit::Percent,
+        "Share of time tasks stalled on memory over the last 10s (some, full)"
+    );
+    describe_counter!(
+        MEMORY_PRESSURE_EVENTS,
+        "Host memory pressure episodes and the VMs restarted to relieve them"
+    );
+    describe_gauge!(
+        STORE_USED,
+        Unit::Percent,
+        "Share of the Nix store's filesystem in use"
+    );
+    describe_counter!(
+        STORE_COLLECTIONS,
+        "Store garbage collections run above the high watermark"
+    );
+    describe_counter!(
+        STORE_FREED,
+        Unit::Bytes,
+        "Bytes freed by store garbage collections"
+    );
+}
+
+fn result_label(ok: bool) -> &'static str {
+    if ok { "ok" } else { "error" }
+}
+
+pub fn vm_booted(elapsed: Duration) {
+    histogram!(VM_BOOT_DURATION).record(elapsed.as_secs_f64());
+}
+
+pub fn image_prepared(elapsed: Duration, ok: bool) {
+    histogram!(IMAGE_PREPARE_DURATION, "result" => result_label(ok)).record(elapsed.as_secs_f64());
+}
+
+/// Count one VMM operation (`create`, `boot`, `shutdown`, `power-button`,
+/// `resize`, `delete`, `kill`).
+pub fn vmm_operation(op: &'static str, ok: bool) {
+    counter!(VMM_OPERATIONS, "op" => op, "result" => result_label(ok)).increment(1);
+}
+
+pub fn vmm_unexpected_exit() {
+    counter!(VMM_UNEXPECTED_EXITS).increment(1);
+}
+
+pub fn vmm_unresponsive() {
+    counter!(VMM_UNRESPONSIVE).increment(1);
+}
+
+pub fn vm_boot_failed() {
+    counter!(VM_BOOT_FAILURES).increment(1);
+}
+
+/// Count one finished drain (`clean`, `forced`).
+pub fn vm_drained(result: &'static str) {
+    counter!(VM_DRAINS, "result" => result).increment(1);
+}
+
+/// Count one VM moved to a new spec (`resized`, `redeployed`).
+pub fn vm_updated(applied: &'static str) {
+    counter!(VM_UPDATES, "applied" => applied).increment(1);
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub fn vms_running(count: usize) {
+    gauge!(VMS_RUNNING).set(count as f64);
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub fn command_queue_depth(depth: usize) {
+    gauge!(COMMAND_QUEUE_DEPTH).set(depth as f64);
+}
+
+/// Count log records the forwarder could not keep.
+pub fn log_records_dropped(reason: &'static str, count: u64) {
+    counter!(LOG_RECORDS_DROPPED, "reason" => reason).increment(count);
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub fn log_spool_bytes(bytes: u64) {
+    gauge!(LOG_SPOOL_BYTES).set(bytes as f64);
+}
+
+/// Count one VM DNS query answered by the proxy.
+pub fn dns_query(result: &'static str) {
+    counter!(DNS_QUERIES, "result" => result).increment(1);
+}
+
+/// Count one VM disk made from a staged image.
+pub fn disk_staged(hit: bool, clone: &'static str) {
+    let image = if hit { "hit" } else { "miss" };
+    counter!(STAGED_DISKS, "image" => image, "clone" => clone).increment(1);
+}
+
+pub fn memory_pressure(some: f32, full: f32) {
+    gauge!(MEMORY_PRESSURE, "kind" => "some").set(f64::from(some));
+    gauge!(MEMORY_PRESSURE, "kind" => "full").set(f64::from(full));
+}
+
+/// Count one memory pressure event (`entered`, `relieved`, `restart`).
+pub fn memory_pressure_event(event: &'static str) {
+    counter!(MEMORY_PRESSURE_EVENTS, "event" => event).increment(1);
+}
+
+pub fn store_used(percent: f32) {
+    gauge!(STORE_USED).set(f64::from(percent));
+}
+
+/// Count one store garbage collection and the bytes it freed.
+pub fn store_collected(freed_bytes: u64, ok: bool) {
+    counter!(STORE_COLLECTIONS, "result" => result_label(ok)).increment(1);
+    counter!(STORE_FREED).increment(freed_bytes);
+}
@@ -0,0 +1,111 @@
+//! Runtime records of the VMs this worker started, one JSON file per VM in
+//! the configured state directory.
+//!
+//! VM processes may outlive the worker (see `shutdown.stop_vms`). A record
+//! holds what a restarted worker needs to adopt such a VM instead of
+//! forgetting it: the spec, the hash it was started with and the
+//! backend's [`ProcessRecord`] (pid, API socket, TAP, directories).
+//!
+//! A record is written once its VM has booted and removed with the VM.
+//! Files are replaced atomically, so a crash leaves either the old or the
+//! new record, never a torn one.
+
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::dto::VmSpec;
+use crate::vmm::ProcessRecord;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VmRecord {
+    pub vm_id: String,
+    pub spec: VmSpec,
+    /// Hash of the spec the process was started with
+    pub spec_hash: String,
+    pub process: ProcessRecord,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordStore {
+    dir: PathBuf,
+}
+
+impl RecordStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, vm_id: &str) -> PathBuf {
+        self.dir.join(format!("{vm_id}.json"))
+    }
+
+    /// Write or replace the record of `record.vm_id`.
+    ///
+    /// # Errors
+    ///
+    /// - if the state directory or the file cannot be written
+    pub fn save(&self, record: &VmRecord) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(&record.vm_id);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(record)?)?;
+        std::fs::rename(&tmp, &path)
+    }
+
+    /// Remove the record of `vm_id`; a missing record is not an error.
+    ///
+    /// # Errors
+    ///
+    /// - if the file exists but cannot be removed
+    pub fn remove(&self, vm_id: &str) -> io::Result<()> {
+        match std::fs::remove_file(self.path(vm_id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every readable record. Unreadable files are logged and removed,
+    /// since nothing could ever adopt them.
+    ///
+    /// # Errors
+    ///
+    /// - if the state directory exists but cannot be listed
+    pub fn load(&self) -> io::Result<Vec<VmRecord>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut records = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match read_record(&path) {
+                Ok(record) => records.push(record),
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "Dropping unreadable VM record");
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+        records.sort_by(|a, b| a.vm_id.cmp(&b.vm_id));
+        Ok(records)
+    }
+}
+
+fn read_record(path: &Path) -> io::Result<VmRecord> {
+    let record: VmRecord = serde_json::from_slice(&std::fs::read(path)?)?;
+    if path.file_stem() != Some(OsStr::new(&record.vm_id)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("record of VM {} in the wrong file", record.vm_id),
+        ));
+    }
+    Ok(record)
+}
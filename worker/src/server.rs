@@ -2,41 +2,87 @@
 //! sends them to the Node via mpsc channel, and fills responses from oneshot replies.
 //!
 //! Holds only a `CommandSender` (cloneable `mpsc::Sender` wrapper). No VMM, no VM state.
+//!
+//! Every handler opens an `rpc` span parented to the caller's `trace`
+//! context; the command sent to the Node carries that span along.
 
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
 
 use capnp::message::ReaderOptions;
 use capnp_rpc::{RpcSystem, rpc_twoparty_capnp, twoparty};
+use commands::common_capnp;
+use commands::hashing;
+use commands::health::Flag;
+use commands::labels::{Page, Selector, read_labels, write_labels};
+use commands::lifecycle::{self, NotifyState};
+use commands::net_backend;
+use commands::telemetry::{TraceHeaders, rpc_span};
+use commands::vm_action::VmAction;
+use commands::vm_state;
 use futures::AsyncReadExt;
-use tracing::{debug, info, instrument};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, info, instrument, warn};
 
-use crate::dto::{CommandPayload, CommandResponse, CommandSender, VmSpec};
+use crate::disk_usage::DiskUsage;
+use crate::dto::{CommandPayload, CommandResponse, CommandSender, VmInfo, VmSpec};
+use crate::memory_pressure::PressureReport;
+use crate::store_janitor::JanitorReport;
 
 #[derive(Clone)]
 pub struct Server {
     tx: CommandSender,
+    listening: Flag,
 }
 
 impl Server {
     #[must_use]
     pub fn new(tx: CommandSender) -> Self {
-        Server { tx }
+        Server {
+            tx,
+            listening: Flag::default(),
+        }
+    }
+
+    /// Raised while `serve` accepts connections, for the health probes.
+    #[must_use]
+    pub fn listening(&self) -> Flag {
+        self.listening.clone()
     }
 
+    /// Accept connections until `shutdown` is cancelled, then return `Ok`.
+    ///
+    /// Reports READY to systemd once the listener is bound.
+    ///
     /// # Errors
     ///
     /// - if the TCP listener fails to bind to the given address
     /// - if the RPC system fails to start
     ///
-    #[instrument(skip(self))]
-    pub async fn serve(self, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    #[instrument(skip(self, shutdown))]
+    pub async fn serve(
+        self,
+        addr: SocketAddr,
+        shutdown: CancellationToken,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         info!(addr = %addr, "Starting server");
         let listener = tokio::net::TcpListener::bind(&addr).await?;
 
+        if let Err(e) = lifecycle::notify(&[NotifyState::Ready]) {
+            warn!(error = %e, "sd_notify READY failed");
+        }
+        let _listening = self.listening.raise();
+
         let client: commands::worker_capnp::worker::Client = capnp_rpc::new_client(self);
 
         loop {
-            let (stream, peer_addr) = listener.accept().await?;
+            let (stream, peer_addr) = tokio::select! {
+                () = shutdown.cancelled() => {
+                    info!("Shutdown requested, no longer accepting connections");
+                    return Ok(());
+                }
+                accepted = listener.accept() => accepted?,
+            };
             debug!(peer_addr = %peer_addr, "New connection");
             stream.set_nodelay(true)?;
             let (reader, writer) =
@@ -57,15 +103,20 @@ impl Server {
 impl commands::worker_capnp::worker::Server for Server {
     fn read(
         &mut self,
-        _params: commands::worker_capnp::worker::ReadParams,
+        params: commands::worker_capnp::worker::ReadParams,
         mut results: commands::worker_capnp::worker::ReadResults,
     ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
-        debug!("Worker.read called");
+        let span = rpc_span(
+            "Worker.read",
+            &TraceHeaders::read(params.get().and_then(|p| p.get_trace())),
+        );
+        debug!(parent: &span, "Worker.read called");
 
         let tx = self.tx.clone();
         ::capnp::capability::Promise::from_future(async move {
             let resp = tx
                 .request(CommandPayload::GetWorkerStatus)
+                .instrument(span)
                 .await
                 .map_err(|e| capnp::Error::failed(e.to_string()))?;
 
@@ -75,6 +126,27 @@ impl commands::worker_capnp::worker::Server for Server {
                     data.set_healthy(info.healthy());
                     data.set_generation(info.generation());
                     data.set_running_vms(info.running_vms());
+                    let mut metrics = data.reborrow().init_metrics();
+                    write_memory_pressure(
+                        &info.memory_pressure(),
+                        metrics.reborrow().init_memory_pressure(),
+                    );
+                    write_store_janitor(
+                        &info.store_janitor(),
+                        metrics.reborrow().init_store_janitor(),
+                    );
+                    write_disk_usage(info.disk_usage(), metrics);
+                    let mut backends = data
+                        .reborrow()
+                        .init_net_backends(info.net_backends().len() as u32);
+                    for (i, backend) in info.net_backends().iter().enumerate() {
+                        backends.set(i as u32, (*backend).into());
+                    }
+                    let hugepages = info.hugepages();
+                    let mut pool = data.init_hugepages();
+                    pool.set_page_size_kb(hugepages.page_size_kb);
+                    pool.set_total(hugepages.total);
+                    pool.set_free(hugepages.free);
                 }
             } else {
                 return Err(capnp::Error::failed(
@@ -88,19 +160,35 @@ impl commands::worker_capnp::worker::Server for Server {
 
     fn list_vms(
         &mut self,
-        _params: commands::worker_capnp::worker::ListVmsParams,
+        params: commands::worker_capnp::worker::ListVmsParams,
         mut results: commands::worker_capnp::worker::ListVmsResults,
     ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
-        debug!("Worker.list_vms called");
+        let span = rpc_span(
+            "Worker.listVms",
+            &TraceHeaders::read(params.get().and_then(|p| p.get_trace())),
+        );
+        debug!(parent: &span, "Worker.list_vms called");
 
         let tx = self.tx.clone();
         ::capnp::capability::Promise::from_future(async move {
+            let params = params.get()?;
+            let selector = Selector::read(params.get_selector())?;
+            let page = Page::read(params.get_page())?;
+
             let resp = tx
                 .request(CommandPayload::List)
+                .instrument(span)
                 .await
                 .map_err(|e| capnp::Error::failed(e.to_string()))?;
 
             if let CommandResponse::VmList(vm_infos) = resp {
+                let matching = vm_infos
+                    .into_iter()
+                    .filter(|info| selector.matches(info.labels()))
+                    .collect();
+                let (vm_infos, next_cursor) = page.apply(matching, VmInfo::id);
+                results.get().set_next_cursor(&next_cursor);
+
                 let mut vms = results.get().init_vms(vm_infos.len() as u32);
                 for (i, info) in vm_infos.iter().enumerate() {
                     let mut vm_status = vms.reborrow().get(i as u32);
@@ -109,9 +197,20 @@ impl commands::worker_capnp::worker::Server for Server {
                     vm_status.set_desired_hash(info.desired_hash());
                     vm_status.set_observed_hash(info.observed_hash());
                     vm_status.set_status(info.status().as_str());
+                    vm_status.set_state(info.status().into());
+                    vm_status.set_boot_log(info.boot_log().unwrap_or_default());
+                    vm_state::write_transitions(
+                        info.transitions(),
+                        vm_status
+                            .reborrow()
+                            .init_transitions(info.transitions().len() as u32),
+                    );
                     vm_status.set_drifted(
-                        info.status()
-                            .is_drifted(info.desired_hash(), info.observed_hash()),
+                        hashing::compare(info.desired_hash(), info.observed_hash()).is_drifted(),
+                    );
+                    write_labels(
+                        info.labels(),
+                        vm_status.reborrow().init_labels(info.labels().len() as u32),
                     );
                     let mut metrics = vm_status.init_metrics();
                     metrics.set_cpu_usage(info.metrics().cpu_usage);
@@ -134,40 +233,19 @@ impl commands::worker_capnp::worker::Server for Server {
         params: commands::worker_capnp::worker::CreateVmParams,
         mut results: commands::worker_capnp::worker::CreateVmResults,
     ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
-        debug!("Worker.create_vm called");
+        let span = rpc_span(
+            "Worker.createVm",
+            &TraceHeaders::read(params.get().and_then(|p| p.get_trace())),
+        );
+        debug!(parent: &span, "Worker.create_vm called");
 
         let tx = self.tx.clone();
         ::capnp::capability::Promise::from_future(async move {
-            let spec_reader = params.get()?.get_spec()?;
-
-            let mut domains = Vec::new();
-            for d in spec_reader.get_network_allowed_domains()? {
-                domains.push(
-                    d?.to_str()
-                        .map_err(|e| capnp::Error::failed(e.to_string()))?
-                        .to_string(),
-                );
-            }
-
-            let to_string = |r: capnp::text::Reader<'_>| -> Result<String, capnp::Error> {
-                r.to_str()
-                    .map(std::string::ToString::to_string)
-                    .map_err(|e| capnp::Error::failed(e.to_string()))
-            };
-
-            let spec = VmSpec::new(
-                to_string(spec_reader.get_toplevel()?)?,
-                to_string(spec_reader.get_kernel_path()?)?,
-                to_string(spec_reader.get_initrd_path()?)?,
-                to_string(spec_reader.get_disk_image_path()?)?,
-                to_string(spec_reader.get_cmdline()?)?,
-                spec_reader.get_cpu(),
-                spec_reader.get_memory_mb(),
-                domains,
-            );
+            let spec = read_spec(params.get()?.get_spec()?)?;
 
             let resp = tx
                 .request(CommandPayload::Create(spec))
+                .instrument(span)
                 .await
                 .map_err(|e| capnp::Error::failed(e.to_string()))?;
 
@@ -187,7 +265,11 @@ impl commands::worker_capnp::worker::Server for Server {
         params: commands::worker_capnp::worker::DeleteVmParams,
         _results: commands::worker_capnp::worker::DeleteVmResults,
     ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
-        debug!("Worker.delete_vm called");
+        let span = rpc_span(
+            "Worker.deleteVm",
+            &TraceHeaders::read(params.get().and_then(|p| p.get_trace())),
+        );
+        debug!(parent: &span, "Worker.delete_vm called");
 
         let tx = self.tx.clone();
         ::capnp::capability::Promise::from_future(async move {
@@ -200,6 +282,7 @@ impl commands::worker_capnp::worker::Server for Server {
 
             let resp = tx
                 .request(CommandPayload::Delete(id))
+                .instrument(span)
                 .await
                 .map_err(|e| capnp::Error::failed(e.to_string()))?;
 
@@ -212,4 +295,158 @@ impl commands::worker_capnp::worker::Server for Server {
             }
         })
     }
+
+    fn update_vm(
+        &mut self,
+        params: commands::worker_capnp::worker::UpdateVmParams,
+        _results: commands::worker_capnp::worker::UpdateVmResults,
+    ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+        let span = rpc_span(
+            "Worker.updateVm",
+            &TraceHeaders::read(params.get().and_then(|p| p.get_trace())),
+        );
+        debug!(parent: &span, "Worker.update_vm called");
+
+        let tx = self.tx.clone();
+        ::capnp::capability::Promise::from_future(async move {
+            let p = params.get()?;
+            let vm_id = p
+                .get_id()?
+                .to_str()
+                .map_err(|e| capnp::Error::failed(e.to_string()))?
+                .to_string();
+            let spec = read_spec(p.get_spec()?)?;
+
+            let resp = tx
+                .request(CommandPayload::Update { vm_id, spec })
+                .instrument(span)
+                .await
+                .map_err(|e| capnp::Error::failed(e.to_string()))?;
+
+            if let CommandResponse::Unit = resp {
+                Ok(())
+            } else {
+                Err(capnp::Error::failed(
+                    "unexpected response for Update".into(),
+                ))
+            }
+        })
+    }
+
+    fn vm_action(
+        &mut self,
+        params: commands::worker_capnp::worker::VmActionParams,
+        _results: commands::worker_capnp::worker::VmActionResults,
+    ) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+        let span = rpc_span(
+            "Worker.vmAction",
+            &TraceHeaders::read(params.get().and_then(|p| p.get_trace())),
+        );
+        debug!(parent: &span, "Worker.vm_action called");
+
+        let tx = self.tx.clone();
+        ::capnp::capability::Promise::from_future(async move {
+            let p = params.get()?;
+            let vm_id = p
+                .get_id()?
+                .to_str()
+                .map_err(|e| capnp::Error::failed(e.to_string()))?
+                .to_string();
+            let action = VmAction::from(p.get_action()?);
+
+            let resp = tx
+                .request(CommandPayload::Action { vm_id, action })
+                .instrument(span)
+                .await
+                .map_err(|e| capnp::Error::failed(e.to_string()))?;
+
+            if let CommandResponse::Unit = resp {
+                Ok(())
+            } else {
+                Err(capnp::Error::failed(
+                    "unexpected response for Action".into(),
+                ))
+            }
+        })
+    }
+}
+
+/// The worker's own [`VmSpec`] from the wire one.
+fn read_spec(spec_reader: common_capnp::vm_spec::Reader<'_>) -> Result<VmSpec, capnp::Error> {
+    let mut domains = Vec::new();
+    for d in spec_reader.get_network_allowed_domains()? {
+        domains.push(
+            d?.to_str()
+                .map_err(|e| capnp::Error::failed(e.to_string()))?
+                .to_string(),
+        );
+    }
+
+    let to_string = |r: capnp::text::Reader<'_>| -> Result<String, capnp::Error> {
+        r.to_str()
+            .map(std::string::ToString::to_string)
+            .map_err(|e| capnp::Error::failed(e.to_string()))
+    };
+
+    Ok(VmSpec::new(
+        to_string(spec_reader.get_toplevel()?)?,
+        to_string(spec_reader.get_kernel_path()?)?,
+        to_string(spec_reader.get_initrd_path()?)?,
+        to_string(spec_reader.get_disk_image_path()?)?,
+        to_string(spec_reader.get_cmdline()?)?,
+        spec_reader.get_cpu(),
+        spec_reader.get_memory_mb(),
+        domains,
+    )
+    .with_labels(read_labels(spec_reader.get_labels()?)?)
+    .with_hugepages(spec_reader.get_hugepages())
+    .with_shared_memory(spec_reader.get_shared_memory())
+    .with_net_backend(net_backend::read(spec_reader.get_net_backend())?))
+}
+
+/// Fill `WorkerMetrics` with what the VMs take on disk.
+fn write_disk_usage(disk: &DiskUsage, mut metrics: common_capnp::worker_metrics::Builder<'_>) {
+    metrics.set_disk_usage(disk.total());
+    metrics.set_log_disk_usage(disk.log_bytes);
+    write_disk_entries(
+        &disk.images,
+        metrics
+            .reborrow()
+            .init_image_disk_usage(disk.images.len() as u32),
+    );
+    write_disk_entries(&disk.vms, metrics.init_vm_disk_usage(disk.vms.len() as u32));
+}
+
+fn write_memory_pressure(
+    report: &PressureReport,
+    mut pressure: common_capnp::memory_pressure::Builder<'_>,
+) {
+    pressure.set_some_avg10(report.psi.some_avg10);
+    pressure.set_full_avg10(report.psi.full_avg10);
+    pressure.set_under_pressure(report.under_pressure);
+    pressure.set_policy(report.policy.map_or("", |policy| policy.as_str()));
+    pressure.set_episodes(report.episodes);
+    pressure.set_restarts(report.restarts);
+}
+
+fn write_store_janitor(
+    report: &JanitorReport,
+    mut janitor: common_capnp::store_janitor::Builder<'_>,
+) {
+    janitor.set_used_percent(report.used_percent);
+    janitor.set_high_watermark(report.high_watermark_percent);
+    janitor.set_pinned_paths(report.pinned_paths);
+    janitor.set_collections(report.collections);
+    janitor.set_freed_bytes(report.freed_bytes);
+}
+
+fn write_disk_entries(
+    entries: &BTreeMap<String, u64>,
+    mut list: capnp::struct_list::Builder<'_, common_capnp::disk_usage::Owned>,
+) {
+    for (i, (name, bytes)) in entries.iter().enumerate() {
+        let mut entry = list.reborrow().get(i as u32);
+        entry.set_name(name);
+        entry.set_bytes(*bytes);
+    }
 }
@@ -0,0 +1,413 @@
+//! # Store janitor
+//!
+//! Every VM boots from store paths the worker fetched, and nothing removes
+//! them once the VM is gone: the store grows until the disk is full and
+//! the next prepare fails.
+//!
+//! With a `store_janitor` section the worker checks the filesystem holding
+//! `store_dir` every `check_interval_secs`. Each check first makes
+//! `gc_roots_dir` hold one symlink per store path an assigned VM boots
+//! from (toplevel, kernel, initrd, disk image) and removes its other links
+//! into the store, so `nix-store --gc` keeps exactly the closures still in
+//! use. Anything else found there is left alone. When usage is
+//! at or above `high_watermark_percent`, the janitor collects garbage until
+//! it would fall to `low_watermark_percent`:
+//!
+//! ```text
+//! nix-store --gc --max-freed <bytes above the low watermark>
+//! ```
+//!
+//! Nix only follows roots below `/nix/var/nix/gcroots`, so `gc_roots_dir`
+//! must be a directory of its own there, never that directory itself. Collections and the bytes they freed are logged,
+//! counted in the metrics and reported in `Worker.read`.
+
+use std::collections::BTreeSet;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::process::Command;
+use tracing::debug;
+
+use crate::dto::VmError;
+use crate::vmm::trust::store_path_of;
+
+/// Where the store lives.
+pub const STORE_DIR: &str = "/nix/store";
+
+/// Where the janitor keeps its roots by default.
+pub const GC_ROOTS_DIR: &str = "/nix/var/nix/gcroots/procurator-worker";
+
+/// Where Nix looks for roots, the system's own among them.
+const NIX_GC_ROOTS: &str = "/nix/var/nix/gcroots";
+
+/// Whether `dir` is below [`NIX_GC_ROOTS`], and not that directory itself.
+#[must_use]
+pub fn is_roots_subdir(dir: &Path) -> bool {
+    !dir.components().any(|c| c == Component::ParentDir)
+        && dir.starts_with(NIX_GC_ROOTS)
+        && dir != Path::new(NIX_GC_ROOTS)
+}
+
+/// Enables the store janitor; disabled when the section is absent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StoreJanitorSection {
+    /// Store whose filesystem is watched.
+    pub store_dir: PathBuf,
+    /// Directory of the janitor's GC roots, below `/nix/var/nix/gcroots`.
+    pub gc_roots_dir: PathBuf,
+    /// How often usage is checked and the roots updated.
+    pub check_interval_secs: u64,
+    /// Usage percentage at which garbage is collected.
+    pub high_watermark_percent: f32,
+    /// Usage percentage a collection aims for.
+    pub low_watermark_percent: f32,
+}
+
+impl Default for StoreJanitorSection {
+    fn default() -> Self {
+        Self {
+            store_dir: PathBuf::from(STORE_DIR),
+            gc_roots_dir: PathBuf::from(GC_ROOTS_DIR),
+            check_interval_secs: 300,
+            high_watermark_percent: 85.0,
+            low_watermark_percent: 70.0,
+        }
+    }
+}
+
+/// Size and use of the filesystem holding the store.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreUsage {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+}
+
+impl StoreUsage {
+    /// Usage of the filesystem holding `path`.
+    ///
+    /// # Errors
+    ///
+    /// - if `path` does not exist or `statvfs` fails on it
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: `path` is NUL-terminated and `stat` is written by the call
+        // before it is read
+        let ret = unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: statvfs succeeded and filled `stat`
+        let stat = unsafe { stat.assume_init() };
+        let fragment = u64::from(stat.f_frsize);
+        let total_bytes = u64::from(stat.f_blocks) * fragment;
+        let free_bytes = u64::from(stat.f_bfree) * fragment;
+        Ok(Self {
+            total_bytes,
+            used_bytes: total_bytes.saturating_sub(free_bytes),
+        })
+    }
+
+    #[must_use]
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    pub fn percent(self) -> f32 {
+        if self.total_bytes == 0 {
+            return 0.0;
+        }
+        (self.used_bytes as f64 * 100.0 / self.total_bytes as f64) as f32
+    }
+}
+
+/// Store janitor activity as `Worker.read` reports it, all zeroes when it
+/// is disabled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct JanitorReport {
+    /// Usage of the store's filesystem at the last check
+    pub used_percent: f32,
+    /// Usage at which garbage is collected, 0 when disabled
+    pub high_watermark_percent: f32,
+    /// Store paths kept as GC roots for the assigned VMs
+    pub pinned_paths: u32,
+    /// Collections since the worker started
+    pub collections: u64,
+    /// Bytes those collections freed
+    pub freed_bytes: u64,
+}
+
+/// Keeps the store below its high watermark, see the module docs.
+#[derive(Debug, Clone)]
+pub struct StoreJanitor {
+    store_dir: PathBuf,
+    gc_roots_dir: PathBuf,
+    check_interval: Duration,
+    high_watermark: f32,
+    low_watermark: f32,
+    report: JanitorReport,
+}
+
+impl StoreJanitor {
+    #[must_use]
+    pub fn check_interval(&self) -> Duration {
+        self.check_interval
+    }
+
+    #[must_use]
+    pub fn store_dir(&self) -> &Path {
+        &self.store_dir
+    }
+
+    #[must_use]
+    pub fn report(&self) -> JanitorReport {
+        self.report
+    }
+
+    /// Record one check of `usage` and return how many bytes to collect,
+    /// `None` below the high watermark.
+    pub fn observe(&mut self, usage: StoreUsage) -> Option<u64> {
+        self.report.used_percent = usage.percent();
+        if self.report.used_percent < self.high_watermark {
+            return None;
+        }
+        let target = watermark_bytes(usage.total_bytes, self.low_watermark);
+        Some(usage.used_bytes.saturating_sub(target))
+    }
+
+    /// Make the janitor's roots pin exactly `pins`, as [`store_paths`]
+    /// makes them.
+    ///
+    /// # Errors
+    ///
+    /// - if the roots directory cannot be read or a link cannot be changed;
+    ///   garbage must not be collected then
+    pub fn pin(&mut self, pins: &BTreeSet<String>) -> io::Result<()> {
+        sync_roots(&self.gc_roots_dir, &self.store_dir, pins)?;
+        self.report.pinned_paths = u32::try_from(pins.len()).unwrap_or(u32::MAX);
+        Ok(())
+    }
+
+    /// Count a collection that freed `bytes`.
+    pub fn collected(&mut self, bytes: u64) {
+        self.report.collections += 1;
+        self.report.freed_bytes += bytes;
+    }
+}
+
+impl From<StoreJanitorSection> for StoreJanitor {
+    fn from(section: StoreJanitorSection) -> Self {
+        Self {
+            store_dir: section.store_dir,
+            gc_roots_dir: section.gc_roots_dir,
+            check_interval: Duration::from_secs(section.check_interval_secs),
+            high_watermark: section.high_watermark_percent,
+            low_watermark: section.low_watermark_percent,
+            report: JanitorReport {
+                high_watermark_percent: section.high_watermark_percent,
+                ..JanitorReport::default()
+            },
+        }
+    }
+}
+
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn watermark_bytes(total_bytes: u64, percent: f32) -> u64 {
+    (total_bytes as f64 * f64::from(percent) / 100.0) as u64
+}
+
+/// Store paths at or above `paths`, deduplicated. Paths outside the store
+/// and empty ones are skipped.
+pub fn store_paths<'a>(paths: impl IntoIterator<Item = &'a str>) -> BTreeSet<String> {
+    paths
+        .into_iter()
+        .filter_map(store_path_of)
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Make `dir` hold one symlink per path of `pins`, named after its base
+/// name, and no other symlink into `store_dir`.
+fn sync_roots(dir: &Path, store_dir: &Path, pins: &BTreeSet<String>) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut missing: BTreeSet<&str> = pins.iter().map(String::as_str).collect();
+    for entry in std::fs::read_dir(dir)? {
+        let link = entry?.path();
+        let Ok(target) = std::fs::read_link(&link) else {
+            continue;
+        };
+        match target.to_str() {
+            Some(target) if missing.remove(target) => {}
+            _ if target.starts_with(store_dir) => {
+                debug!(root = %link.display(), "Dropping GC root");
+                std::fs::remove_file(&link)?;
+            }
+            _ => {}
+        }
+    }
+    for path in missing {
+        let name = Path::new(path).file_name().unwrap_or_default();
+        debug!(path, "Pinning store path");
+        std::os::unix::fs::symlink(path, dir.join(name))?;
+    }
+    Ok(())
+}
+
+/// Run `nix-store --gc --max-freed <max_freed>` and return the bytes it
+/// freed.
+///
+/// # Errors
+///
+/// - [`VmError::Internal`] if `nix-store` cannot be run or fails
+pub async fn collect_garbage(max_freed: u64) -> Result<u64, VmError> {
+    let output = Command::new("nix-store")
+        .args(["--gc", "--max-freed"])
+        .arg(max_freed.to_string())
+        .output()
+        .await
+        .map_err(|e| VmError::Internal(format!("failed to run nix-store --gc: {e}")))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(VmError::Internal(format!(
+            "nix-store --gc failed ({}): {}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(freed_bytes(&stdout)
+        .or_else(|| freed_bytes(&stderr))
+        .unwrap_or(0))
+}
+
+/// Bytes of the `N store paths deleted, X MiB freed` line of a collection.
+fn freed_bytes(output: &str) -> Option<u64> {
+    let line = output
+        .lines()
+        .rev()
+        .find(|l| l.trim_end().ends_with(" freed"))?;
+    let mut amount = line.rsplit(", ").next()?.split_whitespace();
+    let value: f64 = amount.next()?.parse().ok()?;
+    let unit: u32 = match amount.next()? {
+        "bytes" | "B" => 0,
+        "KiB" => 1,
+        "MiB" => 2,
+        "GiB" => 3,
+        "TiB" => 4,
+        _ => return None,
+    };
+    Some(scale(value, 1024_u64.pow(unit)))
+}
+
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn scale(value: f64, unit: u64) -> u64 {
+    (value * unit as f64) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    fn usage(used_gib: u64) -> StoreUsage {
+        StoreUsage {
+            total_bytes: 100 * GIB,
+            used_bytes: used_gib * GIB,
+        }
+    }
+
+    #[test]
+    fn collects_down_to_the_low_watermark() {
+        let section: StoreJanitorSection =
+            serde_json::from_str(r#"{"high_watermark_percent": 80, "low_watermark_percent": 60}"#)
+                .unwrap();
+        assert_eq!(section.gc_roots_dir, Path::new(GC_ROOTS_DIR));
+        let mut janitor = StoreJanitor::from(section);
+        assert_eq!(janitor.observe(usage(79)), None);
+        assert_eq!(janitor.observe(usage(90)), Some(30 * GIB));
+        janitor.collected(30 * GIB);
+
+        let report = janitor.report();
+        assert!((report.used_percent - 90.0).abs() < 0.01);
+        assert!((report.high_watermark_percent - 80.0).abs() < 0.01);
+        assert_eq!(report.collections, 1);
+        assert_eq!(report.freed_bytes, 30 * GIB);
+        assert!(StoreUsage::default().percent().abs() < 0.01);
+    }
+
+    #[test]
+    fn roots_stay_in_a_directory_of_their_own() {
+        assert!(is_roots_subdir(Path::new(GC_ROOTS_DIR)));
+        assert!(is_roots_subdir(Path::new("/nix/var/nix/gcroots/a/b")));
+        assert!(!is_roots_subdir(Path::new("/nix/var/nix/gcroots")));
+        assert!(!is_roots_subdir(Path::new("/nix/var/nix/gcroots/")));
+        assert!(!is_roots_subdir(Path::new("/nix/var/nix/gcroots/..")));
+        assert!(!is_roots_subdir(Path::new("/nix/var/nix/gcroots/x/../..")));
+        assert!(!is_roots_subdir(Path::new("/var/lib/roots")));
+    }
+
+    #[test]
+    fn parses_the_freed_amount() {
+        let output = "deleting '/nix/store/aaa-hello'\n\
+                      deleting unused links...\n\
+                      2 store paths deleted, 1.50 MiB freed\n";
+        assert_eq!(freed_bytes(output), Some(1024 * 1024 * 3 / 2));
+        assert_eq!(freed_bytes("0 store paths deleted, 0.0 KiB freed"), Some(0));
+        assert_eq!(freed_bytes("finding garbage collector roots..."), None);
+    }
+
+    #[test]
+    fn roots_pin_exactly_the_assigned_closures() {
+        let dir = std::env::temp_dir().join(format!("store-janitor-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let pins = store_paths([
+            "/nix/store/aaa-system",
+            "/nix/store/bbb-kernel/bzImage",
+            "/nix/store/bbb-kernel/initrd",
+            "",
+            "/var/lib/images/disk.raw",
+        ]);
+        assert_eq!(pins.len(), 2);
+
+        let mut janitor = StoreJanitor::from(StoreJanitorSection {
+            gc_roots_dir: dir.clone(),
+            ..StoreJanitorSection::default()
+        });
+        janitor.pin(&pins).unwrap();
+        std::os::unix::fs::symlink("/nix/store/ccc-gone", dir.join("ccc-gone")).unwrap();
+        // Not the janitor's: kept
+        std::os::unix::fs::symlink("/nix/var/nix/profiles/system", dir.join("system")).unwrap();
+        std::fs::write(dir.join("notes"), "").unwrap();
+        janitor
+            .pin(&store_paths(["/nix/store/bbb-kernel/bzImage"]))
+            .unwrap();
+
+        let mut roots: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| std::fs::read_link(e.unwrap().path()).ok())
+            .collect();
+        roots.sort();
+        assert_eq!(
+            roots,
+            [
+                PathBuf::from("/nix/store/bbb-kernel"),
+                PathBuf::from("/nix/var/nix/profiles/system"),
+            ]
+        );
+        assert!(dir.join("notes").exists());
+        assert_eq!(janitor.report().pinned_paths, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
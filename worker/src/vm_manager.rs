@@ -16,26 +16,158 @@
 //!
 //! ## Create flow
 //!
-//! UUIDv7 → `prepare(vm_id, spec)` → `spawn(vm_id)` → `build_config(vm_id, spec)`
-//! → `client.create(config)` → `client.boot()` → `attach_network(vm_id)`
-//! → insert `VmHandle`.
-//! On failure, no `VmHandle` is inserted — no partial state.
+//! UUIDv7 → `prepare(vm_id, spec)` → issue identity → `spawn(vm_id)`
+//! → `build_config(vm_id, spec)` → `client.create(config)` → `client.boot()`
+//! → `attach_network(vm_id)` → insert `VmHandle`.
+//! On failure, no `VmHandle` is inserted and the identity is removed — no
+//! partial state.
 //!
 //! ## Delete flow
 //!
 //! Remove from HashMap → `shutdown()` (best-effort) → `delete()` (best-effort)
-//! → `kill()` → `cleanup()` (socket, disk copy, serial log, VM dir).
+//! → `kill()` → `cleanup()` (socket, disk copy, serial log, VM dir)
+//! → remove identity. A VMM that already exited is only killed and
+//! cleaned up.
+//!
+//! ## Drain
+//!
+//! With a [`Drain`] (see [`drain`](crate::drain)), `Delete` on a booting or
+//! running VM only signals its guest to power off and leaves it in the
+//! table as `draining`, so `List` keeps reporting it. `CheckDrains` runs
+//! the delete flow once its VMM exited or the drain timeout passed. A
+//! second `Delete` while it drains does nothing, and actions are refused.
+//!
+//! ## Actions
+//!
+//! `Action` runs a user's [`VmAction`] on one VM: `stop` shuts it down and
+//! keeps it as `stopped` until deleted, `restart` reboots it (or boots it
+//! again when stopped), and `redeploy` goes through the delete flow and then
+//! the create flow again under the same id, with a fresh disk copy.
+//!
+//! ## Updates
+//!
+//! `Update` moves a VM to a new spec. When the new spec only changes its
+//! vCPUs or memory, the VM is running and the backend
+//! [can resize](VmmBackend::can_resize) it, the resources are hot-plugged
+//! and the VM keeps running under the new hash. Any other change, or a
+//! resize the VMM refuses, goes through `redeploy` with the new spec. A
+//! spec with the same hash only updates the labels.
+//!
+//! ## State transitions
+//!
+//! Every status change goes through `VmHandle::transition`, which refuses
+//! the moves [`VmState::can_become`] does not allow as an internal error
+//! and records the others with their time and reason. The latest
+//! `MAX_TRANSITIONS` are kept, across redeploys and restarts after failed
+//! boots, and `List` reports them with the VM.
+//!
+//! ## Boot watchdog
+//!
+//! With a [`BootWatchdog`] (see [`boot_watchdog`](crate::boot_watchdog)),
+//! every deploy on a backend with a serial log leaves the VM `booting`, and
+//! `CheckBoots` moves it to `running` once the guest is ready. A VM past the
+//! boot timeout, or whose VMM exited, has its process killed and cleaned up
+//! and stays in the table as `boot-failed` with its serial log tail, without
+//! a record. The restart policy may deploy it again under the same id; a
+//! restart that fails leaves it `boot-failed`. `restart` on a `booting` or
+//! `boot-failed` VM redeploys it, `stop` on a `boot-failed` one does nothing.
+//!
+//! ## VMM monitor
+//!
+//! With a [`VmmMonitor`] (see [`vmm_monitor`](crate::vmm_monitor)),
+//! `CheckVmms` checks the VMM of every `running` and `stopped` VM. One
+//! whose process exited, or whose API went unanswered for too many checks
+//! in a row, has its process killed and cleaned up and stays in the table
+//! as `crashed`, without a record. A guest that shut down or came back by
+//! itself moves the VM to `stopped` or `running`. `restart` on a `crashed`
+//! VM redeploys it, `stop` does nothing.
+//!
+//! ## Memory pressure
+//!
+//! With a [`MemoryPressure`] monitor (see
+//! [`memory_pressure`](crate::memory_pressure)), `CheckMemoryPressure`
+//! reads the host's pressure and logs every episode as it starts and ends.
+//! Under the `throttle` policy `Create` is refused as unschedulable while
+//! it lasts; under `restart` the running VM with the most memory that is
+//! not hugepage-backed goes through `redeploy`. `GetWorkerStatus` reports
+//! the last reading, so the master sees the pressure instead of VMs
+//! vanishing to the OOM killer.
+//!
+//! ## Store janitor
+//!
+//! With a [`StoreJanitor`] (see [`store_janitor`](crate::store_janitor)),
+//! `CheckStore` pins the store paths of every VM in the table, booting,
+//! running or `boot-failed`, as GC roots, then collects garbage when the
+//! store is above its high watermark. Running it here rather than in its
+//! own task means no VM is being prepared while the store is collected, so
+//! nothing is removed between a fetch and the boot that pins it. A failed
+//! pin skips the collection: the roots would not cover every VM.
+//!
+//! ## Identity
+//!
+//! With an [`IdentityIssuer`] (see [`identity`](crate::identity)), every VM
+//! gets a certificate before it boots, and `RenewIdentities` re-issues the
+//! ones past half their lifetime. Without one, VMs have no identity.
+//!
+//! ## DNS policies
+//!
+//! With [`DnsPolicies`] (see [`dns_proxy`](crate::dns_proxy)), every VM's
+//! allowed domains are registered before it boots and dropped with it, so
+//! the proxy knows what each VM may resolve.
+//!
+//! ## Tracing
+//!
+//! Each command is handled inside the span it was sent from (see
+//! `Message::span`), so artifact preparation and boot appear as children of
+//! the RPC that triggered them in the distributed trace.
+//!
+//! ## Metrics
+//!
+//! Boot and prepare durations, VMM operation outcomes and the number of
+//! running VMs are recorded here (see [`metrics`](crate::metrics)).
+//! `GetWorkerStatus` adds up what each VM takes on disk (see
+//! [`disk_usage`](crate::disk_usage)).
+//!
+//! ## Shutdown flow
+//!
+//! `CommandPayload::Shutdown` is the last command the manager handles. With
+//! `stop_vms` every VM goes through the delete flow; otherwise the VM
+//! processes are detached and left running. Afterwards `is_stopped()` is
+//! true and the recv loop exits.
+//!
+//! ## Adoption
+//!
+//! With a `state_dir`, every booted VM has a [record](crate::records) there
+//! until it is deleted. On startup [`VmManager::adopt_running`] hands each
+//! record to `backend.adopt()`: VMs whose process still runs are tracked
+//! again under the same id, with a fresh identity, instead of being
+//! reported missing and started twice. Records of VMs that did not survive
+//! are dropped once the backend released their resources.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use tracing::{error, info, instrument, warn};
+use commands::vm_action::VmAction;
+use commands::vm_state::{self, VmState};
+use tracing::{Instrument, error, info, info_span, instrument, warn};
 use uuid::Uuid;
 
 use crate::dto::{
     CommandPayload, CommandResponse, Message, VmError, VmInfo,
-    VmMetrics, VmSpec, VmStatus, WorkerInfo,
+    VmSpec, WorkerInfo,
 };
+use crate::boot_watchdog::{self, BootWatchdog};
+use crate::disk_usage::DiskUsage;
+use crate::dns_proxy::DnsPolicies;
+use crate::drain::{Drain, DrainOutcome, DrainSignal};
+use crate::identity::{IdentityIssuer, VmIdentity};
+use crate::memory_pressure::{MemoryPressure, PressurePolicy, Psi, Transition};
+use crate::metrics;
+use crate::records::{RecordStore, VmRecord};
+use crate::store_janitor::{self, StoreJanitor, StoreUsage};
 use crate::vmm::{Vmm, VmmBackend, VmmProcess};
+use crate::vmm_monitor::{Crash, Probe, Verdict, VmmMonitor};
 
 // ─── Per-VM state ──────────────────────────────────────────────────────────
 
@@ -51,7 +183,84 @@ struct VmHandle<B: VmmBackend> {
     /// OS process handle (e.g. CH child process)
     process: B::Process,
     /// Current observed status
-    status: VmStatus,
+    status: VmState,
+    /// Hash of the spec the process was started with; differs from the
+    /// spec's own hash only for a VM adopted across a hashing change
+    observed_hash: String,
+    /// Certificate last installed for the VM, if identities are enabled
+    identity: Option<VmIdentity>,
+    /// When the VMM was last deployed, for the boot timeout
+    deployed_at: Instant,
+    /// Restarts in a row after failed boots
+    boot_restarts: u32,
+    /// Serial log tail of the boot that failed, while `boot-failed`
+    boot_log: Option<String>,
+    /// Latest state changes, oldest first, kept across redeploys
+    transitions: VecDeque<vm_state::Transition>,
+    /// When the guest was signalled to power off, while `draining`
+    drain_started: Option<Instant>,
+    /// Monitor checks in a row the VMM's API went unanswered
+    api_misses: u32,
+}
+
+/// State changes kept per VM, older ones are dropped.
+const MAX_TRANSITIONS: usize = 16;
+
+impl<B: VmmBackend> VmHandle<B> {
+    /// Move VM `vm_id` to `next` if its lifecycle allows it, recording why.
+    fn transition(&mut self, vm_id: &str, next: VmState, reason: &str) -> Result<(), VmError> {
+        if self.status == next {
+            return Ok(());
+        }
+        check_transition(vm_id, self.status, next)?;
+        record_transition(&mut self.transitions, self.status, next, reason);
+        self.status = next;
+        Ok(())
+    }
+
+    /// The VM's transitions followed by one to `via`, for the deploy that
+    /// replaces it.
+    fn transitions_via(
+        &self,
+        vm_id: &str,
+        via: VmState,
+        reason: &str,
+    ) -> Result<VecDeque<vm_state::Transition>, VmError> {
+        check_transition(vm_id, self.status, via)?;
+        let mut transitions = self.transitions.clone();
+        record_transition(&mut transitions, self.status, via, reason);
+        Ok(transitions)
+    }
+}
+
+fn check_transition(vm_id: &str, from: VmState, to: VmState) -> Result<(), VmError> {
+    if from.can_become(to) {
+        return Ok(());
+    }
+    error!(vm_id = %vm_id, %from, %to, "Refusing invalid VM state transition");
+    Err(VmError::Internal(format!(
+        "VM {vm_id} cannot go from {from} to {to}"
+    )))
+}
+
+fn record_transition(
+    transitions: &mut VecDeque<vm_state::Transition>,
+    from: VmState,
+    to: VmState,
+    reason: &str,
+) {
+    if transitions.len() == MAX_TRANSITIONS {
+        transitions.pop_front();
+    }
+    let at_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+    transitions.push_back(vm_state::Transition {
+        from,
+        to,
+        at_ms,
+        reason: reason.to_string(),
+    });
 }
 
 // ─── Configuration ─────────────────────────────────────────────────────────
@@ -63,12 +272,20 @@ struct VmHandle<B: VmmBackend> {
 pub struct VmManagerConfig {
     /// Worker identity string
     pub worker_id: String,
+    /// Most VMs the worker holds at once, stopped ones included until
+    /// deleted; further creates are refused. `None` for no limit.
+    pub max_vms: Option<usize>,
+    /// Where VM records are kept for adoption after a restart. `None`
+    /// forgets every VM when the worker exits.
+    pub state_dir: Option<PathBuf>,
 }
 
 impl Default for VmManagerConfig {
     fn default() -> Self {
         Self {
             worker_id: String::from("worker-local"),
+            max_vms: None,
+            state_dir: None,
         }
     }
 }
@@ -79,20 +296,100 @@ pub struct VmManager<B: VmmBackend> {
     vms: HashMap<String, VmHandle<B>>,
     config: VmManagerConfig,
     backend: B,
+    identity: Option<IdentityIssuer>,
+    dns: Option<DnsPolicies>,
+    watchdog: Option<BootWatchdog>,
+    pressure: Option<MemoryPressure>,
+    janitor: Option<StoreJanitor>,
+    drain: Option<Drain>,
+    monitor: Option<VmmMonitor>,
+    records: Option<RecordStore>,
+    /// Set once `Shutdown` has been handled.
+    stopped: bool,
 }
 
 impl<B: VmmBackend> VmManager<B> {
     pub fn new(backend: B, config: VmManagerConfig) -> Self {
         Self {
             vms: HashMap::new(),
+            records: config.state_dir.as_ref().map(RecordStore::new),
             config,
             backend,
+            identity: None,
+            dns: None,
+            watchdog: None,
+            pressure: None,
+            janitor: None,
+            drain: None,
+            monitor: None,
+            stopped: false,
         }
     }
 
+    /// Issue an identity to every VM created from now on.
+    #[must_use]
+    pub fn with_identity(mut self, issuer: IdentityIssuer) -> Self {
+        self.identity = Some(issuer);
+        self
+    }
+
+    /// Register the allowed domains of every VM with the DNS proxy.
+    #[must_use]
+    pub fn with_dns_policies(mut self, policies: DnsPolicies) -> Self {
+        self.dns = Some(policies);
+        self
+    }
+
+    /// Watch every deployed VM until it is ready, see the module docs.
+    #[must_use]
+    pub fn with_boot_watchdog(mut self, watchdog: BootWatchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Act on host memory pressure per the monitor's policy, see the
+    /// module docs.
+    #[must_use]
+    pub fn with_memory_pressure(mut self, pressure: MemoryPressure) -> Self {
+        self.pressure = Some(pressure);
+        self
+    }
+
+    /// Keep the store below its high watermark, see the module docs.
+    #[must_use]
+    pub fn with_store_janitor(mut self, janitor: StoreJanitor) -> Self {
+        self.janitor = Some(janitor);
+        self
+    }
+
+    /// Let deleted VMs power off before stopping them, see the module docs.
+    #[must_use]
+    pub fn with_drain(mut self, drain: Drain) -> Self {
+        self.drain = Some(drain);
+        self
+    }
+
+    /// Mark VMs whose VMM exited or hung as crashed, see the module docs.
+    #[must_use]
+    pub fn with_vmm_monitor(mut self, monitor: VmmMonitor) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    /// True once a `Shutdown` command has been handled; the recv loop
+    /// should stop feeding commands.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
     /// Dispatch a command to the appropriate handler and send the reply.
     /// Called by Node's recv loop.
     pub async fn handle(&mut self, msg: Message) {
+        let span = msg.span().clone();
+        self.dispatch(msg).instrument(span).await;
+    }
+
+    async fn dispatch(&mut self, msg: Message) {
         let (data, reply) = msg.into_parts();
         match data {
             CommandPayload::Create(spec) => {
@@ -106,6 +403,20 @@ impl<B: VmmBackend> VmManager<B> {
                     .map(|_| CommandResponse::Unit);
                 let _ = reply.send(result);
             }
+            CommandPayload::Action { vm_id, action } => {
+                let result = self
+                    .handle_action(&vm_id, action)
+                    .await
+                    .map(|()| CommandResponse::Unit);
+                let _ = reply.send(result);
+            }
+            CommandPayload::Update { vm_id, spec } => {
+                let result = self
+                    .handle_update(&vm_id, spec)
+                    .await
+                    .map(|()| CommandResponse::Unit);
+                let _ = reply.send(result);
+            }
             CommandPayload::List => {
                 let result = self.handle_list().await.map(CommandResponse::VmList);
                 let _ = reply.send(result);
@@ -117,6 +428,46 @@ impl<B: VmmBackend> VmManager<B> {
                     .map(CommandResponse::WorkerInfo);
                 let _ = reply.send(result);
             }
+            CommandPayload::RenewIdentities => {
+                self.handle_renew_identities();
+                let _ = reply.send(Ok(CommandResponse::Unit));
+            }
+            CommandPayload::CheckBoots => {
+                self.handle_check_boots().await;
+                let _ = reply.send(Ok(CommandResponse::Unit));
+            }
+            CommandPayload::CheckMemoryPressure => {
+                if let Some(pressure) = &self.pressure {
+                    let psi = Psi::read(pressure.psi_path());
+                    self.handle_check_memory_pressure(psi).await;
+                }
+                let _ = reply.send(Ok(CommandResponse::Unit));
+            }
+            CommandPayload::CheckStore => {
+                if let Some(janitor) = &self.janitor {
+                    match StoreUsage::read(janitor.store_dir()) {
+                        Ok(usage) => self.handle_check_store(usage).await,
+                        Err(e) => warn!(
+                            store_dir = %janitor.store_dir().display(),
+                            error = %e,
+                            "Cannot read store usage"
+                        ),
+                    }
+                }
+                let _ = reply.send(Ok(CommandResponse::Unit));
+            }
+            CommandPayload::CheckDrains => {
+                self.handle_check_drains().await;
+                let _ = reply.send(Ok(CommandResponse::Unit));
+            }
+            CommandPayload::CheckVmms => {
+                self.handle_check_vmms().await;
+                let _ = reply.send(Ok(CommandResponse::Unit));
+            }
+            CommandPayload::Shutdown { stop_vms } => {
+                self.handle_shutdown(stop_vms).await;
+                let _ = reply.send(Ok(CommandResponse::Unit));
+            }
         }
     }
 
@@ -124,6 +475,23 @@ impl<B: VmmBackend> VmManager<B> {
 
     #[instrument(skip(self, spec), fields(toplevel = %spec.toplevel()))]
     async fn handle_create(&mut self, spec: VmSpec) -> Result<String, VmError> {
+        if let Some(max) = self.config.max_vms
+            && self.vms.len() >= max
+        {
+            warn!(max_vms = max, "Refusing VM, worker is at capacity");
+            return Err(VmError::AtCapacity(max));
+        }
+        if self
+            .pressure
+            .as_ref()
+            .is_some_and(MemoryPressure::throttles)
+        {
+            warn!("Refusing VM, host is under memory pressure");
+            return Err(VmError::Unschedulable(
+                "host is under memory pressure".to_string(),
+            ));
+        }
+
         let vm_id = Uuid::now_v7().to_string();
         info!(
             vm_id = %vm_id,
@@ -134,41 +502,132 @@ impl<B: VmmBackend> VmManager<B> {
             memory_mb = spec.memory_mb(),
             "Creating VM"
         );
+        self.deploy(&vm_id, spec, VecDeque::new()).await?;
+        info!(vm_id = %vm_id, "VM created and booted successfully");
+        Ok(vm_id)
+    }
+
+    /// Prepare, boot and track VM `vm_id` running `spec` (steps 1-9 of the
+    /// create flow), after the `earlier` transitions of the VM it replaces.
+    /// On failure nothing of the VM is left behind.
+    async fn deploy(
+        &mut self,
+        vm_id: &str,
+        spec: VmSpec,
+        mut transitions: VecDeque<vm_state::Transition>,
+    ) -> Result<(), VmError> {
+        // 0. Refuse hugepage-backed VMs the host has too few pages for, and
+        //    NICs on a backend the host does not offer
+        if spec.hugepages() {
+            self.backend
+                .hugepages()
+                .fits(spec.memory_mb())
+                .map_err(VmError::Unschedulable)?;
+        }
+        if !self.backend.net_backends().contains(&spec.net_backend()) {
+            return Err(VmError::Unschedulable(format!(
+                "{} networking is not set up on this host",
+                spec.net_backend()
+            )));
+        }
 
         // 1. Ensure artifacts are available locally (e.g. nix copy from cache)
         //    Also copies the disk image to a writable location for this VM.
-        self.backend.prepare(&vm_id, &spec).await?;
+        let prepare_started = Instant::now();
+        let prepared = self
+            .backend
+            .prepare(vm_id, &spec)
+            .instrument(info_span!("vm.prepare", vm_id = %vm_id))
+            .await;
+        metrics::image_prepared(prepare_started.elapsed(), prepared.is_ok());
+        prepared?;
         tracing::debug!(vm_id = %vm_id, "prepare complete");
 
-        // 2. Spawn the VMM process via the backend
-        let (client, mut process, socket_path) = self.backend.spawn(&vm_id).await?;
+        // 2. Issue the VM's identity, so it is in place when the guest boots
+        let identity = self.issue_identity(vm_id)?;
+
+        // 3-8. Start the VMM and boot the VM, resolving only its domains
+        self.allow_domains(vm_id, &spec);
+        let (client, process) = match self.start_vm(vm_id, &spec).await {
+            Ok(started) => started,
+            Err(e) => {
+                self.remove_domains(vm_id);
+                self.remove_identity(vm_id);
+                return Err(e);
+            }
+        };
+
+        // 9. Record in our table, and on disk for adoption after a restart.
+        //    Watched VMs only count as running once the guest is ready.
+        let watched = self.watchdog.is_some() && self.backend.serial_log(vm_id).is_some();
+        let status = if watched {
+            VmState::Booting
+        } else {
+            VmState::Running
+        };
+        let from = transitions.back().map_or(VmState::Pending, |t| t.to);
+        record_transition(&mut transitions, from, status, "");
+        let handle = VmHandle {
+            observed_hash: spec.content_hash().to_string(),
+            spec,
+            client,
+            process,
+            status,
+            identity,
+            deployed_at: Instant::now(),
+            boot_restarts: 0,
+            boot_log: None,
+            transitions,
+            drain_started: None,
+            api_misses: 0,
+        };
+        self.save_record(vm_id, &handle);
+        self.vms.insert(vm_id.to_string(), handle);
+        metrics::vms_running(self.vms.len());
+        Ok(())
+    }
+
+    /// Spawn the VMM, create and boot the VM, and check it survived boot.
+    async fn start_vm(
+        &self,
+        vm_id: &str,
+        spec: &VmSpec,
+    ) -> Result<(B::Client, B::Process), VmError> {
+        // 3. Spawn the VMM process via the backend
+        let boot_started = Instant::now();
+        let (client, mut process, socket_path) = self.backend.spawn(vm_id).await?;
         tracing::debug!(vm_id = %vm_id, socket = %socket_path.display(), "VMM process spawned");
 
-        // 3. Build backend-specific config from the platform-agnostic spec
+        // 4. Build backend-specific config from the platform-agnostic spec
         //    Uses the writable disk path created by prepare().
-        let vmm_config = self.backend.build_config(&vm_id, &spec);
+        let vmm_config = self.backend.build_config(vm_id, spec);
 
-        // 4. Create the VM definition via the client
-        client.create(vmm_config).await.map_err(|e| {
-            VmError::Hypervisor(format!("vm.create failed: {e}"))
-        })?;
+        // 5. Create the VM definition via the client
+        let created = client.create(vmm_config).await;
+        metrics::vmm_operation("create", created.is_ok());
+        created.map_err(|e| VmError::Hypervisor(format!("vm.create failed: {e}")))?;
 
-        // 5. Boot the VM
-        client.boot().await.map_err(|e| {
-            VmError::Hypervisor(format!("vm.boot failed: {e}"))
-        })?;
+        // 6. Boot the VM
+        let booted = client
+            .boot()
+            .instrument(info_span!("vm.boot", vm_id = %vm_id))
+            .await;
+        metrics::vmm_operation("boot", booted.is_ok());
+        booted.map_err(|e| VmError::Hypervisor(format!("vm.boot failed: {e}")))?;
 
-        // 6. Attach the VM's TAP device to the host bridge.
+        // 7. Attach the VM's TAP device to the host bridge.
         //    In practice, CH may create/configure the TAP at boot time,
         //    so we attach after boot to avoid a create/attach race.
-        self.backend.attach_network(&vm_id).await?;
+        self.backend.attach_network(vm_id).await?;
         tracing::debug!(vm_id = %vm_id, "network attached");
+        metrics::vm_booted(boot_started.elapsed());
 
-        // 7. Quick liveness check — did CH crash right after boot?
+        // 8. Quick liveness check — did CH crash right after boot?
         //    Give it a moment, then verify the process is still alive.
         tokio::time::sleep(std::time::Duration::from_millis(500)).await;
         match process.try_wait() {
             Ok(Some(exit_status)) => {
+                metrics::vmm_unexpected_exit();
                 error!(
                     vm_id = %vm_id,
                     exit_status = %exit_status,
@@ -190,47 +649,207 @@ impl<B: VmmBackend> VmManager<B> {
             }
         }
 
-        // 8. Record in our table
-        let handle = VmHandle {
-            spec,
-            client,
-            process,
-            status: VmStatus::Running,
-        };
-        self.vms.insert(vm_id.clone(), handle);
-
-        info!(vm_id = %vm_id, "VM created and booted successfully");
-        Ok(vm_id)
+        Ok((client, process))
     }
 
+    /// Drain VM `vm_id` if draining is enabled and it has a guest to wait
+    /// for, otherwise delete it at once.
     #[instrument(skip(self))]
     async fn handle_delete(&mut self, vm_id: &str) -> Result<(), VmError> {
+        let handle = self
+            .vms
+            .get_mut(vm_id)
+            .ok_or_else(|| VmError::NotFound(vm_id.to_string()))?;
+        if matches!(handle.status, VmState::Draining) {
+            info!(vm_id = %vm_id, "VM is already draining");
+            return Ok(());
+        }
+        if let Some(drain) = &self.drain
+            && matches!(handle.status, VmState::Booting | VmState::Running)
+        {
+            check_transition(vm_id, handle.status, VmState::Draining)?;
+            let signal = drain.signal();
+            let signalled = match signal {
+                DrainSignal::PowerButton => handle.client.power_button().await,
+                DrainSignal::Shutdown => handle.client.shutdown().await,
+            };
+            metrics::vmm_operation(signal.as_str(), signalled.is_ok());
+            match signalled {
+                Ok(()) => {
+                    info!(vm_id = %vm_id, %signal, timeout = ?drain.timeout(), "Draining VM");
+                    handle.drain_started = Some(Instant::now());
+                    return handle.transition(vm_id, VmState::Draining, &format!("{signal} sent"));
+                }
+                Err(e) => {
+                    warn!(
+                        vm_id = %vm_id,
+                        %signal,
+                        error = ?e,
+                        "Cannot signal the guest, stopping VM at once"
+                    );
+                }
+            }
+        }
+        self.remove_vm(vm_id).await
+    }
+
+    /// The delete flow, see the module docs.
+    #[instrument(skip(self))]
+    async fn remove_vm(&mut self, vm_id: &str) -> Result<(), VmError> {
         let mut handle = self
             .vms
             .remove(vm_id)
             .ok_or_else(|| VmError::NotFound(vm_id.to_string()))?;
+        metrics::vms_running(self.vms.len());
 
         info!(vm_id = %vm_id, "Deleting VM");
 
-        // Try graceful shutdown, ignore errors (may already be stopped)
-        if let Err(e) = handle.client.shutdown().await {
-            warn!(vm_id = %vm_id, error = ?e, "Shutdown failed (may already be stopped)");
+        // A failed boot or a crash already killed and cleaned up its VMM
+        if !matches!(handle.status, VmState::BootFailed | VmState::Crashed) {
+            Self::stop_vmm(vm_id, &mut handle).await;
         }
+        self.remove_domains(vm_id);
+        self.remove_identity(vm_id);
+        self.remove_record(vm_id);
 
-        // Delete VM definition
-        if let Err(e) = handle.client.delete().await {
-            warn!(vm_id = %vm_id, error = ?e, "Delete failed");
+        info!(vm_id = %vm_id, "VM deleted");
+        Ok(())
+    }
+
+    /// Shut down, delete and kill the VMM of `handle`, then clean up after
+    /// it; only the last two once the VMM exited, e.g. after a drain.
+    /// Best-effort: every failure is only logged.
+    async fn stop_vmm(vm_id: &str, handle: &mut VmHandle<B>) {
+        if !matches!(handle.process.try_wait(), Ok(Some(_))) {
+            // Try graceful shutdown, ignore errors (may already be stopped)
+            let shutdown = handle.client.shutdown().await;
+            metrics::vmm_operation("shutdown", shutdown.is_ok());
+            if let Err(e) = shutdown {
+                warn!(vm_id = %vm_id, error = ?e, "Shutdown failed (may already be stopped)");
+            }
+
+            // Delete VM definition
+            let deleted = handle.client.delete().await;
+            metrics::vmm_operation("delete", deleted.is_ok());
+            if let Err(e) = deleted {
+                warn!(vm_id = %vm_id, error = ?e, "Delete failed");
+            }
         }
 
         // Kill the process and clean up resources
-        if let Err(e) = handle.process.kill().await {
+        let killed = handle.process.kill().await;
+        metrics::vmm_operation("kill", killed.is_ok());
+        if let Err(e) = killed {
             warn!(vm_id = %vm_id, error = ?e, "Failed to kill VMM process");
         }
         if let Err(e) = handle.process.cleanup().await {
             warn!(vm_id = %vm_id, error = ?e, "Cleanup failed");
         }
+    }
 
-        info!(vm_id = %vm_id, "VM deleted");
+    /// Run a user's lifecycle action on VM `vm_id`, see the module docs.
+    #[instrument(skip(self))]
+    async fn handle_action(&mut self, vm_id: &str, action: VmAction) -> Result<(), VmError> {
+        let handle = self
+            .vms
+            .get_mut(vm_id)
+            .ok_or_else(|| VmError::NotFound(vm_id.to_string()))?;
+        info!(vm_id = %vm_id, %action, status = handle.status.as_str(), "Running VM action");
+
+        match action {
+            VmAction::Stop if matches!(handle.status, VmState::BootFailed | VmState::Crashed) => {
+                info!(vm_id = %vm_id, status = handle.status.as_str(), "No VMM left to stop");
+            }
+            VmAction::Stop => {
+                check_transition(vm_id, handle.status, VmState::Stopped)?;
+                let shutdown = handle.client.shutdown().await;
+                metrics::vmm_operation("shutdown", shutdown.is_ok());
+                shutdown.map_err(|e| VmError::Hypervisor(format!("vm.shutdown failed: {e}")))?;
+                handle.transition(vm_id, VmState::Stopped, "stop action")?;
+            }
+            VmAction::Restart
+                if matches!(
+                    handle.status,
+                    VmState::Booting | VmState::BootFailed | VmState::Crashed
+                ) =>
+            {
+                let spec = handle.spec.clone();
+                let transitions =
+                    handle.transitions_via(vm_id, VmState::Restarting, "restart action")?;
+                self.remove_vm(vm_id).await?;
+                self.deploy(vm_id, spec, transitions).await?;
+            }
+            VmAction::Restart => {
+                check_transition(vm_id, handle.status, VmState::Running)?;
+                let (operation, done) = match handle.status {
+                    VmState::Stopped => ("boot", handle.client.boot().await),
+                    _ => ("reboot", handle.client.reboot().await),
+                };
+                metrics::vmm_operation(operation, done.is_ok());
+                done.map_err(|e| VmError::Hypervisor(format!("vm.{operation} failed: {e}")))?;
+                handle.transition(vm_id, VmState::Running, "restart action")?;
+            }
+            VmAction::Redeploy => {
+                let spec = handle.spec.clone();
+                let transitions =
+                    handle.transitions_via(vm_id, VmState::Redeploying, "redeploy action")?;
+                self.remove_vm(vm_id).await?;
+                self.deploy(vm_id, spec, transitions).await?;
+            }
+        }
+
+        info!(vm_id = %vm_id, %action, "VM action done");
+        Ok(())
+    }
+
+    /// Move VM `vm_id` to `spec`, in place when only its resources change,
+    /// see the module docs.
+    #[instrument(skip(self, spec), fields(cpu = spec.cpu(), memory_mb = spec.memory_mb()))]
+    async fn handle_update(&mut self, vm_id: &str, spec: VmSpec) -> Result<(), VmError> {
+        let handle = self
+            .vms
+            .get_mut(vm_id)
+            .ok_or_else(|| VmError::NotFound(vm_id.to_string()))?;
+        let hash = spec.content_hash().to_string();
+
+        if handle.spec.content_hash().to_string() == hash {
+            info!(vm_id = %vm_id, "VM already runs this spec");
+            handle.spec = spec;
+            self.save_record(vm_id, &self.vms[vm_id]);
+            return Ok(());
+        }
+
+        if matches!(handle.status, VmState::Running)
+            && handle.spec.only_resources_differ(&spec)
+            && self.backend.can_resize(&spec)
+        {
+            let resized = handle.client.resize(spec.cpu(), spec.memory_mb()).await;
+            metrics::vmm_operation("resize", resized.is_ok());
+            match resized {
+                Ok(()) => {
+                    info!(
+                        vm_id = %vm_id,
+                        cpu = spec.cpu(),
+                        memory_mb = spec.memory_mb(),
+                        "VM resized in place"
+                    );
+                    handle.spec = spec;
+                    handle.observed_hash = hash;
+                    self.save_record(vm_id, &self.vms[vm_id]);
+                    metrics::vm_updated("resized");
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!(vm_id = %vm_id, error = ?e, "Cannot resize VM in place, redeploying it");
+                }
+            }
+        }
+
+        let transitions = handle.transitions_via(vm_id, VmState::Redeploying, "spec update")?;
+        self.remove_vm(vm_id).await?;
+        self.deploy(vm_id, spec, transitions).await?;
+        metrics::vm_updated("redeployed");
+        info!(vm_id = %vm_id, "VM redeployed with the new spec");
         Ok(())
     }
 
@@ -247,28 +866,541 @@ impl<B: VmmBackend> VmManager<B> {
         let running = self
             .vms
             .values()
-            .filter(|h| matches!(h.status, VmStatus::Running))
+            .filter(|h| matches!(h.status, VmState::Running))
             .count() as u32;
 
-        Ok(WorkerInfo::new(
-            self.config.worker_id.clone(),
-            true,
-            0,
-            running,
-        ))
+        let mut disk = DiskUsage::default();
+        for (vm_id, handle) in &self.vms {
+            let usage = self.backend.disk_usage(vm_id, &handle.spec).await;
+            disk.add(vm_id, handle.spec.toplevel(), usage);
+        }
+
+        Ok(
+            WorkerInfo::new(self.config.worker_id.clone(), true, 0, running, disk)
+                .with_hugepages(self.backend.hugepages())
+                .with_net_backends(self.backend.net_backends())
+                .with_memory_pressure(
+                    self.pressure
+                        .as_ref()
+                        .map(MemoryPressure::report)
+                        .unwrap_or_default(),
+                )
+                .with_store_janitor(
+                    self.janitor
+                        .as_ref()
+                        .map(StoreJanitor::report)
+                        .unwrap_or_default(),
+                ),
+        )
+    }
+
+    /// Re-issue every certificate past half its lifetime. A VM whose
+    /// renewal fails keeps its current certificate and is retried on the
+    /// next round.
+    #[instrument(skip(self))]
+    fn handle_renew_identities(&mut self) {
+        let Some(issuer) = &self.identity else {
+            return;
+        };
+        let now = SystemTime::now();
+        for (vm_id, handle) in &mut self.vms {
+            if !handle
+                .identity
+                .as_ref()
+                .is_some_and(|identity| identity.needs_renewal(now))
+            {
+                continue;
+            }
+            let renewed = issuer.issue(vm_id, now).and_then(|identity| {
+                issuer.install(vm_id, &identity)?;
+                Ok(identity)
+            });
+            match renewed {
+                Ok(identity) => {
+                    info!(vm_id = %vm_id, spiffe_id = %identity.spiffe_id(), "Identity renewed");
+                    handle.identity = Some(identity);
+                }
+                Err(e) => warn!(vm_id = %vm_id, error = %e, "Identity renewal failed"),
+            }
+        }
+    }
+
+    /// Move booting VMs whose serial log shows them ready to `running`,
+    /// and fail the ones past the boot timeout or whose VMM exited.
+    async fn handle_check_boots(&mut self) {
+        let Some(watchdog) = &self.watchdog else {
+            return;
+        };
+        let mut failed = Vec::new();
+        for (vm_id, handle) in &mut self.vms {
+            if !matches!(handle.status, VmState::Booting) {
+                continue;
+            }
+            let Some(path) = self.backend.serial_log(vm_id) else {
+                continue;
+            };
+            let log = boot_watchdog::read_log(&path);
+            let elapsed = handle.deployed_at.elapsed();
+            if watchdog.is_ready(&log) {
+                info!(vm_id = %vm_id, ?elapsed, "VM is ready");
+                let _ = handle.transition(vm_id, VmState::Running, "guest is ready");
+                handle.boot_restarts = 0;
+                continue;
+            }
+            let reason = if matches!(handle.process.try_wait(), Ok(Some(_))) {
+                "VMM process exited during boot"
+            } else if elapsed >= watchdog.timeout() {
+                "not ready within the boot timeout"
+            } else {
+                continue;
+            };
+            failed.push((vm_id.clone(), reason, watchdog.excerpt(&log)));
+        }
+        for (vm_id, reason, excerpt) in failed {
+            self.fail_boot(&vm_id, reason, excerpt).await;
+        }
+    }
+
+    /// Delete the draining VMs whose guest powered off, and the ones past
+    /// the drain timeout.
+    async fn handle_check_drains(&mut self) {
+        let Some(drain) = &self.drain else {
+            return;
+        };
+        let mut done = Vec::new();
+        for (vm_id, handle) in &mut self.vms {
+            let Some(started) = handle.drain_started else {
+                continue;
+            };
+            let exited = matches!(handle.process.try_wait(), Ok(Some(_)));
+            let elapsed = started.elapsed();
+            if let Some(outcome) = drain.outcome(exited, elapsed) {
+                done.push((vm_id.clone(), outcome, elapsed));
+            }
+        }
+        for (vm_id, outcome, elapsed) in done {
+            match outcome {
+                DrainOutcome::Clean => info!(vm_id = %vm_id, ?elapsed, "VM drained"),
+                DrainOutcome::Forced => warn!(
+                    vm_id = %vm_id,
+                    ?elapsed,
+                    "VM did not power off within the drain timeout, stopping it"
+                ),
+            }
+            metrics::vm_drained(outcome.as_str());
+            if let Err(e) = self.remove_vm(&vm_id).await {
+                warn!(vm_id = %vm_id, error = %e, "Failed to delete drained VM");
+            }
+        }
+    }
+
+    /// Check the VMM of every running or stopped VM, crash the ones that
+    /// exited or stopped answering and follow guests that changed state.
+    async fn handle_check_vmms(&mut self) {
+        let Some(monitor) = &self.monitor else {
+            return;
+        };
+        let mut crashed = Vec::new();
+        for (vm_id, handle) in &mut self.vms {
+            if !matches!(handle.status, VmState::Running | VmState::Stopped) {
+                continue;
+            }
+            let probe = Self::probe_vmm(vm_id, handle, monitor.api_timeout()).await;
+            match monitor.verdict(handle.status, probe, handle.api_misses) {
+                Verdict::Healthy => handle.api_misses = 0,
+                Verdict::Missed => handle.api_misses += 1,
+                Verdict::Became(next, reason) => {
+                    info!(vm_id = %vm_id, from = %handle.status, to = %next, reason, "VM changed state");
+                    handle.api_misses = 0;
+                    let _ = handle.transition(vm_id, next, reason);
+                }
+                Verdict::Crashed(crash) => crashed.push((vm_id.clone(), crash)),
+            }
+        }
+        for (vm_id, crash) in crashed {
+            self.crash_vm(&vm_id, crash).await;
+        }
+    }
+
+    /// Whether the VMM of `handle` exited, and otherwise what it answers
+    /// to `vm.info` within `timeout`.
+    async fn probe_vmm(vm_id: &str, handle: &mut VmHandle<B>, timeout: Duration) -> Probe {
+        if let Ok(Some(exit)) = handle.process.try_wait() {
+            return Probe::Exited(exit);
+        }
+        match tokio::time::timeout(timeout, handle.client.info()).await {
+            Ok(Ok(state)) => Probe::Answered(state),
+            Ok(Err(e)) => {
+                warn!(vm_id = %vm_id, error = ?e, "VMM did not answer vm.info");
+                Probe::Unanswered
+            }
+            Err(_) => {
+                warn!(vm_id = %vm_id, ?timeout, "VMM timed out on vm.info");
+                Probe::Unanswered
+            }
+        }
+    }
+
+    /// Kill and clean up the VMM of `vm_id` and keep the VM as `crashed`
+    /// until it is deleted, restarted or redeployed.
+    #[instrument(skip(self))]
+    async fn crash_vm(&mut self, vm_id: &str, crash: Crash) {
+        let Some(handle) = self.vms.get_mut(vm_id) else {
+            return;
+        };
+        let reason = crash.reason();
+        match crash {
+            Crash::Exited(_) => metrics::vmm_unexpected_exit(),
+            Crash::Unresponsive(_) => metrics::vmm_unresponsive(),
+        }
+        error!(vm_id = %vm_id, status = handle.status.as_str(), %reason, "VMM crashed");
+        let killed = handle.process.kill().await;
+        metrics::vmm_operation("kill", killed.is_ok());
+        if let Err(e) = killed {
+            warn!(vm_id = %vm_id, error = ?e, "Failed to kill VMM process");
+        }
+        if let Err(e) = handle.process.cleanup().await {
+            warn!(vm_id = %vm_id, error = ?e, "Cleanup failed");
+        }
+        handle.api_misses = 0;
+        let _ = handle.transition(vm_id, VmState::Crashed, &reason);
+        self.remove_record(vm_id);
+    }
+
+    /// Record the host's memory pressure `psi`, and redeploy the largest
+    /// VM when the `restart` policy calls for it.
+    pub(crate) async fn handle_check_memory_pressure(&mut self, psi: Psi) {
+        let Some(pressure) = &mut self.pressure else {
+            return;
+        };
+        metrics::memory_pressure(psi.some_avg10, psi.full_avg10);
+        let policy = pressure.policy();
+        let transition = pressure.observe(psi);
+        match transition {
+            None => return,
+            Some(Transition::Entered) => {
+                metrics::memory_pressure_event("entered");
+                warn!(
+                    some_avg10 = psi.some_avg10,
+                    full_avg10 = psi.full_avg10,
+                    %policy,
+                    "Host is under memory pressure"
+                );
+            }
+            Some(Transition::Persisting) => {
+                warn!(some_avg10 = psi.some_avg10, %policy, "Host is still under memory pressure");
+            }
+            Some(Transition::Relieved) => {
+                metrics::memory_pressure_event("relieved");
+                info!(some_avg10 = psi.some_avg10, "Host memory pressure relieved");
+                return;
+            }
+        }
+        if policy != PressurePolicy::Restart {
+            return;
+        }
+
+        let Some(vm_id) = self
+            .vms
+            .iter()
+            .filter(|(_, h)| matches!(h.status, VmState::Running) && !h.spec.hugepages())
+            .max_by_key(|(_, h)| h.spec.memory_mb())
+            .map(|(id, _)| id.clone())
+        else {
+            warn!("No VM to restart, memory pressure persists");
+            return;
+        };
+        warn!(vm_id = %vm_id, "Restarting VM to relieve memory pressure");
+        if let Some(pressure) = &mut self.pressure {
+            pressure.restarted();
+        }
+        metrics::memory_pressure_event("restart");
+        if let Err(e) = self.handle_action(&vm_id, VmAction::Redeploy).await {
+            error!(vm_id = %vm_id, error = %e, "Restart to relieve memory pressure failed");
+        }
+    }
+
+    /// Pin the store paths of every VM, then collect garbage if `usage`
+    /// is above the high watermark.
+    pub(crate) async fn handle_check_store(&mut self, usage: StoreUsage) {
+        let Some(janitor) = &mut self.janitor else {
+            return;
+        };
+        let pins = store_janitor::store_paths(self.vms.values().flat_map(|h| {
+            [
+                h.spec.toplevel(),
+                h.spec.kernel_path(),
+                h.spec.initrd_path(),
+                h.spec.disk_image_path(),
+            ]
+        }));
+        if let Err(e) = janitor.pin(&pins) {
+            error!(error = %e, "Cannot pin VM store paths, not collecting garbage");
+            return;
+        }
+        metrics::store_used(usage.percent());
+        let Some(max_freed) = janitor.observe(usage) else {
+            return;
+        };
+
+        warn!(
+            used_percent = usage.percent(),
+            pinned = pins.len(),
+            max_freed,
+            "Store above its high watermark, collecting garbage"
+        );
+        match store_janitor::collect_garbage(max_freed).await {
+            Ok(freed) => {
+                info!(freed, "Store garbage collected");
+                janitor.collected(freed);
+                metrics::store_collected(freed, true);
+            }
+            Err(e) => {
+                error!(error = %e, "Store garbage collection failed");
+                metrics::store_collected(0, false);
+            }
+        }
+    }
+
+    /// Kill the boot attempt of `vm_id`, keep the VM as `boot-failed` with
+    /// `excerpt`, and deploy it again if the restart policy allows.
+    #[instrument(skip(self, excerpt))]
+    async fn fail_boot(&mut self, vm_id: &str, reason: &str, excerpt: String) {
+        let Some(handle) = self.vms.get_mut(vm_id) else {
+            return;
+        };
+        metrics::vm_boot_failed();
+        error!(
+            vm_id = %vm_id,
+            reason,
+            restarts = handle.boot_restarts,
+            serial_log = %excerpt,
+            "VM failed to boot"
+        );
+        let killed = handle.process.kill().await;
+        metrics::vmm_operation("kill", killed.is_ok());
+        if let Err(e) = killed {
+            warn!(vm_id = %vm_id, error = ?e, "Failed to kill VMM process");
+        }
+        if let Err(e) = handle.process.cleanup().await {
+            warn!(vm_id = %vm_id, error = ?e, "Cleanup failed");
+        }
+        let _ = handle.transition(vm_id, VmState::BootFailed, reason);
+        handle.boot_log = Some(excerpt);
+        let restarts = handle.boot_restarts;
+        let spec = handle.spec.clone();
+        let transitions = handle.transitions_via(
+            vm_id,
+            VmState::Restarting,
+            &format!("restart {} after a failed boot", restarts + 1),
+        );
+        self.remove_record(vm_id);
+
+        if !self
+            .watchdog
+            .as_ref()
+            .is_some_and(|watchdog| watchdog.should_restart(restarts))
+        {
+            warn!(vm_id = %vm_id, restarts, "Not restarting VM, it stays boot-failed");
+            return;
+        }
+        let Ok(transitions) = transitions else {
+            return;
+        };
+        info!(vm_id = %vm_id, restart = restarts + 1, "Restarting VM after failed boot");
+        match self.deploy(vm_id, spec, transitions).await {
+            Ok(()) => {
+                if let Some(handle) = self.vms.get_mut(vm_id) {
+                    handle.boot_restarts = restarts + 1;
+                }
+            }
+            Err(e) => warn!(vm_id = %vm_id, error = %e, "Restart failed, VM stays boot-failed"),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn handle_shutdown(&mut self, stop_vms: bool) {
+        self.stopped = true;
+
+        if !stop_vms {
+            // The next worker adopts from the records, so they are
+            // rewritten with what each VM runs now.
+            for (vm_id, handle) in &self.vms {
+                self.save_record(vm_id, handle);
+            }
+            for (vm_id, handle) in &mut self.vms {
+                info!(
+                    vm_id = %vm_id,
+                    status = handle.status.as_str(),
+                    toplevel = %handle.spec.toplevel(),
+                    "Leaving VM running across worker shutdown"
+                );
+                handle.process.detach();
+            }
+            return;
+        }
+
+        let vm_ids: Vec<String> = self.vms.keys().cloned().collect();
+        info!(count = vm_ids.len(), "Stopping all VMs before shutdown");
+        for vm_id in vm_ids {
+            if let Err(e) = self.remove_vm(&vm_id).await {
+                warn!(vm_id = %vm_id, error = %e, "Failed to stop VM during shutdown");
+            }
+        }
+    }
+
+    // ─── Adoption ──────────────────────────────────────────────────────
+
+    /// Adopt the VMs a previous worker process left running, see the module
+    /// docs. Call once, before handling commands. Returns how many VMs
+    /// were adopted.
+    pub async fn adopt_running(&mut self) -> usize {
+        let Some(records) = &self.records else {
+            return 0;
+        };
+        let loaded = match records.load() {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                error!(error = %e, "Cannot read VM records, adopting nothing");
+                return 0;
+            }
+        };
+
+        let mut adopted = 0;
+        for record in loaded {
+            let vm_id = record.vm_id;
+            if self.vms.contains_key(&vm_id) {
+                continue;
+            }
+            match self
+                .backend
+                .adopt(&vm_id, &record.spec, &record.process)
+                .await
+            {
+                Ok((client, process)) => {
+                    let identity = self.issue_identity(&vm_id).unwrap_or_else(|e| {
+                        warn!(vm_id = %vm_id, error = %e, "Adopted VM has no identity");
+                        None
+                    });
+                    info!(
+                        vm_id = %vm_id,
+                        toplevel = %record.spec.toplevel(),
+                        spec_hash = %record.spec_hash,
+                        "Adopted VM from a previous worker"
+                    );
+                    self.allow_domains(&vm_id, &record.spec);
+                    let mut handle = VmHandle {
+                        spec: record.spec,
+                        client,
+                        process,
+                        status: VmState::Running,
+                        observed_hash: record.spec_hash,
+                        identity,
+                        deployed_at: Instant::now(),
+                        boot_restarts: 0,
+                        boot_log: None,
+                        transitions: VecDeque::new(),
+                        drain_started: None,
+                        api_misses: 0,
+                    };
+                    record_transition(
+                        &mut handle.transitions,
+                        VmState::Pending,
+                        VmState::Running,
+                        "adopted from a previous worker",
+                    );
+                    self.vms.insert(vm_id, handle);
+                    adopted += 1;
+                }
+                Err(e) => {
+                    warn!(vm_id = %vm_id, error = %e, "VM did not survive the restart, dropping its record");
+                    self.remove_identity(&vm_id);
+                    self.remove_record(&vm_id);
+                }
+            }
+        }
+        metrics::vms_running(self.vms.len());
+        adopted
     }
 
     // ─── Helpers ───────────────────────────────────────────────────────
 
+    /// Best-effort: without its record the VM is only forgotten on restart.
+    fn save_record(&self, vm_id: &str, handle: &VmHandle<B>) {
+        let (Some(records), Some(process)) = (&self.records, handle.process.record()) else {
+            return;
+        };
+        let record = VmRecord {
+            vm_id: vm_id.to_string(),
+            spec: handle.spec.clone(),
+            spec_hash: handle.observed_hash.clone(),
+            process,
+        };
+        if let Err(e) = records.save(&record) {
+            warn!(vm_id = %vm_id, error = %e, "Failed to write VM record, it will not be adopted");
+        }
+    }
+
+    fn remove_record(&self, vm_id: &str) {
+        if let Some(records) = &self.records
+            && let Err(e) = records.remove(vm_id)
+        {
+            warn!(vm_id = %vm_id, error = %e, "Failed to remove VM record");
+        }
+    }
+
+    /// Issue and install a VM's identity; `None` when identities are off.
+    fn issue_identity(&self, vm_id: &str) -> Result<Option<VmIdentity>, VmError> {
+        let Some(issuer) = &self.identity else {
+            return Ok(None);
+        };
+        let installed = issuer.issue(vm_id, SystemTime::now()).and_then(|identity| {
+            issuer.install(vm_id, &identity)?;
+            Ok(identity)
+        });
+        let identity = match installed {
+            Ok(identity) => identity,
+            Err(e) => {
+                self.remove_identity(vm_id);
+                return Err(VmError::Identity(e));
+            }
+        };
+        info!(vm_id = %vm_id, spiffe_id = %identity.spiffe_id(), "Identity issued");
+        Ok(Some(identity))
+    }
+
+    /// Best-effort: a leftover directory only holds an expiring certificate.
+    fn remove_identity(&self, vm_id: &str) {
+        if let Some(issuer) = &self.identity
+            && let Err(e) = issuer.remove(vm_id)
+        {
+            warn!(vm_id = %vm_id, error = %e, "Failed to remove identity");
+        }
+    }
+
+    fn allow_domains(&self, vm_id: &str, spec: &VmSpec) {
+        if let Some(dns) = &self.dns {
+            dns.allow(vm_id, spec.network_allowed_domains());
+        }
+    }
+
+    fn remove_domains(&self, vm_id: &str) {
+        if let Some(dns) = &self.dns {
+            dns.remove(vm_id);
+        }
+    }
+
     fn build_vm_info(&self, vm_id: &str, handle: &VmHandle<B>) -> VmInfo {
-        let toplevel_hash = handle.spec.toplevel().to_string();
         VmInfo::new(
             vm_id.to_string(),
             self.config.worker_id.clone(),
-            handle.status.clone(),
-            toplevel_hash.clone(),
-            toplevel_hash, // TODO: compute from running state
-            VmMetrics::default(),
+            handle.status,
+            handle.spec.content_hash().to_string(),
+            handle.observed_hash.clone(),
+            handle.client.metrics(),
+            handle.spec.labels().clone(),
         )
+        .with_boot_log(handle.boot_log.clone())
+        .with_transitions(handle.transitions.iter().cloned().collect())
     }
 }
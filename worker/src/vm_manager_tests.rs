@@ -1,12 +1,25 @@
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use commands::labels::Labels;
+    use commands::net_backend::NetBackend;
+    use commands::vm_action::VmAction;
+    use commands::vm_state::VmState;
     use tokio::sync::oneshot;
 
+    use crate::boot_watchdog::{BootWatchdog, RestartPolicy};
+    use crate::drain::{Drain, DrainSignal};
     use crate::dto::{
-        CommandPayload, CommandResponse, Message, VmError, VmSpec,
+        BootMode, CommandPayload, CommandResponse, Message, VmError, VmInfo, VmSpec,
     };
+    use crate::hugepages::HugePages;
+    use crate::memory_pressure::{MemoryPressure, PressurePolicy, Psi};
+    use crate::store_janitor::{StoreJanitor, StoreJanitorSection, StoreUsage};
     use crate::vm_manager::{VmManager, VmManagerConfig};
-    use crate::vmm::mock::{MockBackend, MockBackendConfig};
+    use crate::vmm::GuestState;
+    use crate::vmm::mock::{MockBackend, MockBackendConfig, MockCallTracker};
+    use crate::vmm_monitor::VmmMonitor;
 
     // ─── Helpers ───────────────────────────────────────────────────────
 
@@ -26,6 +39,8 @@ mod tests {
     fn test_config() -> VmManagerConfig {
         VmManagerConfig {
             worker_id: "test-worker".to_string(),
+            max_vms: None,
+            state_dir: None,
         }
     }
 
@@ -153,6 +168,36 @@ mod tests {
         assert!(result.is_ok(), "extra fields should be ignored for forward compat");
     }
 
+    #[test]
+    fn closure_spec_boots_from_its_toplevel() {
+        // What `mkClosureVmSpec` produces: no disk image, no kernel/initrd
+        let json = r#"{
+            "toplevel": "/nix/store/aaaa-nixos-system/",
+            "kernelPath": "",
+            "initrdPath": "",
+            "diskImagePath": "",
+            "cmdline": "",
+            "cpu": 1,
+            "memoryMb": 512,
+            "networkAllowedDomains": []
+        }"#;
+
+        let spec: VmSpec = serde_json::from_str(json).expect("closure spec should deserialize");
+
+        assert_eq!(spec.boot_mode(), BootMode::Closure);
+        assert_eq!(spec.boot_kernel(), "/nix/store/aaaa-nixos-system/kernel");
+        assert_eq!(spec.boot_initrd(), "/nix/store/aaaa-nixos-system/initrd");
+    }
+
+    #[test]
+    fn disk_image_spec_keeps_its_paths() {
+        let spec = test_spec();
+
+        assert_eq!(spec.boot_mode(), BootMode::DiskImage);
+        assert_eq!(spec.boot_kernel(), "/nix/store/bbbb-kernel/bzImage");
+        assert_eq!(spec.boot_initrd(), "/nix/store/cccc-initrd/initrd");
+    }
+
     #[tokio::test]
     async fn create_vm_returns_uuid() {
         let (backend, tracker) = MockBackend::new();
@@ -193,6 +238,32 @@ mod tests {
         assert_eq!(tracker.spawn_count(), 2);
     }
 
+    #[tokio::test]
+    async fn create_beyond_max_vms_is_refused() {
+        let (backend, tracker) = MockBackend::new();
+        let config = VmManagerConfig {
+            max_vms: Some(1),
+            ..test_config()
+        };
+        let mut mgr = VmManager::new(backend, config);
+
+        let id = match send(&mut mgr, CommandPayload::Create(test_spec())).await {
+            Ok(CommandResponse::VmId(id)) => id,
+            other => panic!("expected VmId, got {other:?}"),
+        };
+        match send(&mut mgr, CommandPayload::Create(test_spec())).await {
+            Err(VmError::AtCapacity(1)) => {}
+            other => panic!("expected AtCapacity, got {other:?}"),
+        }
+        assert_eq!(tracker.spawn_count(), 1, "a refused VM is never spawned");
+
+        // Deleting frees the slot
+        let resp = send(&mut mgr, CommandPayload::Delete(id)).await;
+        assert!(matches!(resp, Ok(CommandResponse::Unit)));
+        let resp = send(&mut mgr, CommandPayload::Create(test_spec())).await;
+        assert!(matches!(resp, Ok(CommandResponse::VmId(_))));
+    }
+
     // ─── Delete VM ─────────────────────────────────────────────────────
 
     #[tokio::test]
@@ -299,7 +370,7 @@ mod tests {
                 }
                 // All report status running
                 for info in &list {
-                    assert_eq!(info.status().as_str(), "running");
+                    assert_eq!(info.status(), VmState::Running);
                     assert_eq!(info.worker_id(), "test-worker");
                 }
             }
@@ -307,6 +378,22 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn list_reports_labels_without_changing_the_hash() {
+        let (backend, _tracker) = MockBackend::new();
+        let mut mgr = VmManager::new(backend, test_config());
+
+        let labels: Labels = [("app".to_string(), "web".to_string())].into();
+        let spec = test_spec().with_labels(labels.clone());
+        assert_eq!(spec.content_hash(), test_spec().content_hash());
+        send(&mut mgr, CommandPayload::Create(spec)).await.unwrap();
+
+        match send(&mut mgr, CommandPayload::List).await {
+            Ok(CommandResponse::VmList(list)) => assert_eq!(list[0].labels(), &labels),
+            other => panic!("expected VmList, got {other:?}"),
+        }
+    }
+
     #[tokio::test]
     async fn list_excludes_deleted_vms() {
         let (backend, _tracker) = MockBackend::new();
@@ -334,6 +421,219 @@ mod tests {
         }
     }
 
+    // ─── Actions ───────────────────────────────────────────────────────
+
+    fn action(vm_id: &str, action: VmAction) -> CommandPayload {
+        CommandPayload::Action {
+            vm_id: vm_id.to_string(),
+            action,
+        }
+    }
+
+    async fn status_of(manager: &mut VmManager<MockBackend>, vm_id: &str) -> VmState {
+        match send(manager, CommandPayload::List).await {
+            Ok(CommandResponse::VmList(list)) => list
+                .iter()
+                .find(|info| info.id() == vm_id)
+                .map(VmInfo::status)
+                .expect("VM listed"),
+            other => panic!("expected VmList, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn stop_then_restart_boots_the_vm_again() {
+        let (backend, tracker) = MockBackend::new();
+        let mut mgr = VmManager::new(backend, test_config());
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+
+        send(&mut mgr, action(&id, VmAction::Stop)).await.unwrap();
+        assert_eq!(tracker.shutdown_count(), 1);
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Stopped);
+
+        send(&mut mgr, action(&id, VmAction::Restart)).await.unwrap();
+        assert_eq!(tracker.boot_count(), 2, "a stopped VM is booted again");
+        assert_eq!(tracker.reboot_count(), 0);
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Running);
+
+        send(&mut mgr, action(&id, VmAction::Restart)).await.unwrap();
+        assert_eq!(tracker.reboot_count(), 1, "a running VM is rebooted");
+    }
+
+    #[tokio::test]
+    async fn redeploy_recreates_the_vm_under_the_same_id() {
+        let (backend, tracker) = MockBackend::new();
+        let mut mgr = VmManager::new(backend, test_config());
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+
+        send(&mut mgr, action(&id, VmAction::Redeploy)).await.unwrap();
+        assert_eq!(tracker.cleanup_count(), 1, "old disk copy removed");
+        assert_eq!(tracker.prepare_count(), 2, "fresh disk copy");
+        assert_eq!(tracker.spawn_count(), 2);
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Running);
+    }
+
+    #[tokio::test]
+    async fn transitions_are_kept_across_a_redeploy() {
+        let mut mgr = VmManager::new(MockBackend::new().0, test_config());
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+        for vm_action in [VmAction::Stop, VmAction::Restart, VmAction::Redeploy] {
+            send(&mut mgr, action(&id, vm_action)).await.unwrap();
+        }
+
+        let Ok(CommandResponse::VmList(list)) = send(&mut mgr, CommandPayload::List).await else {
+            panic!("list failed");
+        };
+        let moves: Vec<_> = list[0]
+            .transitions()
+            .iter()
+            .map(|t| (t.from, t.to))
+            .collect();
+        assert_eq!(
+            moves,
+            [
+                (VmState::Pending, VmState::Running),
+                (VmState::Running, VmState::Stopped),
+                (VmState::Stopped, VmState::Running),
+                (VmState::Running, VmState::Redeploying),
+                (VmState::Redeploying, VmState::Running),
+            ]
+        );
+        assert_eq!(list[0].transitions()[1].reason, "stop action");
+        assert!(
+            list[0]
+                .transitions()
+                .windows(2)
+                .all(|pair| pair[0].at_ms <= pair[1].at_ms)
+        );
+    }
+
+    #[tokio::test]
+    async fn action_on_unknown_vm_is_not_found() {
+        let (backend, _tracker) = MockBackend::new();
+        let mut mgr = VmManager::new(backend, test_config());
+        let result = send(&mut mgr, action("nope", VmAction::Restart)).await;
+        assert!(matches!(result, Err(VmError::NotFound(_))));
+    }
+
+    // ─── Updates ───────────────────────────────────────────────────────
+
+    fn update(vm_id: &str, spec: VmSpec) -> CommandPayload {
+        CommandPayload::Update {
+            vm_id: vm_id.to_string(),
+            spec,
+        }
+    }
+
+    /// [`test_spec`] with other resources.
+    fn resized_spec(cpu: u32, memory_mb: u32) -> VmSpec {
+        VmSpec::new(
+            "/nix/store/aaaa-nixos-system".to_string(),
+            "/nix/store/bbbb-kernel/bzImage".to_string(),
+            "/nix/store/cccc-initrd/initrd".to_string(),
+            "/nix/store/dddd-disk/nixos.raw".to_string(),
+            "console=ttyS0 root=/dev/vda rw".to_string(),
+            cpu,
+            memory_mb,
+            vec!["api.openai.com".to_string()],
+        )
+    }
+
+    async fn updated_manager(
+        config: MockBackendConfig,
+    ) -> (VmManager<MockBackend>, MockCallTracker, String) {
+        let (backend, tracker) = MockBackend::with_config(config);
+        let mut mgr = VmManager::new(backend, test_config());
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+        (mgr, tracker, id)
+    }
+
+    #[tokio::test]
+    async fn resource_only_update_resizes_the_vm_in_place() {
+        let (mut mgr, tracker, id) = updated_manager(MockBackendConfig {
+            resizable: true,
+            ..Default::default()
+        })
+        .await;
+        let spec = resized_spec(4, 2048);
+        let hash = spec.content_hash().to_string();
+
+        send(&mut mgr, update(&id, spec)).await.unwrap();
+        assert_eq!(tracker.resize_count(), 1);
+        assert_eq!(tracker.spawn_count(), 1, "no redeploy");
+        let Ok(CommandResponse::VmList(list)) = send(&mut mgr, CommandPayload::List).await else {
+            panic!("list failed");
+        };
+        assert_eq!(list[0].status(), VmState::Running);
+        assert_eq!(list[0].desired_hash(), hash);
+        assert_eq!(list[0].observed_hash(), hash, "the VM runs the new spec");
+        assert_eq!(list[0].transitions().len(), 1, "resizing is no transition");
+    }
+
+    #[tokio::test]
+    async fn other_updates_and_refused_resizes_redeploy() {
+        let (mut mgr, tracker, id) = updated_manager(MockBackendConfig::default()).await;
+        send(&mut mgr, update(&id, resized_spec(4, 2048)))
+            .await
+            .unwrap();
+        assert_eq!(tracker.resize_count(), 0, "backend cannot resize");
+        assert_eq!(tracker.spawn_count(), 2);
+
+        let (mut mgr, tracker, id) = updated_manager(MockBackendConfig {
+            resizable: true,
+            resize_error: Some("no room".to_string()),
+            ..Default::default()
+        })
+        .await;
+        send(&mut mgr, update(&id, resized_spec(4, 2048)))
+            .await
+            .unwrap();
+        assert_eq!(tracker.resize_count(), 1);
+        assert_eq!(tracker.spawn_count(), 2, "redeployed after the refusal");
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Running);
+
+        let new_image = test_spec().with_shared_memory(true);
+        send(&mut mgr, update(&id, new_image)).await.unwrap();
+        assert_eq!(tracker.resize_count(), 1, "not a resource-only change");
+        assert_eq!(tracker.spawn_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn update_to_the_same_spec_only_relabels() {
+        let (mut mgr, tracker, id) = updated_manager(MockBackendConfig::default()).await;
+        let labels: Labels = [("app".to_string(), "web".to_string())].into();
+        send(
+            &mut mgr,
+            update(&id, test_spec().with_labels(labels.clone())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(tracker.spawn_count(), 1);
+        let Ok(CommandResponse::VmList(list)) = send(&mut mgr, CommandPayload::List).await else {
+            panic!("list failed");
+        };
+        assert_eq!(list[0].labels(), &labels);
+
+        let result = send(&mut mgr, update("nope", test_spec())).await;
+        assert!(matches!(result, Err(VmError::NotFound(_))));
+    }
+
     // ─── Worker status ─────────────────────────────────────────────────
 
     #[tokio::test]
@@ -365,6 +665,221 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn worker_status_breaks_disk_usage_down() {
+        let (backend, _tracker) = MockBackend::with_config(MockBackendConfig {
+            synthetic_metrics: true,
+            ..Default::default()
+        });
+        let mut mgr = VmManager::new(backend, test_config());
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            match send(&mut mgr, CommandPayload::Create(test_spec())).await {
+                Ok(CommandResponse::VmId(id)) => ids.push(id),
+                other => panic!("expected VmId, got {other:?}"),
+            }
+        }
+
+        let disk = match send(&mut mgr, CommandPayload::GetWorkerStatus).await {
+            Ok(CommandResponse::WorkerInfo(info)) => info.disk_usage().clone(),
+            other => panic!("expected WorkerInfo, got {other:?}"),
+        };
+        // Both VMs boot the same image, whose closure is counted once
+        assert_eq!(
+            disk.images.get("/nix/store/aaaa-nixos-system"),
+            Some(&(1024 * 1024 * 1024))
+        );
+        assert_eq!(disk.images.len(), 1);
+        for id in &ids {
+            // A volume the size of the spec's 1024 MiB
+            assert_eq!(disk.vms.get(id), Some(&(1024 * 1024 * 1024)));
+        }
+        assert_eq!(disk.log_bytes, 2 * 1024 * 1024);
+        assert_eq!(disk.total(), 3 * 1024 * 1024 * 1024 + 2 * 1024 * 1024);
+    }
+
+    #[tokio::test]
+    async fn hugepage_vms_need_enough_free_hugepages() {
+        let spec = test_spec().with_hugepages(true);
+        let mut mgr = VmManager::new(MockBackend::new().0, test_config());
+        match send(&mut mgr, CommandPayload::Create(spec.clone())).await {
+            Err(VmError::Unschedulable(msg)) => {
+                assert!(msg.contains("no hugepages"), "got: {msg}");
+            }
+            other => panic!("expected Unschedulable, got {other:?}"),
+        }
+
+        let pool = HugePages {
+            page_size_kb: 2048,
+            total: 512,
+            free: 512,
+        };
+        let (backend, _tracker) = MockBackend::with_config(MockBackendConfig {
+            hugepages: pool,
+            ..Default::default()
+        });
+        let mut mgr = VmManager::new(backend, test_config());
+        assert!(matches!(
+            send(&mut mgr, CommandPayload::Create(spec)).await,
+            Ok(CommandResponse::VmId(_))
+        ));
+        match send(&mut mgr, CommandPayload::GetWorkerStatus).await {
+            Ok(CommandResponse::WorkerInfo(info)) => assert_eq!(info.hugepages(), pool),
+            other => panic!("expected WorkerInfo, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn vhost_user_vms_need_a_prepared_host() {
+        let spec = test_spec().with_net_backend(NetBackend::VhostUser);
+        let mut mgr = VmManager::new(MockBackend::new().0, test_config());
+        match send(&mut mgr, CommandPayload::Create(spec.clone())).await {
+            Err(VmError::Unschedulable(msg)) => {
+                assert!(msg.contains("vhost-user"), "got: {msg}");
+            }
+            other => panic!("expected Unschedulable, got {other:?}"),
+        }
+
+        let (backend, _tracker) = MockBackend::with_config(MockBackendConfig {
+            vhost_user_net: true,
+            ..Default::default()
+        });
+        let mut mgr = VmManager::new(backend, test_config());
+        assert!(matches!(
+            send(&mut mgr, CommandPayload::Create(spec)).await,
+            Ok(CommandResponse::VmId(_))
+        ));
+        match send(&mut mgr, CommandPayload::GetWorkerStatus).await {
+            Ok(CommandResponse::WorkerInfo(info)) => {
+                assert_eq!(info.net_backends(), NetBackend::ALL);
+            }
+            other => panic!("expected WorkerInfo, got {other:?}"),
+        }
+    }
+
+    // ─── Memory pressure ───────────────────────────────────────────────
+
+    fn pressure(some_avg10: f32) -> Psi {
+        Psi {
+            some_avg10,
+            full_avg10: 0.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn throttle_refuses_vms_while_under_pressure() {
+        let mut mgr = VmManager::new(MockBackend::new().0, test_config())
+            .with_memory_pressure(MemoryPressure::new(20.0, PressurePolicy::Throttle));
+        for _ in 0..3 {
+            mgr.handle_check_memory_pressure(pressure(60.0)).await;
+        }
+        match send(&mut mgr, CommandPayload::Create(test_spec())).await {
+            Err(VmError::Unschedulable(msg)) => {
+                assert!(msg.contains("memory pressure"), "got: {msg}");
+            }
+            other => panic!("expected Unschedulable, got {other:?}"),
+        }
+        match send(&mut mgr, CommandPayload::GetWorkerStatus).await {
+            Ok(CommandResponse::WorkerInfo(info)) => {
+                let report = info.memory_pressure();
+                assert!(report.under_pressure);
+                assert_eq!(report.episodes, 1);
+                assert_eq!(report.policy, Some(PressurePolicy::Throttle));
+            }
+            other => panic!("expected WorkerInfo, got {other:?}"),
+        }
+
+        mgr.handle_check_memory_pressure(pressure(1.0)).await;
+        assert!(matches!(
+            send(&mut mgr, CommandPayload::Create(test_spec())).await,
+            Ok(CommandResponse::VmId(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn restart_redeploys_the_largest_vm() {
+        let (backend, tracker) = MockBackend::new();
+        let mut mgr = VmManager::new(backend, test_config())
+            .with_memory_pressure(MemoryPressure::new(20.0, PressurePolicy::Restart));
+        let large = VmSpec::new(
+            "/nix/store/eeee-nixos-system".to_string(),
+            "/nix/store/bbbb-kernel/bzImage".to_string(),
+            "/nix/store/cccc-initrd/initrd".to_string(),
+            "/nix/store/dddd-disk/nixos.raw".to_string(),
+            "console=ttyS0 root=/dev/vda rw".to_string(),
+            2,
+            4096,
+            Vec::new(),
+        );
+        send(&mut mgr, CommandPayload::Create(test_spec())).await.unwrap();
+        let Ok(CommandResponse::VmId(id)) = send(&mut mgr, CommandPayload::Create(large)).await
+        else {
+            panic!("create failed");
+        };
+
+        for _ in 0..3 {
+            mgr.handle_check_memory_pressure(pressure(60.0)).await;
+        }
+        assert_eq!(tracker.cleanup_count(), 1, "only one VM restarted");
+        assert_eq!(tracker.spawn_count(), 3);
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Running);
+        match send(&mut mgr, CommandPayload::GetWorkerStatus).await {
+            Ok(CommandResponse::WorkerInfo(info)) => {
+                assert_eq!(info.running_vms(), 2);
+                assert_eq!(info.memory_pressure().restarts, 1);
+            }
+            other => panic!("expected WorkerInfo, got {other:?}"),
+        }
+    }
+
+    // ─── Store janitor ─────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn store_janitor_pins_the_paths_of_assigned_vms() {
+        let dir = std::env::temp_dir().join(format!("worker-gcroots-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let janitor = StoreJanitor::from(StoreJanitorSection {
+            gc_roots_dir: dir.clone(),
+            ..StoreJanitorSection::default()
+        });
+        let mut mgr =
+            VmManager::new(MockBackend::new().0, test_config()).with_store_janitor(janitor);
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+
+        let half = StoreUsage {
+            total_bytes: 100,
+            used_bytes: 50,
+        };
+        mgr.handle_check_store(half).await;
+        let mut roots: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        roots.sort();
+        assert_eq!(
+            roots,
+            ["aaaa-nixos-system", "bbbb-kernel", "cccc-initrd", "dddd-disk"]
+        );
+        match send(&mut mgr, CommandPayload::GetWorkerStatus).await {
+            Ok(CommandResponse::WorkerInfo(info)) => {
+                let report = info.store_janitor();
+                assert_eq!(report.pinned_paths, 4);
+                assert_eq!(report.collections, 0, "below the high watermark");
+                assert!((report.used_percent - 50.0).abs() < 0.01);
+            }
+            other => panic!("expected WorkerInfo, got {other:?}"),
+        }
+
+        send(&mut mgr, CommandPayload::Delete(id)).await.unwrap();
+        mgr.handle_check_store(half).await;
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     // ─── Failure injection ─────────────────────────────────────────────
 
     #[tokio::test]
@@ -454,7 +969,7 @@ mod tests {
     // ─── VmSpec field validation ───────────────────────────────────────
 
     #[tokio::test]
-    async fn vm_info_contains_toplevel_hash() {
+    async fn vm_info_contains_spec_hash() {
         let (backend, _tracker) = MockBackend::new();
         let mut mgr = VmManager::new(backend, test_config());
 
@@ -466,8 +981,8 @@ mod tests {
                 assert_eq!(list.len(), 1);
                 assert_eq!(
                     list[0].desired_hash(),
-                    "/nix/store/aaaa-nixos-system",
-                    "desired_hash should come from spec.toplevel()"
+                    test_spec().content_hash().as_str(),
+                    "desired_hash should be the canonical spec hash"
                 );
             }
             other => panic!("expected VmList, got {other:?}"),
@@ -529,4 +1044,631 @@ mod tests {
             other => panic!("expected empty VmList, got {other:?}"),
         }
     }
+
+    // ─── Metrics ───────────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn create_and_delete_record_metrics() {
+        // Current-thread runtime, so the thread-local recorder sees every call.
+        let recorder = metrics_exporter_prometheus::PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let (backend, _tracker) = MockBackend::new();
+        let mut mgr = VmManager::new(backend, test_config());
+        let id = match send(&mut mgr, CommandPayload::Create(test_spec())).await {
+            Ok(CommandResponse::VmId(id)) => id,
+            other => panic!("expected VmId, got {other:?}"),
+        };
+        let rendered = handle.render();
+        assert!(rendered.contains("procurator_worker_vm_boot_duration_seconds_count 1"));
+        assert!(rendered.contains(
+            "procurator_worker_image_prepare_duration_seconds_count{result=\"ok\"} 1"
+        ));
+        assert!(rendered.contains("procurator_worker_vms_running 1"));
+
+        send(&mut mgr, CommandPayload::Delete(id)).await.unwrap();
+        let rendered = handle.render();
+        assert!(rendered.contains("procurator_worker_vms_running 0"));
+        assert!(rendered.contains(
+            "procurator_worker_vmm_operations_total{op=\"kill\",result=\"ok\"} 1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn simulated_vms_report_synthetic_metrics() {
+        let (backend, _tracker) = MockBackend::with_config(MockBackendConfig {
+            synthetic_metrics: true,
+            ..Default::default()
+        });
+        let mut mgr = VmManager::new(backend, test_config());
+        send(&mut mgr, CommandPayload::Create(test_spec())).await.unwrap();
+
+        let list = match send(&mut mgr, CommandPayload::List).await {
+            Ok(CommandResponse::VmList(list)) => list,
+            other => panic!("expected VmList, got {other:?}"),
+        };
+        let metrics = list[0].metrics();
+        // 60% of the spec's 1024 MiB
+        assert_eq!(metrics.memory_usage, 1024 * 1024 * 1024 * 3 / 5);
+        assert!((0.1..=0.5).contains(&metrics.cpu_usage));
+        assert!(metrics.network_rx_bytes > 0, "traffic grows with uptime");
+    }
+
+    // ─── Identity ──────────────────────────────────────────────────────
+
+    /// Issuer with a throwaway root and worker CA, and the directory it
+    /// writes into.
+    fn test_issuer(name: &str) -> (crate::identity::IdentityIssuer, std::path::PathBuf) {
+        use crate::identity::{IdentityIssuer, issue_worker_ca};
+        use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::default();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let root = params.self_signed(&key).unwrap().pem();
+        let ttl = std::time::Duration::from_hours(1);
+        let ca = issue_worker_ca(
+            "cluster.test",
+            "test-worker",
+            &root,
+            &key.serialize_pem(),
+            ttl,
+            std::time::SystemTime::now(),
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!(
+            "worker-vm-identity-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let issuer = IdentityIssuer::new(
+            "cluster.test",
+            "test-worker",
+            &root,
+            &ca.cert_pem,
+            &ca.key_pem,
+            ttl,
+            dir.clone(),
+        )
+        .unwrap();
+        (issuer, dir)
+    }
+
+    #[tokio::test]
+    async fn identity_lives_and_dies_with_the_vm() {
+        let (backend, _tracker) = MockBackend::new();
+        let (issuer, dir) = test_issuer("lifecycle");
+        let mut mgr = VmManager::new(backend, test_config()).with_identity(issuer);
+
+        let id = match send(&mut mgr, CommandPayload::Create(test_spec())).await {
+            Ok(CommandResponse::VmId(id)) => id,
+            other => panic!("expected VmId, got {other:?}"),
+        };
+        let identity = std::fs::read_to_string(dir.join(&id).join("identity.json")).unwrap();
+        assert!(identity.contains(&format!("spiffe://cluster.test/worker/test-worker/vm/{id}")));
+        assert!(dir.join(&id).join("svid.key").exists());
+
+        // Freshly issued: nothing is due yet
+        let cert = std::fs::read_to_string(dir.join(&id).join("svid.pem")).unwrap();
+        send(&mut mgr, CommandPayload::RenewIdentities).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join(&id).join("svid.pem")).unwrap(), cert);
+
+        send(&mut mgr, CommandPayload::Delete(id.clone())).await.unwrap();
+        assert!(!dir.join(&id).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn boot_failure_removes_identity() {
+        let (backend, _tracker) = MockBackend::with_config(MockBackendConfig {
+            boot_error: Some("kernel panic".to_string()),
+            ..Default::default()
+        });
+        let (issuer, dir) = test_issuer("boot-failure");
+        let mut mgr = VmManager::new(backend, test_config()).with_identity(issuer);
+
+        assert!(send(&mut mgr, CommandPayload::Create(test_spec())).await.is_err());
+        let left = std::fs::read_dir(&dir).map_or(0, Iterator::count);
+        assert_eq!(left, 0, "no identity directory should survive a failed create");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // ─── DNS policies ──────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn allowed_domains_live_and_die_with_the_vm() {
+        use crate::dns_proxy::{DnsPolicies, Verdict, tap_name};
+
+        let (backend, _tracker) = MockBackend::new();
+        let policies = DnsPolicies::default();
+        let mut mgr = VmManager::new(backend, test_config()).with_dns_policies(policies.clone());
+
+        let id = match send(&mut mgr, CommandPayload::Create(test_spec())).await {
+            Ok(CommandResponse::VmId(id)) => id,
+            other => panic!("expected VmId, got {other:?}"),
+        };
+        let tap = tap_name(&id);
+        assert_eq!(
+            policies.check(&tap, "api.openai.com"),
+            Some((id.clone(), Verdict::Allow))
+        );
+        assert_eq!(
+            policies.check(&tap, "example.com"),
+            Some((id.clone(), Verdict::Deny))
+        );
+
+        send(&mut mgr, CommandPayload::Delete(id)).await.unwrap();
+        assert_eq!(policies.check(&tap, "api.openai.com"), None);
+    }
+
+    // ─── Shutdown ──────────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn shutdown_with_stop_vms_deletes_every_vm() {
+        let (backend, tracker) = MockBackend::new();
+        let mut mgr = VmManager::new(backend, test_config());
+
+        let _ = send(&mut mgr, CommandPayload::Create(test_spec())).await;
+        let _ = send(&mut mgr, CommandPayload::Create(test_spec())).await;
+
+        let resp = send(&mut mgr, CommandPayload::Shutdown { stop_vms: true }).await;
+        assert!(matches!(resp, Ok(CommandResponse::Unit)));
+        assert!(mgr.is_stopped());
+        assert_eq!(tracker.kill_count(), 2);
+        assert_eq!(tracker.cleanup_count(), 2);
+
+        let resp = send(&mut mgr, CommandPayload::List).await;
+        match resp {
+            Ok(CommandResponse::VmList(list)) => assert!(list.is_empty()),
+            other => panic!("expected empty VmList, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_without_stop_vms_leaves_vms_running() {
+        let (backend, tracker) = MockBackend::new();
+        let mut mgr = VmManager::new(backend, test_config());
+
+        let _ = send(&mut mgr, CommandPayload::Create(test_spec())).await;
+
+        let resp = send(&mut mgr, CommandPayload::Shutdown { stop_vms: false }).await;
+        assert!(matches!(resp, Ok(CommandResponse::Unit)));
+        assert!(mgr.is_stopped());
+        assert_eq!(tracker.shutdown_count(), 0);
+        assert_eq!(tracker.kill_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn shutdown_without_stop_vms_rewrites_the_vm_records() {
+        let dir = state_dir("shutdown-flush");
+        let (backend, _tracker) = MockBackend::new();
+        let mut first = VmManager::new(backend, config_with_state(&dir));
+        let _ = send(&mut first, CommandPayload::Create(test_spec())).await;
+        // A record lost since boot is written again on the way out
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let _ = send(&mut first, CommandPayload::Shutdown { stop_vms: false }).await;
+        drop(first);
+
+        let (backend, _tracker) = MockBackend::new();
+        let mut second = VmManager::new(backend, config_with_state(&dir));
+        assert_eq!(second.adopt_running().await, 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // ─── Drain ─────────────────────────────────────────────────────────
+
+    fn draining_manager(
+        config: MockBackendConfig,
+        timeout: Duration,
+    ) -> (VmManager<MockBackend>, MockCallTracker) {
+        let (backend, tracker) = MockBackend::with_config(config);
+        let mgr = VmManager::new(backend, test_config())
+            .with_drain(Drain::new(timeout, DrainSignal::PowerButton));
+        (mgr, tracker)
+    }
+
+    #[tokio::test]
+    async fn deleted_vm_drains_until_its_guest_powers_off() {
+        let (mut mgr, tracker) =
+            draining_manager(MockBackendConfig::default(), Duration::from_secs(30));
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+
+        send(&mut mgr, CommandPayload::Delete(id.clone()))
+            .await
+            .unwrap();
+        assert_eq!(tracker.power_button_count(), 1);
+        assert_eq!(tracker.kill_count(), 0, "a draining VM is not killed yet");
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Draining);
+
+        send(&mut mgr, CommandPayload::Delete(id.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            tracker.power_button_count(),
+            1,
+            "draining VMs are signalled once"
+        );
+        assert!(
+            send(&mut mgr, action(&id, VmAction::Restart))
+                .await
+                .is_err()
+        );
+
+        send(&mut mgr, CommandPayload::CheckDrains).await.unwrap();
+        match send(&mut mgr, CommandPayload::List).await {
+            Ok(CommandResponse::VmList(list)) => assert!(list.is_empty()),
+            other => panic!("expected empty VmList, got {other:?}"),
+        }
+        // The VMM exited with its guest: only reaped and cleaned up
+        assert_eq!(tracker.shutdown_count(), 0);
+        assert_eq!(tracker.delete_count(), 0);
+        assert_eq!(tracker.kill_count(), 1);
+        assert_eq!(tracker.cleanup_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn guest_ignoring_the_power_button_is_stopped_at_the_timeout() {
+        let config = MockBackendConfig {
+            ignore_power_button: true,
+            ..Default::default()
+        };
+        let (mut mgr, tracker) = draining_manager(config, Duration::from_millis(50));
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+
+        send(&mut mgr, CommandPayload::Delete(id.clone()))
+            .await
+            .unwrap();
+        send(&mut mgr, CommandPayload::CheckDrains).await.unwrap();
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Draining);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        send(&mut mgr, CommandPayload::CheckDrains).await.unwrap();
+        assert!(send(&mut mgr, CommandPayload::Delete(id)).await.is_err());
+        assert_eq!(tracker.shutdown_count(), 1);
+        assert_eq!(tracker.delete_count(), 1);
+        assert_eq!(tracker.kill_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn vm_whose_guest_cannot_be_signalled_is_stopped_at_once() {
+        let config = MockBackendConfig {
+            power_button_error: Some("no ACPI".to_string()),
+            ..Default::default()
+        };
+        let (mut mgr, tracker) = draining_manager(config, Duration::from_secs(30));
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+
+        send(&mut mgr, CommandPayload::Delete(id)).await.unwrap();
+        assert_eq!(tracker.power_button_count(), 1);
+        assert_eq!(tracker.kill_count(), 1);
+        match send(&mut mgr, CommandPayload::List).await {
+            Ok(CommandResponse::VmList(list)) => assert!(list.is_empty()),
+            other => panic!("expected empty VmList, got {other:?}"),
+        }
+    }
+
+    // ─── VMM monitor ───────────────────────────────────────────────────
+
+    fn monitored_manager(config: MockBackendConfig) -> (VmManager<MockBackend>, MockCallTracker) {
+        let (backend, tracker) = MockBackend::with_config(config);
+        let monitor = VmmMonitor::new(Duration::from_secs(10), Duration::from_millis(100), 3);
+        let mgr = VmManager::new(backend, test_config()).with_vmm_monitor(monitor);
+        (mgr, tracker)
+    }
+
+    /// Reason of the latest transition of VM `vm_id`.
+    async fn last_reason(manager: &mut VmManager<MockBackend>, vm_id: &str) -> String {
+        match send(manager, CommandPayload::List).await {
+            Ok(CommandResponse::VmList(list)) => {
+                let info = list.iter().find(|info| info.id() == vm_id).unwrap();
+                info.transitions().last().unwrap().reason.clone()
+            }
+            other => panic!("expected VmList, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn exited_vmm_leaves_the_vm_crashed_until_restarted() {
+        let (mut mgr, tracker) = monitored_manager(MockBackendConfig::default());
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+        send(&mut mgr, CommandPayload::CheckVmms).await.unwrap();
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Running);
+
+        tracker.crash_vmms();
+        send(&mut mgr, CommandPayload::CheckVmms).await.unwrap();
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Crashed);
+        assert!(
+            last_reason(&mut mgr, &id)
+                .await
+                .starts_with("VMM process exited")
+        );
+        assert_eq!(tracker.kill_count(), 1);
+        assert_eq!(tracker.cleanup_count(), 1);
+
+        send(&mut mgr, action(&id, VmAction::Stop)).await.unwrap();
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Crashed);
+
+        send(&mut mgr, action(&id, VmAction::Restart))
+            .await
+            .unwrap();
+        assert_eq!(tracker.spawn_count(), 2, "restart deploys it again");
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Running);
+        assert_eq!(
+            tracker.shutdown_count(),
+            0,
+            "a crashed VM leaves no VMM to shut down"
+        );
+        assert_eq!(tracker.kill_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn unanswering_vmm_is_killed_after_enough_checks() {
+        let config = MockBackendConfig {
+            info_error: Some("connection refused".to_string()),
+            ..Default::default()
+        };
+        let (mut mgr, tracker) = monitored_manager(config);
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+
+        for _ in 0..2 {
+            send(&mut mgr, CommandPayload::CheckVmms).await.unwrap();
+        }
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Running);
+        assert_eq!(tracker.kill_count(), 0);
+
+        send(&mut mgr, CommandPayload::CheckVmms).await.unwrap();
+        assert_eq!(tracker.info_count(), 3);
+        assert_eq!(tracker.kill_count(), 1);
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Crashed);
+        assert_eq!(
+            last_reason(&mut mgr, &id).await,
+            "VMM unanswered for 3 checks"
+        );
+
+        send(&mut mgr, CommandPayload::CheckVmms).await.unwrap();
+        assert_eq!(tracker.info_count(), 3, "crashed VMs are not checked");
+        send(&mut mgr, CommandPayload::Delete(id)).await.unwrap();
+        assert_eq!(tracker.kill_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn guest_shutting_down_by_itself_stops_the_vm() {
+        let config = MockBackendConfig {
+            guest_state: Some(GuestState::Shutdown),
+            ..Default::default()
+        };
+        let (mut mgr, tracker) = monitored_manager(config);
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+
+        send(&mut mgr, CommandPayload::CheckVmms).await.unwrap();
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Stopped);
+        assert_eq!(last_reason(&mut mgr, &id).await, "guest shut down");
+        assert_eq!(tracker.kill_count(), 0);
+    }
+
+    // ─── Boot watchdog ─────────────────────────────────────────────────
+
+    /// A manager whose mock VMs log to `<dir>/<vm_id>.log`, watched by
+    /// `watchdog`.
+    fn watched_manager(
+        dir: &std::path::Path,
+        watchdog: BootWatchdog,
+    ) -> (VmManager<MockBackend>, MockCallTracker) {
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let (backend, tracker) = MockBackend::with_config(MockBackendConfig {
+            serial_log_dir: Some(dir.to_path_buf()),
+            ..MockBackendConfig::default()
+        });
+        let manager = VmManager::new(backend, test_config()).with_boot_watchdog(watchdog);
+        (manager, tracker)
+    }
+
+    #[tokio::test]
+    async fn booting_vm_runs_once_its_serial_log_shows_ready() {
+        let dir = std::env::temp_dir().join(format!("worker-boot-ready-{}", std::process::id()));
+        let watchdog = BootWatchdog::new(Duration::from_secs(60), "Multi-User System");
+        let (mut mgr, _tracker) = watched_manager(&dir, watchdog);
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Booting);
+
+        let log = dir.join(format!("{id}.log"));
+        std::fs::write(&log, "[  OK  ] Reached target Basic System.\n").unwrap();
+        send(&mut mgr, CommandPayload::CheckBoots).await.unwrap();
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Booting);
+
+        std::fs::write(&log, "[  OK  ] Reached target Multi-User System.\n").unwrap();
+        send(&mut mgr, CommandPayload::CheckBoots).await.unwrap();
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Running);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stuck_boot_is_killed_and_restarted_until_the_policy_gives_up() {
+        let dir = std::env::temp_dir().join(format!("worker-boot-stuck-{}", std::process::id()));
+        let watchdog = BootWatchdog::new(Duration::ZERO, "Multi-User System")
+            .with_restart(RestartPolicy::OnFailure, 1);
+        let (mut mgr, tracker) = watched_manager(&dir, watchdog);
+        let Ok(CommandResponse::VmId(id)) =
+            send(&mut mgr, CommandPayload::Create(test_spec())).await
+        else {
+            panic!("create failed");
+        };
+        std::fs::write(
+            dir.join(format!("{id}.log")),
+            "Starting initrd\nwaiting for /dev/vda\n",
+        )
+        .unwrap();
+
+        send(&mut mgr, CommandPayload::CheckBoots).await.unwrap();
+        assert_eq!(tracker.kill_count(), 1, "the stuck attempt is killed");
+        assert_eq!(tracker.spawn_count(), 2, "and restarted under the same id");
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Booting);
+
+        send(&mut mgr, CommandPayload::CheckBoots).await.unwrap();
+        assert_eq!(tracker.kill_count(), 2);
+        assert_eq!(tracker.spawn_count(), 2, "out of restarts");
+        match send(&mut mgr, CommandPayload::List).await {
+            Ok(CommandResponse::VmList(list)) => {
+                assert_eq!(list[0].status(), VmState::BootFailed);
+                assert_eq!(
+                    list[0].boot_log(),
+                    Some("Starting initrd\nwaiting for /dev/vda")
+                );
+                let failed = list[0].transitions().last().unwrap();
+                assert_eq!((failed.from, failed.to), (VmState::Booting, VmState::BootFailed));
+                assert_eq!(failed.reason, "not ready within the boot timeout");
+                assert_eq!(list[0].transitions().len(), 5, "the restart is kept");
+            }
+            other => panic!("expected VmList, got {other:?}"),
+        }
+
+        send(&mut mgr, action(&id, VmAction::Restart)).await.unwrap();
+        assert_eq!(tracker.spawn_count(), 3, "restart deploys it again");
+        assert_eq!(status_of(&mut mgr, &id).await, VmState::Booting);
+
+        send(&mut mgr, CommandPayload::CheckBoots).await.unwrap();
+        assert_eq!(
+            status_of(&mut mgr, &id).await,
+            VmState::Booting,
+            "restarted by the policy"
+        );
+        send(&mut mgr, CommandPayload::CheckBoots).await.unwrap();
+        send(&mut mgr, CommandPayload::Delete(id)).await.unwrap();
+        assert_eq!(tracker.kill_count(), 4);
+        assert_eq!(
+            tracker.shutdown_count(),
+            0,
+            "a failed boot leaves no VMM to shut down"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // ─── Adoption after restart ────────────────────────────────────────
+
+    /// A fresh directory for VM records.
+    fn state_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("worker-vm-records-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config_with_state(dir: &std::path::Path) -> VmManagerConfig {
+        VmManagerConfig {
+            state_dir: Some(dir.to_path_buf()),
+            ..test_config()
+        }
+    }
+
+    #[tokio::test]
+    async fn restarted_manager_adopts_vms_left_running() {
+        let dir = state_dir("adopt");
+        let (backend, _tracker) = MockBackend::new();
+        let mut first = VmManager::new(backend, config_with_state(&dir));
+        let id = match send(&mut first, CommandPayload::Create(test_spec())).await {
+            Ok(CommandResponse::VmId(id)) => id,
+            other => panic!("expected VmId, got {other:?}"),
+        };
+        let _ = send(&mut first, CommandPayload::Shutdown { stop_vms: false }).await;
+        drop(first);
+
+        let (backend, tracker) = MockBackend::new();
+        let mut second = VmManager::new(backend, config_with_state(&dir));
+        assert_eq!(second.adopt_running().await, 1);
+        assert_eq!(tracker.adopt_count(), 1);
+        assert_eq!(
+            tracker.spawn_count(),
+            0,
+            "an adopted VM is not started again"
+        );
+
+        match send(&mut second, CommandPayload::List).await {
+            Ok(CommandResponse::VmList(list)) => {
+                assert_eq!(list.len(), 1);
+                assert_eq!(list[0].id(), id);
+                assert_eq!(
+                    list[0].observed_hash(),
+                    test_spec().content_hash().to_string()
+                );
+                assert_eq!(list[0].desired_hash(), list[0].observed_hash());
+            }
+            other => panic!("expected VmList, got {other:?}"),
+        }
+
+        // Deleting it drops the record, so nothing is adopted next time
+        let resp = send(&mut second, CommandPayload::Delete(id)).await;
+        assert!(matches!(resp, Ok(CommandResponse::Unit)));
+        let (backend, _tracker) = MockBackend::new();
+        let mut third = VmManager::new(backend, config_with_state(&dir));
+        assert_eq!(third.adopt_running().await, 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn vms_that_did_not_survive_are_forgotten() {
+        let dir = state_dir("gone");
+        let (backend, _tracker) = MockBackend::new();
+        let mut first = VmManager::new(backend, config_with_state(&dir));
+        let _ = send(&mut first, CommandPayload::Create(test_spec())).await;
+        drop(first);
+
+        let (backend, tracker) = MockBackend::with_config(MockBackendConfig {
+            adopt_error: Some("process gone".to_string()),
+            ..MockBackendConfig::default()
+        });
+        let mut second = VmManager::new(backend, config_with_state(&dir));
+        assert_eq!(second.adopt_running().await, 0);
+        assert_eq!(tracker.adopt_count(), 1);
+        assert_eq!(
+            tracker.cleanup_count(),
+            1,
+            "the backend released what was left"
+        );
+        assert_eq!(
+            std::fs::read_dir(&dir).unwrap().count(),
+            0,
+            "record dropped"
+        );
+
+        match send(&mut second, CommandPayload::List).await {
+            Ok(CommandResponse::VmList(list)) => assert!(list.is_empty()),
+            other => panic!("expected VmList, got {other:?}"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
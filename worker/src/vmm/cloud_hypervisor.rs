@@ -5,12 +5,28 @@
 //! - [`CloudHypervisor`] — per-VM REST client (implements [`Vmm`]).
 //! - [`ChProcess`] — handle to one `cloud-hypervisor` OS process (implements [`VmmProcess`]).
 //! - [`CloudHypervisorBackend`] — factory that spawns CH processes (implements [`VmmBackend`]).
+//!
+//! A VM boots either from a writable copy of its disk image or, in
+//! [`BootMode::Closure`], straight from its NixOS toplevel: the kernel,
+//! initrd and `init` of the closure, with the host `/nix/store` shared
+//! read-only over virtio-fs by a per-VM `virtiofsd`. With a staging
+//! directory, disk copies are reflinked from images [staged](staging) on
+//! fast local storage instead of copied from the store.
+//!
+//! A VM's NIC is a TAP device on the host bridge, or with
+//! [`NetBackend::VhostUser`] a vhost-user-net device on a socket shared
+//! with a host switch (OVS-DPDK, passt), see [`VhostUserNet`].
+//!
+//! With [`Hotplug`] room, VMs boot able to grow past their spec: a spec
+//! change that only touches vCPUs and memory is applied with `vm.resize`,
+//! vCPUs through ACPI and memory through virtio-mem, without a reboot.
 
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use std::time::Duration;
 
+use commands::net_backend::NetBackend;
 use hyperlocal::{UnixClientExt, Uri as UnixUri};
 use serde::{Deserialize, Serialize};
 use tokio::process::{Child, Command};
@@ -18,8 +34,12 @@ use tracing::{debug, info, warn};
 use futures::stream::TryStreamExt;
 use rtnetlink;
 
-use crate::dto::{VmError, VmSpec};
-use crate::vmm::{Vmm, VmmBackend, VmmProcess};
+use crate::disk_usage::{self, VmDiskUsage};
+use crate::dto::{BootMode, VmError, VmSpec};
+use crate::hugepages::{self, HugePages};
+use crate::metrics;
+use crate::vmm::staging::{self, ImageStage, StagedImage};
+use crate::vmm::{GuestState, ProcessRecord, Vmm, VmmBackend, VmmProcess, trust};
 
 // ─── Per-VM REST client ───────────────────────────────────────────────────
 
@@ -193,6 +213,94 @@ impl Vmm for CloudHypervisor {
         Ok(())
     }
 
+    async fn power_button(&self) -> Result<(), Self::Error> {
+        let uri = self.build_uri("/api/v1/vm.power-button");
+        let req = hyper::Request::builder()
+            .method(hyper::Method::PUT)
+            .uri(uri)
+            .body(hyper::Body::empty())
+            .map_err(|e| Error::Communication(e.to_string()))?;
+
+        let resp = self
+            .client
+            .request(req)
+            .await
+            .map_err(|e| Error::Communication(e.to_string()))?;
+
+        if !resp.status().is_success() {
+            let body_bytes = hyper::body::to_bytes(resp.into_body())
+                .await
+                .map_err(|e| Error::Communication(e.to_string()))?;
+            let error_msg = String::from_utf8_lossy(&body_bytes);
+            return Err(Error::OperationFailed(format!(
+                "Failed to press the power button: {error_msg}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn reboot(&self) -> Result<(), Self::Error> {
+        let uri = self.build_uri("/api/v1/vm.reboot");
+        let req = hyper::Request::builder()
+            .method(hyper::Method::PUT)
+            .uri(uri)
+            .body(hyper::Body::empty())
+            .map_err(|e| Error::Communication(e.to_string()))?;
+
+        let resp = self
+            .client
+            .request(req)
+            .await
+            .map_err(|e| Error::Communication(e.to_string()))?;
+
+        if !resp.status().is_success() {
+            let body_bytes = hyper::body::to_bytes(resp.into_body())
+                .await
+                .map_err(|e| Error::Communication(e.to_string()))?;
+            let error_msg = String::from_utf8_lossy(&body_bytes);
+            return Err(Error::OperationFailed(format!(
+                "Failed to reboot VM: {error_msg}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn resize(&self, cpu: u32, memory_mb: u32) -> Result<(), Self::Error> {
+        let body = serde_json::to_string(&ChVmResize {
+            desired_vcpus: u8::try_from(cpu).unwrap_or(u8::MAX),
+            desired_ram: u64::from(memory_mb) * MIB,
+        })?;
+        debug!(resize_json = %body, "vm.resize request");
+
+        let uri = self.build_uri("/api/v1/vm.resize");
+        let req = hyper::Request::builder()
+            .method(hyper::Method::PUT)
+            .uri(uri)
+            .header("Content-Type", "application/json")
+            .body(hyper::Body::from(body))
+            .map_err(|e| Error::Communication(e.to_string()))?;
+
+        let resp = self
+            .client
+            .request(req)
+            .await
+            .map_err(|e| Error::Communication(e.to_string()))?;
+
+        if !resp.status().is_success() {
+            let body_bytes = hyper::body::to_bytes(resp.into_body())
+                .await
+                .map_err(|e| Error::Communication(e.to_string()))?;
+            let error_msg = String::from_utf8_lossy(&body_bytes);
+            return Err(Error::OperationFailed(format!(
+                "Failed to resize VM: {error_msg}"
+            )));
+        }
+
+        Ok(())
+    }
+
     async fn delete(&self) -> Result<(), Self::Error> {
         let uri = self.build_uri("/api/v1/vm.delete");
         let req = hyper::Request::builder()
@@ -221,6 +329,34 @@ impl Vmm for CloudHypervisor {
         Ok(())
     }
 
+    async fn info(&self) -> Result<GuestState, Self::Error> {
+        let uri = self.build_uri("/api/v1/vm.info");
+        let req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(uri)
+            .body(hyper::Body::empty())
+            .map_err(|e| Error::Communication(e.to_string()))?;
+
+        let resp = self
+            .client
+            .request(req)
+            .await
+            .map_err(|e| Error::Communication(e.to_string()))?;
+
+        let status = resp.status();
+        let body_bytes = hyper::body::to_bytes(resp.into_body())
+            .await
+            .map_err(|e| Error::Communication(e.to_string()))?;
+        if !status.is_success() {
+            let error_msg = String::from_utf8_lossy(&body_bytes);
+            return Err(Error::OperationFailed(format!(
+                "Failed to get VM info: {error_msg}"
+            )));
+        }
+
+        let info: ChVmInfo = serde_json::from_slice(&body_bytes)?;
+        Ok(info.state)
+    }
 }
 
 // Cloud Hypervisor API data structures — all owned, no lifetimes.
@@ -235,6 +371,8 @@ pub struct ChVmConfig {
     pub payload: Option<ChPayloadConfig>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub disks: Vec<ChDiskConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub fs: Vec<ChFsConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub net: Option<Vec<ChNetConfig>>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -254,6 +392,32 @@ pub struct ChCpusConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChMemoryConfig {
     pub size: u64,
+    /// Required by vhost-user devices such as virtio-fs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shared: Option<bool>,
+    /// Back the memory with hugepages of the default size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hugepages: Option<bool>,
+    /// `VirtioMem` when the VM may grow past `size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hotplug_method: Option<String>,
+    /// Memory that can be plugged on top of `size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hotplug_size: Option<u64>,
+}
+
+/// The part of the `vm.info` reply the worker reads.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChVmInfo {
+    pub state: GuestState,
+}
+
+/// Body of `vm.resize`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChVmResize {
+    pub desired_vcpus: u8,
+    /// In bytes
+    pub desired_ram: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -274,6 +438,14 @@ pub struct ChDiskConfig {
     pub direct: Option<bool>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChFsConfig {
+    pub tag: String,
+    pub socket: String,
+    pub num_queues: usize,
+    pub queue_size: u16,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChNetConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -284,6 +456,14 @@ pub struct ChNetConfig {
     pub mask: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mac: Option<String>,
+    /// The device is vhost-user-net on `vhost_socket` instead of a TAP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vhost_user: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vhost_socket: Option<String>,
+    /// `Client` or `Server`: whether CH connects to the socket or listens on it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vhost_mode: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -307,35 +487,96 @@ pub struct ChSerialConfig {
 
 /// Handle to one `cloud-hypervisor` OS process.
 ///
-/// Owns the [`Child`], the socket path, and the per-VM working directory.
-/// Cleans up all three on [`VmmProcess::cleanup`].
+/// Owns the [`Child`], the socket path, and the per-VM directories.
+/// Cleans up all of them on [`VmmProcess::cleanup`].
+///
+/// A process adopted from a previous worker has no [`Child`]; it is
+/// signalled and watched through its pid instead.
 pub struct ChProcess {
-    child: Child,
+    /// `None` once detached, or when adopted
+    child: Option<Child>,
+    pid: u32,
     socket_path: PathBuf,
-    /// Per-VM working directory (contains writable disk copy, serial log, etc.)
-    vm_dir: PathBuf,
+    /// Per-VM image directory (writable disk copy)
+    image_dir: PathBuf,
+    /// Per-VM log directory (serial log, CH log); may be `image_dir`
+    log_dir: PathBuf,
     /// TAP device name owned by this VM. Deleted on cleanup via netlink.
     /// `None` when the VM was started without networking.
     tap_name: Option<String>,
+    /// `virtiofsd` sharing the store with a closure-booted VM
+    store_share: Option<StoreShare>,
+    /// Staged image the disk copy was made from, kept while the VM exists
+    staged_image: Option<StagedImage>,
+}
+
+/// The `virtiofsd` process serving `/nix/store` to one closure-booted VM.
+/// Like [`ChProcess`], it has no [`Child`] when adopted.
+struct StoreShare {
+    child: Option<Child>,
+    pid: u32,
+    socket_path: PathBuf,
+}
+
+impl StoreShare {
+    /// Stop the daemon and remove its socket, best-effort.
+    async fn stop(&mut self) {
+        let stopped = match &mut self.child {
+            Some(child) => child.kill().await.map_err(|e| e.to_string()),
+            // Another process may have reused the pid since adoption
+            None if !runs_with(self.pid, "--socket-path", &self.socket_path) => Ok(()),
+            None => kill_pid(self.pid).map_err(|e| e.to_string()),
+        };
+        if let Err(e) = stopped {
+            warn!(pid = self.pid, error = %e, "Failed to stop virtiofsd");
+        }
+        if self.socket_path.exists() {
+            let _ = tokio::fs::remove_file(&self.socket_path).await;
+        }
+    }
+}
+
+/// Socket of the store share next to the VM's API socket,
+/// `<vm_id>.store.sock` for `<vm_id>.sock`.
+fn store_socket_path(api_socket: &Path) -> PathBuf {
+    api_socket.with_extension("store.sock")
 }
 
 impl VmmProcess for ChProcess {
     async fn kill(&mut self) -> Result<(), VmError> {
-        self.child
+        let Some(child) = &mut self.child else {
+            // Another process may have reused the pid since adoption
+            if !is_ch_process(self.pid, &self.socket_path) {
+                return Ok(());
+            }
+            return kill_pid(self.pid);
+        };
+        child
             .kill()
             .await
             .map_err(|e| VmError::ProcessFailed(format!("Failed to kill CH process: {e}")))
     }
 
     fn try_wait(&mut self) -> Result<Option<std::process::ExitStatus>, VmError> {
-        self.child
+        let Some(child) = &mut self.child else {
+            // Not our child: whether it runs is known, its exit status is not
+            return if is_ch_process(self.pid, &self.socket_path) {
+                Ok(None)
+            } else {
+                Err(VmError::ProcessFailed(format!(
+                    "CH process {} exited, status unknown",
+                    self.pid
+                )))
+            };
+        };
+        child
             .try_wait()
             .map_err(|e| VmError::ProcessFailed(format!("Failed to check CH process: {e}")))
     }
 
     async fn cleanup(&mut self) -> Result<(), VmError> {
         // Log CH output for post-mortem debugging before cleaning up.
-        let ch_log = self.vm_dir.join("cloud-hypervisor.log");
+        let ch_log = self.log_dir.join("cloud-hypervisor.log");
         if ch_log.exists() {
             match tokio::fs::read_to_string(&ch_log).await {
                 Ok(contents) if !contents.is_empty() => {
@@ -363,15 +604,94 @@ impl VmmProcess for ChProcess {
             }
         }
 
+        if let Some(share) = &mut self.store_share {
+            share.stop().await;
+        }
+
         if self.socket_path.exists() {
             let _ = tokio::fs::remove_file(&self.socket_path).await;
         }
-        // Remove the entire per-VM working directory (writable disk, serial log, etc.)
-        if self.vm_dir.exists() {
-            let _ = tokio::fs::remove_dir_all(&self.vm_dir).await;
+        // Remove the per-VM directories (writable disk, serial log, etc.)
+        for dir in [&self.image_dir, &self.log_dir] {
+            if dir.exists() {
+                let _ = tokio::fs::remove_dir_all(dir).await;
+            }
         }
+        // Removes the staged image too when no other VM uses it
+        self.staged_image = None;
         Ok(())
     }
+
+    fn record(&self) -> Option<ProcessRecord> {
+        Some(ProcessRecord {
+            pid: self.pid,
+            api_socket: self.socket_path.clone(),
+            tap: self.tap_name.clone(),
+            image_dir: self.image_dir.clone(),
+            log_dir: self.log_dir.clone(),
+            store_share_pid: self.store_share.as_ref().map(|share| share.pid),
+        })
+    }
+
+    fn detach(&mut self) {
+        // The child was spawned with kill_on_drop; forgetting the handle
+        // is the only way to drop it without stopping the VM.
+        if let Some(child) = self.child.take() {
+            std::mem::forget(child);
+        }
+        if let Some(child) = self
+            .store_share
+            .as_mut()
+            .and_then(|share| share.child.take())
+        {
+            std::mem::forget(child);
+        }
+        // The next worker adopts the VM and leases the staged image again
+        if let Some(staged_image) = self.staged_image.take() {
+            std::mem::forget(staged_image);
+        }
+    }
+}
+
+/// SIGKILL a process that is not our child. Gone already is fine.
+fn kill_pid(pid: u32) -> Result<(), VmError> {
+    let pid = libc::pid_t::try_from(pid)
+        .map_err(|_| VmError::ProcessFailed(format!("invalid pid {pid}")))?;
+    // SAFETY: kill(2) has no memory-safety preconditions
+    if unsafe { libc::kill(pid, libc::SIGKILL) } == 0 {
+        return Ok(());
+    }
+    match std::io::Error::last_os_error() {
+        e if e.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+        e => Err(VmError::ProcessFailed(format!(
+            "Failed to kill CH process {pid}: {e}"
+        ))),
+    }
+}
+
+/// Whether `pid` is a process started with `--api-socket <socket>`, rather
+/// than an unrelated process that reused the pid.
+fn is_ch_process(pid: u32, socket: &Path) -> bool {
+    runs_with(pid, "--api-socket", socket)
+}
+
+/// Whether `pid` is a running process with `<flag> <path>` on its command
+/// line.
+fn runs_with(pid: u32, flag: &str, path: &Path) -> bool {
+    let Ok(cmdline) = std::fs::read(format!("/proc/{pid}/cmdline")) else {
+        return false;
+    };
+    let path = path.as_os_str().as_encoded_bytes();
+    let mut args = cmdline.split(|b| *b == 0);
+    args.any(|arg| arg == flag.as_bytes()) && args.next() == Some(path)
+}
+
+/// TAP device of VM `vm_id`. Linux limits interface names to 15 chars:
+/// "pcr-" prefix (4) + first 11 chars of the UUID (enough to avoid
+/// collisions).
+#[must_use]
+pub fn tap_name(vm_id: &str) -> String {
+    format!("pcr-{}", vm_id.get(..11).unwrap_or(vm_id))
 }
 
 /// Delete a TAP device by name via netlink.
@@ -513,12 +833,38 @@ fn create_tap_ioctl(tap_name: &str) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+// ─── Closure boot ─────────────────────────────────────────────────────────
+
+/// virtio-fs tag the guest mounts as `/nix/store`.
+pub const STORE_SHARE_TAG: &str = "nix-store";
+
+/// The toplevel of `spec` without a trailing slash.
+fn closure_dir(spec: &VmSpec) -> &str {
+    spec.toplevel().trim_end_matches('/')
+}
+
+/// Kernel command line of a closure boot: the toplevel's own
+/// `kernel-params`, its `init`, then the spec's cmdline, like a NixOS
+/// bootloader entry with extra arguments.
+fn closure_cmdline(spec: &VmSpec, kernel_params: &str) -> String {
+    let init = format!("init={}/init", closure_dir(spec));
+    [kernel_params.trim(), init.as_str(), spec.cmdline().trim()]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 // ─── Backend factory ──────────────────────────────────────────────────────
 
 /// Configuration for [`CloudHypervisorBackend`].
 pub struct CloudHypervisorConfig {
     /// Directory where VM sockets are created (e.g. `/tmp/procurator/vms/`)
     pub socket_dir: PathBuf,
+    /// Directory holding a `<vm_id>/disk.img` writable copy per VM
+    pub image_dir: PathBuf,
+    /// Directory holding the `<vm_id>/` serial and CH logs per VM
+    pub log_dir: PathBuf,
     /// Path to the `cloud-hypervisor` binary
     pub ch_binary: PathBuf,
     /// How long to wait for a CH socket to appear after spawning
@@ -526,15 +872,92 @@ pub struct CloudHypervisorConfig {
     /// Name of the host bridge to attach VM TAP devices to (e.g. `chbr0`).
     /// Set to `None` to skip TAP-to-bridge attachment (VMs get no network).
     pub bridge_name: Option<String>,
+    /// Keys (`name:base64`) the image closure must be signed with before
+    /// a VM boots. Empty disables verification.
+    pub trusted_public_keys: Vec<String>,
+    /// Path to the `virtiofsd` binary sharing the store with VMs booted
+    /// from a closure. `None` refuses closure boots.
+    pub virtiofsd_binary: Option<PathBuf>,
+    /// Fast local disk to stage store images on before they are copied
+    /// to `image_dir`. `None` copies straight from the store.
+    pub staging_dir: Option<PathBuf>,
+    /// Host switch vhost-user NICs connect to. `None` refuses VMs asking
+    /// for [`NetBackend::VhostUser`].
+    pub vhost_user_net: Option<VhostUserNet>,
+    /// Room VMs boot with to be resized in place. `None` redeploys VMs
+    /// on every spec change.
+    pub hotplug: Option<Hotplug>,
+}
+
+/// How far booted VMs can be resized without a redeploy.
+///
+/// Memory is plugged on top of what the VM booted with, in 128 MiB
+/// steps, and cannot be unplugged below it.
+/// Hugepage-backed VMs are never resized: their share of the pool is
+/// checked at boot.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Hotplug {
+    /// Most vCPUs a VM can be given in place
+    pub max_vcpus: u8,
+    /// Most memory a VM can be given in place
+    pub max_memory_mb: u32,
+}
+
+const MIB: u64 = 1024 * 1024;
+
+/// Granularity of virtio-mem hotplug.
+const VIRTIO_MEM_BLOCK: u64 = 128 * MIB;
+
+impl Hotplug {
+    /// Memory VM `spec` can have plugged after boot, `None` when none fits.
+    fn memory_room(&self, spec: &VmSpec) -> Option<u64> {
+        let room = u64::from(self.max_memory_mb.saturating_sub(spec.memory_mb())) * MIB;
+        Some(room - room % VIRTIO_MEM_BLOCK).filter(|room| *room > 0)
+    }
+}
+
+/// Sockets of vhost-user NICs, one `<socket_dir>/<vm_id>.sock` per VM.
+///
+/// The switch side is the host's business: an OVS-DPDK bridge with
+/// `dpdkvhostuserclient` ports, or a `passt --vhost-user` per VM.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct VhostUserNet {
+    pub socket_dir: PathBuf,
+    #[serde(default)]
+    pub mode: VhostUserMode,
+}
+
+/// Which side of a vhost-user socket creates it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VhostUserMode {
+    /// CH listens and the switch connects, as OVS-DPDK client ports do
+    #[default]
+    Server,
+    /// The switch listens, the socket must exist before the VM starts
+    Client,
+}
+
+impl VhostUserNet {
+    fn socket(&self, vm_id: &str) -> PathBuf {
+        self.socket_dir.join(format!("{vm_id}.sock"))
+    }
 }
 
 impl Default for CloudHypervisorConfig {
     fn default() -> Self {
         Self {
             socket_dir: PathBuf::from("/tmp/procurator/vms"),
+            image_dir: PathBuf::from("/tmp/procurator/vms"),
+            log_dir: PathBuf::from("/tmp/procurator/vms"),
             ch_binary: PathBuf::from("cloud-hypervisor"),
             socket_timeout: Duration::from_secs(5),
             bridge_name: Some("chbr0".to_string()),
+            trusted_public_keys: Vec::new(),
+            virtiofsd_binary: Some(PathBuf::from("virtiofsd")),
+            staging_dir: None,
+            vhost_user_net: None,
+            hotplug: None,
         }
     }
 }
@@ -543,12 +966,21 @@ impl Default for CloudHypervisorConfig {
 ///
 /// Tracks the writable paths that replace the immutable Nix store paths.
 struct PreparedVm {
-    /// Writable copy of the disk image (the Nix store original is read-only)
-    writable_disk_path: PathBuf,
+    boot_mode: BootMode,
+    /// Writable copy of the disk image (the Nix store original is read-only).
+    /// `None` for a closure boot, which has no disk.
+    writable_disk_path: Option<PathBuf>,
+    /// Staged image the disk copy was made from, handed to the process
+    staged_image: Option<StagedImage>,
+    /// Kernel command line; a closure boot adds the toplevel's
+    /// `kernel-params` and `init=` to the spec's
+    cmdline: String,
     /// Path where CH will write serial console output
     serial_log_path: PathBuf,
-    /// Per-VM working directory (parent of disk + serial log)
-    vm_dir: PathBuf,
+    /// Per-VM image directory (parent of the disk copy)
+    image_dir: PathBuf,
+    /// Per-VM log directory (parent of the serial and CH logs)
+    log_dir: PathBuf,
     /// TAP device name for this VM's virtio-net interface.
     /// CH creates the TAP at `vm.create()` time; we attach it to the
     /// bridge between `create()` and `boot()`.
@@ -573,13 +1005,21 @@ pub struct CloudHypervisorBackend {
     /// Per-VM prepared state, keyed by vm_id.
     /// Populated by `prepare()`, consumed by `build_config()` and `spawn()`.
     prepared: Mutex<HashMap<String, PreparedVm>>,
+    /// Closure size per image toplevel. Store paths never change, so a
+    /// measured closure stays valid.
+    closures: Mutex<HashMap<String, u64>>,
+    /// Images staged on fast storage, when `staging_dir` is set
+    staging: Option<ImageStage>,
 }
 
 impl CloudHypervisorBackend {
     pub fn new(config: CloudHypervisorConfig) -> Self {
+        let staging = config.staging_dir.as_deref().map(ImageStage::new);
         Self {
             config,
             prepared: Mutex::new(HashMap::new()),
+            closures: Mutex::new(HashMap::new()),
+            staging,
         }
     }
 
@@ -722,7 +1162,203 @@ impl CloudHypervisorBackend {
         Ok(())
     }
 
+    /// Copy the disk image of `spec` to `<image_dir>/disk.img` and make
+    /// it writable. With staging, the copy is made from the staged image,
+    /// returned along with it.
+    async fn copy_disk_image(
+        &self,
+        vm_id: &str,
+        spec: &VmSpec,
+        image_dir: &Path,
+    ) -> Result<(PathBuf, Option<StagedImage>), VmError> {
+        let writable_disk_path = image_dir.join("disk.img");
+        let src = spec.disk_image_path();
+        let staged_image = self.staging.as_ref().and_then(|stage| stage.lease(src));
+        let copy_failed = |from: &dyn std::fmt::Display, e: std::io::Error| {
+            VmError::Internal(format!(
+                "Failed to copy disk image from {from} to {}: {e}",
+                writable_disk_path.display()
+            ))
+        };
+        if let Some(staged) = &staged_image {
+            let hit = staged.stage(src).await?;
+            let staged_path = staged.path();
+            let method = staging::clone_or_copy(&staged_path, &writable_disk_path)
+                .await
+                .map_err(|e| copy_failed(&staged_path.display(), e))?;
+            metrics::disk_staged(hit, method.as_str());
+            tracing::info!(
+                vm_id = %vm_id,
+                src = %staged_path.display(),
+                dst = %writable_disk_path.display(),
+                already_staged = hit,
+                method = method.as_str(),
+                "Disk image cloned from staging"
+            );
+        } else {
+            tracing::info!(
+                vm_id = %vm_id,
+                src = %src,
+                dst = %writable_disk_path.display(),
+                "Copying disk image to writable location"
+            );
+            tokio::fs::copy(src, &writable_disk_path)
+                .await
+                .map_err(|e| copy_failed(&src, e))?;
+        }
+
+        // Make the copy writable — Nix store originals are read-only (0444),
+        // and tokio::fs::copy preserves permissions. CH needs rw access.
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let perms = std::fs::Permissions::from_mode(0o644);
+            tokio::fs::set_permissions(&writable_disk_path, perms)
+                .await
+                .map_err(|e| {
+                    VmError::Internal(format!(
+                        "Failed to set writable permissions on {}: {e}",
+                        writable_disk_path.display()
+                    ))
+                })?;
+        }
+        Ok((writable_disk_path, staged_image))
+    }
+
+    /// Closure size of the image `spec` boots, measured once per toplevel.
+    /// Zero while it cannot be measured, e.g. without `nix` on the host.
+    async fn closure_bytes(&self, spec: &VmSpec) -> u64 {
+        let toplevel = spec.toplevel();
+        let cached = self
+            .closures
+            .lock()
+            .expect("closures lock poisoned")
+            .get(toplevel)
+            .copied();
+        if let Some(bytes) = cached {
+            return bytes;
+        }
+        let paths: Vec<String> = [
+            toplevel,
+            spec.kernel_path(),
+            spec.initrd_path(),
+            spec.disk_image_path(),
+        ]
+        .into_iter()
+        .filter_map(trust::store_path_of)
+        .map(str::to_string)
+        .collect();
+        match disk_usage::closure_bytes(&paths).await {
+            Ok(bytes) => {
+                self.closures
+                    .lock()
+                    .expect("closures lock poisoned")
+                    .insert(toplevel.to_string(), bytes);
+                bytes
+            }
+            Err(e) => {
+                debug!(toplevel, error = %e, "Cannot measure image closure");
+                0
+            }
+        }
+    }
+
+    /// Start `virtiofsd` sharing the host `/nix/store` read-only on
+    /// `socket_path`, logging to `<log_dir>/virtiofsd.log`.
+    ///
+    /// It runs without its namespace sandbox, which needs privileges the
+    /// worker does not have; the share is read-only either way.
+    async fn spawn_store_share(
+        &self,
+        vm_id: &str,
+        socket_path: &Path,
+        log_dir: &Path,
+    ) -> Result<StoreShare, VmError> {
+        let binary = self.config.virtiofsd_binary.as_ref().ok_or_else(|| {
+            VmError::Internal("No virtiofsd_binary configured to share the store".to_string())
+        })?;
+        if socket_path.exists() {
+            let _ = tokio::fs::remove_file(socket_path).await;
+        }
+        let log_path = log_dir.join("virtiofsd.log");
+        let log_file = std::fs::File::create(&log_path).map_err(|e| {
+            VmError::ProcessFailed(format!(
+                "Failed to create virtiofsd log file {}: {e}",
+                log_path.display()
+            ))
+        })?;
+        let stderr_file = log_file.try_clone().map_err(|e| {
+            VmError::ProcessFailed(format!("Failed to clone virtiofsd log file handle: {e}"))
+        })?;
+
+        info!(
+            vm_id = %vm_id,
+            virtiofsd = %binary.display(),
+            socket = %socket_path.display(),
+            "Sharing the store with closure-booted VM"
+        );
+        let child = Command::new(binary)
+            .arg("--socket-path")
+            .arg(socket_path)
+            .args([
+                "--shared-dir",
+                "/nix/store",
+                "--readonly",
+                "--sandbox",
+                "none",
+            ])
+            .stdout(std::process::Stdio::from(log_file))
+            .stderr(std::process::Stdio::from(stderr_file))
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                VmError::ProcessFailed(format!("Failed to spawn {}: {e}", binary.display()))
+            })?;
+        let pid = child.id().ok_or_else(|| {
+            VmError::ProcessFailed("virtiofsd exited right after spawning".to_string())
+        })?;
+
+        Self::wait_for_socket(socket_path, self.config.socket_timeout).await?;
+        Ok(StoreShare {
+            child: Some(child),
+            pid,
+            socket_path: socket_path.to_path_buf(),
+        })
+    }
+
     /// Poll for a unix socket to appear on disk with exponential backoff.
+    /// Check the switch side of VM `vm_id`'s vhost-user socket: in client
+    /// mode the switch must already listen on it, in server mode a stale
+    /// socket from a previous VM is removed so CH can bind it.
+    async fn prepare_vhost_user(&self, vm_id: &str) -> Result<(), VmError> {
+        let Some(vhost_user) = &self.config.vhost_user_net else {
+            return Err(VmError::Unschedulable(
+                "vhost-user networking is not set up on this host".to_string(),
+            ));
+        };
+        let socket = vhost_user.socket(vm_id);
+        match vhost_user.mode {
+            VhostUserMode::Client if !socket.exists() => Err(VmError::Internal(format!(
+                "No vhost-user socket at {}: the host switch has no port for this VM",
+                socket.display()
+            ))),
+            VhostUserMode::Client => Ok(()),
+            VhostUserMode::Server => {
+                tokio::fs::create_dir_all(&vhost_user.socket_dir)
+                    .await
+                    .map_err(|e| {
+                        VmError::ProcessFailed(format!(
+                            "Failed to create vhost-user socket dir {}: {e}",
+                            vhost_user.socket_dir.display()
+                        ))
+                    })?;
+                if socket.exists() {
+                    let _ = tokio::fs::remove_file(&socket).await;
+                }
+                Ok(())
+            }
+        }
+    }
+
     async fn wait_for_socket(path: &Path, timeout: Duration) -> Result<(), VmError> {
         let start = std::time::Instant::now();
         let mut delay = Duration::from_millis(10);
@@ -750,10 +1386,22 @@ impl VmmBackend for CloudHypervisorBackend {
 
     async fn prepare(&self, vm_id: &str, spec: &VmSpec) -> Result<(), VmError> {
         // 1. Validate that all Nix store paths exist locally
+        let boot_mode = spec.boot_mode();
+        let (kernel, initrd) = (spec.boot_kernel(), spec.boot_initrd());
+        let root = match boot_mode {
+            BootMode::DiskImage => ("disk image", spec.disk_image_path().to_string()),
+            BootMode::Closure => ("init", format!("{}/init", closure_dir(spec))),
+        };
+        if boot_mode == BootMode::Closure && self.config.virtiofsd_binary.is_none() {
+            return Err(VmError::Internal(format!(
+                "Cannot boot closure {}: no virtiofsd_binary configured to share the store",
+                spec.toplevel()
+            )));
+        }
         for (label, path) in [
-            ("kernel", spec.kernel_path()),
-            ("initrd", spec.initrd_path()),
-            ("disk image", spec.disk_image_path()),
+            ("kernel", kernel.as_str()),
+            ("initrd", initrd.as_str()),
+            (root.0, root.1.as_str()),
         ] {
             if !Path::new(path).exists() {
                 return Err(VmError::Internal(format!(
@@ -763,57 +1411,61 @@ impl VmmBackend for CloudHypervisorBackend {
             }
         }
 
-        // 2. Create per-VM working directory
-        let vm_dir = self.config.socket_dir.join(vm_id);
-        tokio::fs::create_dir_all(&vm_dir)
-            .await
-            .map_err(|e| VmError::ProcessFailed(format!(
-                "Failed to create VM directory {}: {e}", vm_dir.display()
-            )))?;
+        // 1b. Refuse images whose closure is not signed by a trusted key
+        if !self.config.trusted_public_keys.is_empty() {
+            trust::verify_image(spec, &self.config.trusted_public_keys).await?;
+            debug!(vm_id = %vm_id, "Image signatures verified");
+        }
 
-        // 3. Copy disk image to a writable location
-        //    The Nix store is read-only — CH needs to write to the disk.
-        //    tokio::fs::copy uses copy_file_range on Linux (efficient, works on all FS).
-        let writable_disk_path = vm_dir.join("disk.img");
-        let src = spec.disk_image_path();
-        tracing::info!(
-            vm_id = %vm_id,
-            src = %src,
-            dst = %writable_disk_path.display(),
-            "Copying disk image to writable location"
-        );
-        tokio::fs::copy(src, &writable_disk_path)
-            .await
-            .map_err(|e| VmError::Internal(format!(
-                "Failed to copy disk image from {} to {}: {e}",
-                src, writable_disk_path.display()
-            )))?;
+        // 1c. A vhost-user NIC needs the switch: its socket in client mode,
+        //     the socket directory in server mode
+        if spec.net_backend() == NetBackend::VhostUser {
+            self.prepare_vhost_user(vm_id).await?;
+        }
 
-        // Make the copy writable — Nix store originals are read-only (0444),
-        // and tokio::fs::copy preserves permissions. CH needs rw access.
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let perms = std::fs::Permissions::from_mode(0o644);
-            tokio::fs::set_permissions(&writable_disk_path, perms)
+        // 2. Create per-VM image and log directories
+        let image_dir = self.config.image_dir.join(vm_id);
+        let log_dir = self.config.log_dir.join(vm_id);
+        for dir in [&image_dir, &log_dir] {
+            tokio::fs::create_dir_all(dir)
                 .await
-                .map_err(|e| VmError::Internal(format!(
-                    "Failed to set writable permissions on {}: {e}",
-                    writable_disk_path.display()
+                .map_err(|e| VmError::ProcessFailed(format!(
+                    "Failed to create VM directory {}: {e}", dir.display()
                 )))?;
         }
 
+        // 3. Copy disk image to a writable location
+        //    The Nix store is read-only — CH needs to write to the disk.
+        //    tokio::fs::copy uses copy_file_range on Linux (efficient, works on all FS).
+        //    With staging, the copy is a reflink of the image staged on fast storage.
+        //    A closure boot has no disk: its root is the store shared over virtio-fs.
+        let (writable_disk_path, staged_image, cmdline) = match boot_mode {
+            BootMode::DiskImage => {
+                let (disk, staged_image) = self.copy_disk_image(vm_id, spec, &image_dir).await?;
+                (Some(disk), staged_image, spec.cmdline().to_string())
+            }
+            BootMode::Closure => {
+                let params_path = format!("{}/kernel-params", closure_dir(spec));
+                let params = tokio::fs::read_to_string(&params_path).await.unwrap_or_else(|e| {
+                    warn!(vm_id = %vm_id, path = %params_path, error = %e, "No kernel-params in closure");
+                    String::new()
+                });
+                (None, None, closure_cmdline(spec, &params))
+            }
+        };
+
         // 4. Serial log path (CH will write console output here)
-        let serial_log_path = vm_dir.join("serial.log");
+        let serial_log_path = log_dir.join("serial.log");
 
         // 5. Generate a deterministic TAP device name from the VM ID.
-        //    Linux limits interface names to 15 chars. "pcr-" prefix (4) +
-        //    first 11 chars of the UUID (enough to avoid collisions).
-        let tap_name = format!("pcr-{}", &vm_id[..11]);
+        let tap_name = tap_name(vm_id);
 
         // 6. Check if the host bridge actually exists.
         //    Without it (e.g. dev machine, no NixOS host module), we skip
         //    networking entirely — CH won't get --net, TAP won't be attached.
+        //    A vhost-user NIC has no TAP and does not need the bridge.
         let network_available = match &self.config.bridge_name {
+            _ if spec.net_backend() != NetBackend::Tap => false,
             Some(bridge) => {
                 let exists = Path::new(&format!("/sys/class/net/{bridge}")).exists();
                 if !exists {
@@ -851,9 +1503,13 @@ impl VmmBackend for CloudHypervisorBackend {
 
         // 8. Store prepared state for build_config() and spawn()
         let prepared = PreparedVm {
+            boot_mode,
             writable_disk_path,
+            staged_image,
+            cmdline,
             serial_log_path,
-            vm_dir,
+            image_dir,
+            log_dir,
             tap_name,
             network_available,
         };
@@ -882,18 +1538,35 @@ impl VmmBackend for CloudHypervisorBackend {
             let _ = tokio::fs::remove_file(&socket_path).await;
         }
 
-        // 4. Look up the VM dir from prepared state
-        let vm_dir = self
+        // 4. Look up the VM dirs from prepared state
+        let (image_dir, log_dir, boot_mode) = self
             .prepared
             .lock()
             .expect("prepared lock poisoned")
             .get(vm_id)
-            .map(|p| p.vm_dir.clone())
-            .unwrap_or_else(|| self.config.socket_dir.join(vm_id));
+            .map_or_else(
+                || {
+                    (
+                        self.config.image_dir.join(vm_id),
+                        self.config.log_dir.join(vm_id),
+                        BootMode::DiskImage,
+                    )
+                },
+                |p| (p.image_dir.clone(), p.log_dir.clone(), p.boot_mode),
+            );
+
+        // 4b. A closure boot needs its store share up before CH connects to it
+        let store_share = match boot_mode {
+            BootMode::DiskImage => None,
+            BootMode::Closure => Some(
+                self.spawn_store_share(vm_id, &store_socket_path(&socket_path), &log_dir)
+                    .await?,
+            ),
+        };
 
         // 5. Spawn the CH process, redirecting stderr+stdout to a log file
         //    so we can diagnose crashes (CH exits silently otherwise).
-        let ch_log_path = vm_dir.join("cloud-hypervisor.log");
+        let ch_log_path = log_dir.join("cloud-hypervisor.log");
         let ch_log_file = std::fs::File::create(&ch_log_path)
             .map_err(|e| VmError::ProcessFailed(format!(
                 "Failed to create CH log file {}: {e}",
@@ -927,32 +1600,51 @@ impl VmmBackend for CloudHypervisorBackend {
                 ))
             })?;
 
+        let pid = child.id().ok_or_else(|| {
+            VmError::ProcessFailed("CH process exited right after spawning".to_string())
+        })?;
+
         // 6. Wait for socket to appear
         Self::wait_for_socket(&socket_path, self.config.socket_timeout).await?;
 
         // 7. Look up the TAP name from prepared state (if networking is enabled)
-        let tap_name = self
+        //    and hand the staged image over to the process
+        let (tap_name, staged_image) = self
             .prepared
             .lock()
             .expect("prepared lock poisoned")
-            .get(vm_id)
-            .filter(|p| p.network_available)
-            .map(|p| p.tap_name.clone());
+            .get_mut(vm_id)
+            .map_or((None, None), |p| {
+                let tap_name = p.network_available.then(|| p.tap_name.clone());
+                (tap_name, p.staged_image.take())
+            });
 
         // 8. Create the REST client and process handle
         let client = CloudHypervisor::new(&socket_path);
         let process = ChProcess {
-            child,
+            child: Some(child),
+            pid,
             socket_path: socket_path.clone(),
-            vm_dir,
+            image_dir,
+            log_dir,
             tap_name,
+            store_share,
+            staged_image,
         };
 
         Ok((client, process, socket_path))
     }
 
     fn build_config(&self, vm_id: &str, spec: &VmSpec) -> ChVmConfig {
+        let vhost_user = self
+            .config
+            .vhost_user_net
+            .as_ref()
+            .filter(|_| spec.net_backend() == NetBackend::VhostUser);
+
         let boot_vcpus = spec.cpu() as u8;
+        let hotplug = self.config.hotplug.as_ref().filter(|_| !spec.hugepages());
+        let hotplug_size = hotplug.and_then(|h| h.memory_room(spec));
 
         // Look up per-VM prepared state for writable disk and serial log paths.
         let prepared = self
@@ -963,9 +1655,35 @@ impl VmmBackend for CloudHypervisorBackend {
 
         // Use the writable disk copy if available, otherwise fall back to the
         // original store path (for backward compat / tests without prepare).
-        let disk_path = prepared_vm
-            .map(|p| p.writable_disk_path.to_string_lossy().to_string())
-            .unwrap_or_else(|| spec.disk_image_path().to_string());
+        // A closure boot has no disk at all.
+        let boot_mode = spec.boot_mode();
+        let disks = match (boot_mode, prepared_vm) {
+            (BootMode::Closure, _) => Vec::new(),
+            (BootMode::DiskImage, prepared_vm) => vec![ChDiskConfig {
+                path: prepared_vm
+                    .and_then(|p| p.writable_disk_path.as_ref())
+                    .map_or_else(
+                        || spec.disk_image_path().to_string(),
+                        |path| path.to_string_lossy().to_string(),
+                    ),
+                readonly: Some(false),
+                direct: None,
+            }],
+        };
+
+        // The store share virtiofsd serves next to the API socket
+        let fs = match boot_mode {
+            BootMode::DiskImage => Vec::new(),
+            BootMode::Closure => {
+                let api_socket = self.config.socket_dir.join(format!("{vm_id}.sock"));
+                vec![ChFsConfig {
+                    tag: STORE_SHARE_TAG.to_string(),
+                    socket: store_socket_path(&api_socket).to_string_lossy().to_string(),
+                    num_queues: 1,
+                    queue_size: 1024,
+                }]
+            }
+        };
 
         // Serial: write to file if we have a prepared path, otherwise Null.
         let serial = prepared_vm
@@ -979,30 +1697,57 @@ impl VmmBackend for CloudHypervisorBackend {
             });
 
         // Kernel and initrd are read-only — safe to use from the Nix store directly.
-        let kernel_path = spec.kernel_path().to_string();
-        let initrd_path = spec.initrd_path().to_string();
+        let kernel_path = spec.boot_kernel();
+        let initrd_path = spec.boot_initrd();
 
-        let cmdline = spec.cmdline().to_string();
+        let cmdline = prepared_vm.map_or_else(
+            || match boot_mode {
+                BootMode::DiskImage => spec.cmdline().to_string(),
+                BootMode::Closure => closure_cmdline(spec, ""),
+            },
+            |p| p.cmdline.clone(),
+        );
 
         ChVmConfig {
             cpus: ChCpusConfig {
                 boot_vcpus,
-                max_vcpus: boot_vcpus,
+                max_vcpus: hotplug.map_or(boot_vcpus, |h| h.max_vcpus.max(boot_vcpus)),
             },
             memory: ChMemoryConfig {
-                size: u64::from(spec.memory_mb()) * 1024 * 1024,
+                size: u64::from(spec.memory_mb()) * MIB,
+                // vhost-user devices map the guest memory like virtio-fs does
+                shared: (boot_mode == BootMode::Closure
+                    || spec.shared_memory()
+                    || vhost_user.is_some())
+                .then_some(true),
+                hugepages: spec.hugepages().then_some(true),
+                hotplug_method: hotplug_size.map(|_| "VirtioMem".to_string()),
+                hotplug_size,
             },
             payload: Some(ChPayloadConfig {
                 kernel: kernel_path,
                 cmdline: Some(cmdline),
                 initramfs: Some(initrd_path),
             }),
-            disks: vec![ChDiskConfig {
-                path: disk_path,
-                readonly: Some(false),
-                direct: None,
-            }],
-            net: if prepared_vm.is_some_and(|p| p.network_available) {
+            disks,
+            fs,
+            net: if let Some(vhost_user) = vhost_user {
+                Some(vec![ChNetConfig {
+                    tap: None,
+                    ip: None,
+                    mask: None,
+                    mac: Some(crate::dns_proxy::guest_mac(vm_id)),
+                    vhost_user: Some(true),
+                    vhost_socket: Some(vhost_user.socket(vm_id).to_string_lossy().to_string()),
+                    vhost_mode: Some(
+                        match vhost_user.mode {
+                            VhostUserMode::Server => "Server",
+                            VhostUserMode::Client => "Client",
+                        }
+                        .to_string(),
+                    ),
+                }])
+            } else if prepared_vm.is_some_and(|p| p.network_available) {
                 // Tell CH to create a TAP device with a known name so we
                 // can attach it to the host bridge between create and boot.
                 let tap = prepared_vm
@@ -1012,7 +1757,11 @@ impl VmmBackend for CloudHypervisorBackend {
                     tap: Some(tap),
                     ip: None,
                     mask: None,
-                    mac: None,
+                    // Known MAC, so the DNS proxy can tell which VM asks
+                    mac: Some(crate::dns_proxy::guest_mac(vm_id)),
+                    vhost_user: None,
+                    vhost_socket: None,
+                    vhost_mode: None,
                 }])
             } else {
                 None
@@ -1030,4 +1779,295 @@ impl VmmBackend for CloudHypervisorBackend {
     async fn attach_network(&self, vm_id: &str) -> Result<(), VmError> {
         self.attach_tap_to_bridge(vm_id).await
     }
+
+    fn serial_log(&self, vm_id: &str) -> Option<PathBuf> {
+        Some(self.config.log_dir.join(vm_id).join("serial.log"))
+    }
+
+    fn hugepages(&self) -> HugePages {
+        HugePages::read(Path::new(hugepages::MEMINFO))
+    }
+
+    fn net_backends(&self) -> Vec<NetBackend> {
+        if self.config.vhost_user_net.is_some() {
+            NetBackend::ALL.to_vec()
+        } else {
+            vec![NetBackend::Tap]
+        }
+    }
+
+    fn can_resize(&self, spec: &VmSpec) -> bool {
+        self.config.hotplug.as_ref().is_some_and(|h| {
+            !spec.hugepages()
+                && spec.cpu() <= u32::from(h.max_vcpus)
+                && spec.memory_mb() <= h.max_memory_mb
+        })
+    }
+
+    async fn disk_usage(&self, vm_id: &str, spec: &VmSpec) -> VmDiskUsage {
+        let image_dir = self.config.image_dir.join(vm_id);
+        let log_dir = self.config.log_dir.join(vm_id);
+        let measured = tokio::task::spawn_blocking(move || {
+            let volume = disk_usage::allocated_bytes(&image_dir.join("disk.img"));
+            let logs = disk_usage::allocated_bytes(&log_dir);
+            // A shared per-VM directory holds the disk copy next to the logs
+            let logs = if log_dir == image_dir {
+                logs.saturating_sub(volume)
+            } else {
+                logs
+            };
+            (volume, logs)
+        })
+        .await;
+        let (volume_bytes, log_bytes) = measured.unwrap_or_default();
+        VmDiskUsage {
+            closure_bytes: self.closure_bytes(spec).await,
+            volume_bytes,
+            log_bytes,
+        }
+    }
+
+    async fn adopt(
+        &self,
+        vm_id: &str,
+        spec: &VmSpec,
+        record: &ProcessRecord,
+    ) -> Result<(CloudHypervisor, ChProcess), VmError> {
+        // Keeps the image the previous worker staged for it
+        let staged_image = match spec.boot_mode() {
+            BootMode::DiskImage => self
+                .staging
+                .as_ref()
+                .and_then(|stage| stage.lease(spec.disk_image_path())),
+            BootMode::Closure => None,
+        };
+        let mut process = ChProcess {
+            child: None,
+            pid: record.pid,
+            socket_path: record.api_socket.clone(),
+            image_dir: record.image_dir.clone(),
+            log_dir: record.log_dir.clone(),
+            tap_name: record.tap.clone(),
+            store_share: record.store_share_pid.map(|pid| StoreShare {
+                child: None,
+                pid,
+                socket_path: store_socket_path(&record.api_socket),
+            }),
+            staged_image,
+        };
+
+        let running = is_ch_process(record.pid, &record.api_socket);
+        let reachable = record.api_socket.exists();
+        if running && reachable {
+            info!(vm_id = %vm_id, pid = record.pid, "Adopted running cloud-hypervisor process");
+            return Ok((CloudHypervisor::new(&record.api_socket), process));
+        }
+
+        // Unreachable without its socket, so stop it rather than leak it
+        if running {
+            process.kill().await?;
+        }
+        process.cleanup().await?;
+        Err(VmError::ProcessFailed(if running {
+            format!(
+                "API socket {} is gone, stopped CH process {}",
+                record.api_socket.display(),
+                record.pid
+            )
+        } else {
+            format!("CH process {} is no longer running", record.pid)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closure_spec(cmdline: &str) -> VmSpec {
+        VmSpec::new(
+            "/nix/store/aaaa-nixos-system".to_string(),
+            String::new(),
+            String::new(),
+            String::new(),
+            cmdline.to_string(),
+            2,
+            512,
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn memory_backing_follows_the_spec() {
+        let backend = CloudHypervisorBackend::new(CloudHypervisorConfig::default());
+        let spec = VmSpec::new(
+            String::new(),
+            "/nix/store/k/bzImage".to_string(),
+            "/nix/store/i/initrd".to_string(),
+            "/nix/store/d/nixos.img".to_string(),
+            String::new(),
+            1,
+            512,
+            Vec::new(),
+        );
+        let plain = backend.build_config("vm-1", &spec).memory;
+        assert_eq!((plain.shared, plain.hugepages), (None, None));
+
+        let spec = spec.with_hugepages(true).with_shared_memory(true);
+        let backed = backend.build_config("vm-1", &spec).memory;
+        assert_eq!((backed.shared, backed.hugepages), (Some(true), Some(true)));
+        assert_eq!(
+            serde_json::to_value(&backed).unwrap(),
+            serde_json::json!({"size": 512 * 1024 * 1024, "shared": true, "hugepages": true})
+        );
+    }
+
+    #[test]
+    fn vm_info_reads_the_guest_state() {
+        let info: ChVmInfo = serde_json::from_str(
+            r#"{"config": {"cpus": {"boot_vcpus": 2}}, "state": "Shutdown", "memory_actual_size": 0}"#,
+        )
+        .unwrap();
+        assert_eq!(info.state, GuestState::Shutdown);
+    }
+
+    #[test]
+    fn hotplug_leaves_room_to_resize() {
+        let backend = CloudHypervisorBackend::new(CloudHypervisorConfig {
+            hotplug: Some(Hotplug {
+                max_vcpus: 8,
+                max_memory_mb: 4096,
+            }),
+            ..CloudHypervisorConfig::default()
+        });
+        let spec = VmSpec::new(
+            String::new(),
+            "/nix/store/k/bzImage".to_string(),
+            "/nix/store/i/initrd".to_string(),
+            "/nix/store/d/nixos.img".to_string(),
+            String::new(),
+            2,
+            1000,
+            Vec::new(),
+        );
+        let config = backend.build_config("vm-1", &spec);
+        assert_eq!((config.cpus.boot_vcpus, config.cpus.max_vcpus), (2, 8));
+        assert_eq!(config.memory.hotplug_method.as_deref(), Some("VirtioMem"));
+        assert_eq!(config.memory.hotplug_size, Some(3072 * MIB));
+        assert!(backend.can_resize(&spec));
+
+        let too_big = VmSpec::new(
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            16,
+            1000,
+            Vec::new(),
+        );
+        assert!(!backend.can_resize(&too_big));
+        assert_eq!(backend.build_config("vm-1", &too_big).cpus.max_vcpus, 16);
+
+        let spec = spec.with_hugepages(true);
+        let config = backend.build_config("vm-1", &spec);
+        assert_eq!(config.cpus.max_vcpus, 2);
+        assert_eq!(config.memory.hotplug_size, None);
+        assert!(!backend.can_resize(&spec));
+
+        let fixed = CloudHypervisorBackend::new(CloudHypervisorConfig::default());
+        assert!(!fixed.can_resize(&spec.with_hugepages(false)));
+    }
+
+    #[test]
+    fn vhost_user_nics_use_the_switch_socket() {
+        let backend = CloudHypervisorBackend::new(CloudHypervisorConfig {
+            vhost_user_net: Some(VhostUserNet {
+                socket_dir: PathBuf::from("/run/vhost-user"),
+                mode: VhostUserMode::Server,
+            }),
+            ..CloudHypervisorConfig::default()
+        });
+        assert_eq!(backend.net_backends(), NetBackend::ALL);
+        let spec = VmSpec::new(
+            String::new(),
+            "/nix/store/k/bzImage".to_string(),
+            "/nix/store/i/initrd".to_string(),
+            "/nix/store/d/nixos.img".to_string(),
+            String::new(),
+            1,
+            512,
+            Vec::new(),
+        )
+        .with_net_backend(NetBackend::VhostUser);
+        let vm_id = "0190c0de-0000-7000-8000-000000000000";
+
+        let config = backend.build_config(vm_id, &spec);
+        assert_eq!(config.memory.shared, Some(true));
+        let net = serde_json::to_value(config.net.expect("a NIC")).unwrap();
+        assert_eq!(
+            net,
+            serde_json::json!([{
+                "mac": crate::dns_proxy::guest_mac(vm_id),
+                "vhost_user": true,
+                "vhost_socket": format!("/run/vhost-user/{vm_id}.sock"),
+                "vhost_mode": "Server",
+            }])
+        );
+
+        let tap_only = CloudHypervisorBackend::new(CloudHypervisorConfig::default());
+        assert_eq!(tap_only.net_backends(), [NetBackend::Tap]);
+    }
+
+    #[test]
+    fn closure_cmdline_follows_the_bootloader_entry() {
+        let spec = closure_spec("quiet");
+        assert_eq!(
+            closure_cmdline(&spec, "console=ttyS0 loglevel=4\n"),
+            "console=ttyS0 loglevel=4 init=/nix/store/aaaa-nixos-system/init quiet"
+        );
+        assert_eq!(
+            closure_cmdline(&closure_spec(""), ""),
+            "init=/nix/store/aaaa-nixos-system/init"
+        );
+    }
+
+    #[test]
+    fn closure_boot_shares_the_store_instead_of_a_disk() {
+        let backend = CloudHypervisorBackend::new(CloudHypervisorConfig::default());
+        let config =
+            backend.build_config("0190c0de-0000-7000-8000-000000000000", &closure_spec(""));
+
+        assert!(config.disks.is_empty());
+        assert_eq!(config.memory.shared, Some(true));
+        let [fs] = config.fs.as_slice() else {
+            panic!("expected one fs device, got {:?}", config.fs);
+        };
+        assert_eq!(fs.tag, STORE_SHARE_TAG);
+        assert!(fs.socket.ends_with(".store.sock"), "{}", fs.socket);
+        let payload = config.payload.expect("payload");
+        assert_eq!(payload.kernel, "/nix/store/aaaa-nixos-system/kernel");
+        assert_eq!(
+            payload.initramfs.as_deref(),
+            Some("/nix/store/aaaa-nixos-system/initrd")
+        );
+    }
+
+    #[test]
+    fn a_reused_pid_is_not_taken_for_the_vmm() {
+        let socket = Path::new("/tmp/pcr-reused-pid.sock");
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "sleep 30; :", "sh", "--api-socket"])
+            .arg(socket)
+            .spawn()
+            .unwrap();
+
+        assert!(is_ch_process(child.id(), socket));
+        assert!(!is_ch_process(child.id(), Path::new("/tmp/other.sock")));
+        assert!(!runs_with(child.id(), "--socket-path", socket));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(!is_ch_process(child.id(), socket));
+    }
 }
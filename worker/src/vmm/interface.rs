@@ -12,11 +12,49 @@
 //! - [`VmmBackend`] — factory that spawns VMM processes and creates clients.
 //!   The VmManager is generic over this trait so it can be tested without
 //!   touching real hypervisors, sockets, or the filesystem.
+//!
+//! A [`ProcessRecord`] describes a running process well enough for a
+//! restarted worker to [adopt](VmmBackend::adopt) it again.
 
 use std::fmt::Debug;
 use std::path::PathBuf;
 
-use crate::dto::{VmError, VmSpec};
+use commands::net_backend::NetBackend;
+use serde::{Deserialize, Serialize};
+
+use crate::disk_usage::VmDiskUsage;
+use crate::dto::{VmError, VmMetrics, VmSpec};
+use crate::hugepages::HugePages;
+
+// ─── Runtime record ───────────────────────────────────────────────────────
+
+/// Where to find a VMM process that outlives the worker.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessRecord {
+    pub pid: u32,
+    /// API socket of the process
+    pub api_socket: PathBuf,
+    /// TAP device of the VM, if it has network
+    pub tap: Option<String>,
+    /// Per-VM image directory, removed with the VM
+    pub image_dir: PathBuf,
+    /// Per-VM log directory, removed with the VM; may be `image_dir`
+    pub log_dir: PathBuf,
+    /// Process sharing the host store with the VM, for closure boots
+    #[serde(default)]
+    pub store_share_pid: Option<u32>,
+}
+
+/// State of a VM as its VMM reports it, see [`Vmm::info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum GuestState {
+    /// Defined but never booted
+    Created,
+    Running,
+    /// Shut down, the VMM itself still runs
+    Shutdown,
+    Paused,
+}
 
 // ─── Per-VM client ─────────────────────────────────────────────────────────
 
@@ -40,8 +78,32 @@ pub trait Vmm: Send + 'static {
     /// Gracefully shut down the VM
     fn shutdown(&self) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send;
 
+    /// Press the VM's ACPI power button, asking the guest to power off
+    fn power_button(&self) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Reboot a booted VM
+    fn reboot(&self) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Hot-plug or unplug vCPUs and memory of a booted VM until it has
+    /// `cpu` vCPUs and `memory_mb` of RAM
+    fn resize(
+        &self,
+        cpu: u32,
+        memory_mb: u32,
+    ) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send;
+
     /// Delete the VM definition (must be shut down first)
     fn delete(&self) -> impl std::future::Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Ask the VMM what state the VM is in; an error means the VMM did
+    /// not answer
+    fn info(&self) -> impl std::future::Future<Output = Result<GuestState, Self::Error>> + Send;
+
+    /// Latest resource usage of the VM. Default: all zeroes, for backends
+    /// that do not collect usage yet.
+    fn metrics(&self) -> VmMetrics {
+        VmMetrics::default()
+    }
 }
 
 // ─── VMM process handle ───────────────────────────────────────────────────
@@ -61,6 +123,17 @@ pub trait VmmProcess: Send + 'static {
     /// Clean up resources associated with this process (socket files, TAP
     /// devices, writable disk copies, etc.). Called after `kill`.
     fn cleanup(&mut self) -> impl std::future::Future<Output = Result<(), VmError>> + Send;
+
+    /// What a restarted worker needs to adopt this process. Default:
+    /// `None`, the process cannot be adopted.
+    fn record(&self) -> Option<ProcessRecord> {
+        None
+    }
+
+    /// Let the process outlive this handle: dropping it afterwards neither
+    /// kills nor cleans up. Called when the worker exits with VMs running.
+    /// Default: no-op.
+    fn detach(&mut self) {}
 }
 
 // ─── Backend factory ──────────────────────────────────────────────────────
@@ -134,4 +207,63 @@ pub trait VmmBackend: Send + 'static {
         let _ = vm_id;
         std::future::ready(Ok(()))
     }
+
+    /// Serial console log of VM `vm_id`, which the
+    /// [boot watchdog](crate::boot_watchdog) reads to tell when the guest
+    /// is ready. Default: `None`, VMs are not watched.
+    fn serial_log(&self, vm_id: &str) -> Option<PathBuf> {
+        let _ = vm_id;
+        None
+    }
+
+    /// What VM `vm_id`, booted from `spec`, takes on this host's disk, see
+    /// [`disk_usage`](crate::disk_usage). Default: all zeroes, for backends
+    /// that keep nothing on disk.
+    fn disk_usage(
+        &self,
+        vm_id: &str,
+        spec: &VmSpec,
+    ) -> impl std::future::Future<Output = VmDiskUsage> + Send {
+        let _ = (vm_id, spec);
+        std::future::ready(VmDiskUsage::default())
+    }
+
+    /// Hugepage pool of this host, which hugepage-backed VMs must fit in.
+    /// Default: empty, such VMs are refused.
+    fn hugepages(&self) -> HugePages {
+        HugePages::default()
+    }
+
+    /// Network backends this host can attach a VM's NIC through.
+    /// Default: TAP only, other backends need the host prepared for them.
+    fn net_backends(&self) -> Vec<NetBackend> {
+        vec![NetBackend::Tap]
+    }
+
+    /// Whether a booted VM can be [resized](Vmm::resize) to the vCPUs and
+    /// memory of `spec` instead of being redeployed. Default: `false`,
+    /// every spec change redeploys.
+    fn can_resize(&self, spec: &VmSpec) -> bool {
+        let _ = spec;
+        false
+    }
+
+    /// Reconnect to a VM whose process was started by a previous worker,
+    /// as described by `record`.
+    ///
+    /// Fails when the process is gone or is not the one recorded; the
+    /// backend then releases whatever the record still holds (socket, TAP,
+    /// directories). Default: always fails, nothing is adopted.
+    fn adopt(
+        &self,
+        vm_id: &str,
+        spec: &VmSpec,
+        record: &ProcessRecord,
+    ) -> impl std::future::Future<Output = Result<(Self::Client, Self::Process), VmError>> + Send
+    {
+        let _ = (spec, record);
+        std::future::ready(Err(VmError::Internal(format!(
+            "backend cannot adopt VM {vm_id}"
+        ))))
+    }
 }
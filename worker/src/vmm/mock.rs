@@ -1,4 +1,4 @@
-//! Mock VMM backend for unit testing.
+//! Mock VMM backend for unit testing and `--simulate`.
 //!
 //! Provides [`MockBackend`], [`MockVmm`], and [`MockProcess`] — lightweight
 //! implementations of the VMM traits that track calls without touching real
@@ -6,13 +6,23 @@
 //!
 //! Each mock records what was called so tests can assert on the sequence
 //! of operations. Failures can be injected via [`MockBackendConfig`].
+//!
+//! For local development the same backend stands in for cloud-hypervisor:
+//! `boot_delay` fakes boot times and `synthetic_metrics` makes each VM report
+//! moving CPU, memory and network figures.
 
+use std::os::unix::process::ExitStatusExt;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use commands::net_backend::NetBackend;
 
-use crate::dto::{VmError, VmSpec};
-use crate::vmm::{Vmm, VmmBackend, VmmProcess};
+use crate::disk_usage::VmDiskUsage;
+use crate::dto::{VmError, VmMetrics, VmSpec};
+use crate::hugepages::HugePages;
+use crate::vmm::{GuestState, ProcessRecord, Vmm, VmmBackend, VmmProcess};
 
 // ─── Configuration for failure injection ──────────────────────────────────
 
@@ -32,6 +42,32 @@ pub struct MockBackendConfig {
     pub shutdown_error: Option<String>,
     /// If set, `Vmm::delete()` returns an error
     pub delete_error: Option<String>,
+    /// If set, `Vmm::power_button()` returns an error
+    pub power_button_error: Option<String>,
+    /// Guests ignore the power button, their process keeps running
+    pub ignore_power_button: bool,
+    /// VMs can be resized in place instead of being redeployed
+    pub resizable: bool,
+    /// If set, `Vmm::resize()` returns an error
+    pub resize_error: Option<String>,
+    /// If set, `Vmm::info()` returns an error, as a hung VMM would
+    pub info_error: Option<String>,
+    /// If set, `Vmm::info()` reports this state instead of the one the
+    /// calls so far left the VM in
+    pub guest_state: Option<GuestState>,
+    /// If set, `adopt()` returns this error
+    pub adopt_error: Option<String>,
+    /// How long `Vmm::boot()` takes
+    pub boot_delay: Duration,
+    /// Report made-up usage figures instead of zeroes
+    pub synthetic_metrics: bool,
+    /// Directory of the serial logs the boot watchdog reads,
+    /// `<dir>/<vm_id>.log`; VMs have none when unset
+    pub serial_log_dir: Option<PathBuf>,
+    /// Hugepage pool of the simulated host
+    pub hugepages: HugePages,
+    /// Advertise vhost-user networking besides TAP
+    pub vhost_user_net: bool,
 }
 
 // ─── Call tracker (shared between backend, client, process) ───────────────
@@ -45,9 +81,16 @@ pub struct MockCallTracker {
     pub creates: Arc<AtomicUsize>,
     pub boots: Arc<AtomicUsize>,
     pub shutdowns: Arc<AtomicUsize>,
+    pub power_buttons: Arc<AtomicUsize>,
+    pub reboots: Arc<AtomicUsize>,
+    pub resizes: Arc<AtomicUsize>,
     pub deletes: Arc<AtomicUsize>,
     pub kills: Arc<AtomicUsize>,
     pub cleanups: Arc<AtomicUsize>,
+    pub adopts: Arc<AtomicUsize>,
+    pub infos: Arc<AtomicUsize>,
+    /// Bumped by [`crash_vmms`](Self::crash_vmms)
+    pub crashes: Arc<AtomicUsize>,
 }
 
 impl MockCallTracker {
@@ -75,6 +118,21 @@ impl MockCallTracker {
         self.shutdowns.load(Ordering::Relaxed)
     }
 
+    #[must_use]
+    pub fn power_button_count(&self) -> usize {
+        self.power_buttons.load(Ordering::Relaxed)
+    }
+
+    #[must_use]
+    pub fn reboot_count(&self) -> usize {
+        self.reboots.load(Ordering::Relaxed)
+    }
+
+    #[must_use]
+    pub fn resize_count(&self) -> usize {
+        self.resizes.load(Ordering::Relaxed)
+    }
+
     pub fn delete_count(&self) -> usize {
         self.deletes.load(Ordering::Relaxed)
     }
@@ -86,6 +144,22 @@ impl MockCallTracker {
     pub fn cleanup_count(&self) -> usize {
         self.cleanups.load(Ordering::Relaxed)
     }
+
+    #[must_use]
+    pub fn adopt_count(&self) -> usize {
+        self.adopts.load(Ordering::Relaxed)
+    }
+
+    #[must_use]
+    pub fn info_count(&self) -> usize {
+        self.infos.load(Ordering::Relaxed)
+    }
+
+    /// Make every VMM process spawned or adopted so far look exited, as
+    /// after a crash; later ones run normally.
+    pub fn crash_vmms(&self) {
+        self.crashes.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 // ─── Mock VMM client ──────────────────────────────────────────────────────
@@ -94,6 +168,14 @@ impl MockCallTracker {
 pub struct MockVmm {
     tracker: MockCallTracker,
     config: MockBackendConfig,
+    /// Memory size from `create()`, for synthetic metrics.
+    memory_mb: OnceLock<u32>,
+    /// Set once `boot()` succeeds.
+    booted_at: OnceLock<Instant>,
+    /// Set once the guest powered off, shared with the process.
+    powered_off: Arc<AtomicBool>,
+    /// What the calls so far did to the VM, for `info()`.
+    state: Mutex<GuestState>,
 }
 
 /// Config type for MockVmm (just the VmSpec fields, for assertions).
@@ -121,19 +203,24 @@ impl Vmm for MockVmm {
     type Config = MockVmConfig;
     type Error = MockVmError;
 
-    async fn create(&self, _config: Self::Config) -> Result<(), Self::Error> {
+    async fn create(&self, config: Self::Config) -> Result<(), Self::Error> {
         self.tracker.creates.fetch_add(1, Ordering::Relaxed);
         if let Some(ref e) = self.config.create_error {
             return Err(MockVmError(e.clone()));
         }
+        let _ = self.memory_mb.set(config.memory_mb);
+        self.set_state(GuestState::Created);
         Ok(())
     }
 
     async fn boot(&self) -> Result<(), Self::Error> {
         self.tracker.boots.fetch_add(1, Ordering::Relaxed);
+        tokio::time::sleep(self.config.boot_delay).await;
         if let Some(ref e) = self.config.boot_error {
             return Err(MockVmError(e.clone()));
         }
+        let _ = self.booted_at.set(Instant::now());
+        self.set_state(GuestState::Running);
         Ok(())
     }
 
@@ -142,6 +229,34 @@ impl Vmm for MockVmm {
         if let Some(ref e) = self.config.shutdown_error {
             return Err(MockVmError(e.clone()));
         }
+        self.set_state(GuestState::Shutdown);
+        Ok(())
+    }
+
+    /// The guest powers off, and its process exits, unless
+    /// `ignore_power_button` is set.
+    async fn power_button(&self) -> Result<(), Self::Error> {
+        self.tracker.power_buttons.fetch_add(1, Ordering::Relaxed);
+        if let Some(ref e) = self.config.power_button_error {
+            return Err(MockVmError(e.clone()));
+        }
+        if !self.config.ignore_power_button {
+            self.powered_off.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    async fn reboot(&self) -> Result<(), Self::Error> {
+        self.tracker.reboots.fetch_add(1, Ordering::Relaxed);
+        self.set_state(GuestState::Running);
+        Ok(())
+    }
+
+    async fn resize(&self, _cpu: u32, _memory_mb: u32) -> Result<(), Self::Error> {
+        self.tracker.resizes.fetch_add(1, Ordering::Relaxed);
+        if let Some(ref e) = self.config.resize_error {
+            return Err(MockVmError(e.clone()));
+        }
         Ok(())
     }
 
@@ -153,12 +268,57 @@ impl Vmm for MockVmm {
         Ok(())
     }
 
+    async fn info(&self) -> Result<GuestState, Self::Error> {
+        self.tracker.infos.fetch_add(1, Ordering::Relaxed);
+        if let Some(ref e) = self.config.info_error {
+            return Err(MockVmError(e.clone()));
+        }
+        Ok(self
+            .config
+            .guest_state
+            .unwrap_or_else(|| *self.state.lock().expect("state lock poisoned")))
+    }
+
+    /// With `synthetic_metrics`: CPU swings between 10% and 50%, memory sits
+    /// at 60% of the VM's size, and traffic grows with uptime.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn metrics(&self) -> VmMetrics {
+        let Some(booted_at) = self.booted_at.get() else {
+            return VmMetrics::default();
+        };
+        if !self.config.synthetic_metrics {
+            return VmMetrics::default();
+        }
+        let uptime = booted_at.elapsed().as_secs_f64();
+        let memory_mb = u64::from(self.memory_mb.get().copied().unwrap_or_default());
+        VmMetrics {
+            cpu_usage: (0.3 + 0.2 * (uptime / 30.0).sin()) as f32,
+            memory_usage: memory_mb * 1024 * 1024 * 3 / 5,
+            network_rx_bytes: (uptime * 16_384.0) as u64,
+            network_tx_bytes: (uptime * 4_096.0) as u64,
+        }
+    }
+}
+
+impl MockVmm {
+    fn set_state(&self, state: GuestState) {
+        *self.state.lock().expect("state lock poisoned") = state;
+    }
 }
 
 // ─── Mock process handle ──────────────────────────────────────────────────
 
 pub struct MockProcess {
     tracker: MockCallTracker,
+    socket_path: PathBuf,
+    /// Set once the guest powered off, shared with the client.
+    powered_off: Arc<AtomicBool>,
+    /// `tracker.crashes` when the process started
+    crashes_before: usize,
 }
 
 impl VmmProcess for MockProcess {
@@ -168,7 +328,11 @@ impl VmmProcess for MockProcess {
     }
 
     fn try_wait(&mut self) -> Result<Option<std::process::ExitStatus>, VmError> {
-        // Mock process is always "alive"
+        // Mock process is "alive" until its guest powered off or it crashed
+        let crashed = self.tracker.crashes.load(Ordering::Relaxed) > self.crashes_before;
+        if self.powered_off.load(Ordering::Relaxed) || crashed {
+            return Ok(Some(std::process::ExitStatus::from_raw(0)));
+        }
         Ok(None)
     }
 
@@ -176,6 +340,19 @@ impl VmmProcess for MockProcess {
         self.tracker.cleanups.fetch_add(1, Ordering::Relaxed);
         Ok(())
     }
+
+    /// A record pointing nowhere; [`MockBackend::adopt`] accepts it as
+    /// long as no `adopt_error` is injected.
+    fn record(&self) -> Option<ProcessRecord> {
+        Some(ProcessRecord {
+            pid: 0,
+            api_socket: self.socket_path.clone(),
+            tap: None,
+            image_dir: PathBuf::new(),
+            log_dir: PathBuf::new(),
+            store_share_pid: None,
+        })
+    }
 }
 
 // ─── Mock backend factory ─────────────────────────────────────────────────
@@ -230,17 +407,92 @@ impl VmmBackend for MockBackend {
         }
 
         let socket_path = PathBuf::from(format!("/tmp/mock/{vm_id}.sock"));
+        let powered_off = Arc::new(AtomicBool::new(false));
         let client = MockVmm {
             tracker: self.tracker.clone(),
             config: self.config.clone(),
+            memory_mb: OnceLock::new(),
+            booted_at: OnceLock::new(),
+            powered_off: Arc::clone(&powered_off),
+            state: Mutex::new(GuestState::Created),
         };
         let process = MockProcess {
             tracker: self.tracker.clone(),
+            socket_path: socket_path.clone(),
+            powered_off,
+            crashes_before: self.tracker.crashes.load(Ordering::Relaxed),
         };
 
         Ok((client, process, socket_path))
     }
 
+    /// A simulated VM "survives" a worker restart: it is booted again as of
+    /// now, without counting as a spawn.
+    async fn adopt(
+        &self,
+        _vm_id: &str,
+        spec: &VmSpec,
+        record: &ProcessRecord,
+    ) -> Result<(MockVmm, MockProcess), VmError> {
+        self.tracker.adopts.fetch_add(1, Ordering::Relaxed);
+        if let Some(ref e) = self.config.adopt_error {
+            self.tracker.cleanups.fetch_add(1, Ordering::Relaxed);
+            return Err(VmError::ProcessFailed(e.clone()));
+        }
+        let powered_off = Arc::new(AtomicBool::new(false));
+        let client = MockVmm {
+            tracker: self.tracker.clone(),
+            config: self.config.clone(),
+            memory_mb: OnceLock::from(spec.memory_mb()),
+            booted_at: OnceLock::from(Instant::now()),
+            powered_off: Arc::clone(&powered_off),
+            state: Mutex::new(GuestState::Running),
+        };
+        let process = MockProcess {
+            tracker: self.tracker.clone(),
+            socket_path: record.api_socket.clone(),
+            powered_off,
+            crashes_before: self.tracker.crashes.load(Ordering::Relaxed),
+        };
+        Ok((client, process))
+    }
+
+    fn serial_log(&self, vm_id: &str) -> Option<PathBuf> {
+        self.config
+            .serial_log_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{vm_id}.log")))
+    }
+
+    fn hugepages(&self) -> HugePages {
+        self.config.hugepages
+    }
+
+    fn net_backends(&self) -> Vec<NetBackend> {
+        if self.config.vhost_user_net {
+            NetBackend::ALL.to_vec()
+        } else {
+            vec![NetBackend::Tap]
+        }
+    }
+
+    fn can_resize(&self, _spec: &VmSpec) -> bool {
+        self.config.resizable
+    }
+
+    /// With `synthetic_metrics`: a 1 GiB image, a volume the size of the
+    /// VM's memory and 1 MiB of logs.
+    async fn disk_usage(&self, _vm_id: &str, spec: &VmSpec) -> VmDiskUsage {
+        if !self.config.synthetic_metrics {
+            return VmDiskUsage::default();
+        }
+        VmDiskUsage {
+            closure_bytes: 1024 * 1024 * 1024,
+            volume_bytes: u64::from(spec.memory_mb()) * 1024 * 1024,
+            log_bytes: 1024 * 1024,
+        }
+    }
+
     fn build_config(&self, _vm_id: &str, spec: &VmSpec) -> MockVmConfig {
         MockVmConfig {
             cpu: spec.cpu(),
@@ -9,7 +9,8 @@
 //! - **[`Vmm`]** — per-VM REST client. One instance = one socket = one VM.
 //!   Methods: `create`, `boot`, `shutdown`, `delete`, `info`, `pause`, `resume`, `ping`.
 //! - **[`VmmProcess`]** — OS process handle. `kill()` + `cleanup()` (socket, disk copy, logs).
-//! - **[`VmmBackend`]** — factory. `prepare()` → `spawn()` → `build_config()`.
+//! - **[`VmmBackend`]** — factory. `prepare()` → `spawn()` → `build_config()`,
+//!   and `adopt()` for processes a previous worker started.
 //!   Generic over `Client: Vmm` + `Process: VmmProcess`.
 //!
 //! Three traits (not one) for separation of concerns and testability: tests swap
@@ -23,12 +24,15 @@
 //! ## Modules
 //!
 //! - [`cloud_hypervisor`] — production CH implementation
-//! - [`mock`] — test-only stub (`#[cfg(test)]`)
+//! - [`mock`] — stub for tests, the chaos harness and `--simulate`
+//! - [`trust`] — image signature verification before boot
+//! - [`staging`] — images staged on fast local storage, VM disks reflinked from them
 
 pub mod cloud_hypervisor;
 mod interface;
-#[cfg(test)]
 pub mod mock;
+pub mod staging;
+pub mod trust;
 
 pub use cloud_hypervisor::CloudHypervisorBackend;
-pub use interface::{Vmm, VmmBackend, VmmProcess};
+pub use interface::{GuestState, ProcessRecord, Vmm, VmmBackend, VmmProcess};
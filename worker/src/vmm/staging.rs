@@ -0,0 +1,306 @@
+//! Image staging on fast local storage.
+//!
+//! Every disk-image boot copies the image out of the read-only store. On
+//! hosts whose store lives on slow storage (network or spinning disks) that
+//! copy dominates the boot, so `cloud_hypervisor.staging_dir` names a fast
+//! local disk (e.g. an `NVMe` drive) to stage images on:
+//!
+//! - each store image is copied to `<staging_dir>/images/` once, by the
+//!   first VM booting it, and shared by every VM booting it after;
+//! - the writable disk of each VM is a reflink of the staged image when the
+//!   filesystem supports it (btrfs, XFS), sharing its blocks until the VM
+//!   writes to them, and a plain copy from the fast disk otherwise;
+//! - a staged image is kept while a VM booted from it exists and removed
+//!   along with the last one.
+//!
+//! Only store paths are staged: their contents never change, so a copy
+//! named after the path stays valid. Images from outside the store are
+//! copied straight to the VM's directory, as without staging.
+//!
+//! A VM disk never depends on the staged file (a reflink shares blocks, not
+//! the file), so removing a staged image never affects a running VM. Images
+//! left by a previous worker are used again by the VMs it adopts, or by the
+//! next VM booting the same image.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use tracing::{debug, info, warn};
+
+use crate::dto::VmError;
+use crate::vmm::trust;
+
+/// How the writable disk of a VM was made from its staged image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloneMethod {
+    /// Shares the staged image's blocks, copy-on-write
+    Reflink,
+    /// Full copy, the filesystem cannot share blocks
+    Copy,
+}
+
+impl CloneMethod {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CloneMethod::Reflink => "reflink",
+            CloneMethod::Copy => "copy",
+        }
+    }
+}
+
+/// Staged images in one staging directory, counted by the VMs using them.
+#[derive(Debug, Clone)]
+pub struct ImageStage {
+    inner: Arc<Stage>,
+}
+
+#[derive(Debug)]
+struct Stage {
+    images_dir: PathBuf,
+    /// VMs using each staged image, by file name
+    users: Mutex<HashMap<String, usize>>,
+}
+
+/// One VM's use of a staged image. The image is removed when the last
+/// lease on it is dropped.
+#[derive(Debug)]
+pub struct StagedImage {
+    stage: Arc<Stage>,
+    name: String,
+}
+
+impl ImageStage {
+    /// Stage images under `<staging_dir>/images/`, dropping copies a
+    /// crashed worker left half done.
+    #[must_use]
+    pub fn new(staging_dir: &Path) -> Self {
+        let images_dir = staging_dir.join("images");
+        if let Ok(entries) = std::fs::read_dir(&images_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_some_and(|ext| ext == "partial") {
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+        Self {
+            inner: Arc::new(Stage {
+                images_dir,
+                users: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Count a VM as using the staged copy of `src`, `None` when `src` is
+    /// not in the store and cannot be staged.
+    #[must_use]
+    pub fn lease(&self, src: &str) -> Option<StagedImage> {
+        let name = staged_name(src)?;
+        *self.inner.users().entry(name.clone()).or_default() += 1;
+        Some(StagedImage {
+            stage: Arc::clone(&self.inner),
+            name,
+        })
+    }
+
+    /// Number of VMs using the staged copy of `src`.
+    #[must_use]
+    pub fn users(&self, src: &str) -> usize {
+        staged_name(src).map_or(0, |name| {
+            self.inner.users().get(&name).copied().unwrap_or(0)
+        })
+    }
+}
+
+impl Stage {
+    fn users(&self) -> MutexGuard<'_, HashMap<String, usize>> {
+        // Single increments and decrements, a panicking holder cannot leave
+        // a count half-updated
+        self.users.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl StagedImage {
+    /// Where the staged copy lives.
+    #[must_use]
+    pub fn path(&self) -> PathBuf {
+        self.stage.images_dir.join(&self.name)
+    }
+
+    /// Copy `src` to the staging directory unless it is there already.
+    /// Returns whether it was, i.e. the copy was saved.
+    ///
+    /// The copy is written next to its final name and renamed, so VMs
+    /// staging the same image at once never see a partial file.
+    ///
+    /// # Errors
+    ///
+    /// - if the staging directory cannot be created or written to
+    pub async fn stage(&self, src: &str) -> Result<bool, VmError> {
+        let staged = self.path();
+        if tokio::fs::try_exists(&staged).await.unwrap_or(false) {
+            debug!(src, staged = %staged.display(), "Image already staged");
+            return Ok(true);
+        }
+        let failed = |e: std::io::Error| {
+            VmError::Internal(format!(
+                "Failed to stage {src} in {}: {e}",
+                self.stage.images_dir.display()
+            ))
+        };
+        tokio::fs::create_dir_all(&self.stage.images_dir)
+            .await
+            .map_err(failed)?;
+        let partial =
+            staged.with_file_name(format!("{}.{}.partial", self.name, uuid::Uuid::now_v7()));
+        info!(src, staged = %staged.display(), "Staging image on fast storage");
+        if let Err(e) = tokio::fs::copy(src, &partial).await {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(failed(e));
+        }
+        tokio::fs::rename(&partial, &staged).await.map_err(failed)?;
+        Ok(false)
+    }
+}
+
+impl Drop for StagedImage {
+    fn drop(&mut self) {
+        let mut users = self.stage.users();
+        let Some(count) = users.get_mut(&self.name) else {
+            return;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        users.remove(&self.name);
+        // Under the lock, so a VM leasing the image now stages it again
+        let path = self.path();
+        match std::fs::remove_file(&path) {
+            Ok(()) => debug!(path = %path.display(), "Removed staged image, no VM uses it"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!(path = %path.display(), error = %e, "Failed to remove staged image"),
+        }
+    }
+}
+
+/// File name of the staged copy of `src`: its store path without the store
+/// directory, e.g. `<hash>-nixos-disk-image_nixos.img` for
+/// `/nix/store/<hash>-nixos-disk-image/nixos.img`.
+fn staged_name(src: &str) -> Option<String> {
+    let store_path = trust::store_path_of(src)?;
+    let base = Path::new(store_path).file_name()?.to_str()?;
+    let inner = src[store_path.len()..].trim_matches('/');
+    Some(if inner.is_empty() {
+        base.to_string()
+    } else {
+        format!("{base}_{}", inner.replace('/', "_"))
+    })
+}
+
+/// Make `dst` a reflink of `src`, or a full copy when the filesystem cannot
+/// share blocks between them (not btrfs or XFS, or another filesystem).
+///
+/// # Errors
+///
+/// - if `src` cannot be read or `dst` cannot be written
+pub async fn clone_or_copy(src: &Path, dst: &Path) -> Result<CloneMethod, std::io::Error> {
+    let (src, dst) = (src.to_path_buf(), dst.to_path_buf());
+    tokio::task::spawn_blocking(move || match reflink(&src, &dst) {
+        Ok(()) => Ok(CloneMethod::Reflink),
+        Err(e) if is_unsupported(&e) => {
+            debug!(src = %src.display(), error = %e, "Reflink unsupported, copying");
+            std::fs::copy(&src, &dst).map(|_| CloneMethod::Copy)
+        }
+        Err(e) => Err(e),
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+/// `ioctl(FICLONE)` from `src` into a new `dst`, removed again on failure.
+fn reflink(src: &Path, dst: &Path) -> Result<(), std::io::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    let source = std::fs::File::open(src)?;
+    let target = std::fs::File::create(dst)?;
+    // SAFETY: both descriptors are open for the duration of the call
+    let ret = unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        drop(target);
+        let _ = std::fs::remove_file(dst);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Errors meaning the two files cannot share blocks, not that I/O failed.
+fn is_unsupported(err: &std::io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EOPNOTSUPP | libc::EXDEV | libc::EINVAL | libc::ENOTTY | libc::ENOSYS)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pcr-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn staged_names_follow_the_store_path() {
+        assert_eq!(
+            staged_name("/nix/store/abc-nixos-disk-image/nixos.img").as_deref(),
+            Some("abc-nixos-disk-image_nixos.img")
+        );
+        assert_eq!(
+            staged_name("/nix/store/abc-image.raw").as_deref(),
+            Some("abc-image.raw")
+        );
+        assert_eq!(staged_name("/var/lib/images/dev.img"), None);
+    }
+
+    #[tokio::test]
+    async fn staged_image_is_shared_and_removed_with_the_last_vm() {
+        let dir = temp_dir("staging");
+        std::fs::create_dir_all(dir.join("images")).unwrap();
+        std::fs::write(dir.join("images").join("stale.img.x.partial"), b"half").unwrap();
+        let stage = ImageStage::new(&dir);
+        assert!(!dir.join("images").join("stale.img.x.partial").exists());
+
+        // Stands in for a store path, staging only reads it
+        let src = "/nix/store/abc-image/disk.img";
+        let first = stage.lease(src).unwrap();
+        let second = stage.lease(src).unwrap();
+        assert_eq!(stage.users(src), 2);
+        assert_eq!(first.path(), dir.join("images").join("abc-image_disk.img"));
+        assert!(stage.lease("/tmp/not-in-store.img").is_none());
+
+        std::fs::write(first.path(), b"image").unwrap();
+        assert!(second.stage(src).await.unwrap(), "already staged");
+
+        let disk = dir.join("disk.img");
+        clone_or_copy(&first.path(), &disk).await.unwrap();
+        assert_eq!(std::fs::read(&disk).unwrap(), b"image");
+
+        drop(first);
+        assert!(second.path().exists());
+        let staged = second.path();
+        drop(second);
+        assert_eq!(stage.users(src), 0);
+        assert!(!staged.exists());
+        // The VM disk does not depend on the staged image
+        assert_eq!(std::fs::read(&disk).unwrap(), b"image");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
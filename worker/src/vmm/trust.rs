@@ -0,0 +1,203 @@
+//! Image signature verification before boot.
+//!
+//! A worker only boots images whose whole closure carries a valid
+//! signature from one of the cluster's trusted keys (binary cache, CI).
+//! Verification runs `nix store verify` over the closure of every store
+//! path the spec boots from, without re-hashing contents:
+//!
+//! ```text
+//! nix store verify --recursive --no-contents --sigs-needed 1 \
+//!     --option trusted-public-keys '<keys>' <store paths>
+//! ```
+//!
+//! `--sigs-needed 1` also applies to paths built on this host, which nix
+//! would otherwise trust implicitly: an unsigned local build is refused
+//! like anything else.
+
+use std::collections::BTreeSet;
+
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::dto::{VmError, VmSpec};
+
+const STORE_DIR: &str = "/nix/store/";
+
+/// Untrusted paths named in the error, the rest are counted.
+const MAX_REPORTED_PATHS: usize = 3;
+
+/// Verify the image closure of `spec` against `trusted_public_keys`
+/// (`name:base64` entries, as in `nix.conf`).
+///
+/// # Errors
+///
+/// Fails with [`VmError::Untrusted`] when a path is unsigned or signed by
+/// an unknown key, or when the spec boots from outside the store.
+pub async fn verify_image(spec: &VmSpec, trusted_public_keys: &[String]) -> Result<(), VmError> {
+    let paths = image_store_paths(spec)?;
+
+    let mut command = Command::new("nix");
+    command
+        .args(["store", "verify", "--recursive", "--no-contents"])
+        .args(["--sigs-needed", "1"])
+        .args(["--option", "trusted-public-keys"])
+        .arg(trusted_public_keys.join(" "))
+        .args(&paths);
+    debug!(?paths, "Verifying image signatures");
+
+    let output = command
+        .output()
+        .await
+        .map_err(|e| VmError::Internal(format!("failed to run nix store verify: {e}")))?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let untrusted = untrusted_paths(&stderr);
+    if untrusted.is_empty() {
+        return Err(VmError::Internal(format!(
+            "nix store verify failed ({}): {}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+    warn!(
+        count = untrusted.len(),
+        first = %untrusted[0],
+        "Refusing image with untrusted store paths"
+    );
+    Err(VmError::Untrusted(describe(&untrusted)))
+}
+
+/// Store paths the VM boots from, deduplicated. Files inside a store path
+/// (e.g. `…-kernel/bzImage`) map to that store path.
+fn image_store_paths(spec: &VmSpec) -> Result<Vec<String>, VmError> {
+    let mut paths = BTreeSet::new();
+    for (label, path) in [
+        ("toplevel", spec.toplevel()),
+        ("kernel", spec.kernel_path()),
+        ("initrd", spec.initrd_path()),
+        ("disk image", spec.disk_image_path()),
+    ] {
+        if path.is_empty() {
+            continue;
+        }
+        let store_path = store_path_of(path).ok_or_else(|| {
+            VmError::Untrusted(format!(
+                "{label} {path} is not in {STORE_DIR} and cannot be verified"
+            ))
+        })?;
+        paths.insert(store_path.to_string());
+    }
+    Ok(paths.into_iter().collect())
+}
+
+/// `/nix/store/<hash>-<name>` for any path at or below it. A path with `.`
+/// or `..` components is refused: `…-kernel/../../tmp/evil` would map to a
+/// store path that says nothing about the file it resolves to.
+pub(crate) fn store_path_of(path: &str) -> Option<&str> {
+    let rest = path.strip_prefix(STORE_DIR)?;
+    if rest
+        .split('/')
+        .any(|component| matches!(component, "." | ".."))
+    {
+        return None;
+    }
+    let name = rest.split('/').next().unwrap_or(rest);
+    if name.is_empty() || name.starts_with('.') {
+        return None;
+    }
+    Some(&path[..STORE_DIR.len() + name.len()])
+}
+
+/// Paths from `path '<p>' is untrusted` lines of `nix store verify`.
+fn untrusted_paths(stderr: &str) -> Vec<&str> {
+    stderr
+        .lines()
+        .filter_map(|line| {
+            let rest = &line[line.find("path '")? + "path '".len()..];
+            rest.strip_suffix("' is untrusted")
+        })
+        .collect()
+}
+
+fn describe(untrusted: &[&str]) -> String {
+    let shown = untrusted
+        .iter()
+        .take(MAX_REPORTED_PATHS)
+        .copied()
+        .collect::<Vec<_>>()
+        .join(", ");
+    match untrusted.len().saturating_sub(MAX_REPORTED_PATHS) {
+        0 => format!("no trusted signature on {shown}"),
+        more => format!("no trusted signature on {shown} and {more} more"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(disk_image_path: &str) -> VmSpec {
+        VmSpec::new(
+            "/nix/store/aaaa-nixos-system".to_string(),
+            "/nix/store/bbbb-kernel/bzImage".to_string(),
+            "/nix/store/bbbb-kernel/initrd".to_string(),
+            disk_image_path.to_string(),
+            "console=ttyS0".to_string(),
+            1,
+            512,
+            vec![],
+        )
+    }
+
+    #[test]
+    fn collects_the_store_paths_a_vm_boots_from() {
+        let paths = image_store_paths(&spec("/nix/store/cccc-disk/nixos.raw")).unwrap();
+        assert_eq!(
+            paths,
+            [
+                "/nix/store/aaaa-nixos-system",
+                "/nix/store/bbbb-kernel",
+                "/nix/store/cccc-disk",
+            ]
+        );
+
+        let err = image_store_paths(&spec("/var/lib/images/nixos.raw")).unwrap_err();
+        assert!(matches!(err, VmError::Untrusted(msg) if msg.contains("disk image")));
+        assert_eq!(store_path_of("/nix/store/"), None);
+        assert_eq!(store_path_of("/nix/store/.links/x"), None);
+    }
+
+    #[test]
+    fn refuses_paths_escaping_their_store_path() {
+        assert_eq!(store_path_of("/nix/store/aaaa-x/../../tmp/evil.raw"), None);
+        assert_eq!(store_path_of("/nix/store/aaaa-x/./bzImage"), None);
+        assert_eq!(store_path_of("/nix/store/../tmp/evil.raw"), None);
+        let err =
+            image_store_paths(&spec("/nix/store/cccc-disk/../../../tmp/evil.raw")).unwrap_err();
+        assert!(matches!(err, VmError::Untrusted(msg) if msg.contains("disk image")));
+    }
+
+    #[test]
+    fn reports_untrusted_paths_from_nix_output() {
+        let stderr = "\
+path '/nix/store/aaaa-hello-2.12' is untrusted
+path '/nix/store/bbbb-glibc-2.39' is untrusted
+";
+        let untrusted = untrusted_paths(stderr);
+        assert_eq!(
+            untrusted,
+            ["/nix/store/aaaa-hello-2.12", "/nix/store/bbbb-glibc-2.39"]
+        );
+        assert_eq!(
+            describe(&untrusted),
+            "no trusted signature on /nix/store/aaaa-hello-2.12, /nix/store/bbbb-glibc-2.39"
+        );
+        assert_eq!(
+            describe(&["a", "b", "c", "d", "e"]),
+            "no trusted signature on a, b, c and 2 more"
+        );
+    }
+}
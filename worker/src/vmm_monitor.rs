@@ -0,0 +1,205 @@
+//! # VMM monitor
+//!
+//! A cloud-hypervisor process can die, or hang with its VM, while the
+//! worker still lists the VM as `running`: only boots and drains look at
+//! the process otherwise. With a `vmm_monitor` section, every
+//! `check_interval_secs` the worker checks each `running` and `stopped` VM:
+//!
+//! - a VMM process that exited makes the VM `crashed`;
+//! - otherwise it asks the VMM for `vm.info`. A VMM that does not answer
+//!   within `api_timeout_ms` for `unresponsive_checks` checks in a row is
+//!   hung: it is killed and the VM is `crashed` too;
+//! - a VMM that answers has the VM's state reconciled with what it
+//!   reports: a `running` VM whose guest shut down is `stopped`, and a
+//!   `stopped` VM running again is `running`.
+//!
+//! A crashed VM has its process killed and cleaned up and its record
+//! dropped, and stays in the table until deleted, restarted or
+//! redeployed, like a `boot-failed` one. Booting VMs are left to the
+//! [boot watchdog](crate::boot_watchdog), draining ones to the
+//! [drain](crate::drain).
+
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use commands::vm_state::VmState;
+use serde::Deserialize;
+
+use crate::vmm::GuestState;
+
+/// Enables the VMM monitor; disabled when the section is absent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct VmmMonitorSection {
+    /// How often every VMM is checked.
+    pub check_interval_secs: u64,
+    /// How long a VMM has to answer `vm.info`.
+    pub api_timeout_ms: u64,
+    /// Unanswered checks in a row before a VMM counts as hung.
+    pub unresponsive_checks: u32,
+}
+
+impl Default for VmmMonitorSection {
+    fn default() -> Self {
+        Self {
+            check_interval_secs: 10,
+            api_timeout_ms: 2000,
+            unresponsive_checks: 3,
+        }
+    }
+}
+
+/// What one check of a VMM found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// The process exited
+    Exited(ExitStatus),
+    /// `vm.info` answered
+    Answered(GuestState),
+    /// `vm.info` failed or timed out
+    Unanswered,
+}
+
+/// Why a VMM is given up on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crash {
+    /// Its process exited
+    Exited(ExitStatus),
+    /// Its API went unanswered for that many checks
+    Unresponsive(u32),
+}
+
+impl Crash {
+    /// Transition reason of the VM.
+    #[must_use]
+    pub fn reason(&self) -> String {
+        match self {
+            Crash::Exited(status) => format!("VMM process exited with {status}"),
+            Crash::Unresponsive(checks) => format!("VMM unanswered for {checks} checks"),
+        }
+    }
+}
+
+/// What to do with a VM after a check, see the module docs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Nothing to change
+    Healthy,
+    /// The API did not answer, not for long enough to give up yet
+    Missed,
+    /// The guest changed state behind the worker's back
+    Became(VmState, &'static str),
+    /// The VMM is gone or hung
+    Crashed(Crash),
+}
+
+/// Decides what a check of a VMM means, see the module docs.
+#[derive(Debug, Clone)]
+pub struct VmmMonitor {
+    interval: Duration,
+    api_timeout: Duration,
+    unresponsive_checks: u32,
+}
+
+impl VmmMonitor {
+    pub fn new(interval: Duration, api_timeout: Duration, unresponsive_checks: u32) -> Self {
+        Self {
+            interval,
+            api_timeout,
+            unresponsive_checks,
+        }
+    }
+
+    #[must_use]
+    pub fn check_interval(&self) -> Duration {
+        self.interval
+    }
+
+    #[must_use]
+    pub fn api_timeout(&self) -> Duration {
+        self.api_timeout
+    }
+
+    /// What `probe` means for a VM the worker has as `status`, whose API
+    /// went unanswered for the `misses` checks before.
+    #[must_use]
+    pub fn verdict(&self, status: VmState, probe: Probe, misses: u32) -> Verdict {
+        match probe {
+            Probe::Exited(exit) => Verdict::Crashed(Crash::Exited(exit)),
+            Probe::Unanswered if misses + 1 >= self.unresponsive_checks => {
+                Verdict::Crashed(Crash::Unresponsive(misses + 1))
+            }
+            Probe::Unanswered => Verdict::Missed,
+            Probe::Answered(state) => match (status, state) {
+                (VmState::Running, GuestState::Shutdown | GuestState::Created) => {
+                    Verdict::Became(VmState::Stopped, "guest shut down")
+                }
+                (VmState::Stopped, GuestState::Running) => {
+                    Verdict::Became(VmState::Running, "guest running again")
+                }
+                _ => Verdict::Healthy,
+            },
+        }
+    }
+}
+
+impl From<VmmMonitorSection> for VmmMonitor {
+    fn from(section: VmmMonitorSection) -> Self {
+        Self {
+            interval: Duration::from_secs(section.check_interval_secs),
+            api_timeout: Duration::from_millis(section.api_timeout_ms),
+            unresponsive_checks: section.unresponsive_checks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    fn monitor() -> VmmMonitor {
+        VmmMonitor::from(VmmMonitorSection::default())
+    }
+
+    #[test]
+    fn exited_and_hung_vmms_crash() {
+        let exit = ExitStatus::from_raw(9);
+        assert_eq!(
+            monitor().verdict(VmState::Running, Probe::Exited(exit), 0),
+            Verdict::Crashed(Crash::Exited(exit))
+        );
+        assert_eq!(
+            monitor().verdict(VmState::Running, Probe::Unanswered, 1),
+            Verdict::Missed
+        );
+        let hung = monitor().verdict(VmState::Stopped, Probe::Unanswered, 2);
+        assert_eq!(hung, Verdict::Crashed(Crash::Unresponsive(3)));
+        assert_eq!(
+            Crash::Unresponsive(3).reason(),
+            "VMM unanswered for 3 checks"
+        );
+    }
+
+    #[test]
+    fn answers_reconcile_the_vm_state() {
+        let answered = |status, state| monitor().verdict(status, Probe::Answered(state), 0);
+        assert_eq!(
+            answered(VmState::Running, GuestState::Running),
+            Verdict::Healthy
+        );
+        assert_eq!(
+            answered(VmState::Running, GuestState::Shutdown),
+            Verdict::Became(VmState::Stopped, "guest shut down")
+        );
+        assert_eq!(
+            answered(VmState::Stopped, GuestState::Running),
+            Verdict::Became(VmState::Running, "guest running again")
+        );
+        assert_eq!(
+            answered(VmState::Running, GuestState::Paused),
+            Verdict::Healthy
+        );
+    }
+}